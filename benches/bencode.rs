@@ -0,0 +1,233 @@
+#![feature(test)]
+
+extern crate test;
+#[macro_use]
+extern crate bittorrent_protocol;
+extern crate bytes;
+
+use bittorrent_protocol::bencode::{
+    BDecodeOpt, BMutAccess, BRefAccess, BencodeMut, BencodeRef, BencodeRefKind, BencodeView,
+    BencodeViewKind,
+};
+use bytes::{Bytes, BytesMut};
+use test::Bencher;
+
+const NUM_PEERS: usize = 50;
+const NUM_NODES: usize = 8;
+
+/// KRPC and tracker messages, as a client would send and receive them.
+fn corpus() -> Vec<BencodeMut<'static>> {
+    let peers: Vec<u8> = (0..NUM_PEERS * 6).map(|n| n as u8).collect();
+    let nodes: Vec<u8> = (0..NUM_NODES * 26).map(|n| n as u8).collect();
+
+    let mut values = BencodeMut::new_list();
+    for peer in peers.chunks(6) {
+        values.list_mut().unwrap().push(ben_bytes!(peer.to_vec()));
+    }
+
+    vec![
+        // KRPC ping query
+        ben_map! {
+            "t" => ben_bytes!("aa"),
+            "y" => ben_bytes!("q"),
+            "q" => ben_bytes!("ping"),
+            "a" => ben_map! {
+                "id" => ben_bytes!(vec![1u8; 20])
+            }
+        },
+        // KRPC find_node response
+        ben_map! {
+            "t" => ben_bytes!("ab"),
+            "y" => ben_bytes!("r"),
+            "r" => ben_map! {
+                "id" => ben_bytes!(vec![2u8; 20]),
+                "nodes" => ben_bytes!(nodes)
+            }
+        },
+        // KRPC get_peers response
+        ben_map! {
+            "t" => ben_bytes!("ac"),
+            "y" => ben_bytes!("r"),
+            "r" => ben_map! {
+                "id" => ben_bytes!(vec![3u8; 20]),
+                "token" => ben_bytes!("aoeusnth"),
+                "values" => values
+            }
+        },
+        // KRPC announce_peer query
+        ben_map! {
+            "t" => ben_bytes!("ad"),
+            "y" => ben_bytes!("q"),
+            "q" => ben_bytes!("announce_peer"),
+            "a" => ben_map! {
+                "id" => ben_bytes!(vec![4u8; 20]),
+                "implied_port" => ben_int!(1),
+                "info_hash" => ben_bytes!(vec![5u8; 20]),
+                "port" => ben_int!(6881),
+                "token" => ben_bytes!("aoeusnth")
+            }
+        },
+        // Tracker announce response
+        ben_map! {
+            "complete" => ben_int!(1204),
+            "incomplete" => ben_int!(37),
+            "interval" => ben_int!(1800),
+            "min interval" => ben_int!(900),
+            "peers" => ben_bytes!(peers)
+        },
+        // Tracker scrape response
+        ben_map! {
+            "files" => ben_map! {
+                vec![6u8; 20] => ben_map! {
+                    "complete" => ben_int!(5),
+                    "downloaded" => ben_int!(50),
+                    "incomplete" => ben_int!(10)
+                },
+                vec![7u8; 20] => ben_map! {
+                    "complete" => ben_int!(-1),
+                    "downloaded" => ben_int!(0),
+                    "incomplete" => ben_int!(9_000_000_000)
+                }
+            }
+        },
+    ]
+}
+
+fn encoded_corpus() -> Vec<Bytes> {
+    corpus()
+        .iter()
+        .map(|message| Bytes::from(message.encode()))
+        .collect()
+}
+
+fn corpus_len(messages: &[Bytes]) -> u64 {
+    messages.iter().map(|message| message.len() as u64).sum()
+}
+
+/// Hold on to every byte string, which has to be copied out of a `BencodeRef`.
+fn own_ref_bytes(bencode: &BencodeRef, owned: &mut Vec<Bytes>) {
+    match bencode.kind() {
+        BencodeRefKind::Int(_) => (),
+        BencodeRefKind::Bytes(bytes) => owned.push(Bytes::from(bytes)),
+        BencodeRefKind::List(list) => {
+            for value in list {
+                own_ref_bytes(value, owned);
+            }
+        }
+        BencodeRefKind::Dict(dict) => {
+            for (key, value) in dict.to_list() {
+                owned.push(Bytes::from(*key));
+                own_ref_bytes(value, owned);
+            }
+        }
+    }
+}
+
+/// Hold on to every byte string, which is a slice of the buffer in a `BencodeView`.
+fn own_view_bytes(bencode: &BencodeView, owned: &mut Vec<Bytes>) {
+    match bencode.kind() {
+        BencodeViewKind::Int(_) => (),
+        BencodeViewKind::Bytes(bytes) => owned.push(bytes),
+        BencodeViewKind::List(list) => {
+            for value in list {
+                own_view_bytes(&value, owned);
+            }
+        }
+        BencodeViewKind::Dict(dict) => {
+            for (key, value) in dict {
+                owned.push(key);
+                own_view_bytes(&value, owned);
+            }
+        }
+    }
+}
+
+#[bench]
+fn bench_decode_corpus_ref(b: &mut Bencher) {
+    let messages = encoded_corpus();
+    let mut owned = Vec::new();
+
+    b.bytes = corpus_len(&messages);
+    b.iter(|| {
+        owned.clear();
+
+        for message in messages.iter() {
+            let bencode = BencodeRef::decode(message, BDecodeOpt::default()).unwrap();
+            own_ref_bytes(&bencode, &mut owned);
+        }
+    });
+}
+
+#[bench]
+fn bench_decode_corpus_view(b: &mut Bencher) {
+    let messages = encoded_corpus();
+    let mut owned = Vec::new();
+
+    b.bytes = corpus_len(&messages);
+    b.iter(|| {
+        owned.clear();
+
+        for message in messages.iter() {
+            let bencode = BencodeView::decode(message.clone(), BDecodeOpt::default()).unwrap();
+            own_view_bytes(&bencode, &mut owned);
+        }
+    });
+}
+
+#[bench]
+fn bench_decode_corpus_ref_lookup(b: &mut Bencher) {
+    let messages = encoded_corpus();
+
+    b.bytes = corpus_len(&messages);
+    b.iter(|| {
+        for message in messages.iter() {
+            let bencode = BencodeRef::decode(message, BDecodeOpt::default()).unwrap();
+            let dict = bencode.dict().unwrap();
+
+            test::black_box(dict.lookup(b"y").or_else(|| dict.lookup(b"peers")));
+        }
+    });
+}
+
+#[bench]
+fn bench_decode_corpus_view_lookup(b: &mut Bencher) {
+    let messages = encoded_corpus();
+
+    b.bytes = corpus_len(&messages);
+    b.iter(|| {
+        for message in messages.iter() {
+            let bencode = BencodeView::decode(message.clone(), BDecodeOpt::default()).unwrap();
+            let dict = bencode.dict().unwrap();
+
+            test::black_box(dict.lookup(b"y").or_else(|| dict.lookup(b"peers")));
+        }
+    });
+}
+
+#[bench]
+fn bench_encode_corpus_vec(b: &mut Bencher) {
+    let messages = corpus();
+
+    b.bytes = corpus_len(&encoded_corpus());
+    b.iter(|| {
+        for message in messages.iter() {
+            test::black_box(message.encode());
+        }
+    });
+}
+
+#[bench]
+fn bench_encode_corpus_into(b: &mut Bencher) {
+    let messages = corpus();
+    let mut buffer = BytesMut::new();
+
+    b.bytes = corpus_len(&encoded_corpus());
+    b.iter(|| {
+        buffer.clear();
+
+        for message in messages.iter() {
+            message.encode_into(&mut buffer);
+        }
+        test::black_box(&buffer);
+    });
+}
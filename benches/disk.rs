@@ -0,0 +1,194 @@
+#![feature(test)]
+
+extern crate bittorrent_protocol;
+extern crate bytes;
+extern crate futures;
+extern crate test;
+extern crate tokio;
+
+use std::collections::HashMap;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+use bittorrent_protocol::disk::{
+    Block, BlockMetadata, BlockMut, DiskManagerBuilder, DiskManagerSink, DiskManagerStream,
+    FileSystem, IDiskMessage, ODiskMessage,
+};
+use bittorrent_protocol::metainfo::{DirectAccessor, Metainfo, MetainfoBuilder, PieceLength};
+use bittorrent_protocol::util::bt::InfoHash;
+use bytes::{Bytes, BytesMut};
+use futures::{SinkExt, StreamExt};
+use test::Bencher;
+use tokio::runtime::Runtime;
+
+const PIECE_LEN: usize = 256 * 1024;
+const BLOCK_LEN: usize = 16 * 1024;
+const NUM_BLOCKS: usize = PIECE_LEN / BLOCK_LEN;
+const NUM_PEERS: usize = 10;
+
+/// In memory file system counting the calls to read from a file.
+#[derive(Clone, Default)]
+struct CountingFileSystem {
+    files: Arc<Mutex<HashMap<PathBuf, Vec<u8>>>>,
+    reads: Arc<AtomicUsize>,
+}
+
+impl FileSystem for CountingFileSystem {
+    type File = PathBuf;
+
+    fn open_file<P>(&self, path: P) -> io::Result<Self::File>
+    where
+        P: AsRef<Path> + Send + 'static,
+    {
+        let path = path.as_ref().to_path_buf();
+        self.files
+            .lock()
+            .unwrap()
+            .entry(path.clone())
+            .or_insert_with(Vec::new);
+
+        Ok(path)
+    }
+
+    fn sync_file<P>(&self, _path: P) -> io::Result<()>
+    where
+        P: AsRef<Path> + Send + 'static,
+    {
+        Ok(())
+    }
+
+    fn file_size(&self, file: &Self::File) -> io::Result<u64> {
+        Ok(self.files.lock().unwrap()[file].len() as u64)
+    }
+
+    fn read_file(
+        &self,
+        file: &mut Self::File,
+        offset: u64,
+        buffer: &mut [u8],
+    ) -> io::Result<usize> {
+        self.reads.fetch_add(1, Ordering::SeqCst);
+
+        let files = self.files.lock().unwrap();
+        let offset = offset as usize;
+        buffer.copy_from_slice(&files[file][offset..(offset + buffer.len())]);
+
+        Ok(buffer.len())
+    }
+
+    fn write_file(&self, file: &mut Self::File, offset: u64, buffer: &[u8]) -> io::Result<usize> {
+        let mut files = self.files.lock().unwrap();
+        let file_buffer = files.get_mut(file).unwrap();
+
+        let (offset, end) = (offset as usize, offset as usize + buffer.len());
+        if end > file_buffer.len() {
+            file_buffer.resize(end, 0);
+        }
+        file_buffer[offset..end].copy_from_slice(buffer);
+
+        Ok(buffer.len())
+    }
+}
+
+/// Add a torrent with a single piece and write the piece, through the blocks peers will request.
+async fn setup(
+    send: &mut DiskManagerSink<CountingFileSystem>,
+    recv: &mut DiskManagerStream,
+) -> InfoHash {
+    let data: Vec<u8> = (0..PIECE_LEN).map(|index| index as u8).collect();
+    let metainfo_bytes = MetainfoBuilder::new()
+        .set_piece_length(PieceLength::Custom(PIECE_LEN))
+        .build(1, DirectAccessor::new("file", &data), |_| ())
+        .unwrap();
+    let metainfo_file = Metainfo::from_bytes(metainfo_bytes).unwrap();
+    let info_hash = metainfo_file.info().info_hash();
+
+    send.send(IDiskMessage::AddTorrent(metainfo_file))
+        .await
+        .unwrap();
+    match recv.next().await.unwrap() {
+        ODiskMessage::TorrentAdded(_) => (),
+        unexpected @ _ => panic!("Unexpected Message: {:?}", unexpected),
+    };
+
+    for block_index in 0..NUM_BLOCKS {
+        let block_offset = block_index * BLOCK_LEN;
+        let block = Block::new(
+            BlockMetadata::new(info_hash, 0, block_offset as u64, BLOCK_LEN),
+            Bytes::from(&data[block_offset..(block_offset + BLOCK_LEN)]),
+        );
+
+        send.send(IDiskMessage::ProcessBlock(block)).await.unwrap();
+    }
+
+    let (mut good_piece, mut processed) = (false, 0);
+    while !good_piece || processed != NUM_BLOCKS {
+        match recv.next().await.unwrap() {
+            ODiskMessage::FoundGoodPiece(_, 0) => good_piece = true,
+            ODiskMessage::BlockProcessed(_) => processed += 1,
+            unexpected @ _ => panic!("Unexpected Message: {:?}", unexpected),
+        };
+    }
+
+    info_hash
+}
+
+/// Every peer requests every block of the piece.
+async fn load_piece_for_peers(
+    send: &mut DiskManagerSink<CountingFileSystem>,
+    recv: &mut DiskManagerStream,
+    hash: InfoHash,
+) {
+    for _ in 0..NUM_PEERS {
+        for block_index in 0..NUM_BLOCKS {
+            let metadata = BlockMetadata::new(hash, 0, (block_index * BLOCK_LEN) as u64, BLOCK_LEN);
+            let mut bytes = BytesMut::with_capacity(BLOCK_LEN);
+            bytes.extend_from_slice(&[0u8; BLOCK_LEN]);
+
+            send.send(IDiskMessage::LoadBlock(BlockMut::new(metadata, bytes)))
+                .await
+                .unwrap();
+        }
+    }
+
+    for _ in 0..(NUM_PEERS * NUM_BLOCKS) {
+        match recv.next().await.unwrap() {
+            ODiskMessage::BlockLoaded(_) => (),
+            unexpected @ _ => panic!("Unexpected Message: {:?}", unexpected),
+        };
+    }
+}
+
+/// Asserts the reads from the file system for one round. Reads from our in memory file
+/// system are cheap, so the timings mostly show the overhead of the disk manager.
+fn bench_load_piece_for_peers(b: &mut Bencher, block_cache_size: usize, expected_reads: usize) {
+    let runtime = Runtime::new().unwrap();
+    let filesystem = CountingFileSystem::default();
+
+    let (mut send, mut recv) = DiskManagerBuilder::new()
+        .with_sink_buffer_capacity(NUM_PEERS * NUM_BLOCKS)
+        .with_block_cache_size(block_cache_size)
+        .build(filesystem.clone())
+        .into_parts();
+    let info_hash = runtime.block_on(setup(&mut send, &mut recv));
+
+    // Reads per round, after the piece was written and verified
+    filesystem.reads.store(0, Ordering::SeqCst);
+    runtime.block_on(load_piece_for_peers(&mut send, &mut recv, info_hash));
+    assert_eq!(expected_reads, filesystem.reads.load(Ordering::SeqCst));
+
+    b.bytes = (NUM_PEERS * PIECE_LEN) as u64;
+    b.iter(|| runtime.block_on(load_piece_for_peers(&mut send, &mut recv, info_hash)));
+}
+
+#[bench]
+fn bench_load_hot_piece_without_cache(b: &mut Bencher) {
+    bench_load_piece_for_peers(b, 0, NUM_PEERS * NUM_BLOCKS);
+}
+
+#[bench]
+fn bench_load_hot_piece_with_cache(b: &mut Bencher) {
+    bench_load_piece_for_peers(b, PIECE_LEN, 0);
+}
@@ -0,0 +1,117 @@
+#![feature(test)]
+
+extern crate bittorrent_protocol;
+extern crate futures;
+extern crate test;
+extern crate tokio;
+
+use std::fs;
+use std::path::PathBuf;
+
+use bittorrent_protocol::disk::{
+    DiskManagerBuilder, DiskManagerSink, DiskManagerStream, FileSystem, IDiskMessage,
+    MmapFileSystem, NativeFileSystem, ODiskMessage,
+};
+use bittorrent_protocol::metainfo::{DirectAccessor, Metainfo, MetainfoBuilder, PieceLength};
+use bittorrent_protocol::util::bt::InfoHash;
+use futures::{SinkExt, StreamExt};
+use test::Bencher;
+use tokio::runtime::Runtime;
+
+const FILE_LEN: usize = 32 * 1024 * 1024;
+const PIECE_LEN: usize = 256 * 1024;
+
+/// Write a single file torrent to a new directory, returning the directory and the torrent.
+fn setup(name: &str) -> (PathBuf, Metainfo) {
+    let dir = std::env::temp_dir().join(format!(
+        "bittorrent-protocol_bench_{}_{}",
+        name,
+        std::process::id()
+    ));
+    fs::create_dir_all(&dir).unwrap();
+
+    let data: Vec<u8> = (0..FILE_LEN).map(|index| (index % 251) as u8).collect();
+    fs::write(dir.join("file"), &data).unwrap();
+
+    let metainfo_bytes = MetainfoBuilder::new()
+        .set_piece_length(PieceLength::Custom(PIECE_LEN))
+        .build(1, DirectAccessor::new("file", &data), |_| ())
+        .unwrap();
+
+    (dir, Metainfo::from_bytes(metainfo_bytes).unwrap())
+}
+
+async fn add_torrent<F>(
+    send: &mut DiskManagerSink<F>,
+    recv: &mut DiskManagerStream,
+    metainfo_file: Metainfo,
+) where
+    F: FileSystem + Send + Sync + 'static,
+{
+    send.send(IDiskMessage::AddTorrent(metainfo_file))
+        .await
+        .unwrap();
+
+    // Every piece is found good while adding the torrent
+    loop {
+        match recv.next().await.unwrap() {
+            ODiskMessage::FoundGoodPiece(_, _) => (),
+            ODiskMessage::TorrentAdded(_) => break,
+            unexpected @ _ => panic!("Unexpected Message: {:?}", unexpected),
+        };
+    }
+}
+
+async fn check_torrent<F>(
+    send: &mut DiskManagerSink<F>,
+    recv: &mut DiskManagerStream,
+    hash: InfoHash,
+) where
+    F: FileSystem + Send + Sync + 'static,
+{
+    send.send(IDiskMessage::CheckTorrent(hash)).await.unwrap();
+
+    loop {
+        match recv.next().await.unwrap() {
+            ODiskMessage::TorrentCheckProgress { .. } => (),
+            ODiskMessage::TorrentChecked(_, good_pieces) => {
+                assert!(good_pieces.iter().all(|&good| good));
+
+                break;
+            }
+            unexpected @ _ => panic!("Unexpected Message: {:?}", unexpected),
+        };
+    }
+}
+
+/// Files stay in the page cache between rounds, so the timings mostly show the cost of
+/// getting the bytes out of the file system, next to hashing them.
+fn bench_check_torrent<F, B>(b: &mut Bencher, name: &str, build_fs: B)
+where
+    F: FileSystem + Send + Sync + 'static,
+    B: FnOnce(&PathBuf) -> F,
+{
+    let runtime = Runtime::new().unwrap();
+    let (dir, metainfo_file) = setup(name);
+    let info_hash = metainfo_file.info().info_hash();
+
+    let (mut send, mut recv) = DiskManagerBuilder::new().build(build_fs(&dir)).into_parts();
+    runtime.block_on(add_torrent(&mut send, &mut recv, metainfo_file));
+
+    b.bytes = FILE_LEN as u64;
+    b.iter(|| runtime.block_on(check_torrent(&mut send, &mut recv, info_hash)));
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[bench]
+fn bench_check_torrent_native(b: &mut Bencher) {
+    bench_check_torrent(b, "native", |dir| NativeFileSystem::with_directory(dir));
+}
+
+#[bench]
+fn bench_check_torrent_mmap(b: &mut Bencher) {
+    bench_check_torrent(b, "mmap", |dir| {
+        MmapFileSystem::with_directory(dir, FILE_LEN as u64)
+    });
+}
@@ -0,0 +1,114 @@
+#![feature(test)]
+
+extern crate test;
+extern crate bittorrent_protocol;
+extern crate bytes;
+
+use std::io::{self, IoSlice, Write};
+
+use test::Bencher;
+use bittorrent_protocol::peer::messages::{
+    HaveMessage, MessageLimits, PeerWireProtocolMessage, PieceMessage,
+};
+use bittorrent_protocol::peer::{MessageBatch, MessageCodec, PeerWireMessageCodec};
+use bytes::Bytes;
+
+const BLOCK_LEN: usize = 16 * 1024;
+const NUM_HAVES: u32 = 1000;
+
+/// Writer discarding everything, counting how many write calls it took.
+#[derive(Default)]
+struct CountingWriter {
+    writes: usize,
+}
+
+impl Write for CountingWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.writes += 1;
+
+        Ok(buf.len())
+    }
+
+    fn write_vectored(&mut self, bufs: &[IoSlice]) -> io::Result<usize> {
+        self.writes += 1;
+
+        Ok(bufs.iter().map(|buf| buf.len()).sum())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn have_messages() -> Vec<PeerWireProtocolMessage> {
+    (0..NUM_HAVES)
+        .map(|piece| PeerWireProtocolMessage::Have(HaveMessage::new(piece)))
+        .collect()
+}
+
+fn piece_message_bytes() -> Vec<u8> {
+    let message = PeerWireProtocolMessage::Piece(PieceMessage::new(
+        0,
+        0,
+        Bytes::from(vec![0xAB; BLOCK_LEN]),
+    ));
+
+    let mut bytes = Vec::new();
+    message.write_bytes(&mut bytes, &None).unwrap();
+
+    bytes
+}
+
+#[bench]
+fn bench_parse_piece_shared_buffer(b: &mut Bencher) {
+    let bytes = Bytes::from(piece_message_bytes());
+    let limits = MessageLimits::default();
+
+    b.bytes = bytes.len() as u64;
+    b.iter(|| {
+        PeerWireProtocolMessage::parse_bytes(bytes.clone(), &None, &limits).unwrap()
+    });
+}
+
+#[bench]
+fn bench_parse_piece_copied_buffer(b: &mut Bencher) {
+    let bytes = piece_message_bytes();
+    let limits = MessageLimits::default();
+
+    b.bytes = bytes.len() as u64;
+    b.iter(|| {
+        PeerWireProtocolMessage::parse_bytes(Bytes::from(&bytes[..]), &None, &limits).unwrap()
+    });
+}
+
+#[bench]
+fn bench_write_haves_unbatched(b: &mut Bencher) {
+    let messages = have_messages();
+    let mut codec = PeerWireMessageCodec::new();
+
+    b.iter(|| {
+        let mut writer = CountingWriter::default();
+        for message in messages.iter() {
+            codec.write_bytes(message, &mut writer).unwrap();
+        }
+
+        assert!(writer.writes >= NUM_HAVES as usize);
+    });
+}
+
+#[bench]
+fn bench_write_haves_batched(b: &mut Bencher) {
+    let messages = have_messages();
+    let mut codec = PeerWireMessageCodec::new();
+    let mut batch = MessageBatch::new();
+
+    b.iter(|| {
+        let mut writer = CountingWriter::default();
+        for message in messages.iter() {
+            batch.push(&mut codec, message).unwrap();
+        }
+
+        assert_eq!(1, batch.flush(&mut writer).unwrap());
+        assert_eq!(1, writer.writes);
+    });
+}
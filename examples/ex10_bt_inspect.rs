@@ -0,0 +1,76 @@
+#[macro_use]
+extern crate clap;
+
+use std::fs::File;
+use std::io::{self, Read, Write};
+
+use bittorrent_protocol::bencode::{BDecodeOpt, BPretty, BencodeMut, BencodeRef, BinaryEncoding};
+use serde_json::Value;
+
+fn main() {
+    // Command line argument parsing
+    let matches = clap_app!(myapp =>
+        (version: "1.0")
+        (about: "Inspect bencoded torrent files and DHT or tracker packets")
+        (@arg input: +required "File to read, or - for standard input")
+        (@arg json: -j "Print as JSON instead of pretty printing")
+        (@arg base64: -b "Encode binary strings in JSON as base64 instead of hex")
+        (@arg max_bytes: -m +takes_value "Bytes of each byte string to pretty print")
+        (@arg from_json: -r "Read JSON printed with -j, writing the bencode it came from")
+    )
+    .get_matches();
+
+    let input = read_input(matches.value_of("input").unwrap()).unwrap();
+
+    if matches.is_present("from_json") {
+        let json: Value = serde_json::from_slice(&input).expect("Input Is Not JSON");
+        let bencode = BencodeMut::from_json(&json).unwrap_or_else(|err| panic!("{}", err));
+
+        io::stdout().write_all(&bencode.encode()).unwrap();
+        return;
+    }
+
+    // Packets may be followed by other data, show what decodes and where it ends
+    let opts = BDecodeOpt::new(usize::MAX, false, false);
+    let bencode = BencodeRef::decode(&input, opts).unwrap_or_else(|err| panic!("{}", err));
+
+    if matches.is_present("json") {
+        let encoding = if matches.is_present("base64") {
+            BinaryEncoding::Base64
+        } else {
+            BinaryEncoding::Hex
+        };
+
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&bencode.to_json(encoding)).unwrap()
+        );
+    } else {
+        let mut pretty = BPretty::new(&bencode);
+        if let Some(max_bytes) = matches.value_of("max_bytes") {
+            pretty = pretty.with_max_bytes_len(max_bytes.parse().expect("Invalid Max Bytes"));
+        }
+
+        println!("{}", pretty);
+    }
+
+    if bencode.buffer().len() != input.len() {
+        eprintln!(
+            "Decoded {} Of {} Bytes",
+            bencode.buffer().len(),
+            input.len()
+        );
+    }
+}
+
+fn read_input(path: &str) -> io::Result<Vec<u8>> {
+    let mut input = Vec::new();
+
+    if path == "-" {
+        io::stdin().read_to_end(&mut input)?;
+    } else {
+        File::open(path)?.read_to_end(&mut input)?;
+    }
+
+    Ok(input)
+}
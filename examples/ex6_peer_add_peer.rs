@@ -90,7 +90,7 @@ fn main() {
     let response = manager.poll().unwrap();
 
     match response {
-        OPeerManagerMessage::PeerAdded(info) => {
+        OPeerManagerMessage::PeerAdded(info, _) => {
             info!("PeerAdded\n1: {:?} \n=\n2: {:?}\n", peer_one_info, info)
         }
         _ => panic!("Unexpected First Peer Manager Response"),
@@ -119,7 +119,7 @@ fn main() {
 
 
     match response {
-        OPeerManagerMessage::PeerAdded(info) => {
+        OPeerManagerMessage::PeerAdded(info, _) => {
             info!("PeerAdded\n1: {:?} \n=\n2: {:?}\n", peer_two_info, info)
         }
 
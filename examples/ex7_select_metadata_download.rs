@@ -179,7 +179,7 @@ fn main() {
              info!("[merged_recv] opeer_manager_msg {:?} \n", &opt_item);
 
              let opt_message = match opt_item {
-                 OPeerManagerMessage::PeerAdded(info) => {
+                 OPeerManagerMessage::PeerAdded(info, _) => {
                      Some(IUberMessage::Control(ControlMessage::PeerConnected(info)))
                  }
 
@@ -189,13 +189,7 @@ fn main() {
                      )))
                  }
 
-                 OPeerManagerMessage::PeerDisconnect(info) => {
-                     Some(IUberMessage::Control(ControlMessage::PeerDisconnected(
-                         info,
-                     )))
-                 }
-
-                 OPeerManagerMessage::PeerError(info, error) => {
+                 OPeerManagerMessage::PeerDisconnected { info, .. } => {
                      Some(IUberMessage::Control(ControlMessage::PeerDisconnected(
                          info,
                      )))
@@ -0,0 +1,74 @@
+use bytes::Bytes;
+
+use bittorrent_protocol::peer::messages::builders::ExtendedMessageBuilder;
+use bittorrent_protocol::peer::messages::{
+    BitsExtensionMessage, CustomExtensionMessage, ExtendedType, PeerExtensionProtocolMessage,
+    PeerWireProtocolMessage,
+};
+use bittorrent_protocol::peer::{MessageCodec, PeerWireMessageCodec};
+
+const CHAT_EXTENSION: &'static str = "my_swarm_chat";
+
+/// Write the message with the sending codec, and parse it with the receiving codec.
+fn transfer(
+    from: &mut PeerWireMessageCodec,
+    to: &mut PeerWireMessageCodec,
+    message: &PeerWireProtocolMessage,
+) -> PeerWireProtocolMessage {
+    let mut wire = Vec::new();
+    from.write_bytes(message, &mut wire).unwrap();
+
+    to.parse_bytes(Bytes::from(wire)).unwrap()
+}
+
+fn extended_handshake(chat_id: u8) -> PeerWireProtocolMessage {
+    let extended = ExtendedMessageBuilder::new()
+        .with_extended_type(ExtendedType::Custom(CHAT_EXTENSION.to_string()), Some(chat_id))
+        .build();
+
+    PeerWireProtocolMessage::BitsExtension(BitsExtensionMessage::Extended(extended))
+}
+
+fn chat_message(text: &str) -> PeerWireProtocolMessage {
+    PeerWireProtocolMessage::ProtExtension(PeerExtensionProtocolMessage::Custom(
+        CustomExtensionMessage::new(
+            ExtendedType::Custom(CHAT_EXTENSION.to_string()),
+            Bytes::from(text.as_bytes()),
+        ),
+    ))
+}
+
+fn main() {
+    // Two in memory peers, each registering the chat extension under a different id
+    let mut peer_one = PeerWireMessageCodec::new();
+    let mut peer_two = PeerWireMessageCodec::new();
+
+    transfer(&mut peer_one, &mut peer_two, &extended_handshake(7));
+    transfer(&mut peer_two, &mut peer_one, &extended_handshake(3));
+
+    // Peer one sends a chat message, which peer two echoes back
+    let received = transfer(&mut peer_one, &mut peer_two, &chat_message("hello swarm"));
+    let echo = match received {
+        PeerWireProtocolMessage::ProtExtension(PeerExtensionProtocolMessage::Custom(msg)) => {
+            println!(
+                "Peer Two Received {:?}: {:?}",
+                msg.ext_type().id(),
+                String::from_utf8_lossy(msg.payload())
+            );
+
+            PeerWireProtocolMessage::ProtExtension(PeerExtensionProtocolMessage::Custom(msg))
+        }
+        other => panic!("Peer Two Received Unexpected Message: {:?}", other),
+    };
+
+    match transfer(&mut peer_two, &mut peer_one, &echo) {
+        PeerWireProtocolMessage::ProtExtension(PeerExtensionProtocolMessage::Custom(msg)) => {
+            println!(
+                "Peer One Received Echo {:?}: {:?}",
+                msg.ext_type().id(),
+                String::from_utf8_lossy(msg.payload())
+            );
+        }
+        other => panic!("Peer One Received Unexpected Message: {:?}", other),
+    }
+}
@@ -1,8 +1,10 @@
+use std::str;
+
 use crate::bencode::access::bencode::BRefAccess;
 use crate::bencode::access::bencode::BRefAccessExt;
 use crate::bencode::access::dict::BDictAccess;
 use crate::bencode::access::list::BListAccess;
-use crate::bencode::error::{BencodeConvertError, BencodeConvertErrorKind};
+use crate::bencode::error::BencodeConvertError;
 
 /// Trait for extended casting of bencode objects and converting conversion errors into application specific errors.
 pub trait BConvertExt: BConvert {
@@ -14,12 +16,7 @@ pub trait BConvertExt: BConvert {
     {
         bencode
             .bytes_ext()
-            .ok_or(self.handle_error(BencodeConvertError::from_kind(
-                BencodeConvertErrorKind::WrongType {
-                    key: error_key.as_ref().to_owned(),
-                    expected_type: "Bytes".to_owned(),
-                },
-            )))
+            .ok_or(self.handle_error(BencodeConvertError::wrong_type(error_key.as_ref(), "Bytes")))
     }
 
     /// See BConvert::convert_str.
@@ -30,11 +27,9 @@ pub trait BConvertExt: BConvert {
     {
         bencode
             .str_ext()
-            .ok_or(self.handle_error(BencodeConvertError::from_kind(
-                BencodeConvertErrorKind::WrongType {
-                    key: error_key.as_ref().to_owned(),
-                    expected_type: "UTF-8 Bytes".to_owned(),
-                },
+            .ok_or(self.handle_error(BencodeConvertError::wrong_type(
+                error_key.as_ref(),
+                "UTF-8 Bytes",
             )))
     }
 
@@ -82,11 +77,9 @@ pub trait BConvert {
     {
         bencode
             .int()
-            .ok_or(self.handle_error(BencodeConvertError::from_kind(
-                BencodeConvertErrorKind::WrongType {
-                    key: error_key.as_ref().to_owned(),
-                    expected_type: "Integer".to_owned(),
-                },
+            .ok_or(self.handle_error(BencodeConvertError::wrong_type(
+                error_key.as_ref(),
+                "Integer",
             )))
     }
 
@@ -100,12 +93,7 @@ pub trait BConvert {
     {
         bencode
             .bytes()
-            .ok_or(self.handle_error(BencodeConvertError::from_kind(
-                BencodeConvertErrorKind::WrongType {
-                    key: error_key.as_ref().to_owned(),
-                    expected_type: "Bytes".to_owned(),
-                },
-            )))
+            .ok_or(self.handle_error(BencodeConvertError::wrong_type(error_key.as_ref(), "Bytes")))
     }
 
     /// Attempt to convert the given bencode value into a UTF-8 string.
@@ -118,11 +106,9 @@ pub trait BConvert {
     {
         bencode
             .str()
-            .ok_or(self.handle_error(BencodeConvertError::from_kind(
-                BencodeConvertErrorKind::WrongType {
-                    key: error_key.as_ref().to_owned(),
-                    expected_type: "UTF-8 Bytes".to_owned(),
-                },
+            .ok_or(self.handle_error(BencodeConvertError::wrong_type(
+                error_key.as_ref(),
+                "UTF-8 Bytes",
             )))
     }
 
@@ -140,12 +126,7 @@ pub trait BConvert {
     {
         bencode
             .list()
-            .ok_or(self.handle_error(BencodeConvertError::from_kind(
-                BencodeConvertErrorKind::WrongType {
-                    key: error_key.as_ref().to_owned(),
-                    expected_type: "List".to_owned(),
-                },
-            )))
+            .ok_or(self.handle_error(BencodeConvertError::wrong_type(error_key.as_ref(), "List")))
     }
 
     /// Attempt to convert the given bencode value into a dictionary.
@@ -162,14 +143,17 @@ pub trait BConvert {
     {
         bencode
             .dict()
-            .ok_or(self.handle_error(BencodeConvertError::from_kind(
-                BencodeConvertErrorKind::WrongType {
-                    key: error_key.as_ref().to_owned(),
-                    expected_type: "Dictionary".to_owned(),
-                },
+            .ok_or(self.handle_error(BencodeConvertError::wrong_type(
+                error_key.as_ref(),
+                "Dictionary",
             )))
     }
 
+    /// Attempt to convert the given dictionary key into a UTF-8 string.
+    fn convert_key_str<'a>(&self, key: &'a [u8]) -> Result<&'a str, Self::Error> {
+        str::from_utf8(key).map_err(|_| self.handle_error(BencodeConvertError::non_utf8_key(key)))
+    }
+
     /// Look up a value in a dictionary of bencoded values using the given key.
     fn lookup<'a, B, K1, K2>(
         &self,
@@ -184,11 +168,7 @@ pub trait BConvert {
 
         match dictionary.lookup(key_ref) {
             Some(n) => Ok(n),
-            None => Err(self.handle_error(BencodeConvertError::from_kind(
-                BencodeConvertErrorKind::MissingKey {
-                    key: key_ref.to_owned(),
-                },
-            ))),
+            None => Err(self.handle_error(BencodeConvertError::missing_key(key_ref))),
         }
     }
 
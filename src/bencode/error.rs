@@ -1,3 +1,5 @@
+use crate::bencode::reference::decode_opt::BDecodeLimit;
+
 error_chain! {
     types {
         BencodeParseError, BencodeParseErrorKind, BencodeParseResultExt, BencodeParseResult;
@@ -40,6 +42,19 @@ error_chain! {
             description("Invalid Integer Found To Fail Parsing")
             display("Invalid Integer Found To Fail Parsing At {:?}", pos)
         }
+        InvalidIntOverflow {
+            pos: usize
+         } {
+            description("Invalid Integer Found To Overflow")
+            display("Invalid Integer Found To Overflow At {:?}", pos)
+        }
+        InvalidContainerNoEnd {
+            pos: usize,
+            start: usize
+         } {
+            description("Invalid List Or Dictionary Found With No End")
+            display("Invalid List Or Dictionary Starting At {:?} Found With No End At {:?}", start, pos)
+        }
         InvalidKeyOrdering {
             pos: usize,
             key: Vec<u8>
@@ -66,12 +81,13 @@ error_chain! {
             description("Invalid Byte Length Found To Overflow Buffer Length")
             display("Invalid Byte Length Found To Overflow Buffer Length At {:?}", pos)
         }
-        InvalidRecursionExceeded {
+        LimitExceeded {
             pos: usize,
+            limit: BDecodeLimit,
             max: usize
         } {
-            description("Invalid Recursion Limit Exceeded")
-            display("Invalid Recursion Limit Exceeded At {:?} For Limit {:?}", pos, max)
+            description("Decode Limit Exceeded")
+            display("Decode Limit {:?} Exceeded At {:?} For Limit {:?}", limit, pos, max)
         }
     }
 }
@@ -83,17 +99,116 @@ error_chain! {
 
     errors {
         MissingKey {
-            key: Vec<u8>
+            key: Vec<u8>,
+            path: Box<str>
          } {
             description("Missing Key In Bencode")
-            display("Missing Key In Bencode For {:?}", key)
+            display("Missing Key In Bencode At {}", path)
         }
         WrongType {
             key: Vec<u8>,
-            expected_type: String
+            expected_type: String,
+            path: Box<str>
          } {
             description("Wrong Type In Bencode")
-            display("Wrong Type In Bencode For {:?} Expected Type {}", key, expected_type)
+            display("Wrong Type In Bencode At {} Expected Type {}", path, expected_type)
         }
+        NonUtf8Key {
+            key: Vec<u8>,
+            path: Box<str>
+         } {
+            description("Non UTF-8 Key In Bencode")
+            display("Non UTF-8 Key In Bencode At {}", path)
+        }
+    }
+}
+
+impl BencodeParseError {
+    /// Byte offset into the decoded bytes where the error was found.
+    pub fn pos(&self) -> Option<usize> {
+        match *self.kind() {
+            BencodeParseErrorKind::BytesEmpty { pos }
+            | BencodeParseErrorKind::InvalidByte { pos }
+            | BencodeParseErrorKind::InvalidIntNoDelimiter { pos }
+            | BencodeParseErrorKind::InvalidIntNegativeZero { pos }
+            | BencodeParseErrorKind::InvalidIntZeroPadding { pos }
+            | BencodeParseErrorKind::InvalidIntParseError { pos }
+            | BencodeParseErrorKind::InvalidIntOverflow { pos }
+            | BencodeParseErrorKind::InvalidContainerNoEnd { pos, .. }
+            | BencodeParseErrorKind::InvalidKeyOrdering { pos, .. }
+            | BencodeParseErrorKind::InvalidKeyDuplicates { pos, .. }
+            | BencodeParseErrorKind::InvalidLengthNegative { pos }
+            | BencodeParseErrorKind::InvalidLengthOverflow { pos }
+            | BencodeParseErrorKind::LimitExceeded { pos, .. } => Some(pos),
+            _ => None,
+        }
+    }
+}
+
+impl BencodeConvertError {
+    /// Create an error for a key that was not found in a dictionary.
+    pub fn missing_key(key: &[u8]) -> BencodeConvertError {
+        BencodeConvertError::from_kind(BencodeConvertErrorKind::MissingKey {
+            key: key.to_owned(),
+            path: path_segment(key),
+        })
+    }
+
+    /// Create an error for a value that was not of the type we expected.
+    pub fn wrong_type(key: &[u8], expected_type: &str) -> BencodeConvertError {
+        BencodeConvertError::from_kind(BencodeConvertErrorKind::WrongType {
+            key: key.to_owned(),
+            expected_type: expected_type.to_owned(),
+            path: path_segment(key),
+        })
+    }
+
+    /// Create an error for a dictionary key that was expected to be UTF-8.
+    pub fn non_utf8_key(key: &[u8]) -> BencodeConvertError {
+        BencodeConvertError::from_kind(BencodeConvertErrorKind::NonUtf8Key {
+            key: key.to_owned(),
+            path: path_segment(key),
+        })
+    }
+
+    /// Dotted path to the value that failed to convert, for example `info.files[3].length`.
+    pub fn path(&self) -> Option<&str> {
+        match *self.kind() {
+            BencodeConvertErrorKind::MissingKey { ref path, .. }
+            | BencodeConvertErrorKind::WrongType { ref path, .. }
+            | BencodeConvertErrorKind::NonUtf8Key { ref path, .. } => Some(path),
+            _ => None,
+        }
+    }
+
+    /// Qualify the path of the failing value with the value it was found in.
+    ///
+    /// Parents that are list indices, such as `[3]`, are appended without a dot, and values
+    /// converted with an empty error key take on the path of their parent.
+    pub fn within(mut self, parent: &str) -> BencodeConvertError {
+        match self.0 {
+            BencodeConvertErrorKind::MissingKey { ref mut path, .. }
+            | BencodeConvertErrorKind::WrongType { ref mut path, .. }
+            | BencodeConvertErrorKind::NonUtf8Key { ref mut path, .. } => {
+                *path = join_path(parent, path)
+            }
+            _ => (),
+        }
+
+        self
+    }
+}
+
+fn path_segment(key: &[u8]) -> Box<str> {
+    String::from_utf8_lossy(key).into()
+}
+
+fn join_path(parent: &str, child: &str) -> Box<str> {
+    if child.is_empty() {
+        parent.into()
+    } else if child.starts_with('[') {
+        format!("{}{}", parent, child).into()
+    } else {
+        format!("{}.{}", parent, child).into()
     }
 }
@@ -0,0 +1,243 @@
+use std::borrow::Cow;
+
+use serde_json::{Map, Number, Value};
+
+use crate::bencode::access::bencode::{BMutAccess, BRefAccess, BencodeRefKind};
+use crate::bencode::error::{BencodeConvertError, BencodeConvertResult};
+use crate::bencode::mutable::bencode_mut::BencodeMut;
+use crate::bencode::reference::bencode_ref::BencodeRef;
+use crate::util::convert;
+
+const HEX_MARKER: &'static str = "hex:";
+const BASE64_MARKER: &'static str = "base64:";
+
+/// Encoding of byte strings that can not be written as they are in JSON.
+///
+/// Byte strings that are not UTF-8, or that start with one of the markers, are written as
+/// a JSON string holding the marker followed by the encoded bytes, for example `hex:00ff`
+/// or `base64:AP8=`. Every other byte string is written as the JSON string it is.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum BinaryEncoding {
+    /// Lowercase hex, marked with `hex:`.
+    Hex,
+    /// Padded, standard alphabet base64, marked with `base64:`.
+    Base64,
+}
+
+impl<'a> BencodeRef<'a> {
+    /// Convert the bencode to JSON, encoding binary strings with the given encoding.
+    ///
+    /// See `BencodeMut::from_json` to convert it back.
+    pub fn to_json(&self, encoding: BinaryEncoding) -> Value {
+        to_json(self, encoding)
+    }
+}
+
+impl BencodeMut<'static> {
+    /// Convert JSON written by `to_json` back to bencode.
+    ///
+    /// Binary strings in either encoding are accepted. Encoding the result gives the original
+    /// bencode back, as long as its dictionary keys were sorted.
+    pub fn from_json(json: &Value) -> BencodeConvertResult<BencodeMut<'static>> {
+        from_json(json)
+    }
+}
+
+/// Convert the bencode to JSON, encoding binary strings with the given encoding.
+pub fn to_json<V>(bencode: &V, encoding: BinaryEncoding) -> Value
+where
+    V: BRefAccess<BType = V>,
+    V::BKey: AsRef<[u8]>,
+{
+    match bencode.kind() {
+        BencodeRefKind::Int(n) => Value::Number(n.into()),
+        BencodeRefKind::Bytes(n) => Value::String(bytes_to_json(n, encoding)),
+        BencodeRefKind::List(n) => Value::Array(
+            n.into_iter()
+                .map(|value| to_json(value, encoding))
+                .collect(),
+        ),
+        BencodeRefKind::Dict(n) => {
+            let mut map = Map::new();
+            for (key, value) in n.to_list() {
+                map.insert(
+                    bytes_to_json(key.as_ref(), encoding),
+                    to_json(value, encoding),
+                );
+            }
+
+            Value::Object(map)
+        }
+    }
+}
+
+/// Convert JSON written by `to_json` back to bencode.
+pub fn from_json(json: &Value) -> BencodeConvertResult<BencodeMut<'static>> {
+    match *json {
+        Value::Number(ref n) => number_from_json(n).map(BencodeMut::new_int),
+        Value::String(ref n) => bytes_from_json(n).map(|bytes| BencodeMut::new_bytes(bytes.into())),
+        Value::Array(ref n) => {
+            let mut bencode = BencodeMut::new_list();
+            {
+                let list = bencode.list_mut().unwrap();
+
+                for (index, value) in n.iter().enumerate() {
+                    let value =
+                        from_json(value).map_err(|err| err.within(&format!("[{}]", index)))?;
+
+                    list.push(value);
+                }
+            }
+
+            Ok(bencode)
+        }
+        Value::Object(ref n) => {
+            let mut bencode = BencodeMut::new_dict();
+            {
+                let dict = bencode.dict_mut().unwrap();
+
+                for (key, value) in n.iter() {
+                    let key_bytes = bytes_from_json(key).map_err(|err| err.within(key))?;
+                    let value = from_json(value).map_err(|err| err.within(key))?;
+
+                    dict.insert(Cow::Owned(key_bytes), value);
+                }
+            }
+
+            Ok(bencode)
+        }
+        Value::Null | Value::Bool(_) => Err(BencodeConvertError::wrong_type(
+            b"",
+            "Integer, String, Array Or Object",
+        )),
+    }
+}
+
+fn bytes_to_json(bytes: &[u8], encoding: BinaryEncoding) -> String {
+    match ::std::str::from_utf8(bytes) {
+        Ok(n) if !n.starts_with(HEX_MARKER) && !n.starts_with(BASE64_MARKER) => n.to_owned(),
+        _ => match encoding {
+            BinaryEncoding::Hex => format!("{}{}", HEX_MARKER, super::encode_hex(bytes)),
+            BinaryEncoding::Base64 => {
+                format!("{}{}", BASE64_MARKER, convert::bytes_to_base64(bytes))
+            }
+        },
+    }
+}
+
+fn bytes_from_json(string: &str) -> BencodeConvertResult<Vec<u8>> {
+    if let Some(hex) = string.strip_prefix(HEX_MARKER) {
+        super::decode_hex(hex).ok_or_else(|| BencodeConvertError::wrong_type(b"", "Hex Bytes"))
+    } else if let Some(base64) = string.strip_prefix(BASE64_MARKER) {
+        convert::base64_to_bytes(base64)
+            .ok_or_else(|| BencodeConvertError::wrong_type(b"", "Base64 Bytes"))
+    } else {
+        Ok(string.as_bytes().to_vec())
+    }
+}
+
+fn number_from_json(number: &Number) -> BencodeConvertResult<i64> {
+    number
+        .as_i64()
+        .ok_or_else(|| BencodeConvertError::wrong_type(b"", "Integer"))
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Value};
+
+    use crate::bencode::access::bencode::BRefAccess;
+    use crate::bencode::inspect::json::BinaryEncoding;
+    use crate::bencode::mutable::bencode_mut::BencodeMut;
+    use crate::bencode::reference::bencode_ref::BencodeRef;
+    use crate::bencode::reference::decode_opt::BDecodeOpt;
+
+    const KRPC_RESPONSE: &'static [u8] =
+        b"d1:rd2:id4:\x00\x01\xfe\xff5:nodes0:6:valuesl3:abc4:hex:ee1:t2:aa1:y1:re";
+
+    fn round_trip(bytes: &[u8], encoding: BinaryEncoding) -> Vec<u8> {
+        let bencode = BencodeRef::decode(bytes, BDecodeOpt::default()).unwrap();
+        let json = bencode.to_json(encoding);
+
+        // Make sure what we write survives being written out as text
+        let json: Value = serde_json::from_str(&json.to_string()).unwrap();
+        BencodeMut::from_json(&json).unwrap().encode()
+    }
+
+    #[test]
+    fn positive_to_json_hex() {
+        let bencode = BencodeRef::decode(KRPC_RESPONSE, BDecodeOpt::default()).unwrap();
+
+        assert_eq!(
+            bencode.to_json(BinaryEncoding::Hex),
+            json!({
+                "r": {
+                    "id": "hex:0001feff",
+                    "nodes": "",
+                    "values": ["abc", "hex:6865783a"]
+                },
+                "t": "aa",
+                "y": "r"
+            })
+        );
+    }
+
+    #[test]
+    fn positive_to_json_base64() {
+        let bencode =
+            BencodeRef::decode(b"l4:\x00\x01\xfe\xffi-7ee", BDecodeOpt::default()).unwrap();
+
+        assert_eq!(
+            bencode.to_json(BinaryEncoding::Base64),
+            json!(["base64:AAH+/w==", -7])
+        );
+    }
+
+    #[test]
+    fn positive_to_json_non_utf8_key() {
+        let bencode = BencodeRef::decode(b"d2:\xff\xfei1ee", BDecodeOpt::default()).unwrap();
+
+        assert_eq!(
+            bencode.to_json(BinaryEncoding::Hex),
+            json!({ "hex:fffe": 1 })
+        );
+    }
+
+    #[test]
+    fn positive_json_round_trip() {
+        for &encoding in [BinaryEncoding::Hex, BinaryEncoding::Base64].iter() {
+            assert_eq!(round_trip(KRPC_RESPONSE, encoding), KRPC_RESPONSE);
+            assert_eq!(round_trip(b"d2:\xff\xfei1ee", encoding), b"d2:\xff\xfei1ee");
+            assert_eq!(round_trip(b"llleee", encoding), b"llleee");
+        }
+    }
+
+    #[test]
+    fn positive_from_json() {
+        let json = json!({ "a": [1, "hex:00", "base64:AA=="], "b": {} });
+        let bencode = BencodeMut::from_json(&json).unwrap();
+
+        assert_eq!(bencode.encode(), b"d1:ali1e1:\x001:\x00e1:bdee");
+        assert_eq!(bencode.dict().unwrap().to_list().len(), 2);
+    }
+
+    #[test]
+    fn negative_from_json_wrong_type() {
+        let json = json!({ "info": { "files": [{ "length": 1.5 }] } });
+        let error = BencodeMut::from_json(&json).unwrap_err();
+
+        assert_eq!(error.path(), Some("info.files[0].length"));
+
+        let error = BencodeMut::from_json(&json!([true])).unwrap_err();
+        assert_eq!(error.path(), Some("[0]"));
+    }
+
+    #[test]
+    fn negative_from_json_bad_encoding() {
+        let error = BencodeMut::from_json(&json!({ "a": "hex:0" })).unwrap_err();
+        assert_eq!(error.path(), Some("a"));
+
+        let error = BencodeMut::from_json(&json!({ "base64:A": 1 })).unwrap_err();
+        assert_eq!(error.path(), Some("base64:A"));
+    }
+}
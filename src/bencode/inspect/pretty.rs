@@ -0,0 +1,197 @@
+use std::fmt;
+
+use crate::bencode::access::bencode::{BRefAccess, BencodeRefKind};
+use crate::bencode::reference::bencode_ref::BencodeRef;
+
+const DEFAULT_MAX_BYTES_LEN: usize = 32;
+const DEFAULT_INDENT: usize = 2;
+
+/// Displays bencode over indented lines, shortening long byte strings.
+///
+/// Byte strings that are printable UTF-8 are shown quoted, any other byte string is shown
+/// in hex between angle brackets. Either is cut short after `max_bytes_len` bytes, followed
+/// by its full length, unless it is a dictionary key.
+pub struct BPretty<'b, V: 'b> {
+    bencode: &'b V,
+    max_bytes_len: usize,
+    indent: usize,
+}
+
+impl<'b, V> BPretty<'b, V> {
+    /// Create a new `BPretty` for the given bencode.
+    pub fn new(bencode: &'b V) -> BPretty<'b, V> {
+        BPretty {
+            bencode: bencode,
+            max_bytes_len: DEFAULT_MAX_BYTES_LEN,
+            indent: DEFAULT_INDENT,
+        }
+    }
+
+    /// Show at most this many bytes of each byte string.
+    pub fn with_max_bytes_len(mut self, max_bytes_len: usize) -> BPretty<'b, V> {
+        self.max_bytes_len = max_bytes_len;
+        self
+    }
+
+    /// Indent nested values by this many spaces.
+    pub fn with_indent(mut self, indent: usize) -> BPretty<'b, V> {
+        self.indent = indent;
+        self
+    }
+
+    fn write_value(&self, f: &mut fmt::Formatter, bencode: &V, depth: usize) -> fmt::Result
+    where
+        V: BRefAccess<BType = V>,
+        V::BKey: AsRef<[u8]>,
+    {
+        match bencode.kind() {
+            BencodeRefKind::Int(n) => write!(f, "{}", n),
+            BencodeRefKind::Bytes(n) => write_bytes(f, n, self.max_bytes_len),
+            BencodeRefKind::List(n) if n.len() == 0 => write!(f, "[]"),
+            BencodeRefKind::List(n) => {
+                writeln!(f, "[")?;
+                for value in n {
+                    self.write_indent(f, depth + 1)?;
+                    self.write_value(f, value, depth + 1)?;
+                    writeln!(f)?;
+                }
+                self.write_indent(f, depth)?;
+                write!(f, "]")
+            }
+            BencodeRefKind::Dict(n) => {
+                let mut entries = n.to_list();
+                if entries.is_empty() {
+                    return write!(f, "{{}}");
+                }
+                entries.sort_by(|&(a, _), &(b, _)| a.as_ref().cmp(b.as_ref()));
+
+                writeln!(f, "{{")?;
+                for (key, value) in entries {
+                    self.write_indent(f, depth + 1)?;
+                    // Keys are short, and needed to make sense of their values
+                    write_bytes(f, key.as_ref(), usize::MAX)?;
+                    write!(f, ": ")?;
+                    self.write_value(f, value, depth + 1)?;
+                    writeln!(f)?;
+                }
+                self.write_indent(f, depth)?;
+                write!(f, "}}")
+            }
+        }
+    }
+
+    fn write_indent(&self, f: &mut fmt::Formatter, depth: usize) -> fmt::Result {
+        write!(f, "{:width$}", "", width = depth * self.indent)
+    }
+}
+
+fn write_bytes(f: &mut fmt::Formatter, bytes: &[u8], max_bytes_len: usize) -> fmt::Result {
+    match ::std::str::from_utf8(bytes) {
+        Ok(n) if !n.chars().any(char::is_control) => {
+            // Cut the string short on a character boundary
+            let shown_len = n
+                .char_indices()
+                .map(|(index, c)| index + c.len_utf8())
+                .take_while(|&end| end <= max_bytes_len)
+                .last()
+                .unwrap_or(0);
+
+            if shown_len == n.len() {
+                write!(f, "\"{}\"", n.escape_debug())
+            } else {
+                write!(
+                    f,
+                    "\"{}...\" ({} bytes)",
+                    n[..shown_len].escape_debug(),
+                    n.len()
+                )
+            }
+        }
+        _ => {
+            let shown_len = bytes.len().min(max_bytes_len);
+
+            if shown_len == bytes.len() {
+                write!(f, "<{}>", super::encode_hex(bytes))
+            } else {
+                write!(
+                    f,
+                    "<{}...> ({} bytes)",
+                    super::encode_hex(&bytes[..shown_len]),
+                    bytes.len()
+                )
+            }
+        }
+    }
+}
+
+impl<'b, V> fmt::Display for BPretty<'b, V>
+where
+    V: BRefAccess<BType = V>,
+    V::BKey: AsRef<[u8]>,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.write_value(f, self.bencode, 0)
+    }
+}
+
+impl<'a> BencodeRef<'a> {
+    /// Display the bencode over indented lines, see `BPretty`.
+    pub fn pretty(&self) -> BPretty<'_, BencodeRef<'a>> {
+        BPretty::new(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bencode::inspect::pretty::BPretty;
+    use crate::bencode::reference::bencode_ref::BencodeRef;
+    use crate::bencode::reference::decode_opt::BDecodeOpt;
+
+    #[test]
+    fn positive_pretty_nested() {
+        let bencode = BencodeRef::decode(
+            b"d1:rd2:id4:\x00\x01\xfe\xff6:valuesl3:abcleee1:t2:aa1:yi-1e1:zdee",
+            BDecodeOpt::default(),
+        )
+        .unwrap();
+
+        let expected = "{\n  \"r\": {\n    \"id\": <0001feff>\n    \"values\": [\n      \
+                        \"abc\"\n      []\n    ]\n  }\n  \"t\": \"aa\"\n  \"y\": -1\n  \"z\": {}\n}";
+        assert_eq!(bencode.pretty().to_string(), expected);
+    }
+
+    #[test]
+    fn positive_pretty_truncates_bytes() {
+        let bencode = BencodeRef::decode(
+            b"l6:abcdef6:\xc3\xa9\xc3\xa9\xc3\xa95:\x00\x01\x02\x03\x04e",
+            BDecodeOpt::default(),
+        )
+        .unwrap();
+
+        let expected = "[\n    \"abc...\" (6 bytes)\n    \"\u{e9}...\" (6 bytes)\n    <000102...> (5 bytes)\n]";
+        assert_eq!(
+            BPretty::new(&bencode)
+                .with_max_bytes_len(3)
+                .with_indent(4)
+                .to_string(),
+            expected
+        );
+    }
+
+    #[test]
+    fn positive_pretty_keeps_keys() {
+        let bencode = BencodeRef::decode(b"d6:abcdef6:abcdefe", BDecodeOpt::default()).unwrap();
+
+        assert_eq!(
+            bencode.pretty().with_max_bytes_len(3).to_string(),
+            "{\n  \"abcdef\": \"abc...\" (6 bytes)\n}"
+        );
+    }
+
+    #[test]
+    fn positive_pretty_escapes_strings() {
+        let bencode = BencodeRef::decode(b"d2:a\"1:\ne", BDecodeOpt::default()).unwrap();
+
+        assert_eq!(bencode.pretty().to_string(), "{\n  \"a\\\"\": <0a>\n}");
+    }
+}
@@ -45,7 +45,17 @@ pub use mutable::bencode_mut::BencodeMut;
 
 mod reference;
 pub use reference::bencode_ref::BencodeRef;
-pub use reference::decode_opt::BDecodeOpt;
+pub use reference::decode_opt::{BDecodeLimit, BDecodeOpt};
+
+mod view;
+pub use view::bencode_view::{BDictView, BListView, BencodeView, BencodeViewKind};
+
+#[cfg(feature = "bencode-debug")]
+mod inspect;
+#[cfg(feature = "bencode-debug")]
+pub use inspect::json::BinaryEncoding;
+#[cfg(feature = "bencode-debug")]
+pub use inspect::pretty::BPretty;
 
 mod error;
 pub use error::{BencodeConvertError, BencodeConvertErrorKind, BencodeConvertResult};
@@ -2,6 +2,8 @@ use std::borrow::Cow;
 use std::collections::BTreeMap;
 use std::str;
 
+use bytes::BytesMut;
+
 use crate::bencode::access::bencode::{BMutAccess, BRefAccess, BencodeMutKind, BencodeRefKind};
 use crate::bencode::access::dict::BDictAccess;
 use crate::bencode::access::list::BListAccess;
@@ -53,12 +55,24 @@ impl<'a> BencodeMut<'a> {
 
     /// Encode the `BencodeMut` into a buffer representing the bencode.
     pub fn encode(&self) -> Vec<u8> {
-        let mut buffer = Vec::new();
+        let mut buffer = Vec::with_capacity(self.encoded_len());
 
         encode::encode(self, &mut buffer);
 
         buffer
     }
+
+    /// Encode the `BencodeMut` onto the end of the given buffer.
+    ///
+    /// Grows the buffer by `encoded_len` bytes first, so it is allocated at most once.
+    pub fn encode_into(&self, buffer: &mut BytesMut) {
+        encode::encode_into(self, buffer);
+    }
+
+    /// Number of bytes the `BencodeMut` encodes to.
+    pub fn encoded_len(&self) -> usize {
+        encode::encoded_len(self)
+    }
 }
 
 impl<'a> BRefAccess for BencodeMut<'a> {
@@ -142,6 +156,8 @@ impl<'a> BMutAccess for BencodeMut<'a> {
 
 #[cfg(test)]
 mod test {
+    use bytes::BytesMut;
+
     use crate::bencode::access::bencode::BMutAccess;
     use crate::bencode::mutable::bencode_mut::BencodeMut;
 
@@ -205,4 +221,21 @@ mod test {
         let dict_bytes = b"d3:asd6:asdasde";
         assert_eq!(&dict_bytes[..], &bencode_dict.encode()[..]);
     }
+
+    #[test]
+    fn positive_encode_into_appends() {
+        let mut bencode_list = BencodeMut::new_list();
+
+        {
+            let list_mut = bencode_list.list_mut().unwrap();
+            list_mut.push(BencodeMut::new_int(56));
+            list_mut.push(BencodeMut::new_bytes((&b"asd"[..]).into()));
+        }
+
+        let mut buffer = BytesMut::from(&b"prefix"[..]);
+        bencode_list.encode_into(&mut buffer);
+
+        assert_eq!(11, bencode_list.encoded_len());
+        assert_eq!(&b"prefixli56e3:asde"[..], &buffer[..]);
+    }
 }
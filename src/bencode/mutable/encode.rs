@@ -1,67 +1,280 @@
+use std::vec;
+
+use bytes::BytesMut;
+
 use crate::bencode::access::bencode::{BRefAccess, BencodeRefKind};
-use crate::bencode::access::dict::BDictAccess;
-use crate::bencode::access::list::BListAccess;
+use crate::bencode::access::list::BListIter;
 
-use std::iter::Extend;
+// Enough for any integer, or byte string length, with the bytes around it
+const MAX_TOKEN_LEN: usize = 22;
 
-pub fn encode<T>(val: T, bytes: &mut Vec<u8>)
-where
-    T: BRefAccess,
-    T::BKey: AsRef<[u8]>,
-{
-    match val.kind() {
-        BencodeRefKind::Int(n) => encode_int(n, bytes),
-        BencodeRefKind::Bytes(n) => encode_bytes(&n, bytes),
-        BencodeRefKind::List(n) => encode_list(n, bytes),
-        BencodeRefKind::Dict(n) => encode_dict(n, bytes),
+/// Destination for encoded bytes.
+trait Sink {
+    /// Whether dictionary keys have to be written in sorted order.
+    const ORDERED: bool;
+
+    fn put_byte(&mut self, byte: u8);
+
+    fn put_bytes(&mut self, bytes: &[u8]);
+}
+
+impl Sink for Vec<u8> {
+    const ORDERED: bool = true;
+
+    fn put_byte(&mut self, byte: u8) {
+        self.push(byte);
     }
+
+    fn put_bytes(&mut self, bytes: &[u8]) {
+        self.extend_from_slice(bytes);
+    }
+}
+
+/// Writes into a slice that is exactly as long as the encoded bytes.
+struct SliceSink<'a> {
+    slice: &'a mut [u8],
+    pos: usize,
 }
 
-fn encode_int(val: i64, bytes: &mut Vec<u8>) {
-    bytes.push(crate::bencode::INT_START);
+impl<'a> Sink for SliceSink<'a> {
+    const ORDERED: bool = true;
+
+    fn put_byte(&mut self, byte: u8) {
+        self.slice[self.pos] = byte;
+        self.pos += 1;
+    }
 
-    bytes.extend(val.to_string().into_bytes());
+    fn put_bytes(&mut self, bytes: &[u8]) {
+        let end = self.pos + bytes.len();
 
-    bytes.push(crate::bencode::BEN_END);
+        self.slice[self.pos..end].copy_from_slice(bytes);
+        self.pos = end;
+    }
 }
 
-fn encode_bytes(list: &[u8], bytes: &mut Vec<u8>) {
-    bytes.extend(list.len().to_string().into_bytes());
+/// Counts the bytes that would be written, order doesn't change the length.
+struct Counter(usize);
+
+impl Sink for Counter {
+    const ORDERED: bool = false;
 
-    bytes.push(crate::bencode::BYTE_LEN_END);
+    fn put_byte(&mut self, _byte: u8) {
+        self.0 += 1;
+    }
 
-    bytes.extend(list.iter().map(|n| *n));
+    fn put_bytes(&mut self, bytes: &[u8]) {
+        self.0 += bytes.len();
+    }
 }
 
-fn encode_list<T>(list: &dyn BListAccess<T>, bytes: &mut Vec<u8>)
+/// List or dictionary whose values we are encoding.
+enum Container<'b, V: BRefAccess + 'b> {
+    List(BListIter<'b, V>),
+    Dict(vec::IntoIter<(&'b V::BKey, &'b V)>),
+}
+
+/// Encode the value onto the end of the given buffer.
+pub fn encode<V>(val: &V, bytes: &mut Vec<u8>)
 where
-    T: BRefAccess,
-    T::BKey: AsRef<[u8]>,
+    V: BRefAccess<BType = V>,
+    V::BKey: AsRef<[u8]>,
 {
-    bytes.push(crate::bencode::LIST_START);
+    encode_sink(val, bytes)
+}
 
-    for i in list {
-        encode(i, bytes);
-    }
+/// Encode the value onto the end of the given buffer, growing it once by `encoded_len` bytes.
+pub fn encode_into<V>(val: &V, buffer: &mut BytesMut)
+where
+    V: BRefAccess<BType = V>,
+    V::BKey: AsRef<[u8]>,
+{
+    let start = buffer.len();
+    buffer.resize(start + encoded_len(val), 0);
+
+    let mut sink = SliceSink {
+        slice: &mut buffer[start..],
+        pos: 0,
+    };
+    encode_sink(val, &mut sink);
+}
+
+/// Number of bytes the value encodes to.
+pub fn encoded_len<V>(val: &V) -> usize
+where
+    V: BRefAccess<BType = V>,
+    V::BKey: AsRef<[u8]>,
+{
+    let mut counter = Counter(0);
+    encode_sink(val, &mut counter);
 
-    bytes.push(crate::bencode::BEN_END);
+    counter.0
 }
 
-fn encode_dict<'a, K, V>(dict: &dyn BDictAccess<K, V>, bytes: &mut Vec<u8>)
+/// Encode the value, nested values are encoded with an explicit stack instead of recursion.
+fn encode_sink<'b, V, S>(val: &'b V, sink: &mut S)
 where
-    K: AsRef<[u8]>,
-    V: BRefAccess,
+    V: BRefAccess<BType = V>,
     V::BKey: AsRef<[u8]>,
+    S: Sink,
 {
-    // Need To Sort The Keys In The Map Before Encoding
-    let mut sort_dict = dict.to_list();
-    sort_dict.sort_by(|&(a, _), &(b, _)| a.as_ref().cmp(b.as_ref()));
-
-    bytes.push(crate::bencode::DICT_START);
-    // Iterate And Dictionary Encode The (String, Bencode) Pairs
-    for &(ref key, ref value) in sort_dict.iter() {
-        encode_bytes(key.as_ref(), bytes);
-        encode(*value, bytes);
+    let mut stack: Vec<Container<'b, V>> = Vec::new();
+    let mut opt_next = Some(val);
+
+    loop {
+        if let Some(next) = opt_next.take() {
+            match next.kind() {
+                BencodeRefKind::Int(n) => encode_int(n, sink),
+                BencodeRefKind::Bytes(n) => encode_bytes(n, sink),
+                BencodeRefKind::List(n) => {
+                    sink.put_byte(crate::bencode::LIST_START);
+                    stack.push(Container::List(n.into_iter()));
+                }
+                BencodeRefKind::Dict(n) => {
+                    // Need To Sort The Keys In The Map Before Encoding
+                    let mut sort_dict = n.to_list();
+                    if S::ORDERED {
+                        sort_dict.sort_by(|&(a, _), &(b, _)| a.as_ref().cmp(b.as_ref()));
+                    }
+
+                    sink.put_byte(crate::bencode::DICT_START);
+                    stack.push(Container::Dict(sort_dict.into_iter()));
+                }
+            }
+        }
+
+        let opt_value = match stack.last_mut() {
+            None => return,
+            Some(&mut Container::List(ref mut iter)) => iter.next(),
+            Some(&mut Container::Dict(ref mut iter)) => iter.next().map(|(key, value)| {
+                encode_bytes(key.as_ref(), sink);
+
+                value
+            }),
+        };
+
+        if opt_value.is_none() {
+            stack.pop();
+            sink.put_byte(crate::bencode::BEN_END);
+        }
+        opt_next = opt_value;
+    }
+}
+
+fn encode_int<S: Sink>(val: i64, sink: &mut S) {
+    let mut token = [0u8; MAX_TOKEN_LEN];
+    let mut start = MAX_TOKEN_LEN - 1;
+
+    token[start] = crate::bencode::BEN_END;
+    start = format_digits(val.unsigned_abs(), &mut token[..start]);
+    if val < 0 {
+        start -= 1;
+        token[start] = b'-';
+    }
+    start -= 1;
+    token[start] = crate::bencode::INT_START;
+
+    sink.put_bytes(&token[start..]);
+}
+
+fn encode_bytes<S: Sink>(list: &[u8], sink: &mut S) {
+    let mut token = [0u8; MAX_TOKEN_LEN];
+    let end = MAX_TOKEN_LEN - 1;
+
+    token[end] = crate::bencode::BYTE_LEN_END;
+    let start = format_digits(list.len() as u64, &mut token[..end]);
+
+    sink.put_bytes(&token[start..]);
+    sink.put_bytes(list);
+}
+
+/// Write the decimal digits of the number to the end of the buffer, returning where they start.
+fn format_digits(mut val: u64, digits: &mut [u8]) -> usize {
+    let mut start = digits.len();
+
+    loop {
+        start -= 1;
+        digits[start] = b'0' + (val % 10) as u8;
+        val /= 10;
+
+        if val == 0 {
+            return start;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::BytesMut;
+
+    use crate::bencode::access::bencode::BMutAccess;
+    use crate::bencode::mutable::bencode_mut::BencodeMut;
+    use crate::bencode::mutable::encode;
+
+    fn nested_lists(depth: usize) -> BencodeMut<'static> {
+        let mut bencode = BencodeMut::new_int(0);
+
+        for _ in 0..depth {
+            let mut list = BencodeMut::new_list();
+            list.list_mut().unwrap().push(bencode);
+
+            bencode = list;
+        }
+
+        bencode
+    }
+
+    #[test]
+    fn positive_encode_int_formatting() {
+        for &n in [0, 7, -7, 10, -560, i64::MAX, i64::MIN].iter() {
+            let bencode = BencodeMut::new_int(n);
+
+            assert_eq!(format!("i{}e", n).as_bytes(), &bencode.encode()[..]);
+            assert_eq!(bencode.encode().len(), encode::encoded_len(&bencode));
+        }
+    }
+
+    #[test]
+    fn positive_encode_sorts_keys_at_every_level() {
+        let mut inner = BencodeMut::new_dict();
+        {
+            let dict = inner.dict_mut().unwrap();
+            dict.insert((&b"zz"[..]).into(), BencodeMut::new_int(1));
+            dict.insert((&b"a"[..]).into(), BencodeMut::new_list());
+        }
+        let mut outer = BencodeMut::new_dict();
+        {
+            let dict = outer.dict_mut().unwrap();
+            dict.insert((&b"b"[..]).into(), inner);
+            dict.insert((&b"a"[..]).into(), BencodeMut::new_bytes((&b""[..]).into()));
+        }
+
+        let expected = b"d1:a0:1:bd1:ale2:zzi1eee";
+        assert_eq!(&expected[..], &outer.encode()[..]);
+        assert_eq!(expected.len(), encode::encoded_len(&outer));
+    }
+
+    #[test]
+    fn positive_encode_into_appends() {
+        let bencode = nested_lists(3);
+        let mut buffer = BytesMut::from(&b"prefix"[..]);
+
+        encode::encode_into(&bencode, &mut buffer);
+
+        assert_eq!(&b"prefixllli0eeee"[..], &buffer[..]);
+    }
+
+    #[test]
+    fn positive_encode_deep_nesting() {
+        let bencode = nested_lists(100_000);
+        let encoded = bencode.encode();
+
+        assert_eq!(200_003, encoded.len());
+        assert_eq!(&b"i0e"[..], &encoded[100_000..100_003]);
+
+        // Dropping is recursive, take the nesting apart ourselves
+        let mut bencode = bencode;
+        while let Some(inner) = bencode.list_mut().and_then(|list| list.remove(0)) {
+            bencode = inner;
+        }
     }
-    bytes.push(crate::bencode::BEN_END);
 }
@@ -1,64 +1,165 @@
 use std::collections::btree_map::Entry;
 use std::collections::BTreeMap;
+use std::num::IntErrorKind;
 use std::str::{self};
 
 use crate::bencode::error::{BencodeParseError, BencodeParseErrorKind, BencodeParseResult};
 use crate::bencode::reference::bencode_ref::{BencodeRef, InnerBencodeRef};
-use crate::bencode::reference::decode_opt::BDecodeOpt;
+use crate::bencode::reference::decode_opt::{BDecodeLimit, BDecodeOpt};
+
+/// List or dictionary whose values we are decoding.
+enum Container<'a> {
+    List(Vec<BencodeRef<'a>>),
+    // Key for the value being decoded, along with the position of the value
+    Dict(
+        BTreeMap<&'a [u8], BencodeRef<'a>>,
+        Option<(&'a [u8], usize)>,
+    ),
+}
+
+/// What we expect to find next in the bytes.
+enum Expect {
+    Value,
+    // Either the end of the current container, or its next value
+    EndOrValue,
+    EndOrKey,
+}
 
+/// Decode the value starting at `pos`, returning it along with the position after it.
+///
+/// Nested values are decoded with an explicit stack instead of recursion, so deeply nested
+/// bytes can not overflow our call stack.
 pub fn decode<'a>(
     bytes: &'a [u8],
     pos: usize,
     opts: BDecodeOpt,
-    depth: usize,
-) -> Result<(BencodeRef<'a>, usize), BencodeParseError> {
-    if depth >= opts.max_recursion() {
-        return Err(BencodeParseError::from_kind(
-            BencodeParseErrorKind::InvalidRecursionExceeded {
-                pos: pos,
-                max: depth,
-            },
-        ));
-    }
-    let curr_byte = peek_byte(bytes, pos)?;
+) -> BencodeParseResult<(BencodeRef<'a>, usize)> {
+    // Containers we are in, along with the position they start at
+    let mut stack: Vec<(Container<'a>, usize)> = Vec::new();
+    let mut tokens = 0;
+    let mut curr_pos = pos;
 
-    match curr_byte {
-        crate::bencode::INT_START => {
-            let (bencode, next_pos) = decode_int(bytes, pos + 1, crate::bencode::BEN_END)?;
-            Ok((
-                InnerBencodeRef::Int(bencode, &bytes[pos..next_pos]).into(),
-                next_pos,
-            ))
-        }
-        crate::bencode::LIST_START => {
-            let (bencode, next_pos) = decode_list(bytes, pos + 1, opts, depth)?;
-            Ok((
-                InnerBencodeRef::List(bencode, &bytes[pos..next_pos]).into(),
-                next_pos,
-            ))
-        }
-        crate::bencode::DICT_START => {
-            let (bencode, next_pos) = decode_dict(bytes, pos + 1, opts, depth)?;
-            Ok((
-                InnerBencodeRef::Dict(bencode, &bytes[pos..next_pos]).into(),
-                next_pos,
-            ))
-        }
-        crate::bencode::BYTE_LEN_LOW..=crate::bencode::BYTE_LEN_HIGH => {
-            let (bencode, next_pos) = decode_bytes(bytes, pos)?;
-            // Include the length digit, don't increment position
-            Ok((
-                InnerBencodeRef::Bytes(bencode, &bytes[pos..next_pos]).into(),
-                next_pos,
-            ))
+    loop {
+        let curr_byte = peek_container_byte(bytes, curr_pos, stack.last().map(|n| n.1))?;
+        let expect = match stack.last() {
+            None => Expect::Value,
+            Some(&(Container::List(_), _)) => Expect::EndOrValue,
+            Some(&(Container::Dict(_, None), _)) => Expect::EndOrKey,
+            Some(&(Container::Dict(_, Some(_)), _)) => Expect::Value,
+        };
+
+        let (bencode, next_pos) = match expect {
+            Expect::EndOrValue | Expect::EndOrKey if curr_byte == crate::bencode::BEN_END => {
+                let (container, start_pos) = stack.pop().unwrap();
+                let next_pos = curr_pos + 1;
+                let buffer = &bytes[start_pos..next_pos];
+
+                let bencode = match container {
+                    Container::List(list) => InnerBencodeRef::List(list, buffer),
+                    Container::Dict(dict, _) => InnerBencodeRef::Dict(dict, buffer),
+                };
+                (bencode.into(), next_pos)
+            }
+            Expect::EndOrKey => {
+                count_token(&mut tokens, curr_pos, opts)?;
+                let (key_bytes, next_pos) = decode_limited_bytes(bytes, curr_pos, opts)?;
+
+                if let Some(&mut (Container::Dict(ref dict, ref mut opt_key), _)) = stack.last_mut()
+                {
+                    // Spec says that the keys must be in alphabetical order
+                    match (dict.keys().last(), opts.check_key_sort()) {
+                        (Some(last_key), true) if key_bytes < *last_key => {
+                            return Err(BencodeParseError::from_kind(
+                                BencodeParseErrorKind::InvalidKeyOrdering {
+                                    pos: curr_pos,
+                                    key: key_bytes.to_vec(),
+                                },
+                            ))
+                        }
+                        _ => (),
+                    };
+                    *opt_key = Some((key_bytes, next_pos));
+                }
+
+                curr_pos = next_pos;
+                continue;
+            }
+            Expect::Value | Expect::EndOrValue => {
+                if stack.len() >= opts.max_recursion() {
+                    return Err(limit_exceeded(
+                        curr_pos,
+                        BDecodeLimit::MaxRecursion,
+                        opts.max_recursion(),
+                    ));
+                }
+                count_token(&mut tokens, curr_pos, opts)?;
+
+                match curr_byte {
+                    crate::bencode::INT_START => {
+                        let (bencode, next_pos) =
+                            decode_int(bytes, curr_pos + 1, crate::bencode::BEN_END)?;
+
+                        (
+                            InnerBencodeRef::Int(bencode, &bytes[curr_pos..next_pos]).into(),
+                            next_pos,
+                        )
+                    }
+                    crate::bencode::LIST_START => {
+                        stack.push((Container::List(Vec::new()), curr_pos));
+
+                        curr_pos += 1;
+                        continue;
+                    }
+                    crate::bencode::DICT_START => {
+                        stack.push((Container::Dict(BTreeMap::new(), None), curr_pos));
+
+                        curr_pos += 1;
+                        continue;
+                    }
+                    crate::bencode::BYTE_LEN_LOW..=crate::bencode::BYTE_LEN_HIGH => {
+                        let (bencode, next_pos) = decode_limited_bytes(bytes, curr_pos, opts)?;
+
+                        // Include the length digit, don't increment position
+                        (
+                            InnerBencodeRef::Bytes(bencode, &bytes[curr_pos..next_pos]).into(),
+                            next_pos,
+                        )
+                    }
+                    _ => {
+                        return Err(BencodeParseError::from_kind(
+                            BencodeParseErrorKind::InvalidByte { pos: curr_pos },
+                        ))
+                    }
+                }
+            }
+        };
+
+        match stack.last_mut() {
+            None => return Ok((bencode, next_pos)),
+            Some(&mut (Container::List(ref mut list), _)) => list.push(bencode),
+            Some(&mut (Container::Dict(ref mut dict, ref mut opt_key), _)) => {
+                let (key_bytes, value_pos) = opt_key
+                    .take()
+                    .expect("bittorrent-protocol_bencode: Decoded Dictionary Value Without Key");
+
+                match dict.entry(key_bytes) {
+                    Entry::Vacant(n) => n.insert(bencode),
+                    Entry::Occupied(_) => {
+                        return Err(BencodeParseError::from_kind(
+                            BencodeParseErrorKind::InvalidKeyDuplicates {
+                                pos: value_pos,
+                                key: key_bytes.to_vec(),
+                            },
+                        ))
+                    }
+                };
+            }
         }
-        _ => Err(BencodeParseError::from_kind(
-            BencodeParseErrorKind::InvalidByte { pos: pos },
-        )),
+        curr_pos = next_pos;
     }
 }
 
-fn decode_int<'a>(bytes: &'a [u8], pos: usize, delim: u8) -> BencodeParseResult<(i64, usize)> {
+pub fn decode_int<'a>(bytes: &'a [u8], pos: usize, delim: u8) -> BencodeParseResult<(i64, usize)> {
     let (_, begin_decode) = bytes.split_at(pos);
 
     let relative_end_pos = match begin_decode.iter().position(|n| *n == delim) {
@@ -101,9 +202,16 @@ fn decode_int<'a>(bytes: &'a [u8], pos: usize, delim: u8) -> BencodeParseResult<
     let next_pos = absolute_end_pos + 1;
     match i64::from_str_radix(int_str, 10) {
         Ok(n) => Ok((n, next_pos)),
-        Err(_) => Err(BencodeParseError::from_kind(
-            BencodeParseErrorKind::InvalidIntParseError { pos: pos },
-        )),
+        Err(err) => match *err.kind() {
+            IntErrorKind::PosOverflow | IntErrorKind::NegOverflow => {
+                Err(BencodeParseError::from_kind(
+                    BencodeParseErrorKind::InvalidIntOverflow { pos: pos },
+                ))
+            }
+            _ => Err(BencodeParseError::from_kind(
+                BencodeParseErrorKind::InvalidIntParseError { pos: pos },
+            )),
+        },
     }
 }
 
@@ -131,93 +239,95 @@ fn decode_bytes<'a>(bytes: &'a [u8], pos: usize) -> BencodeParseResult<(&'a [u8]
     Ok((&bytes[start_pos..next_pos], next_pos))
 }
 
-fn decode_list<'a>(
+pub fn decode_limited_bytes<'a>(
     bytes: &'a [u8],
     pos: usize,
     opts: BDecodeOpt,
-    depth: usize,
-) -> BencodeParseResult<(Vec<BencodeRef<'a>>, usize)> {
-    let mut bencode_list = Vec::new();
-
-    let mut curr_pos = pos;
-    let mut curr_byte = peek_byte(bytes, curr_pos)?;
-
-    while curr_byte != crate::bencode::BEN_END {
-        let (bencode, next_pos) = decode(bytes, curr_pos, opts, depth + 1)?;
-
-        bencode_list.push(bencode);
-
-        curr_pos = next_pos;
-        curr_byte = peek_byte(bytes, curr_pos)?;
+) -> BencodeParseResult<(&'a [u8], usize)> {
+    let (decoded, next_pos) = decode_bytes(bytes, pos)?;
+
+    if decoded.len() > opts.max_bytes_len() {
+        Err(limit_exceeded(
+            pos,
+            BDecodeLimit::MaxBytesLen,
+            opts.max_bytes_len(),
+        ))
+    } else {
+        Ok((decoded, next_pos))
     }
-
-    let next_pos = curr_pos + 1;
-    Ok((bencode_list, next_pos))
 }
 
-fn decode_dict<'a>(
-    bytes: &'a [u8],
-    pos: usize,
-    opts: BDecodeOpt,
-    depth: usize,
-) -> BencodeParseResult<(BTreeMap<&'a [u8], BencodeRef<'a>>, usize)> {
-    let mut bencode_dict = BTreeMap::new();
-
-    let mut curr_pos = pos;
-    let mut curr_byte = peek_byte(bytes, curr_pos)?;
-
-    while curr_byte != crate::bencode::BEN_END {
-        let (key_bytes, next_pos) = decode_bytes(bytes, curr_pos)?;
-
-        // Spec says that the keys must be in alphabetical order
-        match (bencode_dict.keys().last(), opts.check_key_sort()) {
-            (Some(last_key), true) if key_bytes < *last_key => {
-                return Err(BencodeParseError::from_kind(
-                    BencodeParseErrorKind::InvalidKeyOrdering {
-                        pos: curr_pos,
-                        key: key_bytes.to_vec(),
-                    },
-                ))
-            }
-            _ => (),
-        };
-        curr_pos = next_pos;
-
-        let (value, next_pos) = decode(bytes, curr_pos, opts, depth + 1).unwrap();
-        match bencode_dict.entry(key_bytes) {
-            Entry::Vacant(n) => n.insert(value),
-            Entry::Occupied(_) => {
-                return Err(BencodeParseError::from_kind(
-                    BencodeParseErrorKind::InvalidKeyDuplicates {
-                        pos: curr_pos,
-                        key: key_bytes.to_vec(),
-                    },
-                ))
-            }
-        };
-
-        curr_pos = next_pos;
-        curr_byte = peek_byte(bytes, curr_pos)?;
+pub fn count_token(tokens: &mut usize, pos: usize, opts: BDecodeOpt) -> BencodeParseResult<()> {
+    *tokens += 1;
+
+    if *tokens > opts.max_tokens() {
+        Err(limit_exceeded(
+            pos,
+            BDecodeLimit::MaxTokens,
+            opts.max_tokens(),
+        ))
+    } else {
+        Ok(())
     }
+}
 
-    let next_pos = curr_pos + 1;
-    Ok((bencode_dict, next_pos))
+pub fn limit_exceeded(pos: usize, limit: BDecodeLimit, max: usize) -> BencodeParseError {
+    BencodeParseError::from_kind(BencodeParseErrorKind::LimitExceeded {
+        pos: pos,
+        limit: limit,
+        max: max,
+    })
 }
 
-fn peek_byte(bytes: &[u8], pos: usize) -> BencodeParseResult<u8> {
+pub fn peek_byte(bytes: &[u8], pos: usize) -> BencodeParseResult<u8> {
     bytes
         .get(pos)
         .map(|n| *n)
         .ok_or_else(|| BencodeParseError::from_kind(BencodeParseErrorKind::BytesEmpty { pos: pos }))
 }
 
+/// Peek at the byte at `pos`, running out of bytes in the container starting at `opt_start`
+/// means that container has no end.
+pub fn peek_container_byte(
+    bytes: &[u8],
+    pos: usize,
+    opt_start: Option<usize>,
+) -> BencodeParseResult<u8> {
+    match (bytes.get(pos), opt_start) {
+        (Some(&byte), _) => Ok(byte),
+        (None, Some(start)) => Err(BencodeParseError::from_kind(
+            BencodeParseErrorKind::InvalidContainerNoEnd {
+                pos: pos,
+                start: start,
+            },
+        )),
+        (None, None) => peek_byte(bytes, pos),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use std::default::Default;
 
     use crate::bencode::access::bencode::BRefAccess;
+    use crate::bencode::error::BencodeParseErrorKind;
     use crate::bencode::reference::bencode_ref::BencodeRef;
-    use crate::bencode::reference::decode_opt::BDecodeOpt;
+    use crate::bencode::reference::decode_opt::{BDecodeLimit, BDecodeOpt};
+    use crate::quickcheck::{QuickCheck, TestResult};
+
+    fn exceeded_limit(bytes: &[u8], opts: BDecodeOpt) -> Option<BDecodeLimit> {
+        match BencodeRef::decode(bytes, opts).map_err(|err| err.0) {
+            Err(BencodeParseErrorKind::LimitExceeded { limit, .. }) => Some(limit),
+            _ => None,
+        }
+    }
+
+    fn nested_lists(depth: usize) -> Vec<u8> {
+        let mut bytes = vec![b'l'; depth];
+        bytes.extend(vec![b'e'; depth]);
+
+        bytes
+    }
 
     // Positive Cases
     const GENERAL: &'static [u8] = b"d0:12:zero_len_key8:location17:udp://test.com:8011:nested dictd4:listli-500500eee6:numberi500500ee";
@@ -479,4 +589,159 @@ mod tests {
     fn negative_decode_dict_dup_keys_diff_data() {
         BencodeRef::decode(DICT_DUP_KEYS_DIFF_DATA, BDecodeOpt::default()).unwrap();
     }
+
+    #[test]
+    fn negative_decode_dict_truncated_value() {
+        let result = BencodeRef::decode(b"d3:keyi5", BDecodeOpt::default());
+
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn negative_decode_int_overflow() {
+        let error =
+            BencodeRef::decode(b"li1ei9223372036854775808ee", BDecodeOpt::default()).unwrap_err();
+
+        assert_eq!(Some(5), error.pos());
+        match error.0 {
+            BencodeParseErrorKind::InvalidIntOverflow { pos: 5 } => (),
+            other => panic!("Unexpected Error: {:?}", other),
+        }
+        BencodeRef::decode(b"i-9223372036854775808e", BDecodeOpt::default()).unwrap();
+    }
+
+    #[test]
+    fn negative_decode_bytes_len_overflow() {
+        match BencodeRef::decode(b"99999999999999999999:a", BDecodeOpt::default())
+            .map_err(|err| err.0)
+        {
+            Err(BencodeParseErrorKind::InvalidIntOverflow { pos: 0 }) => (),
+            other => panic!("Unexpected Result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn negative_decode_container_no_end() {
+        let error = BencodeRef::decode(b"d3:keyli1e", BDecodeOpt::default()).unwrap_err();
+
+        assert_eq!(Some(10), error.pos());
+        match error.0 {
+            BencodeParseErrorKind::InvalidContainerNoEnd { pos: 10, start: 6 } => (),
+            other => panic!("Unexpected Error: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn negative_decode_strict_unordered_keys() {
+        let opts = BDecodeOpt::default().with_strict(true);
+
+        match BencodeRef::decode(DICT_UNORDERED_KEYS, opts).map_err(|err| err.0) {
+            Err(BencodeParseErrorKind::InvalidKeyOrdering { pos: 15, ref key })
+                if key == b"a_key" =>
+            {
+                ()
+            }
+            other => panic!("Unexpected Result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn negative_decode_strict_partial() {
+        let opts = BDecodeOpt::new(5, false, false).with_strict(true);
+
+        match BencodeRef::decode(PARTIAL, opts).map_err(|err| err.0) {
+            Err(BencodeParseErrorKind::InvalidByte { pos: 3 }) => (),
+            other => panic!("Unexpected Result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn positive_decode_at_limits() {
+        let opts = BDecodeOpt::new(3, false, true)
+            .with_max_tokens(5)
+            .with_max_bytes_len(3);
+
+        BencodeRef::decode(b"ll3:abcee", opts).unwrap();
+        BencodeRef::decode(b"d1:a1:b1:ci0ee", opts).unwrap();
+    }
+
+    #[test]
+    fn negative_decode_max_recursion_exceeded() {
+        let opts = BDecodeOpt::default();
+
+        assert_eq!(
+            None,
+            exceeded_limit(&nested_lists(opts.max_recursion()), opts)
+        );
+        assert_eq!(
+            Some(BDecodeLimit::MaxRecursion),
+            exceeded_limit(&nested_lists(opts.max_recursion() + 1), opts)
+        );
+    }
+
+    #[test]
+    fn negative_decode_max_tokens_exceeded() {
+        let opts = BDecodeOpt::default().with_max_tokens(3);
+
+        assert_eq!(
+            Some(BDecodeLimit::MaxTokens),
+            exceeded_limit(b"li1ei2ei3ee", opts)
+        );
+        // Dictionary keys count as well
+        assert_eq!(
+            Some(BDecodeLimit::MaxTokens),
+            exceeded_limit(b"d1:ai1e1:bi2ee", opts)
+        );
+    }
+
+    #[test]
+    fn negative_decode_max_bytes_len_exceeded() {
+        let opts = BDecodeOpt::default().with_max_bytes_len(3);
+
+        assert_eq!(
+            Some(BDecodeLimit::MaxBytesLen),
+            exceeded_limit(b"4:abcd", opts)
+        );
+        assert_eq!(
+            Some(BDecodeLimit::MaxBytesLen),
+            exceeded_limit(b"d4:abcdi0ee", opts)
+        );
+    }
+
+    #[test]
+    fn negative_decode_deep_nesting_without_recursion_limit() {
+        // Decoding never recurses, so only the memory for each level is needed
+        let opts = BDecodeOpt::new(usize::MAX, false, true);
+
+        let bytes = vec![b'l'; 1_000_000];
+
+        assert!(BencodeRef::decode(&bytes, opts).is_err());
+    }
+
+    // Use quickcheck to simulate a malicious peer sending deeply nested bencode
+    #[test]
+    fn quicktest_nested_input() {
+        fn run(depth: u16, tokens: Vec<u8>) -> TestResult {
+            let mut bytes = vec![b'l'; depth as usize];
+            for token in tokens {
+                let token_bytes: &[u8] = match token % 5 {
+                    0 => b"l",
+                    1 => b"d",
+                    2 => b"e",
+                    3 => b"i0e",
+                    _ => b"1:a",
+                };
+                bytes.extend_from_slice(token_bytes);
+            }
+            bytes.extend(vec![b'e'; depth as usize]);
+
+            match BencodeRef::decode(&bytes, BDecodeOpt::default()) {
+                Ok(bencode) => TestResult::from_bool(bencode.buffer() == &bytes[..]),
+                Err(_) => TestResult::passed(),
+            }
+        }
+        QuickCheck::new()
+            .tests(1000)
+            .quickcheck(run as fn(u16, Vec<u8>) -> TestResult)
+    }
 }
@@ -3,6 +3,20 @@ use std::default::Default;
 const DEFAULT_MAX_RECURSION: usize = 50;
 const DEFAULT_CHECK_KEY_SORT: bool = false;
 const DEFAULT_ENFORCE_FULL_DECODE: bool = true;
+const DEFAULT_MAX_TOKENS: usize = usize::MAX;
+const DEFAULT_MAX_BYTES_LEN: usize = usize::MAX;
+const DEFAULT_STRICT: bool = false;
+
+/// Limit on decoding that was exceeded.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum BDecodeLimit {
+    /// Values were nested deeper than `max_recursion`.
+    MaxRecursion,
+    /// More than `max_tokens` values were decoded.
+    MaxTokens,
+    /// A byte string was longer than `max_bytes_len`.
+    MaxBytesLen,
+}
 
 /// Stores decoding options for modifying decode behavior.
 #[derive(Copy, Clone)]
@@ -10,6 +24,9 @@ pub struct BDecodeOpt {
     max_recursion: usize,
     check_key_sort: bool,
     enforce_full_decode: bool,
+    max_tokens: usize,
+    max_bytes_len: usize,
+    strict: bool,
 }
 
 impl BDecodeOpt {
@@ -23,17 +40,56 @@ impl BDecodeOpt {
             max_recursion: max_recursion,
             check_key_sort: check_key_sort,
             enforce_full_decode: enforce_full_decode,
+            max_tokens: DEFAULT_MAX_TOKENS,
+            max_bytes_len: DEFAULT_MAX_BYTES_LEN,
+            strict: DEFAULT_STRICT,
         }
     }
 
+    /// Limit the number of values decoded, counting dictionary keys as values.
+    pub fn with_max_tokens(mut self, max_tokens: usize) -> BDecodeOpt {
+        self.max_tokens = max_tokens;
+        self
+    }
+
+    /// Limit the length of byte strings, including dictionary keys.
+    pub fn with_max_bytes_len(mut self, max_bytes_len: usize) -> BDecodeOpt {
+        self.max_bytes_len = max_bytes_len;
+        self
+    }
+
+    /// Only accept canonical bencode, rejecting out of order dictionary keys and trailing bytes.
+    ///
+    /// Duplicate keys are always rejected. Canonical bencode is what has to be hashed, for
+    /// example the info dictionary of a torrent, so that re-encoding it gives the same bytes.
+    pub fn with_strict(mut self, strict: bool) -> BDecodeOpt {
+        self.strict = strict;
+        self
+    }
+
     /// Maximum limit allowed when decoding bencode.
     pub fn max_recursion(&self) -> usize {
         self.max_recursion
     }
 
+    /// Maximum number of values allowed when decoding bencode.
+    pub fn max_tokens(&self) -> usize {
+        self.max_tokens
+    }
+
+    /// Maximum length of byte strings allowed when decoding bencode.
+    pub fn max_bytes_len(&self) -> usize {
+        self.max_bytes_len
+    }
+
     /// Whether or not an error should be thrown for out of order dictionary keys.
     pub fn check_key_sort(&self) -> bool {
-        self.check_key_sort
+        self.check_key_sort || self.strict
+    }
+
+    /// Whether or not only canonical bencode is accepted.
+    pub fn strict(&self) -> bool {
+        self.strict
     }
 
     /// Whether or not we enforce that the decoded bencode must make up all of the input
@@ -43,7 +99,7 @@ impl BDecodeOpt {
     /// some payload and you would like to disassociate it. In this case, to find where the
     /// rest of the payload starts that wasn't decoded, get the bencode buffer, and call len().
     pub fn enforce_full_decode(&self) -> bool {
-        self.enforce_full_decode
+        self.enforce_full_decode || self.strict
     }
 }
 
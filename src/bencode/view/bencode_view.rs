@@ -0,0 +1,429 @@
+use std::str;
+
+use bytes::Bytes;
+
+use crate::bencode::error::{BencodeParseError, BencodeParseErrorKind, BencodeParseResult};
+use crate::bencode::reference::decode;
+use crate::bencode::reference::decode_opt::BDecodeOpt;
+use crate::bencode::view::validate;
+
+const VALIDATED_EXPECT: &'static str =
+    "bittorrent-protocol_bencode: Validated Bencode Failed To Parse";
+
+/// Bencode object that is a view into a shared buffer.
+///
+/// Decoding only validates the buffer, values are parsed as they are accessed, and byte strings
+/// are slices of the buffer instead of copies.
+#[derive(Debug, Eq, PartialEq, Clone, Hash)]
+pub struct BencodeView {
+    buffer: Bytes,
+}
+
+/// Enumeration of the values a `BencodeView` can hold.
+pub enum BencodeViewKind<'a> {
+    /// Bencode Integer.
+    Int(i64),
+    /// Bencode Bytes.
+    Bytes(Bytes),
+    /// Bencode List.
+    List(BListView<'a>),
+    /// Bencode Dictionary.
+    Dict(BDictView<'a>),
+}
+
+impl BencodeView {
+    fn new(buffer: Bytes) -> BencodeView {
+        BencodeView { buffer: buffer }
+    }
+
+    /// Decode the given bytes into a `BencodeView` using the given decode options.
+    ///
+    /// Accepts and rejects the same bytes as `BencodeRef::decode`.
+    pub fn decode(bytes: Bytes, opts: BDecodeOpt) -> BencodeParseResult<BencodeView> {
+        // Apply try so any errors return before the eof check
+        let end_pos = validate::validate(&bytes, 0, opts)?;
+
+        if end_pos != bytes.len() && opts.enforce_full_decode() {
+            return Err(BencodeParseError::from_kind(
+                BencodeParseErrorKind::InvalidByte { pos: end_pos },
+            ));
+        }
+
+        let mut bytes = bytes;
+        bytes.truncate(end_pos);
+
+        Ok(BencodeView::new(bytes))
+    }
+
+    /// Get the current bencode byte representation, which shares the decoded buffer.
+    pub fn buffer(&self) -> &Bytes {
+        &self.buffer
+    }
+
+    /// Get the value the bencode holds.
+    pub fn kind(&self) -> BencodeViewKind<'_> {
+        match self.buffer[0] {
+            crate::bencode::INT_START => BencodeViewKind::Int(self.parse_int()),
+            crate::bencode::LIST_START => BencodeViewKind::List(BListView::new(&self.buffer)),
+            crate::bencode::DICT_START => BencodeViewKind::Dict(BDictView::new(&self.buffer)),
+            _ => {
+                let (start, end) = bytes_range(&self.buffer, 0);
+
+                BencodeViewKind::Bytes(self.buffer.slice(start, end))
+            }
+        }
+    }
+
+    /// Attempt to access the bencode as an `i64`.
+    pub fn int(&self) -> Option<i64> {
+        if self.buffer[0] == crate::bencode::INT_START {
+            Some(self.parse_int())
+        } else {
+            None
+        }
+    }
+
+    /// Attempt to access the bencode as bytes, sharing the decoded buffer.
+    pub fn bytes(&self) -> Option<Bytes> {
+        self.bytes_range()
+            .map(|(start, end)| self.buffer.slice(start, end))
+    }
+
+    /// Attempt to access the bencode as a `str`.
+    pub fn str(&self) -> Option<&str> {
+        self.bytes_range()
+            .and_then(|(start, end)| str::from_utf8(&self.buffer[start..end]).ok())
+    }
+
+    /// Attempt to access the bencode as a list.
+    pub fn list(&self) -> Option<BListView<'_>> {
+        if self.buffer[0] == crate::bencode::LIST_START {
+            Some(BListView::new(&self.buffer))
+        } else {
+            None
+        }
+    }
+
+    /// Attempt to access the bencode as a dictionary.
+    pub fn dict(&self) -> Option<BDictView<'_>> {
+        if self.buffer[0] == crate::bencode::DICT_START {
+            Some(BDictView::new(&self.buffer))
+        } else {
+            None
+        }
+    }
+
+    fn parse_int(&self) -> i64 {
+        decode::decode_int(&self.buffer, 1, crate::bencode::BEN_END)
+            .expect(VALIDATED_EXPECT)
+            .0
+    }
+
+    fn bytes_range(&self) -> Option<(usize, usize)> {
+        match self.buffer[0] {
+            crate::bencode::BYTE_LEN_LOW..=crate::bencode::BYTE_LEN_HIGH => {
+                Some(bytes_range(&self.buffer, 0))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Iterator over the values of a bencode list, parsed as they are reached.
+#[derive(Debug, Clone)]
+pub struct BListView<'a> {
+    buffer: &'a Bytes,
+    pos: usize,
+}
+
+impl<'a> BListView<'a> {
+    fn new(buffer: &'a Bytes) -> BListView<'a> {
+        BListView {
+            buffer: buffer,
+            pos: 1,
+        }
+    }
+}
+
+impl<'a> Iterator for BListView<'a> {
+    type Item = BencodeView;
+
+    fn next(&mut self) -> Option<BencodeView> {
+        if self.buffer[self.pos] == crate::bencode::BEN_END {
+            return None;
+        }
+
+        let end_pos = value_end(self.buffer, self.pos);
+        let value = self.buffer.slice(self.pos, end_pos);
+        self.pos = end_pos;
+
+        Some(BencodeView::new(value))
+    }
+}
+
+/// Iterator over the entries of a bencode dictionary, parsed as they are reached.
+///
+/// Entries are in the order they were encoded in.
+#[derive(Debug, Clone)]
+pub struct BDictView<'a> {
+    buffer: &'a Bytes,
+    pos: usize,
+}
+
+impl<'a> BDictView<'a> {
+    fn new(buffer: &'a Bytes) -> BDictView<'a> {
+        BDictView {
+            buffer: buffer,
+            pos: 1,
+        }
+    }
+
+    /// Lookup the value for the given key in the remaining entries.
+    pub fn lookup(&self, key: &[u8]) -> Option<BencodeView> {
+        let mut pos = self.pos;
+
+        while self.buffer[pos] != crate::bencode::BEN_END {
+            let (key_start, key_end) = bytes_range(self.buffer, pos);
+            let value_end = value_end(self.buffer, key_end);
+
+            if &self.buffer[key_start..key_end] == key {
+                return Some(BencodeView::new(self.buffer.slice(key_end, value_end)));
+            }
+            pos = value_end;
+        }
+
+        None
+    }
+}
+
+impl<'a> Iterator for BDictView<'a> {
+    type Item = (Bytes, BencodeView);
+
+    fn next(&mut self) -> Option<(Bytes, BencodeView)> {
+        if self.buffer[self.pos] == crate::bencode::BEN_END {
+            return None;
+        }
+
+        let (key_start, key_end) = bytes_range(self.buffer, self.pos);
+        let value_end = value_end(self.buffer, key_end);
+        let key = self.buffer.slice(key_start, key_end);
+        let value = self.buffer.slice(key_end, value_end);
+        self.pos = value_end;
+
+        Some((key, BencodeView::new(value)))
+    }
+}
+
+/// Start and end of the contents of the validated byte string at `pos`.
+fn bytes_range(buffer: &[u8], mut pos: usize) -> (usize, usize) {
+    // Validation already rejected lengths that are malformed or run past the buffer
+    let mut num_bytes = 0usize;
+    while buffer[pos] != crate::bencode::BYTE_LEN_END {
+        num_bytes = num_bytes * 10 + (buffer[pos] - crate::bencode::BYTE_LEN_LOW) as usize;
+
+        pos += 1;
+    }
+
+    (pos + 1, pos + 1 + num_bytes)
+}
+
+/// Position after the validated value at `pos`, skipping nested values without recursion.
+fn value_end(buffer: &[u8], mut pos: usize) -> usize {
+    let mut depth = 0usize;
+
+    loop {
+        match buffer[pos] {
+            crate::bencode::INT_START => {
+                let relative_end_pos = buffer[pos..]
+                    .iter()
+                    .position(|n| *n == crate::bencode::BEN_END)
+                    .expect(VALIDATED_EXPECT);
+
+                pos += relative_end_pos + 1;
+            }
+            crate::bencode::LIST_START | crate::bencode::DICT_START => {
+                depth += 1;
+
+                pos += 1;
+                continue;
+            }
+            crate::bencode::BEN_END => {
+                depth -= 1;
+
+                pos += 1;
+            }
+            _ => pos = bytes_range(buffer, pos).1,
+        }
+
+        if depth == 0 {
+            return pos;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+
+    use crate::bencode::access::bencode::{BRefAccess, BencodeRefKind};
+    use crate::bencode::access::dict::BDictAccess;
+    use crate::bencode::reference::bencode_ref::BencodeRef;
+    use crate::bencode::reference::decode_opt::BDecodeOpt;
+    use crate::bencode::view::bencode_view::{BencodeView, BencodeViewKind};
+    use crate::quickcheck::{QuickCheck, TestResult};
+
+    const GENERAL: &'static [u8] = b"d0:12:zero_len_key8:location17:udp://test.com:8011:nested dictd4:listli-500500eee6:numberi500500ee";
+    const LONG_BYTES: &'static [u8] =
+        b"l64:0123456789012345678901234567890123456789012345678901234567890123e";
+
+    /// Whether the view holds the same values as the reference, in the same order.
+    fn same_values(view: &BencodeView, bencode: &BencodeRef) -> bool {
+        if view.buffer().as_ref() != bencode.buffer() {
+            return false;
+        }
+
+        match (view.kind(), bencode.kind()) {
+            (BencodeViewKind::Int(a), BencodeRefKind::Int(b)) => a == b,
+            (BencodeViewKind::Bytes(a), BencodeRefKind::Bytes(b)) => &a[..] == b,
+            (BencodeViewKind::List(a), BencodeRefKind::List(b)) => {
+                let values: Vec<_> = a.collect();
+
+                values.len() == b.len() && values.iter().zip(b).all(|(a, b)| same_values(a, b))
+            }
+            (BencodeViewKind::Dict(a), BencodeRefKind::Dict(b)) => {
+                let entries: Vec<_> = a.collect();
+                let mut b_entries = b.to_list();
+                b_entries.sort_by_key(|&(_, value)| value.buffer().as_ptr());
+
+                entries.len() == b_entries.len()
+                    && entries
+                        .iter()
+                        .zip(b_entries)
+                        .all(|(a, b)| &a.0[..] == *b.0 && same_values(&a.1, b.1))
+            }
+            _ => false,
+        }
+    }
+
+    #[test]
+    fn positive_view_general() {
+        let view = BencodeView::decode(Bytes::from(GENERAL), BDecodeOpt::default()).unwrap();
+        let dict = view.dict().unwrap();
+
+        assert_eq!(&GENERAL[..], &view.buffer()[..]);
+        assert_eq!(
+            &b"zero_len_key"[..],
+            &dict.lookup(b"").unwrap().bytes().unwrap()[..]
+        );
+        assert_eq!(
+            "udp://test.com:80",
+            dict.lookup(b"location").unwrap().str().unwrap()
+        );
+        assert_eq!(500500, dict.lookup(b"number").unwrap().int().unwrap());
+
+        let nested = dict.lookup(b"nested dict").unwrap();
+        let list = nested.dict().unwrap().lookup(b"list").unwrap();
+        let mut list = list.list().unwrap();
+        assert_eq!(-500500, list.next().unwrap().int().unwrap());
+        assert!(list.next().is_none());
+
+        assert!(dict.lookup(b"missing").is_none());
+        assert!(view.int().is_none());
+        assert!(view.list().is_none());
+    }
+
+    #[test]
+    fn positive_view_dict_iterates_in_order() {
+        let view =
+            BencodeView::decode(Bytes::from(&b"d1:ai1e1:bli2eee"[..]), BDecodeOpt::default())
+                .unwrap();
+
+        let entries: Vec<_> = view
+            .dict()
+            .unwrap()
+            .map(|(key, value)| (key, value.buffer().clone()))
+            .collect();
+
+        assert_eq!(
+            vec![
+                (Bytes::from(&b"a"[..]), Bytes::from(&b"i1e"[..])),
+                (Bytes::from(&b"b"[..]), Bytes::from(&b"li2ee"[..])),
+            ],
+            entries
+        );
+    }
+
+    #[test]
+    fn positive_view_bytes_share_buffer() {
+        let bytes = Bytes::from(LONG_BYTES);
+        let view = BencodeView::decode(bytes.clone(), BDecodeOpt::default()).unwrap();
+
+        let value = view.list().unwrap().next().unwrap().bytes().unwrap();
+
+        assert_eq!(&LONG_BYTES[4..68], &value[..]);
+        assert_eq!(bytes[4..].as_ptr(), value.as_ptr());
+    }
+
+    #[test]
+    fn positive_view_partial() {
+        let opts = BDecodeOpt::new(2, true, false);
+        let view = BencodeView::decode(Bytes::from(&b"i0e_asd"[..]), opts).unwrap();
+
+        assert_eq!(&b"i0e"[..], &view.buffer()[..]);
+    }
+
+    #[test]
+    fn negative_view_partial() {
+        assert!(BencodeView::decode(Bytes::from(&b"i0e_asd"[..]), BDecodeOpt::default()).is_err());
+    }
+
+    #[test]
+    fn negative_view_dict_dup_keys() {
+        let bytes = Bytes::from(&b"d5:a_keyi0e5:a_key7:a_valuee"[..]);
+
+        assert!(BencodeView::decode(bytes, BDecodeOpt::new(2, false, true)).is_err());
+    }
+
+    #[test]
+    fn negative_view_deep_nesting_without_recursion_limit() {
+        let opts = BDecodeOpt::new(usize::MAX, false, true);
+
+        let bytes = Bytes::from(vec![b'l'; 1_000_000]);
+
+        assert!(BencodeView::decode(bytes, opts).is_err());
+    }
+
+    // Use quickcheck to make sure views decode exactly what references decode
+    #[test]
+    fn quicktest_view_matches_reference() {
+        fn run(check_key_sort: bool, tokens: Vec<u8>) -> TestResult {
+            let mut bytes = Vec::new();
+            for token in tokens {
+                let token_bytes: &[u8] = match token % 8 {
+                    0 => b"l",
+                    1 => b"d",
+                    2 => b"e",
+                    3 => b"i-10e",
+                    4 => b"1:a",
+                    5 => b"1:b",
+                    6 => b"i99999999999999999999e",
+                    _ => b"i0",
+                };
+                bytes.extend_from_slice(token_bytes);
+            }
+            let opts = BDecodeOpt::new(50, check_key_sort, true);
+
+            let view = BencodeView::decode(Bytes::from(&bytes[..]), opts);
+            match (view, BencodeRef::decode(&bytes, opts)) {
+                (Ok(view), Ok(bencode)) => TestResult::from_bool(same_values(&view, &bencode)),
+                (Err(a), Err(b)) => {
+                    TestResult::from_bool(format!("{:?}", a.kind()) == format!("{:?}", b.kind()))
+                }
+                _ => TestResult::failed(),
+            }
+        }
+        QuickCheck::new()
+            .tests(2000)
+            .quickcheck(run as fn(bool, Vec<u8>) -> TestResult)
+    }
+}
@@ -0,0 +1,2 @@
+pub mod bencode_view;
+mod validate;
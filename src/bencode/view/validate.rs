@@ -0,0 +1,136 @@
+use crate::bencode::error::{BencodeParseError, BencodeParseErrorKind, BencodeParseResult};
+use crate::bencode::reference::decode;
+use crate::bencode::reference::decode_opt::{BDecodeLimit, BDecodeOpt};
+
+/// List or dictionary whose values we are validating.
+enum Container<'a> {
+    List,
+    // Key for the value being validated along with the position of the value, and where the
+    // keys of the dictionary start in our key buffer
+    Dict(Option<(&'a [u8], usize)>, usize),
+}
+
+/// What we expect to find next in the bytes.
+enum Expect {
+    Value,
+    // Either the end of the current container, or its next value
+    EndOrValue,
+    EndOrKey,
+}
+
+/// Validate the value starting at `pos`, returning the position after it.
+///
+/// Accepts and rejects exactly what `decode::decode` does, with the same errors, but only
+/// keeps track of the dictionary keys instead of building the decoded values.
+pub fn validate<'a>(bytes: &'a [u8], pos: usize, opts: BDecodeOpt) -> BencodeParseResult<usize> {
+    // Containers we are in, along with the position they start at
+    let mut stack: Vec<(Container<'a>, usize)> = Vec::new();
+    // Keys of every dictionary we are in, each dictionary's keys are kept sorted
+    let mut keys: Vec<&'a [u8]> = Vec::new();
+    let mut tokens = 0;
+    let mut curr_pos = pos;
+
+    loop {
+        let curr_byte = decode::peek_container_byte(bytes, curr_pos, stack.last().map(|n| n.1))?;
+        let expect = match stack.last() {
+            None => Expect::Value,
+            Some(&(Container::List, _)) => Expect::EndOrValue,
+            Some(&(Container::Dict(None, _), _)) => Expect::EndOrKey,
+            Some(&(Container::Dict(Some(_), _), _)) => Expect::Value,
+        };
+
+        let next_pos = match expect {
+            Expect::EndOrValue | Expect::EndOrKey if curr_byte == crate::bencode::BEN_END => {
+                if let Some((Container::Dict(_, keys_start), _)) = stack.pop() {
+                    keys.truncate(keys_start);
+                }
+
+                curr_pos + 1
+            }
+            Expect::EndOrKey => {
+                decode::count_token(&mut tokens, curr_pos, opts)?;
+                let (key_bytes, next_pos) = decode::decode_limited_bytes(bytes, curr_pos, opts)?;
+
+                if let Some(&mut (Container::Dict(ref mut opt_key, keys_start), _)) =
+                    stack.last_mut()
+                {
+                    // Spec says that the keys must be in alphabetical order
+                    match (keys[keys_start..].last(), opts.check_key_sort()) {
+                        (Some(last_key), true) if key_bytes < *last_key => {
+                            return Err(BencodeParseError::from_kind(
+                                BencodeParseErrorKind::InvalidKeyOrdering {
+                                    pos: curr_pos,
+                                    key: key_bytes.to_vec(),
+                                },
+                            ))
+                        }
+                        _ => (),
+                    };
+                    *opt_key = Some((key_bytes, next_pos));
+                }
+
+                curr_pos = next_pos;
+                continue;
+            }
+            Expect::Value | Expect::EndOrValue => {
+                if stack.len() >= opts.max_recursion() {
+                    return Err(decode::limit_exceeded(
+                        curr_pos,
+                        BDecodeLimit::MaxRecursion,
+                        opts.max_recursion(),
+                    ));
+                }
+                decode::count_token(&mut tokens, curr_pos, opts)?;
+
+                match curr_byte {
+                    crate::bencode::INT_START => {
+                        decode::decode_int(bytes, curr_pos + 1, crate::bencode::BEN_END)?.1
+                    }
+                    crate::bencode::LIST_START => {
+                        stack.push((Container::List, curr_pos));
+
+                        curr_pos += 1;
+                        continue;
+                    }
+                    crate::bencode::DICT_START => {
+                        stack.push((Container::Dict(None, keys.len()), curr_pos));
+
+                        curr_pos += 1;
+                        continue;
+                    }
+                    crate::bencode::BYTE_LEN_LOW..=crate::bencode::BYTE_LEN_HIGH => {
+                        decode::decode_limited_bytes(bytes, curr_pos, opts)?.1
+                    }
+                    _ => {
+                        return Err(BencodeParseError::from_kind(
+                            BencodeParseErrorKind::InvalidByte { pos: curr_pos },
+                        ))
+                    }
+                }
+            }
+        };
+
+        match stack.last_mut() {
+            None => return Ok(next_pos),
+            Some(&mut (Container::List, _)) => (),
+            Some(&mut (Container::Dict(ref mut opt_key, keys_start), _)) => {
+                let (key_bytes, value_pos) = opt_key
+                    .take()
+                    .expect("bittorrent-protocol_bencode: Validated Dictionary Value Without Key");
+
+                match keys[keys_start..].binary_search(&key_bytes) {
+                    Ok(_) => {
+                        return Err(BencodeParseError::from_kind(
+                            BencodeParseErrorKind::InvalidKeyDuplicates {
+                                pos: value_pos,
+                                key: key_bytes.to_vec(),
+                            },
+                        ))
+                    }
+                    Err(index) => keys.insert(keys_start + index, key_bytes),
+                }
+            }
+        }
+        curr_pos = next_pos;
+    }
+}
@@ -0,0 +1,165 @@
+use std::net::IpAddr;
+
+use crate::util::sha::ShaHash;
+
+/// Length of a scrape bloom filter in bytes.
+pub const BLOOM_FILTER_LEN: usize = 256;
+
+const NUM_BITS: usize = BLOOM_FILTER_LEN * 8;
+
+/// Bloom filter of peer ips used to estimate the size of a swarm, see BEP 33.
+///
+/// See http://www.bittorrent.org/beps/bep_0033.html.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub struct BloomFilter {
+    bits: [u8; BLOOM_FILTER_LEN],
+}
+
+impl BloomFilter {
+    /// Create a new, empty, BloomFilter.
+    pub fn new() -> BloomFilter {
+        BloomFilter {
+            bits: [0u8; BLOOM_FILTER_LEN],
+        }
+    }
+
+    /// Create a BloomFilter from the given bytes.
+    ///
+    /// Returns None if the bytes are not exactly `BLOOM_FILTER_LEN` long.
+    pub fn from_bytes(bytes: &[u8]) -> Option<BloomFilter> {
+        if bytes.len() != BLOOM_FILTER_LEN {
+            return None;
+        }
+
+        let mut filter = BloomFilter::new();
+        filter.bits.copy_from_slice(bytes);
+
+        Some(filter)
+    }
+
+    /// Insert the given ip into the filter.
+    pub fn insert(&mut self, ip: IpAddr) {
+        let hash = match ip {
+            IpAddr::V4(v4_ip) => ShaHash::from_bytes(&v4_ip.octets()),
+            IpAddr::V6(v6_ip) => ShaHash::from_bytes(&v6_ip.octets()),
+        };
+        let hash_bytes = hash.as_ref();
+
+        let index_one = (hash_bytes[0] as usize | (hash_bytes[1] as usize) << 8) % NUM_BITS;
+        let index_two = (hash_bytes[2] as usize | (hash_bytes[3] as usize) << 8) % NUM_BITS;
+
+        self.set_bit(index_one);
+        self.set_bit(index_two);
+    }
+
+    /// Add all ips inserted into the other filter to this filter.
+    pub fn union(&mut self, other: &BloomFilter) {
+        for (dst, src) in self.bits.iter_mut().zip(other.bits.iter()) {
+            *dst |= *src;
+        }
+    }
+
+    /// Estimate the number of unique ips inserted into the filter.
+    pub fn estimate(&self) -> f64 {
+        let zero_bits: usize = self
+            .bits
+            .iter()
+            .map(|byte| byte.count_zeros() as usize)
+            .sum();
+        // A completely full filter would estimate an infinite number of ips
+        let zero_bits = zero_bits.max(1) as f64;
+        let num_bits = NUM_BITS as f64;
+
+        (zero_bits / num_bits).ln() / (2.0 * (1.0 - 1.0 / num_bits).ln())
+    }
+
+    /// Bytes of the filter, as sent in get peers responses.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bits
+    }
+
+    fn set_bit(&mut self, index: usize) {
+        self.bits[index / 8] |= 1 << (index % 8);
+    }
+}
+
+impl Default for BloomFilter {
+    fn default() -> BloomFilter {
+        BloomFilter::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+    use super::{BloomFilter, BLOOM_FILTER_LEN};
+
+    /// Filter of the test vector from BEP 33.
+    fn bep_filter() -> BloomFilter {
+        let mut filter = BloomFilter::new();
+
+        let v4_base = u32::from(Ipv4Addr::new(192, 0, 2, 0));
+        let v6_base = u128::from("2001:db8::".parse::<Ipv6Addr>().unwrap());
+        for offset in 0..256 {
+            filter.insert(IpAddr::V4(Ipv4Addr::from(v4_base + offset)));
+        }
+        for offset in 0..1000 {
+            filter.insert(IpAddr::V6(Ipv6Addr::from(v6_base + offset)));
+        }
+
+        filter
+    }
+
+    #[test]
+    fn positive_insert_bep_vector() {
+        let filter = bep_filter();
+
+        assert_eq!(
+            &filter.as_bytes()[..8],
+            &[0xF6, 0xC3, 0xF5, 0xEA, 0xA0, 0x7F, 0xFD, 0x91]
+        );
+    }
+
+    #[test]
+    fn positive_estimate_bep_vector() {
+        let estimate = bep_filter().estimate();
+
+        assert!((estimate - 1224.9308).abs() < 0.0001, "{}", estimate);
+    }
+
+    #[test]
+    fn positive_estimate_empty() {
+        assert_eq!(BloomFilter::new().estimate(), 0.0);
+    }
+
+    #[test]
+    fn positive_union_same_as_inserting() {
+        let one: IpAddr = "10.0.0.1".parse().unwrap();
+        let two: IpAddr = "10.0.0.2".parse().unwrap();
+
+        let mut filter_one = BloomFilter::new();
+        filter_one.insert(one);
+        let mut filter_two = BloomFilter::new();
+        filter_two.insert(two);
+        filter_two.insert(one);
+        filter_one.union(&filter_two);
+
+        let mut expected = BloomFilter::new();
+        expected.insert(one);
+        expected.insert(two);
+        assert_eq!(filter_one, expected);
+    }
+
+    #[test]
+    fn positive_from_bytes_round_trip() {
+        let filter = bep_filter();
+
+        assert_eq!(BloomFilter::from_bytes(filter.as_bytes()), Some(filter));
+    }
+
+    #[test]
+    fn negative_from_bytes_wrong_length() {
+        assert_eq!(BloomFilter::from_bytes(&[0u8; BLOOM_FILTER_LEN - 1]), None);
+    }
+}
@@ -1,46 +1,121 @@
-use std::collections::HashSet;
+use std::collections::{HashSet, VecDeque};
 use std::io;
 use std::net::{SocketAddr, UdpSocket};
+use std::pin::Pin;
 use std::sync::mpsc::{self, Receiver};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::Duration;
 
+use futures::channel::mpsc::{self as futures_mpsc, UnboundedReceiver};
+use futures::Stream;
 use mio::Sender;
 
-use crate::util::bt::InfoHash;
+use crate::util::bt::{InfoHash, NodeId};
 use crate::util::net;
 
-use crate::dht::handshake::Handshaker;
+use crate::dht::handshake::{Handshaker, SharedHandshaker};
+use crate::dht::item::{GetItem, ImmutableItem, MutableItem, PUBLIC_KEY_LEN};
+use crate::dht::limiter::QueryLimiter;
+use crate::dht::message::Want;
 use crate::dht::router::Router;
-use crate::dht::worker::{self, DhtEvent, OneshotTask, ShutdownCause};
+use crate::dht::sample::SampleInfoHashes;
+use crate::dht::security::NodeIdPolicy;
+use crate::dht::state::DhtState;
+use crate::dht::worker::bootstrap::BootstrapWatchdog;
+use crate::dht::worker::item::ItemOperation;
+use crate::dht::worker::resolve;
+use crate::dht::worker::{
+    self, DhtEvent, DhtStatus, OneshotTask, ReadOnlyQueries, SearchEvent, ShutdownCause,
+};
+
+const DEFAULT_MAX_SAMPLES: usize = 20;
+
+const DEFAULT_MAX_NODE_AGE_SECS: u64 = 24 * 60 * 60;
+
+const DEFAULT_QUERY_RATE_LIMIT_QPS: u32 = 20;
+const DEFAULT_QUERY_RATE_LIMIT_BURST: u32 = 100;
+const DEFAULT_BAN_DURATION_SECS: u64 = 10 * 60;
+
+const DEFAULT_REBOOTSTRAP_MIN_GOOD_NODES: usize = 10;
+const DEFAULT_REBOOTSTRAP_GRACE_SECS: u64 = 60;
+
+/// Address families our node takes part in the DHT with, see BEP 32.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum IpStack {
+    /// Only take part in the IPv4 DHT.
+    V4Only,
+    /// Only take part in the IPv6 DHT.
+    V6Only,
+    /// Take part in both the IPv4 and IPv6 DHT, each with its own socket and routing table.
+    DualStack,
+}
 
 /// Maintains a Distributed Hash (Routing) Table.
 pub struct MainlineDht {
+    // One DHT per address family we take part in, the first one is our primary DHT
+    dhts: Vec<FamilyDht>,
+    shut_down: AtomicBool,
+}
+
+/// DHT running over a socket of a single address family.
+struct FamilyDht {
     send: Sender<OneshotTask>,
+    status: Arc<Mutex<DhtStatus>>,
+    ipv6: bool,
+    shut_down: AtomicBool,
 }
 
-impl MainlineDht {
-    /// Start the MainlineDht with the given DhtBuilder and Handshaker.
-    fn with_builder<H>(builder: DhtBuilder, handshaker: H) -> io::Result<MainlineDht>
+impl FamilyDht {
+    fn start<H>(
+        builder: &DhtBuilder,
+        ipv6: bool,
+        want: Option<Want>,
+        handshaker: H,
+    ) -> io::Result<FamilyDht>
     where
         H: Handshaker + 'static,
     {
-        let send_sock = UdpSocket::bind(&builder.src_addr)?;
+        let (src_addr, ext_addr) = if ipv6 {
+            (builder.src_addr_v6, builder.ext_addr_v6)
+        } else {
+            (builder.src_addr, builder.ext_addr)
+        };
+
+        let send_sock = UdpSocket::bind(&src_addr)?;
         let recv_sock = send_sock.try_clone()?;
 
         let kill_sock = send_sock.try_clone()?;
         let kill_addr = send_sock.local_addr()?;
 
-        let send = worker::start_mainline_dht(
+        let (send, status) = worker::start_mainline_dht(
             send_sock,
             recv_sock,
             builder.read_only,
-            builder.ext_addr,
+            builder.read_only_queries,
+            builder.implied_port,
+            ext_addr,
+            builder.policy,
+            QueryLimiter::new(builder.query_qps, builder.query_burst, builder.ban_duration),
+            builder.max_samples,
+            want,
+            builder.opt_state.as_ref(),
+            builder.max_node_age,
+            BootstrapWatchdog::new(builder.rebootstrap_min_nodes, builder.rebootstrap_grace),
             handshaker,
             kill_sock,
             kill_addr,
         )?;
 
-        let nodes: Vec<SocketAddr> = builder.nodes.into_iter().collect();
-        let routers: Vec<Router> = builder.routers.into_iter().collect();
+        // Nodes of the other address family can not be reached over our socket
+        let nodes: Vec<SocketAddr> = builder
+            .nodes
+            .iter()
+            .filter(|addr| addr.is_ipv6() == ipv6)
+            .cloned()
+            .collect();
+        let routers: Vec<Router> = builder.routers.iter().cloned().collect();
 
         if send
             .send(OneshotTask::StartBootstrap(routers, nodes))
@@ -51,7 +126,91 @@ impl MainlineDht {
             );
         }
 
-        Ok(MainlineDht { send: send })
+        // Hosts may take a while to resolve, so they are added as routers once they do
+        if !builder.hosts.is_empty() {
+            resolve::spawn_host_resolver(builder.hosts.clone(), ipv6, send.clone());
+        }
+
+        Ok(FamilyDht {
+            send: send,
+            status: status,
+            ipv6: ipv6,
+            shut_down: AtomicBool::new(false),
+        })
+    }
+
+    fn save_state(&self) -> DhtState {
+        let (send, recv) = mpsc::channel();
+
+        if self.send.send(OneshotTask::SaveState(send)).is_err() {
+            warn!("bittorrent-protocol_dht: MainlineDht failed to send a save state message...");
+        }
+
+        // Without a running DHT all we have left is our identity
+        recv.recv().unwrap_or_else(|_| {
+            let status = *self.status.lock().unwrap();
+
+            DhtState::empty(status.node_id(), status.external_ip())
+        })
+    }
+
+    fn shutdown(&self) {
+        if self.shut_down.swap(true, Ordering::SeqCst) {
+            return;
+        }
+
+        if self
+            .send
+            .send(OneshotTask::Shutdown(ShutdownCause::ClientInitiated))
+            .is_err()
+        {
+            warn!(
+                "bittorrent-protocol_dht: MainlineDht failed to send a shutdown message (may have already been \
+                   shutdown)..."
+            );
+        }
+    }
+}
+
+impl Drop for FamilyDht {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+impl MainlineDht {
+    /// Start the MainlineDht with the given DhtBuilder and Handshaker.
+    fn with_builder<H>(builder: DhtBuilder, handshaker: H) -> io::Result<MainlineDht>
+    where
+        H: Handshaker + 'static,
+    {
+        let dhts = match builder.ip_stack {
+            IpStack::V4Only => vec![FamilyDht::start(&builder, false, None, handshaker)?],
+            IpStack::V6Only => vec![FamilyDht::start(&builder, true, None, handshaker)?],
+            IpStack::DualStack => {
+                // Both DHTs ask for nodes of both families, so that either one can give
+                // out the nodes of the other one to requesters wanting them
+                let handshaker = SharedHandshaker::new(handshaker);
+                let want = Some(Want::Both);
+
+                vec![
+                    FamilyDht::start(&builder, false, want, handshaker.clone())?,
+                    FamilyDht::start(&builder, true, want, handshaker)?,
+                ]
+            }
+        };
+
+        Ok(MainlineDht {
+            dhts: dhts,
+            shut_down: AtomicBool::new(false),
+        })
+    }
+
+    /// Send the task to the DHT of every address family, returning false if any of them shut down.
+    fn send_all(&self, task: OneshotTask) -> bool {
+        self.dhts.iter().fold(true, |sent, dht| {
+            dht.send.send(task.clone()).is_ok() && sent
+        })
     }
 
     /// Perform a search for the given InfoHash with an optional announce on the closest nodes.
@@ -61,14 +220,207 @@ impl MainlineDht {
     /// for the InfoHash will be able to find your contact information and initiate a handshake.
     ///
     /// If the initial bootstrap has not finished, the search will be queued and executed once
-    /// the bootstrap has completed.
+    /// the bootstrap has completed. With a dual stack, the search runs on both the IPv4 and
+    /// IPv6 DHT.
     pub fn search(&self, hash: InfoHash, announce: bool) {
-        if self
+        if !self.send_all(OneshotTask::StartLookup(hash, announce, false, None)) {
+            warn!("bittorrent-protocol_dht: MainlineDht failed to send a start lookup message...");
+        }
+    }
+
+    /// Perform a search for the given InfoHash, returning a stream of its progress.
+    ///
+    /// Behaves like `MainlineDht::search`, but every peer found is also yielded by the
+    /// returned stream, followed by a `SearchEvent::SearchCompleted` summary once the
+    /// lookup converges, after which the stream ends. Peers are still forwarded to the
+    /// Handshaker as well.
+    ///
+    /// Dropping the stream cancels the lookup, including the announce if it has not
+    /// been sent yet. If the DHT shuts down, the stream ends without a summary.
+    ///
+    /// With a dual stack, the peers found on the IPv4 and IPv6 DHT are merged into the one
+    /// stream and the summary is given once both lookups converged.
+    pub fn search_peers(&self, hash: InfoHash, announce: bool) -> SearchStream {
+        self.start_search(hash, announce, false)
+    }
+
+    /// Perform a search for the given InfoHash, also estimating the size of its swarm.
+    ///
+    /// Behaves like `MainlineDht::search_peers`, but nodes are asked for bloom filters of
+    /// the peers they store as described in BEP 33. The estimated number of seeds and
+    /// downloaders is yielded as a `SearchEvent::Scrape` right before the summary.
+    pub fn scrape_peers(&self, hash: InfoHash, announce: bool) -> SearchStream {
+        self.start_search(hash, announce, true)
+    }
+
+    fn start_search(&self, hash: InfoHash, announce: bool, scrape: bool) -> SearchStream {
+        let recvs = self
+            .dhts
+            .iter()
+            .map(|dht| {
+                let (send, recv) = futures_mpsc::unbounded();
+
+                if dht
+                    .send
+                    .send(OneshotTask::StartLookup(hash, announce, scrape, Some(send)))
+                    .is_err()
+                {
+                    warn!("bittorrent-protocol_dht: MainlineDht failed to send a start lookup message...");
+                }
+
+                recv
+            })
+            .collect();
+
+        SearchStream::new(recvs)
+    }
+
+    /// Store the given immutable item on the nodes closest to its target, returning the target.
+    ///
+    /// A `DhtEvent::PutCompleted` event will be sent with the number of nodes storing the
+    /// item once the put has finished. Items expire from remote nodes after two hours, so
+    /// they have to be put again regularly to keep them available.
+    pub fn put_immutable(&self, item: ImmutableItem) -> InfoHash {
+        let target = item.target();
+
+        self.start_item_lookup(ItemOperation::PutImmutable(item));
+
+        target
+    }
+
+    /// Retrieve the immutable item with the given target.
+    ///
+    /// The returned future resolves to `None` if no node was storing the item. With a dual
+    /// stack, it resolves to the item found first on either the IPv4 or IPv6 DHT.
+    pub fn get_immutable(&self, target: InfoHash) -> GetItem<ImmutableItem> {
+        let (send, recv) = futures_mpsc::unbounded();
+
+        self.start_item_lookup(ItemOperation::GetImmutable(target.into(), send));
+
+        GetItem::new(recv)
+    }
+
+    /// Store the given mutable item on the nodes closest to its target, returning the target.
+    ///
+    /// If a compare and swap sequence number is given, nodes will only replace an item they
+    /// are storing if it has that sequence number.
+    pub fn put_mutable(&self, item: MutableItem, cas: Option<i64>) -> InfoHash {
+        let target = item.target();
+
+        self.start_item_lookup(ItemOperation::PutMutable(item, cas));
+
+        target
+    }
+
+    /// Retrieve the mutable item with the highest sequence number for the given public key and salt.
+    ///
+    /// The returned future resolves to `None` if no node was storing a validly signed item.
+    pub fn get_mutable(
+        &self,
+        public_key: [u8; PUBLIC_KEY_LEN],
+        salt: &[u8],
+    ) -> GetItem<MutableItem> {
+        let (send, recv) = futures_mpsc::unbounded();
+
+        self.start_item_lookup(ItemOperation::GetMutable(public_key, salt.to_vec(), send));
+
+        GetItem::new(recv)
+    }
+
+    fn start_item_lookup(&self, operation: ItemOperation) {
+        if !self.send_all(OneshotTask::StartItemLookup(operation)) {
+            warn!("bittorrent-protocol_dht: MainlineDht failed to send a start item lookup message...");
+        }
+    }
+
+    /// Sample the info hashes stored by the node at the given address, see BEP 51.
+    ///
+    /// The node also sends us the nodes closest to the given target, which can be sampled next
+    /// to crawl the DHT. Nodes tell us how long to wait before sampling them again, sampling a
+    /// node before then resolves to `SampleError::TooEarly` without contacting the node.
+    ///
+    /// Unlike searches, samples are not queued until the initial bootstrap has finished. Nodes of
+    /// an address family we do not take part in can not be reached and time out.
+    pub fn sample_infohashes(&self, node_addr: SocketAddr, target: NodeId) -> SampleInfoHashes {
+        let (send, recv) = futures_mpsc::unbounded();
+        let dht = self
+            .dhts
+            .iter()
+            .find(|dht| dht.ipv6 == node_addr.is_ipv6())
+            .unwrap_or(&self.dhts[0]);
+
+        if dht
             .send
-            .send(OneshotTask::StartLookup(hash, announce))
+            .send(OneshotTask::StartSample(node_addr, target, send))
             .is_err()
         {
-            warn!("bittorrent-protocol_dht: MainlineDht failed to send a start lookup message...");
+            warn!("bittorrent-protocol_dht: MainlineDht failed to send a start sample message...");
+        }
+
+        SampleInfoHashes::new(recv)
+    }
+
+    /// Current status of our node, such as our node id and whether it is BEP 42 compliant.
+    ///
+    /// Our node id is regenerated when enough remote nodes agree on an external ip
+    /// that our current node id was not generated from.
+    ///
+    /// With a dual stack, this is the status of our node in the IPv4 DHT.
+    pub fn status(&self) -> DhtStatus {
+        *self.dhts[0].status.lock().unwrap()
+    }
+
+    /// Save the state of our node, which can be given to `DhtBuilder::with_state` when
+    /// restarting so that we do not have to bootstrap from scratch.
+    ///
+    /// With a dual stack, the state holds the nodes of both the IPv4 and IPv6 DHT along
+    /// with the identity of our node in the IPv4 DHT.
+    pub fn save_state(&self) -> DhtState {
+        let mut states = self.dhts.iter().map(|dht| dht.save_state());
+        let primary = states.next().unwrap();
+
+        states.fold(primary, |state, other| state.merge_nodes(other))
+    }
+
+    /// Save the state of our node, see `save_state`, then shut the DHT down.
+    ///
+    /// Dropping the `MainlineDht` shuts it down as well, without saving the state. Calls
+    /// after the first return None right away.
+    pub async fn shutdown(&self) -> Option<DhtState> {
+        if self.shut_down.swap(true, Ordering::SeqCst) {
+            return None;
+        }
+
+        let state = self.save_state();
+        for dht in self.dhts.iter() {
+            dht.shutdown();
+        }
+
+        Some(state)
+    }
+
+    /// Ping the node at the given address, adding it to our routing table if it responds.
+    ///
+    /// Useful for feeding in nodes from the `nodes` key of a metainfo file or from PORT messages
+    /// of peers. If a bootstrap is running, the node takes part in it. Nodes of an address family
+    /// we do not take part in are ignored.
+    pub fn add_node(&self, node_addr: SocketAddr) {
+        let opt_dht = self.dhts.iter().find(|dht| dht.ipv6 == node_addr.is_ipv6());
+
+        if let Some(dht) = opt_dht {
+            if dht.send.send(OneshotTask::AddNode(node_addr)).is_err() {
+                warn!("bittorrent-protocol_dht: MainlineDht failed to send an add node message...");
+            }
+        }
+    }
+
+    /// Switch our node in or out of read only mode, see DhtBuilder::set_read_only.
+    ///
+    /// Useful when moving between metered and unmetered connections, lookups that are
+    /// running carry on with the new mode.
+    pub fn set_read_only(&self, read_only: bool) {
+        if !self.send_all(OneshotTask::SetReadOnly(read_only)) {
+            warn!("bittorrent-protocol_dht: MainlineDht failed to send a set read only message...");
         }
     }
 
@@ -76,10 +428,12 @@ impl MainlineDht {
     ///
     /// It is important to at least monitor the DHT for shutdown events as any calls
     /// after that event occurs will not be processed but no indication will be given.
+    ///
+    /// With a dual stack, events of both the IPv4 and IPv6 DHT are received.
     pub fn events(&self) -> Receiver<DhtEvent> {
         let (send, recv) = mpsc::channel();
 
-        if self.send.send(OneshotTask::RegisterSender(send)).is_err() {
+        if !self.send_all(OneshotTask::RegisterSender(send)) {
             warn!(
                 "bittorrent-protocol_dht: MainlineDht failed to send a register sender message..."
             );
@@ -92,17 +446,87 @@ impl MainlineDht {
     }
 }
 
-impl Drop for MainlineDht {
-    fn drop(&mut self) {
-        if self
-            .send
-            .send(OneshotTask::Shutdown(ShutdownCause::ClientInitiated))
-            .is_err()
-        {
-            warn!(
-                "bittorrent-protocol_dht: MainlineDht failed to send a shutdown message (may have already been \
-                   shutdown)..."
-            );
+// ----------------------------------------------------------------------------//
+
+/// Stream of the events of a single search started with `MainlineDht::search_peers`.
+pub struct SearchStream {
+    // Lookups still running, one per address family
+    recvs: Vec<UnboundedReceiver<SearchEvent>>,
+    num_lookups: usize,
+    found_peers: HashSet<SocketAddr>,
+    num_completed: usize,
+    nodes_contacted: usize,
+    opt_scrape: Option<(usize, usize)>,
+    summary: VecDeque<SearchEvent>,
+}
+
+impl SearchStream {
+    fn new(recvs: Vec<UnboundedReceiver<SearchEvent>>) -> SearchStream {
+        SearchStream {
+            num_lookups: recvs.len(),
+            recvs: recvs,
+            found_peers: HashSet::new(),
+            num_completed: 0,
+            nodes_contacted: 0,
+            opt_scrape: None,
+            summary: VecDeque::new(),
+        }
+    }
+}
+
+impl Stream for SearchStream {
+    type Item = SearchEvent;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<SearchEvent>> {
+        let stream = &mut *self;
+
+        let mut index = 0;
+        while index < stream.recvs.len() {
+            match Pin::new(&mut stream.recvs[index]).poll_next(cx) {
+                Poll::Ready(Some(SearchEvent::Peer(addr))) => {
+                    // Peers may be stored on both the IPv4 and IPv6 DHT
+                    if stream.found_peers.insert(addr) {
+                        return Poll::Ready(Some(SearchEvent::Peer(addr)));
+                    }
+                }
+                Poll::Ready(Some(SearchEvent::Scrape { seeds, downloaders })) => {
+                    let (total_seeds, total_downloaders) = stream.opt_scrape.unwrap_or((0, 0));
+
+                    stream.opt_scrape =
+                        Some((total_seeds + seeds, total_downloaders + downloaders));
+                }
+                Poll::Ready(Some(SearchEvent::SearchCompleted {
+                    nodes_contacted, ..
+                })) => {
+                    stream.num_completed += 1;
+                    stream.nodes_contacted += nodes_contacted;
+                }
+                Poll::Ready(None) => {
+                    stream.recvs.remove(index);
+
+                    // Summarize once every lookup converged, lookups cut short by a shutdown
+                    // leave the search without a summary
+                    if stream.recvs.is_empty() && stream.num_completed == stream.num_lookups {
+                        if let Some((seeds, downloaders)) = stream.opt_scrape {
+                            stream.summary.push_back(SearchEvent::Scrape {
+                                seeds: seeds,
+                                downloaders: downloaders,
+                            });
+                        }
+                        stream.summary.push_back(SearchEvent::SearchCompleted {
+                            nodes_contacted: stream.nodes_contacted,
+                            peers_found: stream.found_peers.len(),
+                        });
+                    }
+                }
+                Poll::Pending => index += 1,
+            }
+        }
+
+        if stream.recvs.is_empty() {
+            Poll::Ready(stream.summary.pop_front())
+        } else {
+            Poll::Pending
         }
     }
 }
@@ -114,9 +538,24 @@ impl Drop for MainlineDht {
 pub struct DhtBuilder {
     nodes: HashSet<SocketAddr>,
     routers: HashSet<Router>,
+    hosts: Vec<String>,
     read_only: bool,
+    read_only_queries: ReadOnlyQueries,
+    implied_port: bool,
+    ip_stack: IpStack,
     src_addr: SocketAddr,
+    src_addr_v6: SocketAddr,
     ext_addr: Option<SocketAddr>,
+    ext_addr_v6: Option<SocketAddr>,
+    policy: NodeIdPolicy,
+    query_qps: u32,
+    query_burst: u32,
+    ban_duration: Duration,
+    max_samples: usize,
+    opt_state: Option<DhtState>,
+    max_node_age: Duration,
+    rebootstrap_min_nodes: usize,
+    rebootstrap_grace: Duration,
 }
 
 impl DhtBuilder {
@@ -128,9 +567,24 @@ impl DhtBuilder {
         DhtBuilder {
             nodes: HashSet::new(),
             routers: HashSet::new(),
+            hosts: Vec::new(),
             read_only: true,
+            read_only_queries: ReadOnlyQueries::Ignore,
+            implied_port: false,
+            ip_stack: IpStack::V4Only,
             src_addr: net::default_route_v4(),
+            src_addr_v6: net::default_route_v6(),
             ext_addr: None,
+            ext_addr_v6: None,
+            policy: NodeIdPolicy::AcceptAll,
+            query_qps: DEFAULT_QUERY_RATE_LIMIT_QPS,
+            query_burst: DEFAULT_QUERY_RATE_LIMIT_BURST,
+            ban_duration: Duration::from_secs(DEFAULT_BAN_DURATION_SECS),
+            max_samples: DEFAULT_MAX_SAMPLES,
+            opt_state: None,
+            max_node_age: Duration::from_secs(DEFAULT_MAX_NODE_AGE_SECS),
+            rebootstrap_min_nodes: DEFAULT_REBOOTSTRAP_MIN_GOOD_NODES,
+            rebootstrap_grace: Duration::from_secs(DEFAULT_REBOOTSTRAP_GRACE_SECS),
         }
     }
 
@@ -152,7 +606,48 @@ impl DhtBuilder {
         dht.add_router(router)
     }
 
+    /// Creates a DhtBuilder with bootstrap nodes given as host:port strings, such as
+    /// "router.bittorrent.com:6881".
+    ///
+    /// Hosts are resolved when the DHT starts, retrying with a backoff if resolving fails, and
+    /// are used as routers, see DhtBuilder::with_router.
+    pub fn with_bootstrap_nodes(hosts: Vec<String>) -> DhtBuilder {
+        hosts
+            .into_iter()
+            .fold(DhtBuilder::new(), |dht, host| dht.add_bootstrap_node(host))
+    }
+
+    /// Creates a DhtBuilder restoring the state saved with `MainlineDht::save_state`.
+    ///
+    /// The saved nodes are put back in our routing table, those we have not heard from recently
+    /// are pinged and the full bootstrap is skipped if enough of them respond.
+    pub fn with_state(state: DhtState) -> DhtBuilder {
+        let dht = DhtBuilder::new();
+
+        dht.set_state(state)
+    }
+
+    /// Restore the state saved with `MainlineDht::save_state`.
+    ///
+    /// See DhtBuilder::with_state for how the state is restored.
+    pub fn set_state(mut self, state: DhtState) -> DhtBuilder {
+        self.opt_state = Some(state);
+
+        self
+    }
+
+    /// Set the maximum time since we last heard from a saved node for it to be restored.
+    ///
+    /// Default value is 24 hours.
+    pub fn set_max_node_age(mut self, max_age: Duration) -> DhtBuilder {
+        self.max_node_age = max_age;
+
+        self
+    }
+
     /// Add nodes which will be distributed within our routing table.
+    ///
+    /// Nodes of an address family we do not take part in are ignored.
     pub fn add_node(mut self, node_addr: SocketAddr) -> DhtBuilder {
         self.nodes.insert(node_addr);
 
@@ -168,33 +663,147 @@ impl DhtBuilder {
         self
     }
 
+    /// Add a bootstrap node given as a host:port string.
+    ///
+    /// See DhtBuilder::with_bootstrap_nodes for how the host is used.
+    pub fn add_bootstrap_node(mut self, host: String) -> DhtBuilder {
+        if !self.hosts.contains(&host) {
+            self.hosts.push(host);
+        }
+
+        self
+    }
+
+    /// Set when we bootstrap again after our initial bootstrap, which is whenever we have had
+    /// fewer than min_good_nodes good nodes in our routing table for longer than grace.
+    ///
+    /// Our routing table can go stale while we are unable to reach the DHT, such as after
+    /// waking up from sleep. A min_good_nodes of zero disables bootstrapping again. Default
+    /// value is 10 good nodes for 1 minute.
+    pub fn set_rebootstrap_threshold(
+        mut self,
+        min_good_nodes: usize,
+        grace: Duration,
+    ) -> DhtBuilder {
+        self.rebootstrap_min_nodes = min_good_nodes;
+        self.rebootstrap_grace = grace;
+
+        self
+    }
+
     /// Set the read only flag when communicating with other nodes. Indicates
-    /// that remote nodes should not add us to their routing table.
+    /// that remote nodes should not add us to their routing table, see BEP 43.
     ///
-    /// Used when we are behind a restrictive NAT and/or we want to decrease
-    /// incoming network traffic. Defaults value is true.
+    /// Read only nodes still perform lookups and refresh their routing table, but
+    /// do not answer queries from remote nodes. Used when we are behind a restrictive
+    /// NAT and/or we want to decrease incoming network traffic. Can be changed later on
+    /// with MainlineDht::set_read_only. Defaults value is true.
     pub fn set_read_only(mut self, read_only: bool) -> DhtBuilder {
         self.read_only = read_only;
 
         self
     }
 
+    /// Set how queries from remote nodes are treated while we are read only.
+    ///
+    /// Default value is ReadOnlyQueries::Ignore.
+    pub fn set_read_only_queries(mut self, queries: ReadOnlyQueries) -> DhtBuilder {
+        self.read_only_queries = queries;
+
+        self
+    }
+
+    /// Set the implied port flag when announcing to other nodes. Indicates that
+    /// remote nodes should store the source port of our announce instead of the
+    /// port of our Handshaker.
+    ///
+    /// Useful when our peer connections share the DHT port, such as with uTP, or
+    /// when behind a NAT which remaps ports. Default value is false.
+    pub fn set_implied_port(mut self, implied_port: bool) -> DhtBuilder {
+        self.implied_port = implied_port;
+
+        self
+    }
+
     /// Provide the DHT with our external address. If this is not supplied we will
     /// have to deduce this information from remote nodes.
     ///
     /// Purpose of the external address is to generate a NodeId that conforms to
     /// BEP 42 so that nodes can safely store information on our node.
+    ///
+    /// With a dual stack, an external address can be given for each address family.
     pub fn set_external_addr(mut self, addr: SocketAddr) -> DhtBuilder {
-        self.ext_addr = Some(addr);
+        if addr.is_ipv6() {
+            self.ext_addr_v6 = Some(addr);
+        } else {
+            self.ext_addr = Some(addr);
+        }
+
+        self
+    }
+
+    /// Set the policy for admitting remote nodes into our routing table based on whether
+    /// their node id is BEP 42 compliant for their address.
+    ///
+    /// Default value is NodeIdPolicy::AcceptAll.
+    pub fn set_node_id_policy(mut self, policy: NodeIdPolicy) -> DhtBuilder {
+        self.policy = policy;
+
+        self
+    }
+
+    /// Set the rate limit for queries from each remote ip, using a token bucket holding up
+    /// to burst queries that refills at qps queries per second.
+    ///
+    /// Queries over the rate limit are dropped, ips that keep going over it are banned.
+    /// A qps of zero disables the rate limit. Default value is 20 qps with a burst of 100.
+    pub fn set_query_rate_limit(mut self, qps: u32, burst: u32) -> DhtBuilder {
+        self.query_qps = qps;
+        self.query_burst = burst;
+
+        self
+    }
+
+    /// Set how long ips flooding us with queries or sending us malformed messages are banned.
+    ///
+    /// Messages from banned ips are dropped and nodes with a banned ip are not added to our
+    /// routing table. Default value is 10 minutes.
+    pub fn set_ban_duration(mut self, duration: Duration) -> DhtBuilder {
+        self.ban_duration = duration;
+
+        self
+    }
+
+    /// Set the maximum number of info hashes we give out when sampled by remote nodes, see BEP 51.
+    ///
+    /// Responses have to fit in a single udp packet, so this should not be much larger
+    /// than the default. Default value is 20.
+    pub fn set_max_samples(mut self, max_samples: usize) -> DhtBuilder {
+        self.max_samples = max_samples;
+
+        self
+    }
+
+    /// Set the address families we take part in the DHT with, see BEP 32.
+    ///
+    /// Default value is IpStack::V4Only.
+    pub fn set_ip_stack(mut self, ip_stack: IpStack) -> DhtBuilder {
+        self.ip_stack = ip_stack;
 
         self
     }
 
     /// Provide the DHT with the source address.
     ///
-    /// If this is not supplied we will use the OS default route.
+    /// If this is not supplied we will use the OS default route. With a dual stack, a source
+    /// address can be given for each address family. Binding the IPv4 and IPv6 default routes
+    /// to the same port may fail, since most systems let IPv6 sockets take IPv4 traffic too.
     pub fn set_source_addr(mut self, addr: SocketAddr) -> DhtBuilder {
-        self.src_addr = addr;
+        if addr.is_ipv6() {
+            self.src_addr_v6 = addr;
+        } else {
+            self.src_addr = addr;
+        }
 
         self
     }
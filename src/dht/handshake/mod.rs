@@ -1,4 +1,5 @@
 use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
 
 use crate::util::bt::{InfoHash, PeerId};
 
@@ -24,6 +25,53 @@ pub trait Handshaker: Send {
 }
 
 /// Trait for advertisement information that other peers can discover.
+/// Handshaker shared between the IPv4 and IPv6 DHTs of a dual stack node.
+pub(crate) struct SharedHandshaker<H> {
+    handshaker: Arc<Mutex<H>>,
+}
+
+impl<H> SharedHandshaker<H> {
+    pub fn new(handshaker: H) -> SharedHandshaker<H> {
+        SharedHandshaker {
+            handshaker: Arc::new(Mutex::new(handshaker)),
+        }
+    }
+}
+
+impl<H> Clone for SharedHandshaker<H> {
+    fn clone(&self) -> SharedHandshaker<H> {
+        SharedHandshaker {
+            handshaker: self.handshaker.clone(),
+        }
+    }
+}
+
+impl<H> Handshaker for SharedHandshaker<H>
+where
+    H: Handshaker,
+{
+    type Metadata = H::Metadata;
+
+    fn id(&self) -> PeerId {
+        self.handshaker.lock().unwrap().id()
+    }
+
+    fn port(&self) -> u16 {
+        self.handshaker.lock().unwrap().port()
+    }
+
+    fn connect(&mut self, expected: Option<PeerId>, hash: InfoHash, addr: SocketAddr) {
+        self.handshaker
+            .lock()
+            .unwrap()
+            .connect(expected, hash, addr)
+    }
+
+    fn metadata(&mut self, data: H::Metadata) {
+        self.handshaker.lock().unwrap().metadata(data)
+    }
+}
+
 pub trait DhtDiscoveryInfo {
     /// Retrieve our public port that we advertise to others.
     fn port(&self) -> u16;
@@ -0,0 +1,408 @@
+use std::error::Error;
+use std::fmt;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use crypto::ed25519;
+use futures::channel::mpsc::UnboundedReceiver;
+use futures::{Future, Stream};
+
+use crate::bencode::{BDecodeOpt, BencodeMut, BencodeRef};
+use crate::util::bt::InfoHash;
+use crate::util::sha::ShaHashBuilder;
+
+/// Maximum length of the bencoded value of an item.
+pub const MAX_VALUE_LEN: usize = 1000;
+/// Maximum length of the salt of a mutable item.
+pub const MAX_SALT_LEN: usize = 64;
+
+/// Length of an ed25519 public key.
+pub const PUBLIC_KEY_LEN: usize = 32;
+/// Length of an ed25519 seed, which the private key is derived from.
+pub const SEED_LEN: usize = 32;
+/// Length of an ed25519 signature.
+pub const SIGNATURE_LEN: usize = 64;
+
+const SALT_KEY: &'static str = "salt";
+const SEQ_KEY: &'static str = "seq";
+const VALUE_KEY: &'static str = "v";
+
+/// Error constructing an item that could be stored in the DHT.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum ItemError {
+    /// Bencoded value is larger than `MAX_VALUE_LEN`.
+    ValueTooLarge,
+    /// Salt is larger than `MAX_SALT_LEN`.
+    SaltTooLarge,
+    /// Signature does not match the public key and contents of the item.
+    InvalidSignature,
+}
+
+impl fmt::Display for ItemError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            &ItemError::ValueTooLarge => write!(f, "Value Is Larger Than {} Bytes", MAX_VALUE_LEN),
+            &ItemError::SaltTooLarge => write!(f, "Salt Is Larger Than {} Bytes", MAX_SALT_LEN),
+            &ItemError::InvalidSignature => f.write_str("Signature Of The Item Is Invalid"),
+        }
+    }
+}
+
+impl Error for ItemError {}
+
+// ----------------------------------------------------------------------------//
+
+/// Immutable item, stored under the SHA-1 hash of its bencoded value.
+///
+/// See http://www.bittorrent.org/beps/bep_0044.html.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct ImmutableItem {
+    value: Vec<u8>,
+}
+
+impl ImmutableItem {
+    /// Create a new ImmutableItem from the given value.
+    pub fn new(value: &BencodeMut<'_>) -> Result<ImmutableItem, ItemError> {
+        ImmutableItem::from_raw(value.encode())
+    }
+
+    /// Create a new ImmutableItem from an already bencoded value.
+    pub(crate) fn from_raw(value: Vec<u8>) -> Result<ImmutableItem, ItemError> {
+        if value.len() > MAX_VALUE_LEN {
+            Err(ItemError::ValueTooLarge)
+        } else {
+            Ok(ImmutableItem { value: value })
+        }
+    }
+
+    /// Target the item is stored under.
+    pub fn target(&self) -> InfoHash {
+        InfoHash::from_bytes(&self.value)
+    }
+
+    /// Bencoded value of the item.
+    pub fn value(&self) -> &[u8] {
+        &self.value
+    }
+
+    /// Retrieve a `BencodeRef` representing the value of the item.
+    pub fn bencode_ref<'a>(&'a self) -> BencodeRef<'a> {
+        // Values are always produced by an encoder or verified by a decoder
+        BencodeRef::decode(&self.value, BDecodeOpt::default()).unwrap()
+    }
+}
+
+// ----------------------------------------------------------------------------//
+
+/// Mutable item, signed by the owner of a public key and stored under the SHA-1
+/// hash of that public key and an optional salt.
+///
+/// Newer versions of an item carry a higher sequence number.
+///
+/// See http://www.bittorrent.org/beps/bep_0044.html.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct MutableItem {
+    public_key: [u8; PUBLIC_KEY_LEN],
+    salt: Vec<u8>,
+    seq: i64,
+    signature: [u8; SIGNATURE_LEN],
+    value: Vec<u8>,
+}
+
+impl MutableItem {
+    /// Create a new MutableItem, verifying the signature over its contents.
+    pub fn new(
+        public_key: [u8; PUBLIC_KEY_LEN],
+        salt: &[u8],
+        seq: i64,
+        signature: [u8; SIGNATURE_LEN],
+        value: &BencodeMut<'_>,
+    ) -> Result<MutableItem, ItemError> {
+        MutableItem::from_raw(public_key, salt.to_vec(), seq, signature, value.encode())
+    }
+
+    /// Create a new MutableItem, signing its contents with the private key derived
+    /// from the given seed.
+    pub fn sign(
+        seed: &[u8; SEED_LEN],
+        salt: &[u8],
+        seq: i64,
+        value: &BencodeMut<'_>,
+    ) -> Result<MutableItem, ItemError> {
+        let value = value.encode();
+        check_lengths(salt, &value)?;
+
+        let (private_key, public_key) = ed25519::keypair(&seed[..]);
+        let signature = ed25519::signature(&signature_buffer(salt, seq, &value), &private_key);
+
+        MutableItem::from_raw(public_key, salt.to_vec(), seq, signature, value)
+    }
+
+    /// Create a new MutableItem from an already bencoded value, verifying the signature.
+    pub(crate) fn from_raw(
+        public_key: [u8; PUBLIC_KEY_LEN],
+        salt: Vec<u8>,
+        seq: i64,
+        signature: [u8; SIGNATURE_LEN],
+        value: Vec<u8>,
+    ) -> Result<MutableItem, ItemError> {
+        check_lengths(&salt, &value)?;
+
+        if !ed25519::verify(
+            &signature_buffer(&salt, seq, &value),
+            &public_key,
+            &signature,
+        ) {
+            return Err(ItemError::InvalidSignature);
+        }
+
+        Ok(MutableItem {
+            public_key: public_key,
+            salt: salt,
+            seq: seq,
+            signature: signature,
+            value: value,
+        })
+    }
+
+    /// Target the item is stored under.
+    pub fn target(&self) -> InfoHash {
+        mutable_target(&self.public_key, &self.salt)
+    }
+
+    /// Public key the item was signed with.
+    pub fn public_key(&self) -> &[u8; PUBLIC_KEY_LEN] {
+        &self.public_key
+    }
+
+    /// Salt of the item, empty if the item does not use one.
+    pub fn salt(&self) -> &[u8] {
+        &self.salt
+    }
+
+    /// Sequence number of the item.
+    pub fn seq(&self) -> i64 {
+        self.seq
+    }
+
+    /// Signature over the salt, sequence number and value of the item.
+    pub fn signature(&self) -> &[u8; SIGNATURE_LEN] {
+        &self.signature
+    }
+
+    /// Bencoded value of the item.
+    pub fn value(&self) -> &[u8] {
+        &self.value
+    }
+
+    /// Retrieve a `BencodeRef` representing the value of the item.
+    pub fn bencode_ref<'a>(&'a self) -> BencodeRef<'a> {
+        // Values are always produced by an encoder or verified by a decoder
+        BencodeRef::decode(&self.value, BDecodeOpt::default()).unwrap()
+    }
+}
+
+fn check_lengths(salt: &[u8], value: &[u8]) -> Result<(), ItemError> {
+    if salt.len() > MAX_SALT_LEN {
+        Err(ItemError::SaltTooLarge)
+    } else if value.len() > MAX_VALUE_LEN {
+        Err(ItemError::ValueTooLarge)
+    } else {
+        Ok(())
+    }
+}
+
+/// Target a mutable item with the given public key and salt is stored under.
+pub fn mutable_target(public_key: &[u8], salt: &[u8]) -> InfoHash {
+    ShaHashBuilder::new()
+        .add_bytes(public_key)
+        .add_bytes(salt)
+        .build()
+        .into()
+}
+
+/// Buffer that the signature of a mutable item is calculated over.
+///
+/// This is the bencoded salt (if not empty), sequence number and value, without the
+/// surrounding dictionary.
+pub fn signature_buffer(salt: &[u8], seq: i64, value: &[u8]) -> Vec<u8> {
+    let mut buffer = Vec::with_capacity(salt.len() + value.len() + 32);
+
+    if !salt.is_empty() {
+        buffer.extend_from_slice(format!("{}:{}", SALT_KEY.len(), SALT_KEY).as_bytes());
+        buffer.extend_from_slice(format!("{}:", salt.len()).as_bytes());
+        buffer.extend_from_slice(salt);
+    }
+    buffer.extend_from_slice(format!("{}:{}i{}e", SEQ_KEY.len(), SEQ_KEY, seq).as_bytes());
+    buffer.extend_from_slice(format!("{}:{}", VALUE_KEY.len(), VALUE_KEY).as_bytes());
+    buffer.extend_from_slice(value);
+
+    buffer
+}
+
+// ----------------------------------------------------------------------------//
+
+/// Future resolving to an item retrieved from the DHT.
+///
+/// Resolves to None if no node had a valid item, or if the DHT shut down.
+pub struct GetItem<T> {
+    recv: UnboundedReceiver<T>,
+}
+
+impl<T> GetItem<T> {
+    pub(crate) fn new(recv: UnboundedReceiver<T>) -> GetItem<T> {
+        GetItem { recv: recv }
+    }
+}
+
+impl<T> Future for GetItem<T> {
+    type Output = Option<T>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        Pin::new(&mut self.recv).poll_next(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ImmutableItem, ItemError, MutableItem};
+    use crate::bencode::BencodeMut;
+    use crate::util::bt::InfoHash;
+
+    const PUBLIC_KEY: &'static str =
+        "77ff84905a91936367c01360803104f92432fcd904a43511876df5cdf3e7e548";
+    const SIGNATURE_NO_SALT: &'static str = "305ac8aeb6c9c151fa120f120ea2cfb923564e11552d06a5d856091e5e853cff1260d3f39e4999684aa92eb73ffd136e6f4f3ecbfda0ce53a1608ecd7ae21f01";
+    const SIGNATURE_SALT: &'static str = "6834284b6b24c3204eb2fea824d82f88883a3d95e8b4a21b8c0ded553d17d17ddf9a8a7104b1258f30bed3787e6cb896fca78c58f8e03b5f18f14951a87d9a08";
+
+    fn hello_world() -> BencodeMut<'static> {
+        bt_ben_bytes!("Hello World!")
+    }
+
+    fn from_hex<T: AsMut<[u8]> + Default>(hex: &str) -> T {
+        let mut bytes = T::default();
+
+        for (index, dst) in bytes.as_mut().iter_mut().enumerate() {
+            *dst = u8::from_str_radix(&hex[index * 2..index * 2 + 2], 16).unwrap();
+        }
+
+        bytes
+    }
+
+    fn signature(hex: &str) -> [u8; 64] {
+        let (first, second): ([u8; 32], [u8; 32]) = (from_hex(&hex[..64]), from_hex(&hex[64..]));
+
+        let mut signature = [0u8; 64];
+        signature[..32].copy_from_slice(&first);
+        signature[32..].copy_from_slice(&second);
+        signature
+    }
+
+    fn hash(hex: &str) -> InfoHash {
+        let bytes: [u8; 20] = from_hex(hex);
+
+        bytes.into()
+    }
+
+    #[test]
+    fn positive_signature_buffer_vectors() {
+        assert_eq!(
+            super::signature_buffer(b"", 1, b"12:Hello World!"),
+            b"3:seqi1e1:v12:Hello World!".to_vec()
+        );
+        assert_eq!(
+            super::signature_buffer(b"foobar", 1, b"12:Hello World!"),
+            b"4:salt6:foobar3:seqi1e1:v12:Hello World!".to_vec()
+        );
+    }
+
+    #[test]
+    fn positive_mutable_item_vector_no_salt() {
+        let item = MutableItem::new(
+            from_hex(PUBLIC_KEY),
+            b"",
+            1,
+            signature(SIGNATURE_NO_SALT),
+            &hello_world(),
+        )
+        .unwrap();
+
+        assert_eq!(
+            item.target(),
+            hash("4a533d47ec9c7d95b1ad75f576cffc641853b750")
+        );
+    }
+
+    #[test]
+    fn positive_mutable_item_vector_salt() {
+        let item = MutableItem::new(
+            from_hex(PUBLIC_KEY),
+            b"foobar",
+            1,
+            signature(SIGNATURE_SALT),
+            &hello_world(),
+        )
+        .unwrap();
+
+        assert_eq!(
+            item.target(),
+            hash("411eba73b6f087ca51a3795d9c8c938d365e32c1")
+        );
+    }
+
+    #[test]
+    fn positive_immutable_item_vector() {
+        let item = ImmutableItem::new(&hello_world()).unwrap();
+
+        assert_eq!(
+            item.target(),
+            hash("e5f96f6f38320f0f33959cb4d3d656452117aadb")
+        );
+    }
+
+    #[test]
+    fn positive_mutable_item_sign() {
+        let item = MutableItem::sign(&[7u8; 32], b"salt", 5, &hello_world()).unwrap();
+
+        let verified = MutableItem::new(
+            *item.public_key(),
+            item.salt(),
+            item.seq(),
+            *item.signature(),
+            &hello_world(),
+        )
+        .unwrap();
+        assert_eq!(verified, item);
+    }
+
+    #[test]
+    fn negative_mutable_item_wrong_seq() {
+        let error = MutableItem::new(
+            from_hex(PUBLIC_KEY),
+            b"",
+            2,
+            signature(SIGNATURE_NO_SALT),
+            &hello_world(),
+        )
+        .unwrap_err();
+
+        assert_eq!(error, ItemError::InvalidSignature);
+    }
+
+    #[test]
+    fn negative_item_too_large() {
+        let value = bt_ben_bytes!(vec![0u8; super::MAX_VALUE_LEN]);
+
+        assert_eq!(
+            ImmutableItem::new(&value).unwrap_err(),
+            ItemError::ValueTooLarge
+        );
+        assert_eq!(
+            MutableItem::sign(&[7u8; 32], b"", 1, &value).unwrap_err(),
+            ItemError::ValueTooLarge
+        );
+        assert_eq!(
+            MutableItem::sign(&[7u8; 32], &[0u8; 65], 1, &hello_world()).unwrap_err(),
+            ItemError::SaltTooLarge
+        );
+    }
+}
@@ -0,0 +1,287 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+
+/// Number of queries an ip can have dropped before its bucket refills for it to be banned.
+const MAX_RATE_STRIKES: usize = 100;
+
+/// Number of malformed messages an ip can send us before it is banned.
+const MAX_MALFORMED_STRIKES: usize = 20;
+
+/// Interval we forget about ips that have not sent us anything at, so we do not grow forever.
+const HOST_EXPIRE_SECS: u64 = 60;
+
+/// What to do with a query from a remote ip.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub enum QueryCheck {
+    /// Query is within the rate limit and should be answered.
+    Allow,
+    /// Query is over the rate limit and should be dropped.
+    Drop,
+    /// Query is over the rate limit one too many times, the ip is now banned.
+    Ban,
+}
+
+/// Rate limit state of a single remote ip.
+struct Host {
+    tokens: f64,
+    last_refill: Instant,
+    last_seen: Instant,
+    rate_strikes: usize,
+    malformed_strikes: usize,
+}
+
+/// Limits the rate of queries we answer per remote ip with a token bucket, banning ips that
+/// keep going over the limit or keep sending us malformed messages.
+pub struct QueryLimiter {
+    // Queries per second and burst, no limit if None
+    opt_limit: Option<(f64, f64)>,
+    ban_duration: Duration,
+    hosts: HashMap<IpAddr, Host>,
+    bans: HashMap<IpAddr, Instant>,
+    last_expire: Instant,
+}
+
+impl QueryLimiter {
+    /// Create a new QueryLimiter, a qps of zero disables the rate limit.
+    pub fn new(qps: u32, burst: u32, ban_duration: Duration) -> QueryLimiter {
+        let opt_limit = if qps == 0 {
+            None
+        } else {
+            Some((qps as f64, burst.max(1) as f64))
+        };
+
+        QueryLimiter {
+            opt_limit: opt_limit,
+            ban_duration: ban_duration,
+            hosts: HashMap::new(),
+            bans: HashMap::new(),
+            last_expire: Instant::now(),
+        }
+    }
+
+    /// Duration that ips are banned for.
+    pub fn ban_duration(&self) -> Duration {
+        self.ban_duration
+    }
+
+    /// Whether the given ip is currently banned, messages from banned ips should be dropped.
+    pub fn is_banned(&mut self, ip: IpAddr) -> bool {
+        self.is_banned_at(ip, Instant::now())
+    }
+
+    /// Check a query from the given ip against the rate limit.
+    pub fn check_query(&mut self, ip: IpAddr) -> QueryCheck {
+        self.check_query_at(ip, Instant::now())
+    }
+
+    /// Record a malformed message from the given ip, returning true if the ip is now banned.
+    pub fn malformed(&mut self, ip: IpAddr) -> bool {
+        self.malformed_at(ip, Instant::now())
+    }
+
+    fn is_banned_at(&mut self, ip: IpAddr, now: Instant) -> bool {
+        match self.bans.get(&ip) {
+            Some(&until) if until > now => true,
+            Some(_) => {
+                self.bans.remove(&ip);
+                false
+            }
+            None => false,
+        }
+    }
+
+    fn check_query_at(&mut self, ip: IpAddr, now: Instant) -> QueryCheck {
+        let (qps, burst) = match self.opt_limit {
+            Some(limit) => limit,
+            None => return QueryCheck::Allow,
+        };
+        let host = self.host(ip, now, burst);
+
+        let elapsed = now.duration_since(host.last_refill);
+        host.tokens = (host.tokens + elapsed.as_secs_f64() * qps).min(burst);
+        host.last_refill = now;
+
+        // Ips only strike out if they keep going over the limit without backing off
+        if host.tokens >= burst {
+            host.rate_strikes = 0;
+        }
+
+        if host.tokens >= 1.0 {
+            host.tokens -= 1.0;
+
+            QueryCheck::Allow
+        } else {
+            host.rate_strikes += 1;
+
+            if host.rate_strikes >= MAX_RATE_STRIKES {
+                self.ban(ip, now);
+
+                QueryCheck::Ban
+            } else {
+                QueryCheck::Drop
+            }
+        }
+    }
+
+    fn malformed_at(&mut self, ip: IpAddr, now: Instant) -> bool {
+        let burst = self.opt_limit.map(|(_, burst)| burst).unwrap_or(0.0);
+        let host = self.host(ip, now, burst);
+
+        host.malformed_strikes += 1;
+        if host.malformed_strikes >= MAX_MALFORMED_STRIKES {
+            self.ban(ip, now);
+
+            true
+        } else {
+            false
+        }
+    }
+
+    fn ban(&mut self, ip: IpAddr, now: Instant) {
+        self.hosts.remove(&ip);
+        self.bans.insert(ip, now + self.ban_duration);
+    }
+
+    /// Rate limit state of the given ip, starting out with a full bucket.
+    fn host(&mut self, ip: IpAddr, now: Instant, burst: f64) -> &mut Host {
+        let expire = Duration::from_secs(HOST_EXPIRE_SECS);
+
+        if now.duration_since(self.last_expire) >= expire {
+            self.hosts
+                .retain(|_, host| now.duration_since(host.last_seen) < expire);
+            self.bans.retain(|_, &mut until| until > now);
+            self.last_expire = now;
+        }
+
+        let host = self.hosts.entry(ip).or_insert(Host {
+            tokens: burst,
+            last_refill: now,
+            last_seen: now,
+            rate_strikes: 0,
+            malformed_strikes: 0,
+        });
+        host.last_seen = now;
+
+        host
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::IpAddr;
+    use std::time::{Duration, Instant};
+
+    use super::{QueryCheck, QueryLimiter, MAX_MALFORMED_STRIKES, MAX_RATE_STRIKES};
+
+    fn ip(last: u8) -> IpAddr {
+        [10, 0, 0, last].into()
+    }
+
+    #[test]
+    fn positive_check_within_burst() {
+        let mut limiter = QueryLimiter::new(10, 5, Duration::from_secs(60));
+        let now = Instant::now();
+
+        for _ in 0..5 {
+            assert_eq!(limiter.check_query_at(ip(1), now), QueryCheck::Allow);
+        }
+        assert_eq!(limiter.check_query_at(ip(1), now), QueryCheck::Drop);
+    }
+
+    #[test]
+    fn positive_check_refills() {
+        let mut limiter = QueryLimiter::new(10, 5, Duration::from_secs(60));
+        let now = Instant::now();
+
+        for _ in 0..5 {
+            limiter.check_query_at(ip(1), now);
+        }
+        assert_eq!(limiter.check_query_at(ip(1), now), QueryCheck::Drop);
+
+        let later = now + Duration::from_millis(100);
+        assert_eq!(limiter.check_query_at(ip(1), later), QueryCheck::Allow);
+        assert_eq!(limiter.check_query_at(ip(1), later), QueryCheck::Drop);
+    }
+
+    #[test]
+    fn positive_check_other_ip_unaffected() {
+        let mut limiter = QueryLimiter::new(10, 5, Duration::from_secs(60));
+        let now = Instant::now();
+
+        for _ in 0..10 {
+            limiter.check_query_at(ip(1), now);
+        }
+
+        assert_eq!(limiter.check_query_at(ip(2), now), QueryCheck::Allow);
+    }
+
+    #[test]
+    fn positive_check_no_limit() {
+        let mut limiter = QueryLimiter::new(0, 5, Duration::from_secs(60));
+        let now = Instant::now();
+
+        for _ in 0..(MAX_RATE_STRIKES * 2) {
+            assert_eq!(limiter.check_query_at(ip(1), now), QueryCheck::Allow);
+        }
+    }
+
+    #[test]
+    fn positive_check_bans_flood() {
+        let mut limiter = QueryLimiter::new(10, 5, Duration::from_secs(60));
+        let now = Instant::now();
+
+        for _ in 0..5 {
+            limiter.check_query_at(ip(1), now);
+        }
+        for _ in 1..MAX_RATE_STRIKES {
+            assert_eq!(limiter.check_query_at(ip(1), now), QueryCheck::Drop);
+        }
+        assert_eq!(limiter.check_query_at(ip(1), now), QueryCheck::Ban);
+
+        assert!(limiter.is_banned_at(ip(1), now));
+        assert!(!limiter.is_banned_at(ip(2), now));
+    }
+
+    #[test]
+    fn positive_ban_expires() {
+        let mut limiter = QueryLimiter::new(10, 5, Duration::from_secs(60));
+        let now = Instant::now();
+
+        for _ in 0..MAX_MALFORMED_STRIKES {
+            limiter.malformed_at(ip(1), now);
+        }
+
+        assert!(limiter.is_banned_at(ip(1), now + Duration::from_secs(59)));
+        assert!(!limiter.is_banned_at(ip(1), now + Duration::from_secs(60)));
+    }
+
+    #[test]
+    fn positive_malformed_bans() {
+        let mut limiter = QueryLimiter::new(10, 5, Duration::from_secs(60));
+        let now = Instant::now();
+
+        for _ in 1..MAX_MALFORMED_STRIKES {
+            assert!(!limiter.malformed_at(ip(1), now));
+        }
+
+        assert!(limiter.malformed_at(ip(1), now));
+        assert!(limiter.is_banned_at(ip(1), now));
+    }
+
+    #[test]
+    fn negative_check_backing_off_not_banned() {
+        let mut limiter = QueryLimiter::new(10, 5, Duration::from_secs(60));
+        let mut now = Instant::now();
+
+        // Going over the limit every now and then, but letting the bucket refill in between
+        for _ in 0..(MAX_RATE_STRIKES * 2) {
+            for _ in 0..6 {
+                limiter.check_query_at(ip(1), now);
+            }
+            now += Duration::from_secs(1);
+        }
+
+        assert!(!limiter.is_banned_at(ip(1), now));
+    }
+}
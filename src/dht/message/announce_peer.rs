@@ -11,6 +11,7 @@ use crate::dht::message::request::{self, RequestValidate};
 
 const PORT_KEY: &'static str = "port";
 const IMPLIED_PORT_KEY: &'static str = "implied_port";
+const SEED_KEY: &'static str = "seed";
 
 // TODO: Integrate the Token type into the request message.
 
@@ -27,6 +28,8 @@ pub struct AnnouncePeerRequest<'a> {
     info_hash: InfoHash,
     token: &'a [u8],
     port: ConnectPort,
+    // Whether the announcing peer is a seed, see BEP 33
+    seed: bool,
 }
 
 impl<'a> AnnouncePeerRequest<'a> {
@@ -36,6 +39,7 @@ impl<'a> AnnouncePeerRequest<'a> {
         info_hash: InfoHash,
         token: &'a [u8],
         port: ConnectPort,
+        seed: bool,
     ) -> AnnouncePeerRequest<'a> {
         AnnouncePeerRequest {
             trans_id: trans_id,
@@ -43,6 +47,7 @@ impl<'a> AnnouncePeerRequest<'a> {
             info_hash: info_hash,
             token: token,
             port: port,
+            seed: seed,
         }
     }
 
@@ -76,12 +81,18 @@ impl<'a> AnnouncePeerRequest<'a> {
             }
         };
 
+        let seed = match rqst_root.lookup(SEED_KEY.as_bytes()).map(|n| n.int()) {
+            Some(Some(n)) => n != 0,
+            _ => false,
+        };
+
         Ok(AnnouncePeerRequest::new(
             trans_id,
             node_id,
             info_hash,
             token,
             response_port,
+            seed,
         ))
     }
 
@@ -105,6 +116,10 @@ impl<'a> AnnouncePeerRequest<'a> {
         self.port
     }
 
+    pub fn seed(&self) -> bool {
+        self.seed
+    }
+
     pub fn encode(&self) -> Vec<u8> {
         // In case a client errors out when the port key is not present, even when
         // implied port is specified, we will provide a dummy value in that case.
@@ -112,6 +127,7 @@ impl<'a> AnnouncePeerRequest<'a> {
             ConnectPort::Implied => (0, 1),
             ConnectPort::Explicit(n) => (n, 0),
         };
+        let info_hash = self.info_hash.truncated();
 
         (bt_ben_map! {
             //message::CLIENT_TYPE_KEY => ben_bytes!(dht::CLIENT_IDENTIFICATION),
@@ -121,8 +137,9 @@ impl<'a> AnnouncePeerRequest<'a> {
             request::REQUEST_ARGS_KEY => bt_ben_map!{
                 message::NODE_ID_KEY => bt_ben_bytes!(self.node_id.as_ref()),
                 IMPLIED_PORT_KEY => bt_ben_int!(implied_value),
-                message::INFO_HASH_KEY => bt_ben_bytes!(self.info_hash.as_ref()),
+                message::INFO_HASH_KEY => bt_ben_bytes!(&info_hash[..]),
                 PORT_KEY => bt_ben_int!(displayed_port as i64),
+                SEED_KEY => bt_ben_int!(self.seed as i64),
                 message::TOKEN_KEY => bt_ben_bytes!(self.token)
             }
         })
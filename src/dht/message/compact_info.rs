@@ -1,8 +1,9 @@
-use std::net::{Ipv4Addr, SocketAddrV4};
+use std::net::SocketAddr;
 
 // use crate::bencode::{Bencode};
 use crate::dht::bencode::Bencode;
 use crate::util::bt::{self, NodeId};
+use crate::util::convert;
 use crate::util::error::{LengthError, LengthErrorKind, LengthResult};
 use crate::util::sha::ShaHash;
 
@@ -14,35 +15,62 @@ use crate::util::sha::ShaHash;
 const BYTES_PER_COMPACT_IP: usize = 6;
 const BYTES_PER_COMPACT_NODE_INFO: usize = 26;
 
+// Compact info of IPv6 nodes and values (BEP 32)
+const BYTES_PER_COMPACT_IP_V6: usize = 18;
+const BYTES_PER_COMPACT_NODE_INFO_V6: usize = 38;
+
+/// Compact node info, either IPv4 nodes from a `nodes` key or IPv6 nodes from a `nodes6` key.
 #[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
 pub struct CompactNodeInfo<'a> {
     nodes: &'a [u8],
+    ipv6: bool,
 }
 
 impl<'a> CompactNodeInfo<'a> {
+    /// Create a new CompactNodeInfo for IPv4 nodes.
     pub fn new(nodes: &'a [u8]) -> LengthResult<CompactNodeInfo<'a>> {
-        if nodes.len() % BYTES_PER_COMPACT_NODE_INFO != 0 {
+        CompactNodeInfo::with_family(nodes, false)
+    }
+
+    /// Create a new CompactNodeInfo for IPv6 nodes.
+    pub fn new_v6(nodes: &'a [u8]) -> LengthResult<CompactNodeInfo<'a>> {
+        CompactNodeInfo::with_family(nodes, true)
+    }
+
+    fn with_family(nodes: &'a [u8], ipv6: bool) -> LengthResult<CompactNodeInfo<'a>> {
+        let node_len = compact_node_len(ipv6);
+
+        if nodes.len() % node_len != 0 {
             Err(LengthError::new(
                 LengthErrorKind::LengthMultipleExpected,
-                BYTES_PER_COMPACT_NODE_INFO,
+                node_len,
             ))
         } else {
-            Ok(CompactNodeInfo { nodes: nodes })
+            Ok(CompactNodeInfo {
+                nodes: nodes,
+                ipv6: ipv6,
+            })
         }
     }
 
     pub fn nodes(&self) -> &'a [u8] {
         self.nodes
     }
+
+    /// Whether the nodes are IPv6 nodes.
+    pub fn is_ipv6(&self) -> bool {
+        self.ipv6
+    }
 }
 
 impl<'a> IntoIterator for CompactNodeInfo<'a> {
-    type Item = (NodeId, SocketAddrV4);
+    type Item = (NodeId, SocketAddr);
     type IntoIter = CompactNodeInfoIter<'a>;
 
     fn into_iter(self) -> Self::IntoIter {
         CompactNodeInfoIter {
             nodes: self.nodes,
+            node_len: compact_node_len(self.ipv6),
             pos: 0,
         }
     }
@@ -51,28 +79,66 @@ impl<'a> IntoIterator for CompactNodeInfo<'a> {
 #[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
 pub struct CompactNodeInfoIter<'a> {
     nodes: &'a [u8],
+    node_len: usize,
     pos: usize,
 }
 
 impl<'a> Iterator for CompactNodeInfoIter<'a> {
-    type Item = (NodeId, SocketAddrV4);
+    type Item = (NodeId, SocketAddr);
 
-    fn next(&mut self) -> Option<(NodeId, SocketAddrV4)> {
+    fn next(&mut self) -> Option<(NodeId, SocketAddr)> {
         if self.pos == self.nodes.len() {
             None
         } else {
-            let compact_info_offset = self.pos + BYTES_PER_COMPACT_NODE_INFO;
+            let compact_info_offset = self.pos + self.node_len;
             let compact_info = &self.nodes[self.pos..compact_info_offset];
 
-            self.pos += BYTES_PER_COMPACT_NODE_INFO;
+            self.pos += self.node_len;
 
             Some(parts_from_compact_info(compact_info))
         }
     }
 }
 
+/// Nodes out of the IPv4 and IPv6 nodes of a response that share the address family of
+/// the given address, which is usually the address of the node that sent the response.
+pub fn same_family<'a>(
+    addr: &SocketAddr,
+    nodes: Option<CompactNodeInfo<'a>>,
+    nodes6: Option<CompactNodeInfo<'a>>,
+) -> Option<CompactNodeInfo<'a>> {
+    if addr.is_ipv6() {
+        nodes6
+    } else {
+        nodes
+    }
+}
+
+/// Nodes out of the IPv4 and IPv6 nodes of a response that do not share the address family
+/// of the given address.
+pub fn other_family<'a>(
+    addr: &SocketAddr,
+    nodes: Option<CompactNodeInfo<'a>>,
+    nodes6: Option<CompactNodeInfo<'a>>,
+) -> Option<CompactNodeInfo<'a>> {
+    if addr.is_ipv6() {
+        nodes
+    } else {
+        nodes6
+    }
+}
+
+/// Encode the given address as compact peer info, 6 bytes for IPv4 and 18 bytes for IPv6.
+pub fn compact_addr(addr: SocketAddr) -> Vec<u8> {
+    match addr {
+        SocketAddr::V4(v4_addr) => convert::sock_v4_to_bytes_be(v4_addr).to_vec(),
+        SocketAddr::V6(v6_addr) => convert::sock_v6_to_bytes_be(v6_addr).to_vec(),
+    }
+}
+
 // ----------------------------------------------------------------------------//
 
+/// Compact peer info, values may be IPv4 or IPv6 peers.
 #[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
 pub struct CompactValueInfo<'a> {
     values: &'a [Bencode<'a>],
@@ -88,7 +154,9 @@ impl<'a> CompactValueInfo<'a> {
             // TODO: Do not unwrap here please
             let compact_value = node.bytes().unwrap();
 
-            if compact_value.len() != BYTES_PER_COMPACT_IP {
+            if compact_value.len() != BYTES_PER_COMPACT_IP
+                && compact_value.len() != BYTES_PER_COMPACT_IP_V6
+            {
                 return Err(LengthError::with_index(
                     LengthErrorKind::LengthExpected,
                     BYTES_PER_COMPACT_IP,
@@ -106,7 +174,7 @@ impl<'a> CompactValueInfo<'a> {
 }
 
 impl<'a> IntoIterator for CompactValueInfo<'a> {
-    type Item = SocketAddrV4;
+    type Item = SocketAddr;
     type IntoIter = CompactValueInfoIter<'a>;
 
     fn into_iter(self) -> Self::IntoIter {
@@ -124,9 +192,9 @@ pub struct CompactValueInfoIter<'a> {
 }
 
 impl<'a> Iterator for CompactValueInfoIter<'a> {
-    type Item = SocketAddrV4;
+    type Item = SocketAddr;
 
-    fn next(&mut self) -> Option<SocketAddrV4> {
+    fn next(&mut self) -> Option<SocketAddr> {
         if self.pos == self.values.len() {
             None
         } else {
@@ -134,48 +202,53 @@ impl<'a> Iterator for CompactValueInfoIter<'a> {
 
             self.pos += 1;
 
-            Some(socket_v4_from_bytes_be(compact_info.bytes().unwrap()).unwrap())
+            Some(socket_from_bytes_be(compact_info.bytes().unwrap()).unwrap())
         }
     }
 }
 
 // ----------------------------------------------------------------------------//
 
-/// Panics if the size of compact_info is less than BYTES_PER_COMPACT_NODE_INFO.
-fn parts_from_compact_info(compact_info: &[u8]) -> (NodeId, SocketAddrV4) {
+fn compact_node_len(ipv6: bool) -> usize {
+    if ipv6 {
+        BYTES_PER_COMPACT_NODE_INFO_V6
+    } else {
+        BYTES_PER_COMPACT_NODE_INFO
+    }
+}
+
+/// Panics if the size of compact_info is not BYTES_PER_COMPACT_NODE_INFO(_V6).
+fn parts_from_compact_info(compact_info: &[u8]) -> (NodeId, SocketAddr) {
     // Use unwarp here because we know these can never fail, but they arent statically guaranteed
     let node_id = ShaHash::from_hash(&compact_info[0..bt::NODE_ID_LEN]).unwrap();
 
-    let compact_ip_offset = bt::NODE_ID_LEN + BYTES_PER_COMPACT_IP;
-    let socket =
-        socket_v4_from_bytes_be(&compact_info[bt::NODE_ID_LEN..compact_ip_offset]).unwrap();
+    let socket = socket_from_bytes_be(&compact_info[bt::NODE_ID_LEN..]).unwrap();
 
     (node_id, socket)
 }
 
-fn socket_v4_from_bytes_be(bytes: &[u8]) -> LengthResult<SocketAddrV4> {
-    if bytes.len() != BYTES_PER_COMPACT_IP {
+fn socket_from_bytes_be(bytes: &[u8]) -> LengthResult<SocketAddr> {
+    if bytes.len() == BYTES_PER_COMPACT_IP {
+        let mut v4_bytes = [0u8; BYTES_PER_COMPACT_IP];
+        v4_bytes.copy_from_slice(bytes);
+
+        Ok(SocketAddr::V4(convert::bytes_be_to_sock_v4(v4_bytes)))
+    } else if bytes.len() == BYTES_PER_COMPACT_IP_V6 {
+        let mut v6_bytes = [0u8; BYTES_PER_COMPACT_IP_V6];
+        v6_bytes.copy_from_slice(bytes);
+
+        Ok(SocketAddr::V6(convert::bytes_be_to_sock_v6(v6_bytes)))
+    } else {
         Err(LengthError::new(
             LengthErrorKind::LengthExpected,
             BYTES_PER_COMPACT_IP,
         ))
-    } else {
-        let (oc_one, oc_two, oc_three, oc_four) = (bytes[0], bytes[1], bytes[2], bytes[3]);
-
-        let mut port = 0u16;
-        port |= bytes[4] as u16;
-        port <<= 8;
-        port |= bytes[5] as u16;
-
-        let ip = Ipv4Addr::new(oc_one, oc_two, oc_three, oc_four);
-
-        Ok(SocketAddrV4::new(ip, port))
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use std::net::{Ipv4Addr, SocketAddrV4};
+    use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6};
 
     use crate::dht::message::compact_info::{CompactNodeInfo, CompactValueInfo};
     use crate::util::bt::NodeId;
@@ -196,7 +269,7 @@ mod tests {
         ];
         let compact_node = CompactNodeInfo::new(&bytes[..]).unwrap();
 
-        let collected_info: Vec<(NodeId, SocketAddrV4)> = compact_node.into_iter().collect();
+        let collected_info: Vec<(NodeId, SocketAddr)> = compact_node.into_iter().collect();
         assert_eq!(collected_info.len(), 1);
 
         assert_eq!(
@@ -205,7 +278,7 @@ mod tests {
         );
         assert_eq!(
             collected_info[0].1,
-            SocketAddrV4::new(Ipv4Addr::new(192, 168, 0, 1), 43689)
+            SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::new(192, 168, 0, 1), 43689))
         );
     }
 
@@ -217,7 +290,7 @@ mod tests {
         ];
         let compact_node = CompactNodeInfo::new(&bytes[..]).unwrap();
 
-        let collected_info: Vec<(NodeId, SocketAddrV4)> = compact_node.into_iter().collect();
+        let collected_info: Vec<(NodeId, SocketAddr)> = compact_node.into_iter().collect();
         assert_eq!(collected_info.len(), 2);
 
         assert_eq!(
@@ -226,7 +299,7 @@ mod tests {
         );
         assert_eq!(
             collected_info[0].1,
-            SocketAddrV4::new(Ipv4Addr::new(192, 168, 0, 1), 240)
+            SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::new(192, 168, 0, 1), 240))
         );
 
         assert_eq!(
@@ -235,7 +308,7 @@ mod tests {
         );
         assert_eq!(
             collected_info[1].1,
-            SocketAddrV4::new(Ipv4Addr::new(192, 168, 0, 2), 240)
+            SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::new(192, 168, 0, 2), 240))
         );
     }
 
@@ -244,7 +317,7 @@ mod tests {
         let bencode_values = Vec::new();
         let compact_value = CompactValueInfo::new(&bencode_values[..]).unwrap();
 
-        let collected_info: Vec<SocketAddrV4> = compact_value.into_iter().collect();
+        let collected_info: Vec<SocketAddr> = compact_value.into_iter().collect();
 
         assert!(collected_info.is_empty());
     }
@@ -255,12 +328,12 @@ mod tests {
         let bencode_values = dht_ben_list!(dht_ben_bytes!(&bytes));
         let compact_value = CompactValueInfo::new(bencode_values.list().unwrap()).unwrap();
 
-        let collected_info: Vec<SocketAddrV4> = compact_value.into_iter().collect();
+        let collected_info: Vec<SocketAddr> = compact_value.into_iter().collect();
         assert_eq!(collected_info.len(), 1);
 
         assert_eq!(
             collected_info[0],
-            SocketAddrV4::new(Ipv4Addr::new(127, 0, 0, 1), 6881)
+            SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::new(127, 0, 0, 1), 6881))
         );
     }
 
@@ -271,16 +344,74 @@ mod tests {
         let bencode_values = dht_ben_list!(dht_ben_bytes!(&bytes_one), dht_ben_bytes!(&bytes_two));
         let compact_value = CompactValueInfo::new(bencode_values.list().unwrap()).unwrap();
 
-        let collected_info: Vec<SocketAddrV4> = compact_value.into_iter().collect();
+        let collected_info: Vec<SocketAddr> = compact_value.into_iter().collect();
         assert_eq!(collected_info.len(), 2);
 
         assert_eq!(
             collected_info[0],
-            SocketAddrV4::new(Ipv4Addr::new(127, 0, 0, 1), 6881)
+            SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::new(127, 0, 0, 1), 6881))
         );
         assert_eq!(
             collected_info[1],
-            SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 1), 6889)
+            SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 1), 6889))
         );
     }
+
+    #[test]
+    fn positive_compact_nodes_v6_one() {
+        let mut bytes = [1u8; 38];
+        bytes[20..36].copy_from_slice(&Ipv6Addr::LOCALHOST.octets());
+        bytes[36] = 170;
+        bytes[37] = 169;
+        let compact_node = CompactNodeInfo::new_v6(&bytes[..]).unwrap();
+
+        let collected_info: Vec<(NodeId, SocketAddr)> = compact_node.into_iter().collect();
+        assert_eq!(collected_info.len(), 1);
+
+        assert!(compact_node.is_ipv6());
+        assert_eq!(
+            collected_info[0].0,
+            ShaHash::from_hash(&bytes[0..20]).unwrap()
+        );
+        assert_eq!(
+            collected_info[0].1,
+            SocketAddr::V6(SocketAddrV6::new(Ipv6Addr::LOCALHOST, 43689, 0, 0))
+        );
+    }
+
+    #[test]
+    fn negative_compact_nodes_v6_wrong_length() {
+        let bytes = [1u8; 26];
+
+        assert!(CompactNodeInfo::new(&bytes[..]).is_ok());
+        assert!(CompactNodeInfo::new_v6(&bytes[..]).is_err());
+    }
+
+    #[test]
+    fn positive_compact_values_mixed() {
+        let bytes_one = [127, 0, 0, 1, (6881 >> 8) as u8, (6881 & 0x00FF) as u8];
+        let mut bytes_two = [0u8; 18];
+        bytes_two[..16].copy_from_slice(&Ipv6Addr::LOCALHOST.octets());
+        bytes_two[16] = (6889 >> 8) as u8;
+        bytes_two[17] = (6889 & 0x00FF) as u8;
+        let bencode_values = dht_ben_list!(dht_ben_bytes!(&bytes_one), dht_ben_bytes!(&bytes_two));
+        let compact_value = CompactValueInfo::new(bencode_values.list().unwrap()).unwrap();
+
+        let collected_info: Vec<SocketAddr> = compact_value.into_iter().collect();
+        assert_eq!(
+            collected_info,
+            vec![
+                SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::new(127, 0, 0, 1), 6881)),
+                SocketAddr::V6(SocketAddrV6::new(Ipv6Addr::LOCALHOST, 6889, 0, 0))
+            ]
+        );
+    }
+
+    #[test]
+    fn negative_compact_values_wrong_length() {
+        let bytes = [127, 0, 0, 1, 0];
+        let bencode_values = dht_ben_list!(dht_ben_bytes!(&bytes));
+
+        assert!(CompactValueInfo::new(bencode_values.list().unwrap()).is_err());
+    }
 }
@@ -12,10 +12,16 @@ use crate::dht::message;
 const ERROR_ARGS_KEY: &'static str = "e";
 const NUM_ERROR_ARGS: usize = 2;
 
-const GENERIC_ERROR_CODE: u8 = 201;
-const SERVER_ERROR_CODE: u8 = 202;
-const PROTOCOL_ERROR_CODE: u8 = 203;
-const METHOD_UNKNOWN_CODE: u8 = 204;
+const GENERIC_ERROR_CODE: u16 = 201;
+const SERVER_ERROR_CODE: u16 = 202;
+const PROTOCOL_ERROR_CODE: u16 = 203;
+const METHOD_UNKNOWN_CODE: u16 = 204;
+// Error codes for storing items from BEP 44
+const MESSAGE_TOO_BIG_CODE: u16 = 205;
+const INVALID_SIGNATURE_CODE: u16 = 206;
+const SALT_TOO_BIG_CODE: u16 = 207;
+const CAS_MISMATCH_CODE: u16 = 301;
+const SEQUENCE_NUMBER_TOO_LOW_CODE: u16 = 302;
 
 #[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
 pub enum ErrorCode {
@@ -23,15 +29,25 @@ pub enum ErrorCode {
     ServerError,
     ProtocolError,
     MethodUnknown,
+    MessageTooBig,
+    InvalidSignature,
+    SaltTooBig,
+    CasMismatch,
+    SequenceNumberTooLow,
 }
 
 impl ErrorCode {
-    fn new(code: u8) -> DhtResult<ErrorCode> {
+    fn new(code: u16) -> DhtResult<ErrorCode> {
         match code {
             GENERIC_ERROR_CODE => Ok(ErrorCode::GenericError),
             SERVER_ERROR_CODE => Ok(ErrorCode::ServerError),
             PROTOCOL_ERROR_CODE => Ok(ErrorCode::ProtocolError),
             METHOD_UNKNOWN_CODE => Ok(ErrorCode::MethodUnknown),
+            MESSAGE_TOO_BIG_CODE => Ok(ErrorCode::MessageTooBig),
+            INVALID_SIGNATURE_CODE => Ok(ErrorCode::InvalidSignature),
+            SALT_TOO_BIG_CODE => Ok(ErrorCode::SaltTooBig),
+            CAS_MISMATCH_CODE => Ok(ErrorCode::CasMismatch),
+            SEQUENCE_NUMBER_TOO_LOW_CODE => Ok(ErrorCode::SequenceNumberTooLow),
             unknown => Err(DhtError::from_kind(DhtErrorKind::InvalidResponse {
                 details: format!("Error Message Invalid Error Code {:?}", unknown),
             })),
@@ -39,13 +55,18 @@ impl ErrorCode {
     }
 }
 
-impl Into<u8> for ErrorCode {
-    fn into(self) -> u8 {
+impl Into<u16> for ErrorCode {
+    fn into(self) -> u16 {
         match self {
             ErrorCode::GenericError => GENERIC_ERROR_CODE,
             ErrorCode::ServerError => SERVER_ERROR_CODE,
             ErrorCode::ProtocolError => PROTOCOL_ERROR_CODE,
             ErrorCode::MethodUnknown => METHOD_UNKNOWN_CODE,
+            ErrorCode::MessageTooBig => MESSAGE_TOO_BIG_CODE,
+            ErrorCode::InvalidSignature => INVALID_SIGNATURE_CODE,
+            ErrorCode::SaltTooBig => SALT_TOO_BIG_CODE,
+            ErrorCode::CasMismatch => CAS_MISMATCH_CODE,
+            ErrorCode::SequenceNumberTooLow => SEQUENCE_NUMBER_TOO_LOW_CODE,
         }
     }
 }
@@ -56,7 +77,7 @@ impl Into<u8> for ErrorCode {
 struct ErrorValidate;
 
 impl ErrorValidate {
-    fn extract_error_args<'a>(&self, args: &[Bencode<'a>]) -> DhtResult<(u8, &'a str)> {
+    fn extract_error_args<'a>(&self, args: &[Bencode<'a>]) -> DhtResult<(u16, &'a str)> {
         if args.len() != NUM_ERROR_ARGS {
             return Err(DhtError::from_kind(DhtErrorKind::InvalidResponse {
                 details: format!("Error Message Invalid Number Of Error Args: {}", args.len()),
@@ -66,7 +87,7 @@ impl ErrorValidate {
         let code = self.convert_int(&args[0], &format!("{}[0]", ERROR_ARGS_KEY))?;
         let message = self.convert_str(&args[1], &format!("{}[1]", ERROR_ARGS_KEY))?;
 
-        Ok((code as u8, message))
+        Ok((code as u16, message))
     }
 }
 
@@ -135,7 +156,7 @@ impl<'a> ErrorMessage<'a> {
     }
 
     pub fn encode(&self) -> Vec<u8> {
-        let error_code = Into::<u8>::into(self.code) as i64;
+        let error_code = Into::<u16>::into(self.code) as i64;
 
         (dht_ben_map! {
             //message::CLIENT_TYPE_KEY => ben_bytes!(dht::CLIENT_IDENTIFICATION),
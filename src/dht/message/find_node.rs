@@ -1,26 +1,36 @@
+use std::collections::BTreeMap;
+
 // use crate::bencode::{Bencode, BencodeConvert, Dictionary};
 use crate::util::bt::NodeId;
 
 use crate::dht::bencode::{Bencode, BencodeConvert, Dictionary};
-use crate::dht::error::DhtResult;
-use crate::dht::message;
+use crate::dht::error::{DhtError, DhtErrorKind, DhtResult};
 use crate::dht::message::compact_info::CompactNodeInfo;
 use crate::dht::message::request::{self, RequestValidate};
-use crate::dht::message::response::ResponseValidate;
+use crate::dht::message::response::{self, ResponseValidate};
+use crate::dht::message::{self, Want};
 
 #[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
 pub struct FindNodeRequest<'a> {
     trans_id: &'a [u8],
     node_id: NodeId,
     target_id: NodeId,
+    // Address families of the nodes wanted back, see BEP 32
+    want: Option<Want>,
 }
 
 impl<'a> FindNodeRequest<'a> {
-    pub fn new(trans_id: &'a [u8], node_id: NodeId, target_id: NodeId) -> FindNodeRequest<'a> {
+    pub fn new(
+        trans_id: &'a [u8],
+        node_id: NodeId,
+        target_id: NodeId,
+        want: Option<Want>,
+    ) -> FindNodeRequest<'a> {
         FindNodeRequest {
             trans_id: trans_id,
             node_id: node_id,
             target_id: target_id,
+            want: want,
         }
     }
 
@@ -41,7 +51,9 @@ impl<'a> FindNodeRequest<'a> {
         let target_id_bytes = validate.lookup_and_convert_bytes(rqst_root, target_key)?;
         let target_id = validate.validate_node_id(target_id_bytes)?;
 
-        Ok(FindNodeRequest::new(trans_id, node_id, target_id))
+        let want = Want::from_parts(rqst_root);
+
+        Ok(FindNodeRequest::new(trans_id, node_id, target_id, want))
     }
 
     pub fn transaction_id(&self) -> &'a [u8] {
@@ -56,16 +68,31 @@ impl<'a> FindNodeRequest<'a> {
         self.target_id
     }
 
+    pub fn want(&self) -> Option<Want> {
+        self.want
+    }
+
     pub fn encode(&self) -> Vec<u8> {
-        (bt_ben_map! {
+        let mut request_args = BTreeMap::new();
+
+        request_args.insert(
+            message::NODE_ID_KEY.as_bytes(),
+            dht_ben_bytes!(self.node_id.as_ref()),
+        );
+        request_args.insert(
+            message::TARGET_ID_KEY.as_bytes(),
+            dht_ben_bytes!(self.target_id.as_ref()),
+        );
+        if let Some(want) = self.want {
+            request_args.insert(message::WANT_KEY.as_bytes(), want.to_bencode());
+        }
+
+        (dht_ben_map! {
             //message::CLIENT_TYPE_KEY => ben_bytes!(dht::CLIENT_IDENTIFICATION),
-            message::TRANSACTION_ID_KEY => bt_ben_bytes!(self.trans_id),
-            message::MESSAGE_TYPE_KEY => bt_ben_bytes!(message::REQUEST_TYPE_KEY),
-            message::REQUEST_TYPE_KEY => bt_ben_bytes!(request::FIND_NODE_TYPE_KEY),
-            request::REQUEST_ARGS_KEY => bt_ben_map!{
-                message::NODE_ID_KEY => bt_ben_bytes!(self.node_id.as_ref()),
-                message::TARGET_ID_KEY => bt_ben_bytes!(self.target_id.as_ref())
-            }
+            message::TRANSACTION_ID_KEY => dht_ben_bytes!(self.trans_id),
+            message::MESSAGE_TYPE_KEY => dht_ben_bytes!(message::REQUEST_TYPE_KEY),
+            message::REQUEST_TYPE_KEY => dht_ben_bytes!(request::FIND_NODE_TYPE_KEY),
+            request::REQUEST_ARGS_KEY => Bencode::Dict(request_args)
         })
         .encode()
     }
@@ -75,23 +102,24 @@ impl<'a> FindNodeRequest<'a> {
 pub struct FindNodeResponse<'a> {
     trans_id: &'a [u8],
     node_id: NodeId,
-    nodes: CompactNodeInfo<'a>,
+    nodes: Option<CompactNodeInfo<'a>>,
+    // IPv6 nodes, see BEP 32
+    nodes6: Option<CompactNodeInfo<'a>>,
 }
 
 impl<'a> FindNodeResponse<'a> {
     pub fn new(
         trans_id: &'a [u8],
         node_id: NodeId,
-        nodes: &'a [u8],
-    ) -> DhtResult<FindNodeResponse<'a>> {
-        let validate = ResponseValidate::new(trans_id);
-        let compact_nodes = validate.validate_nodes(nodes)?;
-
-        Ok(FindNodeResponse {
+        nodes: Option<CompactNodeInfo<'a>>,
+        nodes6: Option<CompactNodeInfo<'a>>,
+    ) -> FindNodeResponse<'a> {
+        FindNodeResponse {
             trans_id: trans_id,
             node_id: node_id,
-            nodes: compact_nodes,
-        })
+            nodes: nodes,
+            nodes6: nodes6,
+        }
     }
 
     pub fn from_parts(
@@ -103,9 +131,22 @@ impl<'a> FindNodeResponse<'a> {
         let node_id_bytes = validate.lookup_and_convert_bytes(rsp_root, message::NODE_ID_KEY)?;
         let node_id = validate.validate_node_id(node_id_bytes)?;
 
-        let nodes = validate.lookup_and_convert_bytes(rsp_root, message::NODES_KEY)?;
+        let nodes = match validate.lookup_and_convert_bytes(rsp_root, message::NODES_KEY) {
+            Ok(nodes) => Some(validate.validate_nodes(nodes)?),
+            Err(_) => None,
+        };
+        let nodes6 = match validate.lookup_and_convert_bytes(rsp_root, message::NODES6_KEY) {
+            Ok(nodes6) => Some(validate.validate_nodes6(nodes6)?),
+            Err(_) => None,
+        };
+
+        if nodes.is_none() && nodes6.is_none() {
+            return Err(DhtError::from_kind(DhtErrorKind::InvalidResponse {
+                details: "Failed To Find nodes Or nodes6 In Node Response".to_owned(),
+            }));
+        }
 
-        FindNodeResponse::new(trans_id, node_id, nodes)
+        Ok(FindNodeResponse::new(trans_id, node_id, nodes, nodes6))
     }
 
     pub fn transaction_id(&self) -> &'a [u8] {
@@ -116,20 +157,110 @@ impl<'a> FindNodeResponse<'a> {
         self.node_id
     }
 
-    pub fn nodes(&self) -> CompactNodeInfo<'a> {
+    pub fn nodes(&self) -> Option<CompactNodeInfo<'a>> {
         self.nodes
     }
 
+    pub fn nodes6(&self) -> Option<CompactNodeInfo<'a>> {
+        self.nodes6
+    }
+
     pub fn encode(&self) -> Vec<u8> {
-        (bt_ben_map! {
+        let mut response_args = BTreeMap::new();
+
+        response_args.insert(
+            message::NODE_ID_KEY.as_bytes(),
+            dht_ben_bytes!(self.node_id.as_ref()),
+        );
+        if let Some(nodes) = self.nodes {
+            response_args.insert(message::NODES_KEY.as_bytes(), dht_ben_bytes!(nodes.nodes()));
+        }
+        if let Some(nodes6) = self.nodes6 {
+            response_args.insert(
+                message::NODES6_KEY.as_bytes(),
+                dht_ben_bytes!(nodes6.nodes()),
+            );
+        }
+
+        (dht_ben_map! {
             //message::CLIENT_TYPE_KEY => ben_bytes!(dht::CLIENT_IDENTIFICATION),
-            message::TRANSACTION_ID_KEY => bt_ben_bytes!(self.trans_id),
-            message::MESSAGE_TYPE_KEY => bt_ben_bytes!(message::RESPONSE_TYPE_KEY),
-            message::RESPONSE_TYPE_KEY => bt_ben_map!{
-                message::NODE_ID_KEY => bt_ben_bytes!(self.node_id.as_ref()),
-                message::NODES_KEY => bt_ben_bytes!(self.nodes.nodes())
-            }
+            message::TRANSACTION_ID_KEY => dht_ben_bytes!(self.trans_id),
+            message::MESSAGE_TYPE_KEY => dht_ben_bytes!(message::RESPONSE_TYPE_KEY),
+            response::RESPONSE_ARGS_KEY => Bencode::Dict(response_args)
         })
         .encode()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{FindNodeRequest, FindNodeResponse};
+    use crate::dht::bencode::Bencode;
+    use crate::dht::message::compact_info::CompactNodeInfo;
+    use crate::dht::message::request::RequestType;
+    use crate::dht::message::response::{ExpectedResponse, ResponseType};
+    use crate::dht::message::{MessageType, Want};
+
+    #[test]
+    fn positive_find_node_request_want_round_trip() {
+        for &want in &[None, Some(Want::V4), Some(Want::V6), Some(Want::Both)] {
+            let request = FindNodeRequest::new(b"aa", [1u8; 20].into(), [2u8; 20].into(), want);
+            let encoded = request.encode();
+
+            let bencode = Bencode::decode(&encoded).unwrap();
+            match MessageType::new(&bencode, |_| ExpectedResponse::None).unwrap() {
+                MessageType::Request(RequestType::FindNode(decoded)) => {
+                    assert_eq!(decoded, request)
+                }
+                other => panic!("Unexpected Message {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn positive_find_node_request_unknown_want() {
+        let bencode = Bencode::decode(
+            b"d1:ad2:id20:aaaaaaaaaaaaaaaaaaaa6:target20:bbbbbbbbbbbbbbbbbbbb4:wantl2:n52:n6ee\
+              1:q9:find_node1:t2:aa1:y1:qe",
+        )
+        .unwrap();
+
+        match MessageType::new(&bencode, |_| ExpectedResponse::None).unwrap() {
+            MessageType::Request(RequestType::FindNode(decoded)) => {
+                assert_eq!(decoded.want(), Some(Want::V6))
+            }
+            other => panic!("Unexpected Message {:?}", other),
+        }
+    }
+
+    #[test]
+    fn positive_find_node_response_nodes6_round_trip() {
+        let nodes = [0u8; 26];
+        let nodes6 = [0u8; 38];
+        let response = FindNodeResponse::new(
+            b"aa",
+            [1u8; 20].into(),
+            Some(CompactNodeInfo::new(&nodes).unwrap()),
+            Some(CompactNodeInfo::new_v6(&nodes6).unwrap()),
+        );
+        let encoded = response.encode();
+
+        let bencode = Bencode::decode(&encoded).unwrap();
+        match MessageType::new(&bencode, |_| ExpectedResponse::FindNode).unwrap() {
+            MessageType::Response(ResponseType::FindNode(decoded)) => {
+                assert_eq!(decoded, response);
+                assert_eq!(decoded.nodes6().unwrap().into_iter().count(), 1);
+            }
+            other => panic!("Unexpected Message {:?}", other),
+        }
+    }
+
+    #[test]
+    fn negative_find_node_response_no_nodes() {
+        let response = FindNodeResponse::new(b"aa", [1u8; 20].into(), None, None);
+        let encoded = response.encode();
+
+        let bencode = Bencode::decode(&encoded).unwrap();
+        assert!(MessageType::new(&bencode, |_| ExpectedResponse::FindNode).is_err());
+    }
+}
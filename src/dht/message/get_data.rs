@@ -1,18 +1,323 @@
-// use crate::bencode::{Bencode, BencodeConvert, Dictionary};
+use std::collections::BTreeMap;
 
-use error::{DhtResult, DhtError, DhtErrorKind};
+use crate::util::bt::NodeId;
+use crate::util::sha::ShaHash;
+
+use crate::dht::bencode::{Bencode, BencodeConvert, Dictionary};
+use crate::dht::error::{DhtError, DhtErrorKind, DhtResult};
+use crate::dht::item;
+use crate::dht::message;
+use crate::dht::message::compact_info::CompactNodeInfo;
+use crate::dht::message::request::{self, RequestValidate};
+use crate::dht::message::response::{self, ResponseValidate};
 
 #[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
 pub struct GetDataRequest<'a> {
-    trans_id: &'a [u8]
+    trans_id: &'a [u8],
+    node_id: NodeId,
+    target: ShaHash,
+    // Only return the value of a mutable item if it is newer than this
+    seq: Option<i64>,
 }
 
 impl<'a> GetDataRequest<'a> {
-    pub fn new(rqst_root: &Dictionary<'a, Bencode<'a>>, trans_id: &'a [u8]) -> DhtResult<GetDataRequest<'a>> {
-        unimplemented!();
+    pub fn new(
+        trans_id: &'a [u8],
+        node_id: NodeId,
+        target: ShaHash,
+        seq: Option<i64>,
+    ) -> GetDataRequest<'a> {
+        GetDataRequest {
+            trans_id: trans_id,
+            node_id: node_id,
+            target: target,
+            seq: seq,
+        }
     }
-    
+
+    pub fn from_parts(
+        rqst_root: &dyn Dictionary<'a, Bencode<'a>>,
+        trans_id: &'a [u8],
+    ) -> DhtResult<GetDataRequest<'a>> {
+        let validate = RequestValidate::new(trans_id);
+
+        let node_id_bytes = validate.lookup_and_convert_bytes(rqst_root, message::NODE_ID_KEY)?;
+        let node_id = validate.validate_node_id(node_id_bytes)?;
+
+        let target_bytes = validate.lookup_and_convert_bytes(rqst_root, message::TARGET_ID_KEY)?;
+        let target = validate.validate_info_hash(target_bytes)?;
+
+        let seq = validate
+            .lookup_and_convert_int(rqst_root, message::SEQ_KEY)
+            .ok();
+
+        Ok(GetDataRequest::new(trans_id, node_id, target.into(), seq))
+    }
+
     pub fn transaction_id(&self) -> &'a [u8] {
         self.trans_id
     }
-}
\ No newline at end of file
+
+    pub fn node_id(&self) -> NodeId {
+        self.node_id
+    }
+
+    pub fn target(&self) -> ShaHash {
+        self.target
+    }
+
+    pub fn seq(&self) -> Option<i64> {
+        self.seq
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut request_args = BTreeMap::new();
+
+        request_args.insert(
+            message::NODE_ID_KEY.as_bytes(),
+            dht_ben_bytes!(self.node_id.as_ref()),
+        );
+        request_args.insert(
+            message::TARGET_ID_KEY.as_bytes(),
+            dht_ben_bytes!(self.target.as_ref()),
+        );
+        if let Some(seq) = self.seq {
+            request_args.insert(message::SEQ_KEY.as_bytes(), dht_ben_int!(seq));
+        }
+
+        (dht_ben_map! {
+            //message::CLIENT_TYPE_KEY => ben_bytes!(dht::CLIENT_IDENTIFICATION),
+            message::TRANSACTION_ID_KEY => dht_ben_bytes!(self.trans_id),
+            message::MESSAGE_TYPE_KEY => dht_ben_bytes!(message::REQUEST_TYPE_KEY),
+            message::REQUEST_TYPE_KEY => dht_ben_bytes!(request::GET_DATA_TYPE_KEY),
+            request::REQUEST_ARGS_KEY => Bencode::Dict(request_args)
+        })
+        .encode()
+    }
+}
+
+/// Item information contained in a get response.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub enum ItemInfo<'a> {
+    /// Value of an immutable item.
+    Immutable(&'a Bencode<'a>),
+    /// Public key, sequence number, signature and value of a mutable item.
+    Mutable(&'a [u8], i64, &'a [u8], &'a Bencode<'a>),
+    /// Sequence number of a mutable item which was not newer than the requested one.
+    Seq(i64),
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub struct GetDataResponse<'a> {
+    trans_id: &'a [u8],
+    node_id: NodeId,
+    token: Option<&'a [u8]>,
+    nodes: Option<CompactNodeInfo<'a>>,
+    // IPv6 nodes, see BEP 32
+    nodes6: Option<CompactNodeInfo<'a>>,
+    item: Option<ItemInfo<'a>>,
+}
+
+impl<'a> GetDataResponse<'a> {
+    pub fn new(
+        trans_id: &'a [u8],
+        node_id: NodeId,
+        token: Option<&'a [u8]>,
+        nodes: Option<CompactNodeInfo<'a>>,
+        nodes6: Option<CompactNodeInfo<'a>>,
+        item: Option<ItemInfo<'a>>,
+    ) -> GetDataResponse<'a> {
+        GetDataResponse {
+            trans_id: trans_id,
+            node_id: node_id,
+            token: token,
+            nodes: nodes,
+            nodes6: nodes6,
+            item: item,
+        }
+    }
+
+    pub fn from_parts(
+        rsp_root: &'a dyn Dictionary<'a, Bencode<'a>>,
+        trans_id: &'a [u8],
+    ) -> DhtResult<GetDataResponse<'a>> {
+        let validate = ResponseValidate::new(trans_id);
+
+        let node_id_bytes = validate.lookup_and_convert_bytes(rsp_root, message::NODE_ID_KEY)?;
+        let node_id = validate.validate_node_id(node_id_bytes)?;
+
+        let token = validate
+            .lookup_and_convert_bytes(rsp_root, message::TOKEN_KEY)
+            .ok();
+
+        let nodes = match validate.lookup_and_convert_bytes(rsp_root, message::NODES_KEY) {
+            Ok(nodes) => Some(validate.validate_nodes(nodes)?),
+            Err(_) => None,
+        };
+        let nodes6 = match validate.lookup_and_convert_bytes(rsp_root, message::NODES6_KEY) {
+            Ok(nodes6) => Some(validate.validate_nodes6(nodes6)?),
+            Err(_) => None,
+        };
+
+        let seq = validate
+            .lookup_and_convert_int(rsp_root, message::SEQ_KEY)
+            .ok();
+        let item = match (rsp_root.lookup(message::VALUE_KEY.as_bytes()), seq) {
+            (Some(value), Some(seq)) => {
+                let public_key =
+                    validate.lookup_and_convert_bytes(rsp_root, message::PUBLIC_KEY_KEY)?;
+                let signature =
+                    validate.lookup_and_convert_bytes(rsp_root, message::SIGNATURE_KEY)?;
+
+                if public_key.len() != item::PUBLIC_KEY_LEN
+                    || signature.len() != item::SIGNATURE_LEN
+                {
+                    return Err(DhtError::from_kind(DhtErrorKind::InvalidResponse {
+                        details: format!(
+                            "TID {:?} Found Mutable Item With Invalid Key Or Signature Length",
+                            trans_id
+                        ),
+                    }));
+                }
+
+                Some(ItemInfo::Mutable(public_key, seq, signature, value))
+            }
+            (Some(value), None) => Some(ItemInfo::Immutable(value)),
+            (None, Some(seq)) => Some(ItemInfo::Seq(seq)),
+            (None, None) => None,
+        };
+
+        Ok(GetDataResponse::new(
+            trans_id, node_id, token, nodes, nodes6, item,
+        ))
+    }
+
+    pub fn transaction_id(&self) -> &'a [u8] {
+        self.trans_id
+    }
+
+    pub fn node_id(&self) -> NodeId {
+        self.node_id
+    }
+
+    pub fn token(&self) -> Option<&'a [u8]> {
+        self.token
+    }
+
+    pub fn nodes(&self) -> Option<CompactNodeInfo<'a>> {
+        self.nodes
+    }
+
+    pub fn nodes6(&self) -> Option<CompactNodeInfo<'a>> {
+        self.nodes6
+    }
+
+    pub fn item(&self) -> Option<ItemInfo<'a>> {
+        self.item
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut response_args = BTreeMap::new();
+
+        response_args.insert(
+            message::NODE_ID_KEY.as_bytes(),
+            dht_ben_bytes!(self.node_id.as_ref()),
+        );
+        if let Some(token) = self.token {
+            response_args.insert(message::TOKEN_KEY.as_bytes(), dht_ben_bytes!(token));
+        }
+        if let Some(nodes) = self.nodes {
+            response_args.insert(message::NODES_KEY.as_bytes(), dht_ben_bytes!(nodes.nodes()));
+        }
+        if let Some(nodes6) = self.nodes6 {
+            response_args.insert(
+                message::NODES6_KEY.as_bytes(),
+                dht_ben_bytes!(nodes6.nodes()),
+            );
+        }
+
+        match self.item {
+            Some(ItemInfo::Immutable(value)) => {
+                response_args.insert(message::VALUE_KEY.as_bytes(), value.clone());
+            }
+            Some(ItemInfo::Mutable(public_key, seq, signature, value)) => {
+                response_args.insert(
+                    message::PUBLIC_KEY_KEY.as_bytes(),
+                    dht_ben_bytes!(public_key),
+                );
+                response_args.insert(message::SEQ_KEY.as_bytes(), dht_ben_int!(seq));
+                response_args.insert(message::SIGNATURE_KEY.as_bytes(), dht_ben_bytes!(signature));
+                response_args.insert(message::VALUE_KEY.as_bytes(), value.clone());
+            }
+            Some(ItemInfo::Seq(seq)) => {
+                response_args.insert(message::SEQ_KEY.as_bytes(), dht_ben_int!(seq));
+            }
+            None => (),
+        };
+
+        (dht_ben_map! {
+            //message::CLIENT_TYPE_KEY => ben_bytes!(dht::CLIENT_IDENTIFICATION),
+            message::TRANSACTION_ID_KEY => dht_ben_bytes!(self.trans_id),
+            message::MESSAGE_TYPE_KEY => dht_ben_bytes!(message::RESPONSE_TYPE_KEY),
+            response::RESPONSE_ARGS_KEY => Bencode::Dict(response_args)
+        })
+        .encode()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{GetDataRequest, GetDataResponse, ItemInfo};
+    use crate::dht::bencode::Bencode;
+    use crate::dht::message::request::RequestType;
+    use crate::dht::message::response::ExpectedResponse;
+    use crate::dht::message::response::ResponseType;
+    use crate::dht::message::MessageType;
+
+    #[test]
+    fn positive_get_request_round_trip() {
+        let request = GetDataRequest::new(b"aa", [1u8; 20].into(), [2u8; 20].into(), Some(4));
+        let encoded = request.encode();
+
+        let bencode = Bencode::decode(&encoded).unwrap();
+        match MessageType::new(&bencode, |_| ExpectedResponse::None).unwrap() {
+            MessageType::Request(RequestType::GetData(decoded)) => assert_eq!(decoded, request),
+            other => panic!("Unexpected Message {:?}", other),
+        }
+    }
+
+    #[test]
+    fn positive_get_response_mutable_round_trip() {
+        let value_bytes = b"12:Hello World!";
+        let value = Bencode::decode(value_bytes).unwrap();
+        let item = ItemInfo::Mutable(&[3u8; 32], 7, &[4u8; 64], &value);
+        let response = GetDataResponse::new(
+            b"aa",
+            [1u8; 20].into(),
+            Some(&[5u8; 20]),
+            None,
+            None,
+            Some(item),
+        );
+        let encoded = response.encode();
+
+        let bencode = Bencode::decode(&encoded).unwrap();
+        match MessageType::new(&bencode, |_| ExpectedResponse::GetData).unwrap() {
+            MessageType::Response(ResponseType::GetData(decoded)) => {
+                assert_eq!(decoded, response)
+            }
+            other => panic!("Unexpected Message {:?}", other),
+        }
+    }
+
+    #[test]
+    fn negative_get_response_short_public_key() {
+        let value = Bencode::decode(b"12:Hello World!").unwrap();
+        let item = ItemInfo::Mutable(&[3u8; 31], 7, &[4u8; 64], &value);
+        let encoded =
+            GetDataResponse::new(b"aa", [1u8; 20].into(), None, None, None, Some(item)).encode();
+
+        let bencode = Bencode::decode(&encoded).unwrap();
+        assert!(MessageType::new(&bencode, |_| ExpectedResponse::GetData).is_err());
+    }
+}
@@ -4,25 +4,42 @@ use std::collections::BTreeMap;
 use crate::util::bt::{InfoHash, NodeId};
 
 use crate::dht::bencode::{Bencode, BencodeConvert, Dictionary};
+use crate::dht::bloom::BloomFilter;
 use crate::dht::error::{DhtError, DhtErrorKind, DhtResult};
-use crate::dht::message;
 use crate::dht::message::compact_info::{CompactNodeInfo, CompactValueInfo};
 use crate::dht::message::request::{self, RequestValidate};
 use crate::dht::message::response::{self, ResponseValidate};
+use crate::dht::message::{self, Want};
+
+const SCRAPE_KEY: &'static str = "scrape";
+const SEEDS_FILTER_KEY: &'static str = "BFsd";
+const DOWNLOADERS_FILTER_KEY: &'static str = "BFpe";
 
 #[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
 pub struct GetPeersRequest<'a> {
     trans_id: &'a [u8],
     node_id: NodeId,
     info_hash: InfoHash,
+    // Whether bloom filters of the stored peers are requested, see BEP 33
+    scrape: bool,
+    // Address families of the nodes wanted back, see BEP 32
+    want: Option<Want>,
 }
 
 impl<'a> GetPeersRequest<'a> {
-    pub fn new(trans_id: &'a [u8], node_id: NodeId, info_hash: InfoHash) -> GetPeersRequest<'a> {
+    pub fn new(
+        trans_id: &'a [u8],
+        node_id: NodeId,
+        info_hash: InfoHash,
+        scrape: bool,
+        want: Option<Want>,
+    ) -> GetPeersRequest<'a> {
         GetPeersRequest {
             trans_id: trans_id,
             node_id: node_id,
             info_hash: info_hash,
+            scrape: scrape,
+            want: want,
         }
     }
 
@@ -39,7 +56,16 @@ impl<'a> GetPeersRequest<'a> {
             validate.lookup_and_convert_bytes(rqst_root, message::INFO_HASH_KEY)?;
         let info_hash = validate.validate_info_hash(info_hash_bytes)?;
 
-        Ok(GetPeersRequest::new(trans_id, node_id, info_hash))
+        let scrape = match rqst_root.lookup(SCRAPE_KEY.as_bytes()).map(|n| n.int()) {
+            Some(Some(n)) => n != 0,
+            _ => false,
+        };
+
+        let want = Want::from_parts(rqst_root);
+
+        Ok(GetPeersRequest::new(
+            trans_id, node_id, info_hash, scrape, want,
+        ))
     }
 
     pub fn transaction_id(&self) -> &'a [u8] {
@@ -54,28 +80,44 @@ impl<'a> GetPeersRequest<'a> {
         self.info_hash
     }
 
+    pub fn scrape(&self) -> bool {
+        self.scrape
+    }
+
+    pub fn want(&self) -> Option<Want> {
+        self.want
+    }
+
     pub fn encode(&self) -> Vec<u8> {
-        (bt_ben_map! {
+        let mut request_args = BTreeMap::new();
+        let info_hash = self.info_hash.truncated();
+
+        request_args.insert(
+            message::NODE_ID_KEY.as_bytes(),
+            dht_ben_bytes!(self.node_id.as_ref()),
+        );
+        request_args.insert(
+            message::INFO_HASH_KEY.as_bytes(),
+            dht_ben_bytes!(&info_hash),
+        );
+        if self.scrape {
+            request_args.insert(SCRAPE_KEY.as_bytes(), dht_ben_int!(1));
+        }
+        if let Some(want) = self.want {
+            request_args.insert(message::WANT_KEY.as_bytes(), want.to_bencode());
+        }
+
+        (dht_ben_map! {
             //message::CLIENT_TYPE_KEY => ben_bytes!(dht::CLIENT_IDENTIFICATION),
-            message::TRANSACTION_ID_KEY => bt_ben_bytes!(self.trans_id),
-            message::MESSAGE_TYPE_KEY => bt_ben_bytes!(message::REQUEST_TYPE_KEY),
-            message::REQUEST_TYPE_KEY => bt_ben_bytes!(request::GET_PEERS_TYPE_KEY),
-            request::REQUEST_ARGS_KEY => bt_ben_map!{
-                message::NODE_ID_KEY => bt_ben_bytes!(self.node_id.as_ref()),
-                message::INFO_HASH_KEY => bt_ben_bytes!(self.info_hash.as_ref())
-            }
+            message::TRANSACTION_ID_KEY => dht_ben_bytes!(self.trans_id),
+            message::MESSAGE_TYPE_KEY => dht_ben_bytes!(message::REQUEST_TYPE_KEY),
+            message::REQUEST_TYPE_KEY => dht_ben_bytes!(request::GET_PEERS_TYPE_KEY),
+            request::REQUEST_ARGS_KEY => Bencode::Dict(request_args)
         })
         .encode()
     }
 }
 
-#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
-pub enum CompactInfoType<'a> {
-    Nodes(CompactNodeInfo<'a>),
-    Values(CompactValueInfo<'a>),
-    Both(CompactNodeInfo<'a>, CompactValueInfo<'a>),
-}
-
 #[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
 pub struct GetPeersResponse<'a> {
     trans_id: &'a [u8],
@@ -83,7 +125,12 @@ pub struct GetPeersResponse<'a> {
     // It looks like bootstrap nodes don't provide a nodes key, probably
     // because they are only used for bootstraping and not to announce to.
     token: Option<&'a [u8]>,
-    info_type: CompactInfoType<'a>,
+    nodes: Option<CompactNodeInfo<'a>>,
+    // IPv6 nodes, see BEP 32
+    nodes6: Option<CompactNodeInfo<'a>>,
+    values: Option<CompactValueInfo<'a>>,
+    // Bloom filters of the seeds and downloaders stored, see BEP 33
+    scrape: Option<(BloomFilter, BloomFilter)>,
 }
 
 impl<'a> GetPeersResponse<'a> {
@@ -91,13 +138,19 @@ impl<'a> GetPeersResponse<'a> {
         trans_id: &'a [u8],
         node_id: NodeId,
         token: Option<&'a [u8]>,
-        info_type: CompactInfoType<'a>,
+        nodes: Option<CompactNodeInfo<'a>>,
+        nodes6: Option<CompactNodeInfo<'a>>,
+        values: Option<CompactValueInfo<'a>>,
+        scrape: Option<(BloomFilter, BloomFilter)>,
     ) -> GetPeersResponse<'a> {
         GetPeersResponse {
             trans_id: trans_id,
             node_id: node_id,
             token: token,
-            info_type: info_type,
+            nodes: nodes,
+            nodes6: nodes6,
+            values: values,
+            scrape: scrape,
         }
     }
 
@@ -114,33 +167,44 @@ impl<'a> GetPeersResponse<'a> {
             .lookup_and_convert_bytes(rsp_root, message::TOKEN_KEY)
             .ok();
 
-        let maybe_nodes = validate.lookup_and_convert_bytes(rsp_root, message::NODES_KEY);
-        let maybe_values = validate.lookup_and_convert_list(rsp_root, message::VALUES_KEY);
-
+        let nodes = match validate.lookup_and_convert_bytes(rsp_root, message::NODES_KEY) {
+            Ok(nodes) => Some(validate.validate_nodes(nodes)?),
+            Err(_) => None,
+        };
+        let nodes6 = match validate.lookup_and_convert_bytes(rsp_root, message::NODES6_KEY) {
+            Ok(nodes6) => Some(validate.validate_nodes6(nodes6)?),
+            Err(_) => None,
+        };
         // TODO: Check if nodes in the wild actually send a 2d array of bytes as values or if they
         // stick with the more compact single byte array like that used for nodes.
-        let info_type = match (maybe_nodes, maybe_values) {
-            (Ok(nodes), Ok(values)) => {
-                let nodes_info = validate.validate_nodes(nodes)?;
-                let values_info = validate.validate_values(values)?;
-                CompactInfoType::Both(nodes_info, values_info)
-            }
-            (Ok(nodes), Err(_)) => {
-                let nodes_info = validate.validate_nodes(nodes)?;
-                CompactInfoType::Nodes(nodes_info)
-            }
-            (Err(_), Ok(values)) => {
-                let values_info = validate.validate_values(values)?;
-                CompactInfoType::Values(values_info)
-            }
-            (Err(_), Err(_)) => {
-                return Err(DhtError::from_kind(DhtErrorKind::InvalidResponse {
-                    details: "Failed To Find nodes Or values In Node Response".to_owned(),
-                }))
-            }
+        let values = match validate.lookup_and_convert_list(rsp_root, message::VALUES_KEY) {
+            Ok(values) => Some(validate.validate_values(values)?),
+            Err(_) => None,
         };
 
-        Ok(GetPeersResponse::new(trans_id, node_id, token, info_type))
+        if nodes.is_none() && nodes6.is_none() && values.is_none() {
+            return Err(DhtError::from_kind(DhtErrorKind::InvalidResponse {
+                details: "Failed To Find nodes, nodes6 Or values In Node Response".to_owned(),
+            }));
+        }
+
+        // Filters that are missing or malformed are ignored, the nodes and values are still useful
+        let seeds = validate
+            .lookup_and_convert_bytes(rsp_root, SEEDS_FILTER_KEY)
+            .ok()
+            .and_then(BloomFilter::from_bytes);
+        let downloaders = validate
+            .lookup_and_convert_bytes(rsp_root, DOWNLOADERS_FILTER_KEY)
+            .ok()
+            .and_then(BloomFilter::from_bytes);
+        let scrape = match (seeds, downloaders) {
+            (Some(seeds), Some(downloaders)) => Some((seeds, downloaders)),
+            _ => None,
+        };
+
+        Ok(GetPeersResponse::new(
+            trans_id, node_id, token, nodes, nodes6, values, scrape,
+        ))
     }
 
     pub fn transaction_id(&self) -> &'a [u8] {
@@ -155,8 +219,21 @@ impl<'a> GetPeersResponse<'a> {
         self.token
     }
 
-    pub fn info_type(&self) -> CompactInfoType<'a> {
-        self.info_type
+    pub fn nodes(&self) -> Option<CompactNodeInfo<'a>> {
+        self.nodes
+    }
+
+    pub fn nodes6(&self) -> Option<CompactNodeInfo<'a>> {
+        self.nodes6
+    }
+
+    pub fn values(&self) -> Option<CompactValueInfo<'a>> {
+        self.values
+    }
+
+    /// Bloom filters of the seeds and downloaders, in that order, if the node sent them.
+    pub fn scrape(&self) -> Option<(BloomFilter, BloomFilter)> {
+        self.scrape
     }
 
     pub fn encode(&self) -> Vec<u8> {
@@ -173,24 +250,32 @@ impl<'a> GetPeersResponse<'a> {
             None => (),
         };
 
-        match self.info_type {
-            CompactInfoType::Nodes(nodes) => {
-                response_args.insert(message::NODES_KEY.as_bytes(), dht_ben_bytes!(nodes.nodes()));
-            }
-            CompactInfoType::Values(values) => {
-                response_args.insert(
-                    message::VALUES_KEY.as_bytes(),
-                    Bencode::List(values.values().to_vec()),
-                );
-            }
-            CompactInfoType::Both(nodes, values) => {
-                response_args.insert(message::NODES_KEY.as_bytes(), dht_ben_bytes!(nodes.nodes()));
-                response_args.insert(
-                    message::VALUES_KEY.as_bytes(),
-                    Bencode::List(values.values().to_vec()),
-                );
-            }
-        };
+        if let Some(nodes) = self.nodes {
+            response_args.insert(message::NODES_KEY.as_bytes(), dht_ben_bytes!(nodes.nodes()));
+        }
+        if let Some(nodes6) = self.nodes6 {
+            response_args.insert(
+                message::NODES6_KEY.as_bytes(),
+                dht_ben_bytes!(nodes6.nodes()),
+            );
+        }
+        if let Some(values) = self.values {
+            response_args.insert(
+                message::VALUES_KEY.as_bytes(),
+                Bencode::List(values.values().to_vec()),
+            );
+        }
+
+        if let Some((ref seeds, ref downloaders)) = self.scrape {
+            response_args.insert(
+                SEEDS_FILTER_KEY.as_bytes(),
+                dht_ben_bytes!(seeds.as_bytes()),
+            );
+            response_args.insert(
+                DOWNLOADERS_FILTER_KEY.as_bytes(),
+                dht_ben_bytes!(downloaders.as_bytes()),
+            );
+        }
 
         (dht_ben_map! {
             //message::CLIENT_TYPE_KEY => ben_bytes!(dht::CLIENT_IDENTIFICATION),
@@ -202,3 +287,102 @@ impl<'a> GetPeersResponse<'a> {
         .encode()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{GetPeersRequest, GetPeersResponse};
+    use crate::dht::bencode::Bencode;
+    use crate::dht::bloom::BloomFilter;
+    use crate::dht::message::compact_info::{CompactNodeInfo, CompactValueInfo};
+    use crate::dht::message::request::RequestType;
+    use crate::dht::message::response::{ExpectedResponse, ResponseType};
+    use crate::dht::message::{MessageType, Want};
+
+    #[test]
+    fn positive_get_peers_request_scrape_round_trip() {
+        let request = GetPeersRequest::new(b"aa", [1u8; 20].into(), [2u8; 20].into(), true, None);
+        let encoded = request.encode();
+
+        let bencode = Bencode::decode(&encoded).unwrap();
+        match MessageType::new(&bencode, |_| ExpectedResponse::None).unwrap() {
+            MessageType::Request(RequestType::GetPeers(decoded)) => assert_eq!(decoded, request),
+            other => panic!("Unexpected Message {:?}", other),
+        }
+    }
+
+    #[test]
+    fn positive_get_peers_response_scrape_round_trip() {
+        let mut seeds = BloomFilter::new();
+        seeds.insert("10.0.0.1".parse().unwrap());
+        let mut downloaders = BloomFilter::new();
+        downloaders.insert("10.0.0.2".parse().unwrap());
+
+        let nodes = [0u8; 26];
+        let response = GetPeersResponse::new(
+            b"aa",
+            [1u8; 20].into(),
+            Some(b"token"),
+            Some(CompactNodeInfo::new(&nodes).unwrap()),
+            None,
+            None,
+            Some((seeds, downloaders)),
+        );
+        let encoded = response.encode();
+
+        let bencode = Bencode::decode(&encoded).unwrap();
+        match MessageType::new(&bencode, |_| ExpectedResponse::GetPeers).unwrap() {
+            MessageType::Response(ResponseType::GetPeers(decoded)) => {
+                assert_eq!(decoded, response)
+            }
+            other => panic!("Unexpected Message {:?}", other),
+        }
+    }
+
+    #[test]
+    fn positive_get_peers_request_want_round_trip() {
+        let request = GetPeersRequest::new(
+            b"aa",
+            [1u8; 20].into(),
+            [2u8; 20].into(),
+            false,
+            Some(Want::Both),
+        );
+        let encoded = request.encode();
+
+        let bencode = Bencode::decode(&encoded).unwrap();
+        match MessageType::new(&bencode, |_| ExpectedResponse::None).unwrap() {
+            MessageType::Request(RequestType::GetPeers(decoded)) => assert_eq!(decoded, request),
+            other => panic!("Unexpected Message {:?}", other),
+        }
+    }
+
+    #[test]
+    fn positive_get_peers_response_v6_round_trip() {
+        let nodes6 = [0u8; 38];
+        let value_bytes = [1u8; 18];
+        let values = vec![Bencode::Bytes(&value_bytes[..])];
+        let response = GetPeersResponse::new(
+            b"aa",
+            [1u8; 20].into(),
+            Some(b"token"),
+            None,
+            Some(CompactNodeInfo::new_v6(&nodes6).unwrap()),
+            Some(CompactValueInfo::new(&values).unwrap()),
+            None,
+        );
+        let encoded = response.encode();
+
+        let bencode = Bencode::decode(&encoded).unwrap();
+        match MessageType::new(&bencode, |_| ExpectedResponse::GetPeers).unwrap() {
+            MessageType::Response(ResponseType::GetPeers(decoded)) => {
+                assert_eq!(decoded, response);
+                assert!(decoded
+                    .values()
+                    .unwrap()
+                    .into_iter()
+                    .all(|addr| addr.is_ipv6()));
+            }
+            other => panic!("Unexpected Message {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,469 @@
+use std::error::Error;
+use std::fmt;
+
+use crate::dht::bencode::{Bencode, BencodeConvert};
+use crate::dht::error::{DhtError, DhtErrorKind};
+use crate::dht::message::error::{ErrorCode, ErrorMessage};
+use crate::dht::message::request::RequestType;
+use crate::dht::message::response::{ExpectedResponse, ResponseType};
+use crate::dht::message::{
+    MessageType, MessageValidate, ERROR_TYPE_KEY, MESSAGE_TYPE_KEY, REQUEST_TYPE_KEY,
+    RESPONSE_TYPE_KEY, ROOT_ID_KEY, TRANSACTION_ID_KEY,
+};
+
+/// Longest transaction id we accept, longer ids are not worth echoing back to the remote node.
+pub const MAX_TRANSACTION_ID_LEN: usize = 16;
+
+/// Reason a single KRPC packet could not be decoded.
+///
+/// Packets with a usable transaction id that look like queries can be answered with an
+/// `ErrorMessage`, see `KrpcError::error_message`, anything else should be dropped.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum KrpcError {
+    /// Packet is not valid bencode.
+    InvalidBencode,
+    /// Packet is not a bencoded dictionary.
+    InvalidRoot,
+    /// Transaction id is missing or is not a byte string.
+    InvalidTransactionId,
+    /// Transaction id is longer than `MAX_TRANSACTION_ID_LEN`.
+    TransactionIdTooLong { len: usize },
+    /// Message type is missing, is not a string or is unknown.
+    InvalidMessageType { trans_id: Vec<u8> },
+    /// Query method is missing or is not a string.
+    InvalidMethod { trans_id: Vec<u8> },
+    /// Query method is not one we know about.
+    MethodUnknown { trans_id: Vec<u8>, method: String },
+    /// Query arguments are missing or invalid.
+    InvalidArguments { trans_id: Vec<u8>, details: String },
+    /// Query could not be processed for some other reason.
+    InvalidQuery { trans_id: Vec<u8>, details: String },
+    /// Response or error message is invalid.
+    InvalidResponse { details: String },
+    /// Response does not answer any of our transactions, most likely one that already timed out.
+    UnsolicitedResponse,
+}
+
+impl KrpcError {
+    /// Transaction id we can answer the packet under, if the packet can be answered.
+    pub fn transaction_id(&self) -> Option<&[u8]> {
+        match self {
+            &KrpcError::InvalidMessageType { ref trans_id }
+            | &KrpcError::InvalidMethod { ref trans_id }
+            | &KrpcError::MethodUnknown { ref trans_id, .. }
+            | &KrpcError::InvalidArguments { ref trans_id, .. }
+            | &KrpcError::InvalidQuery { ref trans_id, .. } => Some(trans_id),
+            _ => None,
+        }
+    }
+
+    /// Error code we answer the packet with, if the packet can be answered.
+    pub fn error_code(&self) -> Option<ErrorCode> {
+        match self {
+            &KrpcError::InvalidMessageType { .. }
+            | &KrpcError::InvalidMethod { .. }
+            | &KrpcError::InvalidArguments { .. } => Some(ErrorCode::ProtocolError),
+            &KrpcError::MethodUnknown { .. } => Some(ErrorCode::MethodUnknown),
+            &KrpcError::InvalidQuery { .. } => Some(ErrorCode::GenericError),
+            _ => None,
+        }
+    }
+
+    /// Error message to answer the packet with, if the packet can be answered.
+    pub fn error_message(&self) -> Option<ErrorMessage<'static>> {
+        let trans_id = self.transaction_id()?;
+        let code = self.error_code()?;
+
+        Some(ErrorMessage::new(trans_id.to_vec(), code, self.to_string()))
+    }
+
+    /// Whether the remote node is at fault for sending us the packet.
+    ///
+    /// Responses are not, remote nodes may answer transactions after we gave up on them.
+    pub fn is_malformed(&self) -> bool {
+        !matches!(
+            self,
+            &KrpcError::InvalidResponse { .. } | &KrpcError::UnsolicitedResponse
+        )
+    }
+}
+
+impl fmt::Display for KrpcError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            &KrpcError::InvalidBencode => f.write_str("Message Is Not Valid Bencode"),
+            &KrpcError::InvalidRoot => f.write_str("Message Is Not A Dictionary"),
+            &KrpcError::InvalidTransactionId => f.write_str("Message Has No Valid Transaction ID"),
+            &KrpcError::TransactionIdTooLong { len } => write!(
+                f,
+                "Transaction ID With Length {} Is Longer Than {} Bytes",
+                len, MAX_TRANSACTION_ID_LEN
+            ),
+            &KrpcError::InvalidMessageType { .. } => f.write_str("Message Has No Valid Type"),
+            &KrpcError::InvalidMethod { .. } => f.write_str("Query Has No Valid Method"),
+            &KrpcError::MethodUnknown { ref method, .. } => {
+                write!(f, "Received Unknown Request Method: {}", method)
+            }
+            &KrpcError::InvalidArguments { ref details, .. } => {
+                write!(f, "Query Has Invalid Arguments: {}", details)
+            }
+            &KrpcError::InvalidQuery { ref details, .. } => {
+                write!(f, "Query Could Not Be Processed: {}", details)
+            }
+            &KrpcError::InvalidResponse { ref details } => {
+                write!(f, "Node Sent Us An Invalid Response: {}", details)
+            }
+            &KrpcError::UnsolicitedResponse => f.write_str("Node Sent Us An Unsolicited Response"),
+        }
+    }
+}
+
+impl Error for KrpcError {}
+
+// ----------------------------------------------------------------------------//
+
+/// Decode the packet as bencode.
+pub fn decode_bencode<'a>(bytes: &'a [u8]) -> Result<Bencode<'a>, KrpcError> {
+    Bencode::decode(bytes).map_err(|_| KrpcError::InvalidBencode)
+}
+
+/// Decode the bencode of a single packet as a KRPC message.
+///
+/// The transaction mapper gives back the response we expect for the transaction id of a response.
+pub fn decode_message<'a, T>(
+    message: &'a Bencode<'a>,
+    trans_mapper: T,
+) -> Result<MessageType<'a>, KrpcError>
+where
+    T: Fn(&[u8]) -> ExpectedResponse,
+{
+    let validate = MessageValidate;
+    let msg_root = validate
+        .convert_dict(message, ROOT_ID_KEY)
+        .map_err(|_| KrpcError::InvalidRoot)?;
+
+    let trans_id = validate
+        .lookup_and_convert_bytes(msg_root, TRANSACTION_ID_KEY)
+        .map_err(|_| KrpcError::InvalidTransactionId)?;
+    if trans_id.len() > MAX_TRANSACTION_ID_LEN {
+        return Err(KrpcError::TransactionIdTooLong {
+            len: trans_id.len(),
+        });
+    }
+
+    let msg_type = validate
+        .lookup_and_convert_str(msg_root, MESSAGE_TYPE_KEY)
+        .map_err(|_| KrpcError::InvalidMessageType {
+            trans_id: trans_id.to_vec(),
+        })?;
+
+    match msg_type {
+        REQUEST_TYPE_KEY => {
+            let rqst_type = validate
+                .lookup_and_convert_str(msg_root, REQUEST_TYPE_KEY)
+                .map_err(|_| KrpcError::InvalidMethod {
+                    trans_id: trans_id.to_vec(),
+                })?;
+
+            RequestType::from_parts(msg_root, trans_id, rqst_type)
+                .map(MessageType::Request)
+                .map_err(|error| query_error(trans_id, rqst_type, error))
+        }
+        RESPONSE_TYPE_KEY => {
+            let rsp_type = trans_mapper(trans_id);
+
+            ResponseType::from_parts(msg_root, trans_id, rsp_type)
+                .map(MessageType::Response)
+                .map_err(response_error)
+        }
+        ERROR_TYPE_KEY => ErrorMessage::from_parts(msg_root, trans_id)
+            .map(MessageType::Error)
+            .map_err(response_error),
+        _ => Err(KrpcError::InvalidMessageType {
+            trans_id: trans_id.to_vec(),
+        }),
+    }
+}
+
+fn query_error(trans_id: &[u8], method: &str, error: DhtError) -> KrpcError {
+    let trans_id = trans_id.to_vec();
+
+    match error.kind() {
+        &DhtErrorKind::InvalidRequest { ref msg }
+            if msg.error_code() == ErrorCode::MethodUnknown =>
+        {
+            KrpcError::MethodUnknown {
+                trans_id: trans_id,
+                method: method.to_owned(),
+            }
+        }
+        &DhtErrorKind::InvalidRequest { ref msg } => KrpcError::InvalidArguments {
+            trans_id: trans_id,
+            details: msg.error_message().to_owned(),
+        },
+        &DhtErrorKind::Bencode(ref error) => KrpcError::InvalidArguments {
+            trans_id: trans_id,
+            details: error.to_string(),
+        },
+        _ => KrpcError::InvalidQuery {
+            trans_id: trans_id,
+            details: error.to_string(),
+        },
+    }
+}
+
+fn response_error(error: DhtError) -> KrpcError {
+    match error.kind() {
+        &DhtErrorKind::UnsolicitedResponse => KrpcError::UnsolicitedResponse,
+        _ => KrpcError::InvalidResponse {
+            details: error.to_string(),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::dht::bencode::Bencode;
+    use crate::dht::message::error::ErrorCode;
+    use crate::dht::message::request::RequestType;
+    use crate::dht::message::response::ExpectedResponse;
+    use crate::dht::message::MessageType;
+
+    use super::{decode_bencode, decode_message, KrpcError, MAX_TRANSACTION_ID_LEN};
+
+    // Packets as sent by real clients, BEP 5 examples and libtorrent
+    const CORPUS: &'static [&'static [u8]] = &[
+        b"d1:ad2:id20:abcdefghij0123456789e1:q4:ping1:t2:aa1:y1:qe",
+        b"d1:rd2:id20:mnopqrstuvwxyz123456e1:t2:aa1:y1:re",
+        b"d1:ad2:id20:abcdefghij01234567896:target20:mnopqrstuvwxyz123456e1:q9:find_node1:t2:aa1:y1:qe",
+        b"d1:rd2:id20:0123456789abcdefghij5:nodes26:dddddddddddddddddddd\x7f\x00\x00\x01\x1a\xe1e1:t2:aa1:y1:re",
+        b"d1:ad2:id20:abcdefghij01234567899:info_hash20:mnopqrstuvwxyz123456e1:q9:get_peers1:t2:aa1:y1:qe",
+        b"d1:rd2:id20:abcdefghij01234567895:token8:aoeusnth6:valuesl6:axje.u6:idhtnmee1:t2:aa1:y1:re",
+        b"d1:ad2:id20:abcdefghij012345678912:implied_porti1e9:info_hash20:mnopqrstuvwxyz1234564:porti6881e5:token8:aoeusnthe1:q13:announce_peer1:t2:aa1:y1:qe",
+        b"d1:eli201e23:A Generic Error Ocurrede1:t2:aa1:y1:ee",
+        b"d1:ad2:id20:aaaaaaaaaaaaaaaaaaaa6:target20:bbbbbbbbbbbbbbbbbbbbe1:q17:sample_infohashes1:t2:xy1:v4:LT\x01\x021:y1:qe",
+        b"d2:ip6:\x7f\x00\x00\x01\x1a\xe11:rd2:id20:cccccccccccccccccccc8:intervali21600e5:nodes26:dddddddddddddddddddd\x7f\x00\x00\x01\x1a\xe13:numi3e7:samples40:eeeeeeeeeeeeeeeeeeeeffffffffffffffffffffe1:t2:xy1:v4:LT\x01\x021:y1:re",
+    ];
+
+    const NUM_MUTATIONS: usize = 20000;
+
+    // Answers carry the transaction id along with a short error string
+    const MAX_ERROR_MESSAGE_LEN: usize = 256;
+
+    fn decode<T>(bytes: &[u8], trans_mapper: T) -> Result<(), KrpcError>
+    where
+        T: Fn(&[u8]) -> ExpectedResponse,
+    {
+        let bencode = decode_bencode(bytes)?;
+
+        decode_message(&bencode, trans_mapper).map(|_| ())
+    }
+
+    fn expected_response(index: usize) -> ExpectedResponse {
+        match index % 8 {
+            0 => ExpectedResponse::Ping,
+            1 => ExpectedResponse::FindNode,
+            2 => ExpectedResponse::GetPeers,
+            3 => ExpectedResponse::AnnouncePeer,
+            4 => ExpectedResponse::GetData,
+            5 => ExpectedResponse::PutData,
+            6 => ExpectedResponse::SampleInfoHashes,
+            _ => ExpectedResponse::None,
+        }
+    }
+
+    /// Deterministic xorshift generator, keeps failures reproducible.
+    struct Mutator(u64);
+
+    impl Mutator {
+        fn next(&mut self) -> usize {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+
+            self.0 as usize
+        }
+
+        fn mutate(&mut self, packet: &[u8]) -> Vec<u8> {
+            let mut bytes = packet.to_vec();
+
+            for _ in 0..(1 + self.next() % 4) {
+                let pos = self.next() % (bytes.len() + 1);
+
+                match self.next() % 6 {
+                    0 => bytes.truncate(pos),
+                    1 if pos < bytes.len() => bytes[pos] ^= 1 << (self.next() % 8),
+                    2 if pos < bytes.len() => {
+                        bytes.remove(pos);
+                    }
+                    3 => bytes.insert(pos, b"0123456789:deil"[self.next() % 15]),
+                    4 => {
+                        let end = (pos + self.next() % 32).min(bytes.len());
+                        let copy = bytes[pos..end].to_vec();
+                        bytes.splice(pos..pos, copy);
+                    }
+                    _ => bytes.insert(pos, self.next() as u8),
+                }
+            }
+
+            bytes
+        }
+    }
+
+    #[test]
+    fn positive_decode_corpus() {
+        for packet in CORPUS {
+            let bencode = Bencode::decode(packet).unwrap();
+
+            assert!(decode_message(&bencode, |trans| {
+                if trans == b"xy" {
+                    ExpectedResponse::SampleInfoHashes
+                } else if packet.starts_with(b"d1:rd2:id20:mnop") {
+                    ExpectedResponse::Ping
+                } else if packet.starts_with(b"d1:rd2:id20:0123") {
+                    ExpectedResponse::FindNode
+                } else {
+                    ExpectedResponse::GetPeers
+                }
+            })
+            .is_ok());
+        }
+    }
+
+    #[test]
+    fn positive_mutated_corpus_never_panics() {
+        let mut mutator = Mutator(0x2545_f491_4f6c_dd1d);
+
+        for index in 0..NUM_MUTATIONS {
+            let packet = mutator.mutate(CORPUS[index % CORPUS.len()]);
+
+            // Answers stay small no matter what we were sent
+            if let Err(error) = decode(&packet, |_| expected_response(index)) {
+                if let Some(error_msg) = error.error_message() {
+                    assert!(error_msg.transaction_id().len() <= MAX_TRANSACTION_ID_LEN);
+                    assert!(error_msg.encode().len() <= MAX_ERROR_MESSAGE_LEN);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn positive_missing_arguments_protocol_error() {
+        let error = decode(
+            b"d1:ad6:target20:mnopqrstuvwxyz123456e1:q9:find_node1:t2:aa1:y1:qe",
+            |_| ExpectedResponse::None,
+        )
+        .unwrap_err();
+
+        let error_msg = error.error_message().unwrap();
+        assert_eq!(error_msg.transaction_id(), b"aa");
+        assert_eq!(error_msg.error_code(), ErrorCode::ProtocolError);
+    }
+
+    #[test]
+    fn positive_unknown_method_method_unknown() {
+        let error = decode(
+            b"d1:ad2:id20:abcdefghij0123456789e1:q4:pong1:t2:aa1:y1:qe",
+            |_| ExpectedResponse::None,
+        )
+        .unwrap_err();
+
+        assert_eq!(
+            error,
+            KrpcError::MethodUnknown {
+                trans_id: b"aa".to_vec(),
+                method: "pong".to_owned()
+            }
+        );
+        assert_eq!(
+            error.error_message().unwrap().error_code(),
+            ErrorCode::MethodUnknown
+        );
+    }
+
+    #[test]
+    fn positive_forward_compatible_method() {
+        let bencode = Bencode::decode(
+            b"d1:ad2:id20:abcdefghij01234567896:target20:mnopqrstuvwxyz123456e1:q4:pong1:t2:aa1:y1:qe",
+        )
+        .unwrap();
+
+        match decode_message(&bencode, |_| ExpectedResponse::None).unwrap() {
+            MessageType::Request(RequestType::FindNode(_)) => (),
+            message => panic!("Unexpected Message {:?}", message),
+        }
+    }
+
+    #[test]
+    fn positive_wrong_type_method_protocol_error() {
+        let error = decode(
+            b"d1:ad2:id20:abcdefghij0123456789e1:qi5e1:t2:aa1:y1:qe",
+            |_| ExpectedResponse::None,
+        )
+        .unwrap_err();
+
+        assert_eq!(
+            error,
+            KrpcError::InvalidMethod {
+                trans_id: b"aa".to_vec()
+            }
+        );
+        assert_eq!(
+            error.error_message().unwrap().error_code(),
+            ErrorCode::ProtocolError
+        );
+    }
+
+    #[test]
+    fn negative_truncated_bencode_dropped() {
+        let error = decode(b"d1:ad2:id20:abcdefghij0123456789e1:q4:ping1:t2:a", |_| {
+            ExpectedResponse::None
+        })
+        .unwrap_err();
+
+        assert_eq!(error, KrpcError::InvalidBencode);
+        assert!(error.error_message().is_none());
+    }
+
+    #[test]
+    fn negative_wrong_type_transaction_id_dropped() {
+        let error = decode(
+            b"d1:ad2:id20:abcdefghij0123456789e1:q4:ping1:ti5e1:y1:qe",
+            |_| ExpectedResponse::None,
+        )
+        .unwrap_err();
+
+        assert_eq!(error, KrpcError::InvalidTransactionId);
+        assert!(error.error_message().is_none());
+    }
+
+    #[test]
+    fn negative_huge_transaction_id_dropped() {
+        let packet = format!(
+            "d1:ad2:id20:abcdefghij0123456789e1:q4:ping1:t{}:{}1:y1:qe",
+            MAX_TRANSACTION_ID_LEN + 1,
+            "a".repeat(MAX_TRANSACTION_ID_LEN + 1)
+        );
+        let error = decode(packet.as_bytes(), |_| ExpectedResponse::None).unwrap_err();
+
+        assert_eq!(
+            error,
+            KrpcError::TransactionIdTooLong {
+                len: MAX_TRANSACTION_ID_LEN + 1
+            }
+        );
+        assert!(error.error_message().is_none());
+    }
+
+    #[test]
+    fn negative_unsolicited_response_dropped() {
+        let error = decode(b"d1:rd2:id20:mnopqrstuvwxyz123456e1:t2:zz1:y1:re", |_| {
+            ExpectedResponse::None
+        })
+        .unwrap_err();
+
+        assert_eq!(error, KrpcError::UnsolicitedResponse);
+        assert!(error.error_message().is_none());
+        assert!(!error.is_malformed());
+    }
+}
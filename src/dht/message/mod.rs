@@ -1,6 +1,10 @@
 // use crate::bencode::{Bencode, BencodeConvert, BencodeConvertError};
 
-use crate::dht::bencode::{Bencode, BencodeConvert, BencodeConvertError};
+use std::net::SocketAddr;
+
+use crate::util::convert;
+
+use crate::dht::bencode::{Bencode, BencodeConvert, BencodeConvertError, Dictionary};
 use crate::dht::error::{DhtError, DhtErrorKind, DhtResult};
 use crate::dht::message::error::ErrorMessage;
 use crate::dht::message::request::RequestType;
@@ -9,17 +13,25 @@ use crate::dht::message::response::{ExpectedResponse, ResponseType};
 pub mod compact_info;
 
 pub mod error;
+pub mod krpc;
 pub mod request;
 pub mod response;
 
 pub mod announce_peer;
 pub mod find_node;
+pub mod get_data;
 pub mod get_peers;
 pub mod ping;
+pub mod put_data;
+pub mod sample_infohashes;
 
 // Top level message keys
 const TRANSACTION_ID_KEY: &'static str = "t";
 const MESSAGE_TYPE_KEY: &'static str = "y";
+// Address the remote node saw our request come from (BEP 42)
+const REQUESTER_IP_KEY: &'static str = "ip";
+// Requester does not answer queries and should not be added to routing tables (BEP 43)
+const READ_ONLY_KEY: &'static str = "ro";
 // const CLIENT_TYPE_KEY:    &'static str = "v";
 
 // Top level message type sentinels
@@ -33,11 +45,23 @@ const ROOT_ID_KEY: &'static str = "root";
 // Keys common across message types
 const NODE_ID_KEY: &'static str = "id";
 const NODES_KEY: &'static str = "nodes";
+const NODES6_KEY: &'static str = "nodes6";
 const VALUES_KEY: &'static str = "values";
 const TARGET_ID_KEY: &'static str = "target";
 const INFO_HASH_KEY: &'static str = "info_hash";
 const TOKEN_KEY: &'static str = "token";
 
+// Address families of the nodes a requester wants back (BEP 32)
+const WANT_KEY: &'static str = "want";
+const WANT_V4: &'static str = "n4";
+const WANT_V6: &'static str = "n6";
+
+// Keys common across item message types (BEP 44)
+const VALUE_KEY: &'static str = "v";
+const PUBLIC_KEY_KEY: &'static str = "k";
+const SEQ_KEY: &'static str = "seq";
+const SIGNATURE_KEY: &'static str = "sig";
+
 // ----------------------------------------------------------------------------//
 
 #[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
@@ -92,3 +116,201 @@ impl<'a> MessageType<'a> {
         }
     }
 }
+
+// ----------------------------------------------------------------------------//
+
+/// Address families of the nodes a requester wants in the response, see BEP 32.
+///
+/// Without a want, nodes of the same address family as the request are given back.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub enum Want {
+    /// Only IPv4 nodes, under the nodes key.
+    V4,
+    /// Only IPv6 nodes, under the nodes6 key.
+    V6,
+    /// Both IPv4 and IPv6 nodes.
+    Both,
+}
+
+impl Want {
+    /// Want of the given request arguments, families we do not know about are ignored.
+    fn from_parts<'a>(rqst_root: &dyn Dictionary<'a, Bencode<'a>>) -> Option<Want> {
+        let want_list = rqst_root.lookup(WANT_KEY.as_bytes())?.list()?;
+
+        let has_family = |family: &str| {
+            want_list
+                .iter()
+                .any(|want| want.bytes() == Some(family.as_bytes()))
+        };
+
+        match (has_family(WANT_V4), has_family(WANT_V6)) {
+            (true, true) => Some(Want::Both),
+            (true, false) => Some(Want::V4),
+            (false, true) => Some(Want::V6),
+            (false, false) => None,
+        }
+    }
+
+    /// Whether IPv4 nodes are wanted.
+    pub fn v4(&self) -> bool {
+        self != &Want::V6
+    }
+
+    /// Whether IPv6 nodes are wanted.
+    pub fn v6(&self) -> bool {
+        self != &Want::V4
+    }
+
+    fn to_bencode(&self) -> Bencode<'static> {
+        match self {
+            &Want::V4 => dht_ben_list!(dht_ben_bytes!(WANT_V4)),
+            &Want::V6 => dht_ben_list!(dht_ben_bytes!(WANT_V6)),
+            &Want::Both => dht_ben_list!(dht_ben_bytes!(WANT_V4), dht_ben_bytes!(WANT_V6)),
+        }
+    }
+}
+
+/// Address a remote node reported seeing our request come from, if the message included one.
+pub fn requester_addr<'a>(message: &Bencode<'a>) -> Option<SocketAddr> {
+    let addr_bytes = message
+        .dict()?
+        .lookup(REQUESTER_IP_KEY.as_bytes())?
+        .bytes()?;
+
+    if addr_bytes.len() == 6 {
+        let mut bytes = [0u8; 6];
+        bytes.copy_from_slice(addr_bytes);
+
+        Some(SocketAddr::V4(convert::bytes_be_to_sock_v4(bytes)))
+    } else if addr_bytes.len() == 18 {
+        let mut bytes = [0u8; 18];
+        bytes.copy_from_slice(addr_bytes);
+
+        Some(SocketAddr::V6(convert::bytes_be_to_sock_v6(bytes)))
+    } else {
+        None
+    }
+}
+
+/// Add the address of the requester to an encoded response going back to that requester.
+pub fn add_requester_addr(mut response: Vec<u8>, addr: SocketAddr) -> Vec<u8> {
+    let addr_bytes = match addr {
+        SocketAddr::V4(v4_addr) => convert::sock_v4_to_bytes_be(v4_addr).to_vec(),
+        SocketAddr::V6(v6_addr) => convert::sock_v6_to_bytes_be(v6_addr).to_vec(),
+    };
+
+    // All of the other keys in the root dictionary sort after the requester ip key, so it
+    // can go right after the start of the dictionary without breaking the key ordering.
+    let mut entry = format!(
+        "{}:{}{}:",
+        REQUESTER_IP_KEY.len(),
+        REQUESTER_IP_KEY,
+        addr_bytes.len()
+    )
+    .into_bytes();
+    entry.extend_from_slice(&addr_bytes);
+
+    if response.first() == Some(&b'd') {
+        response.splice(1..1, entry);
+    }
+
+    response
+}
+
+/// Whether a remote node marked its message as coming from a read only node, see BEP 43.
+pub fn is_read_only<'a>(message: &Bencode<'a>) -> bool {
+    message
+        .dict()
+        .and_then(|root| root.lookup(READ_ONLY_KEY.as_bytes()))
+        .and_then(|read_only| read_only.int())
+        == Some(1)
+}
+
+/// Mark an encoded message going out from a read only node, see BEP 43.
+///
+/// Only queries carry the read only flag, any other message is left as is.
+pub fn add_read_only(message: Vec<u8>) -> Vec<u8> {
+    let mut root = match Bencode::decode(&message) {
+        Ok(Bencode::Dict(root)) => root,
+        _ => return message,
+    };
+    if root
+        .get(MESSAGE_TYPE_KEY.as_bytes())
+        .and_then(|kind| kind.bytes())
+        != Some(REQUEST_TYPE_KEY.as_bytes())
+    {
+        return message;
+    }
+
+    root.insert(READ_ONLY_KEY.as_bytes(), Bencode::Int(1));
+
+    Bencode::Dict(root).encode()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use crate::dht::bencode::Bencode;
+    use crate::dht::message::ping::{PingRequest, PingResponse};
+    use crate::dht::message::request::RequestType;
+    use crate::dht::message::response::{ExpectedResponse, ResponseType};
+    use crate::dht::message::MessageType;
+
+    #[test]
+    fn positive_requester_addr_round_trip() {
+        let node_id = [5u8; 20].into();
+
+        for &addr in &["124.31.75.21:6881", "[2001:db8::1]:6881"] {
+            let addr: SocketAddr = addr.parse().unwrap();
+            let encoded =
+                super::add_requester_addr(PingResponse::new(b"aa", node_id).encode(), addr);
+            let bencode = Bencode::decode(&encoded).unwrap();
+
+            assert_eq!(super::requester_addr(&bencode), Some(addr));
+
+            // Rest of the message is left intact
+            let message = MessageType::new(&bencode, |_| ExpectedResponse::Ping).unwrap();
+            match message {
+                MessageType::Response(ResponseType::Ping(ping)) => {
+                    assert_eq!(ping.node_id(), node_id)
+                }
+                _ => panic!("Unexpected Message {:?}", message),
+            }
+        }
+    }
+
+    #[test]
+    fn positive_read_only_round_trip() {
+        let encoded = super::add_read_only(PingRequest::new(b"aa", [5u8; 20].into()).encode());
+        let bencode = Bencode::decode(&encoded).unwrap();
+
+        assert!(super::is_read_only(&bencode));
+
+        // Rest of the message is left intact
+        let message = MessageType::new(&bencode, |_| ExpectedResponse::None).unwrap();
+        match message {
+            MessageType::Request(RequestType::Ping(ping)) => {
+                assert_eq!(ping.transaction_id(), b"aa")
+            }
+            _ => panic!("Unexpected Message {:?}", message),
+        }
+    }
+
+    #[test]
+    fn negative_read_only_response() {
+        let response = PingResponse::new(b"aa", [5u8; 20].into()).encode();
+        let encoded = super::add_read_only(response.clone());
+
+        assert_eq!(encoded, response);
+        assert!(!super::is_read_only(&Bencode::decode(&encoded).unwrap()));
+    }
+
+    #[test]
+    fn negative_requester_addr_missing() {
+        let encoded = PingResponse::new(b"aa", [5u8; 20].into()).encode();
+        let bencode = Bencode::decode(&encoded).unwrap();
+
+        assert_eq!(super::requester_addr(&bencode), None);
+    }
+}
@@ -1,18 +1,264 @@
-// use crate::bencode::{Bencode, BencodeConvert, Dictionary};
+use std::collections::BTreeMap;
 
-use error::{DhtResult, DhtError, DhtErrorKind};
+use crate::util::bt::NodeId;
 
+use crate::dht::bencode::{Bencode, BencodeConvert, Dictionary};
+use crate::dht::error::DhtResult;
+use crate::dht::message;
+use crate::dht::message::request::{self, RequestValidate};
+use crate::dht::message::response;
+
+const SALT_KEY: &'static str = "salt";
+const CAS_KEY: &'static str = "cas";
+
+/// Arguments of a put request which only apply to mutable items.
 #[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub struct MutablePutArgs<'a> {
+    public_key: &'a [u8],
+    salt: &'a [u8],
+    seq: i64,
+    signature: &'a [u8],
+    cas: Option<i64>,
+}
+
+impl<'a> MutablePutArgs<'a> {
+    pub fn new(
+        public_key: &'a [u8],
+        salt: &'a [u8],
+        seq: i64,
+        signature: &'a [u8],
+        cas: Option<i64>,
+    ) -> MutablePutArgs<'a> {
+        MutablePutArgs {
+            public_key: public_key,
+            salt: salt,
+            seq: seq,
+            signature: signature,
+            cas: cas,
+        }
+    }
+
+    pub fn public_key(&self) -> &'a [u8] {
+        self.public_key
+    }
+
+    /// Salt of the item, empty if the item does not use one.
+    pub fn salt(&self) -> &'a [u8] {
+        self.salt
+    }
+
+    pub fn seq(&self) -> i64 {
+        self.seq
+    }
+
+    pub fn signature(&self) -> &'a [u8] {
+        self.signature
+    }
+
+    /// Sequence number the currently stored item is expected to have.
+    pub fn cas(&self) -> Option<i64> {
+        self.cas
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
 pub struct PutDataRequest<'a> {
-    trans_id: &'a [u8]
+    trans_id: &'a [u8],
+    node_id: NodeId,
+    token: &'a [u8],
+    value: &'a Bencode<'a>,
+    mutable: Option<MutablePutArgs<'a>>,
 }
 
 impl<'a> PutDataRequest<'a> {
-    pub fn new(rqst_root: &Dictionary<'a, Bencode<'a>>, trans_id: &'a [u8]) -> DhtResult<PutDataRequest<'a>> {
-        unimplemented!();
+    pub fn new(
+        trans_id: &'a [u8],
+        node_id: NodeId,
+        token: &'a [u8],
+        value: &'a Bencode<'a>,
+        mutable: Option<MutablePutArgs<'a>>,
+    ) -> PutDataRequest<'a> {
+        PutDataRequest {
+            trans_id: trans_id,
+            node_id: node_id,
+            token: token,
+            value: value,
+            mutable: mutable,
+        }
     }
-    
+
+    pub fn from_parts(
+        rqst_root: &'a dyn Dictionary<'a, Bencode<'a>>,
+        trans_id: &'a [u8],
+    ) -> DhtResult<PutDataRequest<'a>> {
+        let validate = RequestValidate::new(trans_id);
+
+        let node_id_bytes = validate.lookup_and_convert_bytes(rqst_root, message::NODE_ID_KEY)?;
+        let node_id = validate.validate_node_id(node_id_bytes)?;
+
+        let token = validate.lookup_and_convert_bytes(rqst_root, message::TOKEN_KEY)?;
+        let value = validate.lookup(rqst_root, message::VALUE_KEY)?;
+
+        // Only mutable items are signed
+        let mutable = if rqst_root
+            .lookup(message::PUBLIC_KEY_KEY.as_bytes())
+            .is_some()
+        {
+            let public_key =
+                validate.lookup_and_convert_bytes(rqst_root, message::PUBLIC_KEY_KEY)?;
+            let salt = validate
+                .lookup_and_convert_bytes(rqst_root, SALT_KEY)
+                .unwrap_or(&[]);
+            let seq = validate.lookup_and_convert_int(rqst_root, message::SEQ_KEY)?;
+            let signature = validate.lookup_and_convert_bytes(rqst_root, message::SIGNATURE_KEY)?;
+            let cas = validate.lookup_and_convert_int(rqst_root, CAS_KEY).ok();
+
+            Some(MutablePutArgs::new(public_key, salt, seq, signature, cas))
+        } else {
+            None
+        };
+
+        Ok(PutDataRequest::new(
+            trans_id, node_id, token, value, mutable,
+        ))
+    }
+
     pub fn transaction_id(&self) -> &'a [u8] {
         self.trans_id
     }
-}
\ No newline at end of file
+
+    pub fn node_id(&self) -> NodeId {
+        self.node_id
+    }
+
+    pub fn token(&self) -> &'a [u8] {
+        self.token
+    }
+
+    pub fn value(&self) -> &'a Bencode<'a> {
+        self.value
+    }
+
+    pub fn mutable(&self) -> Option<MutablePutArgs<'a>> {
+        self.mutable
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut request_args = BTreeMap::new();
+
+        request_args.insert(
+            message::NODE_ID_KEY.as_bytes(),
+            dht_ben_bytes!(self.node_id.as_ref()),
+        );
+        request_args.insert(message::TOKEN_KEY.as_bytes(), dht_ben_bytes!(self.token));
+        request_args.insert(message::VALUE_KEY.as_bytes(), self.value.clone());
+
+        if let Some(mutable) = self.mutable {
+            request_args.insert(
+                message::PUBLIC_KEY_KEY.as_bytes(),
+                dht_ben_bytes!(mutable.public_key),
+            );
+            if !mutable.salt.is_empty() {
+                request_args.insert(SALT_KEY.as_bytes(), dht_ben_bytes!(mutable.salt));
+            }
+            request_args.insert(message::SEQ_KEY.as_bytes(), dht_ben_int!(mutable.seq));
+            request_args.insert(
+                message::SIGNATURE_KEY.as_bytes(),
+                dht_ben_bytes!(mutable.signature),
+            );
+            if let Some(cas) = mutable.cas {
+                request_args.insert(CAS_KEY.as_bytes(), dht_ben_int!(cas));
+            }
+        }
+
+        (dht_ben_map! {
+            message::TRANSACTION_ID_KEY => dht_ben_bytes!(self.trans_id),
+            message::MESSAGE_TYPE_KEY => dht_ben_bytes!(message::REQUEST_TYPE_KEY),
+            message::REQUEST_TYPE_KEY => dht_ben_bytes!(request::PUT_DATA_TYPE_KEY),
+            request::REQUEST_ARGS_KEY => Bencode::Dict(request_args)
+        })
+        .encode()
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub struct PutDataResponse<'a> {
+    trans_id: &'a [u8],
+    node_id: NodeId,
+}
+
+impl<'a> PutDataResponse<'a> {
+    pub fn new(trans_id: &'a [u8], node_id: NodeId) -> PutDataResponse<'a> {
+        PutDataResponse {
+            trans_id: trans_id,
+            node_id: node_id,
+        }
+    }
+
+    pub fn from_parts(
+        rsp_root: &dyn Dictionary<'a, Bencode<'a>>,
+        trans_id: &'a [u8],
+    ) -> DhtResult<PutDataResponse<'a>> {
+        let validate = RequestValidate::new(trans_id);
+
+        let node_id_bytes = validate.lookup_and_convert_bytes(rsp_root, message::NODE_ID_KEY)?;
+        let node_id = validate.validate_node_id(node_id_bytes)?;
+
+        Ok(PutDataResponse::new(trans_id, node_id))
+    }
+
+    pub fn transaction_id(&self) -> &'a [u8] {
+        self.trans_id
+    }
+
+    pub fn node_id(&self) -> NodeId {
+        self.node_id
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        (dht_ben_map! {
+            message::TRANSACTION_ID_KEY => dht_ben_bytes!(self.trans_id),
+            message::MESSAGE_TYPE_KEY => dht_ben_bytes!(message::RESPONSE_TYPE_KEY),
+            response::RESPONSE_ARGS_KEY => dht_ben_map!{
+                message::NODE_ID_KEY => dht_ben_bytes!(self.node_id.as_ref())
+            }
+        })
+        .encode()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{MutablePutArgs, PutDataRequest};
+    use crate::dht::bencode::Bencode;
+    use crate::dht::message::request::RequestType;
+    use crate::dht::message::response::ExpectedResponse;
+    use crate::dht::message::MessageType;
+
+    #[test]
+    fn positive_put_request_mutable_round_trip() {
+        let value = Bencode::decode(b"12:Hello World!").unwrap();
+        let mutable = MutablePutArgs::new(&[3u8; 32], b"foobar", 1, &[4u8; 64], Some(0));
+        let request = PutDataRequest::new(b"aa", [1u8; 20].into(), b"token", &value, Some(mutable));
+        let encoded = request.encode();
+
+        let bencode = Bencode::decode(&encoded).unwrap();
+        match MessageType::new(&bencode, |_| ExpectedResponse::None).unwrap() {
+            MessageType::Request(RequestType::PutData(decoded)) => assert_eq!(decoded, request),
+            other => panic!("Unexpected Message {:?}", other),
+        }
+    }
+
+    #[test]
+    fn positive_put_request_immutable_round_trip() {
+        let value = Bencode::decode(b"12:Hello World!").unwrap();
+        let request = PutDataRequest::new(b"aa", [1u8; 20].into(), b"token", &value, None);
+        let encoded = request.encode();
+
+        let bencode = Bencode::decode(&encoded).unwrap();
+        match MessageType::new(&bencode, |_| ExpectedResponse::None).unwrap() {
+            MessageType::Request(RequestType::PutData(decoded)) => assert_eq!(decoded, request),
+            other => panic!("Unexpected Message {:?}", other),
+        }
+    }
+}
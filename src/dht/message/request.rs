@@ -7,8 +7,11 @@ use crate::dht::message;
 use crate::dht::message::announce_peer::AnnouncePeerRequest;
 use crate::dht::message::error::{ErrorCode, ErrorMessage};
 use crate::dht::message::find_node::FindNodeRequest;
+use crate::dht::message::get_data::GetDataRequest;
 use crate::dht::message::get_peers::GetPeersRequest;
 use crate::dht::message::ping::PingRequest;
+use crate::dht::message::put_data::PutDataRequest;
+use crate::dht::message::sample_infohashes::SampleInfoHashesRequest;
 
 pub const REQUEST_ARGS_KEY: &'static str = "a";
 
@@ -17,8 +20,9 @@ pub const PING_TYPE_KEY: &'static str = "ping";
 pub const FIND_NODE_TYPE_KEY: &'static str = "find_node";
 pub const GET_PEERS_TYPE_KEY: &'static str = "get_peers";
 pub const ANNOUNCE_PEER_TYPE_KEY: &'static str = "announce_peer";
-// const GET_DATA_TYPE_KEY:          &'static str = "get";
-// const PUT_DATA_TYPE_KEY:          &'static str = "put";
+pub const GET_DATA_TYPE_KEY: &'static str = "get";
+pub const PUT_DATA_TYPE_KEY: &'static str = "put";
+pub const SAMPLE_INFOHASHES_TYPE_KEY: &'static str = "sample_infohashes";
 
 // ----------------------------------------------------------------------------//
 
@@ -71,13 +75,15 @@ pub enum RequestType<'a> {
     Ping(PingRequest<'a>),
     FindNode(FindNodeRequest<'a>),
     GetPeers(GetPeersRequest<'a>),
-    AnnouncePeer(AnnouncePeerRequest<'a>), /* GetData(GetDataRequest<'a>),
-                                            * PutData(PutDataRequest<'a>) */
+    AnnouncePeer(AnnouncePeerRequest<'a>),
+    GetData(GetDataRequest<'a>),
+    PutData(PutDataRequest<'a>),
+    SampleInfoHashes(SampleInfoHashesRequest<'a>),
 }
 
 impl<'a> RequestType<'a> {
     pub fn from_parts(
-        root: &dyn Dictionary<'a, Bencode<'a>>,
+        root: &'a dyn Dictionary<'a, Bencode<'a>>,
         trans_id: &'a [u8],
         rqst_type: &str,
     ) -> DhtResult<RequestType<'a>> {
@@ -102,14 +108,18 @@ impl<'a> RequestType<'a> {
                 let announce_peer_rqst = AnnouncePeerRequest::from_parts(rqst_root, trans_id)?;
                 Ok(RequestType::AnnouncePeer(announce_peer_rqst))
             }
-            // GET_DATA_TYPE_KEY => {
-            // let get_data_rqst = try!(GetDataRequest::new(rqst_root, trans_id));
-            // Ok(RequestType::GetData(get_data_rqst))
-            // },
-            // PUT_DATA_TYPE_KEY => {
-            // let put_data_rqst = try!(PutDataRequest::new(rqst_root, trans_id));
-            // Ok(RequestType::PutData(put_data_rqst))
-            // },
+            GET_DATA_TYPE_KEY => {
+                let get_data_rqst = GetDataRequest::from_parts(rqst_root, trans_id)?;
+                Ok(RequestType::GetData(get_data_rqst))
+            }
+            PUT_DATA_TYPE_KEY => {
+                let put_data_rqst = PutDataRequest::from_parts(rqst_root, trans_id)?;
+                Ok(RequestType::PutData(put_data_rqst))
+            }
+            SAMPLE_INFOHASHES_TYPE_KEY => {
+                let sample_rqst = SampleInfoHashesRequest::from_parts(rqst_root, trans_id)?;
+                Ok(RequestType::SampleInfoHashes(sample_rqst))
+            }
             unknown => {
                 if let Some(target_key) = forward_compatible_find_node(rqst_root) {
                     let find_node_rqst =
@@ -129,6 +139,18 @@ impl<'a> RequestType<'a> {
             }
         }
     }
+
+    pub fn transaction_id(&self) -> &'a [u8] {
+        match self {
+            &RequestType::Ping(ref p) => p.transaction_id(),
+            &RequestType::FindNode(ref f) => f.transaction_id(),
+            &RequestType::GetPeers(ref g) => g.transaction_id(),
+            &RequestType::AnnouncePeer(ref a) => a.transaction_id(),
+            &RequestType::GetData(ref g) => g.transaction_id(),
+            &RequestType::PutData(ref p) => p.transaction_id(),
+            &RequestType::SampleInfoHashes(ref s) => s.transaction_id(),
+        }
+    }
 }
 
 /// Mainline dht extension for forward compatibility.
@@ -6,8 +6,11 @@ use crate::dht::error::{DhtError, DhtErrorKind, DhtResult};
 use crate::dht::message::announce_peer::AnnouncePeerResponse;
 use crate::dht::message::compact_info::{CompactNodeInfo, CompactValueInfo};
 use crate::dht::message::find_node::FindNodeResponse;
+use crate::dht::message::get_data::GetDataResponse;
 use crate::dht::message::get_peers::GetPeersResponse;
 use crate::dht::message::ping::PingResponse;
+use crate::dht::message::put_data::PutDataResponse;
+use crate::dht::message::sample_infohashes::SampleInfoHashesResponse;
 
 pub const RESPONSE_ARGS_KEY: &'static str = "r";
 
@@ -48,6 +51,20 @@ impl<'a> ResponseValidate<'a> {
         })
     }
 
+    /// Validate the given nodes6 string which should be IPv6 compact
+    pub fn validate_nodes6<'b>(&self, nodes6: &'b [u8]) -> DhtResult<CompactNodeInfo<'b>> {
+        CompactNodeInfo::new_v6(nodes6).map_err(|_| {
+            DhtError::from_kind(DhtErrorKind::InvalidResponse {
+                details: format!(
+                    "TID {:?} Found Nodes6 Structure With {} Number Of Bytes Instead \
+                                  Of Correct Multiple",
+                    self.trans_id,
+                    nodes6.len()
+                ),
+            })
+        })
+    }
+
     pub fn validate_values<'b>(
         &self,
         values: &'b [Bencode<'a>],
@@ -95,6 +112,7 @@ pub enum ExpectedResponse {
     AnnouncePeer,
     GetData,
     PutData,
+    SampleInfoHashes,
     None,
 }
 
@@ -103,8 +121,10 @@ pub enum ResponseType<'a> {
     Ping(PingResponse<'a>),
     FindNode(FindNodeResponse<'a>),
     GetPeers(GetPeersResponse<'a>),
-    AnnouncePeer(AnnouncePeerResponse<'a>), /* GetData(GetDataResponse<'a>),
-                                             * PutData(PutDataResponse<'a>) */
+    AnnouncePeer(AnnouncePeerResponse<'a>),
+    GetData(GetDataResponse<'a>),
+    PutData(PutDataResponse<'a>),
+    SampleInfoHashes(SampleInfoHashesResponse<'a>),
 }
 
 impl<'a> ResponseType<'a> {
@@ -134,10 +154,16 @@ impl<'a> ResponseType<'a> {
                 Ok(ResponseType::AnnouncePeer(announce_peer_rsp))
             }
             ExpectedResponse::GetData => {
-                unimplemented!();
+                let get_data_rsp = GetDataResponse::from_parts(rqst_root, trans_id)?;
+                Ok(ResponseType::GetData(get_data_rsp))
             }
             ExpectedResponse::PutData => {
-                unimplemented!();
+                let put_data_rsp = PutDataResponse::from_parts(rqst_root, trans_id)?;
+                Ok(ResponseType::PutData(put_data_rsp))
+            }
+            ExpectedResponse::SampleInfoHashes => {
+                let sample_rsp = SampleInfoHashesResponse::from_parts(rqst_root, trans_id)?;
+                Ok(ResponseType::SampleInfoHashes(sample_rsp))
             }
             ExpectedResponse::None => Err(DhtError::from_kind(DhtErrorKind::UnsolicitedResponse)),
         }
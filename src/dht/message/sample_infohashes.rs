@@ -0,0 +1,329 @@
+use std::collections::BTreeMap;
+
+use crate::util::bt::{self, InfoHash, NodeId};
+
+use crate::dht::bencode::{Bencode, BencodeConvert, Dictionary};
+use crate::dht::error::{DhtError, DhtErrorKind, DhtResult};
+use crate::dht::message;
+use crate::dht::message::compact_info::CompactNodeInfo;
+use crate::dht::message::request::{self, RequestValidate};
+use crate::dht::message::response::{self, ResponseValidate};
+
+const INTERVAL_KEY: &'static str = "interval";
+const NUM_KEY: &'static str = "num";
+const SAMPLES_KEY: &'static str = "samples";
+
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub struct SampleInfoHashesRequest<'a> {
+    trans_id: &'a [u8],
+    node_id: NodeId,
+    target: NodeId,
+}
+
+impl<'a> SampleInfoHashesRequest<'a> {
+    pub fn new(trans_id: &'a [u8], node_id: NodeId, target: NodeId) -> SampleInfoHashesRequest<'a> {
+        SampleInfoHashesRequest {
+            trans_id: trans_id,
+            node_id: node_id,
+            target: target,
+        }
+    }
+
+    pub fn from_parts(
+        rqst_root: &dyn Dictionary<'a, Bencode<'a>>,
+        trans_id: &'a [u8],
+    ) -> DhtResult<SampleInfoHashesRequest<'a>> {
+        let validate = RequestValidate::new(trans_id);
+
+        let node_id_bytes = validate.lookup_and_convert_bytes(rqst_root, message::NODE_ID_KEY)?;
+        let node_id = validate.validate_node_id(node_id_bytes)?;
+
+        let target_bytes = validate.lookup_and_convert_bytes(rqst_root, message::TARGET_ID_KEY)?;
+        let target = validate.validate_node_id(target_bytes)?;
+
+        Ok(SampleInfoHashesRequest::new(trans_id, node_id, target))
+    }
+
+    pub fn transaction_id(&self) -> &'a [u8] {
+        self.trans_id
+    }
+
+    pub fn node_id(&self) -> NodeId {
+        self.node_id
+    }
+
+    pub fn target(&self) -> NodeId {
+        self.target
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        (bt_ben_map! {
+            message::TRANSACTION_ID_KEY => bt_ben_bytes!(self.trans_id),
+            message::MESSAGE_TYPE_KEY => bt_ben_bytes!(message::REQUEST_TYPE_KEY),
+            message::REQUEST_TYPE_KEY => bt_ben_bytes!(request::SAMPLE_INFOHASHES_TYPE_KEY),
+            request::REQUEST_ARGS_KEY => bt_ben_map!{
+                message::NODE_ID_KEY => bt_ben_bytes!(self.node_id.as_ref()),
+                message::TARGET_ID_KEY => bt_ben_bytes!(self.target.as_ref())
+            }
+        })
+        .encode()
+    }
+}
+
+/// Response to a sample infohashes request, see BEP 51.
+///
+/// Every key besides the node id is optional, nodes which do not support BEP 51 tend to
+/// answer the request like a find node request.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub struct SampleInfoHashesResponse<'a> {
+    trans_id: &'a [u8],
+    node_id: NodeId,
+    interval: Option<i64>,
+    nodes: Option<CompactNodeInfo<'a>>,
+    // IPv6 nodes, see BEP 32
+    nodes6: Option<CompactNodeInfo<'a>>,
+    num: Option<i64>,
+    samples: Option<&'a [u8]>,
+}
+
+impl<'a> SampleInfoHashesResponse<'a> {
+    pub fn new(
+        trans_id: &'a [u8],
+        node_id: NodeId,
+        interval: Option<i64>,
+        nodes: Option<CompactNodeInfo<'a>>,
+        nodes6: Option<CompactNodeInfo<'a>>,
+        num: Option<i64>,
+        samples: Option<&'a [u8]>,
+    ) -> SampleInfoHashesResponse<'a> {
+        SampleInfoHashesResponse {
+            trans_id: trans_id,
+            node_id: node_id,
+            interval: interval,
+            nodes: nodes,
+            nodes6: nodes6,
+            num: num,
+            samples: samples,
+        }
+    }
+
+    pub fn from_parts(
+        rsp_root: &dyn Dictionary<'a, Bencode<'a>>,
+        trans_id: &'a [u8],
+    ) -> DhtResult<SampleInfoHashesResponse<'a>> {
+        let validate = ResponseValidate::new(trans_id);
+
+        let node_id_bytes = validate.lookup_and_convert_bytes(rsp_root, message::NODE_ID_KEY)?;
+        let node_id = validate.validate_node_id(node_id_bytes)?;
+
+        let interval = validate.lookup_and_convert_int(rsp_root, INTERVAL_KEY).ok();
+        let nodes = match validate.lookup_and_convert_bytes(rsp_root, message::NODES_KEY) {
+            Ok(nodes) => Some(validate.validate_nodes(nodes)?),
+            Err(_) => None,
+        };
+        let nodes6 = match validate.lookup_and_convert_bytes(rsp_root, message::NODES6_KEY) {
+            Ok(nodes6) => Some(validate.validate_nodes6(nodes6)?),
+            Err(_) => None,
+        };
+        let num = validate.lookup_and_convert_int(rsp_root, NUM_KEY).ok();
+
+        let samples = validate
+            .lookup_and_convert_bytes(rsp_root, SAMPLES_KEY)
+            .ok();
+        if let Some(samples) = samples {
+            if samples.len() % bt::INFO_HASH_LEN != 0 {
+                return Err(DhtError::from_kind(DhtErrorKind::InvalidResponse {
+                    details: format!(
+                        "TID {:?} Found Samples With {} Number Of Bytes Instead Of Correct Multiple",
+                        trans_id,
+                        samples.len()
+                    ),
+                }));
+            }
+        }
+
+        Ok(SampleInfoHashesResponse::new(
+            trans_id, node_id, interval, nodes, nodes6, num, samples,
+        ))
+    }
+
+    pub fn transaction_id(&self) -> &'a [u8] {
+        self.trans_id
+    }
+
+    pub fn node_id(&self) -> NodeId {
+        self.node_id
+    }
+
+    /// Number of seconds before the node should be queried again.
+    pub fn interval(&self) -> Option<i64> {
+        self.interval
+    }
+
+    pub fn nodes(&self) -> Option<CompactNodeInfo<'a>> {
+        self.nodes
+    }
+
+    pub fn nodes6(&self) -> Option<CompactNodeInfo<'a>> {
+        self.nodes6
+    }
+
+    /// Number of info hashes the node is storing.
+    pub fn num(&self) -> Option<i64> {
+        self.num
+    }
+
+    /// Iterator over the sampled info hashes, empty if the node did not send any.
+    pub fn samples(&self) -> impl Iterator<Item = InfoHash> + 'a {
+        self.samples
+            .unwrap_or(&[])
+            .chunks(bt::INFO_HASH_LEN)
+            .map(|chunk| InfoHash::from_hash(chunk).unwrap())
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut response_args = BTreeMap::new();
+
+        response_args.insert(
+            message::NODE_ID_KEY.as_bytes(),
+            dht_ben_bytes!(self.node_id.as_ref()),
+        );
+        if let Some(interval) = self.interval {
+            response_args.insert(INTERVAL_KEY.as_bytes(), dht_ben_int!(interval));
+        }
+        if let Some(nodes) = self.nodes {
+            response_args.insert(message::NODES_KEY.as_bytes(), dht_ben_bytes!(nodes.nodes()));
+        }
+        if let Some(nodes6) = self.nodes6 {
+            response_args.insert(
+                message::NODES6_KEY.as_bytes(),
+                dht_ben_bytes!(nodes6.nodes()),
+            );
+        }
+        if let Some(num) = self.num {
+            response_args.insert(NUM_KEY.as_bytes(), dht_ben_int!(num));
+        }
+        if let Some(samples) = self.samples {
+            response_args.insert(SAMPLES_KEY.as_bytes(), dht_ben_bytes!(samples));
+        }
+
+        (dht_ben_map! {
+            message::TRANSACTION_ID_KEY => dht_ben_bytes!(self.trans_id),
+            message::MESSAGE_TYPE_KEY => dht_ben_bytes!(message::RESPONSE_TYPE_KEY),
+            response::RESPONSE_ARGS_KEY => Bencode::Dict(response_args)
+        })
+        .encode()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{SampleInfoHashesRequest, SampleInfoHashesResponse};
+    use crate::dht::bencode::Bencode;
+    use crate::dht::message::request::RequestType;
+    use crate::dht::message::response::{ExpectedResponse, ResponseType};
+    use crate::dht::message::MessageType;
+    use crate::util::bt::{InfoHash, NodeId};
+
+    // Laid out the way libtorrent sends them, including its client version and requester ip
+    const REQUEST: &'static [u8] =
+        b"d1:ad2:id20:aaaaaaaaaaaaaaaaaaaa6:target20:bbbbbbbbbbbbbbbbbbbbe\
+        1:q17:sample_infohashes1:t2:xy1:v4:LT\x01\x021:y1:qe";
+    const RESPONSE: &'static [u8] =
+        b"d2:ip6:\x7f\x00\x00\x01\x1a\xe11:rd2:id20:cccccccccccccccccccc\
+        8:intervali21600e5:nodes26:dddddddddddddddddddd\x7f\x00\x00\x01\x1a\xe13:numi3e\
+        7:samples40:eeeeeeeeeeeeeeeeeeeeffffffffffffffffffffe1:t2:xy1:v4:LT\x01\x021:y1:re";
+    // Node not supporting BEP 51, answering like a find node request
+    const FIND_NODE_RESPONSE: &'static [u8] = b"d1:rd2:id20:cccccccccccccccccccc\
+        5:nodes26:dddddddddddddddddddd\x7f\x00\x00\x01\x1a\xe1e1:t2:xy1:y1:re";
+
+    #[test]
+    fn positive_decode_libtorrent_request() {
+        let bencode = Bencode::decode(REQUEST).unwrap();
+
+        match MessageType::new(&bencode, |_| ExpectedResponse::None).unwrap() {
+            MessageType::Request(RequestType::SampleInfoHashes(request)) => {
+                assert_eq!(
+                    request,
+                    SampleInfoHashesRequest::new(b"xy", [b'a'; 20].into(), [b'b'; 20].into())
+                );
+            }
+            other => panic!("Unexpected Message {:?}", other),
+        }
+    }
+
+    #[test]
+    fn positive_encode_request() {
+        let request = SampleInfoHashesRequest::new(b"xy", [b'a'; 20].into(), [b'b'; 20].into());
+
+        let expected: &[u8] = b"d1:ad2:id20:aaaaaaaaaaaaaaaaaaaa6:target20:bbbbbbbbbbbbbbbbbbbbe\
+            1:q17:sample_infohashes1:t2:xy1:y1:qe";
+        assert_eq!(&request.encode()[..], expected);
+    }
+
+    #[test]
+    fn positive_decode_libtorrent_response() {
+        let bencode = Bencode::decode(RESPONSE).unwrap();
+
+        let response =
+            match MessageType::new(&bencode, |_| ExpectedResponse::SampleInfoHashes).unwrap() {
+                MessageType::Response(ResponseType::SampleInfoHashes(response)) => response,
+                other => panic!("Unexpected Message {:?}", other),
+            };
+        assert_eq!(response.node_id(), NodeId::from([b'c'; 20]));
+        assert_eq!(response.interval(), Some(21600));
+        assert_eq!(response.nodes().unwrap().into_iter().count(), 1);
+        assert_eq!(response.num(), Some(3));
+
+        let expected: Vec<InfoHash> = vec![[b'e'; 20].into(), [b'f'; 20].into()];
+        assert_eq!(response.samples().collect::<Vec<_>>(), expected);
+    }
+
+    #[test]
+    fn positive_encode_response() {
+        let bencode = Bencode::decode(RESPONSE).unwrap();
+        let response =
+            match MessageType::new(&bencode, |_| ExpectedResponse::SampleInfoHashes).unwrap() {
+                MessageType::Response(ResponseType::SampleInfoHashes(response)) => response,
+                other => panic!("Unexpected Message {:?}", other),
+            };
+
+        // Same as what libtorrent sent, minus the keys we add elsewhere
+        let expected: &[u8] = b"d1:rd2:id20:cccccccccccccccccccc8:intervali21600e\
+            5:nodes26:dddddddddddddddddddd\x7f\x00\x00\x01\x1a\xe13:numi3e\
+            7:samples40:eeeeeeeeeeeeeeeeeeeeffffffffffffffffffffe1:t2:xy1:y1:re";
+        assert_eq!(&response.encode()[..], expected);
+    }
+
+    #[test]
+    fn positive_decode_find_node_response() {
+        let bencode = Bencode::decode(FIND_NODE_RESPONSE).unwrap();
+
+        match MessageType::new(&bencode, |_| ExpectedResponse::SampleInfoHashes).unwrap() {
+            MessageType::Response(ResponseType::SampleInfoHashes(response)) => {
+                assert_eq!(response.interval(), None);
+                assert_eq!(response.num(), None);
+                assert_eq!(response.samples().count(), 0);
+                assert!(response.nodes().is_some());
+            }
+            other => panic!("Unexpected Message {:?}", other),
+        }
+    }
+
+    #[test]
+    fn negative_decode_truncated_samples() {
+        let response = SampleInfoHashesResponse::new(
+            b"xy",
+            [b'c'; 20].into(),
+            Some(0),
+            None,
+            None,
+            Some(1),
+            Some(&[b'e'; 19]),
+        );
+        let encoded = response.encode();
+
+        let bencode = Bencode::decode(&encoded).unwrap();
+        assert!(MessageType::new(&bencode, |_| ExpectedResponse::SampleInfoHashes).is_err());
+    }
+}
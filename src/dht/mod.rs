@@ -5,7 +5,7 @@
 // - Unrecognized requests which contain either an 'info_hash' or 'target' arguments are interpreted as 'find_node'
 // - Client identification will be present in all outgoing messages in the form of the 'v' key TODO
 // const CLIENT_IDENTIFICATION: &'static [u8] = &[b'B', b'I', b'P', 0, 1];
-// * IPv6 is currently NOT supported in this implementation
+// - IPv6 nodes and dual stack operation as described in BEP 32
 
 // TODO: The Vuze dht operates over a protocol that is different than the mainline dht.
 // It would be possible to create a dht client that can work over both dhts simultaneously,
@@ -21,11 +21,19 @@ pub use self::handshake::Handshaker;
 #[macro_use]
 mod bencode;
 
+mod bloom;
+pub use bloom::{BloomFilter, BLOOM_FILTER_LEN};
+
 mod builder;
-pub use builder::{DhtBuilder, MainlineDht};
+pub use builder::{DhtBuilder, IpStack, MainlineDht, SearchStream};
 
 mod error;
 
+mod item;
+pub use item::{mutable_target, GetItem, ImmutableItem, ItemError, MutableItem};
+
+mod limiter;
+
 pub mod message;
 
 mod router;
@@ -33,7 +41,14 @@ pub use router::Router;
 
 mod routing;
 
+mod sample;
+pub use sample::{SampleError, SampleInfoHashes, SampleResult};
+
 mod security;
+pub use security::NodeIdPolicy;
+
+mod state;
+pub use state::{DhtState, StateError};
 
 mod storage;
 
@@ -42,7 +57,7 @@ mod token;
 mod transaction;
 
 mod worker;
-pub use worker::{DhtEvent, ShutdownCause};
+pub use worker::{DhtEvent, DhtStatus, ReadOnlyQueries, SearchEvent, ShutdownCause};
 
 /// Test
 pub use crate::util::bt::{InfoHash, PeerId};
@@ -99,6 +99,29 @@ impl Bucket {
             false
         }
     }
+
+    /// Replace the first node accepted by the given function which does not have a higher
+    /// status than the given Node.
+    ///
+    /// Returns false if no node was replaced.
+    pub fn replace_node<F>(&mut self, new_node: Node, replaceable: F) -> bool
+    where
+        F: Fn(&Node) -> bool,
+    {
+        let new_node_status = new_node.status();
+        let replace_index = self
+            .nodes
+            .iter()
+            .position(|node| node.status() <= new_node_status && replaceable(node));
+
+        if let Some(index) = replace_index {
+            self.nodes[index] = new_node;
+
+            true
+        } else {
+            false
+        }
+    }
 }
 
 // ----------------------------------------------------------------------------//
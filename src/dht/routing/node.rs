@@ -9,6 +9,7 @@ use std::net::SocketAddr;
 use chrono::{DateTime, Duration, TimeZone, Utc};
 
 use crate::util::bt::NodeId;
+use crate::util::convert;
 use crate::util::test;
 
 // TODO: Should remove as_* functions and replace them with from_requested, from_responded, etc to hide the logic
@@ -73,6 +74,17 @@ impl Node {
         }
     }
 
+    /// Create a node that last responded to us at the given time but never requested from us.
+    pub fn as_responded(id: NodeId, addr: SocketAddr, last_response: DateTime<Utc>) -> Node {
+        Node {
+            id: id,
+            addr: addr,
+            last_response: Cell::new(Some(last_response)),
+            last_request: Cell::new(None),
+            refresh_requests: Cell::new(0),
+        }
+    }
+
     /// Create a new node that has never responded to us or requested from us.
     pub fn as_bad(id: NodeId, addr: SocketAddr) -> Node {
         Node {
@@ -113,33 +125,26 @@ impl Node {
         self.addr
     }
 
-    pub fn encode(&self) -> [u8; 26] {
-        let mut encoded = [0u8; 26];
+    /// Last time we heard from the node, None if it never responded to us.
+    pub fn last_seen(&self) -> Option<DateTime<Utc>> {
+        self.last_response
+            .get()
+            .map(|response_time| match self.last_request.get() {
+                Some(request_time) if request_time > response_time => request_time,
+                _ => response_time,
+            })
+    }
+
+    /// Encode the node as compact node info, 26 bytes for IPv4 nodes and 38 bytes for IPv6 nodes.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut encoded = Vec::with_capacity(38);
 
-        {
-            let mut encoded_iter = encoded.iter_mut();
-
-            // Copy the node id over
-            for (src, dst) in self.id.as_ref().iter().zip(encoded_iter.by_ref()) {
-                *dst = *src;
-            }
-
-            // Copy the ip address over
-            match self.addr {
-                SocketAddr::V4(v4) => {
-                    for (src, dst) in v4.ip().octets().iter().zip(encoded_iter.by_ref()) {
-                        *dst = *src;
-                    }
-                }
-                _ => panic!("bittorrent-protocol_dht: Cannot encode a SocketAddrV6..."),
-            }
+        encoded.extend_from_slice(self.id.as_ref());
+        match self.addr {
+            SocketAddr::V4(v4) => encoded.extend_from_slice(&convert::sock_v4_to_bytes_be(v4)),
+            SocketAddr::V6(v6) => encoded.extend_from_slice(&convert::sock_v6_to_bytes_be(v6)),
         }
 
-        // Copy the port over
-        let port = self.addr.port();
-        encoded[24] = (port >> 8) as u8;
-        encoded[25] = port as u8;
-
         encoded
     }
 
@@ -254,7 +259,7 @@ fn recently_requested(node: &Node, curr_time: DateTime<Utc>) -> NodeStatus {
 #[cfg(test)]
 mod tests {
     use std::iter;
-    use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
+    use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6};
 
     use crate::util::bt::NodeId;
     use crate::util::test as util_test;
@@ -288,6 +293,22 @@ mod tests {
         }
     }
 
+    #[test]
+    fn positive_encode_node_v6() {
+        let node_id = [7u8; 20];
+        let port = 6881;
+        let sock_addr = SocketAddr::V6(SocketAddrV6::new(Ipv6Addr::LOCALHOST, port, 0, 0));
+
+        let node = Node::as_good(node_id.into(), sock_addr);
+
+        let encoded_node = node.encode();
+
+        assert_eq!(encoded_node.len(), 38);
+        assert_eq!(&encoded_node[..20], &node_id[..]);
+        assert_eq!(&encoded_node[20..36], &Ipv6Addr::LOCALHOST.octets()[..]);
+        assert_eq!(&encoded_node[36..], &[(port >> 8) as u8, port as u8][..]);
+    }
+
     #[test]
     fn positive_as_bad() {
         let node = Node::as_bad(
@@ -318,6 +339,43 @@ mod tests {
         assert_eq!(node.status(), NodeStatus::Good);
     }
 
+    #[test]
+    fn positive_as_responded() {
+        let last_response = util_test::travel_into_past(Duration::minutes(60));
+        let node = Node::as_responded(
+            util_test::dummy_node_id(),
+            util_test::dummy_socket_addr_v4(),
+            last_response,
+        );
+
+        assert_eq!(node.status(), NodeStatus::Questionable);
+        assert_eq!(node.last_seen(), Some(last_response));
+    }
+
+    #[test]
+    fn positive_last_seen_request() {
+        let node = Node::as_questionable(
+            util_test::dummy_node_id(),
+            util_test::dummy_socket_addr_v4(),
+        );
+
+        node.remote_request();
+
+        assert!(node.last_seen().unwrap() > Utc::now() - Duration::minutes(1));
+    }
+
+    #[test]
+    fn negative_last_seen_never_responded() {
+        let node = Node::as_bad(
+            util_test::dummy_node_id(),
+            util_test::dummy_socket_addr_v4(),
+        );
+
+        node.remote_request();
+
+        assert_eq!(node.last_seen(), None);
+    }
+
     #[test]
     fn positive_response_renewal() {
         let node = Node::as_questionable(
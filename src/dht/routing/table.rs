@@ -1,8 +1,12 @@
 // TODO: Remove when we use find_node,
 #![allow(unused)]
 
+use std::collections::HashMap;
 use std::iter::Filter;
+use std::mem;
+use std::net::IpAddr;
 use std::slice::Iter;
+use std::time::{Duration, Instant};
 
 use crate::util::bt::NodeId;
 use crate::util::sha::{self, ShaHash, XorRep};
@@ -10,6 +14,7 @@ use rand;
 
 use crate::dht::routing::bucket::{self, Bucket};
 use crate::dht::routing::node::{Node, NodeStatus};
+use crate::dht::security::NodeIdPolicy;
 
 pub const MAX_BUCKETS: usize = sha::SHA_HASH_LEN * 8;
 
@@ -20,16 +25,27 @@ pub struct RoutingTable {
     // of the last bucket in the buckets array.
     buckets: Vec<Bucket>,
     node_id: NodeId,
+    policy: NodeIdPolicy,
+    // Ips we do not admit nodes from until the given time
+    bans: HashMap<IpAddr, Instant>,
 }
 
 impl RoutingTable {
     /// Create a new RoutingTable with the given node id as our id.
     pub fn new(node_id: NodeId) -> RoutingTable {
+        RoutingTable::with_policy(node_id, NodeIdPolicy::AcceptAll)
+    }
+
+    /// Create a new RoutingTable with the given node id as our id, admitting nodes
+    /// according to the given policy.
+    pub fn with_policy(node_id: NodeId, policy: NodeIdPolicy) -> RoutingTable {
         let buckets = vec![Bucket::new()];
 
         RoutingTable {
             buckets: buckets,
             node_id: node_id,
+            policy: policy,
+            bans: HashMap::new(),
         }
     }
 
@@ -38,6 +54,16 @@ impl RoutingTable {
         self.node_id
     }
 
+    /// Change the node id of the RoutingTable, redistributing our nodes amongst new buckets.
+    pub fn set_node_id(&mut self, node_id: NodeId) {
+        let old_buckets = mem::replace(&mut self.buckets, vec![Bucket::new()]);
+        self.node_id = node_id;
+
+        for node in old_buckets.iter().flat_map(|bucket| bucket.iter()) {
+            self.add_node(node.clone());
+        }
+    }
+
     /// Iterator over the closest good nodes to the given node id.
     ///
     /// The closeness of nodes has a maximum granularity of a bucket. For most use
@@ -52,6 +78,13 @@ impl RoutingTable {
         Buckets::new(&self.buckets)
     }
 
+    /// Iterator over all good and questionable nodes in the routing table.
+    pub fn pingable_nodes<'a>(&'a self) -> impl Iterator<Item = &'a Node> + 'a {
+        self.buckets
+            .iter()
+            .flat_map(|bucket| bucket.pingable_nodes())
+    }
+
     /// Find an instance of the target node in the RoutingTable, if it exists.
     pub fn find_node(&self, node: &Node) -> Option<&Node> {
         let bucket_index = leading_bit_count(self.node_id, node.id());
@@ -77,12 +110,30 @@ impl RoutingTable {
         }
     }
 
+    /// Stop admitting nodes with the given ip into the RoutingTable for the given duration.
+    ///
+    /// Nodes with the ip already in the RoutingTable are left alone.
+    pub fn ban_ip(&mut self, ip: IpAddr, duration: Duration) {
+        let now = Instant::now();
+
+        self.bans.retain(|_, &mut until| until > now);
+        self.bans.insert(ip, now + duration);
+    }
+
     /// Add the node to the RoutingTable if there is space for it.
     pub fn add_node(&mut self, node: Node) {
         // Doing some checks and calculations here, outside of the recursion
         if node.status() == NodeStatus::Bad {
             return;
         }
+        if let Some(&until) = self.bans.get(&node.addr().ip()) {
+            if until > Instant::now() {
+                return;
+            }
+        }
+        if self.policy == NodeIdPolicy::RequireCompliant && !is_compliant_node(&node) {
+            return;
+        }
         let num_same_bits = leading_bit_count(self.node_id, node.id());
 
         // Should not add a node that has the same id as us
@@ -101,6 +152,9 @@ impl RoutingTable {
             if self.split_bucket(bucket_index) {
                 // Bucket split successfully, try to add again
                 self.bucket_node(node.clone(), num_same_bits);
+            } else if self.policy == NodeIdPolicy::PreferCompliant && is_compliant_node(&node) {
+                // Bucket can not grow, swap out a non compliant node
+                self.buckets[bucket_index].replace_node(node, |old| !is_compliant_node(old));
             }
         }
     }
@@ -133,6 +187,11 @@ impl RoutingTable {
     }
 }
 
+/// Returns true if the node id of the node is BEP 42 compliant for its address.
+fn is_compliant_node(node: &Node) -> bool {
+    node.id().is_compliant_for_ip(node.addr().ip())
+}
+
 /// Returns true if the bucket can be split.
 fn can_split_bucket(num_buckets: usize, bucket_index: usize) -> bool {
     bucket_index == num_buckets - 1 && bucket_index != MAX_BUCKETS - 1
@@ -451,6 +510,8 @@ fn index_is_in_bounds(length: usize, checked_index: Option<usize>) -> bool {
 
 #[cfg(test)]
 mod tests {
+    use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+    use std::time::Duration;
 
     use crate::util::bt;
     use crate::util::bt::NodeId;
@@ -459,6 +520,7 @@ mod tests {
     use crate::dht::routing::bucket;
     use crate::dht::routing::node::Node;
     use crate::dht::routing::table::{self, BucketContents, RoutingTable};
+    use crate::dht::security::NodeIdPolicy;
 
     // TODO: Move into use crate::util crate
     fn flip_id_bit_at_index(node_id: NodeId, index: usize) -> NodeId {
@@ -471,6 +533,28 @@ mod tests {
         id_bytes.into()
     }
 
+    #[test]
+    fn positive_ban_ip() {
+        let mut table = RoutingTable::new(table::random_node_id());
+        let addr = util_test::dummy_socket_addr_v4();
+
+        table.ban_ip(addr.ip(), Duration::from_secs(60));
+        table.add_node(Node::as_good(table::random_node_id(), addr));
+
+        assert_eq!(table.pingable_nodes().count(), 0);
+    }
+
+    #[test]
+    fn negative_ban_ip_expired() {
+        let mut table = RoutingTable::new(table::random_node_id());
+        let addr = util_test::dummy_socket_addr_v4();
+
+        table.ban_ip(addr.ip(), Duration::from_secs(0));
+        table.add_node(Node::as_good(table::random_node_id(), addr));
+
+        assert_eq!(table.pingable_nodes().count(), 1);
+    }
+
     #[test]
     fn positive_add_node_max_recursion() {
         let table_id = [1u8; bt::NODE_ID_LEN];
@@ -646,4 +730,93 @@ mod tests {
 
         assert_eq!(table.closest_nodes(table_id.into()).count(), 0);
     }
+
+    fn contains(table: &RoutingTable, node: &Node) -> bool {
+        table.closest_nodes(node.id()).any(|n| n == node)
+    }
+
+    /// Node ids that do not match their public address, all placed in the first bucket.
+    fn non_compliant_nodes() -> Vec<Node> {
+        (0..bucket::MAX_BUCKET_SIZE)
+            .map(|index| {
+                let addr =
+                    SocketAddr::new(IpAddr::V4(Ipv4Addr::new(124, 31, 75, index as u8)), 6881);
+
+                Node::as_good([0x80 | index as u8; bt::NODE_ID_LEN].into(), addr)
+            })
+            .collect()
+    }
+
+    /// Node id that matches its public address, placed in the first bucket.
+    fn compliant_node() -> Node {
+        let ip = IpAddr::V4(Ipv4Addr::new(65, 23, 51, 170));
+
+        Node::as_good(NodeId::from_ip_with_rand(ip, 22), SocketAddr::new(ip, 6881))
+    }
+
+    #[test]
+    fn positive_require_compliant_nodes() {
+        let mut table = RoutingTable::with_policy(
+            [1u8; bt::NODE_ID_LEN].into(),
+            NodeIdPolicy::RequireCompliant,
+        );
+
+        let non_compliant = non_compliant_nodes().remove(0);
+        let local = Node::as_good(
+            [0x80; bt::NODE_ID_LEN].into(),
+            util_test::dummy_socket_addr_v4(),
+        );
+        let compliant = compliant_node();
+        table.add_node(non_compliant.clone());
+        table.add_node(compliant.clone());
+        table.add_node(local.clone());
+
+        assert!(!contains(&table, &non_compliant));
+        assert!(contains(&table, &compliant));
+        // Local networks are exempt
+        assert!(contains(&table, &local));
+    }
+
+    #[test]
+    fn positive_prefer_compliant_nodes() {
+        for &(policy, expect_replaced) in &[
+            (NodeIdPolicy::AcceptAll, false),
+            (NodeIdPolicy::PreferCompliant, true),
+        ] {
+            let mut table = RoutingTable::with_policy([1u8; bt::NODE_ID_LEN].into(), policy);
+
+            for node in non_compliant_nodes() {
+                table.add_node(node);
+            }
+            // Bucket is full and can not be split any further
+            let compliant = compliant_node();
+            table.add_node(compliant.clone());
+
+            assert_eq!(contains(&table, &compliant), expect_replaced);
+            let remaining = non_compliant_nodes()
+                .iter()
+                .filter(|node| contains(&table, node))
+                .count();
+            assert_eq!(
+                remaining,
+                bucket::MAX_BUCKET_SIZE - expect_replaced as usize
+            );
+        }
+    }
+
+    #[test]
+    fn positive_set_node_id_keeps_nodes() {
+        let mut table = RoutingTable::new([1u8; bt::NODE_ID_LEN].into());
+        for node in non_compliant_nodes() {
+            table.add_node(node);
+        }
+
+        let new_id: NodeId = [0x40u8; bt::NODE_ID_LEN].into();
+        table.set_node_id(new_id);
+
+        assert_eq!(table.node_id(), new_id);
+        for node in non_compliant_nodes() {
+            assert!(contains(&table, &node));
+        }
+    }
 }
@@ -0,0 +1,121 @@
+use std::error::Error;
+use std::fmt;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use futures::channel::mpsc::UnboundedReceiver;
+use futures::{Future, Stream};
+
+use crate::dht::message::error::ErrorCode;
+use crate::util::bt::{InfoHash, NodeId};
+
+/// Info hashes sampled from the storage of a remote node, see BEP 51.
+///
+/// See http://www.bittorrent.org/beps/bep_0051.html.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SampleResult {
+    interval: Duration,
+    num: usize,
+    samples: Vec<InfoHash>,
+    nodes: Vec<(NodeId, SocketAddr)>,
+}
+
+impl SampleResult {
+    pub(crate) fn new(
+        interval: Duration,
+        num: usize,
+        samples: Vec<InfoHash>,
+        nodes: Vec<(NodeId, SocketAddr)>,
+    ) -> SampleResult {
+        SampleResult {
+            interval: interval,
+            num: num,
+            samples: samples,
+            nodes: nodes,
+        }
+    }
+
+    /// Time to wait before sampling the node again.
+    ///
+    /// Zero if the node did not tell us, which is the case for nodes not supporting BEP 51.
+    pub fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    /// Total number of info hashes the node is storing.
+    pub fn num(&self) -> usize {
+        self.num
+    }
+
+    /// Info hashes sampled by the node.
+    pub fn samples(&self) -> &[InfoHash] {
+        &self.samples
+    }
+
+    /// Nodes close to the target we sampled with, to continue sampling the DHT with.
+    pub fn nodes(&self) -> &[(NodeId, SocketAddr)] {
+        &self.nodes
+    }
+}
+
+/// Error sampling the info hashes of a remote node.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SampleError {
+    /// Node responded with an error message.
+    Remote { code: ErrorCode, message: String },
+    /// Node did not respond in time.
+    Timeout,
+    /// Node asked us to wait the given amount of time before sampling it again.
+    TooEarly(Duration),
+    /// DHT shut down before the node responded.
+    Shutdown,
+}
+
+impl fmt::Display for SampleError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            &SampleError::Remote { code, ref message } => {
+                write!(f, "Node Responded With Error {:?}: {}", code, message)
+            }
+            &SampleError::Timeout => f.write_str("Node Did Not Respond In Time"),
+            &SampleError::TooEarly(remaining) => write!(
+                f,
+                "Node Can Not Be Sampled Again For {} Seconds",
+                remaining.as_secs()
+            ),
+            &SampleError::Shutdown => f.write_str("DHT Shut Down Before The Node Responded"),
+        }
+    }
+}
+
+impl Error for SampleError {}
+
+// ----------------------------------------------------------------------------//
+
+/// Future resolving to the info hashes sampled from a remote node.
+pub struct SampleInfoHashes {
+    recv: UnboundedReceiver<Result<SampleResult, SampleError>>,
+}
+
+impl SampleInfoHashes {
+    pub(crate) fn new(
+        recv: UnboundedReceiver<Result<SampleResult, SampleError>>,
+    ) -> SampleInfoHashes {
+        SampleInfoHashes { recv: recv }
+    }
+}
+
+impl Future for SampleInfoHashes {
+    type Output = Result<SampleResult, SampleError>;
+
+    fn poll(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<SampleResult, SampleError>> {
+        Pin::new(&mut self.recv)
+            .poll_next(cx)
+            .map(|opt_result| opt_result.unwrap_or(Err(SampleError::Shutdown)))
+    }
+}
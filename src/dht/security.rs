@@ -1,10 +1,9 @@
-// TODO: Remove this when we actually use the security module.
-#![allow(unused)]
-
-use std::net::Ipv4Addr;
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
 
 use crate::util::bt::{self, NodeId};
 use crate::util::convert;
+use crate::util::sha::ShaHash;
 use crc::crc32;
 use rand;
 
@@ -13,18 +12,45 @@ const IPV6_MASK: u64 = 0x0103070F1F3F7FFF;
 
 const CRC32C_ARG_SLICE_SIZE: usize = 8;
 
-// TODO: Add IPv6 support, only when proper unit tests have been constructed
+/// Number of nodes that have to agree on an external ip before we switch over to it.
+const EXTERNAL_IP_MIN_VOTES: usize = 5;
+/// Number of voters we remember before starting a new round of votes.
+const EXTERNAL_IP_MAX_VOTERS: usize = 50;
 
-// ----------------------------------------------------------------------------//
+/// Policy for admitting nodes into our routing table based on BEP 42 compliance.
+///
+/// Nodes on local networks are always considered compliant.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum NodeIdPolicy {
+    /// Admit nodes regardless of their node id.
+    AcceptAll,
+    /// Admit any node, but let compliant nodes replace non compliant nodes in full buckets.
+    PreferCompliant,
+    /// Only admit nodes with a compliant node id.
+    RequireCompliant,
+}
 
-/// Generates an ipv4 address compliant node id.
-pub fn generate_compliant_id_ipv4(addr: Ipv4Addr) -> NodeId {
-    let masked_ipv4_be = mask_ipv4_be(addr);
-    let rand = rand::random::<u8>();
+impl ShaHash {
+    /// Generate a BEP 42 compliant node id for the given external ip.
+    ///
+    /// Only the low 3 bits of rand are mixed in to the prefix of the node id, rand as a
+    /// whole ends up as the last byte of the node id and the remaining bytes are random.
+    pub fn from_ip_with_rand(ip: IpAddr, rand: u8) -> NodeId {
+        let (masked_ip_be, num_octets) = mask_ip_be(ip);
 
-    NodeId::from(generate_compliant_id(masked_ipv4_be as u64, 4, rand))
+        NodeId::from(generate_compliant_id(masked_ip_be, num_octets, rand))
+    }
+
+    /// Whether this node id is BEP 42 compliant for the given ip.
+    ///
+    /// Ips on local networks are exempt from the check.
+    pub fn is_compliant_for_ip(&self, ip: IpAddr) -> bool {
+        is_security_compliant_exempt(ip) || is_generated_from_ip(ip, *self)
+    }
 }
 
+// ----------------------------------------------------------------------------//
+
 /// Generates an ip address compliant node id.
 fn generate_compliant_id(masked_ip_be: u64, num_octets: usize, rand: u8) -> [u8; bt::NODE_ID_LEN] {
     let r = rand & 0x07;
@@ -49,22 +75,30 @@ fn generate_compliant_id(masked_ip_be: u64, num_octets: usize, rand: u8) -> [u8;
 
 // ----------------------------------------------------------------------------//
 
-/// Compares the given ipv4 address against the given node id to see if the node id is valid.
-pub fn is_compliant_ipv4_addr(addr: Ipv4Addr, id: NodeId) -> bool {
-    if is_security_compliant_ipv4_exempt(addr) {
-        return true;
-    }
-    let masked_ip_be = mask_ipv4_be(addr) as u64;
+/// Whether the node id was generated from the given ip, local networks are not exempt.
+///
+/// Used for our own node id, which should match our external ip no matter where it is.
+pub fn is_generated_from_ip(ip: IpAddr, id: NodeId) -> bool {
+    let (masked_ip_be, num_octets) = mask_ip_be(ip);
 
-    is_compliant_addr(masked_ip_be, 4, id)
+    is_compliant_addr(masked_ip_be, num_octets, id)
 }
 
-/// Checks to see if the given ipv4 address is exempt from a security check.
-fn is_security_compliant_ipv4_exempt(addr: Ipv4Addr) -> bool {
-    // TODO: Since we are not using this module yet, we dont have to use the ip feature gate which is not stable yet.
-
-    false
-    // addr.is_loopback() || addr.is_private() || addr.is_link_local()
+/// Checks to see if the given ip address is exempt from a security check.
+fn is_security_compliant_exempt(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(v4_addr) => {
+            v4_addr.is_loopback() || v4_addr.is_private() || v4_addr.is_link_local()
+        }
+        IpAddr::V6(v6_addr) => {
+            // Unique local (fc00::/7) and link local (fe80::/10) addresses
+            let first_segment = v6_addr.segments()[0];
+
+            v6_addr.is_loopback()
+                || (first_segment & 0xFE00) == 0xFC00
+                || (first_segment & 0xFFC0) == 0xFE80
+        }
+    }
 }
 
 /// Compares the given masked ip (v4 or v6) against the given node id to see if the node if is valid.
@@ -89,7 +123,7 @@ fn is_compliant_addr(masked_ip_be: u64, num_octets: usize, id: NodeId) -> bool {
     let rand_masked_ip = masked_ip_be | ((r as u64) << (ip_bits_used - 3));
 
     // Move the rand_masked_ip bytes over to an array for running through crc32c
-    let mut rand_masked_ip_bytes = convert::eight_bytes_to_array(rand_masked_ip);
+    let rand_masked_ip_bytes = convert::eight_bytes_to_array(rand_masked_ip);
     let starting_byte = rand_masked_ip_bytes.len() - num_octets;
 
     // Official spec says to store the rand_masked_ip in a 64 bit integer (8 byte array) and hash
@@ -99,7 +133,7 @@ fn is_compliant_addr(masked_ip_be: u64, num_octets: usize, id: NodeId) -> bool {
 
     // TODO: Not sure if this checksum uses a constant internally that depends on endiannes of computer
     // (this sentence is most likely stupid in more than one way).
-    let crc32c_result = crc32::checksum_castagnoli(&rand_masked_ip_bytes[4..]);
+    let crc32c_result = crc32::checksum_castagnoli(&rand_masked_ip_bytes[starting_byte..]);
 
     is_compliant_id(crc32c_result, id_bytes)
 }
@@ -129,9 +163,112 @@ fn mask_ipv4_be(addr: Ipv4Addr) -> u32 {
     ip_be & IPV4_MASK
 }
 
+/// Perform the initial mask of the first 8 octets of an ipv6 address.
+fn mask_ipv6_be(addr: Ipv6Addr) -> u64 {
+    let octets = addr.octets();
+    let mut prefix_bytes = [0u8; 8];
+    prefix_bytes.copy_from_slice(&octets[..8]);
+
+    u64::from_be_bytes(prefix_bytes) & IPV6_MASK
+}
+
+/// Perform the initial mask of an ip address, returning the number of octets used.
+fn mask_ip_be(ip: IpAddr) -> (u64, usize) {
+    match ip {
+        IpAddr::V4(v4_addr) => (mask_ipv4_be(v4_addr) as u64, 4),
+        IpAddr::V6(v6_addr) => (mask_ipv6_be(v6_addr), 8),
+    }
+}
+
+// ----------------------------------------------------------------------------//
+
+/// Tallies the external ips that remote nodes report seeing our messages come from.
+pub struct ExternalIpVotes {
+    current: Option<IpAddr>,
+    votes: HashMap<IpAddr, IpAddr>,
+}
+
+impl ExternalIpVotes {
+    /// Create a new ExternalIpVotes, optionally starting out with a known external ip.
+    pub fn new(current: Option<IpAddr>) -> ExternalIpVotes {
+        ExternalIpVotes {
+            current: current,
+            votes: HashMap::new(),
+        }
+    }
+
+    /// Create a new ExternalIpVotes continuing the votes of a previous session.
+    pub fn with_votes<I>(current: Option<IpAddr>, votes: I) -> ExternalIpVotes
+    where
+        I: IntoIterator<Item = (IpAddr, IpAddr)>,
+    {
+        ExternalIpVotes {
+            current: current,
+            votes: votes.into_iter().collect(),
+        }
+    }
+
+    /// External ip that voters last agreed on, or that we started out with.
+    pub fn current(&self) -> Option<IpAddr> {
+        self.current
+    }
+
+    /// Votes cast since the voters last agreed on an ip.
+    pub fn votes<'a>(&'a self) -> impl Iterator<Item = (IpAddr, IpAddr)> + 'a {
+        self.votes.iter().map(|(&voter, &ip)| (voter, ip))
+    }
+
+    /// Record the ip that the given voter saw us as, one vote is kept per voter ip.
+    ///
+    /// Votes are not kept per voter address, a single host answering from many ports would
+    /// otherwise be enough to move our external ip.
+    ///
+    /// Returns the new external ip if enough voters agreed on an ip other than our current one.
+    pub fn add_vote(&mut self, voter: IpAddr, ip: IpAddr) -> Option<IpAddr> {
+        self.votes.insert(voter, ip);
+
+        let mut tally: HashMap<IpAddr, usize> = HashMap::new();
+        for &ip in self.votes.values() {
+            *tally.entry(ip).or_insert(0) += 1;
+        }
+        let current_votes = self
+            .current
+            .and_then(|ip| tally.get(&ip).cloned())
+            .unwrap_or(0);
+        let opt_winner = tally
+            .into_iter()
+            .filter(|&(ip, count)| {
+                Some(ip) != self.current && count >= EXTERNAL_IP_MIN_VOTES && count > current_votes
+            })
+            .max_by_key(|&(_, count)| count);
+
+        if let Some((ip, _)) = opt_winner {
+            self.current = Some(ip);
+            self.votes.clear();
+
+            Some(ip)
+        } else {
+            if self.votes.len() >= EXTERNAL_IP_MAX_VOTERS {
+                self.votes.clear();
+            }
+
+            None
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use std::net::Ipv4Addr;
+    use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+    use crate::util::bt::NodeId;
+
+    use crate::dht::security::{ExternalIpVotes, EXTERNAL_IP_MIN_VOTES};
+
+    /// Ip of a distinct voter for each index.
+    fn voter(index: usize) -> IpAddr {
+        Ipv4Addr::new(1, 1, 1, index as u8).into()
+    }
 
     const IPV4_ONE: (u8, u8, u8, u8) = (124, 31, 75, 21);
     const IPV4_ONE_RAND: u8 = 1;
@@ -382,4 +519,136 @@ mod tests {
         let masked_ip_be = super::mask_ipv4_be(ip_addr) as u64;
         assert!(super::is_compliant_addr(masked_ip_be, 4, id));
     }
+
+    #[test]
+    fn positive_from_ip_with_rand_test_vectors() {
+        let vectors = [
+            (IPV4_ONE, IPV4_ONE_RAND, IPV4_ONE_BITS),
+            (IPV4_TWO, IPV4_TWO_RAND, IPV4_TWO_BITS),
+            (IPV4_THREE, IPV4_THREE_RAND, IPV4_THREE_BITS),
+            (IPV4_FOUR, IPV4_FOUR_RAND, IPV4_FOUR_BITS),
+            (IPV4_FIVE, IPV4_FIVE_RAND, IPV4_FIVE_BITS),
+        ];
+
+        for &(ip, rand, bits) in vectors.iter() {
+            let ip = IpAddr::V4(Ipv4Addr::new(ip.0, ip.1, ip.2, ip.3));
+            let node_id = NodeId::from_ip_with_rand(ip, rand);
+            let id_bytes: [u8; 20] = node_id.into();
+
+            assert_eq!(id_bytes[0], bits.0);
+            assert_eq!(id_bytes[1], bits.1);
+            assert_eq!(id_bytes[2] & 0xF8, bits.2);
+            assert_eq!(id_bytes[19], rand);
+
+            assert!(node_id.is_compliant_for_ip(ip));
+            assert!(super::is_generated_from_ip(ip, node_id));
+        }
+    }
+
+    #[test]
+    fn positive_from_ip_with_rand_ipv6() {
+        let ip = IpAddr::V6("2001:db8:85a3::8a2e:370:7334".parse::<Ipv6Addr>().unwrap());
+        let node_id = NodeId::from_ip_with_rand(ip, 0xAB);
+
+        assert!(node_id.is_compliant_for_ip(ip));
+        assert!(!node_id.is_compliant_for_ip(IpAddr::V6("2a00:1450::1".parse().unwrap())));
+    }
+
+    #[test]
+    fn positive_is_compliant_local_exempt() {
+        let node_id: NodeId = [0u8; 20].into();
+
+        for &ip in &[
+            "127.0.0.1",
+            "10.0.0.1",
+            "192.168.1.1",
+            "172.16.0.1",
+            "169.254.0.1",
+            "::1",
+            "fd00::1",
+            "fe80::1",
+        ] {
+            let ip: IpAddr = ip.parse().unwrap();
+
+            assert!(node_id.is_compliant_for_ip(ip));
+            assert!(!super::is_generated_from_ip(ip, node_id));
+        }
+    }
+
+    #[test]
+    fn negative_is_compliant_wrong_ip() {
+        let ip_one = IpAddr::V4(Ipv4Addr::new(
+            IPV4_ONE.0, IPV4_ONE.1, IPV4_ONE.2, IPV4_ONE.3,
+        ));
+        let ip_two = IpAddr::V4(Ipv4Addr::new(
+            IPV4_TWO.0, IPV4_TWO.1, IPV4_TWO.2, IPV4_TWO.3,
+        ));
+
+        let node_id = NodeId::from_ip_with_rand(ip_one, IPV4_ONE_RAND);
+        assert!(!node_id.is_compliant_for_ip(ip_two));
+
+        // Changing the rand byte invalidates the prefix
+        let mut id_bytes: [u8; 20] = node_id.into();
+        id_bytes[19] ^= 0x01;
+        assert!(!NodeId::from(id_bytes).is_compliant_for_ip(ip_one));
+    }
+
+    #[test]
+    fn positive_external_ip_votes_agree() {
+        let ip: IpAddr = "124.31.75.21".parse().unwrap();
+        let mut votes = ExternalIpVotes::new(None);
+
+        for index in 1..EXTERNAL_IP_MIN_VOTES {
+            assert_eq!(votes.add_vote(voter(index), ip), None);
+        }
+
+        let voter = voter(EXTERNAL_IP_MIN_VOTES);
+        assert_eq!(votes.add_vote(voter, ip), Some(ip));
+        // Agreeing with our current ip changes nothing
+        assert_eq!(votes.add_vote(voter, ip), None);
+    }
+
+    #[test]
+    fn negative_external_ip_votes_single_voter() {
+        let current: IpAddr = "124.31.75.21".parse().unwrap();
+        let other: IpAddr = "21.75.31.124".parse().unwrap();
+        let mut votes = ExternalIpVotes::new(Some(current));
+
+        // One node voting over and over only counts once
+        for _ in 0..(EXTERNAL_IP_MIN_VOTES * 2) {
+            assert_eq!(votes.add_vote(voter(1), other), None);
+        }
+    }
+
+    #[test]
+    fn negative_external_ip_votes_single_ip_many_ports() {
+        let current: IpAddr = "124.31.75.21".parse().unwrap();
+        let other: IpAddr = "21.75.31.124".parse().unwrap();
+        let mut votes = ExternalIpVotes::new(Some(current));
+
+        // Voters are told apart by their ip, answering from other ports gains nothing
+        for port in 0..(EXTERNAL_IP_MIN_VOTES * 2) {
+            let voter = SocketAddr::new(voter(1), 6881 + port as u16);
+            assert_eq!(votes.add_vote(voter.ip(), other), None);
+        }
+        assert_eq!(votes.current(), Some(current));
+        assert_eq!(votes.votes().count(), 1);
+    }
+
+    #[test]
+    fn positive_external_ip_votes_continued() {
+        let ip: IpAddr = "124.31.75.21".parse().unwrap();
+        let saved: Vec<(IpAddr, IpAddr)> = (1..EXTERNAL_IP_MIN_VOTES)
+            .map(|index| (voter(index), ip))
+            .collect();
+        let mut votes = ExternalIpVotes::with_votes(None, saved.clone());
+
+        let mut continued: Vec<(IpAddr, IpAddr)> = votes.votes().collect();
+        continued.sort();
+        assert_eq!(continued, saved);
+
+        // Votes of the previous session count towards the new one
+        assert_eq!(votes.add_vote(voter(EXTERNAL_IP_MIN_VOTES), ip), Some(ip));
+        assert_eq!(votes.current(), Some(ip));
+    }
 }
@@ -0,0 +1,524 @@
+use std::error::Error;
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+
+use chrono::{DateTime, Duration as ChronoDuration, TimeZone, Utc};
+
+use crate::dht::bencode::{Bencode, BencodeConvert, BencodeConvertError};
+use crate::dht::message::compact_info;
+use crate::dht::routing::node::Node;
+use crate::dht::routing::table::RoutingTable;
+use crate::util::bt::NodeId;
+use crate::util::convert;
+
+const NODE_ID_KEY: &'static str = "id";
+const EXTERNAL_IP_KEY: &'static str = "ip";
+const NODES_KEY: &'static str = "nodes";
+const VOTES_KEY: &'static str = "votes";
+const ROOT_ID_KEY: &'static str = "root";
+
+const NODE_ADDR_KEY: &'static str = "addr";
+const NODE_LAST_SEEN_KEY: &'static str = "seen";
+const VOTER_KEY: &'static str = "voter";
+
+/// Saved state of our node within the DHT, letting us skip the bootstrap when restarting.
+///
+/// Holds our node id, the nodes of our routing table along with when we last heard from
+/// them, and the votes remote nodes cast on our external ip. The state can be persisted
+/// as bencode with `DhtState::to_bytes` and loaded back with `DhtState::from_bytes`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DhtState {
+    node_id: NodeId,
+    opt_external_ip: Option<IpAddr>,
+    nodes: Vec<SavedNode>,
+    votes: Vec<(IpAddr, IpAddr)>,
+}
+
+/// Node from our routing table, last seen times are kept to the second.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+struct SavedNode {
+    id: NodeId,
+    addr: SocketAddr,
+    last_seen: DateTime<Utc>,
+}
+
+impl DhtState {
+    /// Create a new DhtState from our routing table, keeping its good and questionable nodes.
+    pub(crate) fn new<I>(
+        table: &RoutingTable,
+        opt_external_ip: Option<IpAddr>,
+        votes: I,
+    ) -> DhtState
+    where
+        I: IntoIterator<Item = (IpAddr, IpAddr)>,
+    {
+        let nodes = table
+            .pingable_nodes()
+            .filter_map(|node| {
+                let opt_last_seen = node
+                    .last_seen()
+                    .and_then(|last_seen| Utc.timestamp_opt(last_seen.timestamp(), 0).single());
+
+                opt_last_seen.map(|last_seen| SavedNode {
+                    id: node.id(),
+                    addr: node.addr(),
+                    last_seen: last_seen,
+                })
+            })
+            .collect();
+
+        DhtState {
+            node_id: table.node_id(),
+            opt_external_ip: opt_external_ip,
+            nodes: nodes,
+            votes: votes.into_iter().collect(),
+        }
+    }
+
+    /// Create a new DhtState holding only our identity.
+    pub(crate) fn empty(node_id: NodeId, opt_external_ip: Option<IpAddr>) -> DhtState {
+        DhtState {
+            node_id: node_id,
+            opt_external_ip: opt_external_ip,
+            nodes: Vec::new(),
+            votes: Vec::new(),
+        }
+    }
+
+    /// Add the nodes of the other state to our own, used to save both tables of a dual stack.
+    pub(crate) fn merge_nodes(mut self, other: DhtState) -> DhtState {
+        self.nodes.extend(other.nodes);
+
+        self
+    }
+
+    /// Our node id when the state was saved.
+    pub fn node_id(&self) -> NodeId {
+        self.node_id
+    }
+
+    /// External ip that remote nodes agreed on when the state was saved, if any.
+    pub fn external_ip(&self) -> Option<IpAddr> {
+        self.opt_external_ip
+    }
+
+    /// Number of nodes saved from our routing table.
+    pub fn num_nodes(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// Addresses of the nodes saved from our routing table.
+    pub fn node_addrs<'a>(&'a self) -> impl Iterator<Item = SocketAddr> + 'a {
+        self.nodes.iter().map(|node| node.addr)
+    }
+
+    /// Saved nodes of the given address family that we heard from within the max age.
+    pub(crate) fn nodes(&self, ipv6: bool, max_age: Duration) -> Vec<Node> {
+        let max_age = ChronoDuration::from_std(max_age).unwrap_or(ChronoDuration::max_value());
+        let now = Utc::now();
+
+        self.nodes
+            .iter()
+            .filter(|node| node.addr.is_ipv6() == ipv6 && now - node.last_seen <= max_age)
+            .map(|node| Node::as_responded(node.id, node.addr, node.last_seen))
+            .collect()
+    }
+
+    /// Saved votes cast by voters of the given address family.
+    pub(crate) fn votes<'a>(
+        &'a self,
+        ipv6: bool,
+    ) -> impl Iterator<Item = (IpAddr, IpAddr)> + 'a {
+        self.votes
+            .iter()
+            .filter(move |&&(voter, _)| voter.is_ipv6() == ipv6)
+            .cloned()
+    }
+
+    /// Encode the state as bencode.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let node_addrs: Vec<Vec<u8>> = self
+            .nodes
+            .iter()
+            .map(|node| compact_info::compact_addr(node.addr))
+            .collect();
+        let vote_parts: Vec<(Vec<u8>, Vec<u8>)> = self
+            .votes
+            .iter()
+            .map(|&(voter, ip)| (compact_ip(voter), compact_ip(ip)))
+            .collect();
+        let opt_external_ip = self.opt_external_ip.map(compact_ip);
+
+        let nodes = self
+            .nodes
+            .iter()
+            .zip(node_addrs.iter())
+            .map(|(node, addr)| {
+                dht_ben_map! {
+                    NODE_ID_KEY => dht_ben_bytes!(node.id.as_ref()),
+                    NODE_ADDR_KEY => dht_ben_bytes!(&addr[..]),
+                    NODE_LAST_SEEN_KEY => dht_ben_int!(node.last_seen.timestamp())
+                }
+            })
+            .collect();
+        let votes = vote_parts
+            .iter()
+            .map(|&(ref voter, ref ip)| {
+                dht_ben_map! {
+                    VOTER_KEY => dht_ben_bytes!(&voter[..]),
+                    EXTERNAL_IP_KEY => dht_ben_bytes!(&ip[..])
+                }
+            })
+            .collect();
+
+        let mut state = dht_ben_map! {
+            NODE_ID_KEY => dht_ben_bytes!(self.node_id.as_ref()),
+            NODES_KEY => Bencode::List(nodes),
+            VOTES_KEY => Bencode::List(votes)
+        };
+        if let (&mut Bencode::Dict(ref mut map), Some(ip)) = (&mut state, opt_external_ip.as_ref())
+        {
+            map.insert(EXTERNAL_IP_KEY.as_bytes(), dht_ben_bytes!(&ip[..]));
+        }
+
+        state.encode()
+    }
+
+    /// Decode a state previously encoded with `DhtState::to_bytes`.
+    pub fn from_bytes(bytes: &[u8]) -> Result<DhtState, StateError> {
+        let bencode = Bencode::decode(bytes).map_err(|_| StateError::InvalidBencode)?;
+        let validate = StateValidate;
+
+        let root = validate.convert_dict(&bencode, ROOT_ID_KEY)?;
+        let node_id = node_id_from_bytes(validate.lookup_and_convert_bytes(root, NODE_ID_KEY)?)?;
+        let opt_external_ip = match root.lookup(EXTERNAL_IP_KEY.as_bytes()) {
+            Some(ip) => Some(ip_from_bytes(validate.convert_bytes(ip, EXTERNAL_IP_KEY)?)?),
+            None => None,
+        };
+
+        let mut nodes = Vec::new();
+        for node in validate.lookup_and_convert_list(root, NODES_KEY)? {
+            let node = validate.convert_dict(node, NODES_KEY)?;
+
+            let id = node_id_from_bytes(validate.lookup_and_convert_bytes(node, NODE_ID_KEY)?)?;
+            let addr = addr_from_bytes(validate.lookup_and_convert_bytes(node, NODE_ADDR_KEY)?)?;
+            let last_seen = Utc
+                .timestamp_opt(
+                    validate.lookup_and_convert_int(node, NODE_LAST_SEEN_KEY)?,
+                    0,
+                )
+                .single()
+                .ok_or(StateError::InvalidValue(NODE_LAST_SEEN_KEY.to_owned()))?;
+
+            nodes.push(SavedNode {
+                id: id,
+                addr: addr,
+                last_seen: last_seen,
+            });
+        }
+
+        let mut votes = Vec::new();
+        for vote in validate.lookup_and_convert_list(root, VOTES_KEY)? {
+            let vote = validate.convert_dict(vote, VOTES_KEY)?;
+
+            let voter = ip_from_bytes(validate.lookup_and_convert_bytes(vote, VOTER_KEY)?)?;
+            let ip = ip_from_bytes(validate.lookup_and_convert_bytes(vote, EXTERNAL_IP_KEY)?)?;
+
+            votes.push((voter, ip));
+        }
+
+        Ok(DhtState {
+            node_id: node_id,
+            opt_external_ip: opt_external_ip,
+            nodes: nodes,
+            votes: votes,
+        })
+    }
+}
+
+fn compact_ip(ip: IpAddr) -> Vec<u8> {
+    match ip {
+        IpAddr::V4(v4_ip) => convert::ipv4_to_bytes_be(v4_ip).to_vec(),
+        IpAddr::V6(v6_ip) => convert::ipv6_to_bytes_be(v6_ip).to_vec(),
+    }
+}
+
+fn node_id_from_bytes(bytes: &[u8]) -> Result<NodeId, StateError> {
+    NodeId::from_hash(bytes).map_err(|_| StateError::InvalidValue(NODE_ID_KEY.to_owned()))
+}
+
+fn ip_from_bytes(bytes: &[u8]) -> Result<IpAddr, StateError> {
+    if bytes.len() == 4 {
+        let mut v4_bytes = [0u8; 4];
+        v4_bytes.copy_from_slice(bytes);
+
+        Ok(IpAddr::V4(convert::bytes_be_to_ipv4(v4_bytes)))
+    } else if bytes.len() == 16 {
+        let mut v6_bytes = [0u8; 16];
+        v6_bytes.copy_from_slice(bytes);
+
+        Ok(IpAddr::V6(convert::bytes_be_to_ipv6(v6_bytes)))
+    } else {
+        Err(StateError::InvalidValue(EXTERNAL_IP_KEY.to_owned()))
+    }
+}
+
+fn addr_from_bytes(bytes: &[u8]) -> Result<SocketAddr, StateError> {
+    if bytes.len() == 6 {
+        let mut v4_bytes = [0u8; 6];
+        v4_bytes.copy_from_slice(bytes);
+
+        Ok(SocketAddr::V4(convert::bytes_be_to_sock_v4(v4_bytes)))
+    } else if bytes.len() == 18 {
+        let mut v6_bytes = [0u8; 18];
+        v6_bytes.copy_from_slice(bytes);
+
+        Ok(SocketAddr::V6(convert::bytes_be_to_sock_v6(v6_bytes)))
+    } else {
+        Err(StateError::InvalidValue(NODE_ADDR_KEY.to_owned()))
+    }
+}
+
+// ----------------------------------------------------------------------------//
+
+/// Error decoding a saved DhtState.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum StateError {
+    /// State is not valid bencode.
+    InvalidBencode,
+    /// State is missing the given value or the value is malformed.
+    InvalidValue(String),
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            &StateError::InvalidBencode => f.write_str("State Is Not Valid Bencode"),
+            &StateError::InvalidValue(ref key) => {
+                write!(f, "State Has A Missing Or Malformed Value For {}", key)
+            }
+        }
+    }
+}
+
+impl Error for StateError {}
+
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+struct StateValidate;
+
+impl BencodeConvert for StateValidate {
+    type Error = StateError;
+
+    fn handle_error(&self, error: BencodeConvertError) -> StateError {
+        StateError::InvalidValue(error.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{IpAddr, SocketAddr, UdpSocket};
+    use std::time::Duration;
+
+    use chrono::Duration as ChronoDuration;
+
+    use crate::dht::routing::bucket;
+    use crate::dht::routing::node::Node;
+    use crate::dht::routing::table::{self, BucketContents, RoutingTable};
+    use crate::dht::state::{DhtState, StateError};
+    use crate::dht::{DhtBuilder, DhtEvent, Handshaker, Router};
+    use crate::util::bt::{self, InfoHash, NodeId, PeerId};
+    use crate::util::test as util_test;
+
+    const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+    struct MockHandshaker;
+
+    impl Handshaker for MockHandshaker {
+        type Metadata = ();
+
+        fn id(&self) -> PeerId {
+            [0u8; bt::PEER_ID_LEN].into()
+        }
+
+        fn port(&self) -> u16 {
+            6881
+        }
+
+        fn connect(&mut self, _: Option<PeerId>, _: InfoHash, _: SocketAddr) {}
+
+        fn metadata(&mut self, _: ()) {}
+    }
+
+    /// Restores the state on a new DHT, returning whether its router was contacted.
+    fn restore_contacts_router(state: DhtState) -> bool {
+        let router = UdpSocket::bind("127.0.0.1:0").unwrap();
+        router
+            .set_read_timeout(Some(Duration::from_millis(4000)))
+            .unwrap();
+
+        let dht = DhtBuilder::with_state(state.clone())
+            .add_router(Router::Custom(router.local_addr().unwrap()))
+            .set_source_addr("127.0.0.1:0".parse().unwrap())
+            .start_mainline(MockHandshaker)
+            .unwrap();
+        let events = dht.events();
+
+        assert_eq!(dht.status().node_id(), state.node_id());
+        loop {
+            match events.recv_timeout(Duration::from_secs(30)).unwrap() {
+                DhtEvent::BootstrapCompleted { .. } => break,
+                DhtEvent::ShuttingDown(_) => break,
+                _ => (),
+            }
+        }
+
+        let mut buffer = [0u8; 1500];
+        router.recv_from(&mut buffer).is_ok()
+    }
+
+    fn bucket_sizes(table: &RoutingTable) -> Vec<usize> {
+        table
+            .buckets()
+            .map(|bucket| match bucket {
+                BucketContents::Empty => 0,
+                BucketContents::Sorted(b) => b.pingable_nodes().count(),
+                BucketContents::Assorted(b) => b.pingable_nodes().count(),
+            })
+            .collect()
+    }
+
+    fn populated_table() -> RoutingTable {
+        let table_id = [1u8; bt::NODE_ID_LEN];
+        let mut table = RoutingTable::new(table_id.into());
+
+        // Fill the first few buckets, making sure the table splits
+        for bucket_index in 0..4 {
+            let mut node_id = table_id;
+            node_id[0] ^= 0x80 >> bucket_index;
+
+            for index in 0..bucket::MAX_BUCKET_SIZE {
+                node_id[bt::NODE_ID_LEN - 1] = index as u8;
+
+                let addr = SocketAddr::new([10, 0, bucket_index as u8, index as u8].into(), 6881);
+                table.add_node(Node::as_good(node_id.into(), addr));
+            }
+        }
+
+        table
+    }
+
+    #[test]
+    fn positive_state_round_trip() {
+        let table = populated_table();
+        let voter: IpAddr = "10.1.1.1".parse().unwrap();
+        let ip: IpAddr = "124.31.75.21".parse().unwrap();
+        let state = DhtState::new(&table, Some(ip), vec![(voter, ip)]);
+
+        let loaded = DhtState::from_bytes(&state.to_bytes()).unwrap();
+
+        assert_eq!(loaded, state);
+        assert_eq!(loaded.node_id(), table.node_id());
+        assert_eq!(loaded.external_ip(), Some(ip));
+        assert_eq!(loaded.num_nodes(), 4 * bucket::MAX_BUCKET_SIZE);
+        assert_eq!(loaded.votes(false).collect::<Vec<_>>(), vec![(voter, ip)]);
+    }
+
+    #[test]
+    fn positive_state_reconstructs_buckets() {
+        let table = populated_table();
+        let state = DhtState::new(&table, None, Vec::new());
+        let loaded = DhtState::from_bytes(&state.to_bytes()).unwrap();
+
+        let mut restored = RoutingTable::new(loaded.node_id());
+        for node in loaded.nodes(false, DAY) {
+            restored.add_node(node);
+        }
+
+        assert_eq!(bucket_sizes(&restored), bucket_sizes(&table));
+        assert_eq!(restored.buckets().count(), table::MAX_BUCKETS + 1);
+        for node in table.pingable_nodes() {
+            assert!(restored.pingable_nodes().any(|other| other == node));
+        }
+    }
+
+    #[test]
+    fn positive_state_skips_bootstrap() {
+        let table = populated_table();
+        let state = DhtState::new(&table, None, Vec::new());
+        let loaded = DhtState::from_bytes(&state.to_bytes()).unwrap();
+
+        assert!(!restore_contacts_router(loaded));
+    }
+
+    #[test]
+    fn negative_state_unresponsive_nodes_bootstrap() {
+        let mut table = RoutingTable::new([1u8; bt::NODE_ID_LEN].into());
+        let last_seen = util_test::travel_into_past(ChronoDuration::hours(1));
+        for index in 0..bucket::MAX_BUCKET_SIZE {
+            let addr = SocketAddr::new([127, 0, 0, 1].into(), 1 + index as u16);
+
+            table.add_node(Node::as_responded(
+                [2 + index as u8; bt::NODE_ID_LEN].into(),
+                addr,
+                last_seen,
+            ));
+        }
+        let state = DhtState::new(&table, None, Vec::new());
+
+        // None of the stale nodes respond, so we fall back to a full bootstrap
+        assert!(restore_contacts_router(state));
+    }
+
+    #[test]
+    fn positive_state_drops_old_nodes() {
+        let mut table = RoutingTable::new([1u8; bt::NODE_ID_LEN].into());
+        let recent = util_test::dummy_socket_addr_v4();
+        let old: SocketAddr = "10.0.0.2:6881".parse().unwrap();
+
+        table.add_node(Node::as_good([2u8; bt::NODE_ID_LEN].into(), recent));
+        table.add_node(Node::as_responded(
+            [3u8; bt::NODE_ID_LEN].into(),
+            old,
+            util_test::travel_into_past(ChronoDuration::hours(25)),
+        ));
+        let state = DhtState::new(&table, None, Vec::new());
+
+        let nodes = state.nodes(false, DAY);
+        assert_eq!(state.num_nodes(), 2);
+        assert_eq!(nodes.len(), 1);
+        assert_eq!(nodes[0].addr(), recent);
+        assert!(state.nodes(true, DAY).is_empty());
+    }
+
+    #[test]
+    fn positive_state_ipv6_nodes() {
+        let mut table = RoutingTable::new(NodeId::from([1u8; bt::NODE_ID_LEN]));
+        let addr: SocketAddr = "[::1]:6881".parse().unwrap();
+        table.add_node(Node::as_good([2u8; bt::NODE_ID_LEN].into(), addr));
+
+        let state = DhtState::new(&table, None, Vec::new());
+        let loaded = DhtState::from_bytes(&state.to_bytes()).unwrap();
+
+        assert_eq!(loaded.external_ip(), None);
+        assert_eq!(loaded.nodes(true, DAY)[0].addr(), addr);
+    }
+
+    #[test]
+    fn negative_state_invalid_bencode() {
+        assert_eq!(
+            DhtState::from_bytes(b"d2:id"),
+            Err(StateError::InvalidBencode)
+        );
+    }
+
+    #[test]
+    fn negative_state_wrong_node_addr_length() {
+        let state = b"d2:id20:aaaaaaaaaaaaaaaaaaaa5:nodesld4:addr3:abc2:id20:bbbbbbbbbbbbbbbbbbbb4:seeni0eee5:voteslee";
+
+        assert_eq!(
+            DhtState::from_bytes(&state[..]),
+            Err(StateError::InvalidValue("addr".to_owned()))
+        );
+    }
+}
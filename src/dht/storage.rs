@@ -1,14 +1,25 @@
-use std::collections::hash_map::Entry;
 use std::collections::HashMap;
-use std::net::SocketAddr;
+use std::net::{IpAddr, SocketAddr};
 
+use crate::dht::bloom::BloomFilter;
+use crate::dht::item::{ImmutableItem, MutableItem};
 use crate::util::bt::InfoHash;
+use crate::util::sha::ShaHash;
 use chrono::{DateTime, Duration, Utc};
 
-const MAX_ITEMS_STORED: usize = 500;
+/// Maximum number of contacts we store across all InfoHashs.
+const MAX_ITEMS_STORED: usize = 4000;
+/// Maximum number of contacts we store for a single InfoHash, the oldest contact is evicted
+/// to make room for a new one.
+const MAX_ITEMS_PER_INFO_HASH: usize = 100;
+
+/// Maximum number of bytes the compact contacts we give out for an InfoHash can take up, which
+/// leaves room for the rest of a typical get peers response within a single udp packet.
+const MAX_VALUES_BYTES: usize = 800;
 
 /// Manages storage and expiration of contact information for a number of InfoHashs.
 pub struct AnnounceStorage {
+    // Contacts of each InfoHash, in the order they were announced
     storage: HashMap<InfoHash, Vec<AnnounceItem>>,
     expires: Vec<ItemExpiration>,
 }
@@ -23,14 +34,22 @@ impl AnnounceStorage {
     }
 
     /// Returns true if the item was added/it's existing expiration updated, false otherwise.
-    pub fn add_item(&mut self, info_hash: InfoHash, address: SocketAddr) -> bool {
-        self.add(info_hash, address, Utc::now())
+    ///
+    /// Whether the contact is a seed is updated for contacts we already have.
+    pub fn add_item(&mut self, info_hash: InfoHash, address: SocketAddr, seed: bool) -> bool {
+        self.add(info_hash, address, seed, Utc::now())
     }
 
-    fn add(&mut self, info_hash: InfoHash, address: SocketAddr, curr_time: DateTime<Utc>) -> bool {
+    fn add(
+        &mut self,
+        info_hash: InfoHash,
+        address: SocketAddr,
+        seed: bool,
+        curr_time: DateTime<Utc>,
+    ) -> bool {
         // Clear out any old contacts that we have stored
         self.remove_expired_items(curr_time);
-        let item = AnnounceItem::new(info_hash, address);
+        let item = AnnounceItem::new(info_hash, address, seed, curr_time);
         let item_expiration = item.expiration();
 
         // Check if we already have the item and want to update it's expiration
@@ -50,26 +69,89 @@ impl AnnounceStorage {
         }
     }
 
-    /// Invoke the closure once for each contact for the given InfoHash.
-    pub fn find_items<F>(&mut self, info_hash: &InfoHash, item_func: F)
-    where
-        F: FnMut(SocketAddr),
-    {
-        self.find(info_hash, item_func, Utc::now())
+    /// Random subset of the contacts for the given InfoHash, small enough to be given out
+    /// in a single get peers response.
+    pub fn find_items(&mut self, info_hash: &InfoHash) -> Vec<SocketAddr> {
+        self.find(info_hash, Utc::now())
     }
 
-    fn find<F>(&mut self, info_hash: &InfoHash, mut item_func: F, curr_time: DateTime<Utc>)
-    where
-        F: FnMut(SocketAddr),
-    {
+    fn find(&mut self, info_hash: &InfoHash, curr_time: DateTime<Utc>) -> Vec<SocketAddr> {
         // Clear out any old contacts that we have stored
         self.remove_expired_items(curr_time);
 
+        let mut addresses: Vec<SocketAddr> = self
+            .storage
+            .get(info_hash)
+            .map(|items| items.iter().map(|item| item.address()).collect())
+            .unwrap_or_default();
+        crate::util::fisher_shuffle_copy(&mut addresses);
+
+        let mut values_bytes = 0;
+        addresses
+            .into_iter()
+            .take_while(|address| {
+                values_bytes += compact_value_len(address);
+
+                values_bytes <= MAX_VALUES_BYTES
+            })
+            .collect()
+    }
+
+    /// Bloom filters of the seeds and downloaders for the given InfoHash, in that order.
+    pub fn scrape_items(&mut self, info_hash: &InfoHash) -> (BloomFilter, BloomFilter) {
+        self.scrape(info_hash, Utc::now())
+    }
+
+    fn scrape(
+        &mut self,
+        info_hash: &InfoHash,
+        curr_time: DateTime<Utc>,
+    ) -> (BloomFilter, BloomFilter) {
+        // Clear out any old contacts that we have stored
+        self.remove_expired_items(curr_time);
+
+        let (mut seeds, mut downloaders) = (BloomFilter::new(), BloomFilter::new());
         if let Some(items) = self.storage.get(info_hash) {
             for item in items {
-                item_func(item.address());
+                if item.is_seed() {
+                    seeds.insert(item.address().ip());
+                } else {
+                    downloaders.insert(item.address().ip());
+                }
             }
         }
+
+        (seeds, downloaders)
+    }
+
+    /// Number of InfoHashs we have contacts for.
+    pub fn num_info_hashes(&mut self) -> usize {
+        self.remove_expired_items(Utc::now());
+
+        self.storage.len()
+    }
+
+    /// Number of contacts we have across all InfoHashs.
+    pub fn num_items(&mut self) -> usize {
+        self.remove_expired_items(Utc::now());
+
+        self.expires.len()
+    }
+
+    /// Random sample of at most max_samples InfoHashs that we have contacts for, see BEP 51.
+    pub fn sample_info_hashes(&mut self, max_samples: usize) -> Vec<InfoHash> {
+        self.sample(max_samples, Utc::now())
+    }
+
+    fn sample(&mut self, max_samples: usize, curr_time: DateTime<Utc>) -> Vec<InfoHash> {
+        // Clear out any old contacts that we have stored
+        self.remove_expired_items(curr_time);
+
+        let mut info_hashes: Vec<InfoHash> = self.storage.keys().cloned().collect();
+        crate::util::fisher_shuffle_copy(&mut info_hashes);
+        info_hashes.truncate(max_samples);
+
+        info_hashes
     }
 
     /// Returns None if the contact could not be inserted, else, returns Some(true) if the contact was already
@@ -78,30 +160,35 @@ impl AnnounceStorage {
     fn insert_contact(&mut self, item: AnnounceItem) -> Option<bool> {
         let item_info_hash = item.info_hash();
 
-        // Check if the contact is already in our list
-        let already_in_list = if let Some(items) = self.storage.get_mut(&item_info_hash) {
-            items.iter().any(|a| a == &item)
-        } else {
-            false
-        };
-
-        // Check if we need to insert it into the list and if we have room
-        match (already_in_list, self.expires.len() < MAX_ITEMS_STORED) {
-            (false, true) => {
-                // Place it into the appropriate list
-                match self.storage.entry(item_info_hash) {
-                    Entry::Occupied(mut occ) => occ.get_mut().push(item),
-                    Entry::Vacant(vac) => {
-                        vac.insert(vec![item]);
-                    }
-                };
-
-                Some(false)
+        // Check if the contact is already in our list, moving it to the back as the newest contact
+        if let Some(items) = self.storage.get_mut(&item_info_hash) {
+            if let Some(index) = items.iter().position(|a| a == &item) {
+                items.remove(index);
+                items.push(item);
+
+                return Some(true);
             }
-            (false, false) => None,
-            (true, false) => Some(true),
-            (true, true) => Some(true),
         }
+
+        // Contacts for an InfoHash that is full replace the oldest one, so they always fit
+        let is_full = self
+            .storage
+            .get(&item_info_hash)
+            .map(|items| items.len() >= MAX_ITEMS_PER_INFO_HASH)
+            .unwrap_or(false);
+        if !is_full && self.expires.len() >= MAX_ITEMS_STORED {
+            return None;
+        }
+
+        let items = self.storage.entry(item_info_hash).or_insert_with(Vec::new);
+        if is_full {
+            let evicted = items.remove(0).expiration();
+
+            self.expires.retain(|i| i != &evicted);
+        }
+        items.push(item);
+
+        Some(false)
     }
 
     /// Prunes all expired items from the internal list.
@@ -134,17 +221,32 @@ impl AnnounceStorage {
     }
 }
 
+/// Number of bytes the contact takes up as a compact value in a bencoded list.
+fn compact_value_len(address: &SocketAddr) -> usize {
+    match address {
+        &SocketAddr::V4(_) => "6:".len() + 6,
+        &SocketAddr::V6(_) => "18:".len() + 18,
+    }
+}
+
 // ----------------------------------------------------------------------------//
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone)]
 struct AnnounceItem {
     expiration: ItemExpiration,
+    seed: bool,
 }
 
 impl AnnounceItem {
-    pub fn new(info_hash: InfoHash, address: SocketAddr) -> AnnounceItem {
+    pub fn new(
+        info_hash: InfoHash,
+        address: SocketAddr,
+        seed: bool,
+        inserted: DateTime<Utc>,
+    ) -> AnnounceItem {
         AnnounceItem {
-            expiration: ItemExpiration::new(info_hash, address),
+            expiration: ItemExpiration::new(info_hash, address, inserted),
+            seed: seed,
         }
     }
 
@@ -159,11 +261,25 @@ impl AnnounceItem {
     pub fn info_hash(&self) -> InfoHash {
         self.expiration.info_hash()
     }
+
+    pub fn is_seed(&self) -> bool {
+        self.seed
+    }
 }
 
+// Contacts are the same regardless of whether they are seeding
+impl PartialEq for AnnounceItem {
+    fn eq(&self, other: &AnnounceItem) -> bool {
+        self.expiration == other.expiration
+    }
+}
+
+impl Eq for AnnounceItem {}
+
 // ----------------------------------------------------------------------------//
 
-const EXPIRATION_TIME_HOURS: i64 = 24;
+/// Contacts have to announce again within this time to stay in our storage.
+const EXPIRATION_TIME_MINS: i64 = 30;
 
 #[derive(Debug, Clone)]
 struct ItemExpiration {
@@ -173,16 +289,20 @@ struct ItemExpiration {
 }
 
 impl ItemExpiration {
-    pub fn new(info_hash: InfoHash, address: SocketAddr) -> ItemExpiration {
+    pub fn new(
+        info_hash: InfoHash,
+        address: SocketAddr,
+        inserted: DateTime<Utc>,
+    ) -> ItemExpiration {
         ItemExpiration {
             address: address,
-            inserted: Utc::now(),
+            inserted: inserted,
             info_hash: info_hash,
         }
     }
 
     pub fn is_expired(&self, now: DateTime<Utc>) -> bool {
-        now - self.inserted >= Duration::hours(EXPIRATION_TIME_HOURS)
+        now - self.inserted >= Duration::minutes(EXPIRATION_TIME_MINS)
     }
 
     pub fn info_hash(&self) -> InfoHash {
@@ -202,14 +322,189 @@ impl PartialEq for ItemExpiration {
 
 impl Eq for ItemExpiration {}
 
+// ----------------------------------------------------------------------------//
+
+const MAX_DATA_ITEMS_STORED: usize = 500;
+const MAX_DATA_ITEMS_PER_IP: usize = 50;
+const DATA_EXPIRATION_TIME_HOURS: i64 = 2;
+
+/// Item stored on behalf of a remote node.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum StoredItem {
+    Immutable(ImmutableItem),
+    Mutable(MutableItem),
+}
+
+/// Reason an item could not be stored.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PutError {
+    /// Sequence number of the stored item did not match the expected one.
+    CasMismatch,
+    /// Sequence number is lower than that of the stored item, or equal with a different value.
+    SeqTooLow,
+    /// The remote node already stored too many items with us.
+    QuotaExceeded,
+    /// We are storing too many items already.
+    StorageFull,
+}
+
+/// Manages storage and expiration of items put by remote nodes, see BEP 44.
+pub struct ItemStorage {
+    storage: HashMap<ShaHash, (StoredItem, IpAddr, DateTime<Utc>)>,
+}
+
+impl ItemStorage {
+    /// Create a new ItemStorage object.
+    pub fn new() -> ItemStorage {
+        ItemStorage {
+            storage: HashMap::new(),
+        }
+    }
+
+    /// Store an immutable item put by the given ip, renewing its expiration if already stored.
+    pub fn put_immutable(&mut self, item: ImmutableItem, source: IpAddr) -> Result<(), PutError> {
+        self.put(
+            item.target().into(),
+            StoredItem::Immutable(item),
+            None,
+            source,
+            Utc::now(),
+        )
+    }
+
+    /// Store a mutable item put by the given ip, replacing any older version of the item.
+    ///
+    /// If cas is given, the item is only replaced if the stored item has that sequence number.
+    pub fn put_mutable(
+        &mut self,
+        item: MutableItem,
+        cas: Option<i64>,
+        source: IpAddr,
+    ) -> Result<(), PutError> {
+        self.put(
+            item.target().into(),
+            StoredItem::Mutable(item),
+            cas,
+            source,
+            Utc::now(),
+        )
+    }
+
+    fn put(
+        &mut self,
+        target: ShaHash,
+        item: StoredItem,
+        cas: Option<i64>,
+        source: IpAddr,
+        curr_time: DateTime<Utc>,
+    ) -> Result<(), PutError> {
+        // Clear out any old items that we have stored
+        self.remove_expired_items(curr_time);
+
+        if let Some(&mut (ref mut stored, _, ref mut inserted)) = self.storage.get_mut(&target) {
+            if let (&StoredItem::Mutable(ref old), &StoredItem::Mutable(ref new)) =
+                (&*stored, &item)
+            {
+                if cas.map(|cas| cas != old.seq()).unwrap_or(false) {
+                    return Err(PutError::CasMismatch);
+                } else if new.seq() < old.seq()
+                    || (new.seq() == old.seq() && new.value() != old.value())
+                {
+                    return Err(PutError::SeqTooLow);
+                }
+            }
+
+            // Quotas stay with whoever stored the item first
+            *stored = item;
+            *inserted = curr_time;
+
+            return Ok(());
+        }
+
+        if self.storage.len() >= MAX_DATA_ITEMS_STORED {
+            Err(PutError::StorageFull)
+        } else if self
+            .storage
+            .values()
+            .filter(|&&(_, ip, _)| ip == source)
+            .count()
+            >= MAX_DATA_ITEMS_PER_IP
+        {
+            Err(PutError::QuotaExceeded)
+        } else {
+            self.storage.insert(target, (item, source, curr_time));
+
+            Ok(())
+        }
+    }
+
+    /// Item stored under the given target, if any.
+    pub fn find_item(&mut self, target: &ShaHash) -> Option<&StoredItem> {
+        self.find(target, Utc::now())
+    }
+
+    fn find(&mut self, target: &ShaHash, curr_time: DateTime<Utc>) -> Option<&StoredItem> {
+        // Clear out any old items that we have stored
+        self.remove_expired_items(curr_time);
+
+        self.storage.get(target).map(|&(ref item, _, _)| item)
+    }
+
+    /// Prunes all expired items.
+    fn remove_expired_items(&mut self, curr_time: DateTime<Utc>) {
+        self.storage.retain(|_, &mut (_, _, inserted)| {
+            curr_time - inserted < Duration::hours(DATA_EXPIRATION_TIME_HOURS)
+        });
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::util::bt;
+    use crate::util::bt::{self, InfoHash};
     use crate::util::test as util_test;
 
+    use std::net::SocketAddr;
+
+    use crate::dht::bloom::BloomFilter;
+    use crate::dht::item::{ImmutableItem, MutableItem};
     use crate::dht::storage;
-    use crate::dht::storage::AnnounceStorage;
-    use chrono::Duration;
+    use crate::dht::storage::{AnnounceStorage, ItemStorage, PutError, StoredItem};
+    use chrono::{Duration, Utc};
+
+    fn scrape_addrs() -> Vec<SocketAddr> {
+        vec![
+            "10.0.0.1:6881".parse().unwrap(),
+            "10.0.0.2:6881".parse().unwrap(),
+            "[2001:db8::1]:6881".parse().unwrap(),
+        ]
+    }
+
+    fn scrape_filter(addrs: &[SocketAddr]) -> BloomFilter {
+        let mut filter = BloomFilter::new();
+        for addr in addrs {
+            filter.insert(addr.ip());
+        }
+
+        filter
+    }
+
+    fn mutable_item(seq: i64, value: &'static str) -> MutableItem {
+        MutableItem::sign(&[1u8; 32], b"", seq, &bt_ben_bytes!(value)).unwrap()
+    }
+
+    /// Fill up the announce storage completely, spreading the contacts over as few InfoHashs as
+    /// possible, the first of which is [0u8; 20].
+    fn fill_storage(announce_store: &mut AnnounceStorage, sock_addrs: &[SocketAddr]) {
+        for (index, sock_addr) in sock_addrs
+            .iter()
+            .take(storage::MAX_ITEMS_STORED)
+            .enumerate()
+        {
+            let info_hash = [(index / storage::MAX_ITEMS_PER_INFO_HASH) as u8; bt::INFO_HASH_LEN];
+
+            assert!(announce_store.add_item(info_hash.into(), *sock_addr, false));
+        }
+    }
 
     #[test]
     fn positive_add_and_retrieve_contact() {
@@ -217,28 +512,24 @@ mod tests {
         let info_hash = [0u8; bt::INFO_HASH_LEN].into();
         let sock_addr = util_test::dummy_socket_addr_v4();
 
-        assert!(announce_store.add_item(info_hash, sock_addr));
-
-        let mut items = Vec::new();
-        announce_store.find_items(&info_hash, |a| items.push(a));
-        assert_eq!(items.len(), 1);
+        assert!(announce_store.add_item(info_hash, sock_addr, false));
 
-        assert_eq!(items[0], sock_addr);
+        assert_eq!(announce_store.find_items(&info_hash), vec![sock_addr]);
     }
 
     #[test]
     fn positive_add_and_retrieve_contacts() {
         let mut announce_store = AnnounceStorage::new();
         let info_hash = [0u8; bt::INFO_HASH_LEN].into();
-        let sock_addrs = util_test::dummy_block_socket_addrs(storage::MAX_ITEMS_STORED as u16);
+        let sock_addrs =
+            util_test::dummy_block_socket_addrs(storage::MAX_ITEMS_PER_INFO_HASH as u16);
 
         for sock_addr in sock_addrs.iter() {
-            assert!(announce_store.add_item(info_hash, *sock_addr));
+            assert!(announce_store.add_item(info_hash, *sock_addr, false));
         }
 
-        let mut items = Vec::new();
-        announce_store.find_items(&info_hash, |a| items.push(a));
-        assert_eq!(items.len(), storage::MAX_ITEMS_STORED);
+        let items = announce_store.find_items(&info_hash);
+        assert_eq!(items.len(), storage::MAX_ITEMS_PER_INFO_HASH);
 
         for item in items.iter() {
             assert!(sock_addrs.iter().any(|s| s == item));
@@ -248,107 +539,393 @@ mod tests {
     #[test]
     fn positive_renew_contacts() {
         let mut announce_store = AnnounceStorage::new();
-        let info_hash = [0u8; bt::INFO_HASH_LEN].into();
         let sock_addrs =
             util_test::dummy_block_socket_addrs((storage::MAX_ITEMS_STORED + 1) as u16);
 
-        for sock_addr in sock_addrs.iter().take(storage::MAX_ITEMS_STORED) {
-            assert!(announce_store.add_item(info_hash, *sock_addr));
-        }
+        fill_storage(&mut announce_store, &sock_addrs);
 
         // Try to add a new item
-        let other_info_hash = [1u8; bt::INFO_HASH_LEN].into();
+        let other_info_hash = [255u8; bt::INFO_HASH_LEN].into();
 
         // Returns false because it wasnt added
-        assert!(!announce_store.add_item(other_info_hash, sock_addrs[sock_addrs.len() - 1]));
-        // Closure not invoked because it wasnt added
-        let mut times_invoked = 0;
-        announce_store.find_items(&other_info_hash, |_| times_invoked += 1);
-        assert_eq!(times_invoked, 0);
+        assert!(!announce_store.add_item(other_info_hash, sock_addrs[sock_addrs.len() - 1], false));
+        // Not found because it wasnt added
+        assert!(announce_store.find_items(&other_info_hash).is_empty());
 
         // Try to add all of the initial nodes again (renew)
-        for sock_addr in sock_addrs.iter().take(storage::MAX_ITEMS_STORED) {
-            assert!(announce_store.add_item(info_hash, *sock_addr));
-        }
+        fill_storage(&mut announce_store, &sock_addrs);
+        assert_eq!(announce_store.num_items(), storage::MAX_ITEMS_STORED);
     }
 
     #[test]
     fn positive_full_storage_expire_one_infohash() {
         let mut announce_store = AnnounceStorage::new();
-        let info_hash = [0u8; bt::INFO_HASH_LEN].into();
         let sock_addrs =
             util_test::dummy_block_socket_addrs((storage::MAX_ITEMS_STORED + 1) as u16);
 
-        // Fill up the announce storage completely
-        for sock_addr in sock_addrs.iter().take(storage::MAX_ITEMS_STORED) {
-            assert!(announce_store.add_item(info_hash, *sock_addr));
-        }
+        fill_storage(&mut announce_store, &sock_addrs);
 
         // Try to add a new item into the storage (under a different info hash)
-        let other_info_hash = [1u8; bt::INFO_HASH_LEN].into();
+        let other_info_hash = [255u8; bt::INFO_HASH_LEN].into();
 
         // Returned false because it wasnt added
-        assert!(!announce_store.add_item(other_info_hash, sock_addrs[sock_addrs.len() - 1]));
-        // Closure not invoked because it wasnt added
-        let mut times_invoked = 0;
-        announce_store.find_items(&other_info_hash, |_| times_invoked += 1);
-        assert_eq!(times_invoked, 0);
+        assert!(!announce_store.add_item(other_info_hash, sock_addrs[sock_addrs.len() - 1], false));
+        // Not found because it wasnt added
+        assert!(announce_store.find_items(&other_info_hash).is_empty());
 
         // Try to add a new item into the storage mocking the current time
         let mock_current_time =
-            util_test::travel_into_future(Duration::hours(storage::EXPIRATION_TIME_HOURS));
+            util_test::travel_into_future(Duration::minutes(storage::EXPIRATION_TIME_MINS));
         assert!(announce_store.add(
             other_info_hash,
             sock_addrs[sock_addrs.len() - 1],
+            false,
             mock_current_time
         ));
-        // Closure invoked because it was added
-        announce_store.find_items(&other_info_hash, |_| times_invoked += 1);
-        assert_eq!(times_invoked, 1);
+        // Found because it was added
+        assert_eq!(
+            announce_store
+                .find(&other_info_hash, mock_current_time)
+                .len(),
+            1
+        );
     }
 
     #[test]
-    fn positive_full_storage_expire_two_infohash() {
+    fn positive_full_info_hash_evicts_oldest() {
         let mut announce_store = AnnounceStorage::new();
-        let info_hash_one = [0u8; bt::INFO_HASH_LEN].into();
-        let info_hash_two = [1u8; bt::INFO_HASH_LEN].into();
+        let info_hash = [0u8; bt::INFO_HASH_LEN].into();
         let sock_addrs =
-            util_test::dummy_block_socket_addrs((storage::MAX_ITEMS_STORED + 1) as u16);
+            util_test::dummy_block_socket_addrs((storage::MAX_ITEMS_PER_INFO_HASH + 2) as u16);
+        let start_time = Utc::now();
+        let at = |secs: usize| start_time + Duration::seconds(secs as i64);
 
-        // Fill up first info hash
-        let num_contacts_first = storage::MAX_ITEMS_STORED / 2;
-        for sock_addr in sock_addrs.iter().take(num_contacts_first) {
-            assert!(announce_store.add_item(info_hash_one, *sock_addr));
+        for (index, sock_addr) in sock_addrs
+            .iter()
+            .take(storage::MAX_ITEMS_PER_INFO_HASH)
+            .enumerate()
+        {
+            assert!(announce_store.add(info_hash, *sock_addr, false, at(index)));
+        }
+        // Renewing the oldest contact makes the second oldest contact the oldest
+        let renew_time = at(storage::MAX_ITEMS_PER_INFO_HASH);
+        assert!(announce_store.add(info_hash, sock_addrs[0], false, renew_time));
+
+        let new_contacts = &sock_addrs[storage::MAX_ITEMS_PER_INFO_HASH..];
+        for (index, sock_addr) in new_contacts.iter().enumerate() {
+            let add_time = at(storage::MAX_ITEMS_PER_INFO_HASH + 1 + index);
+
+            assert!(announce_store.add(info_hash, *sock_addr, false, add_time));
         }
 
-        // Fill up second info hash
-        let num_contacts_second = storage::MAX_ITEMS_STORED - num_contacts_first;
-        for sock_addr in sock_addrs
+        let items = announce_store.find(&info_hash, at(sock_addrs.len()));
+        assert_eq!(items.len(), storage::MAX_ITEMS_PER_INFO_HASH);
+        assert!(items.contains(&sock_addrs[0]));
+        assert!(!items.contains(&sock_addrs[1]) && !items.contains(&sock_addrs[2]));
+        assert!(new_contacts.iter().all(|addr| items.contains(addr)));
+        assert_eq!(announce_store.num_items(), storage::MAX_ITEMS_PER_INFO_HASH);
+    }
+
+    #[test]
+    fn positive_contacts_expire() {
+        let mut announce_store = AnnounceStorage::new();
+        let info_hash = [0u8; bt::INFO_HASH_LEN].into();
+        let sock_addrs = scrape_addrs();
+        let start_time = Utc::now();
+        let expire_time = Duration::minutes(storage::EXPIRATION_TIME_MINS);
+
+        assert!(announce_store.add(info_hash, sock_addrs[0], false, start_time));
+        let later_time = start_time + Duration::minutes(10);
+        assert!(announce_store.add(info_hash, sock_addrs[1], false, later_time));
+
+        let before_expire = start_time + expire_time - Duration::seconds(1);
+        assert_eq!(announce_store.find(&info_hash, before_expire).len(), 2);
+
+        // Contacts expire in the order they were announced
+        let items = announce_store.find(&info_hash, start_time + expire_time);
+        assert_eq!(items, vec![sock_addrs[1]]);
+
+        assert!(announce_store
+            .find(&info_hash, later_time + expire_time)
+            .is_empty());
+        assert_eq!(announce_store.num_info_hashes(), 0);
+        assert_eq!(announce_store.num_items(), 0);
+    }
+
+    #[test]
+    fn positive_renewed_contact_expires_later() {
+        let mut announce_store = AnnounceStorage::new();
+        let info_hash = [0u8; bt::INFO_HASH_LEN].into();
+        let sock_addr = util_test::dummy_socket_addr_v4();
+        let start_time = Utc::now();
+        let expire_time = Duration::minutes(storage::EXPIRATION_TIME_MINS);
+
+        assert!(announce_store.add(info_hash, sock_addr, false, start_time));
+        let renew_time = start_time + Duration::minutes(20);
+        assert!(announce_store.add(info_hash, sock_addr, true, renew_time));
+
+        assert_eq!(
+            announce_store.find(&info_hash, start_time + expire_time),
+            vec![sock_addr]
+        );
+        assert!(announce_store
+            .find(&info_hash, renew_time + expire_time)
+            .is_empty());
+    }
+
+    #[test]
+    fn positive_find_random_subset_fits_packet() {
+        let mut announce_store = AnnounceStorage::new();
+        let info_hash = [0u8; bt::INFO_HASH_LEN].into();
+        let sock_addrs: Vec<SocketAddr> = (0..storage::MAX_ITEMS_PER_INFO_HASH)
+            .map(|index| format!("[2001:db8::1]:{}", index + 1).parse().unwrap())
+            .collect();
+
+        for sock_addr in sock_addrs.iter() {
+            assert!(announce_store.add_item(info_hash, *sock_addr, false));
+        }
+
+        let items = announce_store.find_items(&info_hash);
+        assert_eq!(items.len(), storage::MAX_VALUES_BYTES / (3 + 18));
+        assert!(items.iter().all(|item| sock_addrs.contains(item)));
+
+        let mut unique_items = items.clone();
+        unique_items.sort();
+        unique_items.dedup();
+        assert_eq!(unique_items.len(), items.len());
+    }
+
+    #[test]
+    fn positive_full_storage_expire_two_infohash() {
+        let mut announce_store = AnnounceStorage::new();
+        let sock_addrs =
+            util_test::dummy_block_socket_addrs((storage::MAX_ITEMS_STORED + 1) as u16);
+        let start_time = Utc::now();
+
+        // Fill up the storage, with the contacts of the first InfoHash announced first
+        for (index, sock_addr) in sock_addrs
             .iter()
-            .skip(num_contacts_first)
-            .take(num_contacts_second)
+            .take(storage::MAX_ITEMS_STORED)
+            .enumerate()
         {
-            assert!(announce_store.add_item(info_hash_two, *sock_addr));
+            let info_hash = [(index / storage::MAX_ITEMS_PER_INFO_HASH) as u8; bt::INFO_HASH_LEN];
+            let add_time = if index < storage::MAX_ITEMS_PER_INFO_HASH {
+                start_time
+            } else {
+                start_time + Duration::minutes(10)
+            };
+
+            assert!(announce_store.add(info_hash.into(), *sock_addr, false, add_time));
         }
 
-        // Try to add a third info hash with a contact
-        let info_hash_three = [2u8; bt::INFO_HASH_LEN].into();
-        assert!(!announce_store.add_item(info_hash_three, sock_addrs[sock_addrs.len() - 1]));
-        // Closure not invoked because it was not added
-        let mut times_invoked = 0;
-        announce_store.find_items(&info_hash_three, |_| times_invoked += 1);
-        assert_eq!(times_invoked, 0);
+        // Try to add another info hash with a contact
+        let other_info_hash = [255u8; bt::INFO_HASH_LEN].into();
+        assert!(!announce_store.add(
+            other_info_hash,
+            sock_addrs[sock_addrs.len() - 1],
+            false,
+            start_time + Duration::minutes(10)
+        ));
+        // Not found because it was not added
+        assert!(announce_store
+            .find(&other_info_hash, start_time + Duration::minutes(10))
+            .is_empty());
 
-        // Try to add a new item into the storage mocking the current time
-        let mock_current_time =
-            util_test::travel_into_future(Duration::hours(storage::EXPIRATION_TIME_HOURS));
+        // Try to add a new item into the storage once only the first info hash expired
+        let mock_current_time = start_time + Duration::minutes(storage::EXPIRATION_TIME_MINS);
         assert!(announce_store.add(
-            info_hash_three,
+            other_info_hash,
             sock_addrs[sock_addrs.len() - 1],
+            false,
             mock_current_time
         ));
-        // Closure invoked because it was added
-        announce_store.find_items(&info_hash_three, |_| times_invoked += 1);
-        assert_eq!(times_invoked, 1);
+        // Found because it was added
+        assert_eq!(
+            announce_store
+                .find(&other_info_hash, mock_current_time)
+                .len(),
+            1
+        );
+        assert!(announce_store
+            .find(&[0u8; bt::INFO_HASH_LEN].into(), mock_current_time)
+            .is_empty());
+        assert_eq!(
+            announce_store
+                .find(&[1u8; bt::INFO_HASH_LEN].into(), mock_current_time)
+                .len(),
+            storage::MAX_ITEMS_PER_INFO_HASH
+        );
+    }
+
+    #[test]
+    fn positive_scrape_seeds_and_downloaders() {
+        let mut announce_store = AnnounceStorage::new();
+        let info_hash = [0u8; bt::INFO_HASH_LEN].into();
+        let sock_addrs = scrape_addrs();
+
+        assert!(announce_store.add_item(info_hash, sock_addrs[0], true));
+        assert!(announce_store.add_item(info_hash, sock_addrs[1], false));
+        assert!(announce_store.add_item(info_hash, sock_addrs[2], false));
+
+        assert_eq!(
+            announce_store.scrape_items(&info_hash),
+            (
+                scrape_filter(&sock_addrs[..1]),
+                scrape_filter(&sock_addrs[1..])
+            )
+        );
+    }
+
+    #[test]
+    fn positive_scrape_contact_becomes_seed() {
+        let mut announce_store = AnnounceStorage::new();
+        let info_hash = [0u8; bt::INFO_HASH_LEN].into();
+        let sock_addrs = scrape_addrs();
+
+        assert!(announce_store.add_item(info_hash, sock_addrs[0], false));
+        assert!(announce_store.add_item(info_hash, sock_addrs[0], true));
+
+        assert_eq!(
+            announce_store.scrape_items(&info_hash),
+            (scrape_filter(&sock_addrs[..1]), BloomFilter::new())
+        );
+
+        assert_eq!(announce_store.find_items(&info_hash), vec![sock_addrs[0]]);
+    }
+
+    #[test]
+    fn positive_sample_info_hashes() {
+        let mut announce_store = AnnounceStorage::new();
+        let sock_addr = util_test::dummy_socket_addr_v4();
+        let info_hashes: Vec<InfoHash> = (0..10u8).map(|i| [i; bt::INFO_HASH_LEN].into()).collect();
+
+        for info_hash in info_hashes.iter() {
+            assert!(announce_store.add_item(*info_hash, sock_addr, false));
+        }
+
+        let samples = announce_store.sample_info_hashes(4);
+        assert_eq!(samples.len(), 4);
+        assert!(samples.iter().all(|hash| info_hashes.contains(hash)));
+        assert_eq!(announce_store.num_info_hashes(), 10);
+    }
+
+    #[test]
+    fn positive_sample_info_hashes_expired() {
+        let mut announce_store = AnnounceStorage::new();
+        let info_hash = [0u8; bt::INFO_HASH_LEN].into();
+
+        assert!(announce_store.add_item(info_hash, util_test::dummy_socket_addr_v4(), false));
+        assert_eq!(announce_store.sample_info_hashes(20), vec![info_hash]);
+
+        let mock_current_time =
+            util_test::travel_into_future(Duration::minutes(storage::EXPIRATION_TIME_MINS));
+        assert!(announce_store.sample(20, mock_current_time).is_empty());
+        assert_eq!(announce_store.num_info_hashes(), 0);
+    }
+
+    #[test]
+    fn positive_put_and_find_immutable_item() {
+        let mut item_store = ItemStorage::new();
+        let item = ImmutableItem::new(&bt_ben_bytes!("value")).unwrap();
+        let source = "127.0.0.1".parse().unwrap();
+
+        assert_eq!(item_store.put_immutable(item.clone(), source), Ok(()));
+        assert_eq!(
+            item_store.find_item(&item.target().into()),
+            Some(&StoredItem::Immutable(item))
+        );
+    }
+
+    #[test]
+    fn positive_put_mutable_item_newer_seq() {
+        let mut item_store = ItemStorage::new();
+        let source = "127.0.0.1".parse().unwrap();
+
+        assert_eq!(
+            item_store.put_mutable(mutable_item(1, "one"), None, source),
+            Ok(())
+        );
+        assert_eq!(
+            item_store.put_mutable(mutable_item(1, "one"), None, source),
+            Ok(())
+        );
+        assert_eq!(
+            item_store.put_mutable(mutable_item(2, "two"), Some(1), source),
+            Ok(())
+        );
+
+        let target = mutable_item(2, "two").target().into();
+        assert_eq!(
+            item_store.find_item(&target),
+            Some(&StoredItem::Mutable(mutable_item(2, "two")))
+        );
+    }
+
+    #[test]
+    fn negative_put_mutable_item_seq_conflicts() {
+        let mut item_store = ItemStorage::new();
+        let source = "127.0.0.1".parse().unwrap();
+
+        assert_eq!(
+            item_store.put_mutable(mutable_item(2, "two"), None, source),
+            Ok(())
+        );
+
+        assert_eq!(
+            item_store.put_mutable(mutable_item(1, "one"), None, source),
+            Err(PutError::SeqTooLow)
+        );
+        assert_eq!(
+            item_store.put_mutable(mutable_item(2, "other"), None, source),
+            Err(PutError::SeqTooLow)
+        );
+        assert_eq!(
+            item_store.put_mutable(mutable_item(3, "three"), Some(1), source),
+            Err(PutError::CasMismatch)
+        );
+    }
+
+    #[test]
+    fn negative_put_items_over_ip_quota() {
+        let mut item_store = ItemStorage::new();
+        let source = "127.0.0.1".parse().unwrap();
+
+        for index in 0..storage::MAX_DATA_ITEMS_PER_IP {
+            let item = ImmutableItem::new(&bt_ben_int!(index as i64)).unwrap();
+            assert_eq!(item_store.put_immutable(item, source), Ok(()));
+        }
+
+        let item = ImmutableItem::new(&bt_ben_bytes!("value")).unwrap();
+        assert_eq!(
+            item_store.put_immutable(item.clone(), source),
+            Err(PutError::QuotaExceeded)
+        );
+        assert_eq!(
+            item_store.put_immutable(item, "::1".parse().unwrap()),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn positive_items_expire() {
+        let mut item_store = ItemStorage::new();
+        let item = ImmutableItem::new(&bt_ben_bytes!("value")).unwrap();
+        let target = item.target().into();
+
+        assert_eq!(
+            item_store.put(
+                target,
+                StoredItem::Immutable(item),
+                None,
+                "127.0.0.1".parse().unwrap(),
+                Utc::now()
+            ),
+            Ok(())
+        );
+        assert!(item_store.find_item(&target).is_some());
+
+        let mock_current_time =
+            util_test::travel_into_future(Duration::hours(storage::DATA_EXPIRATION_TIME_HOURS));
+        assert!(item_store.find(&target, mock_current_time).is_none());
     }
 }
@@ -8,22 +8,19 @@ use crate::util::error::{LengthError, LengthErrorKind, LengthResult};
 use crate::util::net::IpAddr;
 use crate::util::sha::{self, ShaHash};
 
-/// We will partially follow the bittorrent implementation for issuing tokens to nodes, the
-/// secret will change every 10 minutes and tokens up to 10 minutes old will be accepted. This
-/// is in contrast with the bittorrent implementation where the secret changes every 5 minutes
-/// and tokens up to 10 minutes old are accepted. Updating of the token will take place lazily.
-/// However, with our implementation we are not going to store tokens that we have issued, instead,
-/// store the secret and check if the token they gave us is valid for the current or last secret.
-/// This is technically not what we want, but it will have essentially the same result when we
-/// assume that nobody other than us knows the secret.
-
-/// With this scheme we can guarantee that the minimum amount of time a token can be valid for
-/// is the maximum amount of time a token is valid for in bittorrent in order to provide interop.
-/// Since we arent storing the tokens we generate (which is awesome) we CANT track how long each
-/// individual token has been checked out from the store and so each token is valid for some time
-/// between 10 and 20 minutes in contrast with 5 and 10 minutes.
-
-const REFRESH_INTERVAL_MINS: i64 = 10;
+/// We will follow the bittorrent implementation for issuing tokens to nodes, the secret will
+/// change every 5 minutes and tokens up to 10 minutes old will be accepted. Updating of the token
+/// will take place lazily. However, with our implementation we are not going to store tokens that
+/// we have issued, instead, store the secret and check if the token they gave us is valid for the
+/// current or last secret. This is technically not what we want, but it will have essentially the
+/// same result when we assume that nobody other than us knows the secret.
+
+/// With this scheme each token is valid for some time between 5 and 10 minutes, since we arent
+/// storing the tokens we generate (which is awesome) we CANT track how long each individual token
+/// has been checked out from the store. Tokens are generated from the ip of the node they are
+/// issued to, so they can not be used by nodes with another ip.
+
+const REFRESH_INTERVAL_MINS: i64 = 5;
 
 const IPV4_SECRET_BUFFER_LEN: usize = 4 + 4;
 const IPV6_SECRET_BUFFER_LEN: usize = 16 + 4;
@@ -113,7 +110,9 @@ impl TokenStore {
             1 => {
                 self.last_secret = self.curr_secret;
                 self.curr_secret = rand::random::<u32>();
-                self.last_refresh = Utc::now();
+                // Secrets have to change on the interval, not whenever we happen to check,
+                // otherwise the last secret could outlive its interval while we are idle
+                self.last_refresh = self.last_refresh + Duration::minutes(REFRESH_INTERVAL_MINS);
             }
             _ => {
                 self.last_secret = rand::random::<u32>();
@@ -202,7 +201,10 @@ fn validate_token_from_addr_v6(v6_addr: Ipv6Addr, token: Token, secret: u32) ->
 
 #[cfg(test)]
 mod tests {
+    use std::net::Ipv4Addr;
+
     use super::TokenStore;
+    use crate::util::net::IpAddr;
     use crate::util::test as util_test;
     use chrono::Duration;
 
@@ -254,6 +256,34 @@ mod tests {
         assert!(store.checkin(v6_addr, valid_token));
     }
 
+    #[test]
+    fn negative_reject_token_from_other_ip() {
+        let mut store = TokenStore::new();
+        let v4_addr = util_test::dummy_ipv4_addr();
+
+        let valid_token = store.checkout(v4_addr);
+
+        assert!(!store.checkin(util_test::dummy_ipv6_addr(), valid_token));
+        assert!(!store.checkin(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)), valid_token));
+    }
+
+    #[test]
+    fn negative_reject_token_older_than_two_intervals() {
+        let mut store = TokenStore::new();
+        let v4_addr = util_test::dummy_ipv4_addr();
+
+        let valid_token = store.checkout(v4_addr);
+
+        // Checking in right before the token is two intervals old rotates the secrets
+        let past_offset = Duration::minutes((super::REFRESH_INTERVAL_MINS * 2) - 1);
+        store.last_refresh = util_test::travel_into_past(past_offset);
+        assert!(store.checkin(v4_addr, valid_token));
+
+        // A minute later the token is two intervals old
+        store.last_refresh = store.last_refresh - Duration::minutes(1);
+        assert!(!store.checkin(v4_addr, valid_token));
+    }
+
     #[test]
     #[should_panic]
     fn negative_reject_expired_v4_token() {
@@ -2,14 +2,16 @@ use mio::{EventLoop, Timeout};
 use std::collections::{HashMap, HashSet};
 use std::net::SocketAddr;
 use std::sync::mpsc::SyncSender;
+use std::time::{Duration, Instant};
 
 use crate::dht::handshake::Handshaker;
 use crate::dht::message::find_node::FindNodeRequest;
+use crate::dht::message::Want;
 use crate::dht::routing::bucket::Bucket;
 use crate::dht::routing::node::{Node, NodeStatus};
 use crate::dht::routing::table::{self, BucketContents, RoutingTable};
 use crate::dht::transaction::{MIDGenerator, TransactionID};
-use crate::dht::worker::handler::DhtHandler;
+use crate::dht::worker::handler::{self, DhtHandler};
 use crate::dht::worker::ScheduledTask;
 use crate::util::bt::{self, NodeId};
 
@@ -18,6 +20,9 @@ const BOOTSTRAP_NODE_TIMEOUT: u64 = 500;
 
 const BOOTSTRAP_PINGS_PER_BUCKET: usize = 8;
 
+/// Longest interval between two checks of the bootstrap watchdog.
+const WATCHDOG_MAX_CHECK_INTERVAL_MS: u64 = 5000;
+
 #[derive(Debug, PartialEq, Eq)]
 pub enum BootstrapStatus {
     /// Bootstrap has been finished.
@@ -37,6 +42,9 @@ pub struct TableBootstrap {
     active_messages: HashMap<TransactionID, Timeout>,
     starting_routers: HashSet<SocketAddr>,
     curr_bootstrap_bucket: usize,
+    // Whether we are pinging nodes restored from a saved state instead of bootstrapping
+    restoring: bool,
+    want: Option<Want>,
 }
 
 impl TableBootstrap {
@@ -45,6 +53,7 @@ impl TableBootstrap {
         id_generator: MIDGenerator,
         nodes: Vec<SocketAddr>,
         routers: I,
+        want: Option<Want>,
     ) -> TableBootstrap
     where
         I: Iterator<Item = SocketAddr>,
@@ -58,6 +67,8 @@ impl TableBootstrap {
             starting_routers: router_filter,
             active_messages: HashMap::new(),
             curr_bootstrap_bucket: 0,
+            restoring: false,
+            want: want,
         }
     }
 
@@ -72,6 +83,7 @@ impl TableBootstrap {
         // Reset the bootstrap state
         self.active_messages.clear();
         self.curr_bootstrap_bucket = 0;
+        self.restoring = false;
 
         // Generate transaction id for the initial bootstrap messages
         let trans_id = self.id_generator.generate();
@@ -95,7 +107,8 @@ impl TableBootstrap {
         self.active_messages.insert(trans_id, timeout);
 
         let find_node_msg =
-            FindNodeRequest::new(trans_id.as_ref(), self.table_id, self.table_id).encode();
+            FindNodeRequest::new(trans_id.as_ref(), self.table_id, self.table_id, self.want)
+                .encode();
         // Ping all initial routers and nodes
         for addr in self
             .starting_routers
@@ -111,6 +124,160 @@ impl TableBootstrap {
         self.current_bootstrap_status()
     }
 
+    /// Ping the nodes restored into our routing table that we have not heard from recently.
+    ///
+    /// Completes once enough nodes are good again, or all pings were answered or timed out, at
+    /// which point the caller falls back to a full bootstrap if we still have too few nodes.
+    /// Stale nodes left when completing early are pinged by the table refresh later on.
+    pub fn start_restore<H>(
+        &mut self,
+        table: &RoutingTable,
+        out: &SyncSender<(Vec<u8>, SocketAddr)>,
+        event_loop: &mut EventLoop<DhtHandler<H>>,
+    ) -> BootstrapStatus
+    where
+        H: Handshaker,
+    {
+        self.active_messages.clear();
+        self.curr_bootstrap_bucket = 0;
+        self.restoring = true;
+
+        for node in table
+            .pingable_nodes()
+            .filter(|n| n.status() == NodeStatus::Questionable)
+        {
+            let trans_id = self.id_generator.generate();
+            let find_node_msg =
+                FindNodeRequest::new(trans_id.as_ref(), self.table_id, self.table_id, self.want)
+                    .encode();
+
+            if !self.add_timeout(trans_id, BOOTSTRAP_NODE_TIMEOUT, event_loop) {
+                return BootstrapStatus::Failed;
+            }
+
+            if out.send((find_node_msg, node.addr())).is_err() {
+                error!("bittorrent-protocol_dht: Could not send a restore message through the channel...");
+                return BootstrapStatus::Failed;
+            }
+
+            // Mark that we requested from the node
+            node.local_request();
+        }
+
+        // Even with nothing to ping, hold off on completing so event notifiers can register first
+        if self.active_messages.is_empty() {
+            let trans_id = self.id_generator.generate();
+
+            if !self.add_timeout(trans_id, BOOTSTRAP_NODE_TIMEOUT, event_loop) {
+                return BootstrapStatus::Failed;
+            }
+        }
+
+        BootstrapStatus::Bootstrapping
+    }
+
+    fn add_timeout<H>(
+        &mut self,
+        trans_id: TransactionID,
+        timeout_ms: u64,
+        event_loop: &mut EventLoop<DhtHandler<H>>,
+    ) -> bool
+    where
+        H: Handshaker,
+    {
+        let res_timeout = event_loop.timeout_ms(
+            (timeout_ms, ScheduledTask::CheckBootstrapTimeout(trans_id)),
+            timeout_ms,
+        );
+
+        if let Ok(timeout) = res_timeout {
+            self.active_messages.insert(trans_id, timeout);
+
+            true
+        } else {
+            error!(
+                "bittorrent-protocol_dht: Failed to set a timeout for a table bootstrap message..."
+            );
+            false
+        }
+    }
+
+    /// Add routers resolved after the bootstrap started.
+    ///
+    /// See TableBootstrap::add_node for when the routers are contacted.
+    pub fn add_routers<H>(
+        &mut self,
+        routers: &[SocketAddr],
+        out: &SyncSender<(Vec<u8>, SocketAddr)>,
+        event_loop: &mut EventLoop<DhtHandler<H>>,
+    ) -> BootstrapStatus
+    where
+        H: Handshaker,
+    {
+        self.starting_routers.extend(routers.iter().cloned());
+
+        self.contact_late(routers, out, event_loop)
+    }
+
+    /// Add a node given to us after the bootstrap started.
+    ///
+    /// While we are still waiting on the initial routers and nodes, the node is contacted as one
+    /// of them, otherwise it is contacted alongside the bucket we are currently bootstrapping.
+    pub fn add_node<H>(
+        &mut self,
+        addr: SocketAddr,
+        out: &SyncSender<(Vec<u8>, SocketAddr)>,
+        event_loop: &mut EventLoop<DhtHandler<H>>,
+    ) -> BootstrapStatus
+    where
+        H: Handshaker,
+    {
+        self.starting_nodes.push(addr);
+
+        self.contact_late(&[addr], out, event_loop)
+    }
+
+    fn contact_late<H>(
+        &mut self,
+        addrs: &[SocketAddr],
+        out: &SyncSender<(Vec<u8>, SocketAddr)>,
+        event_loop: &mut EventLoop<DhtHandler<H>>,
+    ) -> BootstrapStatus
+    where
+        H: Handshaker,
+    {
+        // Only the timeout of the initial messages is active before we move on to the buckets
+        let opt_initial_id = if !self.restoring && self.curr_bootstrap_bucket == 0 {
+            self.active_messages.keys().next().cloned()
+        } else {
+            None
+        };
+
+        for addr in addrs {
+            let trans_id = if let Some(trans_id) = opt_initial_id {
+                trans_id
+            } else {
+                let trans_id = self.id_generator.generate();
+
+                if !self.add_timeout(trans_id, BOOTSTRAP_NODE_TIMEOUT, event_loop) {
+                    return BootstrapStatus::Failed;
+                }
+
+                trans_id
+            };
+            let find_node_msg =
+                FindNodeRequest::new(trans_id.as_ref(), self.table_id, self.table_id, self.want)
+                    .encode();
+
+            if out.send((find_node_msg, *addr)).is_err() {
+                error!("bittorrent-protocol_dht: Failed to send a late bootstrap message through the channel...");
+                return BootstrapStatus::Failed;
+            }
+        }
+
+        BootstrapStatus::Bootstrapping
+    }
+
     pub fn is_router(&self, addr: &SocketAddr) -> bool {
         self.starting_routers.contains(&addr)
     }
@@ -138,7 +305,7 @@ impl TableBootstrap {
 
         // If this response was from the initial bootstrap, we don't want to clear the timeout or remove
         // the token from the map as we want to wait until the proper timeout has been triggered before starting
-        if self.curr_bootstrap_bucket != 0 {
+        if self.restoring || self.curr_bootstrap_bucket != 0 {
             // Message was not from the initial ping
             // Remove the timeout from the event loop
             event_loop.clear_timeout(timeout);
@@ -147,6 +314,10 @@ impl TableBootstrap {
             self.active_messages.remove(trans_id);
         }
 
+        if self.restoring {
+            return self.current_restore_status(table, event_loop);
+        }
+
         // Check if we need to bootstrap on the next bucket
         if self.active_messages.is_empty() {
             return self.bootstrap_next_bucket(table, out, event_loop);
@@ -173,6 +344,10 @@ impl TableBootstrap {
             return self.current_bootstrap_status();
         }
 
+        if self.restoring {
+            return self.current_restore_status(table, event_loop);
+        }
+
         // Check if we need to bootstrap on the next bucket
         if self.active_messages.is_empty() {
             return self.bootstrap_next_bucket(table, out, event_loop);
@@ -181,6 +356,27 @@ impl TableBootstrap {
         self.current_bootstrap_status()
     }
 
+    /// Restore completes once all pings were answered or timed out, or once we have enough good
+    /// nodes, in which case we stop waiting on the remaining pings.
+    fn current_restore_status<H>(
+        &mut self,
+        table: &RoutingTable,
+        event_loop: &mut EventLoop<DhtHandler<H>>,
+    ) -> BootstrapStatus
+    where
+        H: Handshaker,
+    {
+        if self.active_messages.is_empty() || !handler::should_rebootstrap(table) {
+            for (_, timeout) in self.active_messages.drain() {
+                event_loop.clear_timeout(timeout);
+            }
+
+            BootstrapStatus::Completed
+        } else {
+            BootstrapStatus::Bootstrapping
+        }
+    }
+
     // Returns true if there are more buckets to bootstrap, false otherwise
     fn bootstrap_next_bucket<H>(
         &mut self,
@@ -269,7 +465,8 @@ impl TableBootstrap {
             // Generate a transaction id
             let trans_id = self.id_generator.generate();
             let find_node_msg =
-                FindNodeRequest::new(trans_id.as_ref(), self.table_id, target_id).encode();
+                FindNodeRequest::new(trans_id.as_ref(), self.table_id, target_id, self.want)
+                    .encode();
 
             // Add a timeout for the node
             let res_timeout = event_loop.timeout_ms(
@@ -320,6 +517,69 @@ impl TableBootstrap {
     }
 }
 
+/// Keeps track of how long our routing table has been starved of good nodes, so that we can
+/// bootstrap again once it has been for too long, such as after waking up from sleep.
+pub struct BootstrapWatchdog {
+    min_good_nodes: usize,
+    grace: Duration,
+    starved_since: Option<Instant>,
+}
+
+impl BootstrapWatchdog {
+    /// Create a new BootstrapWatchdog, a min_good_nodes of zero disables the watchdog.
+    pub fn new(min_good_nodes: usize, grace: Duration) -> BootstrapWatchdog {
+        BootstrapWatchdog {
+            min_good_nodes: min_good_nodes,
+            grace: grace,
+            starved_since: None,
+        }
+    }
+
+    /// Whether the watchdog does anything at all.
+    pub fn is_enabled(&self) -> bool {
+        self.min_good_nodes != 0
+    }
+
+    /// Interval the watchdog should be checked at, in milliseconds.
+    pub fn check_interval_ms(&self) -> u64 {
+        let grace_ms = self.grace.as_secs() * 1000 + u64::from(self.grace.subsec_millis());
+
+        grace_ms.max(1).min(WATCHDOG_MAX_CHECK_INTERVAL_MS)
+    }
+
+    /// Check the number of good nodes in our routing table, returning true if we should bootstrap.
+    pub fn check(&mut self, num_good_nodes: usize) -> bool {
+        self.check_at(num_good_nodes, Instant::now())
+    }
+
+    /// Forget about any starvation so far, called whenever a bootstrap finishes.
+    pub fn reset(&mut self) {
+        self.starved_since = None;
+    }
+
+    fn check_at(&mut self, num_good_nodes: usize, now: Instant) -> bool {
+        if num_good_nodes >= self.min_good_nodes {
+            self.starved_since = None;
+
+            return false;
+        }
+
+        match self.starved_since {
+            Some(since) if now.duration_since(since) >= self.grace => {
+                self.starved_since = None;
+
+                true
+            }
+            Some(_) => false,
+            None => {
+                self.starved_since = Some(now);
+
+                false
+            }
+        }
+    }
+}
+
 /// Panics if index is out of bounds.
 /// TODO: Move into use crate::util crate
 fn flip_id_bit_at_index(node_id: NodeId, index: usize) -> NodeId {
@@ -331,3 +591,54 @@ fn flip_id_bit_at_index(node_id: NodeId, index: usize) -> NodeId {
 
     id_bytes.into()
 }
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::BootstrapWatchdog;
+
+    #[test]
+    fn positive_watchdog_starved_past_grace() {
+        let mut watchdog = BootstrapWatchdog::new(10, Duration::from_secs(60));
+        let now = Instant::now();
+
+        assert!(!watchdog.check_at(5, now));
+        assert!(!watchdog.check_at(5, now + Duration::from_secs(59)));
+        assert!(watchdog.check_at(5, now + Duration::from_secs(60)));
+
+        // Starvation starts over after firing
+        assert!(!watchdog.check_at(5, now + Duration::from_secs(61)));
+    }
+
+    #[test]
+    fn positive_watchdog_check_interval() {
+        let watchdog = BootstrapWatchdog::new(10, Duration::from_millis(500));
+        assert_eq!(watchdog.check_interval_ms(), 500);
+
+        let watchdog = BootstrapWatchdog::new(10, Duration::from_secs(60));
+        assert_eq!(watchdog.check_interval_ms(), 5000);
+    }
+
+    #[test]
+    fn negative_watchdog_recovered_within_grace() {
+        let mut watchdog = BootstrapWatchdog::new(10, Duration::from_secs(60));
+        let now = Instant::now();
+
+        assert!(!watchdog.check_at(5, now));
+        assert!(!watchdog.check_at(10, now + Duration::from_secs(30)));
+        assert!(!watchdog.check_at(5, now + Duration::from_secs(60)));
+        assert!(!watchdog.check_at(5, now + Duration::from_secs(119)));
+    }
+
+    #[test]
+    fn negative_watchdog_reset() {
+        let mut watchdog = BootstrapWatchdog::new(10, Duration::from_secs(60));
+        let now = Instant::now();
+
+        watchdog.check_at(5, now);
+        watchdog.reset();
+
+        assert!(!watchdog.check_at(5, now + Duration::from_secs(60)));
+    }
+}
@@ -2,46 +2,62 @@ use std::collections::HashMap;
 use std::convert::AsRef;
 use std::io;
 use std::mem;
-use std::net::{SocketAddr, SocketAddrV4, SocketAddrV6, UdpSocket};
+use std::net::{self as std_net, SocketAddr, SocketAddrV4, SocketAddrV6, UdpSocket};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::mpsc::{self, SyncSender};
+use std::sync::{Arc, Mutex};
 use std::thread;
 
+use futures::channel::mpsc::UnboundedSender;
 use log::Level;
 use mio::{self, EventLoop, Handler};
+use rand;
 
 // use crate::bencode::Bencode;
 use crate::dht::bencode::Bencode;
 
-use crate::util::bt::InfoHash;
-use crate::util::convert;
+use crate::util::bt::{InfoHash, NodeId};
 use crate::util::net::IpAddr;
 
 use crate::dht::handshake::Handshaker;
+use crate::dht::item::{self, ImmutableItem, ItemError, MutableItem};
+use crate::dht::limiter::{QueryCheck, QueryLimiter};
 use crate::dht::message::announce_peer::{AnnouncePeerResponse, ConnectPort};
-use crate::dht::message::compact_info::{CompactNodeInfo, CompactValueInfo};
+use crate::dht::message::compact_info::{self, CompactNodeInfo, CompactValueInfo};
 use crate::dht::message::error::{ErrorCode, ErrorMessage};
 use crate::dht::message::find_node::FindNodeResponse;
-use crate::dht::message::get_peers::{CompactInfoType, GetPeersResponse};
+use crate::dht::message::get_data::{GetDataResponse, ItemInfo};
+use crate::dht::message::get_peers::GetPeersResponse;
+use crate::dht::message::krpc::{self, KrpcError};
 use crate::dht::message::ping::PingResponse;
+use crate::dht::message::put_data::{PutDataRequest, PutDataResponse};
 use crate::dht::message::request::RequestType;
 use crate::dht::message::response::{ExpectedResponse, ResponseType};
-use crate::dht::message::MessageType;
+use crate::dht::message::sample_infohashes::SampleInfoHashesResponse;
+use crate::dht::message::{self, MessageType, Want};
 
 use crate::dht::router::Router;
+use crate::dht::sample::SampleError;
 
 use crate::dht::routing::node::Node;
 use crate::dht::routing::node::NodeStatus;
 use crate::dht::routing::table::BucketContents;
 use crate::dht::routing::table::RoutingTable;
 
-use crate::dht::storage::AnnounceStorage;
+use crate::dht::security::{self, ExternalIpVotes};
+use crate::dht::state::DhtState;
+use crate::dht::storage::{AnnounceStorage, ItemStorage, PutError, StoredItem};
 use crate::dht::token::{Token, TokenStore};
 use crate::dht::transaction::{AIDGenerator, ActionID, TransactionID};
 
-use crate::dht::worker::bootstrap::{BootstrapStatus, TableBootstrap};
+use crate::dht::worker::bootstrap::{BootstrapStatus, BootstrapWatchdog, TableBootstrap};
+use crate::dht::worker::item::{ItemLookup, ItemOperation, ItemStatus};
 use crate::dht::worker::lookup::{LookupStatus, TableLookup};
 use crate::dht::worker::refresh::{RefreshStatus, TableRefresh};
-use crate::dht::worker::{DhtEvent, OneshotTask, ScheduledTask, ShutdownCause};
+use crate::dht::worker::sample::{SampleCache, SampleIntervals, SampleQuery, SampleSender};
+use crate::dht::worker::{
+    DhtEvent, DhtStatus, OneshotTask, ReadOnlyQueries, ScheduledTask, SearchEvent, ShutdownCause,
+};
 
 // TODO: Update modules to use find_node on the routing table to update the status of a given node.
 
@@ -52,7 +68,16 @@ const BOOTSTRAP_GOOD_NODE_THRESHOLD: usize = 10;
 pub fn create_dht_handler<H>(
     table: RoutingTable,
     out: SyncSender<(Vec<u8>, SocketAddr)>,
-    read_only: bool,
+    read_only: Arc<AtomicBool>,
+    read_only_queries: ReadOnlyQueries,
+    implied_port: bool,
+    external_ip_votes: ExternalIpVotes,
+    limiter: QueryLimiter,
+    max_samples: usize,
+    opt_want: Option<Want>,
+    ipv6: bool,
+    watchdog: BootstrapWatchdog,
+    status: Arc<Mutex<DhtStatus>>,
     handshaker: H,
     kill_sock: UdpSocket,
     kill_addr: SocketAddr,
@@ -60,7 +85,21 @@ pub fn create_dht_handler<H>(
 where
     H: Handshaker + 'static,
 {
-    let mut handler = DhtHandler::new(table, out, read_only, handshaker);
+    let mut handler = DhtHandler::new(
+        table,
+        out,
+        read_only,
+        read_only_queries,
+        implied_port,
+        external_ip_votes,
+        limiter,
+        max_samples,
+        opt_want,
+        ipv6,
+        watchdog,
+        status,
+        handshaker,
+    );
     let mut event_loop = EventLoop::new()?;
 
     let loop_channel = event_loop.channel();
@@ -102,14 +141,20 @@ enum TableAction {
     ///
     /// Includes number of bootstrap attempts.
     Bootstrap(TableBootstrap, usize),
+    /// Item lookup action.
+    Item(ItemLookup),
+    /// Sample infohashes query.
+    Sample(SampleQuery),
 }
 
 /// Actions that we want to perform on our RoutingTable after bootstrapping finishes.
 enum PostBootstrapAction {
     /// Future lookup action.
-    Lookup(InfoHash, bool),
+    Lookup(InfoHash, bool, bool, Option<UnboundedSender<SearchEvent>>),
     /// Future refresh action.
     Refresh(TableRefresh, TransactionID),
+    /// Future item lookup action.
+    Item(ItemOperation),
 }
 
 /// Storage for our EventLoop to invoke actions upon.
@@ -121,18 +166,39 @@ pub struct DhtHandler<H> {
 /// Storage separate from the table actions allowing us to hold mutable references
 /// to table actions while still being able to pass around the bulky parameters.
 struct DetachedDhtHandler<H> {
-    read_only: bool,
+    // Shared with the outgoing messenger, see messenger::create_outgoing_messenger
+    read_only: Arc<AtomicBool>,
+    read_only_queries: ReadOnlyQueries,
+    implied_port: bool,
     handshaker: H,
     out_channel: SyncSender<(Vec<u8>, SocketAddr)>,
     token_store: TokenStore,
     aid_generator: AIDGenerator,
     bootstrapping: bool,
+    // Whether our initial bootstrap has completed, later bootstraps failing do not shut us down
+    bootstrapped: bool,
+    // Routers and nodes we bootstrap from, kept around for bootstrapping again later on
+    bootstrap_routers: Vec<Router>,
+    bootstrap_nodes: Vec<SocketAddr>,
+    watchdog: BootstrapWatchdog,
     routing_table: RoutingTable,
+    // Nodes of the other address family than our own, handed out to requesters that
+    // want them (BEP 32) but never contacted over our socket
+    other_table: RoutingTable,
+    opt_want: Option<Want>,
+    // Whether our socket, and so our routing table, is IPv6
+    ipv6: bool,
     active_stores: AnnounceStorage,
+    active_items: ItemStorage,
     // If future actions is not empty, that means we are still bootstrapping
     // since we will always spin up a table refresh action after bootstrapping.
     future_actions: Vec<PostBootstrapAction>,
     event_notifiers: Vec<mpsc::Sender<DhtEvent>>,
+    external_ip_votes: ExternalIpVotes,
+    limiter: QueryLimiter,
+    sample_cache: SampleCache,
+    sample_intervals: SampleIntervals,
+    status: Arc<Mutex<DhtStatus>>,
 }
 
 impl<H> DhtHandler<H>
@@ -142,7 +208,16 @@ where
     fn new(
         table: RoutingTable,
         out: SyncSender<(Vec<u8>, SocketAddr)>,
-        read_only: bool,
+        read_only: Arc<AtomicBool>,
+        read_only_queries: ReadOnlyQueries,
+        implied_port: bool,
+        external_ip_votes: ExternalIpVotes,
+        limiter: QueryLimiter,
+        max_samples: usize,
+        opt_want: Option<Want>,
+        ipv6: bool,
+        watchdog: BootstrapWatchdog,
+        status: Arc<Mutex<DhtStatus>>,
         handshaker: H,
     ) -> DhtHandler<H> {
         let mut aid_generator = AIDGenerator::new();
@@ -150,7 +225,7 @@ where
         // Insert the refresh task to execute after the bootstrap
         let mut mid_generator = aid_generator.generate();
         let refresh_trans_id = mid_generator.generate();
-        let table_refresh = TableRefresh::new(mid_generator);
+        let table_refresh = TableRefresh::new(mid_generator, opt_want);
         let future_actions = vec![PostBootstrapAction::Refresh(
             table_refresh,
             refresh_trans_id,
@@ -158,15 +233,30 @@ where
 
         let detached = DetachedDhtHandler {
             read_only: read_only,
+            read_only_queries: read_only_queries,
+            implied_port: implied_port,
             handshaker: handshaker,
             out_channel: out,
             token_store: TokenStore::new(),
             aid_generator: aid_generator,
             bootstrapping: false,
+            bootstrapped: false,
+            bootstrap_routers: Vec::new(),
+            bootstrap_nodes: Vec::new(),
+            watchdog: watchdog,
+            other_table: RoutingTable::new(table.node_id()),
             routing_table: table,
+            opt_want: opt_want,
+            ipv6: ipv6,
             active_stores: AnnounceStorage::new(),
+            active_items: ItemStorage::new(),
             future_actions: future_actions,
             event_notifiers: Vec::new(),
+            external_ip_votes: external_ip_votes,
+            limiter: limiter,
+            sample_cache: SampleCache::new(max_samples),
+            sample_intervals: SampleIntervals::new(),
+            status: status,
         };
 
         DhtHandler {
@@ -194,15 +284,47 @@ where
             OneshotTask::StartBootstrap(routers, nodes) => {
                 handle_start_bootstrap(self, event_loop, routers, nodes);
             }
-            OneshotTask::StartLookup(info_hash, should_announce) => {
+            OneshotTask::AddRouters(routers) => {
+                handle_add_routers(self, event_loop, routers);
+            }
+            OneshotTask::AddNode(addr) => {
+                handle_add_node(self, event_loop, addr);
+            }
+            OneshotTask::StartLookup(info_hash, should_announce, should_scrape, opt_search) => {
                 handle_start_lookup(
                     &mut self.table_actions,
                     &mut self.detached,
                     event_loop,
                     info_hash,
                     should_announce,
+                    should_scrape,
+                    opt_search,
+                );
+            }
+            OneshotTask::StartItemLookup(operation) => {
+                handle_start_item_lookup(
+                    &mut self.table_actions,
+                    &mut self.detached,
+                    event_loop,
+                    operation,
                 );
             }
+            OneshotTask::StartSample(addr, target, send) => {
+                handle_start_sample(
+                    &mut self.table_actions,
+                    &mut self.detached,
+                    event_loop,
+                    addr,
+                    target,
+                    send,
+                );
+            }
+            OneshotTask::SaveState(send) => {
+                handle_save_state(self, send);
+            }
+            OneshotTask::SetReadOnly(read_only) => {
+                handle_set_read_only(self, read_only);
+            }
             OneshotTask::Shutdown(cause) => {
                 handle_shutdown(self, event_loop, cause);
             }
@@ -224,12 +346,21 @@ where
             ScheduledTask::CheckBootstrapTimeout(trans_id) => {
                 handle_check_bootstrap_timeout(self, event_loop, trans_id);
             }
+            ScheduledTask::CheckBootstrapWatchdog => {
+                handle_check_bootstrap_watchdog(self, event_loop);
+            }
             ScheduledTask::CheckLookupTimeout(trans_id) => {
                 handle_check_lookup_timeout(self, event_loop, trans_id);
             }
             ScheduledTask::CheckLookupEndGame(trans_id) => {
                 handle_check_lookup_endgame(self, event_loop, trans_id);
             }
+            ScheduledTask::CheckItemTimeout(trans_id) => {
+                handle_check_item_timeout(self, event_loop, trans_id);
+            }
+            ScheduledTask::CheckSampleTimeout(trans_id) => {
+                handle_check_sample_timeout(self, trans_id);
+            }
         }
     }
 }
@@ -263,8 +394,25 @@ fn num_good_nodes(table: &RoutingTable) -> usize {
         .count()
 }
 
+/// Broadcast that a lookup has completed, counting it in our status.
+fn broadcast_lookup_completed<H>(work_storage: &mut DetachedDhtHandler<H>, info_hash: InfoHash) {
+    work_storage.status.lock().unwrap().add_lookup_completed();
+
+    broadcast_dht_event(
+        &mut work_storage.event_notifiers,
+        DhtEvent::LookupCompleted(info_hash),
+    );
+}
+
+/// Update the routing table counters of our status.
+fn update_routing_status<H>(work_storage: &mut DetachedDhtHandler<H>) {
+    let num_nodes = num_good_nodes(&work_storage.routing_table);
+
+    work_storage.status.lock().unwrap().set_nodes(num_nodes);
+}
+
 /// We should rebootstrap if we have a low number of nodes.
-fn should_rebootstrap(table: &RoutingTable) -> bool {
+pub fn should_rebootstrap(table: &RoutingTable) -> bool {
     num_good_nodes(table) <= BOOTSTRAP_GOOD_NODE_THRESHOLD
 }
 
@@ -279,13 +427,17 @@ fn broadcast_bootstrap_completed<H>(
     H: Handshaker,
 {
     // Send notification that the bootstrap has completed.
+    let num_nodes = num_good_nodes(&work_storage.routing_table);
+    work_storage.status.lock().unwrap().set_nodes(num_nodes);
     broadcast_dht_event(
         &mut work_storage.event_notifiers,
-        DhtEvent::BootstrapCompleted,
+        DhtEvent::BootstrapCompleted { nodes: num_nodes },
     );
 
     // Indicates we are out of the bootstrapping phase
     work_storage.bootstrapping = false;
+    work_storage.bootstrapped = true;
+    work_storage.watchdog.reset();
 
     // Remove the bootstrap action from our table actions
     table_actions.remove(&action_id);
@@ -294,13 +446,15 @@ fn broadcast_bootstrap_completed<H>(
     let mut future_actions = work_storage.future_actions.split_off(0);
     for table_action in future_actions.drain(..) {
         match table_action {
-            PostBootstrapAction::Lookup(info_hash, should_announce) => {
+            PostBootstrapAction::Lookup(info_hash, should_announce, should_scrape, opt_search) => {
                 handle_start_lookup(
                     table_actions,
                     work_storage,
                     event_loop,
                     info_hash,
                     should_announce,
+                    should_scrape,
+                    opt_search,
                 );
             }
             PostBootstrapAction::Refresh(refresh, trans_id) => {
@@ -308,11 +462,41 @@ fn broadcast_bootstrap_completed<H>(
 
                 handle_check_table_refresh(table_actions, work_storage, event_loop, trans_id);
             }
+            PostBootstrapAction::Item(operation) => {
+                handle_start_item_lookup(table_actions, work_storage, event_loop, operation);
+            }
+        }
+    }
+}
+
+/// Remove the lookup for the given action if its search is no longer being listened to.
+///
+/// Returns true if the lookup was cancelled.
+fn remove_cancelled_lookup<H>(
+    table_actions: &mut HashMap<ActionID, TableAction>,
+    action_id: ActionID,
+    event_loop: &mut EventLoop<DhtHandler<H>>,
+) -> bool
+where
+    H: Handshaker,
+{
+    let cancelled = match table_actions.get(&action_id) {
+        Some(&TableAction::Lookup(ref lookup)) => lookup.is_cancelled(),
+        _ => false,
+    };
+
+    if cancelled {
+        if let Some(TableAction::Lookup(mut lookup)) = table_actions.remove(&action_id) {
+            info!("bittorrent-protocol_dht: Search was dropped, cancelling its TableLookup...");
+            lookup.cancel(event_loop);
         }
     }
+
+    cancelled
 }
 
 /// Attempt to rebootstrap or shutdown the dht if we have no nodes after rebootstrapping multiple time.
+/// Once our initial bootstrap completed, we keep running without nodes and leave it to the watchdog.
 /// Returns None if the DHT is shutting down, Some(true) if the rebootstrap process started, Some(false) if a rebootstrap is not necessary.
 fn attempt_rebootstrap<H>(
     bootstrap: &mut TableBootstrap,
@@ -333,7 +517,7 @@ where
 
     // Check if we reached the maximum bootstrap attempts
     if *attempts >= MAX_BOOTSTRAP_ATTEMPTS {
-        if num_good_nodes(&work_storage.routing_table) == 0 {
+        if num_good_nodes(&work_storage.routing_table) == 0 && !work_storage.bootstrapped {
             // Failed to get any nodes in the rebootstrap attempts, shut down
             shutdown_event_loop(event_loop, ShutdownCause::BootstrapFailed);
             None
@@ -373,17 +557,24 @@ fn handle_incoming<H>(
 {
     let (work_storage, table_actions) = (&mut handler.detached, &mut handler.table_actions);
 
-    // Parse the buffer as a bencoded message
-    let bencode = if let Ok(b) = Bencode::decode(buffer) {
-        b
-    } else {
-        warn!("bittorrent-protocol_dht: Received invalid bencode data...");
+    // Drop anything from banned ips before spending any time on it
+    if work_storage.limiter.is_banned(addr.ip()) {
+        work_storage.status.lock().unwrap().add_query_dropped();
         return;
+    }
+
+    // Parse the buffer as a bencoded message
+    let bencode = match krpc::decode_bencode(buffer) {
+        Ok(b) => b,
+        Err(error) => {
+            handle_krpc_error(work_storage, event_loop, error, addr);
+            return;
+        }
     };
 
     // Parse the bencode as a message
     // Check to make sure we issued the transaction id (or that it is still valid)
-    let message = MessageType::new(&bencode, |trans| {
+    let message = krpc::decode_message(&bencode, |trans| {
         // Check if we can interpret the response transaction id as one of ours.
         let trans_id = if let Some(t) = TransactionID::from_bytes(trans) {
             t
@@ -396,18 +587,67 @@ fn handle_incoming<H>(
             Some(&TableAction::Lookup(_)) => ExpectedResponse::GetPeers,
             Some(&TableAction::Refresh(_)) => ExpectedResponse::FindNode,
             Some(&TableAction::Bootstrap(_, _)) => ExpectedResponse::FindNode,
+            Some(&TableAction::Item(ref item)) => item.expected_response(&trans_id),
+            Some(&TableAction::Sample(_)) => ExpectedResponse::SampleInfoHashes,
             None => ExpectedResponse::None,
         }
     });
 
-    // Do not process requests if we are read only
-    // TODO: Add read only flags to messages we send it we are read only!
-    // Also, check for read only flags on responses we get before adding nodes
-    // to our RoutingTable.
-    if work_storage.read_only {
-        match message {
-            Ok(MessageType::Request(_)) => return,
-            _ => (),
+    // Remote nodes flooding us with queries do not get answered, not even with errors
+    let is_query = match message {
+        Ok(MessageType::Request(_)) => true,
+        Err(ref error) => error.error_message().is_some(),
+        _ => false,
+    };
+    if is_query {
+        match work_storage.limiter.check_query(addr.ip()) {
+            QueryCheck::Allow => (),
+            QueryCheck::Drop => {
+                work_storage.status.lock().unwrap().add_query_dropped();
+                return;
+            }
+            QueryCheck::Ban => {
+                info!(
+                    "bittorrent-protocol_dht: Banning {} for flooding us with queries...",
+                    addr.ip()
+                );
+                handle_ban(work_storage, addr);
+                work_storage.status.lock().unwrap().add_query_dropped();
+                return;
+            }
+        }
+    }
+
+    // Do not process requests if we are read only (BEP 43)
+    if work_storage.read_only.load(Ordering::Relaxed) {
+        if let Ok(MessageType::Request(ref request)) = message {
+            if work_storage.read_only_queries == ReadOnlyQueries::Reject {
+                let error_msg = ErrorMessage::new(
+                    request.transaction_id().to_vec(),
+                    ErrorCode::ServerError,
+                    "Node Is Read Only".to_owned(),
+                );
+
+                if work_storage
+                    .out_channel
+                    .send((error_msg.encode(), addr))
+                    .is_err()
+                {
+                    error!("bittorrent-protocol_dht: Failed to send a read only error on the out channel...");
+                    shutdown_event_loop(event_loop, ShutdownCause::Unspecified);
+                }
+            }
+
+            return;
+        }
+    }
+    // Read only nodes do not want to end up in our routing table
+    let remote_read_only = message::is_read_only(&bencode);
+
+    // Remote nodes tell us what our address looks like to them in their responses
+    if let Ok(MessageType::Response(_)) = message {
+        if let Some(requester_addr) = message::requester_addr(&bencode) {
+            handle_requester_addr(work_storage, addr, requester_addr);
         }
     }
 
@@ -418,14 +658,11 @@ fn handle_incoming<H>(
             let node = Node::as_good(p.node_id(), addr);
 
             // Node requested from us, mark it in the Routingtable
-            work_storage
-                .routing_table
-                .find_node(&node)
-                .map(|n| n.remote_request());
+            mark_remote_request(&work_storage.routing_table, &node, remote_read_only);
 
             let ping_rsp =
                 PingResponse::new(p.transaction_id(), work_storage.routing_table.node_id());
-            let ping_msg = ping_rsp.encode();
+            let ping_msg = message::add_requester_addr(ping_rsp.encode(), addr);
 
             if work_storage.out_channel.send((ping_msg, addr)).is_err() {
                 error!(
@@ -439,28 +676,19 @@ fn handle_incoming<H>(
             let node = Node::as_good(f.node_id(), addr);
 
             // Node requested from us, mark it in the Routingtable
-            work_storage
-                .routing_table
-                .find_node(&node)
-                .map(|n| n.remote_request());
+            mark_remote_request(&work_storage.routing_table, &node, remote_read_only);
 
             // Grab the closest nodes
-            let mut closest_nodes_bytes = Vec::with_capacity(26 * 8);
-            for node in work_storage
-                .routing_table
-                .closest_nodes(f.target_id())
-                .take(8)
-            {
-                closest_nodes_bytes.extend_from_slice(&node.encode());
-            }
+            let (nodes_bytes, nodes6_bytes) =
+                closest_nodes_bytes(work_storage, f.target_id(), addr, f.want());
 
             let find_node_rsp = FindNodeResponse::new(
                 f.transaction_id(),
                 work_storage.routing_table.node_id(),
-                &closest_nodes_bytes,
-            )
-            .unwrap();
-            let find_node_msg = find_node_rsp.encode();
+                compact_nodes(&nodes_bytes, false),
+                compact_nodes(&nodes6_bytes, true),
+            );
+            let find_node_msg = message::add_requester_addr(find_node_rsp.encode(), addr);
 
             if work_storage
                 .out_channel
@@ -476,79 +704,52 @@ fn handle_incoming<H>(
             let node = Node::as_good(g.node_id(), addr);
 
             // Node requested from us, mark it in the Routingtable
-            work_storage
-                .routing_table
-                .find_node(&node)
-                .map(|n| n.remote_request());
-
-            // TODO: Move socket address serialization code into use crate::util
-            // TODO: Check what the maximum number of values we can give without overflowing a udp packet
-            // Also, if we arent going to give all of the contacts, we may want to shuffle which ones we give
-            let mut contact_info_bytes = Vec::with_capacity(6 * 20);
-            work_storage
-                .active_stores
-                .find_items(&g.info_hash(), |addr| {
-                    let mut bytes = [0u8; 6];
-                    let port = addr.port();
-
-                    match addr {
-                        SocketAddr::V4(v4_addr) => {
-                            for (src, dst) in convert::ipv4_to_bytes_be(*v4_addr.ip())
-                                .iter()
-                                .zip(bytes.iter_mut())
-                            {
-                                *dst = *src;
-                            }
-                        }
-                        SocketAddr::V6(_) => {
-                            error!("AnnounceStorage contained an IPv6 Address...");
-                            return;
-                        }
-                    };
+            mark_remote_request(&work_storage.routing_table, &node, remote_read_only);
 
-                    bytes[4] = (port >> 8) as u8;
-                    bytes[5] = (port & 0x00FF) as u8;
-
-                    contact_info_bytes.extend_from_slice(&bytes);
-                });
+            // Storage gives us a random subset of the contacts that fits in the response
+            let contact_info_bytes: Vec<Vec<u8>> = work_storage
+                .active_stores
+                .find_items(&g.info_hash())
+                .into_iter()
+                .map(compact_info::compact_addr)
+                .collect();
             // Grab the bencoded list (ugh, we really have to do this, better apis I say!!!)
-            let mut contact_info_bencode = Vec::with_capacity(contact_info_bytes.len() / 6);
-            for chunk_index in 0..(contact_info_bytes.len() / 6) {
-                let (start, end) = (chunk_index * 6, chunk_index * 6 + 6);
-
-                contact_info_bencode.push(dht_ben_bytes!(&contact_info_bytes[start..end]));
-            }
+            let contact_info_bencode: Vec<Bencode> = contact_info_bytes
+                .iter()
+                .map(|bytes| dht_ben_bytes!(&bytes[..]))
+                .collect();
 
             // Grab the closest nodes
-            let mut closest_nodes_bytes = Vec::with_capacity(26 * 8);
-            for node in work_storage
-                .routing_table
-                .closest_nodes(g.info_hash())
-                .take(8)
-            {
-                closest_nodes_bytes.extend_from_slice(&node.encode());
-            }
+            let (nodes_bytes, nodes6_bytes) =
+                closest_nodes_bytes(work_storage, g.info_hash().into(), addr, g.want());
 
             // Wrap up the nodes/values we are going to be giving them
             let token = work_storage
                 .token_store
                 .checkout(IpAddr::from_socket_addr(addr));
-            let comapct_info_type = if !contact_info_bencode.is_empty() {
-                CompactInfoType::Both(
-                    CompactNodeInfo::new(&closest_nodes_bytes).unwrap(),
-                    CompactValueInfo::new(&contact_info_bencode).unwrap(),
-                )
+            let opt_values = if !contact_info_bencode.is_empty() {
+                Some(CompactValueInfo::new(&contact_info_bencode).unwrap())
+            } else {
+                None
+            };
+
+            // Give them bloom filters of the contacts if they asked for them
+            let opt_scrape = if g.scrape() {
+                Some(work_storage.active_stores.scrape_items(&g.info_hash()))
             } else {
-                CompactInfoType::Nodes(CompactNodeInfo::new(&closest_nodes_bytes).unwrap())
+                None
             };
 
             let get_peers_rsp = GetPeersResponse::new(
                 g.transaction_id(),
                 work_storage.routing_table.node_id(),
                 Some(token.as_ref()),
-                comapct_info_type,
+                compact_nodes(&nodes_bytes, false),
+                compact_nodes(&nodes6_bytes, true),
+                opt_values,
+                opt_scrape,
             );
-            let get_peers_msg = get_peers_rsp.encode();
+            let get_peers_msg = message::add_requester_addr(get_peers_rsp.encode(), addr);
 
             if work_storage
                 .out_channel
@@ -564,10 +765,7 @@ fn handle_incoming<H>(
             let node = Node::as_good(a.node_id(), addr);
 
             // Node requested from us, mark it in the Routingtable
-            work_storage
-                .routing_table
-                .find_node(&node)
-                .map(|n| n.remote_request());
+            mark_remote_request(&work_storage.routing_table, &node, remote_read_only);
 
             // Validate the token
             let is_valid = match Token::new(a.token()) {
@@ -605,11 +803,15 @@ fn handle_incoming<H>(
                 .encode()
             } else if work_storage
                 .active_stores
-                .add_item(a.info_hash(), connect_addr)
+                .add_item(a.info_hash(), connect_addr, a.seed())
             {
                 // Node successfully stored the value with us, send an announce response
-                AnnouncePeerResponse::new(a.transaction_id(), work_storage.routing_table.node_id())
-                    .encode()
+                let announce_rsp = AnnouncePeerResponse::new(
+                    a.transaction_id(),
+                    work_storage.routing_table.node_id(),
+                );
+
+                message::add_requester_addr(announce_rsp.encode(), addr)
             } else {
                 // Node unsuccessfully stored the value with us, send them an error message
                 // TODO: Spec doesnt actually say what error message to send, or even if we should send one...
@@ -629,6 +831,148 @@ fn handle_incoming<H>(
                 error!("bittorrent-protocol_dht: Failed to send an announce peer response on the out channel...");
                 shutdown_event_loop(event_loop, ShutdownCause::Unspecified);
             }
+            update_storage_status(work_storage);
+        }
+        Ok(MessageType::Request(RequestType::GetData(g))) => {
+            info!("bittorrent-protocol_dht: Received a GetDataRequest...");
+            let node = Node::as_good(g.node_id(), addr);
+
+            // Node requested from us, mark it in the Routingtable
+            mark_remote_request(&work_storage.routing_table, &node, remote_read_only);
+
+            // Grab the closest nodes
+            let (nodes_bytes, nodes6_bytes) =
+                closest_nodes_bytes(work_storage, g.target(), addr, None);
+
+            let token = work_storage
+                .token_store
+                .checkout(IpAddr::from_socket_addr(addr));
+
+            // Values of the items we store were valid bencode when they were put
+            let opt_stored = work_storage.active_items.find_item(&g.target()).cloned();
+            let opt_value = match opt_stored {
+                Some(StoredItem::Immutable(ref item)) => Bencode::decode(item.value()).ok(),
+                Some(StoredItem::Mutable(ref item)) => Bencode::decode(item.value()).ok(),
+                None => None,
+            };
+            let item_info = match (&opt_stored, &opt_value) {
+                (&Some(StoredItem::Immutable(_)), &Some(ref value)) => {
+                    Some(ItemInfo::Immutable(value))
+                }
+                (&Some(StoredItem::Mutable(ref item)), &Some(ref value)) => {
+                    // Leave out the value if they already have it
+                    if g.seq().map(|seq| seq >= item.seq()).unwrap_or(false) {
+                        Some(ItemInfo::Seq(item.seq()))
+                    } else {
+                        Some(ItemInfo::Mutable(
+                            &item.public_key()[..],
+                            item.seq(),
+                            &item.signature()[..],
+                            value,
+                        ))
+                    }
+                }
+                _ => None,
+            };
+
+            let get_data_rsp = GetDataResponse::new(
+                g.transaction_id(),
+                work_storage.routing_table.node_id(),
+                Some(token.as_ref()),
+                compact_nodes(&nodes_bytes, false),
+                compact_nodes(&nodes6_bytes, true),
+                item_info,
+            );
+            let get_data_msg = message::add_requester_addr(get_data_rsp.encode(), addr);
+
+            if work_storage.out_channel.send((get_data_msg, addr)).is_err() {
+                error!("bittorrent-protocol_dht: Failed to send a get data response on the out channel...");
+                shutdown_event_loop(event_loop, ShutdownCause::Unspecified);
+            }
+        }
+        Ok(MessageType::Request(RequestType::PutData(p))) => {
+            info!("bittorrent-protocol_dht: Received a PutDataRequest...");
+            let node = Node::as_good(p.node_id(), addr);
+
+            // Node requested from us, mark it in the Routingtable
+            mark_remote_request(&work_storage.routing_table, &node, remote_read_only);
+
+            // Validate the token
+            let is_valid = match Token::new(p.token()) {
+                Ok(t) => work_storage
+                    .token_store
+                    .checkin(IpAddr::from_socket_addr(addr), t),
+                Err(_) => false,
+            };
+
+            let response_msg = if !is_valid {
+                warn!("bittorrent-protocol_dht: Remote node sent us an invalid token for a PutDataRequest...");
+                ErrorMessage::new(
+                    p.transaction_id().to_vec(),
+                    ErrorCode::ProtocolError,
+                    "Received An Invalid Token".to_owned(),
+                )
+                .encode()
+            } else {
+                match store_put_item(&mut work_storage.active_items, &p, addr.ip()) {
+                    Ok(()) => {
+                        let put_data_rsp = PutDataResponse::new(
+                            p.transaction_id(),
+                            work_storage.routing_table.node_id(),
+                        );
+
+                        message::add_requester_addr(put_data_rsp.encode(), addr)
+                    }
+                    Err((code, message)) => {
+                        warn!(
+                            "bittorrent-protocol_dht: Failed to store an item for a PutDataRequest: {}...",
+                            message
+                        );
+                        ErrorMessage::new(p.transaction_id().to_vec(), code, message.to_owned())
+                            .encode()
+                    }
+                }
+            };
+
+            if work_storage.out_channel.send((response_msg, addr)).is_err() {
+                error!("bittorrent-protocol_dht: Failed to send a put data response on the out channel...");
+                shutdown_event_loop(event_loop, ShutdownCause::Unspecified);
+            }
+        }
+        Ok(MessageType::Request(RequestType::SampleInfoHashes(r))) => {
+            info!("bittorrent-protocol_dht: Received a SampleInfoHashesRequest...");
+            let node = Node::as_good(r.node_id(), addr);
+
+            // Node requested from us, mark it in the Routingtable
+            mark_remote_request(&work_storage.routing_table, &node, remote_read_only);
+
+            // Grab the closest nodes
+            let (nodes_bytes, nodes6_bytes) =
+                closest_nodes_bytes(work_storage, r.target(), addr, None);
+
+            let (samples, interval, num) = work_storage
+                .sample_cache
+                .samples(&mut work_storage.active_stores);
+            let mut samples_bytes = Vec::with_capacity(samples.len() * 20);
+            for info_hash in samples {
+                samples_bytes.extend_from_slice(&info_hash.truncated());
+            }
+
+            let sample_rsp = SampleInfoHashesResponse::new(
+                r.transaction_id(),
+                work_storage.routing_table.node_id(),
+                Some(interval),
+                compact_nodes(&nodes_bytes, false),
+                compact_nodes(&nodes6_bytes, true),
+                Some(num as i64),
+                Some(&samples_bytes),
+            );
+            let sample_msg = message::add_requester_addr(sample_rsp.encode(), addr);
+
+            if work_storage.out_channel.send((sample_msg, addr)).is_err() {
+                error!("bittorrent-protocol_dht: Failed to send a sample infohashes response on the out channel...");
+                shutdown_event_loop(event_loop, ShutdownCause::Unspecified);
+            }
         }
         Ok(MessageType::Response(ResponseType::FindNode(f))) => {
             info!("bittorrent-protocol_dht: Received a FindNodeResponse...");
@@ -636,13 +980,15 @@ fn handle_incoming<H>(
             let node = Node::as_good(f.node_id(), addr);
 
             // Add the payload nodes as questionable
-            for (id, v4_addr) in f.nodes() {
-                let sock_addr = SocketAddr::V4(v4_addr);
-
+            for (id, sock_addr) in compact_info::same_family(&addr, f.nodes(), f.nodes6())
+                .into_iter()
+                .flatten()
+            {
                 work_storage
                     .routing_table
                     .add_node(Node::as_questionable(id, sock_addr));
             }
+            add_other_family_nodes(work_storage, addr, f.nodes(), f.nodes6());
 
             let bootstrap_complete = {
                 let opt_bootstrap = match table_actions.get_mut(&trans_id.action_id()) {
@@ -660,6 +1006,14 @@ fn handle_incoming<H>(
                         error!("bittorrent-protocol_dht: Resolved a FindNodeResponse ActionID to a TableLookup...");
                         None
                     }
+                    Some(&mut TableAction::Item(_)) => {
+                        error!("bittorrent-protocol_dht: Resolved a FindNodeResponse ActionID to an ItemLookup...");
+                        None
+                    }
+                    Some(&mut TableAction::Sample(_)) => {
+                        error!("bittorrent-protocol_dht: Resolved a FindNodeResponse ActionID to a SampleQuery...");
+                        None
+                    }
                     None => {
                         error!(
                             "bittorrent-protocol_dht: Resolved a TransactionID to a FindNodeResponse but no \
@@ -734,6 +1088,11 @@ fn handle_incoming<H>(
             let node = Node::as_good(g.node_id(), addr);
 
             work_storage.routing_table.add_node(node.clone());
+            add_other_family_nodes(work_storage, addr, g.nodes(), g.nodes6());
+
+            if remove_cancelled_lookup(table_actions, trans_id.action_id(), event_loop) {
+                return;
+            }
 
             let opt_lookup = {
                 match table_actions.get_mut(&trans_id.action_id()) {
@@ -752,6 +1111,14 @@ fn handle_incoming<H>(
                         );
                         None
                     }
+                    Some(&mut TableAction::Item(_)) => {
+                        error!("bittorrent-protocol_dht: Resolved a GetPeersResponse ActionID to an ItemLookup...");
+                        None
+                    }
+                    Some(&mut TableAction::Sample(_)) => {
+                        error!("bittorrent-protocol_dht: Resolved a GetPeersResponse ActionID to a SampleQuery...");
+                        None
+                    }
                     None => {
                         error!(
                             "bittorrent-protocol_dht: Resolved a TransactionID to a GetPeersResponse but no \
@@ -772,16 +1139,14 @@ fn handle_incoming<H>(
                     event_loop,
                 ) {
                     LookupStatus::Searching => (),
-                    LookupStatus::Completed => broadcast_dht_event(
-                        &mut work_storage.event_notifiers,
-                        DhtEvent::LookupCompleted(lookup.info_hash()),
-                    ),
+                    LookupStatus::Completed => {
+                        broadcast_lookup_completed(work_storage, lookup.info_hash())
+                    }
                     LookupStatus::Failed => {
                         shutdown_event_loop(event_loop, ShutdownCause::Unspecified)
                     }
                     LookupStatus::Values(values) => {
-                        for v4_addr in values {
-                            let sock_addr = SocketAddr::V4(v4_addr);
+                        for sock_addr in values {
                             work_storage
                                 .handshaker
                                 .connect(None, lookup.info_hash(), sock_addr);
@@ -798,6 +1163,103 @@ fn handle_incoming<H>(
         Ok(MessageType::Response(ResponseType::AnnouncePeer(_))) => {
             info!("bittorrent-protocol_dht: Received an AnnouncePeerResponse...");
         }
+        Ok(MessageType::Response(ResponseType::GetData(g))) => {
+            info!("bittorrent-protocol_dht: Received a GetDataResponse...");
+            let trans_id = TransactionID::from_bytes(g.transaction_id()).unwrap();
+            let node = Node::as_good(g.node_id(), addr);
+
+            work_storage.routing_table.add_node(node);
+            add_other_family_nodes(work_storage, addr, g.nodes(), g.nodes6());
+
+            let opt_item_status = match table_actions.get_mut(&trans_id.action_id()) {
+                Some(&mut TableAction::Item(ref mut item)) => Some(item.recv_get_response(
+                    &trans_id,
+                    g,
+                    &work_storage.routing_table,
+                    &work_storage.out_channel,
+                    event_loop,
+                )),
+                _ => {
+                    error!(
+                        "bittorrent-protocol_dht: Resolved a TransactionID to a GetDataResponse but no \
+                            item lookup found..."
+                    );
+                    None
+                }
+            };
+
+            if let Some(status) = opt_item_status {
+                handle_item_status(
+                    table_actions,
+                    work_storage,
+                    event_loop,
+                    trans_id.action_id(),
+                    status,
+                );
+            }
+        }
+        Ok(MessageType::Response(ResponseType::PutData(p))) => {
+            info!("bittorrent-protocol_dht: Received a PutDataResponse...");
+            let trans_id = TransactionID::from_bytes(p.transaction_id()).unwrap();
+
+            let opt_item_status = match table_actions.get_mut(&trans_id.action_id()) {
+                Some(&mut TableAction::Item(ref mut item)) => {
+                    Some(item.recv_put_response(&trans_id, event_loop))
+                }
+                _ => {
+                    error!(
+                        "bittorrent-protocol_dht: Resolved a TransactionID to a PutDataResponse but no \
+                            item lookup found..."
+                    );
+                    None
+                }
+            };
+
+            if let Some(status) = opt_item_status {
+                handle_item_status(
+                    table_actions,
+                    work_storage,
+                    event_loop,
+                    trans_id.action_id(),
+                    status,
+                );
+            }
+        }
+        Ok(MessageType::Response(ResponseType::SampleInfoHashes(r))) => {
+            info!("bittorrent-protocol_dht: Received a SampleInfoHashesResponse...");
+            let trans_id = TransactionID::from_bytes(r.transaction_id()).unwrap();
+            let node = Node::as_good(r.node_id(), addr);
+
+            match table_actions.remove(&trans_id.action_id()) {
+                Some(TableAction::Sample(query)) => {
+                    work_storage.routing_table.add_node(node);
+
+                    // Add the payload nodes as questionable
+                    for (id, sock_addr) in compact_info::same_family(&addr, r.nodes(), r.nodes6())
+                        .into_iter()
+                        .flatten()
+                    {
+                        work_storage
+                            .routing_table
+                            .add_node(Node::as_questionable(id, sock_addr));
+                    }
+                    add_other_family_nodes(work_storage, addr, r.nodes(), r.nodes6());
+
+                    let interval = query.recv_response(&trans_id, &r, event_loop);
+                    work_storage.sample_intervals.insert(addr, interval);
+                }
+                Some(action) => {
+                    error!("bittorrent-protocol_dht: Resolved a SampleInfoHashesResponse ActionID to an action other than a SampleQuery...");
+                    table_actions.insert(trans_id.action_id(), action);
+                }
+                None => {
+                    error!(
+                        "bittorrent-protocol_dht: Resolved a TransactionID to a SampleInfoHashesResponse but no \
+                            action found..."
+                    );
+                }
+            }
+        }
         Ok(MessageType::Error(e)) => {
             info!("bittorrent-protocol_dht: Received an ErrorMessage...");
 
@@ -805,50 +1267,397 @@ fn handle_incoming<H>(
                 "bittorrent-protocol_dht: KRPC error message from {:?}: {:?}",
                 addr, e
             );
+
+            // Item lookups treat errors like requests that were never answered
+            if let Some(trans_id) = TransactionID::from_bytes(e.transaction_id()) {
+                if let Some(&TableAction::Sample(_)) = table_actions.get(&trans_id.action_id()) {
+                    if let Some(TableAction::Sample(query)) =
+                        table_actions.remove(&trans_id.action_id())
+                    {
+                        query.recv_error(&e, event_loop);
+                    }
+                    return;
+                }
+
+                let opt_item_status = match table_actions.get_mut(&trans_id.action_id()) {
+                    Some(&mut TableAction::Item(ref mut item)) => Some(item.recv_timeout(
+                        &trans_id,
+                        &work_storage.routing_table,
+                        &work_storage.out_channel,
+                        event_loop,
+                    )),
+                    _ => None,
+                };
+
+                if let Some(status) = opt_item_status {
+                    handle_item_status(
+                        table_actions,
+                        work_storage,
+                        event_loop,
+                        trans_id.action_id(),
+                        status,
+                    );
+                }
+            }
         }
-        Err(e) => {
-            warn!(
-                "bittorrent-protocol_dht: Error parsing KRPC message: {:?}",
-                e
-            );
-        }
+        Err(error) => handle_krpc_error(work_storage, event_loop, error, addr),
     }
 }
 
-fn handle_register_sender<H>(handler: &mut DhtHandler<H>, sender: mpsc::Sender<DhtEvent>) {
-    handler.detached.event_notifiers.push(sender);
-}
-
-fn handle_start_bootstrap<H>(
-    handler: &mut DhtHandler<H>,
+/// Answer a message we could not decode with a KRPC error if it was a query we can answer,
+/// otherwise drop it.
+fn handle_krpc_error<H>(
+    work_storage: &mut DetachedDhtHandler<H>,
     event_loop: &mut EventLoop<DhtHandler<H>>,
-    routers: Vec<Router>,
-    nodes: Vec<SocketAddr>,
+    error: KrpcError,
+    addr: SocketAddr,
 ) where
     H: Handshaker,
 {
-    let (work_storage, table_actions) = (&mut handler.detached, &mut handler.table_actions);
+    warn!(
+        "bittorrent-protocol_dht: Error parsing KRPC message from {}: {}",
+        addr, error
+    );
 
-    let router_iter = routers
-        .into_iter()
-        .filter_map(|r| r.ipv4_addr().ok().map(|v4| SocketAddr::V4(v4)));
+    // Responses to transactions that timed out are not the fault of the remote node
+    if error.is_malformed() {
+        handle_malformed(work_storage, addr);
+    }
 
-    let mid_generator = work_storage.aid_generator.generate();
-    let action_id = mid_generator.action_id();
-    let mut table_bootstrap = TableBootstrap::new(
-        work_storage.routing_table.node_id(),
-        mid_generator,
-        nodes,
-        router_iter,
-    );
+    // Read only nodes ignoring queries do not answer broken ones either
+    let answer_queries = !work_storage.read_only.load(Ordering::Relaxed)
+        || work_storage.read_only_queries == ReadOnlyQueries::Reject;
 
-    // Begin the bootstrap operation
-    let bootstrap_status = table_bootstrap.start_bootstrap(&work_storage.out_channel, event_loop);
+    match error.error_message() {
+        Some(error_msg) if answer_queries => {
+            if work_storage
+                .out_channel
+                .send((error_msg.encode(), addr))
+                .is_err()
+            {
+                error!(
+                    "bittorrent-protocol_dht: Failed to send a KRPC error on the out channel..."
+                );
+                shutdown_event_loop(event_loop, ShutdownCause::Unspecified);
+            }
+        }
+        _ => work_storage.status.lock().unwrap().add_malformed_dropped(),
+    }
+}
 
-    work_storage.bootstrapping = true;
-    table_actions.insert(action_id, TableAction::Bootstrap(table_bootstrap, 0));
+/// Update the announce storage counters of our status.
+fn update_storage_status<H>(work_storage: &mut DetachedDhtHandler<H>) {
+    let num_info_hashes = work_storage.active_stores.num_info_hashes();
+    let num_items = work_storage.active_stores.num_items();
 
-    let bootstrap_complete = match bootstrap_status {
+    work_storage
+        .status
+        .lock()
+        .unwrap()
+        .set_storage(num_info_hashes, num_items);
+}
+
+/// Count a malformed message against the remote ip, banning it if it keeps sending them.
+fn handle_malformed<H>(work_storage: &mut DetachedDhtHandler<H>, addr: SocketAddr) {
+    if work_storage.limiter.malformed(addr.ip()) {
+        info!(
+            "bittorrent-protocol_dht: Banning {} for sending us malformed messages...",
+            addr.ip()
+        );
+        handle_ban(work_storage, addr);
+    }
+}
+
+fn handle_ban<H>(work_storage: &mut DetachedDhtHandler<H>, addr: SocketAddr) {
+    let ban_duration = work_storage.limiter.ban_duration();
+
+    work_storage.routing_table.ban_ip(addr.ip(), ban_duration);
+    work_storage.status.lock().unwrap().add_ip_banned();
+}
+
+/// Record that the node sent us a request, unless it is a read only node.
+///
+/// Read only nodes do not answer our requests, keeping them around would only slow down our
+/// lookups (BEP 43).
+fn mark_remote_request(table: &RoutingTable, node: &Node, read_only: bool) {
+    if !read_only {
+        table.find_node(node).map(|n| n.remote_request());
+    }
+}
+
+/// Compact IPv4 and IPv6 nodes closest to the target to give back to the requester at the given
+/// address, only including the address families the requester wants (BEP 32).
+fn closest_nodes_bytes<H>(
+    work_storage: &DetachedDhtHandler<H>,
+    target: NodeId,
+    addr: SocketAddr,
+    opt_want: Option<Want>,
+) -> (Option<Vec<u8>>, Option<Vec<u8>>) {
+    let (v4_table, v6_table) = if work_storage.ipv6 {
+        (&work_storage.other_table, &work_storage.routing_table)
+    } else {
+        (&work_storage.routing_table, &work_storage.other_table)
+    };
+    let want = opt_want.unwrap_or(if addr.is_ipv6() { Want::V6 } else { Want::V4 });
+
+    let encode_closest = |table: &RoutingTable, node_len: usize| {
+        let mut closest_nodes_bytes = Vec::with_capacity(node_len * 8);
+        for node in table.closest_nodes(target).take(8) {
+            closest_nodes_bytes.extend_from_slice(&node.encode());
+        }
+
+        closest_nodes_bytes
+    };
+
+    (
+        if want.v4() {
+            Some(encode_closest(v4_table, 26))
+        } else {
+            None
+        },
+        if want.v6() {
+            Some(encode_closest(v6_table, 38))
+        } else {
+            None
+        },
+    )
+}
+
+/// Panics if the bytes were not encoded as IPv4 nodes, or IPv6 nodes if ipv6 is set.
+fn compact_nodes(opt_bytes: &Option<Vec<u8>>, ipv6: bool) -> Option<CompactNodeInfo<'_>> {
+    opt_bytes.as_ref().map(|bytes| {
+        if ipv6 {
+            CompactNodeInfo::new_v6(bytes).unwrap()
+        } else {
+            CompactNodeInfo::new(bytes).unwrap()
+        }
+    })
+}
+
+/// Add the nodes of a response from the given address that are of the other address family
+/// than the node as questionable, so that we can give them out to requesters that want them.
+fn add_other_family_nodes<H>(
+    work_storage: &mut DetachedDhtHandler<H>,
+    addr: SocketAddr,
+    nodes: Option<CompactNodeInfo>,
+    nodes6: Option<CompactNodeInfo>,
+) {
+    for (id, sock_addr) in compact_info::other_family(&addr, nodes, nodes6)
+        .into_iter()
+        .flatten()
+    {
+        work_storage
+            .other_table
+            .add_node(Node::as_questionable(id, sock_addr));
+    }
+}
+
+/// Record the address a remote node saw us as, regenerating our node id if enough nodes
+/// agree on an external ip that our node id was not generated from.
+fn handle_requester_addr<H>(
+    work_storage: &mut DetachedDhtHandler<H>,
+    voter: SocketAddr,
+    requester_addr: SocketAddr,
+) {
+    let new_ip = match work_storage
+        .external_ip_votes
+        .add_vote(voter.ip(), requester_addr.ip())
+    {
+        Some(ip) => ip,
+        None => return,
+    };
+    info!(
+        "bittorrent-protocol_dht: Remote nodes agreed on a new external ip {}...",
+        new_ip
+    );
+
+    let mut node_id = work_storage.routing_table.node_id();
+    if !security::is_generated_from_ip(new_ip, node_id) {
+        node_id = NodeId::from_ip_with_rand(new_ip, rand::random::<u8>());
+
+        info!("bittorrent-protocol_dht: Regenerating our node id for our new external ip...");
+        work_storage.routing_table.set_node_id(node_id);
+        work_storage.other_table.set_node_id(node_id);
+    }
+
+    work_storage
+        .status
+        .lock()
+        .unwrap()
+        .set_identity(node_id, Some(new_ip));
+}
+
+/// Store the item put by a remote node, returning the error to respond with if we can not.
+fn store_put_item(
+    storage: &mut ItemStorage,
+    request: &PutDataRequest,
+    source: std_net::IpAddr,
+) -> Result<(), (ErrorCode, &'static str)> {
+    let value = request.value().encode();
+
+    let res_put = match request.mutable() {
+        Some(mutable) => {
+            if mutable.public_key().len() != item::PUBLIC_KEY_LEN
+                || mutable.signature().len() != item::SIGNATURE_LEN
+            {
+                return Err((
+                    ErrorCode::ProtocolError,
+                    "Public Key Or Signature Has An Invalid Length",
+                ));
+            }
+
+            let mut public_key = [0u8; item::PUBLIC_KEY_LEN];
+            public_key.copy_from_slice(mutable.public_key());
+            let mut signature = [0u8; item::SIGNATURE_LEN];
+            signature.copy_from_slice(mutable.signature());
+
+            let item = MutableItem::from_raw(
+                public_key,
+                mutable.salt().to_vec(),
+                mutable.seq(),
+                signature,
+                value,
+            )
+            .map_err(item_error_response)?;
+            storage.put_mutable(item, mutable.cas(), source)
+        }
+        None => {
+            let item = ImmutableItem::from_raw(value).map_err(item_error_response)?;
+            storage.put_immutable(item, source)
+        }
+    };
+
+    res_put.map_err(|error| match error {
+        PutError::CasMismatch => (ErrorCode::CasMismatch, "CAS Mismatch"),
+        PutError::SeqTooLow => (
+            ErrorCode::SequenceNumberTooLow,
+            "Sequence Number Less Than Current",
+        ),
+        PutError::QuotaExceeded => (ErrorCode::ServerError, "Item Quota Exceeded"),
+        PutError::StorageFull => (ErrorCode::ServerError, "Item Storage Is Full"),
+    })
+}
+
+fn item_error_response(error: ItemError) -> (ErrorCode, &'static str) {
+    match error {
+        ItemError::ValueTooLarge => (ErrorCode::MessageTooBig, "Message (v Field) Too Big"),
+        ItemError::SaltTooLarge => (ErrorCode::SaltTooBig, "Salt (salt Field) Too Big"),
+        ItemError::InvalidSignature => (ErrorCode::InvalidSignature, "Invalid Signature"),
+    }
+}
+
+/// Remove the item lookup for the given action once it is finished, reporting finished puts.
+fn handle_item_status<H>(
+    table_actions: &mut HashMap<ActionID, TableAction>,
+    work_storage: &mut DetachedDhtHandler<H>,
+    event_loop: &mut EventLoop<DhtHandler<H>>,
+    action_id: ActionID,
+    status: ItemStatus,
+) where
+    H: Handshaker,
+{
+    match status {
+        ItemStatus::Searching => (),
+        ItemStatus::Completed => {
+            if let Some(TableAction::Item(item)) = table_actions.remove(&action_id) {
+                if let Some(nodes_stored) = item.nodes_stored() {
+                    broadcast_dht_event(
+                        &mut work_storage.event_notifiers,
+                        DhtEvent::PutCompleted(item.target().into(), nodes_stored),
+                    );
+                }
+            }
+        }
+        ItemStatus::Failed => shutdown_event_loop(event_loop, ShutdownCause::Unspecified),
+    }
+}
+
+fn handle_register_sender<H>(handler: &mut DhtHandler<H>, sender: mpsc::Sender<DhtEvent>) {
+    handler.detached.event_notifiers.push(sender);
+}
+
+fn handle_start_bootstrap<H>(
+    handler: &mut DhtHandler<H>,
+    event_loop: &mut EventLoop<DhtHandler<H>>,
+    routers: Vec<Router>,
+    nodes: Vec<SocketAddr>,
+) where
+    H: Handshaker,
+{
+    let (work_storage, table_actions) = (&mut handler.detached, &mut handler.table_actions);
+
+    let mid_generator = work_storage.aid_generator.generate();
+    let action_id = mid_generator.action_id();
+    let mut table_bootstrap = TableBootstrap::new(
+        work_storage.routing_table.node_id(),
+        mid_generator,
+        nodes.clone(),
+        resolve_routers(&routers, work_storage.ipv6).into_iter(),
+        work_storage.opt_want,
+    );
+    work_storage.bootstrap_routers = routers;
+    work_storage.bootstrap_nodes = nodes;
+
+    // Nodes restored from a saved state may spare us the full bootstrap
+    let bootstrap_status = if work_storage.routing_table.pingable_nodes().next().is_some() {
+        table_bootstrap.start_restore(
+            &work_storage.routing_table,
+            &work_storage.out_channel,
+            event_loop,
+        )
+    } else {
+        table_bootstrap.start_bootstrap(&work_storage.out_channel, event_loop)
+    };
+
+    if work_storage.watchdog.is_enabled() {
+        schedule_bootstrap_watchdog(work_storage, event_loop);
+    }
+
+    run_bootstrap(
+        action_id,
+        table_bootstrap,
+        bootstrap_status,
+        table_actions,
+        work_storage,
+        event_loop,
+    );
+}
+
+/// Resolve the given routers to the addresses of our own address family.
+fn resolve_routers(routers: &[Router], ipv6: bool) -> Vec<SocketAddr> {
+    // Routers may resolve to both address families, only those of our own family are reachable
+    routers
+        .iter()
+        .filter_map(|r| {
+            if ipv6 {
+                r.ipv6_addr().ok().map(|v6| SocketAddr::V6(v6))
+            } else {
+                r.ipv4_addr().ok().map(|v4| SocketAddr::V4(v4))
+            }
+        })
+        .collect()
+}
+
+/// Insert the started bootstrap into our table actions, completing it right away if it is done.
+fn run_bootstrap<H>(
+    action_id: ActionID,
+    table_bootstrap: TableBootstrap,
+    bootstrap_status: BootstrapStatus,
+    table_actions: &mut HashMap<ActionID, TableAction>,
+    work_storage: &mut DetachedDhtHandler<H>,
+    event_loop: &mut EventLoop<DhtHandler<H>>,
+) where
+    H: Handshaker,
+{
+    broadcast_dht_event(
+        &mut work_storage.event_notifiers,
+        DhtEvent::BootstrapStarted,
+    );
+
+    work_storage.bootstrapping = true;
+    table_actions.insert(action_id, TableAction::Bootstrap(table_bootstrap, 0));
+
+    let bootstrap_complete = match bootstrap_status {
         BootstrapStatus::Idle => true,
         BootstrapStatus::Bootstrapping => false,
         BootstrapStatus::Failed => {
@@ -877,23 +1686,136 @@ fn handle_start_bootstrap<H>(
     }
 }
 
+fn handle_add_routers<H>(
+    handler: &mut DhtHandler<H>,
+    event_loop: &mut EventLoop<DhtHandler<H>>,
+    routers: Vec<SocketAddr>,
+) where
+    H: Handshaker,
+{
+    let (work_storage, table_actions) = (&mut handler.detached, &mut handler.table_actions);
+
+    work_storage
+        .bootstrap_routers
+        .extend(routers.iter().map(|&addr| Router::Custom(addr)));
+
+    // A bootstrap that is running can make use of the routers right away
+    for action in table_actions.values_mut() {
+        if let &mut TableAction::Bootstrap(ref mut bootstrap, _) = action {
+            if bootstrap.add_routers(&routers, &work_storage.out_channel, event_loop)
+                == BootstrapStatus::Failed
+            {
+                shutdown_event_loop(event_loop, ShutdownCause::Unspecified);
+            }
+        }
+    }
+}
+
+fn handle_add_node<H>(
+    handler: &mut DhtHandler<H>,
+    event_loop: &mut EventLoop<DhtHandler<H>>,
+    addr: SocketAddr,
+) where
+    H: Handshaker,
+{
+    let (work_storage, table_actions) = (&mut handler.detached, &mut handler.table_actions);
+
+    if addr.is_ipv6() != work_storage.ipv6 {
+        warn!(
+            "bittorrent-protocol_dht: Can not add node {} of the other address family...",
+            addr
+        );
+        return;
+    }
+
+    // Responses to a running bootstrap are only accepted by the bootstrap itself
+    for action in table_actions.values_mut() {
+        let opt_failed = match action {
+            &mut TableAction::Bootstrap(ref mut bootstrap, _) => Some(
+                bootstrap.add_node(addr, &work_storage.out_channel, event_loop)
+                    == BootstrapStatus::Failed,
+            ),
+            &mut TableAction::Refresh(ref mut refresh) if !work_storage.bootstrapping => {
+                match refresh.ping_node(
+                    addr,
+                    &work_storage.routing_table,
+                    &work_storage.out_channel,
+                ) {
+                    RefreshStatus::Refreshing => Some(false),
+                    RefreshStatus::Failed => Some(true),
+                }
+            }
+            _ => None,
+        };
+
+        if let Some(failed) = opt_failed {
+            if failed {
+                shutdown_event_loop(event_loop, ShutdownCause::Unspecified);
+            }
+            return;
+        }
+    }
+
+    warn!(
+        "bittorrent-protocol_dht: Could not add node {}, no bootstrap or refresh is running...",
+        addr
+    );
+}
+
+fn handle_save_state<H>(handler: &mut DhtHandler<H>, send: mpsc::Sender<DhtState>) {
+    let work_storage = &handler.detached;
+    let state = DhtState::new(
+        &work_storage.routing_table,
+        work_storage.external_ip_votes.current(),
+        work_storage.external_ip_votes.votes(),
+    );
+
+    if send.send(state).is_err() {
+        warn!("bittorrent-protocol_dht: Failed to send our saved state, requester hung up...");
+    }
+}
+
+fn handle_set_read_only<H>(handler: &mut DhtHandler<H>, read_only: bool) {
+    info!(
+        "bittorrent-protocol_dht: Setting the read only flag to {}...",
+        read_only
+    );
+
+    handler
+        .detached
+        .read_only
+        .store(read_only, Ordering::Relaxed);
+}
+
 fn handle_start_lookup<H>(
     table_actions: &mut HashMap<ActionID, TableAction>,
     work_storage: &mut DetachedDhtHandler<H>,
     event_loop: &mut EventLoop<DhtHandler<H>>,
     info_hash: InfoHash,
     should_announce: bool,
+    should_scrape: bool,
+    opt_search: Option<UnboundedSender<SearchEvent>>,
 ) where
     H: Handshaker,
 {
     let mid_generator = work_storage.aid_generator.generate();
     let action_id = mid_generator.action_id();
 
+    if opt_search.as_ref().map(|send| send.is_closed()) == Some(true) {
+        // Search was dropped before we got to start it
+        return;
+    }
+
     if work_storage.bootstrapping {
         // Queue it up if we are currently bootstrapping
         work_storage
             .future_actions
-            .push(PostBootstrapAction::Lookup(info_hash, should_announce));
+            .push(PostBootstrapAction::Lookup(
+                info_hash,
+                should_announce,
+                should_scrape,
+                opt_search,
+            ));
     } else {
         // Start the lookup right now if not bootstrapping
         match TableLookup::new(
@@ -901,18 +1823,103 @@ fn handle_start_lookup<H>(
             info_hash,
             mid_generator,
             should_announce,
+            should_scrape,
+            opt_search,
+            work_storage.opt_want,
+            &work_storage.routing_table,
+            &work_storage.out_channel,
+            event_loop,
+        ) {
+            Some(mut lookup) => {
+                work_storage.status.lock().unwrap().add_lookup_started();
+
+                if lookup.current_lookup_status() == LookupStatus::Completed {
+                    // No nodes to contact, nothing will ever wake the lookup up again
+                    lookup.complete_search();
+                    broadcast_lookup_completed(work_storage, info_hash);
+                } else {
+                    table_actions.insert(action_id, TableAction::Lookup(lookup));
+                }
+            }
+            None => shutdown_event_loop(event_loop, ShutdownCause::Unspecified),
+        }
+    }
+}
+
+fn handle_start_item_lookup<H>(
+    table_actions: &mut HashMap<ActionID, TableAction>,
+    work_storage: &mut DetachedDhtHandler<H>,
+    event_loop: &mut EventLoop<DhtHandler<H>>,
+    operation: ItemOperation,
+) where
+    H: Handshaker,
+{
+    let mid_generator = work_storage.aid_generator.generate();
+    let action_id = mid_generator.action_id();
+
+    if work_storage.bootstrapping {
+        // Queue it up if we are currently bootstrapping
+        work_storage
+            .future_actions
+            .push(PostBootstrapAction::Item(operation));
+    } else {
+        // Start the lookup right now if not bootstrapping
+        match ItemLookup::new(
+            work_storage.routing_table.node_id(),
+            operation,
+            mid_generator,
             &work_storage.routing_table,
             &work_storage.out_channel,
             event_loop,
         ) {
-            Some(lookup) => {
-                table_actions.insert(action_id, TableAction::Lookup(lookup));
+            Some(item) => {
+                // With no nodes to contact, the lookup may have finished already
+                let status = item.current_status();
+
+                table_actions.insert(action_id, TableAction::Item(item));
+                handle_item_status(table_actions, work_storage, event_loop, action_id, status);
             }
             None => shutdown_event_loop(event_loop, ShutdownCause::Unspecified),
         }
     }
 }
 
+fn handle_start_sample<H>(
+    table_actions: &mut HashMap<ActionID, TableAction>,
+    work_storage: &mut DetachedDhtHandler<H>,
+    event_loop: &mut EventLoop<DhtHandler<H>>,
+    addr: SocketAddr,
+    target: NodeId,
+    send: SampleSender,
+) where
+    H: Handshaker,
+{
+    // Respect the interval the node gave us the last time we sampled it
+    if let Some(remaining) = work_storage.sample_intervals.remaining(&addr) {
+        let _ = send.unbounded_send(Err(SampleError::TooEarly(remaining)));
+        return;
+    }
+
+    let mid_generator = work_storage.aid_generator.generate();
+    let action_id = mid_generator.action_id();
+
+    // Sampling a specific node does not need our routing table, so no need to wait on a bootstrap
+    match SampleQuery::new(
+        work_storage.routing_table.node_id(),
+        target,
+        addr,
+        mid_generator,
+        send,
+        &work_storage.out_channel,
+        event_loop,
+    ) {
+        Some(query) => {
+            table_actions.insert(action_id, TableAction::Sample(query));
+        }
+        None => shutdown_event_loop(event_loop, ShutdownCause::Unspecified),
+    }
+}
+
 fn handle_shutdown<H>(
     handler: &mut DhtHandler<H>,
     event_loop: &mut EventLoop<DhtHandler<H>>,
@@ -958,6 +1965,14 @@ fn handle_check_table_refresh<H>(
             );
             None
         }
+        Some(&mut TableAction::Item(_)) => {
+            error!("bittorrent-protocol_dht: Resolved a TransactionID to a check table refresh but ItemLookup found...");
+            None
+        }
+        Some(&mut TableAction::Sample(_)) => {
+            error!("bittorrent-protocol_dht: Resolved a TransactionID to a check table refresh but SampleQuery found...");
+            None
+        }
         None => {
             error!(
                 "bittorrent-protocol_dht: Resolved a TransactionID to a check table refresh but no action \
@@ -967,6 +1982,10 @@ fn handle_check_table_refresh<H>(
         }
     };
 
+    // Contacts expire without anyone touching the storage, keep our counters close to the truth
+    update_storage_status(work_storage);
+    update_routing_status(work_storage);
+
     match opt_refresh_status {
         None => (),
         Some(RefreshStatus::Refreshing) => (),
@@ -1009,6 +2028,14 @@ fn handle_check_bootstrap_timeout<H>(
                 );
                 None
             }
+            Some(&mut TableAction::Item(_)) => {
+                error!("bittorrent-protocol_dht: Resolved a TransactionID to a check table bootstrap but ItemLookup found...");
+                None
+            }
+            Some(&mut TableAction::Sample(_)) => {
+                error!("bittorrent-protocol_dht: Resolved a TransactionID to a check table bootstrap but SampleQuery found...");
+                None
+            }
             None => {
                 error!(
                     "bittorrent-protocol_dht: Resolved a TransactionID to a check table bootstrap but no \
@@ -1048,6 +2075,66 @@ fn handle_check_bootstrap_timeout<H>(
     }
 }
 
+fn handle_check_bootstrap_watchdog<H>(
+    handler: &mut DhtHandler<H>,
+    event_loop: &mut EventLoop<DhtHandler<H>>,
+) where
+    H: Handshaker,
+{
+    let (work_storage, table_actions) = (&mut handler.detached, &mut handler.table_actions);
+
+    // Bootstraps that are running retry on their own
+    if !work_storage.bootstrapping
+        && work_storage
+            .watchdog
+            .check(num_good_nodes(&work_storage.routing_table))
+    {
+        info!(
+            "bittorrent-protocol_dht: Routing table starved of good nodes, bootstrapping again..."
+        );
+
+        let mid_generator = work_storage.aid_generator.generate();
+        let action_id = mid_generator.action_id();
+        let mut table_bootstrap = TableBootstrap::new(
+            work_storage.routing_table.node_id(),
+            mid_generator,
+            work_storage.bootstrap_nodes.clone(),
+            resolve_routers(&work_storage.bootstrap_routers, work_storage.ipv6).into_iter(),
+            work_storage.opt_want,
+        );
+        let bootstrap_status =
+            table_bootstrap.start_bootstrap(&work_storage.out_channel, event_loop);
+
+        run_bootstrap(
+            action_id,
+            table_bootstrap,
+            bootstrap_status,
+            table_actions,
+            work_storage,
+            event_loop,
+        );
+    }
+
+    schedule_bootstrap_watchdog(work_storage, event_loop);
+}
+
+fn schedule_bootstrap_watchdog<H>(
+    work_storage: &DetachedDhtHandler<H>,
+    event_loop: &mut EventLoop<DhtHandler<H>>,
+) where
+    H: Handshaker,
+{
+    if event_loop
+        .timeout_ms(
+            (0, ScheduledTask::CheckBootstrapWatchdog),
+            work_storage.watchdog.check_interval_ms(),
+        )
+        .is_err()
+    {
+        error!("bittorrent-protocol_dht: Failed to set a timeout for the bootstrap watchdog...");
+    }
+}
+
 fn handle_check_lookup_timeout<H>(
     handler: &mut DhtHandler<H>,
     event_loop: &mut EventLoop<DhtHandler<H>>,
@@ -1057,6 +2144,10 @@ fn handle_check_lookup_timeout<H>(
 {
     let (work_storage, table_actions) = (&mut handler.detached, &mut handler.table_actions);
 
+    if remove_cancelled_lookup(table_actions, trans_id.action_id(), event_loop) {
+        return;
+    }
+
     let opt_lookup_info = match table_actions.get_mut(&trans_id.action_id()) {
         Some(&mut TableAction::Lookup(ref mut lookup)) => Some((
             lookup.recv_timeout(
@@ -1081,6 +2172,14 @@ fn handle_check_lookup_timeout<H>(
             );
             None
         }
+        Some(&mut TableAction::Item(_)) => {
+            error!("bittorrent-protocol_dht: Resolved a TransactionID to a check table lookup but ItemLookup found...");
+            None
+        }
+        Some(&mut TableAction::Sample(_)) => {
+            error!("bittorrent-protocol_dht: Resolved a TransactionID to a check table lookup but SampleQuery found...");
+            None
+        }
         None => {
             error!(
                 "bittorrent-protocol_dht: Resolved a TransactionID to a check table lookup but no action \
@@ -1093,18 +2192,15 @@ fn handle_check_lookup_timeout<H>(
     match opt_lookup_info {
         None => (),
         Some((LookupStatus::Searching, _)) => (),
-        Some((LookupStatus::Completed, info_hash)) => broadcast_dht_event(
-            &mut work_storage.event_notifiers,
-            DhtEvent::LookupCompleted(info_hash),
-        ),
+        Some((LookupStatus::Completed, info_hash)) => {
+            broadcast_lookup_completed(work_storage, info_hash)
+        }
         Some((LookupStatus::Failed, _)) => {
             shutdown_event_loop(event_loop, ShutdownCause::Unspecified)
         }
         Some((LookupStatus::Values(v), info_hash)) => {
             // Add values to handshaker
-            for v4_addr in v {
-                let sock_addr = SocketAddr::V4(v4_addr);
-
+            for sock_addr in v {
                 work_storage.handshaker.connect(None, info_hash, sock_addr);
             }
         }
@@ -1120,15 +2216,29 @@ fn handle_check_lookup_endgame<H>(
 {
     let (work_storage, table_actions) = (&mut handler.detached, &mut handler.table_actions);
 
+    if remove_cancelled_lookup(table_actions, trans_id.action_id(), event_loop) {
+        return;
+    }
+
+    let connect_port = if work_storage.implied_port {
+        ConnectPort::Implied
+    } else {
+        ConnectPort::Explicit(work_storage.handshaker.port())
+    };
+
     let opt_lookup_info = match table_actions.remove(&trans_id.action_id()) {
-        Some(TableAction::Lookup(mut lookup)) => Some((
-            lookup.recv_finished(
-                work_storage.handshaker.port(),
+        Some(TableAction::Lookup(mut lookup)) => {
+            let status = lookup.recv_finished(
+                connect_port,
                 &work_storage.routing_table,
                 &work_storage.out_channel,
-            ),
-            lookup.info_hash(),
-        )),
+            );
+            if status != LookupStatus::Failed {
+                lookup.complete_search();
+            }
+
+            Some((status, lookup.info_hash()))
+        }
         Some(TableAction::Bootstrap(_, _)) => {
             error!(
                 "bittorrent-protocol_dht: Resolved a TransactionID to a check table lookup but TableBootstrap \
@@ -1143,6 +2253,14 @@ fn handle_check_lookup_endgame<H>(
             );
             None
         }
+        Some(TableAction::Item(_)) => {
+            error!("bittorrent-protocol_dht: Resolved a TransactionID to a check table lookup but ItemLookup found...");
+            None
+        }
+        Some(TableAction::Sample(_)) => {
+            error!("bittorrent-protocol_dht: Resolved a TransactionID to a check table lookup but SampleQuery found...");
+            None
+        }
         None => {
             error!(
                 "bittorrent-protocol_dht: Resolved a TransactionID to a check table lookup but no action \
@@ -1155,20 +2273,64 @@ fn handle_check_lookup_endgame<H>(
     match opt_lookup_info {
         None => (),
         Some((LookupStatus::Searching, _)) => (),
-        Some((LookupStatus::Completed, info_hash)) => broadcast_dht_event(
-            &mut work_storage.event_notifiers,
-            DhtEvent::LookupCompleted(info_hash),
-        ),
+        Some((LookupStatus::Completed, info_hash)) => {
+            broadcast_lookup_completed(work_storage, info_hash)
+        }
         Some((LookupStatus::Failed, _)) => {
             shutdown_event_loop(event_loop, ShutdownCause::Unspecified)
         }
         Some((LookupStatus::Values(v), info_hash)) => {
             // Add values to handshaker
-            for v4_addr in v {
-                let sock_addr = SocketAddr::V4(v4_addr);
-
+            for sock_addr in v {
                 work_storage.handshaker.connect(None, info_hash, sock_addr);
             }
         }
     }
 }
+
+fn handle_check_item_timeout<H>(
+    handler: &mut DhtHandler<H>,
+    event_loop: &mut EventLoop<DhtHandler<H>>,
+    trans_id: TransactionID,
+) where
+    H: Handshaker,
+{
+    let (work_storage, table_actions) = (&mut handler.detached, &mut handler.table_actions);
+
+    let opt_item_status = match table_actions.get_mut(&trans_id.action_id()) {
+        Some(&mut TableAction::Item(ref mut item)) => Some(item.recv_timeout(
+            &trans_id,
+            &work_storage.routing_table,
+            &work_storage.out_channel,
+            event_loop,
+        )),
+        _ => {
+            error!(
+                "bittorrent-protocol_dht: Resolved a TransactionID to a check item timeout but no item \
+                    lookup found..."
+            );
+            None
+        }
+    };
+
+    if let Some(status) = opt_item_status {
+        handle_item_status(
+            table_actions,
+            work_storage,
+            event_loop,
+            trans_id.action_id(),
+            status,
+        );
+    }
+}
+
+fn handle_check_sample_timeout<H>(handler: &mut DhtHandler<H>, trans_id: TransactionID) {
+    match handler.table_actions.remove(&trans_id.action_id()) {
+        Some(TableAction::Sample(query)) => query.recv_timeout(&trans_id),
+        Some(action) => {
+            error!("bittorrent-protocol_dht: Resolved a TransactionID to a check sample timeout but no sample query found...");
+            handler.table_actions.insert(trans_id.action_id(), action);
+        }
+        None => (),
+    }
+}
@@ -0,0 +1,508 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::mpsc::SyncSender;
+
+use futures::channel::mpsc::UnboundedSender;
+use mio::{EventLoop, Timeout};
+
+use crate::util::bt::NodeId;
+use crate::util::sha::ShaHash;
+
+use crate::dht::bencode::Bencode;
+use crate::dht::handshake::Handshaker;
+use crate::dht::item::{self, ImmutableItem, MutableItem};
+use crate::dht::message::compact_info;
+use crate::dht::message::get_data::{GetDataRequest, GetDataResponse, ItemInfo};
+use crate::dht::message::put_data::{MutablePutArgs, PutDataRequest};
+use crate::dht::message::response::ExpectedResponse;
+use crate::dht::routing::bucket;
+use crate::dht::routing::node::{Node, NodeStatus};
+use crate::dht::routing::table::RoutingTable;
+use crate::dht::transaction::{MIDGenerator, TransactionID};
+use crate::dht::worker::handler::DhtHandler;
+use crate::dht::worker::ScheduledTask;
+
+const ITEM_TIMEOUT_MS: u64 = 1500;
+
+const PARALLEL_PICK_NUM: usize = 3; // Alpha
+const CLOSEST_PICK_NUM: usize = 8; // # Nodes converged on and stored to
+
+type Distance = ShaHash;
+
+/// Operation on an item stored in the DHT, see BEP 44.
+#[derive(Clone)]
+pub enum ItemOperation {
+    /// Retrieve the immutable item stored under the given target.
+    GetImmutable(ShaHash, UnboundedSender<ImmutableItem>),
+    /// Retrieve the newest mutable item with the given public key and salt.
+    GetMutable(
+        [u8; item::PUBLIC_KEY_LEN],
+        Vec<u8>,
+        UnboundedSender<MutableItem>,
+    ),
+    /// Store the immutable item.
+    PutImmutable(ImmutableItem),
+    /// Store the mutable item, only replacing an item with the given sequence number.
+    PutMutable(MutableItem, Option<i64>),
+}
+
+impl ItemOperation {
+    /// Target the operation is performed on.
+    pub fn target(&self) -> ShaHash {
+        match self {
+            &ItemOperation::GetImmutable(target, _) => target,
+            &ItemOperation::GetMutable(ref public_key, ref salt, _) => {
+                item::mutable_target(public_key, salt).into()
+            }
+            &ItemOperation::PutImmutable(ref item) => item.target().into(),
+            &ItemOperation::PutMutable(ref item, _) => item.target().into(),
+        }
+    }
+
+    /// Whether the operation stores an item.
+    pub fn is_put(&self) -> bool {
+        match self {
+            &ItemOperation::PutImmutable(_) | &ItemOperation::PutMutable(_, _) => true,
+            &ItemOperation::GetImmutable(_, _) | &ItemOperation::GetMutable(_, _, _) => false,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ItemStatus {
+    Searching,
+    Completed,
+    Failed,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum NodeState {
+    Unrequested,
+    Requested,
+    Responded,
+    Failed,
+}
+
+/// Iterative lookup of the nodes closest to an item target, retrieving the item from
+/// or storing the item on those nodes.
+pub struct ItemLookup {
+    table_id: NodeId,
+    target: ShaHash,
+    operation: ItemOperation,
+    id_generator: MIDGenerator,
+    // Nodes closest to the target first
+    sorted_nodes: Vec<(Distance, Node, NodeState)>,
+    put_tokens: HashMap<SocketAddr, Vec<u8>>,
+    active_gets: HashMap<TransactionID, (SocketAddr, Timeout)>,
+    active_puts: HashMap<TransactionID, Timeout>,
+    storing: bool,
+    nodes_stored: usize,
+    // Newest valid mutable item we have received
+    opt_mutable: Option<MutableItem>,
+}
+
+impl ItemLookup {
+    pub fn new<H>(
+        table_id: NodeId,
+        operation: ItemOperation,
+        id_generator: MIDGenerator,
+        table: &RoutingTable,
+        out: &SyncSender<(Vec<u8>, SocketAddr)>,
+        event_loop: &mut EventLoop<DhtHandler<H>>,
+    ) -> Option<ItemLookup>
+    where
+        H: Handshaker,
+    {
+        let target = operation.target();
+
+        let mut item_lookup = ItemLookup {
+            table_id: table_id,
+            target: target,
+            operation: operation,
+            id_generator: id_generator,
+            sorted_nodes: Vec::with_capacity(bucket::MAX_BUCKET_SIZE),
+            put_tokens: HashMap::new(),
+            active_gets: HashMap::with_capacity(PARALLEL_PICK_NUM),
+            active_puts: HashMap::new(),
+            storing: false,
+            nodes_stored: 0,
+            opt_mutable: None,
+        };
+
+        for node in table
+            .closest_nodes(target)
+            .filter(|n| n.status() == NodeStatus::Good)
+            .take(bucket::MAX_BUCKET_SIZE)
+        {
+            item_lookup.insert_node(node.clone());
+        }
+
+        if item_lookup.continue_lookup(table, out, event_loop) != ItemStatus::Failed {
+            Some(item_lookup)
+        } else {
+            None
+        }
+    }
+
+    pub fn target(&self) -> ShaHash {
+        self.target
+    }
+
+    /// Number of nodes that stored our item, if this lookup stores an item.
+    pub fn nodes_stored(&self) -> Option<usize> {
+        if self.operation.is_put() {
+            Some(self.nodes_stored)
+        } else {
+            None
+        }
+    }
+
+    /// Type of response we expect for the given transaction.
+    pub fn expected_response(&self, trans_id: &TransactionID) -> ExpectedResponse {
+        if self.active_puts.contains_key(trans_id) {
+            ExpectedResponse::PutData
+        } else {
+            ExpectedResponse::GetData
+        }
+    }
+
+    /// Cancel the lookup, clearing all of its outstanding timeouts.
+    pub fn cancel<H>(&mut self, event_loop: &mut EventLoop<DhtHandler<H>>)
+    where
+        H: Handshaker,
+    {
+        for (_, (_, timeout)) in self.active_gets.drain() {
+            event_loop.clear_timeout(timeout);
+        }
+        for (_, timeout) in self.active_puts.drain() {
+            event_loop.clear_timeout(timeout);
+        }
+    }
+
+    pub fn recv_get_response<'a, H>(
+        &mut self,
+        trans_id: &TransactionID,
+        msg: GetDataResponse<'a>,
+        table: &RoutingTable,
+        out: &SyncSender<(Vec<u8>, SocketAddr)>,
+        event_loop: &mut EventLoop<DhtHandler<H>>,
+    ) -> ItemStatus
+    where
+        H: Handshaker,
+    {
+        let (addr, timeout) = if let Some(request) = self.active_gets.remove(trans_id) {
+            request
+        } else {
+            warn!("bittorrent-protocol_dht: Received expired/unsolicited get response for an item lookup...");
+            return self.current_status();
+        };
+        event_loop.clear_timeout(timeout);
+        self.set_node_state(addr, NodeState::Responded);
+
+        if let Some(token) = msg.token() {
+            self.put_tokens.insert(addr, token.to_vec());
+        }
+
+        // Any node holding the immutable item is as good as another
+        if self.recv_item(msg.item()) {
+            self.cancel(event_loop);
+
+            return ItemStatus::Completed;
+        }
+
+        if let Some(nodes) = compact_info::same_family(&addr, msg.nodes(), msg.nodes6()) {
+            for (id, node_addr) in nodes {
+                self.insert_node(Node::as_questionable(id, node_addr));
+            }
+        }
+
+        self.continue_lookup(table, out, event_loop)
+    }
+
+    pub fn recv_put_response<H>(
+        &mut self,
+        trans_id: &TransactionID,
+        event_loop: &mut EventLoop<DhtHandler<H>>,
+    ) -> ItemStatus
+    where
+        H: Handshaker,
+    {
+        if let Some(timeout) = self.active_puts.remove(trans_id) {
+            event_loop.clear_timeout(timeout);
+            self.nodes_stored += 1;
+        } else {
+            warn!("bittorrent-protocol_dht: Received expired/unsolicited put response for an item lookup...");
+        }
+
+        self.current_status()
+    }
+
+    /// Process an error, or the lack of any response, to one of our requests.
+    pub fn recv_timeout<H>(
+        &mut self,
+        trans_id: &TransactionID,
+        table: &RoutingTable,
+        out: &SyncSender<(Vec<u8>, SocketAddr)>,
+        event_loop: &mut EventLoop<DhtHandler<H>>,
+    ) -> ItemStatus
+    where
+        H: Handshaker,
+    {
+        if let Some((addr, timeout)) = self.active_gets.remove(trans_id) {
+            event_loop.clear_timeout(timeout);
+            self.set_node_state(addr, NodeState::Failed);
+
+            self.continue_lookup(table, out, event_loop)
+        } else if let Some(timeout) = self.active_puts.remove(trans_id) {
+            event_loop.clear_timeout(timeout);
+
+            self.current_status()
+        } else {
+            warn!("bittorrent-protocol_dht: Received expired/unsolicited timeout for an item lookup...");
+            self.current_status()
+        }
+    }
+
+    pub fn current_status(&self) -> ItemStatus {
+        if !self.active_gets.is_empty() || !self.active_puts.is_empty() {
+            ItemStatus::Searching
+        } else {
+            ItemStatus::Completed
+        }
+    }
+
+    /// Verify the item sent to us, returning true if the lookup found what it was looking for.
+    fn recv_item(&mut self, opt_item: Option<ItemInfo>) -> bool {
+        match (opt_item, &self.operation) {
+            (Some(ItemInfo::Immutable(value)), &ItemOperation::GetImmutable(target, ref send)) => {
+                let value = value.encode();
+
+                if ShaHash::from_bytes(&value) != target {
+                    warn!("bittorrent-protocol_dht: Received an immutable item not matching its target...");
+                    return false;
+                }
+
+                match ImmutableItem::from_raw(value) {
+                    Ok(item) => {
+                        let _ = send.unbounded_send(item);
+                        true
+                    }
+                    Err(_) => false,
+                }
+            }
+            (
+                Some(ItemInfo::Mutable(public_key, seq, signature, value)),
+                &ItemOperation::GetMutable(ref our_public_key, ref salt, _),
+            ) => {
+                if public_key != &our_public_key[..] {
+                    return false;
+                }
+
+                let mut signature_bytes = [0u8; item::SIGNATURE_LEN];
+                signature_bytes.copy_from_slice(signature);
+
+                match MutableItem::from_raw(
+                    *our_public_key,
+                    salt.clone(),
+                    seq,
+                    signature_bytes,
+                    value.encode(),
+                ) {
+                    Ok(item) => {
+                        if self.opt_mutable.as_ref().map(|m| m.seq() < seq) != Some(false) {
+                            self.opt_mutable = Some(item);
+                        }
+                    }
+                    Err(_) => {
+                        warn!("bittorrent-protocol_dht: Received a mutable item with an invalid signature...");
+                    }
+                }
+
+                false
+            }
+            _ => false,
+        }
+    }
+
+    /// Request the closest nodes we have not heard from, storing or reporting the item
+    /// once the closest nodes have all responded or failed.
+    fn continue_lookup<H>(
+        &mut self,
+        table: &RoutingTable,
+        out: &SyncSender<(Vec<u8>, SocketAddr)>,
+        event_loop: &mut EventLoop<DhtHandler<H>>,
+    ) -> ItemStatus
+    where
+        H: Handshaker,
+    {
+        if self.storing {
+            return self.current_status();
+        }
+
+        let num_requests = PARALLEL_PICK_NUM.saturating_sub(self.active_gets.len());
+        let request_nodes: Vec<Node> = self
+            .sorted_nodes
+            .iter()
+            .filter(|&&(_, _, state)| state != NodeState::Failed)
+            .take(CLOSEST_PICK_NUM)
+            .filter(|&&(_, _, state)| state == NodeState::Unrequested)
+            .take(num_requests)
+            .map(|&(_, ref node, _)| node.clone())
+            .collect();
+
+        for node in request_nodes {
+            let trans_id = self.id_generator.generate();
+
+            let res_timeout = event_loop.timeout_ms(
+                (0, ScheduledTask::CheckItemTimeout(trans_id)),
+                ITEM_TIMEOUT_MS,
+            );
+            let timeout = if let Ok(t) = res_timeout {
+                t
+            } else {
+                error!("bittorrent-protocol_dht: Failed to set a timeout for an item lookup...");
+                return ItemStatus::Failed;
+            };
+            self.active_gets.insert(trans_id, (node.addr(), timeout));
+
+            let get_data_msg =
+                GetDataRequest::new(trans_id.as_ref(), self.table_id, self.target, None).encode();
+            if out.send((get_data_msg, node.addr())).is_err() {
+                error!("bittorrent-protocol_dht: Could not send an item lookup message through the channel...");
+                return ItemStatus::Failed;
+            }
+
+            self.set_node_state(node.addr(), NodeState::Requested);
+            table.find_node(&node).map(|n| n.local_request());
+        }
+
+        if self.active_gets.is_empty() {
+            self.finish_search(table, out, event_loop)
+        } else {
+            ItemStatus::Searching
+        }
+    }
+
+    /// Report the item we found, or store our item on the closest nodes that responded.
+    fn finish_search<H>(
+        &mut self,
+        table: &RoutingTable,
+        out: &SyncSender<(Vec<u8>, SocketAddr)>,
+        event_loop: &mut EventLoop<DhtHandler<H>>,
+    ) -> ItemStatus
+    where
+        H: Handshaker,
+    {
+        self.storing = true;
+
+        let (value, opt_mutable) = match &self.operation {
+            &ItemOperation::GetImmutable(_, _) => return ItemStatus::Completed,
+            &ItemOperation::GetMutable(_, _, ref send) => {
+                if let Some(item) = self.opt_mutable.take() {
+                    let _ = send.unbounded_send(item);
+                }
+
+                return ItemStatus::Completed;
+            }
+            &ItemOperation::PutImmutable(ref item) => (item.value(), None),
+            &ItemOperation::PutMutable(ref item, cas) => (
+                item.value(),
+                Some((
+                    item.public_key(),
+                    item.salt(),
+                    item.seq(),
+                    item.signature(),
+                    cas,
+                )),
+            ),
+        };
+
+        let value = if let Ok(value) = Bencode::decode(value) {
+            value
+        } else {
+            error!(
+                "bittorrent-protocol_dht: Failed to decode the value of an item we are storing..."
+            );
+            return ItemStatus::Completed;
+        };
+        let mutable_args = opt_mutable
+            .as_ref()
+            .map(|&(public_key, salt, seq, signature, cas)| {
+                MutablePutArgs::new(&public_key[..], salt, seq, &signature[..], cas)
+            });
+
+        let put_tokens = &self.put_tokens;
+        let store_nodes = self
+            .sorted_nodes
+            .iter()
+            .filter(|&&(_, ref node, state)| {
+                state == NodeState::Responded && put_tokens.contains_key(&node.addr())
+            })
+            .take(CLOSEST_PICK_NUM);
+
+        for &(_, ref node, _) in store_nodes {
+            let trans_id = self.id_generator.generate();
+            let token = put_tokens.get(&node.addr()).unwrap();
+
+            let put_data_msg = PutDataRequest::new(
+                trans_id.as_ref(),
+                self.table_id,
+                token,
+                &value,
+                mutable_args,
+            )
+            .encode();
+
+            let res_timeout = event_loop.timeout_ms(
+                (0, ScheduledTask::CheckItemTimeout(trans_id)),
+                ITEM_TIMEOUT_MS,
+            );
+            let timeout = if let Ok(t) = res_timeout {
+                t
+            } else {
+                error!("bittorrent-protocol_dht: Failed to set a timeout for an item put...");
+                return ItemStatus::Failed;
+            };
+            self.active_puts.insert(trans_id, timeout);
+
+            if out.send((put_data_msg, node.addr())).is_err() {
+                error!("bittorrent-protocol_dht: Could not send an item put message through the channel...");
+                return ItemStatus::Failed;
+            }
+
+            table.find_node(node).map(|n| n.local_request());
+        }
+
+        self.current_status()
+    }
+
+    /// Insert the node into the list of nodes based on its distance from the target.
+    fn insert_node(&mut self, node: Node) {
+        if self
+            .sorted_nodes
+            .iter()
+            .any(|&(_, ref n, _)| n.addr() == node.addr())
+        {
+            return;
+        }
+
+        let node_dist = self.target ^ node.id();
+        let ins_index = match self
+            .sorted_nodes
+            .binary_search_by(|&(dist, _, _)| dist.cmp(&node_dist))
+        {
+            Ok(index) | Err(index) => index,
+        };
+
+        self.sorted_nodes
+            .insert(ins_index, (node_dist, node, NodeState::Unrequested));
+    }
+
+    fn set_node_state(&mut self, addr: SocketAddr, new_state: NodeState) {
+        for &mut (_, ref node, ref mut state) in self.sorted_nodes.iter_mut() {
+            if node.addr() == addr {
+                *state = new_state;
+            }
+        }
+    }
+}
@@ -1,22 +1,27 @@
 use std::collections::{HashMap, HashSet};
-use std::net::{SocketAddr, SocketAddrV4};
+use std::net::SocketAddr;
 use std::sync::mpsc::SyncSender;
 
+use futures::channel::mpsc::UnboundedSender;
 use mio::{EventLoop, Timeout};
+use tracing::{debug, trace};
 
 use crate::util::bt::{self, InfoHash, NodeId};
 use crate::util::net;
 use crate::util::sha::ShaHash;
 
+use crate::dht::bloom::BloomFilter;
 use crate::dht::handshake::Handshaker;
 use crate::dht::message::announce_peer::{AnnouncePeerRequest, ConnectPort};
-use crate::dht::message::get_peers::{CompactInfoType, GetPeersRequest, GetPeersResponse};
+use crate::dht::message::compact_info;
+use crate::dht::message::get_peers::{GetPeersRequest, GetPeersResponse};
+use crate::dht::message::Want;
 use crate::dht::routing::bucket;
 use crate::dht::routing::node::{Node, NodeStatus};
 use crate::dht::routing::table::RoutingTable;
 use crate::dht::transaction::{MIDGenerator, TransactionID};
 use crate::dht::worker::handler::DhtHandler;
-use crate::dht::worker::ScheduledTask;
+use crate::dht::worker::{ScheduledTask, SearchEvent};
 
 const LOOKUP_TIMEOUT_MS: u64 = 1500;
 const ENDGAME_TIMEOUT_MS: u64 = 1500;
@@ -27,7 +32,7 @@ const ENDGAME_TIMEOUT_MS: u64 = 1500;
 // TODO: Handle case where a request round fails, should we fail the whole lookup (clear acvite lookups?)
 // TODO: Clean up the code in this module.
 
-const INITIAL_PICK_NUM: usize = 4; // Alpha
+const INITIAL_PICK_NUM: usize = 3; // Alpha
 const ITERATIVE_PICK_NUM: usize = 3; // Beta
 const ANNOUNCE_PICK_NUM: usize = 8; // # Announces
 
@@ -37,7 +42,7 @@ type DistanceToBeat = ShaHash;
 #[derive(Debug, PartialEq, Eq)]
 pub enum LookupStatus {
     Searching,
-    Values(Vec<SocketAddrV4>),
+    Values(Vec<SocketAddr>),
     Completed,
     Failed,
 }
@@ -54,11 +59,20 @@ pub struct TableLookup {
     // interestingly enough (and super important), this distance may not be eqaul to the
     // requested node's distance
     active_lookups: HashMap<TransactionID, (DistanceToBeat, Timeout)>,
+    endgame_timeout: Option<Timeout>,
     announce_tokens: HashMap<Node, Vec<u8>>,
     requested_nodes: HashSet<Node>,
     // Storing whether or not it has ever been pinged so that we
     // can perform the brute force lookup if the lookup failed
     all_sorted_nodes: Vec<(Distance, Node, bool)>,
+    // Progress of the lookup for searches started with a sender
+    opt_search: Option<UnboundedSender<SearchEvent>>,
+    nodes_contacted: usize,
+    found_peers: HashSet<SocketAddr>,
+    // Union of the seed and downloader bloom filters received, if scraping
+    opt_scrape: Option<(BloomFilter, BloomFilter)>,
+    // Address families of the nodes we want back, see BEP 32
+    want: Option<Want>,
 }
 
 // Gather nodes
@@ -69,6 +83,9 @@ impl TableLookup {
         target_id: InfoHash,
         id_generator: MIDGenerator,
         will_announce: bool,
+        will_scrape: bool,
+        opt_search: Option<UnboundedSender<SearchEvent>>,
+        want: Option<Want>,
         table: &RoutingTable,
         out: &SyncSender<(Vec<u8>, SocketAddr)>,
         event_loop: &mut EventLoop<DhtHandler<H>>,
@@ -76,14 +93,17 @@ impl TableLookup {
     where
         H: Handshaker,
     {
+        // Distances are to the hash as it is sent to nodes
+        let target = NodeId::from(target_id);
+
         // Pick a buckets worth of nodes and put them into the all_sorted_nodes list
         let mut all_sorted_nodes = Vec::with_capacity(bucket::MAX_BUCKET_SIZE);
         for node in table
-            .closest_nodes(target_id)
+            .closest_nodes(target)
             .filter(|n| n.status() == NodeStatus::Good)
             .take(bucket::MAX_BUCKET_SIZE)
         {
-            insert_sorted_node(&mut all_sorted_nodes, target_id, node.clone(), false);
+            insert_sorted_node(&mut all_sorted_nodes, target, node.clone(), false);
         }
 
         // Call pick_initial_nodes with the all_sorted_nodes list as an iterator
@@ -93,7 +113,7 @@ impl TableLookup {
                 .iter()
                 .filter(|&&(_, good)| good)
                 .map(|&(ref node, _)| {
-                    let distance_to_beat = node.id() ^ target_id;
+                    let distance_to_beat = node.id() ^ target;
 
                     (node, distance_to_beat)
                 });
@@ -110,8 +130,25 @@ impl TableLookup {
             announce_tokens: HashMap::new(),
             requested_nodes: HashSet::new(),
             active_lookups: HashMap::with_capacity(INITIAL_PICK_NUM),
+            endgame_timeout: None,
+            opt_search: opt_search,
+            nodes_contacted: 0,
+            found_peers: HashSet::new(),
+            opt_scrape: if will_scrape {
+                Some((BloomFilter::new(), BloomFilter::new()))
+            } else {
+                None
+            },
+            want: want,
         };
 
+        debug!(
+            info_hash = %target_id,
+            known_nodes = table_lookup.all_sorted_nodes.len(),
+            monotonic_counter.dht_lookups = 1u64,
+            "lookup started"
+        );
+
         // Call start_request_round with the list of initial_nodes (return even if the search completed...for now :D)
         if table_lookup.start_request_round(initial_pick_nodes_filtered, table, out, event_loop)
             != LookupStatus::Failed
@@ -126,6 +163,45 @@ impl TableLookup {
         self.target_id
     }
 
+    /// Whether the lookup was started for a search which is no longer being listened to.
+    pub fn is_cancelled(&self) -> bool {
+        self.opt_search
+            .as_ref()
+            .map(|send| send.is_closed())
+            .unwrap_or(false)
+    }
+
+    /// Cancel the lookup, clearing all of its outstanding timeouts.
+    pub fn cancel<H>(&mut self, event_loop: &mut EventLoop<DhtHandler<H>>)
+    where
+        H: Handshaker,
+    {
+        for (_, (_, timeout)) in self.active_lookups.drain() {
+            event_loop.clear_timeout(timeout);
+        }
+        if let Some(timeout) = self.endgame_timeout.take() {
+            event_loop.clear_timeout(timeout);
+        }
+
+        self.opt_search = None;
+    }
+
+    /// Report the final summary of the lookup to the search, ending it.
+    pub fn complete_search(&mut self) {
+        if let Some(send) = self.opt_search.take() {
+            if let Some((ref seeds, ref downloaders)) = self.opt_scrape {
+                let _ = send.unbounded_send(SearchEvent::Scrape {
+                    seeds: seeds.estimate().round() as usize,
+                    downloaders: downloaders.estimate().round() as usize,
+                });
+            }
+            let _ = send.unbounded_send(SearchEvent::SearchCompleted {
+                nodes_contacted: self.nodes_contacted,
+                peers_found: self.found_peers.len(),
+            });
+        }
+    }
+
     pub fn recv_response<'a, H>(
         &mut self,
         node: Node,
@@ -154,28 +230,46 @@ impl TableLookup {
             event_loop.clear_timeout(timeout);
         }
 
+        let node_addr = node.addr();
+
         // Add the announce token to our list of tokens
         if let Some(token) = msg.token() {
             self.announce_tokens.insert(node, token.to_vec());
         }
 
-        // Pull out the contact information from the message
-        let (opt_values, opt_nodes) = match msg.info_type() {
-            CompactInfoType::Nodes(n) => (None, Some(n)),
-            CompactInfoType::Values(v) => {
-                self.recv_values = true;
-                (Some(v.into_iter().collect()), None)
+        // Peers stored on multiple nodes set the same bits, so they are only counted once
+        if let Some((recv_seeds, recv_downloaders)) = msg.scrape() {
+            if let Some((ref mut seeds, ref mut downloaders)) = self.opt_scrape {
+                seeds.union(&recv_seeds);
+                downloaders.union(&recv_downloaders);
             }
-            CompactInfoType::Both(n, v) => (Some(v.into_iter().collect()), Some(n)),
-        };
+        }
+
+        // Pull out the contact information from the message, nodes of the other address
+        // family than the responding node are of no use to a lookup over this routing table
+        let opt_values: Option<Vec<SocketAddr>> = msg.values().map(|v| v.into_iter().collect());
+        let opt_nodes = compact_info::same_family(&node_addr, msg.nodes(), msg.nodes6());
+        if opt_values.is_some() && opt_nodes.is_none() {
+            self.recv_values = true;
+        }
+
+        // Report peers we have not seen yet to the search
+        if let (Some(send), Some(values)) = (self.opt_search.as_ref(), opt_values.as_ref()) {
+            for &addr in values {
+                if self.found_peers.insert(addr) {
+                    let _ = send.unbounded_send(SearchEvent::Peer(addr));
+                }
+            }
+        }
 
         // Check if we beat the distance, get the next distance to beat
         let (iterate_nodes, next_dist_to_beat) = if let Some(nodes) = opt_nodes {
             let requested_nodes = &self.requested_nodes;
+            let target = NodeId::from(self.target_id);
 
             // Filter for nodes that we have already requested from
-            let already_requested = |node_info: &(NodeId, SocketAddrV4)| {
-                let node = Node::as_questionable(node_info.0, SocketAddr::V4(node_info.1));
+            let already_requested = |node_info: &(NodeId, SocketAddr)| {
+                let node = Node::as_questionable(node_info.0, node_info.1);
 
                 !requested_nodes.contains(&node)
             };
@@ -184,7 +278,7 @@ impl TableLookup {
             let next_dist_to_beat = nodes.into_iter().filter(&already_requested).fold(
                 dist_to_beat,
                 |closest, (id, _)| {
-                    let distance = self.target_id ^ id;
+                    let distance = target ^ id;
 
                     if distance < closest {
                         distance
@@ -198,29 +292,27 @@ impl TableLookup {
             let iterate_nodes = if next_dist_to_beat < dist_to_beat {
                 let iterate_nodes = pick_iterate_nodes(
                     nodes.into_iter().filter(&already_requested),
-                    self.target_id,
+                    target,
                 );
 
                 // Push nodes into the all nodes list
-                for (id, v4_addr) in nodes {
-                    let addr = SocketAddr::V4(v4_addr);
+                for (id, addr) in nodes {
                     let node = Node::as_questionable(id, addr);
                     let will_ping = iterate_nodes
                         .iter()
                         .find(|&&(ref n, _)| n == &node)
                         .is_some();
 
-                    insert_sorted_node(&mut self.all_sorted_nodes, self.target_id, node, will_ping);
+                    insert_sorted_node(&mut self.all_sorted_nodes, target, node, will_ping);
                 }
 
                 Some(iterate_nodes)
             } else {
                 // Push nodes into the all nodes list
-                for (id, v4_addr) in nodes {
-                    let addr = SocketAddr::V4(v4_addr);
+                for (id, addr) in nodes {
                     let node = Node::as_questionable(id, addr);
 
-                    insert_sorted_node(&mut self.all_sorted_nodes, self.target_id, node, false);
+                    insert_sorted_node(&mut self.all_sorted_nodes, target, node, false);
                 }
 
                 None
@@ -231,6 +323,16 @@ impl TableLookup {
             (None, dist_to_beat)
         };
 
+        debug!(
+            info_hash = %self.target_id,
+            node = %node_addr,
+            values = opt_values.as_ref().map_or(0, |values| values.len()),
+            closer = iterate_nodes.is_some(),
+            endgame = self.in_endgame,
+            monotonic_counter.dht_lookup_hops = 1u64,
+            "lookup hop"
+        );
+
         // Check if we need to iterate (not in the endgame already)
         if !self.in_endgame {
             // If the node gave us a closer id than its own to the target id, continue the search
@@ -292,7 +394,7 @@ impl TableLookup {
 
     pub fn recv_finished(
         &mut self,
-        handshake_port: u16,
+        connect_port: ConnectPort,
         table: &RoutingTable,
         out: &SyncSender<(Vec<u8>, SocketAddr)>,
     ) -> LookupStatus {
@@ -317,7 +419,8 @@ impl TableLookup {
                     self.table_id,
                     self.target_id,
                     token.as_ref(),
-                    ConnectPort::Explicit(handshake_port),
+                    connect_port,
+                    false,
                 );
                 let announce_peer_msg = announce_peer_req.encode();
 
@@ -336,8 +439,16 @@ impl TableLookup {
             }
         }
 
+        debug!(
+            info_hash = %self.target_id,
+            nodes_contacted = self.nodes_contacted,
+            peers_found = self.found_peers.len(),
+            "lookup finished"
+        );
+
         // This may not be cleared since we didnt set a timeout for each node, any nodes that didnt respond would still be in here.
         self.active_lookups.clear();
+        self.endgame_timeout = None;
         self.in_endgame = false;
 
         if fatal_error {
@@ -347,7 +458,7 @@ impl TableLookup {
         }
     }
 
-    fn current_lookup_status(&self) -> LookupStatus {
+    pub fn current_lookup_status(&self) -> LookupStatus {
         if self.in_endgame || !self.active_lookups.is_empty() {
             LookupStatus::Searching
         } else {
@@ -389,15 +500,24 @@ impl TableLookup {
                 .insert(trans_id, (dist_to_beat, timeout));
 
             // Send the message to the node
-            let get_peers_msg =
-                GetPeersRequest::new(trans_id.as_ref(), self.table_id, self.target_id).encode();
+            let get_peers_msg = GetPeersRequest::new(
+                trans_id.as_ref(),
+                self.table_id,
+                self.target_id,
+                self.opt_scrape.is_some(),
+                self.want,
+            )
+            .encode();
             if out.send((get_peers_msg, node.addr())).is_err() {
                 error!("bittorrent-protocol_dht: Could not send a lookup message through the channel...");
                 return LookupStatus::Failed;
             }
 
+            trace!(info_hash = %self.target_id, node = %node.addr(), "lookup request sent");
+
             // We requested from the node, mark it down
             self.requested_nodes.insert(node.clone());
+            self.nodes_contacted += 1;
 
             // Update the node in the routing table
             table.find_node(node).map(|n| n.local_request());
@@ -424,6 +544,11 @@ impl TableLookup {
     {
         // Entering the endgame phase
         self.in_endgame = true;
+        debug!(
+            info_hash = %self.target_id,
+            nodes_contacted = self.nodes_contacted,
+            "lookup endgame"
+        );
 
         // Try to start a global message timeout for the endgame
         let res_timeout = event_loop.timeout_ms(
@@ -439,6 +564,7 @@ impl TableLookup {
             error!("bittorrent-protocol_dht: Failed to set a timeout for table lookup endgame...");
             return LookupStatus::Failed;
         };
+        self.endgame_timeout = Some(timeout);
 
         // Request all unpinged nodes if we didnt receive any values
         if !self.recv_values {
@@ -458,8 +584,14 @@ impl TableLookup {
                 self.active_lookups.insert(trans_id, (*node_dist, timeout));
 
                 // Send the message to the node
-                let get_peers_msg =
-                    GetPeersRequest::new(trans_id.as_ref(), self.table_id, self.target_id).encode();
+                let get_peers_msg = GetPeersRequest::new(
+                    trans_id.as_ref(),
+                    self.table_id,
+                    self.target_id,
+                    self.opt_scrape.is_some(),
+                    self.want,
+                )
+                .encode();
                 if out.send((get_peers_msg, node.addr())).is_err() {
                     error!("bittorrent-protocol_dht: Could not send an endgame message through the channel...");
                     return LookupStatus::Failed;
@@ -470,6 +602,7 @@ impl TableLookup {
 
                 // Mark that we requested from the node
                 *req = true;
+                self.nodes_contacted += 1;
             }
         }
 
@@ -485,12 +618,7 @@ where
     let dummy_id = [0u8; bt::NODE_ID_LEN].into();
     let default = (Node::as_bad(dummy_id, net::default_route_v4()), false);
 
-    let mut pick_nodes = [
-        default.clone(),
-        default.clone(),
-        default.clone(),
-        default.clone(),
-    ];
+    let mut pick_nodes = [default.clone(), default.clone(), default.clone()];
     for (src, dst) in sorted_nodes.zip(pick_nodes.iter_mut()) {
         dst.0 = src.1.clone();
         dst.1 = true;
@@ -505,17 +633,16 @@ where
 /// Picks a number of nodes from the unsorted distance iterator to ping on iterative rounds.
 fn pick_iterate_nodes<I>(
     unsorted_nodes: I,
-    target_id: InfoHash,
+    target_id: NodeId,
 ) -> [(Node, bool); ITERATIVE_PICK_NUM]
 where
-    I: Iterator<Item = (NodeId, SocketAddrV4)>,
+    I: Iterator<Item = (NodeId, SocketAddr)>,
 {
     let dummy_id = [0u8; bt::NODE_ID_LEN].into();
     let default = (Node::as_bad(dummy_id, net::default_route_v4()), false);
 
     let mut pick_nodes = [default.clone(), default.clone(), default.clone()];
-    for (id, v4_addr) in unsorted_nodes {
-        let addr = SocketAddr::V4(v4_addr);
+    for (id, addr) in unsorted_nodes {
         let node = Node::as_questionable(id, addr);
 
         insert_closest_nodes(&mut pick_nodes, target_id, node);
@@ -526,7 +653,7 @@ where
 
 /// Inserts the node into the slice if a slot in the slice is unused or a node
 /// in the slice is further from the target id than the node being inserted.
-fn insert_closest_nodes(nodes: &mut [(Node, bool)], target_id: InfoHash, new_node: Node) {
+fn insert_closest_nodes(nodes: &mut [(Node, bool)], target_id: NodeId, new_node: Node) {
     let new_distance = target_id ^ new_node.id();
 
     for &mut (ref mut old_node, ref mut used) in nodes.iter_mut() {
@@ -552,7 +679,7 @@ fn insert_closest_nodes(nodes: &mut [(Node, bool)], target_id: InfoHash, new_nod
 /// Nodes at the start of the list are closer to the target node than nodes at the end.
 fn insert_sorted_node(
     nodes: &mut Vec<(Distance, Node, bool)>,
-    target: InfoHash,
+    target: NodeId,
     node: Node,
     pinged: bool,
 ) {
@@ -1,19 +1,33 @@
 use std::net::{SocketAddr, UdpSocket};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::mpsc::{self, SyncSender};
+use std::sync::Arc;
 use std::thread;
 
 use mio::Sender;
 
+use crate::dht::message;
 use crate::dht::worker::OneshotTask;
 
 const OUTGOING_MESSAGE_CAPACITY: usize = 4096;
 
-pub fn create_outgoing_messenger(socket: UdpSocket) -> SyncSender<(Vec<u8>, SocketAddr)> {
+/// Spawns a worker sending the messages given to it over the socket.
+///
+/// While the read only flag is set, the worker marks the queries going out as coming from
+/// a read only node (BEP 43), so we do not have to track it everywhere queries are made.
+pub fn create_outgoing_messenger(
+    socket: UdpSocket,
+    read_only: Arc<AtomicBool>,
+) -> SyncSender<(Vec<u8>, SocketAddr)> {
     let (send, recv) = mpsc::sync_channel::<(Vec<u8>, SocketAddr)>(OUTGOING_MESSAGE_CAPACITY);
 
     thread::spawn(move || {
-        for (message, addr) in recv {
-            send_bytes(&socket, &message[..], addr);
+        for (mut bytes, addr) in recv {
+            if read_only.load(Ordering::Relaxed) {
+                bytes = message::add_read_only(bytes);
+            }
+
+            send_bytes(&socket, &bytes[..], addr);
         }
 
         info!("bittorrent-protocol_dht: Outgoing messenger received a channel hangup, exiting thread...");
@@ -1,20 +1,34 @@
 use std::io;
-use std::net::{SocketAddr, UdpSocket};
-use std::sync::mpsc;
+use std::net::{IpAddr, SocketAddr, UdpSocket};
+use std::sync::atomic::AtomicBool;
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::Duration;
 
+use futures::channel::mpsc::UnboundedSender;
 use mio;
+use rand;
 
 use crate::dht::handshake::Handshaker;
+use crate::dht::limiter::QueryLimiter;
+use crate::dht::message::Want;
 use crate::dht::router::Router;
 use crate::dht::routing::table::{self, RoutingTable};
+use crate::dht::security::{self, ExternalIpVotes, NodeIdPolicy};
+use crate::dht::state::DhtState;
 use crate::dht::transaction::TransactionID;
-use crate::util::bt::InfoHash;
+use crate::dht::worker::bootstrap::BootstrapWatchdog;
+use crate::dht::worker::item::ItemOperation;
+use crate::dht::worker::sample::SampleSender;
+use crate::util::bt::{InfoHash, NodeId};
 
 pub mod bootstrap;
 pub mod handler;
+pub mod item;
 pub mod lookup;
 pub mod messenger;
 pub mod refresh;
+pub mod resolve;
+pub mod sample;
 
 /// Task that our DHT will execute immediately.
 #[derive(Clone)]
@@ -25,8 +39,22 @@ pub enum OneshotTask {
     RegisterSender(mpsc::Sender<DhtEvent>),
     /// Load a new bootstrap operation into worker storage.
     StartBootstrap(Vec<Router>, Vec<SocketAddr>),
-    /// Start a lookup for the given InfoHash.
-    StartLookup(InfoHash, bool),
+    /// Add routers resolved from bootstrap hosts after the bootstrap started.
+    AddRouters(Vec<SocketAddr>),
+    /// Ping the node at the given address, adding it to our routing table if it responds.
+    AddNode(SocketAddr),
+    /// Start a lookup for the given InfoHash, announcing and scraping it if requested, optionally
+    /// reporting its progress to a sender.
+    StartLookup(InfoHash, bool, bool, Option<UnboundedSender<SearchEvent>>),
+    /// Start a lookup retrieving or storing an item.
+    StartItemLookup(ItemOperation),
+    /// Sample the info hashes stored by the node at the given address, sending nodes close
+    /// to the given target along with them.
+    StartSample(SocketAddr, NodeId, SampleSender),
+    /// Save the state of our node to restore it when restarting.
+    SaveState(mpsc::Sender<DhtState>),
+    /// Switch our node in or out of read only mode.
+    SetReadOnly(bool),
     /// Gracefully shutdown the DHT and associated workers.
     Shutdown(ShutdownCause),
 }
@@ -38,23 +66,187 @@ pub enum ScheduledTask {
     CheckTableRefresh(TransactionID),
     /// Check the progress of the current bootstrap.
     CheckBootstrapTimeout(TransactionID),
+    /// Check whether our routing table has been starved of good nodes for too long.
+    CheckBootstrapWatchdog,
     /// Check the progress of a current lookup.
     CheckLookupTimeout(TransactionID),
     /// Check the progress of the lookup endgame.
     CheckLookupEndGame(TransactionID),
+    /// Check the progress of a request made by an item lookup.
+    CheckItemTimeout(TransactionID),
+    /// Check whether a node responded to our sample query.
+    CheckSampleTimeout(TransactionID),
 }
 
 /// Event that occured within the DHT which clients may be interested in.
 #[derive(Copy, Clone, Debug)]
 pub enum DhtEvent {
-    /// DHT completed the bootstrap.
-    BootstrapCompleted,
+    /// DHT started a bootstrap, either the initial one or because our routing table was
+    /// starved of good nodes for too long.
+    BootstrapStarted,
+    /// DHT completed the bootstrap, with the given number of good nodes in our routing table.
+    BootstrapCompleted { nodes: usize },
     /// Lookup operation for the given InfoHash completed.
     LookupCompleted(InfoHash),
+    /// Put operation for the given item target completed, storing it on the given number of nodes.
+    PutCompleted(InfoHash, usize),
     /// DHT is shutting down for some reason.
     ShuttingDown(ShutdownCause),
 }
 
+/// Event that occured within a single search started through `MainlineDht::search_peers`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SearchEvent {
+    /// Peer found for the InfoHash being searched, each peer is reported once.
+    Peer(SocketAddr),
+    /// Estimated number of seeds and downloaders for the InfoHash, reported right before
+    /// the search converges if a scrape was requested, see BEP 33.
+    Scrape { seeds: usize, downloaders: usize },
+    /// Search converged, this is always the last event of a search.
+    SearchCompleted {
+        nodes_contacted: usize,
+        peers_found: usize,
+    },
+}
+
+/// Snapshot of the identity of our node within the DHT.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct DhtStatus {
+    node_id: NodeId,
+    opt_external_ip: Option<IpAddr>,
+    queries_dropped: u64,
+    malformed_dropped: u64,
+    ips_banned: u64,
+    infohashes_tracked: usize,
+    peers_stored: usize,
+    nodes: usize,
+    lookups_started: u64,
+    lookups_completed: u64,
+}
+
+impl DhtStatus {
+    pub(crate) fn new(node_id: NodeId, opt_external_ip: Option<IpAddr>) -> DhtStatus {
+        DhtStatus {
+            node_id: node_id,
+            opt_external_ip: opt_external_ip,
+            queries_dropped: 0,
+            malformed_dropped: 0,
+            ips_banned: 0,
+            infohashes_tracked: 0,
+            peers_stored: 0,
+            nodes: 0,
+            lookups_started: 0,
+            lookups_completed: 0,
+        }
+    }
+
+    pub(crate) fn set_identity(&mut self, node_id: NodeId, opt_external_ip: Option<IpAddr>) {
+        self.node_id = node_id;
+        self.opt_external_ip = opt_external_ip;
+    }
+
+    pub(crate) fn add_query_dropped(&mut self) {
+        self.queries_dropped += 1;
+    }
+
+    pub(crate) fn add_malformed_dropped(&mut self) {
+        self.malformed_dropped += 1;
+    }
+
+    pub(crate) fn add_ip_banned(&mut self) {
+        self.ips_banned += 1;
+    }
+
+    pub(crate) fn set_storage(&mut self, infohashes_tracked: usize, peers_stored: usize) {
+        self.infohashes_tracked = infohashes_tracked;
+        self.peers_stored = peers_stored;
+    }
+
+    pub(crate) fn set_nodes(&mut self, nodes: usize) {
+        self.nodes = nodes;
+    }
+
+    pub(crate) fn add_lookup_started(&mut self) {
+        self.lookups_started += 1;
+    }
+
+    pub(crate) fn add_lookup_completed(&mut self) {
+        self.lookups_completed += 1;
+    }
+
+    /// Our current node id.
+    pub fn node_id(&self) -> NodeId {
+        self.node_id
+    }
+
+    /// External ip that we were configured with or that remote nodes agreed on, if any.
+    pub fn external_ip(&self) -> Option<IpAddr> {
+        self.opt_external_ip
+    }
+
+    /// Whether our node id was generated from our external ip as described in BEP 42.
+    ///
+    /// False if we do not know our external ip yet.
+    pub fn is_node_id_compliant(&self) -> bool {
+        self.opt_external_ip
+            .map(|ip| security::is_generated_from_ip(ip, self.node_id))
+            .unwrap_or(false)
+    }
+
+    /// Number of messages from remote nodes we dropped for going over the rate limit
+    /// or coming from a banned ip.
+    pub fn queries_dropped(&self) -> u64 {
+        self.queries_dropped
+    }
+
+    /// Number of messages from remote nodes we dropped without answering because we could not
+    /// decode them, including responses to transactions we no longer know about.
+    pub fn malformed_dropped(&self) -> u64 {
+        self.malformed_dropped
+    }
+
+    /// Number of times we banned the ip of a remote node for flooding us with queries
+    /// or sending us malformed messages.
+    pub fn ips_banned(&self) -> u64 {
+        self.ips_banned
+    }
+
+    /// Number of InfoHashs remote nodes announced themselves to us for.
+    pub fn infohashes_tracked(&self) -> usize {
+        self.infohashes_tracked
+    }
+
+    /// Number of peers we store across all InfoHashs, on behalf of the remote nodes that
+    /// announced themselves to us.
+    pub fn peers_stored(&self) -> usize {
+        self.peers_stored
+    }
+
+    /// Number of good nodes in our routing table, as of the last bootstrap or table refresh.
+    pub fn nodes(&self) -> usize {
+        self.nodes
+    }
+
+    /// Number of lookups we started, never decreases.
+    pub fn lookups_started(&self) -> u64 {
+        self.lookups_started
+    }
+
+    /// Number of lookups that ran out of nodes to contact, never decreases.
+    pub fn lookups_completed(&self) -> u64 {
+        self.lookups_completed
+    }
+}
+
+/// How our node treats queries from remote nodes while it is read only, see BEP 43.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum ReadOnlyQueries {
+    /// Silently drop queries, as if our node was not there.
+    Ignore,
+    /// Answer queries with an error, so remote nodes do not have to wait for a timeout.
+    Reject,
+}
+
 /// Event that occured within the DHT which caused it to shutdown.
 #[derive(Copy, Clone, Debug)]
 pub enum ShutdownCause {
@@ -68,26 +260,81 @@ pub enum ShutdownCause {
 
 /// Spawns the necessary workers that make up our local DHT node and connects them via channels
 /// so that they can send and receive DHT messages.
+///
+/// Returns the status of our node alongside the channel, which the workers keep up to date.
 pub fn start_mainline_dht<H>(
     send_socket: UdpSocket,
     recv_socket: UdpSocket,
     read_only: bool,
-    _: Option<SocketAddr>,
+    read_only_queries: ReadOnlyQueries,
+    implied_port: bool,
+    ext_addr: Option<SocketAddr>,
+    policy: NodeIdPolicy,
+    limiter: QueryLimiter,
+    max_samples: usize,
+    want: Option<Want>,
+    opt_state: Option<&DhtState>,
+    max_node_age: Duration,
+    watchdog: BootstrapWatchdog,
     handshaker: H,
     kill_sock: UdpSocket,
     kill_addr: SocketAddr,
-) -> io::Result<mio::Sender<OneshotTask>>
+) -> io::Result<(mio::Sender<OneshotTask>, Arc<Mutex<DhtStatus>>)>
 where
     H: Handshaker + 'static,
 {
-    let outgoing = messenger::create_outgoing_messenger(send_socket);
+    // Our routing table only holds nodes of the address family we are bound to
+    let ipv6 = recv_socket.local_addr()?.is_ipv6();
+    // Shared with the outgoing messenger, which marks our queries while we are read only
+    let read_only = Arc::new(AtomicBool::new(read_only));
+    let outgoing = messenger::create_outgoing_messenger(send_socket, read_only.clone());
+
+    // Saved identities only carry over to the address family they were voted on in
+    let opt_identity = opt_state.filter(|state| {
+        state
+            .external_ip()
+            .map(|ip| ip.is_ipv6() == ipv6)
+            .unwrap_or(true)
+    });
+    let opt_external_ip = ext_addr
+        .map(|addr| addr.ip())
+        .or(opt_identity.and_then(|state| state.external_ip()));
+
+    // Without an external ip we start out random, until remote nodes tell us our ip
+    let node_id = match (opt_identity, opt_external_ip) {
+        (Some(state), None) => state.node_id(),
+        (Some(state), Some(ip)) if security::is_generated_from_ip(ip, state.node_id()) => {
+            state.node_id()
+        }
+        (_, Some(ip)) => NodeId::from_ip_with_rand(ip, rand::random::<u8>()),
+        (None, None) => table::random_node_id(),
+    };
+    let status = Arc::new(Mutex::new(DhtStatus::new(node_id, opt_external_ip)));
+
+    let mut routing_table = RoutingTable::with_policy(node_id, policy);
+    let mut external_ip_votes = ExternalIpVotes::new(opt_external_ip);
+    if let Some(state) = opt_state {
+        for node in state.nodes(ipv6, max_node_age) {
+            routing_table.add_node(node);
+        }
+    }
+    if let Some(state) = opt_identity {
+        external_ip_votes = ExternalIpVotes::with_votes(opt_external_ip, state.votes(ipv6));
+    }
 
-    // TODO: Utilize the security extension.
-    let routing_table = RoutingTable::new(table::random_node_id());
     let message_sender = handler::create_dht_handler(
         routing_table,
         outgoing,
         read_only,
+        read_only_queries,
+        implied_port,
+        external_ip_votes,
+        limiter,
+        max_samples,
+        want,
+        ipv6,
+        watchdog,
+        status.clone(),
         handshaker,
         kill_sock,
         kill_addr,
@@ -95,5 +342,5 @@ where
 
     messenger::create_incoming_messenger(recv_socket, message_sender.clone());
 
-    Ok(message_sender)
+    Ok((message_sender, status))
 }
@@ -7,6 +7,7 @@ use crate::dht::handshake::Handshaker;
 use crate::util::bt::{self, NodeId};
 
 use crate::dht::message::find_node::FindNodeRequest;
+use crate::dht::message::Want;
 use crate::dht::routing::node::NodeStatus;
 use crate::dht::routing::table::{self, RoutingTable};
 use crate::dht::transaction::MIDGenerator;
@@ -25,13 +26,15 @@ pub enum RefreshStatus {
 pub struct TableRefresh {
     id_generator: MIDGenerator,
     curr_refresh_bucket: usize,
+    want: Option<Want>,
 }
 
 impl TableRefresh {
-    pub fn new(id_generator: MIDGenerator) -> TableRefresh {
+    pub fn new(id_generator: MIDGenerator, want: Option<Want>) -> TableRefresh {
         TableRefresh {
             id_generator: id_generator,
             curr_refresh_bucket: 0,
+            want: want,
         }
     }
 
@@ -63,7 +66,8 @@ impl TableRefresh {
             let trans_id = self.id_generator.generate();
 
             // Construct the message
-            let find_node_req = FindNodeRequest::new(trans_id.as_ref(), table.node_id(), target_id);
+            let find_node_req =
+                FindNodeRequest::new(trans_id.as_ref(), table.node_id(), target_id, self.want);
             let find_node_msg = find_node_req.encode();
 
             // Send the message
@@ -98,6 +102,31 @@ impl TableRefresh {
 
         RefreshStatus::Refreshing
     }
+
+    /// Ping the node at the given address with a find node on our own id, the node is added
+    /// to our routing table once it responds.
+    pub fn ping_node(
+        &mut self,
+        addr: SocketAddr,
+        table: &RoutingTable,
+        out: &SyncSender<(Vec<u8>, SocketAddr)>,
+    ) -> RefreshStatus {
+        let trans_id = self.id_generator.generate();
+        let find_node_msg = FindNodeRequest::new(
+            trans_id.as_ref(),
+            table.node_id(),
+            table.node_id(),
+            self.want,
+        )
+        .encode();
+
+        if out.send((find_node_msg, addr)).is_err() {
+            error!("bittorrent-protocol_dht: TableRefresh failed to send a node ping to the out channel...");
+            return RefreshStatus::Failed;
+        }
+
+        RefreshStatus::Refreshing
+    }
 }
 
 /// Panics if index is out of bounds.
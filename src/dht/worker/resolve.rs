@@ -0,0 +1,138 @@
+use std::io::{self, ErrorKind};
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::thread;
+use std::time::Duration;
+
+use mio::Sender;
+
+use crate::dht::worker::OneshotTask;
+
+/// Number of times we try to resolve a bootstrap host before giving up on it.
+const MAX_RESOLVE_ATTEMPTS: u32 = 10;
+
+const RESOLVE_INITIAL_BACKOFF_SECS: u64 = 1;
+const RESOLVE_MAX_BACKOFF_SECS: u64 = 5 * 60;
+
+/// Spawns a thread resolving the given host:port strings, sending the addresses of our own address
+/// family to the DHT as routers.
+///
+/// Hosts failing to resolve are retried with an exponential backoff, since the network may simply
+/// not be up yet. Hosts that can never resolve, such as those missing a port, are given up on.
+pub fn spawn_host_resolver(hosts: Vec<String>, ipv6: bool, send: Sender<OneshotTask>) {
+    thread::spawn(move || {
+        let mut pending = hosts;
+        let mut attempt = 0;
+
+        while !pending.is_empty() && attempt < MAX_RESOLVE_ATTEMPTS {
+            if attempt != 0 {
+                thread::sleep(retry_delay(attempt));
+            }
+            attempt += 1;
+
+            let mut routers = Vec::new();
+            pending.retain(|host| match resolve_host(host, ipv6) {
+                Ok(addrs) => {
+                    routers.extend(addrs);
+                    false
+                }
+                Err(ref error) if error.kind() == ErrorKind::InvalidInput => {
+                    warn!(
+                        "bittorrent-protocol_dht: Bootstrap host {} is invalid, giving up on it: {}",
+                        host, error
+                    );
+                    false
+                }
+                Err(error) => {
+                    info!(
+                        "bittorrent-protocol_dht: Failed to resolve bootstrap host {} on attempt {}: {}",
+                        host, attempt, error
+                    );
+                    true
+                }
+            });
+
+            // DHT shut down, nobody is left to bootstrap
+            if !routers.is_empty() && send.send(OneshotTask::AddRouters(routers)).is_err() {
+                return;
+            }
+        }
+
+        for host in pending {
+            warn!(
+                "bittorrent-protocol_dht: Giving up on resolving bootstrap host {}...",
+                host
+            );
+        }
+    });
+}
+
+/// Resolve the host:port string to the addresses of the given address family.
+fn resolve_host(host: &str, ipv6: bool) -> io::Result<Vec<SocketAddr>> {
+    let addrs: Vec<SocketAddr> = host
+        .to_socket_addrs()?
+        .filter(|addr| addr.is_ipv6() == ipv6)
+        .collect();
+
+    if addrs.is_empty() {
+        Err(io::Error::new(
+            ErrorKind::NotFound,
+            "No Addresses Of Our Family Found For Host",
+        ))
+    } else {
+        Ok(addrs)
+    }
+}
+
+/// Delay before the given resolve attempt, doubling with every attempt.
+fn retry_delay(attempt: u32) -> Duration {
+    let backoff = RESOLVE_INITIAL_BACKOFF_SECS
+        .checked_shl(attempt - 1)
+        .unwrap_or(RESOLVE_MAX_BACKOFF_SECS);
+
+    Duration::from_secs(backoff.min(RESOLVE_MAX_BACKOFF_SECS))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::ErrorKind;
+    use std::net::SocketAddr;
+    use std::time::Duration;
+
+    use super::{resolve_host, retry_delay, RESOLVE_MAX_BACKOFF_SECS};
+
+    #[test]
+    fn positive_resolve_host() {
+        let addrs = resolve_host("127.0.0.1:6881", false).unwrap();
+
+        assert_eq!(addrs, vec!["127.0.0.1:6881".parse::<SocketAddr>().unwrap()]);
+    }
+
+    #[test]
+    fn positive_retry_delay_backs_off() {
+        assert_eq!(retry_delay(1), Duration::from_secs(1));
+        assert_eq!(retry_delay(2), Duration::from_secs(2));
+        assert_eq!(retry_delay(5), Duration::from_secs(16));
+        assert_eq!(
+            retry_delay(20),
+            Duration::from_secs(RESOLVE_MAX_BACKOFF_SECS)
+        );
+        assert_eq!(
+            retry_delay(70),
+            Duration::from_secs(RESOLVE_MAX_BACKOFF_SECS)
+        );
+    }
+
+    #[test]
+    fn negative_resolve_host_missing_port() {
+        let error = resolve_host("router.example.com", false).unwrap_err();
+
+        assert_eq!(error.kind(), ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn negative_resolve_host_other_family() {
+        let error = resolve_host("127.0.0.1:6881", true).unwrap_err();
+
+        assert_eq!(error.kind(), ErrorKind::NotFound);
+    }
+}
@@ -0,0 +1,292 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::mpsc::SyncSender;
+use std::time::{Duration, Instant};
+
+use futures::channel::mpsc::UnboundedSender;
+use mio::{EventLoop, Timeout};
+
+use crate::util::bt::{InfoHash, NodeId};
+
+use crate::dht::handshake::Handshaker;
+use crate::dht::message::error::ErrorMessage;
+use crate::dht::message::sample_infohashes::{SampleInfoHashesRequest, SampleInfoHashesResponse};
+use crate::dht::sample::{SampleError, SampleResult};
+use crate::dht::storage::AnnounceStorage;
+use crate::dht::transaction::{MIDGenerator, TransactionID};
+use crate::dht::worker::handler::DhtHandler;
+use crate::dht::worker::ScheduledTask;
+
+const SAMPLE_TIMEOUT_MS: u64 = 1500;
+
+/// Interval we refresh the samples we give out at, also the longest interval we honor.
+const SAMPLE_INTERVAL_SECS: u64 = 6 * 60 * 60;
+
+pub type SampleSender = UnboundedSender<Result<SampleResult, SampleError>>;
+
+/// Single sample infohashes request made to a remote node, see BEP 51.
+pub struct SampleQuery {
+    trans_id: TransactionID,
+    timeout: Timeout,
+    send: SampleSender,
+}
+
+impl SampleQuery {
+    pub fn new<H>(
+        table_id: NodeId,
+        target: NodeId,
+        addr: SocketAddr,
+        mut id_generator: MIDGenerator,
+        send: SampleSender,
+        out: &SyncSender<(Vec<u8>, SocketAddr)>,
+        event_loop: &mut EventLoop<DhtHandler<H>>,
+    ) -> Option<SampleQuery>
+    where
+        H: Handshaker,
+    {
+        let trans_id = id_generator.generate();
+
+        let timeout = if let Ok(t) = event_loop.timeout_ms(
+            (0, ScheduledTask::CheckSampleTimeout(trans_id)),
+            SAMPLE_TIMEOUT_MS,
+        ) {
+            t
+        } else {
+            error!("bittorrent-protocol_dht: Failed to set a timeout for a sample query...");
+            return None;
+        };
+
+        let sample_msg = SampleInfoHashesRequest::new(trans_id.as_ref(), table_id, target).encode();
+        if out.send((sample_msg, addr)).is_err() {
+            error!("bittorrent-protocol_dht: Could not send a sample query message through the channel...");
+            return None;
+        }
+
+        Some(SampleQuery {
+            trans_id: trans_id,
+            timeout: timeout,
+            send: send,
+        })
+    }
+
+    /// Finish the query with the nodes response, returning how long until the node may be sampled again.
+    pub fn recv_response<H>(
+        self,
+        trans_id: &TransactionID,
+        response: &SampleInfoHashesResponse,
+        event_loop: &mut EventLoop<DhtHandler<H>>,
+    ) -> Duration
+    where
+        H: Handshaker,
+    {
+        self.check_trans_id(trans_id);
+        event_loop.clear_timeout(self.timeout);
+
+        let interval = Duration::from_secs(
+            response
+                .interval()
+                .map(|secs| (secs.max(0) as u64).min(SAMPLE_INTERVAL_SECS))
+                .unwrap_or(0),
+        );
+        let samples: Vec<InfoHash> = response.samples().collect();
+        // Nodes not supporting BEP 51 will not tell us how many info hashes they have
+        let num = response
+            .num()
+            .map(|num| num.max(0) as usize)
+            .unwrap_or(samples.len());
+        let nodes = response
+            .nodes()
+            .into_iter()
+            .chain(response.nodes6())
+            .flatten()
+            .collect();
+
+        let _ = self
+            .send
+            .unbounded_send(Ok(SampleResult::new(interval, num, samples, nodes)));
+
+        interval
+    }
+
+    /// Finish the query with the error message the node responded with.
+    pub fn recv_error<H>(self, error: &ErrorMessage, event_loop: &mut EventLoop<DhtHandler<H>>)
+    where
+        H: Handshaker,
+    {
+        event_loop.clear_timeout(self.timeout);
+
+        let _ = self.send.unbounded_send(Err(SampleError::Remote {
+            code: error.error_code(),
+            message: error.error_message().to_owned(),
+        }));
+    }
+
+    /// Finish the query since the node did not respond in time.
+    pub fn recv_timeout(self, trans_id: &TransactionID) {
+        self.check_trans_id(trans_id);
+
+        let _ = self.send.unbounded_send(Err(SampleError::Timeout));
+    }
+
+    fn check_trans_id(&self, trans_id: &TransactionID) {
+        if trans_id != &self.trans_id {
+            warn!("bittorrent-protocol_dht: Received a sample query message with a mismatched TransactionID...");
+        }
+    }
+}
+
+// ----------------------------------------------------------------------------//
+
+/// Tracks when remote nodes told us we may sample them again.
+pub struct SampleIntervals {
+    next_allowed: HashMap<SocketAddr, Instant>,
+}
+
+impl SampleIntervals {
+    pub fn new() -> SampleIntervals {
+        SampleIntervals {
+            next_allowed: HashMap::new(),
+        }
+    }
+
+    /// Time left before the node at the given address may be sampled again, if any.
+    pub fn remaining(&self, addr: &SocketAddr) -> Option<Duration> {
+        let now = Instant::now();
+
+        self.next_allowed
+            .get(addr)
+            .filter(|&&next| next > now)
+            .map(|&next| next - now)
+    }
+
+    /// Record that the node at the given address asked us to wait for the interval.
+    pub fn insert(&mut self, addr: SocketAddr, interval: Duration) {
+        let now = Instant::now();
+
+        // Clear out any intervals that have passed so this does not grow forever
+        self.next_allowed.retain(|_, next| *next > now);
+
+        if interval > Duration::from_secs(0) {
+            self.next_allowed.insert(addr, now + interval);
+        }
+    }
+}
+
+// ----------------------------------------------------------------------------//
+
+/// Samples of the info hashes we are storing, which we give out to remote nodes.
+///
+/// The same samples are given out until the interval passes so that crawlers can not
+/// enumerate our storage by sampling us over and over.
+pub struct SampleCache {
+    max_samples: usize,
+    samples: Vec<InfoHash>,
+    refreshed: Instant,
+}
+
+impl SampleCache {
+    pub fn new(max_samples: usize) -> SampleCache {
+        SampleCache {
+            max_samples: max_samples,
+            samples: Vec::new(),
+            refreshed: Instant::now(),
+        }
+    }
+
+    /// Current samples from the storage, alongside the number of seconds until they are refreshed
+    /// and the number of info hashes in the storage.
+    pub fn samples(&mut self, storage: &mut AnnounceStorage) -> (&[InfoHash], i64, usize) {
+        let interval = Duration::from_secs(SAMPLE_INTERVAL_SECS);
+        let elapsed = self.refreshed.elapsed();
+
+        // Nothing to hold on to, keep checking until we store something
+        if self.samples.is_empty() || elapsed >= interval {
+            self.samples = storage.sample_info_hashes(self.max_samples);
+            self.refreshed = Instant::now();
+        }
+
+        let remaining_secs = if self.samples.is_empty() {
+            0
+        } else {
+            (interval - self.refreshed.elapsed()).as_secs() as i64
+        };
+
+        (&self.samples, remaining_secs, storage.num_info_hashes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+    use std::time::Duration;
+
+    use super::{SampleCache, SampleIntervals, SAMPLE_INTERVAL_SECS};
+    use crate::dht::storage::AnnounceStorage;
+    use crate::util::bt::InfoHash;
+    use crate::util::test as util_test;
+
+    #[test]
+    fn positive_intervals_too_early() {
+        let mut intervals = SampleIntervals::new();
+        let addr = util_test::dummy_socket_addr_v4();
+
+        intervals.insert(addr, Duration::from_secs(60));
+
+        let remaining = intervals.remaining(&addr).unwrap();
+        assert!(remaining <= Duration::from_secs(60) && remaining > Duration::from_secs(50));
+    }
+
+    #[test]
+    fn positive_intervals_zero_interval() {
+        let mut intervals = SampleIntervals::new();
+        let addr = util_test::dummy_socket_addr_v4();
+
+        intervals.insert(addr, Duration::from_secs(0));
+
+        assert_eq!(intervals.remaining(&addr), None);
+    }
+
+    #[test]
+    fn positive_intervals_other_node() {
+        let mut intervals = SampleIntervals::new();
+        let addr: SocketAddr = "10.0.0.1:6881".parse().unwrap();
+        let other: SocketAddr = "10.0.0.2:6881".parse().unwrap();
+
+        intervals.insert(addr, Duration::from_secs(60));
+
+        assert_eq!(intervals.remaining(&other), None);
+    }
+
+    #[test]
+    fn positive_cache_keeps_samples() {
+        let mut storage = AnnounceStorage::new();
+        let mut cache = SampleCache::new(1);
+        let addr = util_test::dummy_socket_addr_v4();
+
+        storage.add_item(InfoHash::from_bytes(b"one"), addr, false);
+        let first = {
+            let (samples, remaining_secs, num) = cache.samples(&mut storage);
+            assert!(remaining_secs > 0 && remaining_secs <= SAMPLE_INTERVAL_SECS as i64);
+            assert_eq!(num, 1);
+
+            samples.to_vec()
+        };
+
+        storage.add_item(InfoHash::from_bytes(b"two"), addr, false);
+        let (samples, _, num) = cache.samples(&mut storage);
+        assert_eq!(samples, &first[..]);
+        assert_eq!(num, 2);
+    }
+
+    #[test]
+    fn positive_cache_empty_storage() {
+        let mut storage = AnnounceStorage::new();
+        let mut cache = SampleCache::new(20);
+
+        assert_eq!(cache.samples(&mut storage), (&[][..], 0, 0));
+
+        let info_hash = InfoHash::from_bytes(b"one");
+        storage.add_item(info_hash, util_test::dummy_socket_addr_v4(), false);
+        assert_eq!(cache.samples(&mut storage).0, &[info_hash][..]);
+    }
+}
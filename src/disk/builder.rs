@@ -1,13 +1,46 @@
+use std::time::Duration;
+
 use super::fs::FileSystem;
-use crate::disk::DiskManager;
+use crate::disk::{DiskManager, FaultPolicy};
 
 const DEFAULT_PENDING_SIZE: usize = 10;
 const DEFAULT_COMPLETED_SIZE: usize = 10;
+const DEFAULT_CHECK_WORKERS: usize = 2;
+const DEFAULT_BLOCK_CACHE_SIZE: usize = 0;
+const DEFAULT_WRITE_BUFFER_SIZE: usize = 0;
+const DEFAULT_WRITE_FLUSH_MILLIS: u64 = 1000;
+
+/// How the files of a torrent are allocated when it is added.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum AllocationMode {
+    /// Create files at their full size by writing their last byte, leaving
+    /// it up to the `FileSystem` whether storage is reserved before that.
+    Sparse,
+    /// Reserve storage for files up front, so later writes can not run out of space.
+    ///
+    /// Uses `FileSystem::allocate_file` where available, otherwise files are filled
+    /// with zeroes. Progress is reported via `ODiskMessage::AllocationProgress`.
+    Full,
+    /// Do not allocate files, they grow as blocks are written to them.
+    DontAllocate,
+}
+
+impl Default for AllocationMode {
+    fn default() -> AllocationMode {
+        AllocationMode::Sparse
+    }
+}
 
 /// `DiskManagerBuilder` for building `DiskManager`s with different settings.
 pub struct DiskManagerBuilder {
     pending_size: usize,
     completed_size: usize,
+    allocation_mode: AllocationMode,
+    check_workers: usize,
+    block_cache_size: usize,
+    fault_policy: FaultPolicy,
+    write_buffer_size: usize,
+    write_flush_interval: Duration,
 }
 
 impl DiskManagerBuilder {
@@ -16,6 +49,12 @@ impl DiskManagerBuilder {
         DiskManagerBuilder {
             pending_size: DEFAULT_PENDING_SIZE,
             completed_size: DEFAULT_COMPLETED_SIZE,
+            allocation_mode: AllocationMode::default(),
+            check_workers: DEFAULT_CHECK_WORKERS,
+            block_cache_size: DEFAULT_BLOCK_CACHE_SIZE,
+            fault_policy: FaultPolicy::default(),
+            write_buffer_size: DEFAULT_WRITE_BUFFER_SIZE,
+            write_flush_interval: Duration::from_millis(DEFAULT_WRITE_FLUSH_MILLIS),
         }
     }
 
@@ -31,6 +70,48 @@ impl DiskManagerBuilder {
         self
     }
 
+    /// Specify how files are allocated when a torrent is added.
+    pub fn with_allocation_mode(mut self, mode: AllocationMode) -> DiskManagerBuilder {
+        self.allocation_mode = mode;
+        self
+    }
+
+    /// Specify the number of workers hashing pieces for `IDiskMessage::CheckTorrent`.
+    pub fn with_check_workers(mut self, workers: usize) -> DiskManagerBuilder {
+        self.check_workers = workers;
+        self
+    }
+
+    /// Specify the size in bytes of the cache for loaded and processed blocks.
+    ///
+    /// Blocks loaded repeatedly, for example when uploading a piece to multiple
+    /// peers, are then served from memory. A size of zero, the default, disables the cache.
+    pub fn with_block_cache_size(mut self, size: usize) -> DiskManagerBuilder {
+        self.block_cache_size = size;
+        self
+    }
+
+    /// Specify how IO errors hit while processing blocks are handled.
+    pub fn with_fault_policy(mut self, policy: FaultPolicy) -> DiskManagerBuilder {
+        self.fault_policy = policy;
+        self
+    }
+
+    /// Specify the size in bytes of the buffer coalescing processed blocks in to larger writes.
+    ///
+    /// Blocks of a piece are then written at once when the piece completes, and a piece found
+    /// bad is never written. A size of zero, the default, writes every block as it is processed.
+    pub fn with_write_buffer_size(mut self, size: usize) -> DiskManagerBuilder {
+        self.write_buffer_size = size;
+        self
+    }
+
+    /// Specify how long blocks of an incomplete piece are buffered before they are written.
+    pub fn with_write_flush_interval(mut self, interval: Duration) -> DiskManagerBuilder {
+        self.write_flush_interval = interval;
+        self
+    }
+
     /// Retrieve the sink buffer capacity.
     pub fn sink_buffer_capacity(&self) -> usize {
         self.pending_size
@@ -41,6 +122,36 @@ impl DiskManagerBuilder {
         self.completed_size
     }
 
+    /// Retrieve the allocation mode.
+    pub fn allocation_mode(&self) -> AllocationMode {
+        self.allocation_mode
+    }
+
+    /// Retrieve the number of check workers.
+    pub fn check_workers(&self) -> usize {
+        self.check_workers
+    }
+
+    /// Retrieve the block cache size.
+    pub fn block_cache_size(&self) -> usize {
+        self.block_cache_size
+    }
+
+    /// Retrieve the fault policy.
+    pub fn fault_policy(&self) -> FaultPolicy {
+        self.fault_policy
+    }
+
+    /// Retrieve the write buffer size.
+    pub fn write_buffer_size(&self) -> usize {
+        self.write_buffer_size
+    }
+
+    /// Retrieve the write flush interval.
+    pub fn write_flush_interval(&self) -> Duration {
+        self.write_flush_interval
+    }
+
     /// Build a `DiskManager` with the given `FileSystem`.
     pub fn build<F>(self, fs: F) -> DiskManager<F>
     where
@@ -19,6 +19,19 @@ error_chain! {
             description("Failed To Load/Process Block Because Torrent Is Not Loaded")
             display("Failed To Load/Process Block Because The InfoHash {:?} It Is Not Currently Added", hash)
         }
+        PieceSkipped {
+            hash:  InfoHash,
+            index: u64
+        } {
+            description("Failed To Process Block Because Its Piece Lies Inside Skipped Files")
+            display("Failed To Process Block Because Piece {} Of The InfoHash {:?} Lies Inside Skipped Files", index, hash)
+        }
+        ReadOnlyTorrent {
+            hash: InfoHash
+        } {
+            description("Failed To Process Block Because The Torrent Is Only Seeded")
+            display("Failed To Process Block Because The InfoHash {:?} Is Only Seeded", hash)
+        }
     }
 }
 
@@ -41,12 +54,70 @@ error_chain! {
             description("Failed To Add Torrent Because Size Checker Failed For A File")
             display("Failed To Add Torrent Because Size Checker Failed For {:?} Where File Size Was {} But Should Have Been {}", file_path, actual_size, expected_size)
         }
+        AllocationFailed {
+            file_path:      PathBuf,
+            required_bytes: u64
+        } {
+            description("Failed To Add Torrent Because A File Could Not Be Allocated")
+            display("Failed To Add Torrent Because Allocating {} Bytes For {:?} Failed", required_bytes, file_path)
+        }
         ExistingInfoHash {
             hash: InfoHash
         } {
             description("Failed To Add Torrent Because Another Torrent With The Same InfoHash Is Already Added")
             display("Failed To Add Torrent Because Another Torrent With The Same InfoHash {:?} Is Already Added", hash)
         }
+        ExistingCheck {
+            hash: InfoHash
+        } {
+            description("Failed To Check Torrent Because It Is Already Being Checked")
+            display("Failed To Check Torrent Because The InfoHash {:?} Is Already Being Checked", hash)
+        }
+        MoveDuringCheck {
+            hash: InfoHash
+        } {
+            description("Failed To Move Torrent Because It Is Being Checked")
+            display("Failed To Move Torrent Because The InfoHash {:?} Is Being Checked", hash)
+        }
+        MoveFailed {
+            file_path: PathBuf,
+            new_path:  PathBuf
+        } {
+            description("Failed To Move Torrent Because A File Could Not Be Moved")
+            display("Failed To Move Torrent Because Moving {:?} To {:?} Failed", file_path, new_path)
+        }
+        InvalidFilePriorities {
+            hash:     InfoHash,
+            expected: usize,
+            actual:   usize
+        } {
+            description("Failed To Set File Priorities Because The Number Of Priorities Does Not Match The Number Of Files")
+            display("Failed To Set File Priorities Because {} Priorities Were Given For The {} Files Of The InfoHash {:?}", actual, expected, hash)
+        }
+        PrioritiesDuringCheck {
+            hash: InfoHash
+        } {
+            description("Failed To Set File Priorities Because The Torrent Is Being Checked")
+            display("Failed To Set File Priorities Because The InfoHash {:?} Is Being Checked", hash)
+        }
+        TorrentNotPaused {
+            hash: InfoHash
+        } {
+            description("Failed To Resume Torrent Because It Is Not Paused")
+            display("Failed To Resume Torrent Because The InfoHash {:?} Is Not Paused", hash)
+        }
+        ReadOnlyTorrent {
+            hash: InfoHash
+        } {
+            description("Failed To Change Torrent Because It Is Only Seeded")
+            display("Failed To Change Torrent Because The InfoHash {:?} Is Only Seeded", hash)
+        }
+        ModeDuringCheck {
+            hash: InfoHash
+        } {
+            description("Failed To Set Torrent Mode Because The Torrent Is Being Checked")
+            display("Failed To Set Torrent Mode Because The InfoHash {:?} Is Being Checked", hash)
+        }
         InfoHashNotFound {
             hash: InfoHash
         } {
@@ -0,0 +1,178 @@
+use std::io;
+use std::time::Duration;
+
+const DEFAULT_RETRIES: usize = 3;
+const DEFAULT_BACKOFF_MILLIS: u64 = 50;
+
+/// Class of an IO error that a `DiskManager` hit while writing a torrent.
+#[derive(Debug)]
+pub enum DiskFault {
+    /// The file system ran out of space, or the user ran out of quota.
+    OutOfSpace,
+    /// The file system denied access to a file of the torrent.
+    PermissionDenied,
+    /// A file of the torrent, or a directory leading to it, was removed.
+    FileMissing,
+    /// Any other error, which may go away when the write is retried.
+    Transient(io::Error),
+}
+
+impl DiskFault {
+    /// Classify the given IO error.
+    pub fn classify(err: &io::Error) -> DiskFault {
+        if is_out_of_space(err) {
+            return DiskFault::OutOfSpace;
+        }
+
+        match err.kind() {
+            io::ErrorKind::PermissionDenied => DiskFault::PermissionDenied,
+            io::ErrorKind::NotFound => DiskFault::FileMissing,
+            kind => DiskFault::Transient(io::Error::new(kind, err.to_string())),
+        }
+    }
+
+    /// Whether retrying the write can not succeed until the user steps in.
+    pub fn is_fatal(&self) -> bool {
+        !matches!(*self, DiskFault::Transient(_))
+    }
+}
+
+#[cfg(unix)]
+fn is_out_of_space(err: &io::Error) -> bool {
+    matches!(err.raw_os_error(), Some(libc::ENOSPC) | Some(libc::EDQUOT))
+}
+
+#[cfg(windows)]
+fn is_out_of_space(err: &io::Error) -> bool {
+    // ERROR_HANDLE_DISK_FULL and ERROR_DISK_FULL
+    matches!(err.raw_os_error(), Some(39) | Some(112))
+}
+
+#[cfg(not(any(unix, windows)))]
+fn is_out_of_space(_err: &io::Error) -> bool {
+    false
+}
+
+//----------------------------------------------------------------------------//
+
+/// How a `DiskManager` handles IO errors hit while processing blocks.
+///
+/// Transient errors are retried, waiting twice as long before each retry. Fatal errors,
+/// and transient errors still failing after the last retry, pause the torrent.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct FaultPolicy {
+    retries: usize,
+    backoff: Duration,
+    pause_on_fault: bool,
+}
+
+impl FaultPolicy {
+    /// Create a new `FaultPolicy`.
+    pub fn new() -> FaultPolicy {
+        FaultPolicy {
+            retries: DEFAULT_RETRIES,
+            backoff: Duration::from_millis(DEFAULT_BACKOFF_MILLIS),
+            pause_on_fault: true,
+        }
+    }
+
+    /// Specify the number of times a transient error is retried.
+    pub fn with_retries(mut self, retries: usize) -> FaultPolicy {
+        self.retries = retries;
+        self
+    }
+
+    /// Specify how long to wait before the first retry.
+    pub fn with_backoff(mut self, backoff: Duration) -> FaultPolicy {
+        self.backoff = backoff;
+        self
+    }
+
+    /// Specify whether errors pause the torrent.
+    ///
+    /// If not, the error is sent as a `ODiskMessage::ProcessBlockError` message and
+    /// the block is dropped.
+    pub fn with_pause_on_fault(mut self, pause: bool) -> FaultPolicy {
+        self.pause_on_fault = pause;
+        self
+    }
+
+    /// Retrieve the number of retries.
+    pub fn retries(&self) -> usize {
+        self.retries
+    }
+
+    /// Retrieve the wait before the first retry.
+    pub fn backoff(&self) -> Duration {
+        self.backoff
+    }
+
+    /// Retrieve whether errors pause the torrent.
+    pub fn pause_on_fault(&self) -> bool {
+        self.pause_on_fault
+    }
+
+    /// Wait before the retry following the given number of failed retries.
+    pub fn retry_delay(&self, retried: usize) -> Duration {
+        self.backoff
+            .checked_mul(2u32.saturating_pow(retried as u32))
+            .unwrap_or(Duration::MAX)
+    }
+}
+
+impl Default for FaultPolicy {
+    fn default() -> FaultPolicy {
+        FaultPolicy::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use std::time::Duration;
+
+    use super::{DiskFault, FaultPolicy};
+
+    #[test]
+    #[cfg(unix)]
+    fn positive_classify_out_of_space() {
+        let err = io::Error::from_raw_os_error(libc::ENOSPC);
+
+        assert!(matches!(DiskFault::classify(&err), DiskFault::OutOfSpace));
+    }
+
+    #[test]
+    fn positive_classify_by_kind() {
+        let denied = io::Error::new(io::ErrorKind::PermissionDenied, "Denied");
+        let missing = io::Error::new(io::ErrorKind::NotFound, "Missing");
+
+        assert!(matches!(
+            DiskFault::classify(&denied),
+            DiskFault::PermissionDenied
+        ));
+        assert!(matches!(
+            DiskFault::classify(&missing),
+            DiskFault::FileMissing
+        ));
+    }
+
+    #[test]
+    fn positive_classify_transient() {
+        let err = io::Error::new(io::ErrorKind::Interrupted, "Interrupted");
+
+        match DiskFault::classify(&err) {
+            DiskFault::Transient(err) => assert_eq!(io::ErrorKind::Interrupted, err.kind()),
+            unexpected => panic!("Unexpected Fault: {:?}", unexpected),
+        }
+        assert!(!DiskFault::classify(&err).is_fatal());
+    }
+
+    #[test]
+    fn positive_retry_delay_doubles() {
+        let policy = FaultPolicy::new().with_backoff(Duration::from_millis(10));
+
+        assert_eq!(Duration::from_millis(10), policy.retry_delay(0));
+        assert_eq!(Duration::from_millis(20), policy.retry_delay(1));
+        assert_eq!(Duration::from_millis(80), policy.retry_delay(3));
+    }
+}
@@ -0,0 +1,342 @@
+use std::cmp;
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::disk::fs::FileSystem;
+
+/// File that exists in memory.
+pub struct MemoryFile {
+    path: PathBuf,
+}
+
+impl MemoryFile {
+    /// Path the file was opened with.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+/// File system that keeps files in memory, for testing.
+///
+/// Clones share the same files. Modification times come from a counter bumped on every
+/// write, so changing files through `seed_file` or `run_with_lock` does not update them.
+/// Errors can be injected for the next write to a file, or for writes growing the files
+/// past a number of bytes, until `clear_faults` is called.
+#[derive(Clone, Default)]
+pub struct MemoryFileSystem {
+    files: Arc<Mutex<HashMap<PathBuf, Vec<u8>>>>,
+    faults: Arc<Mutex<Faults>>,
+}
+
+#[derive(Default)]
+struct Faults {
+    modified: HashMap<PathBuf, SystemTime>,
+    clock: u64,
+    failing_writes: HashSet<PathBuf>,
+    opt_space_left: Option<u64>,
+}
+
+impl MemoryFileSystem {
+    /// Create a new, empty `MemoryFileSystem`.
+    pub fn new() -> MemoryFileSystem {
+        MemoryFileSystem::default()
+    }
+
+    /// Create, or replace, the file at the given path with the given contents.
+    pub fn seed_file<P>(&self, path: P, contents: Vec<u8>)
+    where
+        P: AsRef<Path>,
+    {
+        self.run_with_lock(|files| {
+            files.insert(path.as_ref().to_path_buf(), contents);
+        })
+    }
+
+    /// Retrieve the contents of the file at the given path.
+    pub fn file_contents<P>(&self, path: P) -> Option<Vec<u8>>
+    where
+        P: AsRef<Path>,
+    {
+        self.run_with_lock(|files| files.get(path.as_ref()).cloned())
+    }
+
+    /// Retrieve the contents of all files.
+    pub fn snapshot(&self) -> HashMap<PathBuf, Vec<u8>> {
+        self.run_with_lock(|files| files.clone())
+    }
+
+    /// Run the given closure with the files locked, for changes not covered by the other methods.
+    pub fn run_with_lock<C, R>(&self, call: C) -> R
+    where
+        C: FnOnce(&mut HashMap<PathBuf, Vec<u8>>) -> R,
+    {
+        let mut lock_files = self.files.lock().expect(
+            "bittorrent-protocol_disk: Failed To Lock Files In MemoryFileSystem::run_with_lock",
+        );
+
+        call(&mut *lock_files)
+    }
+
+    /// Fail the next write to the file at the given path.
+    pub fn fail_next_write<P>(&self, path: P)
+    where
+        P: AsRef<Path>,
+    {
+        self.run_with_faults(|faults| {
+            faults.failing_writes.insert(path.as_ref().to_path_buf());
+        })
+    }
+
+    /// Run out of space once writes grew the files by the given number of bytes.
+    ///
+    /// A write that would grow the files past that fails as a whole, writes
+    /// within the current size of a file always succeed.
+    pub fn fail_writes_after(&self, bytes: u64) {
+        self.run_with_faults(|faults| faults.opt_space_left = Some(bytes))
+    }
+
+    /// Stop injecting errors, as if space was freed on a real disk.
+    pub fn clear_faults(&self) {
+        self.run_with_faults(|faults| {
+            faults.failing_writes.clear();
+            faults.opt_space_left = None;
+        })
+    }
+
+    fn run_with_faults<C, R>(&self, call: C) -> R
+    where
+        C: FnOnce(&mut Faults) -> R,
+    {
+        let mut lock_faults = self.faults.lock().expect(
+            "bittorrent-protocol_disk: Failed To Lock Faults In MemoryFileSystem::run_with_faults",
+        );
+
+        call(&mut *lock_faults)
+    }
+}
+
+impl FileSystem for MemoryFileSystem {
+    type File = MemoryFile;
+
+    fn open_file<P>(&self, path: P) -> io::Result<Self::File>
+    where
+        P: AsRef<Path> + Send + 'static,
+    {
+        let file_path = path.as_ref().to_path_buf();
+
+        self.run_with_lock(|files| {
+            files.entry(file_path.clone()).or_insert_with(Vec::new);
+        });
+
+        Ok(MemoryFile { path: file_path })
+    }
+
+    fn open_file_read_only<P>(&self, path: P) -> io::Result<Self::File>
+    where
+        P: AsRef<Path> + Send + 'static,
+    {
+        let file_path = path.as_ref().to_path_buf();
+
+        self.run_with_lock(|files| {
+            if files.contains_key(&file_path) {
+                Ok(MemoryFile { path: file_path })
+            } else {
+                Err(not_found())
+            }
+        })
+    }
+
+    fn sync_file<P>(&self, _path: P) -> io::Result<()>
+    where
+        P: AsRef<Path> + Send + 'static,
+    {
+        Ok(())
+    }
+
+    fn file_size(&self, file: &Self::File) -> io::Result<u64> {
+        self.run_with_lock(|files| {
+            files
+                .get(&file.path)
+                .map(|file_buffer| file_buffer.len() as u64)
+                .ok_or_else(not_found)
+        })
+    }
+
+    fn file_modified(&self, file: &Self::File) -> io::Result<Option<SystemTime>> {
+        Ok(self.run_with_faults(|faults| faults.modified.get(&file.path).cloned()))
+    }
+
+    fn read_file(
+        &self,
+        file: &mut Self::File,
+        offset: u64,
+        buffer: &mut [u8],
+    ) -> io::Result<usize> {
+        self.run_with_lock(|files| {
+            let file_buffer = files.get(&file.path).ok_or_else(not_found)?;
+
+            let start = cmp::min(offset, file_buffer.len() as u64) as usize;
+            let bytes_to_copy = cmp::min(file_buffer.len() - start, buffer.len());
+            buffer[..bytes_to_copy].copy_from_slice(&file_buffer[start..(start + bytes_to_copy)]);
+
+            Ok(bytes_to_copy)
+        })
+    }
+
+    fn write_file(&self, file: &mut Self::File, offset: u64, buffer: &[u8]) -> io::Result<usize> {
+        // Files are always locked before faults
+        self.run_with_lock(|files| {
+            let file_buffer = files.get_mut(&file.path).ok_or_else(not_found)?;
+            let write_end = offset as usize + buffer.len();
+            let growth = write_end.saturating_sub(file_buffer.len()) as u64;
+
+            self.run_with_faults(|faults| {
+                if faults.failing_writes.remove(&file.path) {
+                    return Err(io::Error::new(
+                        io::ErrorKind::Other,
+                        "Injected Write Failure",
+                    ));
+                }
+
+                match faults.opt_space_left {
+                    Some(space_left) if growth > space_left => return Err(no_space()),
+                    Some(space_left) => faults.opt_space_left = Some(space_left - growth),
+                    None => (),
+                }
+
+                faults.clock += 1;
+                faults.modified.insert(
+                    file.path.clone(),
+                    UNIX_EPOCH + Duration::from_nanos(faults.clock),
+                );
+
+                Ok(())
+            })?;
+
+            if write_end > file_buffer.len() {
+                file_buffer.resize(write_end, 0);
+            }
+            file_buffer[(offset as usize)..write_end].copy_from_slice(buffer);
+
+            Ok(buffer.len())
+        })
+    }
+
+    fn rename_file<P, Q>(&self, from: P, to: Q) -> io::Result<()>
+    where
+        P: AsRef<Path> + Send + 'static,
+        Q: AsRef<Path> + Send + 'static,
+    {
+        let (from_path, to_path) = (from.as_ref().to_path_buf(), to.as_ref().to_path_buf());
+
+        self.run_with_lock(|files| {
+            let file_buffer = files.remove(&from_path).ok_or_else(not_found)?;
+            files.insert(to_path.clone(), file_buffer);
+
+            self.run_with_faults(|faults| {
+                if let Some(modified) = faults.modified.remove(&from_path) {
+                    faults.modified.insert(to_path, modified);
+                }
+            });
+
+            Ok(())
+        })
+    }
+
+    fn remove_file<P>(&self, path: P) -> io::Result<()>
+    where
+        P: AsRef<Path> + Send + 'static,
+    {
+        self.run_with_lock(|files| {
+            files.remove(path.as_ref()).ok_or_else(not_found)?;
+            self.run_with_faults(|faults| faults.modified.remove(path.as_ref()));
+
+            Ok(())
+        })
+    }
+}
+
+fn not_found() -> io::Error {
+    io::Error::new(io::ErrorKind::NotFound, "File Not Found")
+}
+
+#[cfg(unix)]
+fn no_space() -> io::Error {
+    io::Error::from_raw_os_error(libc::ENOSPC)
+}
+
+#[cfg(not(unix))]
+fn no_space() -> io::Error {
+    io::Error::new(io::ErrorKind::Other, "No Space Left On Device")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::MemoryFileSystem;
+    use crate::disk::FileSystem;
+
+    #[test]
+    fn positive_write_and_read() {
+        let filesystem = MemoryFileSystem::new();
+        let mut file = filesystem.open_file("file").unwrap();
+
+        assert_eq!(5, filesystem.write_file(&mut file, 2, b"hello").unwrap());
+
+        let mut buffer = [1u8; 10];
+        assert_eq!(7, filesystem.read_file(&mut file, 0, &mut buffer).unwrap());
+        assert_eq!(b"\0\0hello", &buffer[..7]);
+        assert_eq!(0, filesystem.read_file(&mut file, 20, &mut buffer).unwrap());
+    }
+
+    #[test]
+    fn positive_seed_and_snapshot() {
+        let filesystem = MemoryFileSystem::new();
+        filesystem.seed_file("a", b"seeded".to_vec());
+
+        let mut file = filesystem.open_file("b").unwrap();
+        filesystem.write_file(&mut file, 0, b"written").unwrap();
+
+        let snapshot = filesystem.snapshot();
+        assert_eq!(2, snapshot.len());
+        assert_eq!(b"seeded", &snapshot[&PathBuf::from("a")][..]);
+        assert_eq!(Some(b"written".to_vec()), filesystem.file_contents("b"));
+    }
+
+    #[test]
+    fn negative_fail_next_write() {
+        let filesystem = MemoryFileSystem::new();
+        let (mut file_a, mut file_b) = (
+            filesystem.open_file("a").unwrap(),
+            filesystem.open_file("b").unwrap(),
+        );
+
+        filesystem.fail_next_write("a");
+        assert!(filesystem.write_file(&mut file_b, 0, b"b").is_ok());
+        assert!(filesystem.write_file(&mut file_a, 0, b"a").is_err());
+        assert!(filesystem.write_file(&mut file_a, 0, b"a").is_ok());
+        assert_eq!(Some(b"a".to_vec()), filesystem.file_contents("a"));
+    }
+
+    #[test]
+    fn negative_fail_writes_after() {
+        let filesystem = MemoryFileSystem::new();
+        let mut file = filesystem.open_file("file").unwrap();
+
+        filesystem.fail_writes_after(8);
+        assert!(filesystem.write_file(&mut file, 0, &[1u8; 6]).is_ok());
+        assert!(filesystem.write_file(&mut file, 4, &[2u8; 6]).is_err());
+
+        // Overwriting does not take up more space
+        assert!(filesystem.write_file(&mut file, 0, &[3u8; 6]).is_ok());
+        assert!(filesystem.write_file(&mut file, 6, &[4u8; 2]).is_ok());
+        assert_eq!(
+            Some(vec![3, 3, 3, 3, 3, 3, 4, 4]),
+            filesystem.file_contents("file")
+        );
+    }
+}
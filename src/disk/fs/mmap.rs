@@ -0,0 +1,577 @@
+use std::cmp;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+use crate::disk::fs::native::{NativeFile, NativeFileSystem};
+use crate::disk::fs::FileSystem;
+use lru_cache::LruCache;
+
+/// Size of the regions of a file mapped at once, regions start at multiples of this size.
+const WINDOW_SIZE: u64 = 16 * 1024 * 1024;
+
+/// File that exists on disk, accessed through memory maps where possible.
+pub struct MmapFile {
+    path: PathBuf,
+    file: NativeFile,
+}
+
+/// File system that maps files on disk in to memory.
+///
+/// Files are mapped on demand, in windows of 16 MiB, and the least recently used windows are
+/// unmapped to stay under the given number of mapped bytes. Windows at the end of a file are
+/// mapped again once the file grows past them. Files that can not be mapped, for example on
+/// platforms without memory maps, are accessed with regular reads and writes instead.
+///
+/// Files must not be truncated by other processes while they are mapped.
+pub struct MmapFileSystem {
+    inner: NativeFileSystem,
+    max_mapped_bytes: u64,
+    windows: Mutex<Windows>,
+}
+
+struct Windows {
+    mapped: LruCache<(PathBuf, u64), Arc<sys::Mapping>>,
+    mapped_bytes: u64,
+}
+
+impl MmapFileSystem {
+    /// Initialize a new `MmapFileSystem` with the default directory set, mapping at most
+    /// the given number of bytes at once.
+    pub fn with_directory<P>(default: P, max_mapped_bytes: u64) -> MmapFileSystem
+    where
+        P: AsRef<Path>,
+    {
+        MmapFileSystem {
+            inner: NativeFileSystem::with_directory(default),
+            max_mapped_bytes: max_mapped_bytes,
+            windows: Mutex::new(Windows {
+                mapped: LruCache::new(usize::MAX),
+                mapped_bytes: 0,
+            }),
+        }
+    }
+
+    /// Number of bytes currently mapped.
+    pub fn mapped_bytes(&self) -> u64 {
+        self.run_with_lock(|windows| windows.mapped_bytes)
+    }
+
+    fn run_with_lock<C, R>(&self, call: C) -> R
+    where
+        C: FnOnce(&mut Windows) -> R,
+    {
+        let mut lock_windows = self.windows.lock().expect(
+            "bittorrent-protocol_disk: Failed To Lock Windows In MmapFileSystem::run_with_lock",
+        );
+
+        call(&mut *lock_windows)
+    }
+
+    /// Retrieve the window of the file, mapped at least up to the given length if the file
+    /// is long enough.
+    ///
+    /// Returns None if the window is past the end of the file, or could not be mapped.
+    fn window(
+        &self,
+        file: &MmapFile,
+        index: u64,
+        min_length: u64,
+    ) -> io::Result<Option<Arc<sys::Mapping>>> {
+        self.run_with_lock(|windows| {
+            let key = (file.path.clone(), index);
+            if let Some(mapping) = windows.mapped.get_mut(&key) {
+                if mapping.len() >= min_length {
+                    return Ok(Some(mapping.clone()));
+                }
+            }
+
+            // Either not mapped, or the file may have grown since we mapped the window
+            let (file_size, window_start) =
+                (self.inner.file_size(&file.file)?, index * WINDOW_SIZE);
+            if file_size <= window_start {
+                return Ok(None);
+            }
+
+            let window_length = cmp::min(WINDOW_SIZE, file_size - window_start);
+            if window_length > self.max_mapped_bytes {
+                return Ok(None);
+            }
+
+            // Reads past the end of the file do not need the window mapped again
+            if let Some(mapping) = windows.mapped.get_mut(&key) {
+                if mapping.len() >= window_length {
+                    return Ok(Some(mapping.clone()));
+                }
+            }
+
+            let mapping = match sys::Mapping::new(file.file.as_file(), window_start, window_length)
+            {
+                Ok(mapping) => Arc::new(mapping),
+                Err(err) => {
+                    debug!("Failed To Map {:?}, Using Regular IO: {}", file.path, err);
+
+                    return Ok(None);
+                }
+            };
+
+            if let Some(old_mapping) = windows.mapped.insert(key, mapping.clone()) {
+                windows.mapped_bytes -= old_mapping.len();
+            }
+            windows.mapped_bytes += window_length;
+
+            // Windows still being read from or written to are unmapped once they are done
+            while windows.mapped_bytes > self.max_mapped_bytes {
+                match windows.mapped.remove_lru() {
+                    Some((_, lru_mapping)) => windows.mapped_bytes -= lru_mapping.len(),
+                    None => break,
+                }
+            }
+
+            Ok(Some(mapping))
+        })
+    }
+
+    /// Unmap all windows of the file at the given path.
+    fn unmap<P>(&self, path: P)
+    where
+        P: AsRef<Path>,
+    {
+        self.run_with_lock(|windows| {
+            let keys: Vec<(PathBuf, u64)> = windows
+                .mapped
+                .iter()
+                .filter(|&((window_path, _), _)| window_path == path.as_ref())
+                .map(|(key, _)| key.clone())
+                .collect();
+
+            for key in keys {
+                if let Some(mapping) = windows.mapped.remove(&key) {
+                    windows.mapped_bytes -= mapping.len();
+                }
+            }
+        })
+    }
+}
+
+impl FileSystem for MmapFileSystem {
+    type File = MmapFile;
+
+    fn open_file<P>(&self, path: P) -> io::Result<Self::File>
+    where
+        P: AsRef<Path> + Send + 'static,
+    {
+        let path_buf = path.as_ref().to_path_buf();
+        let file = self.inner.open_file(path)?;
+
+        Ok(MmapFile {
+            path: path_buf,
+            file: file,
+        })
+    }
+
+    fn open_file_read_only<P>(&self, path: P) -> io::Result<Self::File>
+    where
+        P: AsRef<Path> + Send + 'static,
+    {
+        // Windows of a file opened read only fail to map, and fall back to regular IO
+        let path_buf = path.as_ref().to_path_buf();
+        let file = self.inner.open_file_read_only(path)?;
+
+        Ok(MmapFile {
+            path: path_buf,
+            file: file,
+        })
+    }
+
+    fn sync_file<P>(&self, path: P) -> io::Result<()>
+    where
+        P: AsRef<Path> + Send + 'static,
+    {
+        let mappings: Vec<Arc<sys::Mapping>> = self.run_with_lock(|windows| {
+            windows
+                .mapped
+                .iter()
+                .filter(|&((window_path, _), _)| window_path == path.as_ref())
+                .map(|(_, mapping)| mapping.clone())
+                .collect()
+        });
+
+        for mapping in mappings {
+            mapping.sync()?;
+        }
+
+        self.inner.sync_file(path)
+    }
+
+    fn file_size(&self, file: &Self::File) -> io::Result<u64> {
+        self.inner.file_size(&file.file)
+    }
+
+    fn file_modified(&self, file: &Self::File) -> io::Result<Option<SystemTime>> {
+        self.inner.file_modified(&file.file)
+    }
+
+    fn read_file(
+        &self,
+        file: &mut Self::File,
+        offset: u64,
+        buffer: &mut [u8],
+    ) -> io::Result<usize> {
+        let mut read = 0;
+
+        while read < buffer.len() {
+            let position = offset + read as u64;
+            let (index, window_offset) = (position / WINDOW_SIZE, position % WINDOW_SIZE);
+            let wanted = cmp::min((buffer.len() - read) as u64, WINDOW_SIZE - window_offset);
+
+            let bytes_read = match self.window(file, index, window_offset + wanted)? {
+                Some(ref mapping) if mapping.len() > window_offset => {
+                    let length = cmp::min(wanted, mapping.len() - window_offset) as usize;
+                    mapping.read(window_offset, &mut buffer[read..(read + length)]);
+
+                    length
+                }
+                _ => self.inner.read_file(
+                    &mut file.file,
+                    position,
+                    &mut buffer[read..(read + wanted as usize)],
+                )?,
+            };
+
+            // Stop at the end of the file
+            if bytes_read == 0 {
+                break;
+            }
+            read += bytes_read;
+        }
+
+        Ok(read)
+    }
+
+    fn write_file(&self, file: &mut Self::File, offset: u64, buffer: &[u8]) -> io::Result<usize> {
+        // Mapped windows can not grow the file, so grow it before writing to them
+        let write_end = offset + buffer.len() as u64;
+        if write_end > self.inner.file_size(&file.file)? {
+            file.file.as_file().set_len(write_end)?;
+        }
+
+        let mut written = 0;
+        while written < buffer.len() {
+            let position = offset + written as u64;
+            let (index, window_offset) = (position / WINDOW_SIZE, position % WINDOW_SIZE);
+            let wanted = cmp::min((buffer.len() - written) as u64, WINDOW_SIZE - window_offset);
+
+            let bytes_written = match self.window(file, index, window_offset + wanted)? {
+                Some(ref mapping) if mapping.len() >= window_offset + wanted => {
+                    mapping.write(window_offset, &buffer[written..(written + wanted as usize)]);
+
+                    wanted as usize
+                }
+                _ => self.inner.write_file(
+                    &mut file.file,
+                    position,
+                    &buffer[written..(written + wanted as usize)],
+                )?,
+            };
+
+            if bytes_written == 0 {
+                break;
+            }
+            written += bytes_written;
+        }
+
+        Ok(written)
+    }
+
+    fn allocate_file(&self, file: &mut Self::File, size: u64) -> io::Result<bool> {
+        self.inner.allocate_file(&mut file.file, size)
+    }
+
+    fn rename_file<P, Q>(&self, from: P, to: Q) -> io::Result<()>
+    where
+        P: AsRef<Path> + Send + 'static,
+        Q: AsRef<Path> + Send + 'static,
+    {
+        self.unmap(&from);
+        self.unmap(&to);
+
+        self.inner.rename_file(from, to)
+    }
+
+    fn remove_file<P>(&self, path: P) -> io::Result<()>
+    where
+        P: AsRef<Path> + Send + 'static,
+    {
+        self.unmap(&path);
+
+        self.inner.remove_file(path)
+    }
+}
+
+#[cfg(unix)]
+mod sys {
+    use std::fs::File;
+    use std::io;
+    use std::os::unix::io::AsRawFd;
+    use std::ptr;
+
+    /// Region of a file mapped in to memory, unmapped when dropped.
+    pub struct Mapping {
+        ptr: *mut u8,
+        len: u64,
+    }
+
+    // Mapped memory is only accessed by copying in to and out of it
+    unsafe impl Send for Mapping {}
+    unsafe impl Sync for Mapping {}
+
+    impl Mapping {
+        pub fn new(file: &File, offset: u64, len: u64) -> io::Result<Mapping> {
+            let ptr = unsafe {
+                libc::mmap(
+                    ptr::null_mut(),
+                    len as libc::size_t,
+                    libc::PROT_READ | libc::PROT_WRITE,
+                    libc::MAP_SHARED,
+                    file.as_raw_fd(),
+                    offset as libc::off_t,
+                )
+            };
+
+            if ptr == libc::MAP_FAILED {
+                Err(io::Error::last_os_error())
+            } else {
+                Ok(Mapping {
+                    ptr: ptr as *mut u8,
+                    len: len,
+                })
+            }
+        }
+
+        pub fn len(&self) -> u64 {
+            self.len
+        }
+
+        pub fn read(&self, offset: u64, buffer: &mut [u8]) {
+            assert!(offset + buffer.len() as u64 <= self.len);
+
+            unsafe {
+                ptr::copy_nonoverlapping(
+                    self.ptr.add(offset as usize),
+                    buffer.as_mut_ptr(),
+                    buffer.len(),
+                )
+            }
+        }
+
+        pub fn write(&self, offset: u64, buffer: &[u8]) {
+            assert!(offset + buffer.len() as u64 <= self.len);
+
+            unsafe {
+                ptr::copy_nonoverlapping(
+                    buffer.as_ptr(),
+                    self.ptr.add(offset as usize),
+                    buffer.len(),
+                )
+            }
+        }
+
+        pub fn sync(&self) -> io::Result<()> {
+            let result = unsafe {
+                libc::msync(
+                    self.ptr as *mut libc::c_void,
+                    self.len as libc::size_t,
+                    libc::MS_SYNC,
+                )
+            };
+
+            if result == 0 {
+                Ok(())
+            } else {
+                Err(io::Error::last_os_error())
+            }
+        }
+    }
+
+    impl Drop for Mapping {
+        fn drop(&mut self) {
+            unsafe {
+                libc::munmap(self.ptr as *mut libc::c_void, self.len as libc::size_t);
+            }
+        }
+    }
+}
+
+#[cfg(not(unix))]
+mod sys {
+    use std::fs::File;
+    use std::io;
+
+    /// Memory maps are not supported, so there are never any mappings.
+    pub enum Mapping {}
+
+    impl Mapping {
+        pub fn new(_file: &File, _offset: u64, _len: u64) -> io::Result<Mapping> {
+            Err(io::Error::new(
+                io::ErrorKind::Other,
+                "Memory Maps Are Not Supported",
+            ))
+        }
+
+        pub fn len(&self) -> u64 {
+            match *self {}
+        }
+
+        pub fn read(&self, _offset: u64, _buffer: &mut [u8]) {
+            match *self {}
+        }
+
+        pub fn write(&self, _offset: u64, _buffer: &[u8]) {
+            match *self {}
+        }
+
+        pub fn sync(&self) -> io::Result<()> {
+            match *self {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::PathBuf;
+
+    use super::{MmapFileSystem, WINDOW_SIZE};
+    use crate::disk::FileSystem;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "bittorrent-protocol_mmap_{}_{}",
+            name,
+            std::process::id()
+        ));
+        fs::create_dir_all(&dir).unwrap();
+
+        dir
+    }
+
+    #[test]
+    fn positive_write_and_read() {
+        let dir = temp_dir("write_and_read");
+        let filesystem = MmapFileSystem::with_directory(&dir, WINDOW_SIZE);
+
+        let mut file = filesystem.open_file("file").unwrap();
+        assert_eq!(5, filesystem.write_file(&mut file, 10, b"hello").unwrap());
+
+        let mut buffer = [1u8; 15];
+        assert_eq!(15, filesystem.read_file(&mut file, 0, &mut buffer).unwrap());
+        filesystem.sync_file("file").unwrap();
+        let on_disk = fs::read(dir.join("file")).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(b"\0\0\0\0\0\0\0\0\0\0hello", &buffer);
+        assert_eq!(&buffer[..], &on_disk[..]);
+    }
+
+    #[test]
+    fn positive_read_past_grown_window() {
+        let dir = temp_dir("grown_window");
+        let filesystem = MmapFileSystem::with_directory(&dir, WINDOW_SIZE);
+
+        let mut file = filesystem.open_file("file").unwrap();
+        filesystem.write_file(&mut file, 0, &[1u8; 100]).unwrap();
+        filesystem.read_file(&mut file, 0, &mut [0u8; 100]).unwrap();
+        assert_eq!(100, filesystem.mapped_bytes());
+
+        // The window was mapped when the file held 100 bytes, and is mapped again
+        filesystem.write_file(&mut file, 100, &[2u8; 100]).unwrap();
+        let mut buffer = [0u8; 200];
+        let bytes_read = filesystem.read_file(&mut file, 50, &mut buffer).unwrap();
+        let mapped_bytes = filesystem.mapped_bytes();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(150, bytes_read);
+        assert_eq!(&[1u8; 50][..], &buffer[..50]);
+        assert_eq!(&[2u8; 100][..], &buffer[50..150]);
+        assert_eq!(200, mapped_bytes);
+    }
+
+    #[test]
+    fn positive_read_spanning_windows() {
+        let dir = temp_dir("spanning_windows");
+        let filesystem = MmapFileSystem::with_directory(&dir, 2 * WINDOW_SIZE);
+
+        let data: Vec<u8> = (0..200).map(|index| index as u8).collect();
+        let mut file = filesystem.open_file("file").unwrap();
+        filesystem
+            .write_file(&mut file, WINDOW_SIZE - 100, &data)
+            .unwrap();
+
+        let mut buffer = vec![0u8; 200];
+        filesystem
+            .read_file(&mut file, WINDOW_SIZE - 100, &mut buffer)
+            .unwrap();
+        let mapped_bytes = filesystem.mapped_bytes();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(data, buffer);
+        assert_eq!(WINDOW_SIZE + 100, mapped_bytes);
+    }
+
+    #[test]
+    fn positive_unmaps_least_recently_used() {
+        let dir = temp_dir("least_recently_used");
+        let filesystem = MmapFileSystem::with_directory(&dir, 2048);
+
+        for (index, name) in ["a", "b", "c"].iter().enumerate() {
+            let mut file = filesystem.open_file(*name).unwrap();
+            filesystem
+                .write_file(&mut file, 0, &[index as u8; 1024])
+                .unwrap();
+        }
+        assert_eq!(2048, filesystem.mapped_bytes());
+
+        // The first file was unmapped, but is still readable
+        let mut buffer = [1u8; 1024];
+        let mut file = filesystem.open_file("a").unwrap();
+        filesystem.read_file(&mut file, 0, &mut buffer).unwrap();
+        let mapped_bytes = filesystem.mapped_bytes();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!([0u8; 1024], buffer);
+        assert_eq!(2048, mapped_bytes);
+    }
+
+    #[test]
+    fn positive_falls_back_to_regular_io() {
+        let dir = temp_dir("regular_io");
+        let filesystem = MmapFileSystem::with_directory(&dir, 0);
+
+        let mut file = filesystem.open_file("file").unwrap();
+        filesystem.write_file(&mut file, 0, b"hello").unwrap();
+        let mut buffer = [0u8; 10];
+        let bytes_read = filesystem.read_file(&mut file, 0, &mut buffer).unwrap();
+        let mapped_bytes = filesystem.mapped_bytes();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(5, bytes_read);
+        assert_eq!(b"hello", &buffer[..5]);
+        assert_eq!(0, mapped_bytes);
+    }
+
+    #[test]
+    fn positive_remove_file_unmaps() {
+        let dir = temp_dir("remove_file");
+        let filesystem = MmapFileSystem::with_directory(&dir, WINDOW_SIZE);
+
+        let mut file = filesystem.open_file("file").unwrap();
+        filesystem.write_file(&mut file, 0, &[1u8; 100]).unwrap();
+        filesystem.remove_file("file").unwrap();
+        let mapped_bytes = filesystem.mapped_bytes();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(0, mapped_bytes);
+    }
+}
@@ -1,6 +1,9 @@
 use std::io::{self};
 use std::path::Path;
+use std::time::SystemTime;
 
+pub mod memory;
+pub mod mmap;
 pub mod native;
 
 pub mod cache;
@@ -19,6 +22,20 @@ pub trait FileSystem {
     where
         P: AsRef<Path> + Send + 'static;
 
+    /// Open an existing file for reading only.
+    ///
+    /// Fails if the file does not exist, nothing is created. Writing to the
+    /// file returned is allowed to fail.
+    fn open_file_read_only<P>(&self, _path: P) -> io::Result<Self::File>
+    where
+        P: AsRef<Path> + Send + 'static,
+    {
+        Err(io::Error::new(
+            io::ErrorKind::Other,
+            "Opening Files Read Only Is Not Supported",
+        ))
+    }
+
     /// Sync the file.
     fn sync_file<P>(&self, path: P) -> io::Result<()>
     where
@@ -27,6 +44,13 @@ pub trait FileSystem {
     /// Get the size of the file in bytes.
     fn file_size(&self, file: &Self::File) -> io::Result<u64>;
 
+    /// Get the time the file was last modified.
+    ///
+    /// On success, return None if the file system does not track modification times.
+    fn file_modified(&self, _file: &Self::File) -> io::Result<Option<SystemTime>> {
+        Ok(None)
+    }
+
     /// Read the contents of the file at the given offset.
     ///
     /// On success, return the number of bytes read.
@@ -38,6 +62,41 @@ pub trait FileSystem {
     /// On success, return the number of bytes written. If offset is
     /// past the current size of the file, zeroes will be filled in.
     fn write_file(&self, file: &mut Self::File, offset: u64, buffer: &[u8]) -> io::Result<usize>;
+
+    /// Reserve storage for the file up to the given size in bytes.
+    ///
+    /// On success, return false if the file system has no way of reserving
+    /// storage, in which case the caller will fill the file with zeroes.
+    fn allocate_file(&self, _file: &mut Self::File, _size: u64) -> io::Result<bool> {
+        Ok(false)
+    }
+
+    /// Rename the file, replacing any file at the new path.
+    ///
+    /// Intermediate directories will be created if necessary. Fails if the file
+    /// can not be renamed, for example because the new path is on another device,
+    /// in which case the caller will copy the file instead.
+    fn rename_file<P, Q>(&self, _from: P, _to: Q) -> io::Result<()>
+    where
+        P: AsRef<Path> + Send + 'static,
+        Q: AsRef<Path> + Send + 'static,
+    {
+        Err(io::Error::new(
+            io::ErrorKind::Other,
+            "Renaming Files Is Not Supported",
+        ))
+    }
+
+    /// Remove the file.
+    fn remove_file<P>(&self, _path: P) -> io::Result<()>
+    where
+        P: AsRef<Path> + Send + 'static,
+    {
+        Err(io::Error::new(
+            io::ErrorKind::Other,
+            "Removing Files Is Not Supported",
+        ))
+    }
 }
 
 impl<'a, F> FileSystem for &'a F
@@ -53,6 +112,13 @@ where
         FileSystem::open_file(*self, path)
     }
 
+    fn open_file_read_only<P>(&self, path: P) -> io::Result<Self::File>
+    where
+        P: AsRef<Path> + Send + 'static,
+    {
+        FileSystem::open_file_read_only(*self, path)
+    }
+
     fn sync_file<P>(&self, path: P) -> io::Result<()>
     where
         P: AsRef<Path> + Send + 'static,
@@ -64,6 +130,10 @@ where
         FileSystem::file_size(*self, file)
     }
 
+    fn file_modified(&self, file: &Self::File) -> io::Result<Option<SystemTime>> {
+        FileSystem::file_modified(*self, file)
+    }
+
     fn read_file(
         &self,
         file: &mut Self::File,
@@ -76,4 +146,23 @@ where
     fn write_file(&self, file: &mut Self::File, offset: u64, buffer: &[u8]) -> io::Result<usize> {
         FileSystem::write_file(*self, file, offset, buffer)
     }
+
+    fn allocate_file(&self, file: &mut Self::File, size: u64) -> io::Result<bool> {
+        FileSystem::allocate_file(*self, file, size)
+    }
+
+    fn rename_file<P, Q>(&self, from: P, to: Q) -> io::Result<()>
+    where
+        P: AsRef<Path> + Send + 'static,
+        Q: AsRef<Path> + Send + 'static,
+    {
+        FileSystem::rename_file(*self, from, to)
+    }
+
+    fn remove_file<P>(&self, path: P) -> io::Result<()>
+    where
+        P: AsRef<Path> + Send + 'static,
+    {
+        FileSystem::remove_file(*self, path)
+    }
 }
@@ -3,6 +3,7 @@ use std::borrow::Cow;
 use std::fs::{self, File, OpenOptions};
 use std::io::{self, Read, Seek, SeekFrom, Write};
 use std::path::{Path, PathBuf};
+use std::time::SystemTime;
 
 // TODO: This should be sanitizing paths passed into it so they don't escape the base directory!!!
 
@@ -16,6 +17,11 @@ impl NativeFile {
     fn new(file: File) -> NativeFile {
         NativeFile { file: file }
     }
+
+    /// Underlying file on disk.
+    pub(crate) fn as_file(&self) -> &File {
+        &self.file
+    }
 }
 
 /// File system that maps to the OS file system.
@@ -48,6 +54,16 @@ impl FileSystem for NativeFileSystem {
         Ok(NativeFile::new(file))
     }
 
+    fn open_file_read_only<P>(&self, path: P) -> io::Result<Self::File>
+    where
+        P: AsRef<Path> + Send + 'static,
+    {
+        let combine_path = combine_user_path(&path, &self.current_dir);
+        let file = OpenOptions::new().read(true).open(&combine_path)?;
+
+        Ok(NativeFile::new(file))
+    }
+
     fn sync_file<P>(&self, _path: P) -> io::Result<()>
     where
         P: AsRef<Path> + Send + 'static,
@@ -59,6 +75,12 @@ impl FileSystem for NativeFileSystem {
         file.file.metadata().map(|metadata| metadata.len())
     }
 
+    fn file_modified(&self, file: &NativeFile) -> io::Result<Option<SystemTime>> {
+        file.file
+            .metadata()
+            .map(|metadata| metadata.modified().ok())
+    }
+
     fn read_file(
         &self,
         file: &mut NativeFile,
@@ -75,6 +97,45 @@ impl FileSystem for NativeFileSystem {
 
         file.file.write(buffer)
     }
+
+    #[cfg(any(target_os = "linux", target_os = "android", target_os = "freebsd"))]
+    fn allocate_file(&self, file: &mut NativeFile, size: u64) -> io::Result<bool> {
+        use std::os::unix::io::AsRawFd;
+
+        let result =
+            unsafe { libc::posix_fallocate(file.file.as_raw_fd(), 0, size as libc::off_t) };
+
+        match result {
+            0 => Ok(true),
+            // File system does not support it, fall back to zero filling
+            libc::EINVAL | libc::EOPNOTSUPP => Ok(false),
+            errno => Err(io::Error::from_raw_os_error(errno)),
+        }
+    }
+
+    fn rename_file<P, Q>(&self, from: P, to: Q) -> io::Result<()>
+    where
+        P: AsRef<Path> + Send + 'static,
+        Q: AsRef<Path> + Send + 'static,
+    {
+        let combine_from = combine_user_path(&from, &self.current_dir);
+        let combine_to = combine_user_path(&to, &self.current_dir);
+
+        if let Some(parent_dir) = combine_to.parent() {
+            fs::create_dir_all(parent_dir)?;
+        }
+
+        fs::rename(&combine_from, &combine_to)
+    }
+
+    fn remove_file<P>(&self, path: P) -> io::Result<()>
+    where
+        P: AsRef<Path> + Send + 'static,
+    {
+        let combine_path = combine_user_path(&path, &self.current_dir);
+
+        fs::remove_file(&combine_path)
+    }
 }
 
 /// Create a new file with read and write options.
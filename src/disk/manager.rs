@@ -1,4 +1,5 @@
 use std::sync::atomic::{AtomicUsize, Ordering};
+use std::path::PathBuf;
 use std::sync::Arc;
 
 use std::sync::mpsc::{Receiver};
@@ -13,7 +14,12 @@ use futures::{
 };
 use crate::disk::tasks;
 use crate::disk::tasks::context::DiskManagerContext;
-use crate::disk::{DiskManagerBuilder, FileSystem, IDiskMessage, ODiskMessage};
+use crate::disk::error::TorrentResult;
+use crate::disk::{
+    BlockCacheStats, DiskManagerBuilder, FileSystem, IDiskMessage, ODiskMessage, PiecePriorities,
+    ResumeData, WriteBufferStats,
+};
+use crate::util::bt::InfoHash;
 
 /// `DiskManager` object which handles the storage of `Blocks` to the `FileSystem`.
 pub struct DiskManager<F> {
@@ -26,12 +32,27 @@ impl<F> DiskManager<F> {
     pub fn from_builder(mut builder: DiskManagerBuilder, fs: F) -> DiskManager<F> {
         let sink_capacity = builder.sink_buffer_capacity();
         let stream_capacity = builder.stream_buffer_capacity();
+        let allocation_mode = builder.allocation_mode();
+        let check_workers = builder.check_workers();
+        let block_cache_size = builder.block_cache_size();
+        let fault_policy = builder.fault_policy();
+        let write_buffer_size = builder.write_buffer_size();
+        let write_flush_interval = builder.write_flush_interval();
         let cur_sink_capacity = Arc::new(AtomicUsize::new(0));
 
         //let (out_send, out_recv) = tokio::sync::mpsc::channel(stream_capacity);
         let (out_send, out_recv) = std::sync::mpsc::channel();
 
-        let context = DiskManagerContext::new(out_send, fs);
+        let context = DiskManagerContext::new(
+            out_send,
+            fs,
+            allocation_mode,
+            check_workers,
+            block_cache_size,
+            fault_policy,
+            write_buffer_size,
+            write_flush_interval,
+        );
 
         let sink = DiskManagerSink::new(
             context,
@@ -46,6 +67,59 @@ impl<F> DiskManager<F> {
         }
     }
 
+    /// Save resume data for the given torrent, to be passed to `IDiskMessage::AddTorrentWithResume`.
+    pub fn save_resume_data(&self, hash: InfoHash) -> TorrentResult<ResumeData>
+    where
+        F: FileSystem,
+    {
+        self.sink.save_resume_data(hash)
+    }
+
+    /// Retrieve the priority of each piece of the given torrent.
+    pub fn piece_priorities(&self, hash: InfoHash) -> TorrentResult<PiecePriorities> {
+        self.sink.piece_priorities(hash)
+    }
+
+    /// Cancel a running `IDiskMessage::CheckTorrent` for the given torrent.
+    ///
+    /// Returns false if the torrent is not being checked.
+    pub fn cancel_check(&self, hash: InfoHash) -> bool {
+        self.sink.cancel_check(hash)
+    }
+
+    /// Retrieve the hit and miss counters of the block cache.
+    pub fn block_cache_stats(&self) -> BlockCacheStats {
+        self.sink.block_cache_stats()
+    }
+
+    /// Retrieve the coalesced and direct write counters of the write buffer.
+    pub fn write_buffer_stats(&self) -> WriteBufferStats {
+        self.sink.write_buffer_stats()
+    }
+
+    /// Number of messages submitted that have not yet been answered.
+    pub fn queue_depth(&self) -> usize {
+        self.sink.queue_depth()
+    }
+
+    /// Send a `IDiskMessage::MoveTorrent` for the given torrent.
+    ///
+    /// Returns false if the sink is full.
+    pub fn move_torrent(&self, hash: InfoHash, new_root: PathBuf) -> bool
+    where
+        F: FileSystem + Send + Sync + 'static,
+    {
+        self.sink.move_torrent(hash, new_root)
+    }
+
+    /// Finish the messages being executed, and sync every torrent, see `DiskManagerSink::shutdown`.
+    pub async fn shutdown(&self) -> TorrentResult<()>
+    where
+        F: FileSystem,
+    {
+        self.sink.shutdown().await
+    }
+
     /// Break the `DiskManager` into a sink and stream.
     ///
     /// The returned sink implements `Clone`.
@@ -86,7 +160,105 @@ impl<F> DiskManagerSink<F> {
         }
     }
 
+    /// Save resume data for the given torrent, to be passed to `IDiskMessage::AddTorrentWithResume`.
+    ///
+    /// Captures the verified pieces, the size and modification time of every file, and the
+    /// blocks written for incomplete pieces; blocks still being processed may be missing.
+    pub fn save_resume_data(&self, hash: InfoHash) -> TorrentResult<ResumeData>
+    where
+        F: FileSystem,
+    {
+        tasks::execute_save_resume_data(hash, &self.context)
+    }
+
+    /// Retrieve the priority of each piece of the given torrent.
+    ///
+    /// Selection should not request pieces lying wholly inside skipped files, the
+    /// priorities change with every `IDiskMessage::SetFilePriorities` message.
+    pub fn piece_priorities(&self, hash: InfoHash) -> TorrentResult<PiecePriorities> {
+        tasks::execute_piece_priorities(hash, &self.context)
+    }
+
+    /// Cancel a running `IDiskMessage::CheckTorrent` for the given torrent.
+    ///
+    /// The check stops after the pieces already read are hashed, and sends a
+    /// `ODiskMessage::TorrentCheckCancelled` message. Returns false if the torrent
+    /// is not being checked.
+    pub fn cancel_check(&self, hash: InfoHash) -> bool {
+        self.context.cancel_check(hash)
+    }
+
+    /// Retrieve the hit and miss counters of the block cache.
+    pub fn block_cache_stats(&self) -> BlockCacheStats {
+        self.context.block_cache_stats()
+    }
+
+    /// Retrieve the coalesced and direct write counters of the write buffer.
+    pub fn write_buffer_stats(&self) -> WriteBufferStats {
+        self.context.write_buffer_stats()
+    }
+
+    /// Number of messages submitted that have not yet been answered.
+    ///
+    /// Submitting fails once this reaches the sink buffer capacity.
+    pub fn queue_depth(&self) -> usize {
+        self.cur_capacity.load(Ordering::SeqCst)
+    }
+
+    /// Send a `IDiskMessage::MoveTorrent` for the given torrent.
+    ///
+    /// The result is sent as a `ODiskMessage::TorrentMoved` or a
+    /// `ODiskMessage::TorrentMoveFailed` message. Returns false if the sink
+    /// is full.
+    pub fn move_torrent(&self, hash: InfoHash, new_root: PathBuf) -> bool
+    where
+        F: FileSystem + Send + Sync + 'static,
+    {
+        if self.try_submit_work() {
+            tasks::execute_on_pool(
+                IDiskMessage::MoveTorrent(hash, new_root),
+                self.context.clone(),
+            );
+
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Stop taking messages, and wait for the messages being executed to finish.
+    ///
+    /// Running checks are cancelled. Pieces held in the write buffer are written, then the
+    /// files of every torrent are synced, so that `save_resume_data` reflects what is on disk.
+    /// Every torrent is synced even if one fails, the first error is returned. Calls after
+    /// the first return at once.
+    pub async fn shutdown(&self) -> TorrentResult<()>
+    where
+        F: FileSystem,
+    {
+        tasks::execute_shutdown(&self.context).await
+    }
+
+    /// Submit the message to the disk manager without going through `Sink`.
+    ///
+    /// Hands the message back if the sink is full, so it can be sent again later.
+    pub fn try_send(&self, item: IDiskMessage) -> Result<(), IDiskMessage>
+    where
+        F: FileSystem + Send + Sync + 'static,
+    {
+        if self.try_submit_work() {
+            tasks::execute_on_pool(item, self.context.clone());
+
+            Ok(())
+        } else {
+            Err(item)
+        }
+    }
+
     fn try_submit_work(&self) -> bool {
+        if self.context.is_shut_down() {
+            return false;
+        }
         let cur_capacity = self.cur_capacity.fetch_add(1, Ordering::SeqCst);
 
         if cur_capacity < self.max_capacity {
@@ -157,18 +329,30 @@ impl Stream for DiskManagerStream {
     type Item = ODiskMessage;
 
     fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
-        debug!("Polling DiskManagerStream For ODiskMessage");
+        trace!("Polling DiskManagerStream For ODiskMessage");
 
         match self.recv.recv() {
             res @ Ok(ODiskMessage::TorrentAdded(_))
             | res @ Ok(ODiskMessage::TorrentRemoved(_))
             | res @ Ok(ODiskMessage::TorrentSynced(_))
+            | res @ Ok(ODiskMessage::TorrentChecked(_, _))
+            | res @ Ok(ODiskMessage::TorrentCheckCancelled(_))
+            | res @ Ok(ODiskMessage::TorrentMoved(_))
+            | res @ Ok(ODiskMessage::TorrentMoveFailed { .. })
+            | res @ Ok(ODiskMessage::FilePrioritiesSet(_, _))
+            | res @ Ok(ODiskMessage::TorrentResumed(_))
+            | res @ Ok(ODiskMessage::TorrentModeSet(_, _, _))
             | res @ Ok(ODiskMessage::BlockLoaded(_))
-            | res @ Ok(ODiskMessage::BlockProcessed(_)) => {
+            | res @ Ok(ODiskMessage::BlockProcessed(_))
+            | res @ Ok(ODiskMessage::TorrentError(_, _))
+            | res @ Ok(ODiskMessage::LoadBlockError(_, _))
+            | res @ Ok(ODiskMessage::ProcessBlockError(_, _)) => {
                 self.complete_work();
                 Poll::Ready(Some(res.unwrap()))
             }
-            other => Poll::Ready(Some(other.unwrap())),
+            Ok(other) => Poll::Ready(Some(other)),
+            // Every sink, and every task holding on to its context, is gone
+            Err(_) => Poll::Ready(None),
         }
     }
 }
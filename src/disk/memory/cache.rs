@@ -0,0 +1,265 @@
+use std::collections::{HashMap, HashSet};
+
+use bytes::Bytes;
+use lru_cache::LruCache;
+
+use crate::disk::BlockMetadata;
+use crate::util::bt::InfoHash;
+
+/// Hit and miss counters for the block cache of a `DiskManager`.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct BlockCacheStats {
+    hits: u64,
+    misses: u64,
+    cached_bytes: usize,
+}
+
+impl BlockCacheStats {
+    /// Number of blocks loaded from the cache.
+    pub fn hits(&self) -> u64 {
+        self.hits
+    }
+
+    /// Number of blocks loaded from the `FileSystem`.
+    pub fn misses(&self) -> u64 {
+        self.misses
+    }
+
+    /// Number of bytes currently cached.
+    pub fn cached_bytes(&self) -> usize {
+        self.cached_bytes
+    }
+}
+
+// ----------------------------------------------------------------------------//
+
+/// Least recently used cache of blocks, bounded by the total size of the blocks.
+pub struct BlockCache {
+    capacity: usize,
+    blocks: LruCache<BlockMetadata, Bytes>,
+    pieces: HashMap<(InfoHash, u64), HashSet<BlockMetadata>>,
+    stats: BlockCacheStats,
+}
+
+impl BlockCache {
+    /// Create a new `BlockCache` holding up to capacity bytes, a capacity of zero disables the cache.
+    pub fn new(capacity: usize) -> BlockCache {
+        BlockCache {
+            capacity: capacity,
+            blocks: LruCache::new(usize::MAX),
+            pieces: HashMap::new(),
+            stats: BlockCacheStats::default(),
+        }
+    }
+
+    /// Retrieve the block, counting it as a hit or a miss.
+    pub fn get(&mut self, metadata: &BlockMetadata) -> Option<Bytes> {
+        let opt_block = self.blocks.get_mut(metadata).map(|block| block.clone());
+
+        if opt_block.is_some() {
+            self.stats.hits += 1;
+        } else {
+            self.stats.misses += 1;
+        }
+
+        opt_block
+    }
+
+    /// Cache the block, replacing any cached blocks it overlaps with.
+    pub fn insert(&mut self, metadata: BlockMetadata, block: Bytes) {
+        self.invalidate_region(&metadata);
+        if block.len() > self.capacity {
+            return;
+        }
+
+        self.stats.cached_bytes += block.len();
+        self.pieces
+            .entry((metadata.info_hash(), metadata.piece_index()))
+            .or_insert_with(HashSet::new)
+            .insert(metadata);
+        self.blocks.insert(metadata, block);
+
+        while self.stats.cached_bytes > self.capacity {
+            match self.blocks.remove_lru() {
+                Some((lru_metadata, lru_block)) => self.forget(&lru_metadata, &lru_block),
+                None => break,
+            }
+        }
+    }
+
+    /// Remove all cached blocks of the piece.
+    pub fn invalidate_piece(&mut self, hash: InfoHash, piece_index: u64) {
+        for metadata in self.pieces.remove(&(hash, piece_index)).unwrap_or_default() {
+            if let Some(block) = self.blocks.remove(&metadata) {
+                self.stats.cached_bytes -= block.len();
+            }
+        }
+    }
+
+    /// Remove all cached blocks of the torrent.
+    pub fn invalidate_torrent(&mut self, hash: InfoHash) {
+        let piece_indices: Vec<u64> = self
+            .pieces
+            .keys()
+            .filter(|&&(piece_hash, _)| piece_hash == hash)
+            .map(|&(_, piece_index)| piece_index)
+            .collect();
+
+        for piece_index in piece_indices {
+            self.invalidate_piece(hash, piece_index);
+        }
+    }
+
+    /// Retrieve the hit and miss counters.
+    pub fn stats(&self) -> BlockCacheStats {
+        self.stats
+    }
+
+    /// Remove cached blocks of the same piece overlapping the given block.
+    fn invalidate_region(&mut self, metadata: &BlockMetadata) {
+        let key = (metadata.info_hash(), metadata.piece_index());
+        let (start, end) = (
+            metadata.block_offset(),
+            metadata.block_offset() + metadata.block_length() as u64,
+        );
+
+        let overlapping: Vec<BlockMetadata> = match self.pieces.get(&key) {
+            Some(piece_blocks) => piece_blocks
+                .iter()
+                .filter(|cached| {
+                    let cached_end = cached.block_offset() + cached.block_length() as u64;
+
+                    cached.block_offset() < end && start < cached_end
+                })
+                .cloned()
+                .collect(),
+            None => return,
+        };
+
+        for cached in overlapping {
+            if let Some(block) = self.blocks.remove(&cached) {
+                self.forget(&cached, &block);
+            }
+        }
+    }
+
+    /// Update the bookkeeping for a block removed from the lru cache.
+    fn forget(&mut self, metadata: &BlockMetadata, block: &Bytes) {
+        let key = (metadata.info_hash(), metadata.piece_index());
+
+        self.stats.cached_bytes -= block.len();
+        if let Some(piece_blocks) = self.pieces.get_mut(&key) {
+            piece_blocks.remove(metadata);
+
+            if piece_blocks.is_empty() {
+                self.pieces.remove(&key);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+
+    use super::BlockCache;
+    use crate::disk::BlockMetadata;
+    use crate::util::bt::{self, InfoHash};
+
+    fn metadata(piece_index: u64, block_offset: u64, block_length: usize) -> BlockMetadata {
+        BlockMetadata::new(
+            [0u8; bt::INFO_HASH_LEN].into(),
+            piece_index,
+            block_offset,
+            block_length,
+        )
+    }
+
+    #[test]
+    fn positive_hit_and_miss() {
+        let mut cache = BlockCache::new(1024);
+        cache.insert(metadata(0, 0, 16), Bytes::from(vec![1u8; 16]));
+
+        assert_eq!(
+            Some(Bytes::from(vec![1u8; 16])),
+            cache.get(&metadata(0, 0, 16))
+        );
+        assert_eq!(None, cache.get(&metadata(0, 16, 16)));
+
+        let stats = cache.stats();
+        assert_eq!(
+            (1, 1, 16),
+            (stats.hits(), stats.misses(), stats.cached_bytes())
+        );
+    }
+
+    #[test]
+    fn positive_evicts_least_recently_used() {
+        let mut cache = BlockCache::new(32);
+        cache.insert(metadata(0, 0, 16), Bytes::from(vec![0u8; 16]));
+        cache.insert(metadata(1, 0, 16), Bytes::from(vec![1u8; 16]));
+
+        cache.get(&metadata(0, 0, 16));
+        cache.insert(metadata(2, 0, 16), Bytes::from(vec![2u8; 16]));
+
+        assert!(cache.get(&metadata(0, 0, 16)).is_some());
+        assert!(cache.get(&metadata(1, 0, 16)).is_none());
+        assert!(cache.get(&metadata(2, 0, 16)).is_some());
+        assert_eq!(32, cache.stats().cached_bytes());
+    }
+
+    #[test]
+    fn positive_insert_replaces_overlapping() {
+        let mut cache = BlockCache::new(1024);
+        cache.insert(metadata(0, 0, 32), Bytes::from(vec![0u8; 32]));
+        cache.insert(metadata(0, 32, 16), Bytes::from(vec![0u8; 16]));
+
+        cache.insert(metadata(0, 16, 8), Bytes::from(vec![1u8; 8]));
+
+        assert!(cache.get(&metadata(0, 0, 32)).is_none());
+        assert!(cache.get(&metadata(0, 32, 16)).is_some());
+        assert_eq!(24, cache.stats().cached_bytes());
+    }
+
+    #[test]
+    fn positive_invalidate_piece() {
+        let mut cache = BlockCache::new(1024);
+        cache.insert(metadata(0, 0, 16), Bytes::from(vec![0u8; 16]));
+        cache.insert(metadata(0, 16, 16), Bytes::from(vec![0u8; 16]));
+        cache.insert(metadata(1, 0, 16), Bytes::from(vec![0u8; 16]));
+
+        cache.invalidate_piece([0u8; bt::INFO_HASH_LEN].into(), 0);
+
+        assert!(cache.get(&metadata(0, 0, 16)).is_none());
+        assert!(cache.get(&metadata(1, 0, 16)).is_some());
+        assert_eq!(16, cache.stats().cached_bytes());
+    }
+
+    #[test]
+    fn positive_invalidate_torrent() {
+        let other_hash: InfoHash = [1u8; bt::INFO_HASH_LEN].into();
+
+        let mut cache = BlockCache::new(1024);
+        cache.insert(metadata(0, 0, 16), Bytes::from(vec![0u8; 16]));
+        cache.insert(
+            BlockMetadata::new(other_hash, 0, 0, 16),
+            Bytes::from(vec![0u8; 16]),
+        );
+
+        cache.invalidate_torrent([0u8; bt::INFO_HASH_LEN].into());
+
+        assert!(cache.get(&metadata(0, 0, 16)).is_none());
+        assert!(cache
+            .get(&BlockMetadata::new(other_hash, 0, 0, 16))
+            .is_some());
+    }
+
+    #[test]
+    fn negative_zero_capacity_caches_nothing() {
+        let mut cache = BlockCache::new(0);
+        cache.insert(metadata(0, 0, 16), Bytes::from(vec![0u8; 16]));
+
+        assert!(cache.get(&metadata(0, 0, 16)).is_none());
+        assert_eq!(0, cache.stats().cached_bytes());
+    }
+}
@@ -1 +1,3 @@
 pub mod block;
+pub mod cache;
+pub mod write_buffer;
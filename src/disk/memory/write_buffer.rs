@@ -0,0 +1,349 @@
+use std::cmp;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use bytes::Bytes;
+
+use crate::disk::BlockMetadata;
+use crate::util::bt::InfoHash;
+
+/// Write counters for the write buffer of a `DiskManager`.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct WriteBufferStats {
+    coalesced_writes: u64,
+    coalesced_blocks: u64,
+    direct_writes: u64,
+    buffered_bytes: usize,
+}
+
+impl WriteBufferStats {
+    /// Number of writes issued for contiguous runs of buffered blocks.
+    pub fn coalesced_writes(&self) -> u64 {
+        self.coalesced_writes
+    }
+
+    /// Number of blocks written as part of a coalesced write.
+    pub fn coalesced_blocks(&self) -> u64 {
+        self.coalesced_blocks
+    }
+
+    /// Number of blocks written to the `FileSystem` as they were processed.
+    pub fn direct_writes(&self) -> u64 {
+        self.direct_writes
+    }
+
+    /// Number of bytes currently buffered.
+    pub fn buffered_bytes(&self) -> usize {
+        self.buffered_bytes
+    }
+}
+
+// ----------------------------------------------------------------------------//
+
+/// Blocks buffered for a single piece.
+pub struct BufferedPiece {
+    blocks: Vec<(u64, Bytes)>,
+    buffered_at: Instant,
+}
+
+impl BufferedPiece {
+    fn new(buffered_at: Instant) -> BufferedPiece {
+        BufferedPiece {
+            blocks: Vec::new(),
+            buffered_at: buffered_at,
+        }
+    }
+
+    /// Number of blocks buffered for the piece.
+    pub fn num_blocks(&self) -> usize {
+        self.blocks.len()
+    }
+
+    /// True if the blocks cover a piece of the given length without gaps.
+    pub fn covers(&self, piece_length: usize) -> bool {
+        self.run_bounds() == [(0, piece_length as u64)]
+    }
+
+    /// Merge the blocks in to contiguous runs, as the offset of each run and its bytes.
+    ///
+    /// Where blocks overlap, the block buffered last wins.
+    pub fn runs(&self) -> Vec<(u64, Vec<u8>)> {
+        self.run_bounds()
+            .into_iter()
+            .map(|(start, end)| {
+                let mut run = vec![0u8; (end - start) as usize];
+
+                for &(offset, ref bytes) in self.blocks.iter() {
+                    if offset >= start && offset < end {
+                        let run_offset = (offset - start) as usize;
+                        run[run_offset..(run_offset + bytes.len())].copy_from_slice(bytes);
+                    }
+                }
+
+                (start, run)
+            })
+            .collect()
+    }
+
+    fn size(&self) -> usize {
+        self.blocks.iter().map(|&(_, ref bytes)| bytes.len()).sum()
+    }
+
+    fn run_bounds(&self) -> Vec<(u64, u64)> {
+        let mut bounds: Vec<(u64, u64)> = self
+            .blocks
+            .iter()
+            .map(|&(offset, ref bytes)| (offset, offset + bytes.len() as u64))
+            .collect();
+        bounds.sort();
+
+        let mut runs: Vec<(u64, u64)> = Vec::new();
+        for (start, end) in bounds {
+            match runs.last_mut() {
+                Some(last) if start <= last.1 => last.1 = cmp::max(last.1, end),
+                _ => runs.push((start, end)),
+            }
+        }
+
+        runs
+    }
+}
+
+// ----------------------------------------------------------------------------//
+
+/// Buffer of processed blocks, bounded by the total size of the blocks, so contiguous
+/// blocks of a piece can be written to the `FileSystem` at once.
+pub struct WriteBuffer {
+    capacity: usize,
+    pieces: HashMap<(InfoHash, u64), BufferedPiece>,
+    stats: WriteBufferStats,
+}
+
+impl WriteBuffer {
+    /// Create a new `WriteBuffer` holding up to capacity bytes, a capacity of zero disables the buffer.
+    pub fn new(capacity: usize) -> WriteBuffer {
+        WriteBuffer {
+            capacity: capacity,
+            pieces: HashMap::new(),
+            stats: WriteBufferStats::default(),
+        }
+    }
+
+    /// True if the buffer can hold any blocks.
+    pub fn is_enabled(&self) -> bool {
+        self.capacity > 0
+    }
+
+    /// True if blocks are buffered for the piece.
+    pub fn contains_piece(&self, hash: InfoHash, piece_index: u64) -> bool {
+        self.pieces.contains_key(&(hash, piece_index))
+    }
+
+    /// Buffer the block, returning false if it does not fit.
+    pub fn insert(&mut self, metadata: BlockMetadata, block: Bytes) -> bool {
+        if self.stats.buffered_bytes + block.len() > self.capacity {
+            return false;
+        }
+
+        self.stats.buffered_bytes += block.len();
+        self.pieces
+            .entry((metadata.info_hash(), metadata.piece_index()))
+            .or_insert_with(|| BufferedPiece::new(Instant::now()))
+            .blocks
+            .push((metadata.block_offset(), block));
+
+        true
+    }
+
+    /// Put back blocks of the piece that failed to be written.
+    pub fn restore(&mut self, hash: InfoHash, piece_index: u64, piece: BufferedPiece) {
+        self.stats.buffered_bytes += piece.size();
+
+        match self.pieces.remove(&(hash, piece_index)) {
+            Some(mut buffered) => {
+                buffered.blocks.splice(0..0, piece.blocks);
+                buffered.buffered_at = piece.buffered_at;
+
+                self.pieces.insert((hash, piece_index), buffered);
+            }
+            None => {
+                self.pieces.insert((hash, piece_index), piece);
+            }
+        }
+    }
+
+    /// Remove the blocks of the piece if they cover a piece of the given length.
+    pub fn take_complete(
+        &mut self,
+        hash: InfoHash,
+        piece_index: u64,
+        piece_length: usize,
+    ) -> Option<BufferedPiece> {
+        let covered = self
+            .pieces
+            .get(&(hash, piece_index))
+            .map_or(false, |piece| piece.covers(piece_length));
+
+        if covered {
+            self.take_piece(hash, piece_index)
+        } else {
+            None
+        }
+    }
+
+    /// Remove the blocks of the piece.
+    pub fn take_piece(&mut self, hash: InfoHash, piece_index: u64) -> Option<BufferedPiece> {
+        let opt_piece = self.pieces.remove(&(hash, piece_index));
+        if let Some(ref piece) = opt_piece {
+            self.stats.buffered_bytes -= piece.size();
+        }
+
+        opt_piece
+    }
+
+    /// Remove the piece of the torrent that was buffered first.
+    pub fn take_oldest(&mut self, hash: InfoHash) -> Option<(u64, BufferedPiece)> {
+        let opt_oldest = self
+            .pieces
+            .iter()
+            .filter(|&(&(piece_hash, _), _)| piece_hash == hash)
+            .min_by_key(|&(_, piece)| piece.buffered_at)
+            .map(|(&(_, piece_index), _)| piece_index);
+
+        opt_oldest.and_then(|piece_index| {
+            self.take_piece(hash, piece_index)
+                .map(|piece| (piece_index, piece))
+        })
+    }
+
+    /// Remove the pieces of the torrent that were buffered at least max_age ago.
+    pub fn take_expired(&mut self, hash: InfoHash, max_age: Duration) -> Vec<(u64, BufferedPiece)> {
+        self.take_matching(hash, |piece| piece.buffered_at.elapsed() >= max_age)
+    }
+
+    /// Remove all pieces of the torrent.
+    pub fn take_torrent(&mut self, hash: InfoHash) -> Vec<(u64, BufferedPiece)> {
+        self.take_matching(hash, |_| true)
+    }
+
+    /// Count a piece written as the given number of contiguous runs.
+    pub fn record_coalesced(&mut self, piece: &BufferedPiece, writes: usize) {
+        self.stats.coalesced_writes += writes as u64;
+        self.stats.coalesced_blocks += piece.num_blocks() as u64;
+    }
+
+    /// Count a block written as it was processed.
+    pub fn record_direct(&mut self) {
+        self.stats.direct_writes += 1;
+    }
+
+    /// Retrieve the write counters.
+    pub fn stats(&self) -> WriteBufferStats {
+        self.stats
+    }
+
+    fn take_matching<P>(&mut self, hash: InfoHash, predicate: P) -> Vec<(u64, BufferedPiece)>
+    where
+        P: Fn(&BufferedPiece) -> bool,
+    {
+        let mut piece_indices: Vec<u64> = self
+            .pieces
+            .iter()
+            .filter(|&(&(piece_hash, _), piece)| piece_hash == hash && predicate(piece))
+            .map(|(&(_, piece_index), _)| piece_index)
+            .collect();
+        piece_indices.sort();
+
+        piece_indices
+            .into_iter()
+            .filter_map(|piece_index| {
+                self.take_piece(hash, piece_index)
+                    .map(|piece| (piece_index, piece))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use bytes::Bytes;
+
+    use super::WriteBuffer;
+    use crate::disk::BlockMetadata;
+    use crate::util::bt::{self, InfoHash};
+
+    fn hash() -> InfoHash {
+        [0u8; bt::INFO_HASH_LEN].into()
+    }
+
+    fn insert(buffer: &mut WriteBuffer, piece_index: u64, offset: u64, bytes: &[u8]) -> bool {
+        buffer.insert(
+            BlockMetadata::new(hash(), piece_index, offset, bytes.len()),
+            Bytes::from(bytes.to_vec()),
+        )
+    }
+
+    #[test]
+    fn positive_merge_out_of_order_blocks() {
+        let mut buffer = WriteBuffer::new(1024);
+        assert!(insert(&mut buffer, 0, 4, &[2u8; 4]));
+        assert!(insert(&mut buffer, 0, 12, &[4u8; 4]));
+        assert!(insert(&mut buffer, 0, 0, &[1u8; 4]));
+
+        let piece = buffer.take_piece(hash(), 0).unwrap();
+        assert!(!piece.covers(16));
+        assert_eq!(
+            vec![(0, vec![1, 1, 1, 1, 2, 2, 2, 2]), (12, vec![4, 4, 4, 4])],
+            piece.runs()
+        );
+        assert_eq!(0, buffer.stats().buffered_bytes());
+    }
+
+    #[test]
+    fn positive_take_complete_piece() {
+        let mut buffer = WriteBuffer::new(1024);
+        assert!(insert(&mut buffer, 0, 8, &[2u8; 8]));
+        assert!(buffer.take_complete(hash(), 0, 16).is_none());
+
+        // Overlapping blocks, the last one buffered wins
+        assert!(insert(&mut buffer, 0, 0, &[1u8; 10]));
+        let piece = buffer.take_complete(hash(), 0, 16).unwrap();
+        assert_eq!(
+            vec![(0, vec![1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 2, 2, 2, 2, 2, 2])],
+            piece.runs()
+        );
+    }
+
+    #[test]
+    fn positive_take_oldest_and_expired() {
+        let mut buffer = WriteBuffer::new(1024);
+        assert!(insert(&mut buffer, 3, 0, &[0u8; 4]));
+        assert!(insert(&mut buffer, 1, 0, &[0u8; 4]));
+        assert!(insert(&mut buffer, 2, 0, &[0u8; 4]));
+
+        assert_eq!(3, buffer.take_oldest(hash()).unwrap().0);
+
+        let expired: Vec<u64> = buffer
+            .take_expired(hash(), Duration::from_secs(0))
+            .into_iter()
+            .map(|(piece_index, _)| piece_index)
+            .collect();
+        assert_eq!(vec![1, 2], expired);
+        assert!(buffer.take_torrent(hash()).is_empty());
+    }
+
+    #[test]
+    fn negative_insert_over_capacity() {
+        let mut buffer = WriteBuffer::new(8);
+        assert!(insert(&mut buffer, 0, 0, &[0u8; 6]));
+        assert!(!insert(&mut buffer, 0, 6, &[0u8; 6]));
+
+        let piece = buffer.take_piece(hash(), 0).unwrap();
+        buffer.restore(hash(), 0, piece);
+        assert_eq!(6, buffer.stats().buffered_bytes());
+
+        assert!(!WriteBuffer::new(0).is_enabled());
+    }
+}
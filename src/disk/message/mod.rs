@@ -1,7 +1,10 @@
 use crate::disk::error::{BlockError, TorrentError};
-use crate::disk::{Block, BlockMut};
+use crate::disk::{
+    Block, BlockMut, DiskFault, FilePriority, PiecePriorities, ResumeData, TorrentMode,
+};
 use crate::metainfo::Metainfo;
 use crate::util::bt::InfoHash;
+use std::path::PathBuf;
 //----------------------------------------------------------------------------//
 
 /// Messages that can be sent to the `DiskManager`.
@@ -9,12 +12,37 @@ use crate::util::bt::InfoHash;
 pub enum IDiskMessage {
     /// Message to add a torrent to the disk manager.
     AddTorrent(Metainfo),
+    /// Message to add a torrent to the disk manager, using resume data
+    /// saved with `DiskManagerSink::save_resume_data`.
+    ///
+    /// Only pieces backed by files that changed since the resume data was
+    /// saved will be checked. Resume data that is corrupted or does not
+    /// match the torrent will cause every piece to be checked.
+    AddTorrentWithResume(Metainfo, ResumeData),
+    /// Message to add a torrent to the disk manager, with the given
+    /// priority for each file of the torrent.
+    ///
+    /// Skipped files are not allocated. Torrents added with any of the
+    /// other messages start with every file at `FilePriority::Normal`.
+    AddTorrentWithPriorities(Metainfo, Vec<FilePriority>),
+    /// Message to add a torrent to the disk manager in the given mode.
+    ///
+    /// A `TorrentMode::SeedOnly` torrent has every piece checked, its files
+    /// have to exist and are never created, allocated or written to.
+    /// `TorrentMode::Download` is the same as `IDiskMessage::AddTorrent`.
+    AddTorrentWithMode(Metainfo, TorrentMode),
     /// Message to remove a torrent from the disk manager.
     ///
     /// Note, this will NOT remove any data from the `FileSystem`,
     /// and as an added convenience, this message will also trigger
     /// a `IDiskMessage::SyncTorrent` message.
     RemoveTorrent(InfoHash),
+    /// Message to remove a torrent from the disk manager, along with its files.
+    ///
+    /// Files are removed from the `FileSystem` in use, files that were never
+    /// created are ignored. Directories are left in place. The torrent is removed
+    /// even if removing one of its files fails.
+    RemoveTorrentWithData(InfoHash),
     /// Message to tell the `FileSystem` to sync the torrent.
     ///
     /// This message will trigger a call to `FileSystem::sync` for every
@@ -26,9 +54,57 @@ pub enum IDiskMessage {
     /// message should be sent, otherwise, `IDiskMessage::RemoveTorrent` is
     /// sufficient.
     SyncTorrent(InfoHash),
+    /// Message to check every piece of the torrent again, regardless of
+    /// which pieces were found good before.
+    ///
+    /// Progress is sent as `ODiskMessage::TorrentCheckProgress` messages,
+    /// followed by a `ODiskMessage::TorrentChecked` message. The check can
+    /// be stopped with `DiskManagerSink::cancel_check`.
+    CheckTorrent(InfoHash),
+    /// Message to move the files of the torrent under the given root, on
+    /// the `FileSystem` in use.
+    ///
+    /// Files are renamed if the `FileSystem` supports it, otherwise they are
+    /// copied, verified and removed from the old location. Messages for the
+    /// torrent sent during the move are processed after the move finishes.
+    /// A torrent being checked, or a `TorrentMode::SeedOnly` torrent, can not
+    /// be moved.
+    MoveTorrent(InfoHash, PathBuf),
+    /// Message to change the priority of each file of the torrent.
+    ///
+    /// Files that are no longer skipped are allocated, and their pieces
+    /// are wanted again. Blocks already written for pieces shared between
+    /// skipped and wanted files are kept. A torrent being checked can not
+    /// have its priorities changed.
+    SetFilePriorities(InfoHash, Vec<FilePriority>),
+    /// Message to resume a torrent paused by a `ODiskMessage::TorrentPaused` message.
+    ///
+    /// The write that failed is retried first, followed by the messages for the
+    /// torrent sent while it was paused, so the cause of the pause, for example a
+    /// full disk, should be fixed before this message is sent.
+    ResumeTorrent(InfoHash),
+    /// Message to change whether the torrent is downloaded or only seeded.
+    ///
+    /// Files are allocated when switching to `TorrentMode::Download`. Every
+    /// piece is then checked again, with progress sent as
+    /// `ODiskMessage::TorrentCheckProgress` messages, followed by a
+    /// `ODiskMessage::TorrentModeSet` message. Cancelling the check keeps the
+    /// new mode. A torrent being checked can not have its mode changed.
+    SetTorrentMode(InfoHash, TorrentMode),
     /// Message to load the given block in to memory.
+    ///
+    /// The block is served from the block cache of the `DiskManager` if
+    /// it was recently loaded or processed.
     LoadBlock(BlockMut),
     /// Message to process the given block and persist it.
+    ///
+    /// The block is kept in the block cache of the `DiskManager`, unless
+    /// its piece fails verification. IO errors are handled according to
+    /// the `FaultPolicy` of the `DiskManager`.
+    ///
+    /// With a write buffer, blocks of incomplete pieces may be written after
+    /// the block is reported as processed. Syncing, checking or removing the
+    /// torrent writes them out first.
     ProcessBlock(Block),
 }
 
@@ -47,17 +123,83 @@ pub enum ODiskMessage {
     /// Message indicating that a good piece has been identified for
     /// the given torrent (hash), as well as the piece index.
     FoundGoodPiece(InfoHash, u64),
+    /// Message indicating that a piece the resume data marked as good
+    /// for the given torrent (hash) is no longer good, as well as the
+    /// piece index.
+    ///
+    /// Sent BEFORE the `TorrentAdded` message is sent.
+    InvalidatedPiece(InfoHash, u64),
     /// Message indicating that a bad piece has been identified for
     /// the given torrent (hash), as well as the piece index.
     FoundBadPiece(InfoHash, u64),
+    /// Message indicating progress fully allocating the files of a torrent
+    /// being added, as the bytes allocated so far out of the total bytes.
+    ///
+    /// Only sent with `AllocationMode::Full`, BEFORE `TorrentAdded` is sent.
+    AllocationProgress(InfoHash, u64, u64),
+    /// Message indicating progress checking a torrent, as the pieces checked
+    /// and the pieces found good so far, out of the total pieces.
+    TorrentCheckProgress {
+        hash: InfoHash,
+        pieces_checked: u64,
+        pieces_total: u64,
+        pieces_good: u64,
+    },
+    /// Message indicating that the torrent has been checked, as whether each
+    /// piece is good.
+    ///
+    /// Good pieces are NOT sent as `FoundGoodPiece` messages, the result
+    /// replaces any pieces identified for the torrent before the check.
+    TorrentChecked(InfoHash, Vec<bool>),
+    /// Message indicating that checking the torrent was cancelled, leaving the
+    /// pieces identified for the torrent as they were.
+    TorrentCheckCancelled(InfoHash),
+    /// Message indicating that the files of the torrent have been moved.
+    TorrentMoved(InfoHash),
+    /// Message indicating that moving the files of the torrent failed.
+    ///
+    /// If the files moved before the failure were moved back, the torrent
+    /// is left at its old location, otherwise some of its files could not
+    /// be moved back, and the torrent should be checked once they are.
+    TorrentMoveFailed {
+        hash: InfoHash,
+        error: TorrentError,
+        rolled_back: bool,
+    },
+    /// Message indicating that the file priorities of the torrent have been
+    /// changed, as the resulting priority of each piece.
+    FilePrioritiesSet(InfoHash, PiecePriorities),
+    /// Message indicating that the torrent has been paused after writing
+    /// a block failed, as the class of the error.
+    ///
+    /// The failed `ProcessBlock` message, and any other message for the
+    /// torrent, is processed once a `IDiskMessage::ResumeTorrent` message
+    /// is sent. Pieces identified as good are kept. Removing the torrent
+    /// instead fails the deferred messages.
+    TorrentPaused(InfoHash, DiskFault),
+    /// Message indicating that the torrent has been resumed.
+    TorrentResumed(InfoHash),
+    /// Message indicating that the mode of the torrent has been changed, as
+    /// whether each piece is good after checking it again.
+    ///
+    /// The result replaces any pieces identified for the torrent before,
+    /// the same as with `ODiskMessage::TorrentChecked`.
+    TorrentModeSet(InfoHash, TorrentMode, Vec<bool>),
     /// Message indicating that the given block has been loaded.
     BlockLoaded(BlockMut),
     /// Message indicating that the given block has been processed.
     BlockProcessed(Block),
-    /// Error occurring from a `AddTorrent` or `RemoveTorrent` message.
+    /// Error occurring from a `AddTorrent`, `AddTorrentWithResume`, `AddTorrentWithPriorities`,
+    /// `AddTorrentWithMode`, `RemoveTorrent`, `RemoveTorrentWithData`, `CheckTorrent`,
+    /// `SetFilePriorities`, `ResumeTorrent` or `SetTorrentMode` message.
+    ///
+    /// Changing the file priorities of a `TorrentMode::SeedOnly` torrent fails as well.
     TorrentError(InfoHash, TorrentError),
     /// Error occurring from a `LoadBlock` message.
     LoadBlockError(BlockMut, BlockError),
     /// Error occurring from a `ProcessBlock` message.
+    ///
+    /// Blocks for pieces lying wholly inside skipped files, or for a
+    /// `TorrentMode::SeedOnly` torrent, are not processed.
     ProcessBlockError(Block, BlockError),
 }
@@ -1,11 +1,27 @@
 mod memory;
 pub use self::memory::block::{Block, BlockMetadata, BlockMut};
+pub use self::memory::cache::BlockCacheStats;
+pub use self::memory::write_buffer::WriteBufferStats;
 
 pub mod fs;
 pub use self::fs::cache::file_handle::FileHandleCache;
+pub use self::fs::memory::{MemoryFile, MemoryFileSystem};
+pub use self::fs::mmap::{MmapFile, MmapFileSystem};
 pub use self::fs::native::{NativeFile, NativeFileSystem};
 pub use self::fs::FileSystem;
 
+mod fault;
+pub use self::fault::{DiskFault, FaultPolicy};
+
+mod mode;
+pub use self::mode::TorrentMode;
+
+mod priority;
+pub use self::priority::{FilePriority, PiecePriorities};
+
+mod resume;
+pub use self::resume::{ResumeData, ResumeFile, ResumeState, ResumeTotals};
+
 pub mod message;
 pub use self::message::{IDiskMessage, ODiskMessage};
 
@@ -15,7 +31,7 @@ pub mod manager;
 pub use self::manager::{DiskManager, DiskManagerSink, DiskManagerStream};
 
 pub mod builder;
-pub use self::builder::DiskManagerBuilder;
+pub use self::builder::{AllocationMode, DiskManagerBuilder};
 
 /// Both `Block` and `Torrent` error types.
 pub mod error;
@@ -0,0 +1,25 @@
+/// Whether a torrent is downloaded, or only seeded from files that already exist.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum TorrentMode {
+    /// Download the torrent, creating and allocating its files as needed.
+    Download,
+    /// Seed the torrent from its existing files, which are only ever opened for reading.
+    ///
+    /// Files are not created or allocated, so every file has to exist, and pieces are
+    /// only served once a full check found them good. Blocks are never written, which
+    /// makes it possible to seed from a read only file system.
+    SeedOnly,
+}
+
+impl TorrentMode {
+    /// True if the files of the torrent are only ever opened for reading.
+    pub fn is_seed_only(&self) -> bool {
+        *self == TorrentMode::SeedOnly
+    }
+}
+
+impl Default for TorrentMode {
+    fn default() -> TorrentMode {
+        TorrentMode::Download
+    }
+}
@@ -0,0 +1,236 @@
+use std::cmp;
+
+use crate::metainfo::Info;
+
+/// Priority of a file within a torrent.
+///
+/// Priorities are ordered from `Skip` to `High`, pieces take the highest
+/// priority of the files they overlap.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum FilePriority {
+    /// Do not download the file.
+    Skip,
+    /// Download the file after files with a higher priority.
+    Low,
+    /// Download the file.
+    Normal,
+    /// Download the file before files with a lower priority.
+    High,
+}
+
+impl Default for FilePriority {
+    fn default() -> FilePriority {
+        FilePriority::Normal
+    }
+}
+
+/// Priority of each piece of a torrent, derived from the priorities of its files.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PiecePriorities {
+    priorities: Vec<FilePriority>,
+    partial: Vec<bool>,
+}
+
+impl PiecePriorities {
+    /// Create the PiecePriorities for the given priority of each file of the torrent.
+    ///
+    /// Files of zero length do not overlap any piece, and padding files are never stored,
+    /// so their priority is ignored.
+    pub(crate) fn new(info_dict: &Info, file_priorities: &[FilePriority]) -> PiecePriorities {
+        let piece_length = info_dict.piece_length() as u64;
+        let total_pieces = info_dict.pieces().count();
+
+        let mut priorities = vec![FilePriority::Skip; total_pieces];
+        let mut opt_lowest: Vec<Option<FilePriority>> = vec![None; total_pieces];
+
+        let mut file_start = 0;
+        for (file, &priority) in info_dict.files().zip(file_priorities) {
+            let file_end = file_start + file.length() as u64;
+
+            if file_end > file_start && !file.is_padding() {
+                let (first_piece, last_piece) =
+                    (file_start / piece_length, (file_end - 1) / piece_length);
+
+                for piece_index in (first_piece..=last_piece).map(|index| index as usize) {
+                    priorities[piece_index] = cmp::max(priorities[piece_index], priority);
+                    opt_lowest[piece_index] = Some(
+                        opt_lowest[piece_index]
+                            .map_or(priority, |lowest| cmp::min(lowest, priority)),
+                    );
+                }
+            }
+
+            file_start = file_end;
+        }
+
+        let partial = priorities
+            .iter()
+            .zip(opt_lowest)
+            .map(|(&highest, opt_lowest)| {
+                highest != FilePriority::Skip && opt_lowest == Some(FilePriority::Skip)
+            })
+            .collect();
+
+        PiecePriorities {
+            priorities: priorities,
+            partial: partial,
+        }
+    }
+
+    /// Number of pieces in the torrent.
+    pub fn len(&self) -> usize {
+        self.priorities.len()
+    }
+
+    /// True if the torrent has no pieces.
+    pub fn is_empty(&self) -> bool {
+        self.priorities.is_empty()
+    }
+
+    /// Highest priority of the files overlapping the piece, `Skip` if the
+    /// piece lies wholly inside skipped files.
+    ///
+    /// Panics if the piece index is out of bounds.
+    pub fn priority(&self, piece_index: u64) -> FilePriority {
+        self.priorities[piece_index as usize]
+    }
+
+    /// True if the piece lies wholly inside skipped files, so it should not be requested.
+    ///
+    /// Panics if the piece index is out of bounds.
+    pub fn is_skipped(&self, piece_index: u64) -> bool {
+        self.priority(piece_index) == FilePriority::Skip
+    }
+
+    /// True if the piece overlaps both skipped files and files that are wanted.
+    ///
+    /// These pieces still have to be downloaded in full, the bytes belonging to the
+    /// skipped files are kept in a part file next to the files of the torrent.
+    ///
+    /// Panics if the piece index is out of bounds.
+    pub fn is_partial(&self, piece_index: u64) -> bool {
+        self.partial[piece_index as usize]
+    }
+
+    /// Iterator over the index of each piece lying wholly inside skipped files.
+    pub fn skipped_pieces<'a>(&'a self) -> impl Iterator<Item = u64> + 'a {
+        self.priorities
+            .iter()
+            .enumerate()
+            .filter(|&(_, &priority)| priority == FilePriority::Skip)
+            .map(|(index, _)| index as u64)
+    }
+
+    /// Iterator over the index of each piece overlapping both skipped and wanted files.
+    pub fn partial_pieces<'a>(&'a self) -> impl Iterator<Item = u64> + 'a {
+        self.partial
+            .iter()
+            .enumerate()
+            .filter(|&(_, &partial)| partial)
+            .map(|(index, _)| index as u64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FilePriority, PiecePriorities};
+    use crate::metainfo::Metainfo;
+
+    /// Build a multi file torrent with files of the given lengths, the piece hashes are all zeroes.
+    fn metainfo(file_lengths: &[u64], piece_length: u64) -> Metainfo {
+        let total_length: u64 = file_lengths.iter().sum();
+        let total_pieces = (total_length + piece_length - 1) / piece_length;
+
+        let mut bytes = b"d4:infod5:filesl".to_vec();
+        for (index, length) in file_lengths.iter().enumerate() {
+            let name = format!("file{}", index);
+
+            bytes.extend_from_slice(
+                format!("d6:lengthi{}e4:pathl{}:{}ee", length, name.len(), name).as_bytes(),
+            );
+        }
+        bytes.extend_from_slice(
+            format!(
+                "e4:name9:directory12:piece lengthi{}e6:pieces{}:",
+                piece_length,
+                total_pieces * 20
+            )
+            .as_bytes(),
+        );
+        bytes.extend(vec![0u8; total_pieces as usize * 20]);
+        bytes.extend_from_slice(b"ee");
+
+        Metainfo::from_bytes(bytes).unwrap()
+    }
+
+    #[test]
+    fn positive_piece_priorities_all_normal() {
+        let metainfo = metainfo(&[1000, 1048], 1024);
+        let priorities = PiecePriorities::new(metainfo.info(), &[FilePriority::Normal; 2]);
+
+        assert_eq!(2, priorities.len());
+        assert_eq!(0, priorities.skipped_pieces().count());
+        assert_eq!(0, priorities.partial_pieces().count());
+    }
+
+    #[test]
+    fn positive_piece_priorities_boundary_piece() {
+        // Piece 0 holds all of file0 and the start of file1, piece 1 the rest of file1
+        let metainfo = metainfo(&[1000, 1048], 1024);
+
+        let skip_first =
+            PiecePriorities::new(metainfo.info(), &[FilePriority::Skip, FilePriority::Normal]);
+        assert_eq!(
+            Vec::<u64>::new(),
+            skip_first.skipped_pieces().collect::<Vec<u64>>()
+        );
+        assert_eq!(vec![0], skip_first.partial_pieces().collect::<Vec<u64>>());
+
+        let skip_second =
+            PiecePriorities::new(metainfo.info(), &[FilePriority::Normal, FilePriority::Skip]);
+        assert_eq!(vec![1], skip_second.skipped_pieces().collect::<Vec<u64>>());
+        assert_eq!(vec![0], skip_second.partial_pieces().collect::<Vec<u64>>());
+    }
+
+    #[test]
+    fn positive_piece_priorities_file_ending_on_boundary() {
+        // File0 ends exactly where piece 1 starts, so no piece is shared
+        let metainfo = metainfo(&[1024, 1024], 1024);
+        let priorities =
+            PiecePriorities::new(metainfo.info(), &[FilePriority::Skip, FilePriority::Normal]);
+
+        assert_eq!(vec![0], priorities.skipped_pieces().collect::<Vec<u64>>());
+        assert_eq!(0, priorities.partial_pieces().count());
+    }
+
+    #[test]
+    fn positive_piece_priorities_small_files_in_one_piece() {
+        // File1 is empty, file0 and file2 share piece 1 with file3
+        let metainfo = metainfo(&[1500, 0, 100, 500], 1024);
+        let priorities = PiecePriorities::new(
+            metainfo.info(),
+            &[
+                FilePriority::Skip,
+                FilePriority::High,
+                FilePriority::Skip,
+                FilePriority::Low,
+            ],
+        );
+
+        assert_eq!(FilePriority::Skip, priorities.priority(0));
+        assert_eq!(FilePriority::Low, priorities.priority(1));
+        assert!(priorities.is_skipped(0));
+        assert!(priorities.is_partial(1));
+    }
+
+    #[test]
+    fn positive_piece_priorities_highest_file_wins() {
+        let metainfo = metainfo(&[1000, 1048], 1024);
+        let priorities =
+            PiecePriorities::new(metainfo.info(), &[FilePriority::Low, FilePriority::High]);
+
+        assert_eq!(FilePriority::High, priorities.priority(0));
+        assert_eq!(FilePriority::High, priorities.priority(1));
+        assert_eq!(0, priorities.partial_pieces().count());
+    }
+}
@@ -0,0 +1,366 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::bencode::{BDecodeOpt, BMutAccess, BRefAccess, BencodeMut, BencodeRef};
+use crate::metainfo::Info;
+use crate::util::bt::InfoHash;
+
+const INFO_HASH_KEY: &'static [u8] = b"info_hash";
+const PIECES_KEY: &'static [u8] = b"pieces";
+const FILES_KEY: &'static [u8] = b"files";
+const SIZE_KEY: &'static [u8] = b"size";
+const MODIFIED_KEY: &'static [u8] = b"mtime";
+const BLOCKS_KEY: &'static [u8] = b"blocks";
+const UPLOADED_KEY: &'static [u8] = b"uploaded";
+const DOWNLOADED_KEY: &'static [u8] = b"downloaded";
+const SEED_TIME_KEY: &'static [u8] = b"seed_time";
+
+/// Fast resume data for a torrent, serialized as bencode.
+///
+/// Holds the pieces that were verified, the size and modification time of every file,
+/// the blocks written for pieces that are not complete yet, and the amounts transferred.
+/// Data that is corrupted, or no longer matches the torrent, is not an error; the torrent
+/// is fully checked instead.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ResumeData {
+    bytes: Vec<u8>,
+}
+
+impl ResumeData {
+    /// Create a new `ResumeData` from previously saved bytes.
+    pub fn from_bytes<B>(bytes: B) -> ResumeData
+    where
+        B: Into<Vec<u8>>,
+    {
+        ResumeData {
+            bytes: bytes.into(),
+        }
+    }
+
+    /// Bencoded bytes, to be stored alongside the torrent.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// Consume the `ResumeData`, returning the bencoded bytes.
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.bytes
+    }
+}
+
+//----------------------------------------------------------------------------//
+
+/// Size and modification time of a file when resume data was saved.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct ResumeFile {
+    pub size: u64,
+    pub opt_modified: Option<SystemTime>,
+}
+
+/// Amounts transferred for a torrent, across every time it was run.
+///
+/// Kept up by whoever runs the torrent, the disk manager saves them as zero.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct ResumeTotals {
+    pub uploaded: u64,
+    pub downloaded: u64,
+    /// Time spent seeding, counted in whole seconds.
+    pub seed_time: Duration,
+}
+
+/// Decoded `ResumeData`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ResumeState {
+    pub info_hash: InfoHash,
+    pub good_pieces: Vec<bool>,
+    pub files: Vec<ResumeFile>,
+    /// Piece index, block offset and block length of blocks written for incomplete pieces.
+    pub blocks: Vec<(u64, u64, u64)>,
+    /// Zero for data saved without any totals.
+    pub totals: ResumeTotals,
+}
+
+impl ResumeState {
+    /// Decode resume data for the given torrent.
+    ///
+    /// Returns None if the data is corrupted or does not belong to the torrent.
+    pub fn decode(data: &ResumeData, info_dict: &Info) -> Option<ResumeState> {
+        let bencode = BencodeRef::decode(data.as_bytes(), BDecodeOpt::default()).ok()?;
+        let dict = bencode.dict()?;
+
+        let info_hash = InfoHash::from_hash(dict.lookup(INFO_HASH_KEY)?.bytes()?).ok()?;
+        if info_hash != info_dict.info_hash() {
+            return None;
+        }
+
+        let num_pieces = info_dict.pieces().count();
+        let bitfield = dict.lookup(PIECES_KEY)?.bytes()?;
+        if bitfield.len() != (num_pieces + 7) / 8 {
+            return None;
+        }
+        let good_pieces = (0..num_pieces)
+            .map(|index| bitfield[index / 8] & (0x80 >> (index % 8)) != 0)
+            .collect();
+
+        let mut files = Vec::new();
+        for file in dict.lookup(FILES_KEY)?.list()? {
+            let file_dict = file.dict()?;
+            let opt_modified = match file_dict.lookup(MODIFIED_KEY) {
+                Some(modified) => Some(UNIX_EPOCH + Duration::from_nanos(to_u64(modified)?)),
+                None => None,
+            };
+
+            files.push(ResumeFile {
+                size: to_u64(file_dict.lookup(SIZE_KEY)?)?,
+                opt_modified: opt_modified,
+            });
+        }
+        if files.len() != info_dict.files().count() {
+            return None;
+        }
+
+        let mut blocks = Vec::new();
+        for block in dict.lookup(BLOCKS_KEY)?.list()? {
+            let block = block.list()?;
+            if block.len() != 3 {
+                return None;
+            }
+            let (piece_index, block_offset, block_length) =
+                (to_u64(&block[0])?, to_u64(&block[1])?, to_u64(&block[2])?);
+
+            let piece_length = piece_length(info_dict, piece_index)?;
+            if block_offset.checked_add(block_length)? > piece_length {
+                return None;
+            }
+            blocks.push((piece_index, block_offset, block_length));
+        }
+
+        let totals = ResumeTotals {
+            uploaded: lookup_total(dict.lookup(UPLOADED_KEY))?,
+            downloaded: lookup_total(dict.lookup(DOWNLOADED_KEY))?,
+            seed_time: Duration::from_secs(lookup_total(dict.lookup(SEED_TIME_KEY))?),
+        };
+
+        Some(ResumeState {
+            info_hash: info_hash,
+            good_pieces: good_pieces,
+            files: files,
+            blocks: blocks,
+            totals: totals,
+        })
+    }
+
+    /// Encode the state as `ResumeData`.
+    pub fn encode(&self) -> ResumeData {
+        let mut bitfield = vec![0u8; (self.good_pieces.len() + 7) / 8];
+        for (index, _) in self
+            .good_pieces
+            .iter()
+            .enumerate()
+            .filter(|&(_, &good)| good)
+        {
+            bitfield[index / 8] |= 0x80 >> (index % 8);
+        }
+
+        let mut files = BencodeMut::new_list();
+        {
+            let files_access = files.list_mut().unwrap();
+
+            for file in self.files.iter() {
+                let mut file_dict = BencodeMut::new_dict();
+                {
+                    let file_dict_access = file_dict.dict_mut().unwrap();
+
+                    file_dict_access.insert(SIZE_KEY.into(), bt_ben_int!(file.size as i64));
+                    let opt_nanos = file
+                        .opt_modified
+                        .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+                        .map(|since_epoch| since_epoch.as_nanos() as i64);
+                    if let Some(nanos) = opt_nanos {
+                        file_dict_access.insert(MODIFIED_KEY.into(), bt_ben_int!(nanos));
+                    }
+                }
+
+                files_access.push(file_dict);
+            }
+        }
+
+        let mut blocks = BencodeMut::new_list();
+        {
+            let blocks_access = blocks.list_mut().unwrap();
+
+            for &(piece_index, block_offset, block_length) in self.blocks.iter() {
+                blocks_access.push(bt_ben_list!(
+                    bt_ben_int!(piece_index as i64),
+                    bt_ben_int!(block_offset as i64),
+                    bt_ben_int!(block_length as i64)
+                ));
+            }
+        }
+
+        let bytes = (bt_ben_map! {
+            INFO_HASH_KEY => bt_ben_bytes!(self.info_hash.as_ref()),
+            PIECES_KEY    => bt_ben_bytes!(bitfield),
+            FILES_KEY     => files,
+            BLOCKS_KEY    => blocks,
+            UPLOADED_KEY  => bt_ben_int!(self.totals.uploaded as i64),
+            DOWNLOADED_KEY => bt_ben_int!(self.totals.downloaded as i64),
+            SEED_TIME_KEY => bt_ben_int!(self.totals.seed_time.as_secs() as i64)
+        })
+        .encode();
+
+        ResumeData::from_bytes(bytes)
+    }
+}
+
+fn to_u64<B>(bencode: B) -> Option<u64>
+where
+    B: BRefAccess,
+{
+    bencode
+        .int()
+        .filter(|&value| value >= 0)
+        .map(|value| value as u64)
+}
+
+/// Total saved under an optional key, zero if it is missing and None if it is corrupted.
+fn lookup_total<B>(opt_bencode: Option<B>) -> Option<u64>
+where
+    B: BRefAccess,
+{
+    match opt_bencode {
+        Some(bencode) => to_u64(bencode),
+        None => Some(0),
+    }
+}
+
+/// Length of the given piece, None if the piece does not exist.
+fn piece_length(info_dict: &Info, piece_index: u64) -> Option<u64> {
+    let piece_length = info_dict.piece_length() as u64;
+    let total_bytes: u64 = info_dict.files().map(|file| file.length() as u64).sum();
+    let piece_start = piece_index.checked_mul(piece_length)?;
+
+    if piece_start < total_bytes {
+        Some(piece_length.min(total_bytes - piece_start))
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use super::{
+        ResumeData, ResumeFile, ResumeState, ResumeTotals, BLOCKS_KEY, FILES_KEY, INFO_HASH_KEY,
+        MODIFIED_KEY, PIECES_KEY, SIZE_KEY,
+    };
+    use crate::metainfo::{DirectAccessor, Metainfo, MetainfoBuilder, PieceLength};
+
+    fn metainfo() -> Metainfo {
+        let data = vec![7u8; 3000];
+        let accessor = DirectAccessor::new("file", &data[..]);
+        let bytes = MetainfoBuilder::new()
+            .set_piece_length(PieceLength::Custom(1024))
+            .build(1, accessor, |_| ())
+            .unwrap();
+
+        Metainfo::from_bytes(bytes).unwrap()
+    }
+
+    fn state(metainfo: &Metainfo) -> ResumeState {
+        ResumeState {
+            info_hash: metainfo.info().info_hash(),
+            good_pieces: vec![true, false, true],
+            files: vec![ResumeFile {
+                size: 3000,
+                opt_modified: Some(UNIX_EPOCH + Duration::new(1600000000, 123)),
+            }],
+            blocks: vec![(1, 0, 512)],
+            totals: ResumeTotals {
+                uploaded: 4096,
+                downloaded: 3000,
+                seed_time: Duration::from_secs(3600),
+            },
+        }
+    }
+
+    #[test]
+    fn positive_round_trip() {
+        let metainfo = metainfo();
+        let state = state(&metainfo);
+
+        let decoded = ResumeState::decode(&state.encode(), metainfo.info());
+
+        assert_eq!(Some(state), decoded);
+    }
+
+    #[test]
+    fn positive_round_trip_unknown_modified() {
+        let metainfo = metainfo();
+        let mut state = state(&metainfo);
+        state.files[0].opt_modified = None;
+
+        let decoded = ResumeState::decode(&state.encode(), metainfo.info());
+
+        assert_eq!(Some(state), decoded);
+    }
+
+    #[test]
+    fn positive_missing_totals_are_zero() {
+        let metainfo = metainfo();
+        let mut state = state(&metainfo);
+        state.totals = ResumeTotals::default();
+
+        // Saved before totals were added
+        let bytes = (bt_ben_map! {
+            INFO_HASH_KEY => bt_ben_bytes!(state.info_hash.as_ref()),
+            PIECES_KEY    => bt_ben_bytes!(vec![0xA0u8]),
+            FILES_KEY     => bt_ben_list!(bt_ben_map! {
+                SIZE_KEY     => bt_ben_int!(3000),
+                MODIFIED_KEY => bt_ben_int!(1600000000000000123)
+            }),
+            BLOCKS_KEY    => bt_ben_list!(bt_ben_list!(
+                bt_ben_int!(1),
+                bt_ben_int!(0),
+                bt_ben_int!(512)
+            ))
+        })
+        .encode();
+
+        assert_eq!(
+            Some(state),
+            ResumeState::decode(&ResumeData::from_bytes(bytes), metainfo.info())
+        );
+    }
+
+    #[test]
+    fn negative_corrupted_data() {
+        let metainfo = metainfo();
+        let mut bytes = state(&metainfo).encode().into_bytes();
+        bytes.truncate(bytes.len() / 2);
+
+        assert_eq!(
+            None,
+            ResumeState::decode(&ResumeData::from_bytes(bytes), metainfo.info())
+        );
+    }
+
+    #[test]
+    fn negative_other_torrent() {
+        let metainfo = metainfo();
+        let mut state = state(&metainfo);
+        state.info_hash = [0u8; 20].into();
+
+        assert_eq!(None, ResumeState::decode(&state.encode(), metainfo.info()));
+    }
+
+    #[test]
+    fn negative_block_past_last_piece() {
+        let metainfo = metainfo();
+        let mut state = state(&metainfo);
+        // Last piece is only 952 bytes long
+        state.blocks.push((2, 512, 512));
+
+        assert_eq!(None, ResumeState::decode(&state.encode(), metainfo.info()));
+    }
+}
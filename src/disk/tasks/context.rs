@@ -1,10 +1,19 @@
 use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::mpsc::Sender;
 use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+
+use tokio::sync::Notify;
 
 use futures::sink::Sink;
+use crate::disk::memory::cache::BlockCache;
+use crate::disk::memory::write_buffer::WriteBuffer;
 use crate::disk::tasks::helpers::piece_checker::PieceCheckerState;
-use crate::disk::ODiskMessage;
+use crate::disk::{
+    AllocationMode, BlockCacheStats, FaultPolicy, IDiskMessage, ODiskMessage, WriteBufferStats,
+};
 use crate::metainfo::Metainfo;
 use crate::util::bt::InfoHash;
 
@@ -12,11 +21,38 @@ pub struct DiskManagerContext<F> {
     torrents: Arc<RwLock<HashMap<InfoHash, Mutex<MetainfoState>>>>,
     out: Sender<ODiskMessage>,
     fs: Arc<F>,
+    allocation_mode: AllocationMode,
+    check_workers: usize,
+    checks: Arc<Mutex<HashMap<InfoHash, Arc<AtomicBool>>>>,
+    block_cache: Arc<Mutex<BlockCache>>,
+    moves: Arc<Mutex<HashMap<InfoHash, Vec<IDiskMessage>>>>,
+    fault_policy: FaultPolicy,
+    paused: Arc<Mutex<HashMap<InfoHash, Vec<IDiskMessage>>>>,
+    write_buffer: Arc<Mutex<WriteBuffer>>,
+    write_flush_interval: Duration,
+    in_flight: Arc<AtomicUsize>,
+    idle: Arc<Notify>,
+    shut_down: Arc<AtomicBool>,
+}
+
+/// Counts a message as being executed until dropped.
+pub struct WorkGuard {
+    in_flight: Arc<AtomicUsize>,
+    idle: Arc<Notify>,
+}
+
+impl Drop for WorkGuard {
+    fn drop(&mut self) {
+        if self.in_flight.fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.idle.notify_waiters();
+        }
+    }
 }
 
 pub struct MetainfoState {
     file: Metainfo,
     state: PieceCheckerState,
+    opt_root: Option<PathBuf>,
 }
 
 impl MetainfoState {
@@ -24,16 +60,38 @@ impl MetainfoState {
         MetainfoState {
             file: file,
             state: state,
+            opt_root: None,
         }
     }
 }
 
 impl<F> DiskManagerContext<F> {
-    pub fn new(out: Sender<ODiskMessage>, fs: F) -> DiskManagerContext<F> {
+    pub fn new(
+        out: Sender<ODiskMessage>,
+        fs: F,
+        allocation_mode: AllocationMode,
+        check_workers: usize,
+        block_cache_size: usize,
+        fault_policy: FaultPolicy,
+        write_buffer_size: usize,
+        write_flush_interval: Duration,
+    ) -> DiskManagerContext<F> {
         DiskManagerContext {
             torrents: Arc::new(RwLock::new(HashMap::new())),
             out: out,
             fs: Arc::new(fs),
+            allocation_mode: allocation_mode,
+            check_workers: check_workers,
+            checks: Arc::new(Mutex::new(HashMap::new())),
+            block_cache: Arc::new(Mutex::new(BlockCache::new(block_cache_size))),
+            moves: Arc::new(Mutex::new(HashMap::new())),
+            fault_policy: fault_policy,
+            paused: Arc::new(Mutex::new(HashMap::new())),
+            write_buffer: Arc::new(Mutex::new(WriteBuffer::new(write_buffer_size))),
+            write_flush_interval: write_flush_interval,
+            in_flight: Arc::new(AtomicUsize::new(0)),
+            idle: Arc::new(Notify::new()),
+            shut_down: Arc::new(AtomicBool::new(false)),
         }
     }
 
@@ -45,6 +103,229 @@ impl<F> DiskManagerContext<F> {
         &self.fs
     }
 
+    pub fn allocation_mode(&self) -> AllocationMode {
+        self.allocation_mode
+    }
+
+    pub fn check_workers(&self) -> usize {
+        self.check_workers
+    }
+
+    pub fn fault_policy(&self) -> FaultPolicy {
+        self.fault_policy
+    }
+
+    pub fn run_with_block_cache<C, R>(&self, call: C) -> R
+    where
+        C: FnOnce(&mut BlockCache) -> R,
+    {
+        let mut lock_cache = self.block_cache.lock().expect(
+            "bittorrent-protocol_disk: DiskManagerContext::run_with_block_cache Failed To Lock Cache",
+        );
+
+        call(&mut *lock_cache)
+    }
+
+    pub fn block_cache_stats(&self) -> BlockCacheStats {
+        self.run_with_block_cache(|cache| cache.stats())
+    }
+
+    pub fn write_flush_interval(&self) -> Duration {
+        self.write_flush_interval
+    }
+
+    pub fn run_with_write_buffer<C, R>(&self, call: C) -> R
+    where
+        C: FnOnce(&mut WriteBuffer) -> R,
+    {
+        let mut lock_buffer = self.write_buffer.lock().expect(
+            "bittorrent-protocol_disk: DiskManagerContext::run_with_write_buffer Failed To Lock Buffer",
+        );
+
+        call(&mut *lock_buffer)
+    }
+
+    pub fn write_buffer_stats(&self) -> WriteBufferStats {
+        self.run_with_write_buffer(|buffer| buffer.stats())
+    }
+
+    /// Count a message as being executed, until the returned guard is dropped.
+    pub fn start_work(&self) -> WorkGuard {
+        self.in_flight.fetch_add(1, Ordering::SeqCst);
+
+        WorkGuard {
+            in_flight: self.in_flight.clone(),
+            idle: self.idle.clone(),
+        }
+    }
+
+    /// Wait until no message is being executed.
+    pub async fn wait_idle(&self) {
+        loop {
+            // Registered before checking, so a notification in between is not missed
+            let notified = self.idle.notified();
+            if self.in_flight.load(Ordering::SeqCst) == 0 {
+                return;
+            }
+
+            notified.await;
+        }
+    }
+
+    /// Mark the disk manager as shut down, returning false if it already was.
+    pub fn shut_down(&self) -> bool {
+        !self.shut_down.swap(true, Ordering::SeqCst)
+    }
+
+    pub fn is_shut_down(&self) -> bool {
+        self.shut_down.load(Ordering::SeqCst)
+    }
+
+    pub fn torrent_hashes(&self) -> Vec<InfoHash> {
+        self.torrents
+            .read()
+            .expect(
+                "bittorrent-protocol_disk: DiskManagerContext::torrent_hashes Failed To Read Torrent",
+            )
+            .keys()
+            .cloned()
+            .collect()
+    }
+
+    /// Register a check for the torrent, returning the flag used to cancel it, or None if
+    /// the torrent is already being checked.
+    pub fn start_check(&self, hash: InfoHash) -> Option<Arc<AtomicBool>> {
+        let mut lock_checks = self.checks.lock().expect(
+            "bittorrent-protocol_disk: DiskManagerContext::start_check Failed To Lock Checks",
+        );
+
+        if lock_checks.contains_key(&hash) {
+            None
+        } else {
+            let cancel = Arc::new(AtomicBool::new(false));
+            lock_checks.insert(hash, cancel.clone());
+
+            Some(cancel)
+        }
+    }
+
+    pub fn finish_check(&self, hash: InfoHash) {
+        self.checks
+            .lock()
+            .expect(
+                "bittorrent-protocol_disk: DiskManagerContext::finish_check Failed To Lock Checks",
+            )
+            .remove(&hash);
+    }
+
+    pub fn cancel_check(&self, hash: InfoHash) -> bool {
+        let lock_checks = self.checks.lock().expect(
+            "bittorrent-protocol_disk: DiskManagerContext::cancel_check Failed To Lock Checks",
+        );
+
+        match lock_checks.get(&hash) {
+            Some(cancel) => {
+                cancel.store(true, Ordering::SeqCst);
+
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Defer the message if its torrent is being moved, otherwise hand it back.
+    ///
+    /// A move handed back is registered, deferring the messages for its torrent until
+    /// `finish_move` is called.
+    pub fn defer_if_moving(&self, msg: IDiskMessage) -> Option<IDiskMessage> {
+        let hash = match message_hash(&msg) {
+            Some(hash) => hash,
+            None => return Some(msg),
+        };
+
+        let mut lock_moves = self.moves.lock().expect(
+            "bittorrent-protocol_disk: DiskManagerContext::defer_if_moving Failed To Lock Moves",
+        );
+
+        if let Some(deferred) = lock_moves.get_mut(&hash) {
+            deferred.push(msg);
+
+            None
+        } else {
+            if let IDiskMessage::MoveTorrent(_, _) = msg {
+                lock_moves.insert(hash, Vec::new());
+            }
+
+            Some(msg)
+        }
+    }
+
+    /// Unregister the move for the torrent, returning the messages deferred during the move.
+    pub fn finish_move(&self, hash: InfoHash) -> Vec<IDiskMessage> {
+        self.moves
+            .lock()
+            .expect(
+                "bittorrent-protocol_disk: DiskManagerContext::finish_move Failed To Lock Moves",
+            )
+            .remove(&hash)
+            .unwrap_or_default()
+    }
+
+    /// Defer the message if its torrent is paused, otherwise hand it back.
+    ///
+    /// Messages resuming or removing the torrent are always handed back.
+    pub fn defer_if_paused(&self, msg: IDiskMessage) -> Option<IDiskMessage> {
+        if let IDiskMessage::ResumeTorrent(_)
+        | IDiskMessage::RemoveTorrent(_)
+        | IDiskMessage::RemoveTorrentWithData(_) = msg
+        {
+            return Some(msg);
+        }
+        let hash = match message_hash(&msg) {
+            Some(hash) => hash,
+            None => return Some(msg),
+        };
+
+        let mut lock_paused = self.paused.lock().expect(
+            "bittorrent-protocol_disk: DiskManagerContext::defer_if_paused Failed To Lock Paused",
+        );
+
+        match lock_paused.get_mut(&hash) {
+            Some(deferred) => {
+                deferred.push(msg);
+
+                None
+            }
+            None => Some(msg),
+        }
+    }
+
+    /// Pause the torrent, deferring the failed message along with the messages for the
+    /// torrent until `resume_torrent` is called.
+    ///
+    /// Returns false if the torrent was already paused.
+    pub fn pause_torrent(&self, hash: InfoHash, failed_msg: IDiskMessage) -> bool {
+        let mut lock_paused = self.paused.lock().expect(
+            "bittorrent-protocol_disk: DiskManagerContext::pause_torrent Failed To Lock Paused",
+        );
+
+        let deferred = lock_paused.entry(hash).or_insert_with(Vec::new);
+        deferred.push(failed_msg);
+
+        deferred.len() == 1
+    }
+
+    /// Unpause the torrent, returning the messages deferred while it was paused, or None
+    /// if the torrent is not paused.
+    pub fn resume_torrent(&self, hash: InfoHash) -> Option<Vec<IDiskMessage>> {
+        self.paused
+            .lock()
+            .expect(
+                "bittorrent-protocol_disk: DiskManagerContext::resume_torrent Failed To Lock Paused",
+            )
+            .remove(&hash)
+    }
+
     pub fn insert_torrent(&self, file: Metainfo, state: PieceCheckerState) -> bool {
         let mut write_torrents = self.torrents.write().expect(
             "bittorrent-protocol_disk: DiskManagerContext::insert_torrents Failed To Write Torrent",
@@ -62,7 +343,7 @@ impl<F> DiskManagerContext<F> {
 
     pub fn update_torrent<C>(&self, hash: InfoHash, call: C) -> bool
     where
-        C: FnOnce(&Metainfo, &mut PieceCheckerState),
+        C: FnOnce(&Metainfo, &mut PieceCheckerState, &mut Option<PathBuf>),
     {
         let read_torrents = self.torrents.read().expect(
             "bittorrent-protocol_disk: DiskManagerContext::update_torrent Failed To Read Torrent",
@@ -75,7 +356,11 @@ impl<F> DiskManagerContext<F> {
                     .expect("bittorrent-protocol_disk: DiskManagerContext::update_torrent Failed To Lock State");
                 let deref_state = &mut *lock_state;
 
-                call(&deref_state.file, &mut deref_state.state);
+                call(
+                    &deref_state.file,
+                    &mut deref_state.state,
+                    &mut deref_state.opt_root,
+                );
 
                 true
             }
@@ -84,20 +369,58 @@ impl<F> DiskManagerContext<F> {
     }
 
     pub fn remove_torrent(&self, hash: InfoHash) -> bool {
+        self.cancel_check(hash);
+
         let mut write_torrents = self.torrents.write().expect(
             "bittorrent-protocol_disk: DiskManagerContext::remove_torrent Failed To Write Torrent",
         );
 
+        // Blocks are cached and buffered while holding the torrent lock, so none are after this
+        self.run_with_block_cache(|cache| cache.invalidate_torrent(hash));
+        self.run_with_write_buffer(|buffer| buffer.take_torrent(hash));
+
         write_torrents.remove(&hash).map(|_| true).unwrap_or(false)
     }
 }
 
+/// Torrent the message is for, if the torrent was already added.
+fn message_hash(msg: &IDiskMessage) -> Option<InfoHash> {
+    match *msg {
+        IDiskMessage::AddTorrent(_)
+        | IDiskMessage::AddTorrentWithResume(_, _)
+        | IDiskMessage::AddTorrentWithPriorities(_, _)
+        | IDiskMessage::AddTorrentWithMode(_, _) => None,
+        IDiskMessage::RemoveTorrent(hash)
+        | IDiskMessage::RemoveTorrentWithData(hash)
+        | IDiskMessage::SyncTorrent(hash)
+        | IDiskMessage::CheckTorrent(hash)
+        | IDiskMessage::MoveTorrent(hash, _)
+        | IDiskMessage::SetFilePriorities(hash, _)
+        | IDiskMessage::ResumeTorrent(hash)
+        | IDiskMessage::SetTorrentMode(hash, _) => Some(hash),
+        IDiskMessage::LoadBlock(ref block) => Some(block.metadata().info_hash()),
+        IDiskMessage::ProcessBlock(ref block) => Some(block.metadata().info_hash()),
+    }
+}
+
 impl<F> Clone for DiskManagerContext<F> {
     fn clone(&self) -> DiskManagerContext<F> {
         DiskManagerContext {
             torrents: self.torrents.clone(),
             out: self.out.clone(),
             fs: self.fs.clone(),
+            allocation_mode: self.allocation_mode,
+            check_workers: self.check_workers,
+            checks: self.checks.clone(),
+            block_cache: self.block_cache.clone(),
+            moves: self.moves.clone(),
+            fault_policy: self.fault_policy,
+            paused: self.paused.clone(),
+            write_buffer: self.write_buffer.clone(),
+            write_flush_interval: self.write_flush_interval,
+            in_flight: self.in_flight.clone(),
+            idle: self.idle.clone(),
+            shut_down: self.shut_down.clone(),
         }
     }
 }
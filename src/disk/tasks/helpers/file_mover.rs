@@ -0,0 +1,286 @@
+use std::cmp;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::disk::error::{TorrentError, TorrentErrorKind};
+use crate::disk::tasks::helpers::rooted_fs;
+use crate::disk::FileSystem;
+
+const COPY_CHUNK_SIZE: usize = 1024 * 1024;
+
+/// How a file was moved, along with the old and new path.
+enum MovedFile {
+    Renamed(PathBuf, PathBuf),
+    Copied(PathBuf, PathBuf),
+}
+
+/// Move the files at the given paths from under the old root to under the new root.
+///
+/// Files are renamed where possible, otherwise they are copied, verified, and only removed
+/// from the old location once all files were moved. On failure, returns the error and whether
+/// the files moved so far were moved back, leaving all files at the old location.
+pub fn move_files<F>(
+    fs: F,
+    file_paths: &[PathBuf],
+    opt_old_root: Option<&Path>,
+    new_root: &Path,
+) -> Result<(), (TorrentError, bool)>
+where
+    F: FileSystem,
+{
+    let mut moved_files = Vec::new();
+
+    for file_path in file_paths {
+        let (old_path, new_path) = (
+            rooted_fs::rooted_path(opt_old_root, file_path),
+            rooted_fs::rooted_path(Some(new_root), file_path),
+        );
+        if old_path == new_path {
+            continue;
+        }
+
+        match move_file(&fs, &old_path, &new_path) {
+            Ok(moved_file) => moved_files.push(moved_file),
+            Err(err) => {
+                let rolled_back = roll_back(&fs, moved_files);
+
+                return Err((
+                    TorrentError::from(err).chain_err(|| TorrentErrorKind::MoveFailed {
+                        file_path: old_path,
+                        new_path: new_path,
+                    }),
+                    rolled_back,
+                ));
+            }
+        }
+    }
+
+    for moved_file in moved_files {
+        if let MovedFile::Copied(old_path, _) = moved_file {
+            remove_file(&fs, &old_path);
+        }
+    }
+
+    Ok(())
+}
+
+/// Move the file, without replacing a file already existing at the new path.
+fn move_file<F>(fs: &F, old_path: &Path, new_path: &Path) -> io::Result<MovedFile>
+where
+    F: FileSystem,
+{
+    let new_file = fs.open_file(new_path.to_path_buf())?;
+    if fs.file_size(&new_file)? != 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            "File Already Exists At The New Path",
+        ));
+    }
+    drop(new_file);
+
+    if fs
+        .rename_file(old_path.to_path_buf(), new_path.to_path_buf())
+        .is_ok()
+    {
+        return Ok(MovedFile::Renamed(
+            old_path.to_path_buf(),
+            new_path.to_path_buf(),
+        ));
+    }
+
+    if let Err(err) = copy_file(fs, old_path, new_path) {
+        // Whatever we copied so far is not part of the torrent
+        remove_file(fs, new_path);
+
+        return Err(err);
+    }
+
+    Ok(MovedFile::Copied(
+        old_path.to_path_buf(),
+        new_path.to_path_buf(),
+    ))
+}
+
+/// Copy the file, then read both files back to verify the copy.
+fn copy_file<F>(fs: &F, old_path: &Path, new_path: &Path) -> io::Result<()>
+where
+    F: FileSystem,
+{
+    let mut old_file = fs.open_file(old_path.to_path_buf())?;
+    let mut new_file = fs.open_file(new_path.to_path_buf())?;
+    let file_size = fs.file_size(&old_file)?;
+
+    let mut buffer = vec![0u8; cmp::min(file_size, COPY_CHUNK_SIZE as u64) as usize];
+    let mut verify_buffer = buffer.clone();
+
+    let mut offset = 0;
+    while offset < file_size {
+        let length = cmp::min(file_size - offset, buffer.len() as u64) as usize;
+
+        read_exact(fs, &mut old_file, offset, &mut buffer[..length])?;
+        let mut written = 0;
+        while written < length {
+            let bytes_written = fs.write_file(
+                &mut new_file,
+                offset + written as u64,
+                &buffer[written..length],
+            )?;
+            if bytes_written == 0 {
+                return Err(io::Error::new(
+                    io::ErrorKind::WriteZero,
+                    "Failed To Write Copied File",
+                ));
+            }
+
+            written += bytes_written;
+        }
+
+        offset += length as u64;
+    }
+
+    let mut offset = 0;
+    while offset < file_size {
+        let length = cmp::min(file_size - offset, buffer.len() as u64) as usize;
+
+        read_exact(fs, &mut old_file, offset, &mut buffer[..length])?;
+        read_exact(fs, &mut new_file, offset, &mut verify_buffer[..length])?;
+        if buffer[..length] != verify_buffer[..length] {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Copied File Does Not Match The Original",
+            ));
+        }
+
+        offset += length as u64;
+    }
+
+    if fs.file_size(&new_file)? == file_size {
+        Ok(())
+    } else {
+        Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "Copied File Does Not Match The Original",
+        ))
+    }
+}
+
+fn read_exact<F>(fs: &F, file: &mut F::File, offset: u64, buffer: &mut [u8]) -> io::Result<()>
+where
+    F: FileSystem,
+{
+    let mut read = 0;
+    while read < buffer.len() {
+        let bytes_read = fs.read_file(file, offset + read as u64, &mut buffer[read..])?;
+        if bytes_read == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "File Ended While Copying",
+            ));
+        }
+
+        read += bytes_read;
+    }
+
+    Ok(())
+}
+
+/// Move the moved files back, returning false if a renamed file could not be renamed back.
+fn roll_back<F>(fs: &F, moved_files: Vec<MovedFile>) -> bool
+where
+    F: FileSystem,
+{
+    let mut rolled_back = true;
+
+    for moved_file in moved_files.into_iter().rev() {
+        match moved_file {
+            MovedFile::Renamed(old_path, new_path) => {
+                if let Err(err) = fs.rename_file(new_path.clone(), old_path.clone()) {
+                    error!(
+                        "Failed To Move {:?} Back To {:?}: {}",
+                        new_path, old_path, err
+                    );
+                    rolled_back = false;
+                }
+            }
+            // Copies leave the old file alone until all files were moved
+            MovedFile::Copied(_, new_path) => remove_file(fs, &new_path),
+        }
+    }
+
+    rolled_back
+}
+
+/// Remove a file that is no longer part of the torrent, files left behind are only logged.
+fn remove_file<F>(fs: &F, path: &Path)
+where
+    F: FileSystem,
+{
+    if let Err(err) = fs.remove_file(path.to_path_buf()) {
+        warn!("Failed To Remove {:?} After Moving Torrent: {}", path, err);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::PathBuf;
+
+    use crate::disk::NativeFileSystem;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "bittorrent-protocol_file_mover_{}_{}",
+            name,
+            std::process::id()
+        ));
+        fs::create_dir_all(&dir).unwrap();
+
+        dir
+    }
+
+    #[test]
+    fn positive_move_files_renames() {
+        let data = vec![7u8; 3000];
+
+        let dir = temp_dir("renames");
+        fs::write(dir.join("file"), &data).unwrap();
+
+        let result = super::move_files(
+            NativeFileSystem::with_directory(&dir),
+            &[PathBuf::from("file")],
+            None,
+            &dir.join("archive"),
+        );
+        let (old_exists, new_data) = (
+            dir.join("file").exists(),
+            fs::read(dir.join("archive").join("file")).unwrap(),
+        );
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert!(result.is_ok());
+        assert!(!old_exists);
+        assert_eq!(data, new_data);
+    }
+
+    #[test]
+    fn negative_move_files_existing_file() {
+        let data = vec![7u8; 3000];
+
+        let dir = temp_dir("existing");
+        fs::write(dir.join("file"), &data).unwrap();
+        fs::create_dir_all(dir.join("archive")).unwrap();
+        fs::write(dir.join("archive").join("file"), b"other").unwrap();
+
+        let result = super::move_files(
+            NativeFileSystem::with_directory(&dir),
+            &[PathBuf::from("file")],
+            None,
+            &dir.join("archive"),
+        );
+        let old_data = fs::read(dir.join("file")).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert!(result.unwrap_err().1);
+        assert_eq!(data, old_data);
+    }
+}
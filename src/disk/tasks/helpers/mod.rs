@@ -2,8 +2,12 @@ use std::path::{Path, PathBuf};
 
 use crate::metainfo::File;
 
+pub mod file_mover;
+pub mod part_file;
 pub mod piece_accessor;
 pub mod piece_checker;
+pub mod rooted_fs;
+pub mod torrent_checker;
 
 pub fn build_path(parent_directory: Option<&Path>, file: &File) -> PathBuf {
     match parent_directory {
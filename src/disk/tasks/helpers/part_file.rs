@@ -0,0 +1,150 @@
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+
+use crate::disk::{FilePriority, PiecePriorities};
+use crate::metainfo::Info;
+
+/// Keeps the bytes of skipped files that share a piece with other files.
+///
+/// Each piece overlapping multiple files has a slot of a piece length in the part file. Bytes
+/// of such a piece belonging to a skipped file are kept in its slot instead of in that file,
+/// so skipped files are not written to, while the piece can still be written and read as a whole.
+#[derive(Clone)]
+pub struct PartFile {
+    path: PathBuf,
+    piece_length: u64,
+    file_priorities: Vec<FilePriority>,
+    piece_priorities: PiecePriorities,
+    shared_pieces: HashMap<u64, SharedPiece>,
+}
+
+/// Slot of a piece in the part file, along with the index of each file it overlaps.
+#[derive(Clone)]
+struct SharedPiece {
+    slot: u64,
+    files: Vec<usize>,
+}
+
+impl PartFile {
+    /// Create a new PartFile for the given priority of each file of the torrent.
+    ///
+    /// The number of priorities has to match the number of files.
+    pub fn new(info_dict: &Info, file_priorities: Vec<FilePriority>) -> PartFile {
+        let piece_length = info_dict.piece_length() as u64;
+        let part_name = format!(
+            ".{}.parts",
+            info_dict
+                .info_hash()
+                .as_ref()
+                .iter()
+                .map(|byte| format!("{:02x}", byte))
+                .collect::<String>()
+        );
+        let path = match info_dict.directory() {
+            Some(dir) => dir.join(part_name),
+            None => PathBuf::from(part_name),
+        };
+
+        // Only the first and last piece of a file can overlap other files
+        let mut piece_files: BTreeMap<u64, Vec<usize>> = BTreeMap::new();
+        let mut file_start = 0;
+        for (file_index, file) in info_dict.files().enumerate() {
+            let file_end = file_start + file.length() as u64;
+
+            // Padding files are never stored, so pieces are not shared with them
+            if file_end > file_start && !file.is_padding() {
+                let (first_piece, last_piece) =
+                    (file_start / piece_length, (file_end - 1) / piece_length);
+
+                piece_files
+                    .entry(first_piece)
+                    .or_insert_with(Vec::new)
+                    .push(file_index);
+                if last_piece != first_piece {
+                    piece_files
+                        .entry(last_piece)
+                        .or_insert_with(Vec::new)
+                        .push(file_index);
+                }
+            }
+
+            file_start = file_end;
+        }
+
+        let mut shared_pieces = HashMap::new();
+        for (piece_index, files) in piece_files.into_iter().filter(|(_, files)| files.len() > 1) {
+            let slot = shared_pieces.len() as u64;
+
+            shared_pieces.insert(
+                piece_index,
+                SharedPiece {
+                    slot: slot,
+                    files: files,
+                },
+            );
+        }
+
+        PartFile {
+            path: path,
+            piece_length: piece_length,
+            piece_priorities: PiecePriorities::new(info_dict, &file_priorities),
+            file_priorities: file_priorities,
+            shared_pieces: shared_pieces,
+        }
+    }
+
+    /// Path of the part file, relative to the root of the torrent.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Priority of each file of the torrent.
+    pub fn file_priorities(&self) -> &[FilePriority] {
+        &self.file_priorities
+    }
+
+    /// Priority of each piece of the torrent.
+    pub fn piece_priorities(&self) -> &PiecePriorities {
+        &self.piece_priorities
+    }
+
+    /// True if the file is skipped.
+    pub fn is_skipped(&self, file_index: usize) -> bool {
+        self.file_priorities[file_index] == FilePriority::Skip
+    }
+
+    /// True if any file of the torrent is skipped.
+    pub fn has_skipped_files(&self) -> bool {
+        (0..self.file_priorities.len()).any(|file_index| self.is_skipped(file_index))
+    }
+
+    /// Offset in the part file where the piece starts, if the bytes of the piece
+    /// belonging to the given file are kept in the part file.
+    pub fn route(&self, piece_index: u64, file_index: usize) -> Option<u64> {
+        if self.is_skipped(file_index) {
+            self.shared_pieces
+                .get(&piece_index)
+                .map(|shared_piece| shared_piece.slot * self.piece_length)
+        } else {
+            None
+        }
+    }
+
+    /// Index of each piece keeping some of its bytes at a different place with the other
+    /// priorities, because a file it shares with other files was skipped or is no longer skipped.
+    pub fn moved_pieces(&self, other: &PartFile) -> Vec<u64> {
+        let mut moved_pieces: Vec<u64> =
+            self.shared_pieces
+                .iter()
+                .filter(|&(_, shared_piece)| {
+                    shared_piece.files.iter().any(|&file_index| {
+                        self.is_skipped(file_index) != other.is_skipped(file_index)
+                    })
+                })
+                .map(|(&piece_index, _)| piece_index)
+                .collect();
+        moved_pieces.sort();
+
+        moved_pieces
+    }
+}
@@ -2,27 +2,36 @@ use std::cmp;
 use std::io;
 
 use crate::disk::tasks::helpers;
+use crate::disk::tasks::helpers::part_file::PartFile;
 use crate::disk::{BlockMetadata, FileSystem};
 use crate::metainfo::Info;
 
 pub struct PieceAccessor<'a, F> {
     fs: F,
     info_dict: &'a Info,
+    part_file: &'a PartFile,
 }
 
 impl<'a, F> PieceAccessor<'a, F>
 where
     F: FileSystem,
 {
-    pub fn new(fs: F, info_dict: &'a Info) -> PieceAccessor<'a, F> {
+    /// Create a new PieceAccessor, keeping bytes of skipped files in the given part file.
+    pub fn new(fs: F, info_dict: &'a Info, part_file: &'a PartFile) -> PieceAccessor<'a, F> {
         PieceAccessor {
             fs: fs,
             info_dict: info_dict,
+            part_file: part_file,
         }
     }
 
     pub fn read_piece(&self, piece_buffer: &mut [u8], message: &BlockMetadata) -> io::Result<()> {
-        self.run_with_file_regions(message, |mut file, offset, begin, end| {
+        self.run_with_file_regions(message, |opt_file, offset, begin, end| {
+            let mut file = match opt_file {
+                Some(file) => file,
+                None => return Ok(zero_fill(&mut piece_buffer[begin..end])),
+            };
+
             let bytes_read = self
                 .fs
                 .read_file(&mut file, offset, &mut piece_buffer[begin..end])?;
@@ -32,8 +41,44 @@ where
         })
     }
 
+    /// Read the piece, treating bytes past the end of a file as zeroes.
+    pub fn read_piece_zero_filled(
+        &self,
+        piece_buffer: &mut [u8],
+        message: &BlockMetadata,
+    ) -> io::Result<()> {
+        self.run_with_file_regions(message, |opt_file, offset, begin, end| {
+            let mut file = match opt_file {
+                Some(file) => file,
+                None => return Ok(zero_fill(&mut piece_buffer[begin..end])),
+            };
+
+            let file_size = self.fs.file_size(&file)?;
+            let bytes_on_disk = cmp::min(file_size.saturating_sub(offset), (end - begin) as u64);
+
+            let bytes_read = if bytes_on_disk > 0 {
+                self.fs.read_file(
+                    &mut file,
+                    offset,
+                    &mut piece_buffer[begin..(begin + bytes_on_disk as usize)],
+                )?
+            } else {
+                0
+            };
+            zero_fill(&mut piece_buffer[(begin + bytes_read)..end]);
+
+            Ok(())
+        })
+    }
+
+    /// Write the piece, leaving out the bytes of padding files.
     pub fn write_piece(&self, piece_buffer: &[u8], message: &BlockMetadata) -> io::Result<()> {
-        self.run_with_file_regions(message, |mut file, offset, begin, end| {
+        self.run_with_file_regions(message, |opt_file, offset, begin, end| {
+            let mut file = match opt_file {
+                Some(file) => file,
+                None => return Ok(()),
+            };
+
             let bytes_written = self
                 .fs
                 .write_file(&mut file, offset, &piece_buffer[begin..end])?;
@@ -44,10 +89,12 @@ where
     }
 
     /// Run the given closure with the file, the file offset, and the read/write buffer stard (inclusive) and end (exclusive) indices.
+    ///
+    /// Padding files are never opened, the closure is given no file for them.
     /// TODO: We do not detect when/if the file size changes after the initial file size check, so the returned number of
     fn run_with_file_regions<C>(&self, message: &BlockMetadata, mut callback: C) -> io::Result<()>
     where
-        C: FnMut(F::File, u64, usize, usize) -> io::Result<()>,
+        C: FnMut(Option<F::File>, u64, usize, usize) -> io::Result<()>,
     {
         let piece_length = self.info_dict.piece_length() as u64;
 
@@ -56,7 +103,7 @@ where
         let mut total_bytes_accessed = 0;
         let total_block_length = message.block_length() as u64;
 
-        for file in self.info_dict.files() {
+        for (file_index, file) in self.info_dict.files().enumerate() {
             let total_file_size = file.length() as u64;
 
             let mut bytes_to_access = total_file_size;
@@ -66,18 +113,34 @@ where
             bytes_to_access -= min_bytes_to_skip;
 
             if bytes_to_access > 0 && total_bytes_accessed < total_block_length {
-                let file_path = helpers::build_path(self.info_dict.directory(), file);
-                let fs_file = self.fs.open_file(file_path)?;
-
                 let total_max_bytes_to_access = total_block_length - total_bytes_accessed;
                 let actual_bytes_to_access = cmp::min(total_max_bytes_to_access, bytes_to_access);
-                let offset = total_file_size - bytes_to_access;
 
                 let (begin, end) = (
                     total_bytes_accessed as usize,
                     (total_bytes_accessed + actual_bytes_to_access) as usize,
                 );
-                callback(fs_file, offset, begin, end)?;
+                if file.is_padding() {
+                    callback(None, total_file_size - bytes_to_access, begin, end)?;
+                    total_bytes_accessed += actual_bytes_to_access;
+                    continue;
+                }
+
+                // Bytes of skipped files sharing the piece with other files are in the part file
+                let (file_path, offset) =
+                    match self.part_file.route(message.piece_index(), file_index) {
+                        Some(piece_offset) => (
+                            self.part_file.path().to_path_buf(),
+                            piece_offset + message.block_offset() + begin as u64,
+                        ),
+                        None => (
+                            helpers::build_path(self.info_dict.directory(), file),
+                            total_file_size - bytes_to_access,
+                        ),
+                    };
+                let fs_file = self.fs.open_file(file_path)?;
+
+                callback(Some(fs_file), offset, begin, end)?;
                 total_bytes_accessed += actual_bytes_to_access;
             }
         }
@@ -85,3 +148,9 @@ where
         Ok(())
     }
 }
+
+fn zero_fill(buffer: &mut [u8]) {
+    for byte in buffer.iter_mut() {
+        *byte = 0;
+    }
+}
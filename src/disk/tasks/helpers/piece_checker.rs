@@ -4,13 +4,19 @@ use std::io;
 
 use std::sync::Arc;
 use tokio::sync::mpsc::Sender;
-use crate::disk::error::{TorrentError, TorrentErrorKind, TorrentResult};
+use crate::disk::error::{TorrentError, TorrentErrorKind, TorrentResult, TorrentResultExt};
 use crate::disk::tasks::helpers;
+use crate::disk::tasks::helpers::part_file::PartFile;
 use crate::disk::tasks::helpers::piece_accessor::PieceAccessor;
-use crate::disk::{BlockMetadata, FileSystem, ODiskMessage};
+use crate::disk::resume::{ResumeFile, ResumeState, ResumeTotals};
+use crate::disk::{
+    AllocationMode, BlockMetadata, FilePriority, FileSystem, ODiskMessage, TorrentMode,
+};
 use crate::metainfo::Info;
 use crate::util::bt::InfoHash;
 
+const ZERO_FILL_CHUNK_SIZE: u64 = 1024 * 1024;
+
 /// Calculates hashes on existing files within the file system given and reports good/bad pieces.
 pub struct PieceChecker<'a, F> {
     fs: F,
@@ -23,22 +29,141 @@ where
     F: FileSystem + 'a,
 {
     /// Create the initial PieceCheckerState for the PieceChecker.
-    pub fn init_state(fs: F, info_dict: &'a Info) -> TorrentResult<PieceCheckerState> {
+    ///
+    /// Progress fully allocating files is passed to the callback as the bytes allocated so far
+    /// and the total bytes to allocate. Pieces that the resume state marked as good, but failed
+    /// the check because their files changed, can be retrieved with `take_invalidated`.
+    ///
+    /// Skipped files are not allocated, so pieces overlapping them are not checked.
+    pub fn init_state<P>(
+        fs: F,
+        info_dict: &'a Info,
+        mode: AllocationMode,
+        progress: P,
+        opt_resume: Option<&ResumeState>,
+        file_priorities: Vec<FilePriority>,
+    ) -> TorrentResult<PieceCheckerState>
+    where
+        P: FnMut(u64, u64),
+    {
+        check_priorities_count(info_dict, &file_priorities)?;
+
         let total_blocks = info_dict.pieces().count();
         let last_piece_size = last_piece_size(info_dict);
-
-        let mut checker_state = PieceCheckerState::new(total_blocks, last_piece_size);
-        {
+        let wanted_files: Vec<bool> = file_priorities
+            .iter()
+            .map(|&priority| priority != FilePriority::Skip)
+            .collect();
+        let part_file = PartFile::new(info_dict, file_priorities);
+
+        let mut checker_state = PieceCheckerState::new(total_blocks, last_piece_size, part_file);
+        let recheck_pieces = {
             let mut piece_checker = PieceChecker::with_state(fs, info_dict, &mut checker_state);
 
-            piece_checker.validate_files_sizes()?;
-            piece_checker.fill_checker_state()?;
+            let file_sizes = piece_checker.validate_files_sizes(mode, &wanted_files, progress)?;
+            let recheck_pieces = piece_checker.fill_checker_state(&file_sizes, opt_resume)?;
             piece_checker.calculate_diff()?;
-        }
+
+            recheck_pieces
+        };
+
+        checker_state.invalidated = recheck_pieces
+            .into_iter()
+            .filter(|&index| !checker_state.is_good(index))
+            .collect();
 
         Ok(checker_state)
     }
 
+    /// Capture the verified pieces, files and pending blocks as resume state.
+    pub fn resume_state(&self) -> io::Result<ResumeState> {
+        let good_pieces: Vec<bool> = (0..self.checker_state.total_blocks as u64)
+            .map(|index| self.checker_state.is_good(index))
+            .collect();
+
+        let blocks = self
+            .checker_state
+            .pending_blocks
+            .values()
+            .flat_map(|blocks| blocks.iter())
+            .filter(|block| !good_pieces[block.piece_index() as usize])
+            .map(|block| {
+                (
+                    block.piece_index(),
+                    block.block_offset(),
+                    block.block_length() as u64,
+                )
+            })
+            .collect();
+
+        Ok(ResumeState {
+            info_hash: self.info_dict.info_hash(),
+            good_pieces: good_pieces,
+            files: self.file_stats()?,
+            blocks: blocks,
+            totals: ResumeTotals::default(),
+        })
+    }
+
+    /// Change the priority of each file, moving bytes of pieces already written between the
+    /// part file and the files that are now skipped or no longer skipped.
+    ///
+    /// Files that are no longer skipped are allocated as they would have been when adding the torrent.
+    pub fn set_file_priorities(
+        mut self,
+        mode: AllocationMode,
+        file_priorities: Vec<FilePriority>,
+    ) -> TorrentResult<()> {
+        check_priorities_count(self.info_dict, &file_priorities)?;
+
+        let old_part_file = self.checker_state.part_file.clone();
+        let new_part_file = PartFile::new(self.info_dict, file_priorities);
+
+        let unskipped_files: Vec<bool> = (0..new_part_file.file_priorities().len())
+            .map(|index| old_part_file.is_skipped(index) && !new_part_file.is_skipped(index))
+            .collect();
+        self.validate_files_sizes(mode, &unskipped_files, |_, _| ())?;
+
+        let (old_accessor, new_accessor) = (
+            PieceAccessor::new(&self.fs, self.info_dict, &old_part_file),
+            PieceAccessor::new(&self.fs, self.info_dict, &new_part_file),
+        );
+        let piece_length = self.info_dict.piece_length() as usize;
+        let mut piece_buffer = vec![0u8; piece_length];
+
+        // Pieces without any blocks written can be left alone
+        for piece_index in old_part_file
+            .moved_pieces(&new_part_file)
+            .into_iter()
+            .filter(|&index| self.checker_state.has_blocks(index))
+        {
+            let length = self.checker_state.piece_length(piece_index, piece_length);
+            let metadata = BlockMetadata::with_default_hash(piece_index, 0, length);
+
+            old_accessor.read_piece_zero_filled(&mut piece_buffer[..length], &metadata)?;
+            new_accessor.write_piece(&piece_buffer[..length], &metadata)?;
+        }
+
+        // Nothing is kept in the part file anymore, if it was created at all
+        if old_part_file.has_skipped_files() && !new_part_file.has_skipped_files() {
+            let _ = self.fs.remove_file(new_part_file.path().to_path_buf());
+        }
+        self.checker_state.part_file = Arc::new(new_part_file);
+
+        Ok(())
+    }
+
+    /// Allocate the files of the torrent that are not skipped, as they would have been when
+    /// adding the torrent.
+    pub fn allocate_files(mut self, mode: AllocationMode) -> TorrentResult<()> {
+        let wanted_files: Vec<bool> = (0..self.checker_state.part_file.file_priorities().len())
+            .map(|index| !self.checker_state.part_file.is_skipped(index))
+            .collect();
+
+        self.validate_files_sizes(mode, &wanted_files, |_, _| ())
+            .map(|_| ())
+    }
+
     /// Create a new PieceChecker with the given state.
     pub fn with_state(
         fs: F,
@@ -60,23 +185,16 @@ where
         let mut piece_buffer = vec![0u8; piece_length as usize];
 
         let info_dict = self.info_dict;
-        let piece_accessor = PieceAccessor::new(&self.fs, self.info_dict);
-
-        self.checker_state.run_with_whole_pieces(piece_length as usize, |message| {
-            piece_accessor.read_piece(&mut piece_buffer[..message.block_length()], message)?;
+        let part_file = self.checker_state.part_file.clone();
+        let piece_accessor = PieceAccessor::new(&self.fs, self.info_dict, &part_file);
 
-            let calculated_hash = InfoHash::from_bytes(&piece_buffer[..message.block_length()]);
-            let expected_hash = InfoHash::from_hash(
-                info_dict
-                    .pieces()
-                    .skip(message.piece_index() as usize)
-                    .next()
-                    .expect("bittorrent-protocol_peer: Piece Checker Failed To Retrieve Expected Hash"),
-            )
-            .expect("bittorrent-protocol_peer: Wrong Length Of Expected Hash Received");
+        self.checker_state
+            .run_with_whole_pieces(piece_length as usize, |message| {
+                let piece = &mut piece_buffer[..message.block_length()];
+                piece_accessor.read_piece(piece, message)?;
 
-            Ok(calculated_hash == expected_hash)
-        })?;
+                Ok(verify_piece(info_dict, message.piece_index(), piece))
+            })?;
 
         Ok(())
     }
@@ -84,85 +202,322 @@ where
     /// Fill the PieceCheckerState with all piece messages for each file in our info dictionary.
     ///
     /// This is done once when a torrent file is added to see if we have any good pieces that
-    /// the caller can use to skip (if the torrent was partially downloaded before).
-    fn fill_checker_state(&mut self) -> io::Result<()> {
+    /// the caller can use to skip (if the torrent was partially downloaded before). Pieces
+    /// extending past the end of the files on disk are skipped, they can not be good yet.
+    ///
+    /// With a resume state, pieces whose files did not change since it was saved are taken
+    /// from it without a check. Returns the pieces it marked as good that have to be checked.
+    fn fill_checker_state(
+        &mut self,
+        file_sizes: &[u64],
+        opt_resume: Option<&ResumeState>,
+    ) -> io::Result<Vec<u64>> {
         let piece_length = self.info_dict.piece_length() as u64;
-        let total_bytes: u64 = self
+        let file_ends: Vec<u64> = self
             .info_dict
             .files()
-            .map(|file| file.length() as u64)
-            .sum();
+            .scan(0, |total_bytes, file| {
+                *total_bytes += file.length() as u64;
+                Some(*total_bytes)
+            })
+            .collect();
+        let total_bytes = file_ends.last().cloned().unwrap_or(0);
 
         let full_pieces = total_bytes / piece_length;
         let last_piece_size = last_piece_size(self.info_dict);
+        let total_pieces = full_pieces + if last_piece_size != 0 { 1 } else { 0 };
 
-        for piece_index in 0..full_pieces {
-            self.checker_state
-                .add_pending_block(BlockMetadata::with_default_hash(
-                    piece_index,
-                    0,
-                    piece_length as usize,
-                ));
+        let all_on_disk = self
+            .info_dict
+            .files()
+            .zip(file_sizes)
+            .all(|(file, &size)| file.length() as u64 == size);
+        let changed_files: Vec<bool> = match opt_resume {
+            Some(resume) => self
+                .file_stats()?
+                .iter()
+                .zip(resume.files.iter())
+                .map(|(current, saved)| current != saved)
+                .collect(),
+            None => Vec::new(),
+        };
+        let piece_changed = |piece_index: u64, length: u64| {
+            region_files(&file_ends, piece_index * piece_length, length)
+                .iter()
+                .any(|&(index, _)| changed_files[index])
+        };
+
+        let mut recheck_pieces = Vec::new();
+        for piece_index in 0..total_pieces {
+            let length = if piece_index == full_pieces {
+                last_piece_size as u64
+            } else {
+                piece_length
+            };
+
+            if let Some(resume) = opt_resume {
+                let was_good = resume.good_pieces[piece_index as usize];
+
+                if !piece_changed(piece_index, length) {
+                    if was_good {
+                        self.checker_state
+                            .new_states
+                            .push(PieceState::Good(piece_index));
+                    }
+                    continue;
+                } else if was_good {
+                    recheck_pieces.push(piece_index);
+                }
+            }
+
+            let on_disk = all_on_disk
+                || region_files(&file_ends, piece_index * piece_length, length)
+                    .iter()
+                    .all(|&(index, end)| end <= file_sizes[index]);
+            if on_disk {
+                self.checker_state
+                    .add_pending_block(BlockMetadata::with_default_hash(
+                        piece_index,
+                        0,
+                        length as usize,
+                    ));
+            }
         }
 
-        if last_piece_size != 0 {
-            self.checker_state
-                .add_pending_block(BlockMetadata::with_default_hash(
-                    full_pieces,
-                    0,
-                    last_piece_size as usize,
-                ));
+        // Blocks written for incomplete pieces are still there if their files did not change
+        if let Some(resume) = opt_resume {
+            let info_hash = self.info_dict.info_hash();
+
+            for &(piece_index, block_offset, block_length) in resume.blocks.iter() {
+                let length = if piece_index == full_pieces {
+                    last_piece_size as u64
+                } else {
+                    piece_length
+                };
+
+                if !resume.good_pieces[piece_index as usize] && !piece_changed(piece_index, length)
+                {
+                    self.checker_state.add_pending_block(BlockMetadata::new(
+                        info_hash,
+                        piece_index,
+                        block_offset,
+                        block_length as usize,
+                    ));
+                }
+            }
         }
 
-        Ok(())
+        Ok(recheck_pieces)
     }
 
-    /// Validates the file sizes for the given torrent file and block allocates them if they do not exist.
+    /// Size and modification time of each file in our info dictionary.
     ///
-    /// This function will, if the file does not exist, or exists and is zero size, fill the file with zeroes.
-    /// Otherwise, if the file exists and it is of the correct size, it will be left alone. If it is of the wrong
-    /// size, an error will be thrown as we do not want to overwrite and existing file that maybe just had the same
-    /// name as a file in our dictionary.
-    fn validate_files_sizes(&mut self) -> TorrentResult<()> {
-        for file in self.info_dict.files() {
+    /// Skipped files are not opened, so they are not created, and are reported as empty. Padding
+    /// files are not opened either, and are reported at their full length.
+    fn file_stats(&self) -> io::Result<Vec<ResumeFile>> {
+        self.info_dict
+            .files()
+            .enumerate()
+            .map(|(file_index, file)| {
+                if file.is_padding() {
+                    return Ok(ResumeFile {
+                        size: file.length() as u64,
+                        opt_modified: None,
+                    });
+                } else if self.checker_state.part_file.is_skipped(file_index) {
+                    return Ok(ResumeFile {
+                        size: 0,
+                        opt_modified: None,
+                    });
+                }
+
+                let file_path = helpers::build_path(self.info_dict.directory(), file);
+                let fs_file = self.fs.open_file(file_path)?;
+
+                Ok(ResumeFile {
+                    size: self.fs.file_size(&fs_file)?,
+                    opt_modified: self.fs.file_modified(&fs_file)?,
+                })
+            })
+            .collect()
+    }
+
+    /// Validates the file sizes for the given torrent file and allocates them if they do not exist.
+    ///
+    /// This function will, if the file does not exist, or exists and is zero size, allocate the file according
+    /// to the allocation mode. Otherwise, if the file exists and it is of the correct size, it will be left alone.
+    /// If it is of the wrong size, an error will be thrown as we do not want to overwrite and existing file that
+    /// maybe just had the same name as a file in our dictionary. When files are not allocated, smaller files are
+    /// assumed to be partially downloaded. Files not marked in the given flags are left alone.
+    ///
+    /// Padding files are never opened, their bytes are all zeroes without being stored.
+    ///
+    /// Returns the size of each file after allocation, files left alone are reported as empty
+    /// and padding files at their full length.
+    fn validate_files_sizes<P>(
+        &mut self,
+        mode: AllocationMode,
+        files_to_validate: &[bool],
+        mut progress: P,
+    ) -> TorrentResult<Vec<u64>>
+    where
+        P: FnMut(u64, u64),
+    {
+        let mut file_sizes = Vec::new();
+        let mut to_allocate = Vec::new();
+
+        for (file, &validate) in self.info_dict.files().zip(files_to_validate) {
+            if file.is_padding() {
+                file_sizes.push(file.length() as u64);
+                continue;
+            } else if !validate {
+                file_sizes.push(0);
+                continue;
+            }
+
             let file_path = helpers::build_path(self.info_dict.directory(), file);
             let expected_size = file.length() as u64;
 
-            self.fs
-                .open_file(file_path.clone())
-                .map_err(|err| err.into())
-                .and_then(|mut file| {
-                    // File May Or May Not Have Existed Before, If The File Is Zero
-                    // Length, Assume It Wasn't There (User Doesn't Lose Any Data)
-                    let actual_size = self.fs.file_size(&file)?;
-
-                    let size_matches = actual_size == expected_size;
-                    let size_is_zero = actual_size == 0;
-
-                    if !size_matches && size_is_zero {
-                        self.fs
-                            .write_file(&mut file, expected_size - 1, &[0])
-                            .expect(
-                            "bittorrent-protocol_peer: Failed To Create File When Validating Sizes",
-                        );
-                    } else if !size_matches {
-                        return Err(TorrentError::from_kind(
-                            TorrentErrorKind::ExistingFileSizeCheck {
-                                file_path: file_path,
-                                expected_size: expected_size,
-                                actual_size: actual_size,
-                            },
-                        ));
-                    }
+            // File May Or May Not Have Existed Before, If The File Is Zero
+            // Length, Assume It Wasn't There (User Doesn't Lose Any Data)
+            let fs_file = self.fs.open_file(file_path.clone())?;
+            let actual_size = self.fs.file_size(&fs_file)?;
+
+            let size_matches = actual_size == expected_size;
+            let size_is_zero = actual_size == 0;
+            let is_partial = mode == AllocationMode::DontAllocate && actual_size < expected_size;
+
+            if !size_matches && size_is_zero && mode != AllocationMode::DontAllocate {
+                to_allocate.push((file_path, fs_file, expected_size));
+                file_sizes.push(expected_size);
+            } else if !size_matches && !size_is_zero && !is_partial {
+                return Err(TorrentError::from_kind(
+                    TorrentErrorKind::ExistingFileSizeCheck {
+                        file_path: file_path,
+                        expected_size: expected_size,
+                        actual_size: actual_size,
+                    },
+                ));
+            } else {
+                file_sizes.push(actual_size);
+            }
+        }
+
+        let total_bytes = to_allocate.iter().map(|&(_, _, size)| size).sum();
+        let mut allocated_bytes = 0;
+        for (file_path, mut fs_file, size) in to_allocate {
+            self.allocate_file(&mut fs_file, size, mode, |bytes| {
+                allocated_bytes += bytes;
+                progress(allocated_bytes, total_bytes);
+            })
+            .chain_err(|| TorrentErrorKind::AllocationFailed {
+                file_path: file_path,
+                required_bytes: size,
+            })?;
+        }
+
+        Ok(file_sizes)
+    }
+
+    /// Allocate the file up to the given size, passing the bytes allocated to the callback
+    /// as they are allocated when fully allocating.
+    fn allocate_file<C>(
+        &self,
+        file: &mut F::File,
+        size: u64,
+        mode: AllocationMode,
+        mut callback: C,
+    ) -> io::Result<()>
+    where
+        C: FnMut(u64),
+    {
+        if mode == AllocationMode::Sparse {
+            return match self.fs.write_file(file, size - 1, &[0])? {
+                0 => Err(io::Error::new(
+                    io::ErrorKind::WriteZero,
+                    "Failed To Write Last Byte",
+                )),
+                _ => Ok(()),
+            };
+        }
 
-                    Ok(())
-                })?;
+        if self.fs.allocate_file(file, size)? {
+            callback(size);
+            return Ok(());
+        }
+
+        let zeroes = vec![0u8; cmp::min(size, ZERO_FILL_CHUNK_SIZE) as usize];
+        let mut offset = 0;
+        while offset < size {
+            let length = cmp::min(size - offset, zeroes.len() as u64) as usize;
+
+            let bytes_written = self.fs.write_file(file, offset, &zeroes[..length])?;
+            if bytes_written == 0 {
+                return Err(io::Error::new(
+                    io::ErrorKind::WriteZero,
+                    "Failed To Fill File With Zeroes",
+                ));
+            }
+
+            offset += bytes_written as u64;
+            callback(bytes_written as u64);
         }
 
         Ok(())
     }
 }
 
+/// Index of each file overlapping the region of the torrent, along with the end of the overlap
+/// within that file, given the offset in the torrent where each file ends.
+fn region_files(file_ends: &[u64], start: u64, length: u64) -> Vec<(usize, u64)> {
+    let end = start + length;
+    let first_file = file_ends.partition_point(|&file_end| file_end <= start);
+
+    let mut regions = Vec::new();
+    for (index, &file_end) in file_ends.iter().enumerate().skip(first_file) {
+        let file_start = if index == 0 { 0 } else { file_ends[index - 1] };
+
+        if file_start >= end {
+            break;
+        } else if file_start < file_end {
+            regions.push((index, cmp::min(file_end, end) - file_start));
+        }
+    }
+
+    regions
+}
+
+fn check_priorities_count(info_dict: &Info, file_priorities: &[FilePriority]) -> TorrentResult<()> {
+    let total_files = info_dict.files().count();
+
+    if file_priorities.len() == total_files {
+        Ok(())
+    } else {
+        Err(TorrentError::from_kind(
+            TorrentErrorKind::InvalidFilePriorities {
+                hash: info_dict.info_hash(),
+                expected: total_files,
+                actual: file_priorities.len(),
+            },
+        ))
+    }
+}
+
+/// True if the bytes of the piece match its hash in the info dictionary.
+pub fn verify_piece(info_dict: &Info, piece_index: u64, piece: &[u8]) -> bool {
+    let calculated_hash = InfoHash::from_bytes(piece);
+    let expected_hash = InfoHash::from_hash(
+        info_dict
+            .pieces()
+            .skip(piece_index as usize)
+            .next()
+            .expect("bittorrent-protocol_peer: Piece Checker Failed To Retrieve Expected Hash"),
+    )
+    .expect("bittorrent-protocol_peer: Wrong Length Of Expected Hash Received");
+
+    calculated_hash == expected_hash
+}
+
 fn last_piece_size(info_dict: &Info) -> usize {
     let piece_length = info_dict.piece_length() as u64;
     let total_bytes: u64 = info_dict.files().map(|file| file.length() as u64).sum();
@@ -179,6 +534,9 @@ pub struct PieceCheckerState {
     pending_blocks: HashMap<u64, Vec<BlockMetadata>>,
     total_blocks: usize,
     last_block_size: usize,
+    invalidated: Vec<u64>,
+    part_file: Arc<PartFile>,
+    mode: TorrentMode,
 }
 
 #[derive(PartialEq, Eq, Hash,Clone)]
@@ -190,14 +548,93 @@ pub enum PieceState {
 }
 
 impl PieceCheckerState {
-    /// Create a new PieceCheckerState.
-    pub fn new(total_blocks: usize, last_block_size: usize) -> PieceCheckerState {
+    /// Create a new PieceCheckerState, accessing pieces through the given part file.
+    pub fn new(
+        total_blocks: usize,
+        last_block_size: usize,
+        part_file: PartFile,
+    ) -> PieceCheckerState {
         PieceCheckerState {
             new_states: Vec::new(),
             old_states: HashSet::new(),
             pending_blocks: HashMap::new(),
             total_blocks: total_blocks,
             last_block_size: last_block_size,
+            invalidated: Vec::new(),
+            part_file: Arc::new(part_file),
+            mode: TorrentMode::Download,
+        }
+    }
+
+    /// Whether the torrent is downloaded or only seeded.
+    pub fn torrent_mode(&self) -> TorrentMode {
+        self.mode
+    }
+
+    /// Change whether the torrent is downloaded or only seeded.
+    pub fn set_torrent_mode(&mut self, mode: TorrentMode) {
+        self.mode = mode;
+    }
+
+    /// Part file keeping the bytes of skipped files, along with the priorities of the torrent.
+    pub fn part_file(&self) -> &Arc<PartFile> {
+        &self.part_file
+    }
+
+    /// True if the piece was identified as good, or blocks were written for it.
+    pub fn has_blocks(&self, piece_index: u64) -> bool {
+        self.is_good(piece_index)
+            || self
+                .pending_blocks
+                .get(&piece_index)
+                .map_or(false, |blocks| !blocks.is_empty())
+    }
+
+    /// Length of the piece, given the length of every piece but the last.
+    pub fn piece_length(&self, piece_index: u64, piece_length: usize) -> usize {
+        let last_piece_index = self.total_blocks as u64 - 1;
+
+        match self.last_block_size {
+            last_length if last_length != 0 && piece_index == last_piece_index => last_length,
+            _ => piece_length,
+        }
+    }
+
+    /// True if the piece was identified as good.
+    pub fn is_good(&self, piece_index: u64) -> bool {
+        let good = PieceState::Good(piece_index);
+
+        self.old_states.contains(&good) || self.new_states.contains(&good)
+    }
+
+    /// Take the pieces that were marked as good by the resume state but are no longer good.
+    pub fn take_invalidated(&mut self) -> Vec<u64> {
+        self.invalidated.split_off(0)
+    }
+
+    /// Replace the identified pieces with the result of a full check.
+    ///
+    /// Blocks pending for pieces that are not good are kept, so they can still be completed.
+    pub fn reset_checked(&mut self, good_pieces: &[bool]) {
+        self.new_states.clear();
+        self.old_states.clear();
+
+        for (piece_index, _) in good_pieces.iter().enumerate().filter(|&(_, &good)| good) {
+            self.old_states.insert(PieceState::Good(piece_index as u64));
+            self.pending_blocks.remove(&(piece_index as u64));
+        }
+    }
+
+    /// Identify the piece as good or bad, after it was verified without reading it back.
+    ///
+    /// Blocks pending for the piece are dropped.
+    pub fn add_checked_piece(&mut self, piece_index: u64, good: bool) {
+        self.pending_blocks.remove(&piece_index);
+
+        if good {
+            self.new_states.push(PieceState::Good(piece_index));
+        } else {
+            self.new_states.push(PieceState::Bad(piece_index));
         }
     }
 
@@ -220,7 +657,7 @@ impl PieceCheckerState {
 
             self.old_states.insert(piece_state);
         }
-        info!("run_with_diff complete");
+        trace!("run_with_diff complete");
     }
 
     /// Pass any pieces that have not been identified as OldGood into the callback which determines
@@ -0,0 +1,201 @@
+use std::io;
+use std::path::{Component, Path, PathBuf};
+use std::time::SystemTime;
+
+use crate::disk::FileSystem;
+
+/// File system placing the paths given to it under the root a torrent was moved to.
+///
+/// Without a root, paths are passed through to the inner file system unchanged. When read
+/// only, files are opened with `FileSystem::open_file_read_only` and every change fails.
+pub struct RootedFileSystem<'a, F> {
+    fs: F,
+    opt_root: Option<&'a Path>,
+    read_only: bool,
+}
+
+impl<'a, F> RootedFileSystem<'a, F>
+where
+    F: FileSystem,
+{
+    /// Create a new RootedFileSystem.
+    pub fn new(fs: F, opt_root: Option<&'a Path>) -> RootedFileSystem<'a, F> {
+        RootedFileSystem {
+            fs: fs,
+            opt_root: opt_root,
+            read_only: false,
+        }
+    }
+
+    /// Only ever open files for reading, failing any change to them.
+    pub fn with_read_only(mut self, read_only: bool) -> RootedFileSystem<'a, F> {
+        self.read_only = read_only;
+        self
+    }
+
+    /// Path on the inner file system for the given path.
+    pub fn path<P>(&self, path: P) -> PathBuf
+    where
+        P: AsRef<Path>,
+    {
+        rooted_path(self.opt_root, path)
+    }
+
+    fn check_writable(&self) -> io::Result<()> {
+        if self.read_only {
+            Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "File System Is Read Only",
+            ))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Path on the file system for the given path, under the given root.
+///
+/// Absolute paths are placed under the root as well, parent and root components are dropped.
+pub fn rooted_path<P>(opt_root: Option<&Path>, path: P) -> PathBuf
+where
+    P: AsRef<Path>,
+{
+    let root = match opt_root {
+        Some(root) => root,
+        None => return path.as_ref().to_path_buf(),
+    };
+
+    let mut rooted_path = root.to_path_buf();
+    for component in path.as_ref().components() {
+        if let Component::Normal(name) = component {
+            rooted_path.push(name);
+        }
+    }
+
+    rooted_path
+}
+
+impl<'a, F> FileSystem for RootedFileSystem<'a, F>
+where
+    F: FileSystem,
+{
+    type File = F::File;
+
+    fn open_file<P>(&self, path: P) -> io::Result<Self::File>
+    where
+        P: AsRef<Path> + Send + 'static,
+    {
+        if self.read_only {
+            self.fs.open_file_read_only(self.path(path))
+        } else {
+            self.fs.open_file(self.path(path))
+        }
+    }
+
+    fn open_file_read_only<P>(&self, path: P) -> io::Result<Self::File>
+    where
+        P: AsRef<Path> + Send + 'static,
+    {
+        self.fs.open_file_read_only(self.path(path))
+    }
+
+    fn sync_file<P>(&self, path: P) -> io::Result<()>
+    where
+        P: AsRef<Path> + Send + 'static,
+    {
+        self.fs.sync_file(self.path(path))
+    }
+
+    fn file_size(&self, file: &Self::File) -> io::Result<u64> {
+        self.fs.file_size(file)
+    }
+
+    fn file_modified(&self, file: &Self::File) -> io::Result<Option<SystemTime>> {
+        self.fs.file_modified(file)
+    }
+
+    fn read_file(
+        &self,
+        file: &mut Self::File,
+        offset: u64,
+        buffer: &mut [u8],
+    ) -> io::Result<usize> {
+        self.fs.read_file(file, offset, buffer)
+    }
+
+    fn write_file(&self, file: &mut Self::File, offset: u64, buffer: &[u8]) -> io::Result<usize> {
+        self.check_writable()?;
+
+        self.fs.write_file(file, offset, buffer)
+    }
+
+    fn allocate_file(&self, file: &mut Self::File, size: u64) -> io::Result<bool> {
+        self.check_writable()?;
+
+        self.fs.allocate_file(file, size)
+    }
+
+    fn rename_file<P, Q>(&self, from: P, to: Q) -> io::Result<()>
+    where
+        P: AsRef<Path> + Send + 'static,
+        Q: AsRef<Path> + Send + 'static,
+    {
+        self.check_writable()?;
+
+        self.fs.rename_file(self.path(from), self.path(to))
+    }
+
+    fn remove_file<P>(&self, path: P) -> io::Result<()>
+    where
+        P: AsRef<Path> + Send + 'static,
+    {
+        self.check_writable()?;
+
+        self.fs.remove_file(self.path(path))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use super::RootedFileSystem;
+    use crate::disk::{FileSystem, MemoryFileSystem};
+
+    #[test]
+    fn positive_rooted_path_without_root() {
+        assert_eq!(
+            PathBuf::from("/downloads/file"),
+            super::rooted_path(None, "/downloads/file")
+        );
+    }
+
+    #[test]
+    fn positive_rooted_path_places_absolute_under_root() {
+        let root = Path::new("archive");
+
+        assert_eq!(
+            PathBuf::from("archive/downloads/file"),
+            super::rooted_path(Some(root), "/downloads/file")
+        );
+        assert_eq!(
+            PathBuf::from("archive/file"),
+            super::rooted_path(Some(root), "../file")
+        );
+    }
+
+    #[test]
+    fn negative_read_only_never_creates_or_writes() {
+        let filesystem = MemoryFileSystem::new();
+        filesystem.seed_file("file", vec![1, 2, 3]);
+        let rooted = RootedFileSystem::new(&filesystem, None).with_read_only(true);
+
+        assert!(rooted.open_file("missing").is_err());
+        assert_eq!(None, filesystem.file_contents("missing"));
+
+        let mut file = rooted.open_file("file").unwrap();
+        assert!(rooted.write_file(&mut file, 0, &[0]).is_err());
+        assert!(rooted.allocate_file(&mut file, 8).is_err());
+        assert_eq!(Some(vec![1, 2, 3]), filesystem.file_contents("file"));
+    }
+}
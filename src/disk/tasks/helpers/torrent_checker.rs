@@ -0,0 +1,225 @@
+use std::cmp;
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+
+use crossbeam::channel;
+
+use crate::disk::tasks::helpers::part_file::PartFile;
+use crate::disk::tasks::helpers::piece_accessor::PieceAccessor;
+use crate::disk::{BlockMetadata, FileSystem};
+use crate::metainfo::Info;
+use crate::util::bt::InfoHash;
+
+/// Number of progress updates sent over the course of a check.
+const PROGRESS_UPDATES: u64 = 100;
+
+/// Hashes every piece of a torrent against its info dictionary, regardless of what was checked before.
+pub struct TorrentChecker<'a, F> {
+    fs: F,
+    info_dict: &'a Info,
+    part_file: &'a PartFile,
+    num_workers: usize,
+}
+
+impl<'a, F> TorrentChecker<'a, F>
+where
+    F: FileSystem + Sync,
+{
+    /// Create a new TorrentChecker hashing pieces on the given number of workers, reading
+    /// bytes of skipped files from the given part file.
+    pub fn new(
+        fs: F,
+        info_dict: &'a Info,
+        part_file: &'a PartFile,
+        num_workers: usize,
+    ) -> TorrentChecker<'a, F> {
+        TorrentChecker {
+            fs: fs,
+            info_dict: info_dict,
+            part_file: part_file,
+            num_workers: cmp::max(num_workers, 1),
+        }
+    }
+
+    /// Check all pieces, returning whether each piece is good, or None if the check was cancelled.
+    ///
+    /// Pieces are read in order, so each file is read sequentially, while the workers hash them.
+    /// Bytes missing from a file, or the whole file if it is missing, are treated as zeroes. The
+    /// pieces checked and the pieces found good so far are passed to the callback periodically
+    /// and once all pieces were checked.
+    pub fn check<P>(&self, cancel: &AtomicBool, mut progress: P) -> io::Result<Option<Vec<bool>>>
+    where
+        P: FnMut(u64, u64),
+    {
+        let expected_hashes: Vec<&[u8]> = self.info_dict.pieces().collect();
+        let total_pieces = expected_hashes.len() as u64;
+        let progress_interval = cmp::max(total_pieces / PROGRESS_UPDATES, 1);
+
+        let (piece_send, piece_recv) = channel::bounded::<(u64, Vec<u8>)>(self.num_workers * 2);
+        let (result_send, result_recv) = channel::unbounded();
+
+        let mut good_pieces = vec![false; expected_hashes.len()];
+        let read_result = thread::scope(|scope| {
+            let reader = scope.spawn(move || self.read_pieces(total_pieces, cancel, piece_send));
+
+            for _ in 0..cmp::min(self.num_workers as u64, total_pieces) {
+                let (piece_recv, result_send) = (piece_recv.clone(), result_send.clone());
+                let expected_hashes = &expected_hashes;
+
+                scope.spawn(move || {
+                    for (piece_index, piece_buffer) in piece_recv {
+                        let calculated_hash = InfoHash::from_bytes(&piece_buffer);
+                        let is_good = InfoHash::from_hash(expected_hashes[piece_index as usize])
+                            .map(|expected_hash| calculated_hash == expected_hash)
+                            .unwrap_or(false);
+
+                        if result_send.send((piece_index, is_good)).is_err() {
+                            break;
+                        }
+                    }
+                });
+            }
+            drop((piece_recv, result_send));
+
+            let (mut pieces_checked, mut pieces_good) = (0, 0);
+            for (piece_index, is_good) in result_recv {
+                good_pieces[piece_index as usize] = is_good;
+                pieces_checked += 1;
+                pieces_good += is_good as u64;
+
+                if pieces_checked % progress_interval == 0 || pieces_checked == total_pieces {
+                    progress(pieces_checked, pieces_good);
+                }
+            }
+
+            reader
+                .join()
+                .expect("bittorrent-protocol_disk: TorrentChecker Reader Panicked")
+        });
+        read_result?;
+
+        if cancel.load(Ordering::SeqCst) {
+            Ok(None)
+        } else {
+            Ok(Some(good_pieces))
+        }
+    }
+
+    /// Read pieces in order and send them to the workers, until all were read or the check is cancelled.
+    fn read_pieces(
+        &self,
+        total_pieces: u64,
+        cancel: &AtomicBool,
+        piece_send: channel::Sender<(u64, Vec<u8>)>,
+    ) -> io::Result<()> {
+        let piece_length = self.info_dict.piece_length() as u64;
+        let total_bytes: u64 = self
+            .info_dict
+            .files()
+            .map(|file| file.length() as u64)
+            .sum();
+        let piece_accessor = PieceAccessor::new(&self.fs, self.info_dict, self.part_file);
+
+        for piece_index in 0..total_pieces {
+            if cancel.load(Ordering::SeqCst) {
+                break;
+            }
+
+            let length = cmp::min(piece_length, total_bytes - piece_index * piece_length);
+            let mut piece_buffer = vec![0u8; length as usize];
+            piece_accessor.read_piece_zero_filled(
+                &mut piece_buffer,
+                &BlockMetadata::with_default_hash(piece_index, 0, length as usize),
+            )?;
+
+            if piece_send.send((piece_index, piece_buffer)).is_err() {
+                break;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    use super::TorrentChecker;
+    use crate::disk::tasks::helpers::part_file::PartFile;
+    use crate::disk::{FilePriority, NativeFileSystem};
+    use crate::metainfo::{DirectAccessor, Metainfo, MetainfoBuilder, PieceLength};
+
+    fn metainfo(data: &[u8]) -> Metainfo {
+        let accessor = DirectAccessor::new("file", data);
+        let bytes = MetainfoBuilder::new()
+            .set_piece_length(PieceLength::Custom(1024))
+            .build(1, accessor, |_| ())
+            .unwrap();
+
+        Metainfo::from_bytes(bytes).unwrap()
+    }
+
+    fn temp_dir(name: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "bittorrent-protocol_torrent_checker_{}_{}",
+            name,
+            std::process::id()
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        dir
+    }
+
+    #[test]
+    fn positive_check_truncated_file() {
+        let data = vec![7u8; 3000];
+        let metainfo = metainfo(&data);
+
+        let dir = temp_dir("truncated");
+        std::fs::write(dir.join("file"), &data[..1500]).unwrap();
+
+        let part_file = PartFile::new(metainfo.info(), vec![FilePriority::Normal]);
+        let mut progress = Vec::new();
+        let result = TorrentChecker::new(
+            NativeFileSystem::with_directory(&dir),
+            metainfo.info(),
+            &part_file,
+            2,
+        )
+        .check(&AtomicBool::new(false), |checked, good| {
+            progress.push((checked, good))
+        })
+        .unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(Some(vec![true, false, false]), result);
+        assert_eq!(3, progress.len());
+        assert_eq!((3, 1), progress[2]);
+    }
+
+    #[test]
+    fn negative_check_cancelled() {
+        let data = vec![7u8; 3000];
+        let metainfo = metainfo(&data);
+
+        let dir = temp_dir("cancelled");
+        std::fs::write(dir.join("file"), &data).unwrap();
+
+        let cancel = AtomicBool::new(false);
+        cancel.store(true, Ordering::SeqCst);
+        let part_file = PartFile::new(metainfo.info(), vec![FilePriority::Normal]);
+        let result = TorrentChecker::new(
+            NativeFileSystem::with_directory(&dir),
+            metainfo.info(),
+            &part_file,
+            2,
+        )
+        .check(&cancel, |_, _| ())
+        .unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(None, result);
+    }
+}
@@ -1,67 +1,313 @@
 use crate::disk::error::{
     BlockError, BlockErrorKind, BlockResult, TorrentError, TorrentErrorKind, TorrentResult,
 };
-use crate::disk::{Block, BlockMut, FileSystem, IDiskMessage, ODiskMessage};
-use crate::metainfo::Metainfo;
+use crate::disk::memory::write_buffer::{BufferedPiece, WriteBuffer};
+use crate::disk::resume::ResumeState;
+use crate::disk::{
+    AllocationMode, Block, BlockMetadata, BlockMut, DiskFault, FilePriority, FileSystem,
+    IDiskMessage, ODiskMessage, PiecePriorities, ResumeData, TorrentMode,
+};
+use crate::metainfo::{Info, Metainfo};
 use crate::util::bt::InfoHash;
+use bytes::Bytes;
+use std::io;
+use std::path::{Path, PathBuf};
 use std::sync::mpsc::Sender;
 pub mod context;
 use self::context::DiskManagerContext;
 
 mod helpers;
+use self::helpers::file_mover;
 use self::helpers::piece_accessor::PieceAccessor;
-use self::helpers::piece_checker::{PieceChecker, PieceCheckerState, PieceState};
+use self::helpers::piece_checker::{self, PieceChecker, PieceCheckerState, PieceState};
+use self::helpers::rooted_fs::RootedFileSystem;
+use self::helpers::torrent_checker::TorrentChecker;
+use std::sync::atomic::AtomicBool;
 use std::sync::Arc;
+use tracing::{debug, trace, warn};
 
 pub fn execute_on_pool<F>(msg: IDiskMessage, context: DiskManagerContext<F>)
 where
     F: FileSystem + Send + Sync + 'static,
 {
+    let work = context.start_work();
+
     tokio::spawn(async move {
+        // Counted until the messages it hands on to other tasks are counted as well
+        let _work = work;
+
+        // Messages for a torrent being moved are executed once the move finishes
+        let msg = match context.defer_if_moving(msg) {
+            Some(msg) => msg,
+            None => return,
+        };
+        // Same for a paused torrent, until it is resumed
+        let msg = match context.defer_if_paused(msg) {
+            Some(msg) => msg,
+            None => return,
+        };
         let mut blocking_sender = context.blocking_sender();
+        let mut opt_finished_move = None;
+        let mut resumed_msgs = Vec::new();
+
+        let remove_data = matches!(msg, IDiskMessage::RemoveTorrentWithData(_));
 
         let out_msg = match msg {
             IDiskMessage::AddTorrent(metainfo) => {
                 let info_hash = metainfo.info().info_hash();
 
-                match execute_add_torrent(metainfo, &context, blocking_sender.clone()) {
+                match execute_add_torrent(
+                    metainfo,
+                    None,
+                    None,
+                    TorrentMode::Download,
+                    &context,
+                    blocking_sender.clone(),
+                ) {
                     Ok(_) => ODiskMessage::TorrentAdded(info_hash),
                     Err(err) => ODiskMessage::TorrentError(info_hash, err),
                 }
             }
-            IDiskMessage::RemoveTorrent(hash) => match execute_remove_torrent(hash, &context) {
-                Ok(_) => ODiskMessage::TorrentRemoved(hash),
-                Err(err) => ODiskMessage::TorrentError(hash, err),
-            },
-            IDiskMessage::SyncTorrent(hash) => match execute_sync_torrent(hash, &context) {
-                Ok(_) => ODiskMessage::TorrentSynced(hash),
-                Err(err) => ODiskMessage::TorrentError(hash, err),
+            IDiskMessage::AddTorrentWithResume(metainfo, resume) => {
+                let info_hash = metainfo.info().info_hash();
+
+                match execute_add_torrent(
+                    metainfo,
+                    Some(resume),
+                    None,
+                    TorrentMode::Download,
+                    &context,
+                    blocking_sender.clone(),
+                ) {
+                    Ok(_) => ODiskMessage::TorrentAdded(info_hash),
+                    Err(err) => ODiskMessage::TorrentError(info_hash, err),
+                }
+            }
+            IDiskMessage::AddTorrentWithPriorities(metainfo, priorities) => {
+                let info_hash = metainfo.info().info_hash();
+
+                match execute_add_torrent(
+                    metainfo,
+                    None,
+                    Some(priorities),
+                    TorrentMode::Download,
+                    &context,
+                    blocking_sender.clone(),
+                ) {
+                    Ok(_) => ODiskMessage::TorrentAdded(info_hash),
+                    Err(err) => ODiskMessage::TorrentError(info_hash, err),
+                }
+            }
+            IDiskMessage::AddTorrentWithMode(metainfo, mode) => {
+                let info_hash = metainfo.info().info_hash();
+
+                match execute_add_torrent(
+                    metainfo,
+                    None,
+                    None,
+                    mode,
+                    &context,
+                    blocking_sender.clone(),
+                ) {
+                    Ok(_) => ODiskMessage::TorrentAdded(info_hash),
+                    Err(err) => ODiskMessage::TorrentError(info_hash, err),
+                }
+            }
+            IDiskMessage::RemoveTorrent(hash) | IDiskMessage::RemoveTorrentWithData(hash) => {
+                // Messages deferred while the torrent was paused fail once it is removed
+                flush_buffered(hash, &context, |buffer| buffer.take_torrent(hash));
+                let remove_result = if remove_data {
+                    execute_remove_torrent_data(hash, &context)
+                } else {
+                    execute_remove_torrent(hash, &context)
+                };
+                resumed_msgs = context.resume_torrent(hash).unwrap_or_default();
+
+                match remove_result {
+                    Ok(_) => ODiskMessage::TorrentRemoved(hash),
+                    Err(err) => ODiskMessage::TorrentError(hash, err),
+                }
+            }
+            IDiskMessage::SyncTorrent(hash) => {
+                flush_buffered(hash, &context, |buffer| buffer.take_torrent(hash));
+
+                match execute_sync_torrent(hash, &context) {
+                    Ok(_) => ODiskMessage::TorrentSynced(hash),
+                    Err(err) => ODiskMessage::TorrentError(hash, err),
+                }
+            }
+            IDiskMessage::CheckTorrent(hash) => {
+                let (check_context, check_sender) = (context.clone(), blocking_sender.clone());
+
+                // Checks take a while, keep them off of the threads running async tasks
+                let check_result = tokio::task::spawn_blocking(move || {
+                    execute_check_torrent(hash, &check_context, check_sender)
+                })
+                .await
+                .expect(
+                    "bittorrent-protocol_disk: Failed To Join Torrent Check In execute_on_pool",
+                );
+
+                match check_result {
+                    Ok(Some(good_pieces)) => ODiskMessage::TorrentChecked(hash, good_pieces),
+                    Ok(None) => ODiskMessage::TorrentCheckCancelled(hash),
+                    Err(err) => ODiskMessage::TorrentError(hash, err),
+                }
+            }
+            IDiskMessage::MoveTorrent(hash, new_root) => {
+                let move_context = context.clone();
+
+                let move_result = tokio::task::spawn_blocking(move || {
+                    execute_move_torrent(hash, &new_root, &move_context)
+                })
+                .await
+                .expect("bittorrent-protocol_disk: Failed To Join Torrent Move In execute_on_pool");
+                opt_finished_move = Some(hash);
+
+                match move_result {
+                    Ok(_) => ODiskMessage::TorrentMoved(hash),
+                    Err((err, rolled_back)) => ODiskMessage::TorrentMoveFailed {
+                        hash: hash,
+                        error: err,
+                        rolled_back: rolled_back,
+                    },
+                }
+            }
+            IDiskMessage::SetFilePriorities(hash, priorities) => {
+                match execute_set_file_priorities(hash, priorities, &context) {
+                    Ok(piece_priorities) => ODiskMessage::FilePrioritiesSet(hash, piece_priorities),
+                    Err(err) => ODiskMessage::TorrentError(hash, err),
+                }
+            }
+            IDiskMessage::ResumeTorrent(hash) => match context.resume_torrent(hash) {
+                Some(deferred_msgs) => {
+                    resumed_msgs = deferred_msgs;
+
+                    ODiskMessage::TorrentResumed(hash)
+                }
+                None => ODiskMessage::TorrentError(
+                    hash,
+                    TorrentError::from_kind(TorrentErrorKind::TorrentNotPaused { hash: hash }),
+                ),
             },
+            IDiskMessage::SetTorrentMode(hash, mode) => {
+                let (mode_context, mode_sender) = (context.clone(), blocking_sender.clone());
+
+                // Every piece is checked again, so keep it off of the threads running async tasks
+                let mode_result = tokio::task::spawn_blocking(move || {
+                    execute_set_torrent_mode(hash, mode, &mode_context, mode_sender)
+                })
+                .await
+                .expect(
+                    "bittorrent-protocol_disk: Failed To Join Torrent Mode Change In execute_on_pool",
+                );
+
+                match mode_result {
+                    Ok(Some(good_pieces)) => ODiskMessage::TorrentModeSet(hash, mode, good_pieces),
+                    Ok(None) => ODiskMessage::TorrentCheckCancelled(hash),
+                    Err(err) => ODiskMessage::TorrentError(hash, err),
+                }
+            }
             IDiskMessage::LoadBlock(mut block) => match execute_load_block(&mut block, &context) {
                 Ok(_) => ODiskMessage::BlockLoaded(block),
                 Err(err) => ODiskMessage::LoadBlockError(block, err),
             },
-            IDiskMessage::ProcessBlock(mut block) => {
-                match execute_process_block(&mut block, &context, blocking_sender.clone()) {
-                    Ok(_) => ODiskMessage::BlockProcessed(block),
-                    Err(err) => ODiskMessage::ProcessBlockError(block, err),
+            IDiskMessage::ProcessBlock(block) => {
+                // A block failing to process pauses the torrent, and counts as work until resumed
+                let (block_context, block_sender) = (context.clone(), blocking_sender.clone());
+
+                match execute_process_block_with_policy(block, block_context, block_sender).await {
+                    Some(out_msg) => out_msg,
+                    None => return,
                 }
             }
         };
 
+        trace_out_msg(&out_msg);
         blocking_sender
             .send(out_msg)
             .expect("bittorrent-protocol_disk: Failed To Send Out Message In execute_on_pool");
+
+        // Deferred messages were already counted against the capacity of the sink
+        if let Some(hash) = opt_finished_move {
+            for deferred_msg in context.finish_move(hash) {
+                execute_on_pool(deferred_msg, context.clone());
+            }
+        }
+        for resumed_msg in resumed_msgs {
+            execute_on_pool(resumed_msg, context.clone());
+        }
         // blocking_sender
         //     .flush()
         //     .expect("bittorrent-protocol_disk: Failed to Flush Out Messages In execute_on_pool");
 
-        info!("EXECUTE_ON_POOL COMPLETE\n");
+        trace!("execute_on_pool complete");
     });
 }
 
+/// Errors are logged as they leave the disk manager, everything else is left to the consumer.
+fn trace_out_msg(msg: &ODiskMessage) {
+    match msg {
+        ODiskMessage::TorrentError(hash, err) => warn!(
+            info_hash = %hash,
+            error = %err,
+            monotonic_counter.disk_errors = 1u64,
+            "torrent error"
+        ),
+        ODiskMessage::TorrentMoveFailed {
+            hash,
+            error,
+            rolled_back,
+        } => warn!(
+            info_hash = %hash,
+            error = %error,
+            rolled_back = rolled_back,
+            monotonic_counter.disk_errors = 1u64,
+            "torrent move failed"
+        ),
+        ODiskMessage::TorrentPaused(hash, fault) => warn!(
+            info_hash = %hash,
+            fault = ?fault,
+            "torrent paused after disk fault"
+        ),
+        ODiskMessage::LoadBlockError(block, err) => warn!(
+            info_hash = %block.metadata().info_hash(),
+            piece = block.metadata().piece_index(),
+            offset = block.metadata().block_offset(),
+            error = %err,
+            monotonic_counter.disk_errors = 1u64,
+            "failed to load block"
+        ),
+        ODiskMessage::ProcessBlockError(block, err) => warn!(
+            info_hash = %block.metadata().info_hash(),
+            piece = block.metadata().piece_index(),
+            offset = block.metadata().block_offset(),
+            error = %err,
+            monotonic_counter.disk_errors = 1u64,
+            "failed to process block"
+        ),
+        ODiskMessage::TorrentAdded(hash) => debug!(info_hash = %hash, "torrent added to disk"),
+        ODiskMessage::BlockProcessed(block) => trace!(
+            info_hash = %block.metadata().info_hash(),
+            piece = block.metadata().piece_index(),
+            offset = block.metadata().block_offset(),
+            "block processed"
+        ),
+        ODiskMessage::BlockLoaded(block) => trace!(
+            info_hash = %block.metadata().info_hash(),
+            piece = block.metadata().piece_index(),
+            offset = block.metadata().block_offset(),
+            "block loaded"
+        ),
+        _ => (),
+    }
+}
+
 fn execute_add_torrent<F>(
     file: Metainfo,
+    opt_resume: Option<ResumeData>,
+    opt_priorities: Option<Vec<FilePriority>>,
+    mode: TorrentMode,
     context: &DiskManagerContext<F>,
     blocking_sender: Sender<ODiskMessage>,
 ) -> TorrentResult<()>
@@ -69,12 +315,56 @@ where
     F: FileSystem,
 {
     let info_hash = file.info().info_hash();
-    let mut init_state = PieceChecker::init_state(context.filesystem(), file.info())?;
+    let opt_resume_state = opt_resume.and_then(|resume| {
+        let opt_state = ResumeState::decode(&resume, file.info());
+        if opt_state.is_none() {
+            warn!("Resume Data For {:?} Is Invalid, Checking All Pieces", info_hash);
+        }
 
-    info!("PieceChecker init_state complete ");
+        opt_state
+    });
+
+    let file_priorities =
+        opt_priorities.unwrap_or_else(|| vec![FilePriority::Normal; file.info().files().count()]);
+
+    // Files of a seed only torrent are never created or allocated, only checked
+    let (filesystem, allocation_mode) = if mode.is_seed_only() {
+        (
+            RootedFileSystem::new(context.filesystem(), None).with_read_only(true),
+            AllocationMode::DontAllocate,
+        )
+    } else {
+        (
+            RootedFileSystem::new(context.filesystem(), None),
+            context.allocation_mode(),
+        )
+    };
+
+    let progress_sender = blocking_sender.clone();
+    let mut init_state = PieceChecker::init_state(
+        filesystem,
+        file.info(),
+        allocation_mode,
+        |allocated, total| {
+            progress_sender
+                .send(ODiskMessage::AllocationProgress(info_hash, allocated, total))
+                .expect("bittorrent-protocol_disk: Failed To Send Allocation Progress Message");
+        },
+        opt_resume_state.as_ref(),
+        file_priorities,
+    )?;
+
+    debug!(info_hash = %info_hash, "piece checker initialized");
+    init_state.set_torrent_mode(mode);
+
+    for piece_index in init_state.take_invalidated() {
+        blocking_sender
+            .send(ODiskMessage::InvalidatedPiece(info_hash, piece_index))
+            .expect("bittorrent-protocol_disk: Failed To Send Invalidated Piece Message");
+    }
 
     // In case we are resuming a download, we need to send the diff for the newly added torrent
-    send_piece_diff(&mut init_state, info_hash, blocking_sender, true);
+    send_piece_diff(&mut init_state, info_hash, context, blocking_sender, true);
 
     if context.insert_torrent(file, init_state) {
         Ok(())
@@ -85,6 +375,44 @@ where
     }
 }
 
+pub fn execute_save_resume_data<F>(
+    hash: InfoHash,
+    context: &DiskManagerContext<F>,
+) -> TorrentResult<ResumeData>
+where
+    F: FileSystem,
+{
+    let mut resume_result = Err(io::Error::new(io::ErrorKind::Other, "Torrent Not Found"));
+    let found_hash = context.update_torrent(hash, |metainfo_file, checker_state, opt_root| {
+        let filesystem = RootedFileSystem::new(context.filesystem(), opt_root.as_deref())
+            .with_read_only(checker_state.torrent_mode().is_seed_only());
+
+        resume_result = PieceChecker::with_state(filesystem, metainfo_file.info(), checker_state)
+            .resume_state();
+    });
+
+    if found_hash {
+        Ok(resume_result?.encode())
+    } else {
+        Err(TorrentError::from_kind(
+            TorrentErrorKind::InfoHashNotFound { hash: hash },
+        ))
+    }
+}
+
+pub fn execute_piece_priorities<F>(
+    hash: InfoHash,
+    context: &DiskManagerContext<F>,
+) -> TorrentResult<PiecePriorities> {
+    let mut opt_priorities = None;
+    context.update_torrent(hash, |_, checker_state, _| {
+        opt_priorities = Some(checker_state.part_file().piece_priorities().clone());
+    });
+
+    opt_priorities
+        .ok_or_else(|| TorrentError::from_kind(TorrentErrorKind::InfoHashNotFound { hash: hash }))
+}
+
 fn execute_remove_torrent<F>(hash: InfoHash, context: &DiskManagerContext<F>) -> TorrentResult<()>
 where
     F: FileSystem,
@@ -98,19 +426,74 @@ where
     }
 }
 
-fn execute_sync_torrent<F>(hash: InfoHash, context: &DiskManagerContext<F>) -> TorrentResult<()>
+fn execute_remove_torrent_data<F>(hash: InfoHash, context: &DiskManagerContext<F>) -> TorrentResult<()>
 where
     F: FileSystem,
 {
-    let filesystem = context.filesystem();
+    let mut opt_files = None;
+    context.update_torrent(hash, |metainfo_file, checker_state, opt_root| {
+        opt_files = Some((
+            torrent_paths(metainfo_file, checker_state),
+            opt_root.clone(),
+            checker_state.torrent_mode().is_seed_only(),
+        ));
+    });
 
-    let mut sync_result = Ok(());
-    let found_hash = context.update_torrent(hash, |metainfo_file, _| {
-        let opt_parent_dir = metainfo_file.info().directory();
+    execute_remove_torrent(hash, context)?;
+
+    if let Some((paths, opt_root, read_only)) = opt_files {
+        let filesystem = RootedFileSystem::new(context.filesystem(), opt_root.as_deref())
+            .with_read_only(read_only);
+
+        for path in paths {
+            match filesystem.remove_file(path) {
+                // Files of skipped or unallocated pieces may never have been created
+                Err(ref err) if err.kind() == io::ErrorKind::NotFound => (),
+                result => result?,
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Cancel running checks and wait for every message being executed, then write the
+/// buffered pieces and sync the files of every torrent.
+///
+/// Every torrent is synced, the first error is returned. Calls after the first return at once.
+pub async fn execute_shutdown<F>(context: &DiskManagerContext<F>) -> TorrentResult<()>
+where
+    F: FileSystem,
+{
+    if !context.shut_down() {
+        return Ok(());
+    }
+
+    for hash in context.torrent_hashes() {
+        context.cancel_check(hash);
+    }
+    context.wait_idle().await;
 
-        for file in metainfo_file.info().files() {
-            let path = helpers::build_path(opt_parent_dir, file);
+    context
+        .torrent_hashes()
+        .into_iter()
+        .fold(Ok(()), |result, hash| {
+            flush_buffered(hash, context, |buffer| buffer.take_torrent(hash));
+            let sync_result = execute_sync_torrent(hash, context);
 
+            result.and(sync_result)
+        })
+}
+
+fn execute_sync_torrent<F>(hash: InfoHash, context: &DiskManagerContext<F>) -> TorrentResult<()>
+where
+    F: FileSystem,
+{
+    let mut sync_result = Ok(());
+    let found_hash = context.update_torrent(hash, |metainfo_file, checker_state, opt_root| {
+        let filesystem = RootedFileSystem::new(context.filesystem(), opt_root.as_deref());
+
+        for path in torrent_paths(metainfo_file, checker_state) {
             sync_result = filesystem.sync_file(path);
         }
     });
@@ -124,6 +507,225 @@ where
     }
 }
 
+fn execute_check_torrent<F>(
+    hash: InfoHash,
+    context: &DiskManagerContext<F>,
+    blocking_sender: Sender<ODiskMessage>,
+) -> TorrentResult<Option<Vec<bool>>>
+where
+    F: FileSystem + Sync,
+{
+    let cancel = context
+        .start_check(hash)
+        .ok_or_else(|| TorrentError::from_kind(TorrentErrorKind::ExistingCheck { hash: hash }))?;
+    flush_buffered(hash, context, |buffer| buffer.take_torrent(hash));
+
+    let check_result = check_torrent(hash, context, &cancel, blocking_sender);
+    context.finish_check(hash);
+
+    check_result
+}
+
+fn check_torrent<F>(
+    hash: InfoHash,
+    context: &DiskManagerContext<F>,
+    cancel: &AtomicBool,
+    blocking_sender: Sender<ODiskMessage>,
+) -> TorrentResult<Option<Vec<bool>>>
+where
+    F: FileSystem + Sync,
+{
+    // Clone the metainfo so blocks can be processed while we check, without holding the torrent lock
+    let mut opt_metainfo = None;
+    context.update_torrent(hash, |metainfo_file, checker_state, opt_root| {
+        opt_metainfo = Some((
+            metainfo_file.clone(),
+            checker_state.part_file().clone(),
+            opt_root.clone(),
+            checker_state.torrent_mode(),
+        ));
+    });
+    let (metainfo_file, part_file, opt_root, mode) = opt_metainfo.ok_or_else(|| {
+        TorrentError::from_kind(TorrentErrorKind::InfoHashNotFound { hash: hash })
+    })?;
+
+    let pieces_total = metainfo_file.info().pieces().count() as u64;
+    let opt_good_pieces = TorrentChecker::new(
+        RootedFileSystem::new(context.filesystem(), opt_root.as_deref())
+            .with_read_only(mode.is_seed_only()),
+        metainfo_file.info(),
+        &part_file,
+        context.check_workers(),
+    )
+    .check(cancel, |pieces_checked, pieces_good| {
+        blocking_sender
+            .send(ODiskMessage::TorrentCheckProgress {
+                hash: hash,
+                pieces_checked: pieces_checked,
+                pieces_total: pieces_total,
+                pieces_good: pieces_good,
+            })
+            .expect("bittorrent-protocol_disk: Failed To Send Check Progress Message");
+    })?;
+
+    let good_pieces = match opt_good_pieces {
+        Some(good_pieces) => good_pieces,
+        None => return Ok(None),
+    };
+
+    if context.update_torrent(hash, |_, checker_state, _| {
+        checker_state.reset_checked(&good_pieces);
+
+        context.run_with_block_cache(|cache| {
+            for (piece_index, _) in good_pieces.iter().enumerate().filter(|&(_, &good)| !good) {
+                cache.invalidate_piece(hash, piece_index as u64);
+            }
+        });
+    }) {
+        Ok(Some(good_pieces))
+    } else {
+        Err(TorrentError::from_kind(
+            TorrentErrorKind::InfoHashNotFound { hash: hash },
+        ))
+    }
+}
+
+/// Change the mode of the torrent, then check every piece again, returning whether each piece
+/// is good, or None if the check was cancelled.
+fn execute_set_torrent_mode<F>(
+    hash: InfoHash,
+    mode: TorrentMode,
+    context: &DiskManagerContext<F>,
+    blocking_sender: Sender<ODiskMessage>,
+) -> TorrentResult<Option<Vec<bool>>>
+where
+    F: FileSystem + Sync,
+{
+    let cancel = context
+        .start_check(hash)
+        .ok_or_else(|| TorrentError::from_kind(TorrentErrorKind::ModeDuringCheck { hash: hash }))?;
+    flush_buffered(hash, context, |buffer| buffer.take_torrent(hash));
+
+    let mut set_result = Ok(());
+    let found_hash = context.update_torrent(hash, |metainfo_file, checker_state, opt_root| {
+        // Files of a seed only torrent were never allocated
+        if checker_state.torrent_mode().is_seed_only() && !mode.is_seed_only() {
+            let filesystem = RootedFileSystem::new(context.filesystem(), opt_root.as_deref());
+
+            set_result = PieceChecker::with_state(filesystem, metainfo_file.info(), checker_state)
+                .allocate_files(context.allocation_mode());
+        }
+
+        if set_result.is_ok() {
+            checker_state.set_torrent_mode(mode);
+        }
+    });
+
+    let check_result = match (found_hash, set_result) {
+        (true, Ok(_)) => check_torrent(hash, context, &cancel, blocking_sender),
+        (true, Err(err)) => Err(err),
+        (false, _) => Err(TorrentError::from_kind(
+            TorrentErrorKind::InfoHashNotFound { hash: hash },
+        )),
+    };
+    context.finish_check(hash);
+
+    check_result
+}
+
+fn execute_move_torrent<F>(
+    hash: InfoHash,
+    new_root: &Path,
+    context: &DiskManagerContext<F>,
+) -> Result<(), (TorrentError, bool)>
+where
+    F: FileSystem,
+{
+    // Holding the check for the torrent keeps checks from reading files while we move them
+    context.start_check(hash).ok_or_else(|| {
+        (
+            TorrentError::from_kind(TorrentErrorKind::MoveDuringCheck { hash: hash }),
+            true,
+        )
+    })?;
+
+    let mut move_result = Ok(());
+    let found_hash = context.update_torrent(hash, |metainfo_file, checker_state, opt_root| {
+        // Files of a seed only torrent may not be ours to move
+        if checker_state.torrent_mode().is_seed_only() {
+            move_result = Err((
+                TorrentError::from_kind(TorrentErrorKind::ReadOnlyTorrent { hash: hash }),
+                true,
+            ));
+            return;
+        }
+
+        move_result = file_mover::move_files(
+            context.filesystem(),
+            &torrent_paths(metainfo_file, checker_state),
+            opt_root.as_deref(),
+            new_root,
+        );
+
+        if move_result.is_ok() {
+            *opt_root = Some(new_root.to_path_buf());
+        }
+    });
+    context.finish_check(hash);
+
+    if found_hash {
+        move_result
+    } else {
+        Err((
+            TorrentError::from_kind(TorrentErrorKind::InfoHashNotFound { hash: hash }),
+            true,
+        ))
+    }
+}
+
+/// Change the file priorities of the torrent, returning the resulting priority of each piece.
+fn execute_set_file_priorities<F>(
+    hash: InfoHash,
+    priorities: Vec<FilePriority>,
+    context: &DiskManagerContext<F>,
+) -> TorrentResult<PiecePriorities>
+where
+    F: FileSystem,
+{
+    // Holding the check for the torrent keeps checks from reading pieces while we move their bytes
+    context.start_check(hash).ok_or_else(|| {
+        TorrentError::from_kind(TorrentErrorKind::PrioritiesDuringCheck { hash: hash })
+    })?;
+    flush_buffered(hash, context, |buffer| buffer.take_torrent(hash));
+
+    let mut set_result = Ok(());
+    let mut opt_priorities = None;
+    let found_hash = context.update_torrent(hash, |metainfo_file, checker_state, opt_root| {
+        opt_priorities = Some(checker_state.part_file().piece_priorities().clone());
+
+        // Skipping files of a seed only torrent would write to the part file
+        if checker_state.torrent_mode().is_seed_only() {
+            set_result = Err(TorrentError::from_kind(TorrentErrorKind::ReadOnlyTorrent {
+                hash: hash,
+            }));
+            return;
+        }
+
+        let filesystem = RootedFileSystem::new(context.filesystem(), opt_root.as_deref());
+        set_result = PieceChecker::with_state(filesystem, metainfo_file.info(), checker_state)
+            .set_file_priorities(context.allocation_mode(), priorities);
+        opt_priorities = Some(checker_state.part_file().piece_priorities().clone());
+    });
+    context.finish_check(hash);
+
+    match (found_hash, opt_priorities) {
+        (true, Some(piece_priorities)) => set_result.map(|_| piece_priorities),
+        _ => Err(TorrentError::from_kind(
+            TorrentErrorKind::InfoHashNotFound { hash: hash },
+        )),
+    }
+}
+
 fn execute_load_block<F>(block: &mut BlockMut, context: &DiskManagerContext<F>) -> BlockResult<()>
 where
     F: FileSystem,
@@ -132,11 +734,23 @@ where
     let info_hash = metadata.info_hash();
 
     let mut access_result = Ok(());
-    let found_hash = context.update_torrent(info_hash, |metainfo_file, _| {
-        let piece_accessor = PieceAccessor::new(context.filesystem(), metainfo_file.info());
+    let found_hash = context.update_torrent(info_hash, |metainfo_file, checker_state, opt_root| {
+        let opt_cached = context.run_with_block_cache(|cache| cache.get(&metadata));
+        if let Some(cached) = opt_cached.filter(|cached| cached.len() == block.len()) {
+            block.copy_from_slice(&cached);
+            return;
+        }
+
+        let filesystem = RootedFileSystem::new(context.filesystem(), opt_root.as_deref())
+            .with_read_only(checker_state.torrent_mode().is_seed_only());
+        let piece_accessor =
+            PieceAccessor::new(filesystem, metainfo_file.info(), checker_state.part_file());
 
         // Read The Piece In From The Filesystem
-        access_result = piece_accessor.read_piece(&mut *block, &metadata)
+        access_result = piece_accessor.read_piece(&mut *block, &metadata);
+        if access_result.is_ok() {
+            context.run_with_block_cache(|cache| cache.insert(metadata, Bytes::from(&block[..])));
+        }
     });
 
     if found_hash {
@@ -154,46 +768,105 @@ fn execute_process_block<F>(
     blocking_sender: Sender<ODiskMessage>,
 ) -> BlockResult<()>
 where
-    F: FileSystem,
+    F: FileSystem + Send + Sync + 'static,
 {
     let metadata = block.metadata();
     let info_hash = metadata.info_hash();
 
     let mut block_result = Ok(());
-    let found_hash = context.update_torrent(info_hash, |metainfo_file, mut checker_state| {
-        info!(
+    let mut is_read_only = false;
+    let mut is_skipped = false;
+    let mut start_flush_timer = false;
+    let found_hash = context.update_torrent(info_hash, |metainfo_file, checker_state, opt_root| {
+        trace!(
             "Processsing Block, Acquired Torrent Lock For {:?}",
             metainfo_file.info().info_hash()
         );
 
-        let piece_accessor = PieceAccessor::new(context.filesystem(), metainfo_file.info());
+        is_read_only = checker_state.torrent_mode().is_seed_only();
+        if is_read_only {
+            return;
+        }
 
-        // Write Out Piece Out To The Filesystem And Recalculate The Diff
-        block_result = piece_accessor.write_piece(&block, &metadata).and_then(|_| {
-            checker_state.add_pending_block(metadata);
+        // Blocks of pieces no file wants would otherwise create skipped files
+        is_skipped = checker_state
+            .part_file()
+            .piece_priorities()
+            .is_skipped(metadata.piece_index());
+        if is_skipped {
+            return;
+        }
+
+        let filesystem = RootedFileSystem::new(context.filesystem(), opt_root.as_deref());
+        let part_file = checker_state.part_file().clone();
+        let piece_accessor = PieceAccessor::new(&filesystem, metainfo_file.info(), &part_file);
+
+        // Pieces with blocks already written are completed by reading them back
+        let piece_index = metadata.piece_index();
+        let buffer_block = !checker_state.has_blocks(piece_index)
+            && context.run_with_write_buffer(|buffer| buffer.is_enabled());
 
-            PieceChecker::with_state(
-                context.filesystem(),
+        let write_result = if buffer_block {
+            start_flush_timer = !context
+                .run_with_write_buffer(|buffer| buffer.contains_piece(info_hash, piece_index));
+
+            write_buffered(
+                &block,
                 metainfo_file.info(),
-                &mut checker_state,
+                checker_state,
+                &piece_accessor,
+                context,
             )
-            .calculate_diff()
+        } else {
+            write_direct(&block, checker_state, &piece_accessor, context)
+        };
+
+        // Write Out Piece Out To The Filesystem And Recalculate The Diff
+        block_result = write_result.and_then(|_| {
+            let (_, cached_block) = block.clone().into_parts();
+            context.run_with_block_cache(|cache| cache.insert(metadata, cached_block));
+
+            PieceChecker::with_state(&filesystem, metainfo_file.info(), checker_state)
+                .calculate_diff()
         });
 
         send_piece_diff(
             checker_state,
             metainfo_file.info().info_hash(),
+            context,
             blocking_sender,
             false,
         );
 
-        info!(
+        trace!(
             "Processsing Block, Released Torrent Lock For {:?}",
             metainfo_file.info().info_hash()
         );
     });
 
-    if found_hash {
+    // Incomplete pieces are written once their first block was buffered for a while
+    if start_flush_timer {
+        let (timer_context, interval) = (context.clone(), context.write_flush_interval());
+
+        tokio::spawn(async move {
+            tokio::time::sleep(interval).await;
+
+            flush_buffered(info_hash, &timer_context, |buffer| {
+                buffer.take_expired(info_hash, interval)
+            });
+        });
+    }
+
+    if found_hash && is_read_only {
+        Err(BlockError::from_kind(BlockErrorKind::ReadOnlyTorrent {
+            hash: info_hash,
+        }))
+    } else if found_hash && is_skipped {
+        Err(BlockError::from_kind(BlockErrorKind::PieceSkipped {
+            hash: info_hash,
+            index: metadata.piece_index(),
+        }))
+    } else if found_hash {
         Ok(block_result?)
     } else {
         Err(BlockError::from_kind(BlockErrorKind::InfoHashNotFound {
@@ -202,13 +875,250 @@ where
     }
 }
 
-fn send_piece_diff(
+/// Process the block, retrying transient IO errors and pausing the torrent according to
+/// the fault policy.
+///
+/// Returns None if the torrent was paused, the block is then processed once it is resumed.
+async fn execute_process_block_with_policy<F>(
+    mut block: Block,
+    context: DiskManagerContext<F>,
+    blocking_sender: Sender<ODiskMessage>,
+) -> Option<ODiskMessage>
+where
+    F: FileSystem + Send + Sync + 'static,
+{
+    let policy = context.fault_policy();
+    let info_hash = block.metadata().info_hash();
+    let mut retried = 0;
+
+    loop {
+        let err = match execute_process_block(&mut block, &context, blocking_sender.clone()) {
+            Ok(_) => return Some(ODiskMessage::BlockProcessed(block)),
+            Err(err) => err,
+        };
+        let opt_fault = match *err.kind() {
+            BlockErrorKind::Io(ref io_err) => Some(DiskFault::classify(io_err)),
+            _ => None,
+        };
+
+        match opt_fault {
+            Some(DiskFault::Transient(_)) if retried < policy.retries() => {
+                warn!(
+                    "Retrying Block For {:?} After Transient Error: {}",
+                    info_hash, err
+                );
+                tokio::time::sleep(policy.retry_delay(retried)).await;
+
+                retried += 1;
+            }
+            Some(fault) if policy.pause_on_fault() => {
+                warn!("Pausing Torrent {:?} After Error: {}", info_hash, err);
+
+                if context.pause_torrent(info_hash, IDiskMessage::ProcessBlock(block)) {
+                    blocking_sender
+                        .send(ODiskMessage::TorrentPaused(info_hash, fault))
+                        .expect("bittorrent-protocol_disk: Failed To Send Torrent Paused Message");
+                }
+
+                return None;
+            }
+            _ => return Some(ODiskMessage::ProcessBlockError(block, err)),
+        }
+    }
+}
+
+fn write_direct<F, C>(
+    block: &Block,
     checker_state: &mut PieceCheckerState,
+    piece_accessor: &PieceAccessor<F>,
+    context: &DiskManagerContext<C>,
+) -> io::Result<()>
+where
+    F: FileSystem,
+{
+    let metadata = block.metadata();
+
+    piece_accessor.write_piece(block, &metadata)?;
+    context.run_with_write_buffer(|buffer| buffer.record_direct());
+    checker_state.add_pending_block(metadata);
+
+    Ok(())
+}
+
+/// Buffer the block, writing its piece at once if the block completes it.
+///
+/// Pieces of the torrent buffered first are written to make room for the block, if that is
+/// not enough the block is written directly. A complete piece is verified before it is
+/// written, so a bad piece never reaches the `FileSystem`.
+fn write_buffered<F, C>(
+    block: &Block,
+    info_dict: &Info,
+    checker_state: &mut PieceCheckerState,
+    piece_accessor: &PieceAccessor<F>,
+    context: &DiskManagerContext<C>,
+) -> io::Result<()>
+where
+    F: FileSystem,
+{
+    let metadata = block.metadata();
+    let (hash, piece_index) = (metadata.info_hash(), metadata.piece_index());
+    let (_, bytes) = block.clone().into_parts();
+
+    while !context.run_with_write_buffer(|buffer| buffer.insert(metadata, bytes.clone())) {
+        match context.run_with_write_buffer(|buffer| buffer.take_oldest(hash)) {
+            Some((oldest_index, oldest)) => {
+                flush_piece(
+                    hash,
+                    oldest_index,
+                    oldest,
+                    checker_state,
+                    piece_accessor,
+                    context,
+                );
+            }
+            None => return write_direct(block, checker_state, piece_accessor, context),
+        }
+
+        // Writing out our own piece means the rest of it is written as well
+        if checker_state.has_blocks(piece_index) {
+            return write_direct(block, checker_state, piece_accessor, context);
+        }
+    }
+
+    let piece_length = checker_state.piece_length(piece_index, info_dict.piece_length() as usize);
+    let piece = match context
+        .run_with_write_buffer(|buffer| buffer.take_complete(hash, piece_index, piece_length))
+    {
+        Some(piece) => piece,
+        None => return Ok(()),
+    };
+
+    let (_, piece_bytes) = piece
+        .runs()
+        .pop()
+        .expect("bittorrent-protocol_disk: Complete Piece Has No Runs Of Blocks");
+    let good = piece_checker::verify_piece(info_dict, piece_index, &piece_bytes);
+
+    if good {
+        let piece_metadata = BlockMetadata::new(hash, piece_index, 0, piece_length);
+
+        // Keep the piece around, so the block can be retried
+        if let Err(err) = piece_accessor.write_piece(&piece_bytes, &piece_metadata) {
+            context.run_with_write_buffer(|buffer| buffer.restore(hash, piece_index, piece));
+
+            return Err(err);
+        }
+        context.run_with_write_buffer(|buffer| buffer.record_coalesced(&piece, 1));
+    }
+    checker_state.add_checked_piece(piece_index, good);
+
+    Ok(())
+}
+
+/// Write the runs of blocks buffered for the piece, which are then pending for it.
+///
+/// If writing fails, the piece is identified as bad so that it gets downloaded again.
+fn flush_piece<F, C>(
     hash: InfoHash,
+    piece_index: u64,
+    piece: BufferedPiece,
+    checker_state: &mut PieceCheckerState,
+    piece_accessor: &PieceAccessor<F>,
+    context: &DiskManagerContext<C>,
+) where
+    F: FileSystem,
+{
+    let runs = piece.runs();
+    let num_runs = runs.len();
+
+    let flush_result = runs.into_iter().try_for_each(|(offset, run)| {
+        let run_metadata = BlockMetadata::new(hash, piece_index, offset, run.len());
+
+        piece_accessor
+            .write_piece(&run, &run_metadata)
+            .map(|_| checker_state.add_pending_block(run_metadata))
+    });
+
+    match flush_result {
+        Ok(_) => context.run_with_write_buffer(|buffer| buffer.record_coalesced(&piece, num_runs)),
+        Err(err) => {
+            warn!(
+                "Failed To Flush Piece {} For {:?}, Downloading It Again: {}",
+                piece_index, hash, err
+            );
+
+            checker_state.add_checked_piece(piece_index, false);
+        }
+    }
+}
+
+/// Write the pieces of the torrent taken from the write buffer.
+fn flush_buffered<F, T>(hash: InfoHash, context: &DiskManagerContext<F>, take: T)
+where
+    F: FileSystem,
+    T: FnOnce(&mut WriteBuffer) -> Vec<(u64, BufferedPiece)>,
+{
+    context.update_torrent(hash, |metainfo_file, checker_state, opt_root| {
+        let pieces = context.run_with_write_buffer(take);
+        if pieces.is_empty() {
+            return;
+        }
+
+        let filesystem = RootedFileSystem::new(context.filesystem(), opt_root.as_deref());
+        let part_file = checker_state.part_file().clone();
+        let piece_accessor = PieceAccessor::new(&filesystem, metainfo_file.info(), &part_file);
+
+        for (piece_index, piece) in pieces {
+            flush_piece(
+                hash,
+                piece_index,
+                piece,
+                checker_state,
+                &piece_accessor,
+                context,
+            );
+        }
+
+        send_piece_diff(
+            checker_state,
+            hash,
+            context,
+            context.blocking_sender(),
+            false,
+        );
+    });
+}
+
+/// Path of each file of the torrent, except padding files, along with the part file if any file is skipped.
+fn torrent_paths(metainfo_file: &Metainfo, checker_state: &PieceCheckerState) -> Vec<PathBuf> {
+    let info_dict = metainfo_file.info();
+    let mut paths: Vec<PathBuf> = info_dict
+        .files()
+        .filter(|file| !file.is_padding())
+        .map(|file| helpers::build_path(info_dict.directory(), file))
+        .collect();
+
+    let part_file = checker_state.part_file();
+    if part_file.has_skipped_files() {
+        paths.push(part_file.path().to_path_buf());
+    }
+
+    paths
+}
+
+fn send_piece_diff<F>(
+    checker_state: &mut PieceCheckerState,
+    hash: InfoHash,
+    context: &DiskManagerContext<F>,
     blocking_sender: Sender<ODiskMessage>,
     ignore_bad: bool,
 ) {
     checker_state.run_with_diff(move |piece_state| {
+        // Cached blocks of a bad piece do not match what the piece should hold
+        if let PieceState::Bad(index) = piece_state {
+            context.run_with_block_cache(|cache| cache.invalidate_piece(hash, index));
+        }
+
         let opt_out_msg = match (piece_state, ignore_bad) {
             (PieceState::Good(index), _) => Some(ODiskMessage::FoundGoodPiece(hash, index)),
             (PieceState::Bad(index), false) => Some(ODiskMessage::FoundBadPiece(hash, index)),
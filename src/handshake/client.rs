@@ -0,0 +1,240 @@
+use crate::util::bt::PeerId;
+
+/// Characters used to encode version numbers, each one maps to its index.
+const VERSION_CHARS: &[u8] = b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz.-";
+
+/// Maximum number of version characters in a Shadow style peer id.
+const MAX_SHADOW_VERSION_LEN: usize = 5;
+
+const AZUREUS_CLIENTS: &[(&[u8; 2], &str)] = &[
+    (b"AZ", "Vuze"),
+    (b"BC", "BitComet"),
+    (b"BI", "BiglyBT"),
+    (b"BT", "BitTorrent"),
+    (b"DE", "Deluge"),
+    (b"FD", "Free Download Manager"),
+    (b"KT", "KTorrent"),
+    (b"LT", "libtorrent (Rasterbar)"),
+    (b"lt", "libTorrent (Rakshasa)"),
+    (b"qB", "qBittorrent"),
+    (b"TR", "Transmission"),
+    (b"UM", "\u{b5}Torrent Mac"),
+    (b"UT", "\u{b5}Torrent"),
+    (b"UW", "\u{b5}Torrent Web"),
+    (b"WW", "WebTorrent"),
+    (b"XL", "Xunlei"),
+];
+
+const SHADOW_CLIENTS: &[(u8, &str)] = &[
+    (b'A', "ABC"),
+    (b'O', "Osprey Permaseed"),
+    (b'Q', "BTQueue"),
+    (b'R', "Tribler"),
+    (b'S', "Shadow's client"),
+    (b'T', "BitTornado"),
+    (b'U', "UPnP NAT Bit Torrent"),
+];
+
+/// Peer id encoding used by a client.
+///
+/// See http://www.bittorrent.org/beps/bep_0020.html.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum ClientEncoding {
+    /// Two character client code and four version characters, such as `-TR4050-`.
+    Azureus,
+    /// One character client code and up to five version characters, such as `S58B-----`.
+    Shadow,
+}
+
+/// Client name and version decoded from a peer id.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct ClientInfo {
+    encoding: ClientEncoding,
+    code: String,
+    name: Option<&'static str>,
+    version: String,
+}
+
+impl ClientInfo {
+    /// Decode the client from an Azureus or Shadow style peer id.
+    ///
+    /// Returns `None` if the peer id does not follow either encoding.
+    pub fn from_peer_id(pid: &PeerId) -> Option<ClientInfo> {
+        let bytes = pid.as_ref();
+
+        decode_azureus(bytes).or_else(|| decode_shadow(bytes))
+    }
+
+    /// Encoding the peer id was decoded from.
+    pub fn encoding(&self) -> ClientEncoding {
+        self.encoding
+    }
+
+    /// Client code embedded in the peer id.
+    pub fn code(&self) -> &str {
+        &self.code
+    }
+
+    /// Name of the client, or the client code if we do not know it.
+    pub fn name(&self) -> &str {
+        self.name.unwrap_or(&self.code)
+    }
+
+    /// Whether or not we know the client code.
+    pub fn is_known(&self) -> bool {
+        self.name.is_some()
+    }
+
+    /// Dotted version of the client, such as `4.0.5`.
+    pub fn version(&self) -> &str {
+        &self.version
+    }
+}
+
+fn decode_azureus(bytes: &[u8]) -> Option<ClientInfo> {
+    if bytes[0] != b'-' || bytes[7] != b'-' || !bytes[1..3].iter().all(u8::is_ascii_alphanumeric) {
+        return None;
+    }
+    let mut parts = bytes[3..7]
+        .iter()
+        .map(|&byte| version_value(byte))
+        .collect::<Option<Vec<usize>>>()?;
+
+    // Trailing zeroes are padding, but keep at least a major and minor version
+    while parts.len() > 2 && parts.last() == Some(&0) {
+        parts.pop();
+    }
+
+    let name = AZUREUS_CLIENTS
+        .iter()
+        .find(|&&(code, _)| &code[..] == &bytes[1..3])
+        .map(|&(_, name)| name);
+
+    Some(ClientInfo {
+        encoding: ClientEncoding::Azureus,
+        code: String::from_utf8_lossy(&bytes[1..3]).into_owned(),
+        name: name,
+        version: join_version(&parts),
+    })
+}
+
+fn decode_shadow(bytes: &[u8]) -> Option<ClientInfo> {
+    let name = SHADOW_CLIENTS
+        .iter()
+        .find(|&&(code, _)| code == bytes[0])
+        .map(|&(_, name)| name)?;
+
+    let version_bytes = &bytes[1..MAX_SHADOW_VERSION_LEN + 1];
+    let version_len = version_bytes
+        .iter()
+        .position(|&byte| byte == b'-')
+        .unwrap_or(MAX_SHADOW_VERSION_LEN);
+
+    // Unknown clients often start with the same letters, so require the padding
+    if version_len == 0 || &bytes[version_len + 1..version_len + 4] != b"---" {
+        return None;
+    }
+    let parts = version_bytes[..version_len]
+        .iter()
+        .map(|&byte| version_value(byte))
+        .collect::<Option<Vec<usize>>>()?;
+
+    Some(ClientInfo {
+        encoding: ClientEncoding::Shadow,
+        code: (bytes[0] as char).to_string(),
+        name: Some(name),
+        version: join_version(&parts),
+    })
+}
+
+fn version_value(byte: u8) -> Option<usize> {
+    VERSION_CHARS
+        .iter()
+        .position(|&version_char| version_char == byte)
+}
+
+fn join_version(parts: &[usize]) -> String {
+    parts
+        .iter()
+        .map(|part| part.to_string())
+        .collect::<Vec<String>>()
+        .join(".")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ClientEncoding, ClientInfo};
+    use crate::util::bt::PeerId;
+
+    fn peer_id(prefix: &[u8]) -> PeerId {
+        let mut bytes = [b'x'; 20];
+        bytes[..prefix.len()].copy_from_slice(prefix);
+
+        bytes.into()
+    }
+
+    #[test]
+    fn positive_decode_libtorrent_rakshasa() {
+        let info = ClientInfo::from_peer_id(&peer_id(b"-lt0D80-")).unwrap();
+
+        assert_eq!(ClientEncoding::Azureus, info.encoding());
+        assert_eq!("libTorrent (Rakshasa)", info.name());
+        assert_eq!("0.13.8", info.version());
+    }
+
+    #[test]
+    fn positive_decode_transmission() {
+        let info = ClientInfo::from_peer_id(&peer_id(b"-TR4050-")).unwrap();
+
+        assert_eq!("Transmission", info.name());
+        assert_eq!("4.0.5", info.version());
+    }
+
+    #[test]
+    fn positive_decode_qbittorrent() {
+        let info = ClientInfo::from_peer_id(&peer_id(b"-qB4650-")).unwrap();
+
+        assert_eq!("qBittorrent", info.name());
+        assert_eq!("4.6.5", info.version());
+    }
+
+    #[test]
+    fn positive_decode_keeps_minor_version() {
+        let info = ClientInfo::from_peer_id(&peer_id(b"-DE2000-")).unwrap();
+
+        assert_eq!("Deluge", info.name());
+        assert_eq!("2.0", info.version());
+    }
+
+    #[test]
+    fn positive_decode_unknown_azureus_client() {
+        let info = ClientInfo::from_peer_id(&peer_id(b"-XX1234-")).unwrap();
+
+        assert!(!info.is_known());
+        assert_eq!("XX", info.name());
+        assert_eq!("1.2.3.4", info.version());
+    }
+
+    #[test]
+    fn positive_decode_shadow() {
+        let info = ClientInfo::from_peer_id(&peer_id(b"S58B-----")).unwrap();
+
+        assert_eq!(ClientEncoding::Shadow, info.encoding());
+        assert_eq!("Shadow's client", info.name());
+        assert_eq!("5.8.11", info.version());
+    }
+
+    #[test]
+    fn positive_decode_random_with_prefix() {
+        let info = ClientInfo::from_peer_id(&PeerId::random_with_prefix(b"-TR4050-")).unwrap();
+
+        assert_eq!("Transmission", info.name());
+    }
+
+    #[test]
+    fn negative_decode_random_peer_id() {
+        assert_eq!(None, ClientInfo::from_peer_id(&[0u8; 20].into()));
+        assert_eq!(None, ClientInfo::from_peer_id(&peer_id(b"Sxxxxxxx")));
+        assert_eq!(None, ClientInfo::from_peer_id(&peer_id(b"-TR40?0-")));
+    }
+}
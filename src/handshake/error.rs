@@ -0,0 +1,34 @@
+use std::net::SocketAddr;
+
+/// Reason a handshake with a peer did not complete.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum HandshakeError {
+    /// Connecting to the peer failed.
+    ConnectFailed(SocketAddr),
+    /// Connecting to the peer took longer than the connect timeout.
+    ConnectTimedOut(SocketAddr),
+    /// Peer closed the connection or sent an invalid handshake.
+    HandshakeFailed(SocketAddr),
+    /// Peer did not finish the handshake within the handshake timeout.
+    HandshakeTimedOut(SocketAddr),
+    /// Handshake was not attempted, the address failed recently and is cooling down.
+    CoolingDown(SocketAddr),
+    /// Handshake was not attempted, we are at `ConnectionLimits::max_global_peers`.
+    ///
+    /// Peers connecting to us are closed right away.
+    TooManyPeers(SocketAddr),
+}
+
+impl HandshakeError {
+    /// Address of the peer.
+    pub fn address(&self) -> &SocketAddr {
+        match self {
+            HandshakeError::ConnectFailed(addr)
+            | HandshakeError::ConnectTimedOut(addr)
+            | HandshakeError::HandshakeFailed(addr)
+            | HandshakeError::HandshakeTimedOut(addr)
+            | HandshakeError::CoolingDown(addr)
+            | HandshakeError::TooManyPeers(addr) => addr,
+        }
+    }
+}
@@ -1,17 +1,58 @@
 use std::sync::Arc;
 use std::sync::RwLock;
 
-use crate::handshake::HandshakeFilter;
+use crossbeam::channel::Sender;
+
+use crate::handshake::{FilteredMessage, HandshakeFilter};
 
 #[derive(Clone)]
 pub struct Filters {
     filters: Arc<RwLock<Vec<Box<dyn HandshakeFilter + Send + Sync>>>>,
+    shared: Arc<RwLock<Option<Arc<dyn HandshakeFilter + Send + Sync>>>>,
+    opt_events: Option<Sender<FilteredMessage>>,
 }
 
 impl Filters {
     pub fn new() -> Filters {
         Filters {
             filters: Arc::new(RwLock::new(Vec::new())),
+            shared: Arc::new(RwLock::new(None)),
+            opt_events: None,
+        }
+    }
+
+    /// Report handshakes dropped by the filters to the given sender.
+    pub fn with_events(mut self, events: Sender<FilteredMessage>) -> Filters {
+        self.opt_events = Some(events);
+        self
+    }
+
+    /// Replace the shared filter, which is consulted along with all added filters.
+    pub fn set_shared_filter(&self, opt_filter: Option<Arc<dyn HandshakeFilter + Send + Sync>>) {
+        *self
+            .shared
+            .write()
+            .expect("bittorrent-protocol_handshake: Poisoned Write Lock In Filters") = opt_filter;
+    }
+
+    pub fn access_shared_filter<B>(&self, block: B)
+    where
+        B: FnOnce(Option<&(dyn HandshakeFilter + Send + Sync)>),
+    {
+        let ref_shared = self
+            .shared
+            .read()
+            .expect("bittorrent-protocol_handshake: Poisoned Read Lock In Filters");
+
+        block(ref_shared.as_ref().map(|filter| &**filter))
+    }
+
+    /// Report a dropped handshake, if anyone is listening.
+    ///
+    /// Messages are dropped if the receiver is not keeping up.
+    pub fn report(&self, message: FilteredMessage) {
+        if let Some(ref events) = self.opt_events {
+            let _ = events.try_send(message);
         }
     }
 
@@ -199,8 +240,14 @@ pub mod test_filters {
 
 #[cfg(test)]
 mod tests {
+    use std::sync::Arc;
+
+    use crossbeam::channel::bounded;
+
     use super::test_filters::BlockAddrFilter;
     use super::Filters;
+    use crate::handshake::handler;
+    use crate::handshake::FilteredMessage;
 
     #[test]
     fn positive_add_filter() {
@@ -334,4 +381,52 @@ mod tests {
 
         assert_eq!(0, num_filters);
     }
+
+    #[test]
+    fn positive_shared_filter_blocks() {
+        let filters = Filters::new();
+        let addr = "43.43.43.43:4343".parse().unwrap();
+
+        filters.set_shared_filter(Some(Arc::new(BlockAddrFilter::new(addr))));
+        assert!(handler::should_filter(Some(&addr), None, None, None, None, &filters));
+
+        filters.set_shared_filter(None);
+        assert!(!handler::should_filter(Some(&addr), None, None, None, None, &filters));
+    }
+
+    #[test]
+    fn positive_shared_filter_kept_on_clear() {
+        let filters = Filters::new();
+        let addr = "43.43.43.43:4343".parse().unwrap();
+
+        filters.set_shared_filter(Some(Arc::new(BlockAddrFilter::new(addr))));
+        filters.clear_filters();
+
+        assert!(handler::should_filter(Some(&addr), None, None, None, None, &filters));
+    }
+
+    #[test]
+    fn positive_report_filtered() {
+        let (send, recv) = bounded(1);
+        let filters = Filters::new().with_events(send);
+        let message = FilteredMessage::Complete("43.43.43.43:4343".parse().unwrap(), None, None);
+
+        filters.report(message.clone());
+
+        assert_eq!(Ok(message), recv.try_recv());
+    }
+
+    #[test]
+    fn negative_report_filtered_when_full() {
+        let (send, recv) = bounded(1);
+        let filters = Filters::new().with_events(send);
+        let first = FilteredMessage::Complete("43.43.43.43:4343".parse().unwrap(), None, None);
+        let second = FilteredMessage::Complete("43.43.43.43:4344".parse().unwrap(), None, None);
+
+        filters.report(first.clone());
+        filters.report(second);
+
+        assert_eq!(Ok(first), recv.try_recv());
+        assert!(recv.try_recv().is_err());
+    }
 }
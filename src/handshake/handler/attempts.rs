@@ -0,0 +1,261 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex, Weak};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crossbeam::channel::{Receiver, RecvTimeoutError, Sender};
+
+use crate::handshake::handler::HandlerSink;
+use crate::handshake::{HandshakeError, InitiateMessage, RetryPolicy};
+
+/// How often the retry loop checks whether the handshaker is still alive.
+const RETRY_POLL_INTERVAL_MILLIS: u64 = 1000;
+
+struct Cooldown {
+    failures: usize,
+    retry_at: Instant,
+}
+
+/// Tracks the outcome of handshakes, reporting errors and retrying failed initiations.
+#[derive(Clone)]
+pub struct HandshakeAttempts {
+    errors: Sender<HandshakeError>,
+    opt_retry: Option<(RetryPolicy, Sender<(Instant, InitiateMessage)>)>,
+    cooldowns: Arc<Mutex<HashMap<SocketAddr, Cooldown>>>,
+}
+
+impl HandshakeAttempts {
+    pub fn new(errors: Sender<HandshakeError>) -> HandshakeAttempts {
+        HandshakeAttempts {
+            errors: errors,
+            opt_retry: None,
+            cooldowns: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Retry failed initiations by sending them to `retries` once their cool down is over.
+    pub fn with_retries(
+        mut self,
+        policy: RetryPolicy,
+        retries: Sender<(Instant, InitiateMessage)>,
+    ) -> HandshakeAttempts {
+        self.opt_retry = Some((policy, retries));
+        self
+    }
+
+    /// Report an error, if the receiver is keeping up.
+    pub fn report(&self, error: HandshakeError) {
+        let _ = self.errors.try_send(error);
+    }
+
+    /// Whether or not an initiation to the given address should wait.
+    pub fn is_cooling_down(&self, addr: &SocketAddr) -> bool {
+        self.lock_cooldowns()
+            .get(addr)
+            .map(|cooldown| cooldown.retry_at > Instant::now())
+            .unwrap_or(false)
+    }
+
+    /// Forget about any previous failures for the given address.
+    pub fn on_success(&self, addr: &SocketAddr) {
+        if self.opt_retry.is_some() {
+            self.lock_cooldowns().remove(addr);
+        }
+    }
+
+    /// Put the address in a cool down, scheduling a retry if it has any left.
+    pub fn on_failure(&self, item: InitiateMessage) {
+        let (policy, retries) = match self.opt_retry {
+            Some((policy, ref retries)) => (policy, retries),
+            None => return,
+        };
+        let now = Instant::now();
+
+        let mut cooldowns = self.lock_cooldowns();
+        // Addresses that have not failed in a while get a fresh start
+        cooldowns.retain(|_, cooldown| cooldown.retry_at + policy.max_backoff() > now);
+
+        let cooldown = cooldowns.entry(*item.address()).or_insert(Cooldown {
+            failures: 0,
+            retry_at: now,
+        });
+        cooldown.failures += 1;
+        cooldown.retry_at = now + policy.backoff(cooldown.failures);
+
+        if cooldown.failures <= policy.max_retries() {
+            let _ = retries.send((cooldown.retry_at, item));
+        }
+    }
+
+    fn lock_cooldowns(&self) -> ::std::sync::MutexGuard<'_, HashMap<SocketAddr, Cooldown>> {
+        self.cooldowns
+            .lock()
+            .expect("bittorrent-protocol_handshake: Poisoned Lock In HandshakeAttempts")
+    }
+}
+
+/// Create loop for sending scheduled retries back to the initiator once they are due.
+///
+/// The loop terminates when `alive` can no longer be upgraded, or any channel is closed.
+pub fn retry_loop<K>(recv: Receiver<(Instant, InitiateMessage)>, mut send: K, alive: Weak<()>)
+where
+    K: HandlerSink<InitiateMessage> + 'static,
+{
+    thread::spawn(move || {
+        let poll_interval = Duration::from_millis(RETRY_POLL_INTERVAL_MILLIS);
+        let mut pending: Vec<(Instant, InitiateMessage)> = Vec::new();
+
+        loop {
+            let now = Instant::now();
+            let timeout = pending
+                .iter()
+                .map(|&(retry_at, _)| retry_at.saturating_duration_since(now))
+                .min()
+                .unwrap_or(poll_interval)
+                .min(poll_interval);
+
+            match recv.recv_timeout(timeout) {
+                Ok(retry) => pending.push(retry),
+                Err(RecvTimeoutError::Timeout) => (),
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+            if alive.upgrade().is_none() {
+                break;
+            }
+
+            let now = Instant::now();
+            let (due, waiting) = pending
+                .drain(..)
+                .partition::<Vec<_>, _>(|&(retry_at, _)| retry_at <= now);
+            pending = waiting;
+
+            for (_, item) in due {
+                if send.send_blocking(item).is_err() {
+                    return;
+                }
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use crossbeam::channel::unbounded;
+
+    use super::HandshakeAttempts;
+    use crate::handshake::{HandshakeError, InitiateMessage, Protocol, RetryPolicy};
+
+    fn initiate() -> InitiateMessage {
+        InitiateMessage::new(
+            Protocol::BitTorrent,
+            [0u8; 20].into(),
+            "1.2.3.4:5".parse().unwrap(),
+        )
+    }
+
+    fn policy() -> RetryPolicy {
+        RetryPolicy::default()
+            .with_max_retries(2)
+            .with_initial_backoff(Duration::from_millis(20))
+            .with_max_backoff(Duration::from_secs(10))
+    }
+
+    #[test]
+    fn positive_no_retry_policy() {
+        let (errors, _) = unbounded();
+        let attempts = HandshakeAttempts::new(errors);
+
+        attempts.on_failure(initiate());
+
+        assert!(!attempts.is_cooling_down(initiate().address()));
+    }
+
+    #[test]
+    fn positive_failure_schedules_retry() {
+        let (errors, _) = unbounded();
+        let (retries, retry_recv) = unbounded();
+        let attempts = HandshakeAttempts::new(errors).with_retries(policy(), retries);
+
+        attempts.on_failure(initiate());
+
+        assert!(attempts.is_cooling_down(initiate().address()));
+        let (_, retry) = retry_recv.try_recv().unwrap();
+        assert_eq!(initiate(), retry);
+    }
+
+    #[test]
+    fn positive_retries_exhausted() {
+        let (errors, _) = unbounded();
+        let (retries, retry_recv) = unbounded();
+        let attempts = HandshakeAttempts::new(errors).with_retries(policy(), retries);
+
+        for _ in 0..4 {
+            attempts.on_failure(initiate());
+        }
+
+        assert_eq!(2, retry_recv.try_iter().count());
+        assert!(attempts.is_cooling_down(initiate().address()));
+    }
+
+    #[test]
+    fn positive_backoff_grows_between_retries() {
+        let (errors, _) = unbounded();
+        let (retries, retry_recv) = unbounded();
+        let attempts = HandshakeAttempts::new(errors).with_retries(policy(), retries);
+
+        attempts.on_failure(initiate());
+        attempts.on_failure(initiate());
+
+        let (first, _) = retry_recv.try_recv().unwrap();
+        let (second, _) = retry_recv.try_recv().unwrap();
+        assert!(second - first >= Duration::from_millis(15));
+    }
+
+    #[test]
+    fn positive_success_clears_cooldown() {
+        let (errors, _) = unbounded();
+        let (retries, _retry_recv) = unbounded();
+        let attempts = HandshakeAttempts::new(errors).with_retries(policy(), retries);
+
+        attempts.on_failure(initiate());
+        attempts.on_success(initiate().address());
+
+        assert!(!attempts.is_cooling_down(initiate().address()));
+    }
+
+    #[test]
+    fn positive_retry_loop_sends_when_due() {
+        let (retries, retry_recv) = unbounded();
+        let (send, recv) = unbounded();
+        let alive = Arc::new(());
+        let (errors, _) = unbounded();
+        let attempts = HandshakeAttempts::new(errors).with_retries(policy(), retries);
+
+        super::retry_loop(retry_recv, send, Arc::downgrade(&alive));
+        attempts.on_failure(initiate());
+
+        assert_eq!(
+            Err(crossbeam::channel::RecvTimeoutError::Timeout),
+            recv.recv_timeout(Duration::from_millis(5))
+        );
+        assert_eq!(Ok(initiate()), recv.recv_timeout(Duration::from_secs(5)));
+    }
+
+    #[test]
+    fn positive_report_error() {
+        let (errors, error_recv) = unbounded();
+        let attempts = HandshakeAttempts::new(errors);
+        let addr = "1.2.3.4:5".parse().unwrap();
+
+        attempts.report(HandshakeError::ConnectTimedOut(addr));
+
+        assert_eq!(
+            Ok(HandshakeError::ConnectTimedOut(addr)),
+            error_recv.try_recv()
+        );
+    }
+}
@@ -1,42 +1,103 @@
-use std::net::SocketAddr;
 use std::io::{Read, Write};
+use std::net::SocketAddr;
+use std::time::Instant;
 
 use crate::util::bt::PeerId;
 
-use crate::handshake::message::bittorrent::framed::FramedHandshake;
-use crate::handshake::message::bittorrent::message::HandshakeMessage;
 use crate::handshake::filter::filters::Filters;
 use crate::handshake::handler;
+use crate::handshake::handler::attempts::HandshakeAttempts;
 use crate::handshake::handler::timer::HandshakeTimer;
 use crate::handshake::handler::HandshakeType;
-use crate::handshake::{CompleteMessage, Extensions, InitiateMessage};
+use crate::handshake::message::bittorrent::framed::FramedHandshake;
+use crate::handshake::message::bittorrent::message::HandshakeMessage;
+use crate::handshake::mse::{negotiate, SecretKeys};
+use crate::handshake::{
+    CompleteMessage, EncryptionPolicy, Extensions, FilteredMessage, HandshakeError,
+    InitiateMessage, MseSocket, TimeoutSocket,
+};
 
 pub fn execute_handshake<S>(
     item: HandshakeType<S>,
-    context: &(Extensions, PeerId, Filters, HandshakeTimer),
-) -> Result<Option<CompleteMessage<S>>, ()>
+    context: &(
+        Extensions,
+        PeerId,
+        Filters,
+        HandshakeTimer,
+        EncryptionPolicy,
+        SecretKeys,
+        HandshakeAttempts,
+    ),
+) -> Result<Option<CompleteMessage<MseSocket<S>>>, ()>
 where
-    S: Read + Write + 'static,
+    S: Read + Write + TimeoutSocket + 'static,
 {
-    let &(ref ext, ref pid, ref filters, ref timer) = context;
+    let &(ref ext, ref pid, ref filters, ref timer, policy, ref keys, ref attempts) = context;
+    let start = Instant::now();
 
-    match item {
+    // Errors from a single peer should not stop the handshaker, so only report them
+    let (result, addr, opt_retry) = match item {
         HandshakeType::Initiate(sock, init_msg) => {
-            initiate_handshake(sock, init_msg, *ext, *pid, filters.clone(), timer.clone())
+            let addr = *init_msg.address();
+            let opt_retry = if init_msg.is_holepunch() {
+                None
+            } else {
+                Some(init_msg.clone())
+            };
+
+            let result = timer
+                .arm(&sock)
+                .map_err(|_| ())
+                .and_then(|_| negotiate::initiate(sock, init_msg.hash(), policy).map_err(|_| ()))
+                .and_then(|sock| initiate_handshake(sock, init_msg, *ext, *pid, filters));
+
+            (result, addr, opt_retry)
         }
         HandshakeType::Complete(sock, addr) => {
-            complete_handshake(sock, addr, *ext, *pid, filters.clone(), timer.clone())
+            let result = timer
+                .arm(&sock)
+                .map_err(|_| ())
+                .and_then(|_| negotiate::complete(sock, keys, policy).map_err(|_| ()))
+                .and_then(|sock| complete_handshake(sock, addr, *ext, *pid, filters));
+
+            (result, addr, None)
+        }
+    };
+
+    match result {
+        Ok(Some(msg)) => {
+            if timer.disarm(msg.socket().get_ref()).is_err() {
+                attempts.report(HandshakeError::HandshakeFailed(addr));
+
+                return Ok(None);
+            }
+            attempts.on_success(&addr);
+
+            Ok(Some(msg))
+        }
+        Ok(None) => Ok(None),
+        Err(_) => {
+            if timer.timed_out(start) {
+                attempts.report(HandshakeError::HandshakeTimedOut(addr));
+            } else {
+                attempts.report(HandshakeError::HandshakeFailed(addr));
+            }
+            if let Some(retry) = opt_retry {
+                attempts.on_failure(retry);
+            }
+
+            Ok(None)
         }
     }
 }
 
+/// Returns `Ok(None)` if the handshake was filtered, or `Err` if the peer failed to handshake.
 fn initiate_handshake<S>(
     sock: S,
     init_msg: InitiateMessage,
     ext: Extensions,
     pid: PeerId,
-    filters: Filters,
-    timer: HandshakeTimer,
+    filters: &Filters,
 ) -> Result<Option<CompleteMessage<S>>, ()>
 where
     S: Read + Write + 'static,
@@ -46,97 +107,84 @@ where
     let (prot, hash, addr) = init_msg.into_parts();
     let handshake_msg = HandshakeMessage::from_parts(prot.clone(), ext, hash, pid);
 
-        framed.send(handshake_msg).map_err(|_| ());
-
-        timer.timeout();
-
-        let composed_future = framed
-            .poll()
-            .map_err(|_| ())
-            .and_then(|opt_msg| opt_msg.ok_or(()).map(|msg| msg))
-            .and_then(|msg|{
-
-                    let (remote_prot, remote_ext, remote_hash, remote_pid) = msg.into_parts();
-                    let socket = framed.into_inner();
-
-                    // Check that it responds with the same hash and protocol, also check our filters
-                    if remote_hash != hash
-                        || remote_prot != prot
-                        || handler::should_filter(
-                        Some(&addr),
-                        Some(&remote_prot),
-                        Some(&remote_ext),
-                        Some(&remote_hash),
-                        Some(&remote_pid),
-                        &filters,
-                    ) {
-                        Ok(None)
-                    } else {
-                        Ok(Some(CompleteMessage::new(
-                            prot,
-                            ext.union(&remote_ext),
-                            hash,
-                            remote_pid,
-                            addr,
-                            socket,
-                        )))
-                    }
-                })
-                .or_else(|_|Ok(None));
-
-    composed_future
+    framed.send(handshake_msg).map_err(|_| ())?;
+
+    let msg = framed.poll().map_err(|_| ())?.ok_or(())?;
+    let (remote_prot, remote_ext, remote_hash, remote_pid) = msg.into_parts();
+    let socket = framed.into_inner();
+
+    // Check that it responds with the same hash and protocol, also check our filters
+    if remote_hash != hash || remote_prot != prot {
+        Err(())
+    } else if handler::should_filter(
+        Some(&addr),
+        Some(&remote_prot),
+        Some(&remote_ext),
+        Some(&remote_hash),
+        Some(&remote_pid),
+        filters,
+    ) {
+        filters.report(FilteredMessage::Initiate(addr, hash, Some(remote_pid)));
+
+        Ok(None)
+    } else {
+        Ok(Some(
+            CompleteMessage::new(prot, ext.union(&remote_ext), hash, remote_pid, addr, socket)
+                .with_remote_extension_bits(remote_ext.into()),
+        ))
+    }
 }
 
+/// Returns `Ok(None)` if the handshake was filtered, or `Err` if the peer failed to handshake.
 fn complete_handshake<S>(
     sock: S,
     addr: SocketAddr,
     ext: Extensions,
     pid: PeerId,
-    filters: Filters,
-    timer: HandshakeTimer,
-) -> Result<Option<CompleteMessage<S>>,()>
+    filters: &Filters,
+) -> Result<Option<CompleteMessage<S>>, ()>
 where
     S: Read + Write + 'static,
 {
     let mut framed = FramedHandshake::new(sock);
 
-    let composed_future = framed
-        .poll()
-        .map_err(|_|{()})
-        .and_then(|opt_msg| opt_msg.ok_or(()).map(|msg| msg))
-        .and_then(move |msg| {
-            let (remote_prot, remote_ext, remote_hash, remote_pid) = msg.into_parts();
-
-            // Check our filters
-            if handler::should_filter(
-                Some(&addr),
-                Some(&remote_prot),
-                Some(&remote_ext),
-                Some(&remote_hash),
-                Some(&remote_pid),
-                &filters,
-            ) {
-                Err(())
-            } else {
-                    let handshake_msg = HandshakeMessage::from_parts(remote_prot.clone(), ext, remote_hash, pid);
-                    framed.send(handshake_msg);
-
-                    timer.timeout();
-
-                    let socket = framed.into_inner();
-                    Ok(Some(CompleteMessage::new(
-                                remote_prot,
-                                ext.union(&remote_ext),
-                                remote_hash,
-                                remote_pid,
-                                addr,
-                                socket,
-                    )))
-            }
-        })
-        .or_else(|_| Ok(None));
-
-    composed_future
+    let msg = framed.poll().map_err(|_| ())?.ok_or(())?;
+    let (remote_prot, remote_ext, remote_hash, remote_pid) = msg.into_parts();
+
+    // Check our filters
+    if handler::should_filter(
+        Some(&addr),
+        Some(&remote_prot),
+        Some(&remote_ext),
+        Some(&remote_hash),
+        Some(&remote_pid),
+        filters,
+    ) {
+        filters.report(FilteredMessage::Complete(
+            addr,
+            Some(remote_hash),
+            Some(remote_pid),
+        ));
+
+        Ok(None)
+    } else {
+        let handshake_msg =
+            HandshakeMessage::from_parts(remote_prot.clone(), ext, remote_hash, pid);
+        framed.send(handshake_msg).map_err(|_| ())?;
+
+        let socket = framed.into_inner();
+        Ok(Some(
+            CompleteMessage::new(
+                remote_prot,
+                ext.union(&remote_ext),
+                remote_hash,
+                remote_pid,
+                addr,
+                socket,
+            )
+            .with_remote_extension_bits(remote_ext.into()),
+        ))
+    }
 }
 
 #[cfg(test)]
@@ -205,7 +253,6 @@ mod tests {
         let init_ext = any_extensions();
         let init_pid = any_other_peer_id();
         let init_filters = Filters::new();
-        let init_timer = any_handshake_timer();
 
         // Wrap in lazy since we can call wait on non sized types...
         let complete_message = future::lazy(|| {
@@ -214,8 +261,7 @@ mod tests {
                 init_message,
                 init_ext,
                 init_pid,
-                init_filters,
-                init_timer,
+                &init_filters,
             )
         })
         .wait()
@@ -269,7 +315,6 @@ mod tests {
         let comp_ext = any_extensions();
         let comp_pid = any_other_peer_id();
         let comp_filters = Filters::new();
-        let comp_timer = any_handshake_timer();
 
         // Wrap in lazy since we can call wait on non sized types...
         let complete_message = future::lazy(|| {
@@ -278,8 +323,7 @@ mod tests {
                 remote_addr,
                 comp_ext,
                 comp_pid,
-                comp_filters,
-                comp_timer,
+                &comp_filters,
             )
         })
         .wait()
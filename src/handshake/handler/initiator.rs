@@ -1,19 +1,41 @@
+use std::io;
+use std::sync::Arc;
+use std::thread;
+
+use crossbeam::channel::Sender;
 
 use crate::handshake::filter::filters::Filters;
 use crate::handshake::handler;
+use crate::handshake::handler::attempts::HandshakeAttempts;
 use crate::handshake::handler::timer::HandshakeTimer;
 use crate::handshake::handler::HandshakeType;
-use crate::handshake::{InitiateMessage, Transport};
-
-/// Handle the initiation of connections, which are returned as a HandshakeType.
+use crate::handshake::{
+    ConnectionLimits, FilteredMessage, HandshakeError, InitiateMessage, Transport,
+};
+
+/// Everything the initiator handler needs, along with where connections are sent on to.
+pub type InitiatorContext<T> = (
+    Arc<T>,
+    Filters,
+    HandshakeTimer,
+    HandshakeAttempts,
+    ConnectionLimits,
+    Sender<HandshakeType<<T as Transport>::Socket>>,
+);
+
+/// Handle the initiation of connections, which are sent on as a HandshakeType.
+///
+/// Connections are made on threads of their own, as many at once as there are half open
+/// connections allowed, so the handler itself never yields a result.
 pub fn initiator_handler<T>(
     item: InitiateMessage,
-    context: &(T, Filters, HandshakeTimer),
-) -> Result<Option<HandshakeType<T::Socket>>,()>
+    context: &InitiatorContext<T>,
+) -> Result<Option<HandshakeType<T::Socket>>, ()>
 where
-    T: Transport,
+    T: Transport + Send + Sync + 'static,
+    T::Socket: Send,
 {
-    let &(ref transport, ref filters,  ref timer) = context;
+    let &(ref transport, ref filters, ref timer, ref attempts, ref limits, ref send) = context;
 
     if handler::should_filter(
         Some(item.address()),
@@ -23,16 +45,47 @@ where
         None,
         filters,
     ) {
-        Ok(None)
-
+        filters.report(FilteredMessage::Initiate(
+            *item.address(),
+            *item.hash(),
+            None,
+        ));
+    } else if !item.is_holepunch() && attempts.is_cooling_down(item.address()) {
+        attempts.report(HandshakeError::CoolingDown(*item.address()));
+    } else if !limits.try_start_handshake() {
+        attempts.report(HandshakeError::TooManyPeers(*item.address()));
     } else {
-        let res_connect = transport
-            .connect(item.address());
-
-       res_connect
-           .map(|socket| Some(HandshakeType::Initiate(socket, item)))
-           .or_else(|_| Ok(None))
+        let permit = limits.acquire_half_open();
+        let (transport, timer, attempts) = (transport.clone(), timer.clone(), attempts.clone());
+        let (limits, send) = (limits.clone(), send.clone());
+
+        thread::spawn(move || {
+            let result = transport.connect_timeout(item.address(), timer.duration());
+            // No longer half open, whether the peer accepted or not
+            drop(permit);
+
+            match result {
+                Ok(socket) => {
+                    if send.send(HandshakeType::Initiate(socket, item)).is_err() {
+                        limits.finish_handshake(false);
+                    }
+                }
+                Err(err) => {
+                    if err.kind() == io::ErrorKind::TimedOut {
+                        attempts.report(HandshakeError::ConnectTimedOut(*item.address()));
+                    } else {
+                        attempts.report(HandshakeError::ConnectFailed(*item.address()));
+                    }
+                    limits.finish_handshake(false);
+                    if !item.is_holepunch() {
+                        attempts.on_failure(item);
+                    }
+                }
+            }
+        });
     }
+
+    Ok(None)
 }
 
 #[cfg(test)]
@@ -92,7 +145,7 @@ mod tests {
     #[test]
     fn positive_passes_filter() {
         let core = Core::new().unwrap();
-        let timer = HandshakeTimer::new( Duration::from_millis(1000));
+        let timer = HandshakeTimer::new(Duration::from_millis(1000));
 
         let filters = Filters::new();
         filters.add_filter(BlockAddrFilter::new("2.3.4.5:6".parse().unwrap()));
@@ -152,7 +205,7 @@ mod tests {
     #[test]
     fn positive_fails_filter() {
         let core = Core::new().unwrap();
-        let timer = HandshakeTimer::new( Duration::from_millis(1000));
+        let timer = HandshakeTimer::new(Duration::from_millis(1000));
 
         let filters = Filters::new();
         filters.add_filter(BlockProtocolFilter::new(Protocol::Custom(vec![1, 2, 3, 4])));
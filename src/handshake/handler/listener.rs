@@ -2,7 +2,10 @@ use std::net::SocketAddr;
 
 use crate::handshake::filter::filters::Filters;
 use crate::handshake::handler;
+use crate::handshake::handler::attempts::HandshakeAttempts;
 use crate::handshake::handler::HandshakeType;
+use crate::handshake::{ConnectionLimits, FilteredMessage, HandshakeError};
+use crate::util::net;
 use std::io;
 
 pub struct ListenerHandler<S> {
@@ -10,10 +13,22 @@ pub struct ListenerHandler<S> {
 }
 
 impl<S> ListenerHandler<S> {
-    pub fn new(item: (S, SocketAddr), context: &Filters) -> ListenerHandler<S> {
-        let (sock, addr) = item;
+    pub fn new(
+        item: (S, SocketAddr),
+        context: &(Filters, HandshakeAttempts, ConnectionLimits),
+    ) -> ListenerHandler<S> {
+        let (filters, attempts, limits) = context;
+        // Dual stack listeners see ipv4 peers as ipv4 mapped ipv6 addresses
+        let (sock, addr) = (item.0, net::unmap_v4(item.1));
+
+        let opt_item = if handler::should_filter(Some(&addr), None, None, None, None, filters) {
+            filters.report(FilteredMessage::Complete(addr, None, None));
+
+            None
+        } else if !limits.try_start_handshake() {
+            // Dropping the socket closes the connection
+            attempts.report(HandshakeError::TooManyPeers(addr));
 
-        let opt_item = if handler::should_filter(Some(&addr), None, None, None, None, context) {
             None
         } else {
             Some(HandshakeType::Complete(sock, addr))
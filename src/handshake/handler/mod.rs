@@ -1,10 +1,15 @@
+use std::io::{self, Error, ErrorKind};
 use std::net::SocketAddr;
 use crossbeam::channel::{Sender};
+use futures::channel::mpsc;
+use futures::executor;
+use futures::sink::SinkExt;
 use crate::handshake::filter::filters::Filters;
 use crate::handshake::{Extensions, FilterDecision, InitiateMessage, Protocol};
 use crate::util::bt::{InfoHash, PeerId};
 use crate::handshake::stream::Stream;
 
+pub mod attempts;
 pub mod handshaker;
 pub mod initiator;
 pub mod listener;
@@ -15,6 +20,30 @@ pub enum HandshakeType<S> {
     Complete(S, SocketAddr),
 }
 
+/// Sink that a handler loop forwards its results to, blocking while it is full.
+pub trait HandlerSink<R>: Send {
+    fn send_blocking(&mut self, item: R) -> io::Result<()>;
+}
+
+impl<R> HandlerSink<R> for Sender<R>
+where
+    R: Send,
+{
+    fn send_blocking(&mut self, item: R) -> io::Result<()> {
+        Sender::send(self, item).map_err(|_| Error::new(ErrorKind::BrokenPipe, "Receiver dropped"))
+    }
+}
+
+impl<R> HandlerSink<R> for mpsc::Sender<R>
+where
+    R: Send,
+{
+    fn send_blocking(&mut self, item: R) -> io::Result<()> {
+        executor::block_on(self.send(item))
+            .map_err(|_| Error::new(ErrorKind::BrokenPipe, "Receiver dropped"))
+    }
+}
+
 enum LoopError {
     Terminate,
     Recoverable,
@@ -23,9 +52,10 @@ enum LoopError {
 /// Create loop for feeding the handler with the items coming from the stream, and forwarding the result to the sink.
 ///
 /// If the stream is used up, or an error is propogated from any of the elements, the loop will terminate.
-pub fn loop_handler<M, C, H, R>(mut stream:M, context: C, mut handler: H, sink: Sender<R>)
+pub fn loop_handler<M, C, H, R, K>(mut stream:M, context: C, mut handler: H, mut sink: K)
 where
     M: Stream + 'static + Send,
+    K: HandlerSink<R> + 'static,
     C: 'static + Send,
     H: FnMut(M::Item, &C) -> Result<Option<R>,()> + 'static + Send ,
     R: 'static + Send ,
@@ -51,7 +81,7 @@ where
                             })
                 })
                 .and_then(|result| {
-                    sink.send(result)
+                    sink.send_blocking(result)
                         .map_err(|_| LoopError::Terminate)
                 });
 
@@ -90,6 +120,15 @@ pub fn should_filter(
             pid_filter = pid_filter.choose(ref_filter.on_pid(pid));
         }
     });
+    filters.access_shared_filter(|opt_filter| {
+        if let Some(ref_filter) = opt_filter {
+            addr_filter = addr_filter.choose(ref_filter.on_addr(addr));
+            prot_filter = prot_filter.choose(ref_filter.on_prot(prot));
+            ext_filter = ext_filter.choose(ref_filter.on_ext(ext));
+            hash_filter = hash_filter.choose(ref_filter.on_hash(hash));
+            pid_filter = pid_filter.choose(ref_filter.on_pid(pid));
+        }
+    });
 
     // Choose across the results of individual fields
     addr_filter
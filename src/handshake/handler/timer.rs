@@ -1,4 +1,7 @@
-use std::time::Duration;
+use std::io;
+use std::time::{Duration, Instant};
+
+use crate::handshake::TimeoutSocket;
 
 #[derive(Clone)]
 pub struct HandshakeTimer {
@@ -12,25 +15,80 @@ impl HandshakeTimer {
         }
     }
 
-    pub fn timeout(&self) {
-        std::thread::sleep(self.duration);
+    pub fn duration(&self) -> Duration {
+        self.duration
+    }
+
+    /// Bound each read and write on the socket by our duration.
+    pub fn arm<S>(&self, sock: &S) -> io::Result<()>
+    where
+        S: TimeoutSocket,
+    {
+        sock.set_timeout(Some(self.duration))
+    }
+
+    /// Remove the bound, before handing the socket off.
+    pub fn disarm<S>(&self, sock: &S) -> io::Result<()>
+    where
+        S: TimeoutSocket,
+    {
+        sock.set_timeout(None)
+    }
+
+    /// Whether or not our duration has passed since `start`.
+    pub fn timed_out(&self, start: Instant) -> bool {
+        start.elapsed() >= self.duration
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use std::io::{Read, Write};
+    use std::net::{TcpListener, TcpStream};
     use std::time::Duration;
 
     use super::HandshakeTimer;
 
+    fn tcp_pair() -> (TcpStream, TcpStream) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let ours = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (theirs, _) = listener.accept().unwrap();
+
+        (ours, theirs)
+    }
+
     #[test]
     fn positive_finish_before_timeout() {
         let timer = HandshakeTimer::new(Duration::from_millis(50));
+        let (mut ours, mut theirs) = tcp_pair();
+
+        timer.arm(&ours).unwrap();
+        theirs.write_all(&[1]).unwrap();
+
+        let mut buf = [0u8; 1];
+        ours.read_exact(&mut buf).unwrap();
     }
 
     #[test]
     #[should_panic]
     fn negative_finish_after_timeout() {
         let timer = HandshakeTimer::new(Duration::from_millis(50));
+        let (mut ours, _theirs) = tcp_pair();
+
+        timer.arm(&ours).unwrap();
+
+        let mut buf = [0u8; 1];
+        ours.read_exact(&mut buf).unwrap();
+    }
+
+    #[test]
+    fn positive_disarm_blocks_again() {
+        let timer = HandshakeTimer::new(Duration::from_millis(50));
+        let (ours, _theirs) = tcp_pair();
+
+        timer.arm(&ours).unwrap();
+        timer.disarm(&ours).unwrap();
+
+        assert_eq!(None, ours.read_timeout().unwrap());
     }
 }
@@ -0,0 +1,218 @@
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+
+const DEFAULT_MAX_HALF_OPEN_CONNECTIONS: usize = 8;
+
+struct LimitState {
+    max_peers: Option<usize>,
+    max_half_open: usize,
+    // Peers last reported by the owner, plus handshakes completed since
+    connected: usize,
+    handshaking: usize,
+    half_open: usize,
+}
+
+/// Limits on the connections of a `HandshakerManager`, adjustable while it runs.
+///
+/// Handshakes count towards `max_global_peers` while they are in progress, and once they
+/// complete until the next `set_connected_peers`, which should count the peers that were
+/// kept. Over the limit, peers connecting to us are closed right away and handshakes we
+/// initiate are not attempted, both are reported as `HandshakeError::TooManyPeers`.
+#[derive(Clone)]
+pub struct ConnectionLimits {
+    inner: Arc<(Mutex<LimitState>, Condvar)>,
+}
+
+impl ConnectionLimits {
+    /// Create new `ConnectionLimits` with no limit on peers.
+    pub fn new() -> ConnectionLimits {
+        ConnectionLimits {
+            inner: Arc::new((
+                Mutex::new(LimitState {
+                    max_peers: None,
+                    max_half_open: DEFAULT_MAX_HALF_OPEN_CONNECTIONS,
+                    connected: 0,
+                    handshaking: 0,
+                    half_open: 0,
+                }),
+                Condvar::new(),
+            )),
+        }
+    }
+
+    /// Max number of peers connected or handshaking, `None` for no limit.
+    pub fn set_max_global_peers(&self, opt_max: Option<usize>) {
+        self.lock_state().max_peers = opt_max;
+    }
+
+    /// Retrieve the max number of peers connected or handshaking.
+    pub fn max_global_peers(&self) -> Option<usize> {
+        self.lock_state().max_peers
+    }
+
+    /// Max number of outgoing connections waiting on the peer to accept them, at least one.
+    ///
+    /// Defaults to 8, which keeps operating systems that limit these connections and the
+    /// NAT tables of routers from filling up.
+    pub fn set_max_half_open_connections(&self, max: usize) {
+        self.lock_state().max_half_open = max.max(1);
+
+        self.inner.1.notify_all();
+    }
+
+    /// Retrieve the max number of outgoing connections waiting on the peer to accept them.
+    pub fn max_half_open_connections(&self) -> usize {
+        self.lock_state().max_half_open
+    }
+
+    /// Number of peers kept from completed handshakes.
+    pub fn set_connected_peers(&self, count: usize) {
+        self.lock_state().connected = count;
+    }
+
+    /// Number of peers counted as connected, see `set_connected_peers`.
+    pub fn connected_peers(&self) -> usize {
+        self.lock_state().connected
+    }
+
+    /// Number of handshakes in progress.
+    pub fn handshaking_peers(&self) -> usize {
+        self.lock_state().handshaking
+    }
+
+    /// Number of outgoing connections waiting on the peer to accept them.
+    pub fn half_open_connections(&self) -> usize {
+        self.lock_state().half_open
+    }
+
+    /// Start a handshake if it fits within `max_global_peers`.
+    pub(crate) fn try_start_handshake(&self) -> bool {
+        let mut state = self.lock_state();
+
+        match state.max_peers {
+            Some(max) if state.connected + state.handshaking >= max => false,
+            _ => {
+                state.handshaking += 1;
+
+                true
+            }
+        }
+    }
+
+    /// Finish a handshake started with `try_start_handshake`.
+    pub(crate) fn finish_handshake(&self, completed: bool) {
+        let mut state = self.lock_state();
+
+        state.handshaking -= 1;
+        if completed {
+            state.connected += 1;
+        }
+    }
+
+    /// Wait until another outgoing connection fits within `max_half_open_connections`.
+    pub(crate) fn acquire_half_open(&self) -> HalfOpenPermit {
+        let mut state = self.lock_state();
+        while state.half_open >= state.max_half_open {
+            state = self
+                .inner
+                .1
+                .wait(state)
+                .expect("bittorrent-protocol_handshake: Poisoned Lock In ConnectionLimits");
+        }
+        state.half_open += 1;
+
+        HalfOpenPermit {
+            limits: self.clone(),
+        }
+    }
+
+    fn lock_state(&self) -> MutexGuard<'_, LimitState> {
+        self.inner
+            .0
+            .lock()
+            .expect("bittorrent-protocol_handshake: Poisoned Lock In ConnectionLimits")
+    }
+}
+
+impl Default for ConnectionLimits {
+    fn default() -> ConnectionLimits {
+        ConnectionLimits::new()
+    }
+}
+
+/// Outgoing connection counted as half open until the permit is dropped.
+pub(crate) struct HalfOpenPermit {
+    limits: ConnectionLimits,
+}
+
+impl Drop for HalfOpenPermit {
+    fn drop(&mut self) {
+        self.limits.lock_state().half_open -= 1;
+
+        self.limits.inner.1.notify_one();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc;
+    use std::thread;
+    use std::time::Duration;
+
+    use super::ConnectionLimits;
+
+    #[test]
+    fn positive_handshakes_count_until_reported() {
+        let limits = ConnectionLimits::new();
+        limits.set_max_global_peers(Some(2));
+
+        assert!(limits.try_start_handshake());
+        assert!(limits.try_start_handshake());
+        assert!(!limits.try_start_handshake());
+
+        limits.finish_handshake(true);
+        limits.finish_handshake(false);
+        assert_eq!(1, limits.connected_peers());
+        assert!(limits.try_start_handshake());
+        assert!(!limits.try_start_handshake());
+
+        // Owner did not keep the completed peer
+        limits.set_connected_peers(0);
+        assert!(limits.try_start_handshake());
+    }
+
+    #[test]
+    fn positive_no_max_global_peers() {
+        let limits = ConnectionLimits::new();
+
+        for _ in 0..100 {
+            assert!(limits.try_start_handshake());
+        }
+        assert_eq!(100, limits.handshaking_peers());
+    }
+
+    #[test]
+    fn positive_half_open_waits_for_permit() {
+        let limits = ConnectionLimits::new();
+        limits.set_max_half_open_connections(1);
+        let permit = limits.acquire_half_open();
+
+        let (send, recv) = mpsc::channel();
+        let waiting = limits.clone();
+        thread::spawn(move || {
+            let _permit = waiting.acquire_half_open();
+            send.send(()).unwrap();
+        });
+        assert!(recv.recv_timeout(Duration::from_millis(100)).is_err());
+
+        drop(permit);
+        recv.recv_timeout(Duration::from_secs(5)).unwrap();
+    }
+
+    #[test]
+    fn positive_max_half_open_at_least_one() {
+        let limits = ConnectionLimits::new();
+        limits.set_max_half_open_connections(0);
+
+        assert_eq!(1, limits.max_half_open_connections());
+    }
+}
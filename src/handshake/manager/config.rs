@@ -4,18 +4,26 @@ use std::time::Duration;
 const DEFAULT_HANDSHAKE_BUFFER_SIZE: usize = 1000;
 const DEFAULT_WAIT_BUFFER_SIZE: usize = 10;
 const DEFAULT_DONE_BUFFER_SIZE: usize = 10;
+const DEFAULT_FILTERED_BUFFER_SIZE: usize = 100;
+const DEFAULT_ERROR_BUFFER_SIZE: usize = 100;
 
 /// Once we get parallel handshake support (requires
 /// mpmc future channel support, we can bump this up).
 const DEFAULT_HANDSHAKE_TIMEOUT_MILLIS: u64 = 1000;
 const DEFAULT_HANDSHAKE_CONNECT_TIMEOUT_MILLIS: u64 = 1000;
 
+const DEFAULT_MAX_RETRIES: usize = 3;
+const DEFAULT_INITIAL_BACKOFF_MILLIS: u64 = 5 * 1000;
+const DEFAULT_MAX_BACKOFF_MILLIS: u64 = 5 * 60 * 1000;
+
 /// Configures the internals of a `Handshaker`.
 #[derive(PartialEq, Eq, Debug, Copy, Clone)]
 pub struct HandshakerConfig {
     sink_buffer_size: usize,
     wait_buffer_size: usize,
     done_buffer_size: usize,
+    filtered_buffer_size: usize,
+    error_buffer_size: usize,
     handshake_timeout: Duration,
     connect_timeout: Duration,
 }
@@ -42,6 +50,20 @@ impl HandshakerConfig {
         self
     }
 
+    /// Sets the buffer size that `HandshakeStream` uses internally
+    /// to store handshakes dropped by filters before they are yielded.
+    pub fn with_filtered_buffer_size(mut self, size: usize) -> HandshakerConfig {
+        self.filtered_buffer_size = size;
+        self
+    }
+
+    /// Sets the buffer size that `HandshakeStream` uses internally
+    /// to store handshake errors before they are yielded.
+    pub fn with_error_buffer_size(mut self, size: usize) -> HandshakerConfig {
+        self.error_buffer_size = size;
+        self
+    }
+
     /// Sets the handshake timeout that `Handshaker` uses to
     /// make sure peers dont take too long to respond to us.
     pub fn with_handshake_timeout(mut self, timeout: Duration) -> HandshakerConfig {
@@ -72,6 +94,16 @@ impl HandshakerConfig {
         self.done_buffer_size
     }
 
+    /// Gets the filtered buffer size.
+    pub fn filtered_buffer_size(&self) -> usize {
+        self.filtered_buffer_size
+    }
+
+    /// Gets the error buffer size.
+    pub fn error_buffer_size(&self) -> usize {
+        self.error_buffer_size
+    }
+
     /// Gets the handshake timeout.
     pub fn handshake_timeout(&self) -> Duration {
         self.handshake_timeout
@@ -89,8 +121,107 @@ impl Default for HandshakerConfig {
             sink_buffer_size: DEFAULT_HANDSHAKE_BUFFER_SIZE,
             wait_buffer_size: DEFAULT_WAIT_BUFFER_SIZE,
             done_buffer_size: DEFAULT_DONE_BUFFER_SIZE,
+            filtered_buffer_size: DEFAULT_FILTERED_BUFFER_SIZE,
+            error_buffer_size: DEFAULT_ERROR_BUFFER_SIZE,
             handshake_timeout: Duration::from_millis(DEFAULT_HANDSHAKE_TIMEOUT_MILLIS),
             connect_timeout: Duration::from_millis(DEFAULT_HANDSHAKE_CONNECT_TIMEOUT_MILLIS),
         }
     }
 }
+
+//----------------------------------------------------------------------------------//
+
+/// Configures how failed outgoing handshakes are retried.
+///
+/// Each failure puts the address in a cool down, doubling from the initial backoff up
+/// to the max backoff; handshakes to an address that is cooling down are not attempted.
+#[derive(PartialEq, Eq, Debug, Copy, Clone)]
+pub struct RetryPolicy {
+    max_retries: usize,
+    initial_backoff: Duration,
+    max_backoff: Duration,
+}
+
+impl RetryPolicy {
+    /// Sets the number of times a failed handshake is retried.
+    pub fn with_max_retries(mut self, retries: usize) -> RetryPolicy {
+        self.max_retries = retries;
+        self
+    }
+
+    /// Sets the cool down after the first failure.
+    pub fn with_initial_backoff(mut self, backoff: Duration) -> RetryPolicy {
+        self.initial_backoff = backoff;
+        self
+    }
+
+    /// Sets the longest cool down, no matter how many times the address failed.
+    pub fn with_max_backoff(mut self, backoff: Duration) -> RetryPolicy {
+        self.max_backoff = backoff;
+        self
+    }
+
+    /// Gets the max retries.
+    pub fn max_retries(&self) -> usize {
+        self.max_retries
+    }
+
+    /// Gets the initial backoff.
+    pub fn initial_backoff(&self) -> Duration {
+        self.initial_backoff
+    }
+
+    /// Gets the max backoff.
+    pub fn max_backoff(&self) -> Duration {
+        self.max_backoff
+    }
+
+    /// Cool down for an address that has failed the given number of times in a row.
+    pub fn backoff(&self, failures: usize) -> Duration {
+        let doublings = failures.saturating_sub(1).min(u32::MAX as usize) as u32;
+        let factor = 2u32.checked_pow(doublings).unwrap_or(u32::MAX);
+
+        self.initial_backoff
+            .checked_mul(factor)
+            .map(|backoff| backoff.min(self.max_backoff))
+            .unwrap_or(self.max_backoff)
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> RetryPolicy {
+        RetryPolicy {
+            max_retries: DEFAULT_MAX_RETRIES,
+            initial_backoff: Duration::from_millis(DEFAULT_INITIAL_BACKOFF_MILLIS),
+            max_backoff: Duration::from_millis(DEFAULT_MAX_BACKOFF_MILLIS),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::RetryPolicy;
+
+    #[test]
+    fn positive_backoff_doubles() {
+        let policy = RetryPolicy::default()
+            .with_initial_backoff(Duration::from_secs(1))
+            .with_max_backoff(Duration::from_secs(60));
+
+        assert_eq!(Duration::from_secs(1), policy.backoff(1));
+        assert_eq!(Duration::from_secs(2), policy.backoff(2));
+        assert_eq!(Duration::from_secs(8), policy.backoff(4));
+    }
+
+    #[test]
+    fn positive_backoff_capped() {
+        let policy = RetryPolicy::default()
+            .with_initial_backoff(Duration::from_secs(1))
+            .with_max_backoff(Duration::from_secs(60));
+
+        assert_eq!(Duration::from_secs(60), policy.backoff(7));
+        assert_eq!(Duration::from_secs(60), policy.backoff(1000));
+    }
+}
@@ -2,40 +2,59 @@
 use std::cmp;
 use std::io;
 use std::io::{Read, Write};
+use std::pin::Pin;
+use std::sync::Arc;
 use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
+use std::task::{Context, Poll};
 use std::time::Duration;
 use rand::{self, Rng};
 
-use crossbeam::channel::{bounded, Receiver, SendError, Sender};
-use crate::util::bt::PeerId;
+use crossbeam::channel::{bounded, Receiver, SendError};
+use futures::channel::mpsc;
+use futures::executor;
+use futures::future;
+use futures::sink::Sink;
+use futures::stream::{Stream, StreamExt};
+use crate::util::bt::{InfoHash, PeerId};
 use crate::util::convert;
 
 use crate::handshake::discovery::DiscoveryInfo;
+use crate::handshake::error::HandshakeError;
+use crate::handshake::limits::ConnectionLimits;
 use crate::handshake::local_addr::LocalAddr;
-use crate::handshake::transport::Transport;
+use crate::handshake::transport::{TimeoutSocket, Transport};
 
 use crate::handshake::message::complete::CompleteMessage;
-use crate::handshake::message::extensions::Extensions;
+use crate::handshake::message::extensions::{ExtensionBits, Extensions};
+use crate::handshake::message::filtered::FilteredMessage;
 use crate::handshake::message::initiate::InitiateMessage;
 
+use crate::handshake::mse::{EncryptionPolicy, MseSocket, SecretKeys};
+
 use crate::handshake::filter::filters::Filters;
 use crate::handshake::filter::{HandshakeFilter, HandshakeFilters};
 
 use crate::handshake::handler;
+use crate::handshake::handler::attempts::{self, HandshakeAttempts};
 use crate::handshake::handler::listener::ListenerHandler;
 use crate::handshake::handler::timer::HandshakeTimer;
 use crate::handshake::handler::{handshaker, initiator, HandshakeType};
 
 pub mod config;
-use self::config::HandshakerConfig;
+use self::config::{HandshakerConfig, RetryPolicy};
 
 /// Build configuration for `Handshaker` object creation.
-#[derive(Copy, Clone)]
+#[derive(Clone)]
 pub struct HandshakerManagerBuilder {
     bind: SocketAddr,
     port: u16,
     pid: PeerId,
     ext: Extensions,
+    policy: EncryptionPolicy,
+    opt_filter: Option<Arc<dyn HandshakeFilter + Send + Sync>>,
+    opt_retry: Option<RetryPolicy>,
+    opt_max_peers: Option<usize>,
+    opt_max_half_open: Option<usize>,
     config: HandshakerConfig,
 }
 
@@ -52,16 +71,23 @@ impl HandshakerManagerBuilder {
 
         HandshakerManagerBuilder {
             bind: default_sock_addr,
-            port: default_v4_port,
+            port: 0,
             pid: default_peer_id,
             ext: Extensions::new(),
+            policy: EncryptionPolicy::default(),
+            opt_filter: None,
+            opt_retry: None,
+            opt_max_peers: None,
+            opt_max_half_open: None,
             config: HandshakerConfig::default(),
         }
     }
 
     /// Address that the host will listen on.
     ///
-    /// Defaults to IN_ADDR_ANY using port 0 (any free port).
+    /// Defaults to IN_ADDR_ANY using port 0 (any free port). Binding to IN6_ADDR_ANY
+    /// (`[::]`) accepts both ipv4 and ipv6 peers on dual stack hosts, in which case ipv4
+    /// peers are still reported with their ipv4 address.
     pub fn with_bind_addr(&mut self, addr: SocketAddr) -> &mut HandshakerManagerBuilder {
         self.bind = addr;
 
@@ -89,6 +115,15 @@ impl HandshakerManagerBuilder {
         self
     }
 
+    /// Peer id made up of the given client prefix followed by random characters.
+    ///
+    /// For example, `-XX1234-` for version 1.2.3.4 of a client with the code `XX`.
+    pub fn with_peer_id_prefix(&mut self, prefix: &[u8; 8]) -> &mut HandshakerManagerBuilder {
+        self.pid = PeerId::random_with_prefix(prefix);
+
+        self
+    }
+
     /// Extensions supported by our client, advertised to the peer when handshaking.
     pub fn with_extensions(&mut self, ext: Extensions) -> &mut HandshakerManagerBuilder {
         self.ext = ext;
@@ -96,6 +131,90 @@ impl HandshakerManagerBuilder {
         self
     }
 
+    /// Reserved bits advertised to the peer when handshaking.
+    ///
+    /// Bits without a named `Extension` are sent as given.
+    pub fn with_extension_bits(&mut self, bits: ExtensionBits) -> &mut HandshakerManagerBuilder {
+        self.ext = bits.into();
+
+        self
+    }
+
+    /// Whether or not handshakes are encrypted with MSE.
+    ///
+    /// Defaults to `EncryptionPolicy::Disabled`. Peers connecting to us with MSE can only
+    /// find torrents whose info hash was added with `HandshakerManagerSink::add_info_hash`.
+    pub fn with_encryption_policy(
+        &mut self,
+        policy: EncryptionPolicy,
+    ) -> &mut HandshakerManagerBuilder {
+        self.policy = policy;
+
+        self
+    }
+
+    /// Filter checked for both incoming and outgoing handshakes.
+    ///
+    /// It can be replaced after building with `HandshakerManagerSink::set_filter`, and is
+    /// checked along with any filters added through `HandshakeFilters`.
+    pub fn with_filter(
+        &mut self,
+        filter: Arc<dyn HandshakeFilter + Send + Sync>,
+    ) -> &mut HandshakerManagerBuilder {
+        self.opt_filter = Some(filter);
+
+        self
+    }
+
+    /// How long to wait for a connection to a peer we are initiating a handshake with.
+    ///
+    /// Shorthand for `HandshakerConfig::with_connect_timeout`.
+    pub fn with_connect_timeout(&mut self, timeout: Duration) -> &mut HandshakerManagerBuilder {
+        self.config = self.config.with_connect_timeout(timeout);
+
+        self
+    }
+
+    /// How long to wait on each read or write while a peer is handshaking with us.
+    ///
+    /// Shorthand for `HandshakerConfig::with_handshake_timeout`.
+    pub fn with_handshake_timeout(&mut self, timeout: Duration) -> &mut HandshakerManagerBuilder {
+        self.config = self.config.with_handshake_timeout(timeout);
+
+        self
+    }
+
+    /// Retry failed outgoing handshakes, backing off from addresses that keep failing.
+    ///
+    /// Defaults to not retrying, failures are still reported through `poll_error`.
+    pub fn with_retry_policy(&mut self, policy: RetryPolicy) -> &mut HandshakerManagerBuilder {
+        self.opt_retry = Some(policy);
+
+        self
+    }
+
+    /// Max number of peers connected or handshaking, see `ConnectionLimits`.
+    ///
+    /// Defaults to no limit. It can be changed after building through
+    /// `HandshakerManagerSink::connection_limits`.
+    pub fn with_max_global_peers(
+        &mut self,
+        opt_max: Option<usize>,
+    ) -> &mut HandshakerManagerBuilder {
+        self.opt_max_peers = opt_max;
+
+        self
+    }
+
+    /// Max number of outgoing connections waiting on the peer to accept them.
+    ///
+    /// Defaults to 8, see `ConnectionLimits::set_max_half_open_connections`.
+    pub fn with_max_half_open_connections(&mut self, max: usize) -> &mut HandshakerManagerBuilder {
+        self.opt_max_half_open = Some(max);
+
+        self
+    }
+
     /// Configuration that will be used to alter the internal behavior of handshaking.
     ///
     /// This will typically not need to be set unless you know what you are doing.
@@ -106,9 +225,9 @@ impl HandshakerManagerBuilder {
     }
 
     /// Build a `Handshaker` over the given `Transport` with a `Remote` instance.
-    pub fn build<T>(&self, transport: T) -> io::Result<HandshakerManager<T::Socket>>
+    pub fn build<T>(&self, transport: T) -> io::Result<HandshakerManager<MseSocket<T::Socket>>>
         where
-            T: Transport + 'static + Send ,
+            T: Transport + 'static + Send + Sync,
             <T as Transport>::Socket: Send,
     {
         HandshakerManager::with_builder(self, transport)
@@ -143,16 +262,16 @@ impl<S> DiscoveryInfo for HandshakerManager<S> {
     }
 }
 
-impl<S> HandshakerManager<S>
+impl<S> HandshakerManager<MseSocket<S>>
     where
-        S: Read + Write + 'static + Send ,
+        S: Read + Write + TimeoutSocket + 'static + Send,
 {
     fn with_builder<T>(
         builder: &HandshakerManagerBuilder,
         transport: T,
-    ) -> io::Result<HandshakerManager<T::Socket>>
+    ) -> io::Result<HandshakerManager<MseSocket<S>>>
         where
-            T: Transport<Socket = S> + 'static + Send,
+            T: Transport<Socket = S> + 'static + Send + Sync,
     {
         let listener = transport.listen(&builder.bind)?;
 
@@ -164,36 +283,86 @@ impl<S> HandshakerManager<S>
         };
 
         let config = builder.config;
-        let (addr_send, addr_recv) = bounded(config.sink_buffer_size());
+        // Both ends that are handed out are async aware, the handlers in between run on threads
+        let (addr_send, addr_recv) = mpsc::channel(config.sink_buffer_size());
         let (hand_send, hand_recv) = bounded(config.wait_buffer_size());
-        let (sock_send, sock_recv) = bounded(config.done_buffer_size());
-
-        let filters = Filters::new();
+        let (sock_send, sock_recv) = mpsc::channel(config.done_buffer_size());
+        let (filtered_send, filtered_recv) = bounded(config.filtered_buffer_size());
+        let (error_send, error_recv) = bounded(config.error_buffer_size());
+
+        let filters = Filters::new().with_events(filtered_send);
+        filters.set_shared_filter(builder.opt_filter.clone());
+        let keys = SecretKeys::new();
+        let limits = ConnectionLimits::new();
+        limits.set_max_global_peers(builder.opt_max_peers);
+        if let Some(max) = builder.opt_max_half_open {
+            limits.set_max_half_open_connections(max);
+        }
         let (handshake_timer, initiate_timer) =
             configured_handshake_timers(config.handshake_timeout(), config.connect_timeout());
 
+        // Retries are fed back to the initiator for as long as a sink is around
+        let alive = Arc::new(());
+        let mut handshake_attempts = HandshakeAttempts::new(error_send);
+        if let Some(policy) = builder.opt_retry {
+            let (retry_send, retry_recv) = bounded(config.sink_buffer_size());
+
+            attempts::retry_loop(retry_recv, addr_send.clone(), Arc::downgrade(&alive));
+            handshake_attempts = handshake_attempts.with_retries(policy, retry_send);
+        }
+
         // Hook up our pipeline of handlers which will take some connection info, process it, and forward it
+        // Connections are sent on to the handshaker from the threads making them
         handler::loop_handler(
             addr_recv,
-            (transport, filters.clone(), initiate_timer),
+            (
+                Arc::new(transport),
+                filters.clone(),
+                initiate_timer,
+                handshake_attempts.clone(),
+                limits.clone(),
+                hand_send.clone(),
+            ),
             initiator::initiator_handler,
             hand_send.clone(),
         );
         handler::loop_handler(
             listener,
-            filters.clone(),
+            (filters.clone(), handshake_attempts.clone(), limits.clone()),
             |item, context| { ListenerHandler::new(item, context).poll() },
             hand_send,
         );
+        let handshake_limits = limits.clone();
         handler::loop_handler(
             hand_recv,
-            (builder.ext, builder.pid, filters.clone(), handshake_timer),
-            handshaker::execute_handshake,
+            (
+                builder.ext,
+                builder.pid,
+                filters.clone(),
+                handshake_timer,
+                builder.policy,
+                keys.clone(),
+                handshake_attempts,
+            ),
+            move |item, context| {
+                let result = handshaker::execute_handshake(item, context);
+                handshake_limits.finish_handshake(matches!(result, Ok(Some(_))));
+
+                result
+            },
             sock_send,
         );
 
-        let sink = HandshakerManagerSink::new(addr_send, open_port, builder.pid, filters);
-        let stream = HandshakerManagerStream::new(sock_recv);
+        let sink = HandshakerManagerSink::new(
+            addr_send,
+            open_port,
+            builder.pid,
+            filters,
+            keys,
+            limits,
+            alive,
+        );
+        let stream = HandshakerManagerStream::new(sock_recv, filtered_recv, error_recv);
 
         Ok(HandshakerManager {
             sink: sink,
@@ -215,20 +384,93 @@ fn configured_handshake_timers(
 
 impl<S> HandshakerManager<S> {
 
-   pub fn send(
+    /// Initiate a handshake, blocking while the sink is full.
+    ///
+    /// From async code, use the `Sink` implementation instead.
+    pub fn send(
         &mut self,
         item: InitiateMessage,
     ) ->  Result<(), SendError<InitiateMessage>> {
         self.sink.send(item)
     }
 
+    /// Replace the filter set with `HandshakerManagerBuilder::with_filter`.
+    pub fn set_filter(&self, opt_filter: Option<Arc<dyn HandshakeFilter + Send + Sync>>) {
+        self.sink.set_filter(opt_filter);
+    }
+
+    /// Allow peers to request the given torrent over an encrypted connection.
+    pub fn add_info_hash(&self, hash: InfoHash) {
+        self.sink.add_info_hash(hash);
+    }
+
+    /// Stop peers from requesting the given torrent over an encrypted connection.
+    pub fn remove_info_hash(&self, hash: &InfoHash) {
+        self.sink.remove_info_hash(hash);
+    }
+
+    /// Retrieve the `ConnectionLimits` of the handshaker.
+    pub fn connection_limits(&self) -> ConnectionLimits {
+        self.sink.connection_limits()
+    }
 }
 
 impl<S> HandshakerManager<S> {
 
-   pub fn poll(&mut self) -> Result<CompleteMessage<S>, ()> {
+    /// Wait for the next completed handshake, blocking the current thread.
+    ///
+    /// From async code, use the `Stream` implementation instead.
+    pub fn poll(&mut self) -> Result<CompleteMessage<S>, ()> {
         self.stream.poll()
     }
+
+    /// Poll for a handshake that was dropped by our filters, without blocking.
+    pub fn poll_filtered(&mut self) -> Option<FilteredMessage> {
+        self.stream.poll_filtered()
+    }
+
+    /// Poll for a handshake that was dropped by our filters, waiting up to `timeout`.
+    pub fn poll_filtered_timeout(&mut self, timeout: Duration) -> Option<FilteredMessage> {
+        self.stream.poll_filtered_timeout(timeout)
+    }
+
+    /// Poll for a handshake that failed, without blocking.
+    pub fn poll_error(&mut self) -> Option<HandshakeError> {
+        self.stream.poll_error()
+    }
+
+    /// Poll for a handshake that failed, waiting up to `timeout`.
+    pub fn poll_error_timeout(&mut self, timeout: Duration) -> Option<HandshakeError> {
+        self.stream.poll_error_timeout(timeout)
+    }
+}
+
+impl<S> Sink<InitiateMessage> for HandshakerManager<S> {
+    type Error = mpsc::SendError;
+
+    fn poll_ready(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Pin::new(&mut self.sink).poll_ready(cx)
+    }
+
+    fn start_send(mut self: Pin<&mut Self>, item: InitiateMessage) -> Result<(), Self::Error> {
+        Pin::new(&mut self.sink).start_send(item)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Pin::new(&mut self.sink).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Pin::new(&mut self.sink).poll_close(cx)
+    }
+}
+
+impl<S> Stream for HandshakerManager<S> {
+    type Item = CompleteMessage<S>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        Pin::new(&mut self.stream).poll_next(cx)
+    }
 }
 
 impl<S> HandshakeFilters for HandshakerManager<S> {
@@ -256,26 +498,62 @@ impl<S> HandshakeFilters for HandshakerManager<S> {
 /// `Sink` portion of the `Handshaker` for initiating handshakes.
 #[derive(Clone)]
 pub struct HandshakerManagerSink {
-    send: Sender<InitiateMessage>,
+    send: mpsc::Sender<InitiateMessage>,
     port: u16,
     pid: PeerId,
     filters: Filters,
+    keys: SecretKeys,
+    limits: ConnectionLimits,
+    // Keeps the retry loop running, it only holds a weak reference
+    _alive: Arc<()>,
 }
 
 impl HandshakerManagerSink {
     fn new(
-        send: Sender<InitiateMessage>,
+        send: mpsc::Sender<InitiateMessage>,
         port: u16,
         pid: PeerId,
         filters: Filters,
+        keys: SecretKeys,
+        limits: ConnectionLimits,
+        alive: Arc<()>,
     ) -> HandshakerManagerSink {
         HandshakerManagerSink {
             send: send,
             port: port,
             pid: pid,
             filters: filters,
+            keys: keys,
+            limits: limits,
+            _alive: alive,
         }
     }
+
+    /// Replace the filter set with `HandshakerManagerBuilder::with_filter`.
+    ///
+    /// Passing `None` removes it, handshakes in progress may still see the old filter.
+    pub fn set_filter(&self, opt_filter: Option<Arc<dyn HandshakeFilter + Send + Sync>>) {
+        self.filters.set_shared_filter(opt_filter);
+    }
+
+    /// Allow peers to request the given torrent over an encrypted connection.
+    ///
+    /// MSE hides the info hash, so only torrents added here can be matched.
+    pub fn add_info_hash(&self, hash: InfoHash) {
+        self.keys.add_hash(hash);
+    }
+
+    /// Stop peers from requesting the given torrent over an encrypted connection.
+    pub fn remove_info_hash(&self, hash: &InfoHash) {
+        self.keys.remove_hash(hash);
+    }
+
+    /// Retrieve the `ConnectionLimits` of the handshaker.
+    ///
+    /// Limits are shared by every clone of the sink, and can be changed at any time.
+    pub fn connection_limits(&self) -> ConnectionLimits {
+        self.limits.clone()
+    }
 }
 
 impl DiscoveryInfo for HandshakerManagerSink {
@@ -290,14 +568,41 @@ impl DiscoveryInfo for HandshakerManagerSink {
 
 impl  HandshakerManagerSink {
 
-   pub fn send(
+    /// Initiate a handshake, blocking while the sink is full.
+    ///
+    /// From async code, use the `Sink` implementation instead.
+    pub fn send(
         &mut self,
         item: InitiateMessage,
     ) -> Result<(), SendError<InitiateMessage>> {
+        let send = &mut self.send;
+
+        if executor::block_on(future::poll_fn(|cx| send.poll_ready(cx))).is_err() {
+            return Err(SendError(item));
+        }
+        send.try_send(item).map_err(|error| SendError(error.into_inner()))
+    }
+
+}
 
-        self.send.send(item)
+impl Sink<InitiateMessage> for HandshakerManagerSink {
+    type Error = mpsc::SendError;
+
+    fn poll_ready(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Pin::new(&mut self.send).poll_ready(cx)
+    }
+
+    fn start_send(mut self: Pin<&mut Self>, item: InitiateMessage) -> Result<(), Self::Error> {
+        Pin::new(&mut self.send).start_send(item)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Pin::new(&mut self.send).poll_flush(cx)
     }
 
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Pin::new(&mut self.send).poll_close(cx)
+    }
 }
 
 impl HandshakeFilters for HandshakerManagerSink {
@@ -324,19 +629,64 @@ impl HandshakeFilters for HandshakerManagerSink {
 
 /// `Stream` portion of the `Handshaker` for completed handshakes.
 pub struct HandshakerManagerStream<S> {
-    recv: Receiver<CompleteMessage<S>>,
+    recv: mpsc::Receiver<CompleteMessage<S>>,
+    filtered: Receiver<FilteredMessage>,
+    errors: Receiver<HandshakeError>,
 }
 
 impl<S> HandshakerManagerStream<S> {
-    fn new(recv: Receiver<CompleteMessage<S>>) -> HandshakerManagerStream<S> {
-        HandshakerManagerStream { recv: recv }
+    fn new(
+        recv: mpsc::Receiver<CompleteMessage<S>>,
+        filtered: Receiver<FilteredMessage>,
+        errors: Receiver<HandshakeError>,
+    ) -> HandshakerManagerStream<S> {
+        HandshakerManagerStream {
+            recv: recv,
+            filtered: filtered,
+            errors: errors,
+        }
     }
 }
 
 impl<S>  HandshakerManagerStream<S> {
 
-   pub fn poll(&mut self) -> Result<CompleteMessage<S>, ()> {
-        self.recv.recv().map_err(|_|())
+    /// Wait for the next completed handshake, blocking the current thread.
+    ///
+    /// From async code, use the `Stream` implementation instead.
+    pub fn poll(&mut self) -> Result<CompleteMessage<S>, ()> {
+        executor::block_on(self.recv.next()).ok_or(())
+    }
+
+    /// Poll for a handshake that was dropped by our filters, without blocking.
+    ///
+    /// New messages are dropped while `HandshakerConfig::filtered_buffer_size` are waiting.
+    pub fn poll_filtered(&mut self) -> Option<FilteredMessage> {
+        self.filtered.try_recv().ok()
+    }
+
+    /// Poll for a handshake that was dropped by our filters, waiting up to `timeout`.
+    pub fn poll_filtered_timeout(&mut self, timeout: Duration) -> Option<FilteredMessage> {
+        self.filtered.recv_timeout(timeout).ok()
+    }
+
+    /// Poll for a handshake that failed, without blocking.
+    ///
+    /// New errors are dropped while `HandshakerConfig::error_buffer_size` are waiting.
+    pub fn poll_error(&mut self) -> Option<HandshakeError> {
+        self.errors.try_recv().ok()
+    }
+
+    /// Poll for a handshake that failed, waiting up to `timeout`.
+    pub fn poll_error_timeout(&mut self, timeout: Duration) -> Option<HandshakeError> {
+        self.errors.recv_timeout(timeout).ok()
     }
 }
 
+
+impl<S> Stream for HandshakerManagerStream<S> {
+    type Item = CompleteMessage<S>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.recv.poll_next_unpin(cx)
+    }
+}
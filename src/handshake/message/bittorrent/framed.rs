@@ -61,7 +61,7 @@ impl<S> FramedHandshake<S>
                 Ok(written) => {
                     self.write_buffer.split_to(written);
                 }
-                _ => return Ok(()),
+                Err(err) => return Err(err),
             }
 
             if self.write_buffer.is_empty() {
@@ -50,7 +50,7 @@ impl HandshakeMessage {
     {
         self.prot.write_bytes(&mut writer)?;
         self.ext.write_bytes(&mut writer)?;
-        writer.write_all(self.hash.as_ref())?;
+        writer.write_all(&self.hash.truncated())?;
 
         writer.write_all(self.pid.as_ref())?;
 
@@ -186,6 +186,27 @@ mod tests {
         assert_eq!(exp_message, recv_message);
     }
 
+    #[test]
+    fn positive_v2_hash_written_truncated() {
+        let mut buffer = Vec::new();
+
+        let v2_hash = InfoHash::V2([55u8; bt::INFO_HASH_V2_LEN]);
+        let message = HandshakeMessage::from_parts(
+            Protocol::BitTorrent,
+            any_extensions(),
+            v2_hash,
+            any_peer_id(),
+        );
+        message.write_bytes(&mut buffer).unwrap();
+
+        assert_eq!(message.write_len(), buffer.len());
+        let (_, _, recv_hash, _) = HandshakeMessage::from_bytes(&buffer)
+            .unwrap()
+            .1
+            .into_parts();
+        assert_eq!(any_info_hash(), recv_hash);
+    }
+
     #[test]
     #[should_panic]
     fn negative_create_overflow_protocol() {
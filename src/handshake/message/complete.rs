@@ -1,12 +1,13 @@
 use std::net::SocketAddr;
 
-use crate::handshake::{Extensions, Protocol};
+use crate::handshake::{ClientInfo, ExtensionBits, Extensions, Protocol};
 use crate::util::bt::{InfoHash, PeerId};
 
 /// Message containing completed handshaking information.
 pub struct CompleteMessage<S> {
     prot: Protocol,
     ext: Extensions,
+    remote_ext: ExtensionBits,
     hash: InfoHash,
     pid: PeerId,
     addr: SocketAddr,
@@ -15,6 +16,8 @@ pub struct CompleteMessage<S> {
 
 impl<S> CompleteMessage<S> {
     /// Create a new `CompleteMessage` over the given socket S.
+    ///
+    /// The remote extension bits default to the given `Extensions`.
     pub fn new(
         prot: Protocol,
         ext: Extensions,
@@ -26,6 +29,7 @@ impl<S> CompleteMessage<S> {
         CompleteMessage {
             prot: prot,
             ext: ext,
+            remote_ext: ext.into(),
             hash: hash,
             pid: pid,
             addr: addr,
@@ -33,6 +37,12 @@ impl<S> CompleteMessage<S> {
         }
     }
 
+    /// Sets the reserved bits exactly as the peer sent them.
+    pub fn with_remote_extension_bits(mut self, bits: ExtensionBits) -> CompleteMessage<S> {
+        self.remote_ext = bits;
+        self
+    }
+
     /// Protocol that this peer is operating over.
     pub fn protocol(&self) -> &Protocol {
         &self.prot
@@ -43,6 +53,11 @@ impl<S> CompleteMessage<S> {
         &self.ext
     }
 
+    /// Reserved bits that the peer sent us, including bits we do not support.
+    pub fn remote_extension_bits(&self) -> &ExtensionBits {
+        &self.remote_ext
+    }
+
     /// Hash that the peer is interested in.
     pub fn hash(&self) -> &InfoHash {
         &self.hash
@@ -53,6 +68,11 @@ impl<S> CompleteMessage<S> {
         &self.pid
     }
 
+    /// Client name and version decoded from the peer id, if it follows a known encoding.
+    pub fn client(&self) -> Option<ClientInfo> {
+        ClientInfo::from_peer_id(&self.pid)
+    }
+
     /// Address the peer is connected to us on.
     pub fn address(&self) -> &SocketAddr {
         &self.addr
@@ -10,6 +10,10 @@ pub const NUM_EXTENSION_BYTES: usize = 8;
 pub enum Extension {
     /// Support for the extension protocol `http://www.bittorrent.org/beps/bep_0010.html`.
     ExtensionProtocol = 43,
+    /// Support for the fast extension `http://www.bittorrent.org/beps/bep_0006.html`.
+    FastExtension = 61,
+    /// Support for the DHT `http://www.bittorrent.org/beps/bep_0005.html`.
+    Dht = 63,
 }
 
 /// `Extensions` supported by either end of a handshake.
@@ -91,6 +95,110 @@ impl From<[u8; NUM_EXTENSION_BYTES]> for Extensions {
     }
 }
 
+impl From<ExtensionBits> for Extensions {
+    fn from(bits: ExtensionBits) -> Extensions {
+        Extensions { bytes: bits.bytes }
+    }
+}
+
+/// Reserved bits of a handshake, including bits we do not know the meaning of.
+///
+/// Bits are numbered from the most significant bit of the first reserved byte, so the
+/// DHT bit (63) is the least significant bit of the last byte.
+#[derive(Copy, Clone, Eq, Hash, PartialEq, Debug)]
+pub struct ExtensionBits {
+    bytes: [u8; NUM_EXTENSION_BYTES],
+}
+
+impl ExtensionBits {
+    /// Create a new `ExtensionBits` with no bits set.
+    pub fn new() -> ExtensionBits {
+        ExtensionBits {
+            bytes: [0u8; NUM_EXTENSION_BYTES],
+        }
+    }
+
+    /// Bit for the DHT `http://www.bittorrent.org/beps/bep_0005.html`.
+    pub fn dht() -> ExtensionBits {
+        ExtensionBits::bit(Extension::Dht as u8)
+    }
+
+    /// Bit for the extension protocol `http://www.bittorrent.org/beps/bep_0010.html`.
+    pub fn extension_protocol() -> ExtensionBits {
+        ExtensionBits::bit(Extension::ExtensionProtocol as u8)
+    }
+
+    /// Bit for the fast extension `http://www.bittorrent.org/beps/bep_0006.html`.
+    pub fn fast() -> ExtensionBits {
+        ExtensionBits::bit(Extension::FastExtension as u8)
+    }
+
+    /// Arbitrary bit, for extensions we do not have a name for.
+    ///
+    /// Panics if the bit is not less than 64.
+    pub fn bit(bit: u8) -> ExtensionBits {
+        assert!(
+            (bit as usize) < NUM_EXTENSION_BYTES * 8,
+            "bittorrent-protocol_handshake: ExtensionBits::bit Bit {} Out Of Range",
+            bit
+        );
+        let mut bits = ExtensionBits::new();
+        bits.bytes[bit as usize / 8] = 0x80 >> (bit % 8);
+
+        bits
+    }
+
+    /// Bits set in either `self` or `other`.
+    pub fn union(&self, other: &ExtensionBits) -> ExtensionBits {
+        self.zip_with(other, |ours, theirs| ours | theirs)
+    }
+
+    /// Bits set in both `self` and `other`.
+    pub fn intersection(&self, other: &ExtensionBits) -> ExtensionBits {
+        self.zip_with(other, |ours, theirs| ours & theirs)
+    }
+
+    /// Whether or not every bit set in `other` is set in `self`.
+    pub fn contains(&self, other: &ExtensionBits) -> bool {
+        self.intersection(other) == *other
+    }
+
+    /// Whether or not no bits are set.
+    pub fn is_empty(&self) -> bool {
+        self.bytes.iter().all(|&byte| byte == 0)
+    }
+
+    /// Reserved bytes, as they appear in the handshake.
+    pub fn bytes(&self) -> [u8; NUM_EXTENSION_BYTES] {
+        self.bytes
+    }
+
+    fn zip_with<F>(&self, other: &ExtensionBits, combine: F) -> ExtensionBits
+    where
+        F: Fn(u8, u8) -> u8,
+    {
+        let mut result = ExtensionBits::new();
+
+        for index in 0..NUM_EXTENSION_BYTES {
+            result.bytes[index] = combine(self.bytes[index], other.bytes[index]);
+        }
+
+        result
+    }
+}
+
+impl From<[u8; NUM_EXTENSION_BYTES]> for ExtensionBits {
+    fn from(bytes: [u8; NUM_EXTENSION_BYTES]) -> ExtensionBits {
+        ExtensionBits { bytes: bytes }
+    }
+}
+
+impl From<Extensions> for ExtensionBits {
+    fn from(ext: Extensions) -> ExtensionBits {
+        ExtensionBits { bytes: ext.bytes }
+    }
+}
+
 /// Parse the given bytes for extension bits.
 fn parse_extension_bits(bytes: &[u8]) -> IResult<&[u8], Extensions> {
     do_parse!(
@@ -101,7 +209,12 @@ fn parse_extension_bits(bytes: &[u8]) -> IResult<&[u8], Extensions> {
 
 #[cfg(test)]
 mod tests {
-    use super::{Extension, Extensions};
+    use super::{Extension, ExtensionBits, Extensions};
+
+    // Reserved bytes from a libtorrent 2.0 handshake, which also sets the BEP 52 upgrade bit
+    const LIBTORRENT_RESERVED: [u8; 8] = [0x00, 0x00, 0x00, 0x00, 0x00, 0x10, 0x00, 0x15];
+    // Reserved bytes from a Transmission handshake
+    const TRANSMISSION_RESERVED: [u8; 8] = [0x00, 0x00, 0x00, 0x00, 0x00, 0x10, 0x00, 0x05];
 
     #[test]
     fn positive_add_extension_protocol() {
@@ -125,4 +238,74 @@ mod tests {
         assert_eq!(expected_extensions, extensions);
         assert!(!extensions.contains(Extension::ExtensionProtocol));
     }
+
+    #[test]
+    fn positive_add_fast_extension() {
+        let mut extensions = Extensions::new();
+        extensions.add(Extension::FastExtension);
+
+        let expected_extensions: Extensions = [0, 0, 0, 0, 0, 0, 0, 0x04].into();
+
+        assert_eq!(expected_extensions, extensions);
+        assert!(extensions.contains(Extension::FastExtension));
+    }
+
+    #[test]
+    fn positive_named_bits_layout() {
+        assert_eq!([0, 0, 0, 0, 0, 0, 0, 0x01], ExtensionBits::dht().bytes());
+        assert_eq!([0, 0, 0, 0, 0, 0x10, 0, 0], ExtensionBits::extension_protocol().bytes());
+        assert_eq!([0, 0, 0, 0, 0, 0, 0, 0x04], ExtensionBits::fast().bytes());
+        assert_eq!([0x80, 0, 0, 0, 0, 0, 0, 0], ExtensionBits::bit(0).bytes());
+    }
+
+    #[test]
+    fn positive_union_matches_transmission() {
+        let bits = ExtensionBits::dht()
+            .union(&ExtensionBits::extension_protocol())
+            .union(&ExtensionBits::fast());
+
+        assert_eq!(ExtensionBits::from(TRANSMISSION_RESERVED), bits);
+    }
+
+    #[test]
+    fn positive_contains_libtorrent_bits() {
+        let bits = ExtensionBits::from(LIBTORRENT_RESERVED);
+
+        assert!(bits.contains(&ExtensionBits::dht()));
+        assert!(bits.contains(&ExtensionBits::extension_protocol()));
+        assert!(bits.contains(&ExtensionBits::fast()));
+        assert!(bits.contains(&ExtensionBits::bit(59)));
+        assert!(!bits.contains(&ExtensionBits::bit(0)));
+        assert!(!ExtensionBits::dht().contains(&bits));
+    }
+
+    #[test]
+    fn positive_unknown_bits_round_trip() {
+        let (_, extensions) = Extensions::from_bytes(&LIBTORRENT_RESERVED).unwrap();
+        let bits = ExtensionBits::from(extensions);
+
+        let mut written = Vec::new();
+        Extensions::from(bits).write_bytes(&mut written).unwrap();
+
+        assert_eq!(&LIBTORRENT_RESERVED[..], &written[..]);
+        assert!(extensions.contains(Extension::Dht));
+    }
+
+    #[test]
+    fn positive_intersection_is_negotiated_bits() {
+        let ours = ExtensionBits::extension_protocol().union(&ExtensionBits::fast());
+        let theirs = ExtensionBits::from(TRANSMISSION_RESERVED);
+
+        assert_eq!(ours, ours.intersection(&theirs));
+        assert_eq!(
+            Extensions::from(ours),
+            Extensions::from(ours).union(&Extensions::from(theirs))
+        );
+    }
+
+    #[test]
+    #[should_panic]
+    fn negative_bit_out_of_range() {
+        ExtensionBits::bit(64);
+    }
 }
@@ -0,0 +1,40 @@
+use std::net::SocketAddr;
+
+use crate::util::bt::{InfoHash, PeerId};
+
+/// Message describing a handshake that was dropped by a `HandshakeFilter`.
+///
+/// Fields are only known if the filter blocked the handshake after receiving them.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub enum FilteredMessage {
+    /// Handshake we were initiating with the peer.
+    Initiate(SocketAddr, InfoHash, Option<PeerId>),
+    /// Handshake the peer was initiating with us.
+    Complete(SocketAddr, Option<InfoHash>, Option<PeerId>),
+}
+
+impl FilteredMessage {
+    /// Address of the peer.
+    pub fn address(&self) -> &SocketAddr {
+        match self {
+            FilteredMessage::Initiate(addr, _, _) | FilteredMessage::Complete(addr, _, _) => addr,
+        }
+    }
+
+    /// Hash of the torrent the handshake was for.
+    pub fn hash(&self) -> Option<&InfoHash> {
+        match self {
+            FilteredMessage::Initiate(_, hash, _) => Some(hash),
+            FilteredMessage::Complete(_, opt_hash, _) => opt_hash.as_ref(),
+        }
+    }
+
+    /// Id that the peer has given itself.
+    pub fn peer_id(&self) -> Option<&PeerId> {
+        match self {
+            FilteredMessage::Initiate(_, _, opt_pid) | FilteredMessage::Complete(_, _, opt_pid) => {
+                opt_pid.as_ref()
+            }
+        }
+    }
+}
@@ -8,18 +8,31 @@ pub struct InitiateMessage {
     prot: Protocol,
     hash: InfoHash,
     addr: SocketAddr,
+    holepunch: bool,
 }
 
 impl InitiateMessage {
     /// Create a new `InitiateMessage`.
+    ///
+    /// A v2 hash is truncated to the form that is sent to the peer, which the peer answers with.
     pub fn new(prot: Protocol, hash: InfoHash, addr: SocketAddr) -> InitiateMessage {
         InitiateMessage {
             prot: prot,
-            hash: hash,
+            hash: InfoHash::from(hash.truncated()),
             addr: addr,
+            holepunch: false,
         }
     }
 
+    /// Mark the connection as one half of a holepunch, which the peer is opening at the same time.
+    ///
+    /// Holepunch connections ignore any cool down for the address, and are not retried, since
+    /// the peer only dials us back right after the rendezvous.
+    pub fn with_holepunch(mut self) -> InitiateMessage {
+        self.holepunch = true;
+        self
+    }
+
     /// Protocol that we want to connect to the peer with.
     pub fn protocol(&self) -> &Protocol {
         &self.prot
@@ -35,6 +48,11 @@ impl InitiateMessage {
         &self.addr
     }
 
+    /// Whether or not the connection is one half of a holepunch.
+    pub fn is_holepunch(&self) -> bool {
+        self.holepunch
+    }
+
     /// Break the `InitiateMessage` up into its parts.
     pub fn into_parts(self) -> (Protocol, InfoHash, SocketAddr) {
         (self.prot, self.hash, self.addr)
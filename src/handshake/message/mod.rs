@@ -1,5 +1,6 @@
 pub mod complete;
 pub mod bittorrent;
 pub mod extensions;
+pub mod filtered;
 pub mod initiate;
 pub mod protocol;
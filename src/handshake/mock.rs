@@ -0,0 +1,455 @@
+use std::collections::{HashMap, VecDeque};
+use std::io::{self, Error, ErrorKind, Read, Write};
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, Weak};
+use std::time::{Duration, Instant};
+
+use crossbeam::channel::{unbounded, Receiver, Sender};
+
+use crate::handshake::local_addr::LocalAddr;
+use crate::handshake::stream::Stream;
+use crate::handshake::transport::{TimeoutSocket, Transport};
+
+/// First port handed out to listeners binding port 0, and to connecting sockets.
+const FIRST_MOCK_PORT: usize = 40000;
+
+/// Defines a `Transport` operating over in memory connections.
+///
+/// Clones share the same network, so handshakers built with clones of one `MockTransport`
+/// can connect to each other; connecting to an address nobody is listening on is refused.
+#[derive(Clone, Default)]
+pub struct MockTransport {
+    network: Arc<MockNetwork>,
+}
+
+type MockListeners = HashMap<SocketAddr, (Sender<(MockSocket, SocketAddr)>, Weak<()>)>;
+
+#[derive(Default)]
+struct MockNetwork {
+    listeners: Mutex<MockListeners>,
+    next_port: AtomicUsize,
+}
+
+impl MockTransport {
+    /// Create a new `MockTransport` with an empty network.
+    pub fn new() -> MockTransport {
+        MockTransport::default()
+    }
+
+    fn next_port(&self) -> u16 {
+        (FIRST_MOCK_PORT + self.network.next_port.fetch_add(1, Ordering::SeqCst)) as u16
+    }
+
+    fn lock_listeners(&self) -> MutexGuard<'_, MockListeners> {
+        self.network
+            .listeners
+            .lock()
+            .expect("bittorrent-protocol_handshake: Poisoned Lock In MockTransport")
+    }
+}
+
+impl Transport for MockTransport {
+    type Socket = MockSocket;
+    type Listener = MockListener;
+
+    /// Connecting sockets are given the ip of the address they connect to, with a fresh port.
+    fn connect(&self, addr: &SocketAddr) -> io::Result<Self::Socket> {
+        let mut local_addr = *addr;
+        local_addr.set_port(self.next_port());
+
+        let (ours, theirs) = MockSocket::pair();
+        let refused = self
+            .lock_listeners()
+            .get(addr)
+            .map(|&(ref send, _)| send.send((theirs, local_addr)).is_err())
+            .unwrap_or(true);
+
+        if refused {
+            Err(Error::new(
+                ErrorKind::ConnectionRefused,
+                "No MockListener At Address",
+            ))
+        } else {
+            Ok(ours)
+        }
+    }
+
+    fn listen(&self, addr: &SocketAddr) -> io::Result<Self::Listener> {
+        let mut listen_addr = *addr;
+        if listen_addr.port() == 0 {
+            listen_addr.set_port(self.next_port());
+        }
+
+        let mut listeners = self.lock_listeners();
+        // Listeners that were dropped give up their address
+        listeners.retain(|_, &mut (_, ref alive)| alive.upgrade().is_some());
+
+        if listeners.contains_key(&listen_addr) {
+            Err(Error::new(
+                ErrorKind::AddrInUse,
+                "MockListener Already At Address",
+            ))
+        } else {
+            let (send, recv) = unbounded();
+            let alive = Arc::new(());
+            listeners.insert(listen_addr, (send, Arc::downgrade(&alive)));
+
+            Ok(MockListener::new(listen_addr, recv, alive))
+        }
+    }
+}
+
+//----------------------------------------------------------------------------------//
+
+/// Listener for connections made through a `MockTransport`.
+pub struct MockListener {
+    listen_addr: SocketAddr,
+    recv: Receiver<(MockSocket, SocketAddr)>,
+    // Keeps our address taken, the network only holds a weak reference
+    _alive: Arc<()>,
+}
+
+impl MockListener {
+    fn new(
+        listen_addr: SocketAddr,
+        recv: Receiver<(MockSocket, SocketAddr)>,
+        alive: Arc<()>,
+    ) -> MockListener {
+        MockListener {
+            listen_addr: listen_addr,
+            recv: recv,
+            _alive: alive,
+        }
+    }
+}
+
+impl LocalAddr for MockListener {
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        Ok(self.listen_addr)
+    }
+}
+
+impl Stream for MockListener {
+    type Item = (MockSocket, SocketAddr);
+
+    fn poll(&mut self) -> io::Result<(MockSocket, SocketAddr)> {
+        self.recv
+            .recv()
+            .map_err(|_| Error::new(ErrorKind::NotFound, "listener fail"))
+    }
+}
+
+//----------------------------------------------------------------------------------//
+
+/// One end of an in memory connection made through a `MockTransport`.
+///
+/// Clones (through `TryClone`) refer to the same end, the connection is closed once every
+/// clone of one of the ends is dropped.
+#[derive(Clone)]
+pub struct MockSocket {
+    read: Arc<Pipe>,
+    write: Arc<Pipe>,
+    timeout: Arc<Mutex<Option<Duration>>>,
+    _end: Arc<PipeEnd>,
+}
+
+impl MockSocket {
+    /// Create both ends of a connection.
+    pub fn pair() -> (MockSocket, MockSocket) {
+        MockSocket::pair_with_pipes(Pipe::new(None), Pipe::new(None))
+    }
+
+    /// Create both ends of a connection that buffers at most `capacity` bytes in each direction.
+    ///
+    /// Writes block while the other end is not reading, like a socket with full send buffers.
+    pub fn pair_with_capacity(capacity: usize) -> (MockSocket, MockSocket) {
+        MockSocket::pair_with_pipes(Pipe::new(Some(capacity)), Pipe::new(Some(capacity)))
+    }
+
+    fn pair_with_pipes(one: Pipe, two: Pipe) -> (MockSocket, MockSocket) {
+        let (one, two) = (Arc::new(one), Arc::new(two));
+
+        (
+            MockSocket::new(one.clone(), two.clone()),
+            MockSocket::new(two, one),
+        )
+    }
+
+    fn new(read: Arc<Pipe>, write: Arc<Pipe>) -> MockSocket {
+        MockSocket {
+            read: read.clone(),
+            write: write.clone(),
+            timeout: Arc::new(Mutex::new(None)),
+            _end: Arc::new(PipeEnd {
+                read: read,
+                write: write,
+            }),
+        }
+    }
+}
+
+impl TimeoutSocket for MockSocket {
+    fn set_timeout(&self, opt_timeout: Option<Duration>) -> io::Result<()> {
+        *self
+            .timeout
+            .lock()
+            .expect("bittorrent-protocol_handshake: Poisoned Lock In MockSocket") = opt_timeout;
+
+        Ok(())
+    }
+}
+
+impl MockSocket {
+    fn timeout(&self) -> Option<Duration> {
+        *self
+            .timeout
+            .lock()
+            .expect("bittorrent-protocol_handshake: Poisoned Lock In MockSocket")
+    }
+}
+
+impl Read for MockSocket {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.read.read(buf, self.timeout())
+    }
+}
+
+impl Write for MockSocket {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.write.write(buf, self.timeout())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Closes both directions of the connection once the last clone of an end is dropped.
+struct PipeEnd {
+    read: Arc<Pipe>,
+    write: Arc<Pipe>,
+}
+
+impl Drop for PipeEnd {
+    fn drop(&mut self) {
+        self.read.close();
+        self.write.close();
+    }
+}
+
+/// Bytes travelling in one direction of a connection.
+struct Pipe {
+    state: Mutex<PipeState>,
+    // Signalled whenever bytes are written or read, or the pipe is closed
+    ready: Condvar,
+    opt_capacity: Option<usize>,
+}
+
+struct PipeState {
+    buffer: VecDeque<u8>,
+    closed: bool,
+}
+
+impl Pipe {
+    fn new(opt_capacity: Option<usize>) -> Pipe {
+        Pipe {
+            state: Mutex::new(PipeState {
+                buffer: VecDeque::new(),
+                closed: false,
+            }),
+            ready: Condvar::new(),
+            opt_capacity: opt_capacity,
+        }
+    }
+
+    fn lock_state(&self) -> MutexGuard<'_, PipeState> {
+        self.state
+            .lock()
+            .expect("bittorrent-protocol_handshake: Poisoned Lock In MockSocket")
+    }
+
+    fn close(&self) {
+        self.lock_state().closed = true;
+        self.ready.notify_all();
+    }
+
+    /// Block until `ready` returns true for the state, or the timeout passes.
+    fn wait_until<F>(
+        &self,
+        opt_timeout: Option<Duration>,
+        ready: F,
+    ) -> io::Result<MutexGuard<'_, PipeState>>
+    where
+        F: Fn(&PipeState) -> bool,
+    {
+        let opt_deadline = opt_timeout.map(|timeout| Instant::now() + timeout);
+        let mut state = self.lock_state();
+
+        while !ready(&state) {
+            state = match opt_deadline {
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        return Err(Error::new(ErrorKind::TimedOut, "MockSocket Timed Out"));
+                    }
+
+                    self.ready
+                        .wait_timeout(state, deadline - now)
+                        .expect("bittorrent-protocol_handshake: Poisoned Lock In MockSocket")
+                        .0
+                }
+                None => self
+                    .ready
+                    .wait(state)
+                    .expect("bittorrent-protocol_handshake: Poisoned Lock In MockSocket"),
+            };
+        }
+
+        Ok(state)
+    }
+
+    /// Block until bytes are available, returning `Ok(0)` once closed and drained.
+    fn read(&self, buf: &mut [u8], opt_timeout: Option<Duration>) -> io::Result<usize> {
+        let mut state = self.wait_until(opt_timeout, |state| {
+            !state.buffer.is_empty() || state.closed
+        })?;
+
+        let read_len = buf.len().min(state.buffer.len());
+        for (dst, src) in buf.iter_mut().zip(state.buffer.drain(..read_len)) {
+            *dst = src;
+        }
+        // Writers may be waiting on room in the buffer
+        self.ready.notify_all();
+
+        Ok(read_len)
+    }
+
+    /// Block until there is room in the buffer, writing as much as fits.
+    fn write(&self, buf: &[u8], opt_timeout: Option<Duration>) -> io::Result<usize> {
+        let capacity = self.opt_capacity.unwrap_or(usize::MAX);
+        let mut state = self.wait_until(opt_timeout, |state| {
+            state.buffer.len() < capacity || state.closed || buf.is_empty()
+        })?;
+
+        if state.closed {
+            Err(Error::new(ErrorKind::BrokenPipe, "MockSocket Closed"))
+        } else {
+            let write_len = buf.len().min(capacity - state.buffer.len());
+            state.buffer.extend(&buf[..write_len]);
+            self.ready.notify_all();
+
+            Ok(write_len)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{ErrorKind, Read, Write};
+    use std::time::Duration;
+
+    use super::{MockSocket, MockTransport};
+    use crate::handshake::stream::Stream;
+    use crate::handshake::{LocalAddr, TimeoutSocket, Transport};
+
+    #[test]
+    fn positive_pair_read_write() {
+        let (mut one, mut two) = MockSocket::pair();
+
+        one.write_all(b"hello").unwrap();
+        two.write_all(b"world").unwrap();
+
+        let mut buffer = [0u8; 5];
+        two.read_exact(&mut buffer).unwrap();
+        assert_eq!(b"hello", &buffer);
+        one.read_exact(&mut buffer).unwrap();
+        assert_eq!(b"world", &buffer);
+    }
+
+    #[test]
+    fn positive_drop_closes_connection() {
+        let (mut one, mut two) = MockSocket::pair();
+        one.write_all(b"bye").unwrap();
+
+        // Dropping a clone keeps the connection open
+        drop(two.clone());
+        one.write_all(b"!").unwrap();
+        drop(one);
+
+        let mut buffer = Vec::new();
+        two.read_to_end(&mut buffer).unwrap();
+
+        assert_eq!(b"bye!", &buffer[..]);
+        assert_eq!(ErrorKind::BrokenPipe, two.write(b"x").unwrap_err().kind());
+    }
+
+    #[test]
+    fn negative_read_times_out() {
+        let (mut one, _two) = MockSocket::pair();
+
+        one.set_timeout(Some(Duration::from_millis(20))).unwrap();
+
+        assert_eq!(
+            ErrorKind::TimedOut,
+            one.read(&mut [0u8; 1]).unwrap_err().kind()
+        );
+    }
+
+    #[test]
+    fn positive_write_blocks_at_capacity() {
+        let (mut one, mut two) = MockSocket::pair_with_capacity(4);
+
+        assert_eq!(4, one.write(b"hello").unwrap());
+        one.set_timeout(Some(Duration::from_millis(20))).unwrap();
+        assert_eq!(ErrorKind::TimedOut, one.write(b"o").unwrap_err().kind());
+
+        let mut buffer = [0u8; 2];
+        two.read_exact(&mut buffer).unwrap();
+        assert_eq!(2, one.write(b"o!!").unwrap());
+    }
+
+    #[test]
+    fn positive_connect_to_listener() {
+        let transport = MockTransport::new();
+        let mut listener = transport.listen(&"127.0.0.1:0".parse().unwrap()).unwrap();
+        let listen_addr = listener.local_addr().unwrap();
+
+        let mut ours = transport.clone().connect(&listen_addr).unwrap();
+        let (mut theirs, their_addr) = listener.poll().unwrap();
+        ours.write_all(&[1]).unwrap();
+
+        let mut buffer = [0u8; 1];
+        theirs.read_exact(&mut buffer).unwrap();
+        assert_eq!([1], buffer);
+        assert_eq!(listen_addr.ip(), their_addr.ip());
+        assert!(listen_addr != their_addr);
+    }
+
+    #[test]
+    fn negative_connect_refused() {
+        let transport = MockTransport::new();
+        let listen_addr = {
+            let listener = transport.listen(&"127.0.0.1:0".parse().unwrap()).unwrap();
+
+            listener.local_addr().unwrap()
+        };
+
+        let err = transport.connect(&listen_addr).err().unwrap();
+
+        assert_eq!(ErrorKind::ConnectionRefused, err.kind());
+    }
+
+    #[test]
+    fn negative_listen_addr_in_use() {
+        let transport = MockTransport::new();
+        let addr = "127.0.0.1:6881".parse().unwrap();
+
+        let _listener = transport.listen(&addr).unwrap();
+
+        assert_eq!(
+            ErrorKind::AddrInUse,
+            transport.listen(&addr).err().unwrap().kind()
+        );
+    }
+}
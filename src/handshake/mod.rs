@@ -1,26 +1,42 @@
 
 mod manager;
-pub use manager::config::HandshakerConfig;
+pub use manager::config::{HandshakerConfig, RetryPolicy};
 pub use manager::{HandshakerManagerBuilder, HandshakerManagerSink, HandshakerManagerStream};
 
 pub mod handler;
 
+mod error;
+pub use error::HandshakeError;
+
 mod filter;
 pub use filter::{FilterDecision, HandshakeFilter, HandshakeFilters};
 
+mod limits;
+pub use limits::ConnectionLimits;
+
 mod message;
 pub use message::complete::CompleteMessage;
-pub use message::extensions::{Extension, Extensions};
+pub use message::extensions::{Extension, ExtensionBits, Extensions};
+pub use message::filtered::FilteredMessage;
 pub use message::initiate::InitiateMessage;
 pub use message::protocol::Protocol;
 
+mod client;
+pub use client::{ClientEncoding, ClientInfo};
+
+mod mse;
+pub use mse::{EncryptionPolicy, MseSocket};
+
 /// Built in objects implementing `Transport`.
 pub mod transports {
+    pub use super::mock::{MockListener, MockSocket, MockTransport};
     pub use super::transport::{TcpListenerStream, TcpTransport,UtpListenerStream, UtpTransport};
 }
 
+mod mock;
+
 mod transport;
-pub use transport::Transport;
+pub use transport::{TimeoutSocket, Transport};
 
 mod stream;
 pub use stream::Stream;
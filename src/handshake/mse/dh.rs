@@ -0,0 +1,124 @@
+use std::io;
+
+use num::bigint::BigUint;
+use num::One;
+use rand::{self, Rng};
+
+/// Length of public keys and the shared secret, in bytes.
+pub const KEY_LEN: usize = 96;
+
+/// Length of the private key, in bytes.
+const PRIVATE_KEY_LEN: usize = 20;
+
+/// Generator used for the key exchange.
+const GENERATOR: u32 = 2;
+
+/// 768 bit safe prime shared by every MSE implementation.
+const PRIME: [u8; KEY_LEN] = [
+    0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xC9, 0x0F, 0xDA, 0xA2, 0x21, 0x68, 0xC2, 0x34,
+    0xC4, 0xC6, 0x62, 0x8B, 0x80, 0xDC, 0x1C, 0xD1, 0x29, 0x02, 0x4E, 0x08, 0x8A, 0x67, 0xCC, 0x74,
+    0x02, 0x0B, 0xBE, 0xA6, 0x3B, 0x13, 0x9B, 0x22, 0x51, 0x4A, 0x08, 0x79, 0x8E, 0x34, 0x04, 0xDD,
+    0xEF, 0x95, 0x19, 0xB3, 0xCD, 0x3A, 0x43, 0x1B, 0x30, 0x2B, 0x0A, 0x6D, 0xF2, 0x5F, 0x14, 0x37,
+    0x4F, 0xE1, 0x35, 0x6D, 0x6D, 0x51, 0xC2, 0x45, 0xE4, 0x85, 0xB5, 0x76, 0x62, 0x5E, 0x7E, 0xC6,
+    0xF4, 0x4C, 0x42, 0xE9, 0xA6, 0x3A, 0x36, 0x21, 0x00, 0x00, 0x00, 0x00, 0x00, 0x09, 0x05, 0x63,
+];
+
+/// Diffie-Hellman key pair for one side of an MSE handshake.
+pub struct DhKeys {
+    private: BigUint,
+    public: [u8; KEY_LEN],
+}
+
+impl DhKeys {
+    /// Generate a new key pair from a random private key.
+    pub fn generate() -> DhKeys {
+        let mut private = [0u8; PRIVATE_KEY_LEN];
+        rand::thread_rng().fill_bytes(&mut private);
+
+        DhKeys::from_private(&private)
+    }
+
+    /// Create a key pair from the given big endian private key.
+    pub fn from_private(private: &[u8]) -> DhKeys {
+        let private = BigUint::from_bytes_be(private);
+        let public = BigUint::from(GENERATOR).modpow(&private, &prime());
+
+        DhKeys {
+            private: private,
+            public: to_key_bytes(&public),
+        }
+    }
+
+    /// Public key to send to the peer.
+    pub fn public_key(&self) -> &[u8; KEY_LEN] {
+        &self.public
+    }
+
+    /// Compute the shared secret from the public key of the peer.
+    ///
+    /// Returns an error if the peer sent a degenerate key.
+    pub fn shared_secret(&self, remote_public: &[u8]) -> io::Result<[u8; KEY_LEN]> {
+        let prime = prime();
+        let remote = BigUint::from_bytes_be(remote_public);
+
+        if remote <= BigUint::one() || remote >= &prime - BigUint::one() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Peer Sent An Invalid Public Key",
+            ));
+        }
+
+        Ok(to_key_bytes(&remote.modpow(&self.private, &prime)))
+    }
+}
+
+fn prime() -> BigUint {
+    BigUint::from_bytes_be(&PRIME)
+}
+
+/// Left pad the number with zeroes so it always takes up `KEY_LEN` bytes.
+fn to_key_bytes(num: &BigUint) -> [u8; KEY_LEN] {
+    let bytes = num.to_bytes_be();
+    let mut key = [0u8; KEY_LEN];
+
+    key[KEY_LEN - bytes.len()..].copy_from_slice(&bytes);
+
+    key
+}
+
+#[cfg(test)]
+mod tests {
+    use super::DhKeys;
+
+    #[test]
+    fn positive_shared_secret_matches() {
+        let keys_a = DhKeys::generate();
+        let keys_b = DhKeys::generate();
+
+        let secret_a = keys_a.shared_secret(keys_b.public_key()).unwrap();
+        let secret_b = keys_b.shared_secret(keys_a.public_key()).unwrap();
+
+        assert_eq!(&secret_a[..], &secret_b[..]);
+    }
+
+    #[test]
+    fn positive_public_key_is_padded() {
+        let keys = DhKeys::from_private(&[1]);
+
+        let mut expected = [0u8; super::KEY_LEN];
+        expected[super::KEY_LEN - 1] = 2;
+
+        assert_eq!(&expected[..], &keys.public_key()[..]);
+    }
+
+    #[test]
+    fn negative_rejects_degenerate_public_key() {
+        let keys = DhKeys::generate();
+
+        let mut one = [0u8; super::KEY_LEN];
+        one[super::KEY_LEN - 1] = 1;
+
+        assert!(keys.shared_secret(&one).is_err());
+        assert!(keys.shared_secret(&super::PRIME).is_err());
+    }
+}
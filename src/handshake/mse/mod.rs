@@ -0,0 +1,75 @@
+//! Message stream encryption (MSE/PE), obfuscating handshakes and the payload stream.
+//!
+//! See http://wiki.vuze.com/w/Message_Stream_Encryption.
+
+use std::collections::HashSet;
+use std::sync::{Arc, RwLock};
+
+use crate::util::bt::InfoHash;
+
+mod dh;
+pub mod negotiate;
+
+mod socket;
+pub use self::socket::MseSocket;
+
+/// Whether or not handshakes are encrypted.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum EncryptionPolicy {
+    /// Only speak plaintext, as if MSE did not exist.
+    Disabled,
+    /// Prefer RC4, but allow peers to negotiate, or start, plaintext connections.
+    Enabled,
+    /// Require RC4 for every connection.
+    Forced,
+}
+
+impl Default for EncryptionPolicy {
+    fn default() -> EncryptionPolicy {
+        EncryptionPolicy::Disabled
+    }
+}
+
+/// Info hashes that peers may request when connecting to us with MSE.
+///
+/// The peer never sends the info hash in the clear, so we have to try each one.
+#[derive(Clone)]
+pub struct SecretKeys {
+    hashes: Arc<RwLock<HashSet<InfoHash>>>,
+}
+
+impl SecretKeys {
+    pub fn new() -> SecretKeys {
+        SecretKeys {
+            hashes: Arc::new(RwLock::new(HashSet::new())),
+        }
+    }
+
+    /// Register the hash, a v2 hash is registered as the truncated form that peers use.
+    pub fn add_hash(&self, hash: InfoHash) {
+        self.hashes
+            .write()
+            .expect("bittorrent-protocol_handshake: Poisoned Write Lock In SecretKeys")
+            .insert(InfoHash::from(hash.truncated()));
+    }
+
+    pub fn remove_hash(&self, hash: &InfoHash) {
+        self.hashes
+            .write()
+            .expect("bittorrent-protocol_handshake: Poisoned Write Lock In SecretKeys")
+            .remove(&InfoHash::from(hash.truncated()));
+    }
+
+    /// Find the first registered hash matching the predicate.
+    pub fn find<P>(&self, mut predicate: P) -> Option<InfoHash>
+    where
+        P: FnMut(&InfoHash) -> bool,
+    {
+        self.hashes
+            .read()
+            .expect("bittorrent-protocol_handshake: Poisoned Read Lock In SecretKeys")
+            .iter()
+            .find(|hash| predicate(hash))
+            .cloned()
+    }
+}
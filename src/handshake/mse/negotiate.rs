@@ -0,0 +1,617 @@
+use std::io::{self, Read, Write};
+
+use byteorder::{BigEndian, ByteOrder};
+use crypto::rc4::Rc4;
+use crypto::symmetriccipher::SynchronousStreamCipher;
+use rand::{self, Rng};
+
+use crate::handshake::mse::dh::{self, DhKeys};
+use crate::handshake::mse::socket::MseSocket;
+use crate::handshake::mse::{EncryptionPolicy, SecretKeys};
+use crate::util::bt::InfoHash;
+use crate::util::sha::{self, ShaHash, ShaHashBuilder};
+
+/// Verification constant, sent encrypted so the peer can find where the cipher starts.
+const VC: [u8; 8] = [0u8; 8];
+
+const CRYPTO_PLAINTEXT: u32 = 0x01;
+const CRYPTO_RC4: u32 = 0x02;
+
+/// Maximum length of any of the padding fields.
+const MAX_PAD_LEN: usize = 512;
+
+/// Number of key stream bytes discarded before using an RC4 cipher.
+const RC4_DISCARD_LEN: usize = 1024;
+
+/// Start of a plaintext BitTorrent handshake.
+const PLAINTEXT_PREFIX: &[u8] = b"\x13BitTorrent protocol";
+
+/// Padding sent during the handshake, random outside of tests.
+pub struct Pads {
+    dh: Vec<u8>,
+    crypto: Vec<u8>,
+}
+
+impl Pads {
+    pub fn random() -> Pads {
+        Pads {
+            dh: random_pad(),
+            crypto: random_pad(),
+        }
+    }
+
+    #[cfg(test)]
+    pub fn new(dh: Vec<u8>, crypto: Vec<u8>) -> Pads {
+        assert!(dh.len() <= MAX_PAD_LEN && crypto.len() <= MAX_PAD_LEN);
+
+        Pads {
+            dh: dh,
+            crypto: crypto,
+        }
+    }
+}
+
+fn random_pad() -> Vec<u8> {
+    let mut rng = rand::thread_rng();
+
+    let mut pad = vec![0u8; rng.gen_range(0, MAX_PAD_LEN + 1)];
+    rng.fill_bytes(&mut pad);
+
+    pad
+}
+
+/// Negotiate encryption with a peer we connected to, for the given torrent.
+///
+/// Plaintext connections are wrapped as is if encryption is disabled.
+pub fn initiate<S>(sock: S, hash: &InfoHash, policy: EncryptionPolicy) -> io::Result<MseSocket<S>>
+where
+    S: Read + Write,
+{
+    match policy {
+        EncryptionPolicy::Disabled => Ok(MseSocket::plaintext(sock)),
+        EncryptionPolicy::Enabled => initiate_with(
+            sock,
+            hash,
+            CRYPTO_PLAINTEXT | CRYPTO_RC4,
+            DhKeys::generate(),
+            Pads::random(),
+        ),
+        EncryptionPolicy::Forced => {
+            initiate_with(sock, hash, CRYPTO_RC4, DhKeys::generate(), Pads::random())
+        }
+    }
+}
+
+/// Negotiate encryption with a peer that connected to us.
+///
+/// Detects whether the peer started a plaintext handshake or an encrypted one; the
+/// secret key is looked up from the info hashes registered in `keys`.
+pub fn complete<S>(sock: S, keys: &SecretKeys, policy: EncryptionPolicy) -> io::Result<MseSocket<S>>
+where
+    S: Read + Write,
+{
+    match policy {
+        EncryptionPolicy::Disabled => Ok(MseSocket::plaintext(sock)),
+        EncryptionPolicy::Enabled | EncryptionPolicy::Forced => {
+            complete_with(sock, keys, policy, DhKeys::generate(), Pads::random())
+        }
+    }
+}
+
+pub fn initiate_with<S>(
+    mut sock: S,
+    hash: &InfoHash,
+    crypto_provide: u32,
+    keys: DhKeys,
+    pads: Pads,
+) -> io::Result<MseSocket<S>>
+where
+    S: Read + Write,
+{
+    let mut out = keys.public_key().to_vec();
+    out.extend_from_slice(&pads.dh);
+    sock.write_all(&out)?;
+
+    let mut remote_public = [0u8; dh::KEY_LEN];
+    sock.read_exact(&mut remote_public)?;
+    let secret = keys.shared_secret(&remote_public)?;
+
+    let mut encryptor = rc4_cipher(b"keyA", &secret, hash);
+    let mut decryptor = rc4_cipher(b"keyB", &secret, hash);
+
+    let mut plain = VC.to_vec();
+    plain.extend_from_slice(&u32_bytes(crypto_provide));
+    plain.extend_from_slice(&u16_bytes(pads.crypto.len() as u16));
+    plain.extend_from_slice(&pads.crypto);
+    // No initial payload, the bittorrent handshake is sent through the socket afterwards
+    plain.extend_from_slice(&u16_bytes(0));
+
+    let mut out = hash_of(b"req1", &secret).as_ref().to_vec();
+    let req2 = hash_of(b"req2", hash.truncated());
+    out.extend_from_slice((req2 ^ hash_of(b"req3", &secret)).as_ref());
+    out.extend_from_slice(&encrypt(&mut encryptor, &plain));
+    sock.write_all(&out)?;
+
+    // The pad the peer sent after its key ends where its encrypted verification constant starts
+    let encrypted_vc = encrypt(&mut decryptor, &VC);
+    sync_to(&mut sock, &encrypted_vc, MAX_PAD_LEN + VC.len())?;
+
+    let crypto_select = BigEndian::read_u32(&read_decrypt(&mut sock, &mut decryptor, 4)?);
+    let pad_len = BigEndian::read_u16(&read_decrypt(&mut sock, &mut decryptor, 2)?) as usize;
+    check_pad_len(pad_len)?;
+    read_decrypt(&mut sock, &mut decryptor, pad_len)?;
+
+    if crypto_select.count_ones() != 1 || crypto_select & crypto_provide == 0 {
+        return Err(invalid_data(
+            "Peer Selected A Crypto Method We Did Not Provide",
+        ));
+    }
+
+    Ok(finish(
+        sock,
+        crypto_select,
+        decryptor,
+        encryptor,
+        Vec::new(),
+    ))
+}
+
+pub fn complete_with<S>(
+    mut sock: S,
+    keys: &SecretKeys,
+    policy: EncryptionPolicy,
+    dh_keys: DhKeys,
+    pads: Pads,
+) -> io::Result<MseSocket<S>>
+where
+    S: Read + Write,
+{
+    let mut remote_public = [0u8; dh::KEY_LEN];
+    sock.read_exact(&mut remote_public[..PLAINTEXT_PREFIX.len()])?;
+
+    if &remote_public[..PLAINTEXT_PREFIX.len()] == PLAINTEXT_PREFIX {
+        return if policy == EncryptionPolicy::Forced {
+            Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "Peer Sent A Plaintext Handshake",
+            ))
+        } else {
+            let prefix = PLAINTEXT_PREFIX.to_vec();
+
+            Ok(MseSocket::new(sock, None, None, prefix))
+        };
+    }
+    sock.read_exact(&mut remote_public[PLAINTEXT_PREFIX.len()..])?;
+    let secret = dh_keys.shared_secret(&remote_public)?;
+
+    let mut out = dh_keys.public_key().to_vec();
+    out.extend_from_slice(&pads.dh);
+    sock.write_all(&out)?;
+
+    sync_to(
+        &mut sock,
+        hash_of(b"req1", &secret).as_ref(),
+        MAX_PAD_LEN + sha::SHA_HASH_LEN,
+    )?;
+
+    let mut obfuscated = [0u8; sha::SHA_HASH_LEN];
+    sock.read_exact(&mut obfuscated)?;
+    let obfuscated = ShaHash::from(obfuscated) ^ hash_of(b"req3", &secret);
+
+    let hash = keys
+        .find(|hash| hash_of(b"req2", hash) == obfuscated)
+        .ok_or_else(|| invalid_data("Peer Requested An Unknown Info Hash"))?;
+
+    let mut decryptor = rc4_cipher(b"keyA", &secret, &hash);
+    let mut encryptor = rc4_cipher(b"keyB", &secret, &hash);
+
+    if read_decrypt(&mut sock, &mut decryptor, VC.len())? != VC {
+        return Err(invalid_data("Peer Sent An Invalid Verification Constant"));
+    }
+    let crypto_provide = BigEndian::read_u32(&read_decrypt(&mut sock, &mut decryptor, 4)?);
+    let pad_len = BigEndian::read_u16(&read_decrypt(&mut sock, &mut decryptor, 2)?) as usize;
+    check_pad_len(pad_len)?;
+    read_decrypt(&mut sock, &mut decryptor, pad_len)?;
+    let payload_len = BigEndian::read_u16(&read_decrypt(&mut sock, &mut decryptor, 2)?) as usize;
+    let payload = read_decrypt(&mut sock, &mut decryptor, payload_len)?;
+
+    let crypto_select = if crypto_provide & CRYPTO_RC4 != 0 {
+        CRYPTO_RC4
+    } else if crypto_provide & CRYPTO_PLAINTEXT != 0 && policy == EncryptionPolicy::Enabled {
+        CRYPTO_PLAINTEXT
+    } else {
+        return Err(invalid_data(
+            "Peer Did Not Provide An Acceptable Crypto Method",
+        ));
+    };
+
+    let mut plain = VC.to_vec();
+    plain.extend_from_slice(&u32_bytes(crypto_select));
+    plain.extend_from_slice(&u16_bytes(pads.crypto.len() as u16));
+    plain.extend_from_slice(&pads.crypto);
+    sock.write_all(&encrypt(&mut encryptor, &plain))?;
+
+    Ok(finish(sock, crypto_select, decryptor, encryptor, payload))
+}
+
+fn finish<S>(
+    sock: S,
+    crypto_select: u32,
+    decryptor: Rc4,
+    encryptor: Rc4,
+    pending: Vec<u8>,
+) -> MseSocket<S> {
+    if crypto_select == CRYPTO_RC4 {
+        MseSocket::new(sock, Some(decryptor), Some(encryptor), pending)
+    } else {
+        MseSocket::new(sock, None, None, pending)
+    }
+}
+
+/// Read one byte at a time until the last bytes read match `pattern`.
+fn sync_to<S>(sock: &mut S, pattern: &[u8], max_len: usize) -> io::Result<()>
+where
+    S: Read,
+{
+    let mut window = Vec::with_capacity(max_len);
+
+    while window.len() < max_len {
+        let mut byte = [0u8; 1];
+        sock.read_exact(&mut byte)?;
+        window.push(byte[0]);
+
+        if window.ends_with(pattern) {
+            return Ok(());
+        }
+    }
+
+    Err(invalid_data("Peer Sent Too Much Padding"))
+}
+
+fn read_decrypt<S>(sock: &mut S, cipher: &mut Rc4, len: usize) -> io::Result<Vec<u8>>
+where
+    S: Read,
+{
+    let mut buf = vec![0u8; len];
+    sock.read_exact(&mut buf)?;
+
+    Ok(encrypt(cipher, &buf))
+}
+
+fn encrypt(cipher: &mut Rc4, bytes: &[u8]) -> Vec<u8> {
+    let mut out = vec![0u8; bytes.len()];
+    cipher.process(bytes, &mut out);
+
+    out
+}
+
+fn rc4_cipher(name: &[u8], secret: &[u8], hash: &InfoHash) -> Rc4 {
+    let key = ShaHashBuilder::new()
+        .add_bytes(name)
+        .add_bytes(secret)
+        .add_bytes(&hash.truncated())
+        .build();
+    let mut cipher = Rc4::new(key.as_ref());
+
+    encrypt(&mut cipher, &[0u8; RC4_DISCARD_LEN]);
+
+    cipher
+}
+
+fn hash_of<B>(name: &[u8], bytes: B) -> ShaHash
+where
+    B: AsRef<[u8]>,
+{
+    ShaHashBuilder::new()
+        .add_bytes(name)
+        .add_bytes(bytes.as_ref())
+        .build()
+}
+
+fn check_pad_len(len: usize) -> io::Result<()> {
+    if len > MAX_PAD_LEN {
+        Err(invalid_data("Peer Sent Too Much Padding"))
+    } else {
+        Ok(())
+    }
+}
+
+fn invalid_data(message: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn u32_bytes(value: u32) -> [u8; 4] {
+    let mut bytes = [0u8; 4];
+    BigEndian::write_u32(&mut bytes, value);
+
+    bytes
+}
+
+fn u16_bytes(value: u16) -> [u8; 2] {
+    let mut bytes = [0u8; 2];
+    BigEndian::write_u16(&mut bytes, value);
+
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{self, Cursor, Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::thread;
+
+    use super::{Pads, CRYPTO_PLAINTEXT, CRYPTO_RC4};
+    use crate::handshake::mse::dh::DhKeys;
+    use crate::handshake::mse::{EncryptionPolicy, MseSocket, SecretKeys};
+    use crate::util::bt::InfoHash;
+
+    // Transcript of a handshake between two peers with fixed keys and pads, recorded with an
+    // independent implementation of the spec. The initiator provides plaintext and RC4, the
+    // responder selects RC4, then each side sends four payload bytes ("ping" and "pong").
+    const INITIATOR_TRANSCRIPT: &str = "96e112dab29e8c5272accb9b17b26887ce54a144a4e3b697c7d159b7a8\
+        17e556b0918db2b4c658e02a87f7e5fb14b18a553e084cbf3dad2d30f16596ccb982d406258c61b30c5c1dae2d\
+        dc60bdbd48d79896312aad63238c39e1a633821eb693010203361e3668027ecec9d1086d5f839d191781e8de17\
+        ff86a5c7383cb12b4912f17768e0688bd072e568cb54a65b377d988493cbbe06d20b9705f9fe97b0";
+    const RESPONDER_TRANSCRIPT: &str = "8f9c9f400fe9b3258f3e48598a95c7805cc90c995cd770283322679d13\
+        2ebdae09b75eeadc01de698ef86945cf38314a95fad08c2ad5641802bd5f658eb3ea0db2712c04aa5efed03dee\
+        cc5104f75d869d2d197e4336c61f0d71d763bee9941604055c8a3690b2051725a0ae37c265d4aa31984e32";
+    const PAYLOAD_LEN: usize = 4;
+
+    /// Socket reading from a fixed buffer and recording everything written to it.
+    struct Duplex {
+        input: Cursor<Vec<u8>>,
+        output: Vec<u8>,
+    }
+
+    impl Duplex {
+        fn new(input: Vec<u8>) -> Duplex {
+            Duplex {
+                input: Cursor::new(input),
+                output: Vec::new(),
+            }
+        }
+    }
+
+    impl Read for Duplex {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.input.read(buf)
+        }
+    }
+
+    impl Write for Duplex {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.output.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn from_hex(hex: &str) -> Vec<u8> {
+        (0..hex.len())
+            .step_by(2)
+            .map(|index| u8::from_str_radix(&hex[index..index + 2], 16).unwrap())
+            .collect()
+    }
+
+    fn transcript_hash() -> InfoHash {
+        [0xAA; 20].into()
+    }
+
+    fn initiator_keys() -> DhKeys {
+        DhKeys::from_private(&(0x01..0x15).collect::<Vec<u8>>())
+    }
+
+    fn responder_keys() -> DhKeys {
+        DhKeys::from_private(&(0x21..0x35).collect::<Vec<u8>>())
+    }
+
+    fn tcp_pair() -> (TcpStream, TcpStream) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let initiator = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (responder, _) = listener.accept().unwrap();
+
+        (initiator, responder)
+    }
+
+    fn keys_with(hash: InfoHash) -> SecretKeys {
+        let keys = SecretKeys::new();
+        keys.add_hash(hash);
+
+        keys
+    }
+
+    /// Run both sides over TCP, sending "ping" from the initiator and "pong" from the responder.
+    fn run_pair(
+        init_policy: EncryptionPolicy,
+        comp_policy: EncryptionPolicy,
+    ) -> (
+        io::Result<MseSocket<TcpStream>>,
+        io::Result<MseSocket<TcpStream>>,
+    ) {
+        let hash = transcript_hash();
+        let (init_sock, comp_sock) = tcp_pair();
+
+        let responder = thread::spawn(move || {
+            super::complete(comp_sock, &keys_with(hash), comp_policy).map(|mut sock| {
+                let mut buf = [0u8; PAYLOAD_LEN];
+                sock.read_exact(&mut buf).unwrap();
+                assert_eq!(b"ping", &buf);
+                sock.write_all(b"pong").unwrap();
+
+                sock
+            })
+        });
+
+        let initiated = super::initiate(init_sock, &hash, init_policy).map(|mut sock| {
+            sock.write_all(b"ping").unwrap();
+            let mut buf = [0u8; PAYLOAD_LEN];
+            sock.read_exact(&mut buf).unwrap();
+            assert_eq!(b"pong", &buf);
+
+            sock
+        });
+
+        (initiated, responder.join().unwrap())
+    }
+
+    #[test]
+    fn positive_initiate_matches_transcript() {
+        let responder = from_hex(RESPONDER_TRANSCRIPT);
+        let initiator = from_hex(INITIATOR_TRANSCRIPT);
+
+        let mut sock = super::initiate_with(
+            Duplex::new(responder),
+            &transcript_hash(),
+            CRYPTO_PLAINTEXT | CRYPTO_RC4,
+            initiator_keys(),
+            Pads::new(vec![1, 2, 3], Vec::new()),
+        )
+        .unwrap();
+
+        let mut buf = [0u8; PAYLOAD_LEN];
+        sock.read_exact(&mut buf).unwrap();
+        sock.write_all(b"ping").unwrap();
+
+        assert_eq!(b"pong", &buf);
+        assert!(sock.is_encrypted());
+        assert_eq!(initiator, sock.get_ref().output);
+    }
+
+    #[test]
+    fn positive_complete_matches_transcript() {
+        let responder = from_hex(RESPONDER_TRANSCRIPT);
+        let initiator = from_hex(INITIATOR_TRANSCRIPT);
+
+        let mut sock = super::complete_with(
+            Duplex::new(initiator),
+            &keys_with(transcript_hash()),
+            EncryptionPolicy::Enabled,
+            responder_keys(),
+            Pads::new(vec![4, 5], vec![6]),
+        )
+        .unwrap();
+
+        let mut buf = [0u8; PAYLOAD_LEN];
+        sock.read_exact(&mut buf).unwrap();
+        sock.write_all(b"pong").unwrap();
+
+        assert_eq!(b"ping", &buf);
+        assert!(sock.is_encrypted());
+        assert_eq!(responder, sock.get_ref().output);
+    }
+
+    #[test]
+    fn positive_enabled_negotiates_rc4() {
+        let (initiated, completed) = run_pair(EncryptionPolicy::Enabled, EncryptionPolicy::Enabled);
+
+        assert!(initiated.unwrap().is_encrypted());
+        assert!(completed.unwrap().is_encrypted());
+    }
+
+    #[test]
+    fn positive_forced_accepts_forced() {
+        let (initiated, completed) = run_pair(EncryptionPolicy::Forced, EncryptionPolicy::Forced);
+
+        assert!(initiated.unwrap().is_encrypted());
+        assert!(completed.unwrap().is_encrypted());
+    }
+
+    #[test]
+    fn positive_enabled_selects_plaintext_when_only_provided() {
+        let hash = transcript_hash();
+        let (init_sock, comp_sock) = tcp_pair();
+
+        let responder = thread::spawn(move || {
+            super::complete(comp_sock, &keys_with(hash), EncryptionPolicy::Enabled).unwrap()
+        });
+        let initiated = super::initiate_with(
+            init_sock,
+            &hash,
+            CRYPTO_PLAINTEXT,
+            DhKeys::generate(),
+            Pads::random(),
+        )
+        .unwrap();
+        let mut completed = responder.join().unwrap();
+
+        let mut raw = initiated.get_ref();
+        raw.write_all(b"ping").unwrap();
+        let mut buf = [0u8; PAYLOAD_LEN];
+        completed.read_exact(&mut buf).unwrap();
+
+        assert_eq!(b"ping", &buf);
+        assert!(!initiated.is_encrypted());
+        assert!(!completed.is_encrypted());
+    }
+
+    #[test]
+    fn positive_enabled_detects_plaintext_handshake() {
+        let mut bytes = b"\x13BitTorrent protocol".to_vec();
+        bytes.extend_from_slice(&[1, 2, 3]);
+
+        let mut sock = super::complete(
+            Duplex::new(bytes.clone()),
+            &SecretKeys::new(),
+            EncryptionPolicy::Enabled,
+        )
+        .unwrap();
+
+        // The bytes used to detect the handshake are handed back out
+        let mut read = Vec::new();
+        sock.read_to_end(&mut read).unwrap();
+
+        assert_eq!(bytes, read);
+        assert!(!sock.is_encrypted());
+        assert!(sock.get_ref().output.is_empty());
+    }
+
+    #[test]
+    fn negative_forced_rejects_plaintext_handshake() {
+        let bytes = b"\x13BitTorrent protocol".to_vec();
+
+        let result = super::complete(
+            Duplex::new(bytes),
+            &SecretKeys::new(),
+            EncryptionPolicy::Forced,
+        );
+
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn negative_forced_rejects_plaintext_crypto() {
+        let hash = transcript_hash();
+        let (init_sock, comp_sock) = tcp_pair();
+
+        let responder = thread::spawn(move || {
+            super::complete(comp_sock, &keys_with(hash), EncryptionPolicy::Forced).is_err()
+        });
+        let initiated = super::initiate_with(
+            init_sock,
+            &hash,
+            CRYPTO_PLAINTEXT,
+            DhKeys::generate(),
+            Pads::random(),
+        );
+
+        assert!(responder.join().unwrap());
+        assert!(initiated.is_err());
+    }
+
+    #[test]
+    fn negative_unknown_info_hash() {
+        let (init_sock, comp_sock) = tcp_pair();
+
+        let responder = thread::spawn(move || {
+            let keys = keys_with([0xBB; 20].into());
+
+            super::complete(comp_sock, &keys, EncryptionPolicy::Enabled).is_err()
+        });
+        let initiated = super::initiate(init_sock, &transcript_hash(), EncryptionPolicy::Enabled);
+
+        assert!(responder.join().unwrap());
+        assert!(initiated.is_err());
+    }
+}
@@ -0,0 +1,196 @@
+use std::fmt;
+use std::io::{self, Read, Write};
+
+use crypto::rc4::Rc4;
+use crypto::symmetriccipher::SynchronousStreamCipher;
+
+/// Socket returned from a handshake, which may be encrypted with MSE.
+///
+/// Reads and writes pass straight through for plaintext connections, otherwise
+/// they are run through the RC4 ciphers negotiated for each direction.
+pub struct MseSocket<S> {
+    sock: S,
+    read_cipher: Option<Rc4>,
+    write_cipher: Option<Rc4>,
+    pending: Vec<u8>,
+    pending_pos: usize,
+}
+
+impl<S> MseSocket<S> {
+    /// Wrap the socket without encrypting anything.
+    pub(crate) fn plaintext(sock: S) -> MseSocket<S> {
+        MseSocket::new(sock, None, None, Vec::new())
+    }
+
+    /// Wrap the socket, handing out the already decrypted `pending` bytes before reading from it.
+    pub(crate) fn new(
+        sock: S,
+        read_cipher: Option<Rc4>,
+        write_cipher: Option<Rc4>,
+        pending: Vec<u8>,
+    ) -> MseSocket<S> {
+        MseSocket {
+            sock: sock,
+            read_cipher: read_cipher,
+            write_cipher: write_cipher,
+            pending: pending,
+            pending_pos: 0,
+        }
+    }
+
+    /// Wrap another socket to the same peer, continuing from our cipher state.
+    ///
+    /// Ciphers are independent per direction, so each copy can be used for
+    /// either reading or writing, but not both.
+    pub(crate) fn with_socket<T>(&self, sock: T) -> MseSocket<T> {
+        MseSocket {
+            sock: sock,
+            read_cipher: self.read_cipher,
+            write_cipher: self.write_cipher,
+            pending: self.pending[self.pending_pos..].to_vec(),
+            pending_pos: 0,
+        }
+    }
+
+    /// Whether or not the payload stream is encrypted with RC4.
+    pub fn is_encrypted(&self) -> bool {
+        self.read_cipher.is_some()
+    }
+
+    /// Reference to the underlying socket.
+    pub fn get_ref(&self) -> &S {
+        &self.sock
+    }
+}
+
+impl<S> fmt::Debug for MseSocket<S>
+where
+    S: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("MseSocket")
+            .field("sock", &self.sock)
+            .field("encrypted", &self.is_encrypted())
+            .finish()
+    }
+}
+
+impl<S> Read for MseSocket<S>
+where
+    S: Read,
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pending_pos < self.pending.len() {
+            let remaining = &self.pending[self.pending_pos..];
+            let copy_len = remaining.len().min(buf.len());
+
+            buf[..copy_len].copy_from_slice(&remaining[..copy_len]);
+            self.pending_pos += copy_len;
+
+            return Ok(copy_len);
+        }
+
+        let read = self.sock.read(buf)?;
+
+        if let Some(ref mut cipher) = self.read_cipher {
+            let encrypted = buf[..read].to_vec();
+
+            cipher.process(&encrypted, &mut buf[..read]);
+        }
+
+        Ok(read)
+    }
+}
+
+impl<S> Write for MseSocket<S>
+where
+    S: Write,
+{
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self.write_cipher {
+            Some(ref mut cipher) => {
+                // The cipher has advanced past all of the bytes, so all of them have to go out
+                let mut encrypted = vec![0u8; buf.len()];
+                cipher.process(buf, &mut encrypted);
+
+                self.sock.write_all(&encrypted)?;
+
+                Ok(buf.len())
+            }
+            None => self.sock.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.sock.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Cursor, Read, Write};
+
+    use crypto::rc4::Rc4;
+
+    use super::MseSocket;
+
+    #[test]
+    fn positive_plaintext_passes_through() {
+        let mut sock = MseSocket::plaintext(Cursor::new(vec![1, 2, 3]));
+
+        let mut buf = [0u8; 3];
+        sock.read_exact(&mut buf).unwrap();
+
+        assert_eq!([1, 2, 3], buf);
+        assert!(!sock.is_encrypted());
+    }
+
+    #[test]
+    fn positive_reads_pending_before_socket() {
+        let mut sock = MseSocket::new(Cursor::new(vec![3, 4]), None, None, vec![1, 2]);
+
+        let mut buf = Vec::new();
+        sock.read_to_end(&mut buf).unwrap();
+
+        assert_eq!(vec![1, 2, 3, 4], buf);
+    }
+
+    #[test]
+    fn positive_encrypted_round_trip() {
+        let cipher = Rc4::new(b"key");
+        let mut writer = MseSocket::new(Cursor::new(Vec::new()), None, Some(cipher), Vec::new());
+
+        writer.write_all(b"hello ").unwrap();
+        writer.write_all(b"world").unwrap();
+        assert_ne!(&b"hello world"[..], &writer.get_ref().get_ref()[..]);
+
+        let written = writer.get_ref().get_ref().clone();
+        let mut reader = MseSocket::new(Cursor::new(written), Some(cipher), None, Vec::new());
+
+        let mut buf = Vec::new();
+        reader.read_to_end(&mut buf).unwrap();
+
+        assert_eq!(&b"hello world"[..], &buf[..]);
+        assert!(reader.is_encrypted());
+    }
+
+    #[test]
+    fn positive_with_socket_keeps_cipher_state() {
+        let cipher = Rc4::new(b"key");
+        let mut writer = MseSocket::new(Cursor::new(Vec::new()), None, Some(cipher), Vec::new());
+        writer.write_all(b"first").unwrap();
+
+        // The copy continues the key stream instead of starting it over
+        let mut copy = writer.with_socket(Cursor::new(Vec::new()));
+        copy.write_all(b"second").unwrap();
+
+        let mut written = writer.get_ref().get_ref().clone();
+        written.extend_from_slice(copy.get_ref().get_ref());
+
+        let mut reader = MseSocket::new(Cursor::new(written), Some(cipher), None, Vec::new());
+        let mut buf = Vec::new();
+        reader.read_to_end(&mut buf).unwrap();
+
+        assert_eq!(&b"firstsecond"[..], &buf[..]);
+    }
+}
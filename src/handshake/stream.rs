@@ -1,5 +1,8 @@
 use std::io;
 use crossbeam::channel::{Sender,Receiver};
+use futures::channel::mpsc;
+use futures::executor;
+use futures::stream::StreamExt;
 use std::io::{Error, ErrorKind};
 
 pub trait Stream: Send {
@@ -22,3 +25,15 @@ impl<T> Stream for  Receiver<T> where T :Send
         }
     }
 }
+
+impl<T> Stream for mpsc::Receiver<T>
+where
+    T: Send,
+{
+    type Item = T;
+
+    fn poll(&mut self) -> io::Result<Self::Item> {
+        executor::block_on(self.next())
+            .ok_or_else(|| Error::new(ErrorKind::NotFound, "Receiver recv not found"))
+    }
+}
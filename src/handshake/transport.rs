@@ -6,13 +6,34 @@ use std::io::{Read, Write, Error, ErrorKind};
 use std::net::{TcpStream, TcpListener};
 use std::net::{SocketAddr, Incoming};
 use std::option::Option::Some;
+use std::time::Duration;
 use super::stream::Stream;
 use crate::utp::{UtpSocket, UtpListener, UtpStream};
 
+/// Trait for sockets that can bound how long reads and writes block.
+pub trait TimeoutSocket {
+    /// Set the read and write timeout, `None` blocks indefinitely.
+    fn set_timeout(&self, opt_timeout: Option<Duration>) -> io::Result<()>;
+}
+
+impl TimeoutSocket for TcpStream {
+    fn set_timeout(&self, opt_timeout: Option<Duration>) -> io::Result<()> {
+        self.set_read_timeout(opt_timeout)?;
+        self.set_write_timeout(opt_timeout)
+    }
+}
+
+impl TimeoutSocket for UtpSocket {
+    /// uTP sockets time out on their own once retransmissions go unanswered.
+    fn set_timeout(&self, _opt_timeout: Option<Duration>) -> io::Result<()> {
+        Ok(())
+    }
+}
+
 /// Trait for initializing connections over an abstract `Transport`.
 pub trait Transport {
     /// Concrete socket.
-    type Socket: Read + Write + 'static;
+    type Socket: Read + Write + TimeoutSocket + 'static;
 
     /// Concrete listener.
     type Listener: Stream<Item = (Self::Socket, SocketAddr) > + LocalAddr + 'static;
@@ -20,6 +41,14 @@ pub trait Transport {
     /// Connect to the given address over this transport, using the supplied `Handle`.
     fn connect(&self, addr: &SocketAddr) -> io::Result<Self::Socket>;
 
+    /// Connect to the given address, giving up after `timeout`.
+    ///
+    /// Timeouts should fail with `io::ErrorKind::TimedOut`. Defaults to `connect`,
+    /// for transports that time out on their own.
+    fn connect_timeout(&self, addr: &SocketAddr, _timeout: Duration) -> io::Result<Self::Socket> {
+        self.connect(addr)
+    }
+
     /// Listen to the given address for this transport, using the supplied `Handle`.
     fn listen(&self, addr: &SocketAddr ) -> io::Result<Self::Listener>;
 }
@@ -37,6 +66,10 @@ impl Transport for TcpTransport {
         TcpStream::connect(addr)
     }
 
+    fn connect_timeout(&self, addr: &SocketAddr, timeout: Duration) -> io::Result<Self::Socket> {
+        TcpStream::connect_timeout(addr, timeout)
+    }
+
     fn listen(&self, addr: &SocketAddr) -> io::Result<Self::Listener> {
         let listener = TcpListener::bind(addr)?;
         let listen_addr = listener.local_addr()?;
@@ -140,12 +173,19 @@ impl Stream for UtpListenerStream  {
 pub mod test_transports {
     use std::io::{self, Cursor, Error, ErrorKind};
     use std::net::SocketAddr;
+    use std::time::Duration;
 
-    use super::Transport;
+    use super::{TimeoutSocket, Transport};
     use crate::handshake::LocalAddr;
     use crate::handshake::stream::Stream;
 
 
+    impl TimeoutSocket for Cursor<Vec<u8>> {
+        fn set_timeout(&self, _opt_timeout: Option<Duration>) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
     pub struct MockTransport;
 
     impl Transport for MockTransport {
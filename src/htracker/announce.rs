@@ -0,0 +1,673 @@
+//! Messaging primitives for announcing to http trackers.
+
+use std::fmt::Write;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+use crate::bencode::{BDecodeOpt, BDictAccess, BRefAccess, BencodeRef};
+use crate::htracker::error::{HttpTrackerError, HttpTrackerErrorKind, HttpTrackerResult};
+use crate::util::bt::{self, InfoHash, PeerId};
+use crate::utracker::announce::{AnnounceEvent, ClientState, DesiredPeers};
+
+pub const FAILURE_REASON_KEY: &'static [u8] = b"failure reason";
+const WARNING_MESSAGE_KEY: &'static [u8] = b"warning message";
+const INTERVAL_KEY: &'static [u8] = b"interval";
+const MIN_INTERVAL_KEY: &'static [u8] = b"min interval";
+const TRACKER_ID_KEY: &'static [u8] = b"tracker id";
+const COMPLETE_KEY: &'static [u8] = b"complete";
+const INCOMPLETE_KEY: &'static [u8] = b"incomplete";
+const PEERS_KEY: &'static [u8] = b"peers";
+const PEERS6_KEY: &'static [u8] = b"peers6";
+const PEER_ID_KEY: &'static [u8] = b"peer id";
+const PEER_IP_KEY: &'static [u8] = b"ip";
+const PEER_PORT_KEY: &'static [u8] = b"port";
+const EXTERNAL_IP_KEY: &'static [u8] = b"external ip";
+
+const COMPACT_PEER_V4_LEN: usize = 6;
+const COMPACT_PEER_V6_LEN: usize = 18;
+
+/// Announce request sent from the client to an http tracker.
+///
+/// Responses are always requested in the compact form, trackers are still free to
+/// answer with a list of peer dictionaries.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AnnounceRequest {
+    info_hash: InfoHash,
+    peer_id: PeerId,
+    state: ClientState,
+    key: u32,
+    num_want: DesiredPeers,
+    port: u16,
+    opt_tracker_id: Option<Vec<u8>>,
+    opt_reported_v4: Option<Ipv4Addr>,
+    opt_reported_v6: Option<Ipv6Addr>,
+}
+
+impl AnnounceRequest {
+    /// Create a new AnnounceRequest.
+    pub fn new(
+        hash: InfoHash,
+        peer_id: PeerId,
+        state: ClientState,
+        key: u32,
+        num_want: DesiredPeers,
+        port: u16,
+    ) -> AnnounceRequest {
+        AnnounceRequest {
+            info_hash: hash,
+            peer_id: peer_id,
+            state: state,
+            key: key,
+            num_want: num_want,
+            port: port,
+            opt_tracker_id: None,
+            opt_reported_v4: None,
+            opt_reported_v6: None,
+        }
+    }
+
+    /// Send back the tracker id given to us in a previous response of the tracker.
+    pub fn with_tracker_id(mut self, tracker_id: &[u8]) -> AnnounceRequest {
+        self.opt_tracker_id = Some(tracker_id.to_vec());
+
+        self
+    }
+
+    /// Ask the tracker to hand out the given address instead of the one we announce from.
+    ///
+    /// IPv4 addresses are sent as `ip` and IPv6 addresses as `ipv6` (BEP 7), so one address
+    /// of each version can be reported.
+    pub fn with_reported_ip(mut self, ip: IpAddr) -> AnnounceRequest {
+        match ip {
+            IpAddr::V4(v4_ip) => self.opt_reported_v4 = Some(v4_ip),
+            IpAddr::V6(v6_ip) => self.opt_reported_v6 = Some(v6_ip),
+        }
+
+        self
+    }
+
+    /// InfoHash of the current request.
+    pub fn info_hash(&self) -> InfoHash {
+        self.info_hash
+    }
+
+    /// PeerId of the current request.
+    pub fn peer_id(&self) -> PeerId {
+        self.peer_id
+    }
+
+    /// State reported by the client in the given request.
+    pub fn state(&self) -> ClientState {
+        self.state
+    }
+
+    /// Unique key randomized by the client that the server can use.
+    pub fn key(&self) -> u32 {
+        self.key
+    }
+
+    /// Number of peers desired by the client.
+    pub fn num_want(&self) -> DesiredPeers {
+        self.num_want
+    }
+
+    /// Port to send peers to us on.
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    /// Tracker id sent back to the tracker, if any.
+    pub fn tracker_id(&self) -> Option<&[u8]> {
+        self.opt_tracker_id.as_ref().map(|id| &id[..])
+    }
+
+    /// IPv4 address reported to the tracker, if any.
+    pub fn reported_ipv4(&self) -> Option<Ipv4Addr> {
+        self.opt_reported_v4
+    }
+
+    /// IPv6 address reported to the tracker, if any.
+    pub fn reported_ipv6(&self) -> Option<Ipv6Addr> {
+        self.opt_reported_v6
+    }
+
+    /// Percent encoded query string of the request, without a leading question mark.
+    pub fn query(&self) -> String {
+        let mut query = String::new();
+
+        query.push_str("info_hash=");
+        percent_encode(&self.info_hash.truncated(), &mut query);
+        query.push_str("&peer_id=");
+        percent_encode(self.peer_id.as_ref(), &mut query);
+        write!(
+            query,
+            "&port={}&uploaded={}&downloaded={}&left={}&compact=1&key={:08X}",
+            self.port,
+            self.state.bytes_uploaded(),
+            self.state.bytes_downloaded(),
+            self.state.bytes_left(),
+            self.key
+        )
+        .unwrap();
+
+        if let DesiredPeers::Specified(num_want) = self.num_want {
+            write!(query, "&numwant={}", num_want).unwrap();
+        }
+
+        match self.state.event() {
+            AnnounceEvent::None => (),
+            AnnounceEvent::Started => query.push_str("&event=started"),
+            AnnounceEvent::Stopped => query.push_str("&event=stopped"),
+            AnnounceEvent::Completed => query.push_str("&event=completed"),
+        }
+
+        if let Some(ref tracker_id) = self.opt_tracker_id {
+            query.push_str("&trackerid=");
+            percent_encode(tracker_id, &mut query);
+        }
+
+        if let Some(v4_ip) = self.opt_reported_v4 {
+            write!(query, "&ip={}", v4_ip).unwrap();
+        }
+        if let Some(v6_ip) = self.opt_reported_v6 {
+            query.push_str("&ipv6=");
+            percent_encode(v6_ip.to_string().as_bytes(), &mut query);
+        }
+
+        query
+    }
+}
+
+/// Percent encode every byte other than the unreserved characters of a uri.
+pub fn percent_encode(bytes: &[u8], output: &mut String) {
+    for &byte in bytes {
+        match byte {
+            b'0'..=b'9' | b'a'..=b'z' | b'A'..=b'Z' | b'-' | b'.' | b'_' | b'~' => {
+                output.push(byte as char)
+            }
+            _ => write!(output, "%{:02X}", byte).unwrap(),
+        }
+    }
+}
+
+// ----------------------------------------------------------------------------//
+
+/// Announce response sent from an http tracker to the client.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AnnounceResponse {
+    interval: u64,
+    opt_min_interval: Option<u64>,
+    opt_complete: Option<u64>,
+    opt_incomplete: Option<u64>,
+    opt_tracker_id: Option<Vec<u8>>,
+    opt_warning: Option<String>,
+    opt_external_ip: Option<IpAddr>,
+    peers: Vec<TrackerPeer>,
+}
+
+impl AnnounceResponse {
+    /// Create a new AnnounceResponse.
+    pub fn new(interval: u64, peers: Vec<TrackerPeer>) -> AnnounceResponse {
+        AnnounceResponse {
+            interval: interval,
+            opt_min_interval: None,
+            opt_complete: None,
+            opt_incomplete: None,
+            opt_tracker_id: None,
+            opt_warning: None,
+            opt_external_ip: None,
+            peers: peers,
+        }
+    }
+
+    /// Set the minimum number of seconds to wait before announcing again.
+    pub fn with_min_interval(mut self, min_interval: u64) -> AnnounceResponse {
+        self.opt_min_interval = Some(min_interval);
+
+        self
+    }
+
+    /// Set the number of seeders of the torrent.
+    pub fn with_complete(mut self, complete: u64) -> AnnounceResponse {
+        self.opt_complete = Some(complete);
+
+        self
+    }
+
+    /// Set the number of leechers of the torrent.
+    pub fn with_incomplete(mut self, incomplete: u64) -> AnnounceResponse {
+        self.opt_incomplete = Some(incomplete);
+
+        self
+    }
+
+    /// Set the warning message sent along with the response.
+    pub fn with_warning_message(mut self, warning: &str) -> AnnounceResponse {
+        self.opt_warning = Some(warning.to_owned());
+
+        self
+    }
+
+    /// Set the tracker id to send back on the next announce.
+    pub fn with_tracker_id(mut self, tracker_id: &[u8]) -> AnnounceResponse {
+        self.opt_tracker_id = Some(tracker_id.to_vec());
+
+        self
+    }
+
+    /// Set the address the tracker saw our announce come from.
+    pub fn with_external_ip(mut self, ip: IpAddr) -> AnnounceResponse {
+        self.opt_external_ip = Some(ip);
+
+        self
+    }
+
+    /// Parse the bencoded body of a tracker response.
+    ///
+    /// A response carrying a failure reason is returned as a `TrackerFailure` error. Peers
+    /// in the dictionary form without a valid ip address and port are skipped, as is an
+    /// external ip that is neither 4 nor 16 bytes long.
+    pub fn from_bytes(bytes: &[u8]) -> HttpTrackerResult<AnnounceResponse> {
+        let root_bencode = BencodeRef::decode(bytes, BDecodeOpt::default())?;
+        let root_dict = root_bencode
+            .dict()
+            .ok_or_else(|| invalid("Response Is Not A Dictionary"))?;
+
+        if let Some(reason) = root_dict.lookup(FAILURE_REASON_KEY) {
+            let reason = reason
+                .bytes()
+                .ok_or_else(|| invalid("Failure Reason Is Not A String"))?;
+
+            return Err(HttpTrackerErrorKind::TrackerFailure {
+                reason: String::from_utf8_lossy(reason).into_owned(),
+            }
+            .into());
+        }
+
+        let interval = lookup_count(root_dict, INTERVAL_KEY)?
+            .ok_or_else(|| invalid("Response Has No Interval"))?;
+        let opt_min_interval = lookup_count(root_dict, MIN_INTERVAL_KEY)?;
+        let opt_complete = lookup_count(root_dict, COMPLETE_KEY)?;
+        let opt_incomplete = lookup_count(root_dict, INCOMPLETE_KEY)?;
+
+        let opt_tracker_id = root_dict
+            .lookup(TRACKER_ID_KEY)
+            .and_then(|tracker_id| tracker_id.bytes())
+            .map(|tracker_id| tracker_id.to_vec());
+        let opt_warning = root_dict
+            .lookup(WARNING_MESSAGE_KEY)
+            .and_then(|warning| warning.bytes())
+            .map(|warning| String::from_utf8_lossy(warning).into_owned());
+        let opt_external_ip = root_dict
+            .lookup(EXTERNAL_IP_KEY)
+            .and_then(|external_ip| external_ip.bytes())
+            .and_then(parse_external_ip);
+
+        let mut peers = Vec::new();
+        if let Some(peers_bencode) = root_dict.lookup(PEERS_KEY) {
+            if let Some(compact) = peers_bencode.bytes() {
+                parse_compact_peers(compact, COMPACT_PEER_V4_LEN, &mut peers)?;
+            } else if let Some(list) = peers_bencode.list() {
+                peers.extend(
+                    list.into_iter()
+                        .filter_map(|peer| parse_dictionary_peer(peer)),
+                );
+            } else {
+                return Err(invalid("Peers Is Not A String Or A List"));
+            }
+        }
+
+        if let Some(peers6_bencode) = root_dict.lookup(PEERS6_KEY) {
+            let compact = peers6_bencode
+                .bytes()
+                .ok_or_else(|| invalid("Peers6 Is Not A String"))?;
+            parse_compact_peers(compact, COMPACT_PEER_V6_LEN, &mut peers)?;
+        }
+
+        Ok(AnnounceResponse {
+            interval: interval,
+            opt_min_interval: opt_min_interval,
+            opt_complete: opt_complete,
+            opt_incomplete: opt_incomplete,
+            opt_tracker_id: opt_tracker_id,
+            opt_warning: opt_warning,
+            opt_external_ip: opt_external_ip,
+            peers: peers,
+        })
+    }
+
+    /// Seconds the client should wait before announcing again.
+    pub fn interval(&self) -> u64 {
+        self.interval
+    }
+
+    /// Seconds the client must wait before announcing again, if given.
+    pub fn min_interval(&self) -> Option<u64> {
+        self.opt_min_interval
+    }
+
+    /// Number of peers with the complete torrent, if given.
+    pub fn complete(&self) -> Option<u64> {
+        self.opt_complete
+    }
+
+    /// Number of peers without the complete torrent, if given.
+    pub fn incomplete(&self) -> Option<u64> {
+        self.opt_incomplete
+    }
+
+    /// Tracker id to send back on our next announce, if given.
+    pub fn tracker_id(&self) -> Option<&[u8]> {
+        self.opt_tracker_id.as_ref().map(|id| &id[..])
+    }
+
+    /// Warning message of the tracker, if given.
+    pub fn warning_message(&self) -> Option<&str> {
+        self.opt_warning.as_ref().map(|warning| &warning[..])
+    }
+
+    /// Address the tracker saw our announce come from, if given (BEP 24).
+    pub fn external_ip(&self) -> Option<IpAddr> {
+        self.opt_external_ip
+    }
+
+    /// Peers sent by the tracker, both IPv4 and IPv6.
+    pub fn peers(&self) -> &[TrackerPeer] {
+        &self.peers
+    }
+}
+
+/// Peer sent by an http tracker.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct TrackerPeer {
+    addr: SocketAddr,
+    opt_peer_id: Option<PeerId>,
+}
+
+impl TrackerPeer {
+    /// Create a new TrackerPeer.
+    pub fn new(addr: SocketAddr, opt_peer_id: Option<PeerId>) -> TrackerPeer {
+        TrackerPeer {
+            addr: addr,
+            opt_peer_id: opt_peer_id,
+        }
+    }
+
+    /// Address of the peer.
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// PeerId of the peer, only sent in the dictionary form.
+    pub fn peer_id(&self) -> Option<PeerId> {
+        self.opt_peer_id
+    }
+}
+
+/// Error for a response with the given invalid details.
+pub fn invalid(details: &str) -> HttpTrackerError {
+    HttpTrackerErrorKind::InvalidResponse {
+        details: details.to_owned(),
+    }
+    .into()
+}
+
+/// Non negative integer under the given key, if present.
+pub fn lookup_count<B>(
+    dict: &dyn BDictAccess<B::BKey, B>,
+    key: &[u8],
+) -> HttpTrackerResult<Option<u64>>
+where
+    B: BRefAccess<BType = B>,
+{
+    match dict.lookup(key).map(|value| value.int()) {
+        Some(Some(count)) if count >= 0 => Ok(Some(count as u64)),
+        Some(_) => Err(invalid(&format!(
+            "{} Is Not A Non Negative Integer",
+            String::from_utf8_lossy(key)
+        ))),
+        None => Ok(None),
+    }
+}
+
+fn parse_compact_peers(
+    bytes: &[u8],
+    peer_len: usize,
+    peers: &mut Vec<TrackerPeer>,
+) -> HttpTrackerResult<()> {
+    if bytes.len() % peer_len != 0 {
+        return Err(invalid("Compact Peers Length Is Not A Multiple Of A Peer"));
+    }
+
+    for chunk in bytes.chunks(peer_len) {
+        let (ip_bytes, port_bytes) = chunk.split_at(peer_len - 2);
+        let ip = if peer_len == COMPACT_PEER_V4_LEN {
+            let mut octets = [0u8; 4];
+            octets.copy_from_slice(ip_bytes);
+
+            IpAddr::V4(Ipv4Addr::from(octets))
+        } else {
+            let mut octets = [0u8; 16];
+            octets.copy_from_slice(ip_bytes);
+
+            IpAddr::V6(Ipv6Addr::from(octets))
+        };
+        let port = ((port_bytes[0] as u16) << 8) | port_bytes[1] as u16;
+
+        peers.push(TrackerPeer::new(SocketAddr::new(ip, port), None));
+    }
+
+    Ok(())
+}
+
+fn parse_external_ip(bytes: &[u8]) -> Option<IpAddr> {
+    if let Ok(octets) = <[u8; 4]>::try_from(bytes) {
+        Some(IpAddr::V4(Ipv4Addr::from(octets)))
+    } else if let Ok(octets) = <[u8; 16]>::try_from(bytes) {
+        Some(IpAddr::V6(Ipv6Addr::from(octets)))
+    } else {
+        None
+    }
+}
+
+fn parse_dictionary_peer<B>(peer: &B) -> Option<TrackerPeer>
+where
+    B: BRefAccess<BType = B>,
+{
+    let peer_dict = peer.dict()?;
+
+    let ip: IpAddr = peer_dict.lookup(PEER_IP_KEY)?.str()?.parse().ok()?;
+    let port = peer_dict.lookup(PEER_PORT_KEY)?.int()?;
+    if port <= 0 || port > u16::max_value() as i64 {
+        return None;
+    }
+
+    let opt_peer_id = peer_dict
+        .lookup(PEER_ID_KEY)
+        .and_then(|peer_id| peer_id.bytes())
+        .filter(|peer_id| peer_id.len() == bt::PEER_ID_LEN)
+        .and_then(|peer_id| PeerId::from_hash(peer_id).ok());
+
+    Some(TrackerPeer::new(
+        SocketAddr::new(ip, port as u16),
+        opt_peer_id,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{IpAddr, SocketAddr};
+
+    use super::{AnnounceRequest, AnnounceResponse, TrackerPeer};
+    use crate::htracker::error::HttpTrackerErrorKind;
+    use crate::util::bt::{InfoHash, PeerId};
+    use crate::utracker::announce::{AnnounceEvent, ClientState, DesiredPeers};
+
+    #[test]
+    fn positive_query_encodes_raw_bytes() {
+        let mut hash = [0x41u8; 20];
+        hash[0] = 0x00;
+        hash[1] = 0xFF;
+        hash[2] = b'~';
+        hash[3] = b' ';
+
+        let request = AnnounceRequest::new(
+            InfoHash::from(hash),
+            PeerId::from(*b"-BP0300-abcdefghijkl"),
+            ClientState::new(10, 20, 30, AnnounceEvent::Started),
+            0xDEADBEEF,
+            DesiredPeers::Specified(50),
+            6881,
+        )
+        .with_tracker_id(b"id 1");
+
+        assert_eq!(
+            request.query(),
+            "info_hash=%00%FF~%20AAAAAAAAAAAAAAAA&peer_id=-BP0300-abcdefghijkl&port=6881\
+             &uploaded=30&downloaded=10&left=20&compact=1&key=DEADBEEF&numwant=50\
+             &event=started&trackerid=id%201"
+        );
+    }
+
+    #[test]
+    fn positive_query_omits_regular_event_and_default_numwant() {
+        let request = AnnounceRequest::new(
+            [0u8; 20].into(),
+            [0u8; 20].into(),
+            ClientState::new(0, 0, 0, AnnounceEvent::None),
+            1,
+            DesiredPeers::Default,
+            1,
+        );
+        let query = request.query();
+
+        assert!(!query.contains("event="));
+        assert!(!query.contains("numwant="));
+        assert!(!query.contains("trackerid="));
+        assert!(query.ends_with("&compact=1&key=00000001"));
+    }
+
+    #[test]
+    fn positive_query_reported_ips() {
+        let request = AnnounceRequest::new(
+            [0u8; 20].into(),
+            [0u8; 20].into(),
+            ClientState::new(0, 0, 0, AnnounceEvent::None),
+            1,
+            DesiredPeers::Default,
+            1,
+        )
+        .with_reported_ip("203.0.113.7".parse().unwrap())
+        .with_reported_ip("2001:db8::7".parse().unwrap());
+
+        assert!(request
+            .query()
+            .ends_with("&key=00000001&ip=203.0.113.7&ipv6=2001%3Adb8%3A%3A7"));
+    }
+
+    #[test]
+    fn positive_parse_external_ip() {
+        let mut v4_bytes = b"d11:external ip4:".to_vec();
+        v4_bytes.extend_from_slice(&[203, 0, 113, 7]);
+        v4_bytes.extend_from_slice(b"8:intervali60ee");
+        let mut v6_bytes = b"d11:external ip16:".to_vec();
+        v6_bytes.extend_from_slice(&[0x20, 0x01, 0x0D, 0xB8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 7]);
+        v6_bytes.extend_from_slice(b"8:intervali60ee");
+
+        assert_eq!(
+            AnnounceResponse::from_bytes(&v4_bytes)
+                .unwrap()
+                .external_ip(),
+            Some("203.0.113.7".parse::<IpAddr>().unwrap())
+        );
+        assert_eq!(
+            AnnounceResponse::from_bytes(&v6_bytes)
+                .unwrap()
+                .external_ip(),
+            Some("2001:db8::7".parse::<IpAddr>().unwrap())
+        );
+        assert_eq!(
+            AnnounceResponse::from_bytes(b"d11:external ip3:abc8:intervali60ee")
+                .unwrap()
+                .external_ip(),
+            None
+        );
+    }
+
+    #[test]
+    fn positive_parse_compact_response() {
+        let mut bytes =
+            b"d8:completei5e10:incompletei3e8:intervali1800e12:min intervali900e5:peers12:"
+                .to_vec();
+        bytes.extend_from_slice(&[127, 0, 0, 1, 0x1A, 0xE1, 10, 0, 0, 2, 0x00, 0x50]);
+        bytes.extend_from_slice(b"6:peers618:");
+        bytes.extend_from_slice(&[0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0x1A, 0xE2]);
+        bytes.extend_from_slice(b"10:tracker id3:abce");
+
+        let response = AnnounceResponse::from_bytes(&bytes).unwrap();
+        assert_eq!(response.interval(), 1800);
+        assert_eq!(response.min_interval(), Some(900));
+        assert_eq!(response.complete(), Some(5));
+        assert_eq!(response.incomplete(), Some(3));
+        assert_eq!(response.tracker_id(), Some(&b"abc"[..]));
+        assert_eq!(response.warning_message(), None);
+        assert_eq!(
+            response.peers(),
+            &[
+                TrackerPeer::new("127.0.0.1:6881".parse::<SocketAddr>().unwrap(), None),
+                TrackerPeer::new("10.0.0.2:80".parse::<SocketAddr>().unwrap(), None),
+                TrackerPeer::new("[::1]:6882".parse::<SocketAddr>().unwrap(), None),
+            ][..]
+        );
+    }
+
+    #[test]
+    fn positive_parse_dictionary_response() {
+        let bytes = b"d8:intervali60e5:peersld2:ip9:127.0.0.17:peer id20:-BP0300-abcdefghijkl\
+                      4:porti6881eed2:ip3:::14:porti80eed2:ip11:example.com4:porti80eeee";
+
+        let response = AnnounceResponse::from_bytes(&bytes[..]).unwrap();
+        assert_eq!(
+            response.peers(),
+            &[
+                TrackerPeer::new(
+                    "127.0.0.1:6881".parse::<SocketAddr>().unwrap(),
+                    Some(PeerId::from(*b"-BP0300-abcdefghijkl"))
+                ),
+                TrackerPeer::new("[::1]:80".parse::<SocketAddr>().unwrap(), None),
+            ][..]
+        );
+    }
+
+    #[test]
+    fn negative_parse_failure_reason() {
+        let error =
+            AnnounceResponse::from_bytes(b"d14:failure reason17:torrent not founde").unwrap_err();
+
+        match error.kind() {
+            HttpTrackerErrorKind::TrackerFailure { reason } => {
+                assert_eq!(reason, "torrent not found")
+            }
+            kind => panic!("Unexpected Error: {:?}", kind),
+        }
+    }
+
+    #[test]
+    fn negative_parse_missing_interval() {
+        let error = AnnounceResponse::from_bytes(b"d5:peers0:e").unwrap_err();
+
+        match error.kind() {
+            HttpTrackerErrorKind::InvalidResponse { .. } => (),
+            kind => panic!("Unexpected Error: {:?}", kind),
+        }
+    }
+
+    #[test]
+    fn negative_parse_truncated_compact_peers() {
+        let error = AnnounceResponse::from_bytes(b"d8:intervali60e5:peers5:abcdee").unwrap_err();
+
+        match error.kind() {
+            HttpTrackerErrorKind::InvalidResponse { .. } => (),
+            kind => panic!("Unexpected Error: {:?}", kind),
+        }
+    }
+}
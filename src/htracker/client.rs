@@ -0,0 +1,340 @@
+use std::io;
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures::future::BoxFuture;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpStream;
+use url::Url;
+
+use crate::bencode::{BDecodeOpt, BRefAccess, BencodeRef};
+use crate::htracker::announce::{AnnounceRequest, AnnounceResponse, FAILURE_REASON_KEY};
+use crate::htracker::error::{HttpTrackerError, HttpTrackerErrorKind, HttpTrackerResult};
+use crate::htracker::http::{self, HttpResponse};
+use crate::htracker::scrape::{self, ScrapeResponse};
+use crate::util::bt::InfoHash;
+use crate::util::proxy::{self, ProxyConfig, ProxyError};
+
+const DEFAULT_TIMEOUT_SECS: u64 = 30;
+const DEFAULT_MAX_REDIRECTS: usize = 5;
+const DEFAULT_MAX_RESPONSE_LEN: usize = 4 * 1024 * 1024;
+
+/// Stream that an http request can be sent over.
+pub trait HttpStream: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T> HttpStream for T where T: AsyncRead + AsyncWrite + Unpin + Send {}
+
+/// Trait for opening connections to trackers.
+///
+/// The default `TcpConnector` only supports plain http, trackers behind https can be
+/// reached by supplying a connector that wraps its connections in tls.
+pub trait HttpConnector: Send + Sync {
+    /// Whether or not connections for urls of the given scheme can be opened.
+    fn supports_scheme(&self, scheme: &str) -> bool;
+
+    /// Open a connection for a url of the given scheme to the given host and port.
+    fn connect(
+        &self,
+        scheme: &str,
+        host: &str,
+        port: u16,
+    ) -> BoxFuture<'static, io::Result<Box<dyn HttpStream>>>;
+}
+
+/// Connector opening plain tcp connections for http urls.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct TcpConnector;
+
+impl HttpConnector for TcpConnector {
+    fn supports_scheme(&self, scheme: &str) -> bool {
+        scheme == "http"
+    }
+
+    fn connect(
+        &self,
+        _scheme: &str,
+        host: &str,
+        port: u16,
+    ) -> BoxFuture<'static, io::Result<Box<dyn HttpStream>>> {
+        // IPv6 addresses are bracketed in urls, but not when resolved
+        let host = host
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+            .to_owned();
+
+        Box::pin(async move {
+            let stream = TcpStream::connect((&host[..], port)).await?;
+            stream.set_nodelay(true)?;
+
+            Ok(Box::new(stream) as Box<dyn HttpStream>)
+        })
+    }
+}
+
+/// Connector opening plain tcp connections for http urls through a proxy.
+///
+/// Connectors for https trackers can wrap the connections opened by `tunnel` in tls.
+#[derive(Clone, Debug)]
+pub struct ProxyConnector {
+    proxy: ProxyConfig,
+}
+
+impl ProxyConnector {
+    /// Create a new ProxyConnector for the given proxy.
+    pub fn new(proxy: ProxyConfig) -> ProxyConnector {
+        ProxyConnector { proxy: proxy }
+    }
+
+    /// Open a tcp connection to the given host and port through the proxy.
+    pub fn tunnel(&self, host: &str, port: u16) -> BoxFuture<'static, io::Result<TcpStream>> {
+        let proxy = self.proxy.clone();
+        let host = host.to_owned();
+
+        Box::pin(async move { proxy::connect(&proxy, &host, port).await })
+    }
+}
+
+impl HttpConnector for ProxyConnector {
+    fn supports_scheme(&self, scheme: &str) -> bool {
+        scheme == "http"
+    }
+
+    fn connect(
+        &self,
+        _scheme: &str,
+        host: &str,
+        port: u16,
+    ) -> BoxFuture<'static, io::Result<Box<dyn HttpStream>>> {
+        let tunnel = self.tunnel(host, port);
+
+        Box::pin(async move { Ok(Box::new(tunnel.await?) as Box<dyn HttpStream>) })
+    }
+}
+
+// ----------------------------------------------------------------------------//
+
+/// Client for announcing to http trackers.
+///
+/// Redirects (301, 302, 303, 307 and 308) are followed to their location, as is, up to
+/// the configured limit. Status codes other than 200 are returned as a `TrackerFailure`
+/// if the body still carries a failure reason, otherwise as an `HttpStatus` error.
+#[derive(Clone)]
+pub struct HttpTrackerClient {
+    connector: Arc<dyn HttpConnector>,
+    timeout: Duration,
+    max_redirects: usize,
+    max_response_len: usize,
+}
+
+impl HttpTrackerClient {
+    /// Create a new HttpTrackerClient for plain http trackers.
+    pub fn new() -> HttpTrackerClient {
+        HttpTrackerClient {
+            connector: Arc::new(TcpConnector),
+            timeout: Duration::from_secs(DEFAULT_TIMEOUT_SECS),
+            max_redirects: DEFAULT_MAX_REDIRECTS,
+            max_response_len: DEFAULT_MAX_RESPONSE_LEN,
+        }
+    }
+
+    /// Open connections to trackers with the given connector.
+    pub fn with_connector<C>(mut self, connector: C) -> HttpTrackerClient
+    where
+        C: HttpConnector + 'static,
+    {
+        self.connector = Arc::new(connector);
+
+        self
+    }
+
+    /// Open connections to plain http trackers through the given proxy.
+    ///
+    /// Shorthand for a `ProxyConnector`, connectors for https trackers have to use the proxy
+    /// themselves.
+    pub fn with_proxy(self, proxy: ProxyConfig) -> HttpTrackerClient {
+        self.with_connector(ProxyConnector::new(proxy))
+    }
+
+    /// Time allowed for each request, from connecting to reading the whole response.
+    pub fn with_timeout(mut self, timeout: Duration) -> HttpTrackerClient {
+        self.timeout = timeout;
+
+        self
+    }
+
+    /// Maximum number of redirects followed for a single announce.
+    pub fn with_max_redirects(mut self, max_redirects: usize) -> HttpTrackerClient {
+        self.max_redirects = max_redirects;
+
+        self
+    }
+
+    /// Maximum length of a response, in bytes.
+    pub fn with_max_response_len(mut self, max_response_len: usize) -> HttpTrackerClient {
+        self.max_response_len = max_response_len;
+
+        self
+    }
+
+    /// Announce to the tracker at the given announce url.
+    pub async fn announce(
+        &self,
+        tracker: &Url,
+        request: &AnnounceRequest,
+    ) -> HttpTrackerResult<AnnounceResponse> {
+        let body = self.fetch(with_query(tracker, &request.query())).await?;
+
+        AnnounceResponse::from_bytes(&body)
+    }
+
+    /// Scrape the given hashes from the tracker at the given announce url.
+    ///
+    /// The scrape url is derived from the announce url, failing with a `ScrapeNotSupported`
+    /// error if it does not follow the scrape convention. All hashes are sent in a single
+    /// request, an empty list of hashes asks the tracker for every torrent it tracks.
+    pub async fn scrape(
+        &self,
+        tracker: &Url,
+        hashes: &[InfoHash],
+    ) -> HttpTrackerResult<ScrapeResponse> {
+        let url = scrape::scrape_url(tracker)?;
+        let body = self.fetch(with_query(&url, &scrape::query(hashes))).await?;
+
+        ScrapeResponse::from_bytes(&body)
+    }
+
+    /// Get the body of a successful response for the given url, following redirects.
+    async fn fetch(&self, mut url: Url) -> HttpTrackerResult<Vec<u8>> {
+        let mut redirects = 0;
+        loop {
+            let response = self.get(&url).await?;
+
+            match response.status() {
+                200 => return Ok(response.body().to_vec()),
+                301 | 302 | 303 | 307 | 308 => {
+                    if redirects == self.max_redirects {
+                        return Err(HttpTrackerErrorKind::TooManyRedirects {
+                            limit: self.max_redirects,
+                        }
+                        .into());
+                    }
+                    redirects += 1;
+
+                    let location = response.header("location").ok_or_else(|| {
+                        HttpTrackerErrorKind::InvalidResponse {
+                            details: "Redirect Has No Location".to_owned(),
+                        }
+                    })?;
+                    url = url
+                        .join(location)
+                        .map_err(|_| HttpTrackerErrorKind::InvalidUrl {
+                            url: location.to_owned(),
+                        })?;
+                }
+                status => {
+                    return Err(match failure_reason(response.body()) {
+                        Some(reason) => HttpTrackerErrorKind::TrackerFailure { reason: reason },
+                        None => HttpTrackerErrorKind::HttpStatus {
+                            status: status,
+                            reason: response.reason().to_owned(),
+                        },
+                    }
+                    .into())
+                }
+            }
+        }
+    }
+
+    /// Send a single GET for the given url, returning the response.
+    async fn get(&self, url: &Url) -> HttpTrackerResult<HttpResponse> {
+        if !self.connector.supports_scheme(&url.scheme) {
+            return Err(HttpTrackerErrorKind::UnsupportedScheme {
+                scheme: url.scheme.clone(),
+            }
+            .into());
+        }
+
+        let invalid_url = || HttpTrackerErrorKind::InvalidUrl {
+            url: url.serialize(),
+        };
+        let host = url.serialize_host().ok_or_else(invalid_url)?;
+        let port = url.port_or_default().ok_or_else(invalid_url)?;
+        let host_header = match url.port() {
+            Some(port) => format!("{}:{}", host, port),
+            None => host.clone(),
+        };
+        let target = match url.query {
+            Some(ref query) => format!("{}?{}", url.serialize_path().unwrap(), query),
+            None => url.serialize_path().unwrap(),
+        };
+
+        let exchange = async {
+            let mut stream = self.connector.connect(&url.scheme, &host, port).await?;
+            stream
+                .write_all(&http::get_request(&target, &host_header))
+                .await?;
+            stream.flush().await?;
+
+            let mut bytes = Vec::new();
+            let bytes_read = (&mut stream)
+                .take(self.max_response_len as u64 + 1)
+                .read_to_end(&mut bytes)
+                .await?;
+            if bytes_read > self.max_response_len {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "Tracker Response Exceeds The Maximum Length",
+                ));
+            }
+
+            Ok(bytes)
+        };
+
+        let bytes = match tokio::time::timeout(self.timeout, exchange).await {
+            Ok(result) => result.map_err(io_error)?,
+            Err(_) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    "Tracker Did Not Respond In Time",
+                )
+                .into())
+            }
+        };
+
+        HttpResponse::from_bytes(&bytes)
+    }
+}
+
+/// Url with the given query appended to any query it already has, without a fragment.
+fn with_query(url: &Url, query: &str) -> Url {
+    let mut url = url.clone();
+    url.fragment = None;
+    url.query = Some(match url.query.take() {
+        Some(ref existing) if !existing.is_empty() => format!("{}&{}", existing, query),
+        _ => query.to_owned(),
+    });
+
+    url
+}
+
+/// Error for an io error while talking to a tracker, telling failures of a proxy apart.
+pub fn io_error(error: io::Error) -> HttpTrackerError {
+    match ProxyError::from_io(&error) {
+        Some(proxy_error) => HttpTrackerErrorKind::ProxyFailure { error: proxy_error }.into(),
+        None => error.into(),
+    }
+}
+
+/// Failure reason carried in the body of a response, if any.
+fn failure_reason(body: &[u8]) -> Option<String> {
+    let root_bencode = BencodeRef::decode(body, BDecodeOpt::default()).ok()?;
+    let reason = root_bencode.dict()?.lookup(FAILURE_REASON_KEY)?.bytes()?;
+
+    Some(String::from_utf8_lossy(reason).into_owned())
+}
+
+impl Default for HttpTrackerClient {
+    fn default() -> HttpTrackerClient {
+        HttpTrackerClient::new()
+    }
+}
@@ -0,0 +1,69 @@
+//! Errors for announcing to http trackers.
+
+use std::io;
+
+use crate::bencode::BencodeParseError;
+use crate::util::proxy::ProxyError;
+
+error_chain! {
+    types {
+        HttpTrackerError, HttpTrackerErrorKind, HttpTrackerResultExt, HttpTrackerResult;
+    }
+
+    foreign_links {
+        Io(io::Error);
+        BencodeParse(BencodeParseError);
+    }
+
+    errors {
+        TrackerFailure {
+            reason: String
+        } {
+            description("Tracker Refused The Announce")
+            display("Tracker Refused The Announce: {}", reason)
+        }
+        InvalidResponse {
+            details: String
+        } {
+            description("Tracker Sent An Invalid Response")
+            display("Tracker Sent An Invalid Response: {}", details)
+        }
+        HttpStatus {
+            status: u16,
+            reason: String
+        } {
+            description("Tracker Responded With A Non Success Status")
+            display("Tracker Responded With Status {} {}", status, reason)
+        }
+        TooManyRedirects {
+            limit: usize
+        } {
+            description("Tracker Redirected Too Many Times")
+            display("Tracker Redirected More Than {} Times", limit)
+        }
+        UnsupportedScheme {
+            scheme: String
+        } {
+            description("Tracker Url Scheme Is Not Supported By The Connector")
+            display("Tracker Url Scheme {:?} Is Not Supported By The Connector", scheme)
+        }
+        ScrapeNotSupported {
+            url: String
+        } {
+            description("Tracker Does Not Follow The Scrape Convention")
+            display("Tracker Url {:?} Does Not Follow The Scrape Convention", url)
+        }
+        ProxyFailure {
+            error: ProxyError
+        } {
+            description("Tracker Could Not Be Reached Through The Proxy")
+            display("Tracker Could Not Be Reached Through The Proxy: {}", error)
+        }
+        InvalidUrl {
+            url: String
+        } {
+            description("Tracker Url Is Invalid")
+            display("Tracker Url {:?} Is Invalid", url)
+        }
+    }
+}
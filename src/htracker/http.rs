@@ -0,0 +1,214 @@
+//! Just enough of HTTP/1.1 to send an announce and read back the response of a tracker,
+//! or to fetch a range of a file from a web seed.
+
+use std::str;
+
+use crate::htracker::error::{HttpTrackerError, HttpTrackerErrorKind, HttpTrackerResult};
+
+const HEADER_END: &'static [u8] = b"\r\n\r\n";
+const LINE_END: &'static [u8] = b"\r\n";
+
+/// Response read from a tracker.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HttpResponse {
+    status: u16,
+    reason: String,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+impl HttpResponse {
+    /// Parse a complete response, read until the tracker closed the connection.
+    pub fn from_bytes(bytes: &[u8]) -> HttpTrackerResult<HttpResponse> {
+        let header_end = find(bytes, HEADER_END).ok_or_else(|| invalid("Incomplete Header"))?;
+        let header = str::from_utf8(&bytes[..header_end])
+            .map_err(|_| invalid("Header Is Not Valid Utf8"))?;
+        let mut lines = header.split("\r\n");
+
+        let status_line = lines.next().unwrap_or("");
+        let mut status_parts = status_line.splitn(3, ' ');
+        if !status_parts.next().unwrap_or("").starts_with("HTTP/1.") {
+            return Err(invalid("Invalid Status Line"));
+        }
+        let status = status_parts
+            .next()
+            .and_then(|status| status.parse().ok())
+            .ok_or_else(|| invalid("Invalid Status Code"))?;
+        let reason = status_parts.next().unwrap_or("").to_owned();
+
+        let mut headers = Vec::new();
+        for line in lines {
+            let colon = line
+                .find(':')
+                .ok_or_else(|| invalid("Invalid Header Line"))?;
+
+            headers.push((
+                line[..colon].trim().to_ascii_lowercase(),
+                line[colon + 1..].trim().to_owned(),
+            ));
+        }
+
+        let mut response = HttpResponse {
+            status: status,
+            reason: reason,
+            headers: headers,
+            body: Vec::new(),
+        };
+
+        let content = &bytes[header_end + HEADER_END.len()..];
+        response.body = if response
+            .header("transfer-encoding")
+            .map_or(false, |encoding| encoding.eq_ignore_ascii_case("chunked"))
+        {
+            decode_chunked(content)?
+        } else if let Some(length) = response.header("content-length") {
+            let length: usize = length
+                .parse()
+                .map_err(|_| invalid("Invalid Content Length"))?;
+            if content.len() < length {
+                return Err(invalid("Body Shorter Than Content Length"));
+            }
+
+            content[..length].to_vec()
+        } else {
+            content.to_vec()
+        };
+
+        Ok(response)
+    }
+
+    /// Status code of the response.
+    pub fn status(&self) -> u16 {
+        self.status
+    }
+
+    /// Reason phrase of the response.
+    pub fn reason(&self) -> &str {
+        &self.reason
+    }
+
+    /// Value of the first header with the given lowercase name.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|&&(ref header, _)| header == name)
+            .map(|&(_, ref value)| &value[..])
+    }
+
+    /// Decoded body of the response.
+    pub fn body(&self) -> &[u8] {
+        &self.body
+    }
+}
+
+/// Request head of a GET for the given target, asking the tracker to close the connection.
+pub fn get_request(target: &str, host: &str) -> Vec<u8> {
+    request_head(target, host, "")
+}
+
+/// Request head of a GET for the inclusive byte range of the given target, as sent to web seeds.
+pub fn get_range_request(target: &str, host: &str, first: u64, last: u64) -> Vec<u8> {
+    request_head(
+        target,
+        host,
+        &format!("Range: bytes={}-{}\r\n", first, last),
+    )
+}
+
+fn request_head(target: &str, host: &str, extra_headers: &str) -> Vec<u8> {
+    format!(
+        "GET {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: bittorrent-protocol\r\n\
+         Accept-Encoding: identity\r\n{}Connection: close\r\n\r\n",
+        target, host, extra_headers
+    )
+    .into_bytes()
+}
+
+fn decode_chunked(mut content: &[u8]) -> HttpTrackerResult<Vec<u8>> {
+    let mut body = Vec::new();
+
+    loop {
+        let line_end = find(content, LINE_END).ok_or_else(|| invalid("Incomplete Chunk Size"))?;
+        let size_line =
+            str::from_utf8(&content[..line_end]).map_err(|_| invalid("Invalid Chunk Size"))?;
+        let size_hex = size_line.split(';').next().unwrap_or("").trim();
+        let size =
+            usize::from_str_radix(size_hex, 16).map_err(|_| invalid("Invalid Chunk Size"))?;
+        content = &content[line_end + LINE_END.len()..];
+
+        if size == 0 {
+            return Ok(body);
+        }
+
+        if content.len() < size + LINE_END.len()
+            || &content[size..size + LINE_END.len()] != LINE_END
+        {
+            return Err(invalid("Incomplete Chunk"));
+        }
+        body.extend_from_slice(&content[..size]);
+        content = &content[size + LINE_END.len()..];
+    }
+}
+
+fn find(bytes: &[u8], pattern: &[u8]) -> Option<usize> {
+    bytes
+        .windows(pattern.len())
+        .position(|window| window == pattern)
+}
+
+fn invalid(details: &str) -> HttpTrackerError {
+    HttpTrackerErrorKind::InvalidResponse {
+        details: format!("Http {}", details),
+    }
+    .into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::HttpResponse;
+
+    #[test]
+    fn positive_parse_content_length() {
+        let response = HttpResponse::from_bytes(
+            b"HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\nContent-Length: 5\r\n\r\nhello trailing",
+        )
+        .unwrap();
+
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.reason(), "OK");
+        assert_eq!(response.header("content-type"), Some("text/plain"));
+        assert_eq!(response.body(), b"hello");
+    }
+
+    #[test]
+    fn positive_parse_chunked() {
+        let response = HttpResponse::from_bytes(
+            b"HTTP/1.1 200 OK\r\nTransfer-Encoding: Chunked\r\n\r\n4\r\nd8:i\r\nA;ext=1\r\nntervali1e\r\n1\r\ne\r\n0\r\n\r\n",
+        )
+        .unwrap();
+
+        assert_eq!(response.body(), b"d8:intervali1ee");
+    }
+
+    #[test]
+    fn positive_parse_until_close() {
+        let response = HttpResponse::from_bytes(b"HTTP/1.0 404 Not Found\r\n\r\nmissing").unwrap();
+
+        assert_eq!(response.status(), 404);
+        assert_eq!(response.reason(), "Not Found");
+        assert_eq!(response.body(), b"missing");
+    }
+
+    #[test]
+    fn negative_parse_incomplete() {
+        assert!(HttpResponse::from_bytes(b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n").is_err());
+        assert!(
+            HttpResponse::from_bytes(b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nhey").is_err()
+        );
+        assert!(HttpResponse::from_bytes(
+            b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhey"
+        )
+        .is_err());
+        assert!(HttpResponse::from_bytes(b"SSH-2.0\r\n\r\n").is_err());
+    }
+}
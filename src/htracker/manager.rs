@@ -0,0 +1,595 @@
+//! Announcing a single torrent to the tiers of trackers of its announce-list.
+
+use std::cmp;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use futures::future::BoxFuture;
+use tokio::sync::{mpsc, oneshot};
+use tokio::time::{self, Instant};
+
+use crate::htracker::announce::{AnnounceRequest, AnnounceResponse};
+use crate::htracker::client::HttpTrackerClient;
+use crate::htracker::error::{HttpTrackerErrorKind, HttpTrackerResult};
+use crate::metainfo::{TrackerProtocol, TrackerUrl};
+use crate::util::bt::InfoHash;
+use crate::utracker::announce::{AnnounceEvent, ClientState, DesiredPeers};
+use crate::utracker::Handshaker;
+
+const DEFAULT_RETRY_DELAY_SECS: u64 = 15;
+const DEFAULT_MAX_RETRY_DELAY_SECS: u64 = 30 * 60;
+const DEFAULT_STOP_TIMEOUT_SECS: u64 = 5;
+
+/// Trackers asking us to announce more often than this are not listened to.
+const MIN_ANNOUNCE_INTERVAL_SECS: u64 = 30;
+
+/// Trait for sending announces to trackers on behalf of a `TrackerManager`.
+pub trait TrackerTransport: Send + Sync {
+    /// Send the announce to the given tracker.
+    fn send_announce(
+        &self,
+        tracker: &TrackerUrl,
+        request: &AnnounceRequest,
+    ) -> BoxFuture<'static, HttpTrackerResult<AnnounceResponse>>;
+}
+
+impl TrackerTransport for HttpTrackerClient {
+    fn send_announce(
+        &self,
+        tracker: &TrackerUrl,
+        request: &AnnounceRequest,
+    ) -> BoxFuture<'static, HttpTrackerResult<AnnounceResponse>> {
+        let (client, url, request) = (self.clone(), tracker.url().clone(), request.clone());
+        let protocol = tracker.protocol();
+
+        Box::pin(async move {
+            if let TrackerProtocol::Udp | TrackerProtocol::Ws | TrackerProtocol::Wss = protocol {
+                return Err(HttpTrackerErrorKind::UnsupportedScheme {
+                    scheme: url.scheme.clone(),
+                }
+                .into());
+            }
+
+            client.announce(&url, &request).await
+        })
+    }
+}
+
+// ----------------------------------------------------------------------------//
+
+/// Event observed by a `TrackerManager` while announcing.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TrackerEvent {
+    /// Tracker reported the address it saw our announce come from.
+    ///
+    /// Sent for every response carrying an external ip, trackers disagreeing on our address
+    /// are left for the receiver to weigh.
+    ExternalIpObserved(TrackerUrl, IpAddr),
+}
+
+/// Status of a single tracker of a `TrackerManager`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TrackerStatus {
+    url: TrackerUrl,
+    tier: usize,
+    opt_last_announce: Option<Instant>,
+    opt_last_error: Option<String>,
+    peers_returned: usize,
+    failures: u32,
+    opt_retry_at: Option<Instant>,
+    opt_tracker_id: Option<Vec<u8>>,
+    opt_external_ip: Option<IpAddr>,
+    opt_seeders: Option<u64>,
+    opt_leechers: Option<u64>,
+}
+
+impl TrackerStatus {
+    fn new(url: TrackerUrl, tier: usize) -> TrackerStatus {
+        TrackerStatus {
+            url: url,
+            tier: tier,
+            opt_last_announce: None,
+            opt_last_error: None,
+            peers_returned: 0,
+            failures: 0,
+            opt_retry_at: None,
+            opt_tracker_id: None,
+            opt_external_ip: None,
+            opt_seeders: None,
+            opt_leechers: None,
+        }
+    }
+
+    /// Announce url of the tracker.
+    pub fn url(&self) -> &TrackerUrl {
+        &self.url
+    }
+
+    /// Index of the tier the tracker is in.
+    pub fn tier(&self) -> usize {
+        self.tier
+    }
+
+    /// Time of the last successful announce to the tracker, if any.
+    pub fn last_announce(&self) -> Option<Instant> {
+        self.opt_last_announce
+    }
+
+    /// Error of the last announce to the tracker, if it failed.
+    pub fn last_error(&self) -> Option<&str> {
+        self.opt_last_error.as_ref().map(|error| &error[..])
+    }
+
+    /// Number of peers returned by the last successful announce to the tracker.
+    pub fn peers_returned(&self) -> usize {
+        self.peers_returned
+    }
+
+    /// Number of announces to the tracker that failed in a row.
+    pub fn failures(&self) -> u32 {
+        self.failures
+    }
+
+    /// Time before which the tracker is not announced to again, after failing.
+    pub fn retry_at(&self) -> Option<Instant> {
+        self.opt_retry_at
+    }
+
+    /// Address the tracker last reported our announces coming from, if it ever did.
+    pub fn external_ip(&self) -> Option<IpAddr> {
+        self.opt_external_ip
+    }
+
+    /// Number of peers with the complete torrent, as last reported by the tracker.
+    pub fn seeders(&self) -> Option<u64> {
+        self.opt_seeders
+    }
+
+    /// Number of peers without the complete torrent, as last reported by the tracker.
+    pub fn leechers(&self) -> Option<u64> {
+        self.opt_leechers
+    }
+}
+
+// ----------------------------------------------------------------------------//
+
+/// Builder for configuring and spawning a `TrackerManager`.
+#[derive(Clone, Debug)]
+pub struct TrackerManagerBuilder {
+    num_want: DesiredPeers,
+    retry_delay: Duration,
+    max_retry_delay: Duration,
+    stop_timeout: Duration,
+    reported_ips: Vec<IpAddr>,
+    opt_events: Option<mpsc::UnboundedSender<TrackerEvent>>,
+}
+
+impl TrackerManagerBuilder {
+    /// Create a new `TrackerManagerBuilder`.
+    pub fn new() -> TrackerManagerBuilder {
+        TrackerManagerBuilder {
+            num_want: DesiredPeers::Default,
+            retry_delay: Duration::from_secs(DEFAULT_RETRY_DELAY_SECS),
+            max_retry_delay: Duration::from_secs(DEFAULT_MAX_RETRY_DELAY_SECS),
+            stop_timeout: Duration::from_secs(DEFAULT_STOP_TIMEOUT_SECS),
+            reported_ips: Vec::new(),
+            opt_events: None,
+        }
+    }
+
+    /// Number of peers asked for in each announce.
+    pub fn with_num_want(mut self, num_want: DesiredPeers) -> TrackerManagerBuilder {
+        self.num_want = num_want;
+
+        self
+    }
+
+    /// Delay before announcing to a tracker again after its first failure, doubling with
+    /// every failure after that.
+    pub fn with_retry_delay(mut self, delay: Duration) -> TrackerManagerBuilder {
+        self.retry_delay = delay;
+
+        self
+    }
+
+    /// Maximum delay before announcing to a failing tracker again.
+    pub fn with_max_retry_delay(mut self, delay: Duration) -> TrackerManagerBuilder {
+        self.max_retry_delay = delay;
+
+        self
+    }
+
+    /// Time allowed for the stopped announce when the manager is stopped.
+    pub fn with_stop_timeout(mut self, timeout: Duration) -> TrackerManagerBuilder {
+        self.stop_timeout = timeout;
+
+        self
+    }
+
+    /// Ask trackers to hand out the given address instead of the one we announce from.
+    ///
+    /// Can be given an IPv4 and an IPv6 address, see `AnnounceRequest::with_reported_ip`.
+    pub fn with_reported_ip(mut self, ip: IpAddr) -> TrackerManagerBuilder {
+        self.reported_ips.push(ip);
+
+        self
+    }
+
+    /// Send events observed while announcing to the given channel.
+    pub fn with_events(
+        mut self,
+        events: mpsc::UnboundedSender<TrackerEvent>,
+    ) -> TrackerManagerBuilder {
+        self.opt_events = Some(events);
+
+        self
+    }
+
+    /// Spawn a `TrackerManager` announcing the torrent to the given tiers of trackers.
+    ///
+    /// Discovered peers, along with our peer id and port, go through the handshaker.
+    ///
+    /// Must be called from within a tokio runtime.
+    pub fn build<T, H>(
+        self,
+        hash: InfoHash,
+        tiers: Vec<Vec<TrackerUrl>>,
+        state: ClientState,
+        transport: T,
+        handshaker: H,
+    ) -> TrackerManager
+    where
+        T: TrackerTransport + 'static,
+        H: Handshaker + 'static,
+    {
+        let tiers = Arc::new(Mutex::new(
+            tiers
+                .into_iter()
+                .enumerate()
+                .map(|(index, tier)| {
+                    tier.into_iter()
+                        .map(|url| TrackerStatus::new(url, index))
+                        .collect::<Vec<TrackerStatus>>()
+                })
+                .collect::<Vec<Vec<TrackerStatus>>>(),
+        ));
+        let (send, recv) = mpsc::unbounded_channel();
+
+        let now = Instant::now();
+        let task = ManagerTask {
+            info_hash: hash,
+            transport: transport,
+            handshaker: handshaker,
+            config: self,
+            key: rand::random::<u32>(),
+            state: state,
+            tiers: tiers.clone(),
+            started: false,
+            completed_pending: false,
+            completed_sent: false,
+            opt_last_tracker: None,
+            next_announce: now,
+            min_interval_until: now,
+        };
+        tokio::spawn(task.run(recv));
+
+        TrackerManager {
+            send: send,
+            tiers: tiers,
+        }
+    }
+}
+
+// ----------------------------------------------------------------------------//
+
+enum ManagerMessage {
+    State(ClientState),
+    Completed,
+    Stop(oneshot::Sender<()>),
+}
+
+/// Handle to the task announcing a single torrent to its trackers.
+///
+/// Announces `started` as soon as it is spawned, then re-announces at the interval given by
+/// the tracker, never more often than its minimum interval. Within a tier, the tracker that
+/// answers is moved to the front; when a whole tier fails, the next tier is tried, and
+/// failing trackers are retried with an exponential backoff.
+///
+/// Dropping the handle stops the manager just like `stop`, without waiting for it.
+pub struct TrackerManager {
+    send: mpsc::UnboundedSender<ManagerMessage>,
+    tiers: Arc<Mutex<Vec<Vec<TrackerStatus>>>>,
+}
+
+impl TrackerManager {
+    /// Update the amounts reported on the next announce, the event of the state is ignored.
+    pub fn update_state(&self, state: ClientState) {
+        let _ = self.send.send(ManagerMessage::State(state));
+    }
+
+    /// Announce `completed` to the trackers, only the first call has any effect.
+    pub fn completed(&self) {
+        let _ = self.send.send(ManagerMessage::Completed);
+    }
+
+    /// Status of every tracker, in the order they will be tried in.
+    pub fn status(&self) -> Vec<TrackerStatus> {
+        self.tiers
+            .lock()
+            .unwrap()
+            .iter()
+            .flat_map(|tier| tier.iter().cloned())
+            .collect()
+    }
+
+    /// Stop the manager, waiting until `stopped` was announced to the last tracker that
+    /// answered, if any did.
+    pub async fn stop(self) {
+        self.shutdown().await
+    }
+
+    /// Same as `stop`, without giving up the handle.
+    ///
+    /// The announce gives up after the stop timeout of the `TrackerManagerBuilder`. Calls
+    /// after the manager stopped return at once.
+    pub async fn shutdown(&self) {
+        let (send, recv) = oneshot::channel();
+
+        if self.send.send(ManagerMessage::Stop(send)).is_ok() {
+            let _ = recv.await;
+        }
+    }
+}
+
+// ----------------------------------------------------------------------------//
+
+struct ManagerTask<T, H> {
+    info_hash: InfoHash,
+    transport: T,
+    handshaker: H,
+    config: TrackerManagerBuilder,
+    key: u32,
+    state: ClientState,
+    tiers: Arc<Mutex<Vec<Vec<TrackerStatus>>>>,
+    started: bool,
+    completed_pending: bool,
+    completed_sent: bool,
+    opt_last_tracker: Option<TrackerUrl>,
+    next_announce: Instant,
+    min_interval_until: Instant,
+}
+
+impl<T, H> ManagerTask<T, H>
+where
+    T: TrackerTransport,
+    H: Handshaker,
+{
+    async fn run(mut self, mut recv: mpsc::UnboundedReceiver<ManagerMessage>) {
+        loop {
+            tokio::select! {
+                opt_message = recv.recv() => match opt_message {
+                    Some(ManagerMessage::State(state)) => self.state = state,
+                    Some(ManagerMessage::Completed) => self.queue_completed(),
+                    Some(ManagerMessage::Stop(done)) => {
+                        self.announce_stopped().await;
+                        let _ = done.send(());
+
+                        return;
+                    }
+                    None => {
+                        self.announce_stopped().await;
+
+                        return;
+                    }
+                },
+                _ = time::sleep_until(self.next_announce) => self.announce().await,
+            }
+        }
+    }
+
+    fn queue_completed(&mut self) {
+        if self.completed_pending || self.completed_sent {
+            return;
+        }
+        self.completed_pending = true;
+
+        // Before started went through, completed follows right after it
+        if self.started {
+            let earliest = cmp::max(Instant::now(), self.min_interval_until);
+            self.next_announce = cmp::min(self.next_announce, earliest);
+        }
+    }
+
+    fn next_event(&self) -> AnnounceEvent {
+        if !self.started {
+            AnnounceEvent::Started
+        } else if self.completed_pending {
+            AnnounceEvent::Completed
+        } else {
+            AnnounceEvent::None
+        }
+    }
+
+    fn request(&self, event: AnnounceEvent, opt_tracker_id: Option<&[u8]>) -> AnnounceRequest {
+        let state = ClientState::new(
+            self.state.bytes_downloaded(),
+            self.state.bytes_left(),
+            self.state.bytes_uploaded(),
+            event,
+        );
+        let request = self.config.reported_ips.iter().fold(
+            AnnounceRequest::new(
+                self.info_hash,
+                self.handshaker.id(),
+                state,
+                self.key,
+                self.config.num_want,
+                self.handshaker.port(),
+            ),
+            |request, &ip| request.with_reported_ip(ip),
+        );
+
+        match opt_tracker_id {
+            Some(tracker_id) => request.with_tracker_id(tracker_id),
+            None => request,
+        }
+    }
+
+    /// Announce to the first tracker, in tier order, that answers.
+    async fn announce(&mut self) {
+        let event = self.next_event();
+        let now = Instant::now();
+        let trackers: Vec<(usize, TrackerUrl, Option<Vec<u8>>)> = self
+            .tiers
+            .lock()
+            .unwrap()
+            .iter()
+            .flat_map(|tier| tier.iter())
+            .filter(|status| status.opt_retry_at.map_or(true, |retry_at| retry_at <= now))
+            .map(|status| {
+                (
+                    status.tier,
+                    status.url.clone(),
+                    status.opt_tracker_id.clone(),
+                )
+            })
+            .collect();
+
+        for (tier, url, opt_tracker_id) in trackers {
+            let request = self.request(event, opt_tracker_id.as_ref().map(|id| &id[..]));
+
+            match self.transport.send_announce(&url, &request).await {
+                Ok(response) => return self.announce_succeeded(tier, url, event, response),
+                Err(error) => self.announce_failed(tier, &url, error.to_string()),
+            }
+        }
+
+        // No tracker answered, wait for the first one to come out of its backoff
+        let opt_retry_at = self
+            .tiers
+            .lock()
+            .unwrap()
+            .iter()
+            .flat_map(|tier| tier.iter())
+            .filter_map(|status| status.opt_retry_at)
+            .min();
+        self.next_announce =
+            opt_retry_at.unwrap_or_else(|| Instant::now() + self.config.retry_delay);
+    }
+
+    fn announce_succeeded(
+        &mut self,
+        tier: usize,
+        url: TrackerUrl,
+        event: AnnounceEvent,
+        response: AnnounceResponse,
+    ) {
+        let now = Instant::now();
+
+        {
+            let mut tiers = self.tiers.lock().unwrap();
+            let trackers = &mut tiers[tier];
+            let index = trackers
+                .iter()
+                .position(|status| status.url == url)
+                .unwrap();
+
+            let mut status = trackers.remove(index);
+            status.opt_last_announce = Some(now);
+            status.opt_last_error = None;
+            status.peers_returned = response.peers().len();
+            status.failures = 0;
+            status.opt_retry_at = None;
+            if let Some(tracker_id) = response.tracker_id() {
+                status.opt_tracker_id = Some(tracker_id.to_vec());
+            }
+            if response.complete().is_some() || response.incomplete().is_some() {
+                status.opt_seeders = response.complete();
+                status.opt_leechers = response.incomplete();
+            }
+            if let Some(external_ip) = response.external_ip() {
+                status.opt_external_ip = Some(external_ip);
+            }
+
+            // Trackers that answer move to the front of their tier
+            trackers.insert(0, status);
+        }
+
+        for peer in response.peers() {
+            self.handshaker
+                .connect(peer.peer_id(), self.info_hash, peer.addr());
+        }
+
+        if let (Some(external_ip), Some(events)) = (response.external_ip(), &self.config.opt_events)
+        {
+            let _ = events.send(TrackerEvent::ExternalIpObserved(url.clone(), external_ip));
+        }
+
+        match event {
+            AnnounceEvent::Started => self.started = true,
+            AnnounceEvent::Completed => {
+                self.completed_pending = false;
+                self.completed_sent = true;
+            }
+            _ => (),
+        }
+        self.opt_last_tracker = Some(url);
+
+        let min_interval = Duration::from_secs(response.min_interval().unwrap_or(0));
+        let interval =
+            Duration::from_secs(cmp::max(response.interval(), MIN_ANNOUNCE_INTERVAL_SECS));
+        self.min_interval_until = now + min_interval;
+        self.next_announce = if self.completed_pending {
+            self.min_interval_until
+        } else {
+            now + cmp::max(interval, min_interval)
+        };
+    }
+
+    fn announce_failed(&mut self, tier: usize, url: &TrackerUrl, error: String) {
+        let mut tiers = self.tiers.lock().unwrap();
+        let status = tiers[tier]
+            .iter_mut()
+            .find(|status| &status.url == url)
+            .unwrap();
+
+        let backoff = self
+            .config
+            .retry_delay
+            .checked_mul(1 << cmp::min(status.failures, 16))
+            .map_or(self.config.max_retry_delay, |delay| {
+                cmp::min(delay, self.config.max_retry_delay)
+            });
+
+        status.opt_last_error = Some(error);
+        status.failures += 1;
+        status.opt_retry_at = Some(Instant::now() + backoff);
+    }
+
+    /// Announce stopped to the last tracker that answered, if we ever started.
+    async fn announce_stopped(&mut self) {
+        let url = match self.opt_last_tracker.take() {
+            Some(url) if self.started => url,
+            _ => return,
+        };
+        let opt_tracker_id = self
+            .tiers
+            .lock()
+            .unwrap()
+            .iter()
+            .flat_map(|tier| tier.iter())
+            .find(|status| status.url == url)
+            .and_then(|status| status.opt_tracker_id.clone());
+
+        let request = self.request(
+            AnnounceEvent::Stopped,
+            opt_tracker_id.as_ref().map(|id| &id[..]),
+        );
+        let _ = time::timeout(
+            self.config.stop_timeout,
+            self.transport.send_announce(&url, &request),
+        )
+        .await;
+    }
+}
@@ -1,2 +1,34 @@
-#[test]
-fn it_works() {}
+//! Library for announcing to and scraping bittorrent HTTP trackers.
+//!
+//! Includes a default client announcing over plain http, which can be given a
+//! custom connector for reaching https trackers, and a manager announcing a
+//! torrent to all tiers of its trackers. Trackers can be reached through a SOCKS5 or
+//! HTTP proxy with a `ProxyConnector`.
+//!
+//! With the `websocket` feature, WebTorrent trackers can be announced to as well.
+
+mod announce;
+mod client;
+pub(crate) mod http;
+mod manager;
+mod scrape;
+#[cfg(feature = "websocket")]
+mod websocket;
+
+pub mod error;
+
+pub use announce::{AnnounceRequest, AnnounceResponse, TrackerPeer};
+pub use client::{HttpConnector, HttpStream, HttpTrackerClient, ProxyConnector, TcpConnector};
+pub use manager::{
+    TrackerEvent, TrackerManager, TrackerManagerBuilder, TrackerStatus, TrackerTransport,
+};
+pub use scrape::{scrape_url, ScrapeResponse};
+#[cfg(feature = "websocket")]
+pub use websocket::{WsSignal, WsTrackerClient, WsTrackerEvent};
+
+pub use crate::metainfo::{TrackerProtocol, TrackerUrl};
+pub use crate::util::bt::{InfoHash, PeerId};
+pub use crate::util::proxy::{ProxyAuth, ProxyConfig, ProxyError, ProxyKind};
+pub use crate::utracker::announce::{AnnounceEvent, ClientState, DesiredPeers};
+pub use crate::utracker::scrape::ScrapeStats;
+pub use url::Url;
@@ -0,0 +1,293 @@
+//! Messaging primitives for scraping http trackers.
+
+use std::cmp;
+use std::collections::HashMap;
+
+use url::Url;
+
+use crate::bencode::{BDecodeOpt, BDictAccess, BRefAccess, BencodeRef};
+use crate::htracker::announce::{self, FAILURE_REASON_KEY};
+use crate::htracker::error::{HttpTrackerErrorKind, HttpTrackerResult};
+use crate::util::bt::{self, InfoHash};
+use crate::utracker::scrape::ScrapeStats;
+
+const FILES_KEY: &'static [u8] = b"files";
+const COMPLETE_KEY: &'static [u8] = b"complete";
+const DOWNLOADED_KEY: &'static [u8] = b"downloaded";
+const INCOMPLETE_KEY: &'static [u8] = b"incomplete";
+const FLAGS_KEY: &'static [u8] = b"flags";
+const MIN_REQUEST_INTERVAL_KEY: &'static [u8] = b"min_request_interval";
+
+const ANNOUNCE_COMPONENT: &'static str = "announce";
+const SCRAPE_COMPONENT: &'static str = "scrape";
+
+/// Scrape url of the tracker at the given announce url.
+///
+/// Following the convention, the last path component has to start with `announce`,
+/// which is replaced by `scrape`, keeping the rest of the component and the query.
+pub fn scrape_url(announce: &Url) -> HttpTrackerResult<Url> {
+    let mut url = announce.clone();
+
+    let replaced = match url.path_mut().and_then(|path| path.last_mut()) {
+        Some(last) if last.starts_with(ANNOUNCE_COMPONENT) => {
+            *last = format!("{}{}", SCRAPE_COMPONENT, &last[ANNOUNCE_COMPONENT.len()..]);
+            true
+        }
+        _ => false,
+    };
+
+    if replaced {
+        Ok(url)
+    } else {
+        Err(HttpTrackerErrorKind::ScrapeNotSupported {
+            url: announce.serialize(),
+        }
+        .into())
+    }
+}
+
+/// Percent encoded query string scraping the given hashes, without a leading question mark.
+pub fn query(hashes: &[InfoHash]) -> String {
+    let mut query = String::new();
+
+    for hash in hashes {
+        if !query.is_empty() {
+            query.push('&');
+        }
+        query.push_str("info_hash=");
+        announce::percent_encode(&hash.truncated(), &mut query);
+    }
+
+    query
+}
+
+// ----------------------------------------------------------------------------//
+
+/// Scrape response sent from an http tracker to the client.
+///
+/// Statistics map complete to seeders, and incomplete to leechers.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ScrapeResponse {
+    files: HashMap<InfoHash, ScrapeStats>,
+    opt_min_request_interval: Option<u64>,
+}
+
+impl ScrapeResponse {
+    /// Parse the bencoded body of a scrape response.
+    ///
+    /// A response carrying a failure reason is returned as a `TrackerFailure` error. Entries
+    /// not keyed by a raw info hash, or not holding a dictionary, are skipped, and missing
+    /// counts are taken as zero.
+    pub fn from_bytes(bytes: &[u8]) -> HttpTrackerResult<ScrapeResponse> {
+        let root_bencode = BencodeRef::decode(bytes, BDecodeOpt::default())?;
+        let root_dict = root_bencode
+            .dict()
+            .ok_or_else(|| announce::invalid("Response Is Not A Dictionary"))?;
+
+        if let Some(reason) = root_dict.lookup(FAILURE_REASON_KEY) {
+            let reason = reason
+                .bytes()
+                .ok_or_else(|| announce::invalid("Failure Reason Is Not A String"))?;
+
+            return Err(HttpTrackerErrorKind::TrackerFailure {
+                reason: String::from_utf8_lossy(reason).into_owned(),
+            }
+            .into());
+        }
+
+        let files_dict = root_dict
+            .lookup(FILES_KEY)
+            .ok_or_else(|| announce::invalid("Response Has No Files"))?
+            .dict()
+            .ok_or_else(|| announce::invalid("Files Is Not A Dictionary"))?;
+
+        let mut files = HashMap::new();
+        for (hash, stats) in files_dict.to_list() {
+            if hash.len() != bt::INFO_HASH_LEN {
+                continue;
+            }
+
+            if let Some(stats_dict) = stats.dict() {
+                files.insert(
+                    InfoHash::from_hash(hash).unwrap(),
+                    ScrapeStats::new(
+                        lookup_stat(stats_dict, COMPLETE_KEY),
+                        lookup_stat(stats_dict, DOWNLOADED_KEY),
+                        lookup_stat(stats_dict, INCOMPLETE_KEY),
+                    ),
+                );
+            }
+        }
+
+        let opt_min_request_interval = match root_dict.lookup(FLAGS_KEY).and_then(|f| f.dict()) {
+            Some(flags_dict) => announce::lookup_count(flags_dict, MIN_REQUEST_INTERVAL_KEY)?,
+            None => None,
+        };
+
+        Ok(ScrapeResponse {
+            files: files,
+            opt_min_request_interval: opt_min_request_interval,
+        })
+    }
+
+    /// Statistics for the given hash, if the tracker sent any.
+    ///
+    /// Trackers only know the truncated form of a v2 hash, which the hash is looked up by.
+    pub fn get(&self, hash: &InfoHash) -> Option<ScrapeStats> {
+        self.files.get(&InfoHash::from(hash.truncated())).cloned()
+    }
+
+    /// Statistics for every hash the tracker sent.
+    pub fn files(&self) -> &HashMap<InfoHash, ScrapeStats> {
+        &self.files
+    }
+
+    /// Seconds the client must wait before scraping again, if given.
+    pub fn min_request_interval(&self) -> Option<u64> {
+        self.opt_min_request_interval
+    }
+}
+
+/// Count under the given key, clamped to the range of the statistics.
+fn lookup_stat<B>(dict: &dyn BDictAccess<B::BKey, B>, key: &[u8]) -> i32
+where
+    B: BRefAccess<BType = B>,
+{
+    dict.lookup(key)
+        .and_then(|value| value.int())
+        .map(|count| cmp::max(0, cmp::min(count, i32::max_value() as i64)) as i32)
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ScrapeResponse;
+    use crate::htracker::error::HttpTrackerErrorKind;
+    use crate::util::bt::InfoHash;
+    use url::Url;
+
+    fn scrape_url(announce: &str) -> Option<String> {
+        super::scrape_url(&Url::parse(announce).unwrap())
+            .ok()
+            .map(|url| url.serialize())
+    }
+
+    #[test]
+    fn positive_scrape_url() {
+        assert_eq!(
+            scrape_url("http://example.com/announce").unwrap(),
+            "http://example.com/scrape"
+        );
+        assert_eq!(
+            scrape_url("http://example.com/x/announce").unwrap(),
+            "http://example.com/x/scrape"
+        );
+        assert_eq!(
+            scrape_url("http://example.com/announce?x2%0644").unwrap(),
+            "http://example.com/scrape?x2%0644"
+        );
+        assert_eq!(
+            scrape_url("http://example.com/announce?x=2/4").unwrap(),
+            "http://example.com/scrape?x=2/4"
+        );
+        assert_eq!(
+            scrape_url("http://example.com/announce.php").unwrap(),
+            "http://example.com/scrape.php"
+        );
+        assert_eq!(
+            scrape_url("http://example.com:8080/passkey/announce").unwrap(),
+            "http://example.com:8080/passkey/scrape"
+        );
+    }
+
+    #[test]
+    fn negative_scrape_url() {
+        assert_eq!(scrape_url("http://example.com/a"), None);
+        assert_eq!(scrape_url("http://example.com/x%064announce"), None);
+        assert_eq!(scrape_url("http://example.com/announce/"), None);
+        assert_eq!(scrape_url("http://example.com/"), None);
+    }
+
+    #[test]
+    fn positive_query_repeats_info_hash() {
+        let hashes: Vec<InfoHash> = vec![[0xAAu8; 20].into(), [b'a'; 20].into()];
+
+        assert_eq!(
+            super::query(&hashes),
+            format!(
+                "info_hash={}&info_hash={}",
+                "%AA".repeat(20),
+                "a".repeat(20)
+            )
+        );
+    }
+
+    #[test]
+    fn positive_parse_files() {
+        let mut bytes = b"d5:filesd20:".to_vec();
+        bytes.extend_from_slice(&[0x01u8; 20]);
+        bytes.extend_from_slice(b"d8:completei5e10:downloadedi50e10:incompletei10ee20:");
+        bytes.extend_from_slice(&[0x02u8; 20]);
+        bytes.extend_from_slice(b"d8:completei1ee3:bad");
+        bytes.extend_from_slice(b"d8:completei1eee");
+        bytes.extend_from_slice(b"5:flagsd20:min_request_intervali900eee");
+
+        let response = ScrapeResponse::from_bytes(&bytes).unwrap();
+        assert_eq!(response.files().len(), 2);
+        assert_eq!(response.min_request_interval(), Some(900));
+
+        let first = response.get(&[0x01u8; 20].into()).unwrap();
+        assert_eq!(first.num_seeders(), 5);
+        assert_eq!(first.num_downloads(), 50);
+        assert_eq!(first.num_leechers(), 10);
+
+        let second = response.get(&[0x02u8; 20].into()).unwrap();
+        assert_eq!(second.num_seeders(), 1);
+        assert_eq!(second.num_downloads(), 0);
+        assert_eq!(second.num_leechers(), 0);
+
+        assert_eq!(response.get(&[0x03u8; 20].into()), None);
+    }
+
+    #[test]
+    fn positive_v2_hash_truncated() {
+        let mut v2_hash = [0x01u8; 32];
+        v2_hash[20..].copy_from_slice(&[0xAAu8; 12]);
+        let v2_hash = InfoHash::V2(v2_hash);
+
+        assert_eq!(
+            super::query(&[v2_hash]),
+            format!("info_hash={}", "%01".repeat(20))
+        );
+
+        let mut bytes = b"d5:filesd20:".to_vec();
+        bytes.extend_from_slice(&[0x01u8; 20]);
+        bytes.extend_from_slice(b"d8:completei5eeee");
+
+        let response = ScrapeResponse::from_bytes(&bytes).unwrap();
+        assert_eq!(response.get(&v2_hash).unwrap().num_seeders(), 5);
+    }
+
+    #[test]
+    fn negative_parse_failure_reason() {
+        let error =
+            ScrapeResponse::from_bytes(b"d14:failure reason15:scrape disablede").unwrap_err();
+
+        match error.kind() {
+            HttpTrackerErrorKind::TrackerFailure { reason } => {
+                assert_eq!(reason, "scrape disabled")
+            }
+            kind => panic!("Unexpected Error: {:?}", kind),
+        }
+    }
+
+    #[test]
+    fn negative_parse_missing_files() {
+        let error = ScrapeResponse::from_bytes(b"d8:intervali60ee").unwrap_err();
+
+        match error.kind() {
+            HttpTrackerErrorKind::InvalidResponse { .. } => (),
+            kind => panic!("Unexpected Error: {:?}", kind),
+        }
+    }
+}
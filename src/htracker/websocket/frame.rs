@@ -0,0 +1,353 @@
+//! Just enough of RFC 6455 to exchange text messages with a websocket tracker.
+
+use crate::htracker::error::{HttpTrackerError, HttpTrackerErrorKind, HttpTrackerResult};
+use crate::htracker::http::HttpResponse;
+use crate::util::convert;
+use crate::util::sha::ShaHashBuilder;
+
+const ACCEPT_GUID: &'static [u8] = b"258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+const HEADER_END: &'static [u8] = b"\r\n\r\n";
+
+pub const CONTINUATION_OPCODE: u8 = 0x0;
+pub const TEXT_OPCODE: u8 = 0x1;
+pub const BINARY_OPCODE: u8 = 0x2;
+pub const CLOSE_OPCODE: u8 = 0x8;
+pub const PING_OPCODE: u8 = 0x9;
+pub const PONG_OPCODE: u8 = 0xA;
+
+/// Complete message received from a tracker, with fragments joined together.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Message {
+    Text(String),
+    Binary(Vec<u8>),
+    Ping(Vec<u8>),
+    Pong(Vec<u8>),
+    Close,
+}
+
+/// Request head upgrading a connection for the given target to a websocket.
+pub fn handshake_request(target: &str, host: &str, key: &str) -> Vec<u8> {
+    format!(
+        "GET {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: bittorrent-protocol\r\n\
+         Upgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Key: {}\r\n\
+         Sec-WebSocket-Version: 13\r\n\r\n",
+        target, host, key
+    )
+    .into_bytes()
+}
+
+/// Base64 encoded key for the given random bytes.
+pub fn handshake_key(random: &[u8; 16]) -> String {
+    convert::bytes_to_base64(random)
+}
+
+/// Accept value a tracker has to answer the given key with.
+pub fn accept_key(key: &str) -> String {
+    let hash = ShaHashBuilder::new()
+        .add_bytes(key.as_bytes())
+        .add_bytes(ACCEPT_GUID)
+        .build();
+
+    convert::bytes_to_base64(hash.as_ref())
+}
+
+/// Check the response to our handshake, returning the length of its head once complete.
+///
+/// Anything after the head is already part of the first frames sent by the tracker.
+pub fn check_handshake(bytes: &[u8], key: &str) -> HttpTrackerResult<Option<usize>> {
+    let head_len = match find(bytes, HEADER_END) {
+        Some(header_end) => header_end + HEADER_END.len(),
+        None => return Ok(None),
+    };
+    let response = HttpResponse::from_bytes(&bytes[..head_len])?;
+
+    if response.status() != 101 {
+        return Err(HttpTrackerErrorKind::HttpStatus {
+            status: response.status(),
+            reason: response.reason().to_owned(),
+        }
+        .into());
+    }
+
+    let upgraded = response
+        .header("upgrade")
+        .map_or(false, |upgrade| upgrade.eq_ignore_ascii_case("websocket"));
+    if !upgraded {
+        return Err(invalid("Connection Was Not Upgraded"));
+    }
+
+    if response.header("sec-websocket-accept") != Some(&accept_key(key)[..]) {
+        return Err(invalid("Handshake Accept Key Mismatch"));
+    }
+
+    Ok(Some(head_len))
+}
+
+/// Encode a single final frame, masked with the given key as all client frames have to be.
+pub fn encode_frame(opcode: u8, payload: &[u8], mask: [u8; 4]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(payload.len() + 14);
+    frame.push(0x80 | opcode);
+
+    if payload.len() < 126 {
+        frame.push(0x80 | payload.len() as u8);
+    } else if payload.len() <= u16::max_value() as usize {
+        frame.push(0x80 | 126);
+        frame.extend_from_slice(&(payload.len() as u16).to_be_bytes());
+    } else {
+        frame.push(0x80 | 127);
+        frame.extend_from_slice(&(payload.len() as u64).to_be_bytes());
+    }
+
+    frame.extend_from_slice(&mask);
+    frame.extend(
+        payload
+            .iter()
+            .enumerate()
+            .map(|(index, byte)| byte ^ mask[index % 4]),
+    );
+
+    frame
+}
+
+/// Reads frames out of the bytes received from a tracker.
+pub struct FrameReader {
+    buffer: Vec<u8>,
+    fragments: Vec<u8>,
+    opt_fragment_opcode: Option<u8>,
+    max_message_len: usize,
+}
+
+impl FrameReader {
+    /// Create a new FrameReader, refusing messages longer than the given length.
+    pub fn new(max_message_len: usize) -> FrameReader {
+        FrameReader {
+            buffer: Vec::new(),
+            fragments: Vec::new(),
+            opt_fragment_opcode: None,
+            max_message_len: max_message_len,
+        }
+    }
+
+    /// Add bytes received from the tracker.
+    pub fn extend(&mut self, bytes: &[u8]) {
+        self.buffer.extend_from_slice(bytes);
+    }
+
+    /// Next complete message, if enough bytes were received for one.
+    pub fn next_message(&mut self) -> HttpTrackerResult<Option<Message>> {
+        loop {
+            let (fin, opcode, payload) = match self.next_frame()? {
+                Some(frame) => frame,
+                None => return Ok(None),
+            };
+
+            match opcode {
+                PING_OPCODE => return Ok(Some(Message::Ping(payload))),
+                PONG_OPCODE => return Ok(Some(Message::Pong(payload))),
+                CLOSE_OPCODE => return Ok(Some(Message::Close)),
+                TEXT_OPCODE | BINARY_OPCODE if self.opt_fragment_opcode.is_none() => {
+                    if fin {
+                        return to_message(opcode, payload).map(Some);
+                    }
+
+                    self.opt_fragment_opcode = Some(opcode);
+                    self.fragments = payload;
+                }
+                CONTINUATION_OPCODE if self.opt_fragment_opcode.is_some() => {
+                    if self.fragments.len() + payload.len() > self.max_message_len {
+                        return Err(invalid("Message Too Long"));
+                    }
+                    self.fragments.extend_from_slice(&payload);
+
+                    if fin {
+                        let opcode = self.opt_fragment_opcode.take().unwrap();
+                        let message = std::mem::take(&mut self.fragments);
+
+                        return to_message(opcode, message).map(Some);
+                    }
+                }
+                _ => return Err(invalid("Unexpected Frame Opcode")),
+            }
+        }
+    }
+
+    fn next_frame(&mut self) -> HttpTrackerResult<Option<(bool, u8, Vec<u8>)>> {
+        if self.buffer.len() < 2 {
+            return Ok(None);
+        }
+        let (first, second) = (self.buffer[0], self.buffer[1]);
+
+        if first & 0x70 != 0 {
+            return Err(invalid("Reserved Frame Bits Set"));
+        }
+        let (fin, opcode, masked) = (first & 0x80 != 0, first & 0x0F, second & 0x80 != 0);
+
+        let (payload_len, mut offset) = match second & 0x7F {
+            126 if self.buffer.len() >= 4 => (
+                u16::from_be_bytes([self.buffer[2], self.buffer[3]]) as u64,
+                4,
+            ),
+            127 if self.buffer.len() >= 10 => {
+                let mut length = [0u8; 8];
+                length.copy_from_slice(&self.buffer[2..10]);
+
+                (u64::from_be_bytes(length), 10)
+            }
+            126 | 127 => return Ok(None),
+            length => (length as u64, 2),
+        };
+        if payload_len > self.max_message_len as u64 {
+            return Err(invalid("Message Too Long"));
+        }
+
+        let opt_mask = if masked {
+            if self.buffer.len() < offset + 4 {
+                return Ok(None);
+            }
+            let mut mask = [0u8; 4];
+            mask.copy_from_slice(&self.buffer[offset..offset + 4]);
+            offset += 4;
+
+            Some(mask)
+        } else {
+            None
+        };
+
+        let frame_len = offset + payload_len as usize;
+        if self.buffer.len() < frame_len {
+            return Ok(None);
+        }
+
+        let mut payload: Vec<u8> = self.buffer.drain(..frame_len).skip(offset).collect();
+        if let Some(mask) = opt_mask {
+            for (index, byte) in payload.iter_mut().enumerate() {
+                *byte ^= mask[index % 4];
+            }
+        }
+
+        Ok(Some((fin, opcode, payload)))
+    }
+}
+
+fn to_message(opcode: u8, payload: Vec<u8>) -> HttpTrackerResult<Message> {
+    if opcode == TEXT_OPCODE {
+        String::from_utf8(payload)
+            .map(Message::Text)
+            .map_err(|_| invalid("Text Message Is Not Valid Utf8"))
+    } else {
+        Ok(Message::Binary(payload))
+    }
+}
+
+fn find(bytes: &[u8], pattern: &[u8]) -> Option<usize> {
+    bytes
+        .windows(pattern.len())
+        .position(|window| window == pattern)
+}
+
+fn invalid(details: &str) -> HttpTrackerError {
+    HttpTrackerErrorKind::InvalidResponse {
+        details: format!("WebSocket {}", details),
+    }
+    .into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FrameReader, Message};
+
+    #[test]
+    fn positive_accept_key() {
+        // Example handshake of RFC 6455
+        assert_eq!(
+            super::accept_key("dGhlIHNhbXBsZSBub25jZQ=="),
+            "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+        );
+        assert_eq!(
+            super::handshake_key(b"the sample nonce"),
+            "dGhlIHNhbXBsZSBub25jZQ=="
+        );
+    }
+
+    #[test]
+    fn positive_check_handshake() {
+        let response = b"HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\n\
+                         Connection: Upgrade\r\nSec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n\r\n\x81\x00";
+
+        assert_eq!(
+            super::check_handshake(&response[..40], "dGhlIHNhbXBsZSBub25jZQ==").unwrap(),
+            None
+        );
+        assert_eq!(
+            super::check_handshake(response, "dGhlIHNhbXBsZSBub25jZQ==").unwrap(),
+            Some(response.len() - 2)
+        );
+    }
+
+    #[test]
+    fn negative_check_handshake() {
+        let wrong_key = b"HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\n\
+                          Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n\r\n";
+        let not_upgraded = b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n";
+
+        assert!(super::check_handshake(wrong_key, "AAAAAAAAAAAAAAAAAAAAAA==").is_err());
+        assert!(super::check_handshake(not_upgraded, "dGhlIHNhbXBsZSBub25jZQ==").is_err());
+    }
+
+    #[test]
+    fn positive_encode_masked_frame() {
+        // Masked "Hello" example of RFC 6455
+        assert_eq!(
+            super::encode_frame(super::TEXT_OPCODE, b"Hello", [0x37, 0xfa, 0x21, 0x3d]),
+            b"\x81\x85\x37\xfa\x21\x3d\x7f\x9f\x4d\x51\x58"
+        );
+
+        let long = super::encode_frame(super::BINARY_OPCODE, &[0u8; 256], [0u8; 4]);
+        assert_eq!(&long[..4], b"\x82\xFE\x01\x00");
+        assert_eq!(long.len(), 4 + 4 + 256);
+    }
+
+    #[test]
+    fn positive_read_split_and_fragmented_messages() {
+        let mut reader = FrameReader::new(1024);
+
+        // Unmasked "Hello" split across reads
+        reader.extend(b"\x81\x05\x48\x65");
+        assert_eq!(reader.next_message().unwrap(), None);
+        reader.extend(b"\x6c\x6c\x6f");
+        assert_eq!(
+            reader.next_message().unwrap(),
+            Some(Message::Text("Hello".to_owned()))
+        );
+
+        // Fragmented "Hello" with a ping in between, as in RFC 6455
+        reader.extend(b"\x01\x03\x48\x65\x6c\x89\x00\x80\x02\x6c\x6f");
+        assert_eq!(
+            reader.next_message().unwrap(),
+            Some(Message::Ping(Vec::new()))
+        );
+        assert_eq!(
+            reader.next_message().unwrap(),
+            Some(Message::Text("Hello".to_owned()))
+        );
+
+        reader.extend(b"\x88\x00");
+        assert_eq!(reader.next_message().unwrap(), Some(Message::Close));
+        assert_eq!(reader.next_message().unwrap(), None);
+    }
+
+    #[test]
+    fn negative_read_invalid_frames() {
+        let mut too_long = FrameReader::new(4);
+        too_long.extend(b"\x81\x05Hello");
+        assert!(too_long.next_message().is_err());
+
+        let mut stray_continuation = FrameReader::new(1024);
+        stray_continuation.extend(b"\x80\x00");
+        assert!(stray_continuation.next_message().is_err());
+
+        let mut reserved_bits = FrameReader::new(1024);
+        reserved_bits.extend(b"\xC1\x00");
+        assert!(reserved_bits.next_message().is_err());
+    }
+}
@@ -0,0 +1,346 @@
+//! JSON messages exchanged with WebTorrent trackers.
+//!
+//! Info hashes, peer ids and offer ids are sent as binary strings, where every byte is
+//! the code point of a single character.
+
+use serde_json::{Map, Value};
+
+use crate::htracker::announce::{self, AnnounceRequest, AnnounceResponse};
+use crate::htracker::error::HttpTrackerResult;
+use crate::util::bt::{InfoHash, PeerId};
+use crate::util::sha::ShaHash;
+use crate::utracker::announce::{AnnounceEvent, DesiredPeers};
+
+const ANNOUNCE_ACTION: &'static str = "announce";
+
+/// WebRTC offer or answer relayed by the tracker from another peer.
+///
+/// The session description is kept as is, for a WebRTC transport to make sense of.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct WsSignal {
+    info_hash: InfoHash,
+    peer_id: PeerId,
+    offer_id: Vec<u8>,
+    sdp: String,
+}
+
+impl WsSignal {
+    /// Create a new WsSignal.
+    pub fn new(info_hash: InfoHash, peer_id: PeerId, offer_id: &[u8], sdp: &str) -> WsSignal {
+        WsSignal {
+            info_hash: info_hash,
+            peer_id: peer_id,
+            offer_id: offer_id.to_vec(),
+            sdp: sdp.to_owned(),
+        }
+    }
+
+    /// Info hash of the torrent the signal is for.
+    pub fn info_hash(&self) -> InfoHash {
+        self.info_hash
+    }
+
+    /// Peer id of the peer that sent the signal.
+    pub fn peer_id(&self) -> PeerId {
+        self.peer_id
+    }
+
+    /// Id of the offer, which an answer refers back to.
+    pub fn offer_id(&self) -> &[u8] {
+        &self.offer_id
+    }
+
+    /// Session description of the signal.
+    pub fn sdp(&self) -> &str {
+        &self.sdp
+    }
+}
+
+/// Message received from a WebTorrent tracker.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TrackerMessage {
+    Response(InfoHash, AnnounceResponse),
+    Failure(Option<InfoHash>, String),
+    Offer(WsSignal),
+    Answer(WsSignal),
+    /// Messages we take no part in, such as scrape responses.
+    Other,
+}
+
+/// JSON announce for the given request, without any WebRTC offers.
+pub fn announce_message(request: &AnnounceRequest) -> String {
+    let state = request.state();
+    let mut message = Map::new();
+
+    message.insert("action".to_owned(), ANNOUNCE_ACTION.into());
+    message.insert(
+        "info_hash".to_owned(),
+        binary_string(&request.info_hash().truncated()).into(),
+    );
+    message.insert(
+        "peer_id".to_owned(),
+        binary_string(request.peer_id().as_ref()).into(),
+    );
+    message.insert("uploaded".to_owned(), state.bytes_uploaded().into());
+    message.insert("downloaded".to_owned(), state.bytes_downloaded().into());
+    message.insert("left".to_owned(), state.bytes_left().into());
+
+    if let DesiredPeers::Specified(num_want) = request.num_want() {
+        message.insert("numwant".to_owned(), num_want.into());
+    }
+
+    let opt_event = match state.event() {
+        AnnounceEvent::None => None,
+        AnnounceEvent::Started => Some("started"),
+        AnnounceEvent::Stopped => Some("stopped"),
+        AnnounceEvent::Completed => Some("completed"),
+    };
+    if let Some(event) = opt_event {
+        message.insert("event".to_owned(), event.into());
+    }
+
+    message.insert("offers".to_owned(), Value::Array(Vec::new()));
+
+    Value::Object(message).to_string()
+}
+
+/// Parse a text message received from a tracker.
+pub fn parse_message(text: &str) -> HttpTrackerResult<TrackerMessage> {
+    let value: Value = serde_json::from_str(text)
+        .map_err(|_| announce::invalid("WebSocket Message Is Not Valid Json"))?;
+    let message = value
+        .as_object()
+        .ok_or_else(|| announce::invalid("WebSocket Message Is Not An Object"))?;
+
+    let opt_info_hash = match message.get("info_hash") {
+        Some(value) => Some(lookup_hash(value, "info_hash").map(InfoHash::from)?),
+        None => None,
+    };
+
+    if let Some(reason) = message.get("failure reason") {
+        let reason = reason.as_str().unwrap_or("Unknown Failure").to_owned();
+
+        return Ok(TrackerMessage::Failure(opt_info_hash, reason));
+    }
+
+    if message.get("action").and_then(Value::as_str) != Some(ANNOUNCE_ACTION) {
+        return Ok(TrackerMessage::Other);
+    }
+    let info_hash =
+        opt_info_hash.ok_or_else(|| announce::invalid("WebSocket Announce Has No Info Hash"))?;
+
+    if let Some(offer) = message.get("offer") {
+        return parse_signal(message, info_hash, offer).map(TrackerMessage::Offer);
+    }
+    if let Some(answer) = message.get("answer") {
+        return parse_signal(message, info_hash, answer).map(TrackerMessage::Answer);
+    }
+
+    let interval = match message.get("interval") {
+        Some(interval) => interval
+            .as_u64()
+            .ok_or_else(|| announce::invalid("interval Is Not A Non Negative Integer"))?,
+        None => return Ok(TrackerMessage::Other),
+    };
+    let mut response = AnnounceResponse::new(interval, Vec::new());
+
+    if let Some(min_interval) = lookup_count(message, "min interval")? {
+        response = response.with_min_interval(min_interval);
+    }
+    if let Some(complete) = lookup_count(message, "complete")? {
+        response = response.with_complete(complete);
+    }
+    if let Some(incomplete) = lookup_count(message, "incomplete")? {
+        response = response.with_incomplete(incomplete);
+    }
+    if let Some(warning) = message.get("warning message").and_then(Value::as_str) {
+        response = response.with_warning_message(warning);
+    }
+
+    Ok(TrackerMessage::Response(info_hash, response))
+}
+
+fn parse_signal(
+    message: &Map<String, Value>,
+    info_hash: InfoHash,
+    signal: &Value,
+) -> HttpTrackerResult<WsSignal> {
+    let peer_id = lookup_hash(
+        message
+            .get("peer_id")
+            .ok_or_else(|| announce::invalid("WebSocket Signal Has No Peer Id"))?,
+        "peer_id",
+    )?;
+    let offer_id = message
+        .get("offer_id")
+        .and_then(Value::as_str)
+        .and_then(binary_bytes)
+        .ok_or_else(|| announce::invalid("WebSocket Signal Has No Valid Offer Id"))?;
+    let sdp = signal
+        .get("sdp")
+        .and_then(Value::as_str)
+        .ok_or_else(|| announce::invalid("WebSocket Signal Has No Session Description"))?;
+
+    Ok(WsSignal::new(info_hash, peer_id, &offer_id, sdp))
+}
+
+fn lookup_hash(value: &Value, key: &str) -> HttpTrackerResult<ShaHash> {
+    value
+        .as_str()
+        .and_then(binary_bytes)
+        .and_then(|bytes| ShaHash::from_hash(&bytes).ok())
+        .ok_or_else(|| announce::invalid(&format!("{} Is Not A Valid Binary Hash", key)))
+}
+
+fn lookup_count(message: &Map<String, Value>, key: &str) -> HttpTrackerResult<Option<u64>> {
+    match message.get(key).map(Value::as_u64) {
+        Some(Some(count)) => Ok(Some(count)),
+        Some(None) => Err(announce::invalid(&format!(
+            "{} Is Not A Non Negative Integer",
+            key
+        ))),
+        None => Ok(None),
+    }
+}
+
+/// Binary string holding the given bytes.
+pub fn binary_string(bytes: &[u8]) -> String {
+    bytes.iter().map(|&byte| byte as char).collect()
+}
+
+/// Bytes held in the given binary string, if every character fits in a byte.
+pub fn binary_bytes(string: &str) -> Option<Vec<u8>> {
+    string
+        .chars()
+        .map(|character| {
+            if (character as u32) <= 0xFF {
+                Some(character as u8)
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Value};
+
+    use super::{TrackerMessage, WsSignal};
+    use crate::htracker::announce::{AnnounceRequest, AnnounceResponse};
+    use crate::utracker::announce::{AnnounceEvent, ClientState, DesiredPeers};
+
+    fn binary_hash(byte: u8) -> String {
+        super::binary_string(&[byte; 20])
+    }
+
+    #[test]
+    fn positive_announce_message() {
+        let request = AnnounceRequest::new(
+            [0xE9u8; 20].into(),
+            [0x2Du8; 20].into(),
+            ClientState::new(100, 200, 300, AnnounceEvent::Started),
+            0,
+            DesiredPeers::Specified(5),
+            6881,
+        );
+        let message: Value = serde_json::from_str(&super::announce_message(&request)).unwrap();
+
+        assert_eq!(message["action"], "announce");
+        assert_eq!(message["info_hash"], binary_hash(0xE9));
+        assert_eq!(message["peer_id"], binary_hash(0x2D));
+        assert_eq!(message["downloaded"], 100);
+        assert_eq!(message["left"], 200);
+        assert_eq!(message["uploaded"], 300);
+        assert_eq!(message["numwant"], 5);
+        assert_eq!(message["event"], "started");
+        assert_eq!(message["offers"], Value::Array(Vec::new()));
+    }
+
+    #[test]
+    fn positive_binary_string_round_trip() {
+        let bytes: Vec<u8> = (0..=255).collect();
+
+        assert_eq!(
+            super::binary_bytes(&super::binary_string(&bytes)),
+            Some(bytes)
+        );
+        assert_eq!(super::binary_bytes("\u{100}"), None);
+    }
+
+    #[test]
+    fn positive_parse_response() {
+        let text = format!(
+            r#"{{"action":"announce","interval":120,"info_hash":"{}","complete":3,"incomplete":7}}"#,
+            binary_hash(0xAB)
+        );
+
+        assert_eq!(
+            super::parse_message(&text).unwrap(),
+            TrackerMessage::Response(
+                [0xABu8; 20].into(),
+                AnnounceResponse::new(120, Vec::new())
+                    .with_complete(3)
+                    .with_incomplete(7)
+            )
+        );
+    }
+
+    #[test]
+    fn positive_parse_offer_and_answer() {
+        // Control characters of binary strings have to be escaped
+        let signal = |kind: &str| {
+            json!({
+                "action": "announce",
+                kind: { "type": kind, "sdp": "v=0" },
+                "offer_id": super::binary_string(b"offer"),
+                "peer_id": binary_hash(0x01),
+                "info_hash": binary_hash(0xAB),
+            })
+            .to_string()
+        };
+        let expected = WsSignal::new([0xABu8; 20].into(), [0x01u8; 20].into(), b"offer", "v=0");
+
+        assert_eq!(
+            super::parse_message(&signal("offer")).unwrap(),
+            TrackerMessage::Offer(expected.clone())
+        );
+        assert_eq!(
+            super::parse_message(&signal("answer")).unwrap(),
+            TrackerMessage::Answer(expected)
+        );
+    }
+
+    #[test]
+    fn positive_parse_failure_and_other() {
+        let failure = format!(
+            r#"{{"action":"announce","failure reason":"torrent not allowed","info_hash":"{}"}}"#,
+            binary_hash(0xAB)
+        );
+
+        assert_eq!(
+            super::parse_message(&failure).unwrap(),
+            TrackerMessage::Failure(Some([0xABu8; 20].into()), "torrent not allowed".to_owned())
+        );
+        assert_eq!(
+            super::parse_message(r#"{"action":"scrape","files":{}}"#).unwrap(),
+            TrackerMessage::Other
+        );
+    }
+
+    #[test]
+    fn negative_parse_invalid_messages() {
+        assert!(super::parse_message("not json").is_err());
+        assert!(super::parse_message("[]").is_err());
+        assert!(super::parse_message(r#"{"action":"announce","interval":120}"#).is_err());
+        assert!(super::parse_message(
+            r#"{"action":"announce","interval":120,"info_hash":"too short"}"#
+        )
+        .is_err());
+        assert!(super::parse_message(&format!(
+            r#"{{"action":"announce","interval":-1,"info_hash":"{}"}}"#,
+            binary_hash(0xAB)
+        ))
+        .is_err());
+    }
+}
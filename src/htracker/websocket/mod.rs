@@ -0,0 +1,546 @@
+//! Client for announcing to WebTorrent trackers over websockets.
+
+use std::cmp;
+use std::collections::HashMap;
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use futures::future::BoxFuture;
+use tokio::io::{AsyncReadExt, AsyncWriteExt, WriteHalf};
+use tokio::sync::mpsc::error::TryRecvError;
+use tokio::sync::{mpsc, oneshot};
+use tokio::time;
+use url::Url;
+
+use crate::htracker::announce::{AnnounceRequest, AnnounceResponse};
+use crate::htracker::client::{self, HttpConnector, HttpStream, TcpConnector};
+use crate::htracker::error::{HttpTrackerError, HttpTrackerErrorKind, HttpTrackerResult};
+use crate::htracker::manager::TrackerTransport;
+use crate::metainfo::{TrackerProtocol, TrackerUrl};
+use crate::util::bt::InfoHash;
+use crate::utracker::announce::{AnnounceEvent, ClientState};
+
+use self::frame::{FrameReader, Message};
+use self::message::TrackerMessage;
+
+mod frame;
+mod message;
+
+pub use self::message::WsSignal;
+
+const DEFAULT_TIMEOUT_SECS: u64 = 30;
+const DEFAULT_RETRY_DELAY_SECS: u64 = 5;
+const DEFAULT_MAX_RETRY_DELAY_SECS: u64 = 5 * 60;
+const DEFAULT_MAX_MESSAGE_LEN: usize = 1024 * 1024;
+
+const READ_BUFFER_LEN: usize = 4096;
+
+/// Event received from a WebTorrent tracker.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum WsTrackerEvent {
+    /// Tracker answered an announce for the torrent.
+    Announce(InfoHash, AnnounceResponse),
+    /// Tracker refused an announce for the torrent.
+    Failure(Option<InfoHash>, String),
+    /// Peer wants to connect to us over WebRTC.
+    Offer(WsSignal),
+    /// Peer answered one of our WebRTC offers.
+    Answer(WsSignal),
+}
+
+/// Client for announcing to WebTorrent trackers.
+///
+/// A single websocket is kept open per tracker, which the tracker relays offers and answers
+/// of other peers over as long as it stays open. When the socket drops, outstanding
+/// announces fail and the client reconnects with an exponential backoff, announcing every
+/// torrent again so that it keeps receiving offers.
+///
+/// Connections are opened with the connector as if for the http equivalent of the url, so
+/// ws trackers as http and wss trackers as https.
+#[derive(Clone)]
+pub struct WsTrackerClient {
+    connector: Arc<dyn HttpConnector>,
+    timeout: Duration,
+    retry_delay: Duration,
+    max_retry_delay: Duration,
+    max_message_len: usize,
+    opt_events: Option<mpsc::UnboundedSender<WsTrackerEvent>>,
+    connections: Arc<Mutex<HashMap<String, mpsc::UnboundedSender<Command>>>>,
+}
+
+impl WsTrackerClient {
+    /// Create a new WsTrackerClient for plain ws trackers.
+    pub fn new() -> WsTrackerClient {
+        WsTrackerClient {
+            connector: Arc::new(TcpConnector),
+            timeout: Duration::from_secs(DEFAULT_TIMEOUT_SECS),
+            retry_delay: Duration::from_secs(DEFAULT_RETRY_DELAY_SECS),
+            max_retry_delay: Duration::from_secs(DEFAULT_MAX_RETRY_DELAY_SECS),
+            max_message_len: DEFAULT_MAX_MESSAGE_LEN,
+            opt_events: None,
+            connections: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Open connections to trackers with the given connector.
+    pub fn with_connector<C>(mut self, connector: C) -> WsTrackerClient
+    where
+        C: HttpConnector + 'static,
+    {
+        self.connector = Arc::new(connector);
+
+        self
+    }
+
+    /// Time allowed for connecting to a tracker, and for a tracker to answer an announce.
+    pub fn with_timeout(mut self, timeout: Duration) -> WsTrackerClient {
+        self.timeout = timeout;
+
+        self
+    }
+
+    /// Delay before reconnecting to a tracker, doubled after every failed reconnect.
+    pub fn with_retry_delay(mut self, delay: Duration) -> WsTrackerClient {
+        self.retry_delay = delay;
+
+        self
+    }
+
+    /// Upper bound for the delay before reconnecting to a tracker.
+    pub fn with_max_retry_delay(mut self, delay: Duration) -> WsTrackerClient {
+        self.max_retry_delay = delay;
+
+        self
+    }
+
+    /// Maximum length of a message received from a tracker, in bytes.
+    pub fn with_max_message_len(mut self, max_message_len: usize) -> WsTrackerClient {
+        self.max_message_len = max_message_len;
+
+        self
+    }
+
+    /// Send every event received from trackers to the given sender.
+    pub fn with_events(mut self, events: mpsc::UnboundedSender<WsTrackerEvent>) -> WsTrackerClient {
+        self.opt_events = Some(events);
+
+        self
+    }
+
+    /// Announce to the tracker at the given url, connecting to it if not connected yet.
+    ///
+    /// Peers are only ever handed out as offers, so the response itself holds no peers.
+    pub async fn announce(
+        &self,
+        tracker: &Url,
+        request: &AnnounceRequest,
+    ) -> HttpTrackerResult<AnnounceResponse> {
+        if !self
+            .connector
+            .supports_scheme(http_scheme(&tracker.scheme)?)
+        {
+            return Err(HttpTrackerErrorKind::UnsupportedScheme {
+                scheme: tracker.scheme.clone(),
+            }
+            .into());
+        }
+
+        let reannounce = AnnounceRequest::new(
+            request.info_hash(),
+            request.peer_id(),
+            without_event(request.state()),
+            request.key(),
+            request.num_want(),
+            request.port(),
+        );
+        let (send, recv) = oneshot::channel();
+        let command = Command {
+            info_hash: request.info_hash(),
+            message: message::announce_message(request),
+            opt_reannounce: if request.state().event() == AnnounceEvent::Stopped {
+                None
+            } else {
+                Some(message::announce_message(&reannounce))
+            },
+            response: send,
+        };
+        self.connection(tracker)
+            .send(command)
+            .map_err(|_| disconnected("Connection Task Stopped"))?;
+
+        match time::timeout(self.timeout, recv).await {
+            Ok(Ok(result)) => result,
+            Ok(Err(_)) => Err(disconnected("Connection Task Stopped")),
+            Err(_) => Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "Tracker Did Not Respond In Time",
+            )
+            .into()),
+        }
+    }
+
+    /// Sender for the connection to the given tracker, starting it if it is not running.
+    fn connection(&self, tracker: &Url) -> mpsc::UnboundedSender<Command> {
+        let mut connections = self.connections.lock().unwrap();
+        let key = tracker.serialize();
+
+        if let Some(sender) = connections.get(&key) {
+            if !sender.is_closed() {
+                return sender.clone();
+            }
+        }
+
+        let (send, recv) = mpsc::unbounded_channel();
+        let connection = Connection {
+            client: self.clone_config(),
+            url: tracker.clone(),
+            commands: recv,
+            pending: HashMap::new(),
+            reannounces: HashMap::new(),
+            failures: 0,
+        };
+        tokio::spawn(connection.run());
+        connections.insert(key, send.clone());
+
+        send
+    }
+
+    /// Copy of the client configuration, without the connections.
+    fn clone_config(&self) -> WsTrackerClient {
+        WsTrackerClient {
+            connections: Arc::new(Mutex::new(HashMap::new())),
+            ..self.clone()
+        }
+    }
+}
+
+impl Default for WsTrackerClient {
+    fn default() -> WsTrackerClient {
+        WsTrackerClient::new()
+    }
+}
+
+impl TrackerTransport for WsTrackerClient {
+    fn send_announce(
+        &self,
+        tracker: &TrackerUrl,
+        request: &AnnounceRequest,
+    ) -> BoxFuture<'static, HttpTrackerResult<AnnounceResponse>> {
+        let (client, url, request) = (self.clone(), tracker.url().clone(), request.clone());
+        let protocol = tracker.protocol();
+
+        Box::pin(async move {
+            if let TrackerProtocol::Ws | TrackerProtocol::Wss = protocol {
+                client.announce(&url, &request).await
+            } else {
+                Err(HttpTrackerErrorKind::UnsupportedScheme {
+                    scheme: url.scheme.clone(),
+                }
+                .into())
+            }
+        })
+    }
+}
+
+// ----------------------------------------------------------------------------//
+
+/// Announce to send over the connection, answered once the tracker responds.
+struct Command {
+    info_hash: InfoHash,
+    message: String,
+    opt_reannounce: Option<String>,
+    response: oneshot::Sender<HttpTrackerResult<AnnounceResponse>>,
+}
+
+/// Reason a connected socket stopped being used.
+enum Disconnect {
+    /// Every client handle is gone.
+    Shutdown,
+    Dropped(HttpTrackerError),
+}
+
+/// Task keeping the websocket to a single tracker open.
+struct Connection {
+    client: WsTrackerClient,
+    url: Url,
+    commands: mpsc::UnboundedReceiver<Command>,
+    pending: HashMap<InfoHash, Vec<oneshot::Sender<HttpTrackerResult<AnnounceResponse>>>>,
+    reannounces: HashMap<InfoHash, String>,
+    failures: u32,
+}
+
+impl Connection {
+    async fn run(mut self) {
+        let mut queued = Vec::new();
+
+        loop {
+            // Nothing to announce, so no reason to be connected
+            if queued.is_empty() && self.reannounces.is_empty() && self.pending.is_empty() {
+                match self.commands.recv().await {
+                    Some(command) => queued.push(self.queue(command)),
+                    None => return,
+                }
+            }
+
+            let connect = time::timeout(self.client.timeout, self.connect());
+            let result = match connect.await {
+                Ok(Ok((stream, reader))) => {
+                    self.failures = 0;
+                    self.exchange(stream, reader, &mut queued).await
+                }
+                Ok(Err(error)) => Disconnect::Dropped(error),
+                Err(_) => Disconnect::Dropped(
+                    io::Error::new(io::ErrorKind::TimedOut, "Tracker Did Not Connect In Time")
+                        .into(),
+                ),
+            };
+
+            match result {
+                Disconnect::Shutdown => return,
+                Disconnect::Dropped(error) => {
+                    for (_, responses) in self.pending.drain() {
+                        for response in responses {
+                            let _ = response.send(Err(disconnected(&error.to_string())));
+                        }
+                    }
+                    queued.clear();
+                }
+            }
+
+            let delay = self
+                .client
+                .retry_delay
+                .checked_mul(2u32.saturating_pow(self.failures))
+                .unwrap_or(self.client.max_retry_delay);
+            self.failures = self.failures.saturating_add(1);
+            time::sleep(cmp::min(delay, self.client.max_retry_delay)).await;
+
+            // Pick up announces made while disconnected, instead of failing them right away
+            loop {
+                match self.commands.try_recv() {
+                    Ok(command) => queued.push(self.queue(command)),
+                    Err(TryRecvError::Empty) => break,
+                    Err(TryRecvError::Disconnected) => return,
+                }
+            }
+        }
+    }
+
+    /// Register the command, returning the message to send.
+    fn queue(&mut self, command: Command) -> String {
+        match command.opt_reannounce {
+            Some(reannounce) => self.reannounces.insert(command.info_hash, reannounce),
+            None => self.reannounces.remove(&command.info_hash),
+        };
+        self.pending
+            .entry(command.info_hash)
+            .or_insert_with(Vec::new)
+            .push(command.response);
+
+        command.message
+    }
+
+    /// Open the socket and complete the websocket handshake.
+    async fn connect(&self) -> HttpTrackerResult<(Box<dyn HttpStream>, FrameReader)> {
+        let invalid_url = || HttpTrackerErrorKind::InvalidUrl {
+            url: self.url.serialize(),
+        };
+        let host = self.url.serialize_host().ok_or_else(invalid_url)?;
+        let port = self.url.port_or_default().ok_or_else(invalid_url)?;
+        let host_header = match self.url.port() {
+            Some(port) => format!("{}:{}", host, port),
+            None => host.clone(),
+        };
+        let target = match self.url.query {
+            Some(ref query) => format!("{}?{}", self.url.serialize_path().unwrap(), query),
+            None => self.url.serialize_path().unwrap(),
+        };
+
+        let mut stream = self
+            .client
+            .connector
+            .connect(http_scheme(&self.url.scheme)?, &host, port)
+            .await
+            .map_err(client::io_error)?;
+        let key = frame::handshake_key(&rand::random());
+        stream
+            .write_all(&frame::handshake_request(&target, &host_header, &key))
+            .await?;
+        stream.flush().await?;
+
+        let mut head = Vec::new();
+        let mut buffer = [0u8; READ_BUFFER_LEN];
+        loop {
+            let bytes_read = stream.read(&mut buffer).await?;
+            if bytes_read == 0 {
+                return Err(disconnected("Tracker Closed The Handshake"));
+            }
+            head.extend_from_slice(&buffer[..bytes_read]);
+
+            if let Some(head_len) = frame::check_handshake(&head, &key)? {
+                let mut reader = FrameReader::new(self.client.max_message_len);
+                reader.extend(&head[head_len..]);
+
+                return Ok((stream, reader));
+            }
+            if head.len() > self.client.max_message_len {
+                return Err(disconnected("Handshake Response Too Long"));
+            }
+        }
+    }
+
+    /// Exchange messages with the tracker until the socket drops.
+    async fn exchange(
+        &mut self,
+        stream: Box<dyn HttpStream>,
+        mut reader: FrameReader,
+        queued: &mut Vec<String>,
+    ) -> Disconnect {
+        let (mut read_half, mut write_half) = tokio::io::split(stream);
+
+        // Announce every torrent again after reconnecting, besides the ones just queued
+        let mut messages: Vec<String> = self
+            .reannounces
+            .iter()
+            .filter(|&(hash, _)| !self.pending.contains_key(hash))
+            .map(|(_, message)| message.clone())
+            .collect();
+        messages.append(queued);
+        for message in messages {
+            if let Err(error) = send(&mut write_half, frame::TEXT_OPCODE, message.as_bytes()).await
+            {
+                return Disconnect::Dropped(error);
+            }
+        }
+
+        let mut buffer = [0u8; READ_BUFFER_LEN];
+        loop {
+            // Handle everything already buffered before waiting on the socket again
+            loop {
+                match reader.next_message() {
+                    Ok(Some(Message::Text(text))) => self.handle(&text),
+                    Ok(Some(Message::Ping(payload))) => {
+                        if let Err(error) =
+                            send(&mut write_half, frame::PONG_OPCODE, &payload).await
+                        {
+                            return Disconnect::Dropped(error);
+                        }
+                    }
+                    Ok(Some(Message::Close)) => {
+                        return Disconnect::Dropped(disconnected("Tracker Closed The Connection"))
+                    }
+                    Ok(Some(_)) => (),
+                    Ok(None) => break,
+                    Err(error) => return Disconnect::Dropped(error),
+                }
+            }
+
+            tokio::select! {
+                opt_command = self.commands.recv() => {
+                    let message = match opt_command {
+                        Some(command) => self.queue(command),
+                        None => {
+                            let _ = send(&mut write_half, frame::CLOSE_OPCODE, &[]).await;
+
+                            return Disconnect::Shutdown;
+                        }
+                    };
+
+                    if let Err(error) = send(&mut write_half, frame::TEXT_OPCODE, message.as_bytes()).await {
+                        return Disconnect::Dropped(error);
+                    }
+                }
+                read_result = read_half.read(&mut buffer) => {
+                    match read_result {
+                        Ok(0) => return Disconnect::Dropped(disconnected("Tracker Closed The Connection")),
+                        Ok(bytes_read) => reader.extend(&buffer[..bytes_read]),
+                        Err(error) => return Disconnect::Dropped(error.into()),
+                    }
+                }
+            }
+        }
+    }
+
+    /// Handle a text message received from the tracker.
+    fn handle(&mut self, text: &str) {
+        // Trackers relay messages of other peers as is, so a bad one does not end the connection
+        let event = match message::parse_message(text) {
+            Ok(TrackerMessage::Response(info_hash, response)) => {
+                for sender in self.pending.remove(&info_hash).into_iter().flatten() {
+                    let _ = sender.send(Ok(response.clone()));
+                }
+
+                WsTrackerEvent::Announce(info_hash, response)
+            }
+            Ok(TrackerMessage::Failure(opt_info_hash, reason)) => {
+                let senders = match opt_info_hash {
+                    Some(info_hash) => self.pending.remove(&info_hash).unwrap_or_default(),
+                    None => self
+                        .pending
+                        .drain()
+                        .flat_map(|(_, senders)| senders)
+                        .collect(),
+                };
+                for sender in senders {
+                    let _ = sender.send(Err(HttpTrackerErrorKind::TrackerFailure {
+                        reason: reason.clone(),
+                    }
+                    .into()));
+                }
+
+                WsTrackerEvent::Failure(opt_info_hash, reason)
+            }
+            Ok(TrackerMessage::Offer(signal)) => WsTrackerEvent::Offer(signal),
+            Ok(TrackerMessage::Answer(signal)) => WsTrackerEvent::Answer(signal),
+            Ok(TrackerMessage::Other) | Err(_) => return, // TODO: Add Logging
+        };
+
+        if let Some(ref events) = self.client.opt_events {
+            let _ = events.send(event);
+        }
+    }
+}
+
+/// Send a single masked frame to the tracker.
+async fn send(
+    stream: &mut WriteHalf<Box<dyn HttpStream>>,
+    opcode: u8,
+    payload: &[u8],
+) -> HttpTrackerResult<()> {
+    stream
+        .write_all(&frame::encode_frame(opcode, payload, rand::random()))
+        .await?;
+    stream.flush().await?;
+
+    Ok(())
+}
+
+/// Scheme of the http url a websocket url is connected to as.
+fn http_scheme(scheme: &str) -> HttpTrackerResult<&'static str> {
+    match scheme {
+        "ws" => Ok("http"),
+        "wss" => Ok("https"),
+        _ => Err(HttpTrackerErrorKind::UnsupportedScheme {
+            scheme: scheme.to_owned(),
+        }
+        .into()),
+    }
+}
+
+fn without_event(state: ClientState) -> ClientState {
+    ClientState::new(
+        state.bytes_downloaded(),
+        state.bytes_left(),
+        state.bytes_uploaded(),
+        AnnounceEvent::None,
+    )
+}
+
+fn disconnected(details: &str) -> HttpTrackerError {
+    io::Error::new(
+        io::ErrorKind::ConnectionAborted,
+        format!("WebSocket Tracker Disconnected: {}", details),
+    )
+    .into()
+}
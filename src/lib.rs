@@ -34,6 +34,7 @@ pub mod utp;
 pub mod handshake;
 pub mod peer;
 pub mod select;
+pub mod session;
 
 #[cfg(test)]
 mod tests {
@@ -0,0 +1,321 @@
+use std::io;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV6, UdpSocket};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Sender};
+use std::sync::Arc;
+use std::time::Duration;
+
+use rand;
+
+use crate::lsd::message::{LSD_MULTICAST_V4, LSD_MULTICAST_V6, LSD_PORT};
+use crate::lsd::worker::{self, AnnounceSocket, LsdTask, LsdWorker, MIN_ANNOUNCE_INTERVAL_SECS};
+use crate::util::bt::InfoHash;
+use crate::utracker::Handshaker;
+
+const DEFAULT_ANNOUNCE_INTERVAL_SECS: u64 = 5 * 60;
+
+/// Stores information for initializing a local service discovery service.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LsdBuilder {
+    ipv4: bool,
+    ipv6: bool,
+    interface_v4: Ipv4Addr,
+    interface_v6: u32,
+    announce_interval: Duration,
+}
+
+impl Default for LsdBuilder {
+    fn default() -> LsdBuilder {
+        LsdBuilder::new()
+    }
+}
+
+impl LsdBuilder {
+    /// Create a new LsdBuilder, announcing over IPv4 on the default interface.
+    pub fn new() -> LsdBuilder {
+        LsdBuilder {
+            ipv4: true,
+            ipv6: false,
+            interface_v4: Ipv4Addr::UNSPECIFIED,
+            interface_v6: 0,
+            announce_interval: Duration::from_secs(DEFAULT_ANNOUNCE_INTERVAL_SECS),
+        }
+    }
+
+    /// Whether to announce to and look for peers in the IPv4 multicast group.
+    ///
+    /// Default value is true.
+    pub fn set_ipv4(mut self, ipv4: bool) -> LsdBuilder {
+        self.ipv4 = ipv4;
+
+        self
+    }
+
+    /// Whether to announce to and look for peers in the IPv6 multicast group.
+    ///
+    /// Default value is false.
+    pub fn set_ipv6(mut self, ipv6: bool) -> LsdBuilder {
+        self.ipv6 = ipv6;
+
+        self
+    }
+
+    /// Address of the interface to join the IPv4 multicast group and send announcements on.
+    ///
+    /// Default value is the unspecified address, leaving the interface up to the os.
+    pub fn set_interface_v4(mut self, interface: Ipv4Addr) -> LsdBuilder {
+        self.interface_v4 = interface;
+
+        self
+    }
+
+    /// Index of the interface to join the IPv6 multicast group and send announcements on.
+    ///
+    /// Default value is 0, leaving the interface up to the os.
+    pub fn set_interface_v6(mut self, index: u32) -> LsdBuilder {
+        self.interface_v6 = index;
+
+        self
+    }
+
+    /// Interval at which we announce our torrents again.
+    ///
+    /// Intervals below a minute are raised to a minute, as asked for by BEP 14. Default
+    /// value is 5 minutes.
+    pub fn set_announce_interval(mut self, interval: Duration) -> LsdBuilder {
+        self.announce_interval = interval.max(Duration::from_secs(MIN_ANNOUNCE_INTERVAL_SECS));
+
+        self
+    }
+
+    /// Start a local service discovery service, connecting to peers found through the handshaker.
+    ///
+    /// Announcements carry the port of the handshaker.
+    pub fn start<H>(self, handshaker: H) -> io::Result<LsdService>
+    where
+        H: Handshaker + 'static,
+    {
+        if !self.ipv4 && !self.ipv6 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "No Address Family Enabled For Local Service Discovery",
+            ));
+        }
+
+        let (send, recv) = mpsc::channel();
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let mut sockets = Vec::new();
+
+        if self.ipv4 {
+            let listen = bind_reusable(SocketAddr::from((Ipv4Addr::UNSPECIFIED, LSD_PORT)))?;
+            listen.join_multicast_v4(&LSD_MULTICAST_V4, &self.interface_v4)?;
+
+            // Bound to the interface address, multicast goes out over that interface
+            let socket = UdpSocket::bind(SocketAddr::from((self.interface_v4, 0)))?;
+            sockets.push(AnnounceSocket {
+                socket: socket,
+                group: SocketAddr::from((LSD_MULTICAST_V4, LSD_PORT)),
+            });
+
+            worker::spawn_receiver(listen, send.clone(), shutdown.clone())?;
+        }
+
+        if self.ipv6 {
+            let listen = bind_reusable(SocketAddr::from((Ipv6Addr::UNSPECIFIED, LSD_PORT)))?;
+            listen.join_multicast_v6(&LSD_MULTICAST_V6, self.interface_v6)?;
+
+            // Scope id of the group picks the interface multicast goes out over
+            let socket = UdpSocket::bind(SocketAddr::from((Ipv6Addr::UNSPECIFIED, 0)))?;
+            sockets.push(AnnounceSocket {
+                socket: socket,
+                group: SocketAddr::V6(SocketAddrV6::new(
+                    LSD_MULTICAST_V6,
+                    LSD_PORT,
+                    0,
+                    self.interface_v6,
+                )),
+            });
+
+            worker::spawn_receiver(listen, send.clone(), shutdown.clone())?;
+        }
+
+        let cookie = format!("{:016x}", rand::random::<u64>());
+        let lsd_worker = LsdWorker::new(handshaker, cookie, self.announce_interval);
+        worker::spawn_worker(lsd_worker, sockets, recv, shutdown.clone());
+
+        Ok(LsdService {
+            send: send,
+            shutdown: shutdown,
+        })
+    }
+}
+
+/// Bind a socket to the given address, letting other clients on this host bind it as well.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn bind_reusable(addr: SocketAddr) -> io::Result<UdpSocket> {
+    use std::mem;
+    use std::os::unix::io::FromRawFd;
+
+    fn set_option(fd: libc::c_int, level: libc::c_int, name: libc::c_int) -> io::Result<()> {
+        let enable: libc::c_int = 1;
+        let result = unsafe {
+            libc::setsockopt(
+                fd,
+                level,
+                name,
+                &enable as *const libc::c_int as *const libc::c_void,
+                mem::size_of::<libc::c_int>() as libc::socklen_t,
+            )
+        };
+
+        if result == 0 {
+            Ok(())
+        } else {
+            Err(io::Error::last_os_error())
+        }
+    }
+
+    let domain = if addr.is_ipv6() {
+        libc::AF_INET6
+    } else {
+        libc::AF_INET
+    };
+    let fd = unsafe { libc::socket(domain, libc::SOCK_DGRAM | libc::SOCK_CLOEXEC, 0) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // Closes the socket for us if anything below fails
+    let socket = unsafe { UdpSocket::from_raw_fd(fd) };
+
+    set_option(fd, libc::SOL_SOCKET, libc::SO_REUSEADDR)?;
+
+    let result = match addr {
+        SocketAddr::V4(v4_addr) => {
+            let mut raw: libc::sockaddr_in = unsafe { mem::zeroed() };
+            raw.sin_family = libc::AF_INET as libc::sa_family_t;
+            raw.sin_port = v4_addr.port().to_be();
+            raw.sin_addr.s_addr = u32::from(*v4_addr.ip()).to_be();
+
+            unsafe {
+                libc::bind(
+                    fd,
+                    &raw as *const libc::sockaddr_in as *const libc::sockaddr,
+                    mem::size_of::<libc::sockaddr_in>() as libc::socklen_t,
+                )
+            }
+        }
+        SocketAddr::V6(v6_addr) => {
+            // Our IPv4 socket is bound to the same port
+            set_option(fd, libc::IPPROTO_IPV6, libc::IPV6_V6ONLY)?;
+
+            let mut raw: libc::sockaddr_in6 = unsafe { mem::zeroed() };
+            raw.sin6_family = libc::AF_INET6 as libc::sa_family_t;
+            raw.sin6_port = v6_addr.port().to_be();
+            raw.sin6_addr.s6_addr = v6_addr.ip().octets();
+            raw.sin6_scope_id = v6_addr.scope_id();
+
+            unsafe {
+                libc::bind(
+                    fd,
+                    &raw as *const libc::sockaddr_in6 as *const libc::sockaddr,
+                    mem::size_of::<libc::sockaddr_in6>() as libc::socklen_t,
+                )
+            }
+        }
+    };
+
+    if result == 0 {
+        Ok(socket)
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+/// Bind a socket to the given address, other clients on this host will not be able to bind it.
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+fn bind_reusable(addr: SocketAddr) -> io::Result<UdpSocket> {
+    UdpSocket::bind(addr)
+}
+
+// ----------------------------------------------------------------------------//
+
+/// Local service discovery service, finding peers on the local network as described in BEP 14.
+///
+/// Peers found for the torrents we added are connected to through the handshaker, just like
+/// peers found through trackers or the DHT.
+pub struct LsdService {
+    send: Sender<LsdTask>,
+    shutdown: Arc<AtomicBool>,
+}
+
+impl LsdService {
+    /// Start announcing and looking for peers of the InfoHash.
+    pub fn add_info_hash(&self, info_hash: InfoHash) {
+        self.send_task(LsdTask::AddInfoHash(info_hash));
+    }
+
+    /// Stop announcing and looking for peers of the InfoHash.
+    pub fn remove_info_hash(&self, info_hash: InfoHash) {
+        self.send_task(LsdTask::RemoveInfoHash(info_hash));
+    }
+
+    fn send_task(&self, task: LsdTask) {
+        if self.send.send(task).is_err() {
+            warn!("bittorrent-protocol_lsd: LsdService failed to send a task to the worker (may have already been shutdown)...");
+        }
+    }
+}
+
+impl Drop for LsdService {
+    fn drop(&mut self) {
+        self.shutdown.store(true, Ordering::Relaxed);
+        self.send_task(LsdTask::Shutdown);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use std::net::SocketAddr;
+    use std::time::Duration;
+
+    use crate::util::bt::{InfoHash, PeerId};
+    use crate::utracker::Handshaker;
+
+    use super::LsdBuilder;
+
+    struct NoopHandshaker;
+
+    impl Handshaker for NoopHandshaker {
+        type Metadata = ();
+
+        fn id(&self) -> PeerId {
+            [0u8; 20].into()
+        }
+
+        fn port(&self) -> u16 {
+            6881
+        }
+
+        fn connect(&mut self, _: Option<PeerId>, _: InfoHash, _: SocketAddr) {}
+
+        fn metadata(&mut self, _: ()) {}
+    }
+
+    #[test]
+    fn positive_announce_interval_clamped() {
+        let builder = LsdBuilder::new().set_announce_interval(Duration::from_secs(5));
+
+        assert_eq!(builder.announce_interval, Duration::from_secs(60));
+    }
+
+    #[test]
+    fn negative_start_no_address_family() {
+        let result = LsdBuilder::new()
+            .set_ipv4(false)
+            .set_ipv6(false)
+            .start(NoopHandshaker);
+
+        assert_eq!(result.err().unwrap().kind(), io::ErrorKind::InvalidInput);
+    }
+}
@@ -0,0 +1,340 @@
+use std::error::Error;
+use std::fmt;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::str;
+
+use crate::util::bt::{InfoHash, INFO_HASH_LEN};
+
+/// Port that local service discovery announcements are multicast to.
+pub const LSD_PORT: u16 = 6771;
+
+/// IPv4 multicast group that local service discovery announcements are sent to.
+pub const LSD_MULTICAST_V4: Ipv4Addr = Ipv4Addr::new(239, 192, 152, 143);
+
+/// IPv6 multicast group that local service discovery announcements are sent to.
+pub const LSD_MULTICAST_V6: Ipv6Addr = Ipv6Addr::new(0xff15, 0, 0, 0, 0, 0, 0xefc0, 0x988f);
+
+/// Most InfoHashs we put in a single announcement, keeping it well within a single datagram.
+pub const MAX_ANNOUNCE_INFO_HASHES: usize = 20;
+
+const REQUEST_LINE: &'static str = "BT-SEARCH * HTTP/1.1";
+
+const HOST_HEADER: &'static str = "Host";
+const PORT_HEADER: &'static str = "Port";
+const INFO_HASH_HEADER: &'static str = "Infohash";
+const COOKIE_HEADER: &'static str = "cookie";
+
+/// Error parsing a local service discovery announcement.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum LsdError {
+    /// Announcement is not valid UTF-8 text.
+    InvalidText,
+    /// Announcement does not start with the BT-SEARCH request line.
+    InvalidRequestLine,
+    /// Header line is not a name and value separated by a colon.
+    InvalidHeader,
+    /// Port header is missing.
+    MissingPort,
+    /// Port header is not a valid, non zero, port.
+    InvalidPort,
+    /// Announcement holds no InfoHashs.
+    MissingInfoHash,
+    /// Infohash header is not a hex encoded InfoHash.
+    InvalidInfoHash,
+}
+
+impl fmt::Display for LsdError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            &LsdError::InvalidText => f.write_str("Announcement Is Not Valid UTF-8"),
+            &LsdError::InvalidRequestLine => {
+                f.write_str("Announcement Has An Invalid Request Line")
+            }
+            &LsdError::InvalidHeader => f.write_str("Announcement Has An Invalid Header Line"),
+            &LsdError::MissingPort => f.write_str("Announcement Has No Port"),
+            &LsdError::InvalidPort => f.write_str("Announcement Has An Invalid Port"),
+            &LsdError::MissingInfoHash => f.write_str("Announcement Has No InfoHash"),
+            &LsdError::InvalidInfoHash => f.write_str("Announcement Has An Invalid InfoHash"),
+        }
+    }
+}
+
+impl Error for LsdError {}
+
+// ----------------------------------------------------------------------------//
+
+/// Local service discovery announcement, as described in BEP 14.
+///
+/// Announces that the sender accepts peer connections for the InfoHashs on the given port, at
+/// the address the announcement came from.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct LsdAnnounce {
+    port: u16,
+    info_hashes: Vec<InfoHash>,
+    opt_cookie: Option<String>,
+}
+
+impl LsdAnnounce {
+    /// Create a new LsdAnnounce.
+    ///
+    /// The cookie lets the sender recognize its own announcements when they are looped back to it.
+    pub fn new(port: u16, info_hashes: Vec<InfoHash>, opt_cookie: Option<String>) -> LsdAnnounce {
+        LsdAnnounce {
+            port: port,
+            info_hashes: info_hashes,
+            opt_cookie: opt_cookie,
+        }
+    }
+
+    /// Parse an announcement from the given bytes.
+    ///
+    /// Header names are matched case insensitively and unknown headers are ignored.
+    pub fn from_bytes(bytes: &[u8]) -> Result<LsdAnnounce, LsdError> {
+        let text = str::from_utf8(bytes).map_err(|_| LsdError::InvalidText)?;
+        let mut lines = text.split('\n').map(|line| line.trim_end_matches('\r'));
+
+        if lines.next() != Some(REQUEST_LINE) {
+            return Err(LsdError::InvalidRequestLine);
+        }
+
+        let mut opt_port = None;
+        let mut info_hashes = Vec::new();
+        let mut opt_cookie = None;
+
+        for line in lines.take_while(|line| !line.is_empty()) {
+            let (name, value) = line.split_once(':').ok_or(LsdError::InvalidHeader)?;
+            let value = value.trim();
+
+            if name.eq_ignore_ascii_case(PORT_HEADER) {
+                let port = value.parse::<u16>().map_err(|_| LsdError::InvalidPort)?;
+                if port == 0 {
+                    return Err(LsdError::InvalidPort);
+                }
+
+                opt_port = Some(port);
+            } else if name.eq_ignore_ascii_case(INFO_HASH_HEADER) {
+                let info_hash = decode_info_hash(value).ok_or(LsdError::InvalidInfoHash)?;
+
+                if !info_hashes.contains(&info_hash) {
+                    info_hashes.push(info_hash);
+                }
+            } else if name.eq_ignore_ascii_case(COOKIE_HEADER) {
+                opt_cookie = Some(value.to_owned());
+            }
+        }
+
+        let port = opt_port.ok_or(LsdError::MissingPort)?;
+        if info_hashes.is_empty() {
+            return Err(LsdError::MissingInfoHash);
+        }
+
+        Ok(LsdAnnounce::new(port, info_hashes, opt_cookie))
+    }
+
+    /// Write the announcement, sent to the given multicast group, as bytes.
+    pub fn write_bytes(&self, group: SocketAddr) -> Vec<u8> {
+        let mut text = format!(
+            "{}\r\n{}: {}\r\n{}: {}\r\n",
+            REQUEST_LINE, HOST_HEADER, group, PORT_HEADER, self.port
+        );
+
+        for info_hash in self.info_hashes.iter() {
+            text.push_str(&format!(
+                "{}: {}\r\n",
+                INFO_HASH_HEADER,
+                encode_hex(info_hash.as_ref())
+            ));
+        }
+
+        if let Some(ref cookie) = self.opt_cookie {
+            text.push_str(&format!("{}: {}\r\n", COOKIE_HEADER, cookie));
+        }
+
+        // Trailing empty lines as in the examples of BEP 14
+        text.push_str("\r\n\r\n");
+
+        text.into_bytes()
+    }
+
+    /// Port the sender accepts peer connections on.
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    /// InfoHashs the sender is announcing.
+    pub fn info_hashes(&self) -> &[InfoHash] {
+        &self.info_hashes
+    }
+
+    /// Cookie the sender tagged the announcement with, if any.
+    pub fn cookie(&self) -> Option<&str> {
+        self.opt_cookie.as_ref().map(|cookie| &cookie[..])
+    }
+}
+
+fn decode_info_hash(hex: &str) -> Option<InfoHash> {
+    if hex.len() != INFO_HASH_LEN * 2 || !hex.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+        return None;
+    }
+
+    let bytes: Vec<u8> = (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+        .collect();
+
+    InfoHash::from_hash(&bytes).ok()
+}
+
+fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use crate::util::bt::InfoHash;
+
+    use super::{LsdAnnounce, LsdError, LSD_MULTICAST_V4, LSD_MULTICAST_V6, LSD_PORT};
+
+    // Laid out the way libtorrent sends them
+    const ANNOUNCE: &'static [u8] = b"BT-SEARCH * HTTP/1.1\r\n\
+        Host: 239.192.152.143:6771\r\n\
+        Port: 6881\r\n\
+        Infohash: 0102030405060708090a0b0c0d0e0f1011121314\r\n\
+        cookie: 5f3a9c1e\r\n\
+        \r\n\r\n";
+
+    fn info_hash(byte: u8) -> InfoHash {
+        [byte; 20].into()
+    }
+
+    #[test]
+    fn positive_parse_libtorrent_announce() {
+        let announce = LsdAnnounce::from_bytes(ANNOUNCE).unwrap();
+
+        let expected: Vec<u8> = (1..21).collect();
+        assert_eq!(announce.port(), 6881);
+        assert_eq!(
+            announce.info_hashes(),
+            &[InfoHash::from_hash(&expected).unwrap()]
+        );
+        assert_eq!(announce.cookie(), Some("5f3a9c1e"));
+    }
+
+    #[test]
+    fn positive_round_trip_multiple_info_hashes() {
+        let announce = LsdAnnounce::new(
+            6881,
+            vec![info_hash(1), info_hash(2), info_hash(3)],
+            Some("abcd".to_owned()),
+        );
+        let group = SocketAddr::from((LSD_MULTICAST_V4, LSD_PORT));
+
+        let bytes = announce.write_bytes(group);
+        assert!(bytes.starts_with(b"BT-SEARCH * HTTP/1.1\r\nHost: 239.192.152.143:6771\r\n"));
+
+        assert_eq!(LsdAnnounce::from_bytes(&bytes).unwrap(), announce);
+    }
+
+    #[test]
+    fn positive_write_ipv6_host() {
+        let announce = LsdAnnounce::new(6881, vec![info_hash(1)], None);
+        let group = SocketAddr::from((LSD_MULTICAST_V6, LSD_PORT));
+
+        let bytes = announce.write_bytes(group);
+        let text = String::from_utf8(bytes.clone()).unwrap();
+
+        assert!(text.contains("Host: [ff15::efc0:988f]:6771\r\n"));
+        assert_eq!(LsdAnnounce::from_bytes(&bytes).unwrap(), announce);
+    }
+
+    #[test]
+    fn positive_parse_case_insensitive_bare_newlines() {
+        let announce = LsdAnnounce::from_bytes(
+            b"BT-SEARCH * HTTP/1.1\n\
+            host: 239.192.152.143:6771\n\
+            PORT: 51413\n\
+            INFOHASH: 0101010101010101010101010101010101010101\n\
+            X-Unknown: ignored\n\n",
+        )
+        .unwrap();
+
+        assert_eq!(announce.port(), 51413);
+        assert_eq!(announce.info_hashes(), &[info_hash(1)]);
+        assert_eq!(announce.cookie(), None);
+    }
+
+    #[test]
+    fn positive_parse_duplicate_info_hashes() {
+        let announce = LsdAnnounce::new(6881, vec![info_hash(1), info_hash(1)], None);
+        let bytes = announce.write_bytes(([239, 192, 152, 143], LSD_PORT).into());
+
+        assert_eq!(
+            LsdAnnounce::from_bytes(&bytes).unwrap().info_hashes(),
+            &[info_hash(1)]
+        );
+    }
+
+    #[test]
+    fn negative_parse_wrong_request_line() {
+        assert_eq!(
+            LsdAnnounce::from_bytes(b"M-SEARCH * HTTP/1.1\r\nPort: 6881\r\n\r\n"),
+            Err(LsdError::InvalidRequestLine)
+        );
+    }
+
+    #[test]
+    fn negative_parse_missing_port() {
+        assert_eq!(
+            LsdAnnounce::from_bytes(
+                b"BT-SEARCH * HTTP/1.1\r\nInfohash: 0101010101010101010101010101010101010101\r\n\r\n"
+            ),
+            Err(LsdError::MissingPort)
+        );
+    }
+
+    #[test]
+    fn negative_parse_zero_port() {
+        assert_eq!(
+            LsdAnnounce::from_bytes(b"BT-SEARCH * HTTP/1.1\r\nPort: 0\r\n\r\n"),
+            Err(LsdError::InvalidPort)
+        );
+    }
+
+    #[test]
+    fn negative_parse_missing_info_hash() {
+        assert_eq!(
+            LsdAnnounce::from_bytes(b"BT-SEARCH * HTTP/1.1\r\nPort: 6881\r\n\r\n"),
+            Err(LsdError::MissingInfoHash)
+        );
+    }
+
+    #[test]
+    fn negative_parse_short_info_hash() {
+        assert_eq!(
+            LsdAnnounce::from_bytes(
+                b"BT-SEARCH * HTTP/1.1\r\nPort: 6881\r\nInfohash: 0101\r\n\r\n"
+            ),
+            Err(LsdError::InvalidInfoHash)
+        );
+    }
+
+    #[test]
+    fn negative_parse_non_hex_info_hash() {
+        assert_eq!(
+            LsdAnnounce::from_bytes(
+                b"BT-SEARCH * HTTP/1.1\r\nPort: 6881\r\nInfohash: +101010101010101010101010101010101010101\r\n\r\n"
+            ),
+            Err(LsdError::InvalidInfoHash)
+        );
+    }
+
+    #[test]
+    fn negative_parse_invalid_header() {
+        assert_eq!(
+            LsdAnnounce::from_bytes(b"BT-SEARCH * HTTP/1.1\r\nPort 6881\r\n\r\n"),
+            Err(LsdError::InvalidHeader)
+        );
+    }
+}
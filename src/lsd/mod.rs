@@ -1,2 +1,15 @@
-#[test]
-fn it_works() {}
+//! Local service discovery, finding peers of our torrents on the local network (BEP 14).
+//!
+//! Announcements are multicast to 239.192.152.143:6771 and [ff15::efc0:988f]:6771, peers
+//! announcing torrents we are interested in are handed to the same `Handshaker` that
+//! trackers hand their peers to.
+
+mod builder;
+pub use self::builder::{LsdBuilder, LsdService};
+
+mod message;
+pub use self::message::{
+    LsdAnnounce, LsdError, LSD_MULTICAST_V4, LSD_MULTICAST_V6, LSD_PORT, MAX_ANNOUNCE_INFO_HASHES,
+};
+
+mod worker;
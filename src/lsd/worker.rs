@@ -0,0 +1,528 @@
+use std::collections::HashMap;
+use std::io;
+use std::net::{SocketAddr, UdpSocket};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{Receiver, RecvTimeoutError, Sender};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use rand;
+
+use crate::lsd::message::{LsdAnnounce, MAX_ANNOUNCE_INFO_HASHES};
+use crate::util::bt::InfoHash;
+use crate::utracker::Handshaker;
+
+/// BEP 14 asks for no more than one announcement per torrent per minute.
+pub const MIN_ANNOUNCE_INTERVAL_SECS: u64 = 60;
+
+/// Delay before announcing a torrent that was just added, spread so that peers starting
+/// together do not announce in lock step.
+const ADD_ANNOUNCE_JITTER_MS: u64 = 2 * 1000;
+
+/// Spread added on top of the announce interval for the same reason.
+const ANNOUNCE_JITTER_MS: u64 = 30 * 1000;
+
+/// Peers announcing a torrent again within this interval are not connected to again.
+const PEER_CONNECT_INTERVAL_SECS: u64 = 60;
+
+/// Most peers we remember connecting to within the connect interval, new peers past that
+/// are not connected to until older ones expire.
+const MAX_RECENT_CONNECTS: usize = 1024;
+
+/// Interval at which the receive threads check whether the service shut down.
+const RECV_SHUTDOWN_CHECK_MS: u64 = 1000;
+
+/// How long the worker waits for tasks while it has nothing to announce.
+const IDLE_TIMEOUT_SECS: u64 = 60;
+
+const MAX_ANNOUNCE_LEN: usize = 2048;
+
+/// Task for the local service discovery worker.
+pub enum LsdTask {
+    /// Start announcing and looking for peers of the InfoHash.
+    AddInfoHash(InfoHash),
+    /// Stop announcing and looking for peers of the InfoHash.
+    RemoveInfoHash(InfoHash),
+    /// Received an announcement from the given address.
+    Incoming(Vec<u8>, SocketAddr),
+    /// Shut down the worker.
+    Shutdown,
+}
+
+/// Socket we send announcements from, along with the multicast group they are sent to.
+pub struct AnnounceSocket {
+    pub socket: UdpSocket,
+    pub group: SocketAddr,
+}
+
+/// Announce state of a single torrent.
+struct Torrent {
+    next_announce: Instant,
+    opt_last_announce: Option<Instant>,
+}
+
+/// Local service discovery state, kept separate from the sockets so it can be driven by hand.
+pub struct LsdWorker<H> {
+    handshaker: H,
+    port: u16,
+    cookie: String,
+    announce_interval: Duration,
+    torrents: HashMap<InfoHash, Torrent>,
+    // Last announce of torrents that were removed, so adding them back does not skip the limit
+    removed: HashMap<InfoHash, Instant>,
+    connected: HashMap<(InfoHash, SocketAddr), Instant>,
+}
+
+impl<H> LsdWorker<H>
+where
+    H: Handshaker,
+{
+    pub fn new(handshaker: H, cookie: String, announce_interval: Duration) -> LsdWorker<H> {
+        let port = handshaker.port();
+
+        LsdWorker {
+            handshaker: handshaker,
+            port: port,
+            cookie: cookie,
+            announce_interval: announce_interval,
+            torrents: HashMap::new(),
+            removed: HashMap::new(),
+            connected: HashMap::new(),
+        }
+    }
+
+    pub fn add_info_hash(&mut self, info_hash: InfoHash, now: Instant) {
+        if self.torrents.contains_key(&info_hash) {
+            return;
+        }
+
+        let opt_last_announce = self.removed.remove(&info_hash);
+        let mut next_announce = now + jitter(ADD_ANNOUNCE_JITTER_MS);
+        if let Some(last_announce) = opt_last_announce {
+            next_announce =
+                next_announce.max(last_announce + Duration::from_secs(MIN_ANNOUNCE_INTERVAL_SECS));
+        }
+
+        self.torrents.insert(
+            info_hash,
+            Torrent {
+                next_announce: next_announce,
+                opt_last_announce: opt_last_announce,
+            },
+        );
+    }
+
+    pub fn remove_info_hash(&mut self, info_hash: InfoHash) {
+        if let Some(torrent) = self.torrents.remove(&info_hash) {
+            if let Some(last_announce) = torrent.opt_last_announce {
+                self.removed.insert(info_hash, last_announce);
+            }
+        }
+
+        self.connected.retain(|&(hash, _), _| hash != info_hash);
+    }
+
+    /// Time at which the next announcement is due, if we have anything to announce.
+    pub fn next_announce(&self) -> Option<Instant> {
+        self.torrents
+            .values()
+            .map(|torrent| torrent.next_announce)
+            .min()
+    }
+
+    /// Announcements due at the given time.
+    ///
+    /// Torrents due a little later are announced along to fill up the announcements, as long
+    /// as that does not announce them more than once per minute.
+    pub fn due_announcements(&mut self, now: Instant) -> Vec<LsdAnnounce> {
+        let min_interval = Duration::from_secs(MIN_ANNOUNCE_INTERVAL_SECS);
+        let batch_until = now + Duration::from_millis(ADD_ANNOUNCE_JITTER_MS);
+
+        let is_due = |torrent: &Torrent| torrent.next_announce <= now;
+        if !self.torrents.values().any(is_due) {
+            return Vec::new();
+        }
+
+        let mut info_hashes: Vec<InfoHash> = self
+            .torrents
+            .iter()
+            .filter(|&(_, torrent)| {
+                let rate_limited = torrent
+                    .opt_last_announce
+                    .map(|last_announce| now < last_announce + min_interval)
+                    .unwrap_or(false);
+
+                torrent.next_announce <= batch_until && !rate_limited
+            })
+            .map(|(&info_hash, _)| info_hash)
+            .collect();
+        info_hashes.sort();
+
+        for info_hash in info_hashes.iter() {
+            let torrent = self.torrents.get_mut(info_hash).unwrap();
+
+            torrent.opt_last_announce = Some(now);
+            torrent.next_announce = now + self.announce_interval + jitter(ANNOUNCE_JITTER_MS);
+        }
+
+        // Torrents that were rate limited are picked up once their minute is up
+        for torrent in self.torrents.values_mut().filter(|torrent| is_due(torrent)) {
+            torrent.next_announce = torrent.opt_last_announce.unwrap() + min_interval;
+        }
+
+        self.removed
+            .retain(|_, &mut last_announce| now < last_announce + min_interval);
+        self.connected.retain(|_, &mut connect| {
+            now < connect + Duration::from_secs(PEER_CONNECT_INTERVAL_SECS)
+        });
+
+        info_hashes
+            .chunks(MAX_ANNOUNCE_INFO_HASHES)
+            .map(|chunk| LsdAnnounce::new(self.port, chunk.to_vec(), Some(self.cookie.clone())))
+            .collect()
+    }
+
+    /// Handle an announcement from the given address, connecting to the peer for any of the
+    /// torrents we are interested in.
+    pub fn handle_announce(&mut self, bytes: &[u8], addr: SocketAddr, now: Instant) {
+        let announce = match LsdAnnounce::from_bytes(bytes) {
+            Ok(announce) => announce,
+            Err(error) => {
+                info!(
+                    "bittorrent-protocol_lsd: Received an invalid announcement from {}: {}",
+                    addr, error
+                );
+                return;
+            }
+        };
+
+        // Our own announcements are looped back to us
+        if announce.cookie() == Some(&self.cookie[..]) {
+            return;
+        }
+
+        let connect_interval = Duration::from_secs(PEER_CONNECT_INTERVAL_SECS);
+
+        // Keep the scope id of link local IPv6 addresses around
+        let mut peer_addr = addr;
+        peer_addr.set_port(announce.port());
+
+        for &info_hash in announce.info_hashes() {
+            if !self.torrents.contains_key(&info_hash) {
+                continue;
+            }
+
+            let recently_connected = self
+                .connected
+                .get(&(info_hash, peer_addr))
+                .map(|&connect| now < connect + connect_interval)
+                .unwrap_or(false);
+            if recently_connected {
+                continue;
+            }
+
+            // Anyone can multicast to us, do not let them grow our state without bound
+            if self.connected.len() >= MAX_RECENT_CONNECTS {
+                self.connected
+                    .retain(|_, &mut connect| now < connect + connect_interval);
+            }
+            if self.connected.len() >= MAX_RECENT_CONNECTS {
+                continue;
+            }
+
+            self.connected.insert((info_hash, peer_addr), now);
+            self.handshaker.connect(None, info_hash, peer_addr);
+        }
+    }
+}
+
+/// Random delay of up to the given number of milliseconds.
+fn jitter(max_ms: u64) -> Duration {
+    Duration::from_millis(rand::random::<u64>() % (max_ms + 1))
+}
+
+// ----------------------------------------------------------------------------//
+
+/// Spawns the worker announcing over the given sockets and handling tasks from the channel.
+pub fn spawn_worker<H>(
+    mut worker: LsdWorker<H>,
+    sockets: Vec<AnnounceSocket>,
+    recv: Receiver<LsdTask>,
+    shutdown: Arc<AtomicBool>,
+) where
+    H: Handshaker + 'static,
+{
+    thread::spawn(move || {
+        loop {
+            let now = Instant::now();
+            for announce in worker.due_announcements(now) {
+                for socket in sockets.iter() {
+                    let bytes = announce.write_bytes(socket.group);
+
+                    if let Err(error) = socket.socket.send_to(&bytes, socket.group) {
+                        warn!(
+                            "bittorrent-protocol_lsd: Failed to send an announcement to {}: {}",
+                            socket.group, error
+                        );
+                    }
+                }
+            }
+
+            let timeout = worker
+                .next_announce()
+                .map(|next_announce| next_announce.saturating_duration_since(Instant::now()))
+                .unwrap_or(Duration::from_secs(IDLE_TIMEOUT_SECS));
+
+            match recv.recv_timeout(timeout) {
+                Ok(LsdTask::AddInfoHash(info_hash)) => {
+                    worker.add_info_hash(info_hash, Instant::now())
+                }
+                Ok(LsdTask::RemoveInfoHash(info_hash)) => worker.remove_info_hash(info_hash),
+                Ok(LsdTask::Incoming(bytes, addr)) => {
+                    worker.handle_announce(&bytes, addr, Instant::now())
+                }
+                Ok(LsdTask::Shutdown) | Err(RecvTimeoutError::Disconnected) => break,
+                Err(RecvTimeoutError::Timeout) => (),
+            }
+        }
+
+        shutdown.store(true, Ordering::Relaxed);
+        info!("bittorrent-protocol_lsd: Worker received a shutdown, exiting thread...");
+    });
+}
+
+/// Spawns a thread forwarding the announcements received on the socket to the worker.
+pub fn spawn_receiver(
+    socket: UdpSocket,
+    send: Sender<LsdTask>,
+    shutdown: Arc<AtomicBool>,
+) -> io::Result<()> {
+    socket.set_read_timeout(Some(Duration::from_millis(RECV_SHUTDOWN_CHECK_MS)))?;
+
+    thread::spawn(move || {
+        let mut buffer = vec![0u8; MAX_ANNOUNCE_LEN];
+
+        while !shutdown.load(Ordering::Relaxed) {
+            match socket.recv_from(&mut buffer) {
+                Ok((size, addr)) => {
+                    if send
+                        .send(LsdTask::Incoming(buffer[..size].to_vec(), addr))
+                        .is_err()
+                    {
+                        break;
+                    }
+                }
+                Err(ref error)
+                    if error.kind() == io::ErrorKind::WouldBlock
+                        || error.kind() == io::ErrorKind::TimedOut => {}
+                Err(error) => {
+                    warn!(
+                        "bittorrent-protocol_lsd: Failed to receive an announcement: {}",
+                        error
+                    );
+                    break;
+                }
+            }
+        }
+    });
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+    use std::sync::mpsc::{self, Receiver, Sender};
+    use std::time::{Duration, Instant};
+
+    use crate::lsd::message::{LsdAnnounce, LSD_MULTICAST_V4, LSD_PORT, MAX_ANNOUNCE_INFO_HASHES};
+    use crate::util::bt::{InfoHash, PeerId};
+    use crate::utracker::Handshaker;
+
+    use super::{LsdWorker, ANNOUNCE_JITTER_MS, MIN_ANNOUNCE_INTERVAL_SECS};
+
+    const ANNOUNCE_INTERVAL_SECS: u64 = 5 * 60;
+
+    struct MockHandshaker {
+        send: Sender<(InfoHash, SocketAddr)>,
+    }
+
+    impl Handshaker for MockHandshaker {
+        type Metadata = ();
+
+        fn id(&self) -> PeerId {
+            [0u8; 20].into()
+        }
+
+        fn port(&self) -> u16 {
+            6881
+        }
+
+        fn connect(&mut self, _: Option<PeerId>, hash: InfoHash, addr: SocketAddr) {
+            self.send.send((hash, addr)).unwrap();
+        }
+
+        fn metadata(&mut self, _: ()) {}
+    }
+
+    fn worker() -> (LsdWorker<MockHandshaker>, Receiver<(InfoHash, SocketAddr)>) {
+        let (send, recv) = mpsc::channel();
+        let worker = LsdWorker::new(
+            MockHandshaker { send: send },
+            "ourcookie".to_owned(),
+            Duration::from_secs(ANNOUNCE_INTERVAL_SECS),
+        );
+
+        (worker, recv)
+    }
+
+    fn info_hash(byte: u8) -> InfoHash {
+        [byte; 20].into()
+    }
+
+    fn announce_bytes(info_hashes: Vec<InfoHash>, cookie: &str) -> Vec<u8> {
+        LsdAnnounce::new(51413, info_hashes, Some(cookie.to_owned()))
+            .write_bytes((LSD_MULTICAST_V4, LSD_PORT).into())
+    }
+
+    #[test]
+    fn positive_announce_added_info_hashes_together() {
+        let (mut worker, _recv) = worker();
+        let now = Instant::now();
+
+        worker.add_info_hash(info_hash(1), now);
+        worker.add_info_hash(info_hash(2), now);
+
+        let announces = worker.due_announcements(now + Duration::from_secs(10));
+        assert_eq!(announces.len(), 1);
+        assert_eq!(announces[0].port(), 6881);
+        assert_eq!(announces[0].info_hashes(), &[info_hash(1), info_hash(2)]);
+        assert_eq!(announces[0].cookie(), Some("ourcookie"));
+    }
+
+    #[test]
+    fn positive_announce_splits_info_hashes() {
+        let (mut worker, _recv) = worker();
+        let now = Instant::now();
+
+        for byte in 0..(MAX_ANNOUNCE_INFO_HASHES as u8 + 1) {
+            worker.add_info_hash(info_hash(byte), now);
+        }
+
+        let announces = worker.due_announcements(now + Duration::from_secs(10));
+        assert_eq!(announces.len(), 2);
+        assert_eq!(announces[0].info_hashes().len(), MAX_ANNOUNCE_INFO_HASHES);
+        assert_eq!(announces[1].info_hashes().len(), 1);
+    }
+
+    #[test]
+    fn positive_reannounce_after_interval() {
+        let (mut worker, _recv) = worker();
+        let now = Instant::now() + Duration::from_secs(10);
+
+        worker.add_info_hash(info_hash(1), Instant::now());
+        assert_eq!(worker.due_announcements(now).len(), 1);
+        assert!(worker.due_announcements(now).is_empty());
+
+        let next_announce = worker.next_announce().unwrap();
+        assert!(next_announce >= now + Duration::from_secs(ANNOUNCE_INTERVAL_SECS));
+        assert!(
+            next_announce
+                <= now + Duration::from_millis(ANNOUNCE_INTERVAL_SECS * 1000 + ANNOUNCE_JITTER_MS)
+        );
+        assert_eq!(worker.due_announcements(next_announce).len(), 1);
+    }
+
+    #[test]
+    fn positive_readd_rate_limited() {
+        let (mut worker, _recv) = worker();
+        let now = Instant::now() + Duration::from_secs(10);
+
+        worker.add_info_hash(info_hash(1), Instant::now());
+        assert_eq!(worker.due_announcements(now).len(), 1);
+
+        worker.remove_info_hash(info_hash(1));
+        worker.add_info_hash(info_hash(1), now);
+        assert!(worker
+            .due_announcements(now + Duration::from_secs(10))
+            .is_empty());
+
+        let min_interval = Duration::from_secs(MIN_ANNOUNCE_INTERVAL_SECS);
+        assert_eq!(worker.next_announce(), Some(now + min_interval));
+        assert_eq!(worker.due_announcements(now + min_interval).len(), 1);
+    }
+
+    #[test]
+    fn positive_announce_connects_interested() {
+        let (mut worker, recv) = worker();
+        let now = Instant::now();
+        worker.add_info_hash(info_hash(1), now);
+        worker.add_info_hash(info_hash(2), now);
+
+        let addr: SocketAddr = "192.168.1.20:6771".parse().unwrap();
+        let bytes = announce_bytes(vec![info_hash(1), info_hash(2), info_hash(3)], "other");
+        worker.handle_announce(&bytes, addr, now);
+
+        let peer_addr: SocketAddr = "192.168.1.20:51413".parse().unwrap();
+        let connects: Vec<(InfoHash, SocketAddr)> = recv.try_iter().collect();
+        assert_eq!(
+            connects,
+            vec![(info_hash(1), peer_addr), (info_hash(2), peer_addr)]
+        );
+    }
+
+    #[test]
+    fn positive_announce_keeps_scope_id() {
+        let (mut worker, recv) = worker();
+        let now = Instant::now();
+        worker.add_info_hash(info_hash(1), now);
+
+        let addr: SocketAddr = "[fe80::1%3]:6771".parse().unwrap();
+        worker.handle_announce(&announce_bytes(vec![info_hash(1)], "other"), addr, now);
+
+        let peer_addr: SocketAddr = "[fe80::1%3]:51413".parse().unwrap();
+        assert_eq!(recv.try_recv().unwrap(), (info_hash(1), peer_addr));
+    }
+
+    #[test]
+    fn positive_repeated_announce_connects_once() {
+        let (mut worker, recv) = worker();
+        let now = Instant::now();
+        worker.add_info_hash(info_hash(1), now);
+
+        let addr: SocketAddr = "192.168.1.20:6771".parse().unwrap();
+        let bytes = announce_bytes(vec![info_hash(1)], "other");
+        worker.handle_announce(&bytes, addr, now);
+        worker.handle_announce(&bytes, addr, now + Duration::from_secs(30));
+        assert_eq!(recv.try_iter().count(), 1);
+
+        worker.handle_announce(&bytes, addr, now + Duration::from_secs(61));
+        assert_eq!(recv.try_iter().count(), 1);
+    }
+
+    #[test]
+    fn negative_own_announce_ignored() {
+        let (mut worker, recv) = worker();
+        let now = Instant::now();
+        worker.add_info_hash(info_hash(1), now);
+
+        let addr: SocketAddr = "192.168.1.20:6771".parse().unwrap();
+        worker.handle_announce(&announce_bytes(vec![info_hash(1)], "ourcookie"), addr, now);
+
+        assert!(recv.try_recv().is_err());
+    }
+
+    #[test]
+    fn negative_announce_removed_info_hash_ignored() {
+        let (mut worker, recv) = worker();
+        let now = Instant::now();
+        worker.add_info_hash(info_hash(1), now);
+        worker.remove_info_hash(info_hash(1));
+
+        let addr: SocketAddr = "192.168.1.20:6771".parse().unwrap();
+        worker.handle_announce(&announce_bytes(vec![info_hash(1)], "other"), addr, now);
+
+        assert!(recv.try_recv().is_err());
+    }
+}
@@ -0,0 +1,29 @@
+use std::error::Error;
+use std::fmt;
+
+/// Reason a magnet link could not be parsed.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum MagnetError {
+    /// Link is not a valid uri, with the reason.
+    InvalidUri(String),
+    /// Link is a uri with a scheme other than magnet, with the scheme.
+    NotMagnetLink(String),
+    /// Link has no exact topic naming a BitTorrent info hash.
+    MissingExactTopic,
+}
+
+impl fmt::Display for MagnetError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MagnetError::InvalidUri(reason) => write!(f, "Invalid Magnet Link Uri: {}", reason),
+            MagnetError::NotMagnetLink(scheme) => {
+                write!(f, "Uri With Scheme {:?} Is Not A Magnet Link", scheme)
+            }
+            MagnetError::MissingExactTopic => {
+                write!(f, "Magnet Link Has No BitTorrent Exact Topic")
+            }
+        }
+    }
+}
+
+impl Error for MagnetError {}
@@ -1,43 +1,64 @@
+use crate::metainfo::{Metainfo, SHA256_HASH_LEN};
 use crate::util::bt::InfoHash;
-use crate::util::sha::ShaHash;
 use std::default::Default;
+use std::fmt;
+use std::ops::RangeInclusive;
+
+use url::form_urlencoded;
 use url::Url;
 
+mod error;
+pub use self::error::MagnetError;
+
+/// Multihash prefix of a SHA-256 digest in hex, as used by `urn:btmh:` topics.
+const SHA256_MULTIHASH_PREFIX: &'static str = "1220";
+
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub enum Topic {
     BitTorrentInfoHash(InfoHash),
+    /// SHA-256 hash of the info dictionary of a v2 torrent.
+    BitTorrentMultihash([u8; SHA256_HASH_LEN]),
 }
 
 impl Topic {
     fn parse(s: &str) -> Option<Self> {
-        if s.starts_with("urn:btih:") && s.len() == 9 + 40 {
-            // BitTorrent Info Hash, hex
-            let mut hash = Vec::with_capacity(20);
-            for i in 0..20 {
-                let j = 9 + 2 * i;
-                match u8::from_str_radix(&s[j..j + 2], 16) {
-                    Ok(byte) => hash.push(byte),
-                    Err(_) => return None,
+        if s.starts_with("urn:btih:") {
+            // BitTorrent Info Hash, hex or base-32
+            s[9..]
+                .parse::<InfoHash>()
+                .ok()
+                .filter(|hash| !hash.is_v2())
+                .map(Topic::BitTorrentInfoHash)
+        } else if s.starts_with("urn:btmh:") {
+            // BitTorrent v2 Info Hash, hex multihash, only SHA-256 is used
+            let digest = s[9..].strip_prefix(SHA256_MULTIHASH_PREFIX)?;
+
+            match digest.parse::<InfoHash>() {
+                Ok(InfoHash::V2(hash)) if digest.len() == 2 * SHA256_HASH_LEN => {
+                    Some(Topic::BitTorrentMultihash(hash))
                 }
+                _ => None,
             }
-            match ShaHash::from_hash(&hash[..]) {
-                Ok(sha_hash) => Some(Topic::BitTorrentInfoHash(sha_hash)),
-                Err(_) => None,
-            }
-        } else if s.starts_with("urn:btih:") && s.len() == 9 + 32 {
-            // BitTorrent Info Hash, base-32
-            base32::decode(base32::Alphabet::RFC4648 { padding: true }, &s[9..]).and_then(|hash| {
-                match ShaHash::from_hash(&hash[..]) {
-                    Ok(sha_hash) => Some(Topic::BitTorrentInfoHash(sha_hash)),
-                    Err(_) => None,
-                }
-            })
         } else {
             None
         }
     }
 }
 
+impl fmt::Display for Topic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Topic::BitTorrentInfoHash(info_hash) => write!(f, "urn:btih:{}", info_hash),
+            Topic::BitTorrentMultihash(hash) => write!(
+                f,
+                "urn:btmh:{}{}",
+                SHA256_MULTIHASH_PREFIX,
+                InfoHash::V2(*hash)
+            ),
+        }
+    }
+}
+
 /**
  * From <https://en.wikipedia.org/wiki/Magnet_URI_scheme#Parameters>:
  *
@@ -49,17 +70,26 @@ impl Topic {
  * kt (Keyword Topic) – Key words for search
  * mt (Manifest Topic) – link to the metafile that contains a list of magneto (MAGMA – MAGnet MAnifest)
  * tr (address TRacker) – Tracker URL for BitTorrent downloads
+ *
+ * From BEP 9 and BEP 53:
+ *
+ * ws (Web Seed) – Url of a web seed for the torrent
+ * so (Select Only) – Indices and ranges of the files to download
+ * x.pe (Peer) – Address of a peer for the torrent
  **/
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct MagnetLink {
     display_name: Option<String>,
-    exact_length: Option<usize>,
-    exact_topic: Option<Topic>,
+    exact_length: Option<u64>,
+    exact_topics: Vec<Topic>,
     acceptable_source: Vec<String>,
     exact_source: Vec<String>,
     keyword_topic: Vec<String>,
     manifest_topic: Option<String>,
     address_tracker: Vec<String>,
+    web_seed: Vec<String>,
+    select_only: Vec<RangeInclusive<usize>>,
+    peer: Vec<(String, u16)>,
 }
 
 impl Default for MagnetLink {
@@ -67,43 +97,48 @@ impl Default for MagnetLink {
         MagnetLink {
             display_name: None,
             exact_length: None,
-            exact_topic: None,
+            exact_topics: vec![],
             acceptable_source: vec![],
             exact_source: vec![],
             keyword_topic: vec![],
             manifest_topic: None,
             address_tracker: vec![],
+            web_seed: vec![],
+            select_only: vec![],
+            peer: vec![],
         }
     }
 }
 
 impl MagnetLink {
-    pub fn parse(s: &str) -> Option<Self> {
+    /// Parse the given magnet link.
+    ///
+    /// Parameters that can not be parsed are skipped, but at least one exact topic has to
+    /// name a BitTorrent info hash, either v1 (`urn:btih:`, hex or base-32) or v2
+    /// (`urn:btmh:`). Values are percent decoded, with `+` decoded as a space.
+    pub fn parse(s: &str) -> Result<Self, MagnetError> {
         // Parse URL
         let url = match Url::parse(s) {
             Ok(url) => url,
-            Err(_) => return None,
+            Err(err) => return Err(MagnetError::InvalidUri(err.to_string())),
         };
         // Is Magnet Link?
         if url.scheme != "magnet" {
-            return None;
+            return Err(MagnetError::NotMagnetLink(url.scheme));
         };
 
         // Gather Magnet Link data from query string
         let mut result: Self = Default::default();
-        let pairs = match url.query_pairs() {
-            Some(pairs) => pairs,
-            None => return None,
-        };
+        let pairs = url.query_pairs().unwrap_or_else(Vec::new);
         for (k, v) in pairs {
             match &k[..] {
                 "dn" => result.display_name = Some(v),
-                "xl" => match usize::from_str_radix(&v[..], 10) {
+                "xl" => match u64::from_str_radix(&v[..], 10) {
                     Ok(exact_length) => result.exact_length = Some(exact_length),
                     Err(_) => (),
                 },
                 "xt" => match Topic::parse(&v[..]) {
-                    Some(topic) => result.exact_topic = Some(topic),
+                    Some(topic) => result.exact_topics.push(topic),
                     None => (),
                 },
                 "as" => result.acceptable_source.push(v),
@@ -111,25 +146,239 @@ impl MagnetLink {
                 "kt" => result.keyword_topic.push(v),
                 "mt" => result.manifest_topic = Some(v),
                 "tr" => result.address_tracker.push(v),
+                "ws" => result.web_seed.push(v),
+                "so" => result
+                    .select_only
+                    .extend(v.split(',').filter_map(parse_select_only)),
+                "x.pe" => match parse_peer(&v[..]) {
+                    Some(peer) => result.peer.push(peer),
+                    None => (),
+                },
                 _ => (),
             }
         }
 
-        Some(result)
+        if result.exact_topics.is_empty() {
+            return Err(MagnetError::MissingExactTopic);
+        }
+
+        Ok(result)
     }
 
+    /// Create a magnet link for the given metainfo file.
+    ///
+    /// The link names the v1 info hash, and the v2 info hash of a hybrid torrent, with every
+    /// tracker in tier order after the main tracker, every web seed, and the length of the
+    /// files, not counting padding files.
+    pub fn from_metainfo(metainfo: &Metainfo) -> Self {
+        let info = metainfo.info();
+
+        let mut exact_topics = vec![Topic::BitTorrentInfoHash(info.info_hash())];
+        if let Some(InfoHash::V2(hash)) = info.v2_info_hash() {
+            exact_topics.push(Topic::BitTorrentMultihash(hash));
+        }
+
+        let display_name = match info.directory() {
+            Some(directory) => directory.to_string_lossy().into_owned(),
+            None => info
+                .files()
+                .next()
+                .map(|file| file.path().to_string_lossy().into_owned())
+                .unwrap_or_default(),
+        };
+
+        let mut address_tracker: Vec<String> = metainfo
+            .main_tracker()
+            .map(String::from)
+            .into_iter()
+            .collect();
+        for tracker in metainfo.trackers().into_iter().flatten().flatten() {
+            if !address_tracker.contains(tracker) {
+                address_tracker.push(tracker.clone());
+            }
+        }
+
+        MagnetLink {
+            display_name: Some(display_name).filter(|name| !name.is_empty()),
+            exact_length: Some(
+                info.files()
+                    .filter(|file| !file.is_padding())
+                    .map(|file| file.length())
+                    .sum(),
+            ),
+            exact_topics: exact_topics,
+            address_tracker: address_tracker,
+            web_seed: metainfo.web_seeds().cloned().unwrap_or_else(Vec::new),
+            ..Default::default()
+        }
+    }
+
+    /// Info hash of the v1 torrent, if the link names one.
     pub fn get_info_hash(&self) -> Option<InfoHash> {
-        match self.exact_topic {
-            Some(Topic::BitTorrentInfoHash(info_hash)) => Some(info_hash),
+        self.exact_topics.iter().find_map(|topic| match topic {
+            Topic::BitTorrentInfoHash(info_hash) => Some(*info_hash),
             _ => None,
+        })
+    }
+
+    /// Info hash of the v2 torrent, if the link names one.
+    pub fn get_v2_info_hash(&self) -> Option<InfoHash> {
+        self.exact_topics.iter().find_map(|topic| match topic {
+            Topic::BitTorrentMultihash(hash) => Some(InfoHash::V2(*hash)),
+            _ => None,
+        })
+    }
+
+    /// Exact topics of the link that name a BitTorrent info hash.
+    pub fn get_exact_topics(&self) -> &[Topic] {
+        &self.exact_topics
+    }
+
+    /// Name to display for the torrent.
+    pub fn get_display_name(&self) -> Option<&str> {
+        self.display_name.as_ref().map(|name| &name[..])
+    }
+
+    /// Total length in bytes of the files of the torrent.
+    pub fn get_exact_length(&self) -> Option<u64> {
+        self.exact_length
+    }
+
+    /// Announce urls of the trackers, in the order found in the link.
+    pub fn get_trackers(&self) -> &[String] {
+        &self.address_tracker
+    }
+
+    /// Urls of the web seeds serving the files of the torrent.
+    pub fn get_web_seeds(&self) -> &[String] {
+        &self.web_seed
+    }
+
+    /// Web links to the files of the torrent.
+    pub fn get_acceptable_sources(&self) -> &[String] {
+        &self.acceptable_source
+    }
+
+    /// Links to the metainfo file of the torrent.
+    pub fn get_exact_sources(&self) -> &[String] {
+        &self.exact_source
+    }
+
+    /// Key words to search for the torrent with.
+    pub fn get_keyword_topics(&self) -> &[String] {
+        &self.keyword_topic
+    }
+
+    /// Link to a list of magnet links.
+    pub fn get_manifest_topic(&self) -> Option<&str> {
+        self.manifest_topic.as_ref().map(|topic| &topic[..])
+    }
+
+    /// Ranges of the indices of the files to download, every file if empty.
+    pub fn get_select_only(&self) -> &[RangeInclusive<usize>] {
+        &self.select_only
+    }
+
+    /// Host and port of each peer to connect to directly.
+    pub fn get_peers(&self) -> &[(String, u16)] {
+        &self.peer
+    }
+}
+
+impl fmt::Display for MagnetLink {
+    /// Write the canonical link, with the exact topics first, followed by every other
+    /// parameter in a fixed order.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let topics: Vec<String> = self
+            .exact_topics
+            .iter()
+            .map(|topic| format!("xt={}", topic))
+            .collect();
+
+        let mut pairs: Vec<(&str, String)> = Vec::new();
+        pairs.extend(self.display_name.iter().map(|name| ("dn", name.clone())));
+        pairs.extend(self.exact_length.iter().map(|len| ("xl", len.to_string())));
+        pairs.extend(self.address_tracker.iter().map(|url| ("tr", url.clone())));
+        pairs.extend(self.web_seed.iter().map(|url| ("ws", url.clone())));
+        pairs.extend(self.acceptable_source.iter().map(|url| ("as", url.clone())));
+        pairs.extend(self.exact_source.iter().map(|url| ("xs", url.clone())));
+        pairs.extend(self.keyword_topic.iter().map(|words| ("kt", words.clone())));
+        pairs.extend(self.manifest_topic.iter().map(|url| ("mt", url.clone())));
+        if !self.select_only.is_empty() {
+            let ranges: Vec<String> = self
+                .select_only
+                .iter()
+                .map(|range| {
+                    if range.start() == range.end() {
+                        range.start().to_string()
+                    } else {
+                        format!("{}-{}", range.start(), range.end())
+                    }
+                })
+                .collect();
+
+            pairs.push(("so", ranges.join(",")));
+        }
+        pairs.extend(
+            self.peer
+                .iter()
+                .map(|&(ref host, port)| ("x.pe", format_peer(host, port))),
+        );
+
+        write!(f, "magnet:?{}", topics.join("&"))?;
+        if !pairs.is_empty() {
+            write!(f, "&{}", form_urlencoded::serialize(pairs))?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Parse a file index, or an inclusive range of file indices.
+fn parse_select_only(s: &str) -> Option<RangeInclusive<usize>> {
+    let (start, end) = match s.find('-') {
+        Some(index) => (s[..index].parse().ok()?, s[index + 1..].parse().ok()?),
+        None => {
+            let index = s.parse().ok()?;
+
+            (index, index)
         }
+    };
+
+    if start <= end {
+        Some(start..=end)
+    } else {
+        None
+    }
+}
+
+/// Parse a peer address of a host and port, with IPv6 hosts in brackets.
+fn parse_peer(s: &str) -> Option<(String, u16)> {
+    let index = s.rfind(':')?;
+    let port = s[index + 1..].parse().ok().filter(|&port| port != 0)?;
+    let host = s[..index].trim_start_matches('[').trim_end_matches(']');
+
+    if host.is_empty() {
+        None
+    } else {
+        Some((host.to_owned(), port))
+    }
+}
+
+fn format_peer(host: &str, port: u16) -> String {
+    if host.contains(':') {
+        format!("[{}]:{}", host, port)
+    } else {
+        format!("{}:{}", host, port)
     }
 }
 
 #[cfg(test)]
 mod tests {
 
-    use crate::util::sha::ShaHash;
+    use super::{MagnetError, MagnetLink, Topic};
+    use crate::metainfo::{DirectAccessor, Metainfo, MetainfoBuilder, PieceLength};
+    use crate::util::bt::InfoHash;
 
     #[test]
     fn test_wikipedia() {
@@ -149,7 +398,7 @@ mod tests {
         ];
         assert_eq!(
             link.get_info_hash(),
-            Some(ShaHash::from_hash(&expected_info_hash[..]).unwrap())
+            Some(InfoHash::from_hash(&expected_info_hash[..]).unwrap())
         );
 
         assert_eq!(link.exact_length, Some(10826029));
@@ -189,7 +438,7 @@ mod tests {
         ];
         assert_eq!(
             link.get_info_hash(),
-            Some(ShaHash::from_hash(&expected_info_hash[..]).unwrap())
+            Some(InfoHash::from_hash(&expected_info_hash[..]).unwrap())
         );
 
         assert_eq!(
@@ -206,4 +455,294 @@ mod tests {
             ]
         );
     }
+
+    /// Magnet links as written by common clients and sites, with the hex v1 info hash, hex v2
+    /// multihash, display name, number of trackers and number of web seeds expected from each.
+    const REAL_WORLD_LINKS: &[(&str, Option<&str>, Option<&str>, Option<&str>, usize, usize)] = &[
+        (
+            "magnet:?xt=urn:btih:dd8255ecdc7ca55fb0bbf81323d87062db1f6d1c&dn=Big+Buck+Bunny\
+             &tr=udp%3A%2F%2Fexplodie.org%3A6969&tr=udp%3A%2F%2Ftracker.coppersurfer.tk%3A6969\
+             &tr=udp%3A%2F%2Ftracker.empire-js.us%3A1337&tr=udp%3A%2F%2Ftracker.leechers-paradise.org%3A6969\
+             &tr=udp%3A%2F%2Ftracker.opentrackr.org%3A1337&tr=wss%3A%2F%2Ftracker.btorrent.xyz\
+             &tr=wss%3A%2F%2Ftracker.fastcast.nz&tr=wss%3A%2F%2Ftracker.openwebtorrent.com\
+             &ws=https%3A%2F%2Fwebtorrent.io%2Ftorrents%2F\
+             &xs=https%3A%2F%2Fwebtorrent.io%2Ftorrents%2Fbig-buck-bunny.torrent",
+            Some("dd8255ecdc7ca55fb0bbf81323d87062db1f6d1c"),
+            None,
+            Some("Big Buck Bunny"),
+            8,
+            1,
+        ),
+        (
+            "magnet:?xt=urn:btih:08ada5a7a6183aae1e09d831df6748d566095a10&dn=Sintel\
+             &tr=udp%3A%2F%2Fexplodie.org%3A6969&tr=wss%3A%2F%2Ftracker.webtorrent.io\
+             &ws=https%3A%2F%2Fwebtorrent.io%2Ftorrents%2F\
+             &xs=https%3A%2F%2Fwebtorrent.io%2Ftorrents%2Fsintel.torrent",
+            Some("08ada5a7a6183aae1e09d831df6748d566095a10"),
+            None,
+            Some("Sintel"),
+            2,
+            1,
+        ),
+        (
+            "magnet:?xt=urn:btih:631a31dd0a46257d5078c0dee4e66e26f73e42ac\
+             &xt=urn:btmh:1220d8dd32ac93357c368556af3ac1d95c9d76bd0dff6fa9833ecdac3d53134efabb\
+             &dn=bittorrent-v1-v2-hybrid-test",
+            Some("631a31dd0a46257d5078c0dee4e66e26f73e42ac"),
+            Some("d8dd32ac93357c368556af3ac1d95c9d76bd0dff6fa9833ecdac3d53134efabb"),
+            Some("bittorrent-v1-v2-hybrid-test"),
+            0,
+            0,
+        ),
+        (
+            "magnet:?xt=urn:btmh:1220caf1e1c30e81cb361b9ee167c4aa64228a7fa4fa9f6105232b28ad099f3a302e\
+             &dn=bittorrent-v2-test",
+            None,
+            Some("caf1e1c30e81cb361b9ee167c4aa64228a7fa4fa9f6105232b28ad099f3a302e"),
+            Some("bittorrent-v2-test"),
+            0,
+            0,
+        ),
+        (
+            "magnet:?xt=urn:btih:QHQXPYWMACKDWKP47RRVIV7VOURXFE5Q&xl=10826029\
+             &dn=mediawiki-1.15.1.tar.gz&tr=udp%3A%2F%2Ftracker.openbittorrent.com%3A80%2Fannounce",
+            Some("81e177e2cc00943b29fcfc635457f575237293b0"),
+            None,
+            Some("mediawiki-1.15.1.tar.gz"),
+            1,
+            0,
+        ),
+        (
+            "magnet:?xt=urn:btih:d9be6909325d28912f400fcb324005dd5861e49f\
+             &dn=Crunchbang+GNU%2FLinux+-+AMD64+ISO&tr=udp%3A%2F%2Ftracker.openbittorrent.com%3A80\
+             &tr=udp%3A%2F%2Fopen.demonii.com%3A1337",
+            Some("d9be6909325d28912f400fcb324005dd5861e49f"),
+            None,
+            Some("Crunchbang GNU/Linux - AMD64 ISO"),
+            2,
+            0,
+        ),
+        (
+            "magnet:?xt=urn:btih:A4104A9D2F5615601C429FE8BAB8177C47C05C84\
+             &dn=ubuntu-22.04.3-desktop-amd64.iso\
+             &tr=https%3A%2F%2Ftorrent.ubuntu.com%2Fannounce\
+             &tr=https%3A%2F%2Fipv6.torrent.ubuntu.com%2Fannounce",
+            Some("a4104a9d2f5615601c429fe8bab8177c47c05c84"),
+            None,
+            Some("ubuntu-22.04.3-desktop-amd64.iso"),
+            2,
+            0,
+        ),
+        (
+            "magnet:?xt=urn:btih:b7b0fbab74a85d4ac170662c645982a862826455\
+             &dn=debian-12.1.0-amd64-netinst.iso&xl=657457152\
+             &tr=http%3A%2F%2Fbttracker.debian.org%3A6969%2Fannounce",
+            Some("b7b0fbab74a85d4ac170662c645982a862826455"),
+            None,
+            Some("debian-12.1.0-amd64-netinst.iso"),
+            1,
+            0,
+        ),
+        (
+            "magnet:?xt=urn:btih:a88fda5954e89178c372716a6a78b8180ed4dad3\
+             &dn=The%20WIRED%20CD%20-%20Rip.%20Sample.%20Mash.%20Share\
+             &tr=udp%3A%2F%2Ftracker.leechers-paradise.org%3A6969\
+             &ws=https%3A%2F%2Fwebtorrent.io%2Ftorrents%2F",
+            Some("a88fda5954e89178c372716a6a78b8180ed4dad3"),
+            None,
+            Some("The WIRED CD - Rip. Sample. Mash. Share"),
+            1,
+            1,
+        ),
+        (
+            "magnet:?xt=urn:btih:c9e15763f722f23e98a29decdfae341b98d53056&dn=Cosmos+Laundromat\
+             &tr=udp%3A%2F%2Fexplodie.org%3A6969&tr=udp%3A%2F%2Ftracker.coppersurfer.tk%3A6969\
+             &ws=https%3A%2F%2Fwebtorrent.io%2Ftorrents%2F",
+            Some("c9e15763f722f23e98a29decdfae341b98d53056"),
+            None,
+            Some("Cosmos Laundromat"),
+            2,
+            1,
+        ),
+        (
+            "magnet:?xt=urn:btih:2cbd5ff1f0904d61b36ed8a6393ff9b6a5f9ef5b&dn=archive.org+item\
+             &tr=http%3A%2F%2Fbt1.archive.org%3A6969%2Fannounce\
+             &tr=http%3A%2F%2Fbt2.archive.org%3A6969%2Fannounce\
+             &ws=http%3A%2F%2Fia800300.us.archive.org%2F1%2Fitems%2F\
+             &ws=https%3A%2F%2Farchive.org%2Fdownload%2F",
+            Some("2cbd5ff1f0904d61b36ed8a6393ff9b6a5f9ef5b"),
+            None,
+            Some("archive.org item"),
+            2,
+            2,
+        ),
+        (
+            "magnet:?xt=urn:ed2k:354B15E68FB8F36D7CD88FF94116CDC1\
+             &xt=urn:btih:5c1ba4f4c7b1d9cea4a2f2aab4da7b0e1ea5c4a9\
+             &tr=udp%3A%2F%2Ftracker.torrent.eu.org%3A451%2Fannounce&so=0,2-3",
+            Some("5c1ba4f4c7b1d9cea4a2f2aab4da7b0e1ea5c4a9"),
+            None,
+            None,
+            1,
+            0,
+        ),
+    ];
+
+    fn hex_topic(link: &MagnetLink, v2: bool) -> Option<String> {
+        link.get_exact_topics()
+            .iter()
+            .find_map(|topic| match (topic, v2) {
+                (Topic::BitTorrentInfoHash(hash), false) => Some(hash.to_string()),
+                (Topic::BitTorrentMultihash(hash), true) => Some(InfoHash::V2(*hash).to_string()),
+                _ => None,
+            })
+    }
+
+    #[test]
+    fn positive_parse_real_world_links() {
+        for &(uri, v1_hex, v2_hex, display_name, trackers, web_seeds) in REAL_WORLD_LINKS {
+            let link = MagnetLink::parse(uri).unwrap();
+
+            assert_eq!(hex_topic(&link, false).as_deref(), v1_hex, "{}", uri);
+            assert_eq!(hex_topic(&link, true).as_deref(), v2_hex, "{}", uri);
+            assert_eq!(link.get_display_name(), display_name, "{}", uri);
+            assert_eq!(link.get_trackers().len(), trackers, "{}", uri);
+            assert_eq!(link.get_web_seeds().len(), web_seeds, "{}", uri);
+
+            // The canonical link parses back to the same link
+            assert_eq!(MagnetLink::parse(&link.to_string()).unwrap(), link);
+        }
+    }
+
+    fn hex_info_hash(hex: &str) -> InfoHash {
+        hex.parse().unwrap()
+    }
+
+    #[test]
+    fn positive_parse_v2_info_hash() {
+        let (uri, v1_hex, v2_hex, _, _, _) = REAL_WORLD_LINKS[2];
+        let link = MagnetLink::parse(uri).unwrap();
+
+        assert_eq!(link.get_info_hash(), Some(hex_info_hash(v1_hex.unwrap())));
+        assert_eq!(
+            link.get_v2_info_hash(),
+            Some(hex_info_hash(v2_hex.unwrap()))
+        );
+    }
+
+    #[test]
+    fn positive_parse_select_only_and_peers() {
+        let link = MagnetLink::parse(
+            "magnet:?xt=urn:btih:d9be6909325d28912f400fcb324005dd5861e49f&so=0,2,4-6,9-7,x\
+             &x.pe=10.0.0.1:6881&x.pe=%5B2001%3Adb8%3A%3A1%5D%3A51413&x.pe=peer.example.com:0",
+        )
+        .unwrap();
+
+        assert_eq!(link.get_select_only(), &[0..=0, 2..=2, 4..=6]);
+        assert_eq!(
+            link.get_peers(),
+            &[
+                ("10.0.0.1".to_owned(), 6881),
+                ("2001:db8::1".to_owned(), 51413)
+            ]
+        );
+    }
+
+    #[test]
+    fn positive_to_string_canonical() {
+        let link = MagnetLink::parse(
+            "magnet:?tr=udp%3A%2F%2Ftracker.example.com%3A6969&dn=My+File&so=4-6,0\
+             &xt=urn:btih:D9BE6909325D28912F400FCB324005DD5861E49F&x.pe=[::1]:6881",
+        )
+        .unwrap();
+
+        assert_eq!(
+            link.to_string(),
+            "magnet:?xt=urn:btih:d9be6909325d28912f400fcb324005dd5861e49f&dn=My+File\
+             &tr=udp%3A%2F%2Ftracker.example.com%3A6969&so=4-6%2C0&x.pe=%5B%3A%3A1%5D%3A6881"
+        );
+    }
+
+    #[test]
+    fn negative_parse_missing_exact_topic() {
+        assert_eq!(
+            MagnetLink::parse(
+                "magnet:?xt=urn:ed2k:354B15E68FB8F36D7CD88FF94116CDC1&dn=mediawiki-1.15.1.tar.gz"
+            ),
+            Err(MagnetError::MissingExactTopic)
+        );
+        assert_eq!(
+            MagnetLink::parse("magnet:?xt=urn:btmh:1114d8dd32ac93357c368556af3ac1d95c9d76bd0d"),
+            Err(MagnetError::MissingExactTopic)
+        );
+        // Hex digits are never signed
+        assert_eq!(
+            MagnetLink::parse(
+                "magnet:?xt=urn:btmh:1220%2Bff1e1c30e81cb361b9ee167c4aa64228a7fa4fa9f6105232b28ad099f3a302e"
+            ),
+            Err(MagnetError::MissingExactTopic)
+        );
+    }
+
+    #[test]
+    fn negative_parse_not_magnet_link() {
+        assert_eq!(
+            MagnetLink::parse(
+                "http://example.com/?xt=urn:btih:d9be6909325d28912f400fcb324005dd5861e49f"
+            ),
+            Err(MagnetError::NotMagnetLink("http".to_owned()))
+        );
+    }
+
+    #[test]
+    fn positive_from_metainfo() {
+        let trackers = vec![
+            vec![
+                "udp://tracker.example.com:6969".to_owned(),
+                "http://main.example.com/announce".to_owned(),
+            ],
+            vec!["https://backup.example.com/announce?key=a&b".to_owned()],
+        ];
+        let web_seeds = vec!["http://seed.example.com/files/".to_owned()];
+        let metainfo_bytes = MetainfoBuilder::new()
+            .set_main_tracker(Some("http://main.example.com/announce"))
+            .set_trackers(Some(&trackers))
+            .set_web_seeds(Some(&web_seeds))
+            .set_piece_length(PieceLength::Custom(1024))
+            .build(1, DirectAccessor::new("My File.txt", &[1u8; 2500]), |_| ())
+            .unwrap();
+        let metainfo = Metainfo::from_bytes(metainfo_bytes).unwrap();
+
+        let link = MagnetLink::parse(&MagnetLink::from_metainfo(&metainfo).to_string()).unwrap();
+
+        assert_eq!(link.get_info_hash(), Some(metainfo.info().info_hash()));
+        assert_eq!(link.get_v2_info_hash(), None);
+        assert_eq!(link.get_display_name(), Some("My File.txt"));
+        assert_eq!(link.get_exact_length(), Some(2500));
+        assert_eq!(
+            link.get_trackers(),
+            &[
+                "http://main.example.com/announce",
+                "udp://tracker.example.com:6969",
+                "https://backup.example.com/announce?key=a&b"
+            ]
+        );
+        assert_eq!(link.get_web_seeds(), &web_seeds[..]);
+    }
+
+    #[test]
+    fn positive_from_hybrid_metainfo() {
+        let metainfo_bytes = MetainfoBuilder::new()
+            .set_hybrid(true)
+            .build(1, DirectAccessor::new("file.bin", &[2u8; 40000]), |_| ())
+            .unwrap();
+        let metainfo = Metainfo::from_bytes(metainfo_bytes).unwrap();
+
+        let link = MagnetLink::parse(&MagnetLink::from_metainfo(&metainfo).to_string()).unwrap();
+
+        assert_eq!(link.get_info_hash(), Some(metainfo.info().info_hash()));
+        assert_eq!(link.get_v2_info_hash(), metainfo.info().v2_info_hash());
+        assert_eq!(link.get_exact_length(), Some(40000));
+        assert!(link.get_trackers().is_empty());
+    }
 }
@@ -1,3 +1,4 @@
+use std::ffi::OsStr;
 use std::fs::File;
 use std::io::{self, Cursor, Read};
 use std::path::{Path, PathBuf};
@@ -73,9 +74,14 @@ pub enum PieceAccess<'a> {
 // ----------------------------------------------------------------------------//
 
 /// Accessor that pulls data in from the file system.
+///
+/// Files are walked in order of their paths, so the same directory always
+/// produces the same torrent file.
 pub struct FileAccessor {
     absolute_path: PathBuf,
     directory_name: Option<PathBuf>,
+    ignore_hidden: bool,
+    ignore_patterns: Vec<String>,
 }
 
 impl FileAccessor {
@@ -96,8 +102,49 @@ impl FileAccessor {
         Ok(FileAccessor {
             absolute_path: absolute_path,
             directory_name: directory_name,
+            ignore_hidden: false,
+            ignore_patterns: Vec::new(),
         })
     }
+
+    /// Ignore files and directories whose name starts with a `.`.
+    pub fn with_ignore_hidden(mut self, ignore_hidden: bool) -> FileAccessor {
+        self.ignore_hidden = ignore_hidden;
+
+        self
+    }
+
+    /// Ignore files and directories whose name matches the given pattern.
+    ///
+    /// A pattern is matched against single names, where `*` matches any
+    /// number of characters and `?` matches exactly one character.
+    pub fn with_ignore_pattern(mut self, pattern: &str) -> FileAccessor {
+        self.ignore_patterns.push(pattern.to_owned());
+
+        self
+    }
+
+    /// Walk the files under the path, in order, skipping ignored entries.
+    ///
+    /// The path given to the accessor itself is never ignored.
+    fn walk_files<'a>(&'a self) -> impl Iterator<Item = walkdir::Result<DirEntry>> + 'a {
+        WalkDir::new(&self.absolute_path)
+            .sort_by(|a, b| a.file_name().cmp(b.file_name()))
+            .into_iter()
+            .filter_entry(move |entry| entry.depth() == 0 || !self.is_ignored(entry.file_name()))
+            .filter(entry_file_filter)
+    }
+
+    /// Returns true if the file or directory name should be ignored.
+    fn is_ignored(&self, name: &OsStr) -> bool {
+        let name = name.to_string_lossy();
+
+        (self.ignore_hidden && name.starts_with('.'))
+            || self
+                .ignore_patterns
+                .iter()
+                .any(|pattern| pattern_matches(pattern.as_bytes(), name.as_bytes()))
+    }
 }
 
 impl IntoAccessor for FileAccessor {
@@ -134,10 +181,7 @@ impl Accessor for FileAccessor {
             self.absolute_path.iter().count() - 1
         };
 
-        for res_entry in WalkDir::new(&self.absolute_path)
-            .into_iter()
-            .filter(entry_file_filter)
-        {
+        for res_entry in self.walk_files() {
             let entry = res_entry?;
             let entry_metadata = entry.metadata()?;
 
@@ -163,10 +207,7 @@ impl Accessor for FileAccessor {
     where
         C: for<'a> FnMut(PieceAccess<'a>) -> io::Result<()>,
     {
-        for res_entry in WalkDir::new(&self.absolute_path)
-            .into_iter()
-            .filter(entry_file_filter)
-        {
+        for res_entry in self.walk_files() {
             let entry = res_entry?;
             let mut file = File::open(entry.path())?;
 
@@ -185,6 +226,20 @@ fn entry_file_filter(res_entry: &walkdir::Result<DirEntry>) -> bool {
         .unwrap_or(true)
 }
 
+/// Returns true if the name matches the pattern, see `FileAccessor::with_ignore_pattern`.
+fn pattern_matches(pattern: &[u8], name: &[u8]) -> bool {
+    match (pattern.first(), name.first()) {
+        (None, None) => true,
+        (Some(b'*'), _) => {
+            pattern_matches(&pattern[1..], name)
+                || (!name.is_empty() && pattern_matches(pattern, &name[1..]))
+        }
+        (Some(b'?'), Some(_)) => pattern_matches(&pattern[1..], &name[1..]),
+        (Some(p), Some(n)) if p == n => pattern_matches(&pattern[1..], &name[1..]),
+        _ => false,
+    }
+}
+
 // ----------------------------------------------------------------------------//
 
 /// Accessor that pulls data in directly from memory.
@@ -237,3 +292,24 @@ impl<'a> Accessor for DirectAccessor<'a> {
         callback(PieceAccess::Compute(&mut cursor))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::pattern_matches;
+
+    #[test]
+    fn positive_pattern_matches_wildcards() {
+        assert!(pattern_matches(b"*.tmp", b"file.tmp"));
+        assert!(pattern_matches(b"*.tmp", b".tmp"));
+        assert!(pattern_matches(b"file?.txt", b"file1.txt"));
+        assert!(pattern_matches(b"Thumbs.db", b"Thumbs.db"));
+        assert!(pattern_matches(b"*", b""));
+    }
+
+    #[test]
+    fn negative_pattern_matches_wildcards() {
+        assert!(!pattern_matches(b"*.tmp", b"file.tmp.txt"));
+        assert!(!pattern_matches(b"file?.txt", b"file.txt"));
+        assert!(!pattern_matches(b"Thumbs.db", b"thumbs.db"));
+    }
+}
@@ -1,13 +1,18 @@
+use std::cmp;
 use std::iter::ExactSizeIterator;
 
 use crate::bencode::{BMutAccess, BRefAccess, BencodeMut};
 use crate::util::sha::{self, ShaHash};
 
 use super::accessor::{Accessor, IntoAccessor};
-use super::error::ParseResult;
+use super::error::{ParseError, ParseErrorKind, ParseResult};
 use super::parse;
+use super::v2::{self, MERKLE_BLOCK_LEN, SHA256_HASH_LEN};
+
+use self::padding::PaddedAccessor;
 
 mod buffer;
+mod padding;
 mod worker;
 
 // Piece length is inversly related to the file size.
@@ -53,6 +58,8 @@ pub enum PieceLength {
 pub struct MetainfoBuilder<'a> {
     root: BencodeMut<'a>,
     info: InfoBuilder<'a>,
+    padding: bool,
+    hybrid: bool,
 }
 
 impl<'a> MetainfoBuilder<'a> {
@@ -61,6 +68,8 @@ impl<'a> MetainfoBuilder<'a> {
         MetainfoBuilder {
             root: BencodeMut::new_dict(),
             info: InfoBuilder::new(),
+            padding: false,
+            hybrid: false,
         }
     }
 
@@ -73,27 +82,7 @@ impl<'a> MetainfoBuilder<'a> {
             let dict_access = self.root.dict_mut().unwrap();
 
             if let Some(groups) = opt_trackers {
-                let mut list = BencodeMut::new_list();
-
-                {
-                    let list_access = list.list_mut().unwrap();
-
-                    for group in groups.iter() {
-                        let mut tracker_list = BencodeMut::new_list();
-
-                        {
-                            let tracker_list_access = tracker_list.list_mut().unwrap();
-
-                            for tracker_url in group.iter() {
-                                tracker_list_access.push(bt_ben_bytes!(&tracker_url[..]));
-                            }
-                        }
-
-                        list_access.push(tracker_list);
-                    }
-                }
-
-                dict_access.insert(parse::ANNOUNCE_LIST_KEY.into(), list);
+                dict_access.insert(parse::ANNOUNCE_LIST_KEY.into(), build_trackers_list(groups));
             } else {
                 dict_access.remove(parse::ANNOUNCE_LIST_KEY);
             }
@@ -162,6 +151,21 @@ impl<'a> MetainfoBuilder<'a> {
         self
     }
 
+    /// Set or unset the url-list of web seeds for the torrent file.
+    pub fn set_web_seeds(mut self, opt_web_seeds: Option<&'a Vec<String>>) -> MetainfoBuilder<'a> {
+        {
+            let dict_access = self.root.dict_mut().unwrap();
+
+            if let Some(web_seeds) = opt_web_seeds {
+                dict_access.insert(parse::URL_LIST_KEY.into(), build_web_seeds_list(web_seeds));
+            } else {
+                dict_access.remove(parse::URL_LIST_KEY);
+            }
+        }
+
+        self
+    }
+
     /// Set or unset the private flag for the torrent file.
     pub fn set_private_flag(mut self, opt_is_private: Option<bool>) -> MetainfoBuilder<'a> {
         self.info = self.info.set_private_flag(opt_is_private);
@@ -176,6 +180,27 @@ impl<'a> MetainfoBuilder<'a> {
         self
     }
 
+    /// Sets whether every file is followed by a padding file aligning the next file to a piece boundary.
+    ///
+    /// See http://www.bittorrent.org/beps/bep_0047.html.
+    pub fn set_padding(mut self, padding: bool) -> MetainfoBuilder<'a> {
+        self.padding = padding;
+
+        self
+    }
+
+    /// Sets whether the torrent file also contains the v2 file tree and piece layers.
+    ///
+    /// Hybrid torrent files are always padded, and their piece length is a power of two of
+    /// at least 16 KiB, a custom piece length that is not fails the build.
+    ///
+    /// See http://www.bittorrent.org/beps/bep_0052.html.
+    pub fn set_hybrid(mut self, hybrid: bool) -> MetainfoBuilder<'a> {
+        self.hybrid = hybrid;
+
+        self
+    }
+
     /// Get decoded value of announce-list key
     pub fn get_trackers(&self) -> Option<Vec<Vec<String>>> {
         let dict_access = self.root.dict().unwrap();
@@ -211,6 +236,13 @@ impl<'a> MetainfoBuilder<'a> {
         parse::parse_created_by(dict_access).map(String::from)
     }
 
+    /// Get decoded value of url-list key
+    pub fn get_web_seeds(&self) -> Option<Vec<String>> {
+        let dict_access = self.root.dict().unwrap();
+
+        parse::parse_url_list(dict_access)
+    }
+
     /// Build the metainfo file from the given accessor and the number of worker threads.
     ///
     /// Panics if threads is equal to zero.
@@ -228,6 +260,8 @@ impl<'a> MetainfoBuilder<'a> {
             Some(self.root),
             self.info.info,
             self.info.piece_length,
+            self.padding || self.hybrid,
+            self.hybrid,
         )
     }
 }
@@ -257,11 +291,12 @@ impl<'a> InfoBuilder<'a> {
 
         {
             let dict_access = self.info.dict_mut().unwrap();
-            opt_numeric_is_private
-                .and_then(|numeric_is_private| {
-                    dict_access.insert(parse::PRIVATE_KEY.into(), bt_ben_int!(numeric_is_private))
-                })
-                .or_else(|| dict_access.remove(parse::PRIVATE_KEY));
+
+            if let Some(numeric_is_private) = opt_numeric_is_private {
+                dict_access.insert(parse::PRIVATE_KEY.into(), bt_ben_int!(numeric_is_private));
+            } else {
+                dict_access.remove(parse::PRIVATE_KEY);
+            }
         }
 
         self
@@ -291,6 +326,8 @@ impl<'a> InfoBuilder<'a> {
             None,
             self.info,
             self.piece_length,
+            false,
+            false,
         )
     }
 }
@@ -304,6 +341,8 @@ fn build_with_accessor<'a, A, C>(
     opt_root: Option<BencodeMut<'a>>,
     info: BencodeMut<'a>,
     piece_length: PieceLength,
+    padding: bool,
+    hybrid: bool,
 ) -> ParseResult<Vec<u8>>
 where
     A: Accessor,
@@ -324,21 +363,88 @@ where
         files_info.push((len, path_list));
     })?;
 
+    // Files have to be in the same order in the files list as in the file tree
+    if hybrid && !files_info.windows(2).all(|files| files[0].1 < files[1].1) {
+        return Err(ParseError::from_kind(ParseErrorKind::InvalidData {
+            details: "Files Of A Hybrid Torrent Are Not In Path Order".to_owned(),
+        }));
+    }
+
     // Build the pieces for the data our accessor is pointing at
     let total_files_len = files_info.iter().fold(0, |acc, nex| acc + nex.0);
-    let piece_length = determine_piece_length(total_files_len, piece_length);
-    let total_num_pieces = ((total_files_len as f64) / (piece_length as f64)).ceil() as u64;
-    let pieces_list =
-        worker::start_hasher_workers(&accessor, piece_length, total_num_pieces, threads, progress)?;
+    let piece_length = determine_piece_length(total_files_len, piece_length, hybrid)?;
+
+    // Every file but the last is padded up to the next piece boundary
+    let paddings: Vec<(u64, String)> = files_info
+        .iter()
+        .enumerate()
+        .map(|(index, &(len, _))| {
+            let padding_len = if padding && index + 1 != files_info.len() {
+                (piece_length as u64 - len % piece_length as u64) % piece_length as u64
+            } else {
+                0
+            };
+
+            (padding_len, padding_len.to_string())
+        })
+        .collect();
+    let total_padded_len = total_files_len + paddings.iter().map(|&(len, _)| len).sum::<u64>();
+    let total_num_pieces = ((total_padded_len as f64) / (piece_length as f64)).ceil() as u64;
+
+    let (pieces_list, opt_block_hashes) = if padding {
+        let padded_files = files_info
+            .iter()
+            .zip(paddings.iter())
+            .map(|(&(len, _), &(padding_len, _))| (len, padding_len))
+            .collect();
+        let padded_accessor = PaddedAccessor::new(&accessor, padded_files, hybrid);
+
+        let pieces_list = worker::start_hasher_workers(
+            &padded_accessor,
+            piece_length,
+            total_num_pieces,
+            threads,
+            progress,
+        )?;
+
+        (pieces_list, padded_accessor.into_block_hashes())
+    } else {
+        let pieces_list = worker::start_hasher_workers(
+            &accessor,
+            piece_length,
+            total_num_pieces,
+            threads,
+            progress,
+        )?;
+
+        (pieces_list, None)
+    };
     let pieces = map_pieces_list(pieces_list.into_iter().map(|(_, piece)| piece));
 
+    // Merkle tree hashes of each file, for the v2 file tree and piece layers
+    let merkle_hashes: Vec<Option<([u8; SHA256_HASH_LEN], Option<Vec<u8>>)>> = opt_block_hashes
+        .unwrap_or_else(Vec::new)
+        .iter()
+        .zip(files_info.iter())
+        .map(|(block_hashes, &(len, _))| {
+            if len == 0 {
+                None
+            } else {
+                let (pieces_root, opt_layer) =
+                    v2::file_merkle_hashes(block_hashes, piece_length as u64);
+
+                Some((pieces_root, opt_layer.map(|layer| layer.concat())))
+            }
+        })
+        .collect();
+
     let mut single_file_name = String::new();
     let access_directory = accessor
         .access_directory()
         .map(|path| path.to_string_lossy());
 
     // Move these below access directory for borrow checker
-    let opt_root = opt_root;
+    let mut opt_root = opt_root;
     let mut info = info;
 
     // Update the info bencode with values
@@ -355,60 +461,20 @@ where
         // If the directory is not present but there are multiple files, the direcotry field will be set to empty
         match (&access_directory, files_info.len() > 1) {
             (&Some(ref directory), _) => {
-                let mut bencode_files = BencodeMut::new_list();
-
-                {
-                    let bencode_files_access = bencode_files.list_mut().unwrap();
-
-                    // Multi File
-                    for &(len, ref path) in files_info.iter() {
-                        let mut bencode_path = BencodeMut::new_list();
-
-                        {
-                            let bencode_path_access = bencode_path.list_mut().unwrap();
-
-                            for path_element in path.iter() {
-                                bencode_path_access.push(bt_ben_bytes!(&path_element[..]));
-                            }
-                        }
-
-                        bencode_files_access.push(bt_ben_map! {
-                            parse::LENGTH_KEY => bt_ben_int!(len as i64),
-                            parse::PATH_KEY   => bencode_path
-                        });
-                    }
-                }
-
+                // Multi File
                 info_access.insert(parse::NAME_KEY.into(), bt_ben_bytes!(directory.as_ref()));
-                info_access.insert(parse::FILES_KEY.into(), bencode_files);
+                info_access.insert(
+                    parse::FILES_KEY.into(),
+                    build_files_list(&files_info, &paddings),
+                );
             }
             (&None, true) => {
-                let mut bencode_files = BencodeMut::new_list();
-
-                {
-                    let bencode_files_access = bencode_files.list_mut().unwrap();
-
-                    // Multi File
-                    for &(len, ref path) in files_info.iter() {
-                        let mut bencode_path = BencodeMut::new_list();
-
-                        {
-                            let bencode_path_access = bencode_path.list_mut().unwrap();
-
-                            for path_element in path.iter() {
-                                bencode_path_access.push(bt_ben_bytes!(&path_element[..]));
-                            }
-                        }
-
-                        bencode_files_access.push(bt_ben_map! {
-                            parse::LENGTH_KEY => bt_ben_int!(len as i64),
-                            parse::PATH_KEY   => bencode_path
-                        });
-                    }
-                }
-
+                // Multi File
                 info_access.insert(parse::NAME_KEY.into(), bt_ben_bytes!(""));
-                info_access.insert(parse::FILES_KEY.into(), bencode_files);
+                info_access.insert(
+                    parse::FILES_KEY.into(),
+                    build_files_list(&files_info, &paddings),
+                );
             }
             (&None, false) => {
                 // Single File
@@ -423,6 +489,37 @@ where
                 info_access.insert(parse::NAME_KEY.into(), bt_ben_bytes!(&single_file_name[..]));
             }
         }
+
+        if hybrid {
+            info_access.insert(parse::META_VERSION_KEY.into(), bt_ben_int!(2));
+            info_access.insert(
+                parse::FILE_TREE_KEY.into(),
+                build_file_tree(&files_info, &merkle_hashes),
+            );
+        }
+    }
+
+    // Piece layers are kept outside of the info dictionary
+    match opt_root {
+        Some(ref mut root) if hybrid => {
+            let mut piece_layers = BencodeMut::new_dict();
+
+            {
+                let piece_layers_access = piece_layers.dict_mut().unwrap();
+
+                for &(ref pieces_root, ref opt_layer) in merkle_hashes.iter().flatten() {
+                    if let Some(ref layer) = *opt_layer {
+                        piece_layers_access
+                            .insert((&pieces_root[..]).into(), bt_ben_bytes!(&layer[..]));
+                    }
+                }
+            }
+
+            root.dict_mut()
+                .unwrap()
+                .insert(parse::PIECE_LAYERS_KEY.into(), piece_layers);
+        }
+        _ => (),
     }
 
     if let Some(mut root) = opt_root {
@@ -436,12 +533,152 @@ where
     }
 }
 
+/// Build the announce-list of a torrent file from the given tracker groups.
+pub(crate) fn build_trackers_list<'a>(groups: &'a [Vec<String>]) -> BencodeMut<'a> {
+    let mut list = BencodeMut::new_list();
+
+    {
+        let list_access = list.list_mut().unwrap();
+
+        for group in groups.iter() {
+            let mut tracker_list = BencodeMut::new_list();
+
+            {
+                let tracker_list_access = tracker_list.list_mut().unwrap();
+
+                for tracker_url in group.iter() {
+                    tracker_list_access.push(bt_ben_bytes!(&tracker_url[..]));
+                }
+            }
+
+            list_access.push(tracker_list);
+        }
+    }
+
+    list
+}
+
+/// Build the url-list of a torrent file from the given web seeds.
+pub(crate) fn build_web_seeds_list<'a>(web_seeds: &'a [String]) -> BencodeMut<'a> {
+    let mut list = BencodeMut::new_list();
+
+    {
+        let list_access = list.list_mut().unwrap();
+
+        for web_seed in web_seeds.iter() {
+            list_access.push(bt_ben_bytes!(&web_seed[..]));
+        }
+    }
+
+    list
+}
+
+/// Build the files list of a multi file torrent, following each file by its padding file, if any.
+fn build_files_list<'a>(
+    files_info: &'a [(u64, Vec<String>)],
+    paddings: &'a [(u64, String)],
+) -> BencodeMut<'a> {
+    let mut bencode_files = BencodeMut::new_list();
+
+    {
+        let bencode_files_access = bencode_files.list_mut().unwrap();
+
+        for (&(len, ref path), &(padding_len, ref padding_name)) in
+            files_info.iter().zip(paddings.iter())
+        {
+            let mut bencode_path = BencodeMut::new_list();
+
+            {
+                let bencode_path_access = bencode_path.list_mut().unwrap();
+
+                for path_element in path.iter() {
+                    bencode_path_access.push(bt_ben_bytes!(&path_element[..]));
+                }
+            }
+
+            bencode_files_access.push(bt_ben_map! {
+                parse::LENGTH_KEY => bt_ben_int!(len as i64),
+                parse::PATH_KEY   => bencode_path
+            });
+
+            if padding_len != 0 {
+                let padding_path =
+                    bt_ben_list!(bt_ben_bytes!(".pad"), bt_ben_bytes!(&padding_name[..]));
+
+                bencode_files_access.push(bt_ben_map! {
+                    parse::ATTR_KEY   => bt_ben_bytes!("p"),
+                    parse::LENGTH_KEY => bt_ben_int!(padding_len as i64),
+                    parse::PATH_KEY   => padding_path
+                });
+            }
+        }
+    }
+
+    bencode_files
+}
+
+/// Build the v2 file tree from the path and merkle tree hashes of each file.
+fn build_file_tree<'a>(
+    files_info: &'a [(u64, Vec<String>)],
+    merkle_hashes: &'a [Option<([u8; SHA256_HASH_LEN], Option<Vec<u8>>)>],
+) -> BencodeMut<'a> {
+    let mut file_tree = BencodeMut::new_dict();
+
+    for (&(len, ref path), opt_hashes) in files_info.iter().zip(merkle_hashes.iter()) {
+        let mut file_dict = bt_ben_map! {
+            parse::LENGTH_KEY => bt_ben_int!(len as i64)
+        };
+        if let Some((ref pieces_root, _)) = *opt_hashes {
+            file_dict.dict_mut().unwrap().insert(
+                parse::PIECES_ROOT_KEY.into(),
+                bt_ben_bytes!(&pieces_root[..]),
+            );
+        }
+
+        // Walk down to the directory of the file, creating it as we go
+        let (file_name, dir_path) = path.split_last().unwrap();
+        let mut dir = &mut file_tree;
+        for path_element in dir_path.iter() {
+            let dir_access = dir.dict_mut().unwrap();
+
+            if dir_access.lookup(path_element.as_bytes()).is_none() {
+                dir_access.insert(path_element.as_bytes().into(), BencodeMut::new_dict());
+            }
+            dir = dir_access.lookup_mut(path_element.as_bytes()).unwrap();
+        }
+
+        dir.dict_mut().unwrap().insert(
+            file_name.as_bytes().into(),
+            bt_ben_map! {
+                parse::FILE_TREE_ENTRY_KEY => file_dict
+            },
+        );
+    }
+
+    file_tree
+}
+
 /// Calculate the final piece length given the total file size and piece length strategy.
 ///
 /// Lower piece length will result in a bigger file but better transfer reliability and vice versa.
-fn determine_piece_length(total_file_size: u64, piece_length: PieceLength) -> usize {
-    match piece_length {
-        PieceLength::Custom(len) => len,
+/// Piece lengths of hybrid torrent files are at least the length of a merkle tree block.
+fn determine_piece_length(
+    total_file_size: u64,
+    piece_length: PieceLength,
+    hybrid: bool,
+) -> ParseResult<usize> {
+    let piece_length = match piece_length {
+        PieceLength::Custom(len) => {
+            let is_merkle_aligned = len.is_power_of_two() && len as u64 >= MERKLE_BLOCK_LEN;
+
+            if hybrid && !is_merkle_aligned {
+                return Err(ParseError::from_kind(ParseErrorKind::InvalidData {
+                    details: format!("Piece Length Of {} Is Invalid For A Hybrid Torrent", len),
+                }));
+            }
+
+            len
+        }
         PieceLength::OptBalanced => calculate_piece_length(
             total_file_size,
             BALANCED_MAX_PIECES_SIZE,
@@ -457,6 +694,12 @@ fn determine_piece_length(total_file_size: u64, piece_length: PieceLength) -> us
             TRANSFER_MAX_PIECES_SIZE,
             TRANSFER_MIN_PIECE_LENGTH,
         ),
+    };
+
+    if hybrid {
+        Ok(cmp::max(piece_length, MERKLE_BLOCK_LEN as usize))
+    } else {
+        Ok(piece_length)
     }
 }
 
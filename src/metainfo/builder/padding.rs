@@ -0,0 +1,186 @@
+use std::cell::RefCell;
+use std::cmp;
+use std::io::{self, Read};
+use std::path::Path;
+
+use crypto::digest::Digest;
+use crypto::sha2::Sha256;
+
+use crate::metainfo::v2::{MERKLE_BLOCK_LEN, SHA256_HASH_LEN};
+use crate::metainfo::{Accessor, PieceAccess};
+
+/// Accessor that follows every file of some accessor with the zeroes of its padding file.
+///
+/// Optionally hashes each block of every file for building its merkle tree.
+pub struct PaddedAccessor<A> {
+    accessor: A,
+    files: Vec<(u64, u64)>,
+    opt_block_hashes: Option<RefCell<Vec<Vec<[u8; SHA256_HASH_LEN]>>>>,
+}
+
+impl<A> PaddedAccessor<A>
+where
+    A: Accessor,
+{
+    /// Create a new PaddedAccessor, from the length and padding length of each file.
+    pub fn new(accessor: A, files: Vec<(u64, u64)>, hash_blocks: bool) -> PaddedAccessor<A> {
+        let opt_block_hashes = if hash_blocks {
+            Some(RefCell::new(vec![Vec::new(); files.len()]))
+        } else {
+            None
+        };
+
+        PaddedAccessor {
+            accessor: accessor,
+            files: files,
+            opt_block_hashes: opt_block_hashes,
+        }
+    }
+
+    /// Hash of each block of every file, if blocks were hashed.
+    pub fn into_block_hashes(self) -> Option<Vec<Vec<[u8; SHA256_HASH_LEN]>>> {
+        self.opt_block_hashes
+            .map(|block_hashes| block_hashes.into_inner())
+    }
+}
+
+impl<A> Accessor for PaddedAccessor<A>
+where
+    A: Accessor,
+{
+    fn access_directory(&self) -> Option<&Path> {
+        self.accessor.access_directory()
+    }
+
+    fn access_metadata<C>(&self, callback: C) -> io::Result<()>
+    where
+        C: FnMut(u64, &Path),
+    {
+        self.accessor.access_metadata(callback)
+    }
+
+    fn access_pieces<C>(&self, mut callback: C) -> io::Result<()>
+    where
+        C: for<'a> FnMut(PieceAccess<'a>) -> io::Result<()>,
+    {
+        let mut opt_block_hashes = self
+            .opt_block_hashes
+            .as_ref()
+            .map(|block_hashes| block_hashes.borrow_mut());
+        let mut state = PaddingState {
+            files: &self.files,
+            opt_block_hashes: opt_block_hashes
+                .as_mut()
+                .map(|block_hashes| &mut block_hashes[..]),
+            file_index: 0,
+            file_read: 0,
+            padding_read: 0,
+            block: Sha256::new(),
+            block_read: 0,
+        };
+
+        // Padding is only known where the data of each file given to us ends
+        self.accessor
+            .access_pieces(|piece_access| match piece_access {
+                PieceAccess::Compute(piece_region) => {
+                    callback(PieceAccess::Compute(&mut PaddingReader {
+                        state: &mut state,
+                        region: piece_region,
+                    }))
+                }
+                PieceAccess::PreComputed(_) => Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "Precomputed Pieces Can Not Be Padded",
+                )),
+            })
+    }
+}
+
+// ----------------------------------------------------------------------------//
+
+/// Position within the files, kept between the regions given to us.
+struct PaddingState<'a> {
+    files: &'a [(u64, u64)],
+    opt_block_hashes: Option<&'a mut [Vec<[u8; SHA256_HASH_LEN]>]>,
+    file_index: usize,
+    file_read: u64,
+    padding_read: u64,
+    block: Sha256,
+    block_read: u64,
+}
+
+impl<'a> PaddingState<'a> {
+    /// Hash the bytes read from the current file into its blocks.
+    fn hash_file_bytes(&mut self, mut bytes: &[u8], file_len: u64) {
+        let block_hashes = match self.opt_block_hashes {
+            Some(ref mut block_hashes) => &mut block_hashes[self.file_index],
+            None => return,
+        };
+
+        while !bytes.is_empty() {
+            let block_bytes = cmp::min(bytes.len() as u64, MERKLE_BLOCK_LEN - self.block_read);
+            self.block.input(&bytes[..block_bytes as usize]);
+            self.block_read += block_bytes;
+            bytes = &bytes[block_bytes as usize..];
+
+            if self.block_read == MERKLE_BLOCK_LEN {
+                block_hashes.push(finish_block(&mut self.block));
+                self.block_read = 0;
+            }
+        }
+
+        // The last block of a file is hashed without any padding
+        if self.file_read == file_len && self.block_read != 0 {
+            block_hashes.push(finish_block(&mut self.block));
+            self.block_read = 0;
+        }
+    }
+}
+
+fn finish_block(block: &mut Sha256) -> [u8; SHA256_HASH_LEN] {
+    let mut hash = [0u8; SHA256_HASH_LEN];
+    block.result(&mut hash);
+    block.reset();
+
+    hash
+}
+
+/// Reader over a region of the files, followed by the padding of each file ending in it.
+struct PaddingReader<'a, 'b> {
+    state: &'a mut PaddingState<'b>,
+    region: &'a mut dyn Read,
+}
+
+impl<'a, 'b> Read for PaddingReader<'a, 'b> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let (file_len, padding_len) = match self.state.files.get(self.state.file_index) {
+                Some(&file) => file,
+                None => return self.region.read(buf),
+            };
+
+            if self.state.file_read < file_len {
+                let max_read = cmp::min(buf.len() as u64, file_len - self.state.file_read);
+                let bytes_read = self.region.read(&mut buf[..max_read as usize])?;
+
+                self.state.file_read += bytes_read as u64;
+                self.state.hash_file_bytes(&buf[..bytes_read], file_len);
+
+                return Ok(bytes_read);
+            } else if self.state.padding_read < padding_len {
+                let max_read = cmp::min(buf.len() as u64, padding_len - self.state.padding_read);
+                for byte in buf[..max_read as usize].iter_mut() {
+                    *byte = 0;
+                }
+
+                self.state.padding_read += max_read;
+
+                return Ok(max_read as usize);
+            } else {
+                self.state.file_index += 1;
+                self.state.file_read = 0;
+                self.state.padding_read = 0;
+            }
+        }
+    }
+}
@@ -1,5 +1,6 @@
 //! Errors for torrent file building and parsing.
 
+use std::fmt;
 use std::io;
 
 use crate::bencode::{BencodeConvertError, BencodeParseError};
@@ -24,5 +25,44 @@ error_chain! {
             description("Missing Data Detected In File")
             display("Missing Data Detected In File: {}", details)
         }
+        InvalidData {
+            details: String
+        } {
+            description("Invalid Data Detected In File")
+            display("Invalid Data Detected In File: {}", details)
+        }
+    }
+}
+
+/// Malformed entry skipped while parsing a metainfo file.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct ParseWarning {
+    key: String,
+    details: String,
+}
+
+impl ParseWarning {
+    /// Create a new ParseWarning for an entry under the given key.
+    pub fn new(key: &[u8], details: String) -> ParseWarning {
+        ParseWarning {
+            key: String::from_utf8_lossy(key).into_owned(),
+            details: details,
+        }
+    }
+
+    /// Key of the dictionary the entry was found under.
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    /// Description of what was wrong with the entry.
+    pub fn details(&self) -> &str {
+        &self.details
+    }
+}
+
+impl fmt::Display for ParseWarning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Skipped Entry Of {}: {}", self.key, self.details)
     }
 }
@@ -1,15 +1,21 @@
 //! Accessing the fields of a Metainfo file.
+use std::collections::BTreeMap;
 use std::io;
 use std::path::{Path, PathBuf};
 
+use rand::{self, Rng};
+use url::Url;
+
 use crate::bencode::{BDecodeOpt, BDictAccess, BRefAccess, BencodeRef};
 use crate::util::bt::InfoHash;
 use crate::util::sha::{self, ShaHash};
 
 use super::accessor::{Accessor, IntoAccessor, PieceAccess};
-use super::builder::{InfoBuilder, MetainfoBuilder, PieceLength};
-use super::error::{ParseError, ParseErrorKind, ParseResult};
+use super::builder;
+use super::error::{ParseError, ParseErrorKind, ParseResult, ParseWarning};
 use super::parse;
+use super::sources::{self, TrackerUrl};
+use super::v2;
 
 /// Contains optional metadata for a torrent file.
 #[derive(Debug, Clone, Eq, PartialEq)]
@@ -20,6 +26,14 @@ pub struct Metainfo {
     encoding: Option<String>,
     created_by: Option<String>,
     creation_date: Option<i64>,
+    url_list: Option<Vec<String>>,
+    tracker_tiers: Vec<Vec<TrackerUrl>>,
+    web_seed_urls: Vec<Url>,
+    http_seeds: Vec<Url>,
+    nodes: Vec<(String, u16)>,
+    warnings: Vec<ParseWarning>,
+    // Bencoded values of the keys we do not write ourselves, by key.
+    unknown_keys: BTreeMap<Vec<u8>, Vec<u8>>,
     info: Info,
 }
 
@@ -31,7 +45,20 @@ impl Metainfo {
     {
         let bytes_slice = bytes.as_ref();
 
-        parse_meta_bytes(bytes_slice)
+        parse_meta_bytes(bytes_slice, BDecodeOpt::default())
+    }
+
+    /// Read a `Metainfo` from metainfo file bytes, only accepting canonical bencode.
+    ///
+    /// Unsorted dictionary keys would change the info hash once the info dictionary is
+    /// re-encoded, so they are rejected, along with trailing bytes.
+    pub fn from_bytes_strict<B>(bytes: B) -> ParseResult<Metainfo>
+    where
+        B: AsRef<[u8]>,
+    {
+        let bytes_slice = bytes.as_ref();
+
+        parse_meta_bytes(bytes_slice, BDecodeOpt::default().with_strict(true))
     }
 
     /// Announce url for the main tracker of the metainfo file.
@@ -64,24 +91,143 @@ impl Metainfo {
         self.creation_date
     }
 
+    /// Urls of web seeds serving the files of the metainfo file.
+    pub fn web_seeds(&self) -> Option<&Vec<String>> {
+        self.url_list.as_ref()
+    }
+
+    /// Tiers of trackers to announce to, in the order they should be tried (BEP 12).
+    ///
+    /// Taken from the announce-list, or from the main tracker if the announce-list does not
+    /// list any valid tracker. Trackers are in the order found in the metainfo file, see
+    /// `Metainfo::shuffled_tracker_tiers` for the order a client should start with.
+    pub fn tracker_tiers(&self) -> &[Vec<TrackerUrl>] {
+        &self.tracker_tiers
+    }
+
+    /// Tiers of trackers to announce to, with the trackers of each tier shuffled.
+    pub fn shuffled_tracker_tiers(&self) -> Vec<Vec<TrackerUrl>> {
+        let mut rng = rand::thread_rng();
+        let mut tiers = self.tracker_tiers.clone();
+
+        for tier in tiers.iter_mut() {
+            rng.shuffle(tier);
+        }
+
+        tiers
+    }
+
+    /// Http urls of web seeds from the url-list, serving the files of the metainfo file (BEP 19).
+    pub fn web_seed_urls(&self) -> &[Url] {
+        &self.web_seed_urls
+    }
+
+    /// Http urls of seeds from the httpseeds, serving pieces of the metainfo file (BEP 17).
+    pub fn http_seeds(&self) -> &[Url] {
+        &self.http_seeds
+    }
+
+    /// Host and port of each DHT node to bootstrap with (BEP 5).
+    pub fn nodes(&self) -> &[(String, u16)] {
+        &self.nodes
+    }
+
+    /// Whether or not the torrent is private, a missing private flag is not private.
+    pub fn is_private(&self) -> bool {
+        self.info.is_private().unwrap_or(false)
+    }
+
+    /// Malformed entries of the trackers, seeds and nodes skipped while parsing.
+    pub fn parse_warnings(&self) -> &[ParseWarning] {
+        &self.warnings
+    }
+
     /// Info dictionary for the metainfo file.
     pub fn info(&self) -> &Info {
         &self.info
     }
 
+    /// Bencoded bytes of the info dictionary, exactly as they were read.
+    pub fn info_bytes(&self) -> &[u8] {
+        &self.info.info_bytes
+    }
+
+    /// Set or unset the announce url for the main tracker.
+    pub fn set_main_tracker(mut self, opt_tracker_url: Option<&str>) -> Metainfo {
+        self.announce = opt_tracker_url.map(|tracker_url| tracker_url.to_owned());
+        self.update_tracker_tiers();
+
+        self
+    }
+
+    /// Set or unset the list of announce urls.
+    pub fn set_trackers(mut self, opt_trackers: Option<&Vec<Vec<String>>>) -> Metainfo {
+        self.announce_list = opt_trackers.cloned();
+        self.update_tracker_tiers();
+
+        self
+    }
+
+    /// Invalid trackers set are skipped, without adding to the parse warnings.
+    fn update_tracker_tiers(&mut self) {
+        self.tracker_tiers =
+            sources::tracker_tiers(self.main_tracker(), self.trackers(), &mut Vec::new());
+    }
+
     /// Retrieve the bencoded bytes for the `Metainfo` file.
+    ///
+    /// The info dictionary is written exactly as it was read, so the info hash never
+    /// changes, and keys we do not know are kept. The keys of the outer dictionary
+    /// are written in sorted order.
     pub fn to_bytes(&self) -> Vec<u8> {
-        // Since there are no file system accesses here, should be fine to unwrap
-        MetainfoBuilder::new()
-            .set_main_tracker(self.main_tracker())
-            .set_creation_date(self.creation_date())
-            .set_comment(self.comment())
-            .set_created_by(self.created_by())
-            .set_private_flag(self.info().is_private())
-            // TODO: Revisit this cast...
-            .set_piece_length(PieceLength::Custom(self.info().piece_length() as usize))
-            .build(1, &self.info, |_| ())
-            .unwrap()
+        let mut values: BTreeMap<&[u8], Vec<u8>> = self
+            .unknown_keys
+            .iter()
+            .map(|(key, value)| (&key[..], value.clone()))
+            .collect();
+
+        if let Some(announce) = self.main_tracker() {
+            values.insert(parse::ANNOUNCE_URL_KEY, bt_ben_bytes!(announce).encode());
+        }
+        if let Some(announce_list) = self.trackers() {
+            values.insert(
+                parse::ANNOUNCE_LIST_KEY,
+                builder::build_trackers_list(announce_list).encode(),
+            );
+        }
+        if let Some(comment) = self.comment() {
+            values.insert(parse::COMMENT_KEY, bt_ben_bytes!(comment).encode());
+        }
+        if let Some(created_by) = self.created_by() {
+            values.insert(parse::CREATED_BY_KEY, bt_ben_bytes!(created_by).encode());
+        }
+        if let Some(creation_date) = self.creation_date() {
+            values.insert(
+                parse::CREATION_DATE_KEY,
+                bt_ben_int!(creation_date).encode(),
+            );
+        }
+        if let Some(encoding) = self.encoding() {
+            values.insert(parse::ENCODING_KEY, bt_ben_bytes!(encoding).encode());
+        }
+        if let Some(url_list) = self.web_seeds() {
+            values.insert(
+                parse::URL_LIST_KEY,
+                builder::build_web_seeds_list(url_list).encode(),
+            );
+        }
+        values.insert(parse::INFO_KEY, self.info.info_bytes.clone());
+
+        let mut bytes = vec![b'd'];
+        for (key, value) in values {
+            bytes.extend_from_slice(key.len().to_string().as_bytes());
+            bytes.push(b':');
+            bytes.extend_from_slice(key);
+            bytes.extend_from_slice(&value);
+        }
+        bytes.push(b'e');
+
+        bytes
     }
 }
 
@@ -94,31 +240,51 @@ impl From<Info> for Metainfo {
             encoding: None,
             created_by: None,
             creation_date: None,
+            url_list: None,
+            tracker_tiers: Vec::new(),
+            web_seed_urls: Vec::new(),
+            http_seeds: Vec::new(),
+            nodes: Vec::new(),
+            warnings: Vec::new(),
+            unknown_keys: BTreeMap::new(),
             info: info,
         }
     }
 }
 
 /// Parses the given metainfo bytes and builds a Metainfo from them.
-fn parse_meta_bytes(bytes: &[u8]) -> ParseResult<Metainfo> {
-    let root_bencode = BencodeRef::decode(bytes, BDecodeOpt::default())?;
+fn parse_meta_bytes(bytes: &[u8], opts: BDecodeOpt) -> ParseResult<Metainfo> {
+    let root_bencode = BencodeRef::decode(bytes, opts)?;
     let root_dict = parse::parse_root_dict(&root_bencode)?;
 
+    let mut warnings = Vec::new();
     let announce = parse::parse_announce_url(root_dict).map(|e| e.to_owned());
 
-    let opt_announce_list = {
-        parse::parse_announce_list(root_dict)
-            .and_then(|list| Some(parse::convert_announce_list(list)))
-            .or(None)
-    };
+    let opt_announce_list = sources::parse_announce_tiers(root_dict, &mut warnings);
+    let tracker_tiers = sources::tracker_tiers(
+        announce.as_ref().map(|a| &a[..]),
+        opt_announce_list.as_ref(),
+        &mut warnings,
+    );
 
     let opt_comment = parse::parse_comment(root_dict).map(|e| e.to_owned());
     let opt_encoding = parse::parse_encoding(root_dict).map(|e| e.to_owned());
     let opt_created_by = parse::parse_created_by(root_dict).map(|e| e.to_owned());
     let opt_creation_date = parse::parse_creation_date(root_dict);
+    let opt_url_list = parse::parse_url_list(root_dict);
+    let web_seed_urls = sources::parse_seed_urls(root_dict, parse::URL_LIST_KEY, &mut warnings);
+    let http_seeds = sources::parse_seed_urls(root_dict, parse::HTTP_SEEDS_KEY, &mut warnings);
+    let nodes = sources::parse_nodes(root_dict, &mut warnings);
+    let unknown_keys = root_dict
+        .to_list()
+        .into_iter()
+        .filter(|&(key, _)| !KNOWN_ROOT_KEYS.contains(key))
+        .map(|(key, value)| (key.to_vec(), value.buffer().to_vec()))
+        .collect();
 
     let info_bencode = parse::parse_info_bencode(root_dict)?;
-    let info = parse_info_dictionary(info_bencode)?;
+    let info = parse_info_dictionary(info_bencode)
+        .map_err(|err| parse::within(err, parse::INFO_KEY))?;
 
     Ok(Metainfo {
         comment: opt_comment,
@@ -127,22 +293,43 @@ fn parse_meta_bytes(bytes: &[u8]) -> ParseResult<Metainfo> {
         encoding: opt_encoding,
         created_by: opt_created_by,
         creation_date: opt_creation_date,
+        url_list: opt_url_list,
+        tracker_tiers: tracker_tiers,
+        web_seed_urls: web_seed_urls,
+        http_seeds: http_seeds,
+        nodes: nodes,
+        warnings: warnings,
+        unknown_keys: unknown_keys,
         info: info,
     })
 }
 
+/// Keys of the root dictionary stored in the fields of a `Metainfo`.
+const KNOWN_ROOT_KEYS: &[&[u8]] = &[
+    parse::ANNOUNCE_LIST_KEY,
+    parse::ANNOUNCE_URL_KEY,
+    parse::CREATION_DATE_KEY,
+    parse::COMMENT_KEY,
+    parse::CREATED_BY_KEY,
+    parse::ENCODING_KEY,
+    parse::INFO_KEY,
+    parse::URL_LIST_KEY,
+];
+
 // ----------------------------------------------------------------------------//
 
 /// Contains directory and checksum data for a torrent file.
 #[derive(Debug, Clone, Eq, PartialEq)]
 pub struct Info {
     info_hash: InfoHash,
+    v2_info_hash: Option<InfoHash>,
     files: Vec<File>,
     pieces: Vec<[u8; sha::SHA_HASH_LEN]>,
     piece_len: u64,
     is_private: Option<bool>,
     // Present only for multi file torrents.
     file_directory: Option<PathBuf>,
+    info_bytes: Vec<u8>,
 }
 
 impl Info {
@@ -161,6 +348,13 @@ impl Info {
         self.info_hash
     }
 
+    /// The v2 hash of a hybrid torrent, otherwise None.
+    ///
+    /// See `MetainfoV2` for accessing the rest of the v2 fields.
+    pub fn v2_info_hash(&self) -> Option<InfoHash> {
+        self.v2_info_hash
+    }
+
     /// Some file directory if this is a multi-file torrent, otherwise None.
     ///
     /// If you want to check to see if this is a multi-file torrent, you should
@@ -199,14 +393,10 @@ impl Info {
     }
 
     /// Retrieve the bencoded bytes for the `Info` dictionary.
+    ///
+    /// These are the bytes the `Info` was read from, including any keys we do not know.
     pub fn to_bytes(&self) -> Vec<u8> {
-        // Since there are no file system accesses here, should be fine to unwrap
-        InfoBuilder::new()
-            .set_private_flag(self.is_private())
-            // TODO: Revisit this cast...
-            .set_piece_length(PieceLength::Custom(self.piece_length() as usize))
-            .build(1, self, |_| ())
-            .unwrap()
+        self.info_bytes.clone()
     }
 }
 
@@ -266,6 +456,9 @@ fn parse_info_dictionary<'a>(info_bencode: &BencodeRef<'a>) -> ParseResult<Info>
     let info_hash = InfoHash::from_bytes(info_bencode.buffer());
 
     let info_dict = parse::parse_root_dict(info_bencode)?;
+    let v2_info_hash = parse::parse_meta_version(info_dict)
+        .filter(|&version| version == 2)
+        .map(|_| v2::v2_info_hash(info_bencode.buffer()));
     let piece_len = parse::parse_piece_length(info_dict)?;
     let is_private = parse::parse_private(info_dict);
 
@@ -280,31 +473,36 @@ fn parse_info_dictionary<'a>(info_bencode: &BencodeRef<'a>) -> ParseResult<Info>
         let files_bencode = parse::parse_files_list(info_dict)?;
 
         let mut files_list = Vec::with_capacity(files_bencode.len());
-        for file_bencode in files_bencode {
-            let file_dict = parse::parse_file_dict(file_bencode)?;
-            let file = File::as_multi_file(file_dict)?;
+        for (index, file_bencode) in files_bencode.into_iter().enumerate() {
+            let file = parse::parse_file_dict(file_bencode)
+                .and_then(File::as_multi_file)
+                .map_err(|err| parse::within(err, parse::element_path(parse::FILES_KEY, index)))?;
 
             files_list.push(file);
         }
 
         Ok(Info {
             info_hash: info_hash,
+            v2_info_hash: v2_info_hash,
             files: files_list,
             pieces: piece_buffers,
             piece_len: piece_len,
             is_private: is_private,
             file_directory: Some(file_directory_path),
+            info_bytes: info_bencode.buffer().to_vec(),
         })
     } else {
         let file = File::as_single_file(info_dict)?;
 
         Ok(Info {
             info_hash: info_hash,
+            v2_info_hash: v2_info_hash,
             files: vec![file],
             pieces: piece_buffers,
             piece_len: piece_len,
             is_private: is_private,
             file_directory: None,
+            info_bytes: info_bencode.buffer().to_vec(),
         })
     }
 }
@@ -348,6 +546,7 @@ pub struct File {
     len: u64,
     path: PathBuf,
     md5sum: Option<Vec<u8>>,
+    attr: Option<String>,
 }
 
 impl File {
@@ -358,12 +557,14 @@ impl File {
     {
         let length = parse::parse_length(info_dict)?;
         let md5sum = parse::parse_md5sum(info_dict).map(|m| m.to_owned());
+        let attr = parse::parse_attr(info_dict).map(|a| a.to_owned());
         let name = parse::parse_name(info_dict)?;
 
         Ok(File {
             len: length,
             path: name.to_owned().into(),
             md5sum: md5sum,
+            attr: attr,
         })
     }
 
@@ -374,12 +575,14 @@ impl File {
     {
         let length = parse::parse_length(file_dict)?;
         let md5sum = parse::parse_md5sum(file_dict).map(|m| m.to_owned());
+        let attr = parse::parse_attr(file_dict).map(|a| a.to_owned());
 
         let path_list_bencode = parse::parse_path_list(file_dict)?;
 
         let mut path_buf = PathBuf::new();
-        for path_bencode in path_list_bencode {
-            let path = parse::parse_path_str(path_bencode)?;
+        for (index, path_bencode) in path_list_bencode.into_iter().enumerate() {
+            let path = parse::parse_path_str(path_bencode)
+                .map_err(|err| parse::within(err, parse::element_path(parse::PATH_KEY, index)))?;
 
             path_buf.push(path);
         }
@@ -388,6 +591,7 @@ impl File {
             len: length,
             path: path_buf,
             md5sum: md5sum,
+            attr: attr,
         })
     }
 
@@ -407,6 +611,16 @@ impl File {
     pub fn path(&self) -> &Path {
         &self.path
     }
+
+    /// Whether or not the file is a padding file.
+    ///
+    /// Padding files align the file after them to a piece boundary, they only
+    /// hold zeroes and should not be written to disk.
+    ///
+    /// See http://www.bittorrent.org/beps/bep_0047.html.
+    pub fn is_padding(&self) -> bool {
+        self.attr.as_ref().map_or(false, |attr| attr.contains('p'))
+    }
 }
 /// Iterator over each File within the MetainfoFile.
 pub struct Files<'a> {
@@ -1047,6 +1261,206 @@ mod tests {
         );
     }
 
+    #[test]
+    fn positive_parse_url_list_single_url() {
+        let web_seed = "http://dummy_domain.com/files/";
+        let pieces = [0u8; sha::SHA_HASH_LEN];
+
+        let mut root_dict = BencodeMut::new_dict();
+        {
+            let root_dict_access = root_dict.dict_mut().unwrap();
+            root_dict_access.insert(parse::URL_LIST_KEY.into(), bt_ben_bytes!(web_seed));
+
+            let mut info_dict = BencodeMut::new_dict();
+            {
+                let info_dict_access = info_dict.dict_mut().unwrap();
+
+                info_dict_access.insert(parse::PIECE_LENGTH_KEY.into(), bt_ben_int!(1024));
+                info_dict_access.insert(parse::PIECES_KEY.into(), bt_ben_bytes!(&pieces[..]));
+                info_dict_access.insert(parse::NAME_KEY.into(), bt_ben_bytes!("dummy_file_name"));
+                info_dict_access.insert(parse::LENGTH_KEY.into(), bt_ben_int!(0));
+            }
+
+            root_dict_access.insert(parse::INFO_KEY.into(), info_dict);
+        }
+
+        let metainfo_file = Metainfo::from_bytes(root_dict.encode()).unwrap();
+
+        assert_eq!(metainfo_file.web_seeds(), Some(&vec![web_seed.to_owned()]));
+    }
+
+    /// Parse a single file metainfo file with the given entries in its root dictionary.
+    fn parse_with_root_entries(entries: Vec<(&'static [u8], BencodeMut<'static>)>) -> Metainfo {
+        let pieces = [0u8; sha::SHA_HASH_LEN];
+
+        let mut root_dict = BencodeMut::new_dict();
+        {
+            let root_dict_access = root_dict.dict_mut().unwrap();
+            for (key, value) in entries {
+                root_dict_access.insert(key.into(), value);
+            }
+
+            let mut info_dict = BencodeMut::new_dict();
+            {
+                let info_dict_access = info_dict.dict_mut().unwrap();
+
+                info_dict_access.insert(parse::PIECE_LENGTH_KEY.into(), bt_ben_int!(1024));
+                info_dict_access.insert(parse::PIECES_KEY.into(), bt_ben_bytes!(&pieces[..]));
+                info_dict_access.insert(parse::NAME_KEY.into(), bt_ben_bytes!("dummy_file_name"));
+                info_dict_access.insert(parse::LENGTH_KEY.into(), bt_ben_int!(0));
+                info_dict_access.insert(parse::PRIVATE_KEY.into(), bt_ben_int!(1));
+            }
+
+            root_dict_access.insert(parse::INFO_KEY.into(), info_dict);
+        }
+
+        Metainfo::from_bytes(root_dict.encode()).unwrap()
+    }
+
+    fn tier_urls(metainfo_file: &Metainfo) -> Vec<Vec<String>> {
+        metainfo_file
+            .tracker_tiers()
+            .iter()
+            .map(|tier| tier.iter().map(|tracker| tracker.to_string()).collect())
+            .collect()
+    }
+
+    #[test]
+    fn positive_parse_peer_sources() {
+        let metainfo_file = parse_with_root_entries(vec![
+            (
+                parse::ANNOUNCE_URL_KEY,
+                bt_ben_bytes!("udp://main.example.com:6969"),
+            ),
+            (
+                parse::ANNOUNCE_LIST_KEY,
+                bt_ben_list!(
+                    bt_ben_list!(
+                        bt_ben_bytes!("udp://a.example.com:6969"),
+                        bt_ben_bytes!("http://b.example.com/announce")
+                    ),
+                    bt_ben_bytes!("https://c.example.com/announce")
+                ),
+            ),
+            (
+                parse::URL_LIST_KEY,
+                bt_ben_bytes!("http://seed.example.com/files/"),
+            ),
+            (
+                parse::HTTP_SEEDS_KEY,
+                bt_ben_list!(bt_ben_bytes!("http://seed.example.com/seed.php")),
+            ),
+            (
+                parse::NODES_KEY,
+                bt_ben_list!(
+                    bt_ben_list!(bt_ben_bytes!("router.example.com"), bt_ben_int!(6881)),
+                    bt_ben_bytes!("127.0.0.1:6882")
+                ),
+            ),
+        ]);
+
+        // The main tracker is not used along with an announce-list
+        assert_eq!(
+            tier_urls(&metainfo_file),
+            vec![
+                vec![
+                    "udp://a.example.com:6969/".to_owned(),
+                    "http://b.example.com/announce".to_owned()
+                ],
+                vec!["https://c.example.com/announce".to_owned()],
+            ]
+        );
+        // Shuffling keeps the trackers within their tier
+        let mut tiers = metainfo_file.tracker_tiers().to_vec();
+        let mut shuffled = metainfo_file.shuffled_tracker_tiers();
+        for tier in tiers.iter_mut().chain(shuffled.iter_mut()) {
+            tier.sort_by_key(|tracker| tracker.to_string());
+        }
+        assert_eq!(shuffled, tiers);
+
+        assert_eq!(
+            metainfo_file
+                .web_seed_urls()
+                .iter()
+                .map(|url| url.to_string())
+                .collect::<Vec<String>>(),
+            vec!["http://seed.example.com/files/".to_owned()]
+        );
+        assert_eq!(metainfo_file.http_seeds().len(), 1);
+        assert_eq!(
+            metainfo_file.nodes(),
+            &[
+                ("router.example.com".to_owned(), 6881),
+                ("127.0.0.1".to_owned(), 6882)
+            ]
+        );
+        assert!(metainfo_file.is_private());
+        assert!(metainfo_file.parse_warnings().is_empty());
+    }
+
+    #[test]
+    fn positive_parse_peer_sources_skips_malformed() {
+        let metainfo_file = parse_with_root_entries(vec![
+            (
+                parse::ANNOUNCE_URL_KEY,
+                bt_ben_bytes!("udp://main.example.com:6969"),
+            ),
+            (
+                parse::ANNOUNCE_LIST_KEY,
+                bt_ben_list!(
+                    bt_ben_list!(bt_ben_bytes!("udp://no-port.example.com"), bt_ben_int!(5)),
+                    bt_ben_int!(6)
+                ),
+            ),
+            (
+                parse::URL_LIST_KEY,
+                bt_ben_list!(
+                    bt_ben_bytes!("ftp://seed.example.com/files/"),
+                    bt_ben_bytes!("http://seed.example.com/files/")
+                ),
+            ),
+            (parse::HTTP_SEEDS_KEY, bt_ben_int!(7)),
+            (
+                parse::NODES_KEY,
+                bt_ben_list!(
+                    bt_ben_list!(bt_ben_bytes!("router.example.com"), bt_ben_int!(70000)),
+                    bt_ben_bytes!("no-port.example.com"),
+                    bt_ben_list!(bt_ben_bytes!("router.example.com"), bt_ben_int!(6881))
+                ),
+            ),
+        ]);
+
+        // Without any valid tracker in the announce-list, the main tracker is used
+        assert_eq!(
+            tier_urls(&metainfo_file),
+            vec![vec!["udp://main.example.com:6969/".to_owned()]]
+        );
+        assert_eq!(metainfo_file.web_seed_urls().len(), 1);
+        assert!(metainfo_file.http_seeds().is_empty());
+        assert_eq!(
+            metainfo_file.nodes(),
+            &[("router.example.com".to_owned(), 6881)]
+        );
+
+        let warned_keys: Vec<&str> = metainfo_file
+            .parse_warnings()
+            .iter()
+            .map(|warning| warning.key())
+            .collect();
+        assert_eq!(
+            warned_keys,
+            vec![
+                "announce-list",
+                "announce-list",
+                "announce-list",
+                "url-list",
+                "httpseeds",
+                "nodes",
+                "nodes"
+            ]
+        );
+    }
+
     #[test]
     #[should_panic]
     fn negative_parse_from_single_file_with_no_file_name() {
@@ -69,4 +69,11 @@ pub use metainfo::{File, Info, Metainfo};
 
 mod parse;
 
+mod sources;
+pub use sources::{TrackerProtocol, TrackerUrl};
+
+mod v2;
+pub use v2::{FileV2, MetainfoV2, MERKLE_BLOCK_LEN, SHA256_HASH_LEN};
+
 pub use crate::util::bt::InfoHash;
+pub use url::Url;
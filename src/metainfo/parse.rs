@@ -1,7 +1,7 @@
 use crate::bencode::BRefAccess;
 use crate::bencode::{BConvert, BDictAccess, BListAccess, BencodeConvertError};
 
-use crate::metainfo::error::{ParseError, ParseResult};
+use crate::metainfo::error::{ParseError, ParseErrorKind, ParseResult};
 
 /// Struct implemented the BencodeConvert trait for decoding the metainfo file.
 struct MetainfoConverter;
@@ -20,6 +20,9 @@ const CONVERT: MetainfoConverter = MetainfoConverter;
 /// Used as an error key to refer to the root bencode object.
 pub const ROOT_ERROR_KEY: &'static [u8] = b"root";
 
+/// Used as an error key to refer to a value by where it was found, such as a list element.
+pub const ELEMENT_ERROR_KEY: &'static [u8] = b"";
+
 /// Keys found within the root dictionary of a metainfo file.
 pub const ANNOUNCE_LIST_KEY: &'static [u8] = b"announce-list";
 pub const ANNOUNCE_URL_KEY: &'static [u8] = b"announce";
@@ -28,6 +31,10 @@ pub const COMMENT_KEY: &'static [u8] = b"comment";
 pub const CREATED_BY_KEY: &'static [u8] = b"created by";
 pub const ENCODING_KEY: &'static [u8] = b"encoding";
 pub const INFO_KEY: &'static [u8] = b"info";
+pub const URL_LIST_KEY: &'static [u8] = b"url-list";
+pub const HTTP_SEEDS_KEY: &'static [u8] = b"httpseeds";
+pub const NODES_KEY: &'static [u8] = b"nodes";
+pub const PIECE_LAYERS_KEY: &'static [u8] = b"piece layers";
 
 /// Keys found within the info dictionary of a metainfo file.
 pub const PIECE_LENGTH_KEY: &'static [u8] = b"piece length";
@@ -35,11 +42,38 @@ pub const PIECES_KEY: &'static [u8] = b"pieces";
 pub const PRIVATE_KEY: &'static [u8] = b"private";
 pub const NAME_KEY: &'static [u8] = b"name";
 pub const FILES_KEY: &'static [u8] = b"files";
+pub const META_VERSION_KEY: &'static [u8] = b"meta version";
+pub const FILE_TREE_KEY: &'static [u8] = b"file tree";
 
 /// Keys found within the files dictionary of a metainfo file.
 pub const LENGTH_KEY: &'static [u8] = b"length";
 pub const MD5SUM_KEY: &'static [u8] = b"md5sum";
 pub const PATH_KEY: &'static [u8] = b"path";
+pub const ATTR_KEY: &'static [u8] = b"attr";
+
+/// Keys found within the file tree of a metainfo file.
+pub const FILE_TREE_ENTRY_KEY: &'static [u8] = b"";
+pub const PIECES_ROOT_KEY: &'static [u8] = b"pieces root";
+
+/// Qualify the path of a conversion error with the value it was found in.
+pub fn within<P>(error: ParseError, parent: P) -> ParseError
+where
+    P: AsRef<[u8]>,
+{
+    match error {
+        ParseError(ParseErrorKind::BencodeConvert(err), state) => {
+            let parent = String::from_utf8_lossy(parent.as_ref());
+
+            ParseError(ParseErrorKind::BencodeConvert(err.within(&parent)), state)
+        }
+        other => other,
+    }
+}
+
+/// Path of the element at the given index of the list under the given key.
+pub fn element_path(key: &[u8], index: usize) -> String {
+    format!("{}[{}]", String::from_utf8_lossy(key), index)
+}
 
 /// Parses the root bencode as a dictionary.
 pub fn parse_root_dict<B>(root_bencode: &B) -> ParseResult<&dyn BDictAccess<B::BKey, B::BType>>
@@ -78,6 +112,28 @@ where
         .collect()
 }
 
+/// Parses the web seed urls from the root dictionary.
+///
+/// The url list may hold either a single url, or a list of urls.
+pub fn parse_url_list<B>(root_dict: &dyn BDictAccess<B::BKey, B>) -> Option<Vec<String>>
+where
+    B: BRefAccess<BType = B>,
+{
+    if let Ok(url) = CONVERT.lookup_and_convert_str(root_dict, URL_LIST_KEY) {
+        return Some(vec![url.to_owned()]);
+    }
+
+    CONVERT
+        .lookup_and_convert_list(root_dict, URL_LIST_KEY)
+        .ok()
+        .map(|list| {
+            list.into_iter()
+                .filter_map(|bencode_str| bencode_str.str())
+                .map(String::from)
+                .collect()
+        })
+}
+
 /// Parses the announce url from the root dictionary.
 pub fn parse_announce_url<'a, B>(root_dict: &'a dyn BDictAccess<B::BKey, B>) -> Option<&'a str>
 where
@@ -124,6 +180,18 @@ where
     CONVERT.lookup_and_convert_str(root_dict, ENCODING_KEY).ok()
 }
 
+/// Parses the piece layers dictionary from the root dictionary.
+pub fn parse_piece_layers<B>(
+    root_dict: &dyn BDictAccess<B::BKey, B>,
+) -> Option<&dyn BDictAccess<B::BKey, B::BType>>
+where
+    B: BRefAccess,
+{
+    CONVERT
+        .lookup_and_convert_dict(root_dict, PIECE_LAYERS_KEY)
+        .ok()
+}
+
 /// Parses the info dictionary from the root dictionary.
 pub fn parse_info_bencode<'a, B>(root_dict: &'a dyn BDictAccess<B::BKey, B>) -> ParseResult<&B>
 where
@@ -171,6 +239,26 @@ where
     CONVERT.lookup_and_convert_str(info_dict, NAME_KEY)
 }
 
+/// Parses the meta version from the info dictionary.
+pub fn parse_meta_version<B>(info_dict: &dyn BDictAccess<B::BKey, B>) -> Option<i64>
+where
+    B: BRefAccess,
+{
+    CONVERT
+        .lookup_and_convert_int(info_dict, META_VERSION_KEY)
+        .ok()
+}
+
+/// Parses the file tree from the info dictionary.
+pub fn parse_file_tree<B>(
+    info_dict: &dyn BDictAccess<B::BKey, B>,
+) -> ParseResult<&dyn BDictAccess<B::BKey, B::BType>>
+where
+    B: BRefAccess,
+{
+    CONVERT.lookup_and_convert_dict(info_dict, FILE_TREE_KEY)
+}
+
 /// Parses the files list from the info dictionary.
 pub fn parse_files_list<B>(
     info_dict: &dyn BDictAccess<B::BKey, B>,
@@ -188,7 +276,7 @@ pub fn parse_file_dict<B>(file_bencode: &B) -> ParseResult<&dyn BDictAccess<B::B
 where
     B: BRefAccess,
 {
-    CONVERT.convert_dict(file_bencode, FILES_KEY)
+    CONVERT.convert_dict(file_bencode, ELEMENT_ERROR_KEY)
 }
 
 /// Parses the length from the info or file dictionary.
@@ -211,6 +299,16 @@ where
         .ok()
 }
 
+/// Parses the attributes from the info or file dictionary.
+pub fn parse_attr<'a, B>(info_or_file_dict: &'a dyn BDictAccess<B::BKey, B>) -> Option<&'a str>
+where
+    B: BRefAccess + 'a,
+{
+    CONVERT
+        .lookup_and_convert_str(info_or_file_dict, ATTR_KEY)
+        .ok()
+}
+
 /// Parses the path list from the file dictionary.
 pub fn parse_path_list<B>(
     file_dict: &dyn BDictAccess<B::BKey, B>,
@@ -226,5 +324,32 @@ pub fn parse_path_str<B>(path_bencode: &B) -> ParseResult<&str>
 where
     B: BRefAccess,
 {
-    CONVERT.convert_str(path_bencode, PATH_KEY)
+    CONVERT.convert_str(path_bencode, ELEMENT_ERROR_KEY)
+}
+
+// ----------------------------------------------------------------------------//
+
+/// Parses a directory or file dictionary from the file tree bencode.
+pub fn parse_file_tree_dict<B>(
+    file_tree_bencode: &B,
+) -> ParseResult<&dyn BDictAccess<B::BKey, B::BType>>
+where
+    B: BRefAccess,
+{
+    CONVERT.convert_dict(file_tree_bencode, ELEMENT_ERROR_KEY)
+}
+
+/// Parses a key of the file tree as a UTF-8 path element.
+pub fn parse_file_tree_key(key: &[u8]) -> ParseResult<&str> {
+    CONVERT.convert_key_str(key)
+}
+
+/// Parses the pieces root from the file dictionary of the file tree.
+pub fn parse_pieces_root<'a, B>(file_dict: &'a dyn BDictAccess<B::BKey, B>) -> Option<&'a [u8]>
+where
+    B: BRefAccess + 'a,
+{
+    CONVERT
+        .lookup_and_convert_bytes(file_dict, PIECES_ROOT_KEY)
+        .ok()
 }
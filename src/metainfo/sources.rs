@@ -0,0 +1,340 @@
+//! Sources of peers listed in a metainfo file, as trackers, web seeds and DHT nodes.
+use std::fmt;
+
+use url::{SchemeType, Url, UrlParser};
+
+use crate::bencode::{BDictAccess, BRefAccess};
+
+use super::error::ParseWarning;
+use super::parse;
+
+/// Protocol used to announce to a tracker.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum TrackerProtocol {
+    Http,
+    Https,
+    Udp,
+    /// WebTorrent tracker over a websocket.
+    Ws,
+    /// WebTorrent tracker over a secure websocket.
+    Wss,
+}
+
+/// Announce url of a tracker.
+///
+/// Http, https and websocket trackers default to their usual port, udp trackers have to
+/// specify one.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct TrackerUrl {
+    protocol: TrackerProtocol,
+    url: Url,
+}
+
+impl TrackerUrl {
+    /// Parse the given announce url, returning None if it is not the url of a tracker.
+    pub fn parse(url: &str) -> Option<TrackerUrl> {
+        let url = UrlParser::new()
+            .scheme_type_mapper(tracker_scheme_type_mapper)
+            .parse(url)
+            .ok()?;
+        let protocol = match &url.scheme[..] {
+            "http" => TrackerProtocol::Http,
+            "https" => TrackerProtocol::Https,
+            "udp" if url.port().is_some() => TrackerProtocol::Udp,
+            "ws" => TrackerProtocol::Ws,
+            "wss" => TrackerProtocol::Wss,
+            _ => return None,
+        };
+
+        if url.serialize_host().map_or(true, |host| host.is_empty()) {
+            return None;
+        }
+
+        Some(TrackerUrl {
+            protocol: protocol,
+            url: url,
+        })
+    }
+
+    /// Protocol to announce to the tracker with.
+    pub fn protocol(&self) -> TrackerProtocol {
+        self.protocol
+    }
+
+    /// Host name or address of the tracker.
+    pub fn host(&self) -> String {
+        self.url.serialize_host().unwrap()
+    }
+
+    /// Port of the tracker.
+    pub fn port(&self) -> u16 {
+        self.url.port_or_default().unwrap()
+    }
+
+    /// Announce url of the tracker.
+    pub fn url(&self) -> &Url {
+        &self.url
+    }
+}
+
+impl fmt::Display for TrackerUrl {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.url.fmt(f)
+    }
+}
+
+/// Udp trackers are relative urls, without a default port.
+fn tracker_scheme_type_mapper(scheme: &str) -> SchemeType {
+    match scheme {
+        "udp" => SchemeType::Relative(0),
+        _ => url::whatwg_scheme_type_mapper(scheme),
+    }
+}
+
+// ----------------------------------------------------------------------------//
+
+/// Tiers of trackers, from the announce-list if it lists any valid tracker, otherwise
+/// from the main tracker.
+pub fn tracker_tiers(
+    opt_announce: Option<&str>,
+    opt_announce_list: Option<&Vec<Vec<String>>>,
+    warnings: &mut Vec<ParseWarning>,
+) -> Vec<Vec<TrackerUrl>> {
+    let tiers: Vec<Vec<TrackerUrl>> = opt_announce_list
+        .iter()
+        .flat_map(|tiers| tiers.iter())
+        .map(|tier| {
+            tier.iter()
+                .filter_map(|url| tracker_url(parse::ANNOUNCE_LIST_KEY, url, warnings))
+                .collect::<Vec<TrackerUrl>>()
+        })
+        .filter(|tier| !tier.is_empty())
+        .collect();
+
+    if !tiers.is_empty() {
+        return tiers;
+    }
+
+    opt_announce
+        .and_then(|url| tracker_url(parse::ANNOUNCE_URL_KEY, url, warnings))
+        .map(|tracker| vec![vec![tracker]])
+        .unwrap_or_else(Vec::new)
+}
+
+fn tracker_url(key: &[u8], url: &str, warnings: &mut Vec<ParseWarning>) -> Option<TrackerUrl> {
+    let opt_tracker = TrackerUrl::parse(url);
+    if opt_tracker.is_none() {
+        warnings.push(ParseWarning::new(
+            key,
+            format!("Invalid Tracker Url {:?}", url),
+        ));
+    }
+
+    opt_tracker
+}
+
+/// Tiers of the announce-list in the root dictionary, as strings.
+///
+/// A tier holding a single url as a string, instead of a list, is taken as is.
+pub fn parse_announce_tiers<B>(
+    root_dict: &dyn BDictAccess<B::BKey, B>,
+    warnings: &mut Vec<ParseWarning>,
+) -> Option<Vec<Vec<String>>>
+where
+    B: BRefAccess<BType = B>,
+{
+    let announce_list = root_dict.lookup(parse::ANNOUNCE_LIST_KEY)?;
+    let list = match announce_list.list() {
+        Some(list) => list,
+        None => {
+            warnings.push(ParseWarning::new(
+                parse::ANNOUNCE_LIST_KEY,
+                "Announce List Is Not A List".to_owned(),
+            ));
+            return None;
+        }
+    };
+
+    Some(
+        list.into_iter()
+            .filter_map(|tier| str_or_str_list(parse::ANNOUNCE_LIST_KEY, tier, warnings))
+            .collect(),
+    )
+}
+
+/// Http urls of the seeds under the given key of the root dictionary, either a single
+/// url or a list of urls.
+pub fn parse_seed_urls<B>(
+    root_dict: &dyn BDictAccess<B::BKey, B>,
+    key: &[u8],
+    warnings: &mut Vec<ParseWarning>,
+) -> Vec<Url>
+where
+    B: BRefAccess<BType = B>,
+{
+    let urls = root_dict
+        .lookup(key)
+        .and_then(|value| str_or_str_list(key, value, warnings))
+        .unwrap_or_else(Vec::new);
+
+    urls.iter()
+        .filter_map(|url| match Url::parse(url) {
+            Ok(seed) if seed.scheme == "http" || seed.scheme == "https" => Some(seed),
+            _ => {
+                warnings.push(ParseWarning::new(
+                    key,
+                    format!("Invalid Seed Url {:?}", url),
+                ));
+                None
+            }
+        })
+        .collect()
+}
+
+/// DHT nodes of the root dictionary, each either a list of a host and a port, or a
+/// host and a port separated by a colon.
+pub fn parse_nodes<B>(
+    root_dict: &dyn BDictAccess<B::BKey, B>,
+    warnings: &mut Vec<ParseWarning>,
+) -> Vec<(String, u16)>
+where
+    B: BRefAccess<BType = B>,
+{
+    let list = match root_dict.lookup(parse::NODES_KEY).map(|nodes| nodes.list()) {
+        Some(Some(list)) => list,
+        Some(None) => {
+            warnings.push(ParseWarning::new(
+                parse::NODES_KEY,
+                "Nodes Is Not A List".to_owned(),
+            ));
+            return Vec::new();
+        }
+        None => return Vec::new(),
+    };
+
+    list.into_iter()
+        .filter_map(|node| {
+            let opt_node = node_host_port(node).filter(|&(ref host, _)| !host.is_empty());
+            if opt_node.is_none() {
+                warnings.push(ParseWarning::new(
+                    parse::NODES_KEY,
+                    "Invalid Node Entry".to_owned(),
+                ));
+            }
+
+            opt_node
+        })
+        .collect()
+}
+
+fn node_host_port<B>(node: &B) -> Option<(String, u16)>
+where
+    B: BRefAccess<BType = B>,
+{
+    if let Some(host_port) = node.str() {
+        let index = host_port.rfind(':')?;
+        let port = host_port[index + 1..]
+            .parse()
+            .ok()
+            .filter(|&port| port != 0)?;
+
+        return Some((host_port[..index].to_owned(), port));
+    }
+
+    let pair = node.list()?;
+    if pair.len() != 2 {
+        return None;
+    }
+
+    let host = pair.get(0)?.str()?;
+    let port = pair.get(1)?.int().and_then(port_from_int)?;
+
+    Some((host.to_owned(), port))
+}
+
+fn port_from_int(port: i64) -> Option<u16> {
+    if port > 0 && port <= u16::max_value() as i64 {
+        Some(port as u16)
+    } else {
+        None
+    }
+}
+
+/// Strings of a value holding either a single string, or a list of strings.
+fn str_or_str_list<B>(
+    key: &[u8],
+    value: &B,
+    warnings: &mut Vec<ParseWarning>,
+) -> Option<Vec<String>>
+where
+    B: BRefAccess<BType = B>,
+{
+    if let Some(string) = value.str() {
+        return Some(vec![string.to_owned()]);
+    }
+
+    match value.list() {
+        Some(list) => Some(
+            list.into_iter()
+                .filter_map(|entry| {
+                    let opt_string = entry.str().map(String::from);
+                    if opt_string.is_none() {
+                        warnings.push(ParseWarning::new(key, "Entry Is Not A String".to_owned()));
+                    }
+
+                    opt_string
+                })
+                .collect(),
+        ),
+        None => {
+            warnings.push(ParseWarning::new(
+                key,
+                "Value Is Not A String Or A List".to_owned(),
+            ));
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{TrackerProtocol, TrackerUrl};
+
+    #[test]
+    fn positive_parse_tracker_urls() {
+        let http = TrackerUrl::parse("http://tracker.example.com/announce").unwrap();
+        assert_eq!(http.protocol(), TrackerProtocol::Http);
+        assert_eq!(http.host(), "tracker.example.com");
+        assert_eq!(http.port(), 80);
+
+        let https = TrackerUrl::parse("https://tracker.example.com:8443/announce").unwrap();
+        assert_eq!(https.protocol(), TrackerProtocol::Https);
+        assert_eq!(https.port(), 8443);
+
+        let udp = TrackerUrl::parse("udp://127.0.0.1:6969/announce").unwrap();
+        assert_eq!(udp.protocol(), TrackerProtocol::Udp);
+        assert_eq!(udp.host(), "127.0.0.1");
+        assert_eq!(udp.port(), 6969);
+
+        let wss = TrackerUrl::parse("wss://tracker.example.com").unwrap();
+        assert_eq!(wss.protocol(), TrackerProtocol::Wss);
+        assert_eq!(wss.port(), 443);
+
+        let ws = TrackerUrl::parse("ws://tracker.example.com:8000/").unwrap();
+        assert_eq!(ws.protocol(), TrackerProtocol::Ws);
+        assert_eq!(ws.port(), 8000);
+    }
+
+    #[test]
+    fn negative_parse_tracker_urls() {
+        assert_eq!(
+            TrackerUrl::parse("udp://tracker.example.com/announce"),
+            None
+        );
+        assert_eq!(
+            TrackerUrl::parse("ftp://tracker.example.com/announce"),
+            None
+        );
+        assert_eq!(TrackerUrl::parse("not a url"), None);
+    }
+}
@@ -0,0 +1,556 @@
+//! Accessing the BitTorrent v2 fields of a Metainfo file.
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use crypto::digest::Digest;
+use crypto::sha2::Sha256;
+
+use crate::bencode::{BDecodeOpt, BDictAccess, BRefAccess, BencodeRef};
+use crate::util::bt::InfoHash;
+
+use super::error::{ParseError, ParseErrorKind, ParseResult};
+use super::parse;
+
+/// Length of a SHA-256 hash.
+pub const SHA256_HASH_LEN: usize = 32;
+
+/// Length in bytes of the blocks hashed as the leaves of the merkle tree of each file.
+pub const MERKLE_BLOCK_LEN: u64 = 16 * 1024;
+
+/// Only meta version supported by this module.
+const META_VERSION_V2: i64 = 2;
+
+/// Contains the v2 fields of a v2 or hybrid torrent file.
+///
+/// See http://www.bittorrent.org/beps/bep_0052.html.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct MetainfoV2 {
+    info_hash: InfoHash,
+    v1_info_hash: Option<InfoHash>,
+    name: String,
+    piece_len: u64,
+    is_private: Option<bool>,
+    files: Vec<FileV2>,
+    piece_layers: HashMap<[u8; SHA256_HASH_LEN], Vec<[u8; SHA256_HASH_LEN]>>,
+}
+
+impl MetainfoV2 {
+    /// Read a `MetainfoV2` from metainfo file bytes.
+    ///
+    /// Every non-empty file must have a pieces root, and the piece layer of every
+    /// file larger than a piece must hash up to the pieces root of the file.
+    pub fn from_bytes<B>(bytes: B) -> ParseResult<MetainfoV2>
+    where
+        B: AsRef<[u8]>,
+    {
+        let bytes_slice = bytes.as_ref();
+
+        parse_meta_v2_bytes(bytes_slice)
+    }
+
+    /// The v2 hash to uniquely identify this torrent.
+    ///
+    /// This is the SHA-256 hash of the info dictionary, peers, trackers and the
+    /// DHT are sent its `InfoHash::truncated` form when talking about the v2 swarm.
+    pub fn info_hash(&self) -> InfoHash {
+        self.info_hash
+    }
+
+    /// The v1 hash of a hybrid torrent, otherwise None.
+    pub fn v1_info_hash(&self) -> Option<InfoHash> {
+        self.v1_info_hash
+    }
+
+    /// Whether or not the torrent also contains the v1 fields.
+    pub fn is_hybrid(&self) -> bool {
+        self.v1_info_hash.is_some()
+    }
+
+    /// Display name of the torrent.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Length in bytes of each piece.
+    pub fn piece_length(&self) -> u64 {
+        self.piece_len
+    }
+
+    /// Whether or not the torrent is private.
+    pub fn is_private(&self) -> Option<bool> {
+        self.is_private
+    }
+
+    /// Each file within the file tree, ordered by path.
+    pub fn files(&self) -> &[FileV2] {
+        &self.files
+    }
+
+    /// Piece layer for the file with the given pieces root.
+    ///
+    /// Only files larger than a single piece have a piece layer, which holds
+    /// the merkle root hash of each piece of the file.
+    pub fn piece_layer(&self, pieces_root: &[u8]) -> Option<&[[u8; SHA256_HASH_LEN]]> {
+        let mut root = [0u8; SHA256_HASH_LEN];
+        if pieces_root.len() != SHA256_HASH_LEN {
+            return None;
+        }
+        root.copy_from_slice(pieces_root);
+
+        self.piece_layers.get(&root).map(|layer| &layer[..])
+    }
+}
+
+/// Compute the v2 info hash for the given info dictionary bytes.
+pub(crate) fn v2_info_hash(info_bytes: &[u8]) -> InfoHash {
+    InfoHash::V2(sha256(&[info_bytes]))
+}
+
+/// Parses the given metainfo bytes and builds a MetainfoV2 from them.
+fn parse_meta_v2_bytes(bytes: &[u8]) -> ParseResult<MetainfoV2> {
+    let root_bencode = BencodeRef::decode(bytes, BDecodeOpt::default())?;
+    let root_dict = parse::parse_root_dict(&root_bencode)?;
+
+    let info_bencode = parse::parse_info_bencode(root_dict)?;
+    let info_dict = parse::parse_root_dict(info_bencode)?;
+
+    match parse::parse_meta_version(info_dict) {
+        Some(META_VERSION_V2) => (),
+        Some(version) => {
+            return Err(invalid_data(format!(
+                "Meta Version Of {} Is Not Supported",
+                version
+            )))
+        }
+        None => {
+            return Err(ParseError::from_kind(ParseErrorKind::MissingData {
+                details: "Meta Version Is Missing".to_owned(),
+            }))
+        }
+    }
+
+    let piece_len = parse::parse_piece_length(info_dict)?;
+    if !piece_len.is_power_of_two() || piece_len < MERKLE_BLOCK_LEN {
+        return Err(invalid_data(format!(
+            "Piece Length Of {} Is Invalid",
+            piece_len
+        )));
+    }
+
+    let mut files = Vec::new();
+    let file_tree =
+        parse::parse_file_tree(info_dict).map_err(|err| parse::within(err, parse::INFO_KEY))?;
+    parse_file_tree(file_tree, &mut PathBuf::new(), &mut files).map_err(|err| {
+        let err = parse::within(err, parse::FILE_TREE_KEY);
+
+        parse::within(err, parse::INFO_KEY)
+    })?;
+
+    let opt_layers_dict = parse::parse_piece_layers(root_dict);
+    let mut piece_layers = HashMap::new();
+    for file in files.iter().filter(|file| file.length() > piece_len) {
+        // Files without a pieces root were already rejected
+        let pieces_root = file.pieces_root.unwrap();
+        let layer = opt_layers_dict
+            .and_then(|layers_dict| layers_dict.lookup(&pieces_root[..]))
+            .and_then(|layer_bencode| layer_bencode.bytes())
+            .ok_or_else(|| {
+                ParseError::from_kind(ParseErrorKind::MissingData {
+                    details: format!("Piece Layer For {:?} Is Missing", file.path()),
+                })
+            })?;
+
+        let layer = validate_piece_layer(file, layer, piece_len)?;
+        piece_layers.insert(pieces_root, layer);
+    }
+
+    let v1_info_hash = parse::parse_pieces(info_dict)
+        .ok()
+        .map(|_| InfoHash::from_bytes(info_bencode.buffer()));
+
+    Ok(MetainfoV2 {
+        info_hash: v2_info_hash(info_bencode.buffer()),
+        v1_info_hash: v1_info_hash,
+        name: parse::parse_name(info_dict)?.to_owned(),
+        piece_len: piece_len,
+        is_private: parse::parse_private(info_dict),
+        files: files,
+        piece_layers: piece_layers,
+    })
+}
+
+/// Parses the directory dictionary, pushing every file found under it.
+fn parse_file_tree<'a>(
+    dir_dict: &dyn BDictAccess<&'a [u8], BencodeRef<'a>>,
+    path: &mut PathBuf,
+    files: &mut Vec<FileV2>,
+) -> ParseResult<()> {
+    let mut entries = dir_dict.to_list();
+    entries.sort_by_key(|&(key, _)| *key);
+
+    for (key, value) in entries {
+        let name = validate_path_element(parse::parse_file_tree_key(key)?)?;
+
+        path.push(name);
+        parse_file_tree_entry(value, path, files).map_err(|err| parse::within(err, name))?;
+        path.pop();
+    }
+
+    Ok(())
+}
+
+/// Parses the file or directory found at the given path of the file tree.
+fn parse_file_tree_entry<'a>(
+    entry_bencode: &BencodeRef<'a>,
+    path: &mut PathBuf,
+    files: &mut Vec<FileV2>,
+) -> ParseResult<()> {
+    let entry_dict = parse::parse_file_tree_dict(entry_bencode)?;
+
+    if let Some(file_bencode) = entry_dict.lookup(parse::FILE_TREE_ENTRY_KEY) {
+        if entry_dict.to_list().len() != 1 {
+            return Err(invalid_data(format!("File {:?} Also Contains Files", path)));
+        }
+
+        let file_dict = parse::parse_file_tree_dict(file_bencode)?;
+        files.push(FileV2::as_file_tree_file(file_dict, path.clone())?);
+
+        Ok(())
+    } else {
+        parse_file_tree(entry_dict, path, files)
+    }
+}
+
+/// Validate that the path element can be safely used as a single file or directory name.
+fn validate_path_element(element: &str) -> ParseResult<&str> {
+    if element.is_empty() || element == "." || element == ".." || element.contains(&['/', '\\'][..])
+    {
+        Err(invalid_data(format!(
+            "Path Element {:?} Is Invalid",
+            element
+        )))
+    } else {
+        Ok(element)
+    }
+}
+
+/// Validate that the piece layer hashes up to the pieces root of the file.
+fn validate_piece_layer(
+    file: &FileV2,
+    layer: &[u8],
+    piece_len: u64,
+) -> ParseResult<Vec<[u8; SHA256_HASH_LEN]>> {
+    let num_pieces = (file.length() + piece_len - 1) / piece_len;
+    if layer.len() as u64 != num_pieces * SHA256_HASH_LEN as u64 {
+        return Err(invalid_data(format!(
+            "Piece Layer Length Of {} For {:?} Is Invalid",
+            layer.len(),
+            file.path()
+        )));
+    }
+
+    let leaves: Vec<[u8; SHA256_HASH_LEN]> = layer
+        .chunks(SHA256_HASH_LEN)
+        .map(|chunk| {
+            let mut leaf = [0u8; SHA256_HASH_LEN];
+            leaf.copy_from_slice(chunk);
+
+            leaf
+        })
+        .collect();
+
+    if Some(merkle_root(&leaves, pad_piece_hash(piece_len))) != file.pieces_root {
+        Err(invalid_data(format!(
+            "Piece Layer For {:?} Does Not Match The Pieces Root",
+            file.path()
+        )))
+    } else {
+        Ok(leaves)
+    }
+}
+
+/// Computes the pieces root of a file from the hash of each of its blocks, along with its
+/// piece layer if the file is larger than a single piece.
+pub(crate) fn file_merkle_hashes(
+    block_hashes: &[[u8; SHA256_HASH_LEN]],
+    piece_len: u64,
+) -> ([u8; SHA256_HASH_LEN], Option<Vec<[u8; SHA256_HASH_LEN]>>) {
+    let blocks_per_piece = (piece_len / MERKLE_BLOCK_LEN) as usize;
+
+    if block_hashes.len() <= blocks_per_piece {
+        (merkle_root(block_hashes, [0u8; SHA256_HASH_LEN]), None)
+    } else {
+        let layer: Vec<[u8; SHA256_HASH_LEN]> = block_hashes
+            .chunks(blocks_per_piece)
+            .map(|piece_blocks| {
+                let mut leaves = piece_blocks.to_vec();
+                leaves.resize(blocks_per_piece, [0u8; SHA256_HASH_LEN]);
+
+                merkle_root(&leaves, [0u8; SHA256_HASH_LEN])
+            })
+            .collect();
+
+        (merkle_root(&layer, pad_piece_hash(piece_len)), Some(layer))
+    }
+}
+
+/// Computes the hash of a piece past the end of a file, which hashes as if every block in it was zero hashes.
+pub(crate) fn pad_piece_hash(piece_len: u64) -> [u8; SHA256_HASH_LEN] {
+    let mut pad_hash = [0u8; SHA256_HASH_LEN];
+    for _ in 0..(piece_len / MERKLE_BLOCK_LEN).trailing_zeros() {
+        pad_hash = sha256(&[&pad_hash, &pad_hash]);
+    }
+
+    pad_hash
+}
+
+/// Computes the root of the merkle tree with the given leaves, padded with the given hash.
+pub(crate) fn merkle_root(
+    leaves: &[[u8; SHA256_HASH_LEN]],
+    pad_hash: [u8; SHA256_HASH_LEN],
+) -> [u8; SHA256_HASH_LEN] {
+    let mut layer = leaves.to_vec();
+    layer.resize(leaves.len().next_power_of_two(), pad_hash);
+
+    while layer.len() > 1 {
+        layer = layer
+            .chunks(2)
+            .map(|pair| sha256(&[&pair[0], &pair[1]]))
+            .collect();
+    }
+
+    layer[0]
+}
+
+/// Computes the SHA-256 hash of the given byte slices.
+pub(crate) fn sha256(slices: &[&[u8]]) -> [u8; SHA256_HASH_LEN] {
+    let mut sha = Sha256::new();
+    for bytes in slices {
+        sha.input(bytes);
+    }
+
+    let mut hash = [0u8; SHA256_HASH_LEN];
+    sha.result(&mut hash);
+
+    hash
+}
+
+fn invalid_data(details: String) -> ParseError {
+    ParseError::from_kind(ParseErrorKind::InvalidData { details: details })
+}
+
+// ----------------------------------------------------------------------------//
+
+/// Contains information for a single file within the file tree.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct FileV2 {
+    len: u64,
+    path: PathBuf,
+    pieces_root: Option<[u8; SHA256_HASH_LEN]>,
+}
+
+impl FileV2 {
+    /// Parse the file dictionary of the file tree and generate a FileV2.
+    fn as_file_tree_file<B>(
+        file_dict: &dyn BDictAccess<B::BKey, B>,
+        path: PathBuf,
+    ) -> ParseResult<FileV2>
+    where
+        B: BRefAccess,
+    {
+        let length = parse::parse_length(file_dict)?;
+
+        let pieces_root = match parse::parse_pieces_root(file_dict) {
+            Some(root) if root.len() == SHA256_HASH_LEN => {
+                let mut pieces_root = [0u8; SHA256_HASH_LEN];
+                pieces_root.copy_from_slice(root);
+
+                Some(pieces_root)
+            }
+            Some(root) => {
+                return Err(invalid_data(format!(
+                    "Pieces Root Length Of {} For {:?} Is Invalid",
+                    root.len(),
+                    path
+                )))
+            }
+            None if length != 0 => {
+                return Err(ParseError::from_kind(ParseErrorKind::MissingData {
+                    details: format!("Pieces Root For {:?} Is Missing", path),
+                }))
+            }
+            None => None,
+        };
+
+        Ok(FileV2 {
+            len: length,
+            path: path,
+            pieces_root: pieces_root,
+        })
+    }
+
+    /// Length of the file in bytes.
+    pub fn length(&self) -> u64 {
+        self.len
+    }
+
+    /// Path of the file within the file tree.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Root hash of the merkle tree of the file, None if the file is empty.
+    pub fn pieces_root(&self) -> Option<&[u8]> {
+        self.pieces_root.as_ref().map(|root| &root[..])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bencode::{BMutAccess, BencodeMut};
+    use crate::metainfo::error::ParseErrorKind;
+    use crate::metainfo::parse;
+
+    use super::{MetainfoV2, MERKLE_BLOCK_LEN, SHA256_HASH_LEN};
+
+    /// Encode a torrent file with a piece length of a single block.
+    fn encode_torrent<'a>(
+        meta_version: i64,
+        file_tree: BencodeMut<'a>,
+        piece_layers: BencodeMut<'a>,
+    ) -> Vec<u8> {
+        let info = bt_ben_map! {
+            parse::NAME_KEY => bt_ben_bytes!("dummy"),
+            parse::PIECE_LENGTH_KEY => bt_ben_int!(MERKLE_BLOCK_LEN as i64),
+            parse::META_VERSION_KEY => bt_ben_int!(meta_version),
+            parse::FILE_TREE_KEY => file_tree
+        };
+
+        (bt_ben_map! {
+            parse::INFO_KEY => info,
+            parse::PIECE_LAYERS_KEY => piece_layers
+        })
+        .encode()
+    }
+
+    /// Dictionary for a file within the file tree.
+    fn file_entry<'a>(length: i64, opt_pieces_root: Option<&'a [u8]>) -> BencodeMut<'a> {
+        let mut file_dict = bt_ben_map! { parse::LENGTH_KEY => bt_ben_int!(length) };
+        if let Some(pieces_root) = opt_pieces_root {
+            file_dict
+                .dict_mut()
+                .unwrap()
+                .insert(parse::PIECES_ROOT_KEY.into(), bt_ben_bytes!(pieces_root));
+        }
+
+        bt_ben_map! { parse::FILE_TREE_ENTRY_KEY => file_dict }
+    }
+
+    /// Two leaves of a piece layer, and the pieces root they hash up to.
+    fn two_piece_layer() -> (Vec<u8>, [u8; SHA256_HASH_LEN]) {
+        let layer = [[1u8; SHA256_HASH_LEN], [2u8; SHA256_HASH_LEN]].concat();
+        let pieces_root = super::sha256(&[&layer]);
+
+        (layer, pieces_root)
+    }
+
+    fn parse_error_kind(bytes: &[u8]) -> ParseErrorKind {
+        MetainfoV2::from_bytes(bytes).unwrap_err().0
+    }
+
+    #[test]
+    fn positive_parse_piece_layer() {
+        let (layer, pieces_root) = two_piece_layer();
+        let bytes = encode_torrent(
+            2,
+            bt_ben_map! {
+                "a" => file_entry(2 * MERKLE_BLOCK_LEN as i64, Some(&pieces_root)),
+                "b" => bt_ben_map! { "c" => file_entry(0, None) }
+            },
+            bt_ben_map! { &pieces_root[..] => bt_ben_bytes!(&layer[..]) },
+        );
+
+        let metainfo = MetainfoV2::from_bytes(&bytes).unwrap();
+
+        assert!(!metainfo.is_hybrid());
+        assert_eq!(metainfo.name(), "dummy");
+        assert_eq!(metainfo.files().len(), 2);
+        assert_eq!(metainfo.files()[0].pieces_root(), Some(&pieces_root[..]));
+        assert_eq!(metainfo.files()[1].path(), ::std::path::Path::new("b/c"));
+        assert_eq!(metainfo.files()[1].pieces_root(), None);
+        assert_eq!(metainfo.piece_layer(&pieces_root).unwrap().concat(), layer);
+    }
+
+    #[test]
+    fn negative_piece_layer_does_not_match_pieces_root() {
+        let (layer, _) = two_piece_layer();
+        let pieces_root = [3u8; SHA256_HASH_LEN];
+        let bytes = encode_torrent(
+            2,
+            bt_ben_map! { "a" => file_entry(2 * MERKLE_BLOCK_LEN as i64, Some(&pieces_root)) },
+            bt_ben_map! { &pieces_root[..] => bt_ben_bytes!(&layer[..]) },
+        );
+
+        match parse_error_kind(&bytes) {
+            ParseErrorKind::InvalidData { .. } => (),
+            unexpected @ _ => panic!("Unexpected Error: {:?}", unexpected),
+        }
+    }
+
+    #[test]
+    fn negative_missing_piece_layer() {
+        let (_, pieces_root) = two_piece_layer();
+        let bytes = encode_torrent(
+            2,
+            bt_ben_map! { "a" => file_entry(2 * MERKLE_BLOCK_LEN as i64, Some(&pieces_root)) },
+            BencodeMut::new_dict(),
+        );
+
+        match parse_error_kind(&bytes) {
+            ParseErrorKind::MissingData { .. } => (),
+            unexpected @ _ => panic!("Unexpected Error: {:?}", unexpected),
+        }
+    }
+
+    #[test]
+    fn negative_missing_pieces_root() {
+        let bytes = encode_torrent(
+            2,
+            bt_ben_map! { "a" => file_entry(100, None) },
+            BencodeMut::new_dict(),
+        );
+
+        match parse_error_kind(&bytes) {
+            ParseErrorKind::MissingData { .. } => (),
+            unexpected @ _ => panic!("Unexpected Error: {:?}", unexpected),
+        }
+    }
+
+    #[test]
+    fn negative_unsupported_meta_version() {
+        let bytes = encode_torrent(
+            1,
+            bt_ben_map! { "a" => file_entry(0, None) },
+            BencodeMut::new_dict(),
+        );
+
+        match parse_error_kind(&bytes) {
+            ParseErrorKind::InvalidData { .. } => (),
+            unexpected @ _ => panic!("Unexpected Error: {:?}", unexpected),
+        }
+    }
+
+    #[test]
+    fn negative_path_element_leaves_file_tree() {
+        let bytes = encode_torrent(
+            2,
+            bt_ben_map! { ".." => bt_ben_map! { "a" => file_entry(0, None) } },
+            BencodeMut::new_dict(),
+        );
+
+        match parse_error_kind(&bytes) {
+            ParseErrorKind::InvalidData { .. } => (),
+            unexpected @ _ => panic!("Unexpected Error: {:?}", unexpected),
+        }
+    }
+}
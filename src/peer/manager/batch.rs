@@ -0,0 +1,330 @@
+use std::io::{self, IoSlice, Write};
+use std::mem;
+
+use bytes::Bytes;
+
+use crate::peer::message::PeerWireProtocolMessage;
+use crate::peer::{MessageCodec, PeerWireMessageCodec};
+
+/// Most slices handed to a single vectored write, well under `IOV_MAX` on any platform.
+const MAX_WRITE_SLICES: usize = 64;
+
+/// Outbound messages coalesced into as few writes as possible.
+///
+/// Messages are encoded back to back into a shared buffer, piece blocks are chained on
+/// without being copied, and `flush` hands all of it to the writer as vectored writes.
+/// Messages come out in the order they were pushed, and always whole within one flush.
+#[derive(Default)]
+pub struct MessageBatch {
+    segments: Vec<Bytes>,
+    buffer: Vec<u8>,
+    messages: usize,
+}
+
+impl MessageBatch {
+    /// Create a new, empty `MessageBatch`.
+    pub fn new() -> MessageBatch {
+        MessageBatch::default()
+    }
+
+    /// Encode the message onto the end of the batch.
+    ///
+    /// The batch is left as it was if the message could not be encoded.
+    pub fn push(
+        &mut self,
+        codec: &mut PeerWireMessageCodec,
+        message: &PeerWireProtocolMessage,
+    ) -> io::Result<()> {
+        let start = self.buffer.len();
+        let result = match *message {
+            PeerWireProtocolMessage::Piece(ref piece) => {
+                piece.write_header(&mut self.buffer).map(|_| {
+                    if piece.block_length() != 0 {
+                        self.seal_buffer();
+                        self.segments.push(piece.block());
+                    }
+                })
+            }
+            _ => codec.write_bytes(message, &mut self.buffer),
+        };
+
+        match result {
+            Ok(()) => {
+                self.messages += 1;
+
+                Ok(())
+            }
+            Err(err) => {
+                self.buffer.truncate(start);
+
+                Err(err)
+            }
+        }
+    }
+
+    /// Number of messages waiting to be flushed.
+    pub fn messages(&self) -> usize {
+        self.messages
+    }
+
+    /// Number of bytes waiting to be flushed.
+    pub fn len(&self) -> usize {
+        self.segments.iter().map(Bytes::len).sum::<usize>() + self.buffer.len()
+    }
+
+    /// Whether or not there is nothing waiting to be flushed.
+    pub fn is_empty(&self) -> bool {
+        self.messages == 0
+    }
+
+    /// Write out every message in the batch, returning how many writes that took.
+    ///
+    /// The batch is empty afterwards, even on error, when only some of it may have been written.
+    pub fn flush<W>(&mut self, mut writer: W) -> io::Result<usize>
+    where
+        W: Write,
+    {
+        self.seal_buffer();
+        let segments = mem::take(&mut self.segments);
+        self.messages = 0;
+
+        let mut writes = 0;
+        let (mut index, mut offset) = (0, 0);
+        while index < segments.len() {
+            let slices: Vec<IoSlice> = segments[index..]
+                .iter()
+                .take(MAX_WRITE_SLICES)
+                .enumerate()
+                .map(|(position, segment)| {
+                    let skip = if position == 0 { offset } else { 0 };
+
+                    IoSlice::new(&segment[skip..])
+                })
+                .collect();
+
+            let mut written = match writer.write_vectored(&slices) {
+                Ok(0) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::WriteZero,
+                        "Failed To Write Whole MessageBatch",
+                    ))
+                }
+                Ok(written) => written,
+                Err(ref err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err),
+            };
+            writes += 1;
+
+            // Step over the segments written in full, the rest is where we pick up from
+            while index < segments.len() && written >= segments[index].len() - offset {
+                written -= segments[index].len() - offset;
+                index += 1;
+                offset = 0;
+            }
+            offset += written;
+        }
+
+        Ok(writes)
+    }
+
+    fn seal_buffer(&mut self) {
+        if !self.buffer.is_empty() {
+            let buffer = mem::take(&mut self.buffer);
+
+            self.segments.push(Bytes::from(buffer));
+        }
+    }
+}
+
+/// Fold the `Have` messages into the `BitField` leading the messages, leaving `None` in their place.
+///
+/// Only valid while the bitfield has not reached the peer, that is before the first flush.
+/// Haves past the end of the bitfield are left alone. Returns the number of messages folded.
+pub(crate) fn fold_haves<A>(messages: &mut [(Option<PeerWireProtocolMessage>, A)]) -> usize {
+    let (first, rest) = match messages.split_first_mut() {
+        Some(split) => split,
+        None => return 0,
+    };
+    let bitfield = match first.0 {
+        Some(PeerWireProtocolMessage::BitField(ref mut bitfield)) => bitfield,
+        _ => return 0,
+    };
+    let num_bits = bitfield.bitfield().len() * 8;
+
+    let mut folded = 0;
+    for entry in rest.iter_mut() {
+        let piece = match entry.0 {
+            Some(PeerWireProtocolMessage::Have(ref have))
+                if (have.piece_index() as usize) < num_bits =>
+            {
+                have.piece_index() as usize
+            }
+            _ => continue,
+        };
+
+        bitfield.set_piece(piece);
+        entry.0 = None;
+        folded += 1;
+    }
+
+    folded
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{self, IoSlice, Write};
+
+    use bytes::Bytes;
+
+    use super::{fold_haves, MessageBatch};
+    use crate::peer::message::{
+        BitFieldMessage, HaveMessage, PeerWireProtocolMessage, PieceMessage,
+    };
+    use crate::peer::{MessageCodec, PeerWireMessageCodec};
+
+    /// Writer counting its write calls, accepting at most `max_write` bytes per call.
+    struct CountingWriter {
+        bytes: Vec<u8>,
+        writes: usize,
+        max_write: usize,
+    }
+
+    impl CountingWriter {
+        fn new(max_write: usize) -> CountingWriter {
+            CountingWriter {
+                bytes: Vec::new(),
+                writes: 0,
+                max_write: max_write,
+            }
+        }
+    }
+
+    impl Write for CountingWriter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.write_vectored(&[IoSlice::new(buf)])
+        }
+
+        fn write_vectored(&mut self, bufs: &[IoSlice]) -> io::Result<usize> {
+            self.writes += 1;
+
+            let mut written = 0;
+            for buf in bufs {
+                let len = buf.len().min(self.max_write - written);
+                self.bytes.extend_from_slice(&buf[..len]);
+                written += len;
+            }
+
+            Ok(written)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn haves(count: u32) -> Vec<PeerWireProtocolMessage> {
+        (0..count)
+            .map(|piece| PeerWireProtocolMessage::Have(HaveMessage::new(piece)))
+            .collect()
+    }
+
+    #[test]
+    fn positive_thousand_haves_single_write() {
+        let mut codec = PeerWireMessageCodec::new();
+        let messages = haves(1000);
+
+        let mut unbatched = CountingWriter::new(usize::max_value());
+        for message in messages.iter() {
+            codec.write_bytes(message, &mut unbatched).unwrap();
+        }
+
+        let mut batch = MessageBatch::new();
+        for message in messages.iter() {
+            batch.push(&mut codec, message).unwrap();
+        }
+        assert_eq!(1000, batch.messages());
+        assert_eq!(9 * 1000, batch.len());
+
+        let mut batched = CountingWriter::new(usize::max_value());
+        assert_eq!(1, batch.flush(&mut batched).unwrap());
+
+        assert!(unbatched.writes >= 1000);
+        assert_eq!(1, batched.writes);
+        assert_eq!(unbatched.bytes, batched.bytes);
+        assert!(batch.is_empty());
+    }
+
+    #[test]
+    fn positive_partial_writes_keep_order() {
+        let mut codec = PeerWireMessageCodec::new();
+        let messages = vec![
+            PeerWireProtocolMessage::Interested,
+            PeerWireProtocolMessage::Piece(PieceMessage::new(1, 0, Bytes::from(vec![7u8; 100]))),
+            PeerWireProtocolMessage::Have(HaveMessage::new(1)),
+            PeerWireProtocolMessage::Piece(PieceMessage::new(2, 0, Bytes::new())),
+            PeerWireProtocolMessage::Piece(PieceMessage::new(3, 0, Bytes::from(vec![8u8; 50]))),
+        ];
+
+        let mut expected = Vec::new();
+        let mut batch = MessageBatch::new();
+        for message in messages.iter() {
+            message.write_bytes(&mut expected, &None).unwrap();
+            batch.push(&mut codec, message).unwrap();
+        }
+
+        let mut writer = CountingWriter::new(7);
+        let writes = batch.flush(&mut writer).unwrap();
+
+        assert_eq!(expected, writer.bytes);
+        assert_eq!((expected.len() + 6) / 7, writes);
+    }
+
+    #[test]
+    fn positive_fold_haves_into_leading_bitfield() {
+        let mut messages = vec![
+            (
+                Some(PeerWireProtocolMessage::BitField(
+                    BitFieldMessage::with_capacity(10),
+                )),
+                0,
+            ),
+            (Some(PeerWireProtocolMessage::Have(HaveMessage::new(3))), 1),
+            (Some(PeerWireProtocolMessage::Interested), 2),
+            (Some(PeerWireProtocolMessage::Have(HaveMessage::new(9))), 3),
+            // Past the end of the bitfield
+            (Some(PeerWireProtocolMessage::Have(HaveMessage::new(16))), 4),
+        ];
+
+        assert_eq!(2, fold_haves(&mut messages));
+
+        let expected = BitFieldMessage::from_pieces(10, vec![3, 9].into_iter());
+        assert_eq!(
+            Some(PeerWireProtocolMessage::BitField(expected)),
+            messages[0].0
+        );
+        assert_eq!(None, messages[1].0);
+        assert_eq!(Some(PeerWireProtocolMessage::Interested), messages[2].0);
+        assert_eq!(None, messages[3].0);
+        assert_eq!(
+            Some(PeerWireProtocolMessage::Have(HaveMessage::new(16))),
+            messages[4].0
+        );
+    }
+
+    #[test]
+    fn negative_fold_haves_without_leading_bitfield() {
+        let mut messages = vec![
+            (Some(PeerWireProtocolMessage::Interested), ()),
+            (
+                Some(PeerWireProtocolMessage::BitField(
+                    BitFieldMessage::with_capacity(10),
+                )),
+                (),
+            ),
+            (Some(PeerWireProtocolMessage::Have(HaveMessage::new(3))), ()),
+        ];
+
+        assert_eq!(0, fold_haves(&mut messages));
+        assert!(messages.iter().all(|entry| entry.0.is_some()));
+    }
+}
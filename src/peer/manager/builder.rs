@@ -1,21 +1,40 @@
 use std::time::Duration;
 
 use super::{ManagedMessage, PeerManager};
+use crate::peer::message::{MessageLimits, MessageValidator};
 
 const DEFAULT_PEER_CAPACITY: usize = 1000;
 const DEFAULT_SINK_BUFFER_CAPACITY: usize = 100;
 const DEFAULT_STREAM_BUFFER_CAPACITY: usize = 100;
-const DEFAULT_HEARTBEAT_INTERVAL_MILLIS: u64 = 1 * 60 * 1000;
-const DEFAULT_HEARTBEAT_TIMEOUT_MILLIS: u64 = 2 * 60 * 1000;
+const DEFAULT_KEEP_ALIVE_INTERVAL_MILLIS: u64 = 1 * 60 * 1000;
+const DEFAULT_PEER_TIMEOUT_MILLIS: u64 = 2 * 60 * 1000;
+const DEFAULT_QUEUE_BYTE_BUDGET: usize = 1024 * 1024;
+const DEFAULT_SHUTDOWN_TIMEOUT_MILLIS: u64 = 5 * 1000;
+
+/// Action taken when a message fails validation.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ValidationPolicy {
+    /// Drop the message, but keep the peer connected.
+    DropMessage,
+    /// Disconnect the peer, reporting `PeerDisconnectReason::ProtocolViolation`.
+    DisconnectPeer,
+}
 
 /// Builder for configuring a `PeerManager`.
 #[derive(Copy, Clone)]
 pub struct PeerManagerBuilder {
     peer: usize,
+    torrent_peer: usize,
     sink_buffer: usize,
     stream_buffer: usize,
-    heartbeat_interval: Duration,
-    heartbeat_timeout: Duration,
+    keep_alive_interval: Duration,
+    peer_timeout: Duration,
+    queue_byte_budget: usize,
+    message_limits: MessageLimits,
+    message_validator: Option<MessageValidator>,
+    validation_policy: ValidationPolicy,
+    dht_port: Option<u16>,
+    shutdown_timeout: Duration,
 }
 
 impl PeerManagerBuilder {
@@ -23,19 +42,36 @@ impl PeerManagerBuilder {
     pub fn new() -> PeerManagerBuilder {
         PeerManagerBuilder {
             peer: DEFAULT_PEER_CAPACITY,
+            torrent_peer: usize::max_value(),
             sink_buffer: DEFAULT_SINK_BUFFER_CAPACITY,
             stream_buffer: DEFAULT_STREAM_BUFFER_CAPACITY,
-            heartbeat_interval: Duration::from_millis(DEFAULT_HEARTBEAT_INTERVAL_MILLIS),
-            heartbeat_timeout: Duration::from_millis(DEFAULT_HEARTBEAT_TIMEOUT_MILLIS),
+            keep_alive_interval: Duration::from_millis(DEFAULT_KEEP_ALIVE_INTERVAL_MILLIS),
+            peer_timeout: Duration::from_millis(DEFAULT_PEER_TIMEOUT_MILLIS),
+            queue_byte_budget: DEFAULT_QUEUE_BYTE_BUDGET,
+            message_limits: MessageLimits::default(),
+            message_validator: None,
+            validation_policy: ValidationPolicy::DisconnectPeer,
+            dht_port: None,
+            shutdown_timeout: Duration::from_millis(DEFAULT_SHUTDOWN_TIMEOUT_MILLIS),
         }
     }
 
     /// Max number of peers we can manage.
+    ///
+    /// It can be changed after building through `PeerManager::capacity`.
     pub fn with_peer_capacity(mut self, capacity: usize) -> PeerManagerBuilder {
         self.peer = capacity;
         self
     }
 
+    /// Max number of peers we can manage for any one torrent.
+    ///
+    /// Defaults to no limit besides the peer capacity.
+    pub fn with_torrent_peer_capacity(mut self, capacity: usize) -> PeerManagerBuilder {
+        self.torrent_peer = capacity;
+        self
+    }
+
     /// Capacity of pending sent messages.
     pub fn with_sink_buffer_capacity(mut self, capacity: usize) -> PeerManagerBuilder {
         self.sink_buffer = capacity;
@@ -49,14 +85,78 @@ impl PeerManagerBuilder {
     }
 
     /// Interval at which we send keep-alive messages.
-    pub fn with_heartbeat_interval(mut self, interval: Duration) -> PeerManagerBuilder {
-        self.heartbeat_interval = interval;
+    ///
+    /// Keep-alives are only sent if we have not sent any other message within the interval.
+    pub fn with_keep_alive_interval(mut self, interval: Duration) -> PeerManagerBuilder {
+        self.keep_alive_interval = interval;
+        self
+    }
+
+    /// Timeout at which we disconnect from a peer that has not sent us any message.
+    ///
+    /// Any message received from the peer, including a keep-alive, resets the timeout.
+    pub fn with_peer_timeout(mut self, timeout: Duration) -> PeerManagerBuilder {
+        self.peer_timeout = timeout;
+        self
+    }
+
+    /// Number of bytes of piece messages that can be queued for a single peer.
+    ///
+    /// Sending a piece to a peer with a full queue blocks (or fails, with `try_send`) until
+    /// the peer reads enough, other messages are never held back by the budget, and are
+    /// sent ahead of any queued pieces.
+    pub fn with_queue_byte_budget(mut self, bytes: usize) -> PeerManagerBuilder {
+        self.queue_byte_budget = bytes;
         self
     }
 
+    /// Interval at which we send keep-alive messages.
+    #[deprecated(note = "use `with_keep_alive_interval`")]
+    pub fn with_heartbeat_interval(self, interval: Duration) -> PeerManagerBuilder {
+        self.with_keep_alive_interval(interval)
+    }
+
     /// Timeout at which we disconnect from the peer without seeing a keep-alive message.
-    pub fn with_heartbeat_timeout(mut self, timeout: Duration) -> PeerManagerBuilder {
-        self.heartbeat_timeout = timeout;
+    #[deprecated(note = "use `with_peer_timeout`")]
+    pub fn with_heartbeat_timeout(self, timeout: Duration) -> PeerManagerBuilder {
+        self.with_peer_timeout(timeout)
+    }
+
+    /// Limits that messages received from peers are checked against.
+    pub fn with_message_limits(mut self, limits: MessageLimits) -> PeerManagerBuilder {
+        self.message_limits = limits;
+        self
+    }
+
+    /// Validator that messages received from peers are checked against.
+    ///
+    /// Since the validator describes a single torrent, this should only be set if
+    /// all peers being managed are for the same torrent.
+    pub fn with_message_validator(mut self, validator: MessageValidator) -> PeerManagerBuilder {
+        self.message_validator = Some(validator);
+        self
+    }
+
+    /// Action to take when a message fails validation.
+    pub fn with_validation_policy(mut self, policy: ValidationPolicy) -> PeerManagerBuilder {
+        self.validation_policy = policy;
+        self
+    }
+
+    /// Port our DHT is listening on, sent to peers in a `PortMessage`.
+    ///
+    /// The port is sent right after the first message we send a peer, which should be the
+    /// bitfield, if both sides set the DHT bit in the handshake.
+    pub fn with_dht_port(mut self, port: u16) -> PeerManagerBuilder {
+        self.dht_port = Some(port);
+        self
+    }
+
+    /// Time that `PeerManagerSink::shutdown` waits for peers to be sent what was queued for them.
+    ///
+    /// Whatever is still queued for a peer after the timeout is dropped.
+    pub fn with_shutdown_timeout(mut self, timeout: Duration) -> PeerManagerBuilder {
+        self.shutdown_timeout = timeout;
         self
     }
 
@@ -65,6 +165,11 @@ impl PeerManagerBuilder {
         self.peer
     }
 
+    /// Retrieve the peer capacity for any one torrent.
+    pub fn torrent_peer_capacity(&self) -> usize {
+        self.torrent_peer
+    }
+
     /// Retrieve the sink buffer capacity.
     pub fn sink_buffer_capacity(&self) -> usize {
         self.sink_buffer
@@ -75,14 +180,56 @@ impl PeerManagerBuilder {
         self.stream_buffer
     }
 
+    /// Retrieve the keep-alive interval `Duration`.
+    pub fn keep_alive_interval(&self) -> Duration {
+        self.keep_alive_interval
+    }
+
+    /// Retrieve the peer timeout `Duration`.
+    pub fn peer_timeout(&self) -> Duration {
+        self.peer_timeout
+    }
+
+    /// Retrieve the queue byte budget.
+    pub fn queue_byte_budget(&self) -> usize {
+        self.queue_byte_budget
+    }
+
     /// Retrieve the hearbeat interval `Duration`.
+    #[deprecated(note = "use `keep_alive_interval`")]
     pub fn heartbeat_interval(&self) -> Duration {
-        self.heartbeat_interval
+        self.keep_alive_interval
     }
 
     /// Retrieve the heartbeat timeout `Duration`.
+    #[deprecated(note = "use `peer_timeout`")]
     pub fn heartbeat_timeout(&self) -> Duration {
-        self.heartbeat_timeout
+        self.peer_timeout
+    }
+
+    /// Retrieve the `MessageLimits`.
+    pub fn message_limits(&self) -> MessageLimits {
+        self.message_limits
+    }
+
+    /// Retrieve the `MessageValidator`, if any.
+    pub fn message_validator(&self) -> Option<MessageValidator> {
+        self.message_validator
+    }
+
+    /// Retrieve the `ValidationPolicy`.
+    pub fn validation_policy(&self) -> ValidationPolicy {
+        self.validation_policy
+    }
+
+    /// Retrieve the DHT port, if any.
+    pub fn dht_port(&self) -> Option<u16> {
+        self.dht_port
+    }
+
+    /// Retrieve the shutdown timeout `Duration`.
+    pub fn shutdown_timeout(&self) -> Duration {
+        self.shutdown_timeout
     }
 
     /// Build a `PeerManager` from the current `PeerManagerBuilder`.
@@ -0,0 +1,189 @@
+use std::collections::HashMap;
+
+use crate::handshake::{ExtensionBits, Extensions};
+use crate::peer::message::{ExtendedMessage, ExtendedType};
+
+/// Capabilities negotiated with a peer through the handshake and extended handshakes.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PeerCapabilities {
+    bits: ExtensionBits,
+    extended_ids: HashMap<ExtendedType, u8>,
+    max_requests: Option<u32>,
+    metadata_size: Option<i64>,
+    client: Option<String>,
+}
+
+impl PeerCapabilities {
+    /// Create new `PeerCapabilities` from the extensions exchanged during the handshake.
+    pub fn new(extensions: Extensions) -> PeerCapabilities {
+        PeerCapabilities {
+            bits: extensions.into(),
+            extended_ids: HashMap::new(),
+            max_requests: None,
+            metadata_size: None,
+            client: None,
+        }
+    }
+
+    /// Update the capabilities from an extended handshake sent by the peer.
+    ///
+    /// Extended handshakes after the first one only have to contain what changed,
+    /// so fields that are not sent keep their value and an id of 0 disables an extension.
+    pub fn update(&mut self, extended: &ExtendedMessage) {
+        for (ext_type, &id) in extended.id_map() {
+            if id == 0 {
+                self.extended_ids.remove(ext_type);
+            } else {
+                self.extended_ids.insert(ext_type.clone(), id);
+            }
+        }
+
+        self.max_requests = extended.request_queue_size().or(self.max_requests);
+        self.metadata_size = extended.metadata_size().or(self.metadata_size);
+        if let Some(client) = extended.client_version() {
+            self.client = Some(client.to_string());
+        }
+    }
+
+    /// Reserved bits the handshake was completed with.
+    pub fn extension_bits(&self) -> ExtensionBits {
+        self.bits
+    }
+
+    /// Whether or not the peer supports the DHT (BEP 5).
+    pub fn supports_dht(&self) -> bool {
+        self.bits.contains(&ExtensionBits::dht())
+    }
+
+    /// Whether or not the peer supports the fast extension (BEP 6).
+    pub fn supports_fast(&self) -> bool {
+        self.bits.contains(&ExtensionBits::fast())
+    }
+
+    /// Whether or not the peer supports the extension protocol (BEP 10).
+    pub fn supports_extension_protocol(&self) -> bool {
+        self.bits.contains(&ExtensionBits::extension_protocol())
+    }
+
+    /// Id the peer expects for the given extension, if it supports it.
+    ///
+    /// `ProtExtension` messages for an extension without an id should not be sent.
+    pub fn extended_id_for(&self, ext_type: &ExtendedType) -> Option<u8> {
+        self.extended_ids.get(ext_type).map(|id| *id)
+    }
+
+    /// Extensions the peer supports, along with their ids.
+    pub fn extended_ids(&self) -> &HashMap<ExtendedType, u8> {
+        &self.extended_ids
+    }
+
+    /// Maximum number of outstanding requests (`reqq`) the peer allows.
+    pub fn max_requests(&self) -> Option<u32> {
+        self.max_requests
+    }
+
+    /// Size of the info dictionary the peer advertised.
+    pub fn metadata_size(&self) -> Option<i64> {
+        self.metadata_size
+    }
+
+    /// Client name and version the peer advertised.
+    pub fn client(&self) -> Option<&str> {
+        self.client.as_ref().map(|client| &**client)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PeerCapabilities;
+    use crate::handshake::{ExtensionBits, Extensions};
+    use crate::peer::message::{ExtendedMessageBuilder, ExtendedType};
+
+    fn capabilities() -> PeerCapabilities {
+        let bits = ExtensionBits::extension_protocol().union(&ExtensionBits::fast());
+
+        PeerCapabilities::new(Extensions::from(bits))
+    }
+
+    #[test]
+    fn positive_handshake_bits() {
+        let capabilities = capabilities();
+
+        assert!(capabilities.supports_extension_protocol());
+        assert!(capabilities.supports_fast());
+        assert!(!capabilities.supports_dht());
+        assert_eq!(None, capabilities.extended_id_for(&ExtendedType::UtPex));
+    }
+
+    #[test]
+    fn positive_first_extended_handshake() {
+        let mut capabilities = capabilities();
+
+        capabilities.update(
+            &ExtendedMessageBuilder::new()
+                .with_extended_type(ExtendedType::UtMetadata, Some(1))
+                .with_extended_type(ExtendedType::UtPex, Some(2))
+                .with_our_id(Some("Transmission 4.0.5".to_string()))
+                .with_max_requests(Some(250))
+                .with_metadata_size(Some(31235))
+                .build(),
+        );
+
+        assert_eq!(
+            Some(1),
+            capabilities.extended_id_for(&ExtendedType::UtMetadata)
+        );
+        assert_eq!(Some(2), capabilities.extended_id_for(&ExtendedType::UtPex));
+        assert_eq!(Some("Transmission 4.0.5"), capabilities.client());
+        assert_eq!(Some(250), capabilities.max_requests());
+        assert_eq!(Some(31235), capabilities.metadata_size());
+    }
+
+    #[test]
+    fn positive_rehandshake_removes_extension() {
+        let mut capabilities = capabilities();
+        capabilities.update(
+            &ExtendedMessageBuilder::new()
+                .with_extended_type(ExtendedType::UtMetadata, Some(1))
+                .with_extended_type(ExtendedType::UtPex, Some(2))
+                .with_max_requests(Some(250))
+                .build(),
+        );
+
+        capabilities.update(
+            &ExtendedMessageBuilder::new()
+                .with_extended_type(ExtendedType::UtPex, Some(0))
+                .build(),
+        );
+
+        assert_eq!(None, capabilities.extended_id_for(&ExtendedType::UtPex));
+        assert_eq!(
+            Some(1),
+            capabilities.extended_id_for(&ExtendedType::UtMetadata)
+        );
+        assert_eq!(Some(250), capabilities.max_requests());
+    }
+
+    #[test]
+    fn positive_rehandshake_moves_extension() {
+        let mut capabilities = capabilities();
+        capabilities.update(
+            &ExtendedMessageBuilder::new()
+                .with_extended_type(ExtendedType::UtPex, Some(2))
+                .build(),
+        );
+
+        capabilities.update(
+            &ExtendedMessageBuilder::new()
+                .with_extended_type(ExtendedType::UtPex, Some(5))
+                .with_extended_type(ExtendedType::LtDontHave, Some(6))
+                .build(),
+        );
+
+        assert_eq!(Some(5), capabilities.extended_id_for(&ExtendedType::UtPex));
+        assert_eq!(
+            Some(6),
+            capabilities.extended_id_for(&ExtendedType::LtDontHave)
+        );
+    }
+}
@@ -0,0 +1,136 @@
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use crate::peer::PeerInfo;
+
+/// Limits on the peers of a `PeerManager`, shared by all of its halves.
+///
+/// Peers added beyond either limit are turned away with `PeerDisconnectReason::TooManyPeers`,
+/// lowering a limit leaves the peers that were already added connected. Peers being shed
+/// no longer count against either limit.
+#[derive(Clone)]
+pub struct PeerCapacity {
+    global: Arc<AtomicUsize>,
+    torrent: Arc<AtomicUsize>,
+    shed: Arc<Mutex<HashSet<PeerInfo>>>,
+}
+
+impl PeerCapacity {
+    pub(crate) fn new(global: usize, torrent: usize) -> PeerCapacity {
+        PeerCapacity {
+            global: Arc::new(AtomicUsize::new(global)),
+            torrent: Arc::new(AtomicUsize::new(torrent)),
+            shed: Arc::new(Mutex::new(HashSet::new())),
+        }
+    }
+
+    /// Set the max number of peers across every torrent.
+    pub fn set_peer_capacity(&self, capacity: usize) {
+        self.global.store(capacity, Ordering::SeqCst);
+    }
+
+    /// Retrieve the max number of peers across every torrent.
+    pub fn peer_capacity(&self) -> usize {
+        self.global.load(Ordering::SeqCst)
+    }
+
+    /// Set the max number of peers for any one torrent.
+    pub fn set_torrent_peer_capacity(&self, capacity: usize) {
+        self.torrent.store(capacity, Ordering::SeqCst);
+    }
+
+    /// Retrieve the max number of peers for any one torrent.
+    pub fn torrent_peer_capacity(&self) -> usize {
+        self.torrent.load(Ordering::SeqCst)
+    }
+
+    /// Whether or not the peer fits next to the peers that were already added.
+    pub(crate) fn has_room<'a, I>(&self, info: &PeerInfo, peers: I) -> bool
+    where
+        I: Iterator<Item = &'a PeerInfo>,
+    {
+        let shed = self.lock_shed();
+        let (total, torrent) = peers
+            .filter(|other| !shed.contains(other))
+            .fold((0, 0), |(total, torrent), other| {
+                (total + 1, torrent + (other.hash() == info.hash()) as usize)
+            });
+
+        total < self.peer_capacity() && torrent < self.torrent_peer_capacity()
+    }
+
+    /// Stop counting the peer while it is being shed, false if it already was.
+    pub(crate) fn start_shedding(&self, info: PeerInfo) -> bool {
+        self.lock_shed().insert(info)
+    }
+
+    /// Whether or not the peer that is gone was being shed.
+    pub(crate) fn finish_shedding(&self, info: &PeerInfo) -> bool {
+        self.lock_shed().remove(info)
+    }
+
+    fn lock_shed(&self) -> MutexGuard<'_, HashSet<PeerInfo>> {
+        self.shed
+            .lock()
+            .expect("bittorrent-protocol_peer: Poisoned Lock In PeerCapacity")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PeerCapacity;
+    use crate::handshake::Extensions;
+    use crate::peer::PeerInfo;
+
+    fn peer_info(port: u16, hash: u8) -> PeerInfo {
+        PeerInfo::new(
+            ([127, 0, 0, 1], port).into(),
+            [port as u8; 20].into(),
+            [hash; 20].into(),
+            Extensions::new(),
+        )
+    }
+
+    #[test]
+    fn positive_room_within_both_capacities() {
+        let capacity = PeerCapacity::new(3, 2);
+        let peers = vec![peer_info(1, 0), peer_info(2, 1)];
+
+        assert!(capacity.has_room(&peer_info(3, 0), peers.iter()));
+    }
+
+    #[test]
+    fn negative_no_room_at_torrent_capacity() {
+        let capacity = PeerCapacity::new(3, 2);
+        let peers = vec![peer_info(1, 0), peer_info(2, 0)];
+
+        assert!(!capacity.has_room(&peer_info(3, 0), peers.iter()));
+        assert!(capacity.has_room(&peer_info(3, 1), peers.iter()));
+    }
+
+    #[test]
+    fn negative_no_room_at_peer_capacity() {
+        let capacity = PeerCapacity::new(2, 2);
+        let peers = vec![peer_info(1, 0), peer_info(2, 1)];
+
+        assert!(!capacity.has_room(&peer_info(3, 2), peers.iter()));
+
+        capacity.set_peer_capacity(3);
+        assert!(capacity.has_room(&peer_info(3, 2), peers.iter()));
+    }
+
+    #[test]
+    fn positive_room_left_by_shed_peer() {
+        let capacity = PeerCapacity::new(2, 2);
+        let peers = vec![peer_info(1, 0), peer_info(2, 0)];
+
+        assert!(capacity.start_shedding(peer_info(1, 0)));
+        assert!(!capacity.start_shedding(peer_info(1, 0)));
+        assert!(capacity.has_room(&peer_info(3, 0), peers.iter()));
+
+        assert!(capacity.finish_shedding(&peer_info(1, 0)));
+        assert!(!capacity.finish_shedding(&peer_info(1, 0)));
+        assert!(!capacity.has_room(&peer_info(3, 0), peers.iter()));
+    }
+}
@@ -12,6 +12,12 @@ error_chain! {
             description("Peer Was Not Found")
             display("Peer Was Not Found With PeerInfo {:?}", info)
         }
+        QueueFull {
+            info: PeerInfo
+         } {
+            description("Peer Outbound Queue Is Full")
+            display("Peer Outbound Queue Is Full For PeerInfo {:?}", info)
+        }
 
     }
 }
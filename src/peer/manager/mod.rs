@@ -1,9 +1,16 @@
 use std::collections::hash_map::Entry;
 use std::collections::HashMap;
-use std::io;
 use std::io::{Read, Write};
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::mpsc::{self, Receiver, Sender};
 use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Instant;
+
+use futures::channel::oneshot;
+
+pub mod batch;
 
 pub mod builder;
 use builder::PeerManagerBuilder;
@@ -13,11 +20,29 @@ use peer_info::PeerInfo;
 
 pub mod error;
 
-use crate::peer::messages::PeerWireProtocolMessage;
+pub mod capabilities;
+use capabilities::PeerCapabilities;
+
+pub mod capacity;
+use capacity::PeerCapacity;
+
+use crate::peer::error::{PeerManagerErrorKind, PeerManagerResult};
+use crate::handshake::Extension;
+use crate::peer::messages::{BitsExtensionMessage, PeerWireProtocolMessage};
 use std::net::TcpStream;
 
+pub mod stats;
+use stats::PeerStats;
+
+mod queue;
+use queue::QueueSender;
+
+pub mod rate_limit;
+use rate_limit::RateLimiter;
+
 mod task_one_thread;
 mod task_split;
+mod timer;
 
 mod try_clone;
 pub use try_clone::TryClone;
@@ -25,7 +50,10 @@ pub use try_clone::TryClone;
 // We configure our tick duration based on this, could let users configure this in the future...
 const DEFAULT_TIMER_SLOTS: usize = 2048;
 
-/// Manages a set of peers with heartbeating heartbeating.
+type PeerStatsMap = HashMap<PeerInfo, Arc<Mutex<PeerStats>>>;
+type PeerCapabilitiesMap = HashMap<PeerInfo, Arc<Mutex<PeerCapabilities>>>;
+
+/// Manages a set of peers with heartbeating.
 pub struct PeerManager<S> {
     sink: PeerManagerSink<S>,
     stream: PeerManagerStream<S>,
@@ -36,9 +64,23 @@ impl<S> PeerManager<S> {
     pub fn from_builder(builder: PeerManagerBuilder) -> PeerManager<S> {
         let (res_send, res_recv) = mpsc::channel();
         let peers = Arc::new(Mutex::new(HashMap::new()));
-
-        let sink = PeerManagerSink::new(builder, res_send, peers.clone());
-        let stream = PeerManagerStream::new(res_recv, peers);
+        let stats = Arc::new(Mutex::new(HashMap::new()));
+        let capabilities = Arc::new(Mutex::new(HashMap::new()));
+        let limiter = RateLimiter::new();
+        let capacity =
+            PeerCapacity::new(builder.peer_capacity(), builder.torrent_peer_capacity());
+
+        let sink = PeerManagerSink::new(
+            builder,
+            res_send,
+            peers.clone(),
+            stats.clone(),
+            capabilities.clone(),
+            limiter.clone(),
+            capacity.clone(),
+        );
+        let stream =
+            PeerManagerStream::new(res_recv, peers, stats, capabilities, limiter, capacity);
 
         PeerManager {
             sink: sink,
@@ -52,6 +94,36 @@ impl<S> PeerManager<S> {
     pub fn into_parts(self) -> (PeerManagerSink<S>, PeerManagerStream<S>) {
         (self.sink, self.stream)
     }
+
+    /// Retrieve a snapshot of the statistics for the given peer.
+    pub fn peer_stats(&self, info: &PeerInfo) -> Option<PeerStats> {
+        self.sink.peer_stats(info)
+    }
+
+    /// Retrieve a snapshot of the statistics for all peers.
+    pub fn all_peer_stats(&self) -> HashMap<PeerInfo, PeerStats> {
+        self.sink.all_peer_stats()
+    }
+
+    /// Retrieve a snapshot of the capabilities negotiated with the given peer.
+    pub fn capabilities(&self, info: &PeerInfo) -> Option<PeerCapabilities> {
+        self.sink.capabilities(info)
+    }
+
+    /// Record whether or not the given peer is snubbing us, for `PeerStats::is_snubbed`.
+    pub fn set_snubbed(&self, info: &PeerInfo, snubbed: bool) {
+        self.sink.set_snubbed(info, snubbed)
+    }
+
+    /// Retrieve the `RateLimiter` throttling piece payload for all peers.
+    pub fn rate_limiter(&self) -> RateLimiter {
+        self.sink.rate_limiter()
+    }
+
+    /// Retrieve the `PeerCapacity` that peers being added are checked against.
+    pub fn capacity(&self) -> PeerCapacity {
+        self.sink.capacity()
+    }
 }
 
 impl<S> PeerManager<S>
@@ -62,6 +134,14 @@ impl<S> PeerManager<S>
         self.sink.send(item)
     }
 
+    pub fn try_send(&mut self, item: IPeerManagerMessage<S>) -> PeerManagerResult<()> {
+        self.sink.try_send(item)
+    }
+
+    /// Close every peer, see `PeerManagerSink::shutdown`.
+    pub async fn shutdown(&self) {
+        self.sink.shutdown().await
+    }
 }
 
 impl<S> PeerManager<S> {
@@ -69,6 +149,11 @@ impl<S> PeerManager<S> {
     pub fn poll(&mut self) -> Option<OPeerManagerMessage>{
         self.stream.poll()
     }
+
+    /// Poll the next message as a `PeerManagerEvent`.
+    pub fn poll_event(&mut self) -> Option<PeerManagerEvent> {
+        self.stream.poll_event()
+    }
 }
 
 //----------------------------------------------------------------------------//
@@ -77,7 +162,12 @@ impl<S> PeerManager<S> {
 pub struct PeerManagerSink<S> {
     build: PeerManagerBuilder,
     send: Sender<OPeerManagerMessage>,
-    peers: Arc<Mutex<HashMap<PeerInfo, Sender<IPeerManagerMessage<S>>>>>,
+    peers: Arc<Mutex<HashMap<PeerInfo, QueueSender<IPeerManagerMessage<S>>>>>,
+    stats: Arc<Mutex<PeerStatsMap>>,
+    capabilities: Arc<Mutex<PeerCapabilitiesMap>>,
+    limiter: RateLimiter,
+    capacity: PeerCapacity,
+    shut_down: Arc<AtomicBool>,
 }
 
 impl<S> Clone for PeerManagerSink<S> {
@@ -86,6 +176,11 @@ impl<S> Clone for PeerManagerSink<S> {
             build: self.build,
             send: self.send.clone(),
             peers: self.peers.clone(),
+            stats: self.stats.clone(),
+            capabilities: self.capabilities.clone(),
+            limiter: self.limiter.clone(),
+            capacity: self.capacity.clone(),
+            shut_down: self.shut_down.clone(),
         }
     }
 }
@@ -94,28 +189,91 @@ impl<S> PeerManagerSink<S> {
     fn new(
         build: PeerManagerBuilder,
         send: Sender<OPeerManagerMessage>,
-        peers: Arc<Mutex<HashMap<PeerInfo, Sender<IPeerManagerMessage<S>>>>>,
+        peers: Arc<Mutex<HashMap<PeerInfo, QueueSender<IPeerManagerMessage<S>>>>>,
+        stats: Arc<Mutex<PeerStatsMap>>,
+        capabilities: Arc<Mutex<PeerCapabilitiesMap>>,
+        limiter: RateLimiter,
+        capacity: PeerCapacity,
     ) -> PeerManagerSink<S> {
         PeerManagerSink {
             build: build,
             send: send,
             peers: peers,
+            stats: stats,
+            capabilities: capabilities,
+            limiter: limiter,
+            capacity: capacity,
+            shut_down: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// Retrieve a snapshot of the statistics for the given peer.
+    pub fn peer_stats(&self, info: &PeerInfo) -> Option<PeerStats> {
+        self.stats
+            .lock()
+            .unwrap()
+            .get(info)
+            .map(|stats| stats.lock().unwrap().clone())
+    }
+
+    /// Retrieve a snapshot of the statistics for all peers.
+    pub fn all_peer_stats(&self) -> HashMap<PeerInfo, PeerStats> {
+        self.stats
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(info, stats)| (*info, stats.lock().unwrap().clone()))
+            .collect()
+    }
+
+    /// Retrieve a snapshot of the capabilities negotiated with the given peer.
+    ///
+    /// Updated whenever the peer sends an extended handshake.
+    pub fn capabilities(&self, info: &PeerInfo) -> Option<PeerCapabilities> {
+        self.capabilities
+            .lock()
+            .unwrap()
+            .get(info)
+            .map(|capabilities| capabilities.lock().unwrap().clone())
+    }
+
+    /// Record whether or not the given peer is snubbing us, for `PeerStats::is_snubbed`.
+    ///
+    /// Snubbing is detected by `select::request::RequestQueue`, which knows what we requested.
+    pub fn set_snubbed(&self, info: &PeerInfo, snubbed: bool) {
+        if let Some(stats) = self.stats.lock().unwrap().get(info) {
+            stats.lock().unwrap().set_snubbed(snubbed);
         }
     }
 
+    /// Retrieve the `RateLimiter` throttling piece payload for all peers.
+    pub fn rate_limiter(&self) -> RateLimiter {
+        self.limiter.clone()
+    }
+
+    /// Retrieve the `PeerCapacity` that peers being added are checked against.
+    ///
+    /// Capacities are shared by every clone of the sink, and can be changed at any time.
+    pub fn capacity(&self) -> PeerCapacity {
+        self.capacity.clone()
+    }
+
     fn run_with_lock_sink<F, I>(&mut self, item: I, call: F)
     where
         F: FnOnce(
             I,
             &mut PeerManagerBuilder,
             &mut Sender<OPeerManagerMessage>,
-            &mut HashMap<PeerInfo, Sender<IPeerManagerMessage<S>>>,
+            &mut HashMap<PeerInfo, QueueSender<IPeerManagerMessage<S>>>,
         ),
     {
-        while let Ok(mut guard) = self.peers.try_lock() {
-            let _result = call(item, &mut self.build, &mut self.send, &mut *guard);
-            break;
-        }
+        // The stream only holds the lock briefly, waiting on it beats dropping the item
+        let mut guard = self
+            .peers
+            .lock()
+            .expect("bittorrent-protocol_peer: PeerManagerSink Failed To Lock Peers");
+
+        call(item, &mut self.build, &mut self.send, &mut *guard);
     }
 }
 
@@ -123,19 +281,53 @@ impl<S> PeerManagerSink<S>
     where S: Read + Write + TryClone + Send + 'static,
           <S as TryClone>::Item: Send{
 
+    /// Send the item, blocking while the queue of the peer has no room for a piece message.
     pub fn send(&mut self, item: IPeerManagerMessage<S>) {
         match item {
             IPeerManagerMessage::AddPeer(info, peer) => {
+                let stats_map = self.stats.clone();
+                let capabilities_map = self.capabilities.clone();
+                let limiter = self.limiter.clone();
+                let capacity = self.capacity.clone();
+                let shut_down = self.shut_down.load(Ordering::SeqCst);
+
                 self.run_with_lock_sink((info, peer), |(info, peer), builder, send, peers| {
-                    if peers.len() >= builder.peer_capacity() {
-                        panic!("bittorrent-protocol_peer: PeerManager Failed To Send AddPeer");
+                    if shut_down {
+                        let _ = send.send(OPeerManagerMessage::PeerDisconnected {
+                            info: info,
+                            reason: PeerDisconnectReason::ShutDown,
+                        });
+                    } else if !capacity.has_room(&info, peers.keys()) {
+                        // Dropping the peer closes the connection
+                        let _ = send.send(OPeerManagerMessage::PeerDisconnected {
+                            info: info,
+                            reason: PeerDisconnectReason::TooManyPeers,
+                        });
                     } else {
                         match peers.entry(info) {
                             Entry::Occupied(_) => panic!(
                                 "bittorrent-protocol_peer: PeerManager Failed To Send AddPeer"
                             ),
                             Entry::Vacant(vac) => {
-                                vac.insert(task_split::run_peer(peer, info, send.clone()));
+                                let stats = Arc::new(Mutex::new(PeerStats::new()));
+                                stats_map.lock().unwrap().insert(info, stats.clone());
+                                let capabilities = Arc::new(Mutex::new(PeerCapabilities::new(
+                                    *info.extensions(),
+                                )));
+                                capabilities_map
+                                    .lock()
+                                    .unwrap()
+                                    .insert(info, capabilities.clone());
+
+                                vac.insert(task_split::run_peer(
+                                    peer,
+                                    info,
+                                    *builder,
+                                    stats,
+                                    capabilities,
+                                    limiter,
+                                    send.clone(),
+                                ));
                             }
                         }
                     }
@@ -143,25 +335,137 @@ impl<S> PeerManagerSink<S>
             }
             IPeerManagerMessage::RemovePeer(info) => {
                 self.run_with_lock_sink(info, |info, _, _, peers| {
-                    peers
-                        .get_mut(&info)
-                        .unwrap()
-                        .send(IPeerManagerMessage::RemovePeer(info))
-                        .expect("bittorrent-protocol_peer: PeerManager Failed To Send RemovePeer");
+                    // Peers that disconnected on their own are already gone
+                    if let Some(queue) = peers.get_mut(&info) {
+                        if queue.push_control(IPeerManagerMessage::RemovePeer(info)).is_err() {
+                            panic!(
+                                "bittorrent-protocol_peer: PeerManager Failed To Send RemovePeer"
+                            );
+                        }
+                    }
                 })
             }
-            IPeerManagerMessage::SendMessage(info, mid, peer_message) => self.run_with_lock_sink(
-                (info, mid, peer_message),
-                |(info, mid, peer_message), _, _, peers| {
-                    peers
-                        .get_mut(&info)
-                        .unwrap()
-                        .send(IPeerManagerMessage::SendMessage(info, mid, peer_message))
-                        .expect("bittorrent-protocol_peer: PeerManager Failed to Send SendMessage");
-                },
-            ),
+            IPeerManagerMessage::ShedPeer(info) => {
+                let capacity = self.capacity.clone();
+
+                self.run_with_lock_sink(info, |info, _, _, peers| {
+                    // Stops counting against the capacity right away, so the room it leaves
+                    // can be taken before the peer is gone
+                    if let Some(queue) = peers.get(&info) {
+                        if capacity.start_shedding(info) {
+                            // Closed queues belong to peers that are on their way out already
+                            let _ = queue.push_control(IPeerManagerMessage::ShedPeer(info));
+                        }
+                    }
+                })
+            }
+            IPeerManagerMessage::DisconnectPeer(info, reason) => {
+                self.run_with_lock_sink(info, |info, _, _, peers| {
+                    // Peers that disconnected on their own are already gone
+                    if let Some(queue) = peers.get(&info) {
+                        let message = IPeerManagerMessage::DisconnectPeer(info, reason);
+                        let _ = queue.push_control(message);
+                    }
+                })
+            }
+            IPeerManagerMessage::SendMessage(info, mid, peer_message) => {
+                // Pieces wait for room in the queue, so do not hold up other peers while blocked
+                let queue = self
+                    .queue(&info)
+                    .expect("bittorrent-protocol_peer: PeerManager Failed to Send SendMessage");
+                let payload_len = payload_len(&peer_message);
+                let item = IPeerManagerMessage::SendMessage(info, mid, peer_message);
+
+                let result = match payload_len {
+                    Some(payload_len) => queue.push_payload(item, payload_len),
+                    None => queue.push_control(item),
+                };
+                if result.is_err() {
+                    panic!("bittorrent-protocol_peer: PeerManager Failed to Send SendMessage");
+                }
+            }
+        }
+    }
+
+    /// Send the item, failing with `QueueFull` instead of blocking if the peer is saturated.
+    ///
+    /// The piece message is dropped if the queue is full, it should be sent again later.
+    pub fn try_send(&mut self, item: IPeerManagerMessage<S>) -> PeerManagerResult<()> {
+        match item {
+            IPeerManagerMessage::SendMessage(info, mid, peer_message) => {
+                let queue = self
+                    .queue(&info)
+                    .ok_or_else(|| PeerManagerErrorKind::PeerNotFound { info: info })?;
+                let payload_len = payload_len(&peer_message);
+                let item = IPeerManagerMessage::SendMessage(info, mid, peer_message);
+
+                let result = match payload_len {
+                    Some(payload_len) => queue.try_push_payload(item, payload_len),
+                    None => queue.push_control(item),
+                };
+                result.map_err(|_| PeerManagerErrorKind::QueueFull { info: info }.into())
+            }
+            other => {
+                self.send(other);
+
+                Ok(())
+            }
         }
     }
+
+    /// Close every peer once what was queued for it was sent, waiting at most the shutdown
+    /// timeout of the `PeerManagerBuilder`.
+    ///
+    /// Peers are reported as `PeerDisconnectReason::Requested`, peers added from now on are
+    /// turned away with `PeerDisconnectReason::ShutDown`. Calls after the first return at once.
+    pub async fn shutdown(&self) {
+        if self.shut_down.swap(true, Ordering::SeqCst) {
+            return;
+        }
+
+        let queues: Vec<QueueSender<IPeerManagerMessage<S>>> = self
+            .peers
+            .lock()
+            .expect("bittorrent-protocol_peer: PeerManagerSink Failed To Lock Peers")
+            .values()
+            .cloned()
+            .collect();
+        for queue in queues.iter() {
+            queue.close();
+        }
+
+        // Writers of the peers block on their sockets, so wait for them on a thread of our own
+        let deadline = Instant::now() + self.build.shutdown_timeout();
+        let (send, recv) = oneshot::channel();
+        thread::spawn(move || {
+            for queue in queues.iter() {
+                if !queue.wait_receiver_gone(deadline) {
+                    queue.clear();
+                }
+            }
+
+            let _ = send.send(());
+        });
+
+        let _ = recv.await;
+    }
+
+    fn queue(&mut self, info: &PeerInfo) -> Option<QueueSender<IPeerManagerMessage<S>>> {
+        let mut opt_queue = None;
+        self.run_with_lock_sink(info, |info, _, _, peers| {
+            opt_queue = peers.get(info).cloned();
+        });
+
+        opt_queue
+    }
+}
+
+/// Size of the message counted against the queue byte budget, `None` for control messages.
+fn payload_len(message: &PeerWireProtocolMessage) -> Option<usize> {
+    match message {
+        &PeerWireProtocolMessage::Piece(_) => Some(message.message_size()),
+        _ => None,
+    }
 }
 
 //----------------------------------------------------------------------------//
@@ -169,19 +473,33 @@ impl<S> PeerManagerSink<S>
 /// Stream half of a `PeerManager`.
 pub struct PeerManagerStream<S> {
     recv: Receiver<OPeerManagerMessage>,
-    peers: Arc<Mutex<HashMap<PeerInfo, Sender<IPeerManagerMessage<S>>>>>,
+    peers: Arc<Mutex<HashMap<PeerInfo, QueueSender<IPeerManagerMessage<S>>>>>,
+    stats: Arc<Mutex<PeerStatsMap>>,
+    capabilities: Arc<Mutex<PeerCapabilitiesMap>>,
+    limiter: RateLimiter,
+    capacity: PeerCapacity,
     opt_pending: Option<OPeerManagerMessage>,
+    closed: bool,
 }
 
 impl<S> PeerManagerStream<S> {
     fn new(
         recv: Receiver<OPeerManagerMessage>,
-        peers: Arc<Mutex<HashMap<PeerInfo, Sender<IPeerManagerMessage<S>>>>>,
+        peers: Arc<Mutex<HashMap<PeerInfo, QueueSender<IPeerManagerMessage<S>>>>>,
+        stats: Arc<Mutex<PeerStatsMap>>,
+        capabilities: Arc<Mutex<PeerCapabilitiesMap>>,
+        limiter: RateLimiter,
+        capacity: PeerCapacity,
     ) -> PeerManagerStream<S> {
         PeerManagerStream {
             recv: recv,
             peers: peers,
+            stats: stats,
+            capabilities: capabilities,
+            limiter: limiter,
+            capacity: capacity,
             opt_pending: None,
+            closed: false,
         }
     }
 
@@ -194,7 +512,7 @@ impl<S> PeerManagerStream<S> {
     where
         F: FnOnce(
             I,
-            &mut HashMap<PeerInfo, Sender<IPeerManagerMessage<S>>>,
+            &mut HashMap<PeerInfo, QueueSender<IPeerManagerMessage<S>>>,
         ) -> Option<OPeerManagerMessage>,
         G: FnOnce(I) -> Option<OPeerManagerMessage>,
     {
@@ -213,50 +531,100 @@ impl<S> PeerManagerStream<S> {
 }
 
 impl<S> PeerManagerStream<S> {
+    /// Block until the next message arrives.
+    ///
+    /// Returns `None` for messages handled by the stream itself, and once the
+    /// `PeerManager` is closed, see `is_closed`.
     pub fn poll(&mut self) -> Option<OPeerManagerMessage> {
         // Intercept and propogate any messages indicating the peer shutdown so we can remove them from our peer map
-        let next_message = self
-            .opt_pending
-            .take()
-            .map(|pending| pending)
-            .unwrap_or_else(|| self.recv.recv().unwrap());
-
-        match next_message{
+        let next_message = match self.opt_pending.take() {
+            Some(pending) => pending,
+            None => match self.recv.recv() {
+                Ok(message) => message,
+                Err(_) => {
+                    self.closed = true;
+
+                    return None;
+                }
+            },
+        };
+
+        let capacity = self.capacity.clone();
+        let opt_message = match next_message{
                 OPeerManagerMessage::PeerRemoved(info) => self.run_with_lock_poll(
                     info,
                     |info, peers| {
-                        peers
-                            .remove(&info)
-                            .unwrap_or_else(|| panic!("bittorrent-protocol_peer: Received PeerRemoved Message With No Matching Peer In Map"));
+                        // Peer may have disconnected while it was being removed
+                        let message = OPeerManagerMessage::PeerRemoved(info);
 
-                       Some(OPeerManagerMessage::PeerRemoved(info))
+                        peers.remove(&info).map(|_| shed_or(&capacity, info, message))
                     },
                     |info| Some(OPeerManagerMessage::PeerRemoved(info)),
                 ),
-                OPeerManagerMessage::PeerDisconnect(info) => self.run_with_lock_poll(
-                    info,
-                    |info, peers| {
-                        peers
-                            .remove(&info)
-                            .unwrap_or_else(|| panic!("bittorrent-protocol_peer: Received PeerDisconnect Message With No Matching Peer In Map"));
-
-                        Some(OPeerManagerMessage::PeerDisconnect(info))
-                    },
-                    |info| Some(OPeerManagerMessage::PeerDisconnect(info)),
-                ),
-                OPeerManagerMessage::PeerError(info, error) => self.run_with_lock_poll(
-                    (info, error),
-                    |(info, error), peers| {
-                        peers
-                            .remove(&info)
-                            .unwrap_or_else(|| panic!("bittorrent-protocol_peer: Received PeerError Message With No Matching Peer In Map"));
-
-                        Some(OPeerManagerMessage::PeerError(info, error))
+                OPeerManagerMessage::PeerDisconnected { info, reason } => self.run_with_lock_poll(
+                    (info, reason),
+                    |(info, reason), peers| {
+                        // Both the reader and the writer of a peer can notice it is gone, only
+                        // report the first; peers turned away were never in the map
+                        let message = OPeerManagerMessage::PeerDisconnected { info, reason };
+                        if peers.remove(&info).is_some() {
+                            Some(shed_or(&capacity, info, message))
+                        } else if reason == PeerDisconnectReason::TooManyPeers
+                            || reason == PeerDisconnectReason::ShutDown
+                        {
+                            Some(message)
+                        } else {
+                            None
+                        }
                     },
-                    |(info, error)| Some(OPeerManagerMessage::PeerError(info, error)),
+                    |(info, reason)| Some(OPeerManagerMessage::PeerDisconnected { info, reason }),
                 ),
                 other => Some(other),
-            }
+            };
+
+        // Statistics stick around until the user has seen that the peer is gone
+        if let Some(info) = opt_message.as_ref().and_then(removed_peer) {
+            self.stats.lock().unwrap().remove(&info);
+            self.capabilities.lock().unwrap().remove(&info);
+            self.limiter.remove_peer(&info);
+        }
+
+        opt_message
+    }
+
+    /// Whether every sink and every peer is gone, so no message will arrive again.
+    pub fn is_closed(&self) -> bool {
+        self.closed
+    }
+
+    /// Poll the next message as a `PeerManagerEvent`.
+    pub fn poll_event(&mut self) -> Option<PeerManagerEvent> {
+        self.poll().map(PeerManagerEvent::from)
+    }
+}
+
+/// Message reporting the peer as gone, which is `TooManyPeers` whichever way a shed peer went.
+fn shed_or(
+    capacity: &PeerCapacity,
+    info: PeerInfo,
+    message: OPeerManagerMessage,
+) -> OPeerManagerMessage {
+    if capacity.finish_shedding(&info) {
+        OPeerManagerMessage::PeerDisconnected {
+            info: info,
+            reason: PeerDisconnectReason::TooManyPeers,
+        }
+    } else {
+        message
+    }
+}
+
+/// Retrieve the peer that the given message is telling us was removed, if any.
+fn removed_peer(message: &OPeerManagerMessage) -> Option<PeerInfo> {
+    match message {
+        &OPeerManagerMessage::PeerRemoved(info)
+        | &OPeerManagerMessage::PeerDisconnected { info, .. } => Some(info),
+        _ => None,
     }
 }
 
@@ -288,27 +656,147 @@ pub enum IPeerManagerMessage<S>{
     AddPeer(PeerInfo, S),
     /// Remove a peer from the peer manager.
     RemovePeer(PeerInfo),
+    /// Remove a peer to make room for another, reported with `PeerDisconnectReason::TooManyPeers`.
+    ///
+    /// Unlike `RemovePeer`, the room is freed before the peer has been closed.
+    ShedPeer(PeerInfo),
+    /// Remove a peer, reported as disconnected with the given reason.
+    ///
+    /// Used when we decide to drop a peer for its behavior, such as sending corrupt blocks.
+    DisconnectPeer(PeerInfo, PeerDisconnectReason),
     /// Send a message to a peer.
-    SendMessage(PeerInfo, MessageId, PeerWireProtocolMessage), // TODO: Support querying for statistics
+    SendMessage(PeerInfo, MessageId, PeerWireProtocolMessage),
 }
 
 /// Message that can be received from the `PeerManager`.
 #[derive(Debug)]
 pub enum OPeerManagerMessage {
     /// Message indicating a peer has been added to the peer manager.
-    PeerAdded(PeerInfo),
+    ///
+    /// Capabilities only reflect the handshake, extended handshakes have not been received yet.
+    PeerAdded(PeerInfo, PeerCapabilities),
     /// Message indicating a peer has been removed from the peer manager.
     PeerRemoved(PeerInfo),
     /// Message indicating a message has been sent to the given peer.
     SentMessage(PeerInfo, MessageId),
     /// Message indicating we have received a message from a peer.
     ReceivedMessage(PeerInfo, PeerWireProtocolMessage),
-    /// Message indicating a peer has disconnected from us.
+    /// Message indicating we are no longer connected to a peer.
     ///
     /// Same semantics as `PeerRemoved`, but the peer is not returned.
-    PeerDisconnect(PeerInfo),
-    /// Message indicating a peer errored out.
+    PeerDisconnected {
+        info: PeerInfo,
+        reason: PeerDisconnectReason,
+    },
+}
+
+impl OPeerManagerMessage {
+    /// Address of the DHT node that the peer told us about in a `PortMessage`, if any.
     ///
-    /// Same semantics as `PeerRemoved`, but the peer is not returned.
-    PeerError(PeerInfo, io::Error),
+    /// Only peers that set the DHT bit in the handshake are taken up on their port, the
+    /// address can be given to `MainlineDht::add_node` to ping the node.
+    pub fn dht_node(&self) -> Option<SocketAddr> {
+        match self {
+            &OPeerManagerMessage::ReceivedMessage(
+                ref info,
+                PeerWireProtocolMessage::BitsExtension(BitsExtensionMessage::Port(port)),
+            ) if info.extensions().contains(Extension::Dht) && port.port() != 0 => {
+                Some(SocketAddr::new(info.addr().ip(), port.port()))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Reason for a peer no longer being connected to us.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PeerDisconnectReason {
+    /// Peer closed the connection.
+    RemoteClosed,
+    /// Reading from the peer failed.
+    ReadError,
+    /// Writing to the peer failed.
+    WriteError,
+    /// Peer sent a message breaking the given rule of the protocol.
+    ///
+    /// See `ProtocolViolation::rule` for the rules.
+    ProtocolViolation(&'static str),
+    /// Peer did not send us any message within the peer timeout.
+    Timeout,
+    /// Peer was not added because the peer manager was at capacity, or it was removed with
+    /// `IPeerManagerMessage::ShedPeer` to make room for another.
+    TooManyPeers,
+    /// Peer was not added because the peer manager was shut down.
+    ShutDown,
+    /// Peer was removed with `IPeerManagerMessage::RemovePeer`.
+    Requested,
+}
+
+/// Event that can be received from the `PeerManager` event stream.
+///
+/// Simplified view of `OPeerManagerMessage`, where every way of losing a peer
+/// is reported as `PeerDisconnected`.
+#[derive(Debug)]
+pub enum PeerManagerEvent {
+    /// Peer has been added to the peer manager.
+    PeerConnected {
+        peer: PeerInfo,
+        capabilities: PeerCapabilities,
+    },
+    /// Message has been received from a peer.
+    MessageReceived {
+        peer: PeerInfo,
+        message: PeerWireProtocolMessage,
+    },
+    /// Message has been sent to a peer.
+    MessageSent { peer: PeerInfo, id: MessageId },
+    /// Peer told us the port of its DHT node, see `OPeerManagerMessage::dht_node`.
+    ///
+    /// Reported instead of the `PortMessage` being received.
+    DhtNodeDiscovered { peer: PeerInfo, addr: SocketAddr },
+    /// Peer is no longer connected to us.
+    PeerDisconnected {
+        peer: PeerInfo,
+        reason: PeerDisconnectReason,
+    },
+}
+
+impl From<OPeerManagerMessage> for PeerManagerEvent {
+    fn from(message: OPeerManagerMessage) -> PeerManagerEvent {
+        if let Some(addr) = message.dht_node() {
+            if let OPeerManagerMessage::ReceivedMessage(peer, _) = message {
+                return PeerManagerEvent::DhtNodeDiscovered {
+                    peer: peer,
+                    addr: addr,
+                };
+            }
+        }
+
+        match message {
+            OPeerManagerMessage::PeerAdded(peer, capabilities) => PeerManagerEvent::PeerConnected {
+                peer: peer,
+                capabilities: capabilities,
+            },
+            OPeerManagerMessage::PeerRemoved(peer) => PeerManagerEvent::PeerDisconnected {
+                peer: peer,
+                reason: PeerDisconnectReason::Requested,
+            },
+            OPeerManagerMessage::SentMessage(peer, id) => PeerManagerEvent::MessageSent {
+                peer: peer,
+                id: id,
+            },
+            OPeerManagerMessage::ReceivedMessage(peer, message) => {
+                PeerManagerEvent::MessageReceived {
+                    peer: peer,
+                    message: message,
+                }
+            }
+            OPeerManagerMessage::PeerDisconnected { info, reason } => {
+                PeerManagerEvent::PeerDisconnected {
+                    peer: info,
+                    reason: reason,
+                }
+            }
+        }
+    }
 }
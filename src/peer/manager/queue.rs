@@ -0,0 +1,439 @@
+use std::collections::VecDeque;
+use std::sync::mpsc::RecvTimeoutError;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use super::stats::PeerStats;
+
+/// Create a bounded outbound queue for a single peer.
+///
+/// Payload is only accepted while it fits in `byte_budget` (a single payload larger
+/// than the budget is accepted into an empty queue), control items are always accepted
+/// and are handed to the receiver before any queued payload.
+pub fn outbound_queue<T>(
+    byte_budget: usize,
+    stats: Arc<Mutex<PeerStats>>,
+) -> (QueueSender<T>, QueueReceiver<T>) {
+    let shared = Arc::new(Shared {
+        state: Mutex::new(QueueState {
+            control: VecDeque::new(),
+            payload: VecDeque::new(),
+            payload_bytes: 0,
+            senders: 1,
+            receiver_gone: false,
+            closing: false,
+        }),
+        ready: Condvar::new(),
+        byte_budget: byte_budget,
+        stats: stats,
+    });
+
+    (
+        QueueSender {
+            shared: shared.clone(),
+        },
+        QueueReceiver { shared: shared },
+    )
+}
+
+struct Shared<T> {
+    state: Mutex<QueueState<T>>,
+    // Signalled whenever an item is pushed or popped, or either side goes away
+    ready: Condvar,
+    byte_budget: usize,
+    stats: Arc<Mutex<PeerStats>>,
+}
+
+struct QueueState<T> {
+    control: VecDeque<T>,
+    payload: VecDeque<(T, usize)>,
+    payload_bytes: usize,
+    senders: usize,
+    receiver_gone: bool,
+    // Receiver is disconnected once the queue is empty, even with senders left
+    closing: bool,
+}
+
+impl<T> Shared<T> {
+    fn lock_state(&self) -> MutexGuard<'_, QueueState<T>> {
+        self.state
+            .lock()
+            .expect("bittorrent-protocol_peer: Poisoned Lock In OutboundQueue")
+    }
+
+    fn update_stats(&self, state: &QueueState<T>) {
+        self.stats.lock().unwrap().set_queue_depth(
+            state.control.len() + state.payload.len(),
+            state.payload_bytes,
+        );
+    }
+}
+
+impl<T> QueueState<T> {
+    fn payload_fits(&self, byte_budget: usize, payload_len: usize) -> bool {
+        self.payload_bytes == 0 || self.payload_bytes + payload_len <= byte_budget
+    }
+}
+
+/// Sending half of an outbound queue.
+///
+/// The receiver is disconnected once every clone of the sender is dropped.
+pub struct QueueSender<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> QueueSender<T> {
+    /// Push a control item, handing it back if the receiver is gone.
+    pub fn push_control(&self, item: T) -> Result<(), T> {
+        let mut state = self.shared.lock_state();
+        if state.receiver_gone {
+            return Err(item);
+        }
+
+        state.control.push_back(item);
+        self.shared.update_stats(&state);
+        self.shared.ready.notify_all();
+
+        Ok(())
+    }
+
+    /// Push a payload item, blocking until it fits in the byte budget.
+    ///
+    /// Hands the item back if the receiver is gone.
+    pub fn push_payload(&self, item: T, payload_len: usize) -> Result<(), T> {
+        let mut state = self.shared.lock_state();
+        while !state.receiver_gone && !state.payload_fits(self.shared.byte_budget, payload_len) {
+            state = self
+                .shared
+                .ready
+                .wait(state)
+                .expect("bittorrent-protocol_peer: Poisoned Lock In OutboundQueue");
+        }
+
+        self.push_payload_locked(state, item, payload_len)
+    }
+
+    /// Push a payload item if it fits in the byte budget, handing it back otherwise.
+    pub fn try_push_payload(&self, item: T, payload_len: usize) -> Result<(), T> {
+        let state = self.shared.lock_state();
+        if !state.payload_fits(self.shared.byte_budget, payload_len) {
+            return Err(item);
+        }
+
+        self.push_payload_locked(state, item, payload_len)
+    }
+
+    fn push_payload_locked(
+        &self,
+        mut state: MutexGuard<QueueState<T>>,
+        item: T,
+        payload_len: usize,
+    ) -> Result<(), T> {
+        if state.receiver_gone {
+            return Err(item);
+        }
+
+        state.payload.push_back((item, payload_len));
+        state.payload_bytes += payload_len;
+        self.shared.update_stats(&state);
+        self.shared.ready.notify_all();
+
+        Ok(())
+    }
+
+    /// Disconnect the receiver once every queued item was handed out to it.
+    ///
+    /// Items can still be pushed, they are handed out before the receiver is disconnected.
+    pub fn close(&self) {
+        self.shared.lock_state().closing = true;
+        self.shared.ready.notify_all();
+    }
+
+    /// Drop every queued item, so a closed queue disconnects the receiver right away.
+    pub fn clear(&self) {
+        let mut state = self.shared.lock_state();
+        state.control.clear();
+        state.payload.clear();
+        state.payload_bytes = 0;
+
+        self.shared.update_stats(&state);
+        self.shared.ready.notify_all();
+    }
+
+    /// Wait until the receiver is gone, at most until the deadline.
+    ///
+    /// Returns false if the deadline passed first.
+    pub fn wait_receiver_gone(&self, deadline: Instant) -> bool {
+        let mut state = self.shared.lock_state();
+
+        while !state.receiver_gone {
+            let now = Instant::now();
+            if now >= deadline {
+                return false;
+            }
+            state = self
+                .shared
+                .ready
+                .wait_timeout(state, deadline - now)
+                .expect("bittorrent-protocol_peer: Poisoned Lock In OutboundQueue")
+                .0;
+        }
+
+        true
+    }
+}
+
+impl<T> Clone for QueueSender<T> {
+    fn clone(&self) -> QueueSender<T> {
+        self.shared.lock_state().senders += 1;
+
+        QueueSender {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<T> Drop for QueueSender<T> {
+    fn drop(&mut self) {
+        let mut state = self.shared.lock_state();
+        state.senders -= 1;
+
+        if state.senders == 0 {
+            self.shared.ready.notify_all();
+        }
+    }
+}
+
+/// Receiving half of an outbound queue.
+pub struct QueueReceiver<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> QueueReceiver<T> {
+    /// Pop the next item, control items first, waiting at most `timeout` for one.
+    ///
+    /// Queued items are still handed out after every sender is dropped, or the queue is closed.
+    pub fn pop_timeout(&self, timeout: Duration) -> Result<T, RecvTimeoutError> {
+        let deadline = Instant::now() + timeout;
+        let mut state = self.shared.lock_state();
+
+        loop {
+            if let Some(item) = self.pop_locked(&mut state) {
+                return Ok(item);
+            } else if state.senders == 0 || state.closing {
+                return Err(RecvTimeoutError::Disconnected);
+            }
+
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(RecvTimeoutError::Timeout);
+            }
+            state = self
+                .shared
+                .ready
+                .wait_timeout(state, deadline - now)
+                .expect("bittorrent-protocol_peer: Poisoned Lock In OutboundQueue")
+                .0;
+        }
+    }
+
+    /// Pop the next item, control items first, if one is queued right now.
+    pub fn try_pop(&self) -> Option<T> {
+        let mut state = self.shared.lock_state();
+
+        self.pop_locked(&mut state)
+    }
+
+    fn pop_locked(&self, state: &mut QueueState<T>) -> Option<T> {
+        let opt_item = state.control.pop_front().or_else(|| {
+            state.payload.pop_front().map(|(item, payload_len)| {
+                state.payload_bytes -= payload_len;
+                item
+            })
+        });
+
+        if opt_item.is_some() {
+            self.shared.update_stats(state);
+            // Wake up senders waiting for room in the budget
+            self.shared.ready.notify_all();
+        }
+
+        opt_item
+    }
+}
+
+impl<T> Drop for QueueReceiver<T> {
+    fn drop(&mut self) {
+        let mut state = self.shared.lock_state();
+        state.receiver_gone = true;
+        state.control.clear();
+        state.payload.clear();
+        state.payload_bytes = 0;
+
+        self.shared.update_stats(&state);
+        self.shared.ready.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc::RecvTimeoutError;
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use super::outbound_queue;
+    use crate::peer::manager::stats::PeerStats;
+
+    const NO_WAIT: Duration = Duration::from_millis(0);
+
+    fn stats() -> Arc<Mutex<PeerStats>> {
+        Arc::new(Mutex::new(PeerStats::new()))
+    }
+
+    #[test]
+    fn positive_control_preempts_payload() {
+        let (send, recv) = outbound_queue(100, stats());
+
+        send.push_payload("piece one", 40).unwrap();
+        send.push_payload("piece two", 40).unwrap();
+        send.push_control("choke").unwrap();
+
+        assert_eq!(Ok("choke"), recv.pop_timeout(NO_WAIT));
+        assert_eq!(Ok("piece one"), recv.pop_timeout(NO_WAIT));
+        assert_eq!(Ok("piece two"), recv.pop_timeout(NO_WAIT));
+        assert_eq!(Err(RecvTimeoutError::Timeout), recv.pop_timeout(NO_WAIT));
+    }
+
+    #[test]
+    fn positive_control_ignores_budget() {
+        let (send, _recv) = outbound_queue(100, stats());
+
+        send.try_push_payload("piece", 100).unwrap();
+
+        assert_eq!(Err("piece"), send.try_push_payload("piece", 1));
+        assert_eq!(Ok(()), send.push_control("keep alive"));
+    }
+
+    #[test]
+    fn positive_oversized_payload_into_empty_queue() {
+        let (send, recv) = outbound_queue(100, stats());
+
+        send.try_push_payload("huge", 1000).unwrap();
+        assert_eq!(Err("small"), send.try_push_payload("small", 1));
+
+        recv.pop_timeout(NO_WAIT).unwrap();
+        assert_eq!(Ok(()), send.try_push_payload("small", 1));
+    }
+
+    #[test]
+    fn positive_try_pop_only_queued_items() {
+        let (send, recv) = outbound_queue(100, stats());
+
+        send.push_payload("piece", 40).unwrap();
+        send.push_control("choke").unwrap();
+
+        assert_eq!(Some("choke"), recv.try_pop());
+        assert_eq!(Some("piece"), recv.try_pop());
+        assert_eq!(None, recv.try_pop());
+    }
+
+    #[test]
+    fn positive_queue_depth_in_stats() {
+        let stats = stats();
+        let (send, recv) = outbound_queue(100, stats.clone());
+
+        send.push_payload("piece", 60).unwrap();
+        send.push_control("have").unwrap();
+        assert_eq!(2, stats.lock().unwrap().queued_messages());
+        assert_eq!(60, stats.lock().unwrap().queued_bytes());
+
+        recv.pop_timeout(NO_WAIT).unwrap();
+        recv.pop_timeout(NO_WAIT).unwrap();
+        assert_eq!(0, stats.lock().unwrap().queued_messages());
+        assert_eq!(0, stats.lock().unwrap().queued_bytes());
+    }
+
+    #[test]
+    fn positive_push_blocks_until_room() {
+        let (send, recv) = outbound_queue(100, stats());
+        send.push_payload(1, 100).unwrap();
+
+        let pusher = thread::spawn(move || {
+            send.push_payload(2, 100).unwrap();
+        });
+        thread::sleep(Duration::from_millis(50));
+        assert!(!pusher.is_finished());
+
+        assert_eq!(Ok(1), recv.pop_timeout(NO_WAIT));
+        pusher.join().unwrap();
+        assert_eq!(Ok(2), recv.pop_timeout(NO_WAIT));
+    }
+
+    #[test]
+    fn negative_push_after_receiver_dropped() {
+        let (send, recv) = outbound_queue(100, stats());
+        send.push_payload(1, 100).unwrap();
+
+        let pusher = thread::spawn(move || send.push_payload(2, 100));
+        drop(recv);
+
+        assert_eq!(Err(2), pusher.join().unwrap());
+    }
+
+    #[test]
+    fn negative_pop_after_senders_dropped() {
+        let (send, recv) = outbound_queue(100, stats());
+
+        send.clone().push_control(1).unwrap();
+        drop(send);
+
+        assert_eq!(Ok(1), recv.pop_timeout(NO_WAIT));
+        assert_eq!(
+            Err(RecvTimeoutError::Disconnected),
+            recv.pop_timeout(Duration::from_secs(5))
+        );
+    }
+
+    #[test]
+    fn positive_close_hands_out_queued_items() {
+        let (send, recv) = outbound_queue(100, stats());
+
+        send.push_payload("piece", 40).unwrap();
+        send.close();
+        send.push_control("have").unwrap();
+
+        assert_eq!(Ok("have"), recv.pop_timeout(NO_WAIT));
+        assert_eq!(Ok("piece"), recv.pop_timeout(NO_WAIT));
+        assert_eq!(
+            Err(RecvTimeoutError::Disconnected),
+            recv.pop_timeout(Duration::from_secs(5))
+        );
+    }
+
+    #[test]
+    fn positive_wait_receiver_gone() {
+        let (send, recv) = outbound_queue(100, stats());
+        send.push_control(1).unwrap();
+        send.close();
+
+        let popper = thread::spawn(move || {
+            while recv.pop_timeout(Duration::from_secs(5)).is_ok() {}
+        });
+
+        assert!(send.wait_receiver_gone(Instant::now() + Duration::from_secs(5)));
+        popper.join().unwrap();
+    }
+
+    #[test]
+    fn negative_wait_receiver_gone_past_deadline() {
+        let (send, recv) = outbound_queue(100, stats());
+        send.push_control(1).unwrap();
+        send.close();
+
+        assert!(!send.wait_receiver_gone(Instant::now() + Duration::from_millis(50)));
+
+        send.clear();
+        assert_eq!(Err(RecvTimeoutError::Disconnected), recv.pop_timeout(NO_WAIT));
+    }
+}
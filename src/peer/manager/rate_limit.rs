@@ -0,0 +1,317 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+use std::time::Duration;
+
+use tokio::time::Instant;
+
+use super::peer_info::PeerInfo;
+
+/// Shared handle for limiting the rate of piece payload sent to and received from peers.
+///
+/// Limits are in bytes per second and can be changed at any time, `None` removes the limit.
+/// Only piece payload is throttled, all other messages are sent and received as usual.
+///
+/// Payload is reserved from a token bucket holding at most one second worth of bytes,
+/// going into debt if needed; each peer waits out its debt before reserving more, so
+/// peers take turns instead of one fast peer starving the others.
+#[derive(Clone, Default)]
+pub struct RateLimiter {
+    limits: Arc<Mutex<RateLimits>>,
+}
+
+#[derive(Default)]
+struct RateLimits {
+    upload: TokenBucket,
+    download: TokenBucket,
+    peers: HashMap<PeerInfo, PeerLimits>,
+}
+
+#[derive(Default)]
+struct PeerLimits {
+    upload: TokenBucket,
+    download: TokenBucket,
+}
+
+impl RateLimiter {
+    /// Create a new `RateLimiter` without any limits.
+    pub fn new() -> RateLimiter {
+        RateLimiter::default()
+    }
+
+    /// Set the limit on payload uploaded to all peers combined.
+    pub fn set_upload_limit(&self, opt_limit: Option<u64>) {
+        self.lock_limits()
+            .upload
+            .set_limit(opt_limit, Instant::now());
+    }
+
+    /// Set the limit on payload downloaded from all peers combined.
+    pub fn set_download_limit(&self, opt_limit: Option<u64>) {
+        self.lock_limits()
+            .download
+            .set_limit(opt_limit, Instant::now());
+    }
+
+    /// Set the limit on payload uploaded to the given peer.
+    pub fn set_peer_upload_limit(&self, info: PeerInfo, opt_limit: Option<u64>) {
+        self.lock_limits()
+            .peers
+            .entry(info)
+            .or_insert_with(PeerLimits::default)
+            .upload
+            .set_limit(opt_limit, Instant::now());
+    }
+
+    /// Set the limit on payload downloaded from the given peer.
+    pub fn set_peer_download_limit(&self, info: PeerInfo, opt_limit: Option<u64>) {
+        self.lock_limits()
+            .peers
+            .entry(info)
+            .or_insert_with(PeerLimits::default)
+            .download
+            .set_limit(opt_limit, Instant::now());
+    }
+
+    /// Retrieve the limit on payload uploaded to all peers combined.
+    pub fn upload_limit(&self) -> Option<u64> {
+        self.lock_limits().upload.limit()
+    }
+
+    /// Retrieve the limit on payload downloaded from all peers combined.
+    pub fn download_limit(&self) -> Option<u64> {
+        self.lock_limits().download.limit()
+    }
+
+    /// Block until `bytes` of payload downloaded from the given peer are accounted for.
+    pub(crate) fn acquire_download(&self, info: &PeerInfo, bytes: usize) {
+        sleep(self.reserve_download(info, bytes));
+    }
+
+    /// Reserve `bytes` of upload, returning how long to wait before sending them.
+    pub(crate) fn reserve_upload(&self, info: &PeerInfo, bytes: usize) -> Duration {
+        let now = Instant::now();
+        let mut limits = self.lock_limits();
+
+        let peer_wait = limits
+            .peers
+            .get_mut(info)
+            .map(|peer| peer.upload.reserve(bytes, now))
+            .unwrap_or_default();
+        peer_wait.max(limits.upload.reserve(bytes, now))
+    }
+
+    /// Reserve `bytes` of download, returning how long to wait before receiving more.
+    pub(crate) fn reserve_download(&self, info: &PeerInfo, bytes: usize) -> Duration {
+        let now = Instant::now();
+        let mut limits = self.lock_limits();
+
+        let peer_wait = limits
+            .peers
+            .get_mut(info)
+            .map(|peer| peer.download.reserve(bytes, now))
+            .unwrap_or_default();
+        peer_wait.max(limits.download.reserve(bytes, now))
+    }
+
+    /// Forget the limits of a peer that was removed.
+    pub(crate) fn remove_peer(&self, info: &PeerInfo) {
+        self.lock_limits().peers.remove(info);
+    }
+
+    fn lock_limits(&self) -> MutexGuard<'_, RateLimits> {
+        self.limits
+            .lock()
+            .expect("bittorrent-protocol_peer: Poisoned Lock In RateLimiter")
+    }
+}
+
+fn sleep(wait: Duration) {
+    if wait > Duration::from_secs(0) {
+        thread::sleep(wait);
+    }
+}
+
+/// Bucket refilled at the limit, holding at most one second worth of bytes.
+///
+/// Tokens go negative when bytes are reserved faster than the limit allows.
+struct TokenBucket {
+    opt_limit: Option<u64>,
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl Default for TokenBucket {
+    fn default() -> TokenBucket {
+        TokenBucket {
+            opt_limit: None,
+            tokens: 0.0,
+            last_refill: Instant::now(),
+        }
+    }
+}
+
+impl TokenBucket {
+    fn limit(&self) -> Option<u64> {
+        self.opt_limit
+    }
+
+    /// Limits below one byte per second are treated as one byte per second.
+    fn set_limit(&mut self, opt_limit: Option<u64>, now: Instant) {
+        self.refill(now);
+
+        let had_limit = self.opt_limit.is_some();
+        self.opt_limit = opt_limit.map(|limit| limit.max(1));
+
+        match self.opt_limit {
+            // A fresh limit starts out with a full bucket
+            Some(limit) if !had_limit => self.tokens = limit as f64,
+            Some(limit) => self.tokens = self.tokens.min(limit as f64),
+            None => self.tokens = 0.0,
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        if let Some(limit) = self.opt_limit {
+            let elapsed = now
+                .saturating_duration_since(self.last_refill)
+                .as_secs_f64();
+
+            self.tokens = (self.tokens + elapsed * limit as f64).min(limit as f64);
+        }
+        self.last_refill = now;
+    }
+
+    /// Take `bytes` out of the bucket, returning how long until it is out of debt.
+    fn reserve(&mut self, bytes: usize, now: Instant) -> Duration {
+        let limit = match self.opt_limit {
+            Some(limit) => limit,
+            None => return Duration::from_secs(0),
+        };
+        self.refill(now);
+
+        self.tokens -= bytes as f64;
+        if self.tokens < 0.0 {
+            Duration::from_secs_f64(-self.tokens / limit as f64)
+        } else {
+            Duration::from_secs(0)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tokio::time::{self, Instant};
+
+    use super::RateLimiter;
+    use crate::handshake::Extensions;
+    use crate::peer::manager::peer_info::PeerInfo;
+
+    const BLOCK_LEN: usize = 16 * 1024;
+    const LIMIT: u64 = 100 * 1024;
+
+    fn peer(port: u16) -> PeerInfo {
+        PeerInfo::new(
+            ([127, 0, 0, 1], port).into(),
+            [0u8; 20].into(),
+            [0u8; 20].into(),
+            Extensions::new(),
+        )
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn positive_upload_megabyte_at_limit() {
+        let limiter = RateLimiter::new();
+        limiter.set_upload_limit(Some(LIMIT));
+
+        let start = Instant::now();
+        for _ in 0..(1024 * 1024 / BLOCK_LEN) {
+            let wait = limiter.reserve_upload(&peer(1), BLOCK_LEN);
+            time::advance(wait).await;
+        }
+        let elapsed = start.elapsed();
+
+        // First second worth of payload goes out in a burst
+        assert!(elapsed >= Duration::from_millis(9200), "{:?}", elapsed);
+        assert!(elapsed <= Duration::from_millis(10300), "{:?}", elapsed);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn positive_no_limit_never_waits() {
+        let limiter = RateLimiter::new();
+
+        for _ in 0..1000 {
+            assert_eq!(
+                Duration::from_secs(0),
+                limiter.reserve_upload(&peer(1), BLOCK_LEN)
+            );
+        }
+        assert_eq!(None, limiter.upload_limit());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn positive_peer_limit_below_global() {
+        let limiter = RateLimiter::new();
+        limiter.set_download_limit(Some(LIMIT));
+        limiter.set_peer_download_limit(peer(1), Some(LIMIT / 10));
+
+        // Both start with a full bucket, after that the peer limit dominates
+        time::advance(Duration::from_secs(1)).await;
+        limiter.reserve_download(&peer(1), (LIMIT / 10) as usize);
+        let wait = limiter.reserve_download(&peer(1), (LIMIT / 10) as usize);
+
+        assert_eq!(Duration::from_secs(1), wait);
+        assert_eq!(
+            Duration::from_secs(0),
+            limiter.reserve_download(&peer(2), BLOCK_LEN)
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn positive_limit_changed_at_runtime() {
+        let limiter = RateLimiter::new();
+        limiter.set_upload_limit(Some(LIMIT));
+        limiter.reserve_upload(&peer(1), LIMIT as usize);
+
+        limiter.set_upload_limit(Some(LIMIT * 2));
+        let wait = limiter.reserve_upload(&peer(1), (LIMIT * 2) as usize);
+
+        assert_eq!(Duration::from_secs(1), wait);
+        limiter.set_upload_limit(None);
+        assert_eq!(
+            Duration::from_secs(0),
+            limiter.reserve_upload(&peer(1), BLOCK_LEN)
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn positive_peers_take_turns() {
+        let limiter = RateLimiter::new();
+        limiter.set_upload_limit(Some(LIMIT));
+        let peers = [peer(1), peer(2), peer(3)];
+        // Whoever gets there first can have the initial burst, turns only matter after that
+        limiter.reserve_upload(&peer(4), LIMIT as usize);
+
+        // Every peer reserves again as soon as its previous reservation is paid off
+        let start = Instant::now();
+        let mut ready_at = [start; 3];
+        let mut uploaded = [0usize; 3];
+        while start.elapsed() < Duration::from_secs(30) {
+            let (index, &at) = ready_at
+                .iter()
+                .enumerate()
+                .min_by_key(|&(_, at)| *at)
+                .unwrap();
+            time::advance(at.saturating_duration_since(Instant::now())).await;
+
+            ready_at[index] = Instant::now() + limiter.reserve_upload(&peers[index], BLOCK_LEN);
+            uploaded[index] += BLOCK_LEN;
+        }
+
+        let most = *uploaded.iter().max().unwrap();
+        let least = *uploaded.iter().min().unwrap();
+        assert!(most - least <= BLOCK_LEN, "{:?}", uploaded);
+    }
+}
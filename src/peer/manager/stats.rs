@@ -0,0 +1,428 @@
+use std::collections::{HashMap, VecDeque};
+use std::time::Duration;
+
+use tokio::time::Instant;
+
+use crate::peer::message::{BitsExtensionMessage, PeerWireProtocolMessage};
+
+/// Length of each bucket that payload bytes are summed into for computing rates.
+const RATE_BUCKET_SECS: u64 = 1;
+/// Number of buckets we keep around, older payload does not contribute to rates.
+const MAX_RATE_BUCKETS: u64 = 5 * 60 / RATE_BUCKET_SECS;
+
+/// Type of a `PeerWireProtocolMessage`, for counting messages.
+#[derive(Copy, Clone, Debug, Hash, PartialEq, Eq)]
+pub enum MessageKind {
+    KeepAlive,
+    Choke,
+    UnChoke,
+    Interested,
+    UnInterested,
+    Have,
+    BitField,
+    Request,
+    Piece,
+    Cancel,
+    HaveAll,
+    HaveNone,
+    Suggest,
+    Reject,
+    AllowedFast,
+    Port,
+    Extended,
+    ProtExtension,
+}
+
+impl<'a> From<&'a PeerWireProtocolMessage> for MessageKind {
+    fn from(message: &'a PeerWireProtocolMessage) -> MessageKind {
+        match message {
+            &PeerWireProtocolMessage::KeepAlive => MessageKind::KeepAlive,
+            &PeerWireProtocolMessage::Choke => MessageKind::Choke,
+            &PeerWireProtocolMessage::UnChoke => MessageKind::UnChoke,
+            &PeerWireProtocolMessage::Interested => MessageKind::Interested,
+            &PeerWireProtocolMessage::UnInterested => MessageKind::UnInterested,
+            &PeerWireProtocolMessage::Have(_) => MessageKind::Have,
+            &PeerWireProtocolMessage::BitField(_) => MessageKind::BitField,
+            &PeerWireProtocolMessage::Request(_) => MessageKind::Request,
+            &PeerWireProtocolMessage::Piece(_) => MessageKind::Piece,
+            &PeerWireProtocolMessage::Cancel(_) => MessageKind::Cancel,
+            &PeerWireProtocolMessage::HaveAll => MessageKind::HaveAll,
+            &PeerWireProtocolMessage::HaveNone => MessageKind::HaveNone,
+            &PeerWireProtocolMessage::Suggest(_) => MessageKind::Suggest,
+            &PeerWireProtocolMessage::Reject(_) => MessageKind::Reject,
+            &PeerWireProtocolMessage::AllowedFast(_) => MessageKind::AllowedFast,
+            &PeerWireProtocolMessage::BitsExtension(BitsExtensionMessage::Port(_)) => {
+                MessageKind::Port
+            }
+            &PeerWireProtocolMessage::BitsExtension(BitsExtensionMessage::Extended(_)) => {
+                MessageKind::Extended
+            }
+            &PeerWireProtocolMessage::ProtExtension(_) => MessageKind::ProtExtension,
+        }
+    }
+}
+
+/// Upload and download rates of piece payload, in bytes per second.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct PeerRates {
+    upload: f64,
+    download: f64,
+}
+
+impl PeerRates {
+    /// Create new `PeerRates` from the given upload and download rates.
+    pub fn new(upload: f64, download: f64) -> PeerRates {
+        PeerRates {
+            upload: upload,
+            download: download,
+        }
+    }
+
+    /// Rate at which we are uploading payload to the peer.
+    pub fn upload(&self) -> f64 {
+        self.upload
+    }
+
+    /// Rate at which we are downloading payload from the peer.
+    pub fn download(&self) -> f64 {
+        self.download
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+struct RateBucket {
+    index: u64,
+    uploaded: u64,
+    downloaded: u64,
+}
+
+/// Statistics for a single peer.
+///
+/// Payload counts only the blocks of piece messages, everything else we send or
+/// receive (including the headers of piece messages) counts as overhead.
+#[derive(Clone, Debug)]
+pub struct PeerStats {
+    connected_at: Instant,
+    last_sent: Option<Instant>,
+    last_received: Option<Instant>,
+    uploaded_payload: u64,
+    downloaded_payload: u64,
+    uploaded_overhead: u64,
+    downloaded_overhead: u64,
+    sent_messages: HashMap<MessageKind, u64>,
+    received_messages: HashMap<MessageKind, u64>,
+    buckets: VecDeque<RateBucket>,
+    queued_messages: usize,
+    queued_bytes: usize,
+    snubbed: bool,
+}
+
+impl PeerStats {
+    pub(crate) fn new() -> PeerStats {
+        PeerStats {
+            connected_at: Instant::now(),
+            last_sent: None,
+            last_received: None,
+            uploaded_payload: 0,
+            downloaded_payload: 0,
+            uploaded_overhead: 0,
+            downloaded_overhead: 0,
+            sent_messages: HashMap::new(),
+            received_messages: HashMap::new(),
+            buckets: VecDeque::new(),
+            queued_messages: 0,
+            queued_bytes: 0,
+            snubbed: false,
+        }
+    }
+
+    /// Record a message we sent to the peer.
+    pub(crate) fn record_sent(&mut self, message: &PeerWireProtocolMessage) {
+        let (payload, overhead) = split_payload(message);
+        let now = Instant::now();
+
+        self.last_sent = Some(now);
+        self.uploaded_payload += payload;
+        self.uploaded_overhead += overhead;
+        *self.sent_messages.entry(message.into()).or_insert(0) += 1;
+        self.current_bucket(now).uploaded += payload;
+    }
+
+    /// Record a message we received from the peer.
+    pub(crate) fn record_received(&mut self, message: &PeerWireProtocolMessage) {
+        let (payload, overhead) = split_payload(message);
+        let now = Instant::now();
+
+        self.last_received = Some(now);
+        self.downloaded_payload += payload;
+        self.downloaded_overhead += overhead;
+        *self.received_messages.entry(message.into()).or_insert(0) += 1;
+        self.current_bucket(now).downloaded += payload;
+    }
+
+    /// Record how much is waiting in the outbound queue of the peer.
+    pub(crate) fn set_queue_depth(&mut self, messages: usize, bytes: usize) {
+        self.queued_messages = messages;
+        self.queued_bytes = bytes;
+    }
+
+    /// Record whether or not the peer is snubbing us.
+    pub(crate) fn set_snubbed(&mut self, snubbed: bool) {
+        self.snubbed = snubbed;
+    }
+
+    /// Time at which the peer was added to the manager.
+    pub fn connected_at(&self) -> Instant {
+        self.connected_at
+    }
+
+    /// Time at which we last sent a message to the peer.
+    pub fn last_sent(&self) -> Option<Instant> {
+        self.last_sent
+    }
+
+    /// Time at which we last received a message from the peer.
+    pub fn last_received(&self) -> Option<Instant> {
+        self.last_received
+    }
+
+    /// Time at which we last sent or received a message, or when the peer was added.
+    pub fn last_activity(&self) -> Instant {
+        self.last_sent
+            .into_iter()
+            .chain(self.last_received)
+            .fold(self.connected_at, |latest, at| latest.max(at))
+    }
+
+    /// Number of piece payload bytes we have sent to the peer.
+    pub fn uploaded_payload(&self) -> u64 {
+        self.uploaded_payload
+    }
+
+    /// Number of piece payload bytes we have received from the peer.
+    pub fn downloaded_payload(&self) -> u64 {
+        self.downloaded_payload
+    }
+
+    /// Number of non payload bytes we have sent to the peer.
+    pub fn uploaded_overhead(&self) -> u64 {
+        self.uploaded_overhead
+    }
+
+    /// Number of non payload bytes we have received from the peer.
+    pub fn downloaded_overhead(&self) -> u64 {
+        self.downloaded_overhead
+    }
+
+    /// Number of messages of the given kind we have sent to the peer.
+    pub fn messages_sent(&self, kind: MessageKind) -> u64 {
+        self.sent_messages.get(&kind).cloned().unwrap_or(0)
+    }
+
+    /// Number of messages of the given kind we have received from the peer.
+    pub fn messages_received(&self, kind: MessageKind) -> u64 {
+        self.received_messages.get(&kind).cloned().unwrap_or(0)
+    }
+
+    /// Number of messages waiting to be sent to the peer.
+    pub fn queued_messages(&self) -> usize {
+        self.queued_messages
+    }
+
+    /// Number of bytes of piece messages waiting to be sent to the peer.
+    ///
+    /// Once this nears the queue byte budget of the manager, sending more pieces will
+    /// block, so there is no point in reading more blocks for the peer.
+    pub fn queued_bytes(&self) -> usize {
+        self.queued_bytes
+    }
+
+    /// Whether or not the peer is snubbing us, as last reported with `PeerManager::set_snubbed`.
+    pub fn is_snubbed(&self) -> bool {
+        self.snubbed
+    }
+
+    /// Exponentially weighted moving average of the payload rates.
+    ///
+    /// Payload from `window` ago is weighted `1 / e` as much as payload from right now.
+    /// Only the last five minutes of payload is taken into account.
+    pub fn rates(&self, window: Duration) -> PeerRates {
+        let now_index = self.bucket_index(Instant::now());
+        // Weight of each bucket relative to the next newer bucket
+        let decay = (-(RATE_BUCKET_SECS as f64) / window.as_secs_f64()).exp();
+
+        let (uploaded, downloaded) = self
+            .buckets
+            .iter()
+            .filter(|bucket| now_index - bucket.index < MAX_RATE_BUCKETS)
+            .fold((0f64, 0f64), |(up, down), bucket| {
+                let weight = decay.powi((now_index - bucket.index) as i32);
+
+                (
+                    up + bucket.uploaded as f64 * weight,
+                    down + bucket.downloaded as f64 * weight,
+                )
+            });
+
+        // Buckets without any payload still count towards the average
+        let num_buckets = (now_index + 1).min(MAX_RATE_BUCKETS);
+        let total_weight = if decay < 1.0 {
+            (1.0 - decay.powi(num_buckets as i32)) / (1.0 - decay)
+        } else {
+            num_buckets as f64
+        };
+        let bucket_secs = total_weight * RATE_BUCKET_SECS as f64;
+
+        PeerRates::new(uploaded / bucket_secs, downloaded / bucket_secs)
+    }
+
+    fn bucket_index(&self, at: Instant) -> u64 {
+        at.saturating_duration_since(self.connected_at).as_secs() / RATE_BUCKET_SECS
+    }
+
+    fn current_bucket(&mut self, now: Instant) -> &mut RateBucket {
+        let index = self.bucket_index(now);
+
+        while self
+            .buckets
+            .front()
+            .map_or(false, |bucket| bucket.index + MAX_RATE_BUCKETS <= index)
+        {
+            self.buckets.pop_front();
+        }
+
+        if self.buckets.back().map_or(true, |bucket| bucket.index != index) {
+            self.buckets.push_back(RateBucket {
+                index: index,
+                uploaded: 0,
+                downloaded: 0,
+            });
+        }
+
+        self.buckets.back_mut().unwrap()
+    }
+}
+
+/// Split the size of a message into its payload and overhead.
+fn split_payload(message: &PeerWireProtocolMessage) -> (u64, u64) {
+    let payload = match message {
+        &PeerWireProtocolMessage::Piece(ref msg) => msg.block_length(),
+        _ => 0,
+    };
+
+    (payload as u64, (message.message_size() - payload) as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{MessageKind, PeerStats};
+    use crate::peer::message::{HaveMessage, PeerWireProtocolMessage, PieceMessage};
+
+    use bytes::Bytes;
+    use std::time::Duration;
+    use tokio::time;
+
+    const BLOCK_LEN: usize = 16 * 1024;
+
+    fn piece() -> PeerWireProtocolMessage {
+        PeerWireProtocolMessage::Piece(PieceMessage::new(0, 0, Bytes::from(vec![0u8; BLOCK_LEN])))
+    }
+
+    fn assert_close(expected: f64, actual: f64) {
+        assert!(
+            (expected - actual).abs() < expected * 0.001,
+            "Expected {} But Got {}",
+            expected,
+            actual
+        );
+    }
+
+    #[test]
+    fn positive_payload_separated_from_overhead() {
+        let mut stats = PeerStats::new();
+
+        stats.record_received(&piece());
+        stats.record_received(&PeerWireProtocolMessage::Have(HaveMessage::new(0)));
+        stats.record_sent(&PeerWireProtocolMessage::Interested);
+
+        assert_eq!(BLOCK_LEN as u64, stats.downloaded_payload());
+        // Piece header (13) plus have message (9)
+        assert_eq!(22, stats.downloaded_overhead());
+        assert_eq!(0, stats.uploaded_payload());
+        assert_eq!(5, stats.uploaded_overhead());
+    }
+
+    #[test]
+    fn positive_count_messages_per_kind() {
+        let mut stats = PeerStats::new();
+
+        stats.record_received(&piece());
+        stats.record_received(&piece());
+        stats.record_sent(&PeerWireProtocolMessage::KeepAlive);
+
+        assert_eq!(2, stats.messages_received(MessageKind::Piece));
+        assert_eq!(0, stats.messages_received(MessageKind::KeepAlive));
+        assert_eq!(1, stats.messages_sent(MessageKind::KeepAlive));
+        assert!(stats.last_sent().is_some() && stats.last_received().is_some());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn positive_last_activity_tracks_latest_message() {
+        let mut stats = PeerStats::new();
+        assert_eq!(stats.connected_at(), stats.last_activity());
+
+        time::advance(Duration::from_secs(5)).await;
+        stats.record_sent(&PeerWireProtocolMessage::KeepAlive);
+        time::advance(Duration::from_secs(5)).await;
+
+        assert_eq!(stats.connected_at() + Duration::from_secs(5), stats.last_activity());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn positive_rates_steady_download() {
+        let mut stats = PeerStats::new();
+
+        for _ in 0..60 {
+            stats.record_received(&piece());
+            time::advance(Duration::from_secs(1)).await;
+        }
+        stats.record_received(&piece());
+
+        let rates = stats.rates(Duration::from_secs(10));
+        assert_close(BLOCK_LEN as f64, rates.download());
+        assert_eq!(0.0, rates.upload());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn positive_rates_decay_after_window() {
+        let mut stats = PeerStats::new();
+
+        for _ in 0..300 {
+            stats.record_sent(&piece());
+            time::advance(Duration::from_secs(1)).await;
+        }
+        time::advance(Duration::from_secs(9)).await;
+
+        // Ten buckets are now empty, so payload is weighted at most 1 / e
+        let rates = stats.rates(Duration::from_secs(10));
+        assert_close(BLOCK_LEN as f64 * (-1f64).exp(), rates.upload());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn positive_rates_short_window_favors_recent() {
+        let mut stats = PeerStats::new();
+
+        for _ in 0..30 {
+            time::advance(Duration::from_secs(1)).await;
+        }
+        for _ in 0..5 {
+            stats.record_received(&piece());
+            time::advance(Duration::from_secs(1)).await;
+        }
+        stats.record_received(&piece());
+
+        let short = stats.rates(Duration::from_secs(2)).download();
+        let long = stats.rates(Duration::from_secs(60)).download();
+        assert!(short > long);
+    }
+}
@@ -1,8 +1,9 @@
 #![allow(deprecated)]
 
+use super::capabilities::PeerCapabilities;
 use super::peer_info::PeerInfo;
-use super::{IPeerManagerMessage, OPeerManagerMessage};
-use crate::peer::message::PeerWireProtocolMessage;
+use super::{IPeerManagerMessage, OPeerManagerMessage, PeerDisconnectReason};
+use crate::peer::message::{MessageLimits, PeerWireProtocolMessage};
 use bytes::Bytes;
 use std::net::TcpStream;
 use std::io::{Read, Cursor, Write};
@@ -16,6 +17,7 @@ use crate::peer::manager::TryClone;
 pub fn run_peer<S>(
     mut peer_stream: S,
     peer_info: PeerInfo,
+    limits: MessageLimits,
     o_send: Sender<OPeerManagerMessage>,
 ) -> Sender<IPeerManagerMessage<S>>
     where S: Read + Write +Send + 'static {
@@ -23,10 +25,14 @@ pub fn run_peer<S>(
     let (m_send, m_recv) = mpsc::channel::<IPeerManagerMessage<S>>();
 
     std::thread::spawn(move || {
-        o_send.send(OPeerManagerMessage::PeerAdded(peer_info)).unwrap();
+        o_send.send(OPeerManagerMessage::PeerAdded(
+            peer_info,
+            PeerCapabilities::new(*peer_info.extensions()),
+        )).unwrap();
 
         //let mut msg_codec = PeerWireMessageCodec::new();
-        let mut msg_codec = PeerWireMessageCodec::new();
+        let mut msg_codec =
+            PeerWireMessageCodec::with_extensions(peer_info.extensions()).with_limits(limits);
 
         let num= 24*1024;
         let mut in_buffer = Cursor::new(vec![0u8; num]);
@@ -43,16 +49,32 @@ pub fn run_peer<S>(
                     Some(OPeerManagerMessage::SentMessage(p_info, mid)),
                     true,
                 )),
-                Ok(IPeerManagerMessage::RemovePeer(p_info)) => {
+                Ok(IPeerManagerMessage::RemovePeer(p_info))
+                | Ok(IPeerManagerMessage::ShedPeer(p_info)) => {
                     Ok((None, Some(OPeerManagerMessage::PeerRemoved(p_info)), false))
                 }
+                Ok(IPeerManagerMessage::DisconnectPeer(p_info, reason)) => Ok((
+                    None,
+                    Some(OPeerManagerMessage::PeerDisconnected {
+                        info: p_info,
+                        reason: reason,
+                    }),
+                    false,
+                )),
 
                 Ok(_) => {
                     info!("bittorrent-protocol_peer: Peer Future Received Invalid Message From Peer Manager");
                     Err(())
                 }
 
-                Err(_err) => Ok((None, Some(OPeerManagerMessage::PeerDisconnect(peer_info)), false)),
+                Err(_err) => Ok((
+                    None,
+                    Some(OPeerManagerMessage::PeerDisconnected {
+                        info: peer_info,
+                        reason: PeerDisconnectReason::Requested,
+                    }),
+                    false,
+                )),
             };
 
             //result第一项处理
@@ -1,117 +1,282 @@
 #![allow(deprecated)]
 
+use super::builder::{PeerManagerBuilder, ValidationPolicy};
+use super::capabilities::PeerCapabilities;
 use super::peer_info::PeerInfo;
-use super::{IPeerManagerMessage, OPeerManagerMessage};
-use crate::peer::message::PeerWireProtocolMessage;
-use bytes::Bytes;
+use super::batch::{self, MessageBatch};
+use super::queue::{self, QueueReceiver, QueueSender};
+use super::rate_limit::RateLimiter;
+use super::stats::PeerStats;
+use super::timer::{PeerTimers, TimerAction};
+use super::{IPeerManagerMessage, OPeerManagerMessage, PeerDisconnectReason};
+use crate::peer::message::{
+    BitsExtensionMessage, PeerWireProtocolMessage, PortMessage, ProtocolViolation,
+};
+use bytes::BytesMut;
 use std::net::TcpStream;
-use std::io::{Read, Cursor, Write};
-use std::sync::mpsc::{self, Sender};
-use crate::peer::{PeerWireMessageCodec, MessageCodec};
+use std::io::{self, Read, Write};
+use std::iter;
+use std::mem;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{RecvTimeoutError, Sender};
+use crate::peer::{PeerWireMessageCodec, PeerWireMessageDecoder};
 use std::sync::{Arc, Mutex};
-use std::borrow::BorrowMut;
 use crate::peer::manager::TryClone;
+use std::thread;
+use std::time::Duration;
+use tracing::{debug, debug_span, info, trace};
+
+/// Number of bytes we attempt to read from the peer at a time.
+const READ_CHUNK_LEN: usize = 24 * 1024;
+
+/// Most bytes of queued messages gathered into a single write.
+const MAX_BATCH_LEN: usize = 64 * 1024;
+
+/// Rule reported for malformed messages that do not say which rule they broke.
+const MALFORMED_MESSAGE_RULE: &str = "malformed message";
 
 pub fn run_peer<S>(
     peer: S,
     info: PeerInfo,
+    builder: PeerManagerBuilder,
+    stats: Arc<Mutex<PeerStats>>,
+    capabilities: Arc<Mutex<PeerCapabilities>>,
+    limiter: RateLimiter,
     o_send: Sender<OPeerManagerMessage>,
-) -> Sender<IPeerManagerMessage<S>>
+) -> QueueSender<IPeerManagerMessage<S>>
     where S: Read + Write + TryClone + Send + 'static,
           <S as TryClone>::Item: Send {
 
     let mut p_recv = peer.try_clone().unwrap();
     let o_send1 = o_send.clone();
     let me_info = info.clone();
-    let msg_codec = Arc::new(Mutex::new(PeerWireMessageCodec::new()));
+    let msg_codec = Arc::new(Mutex::new(PeerWireMessageDecoder::new(
+        PeerWireMessageCodec::with_extensions(info.extensions())
+            .with_limits(builder.message_limits()),
+    )));
     let me_msg_codec = msg_codec.clone();
+    let timers = Arc::new(Mutex::new(PeerTimers::new(
+        builder.keep_alive_interval(),
+        builder.peer_timeout(),
+    )));
+    let me_timers = timers.clone();
+    let me_stats = stats.clone();
+    let initial_capabilities = capabilities.lock().unwrap().clone();
+    // Peers supporting the DHT are told our DHT port once, after the bitfield
+    let opt_port_message = builder
+        .dht_port()
+        .filter(|_| initial_capabilities.supports_dht())
+        .map(|port| {
+            PeerWireProtocolMessage::BitsExtension(BitsExtensionMessage::Port(PortMessage::new(port)))
+        });
+    let me_limiter = limiter.clone();
+    // Set once the writer is done with the peer, so the reader stops forwarding messages
+    let closed = Arc::new(AtomicBool::new(false));
+    let me_closed = closed.clone();
+    // Set once either thread reported the peer gone, the manager ignores any later report
+    let reported = Arc::new(AtomicBool::new(false));
+    let me_reported = reported.clone();
+    let opt_validator = builder.message_validator();
+    let policy = builder.validation_policy();
+    // Everything logged by either thread is about this connection
+    let span = debug_span!(
+        "peer",
+        addr = %info.addr(),
+        peer_id = %info.peer_id(),
+        info_hash = %info.hash()
+    );
+    let me_span = span.clone();
+    span.in_scope(|| debug!(addr = %info.addr(), monotonic_counter.peers_connected = 1u64, "peer connected"));
+    // Sent before the reader starts, so no message from the peer comes before it
+    o_send.send(OPeerManagerMessage::PeerAdded(info, initial_capabilities)).unwrap();
     std::thread::spawn(move ||{
-        let num= 24*1024;
-        let mut in_buffer = Cursor::new(vec![0u8; num]);
+        let _entered = me_span.enter();
+        let mut in_buffer = BytesMut::with_capacity(READ_CHUNK_LEN);
         loop {
-            let mut read_position = in_buffer.position() as usize;
-            //info!("[peer task] in read_position:{:?}",read_position);
-            let in_slice = &mut in_buffer.get_mut()[read_position..];
-            let read_result = p_recv.read(in_slice);
-            if let Ok(bytes_read) = read_result {
-                read_position += bytes_read;
+            let read_position = in_buffer.len();
+            in_buffer.resize(read_position + READ_CHUNK_LEN, 0);
+            let read_result = p_recv.read(&mut in_buffer[read_position..]);
+            in_buffer.truncate(read_position + *read_result.as_ref().unwrap_or(&0));
+            if me_closed.load(Ordering::SeqCst) {
+                return;
             }
-            in_buffer.set_position(read_position as u64);
 
-            // Try to parse whatever part of the message we currently have (see if we need to disconnect early)
-            let mut data_slice = &in_buffer.get_mut()[..read_position];
+            let opt_reason = match read_result {
+                Ok(0) => Some(PeerDisconnectReason::RemoteClosed),
+                Ok(_) => None,
+                Err(ref err) if is_transient(err) => None,
+                Err(err) => {
+                    debug!(error = ?err, "read error");
+                    Some(PeerDisconnectReason::ReadError)
+                }
+            };
+            if let Some(reason) = opt_reason {
+                let _ = o_send1.send(disconnected(me_info, reason, &me_reported));
+                return;
+            }
 
+            // Try to parse whatever part of the message we currently have (see if we need to disconnect early)
+            let mut downloaded_payload = 0;
             loop {
                 let me_msg_code_lock = me_msg_codec.lock();
                 if let Ok(mut msg_codec) = me_msg_code_lock {
-                    info!("[peer task] read read_position:{:?}",read_position);
-                    info!("[peer task] msg_head:{:?}",&data_slice[0..4]);
-
-                    //此处使用 if let 则在接受到 多个数据时只会解析一个,造成卡顿.
-                    //此处使用 while let ,在输入缓冲大时可提高性能,但要处理数据不全时 数据头里记录的长度与读取到的长度不相符而导致的断言异常
-                    while let Ok(msg) = msg_codec.parse_bytes(Bytes::from(data_slice)){
-                        let message_size = msg.message_size();
-                        info!("[peer task] message_size:{:?}\n",message_size);
-
-                        data_slice= &data_slice[message_size..];
-                        //data_slice= &(in_buffer.get_mut()[msg.message_size()..read_position].to_vec());
+                    trace!(buffered = in_buffer.len(), "read from peer");
 
-                        o_send1.send(OPeerManagerMessage::ReceivedMessage(me_info, msg)).unwrap();
+                    loop {
+                        match msg_codec.decode(&mut in_buffer) {
+                            Ok(Some(msg)) => {
+                                me_timers.lock().unwrap().on_receive();
+                                me_stats.lock().unwrap().record_received(&msg);
+                                trace!(kind = msg.name(), "received message");
+                                if let PeerWireProtocolMessage::Piece(ref piece) = msg {
+                                    downloaded_payload += piece.block_length();
+                                }
+                                // The codec merges every extended handshake, so take its view of the peer
+                                if let PeerWireProtocolMessage::BitsExtension(BitsExtensionMessage::Extended(_)) = msg {
+                                    *capabilities.lock().unwrap() =
+                                        msg_codec.codec().their_capabilities().clone();
+                                }
 
+                                match opt_validator.map(|validator| validator.validate(&msg)) {
+                                    Some(Err(err)) if policy == ValidationPolicy::DisconnectPeer => {
+                                        debug!(error = %err, "disconnecting for invalid message");
+                                        let _ = o_send1.send(disconnected(
+                                            me_info,
+                                            PeerDisconnectReason::ProtocolViolation(err.rule()),
+                                            &me_reported,
+                                        ));
+                                        return;
+                                    }
+                                    Some(Err(err)) => {
+                                        debug!(error = %err, "dropping invalid message");
+                                    }
+                                    _ => {
+                                        o_send1.send(OPeerManagerMessage::ReceivedMessage(me_info, msg)).unwrap();
+                                    }
+                                }
+                            }
+                            Ok(None) => break,
+                            // Peer violated the protocol, no amount of extra data will fix that
+                            Err(err) if err.kind() == io::ErrorKind::InvalidData => {
+                                debug!(error = %err, "disconnecting for malformed message");
+                                let rule = ProtocolViolation::rule_of(&err).unwrap_or(MALFORMED_MESSAGE_RULE);
+                                let _ = o_send1.send(disconnected(
+                                    me_info,
+                                    PeerDisconnectReason::ProtocolViolation(rule),
+                                    &me_reported,
+                                ));
+                                return;
+                            }
+                            Err(err) => {
+                                me_timers.lock().unwrap().on_receive();
+                                debug!(error = ?err, "skipping unrecognized message");
+                            }
+                        }
                     }
-
-                    let mut v= data_slice.to_vec();
-                    let len = v.len();
-                    if len < num {
-                        v.append(vec![0_u8;num-len].borrow_mut());
-                    }
-
-                    in_buffer = Cursor::new(v);
-                    in_buffer.set_position(len as u64);
                     break;
                 }
             }
+
+            // Hold off on reading more until the payload fits in the download limit
+            me_limiter.acquire_download(&me_info, downloaded_payload);
         }
     });
 
-    let p_send = peer;
-    let (m_send, m_recv) = mpsc::channel::<IPeerManagerMessage<S>>();
+    let mut writer = PeerWriter {
+        writer: peer,
+        batch: MessageBatch::new(),
+        unflushed: Vec::new(),
+        unacked: Vec::new(),
+        acked: Vec::new(),
+        flushed: false,
+        opt_port_message: opt_port_message,
+        info: info,
+        msg_codec: msg_codec,
+        timers: timers.clone(),
+        stats: stats.clone(),
+        limiter: limiter,
+    };
+    let (m_send, m_recv) =
+        queue::outbound_queue::<IPeerManagerMessage<S>>(builder.queue_byte_budget(), stats);
     std::thread::spawn(move || {
-        o_send.send(OPeerManagerMessage::PeerAdded(info)).unwrap();
+        let _entered = span.enter();
+        // Popped while gathering a batch, but has to wait until the batch went out
+        let mut opt_pending = None;
         loop {
             //构造result
-            let result = match m_recv.recv() {
-                Ok(IPeerManagerMessage::SendMessage(p_info, mid, p_message)) => Ok((
-                    Some(p_message),
-                    Some(OPeerManagerMessage::SentMessage(p_info, mid)),
-                    true,
-                )),
-                Ok(IPeerManagerMessage::RemovePeer(p_info)) => {
-                    Ok((None, Some(OPeerManagerMessage::PeerRemoved(p_info)), false))
+            let wait = timers.lock().unwrap().time_until_action();
+            let popped = match opt_pending.take() {
+                Some(item) => Ok(item),
+                None => m_recv.pop_timeout(wait),
+            };
+            let result = match popped {
+                Ok(IPeerManagerMessage::SendMessage(p_info, mid, p_message)) => {
+                    let mut outgoing = vec![(
+                        Some(p_message),
+                        Some(OPeerManagerMessage::SentMessage(p_info, mid)),
+                    )];
+                    opt_pending = gather_queued(&m_recv, &mut outgoing);
+
+                    Ok((outgoing, true))
+                }
+                // Shed peers are told apart from removed ones by the stream
+                Ok(IPeerManagerMessage::RemovePeer(p_info))
+                | Ok(IPeerManagerMessage::ShedPeer(p_info)) => {
+                    Ok((vec![(None, Some(OPeerManagerMessage::PeerRemoved(p_info)))], false))
                 }
+                Ok(IPeerManagerMessage::DisconnectPeer(p_info, reason)) => Ok((
+                    vec![(None, Some(disconnected(p_info, reason, &reported)))],
+                    false,
+                )),
 
                 Ok(_) => {
                     info!("bittorrent-protocol_peer: Peer Future Received Invalid Message From Peer Manager");
                     Err(())
                 }
 
-                Err(_err) => Ok((None, Some(OPeerManagerMessage::PeerDisconnect(info)), false)),
+                Err(RecvTimeoutError::Timeout) => match timers.lock().unwrap().poll() {
+                    Some(TimerAction::Timeout) => Ok((
+                        vec![(
+                            None,
+                            Some(disconnected(info, PeerDisconnectReason::Timeout, &reported)),
+                        )],
+                        false,
+                    )),
+                    Some(TimerAction::KeepAlive) => {
+                        Ok((vec![(Some(PeerWireProtocolMessage::KeepAlive), None)], true))
+                    }
+                    None => Ok((Vec::new(), true)),
+                },
+
+                // The manager only lets go of us after we were reported gone, or when it was dropped
+                Err(RecvTimeoutError::Disconnected) => Ok((
+                    vec![(
+                        None,
+                        Some(disconnected(info, PeerDisconnectReason::Requested, &reported)),
+                    )],
+                    false,
+                )),
             };
 
             //result第一项处理
             let result = match result {
-                Ok((opt_send, opt_ack, is_good)) => {
-                    if let Some(peer_write_msg) = opt_send {
-                        loop {
-                            let msg_codec_lock = msg_codec.lock();
-                            if let Ok(mut msg_codec)= msg_codec_lock {
-                                msg_codec.write_bytes(&peer_write_msg,p_send.try_clone().unwrap()).unwrap();
-                                break;
-                            }
+                Ok((outgoing, is_good)) => {
+                    let write_result = writer.write(outgoing);
+                    let mut acks = writer.take_acks();
+
+                    match write_result {
+                        Ok(()) => Ok((acks, is_good)),
+                        // Includes extension messages the peer has no id for
+                        Err(err) => {
+                            debug!(error = ?err, "write error");
+                            acks.push(disconnected(
+                                info,
+                                PeerDisconnectReason::WriteError,
+                                &reported,
+                            ));
+
+                            Ok((acks, false))
                         }
-                        Ok((opt_ack, is_good))
-                    } else {
-                        Ok((opt_ack, is_good))
                     }
                 }
                 Err(_err) => Err(()),
@@ -119,14 +284,12 @@ pub fn run_peer<S>(
 
             //result第二项处理
             let result = match result {
-                Ok((opt_ack, is_good)) => {
-                    if let Some(o_peer_manager_msg) = opt_ack {
-                        let _= o_send.send(o_peer_manager_msg);
-                        Ok(is_good)
-                    } else {
-                        // Either we had no recv message (from remote), or it was a keep alive message, which we dont propagate
-                        Ok(is_good)
+                Ok((acks, is_good)) => {
+                    // Keep alives from us have no ack, they are not propagated
+                    for o_peer_manager_msg in acks {
+                        let _ = o_send.send(o_peer_manager_msg);
                     }
+                    Ok(is_good)
                 }
                 _ => Err(()),
             };
@@ -147,7 +310,171 @@ pub fn run_peer<S>(
                 }
             }
         } //loop end
+
+        closed.store(true, Ordering::SeqCst);
     }); // thread end
 
     m_send
 }
+
+/// Message to send to the peer, along with the ack for the manager once it was sent.
+type Outgoing = (Option<PeerWireProtocolMessage>, Option<OPeerManagerMessage>);
+
+/// Move messages that are already queued on to the end of the outgoing messages, so they
+/// go out in the same write, until there are at least `MAX_BATCH_LEN` bytes of them.
+///
+/// Returns the first item popped that was not a message, it has to wait for the batch.
+fn gather_queued<S>(
+    m_recv: &QueueReceiver<IPeerManagerMessage<S>>,
+    outgoing: &mut Vec<Outgoing>,
+) -> Option<IPeerManagerMessage<S>> {
+    let mut batch_len: usize = outgoing
+        .iter()
+        .filter_map(|entry| entry.0.as_ref())
+        .map(PeerWireProtocolMessage::message_size)
+        .sum();
+
+    while batch_len < MAX_BATCH_LEN {
+        match m_recv.try_pop() {
+            Some(IPeerManagerMessage::SendMessage(p_info, mid, p_message)) => {
+                batch_len += p_message.message_size();
+                outgoing.push((
+                    Some(p_message),
+                    Some(OPeerManagerMessage::SentMessage(p_info, mid)),
+                ));
+            }
+            Some(other) => return Some(other),
+            None => break,
+        }
+    }
+
+    None
+}
+
+/// Writing half of a peer, coalescing outgoing messages into as few writes as possible.
+struct PeerWriter<W> {
+    writer: W,
+    batch: MessageBatch,
+    // Messages in the batch, recorded in the stats once they are flushed
+    unflushed: Vec<PeerWireProtocolMessage>,
+    // Acks for the manager, released once the messages before them are flushed
+    unacked: Vec<OPeerManagerMessage>,
+    acked: Vec<OPeerManagerMessage>,
+    // Haves are only folded into our bitfield until it went out in the first flush
+    flushed: bool,
+    // Peers supporting the DHT are told our DHT port once, after the bitfield
+    opt_port_message: Option<PeerWireProtocolMessage>,
+    info: PeerInfo,
+    msg_codec: Arc<Mutex<PeerWireMessageDecoder>>,
+    timers: Arc<Mutex<PeerTimers>>,
+    stats: Arc<Mutex<PeerStats>>,
+    limiter: RateLimiter,
+}
+
+impl<W> PeerWriter<W>
+where
+    W: Write,
+{
+    /// Write out the messages in order, stopping at the first that could not be written.
+    ///
+    /// Every message is written whole within one flush, a piece that has to wait on the
+    /// upload limit first flushes whatever is ahead of it, so that does not wait as well.
+    fn write(&mut self, mut outgoing: Vec<Outgoing>) -> io::Result<()> {
+        if !self.flushed {
+            let folded = batch::fold_haves(&mut outgoing);
+            if folded != 0 {
+                trace!(folded = folded, "folded haves into bitfield");
+            }
+        }
+
+        for (opt_message, opt_ack) in outgoing {
+            if let Some(message) = opt_message {
+                if let PeerWireProtocolMessage::Piece(ref piece) = message {
+                    let wait = self.limiter.reserve_upload(&self.info, piece.block_length());
+                    if wait > Duration::from_secs(0) {
+                        self.flush()?;
+                        thread::sleep(wait);
+                    }
+                }
+
+                let opt_port = match message {
+                    PeerWireProtocolMessage::KeepAlive => None,
+                    _ => self.opt_port_message.take(),
+                };
+                for message in iter::once(message).chain(opt_port) {
+                    if let Err(err) = self.push(message) {
+                        // Messages ahead of it still go out
+                        self.flush()?;
+
+                        return Err(err);
+                    }
+                }
+            }
+
+            self.unacked.extend(opt_ack);
+        }
+
+        self.flush()
+    }
+
+    /// Acks for every message that was written so far.
+    fn take_acks(&mut self) -> Vec<OPeerManagerMessage> {
+        mem::take(&mut self.acked)
+    }
+
+    fn push(&mut self, message: PeerWireProtocolMessage) -> io::Result<()> {
+        let mut msg_codec = self.msg_codec.lock().unwrap();
+        self.batch.push(msg_codec.codec_mut(), &message)?;
+        self.unflushed.push(message);
+
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if !self.batch.is_empty() {
+            let messages = self.batch.messages();
+            let writes = self.batch.flush(&mut self.writer)?;
+            self.flushed = true;
+            trace!(messages = messages, writes = writes, "flushed messages");
+
+            self.timers.lock().unwrap().on_send();
+            let mut stats = self.stats.lock().unwrap();
+            for message in self.unflushed.drain(..) {
+                stats.record_sent(&message);
+                trace!(kind = message.name(), "sent message");
+            }
+        }
+        self.acked.append(&mut self.unacked);
+
+        Ok(())
+    }
+}
+
+/// Message telling the manager the peer is gone, logged in the span of the peer if it is the first.
+fn disconnected(
+    info: PeerInfo,
+    reason: PeerDisconnectReason,
+    reported: &AtomicBool,
+) -> OPeerManagerMessage {
+    if !reported.swap(true, Ordering::SeqCst) {
+        debug!(
+            addr = %info.addr(),
+            reason = ?reason,
+            monotonic_counter.peers_disconnected = 1u64,
+            "peer disconnected"
+        );
+    }
+
+    OPeerManagerMessage::PeerDisconnected {
+        info: info,
+        reason: reason,
+    }
+}
+
+/// Whether or not a read error goes away by reading again.
+fn is_transient(err: &io::Error) -> bool {
+    match err.kind() {
+        io::ErrorKind::Interrupted | io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => true,
+        _ => false,
+    }
+}
@@ -0,0 +1,139 @@
+use std::cmp;
+use std::time::Duration;
+
+use tokio::time::Instant;
+
+/// Action that a peer's timers are asking us to take.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub(crate) enum TimerAction {
+    /// Nothing has been sent for a while, send a keep-alive.
+    KeepAlive,
+    /// Nothing has been received for too long, disconnect the peer.
+    Timeout,
+}
+
+/// Keep-alive and idle timeout tracking for a single peer.
+///
+/// Timestamps come from `tokio::time`, which falls back to the system clock
+/// outside of a runtime, so timers can be driven with paused time in tests.
+pub(crate) struct PeerTimers {
+    keep_alive_interval: Duration,
+    peer_timeout: Duration,
+    last_sent: Instant,
+    last_received: Instant,
+}
+
+impl PeerTimers {
+    /// Create new `PeerTimers`, starting both timers now.
+    pub fn new(keep_alive_interval: Duration, peer_timeout: Duration) -> PeerTimers {
+        let now = Instant::now();
+
+        PeerTimers {
+            keep_alive_interval: keep_alive_interval,
+            peer_timeout: peer_timeout,
+            last_sent: now,
+            last_received: now,
+        }
+    }
+
+    /// Reset the keep-alive timer, any message we send counts as a keep-alive.
+    pub fn on_send(&mut self) {
+        self.last_sent = Instant::now();
+    }
+
+    /// Reset the idle timer, any message we receive (including keep-alives) counts.
+    pub fn on_receive(&mut self) {
+        self.last_received = Instant::now();
+    }
+
+    /// Retrieve how long we can wait before `poll` may return an action.
+    pub fn time_until_action(&self) -> Duration {
+        let keep_alive_at = self.last_sent + self.keep_alive_interval;
+        let timeout_at = self.last_received + self.peer_timeout;
+
+        cmp::min(keep_alive_at, timeout_at).saturating_duration_since(Instant::now())
+    }
+
+    /// Check for an expired timer.
+    ///
+    /// Timeouts take precedence over keep-alives, there is no point in heartbeating a dead peer.
+    pub fn poll(&self) -> Option<TimerAction> {
+        let now = Instant::now();
+
+        if now.duration_since(self.last_received) >= self.peer_timeout {
+            Some(TimerAction::Timeout)
+        } else if now.duration_since(self.last_sent) >= self.keep_alive_interval {
+            Some(TimerAction::KeepAlive)
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{PeerTimers, TimerAction};
+
+    use std::time::Duration;
+    use tokio::time;
+
+    const KEEP_ALIVE: Duration = Duration::from_secs(60);
+    const TIMEOUT: Duration = Duration::from_secs(180);
+
+    #[tokio::test(start_paused = true)]
+    async fn positive_keep_alive_after_interval() {
+        let mut timers = PeerTimers::new(KEEP_ALIVE, TIMEOUT);
+
+        time::advance(KEEP_ALIVE - Duration::from_millis(1)).await;
+        assert_eq!(None, timers.poll());
+        assert_eq!(Duration::from_millis(1), timers.time_until_action());
+
+        time::advance(Duration::from_millis(1)).await;
+        assert_eq!(Some(TimerAction::KeepAlive), timers.poll());
+
+        timers.on_send();
+        assert_eq!(None, timers.poll());
+        assert_eq!(KEEP_ALIVE, timers.time_until_action());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn positive_timeout_after_silence() {
+        let mut timers = PeerTimers::new(KEEP_ALIVE, TIMEOUT);
+
+        for _ in 0..2 {
+            time::advance(KEEP_ALIVE).await;
+            assert_eq!(Some(TimerAction::KeepAlive), timers.poll());
+            timers.on_send();
+        }
+
+        time::advance(KEEP_ALIVE - Duration::from_millis(1)).await;
+        assert_eq!(None, timers.poll());
+        time::advance(Duration::from_millis(1)).await;
+        assert_eq!(Some(TimerAction::Timeout), timers.poll());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn positive_receive_resets_timeout() {
+        let mut timers = PeerTimers::new(KEEP_ALIVE, TIMEOUT);
+
+        time::advance(TIMEOUT - Duration::from_secs(1)).await;
+        timers.on_receive();
+        timers.on_send();
+
+        time::advance(TIMEOUT - Duration::from_secs(1)).await;
+        assert_eq!(Some(TimerAction::KeepAlive), timers.poll());
+
+        time::advance(Duration::from_secs(1)).await;
+        assert_eq!(Some(TimerAction::Timeout), timers.poll());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn positive_time_until_timeout_when_sooner() {
+        let mut timers = PeerTimers::new(KEEP_ALIVE, TIMEOUT);
+
+        time::advance(TIMEOUT - Duration::from_secs(10)).await;
+        timers.on_send();
+
+        assert_eq!(Duration::from_secs(10), timers.time_until_action());
+    }
+}
@@ -1,6 +1,8 @@
 use std::net::TcpStream;
 use std::io;
 use std::io::{Read, Write};
+use crate::handshake::transports::MockSocket;
+use crate::handshake::MseSocket;
 use crate::utp::UtpSocket;
 
 pub trait TryClone{
@@ -23,3 +25,22 @@ impl TryClone for UtpSocket {
         UtpSocket::try_clone(self)
     }
 }
+
+impl TryClone for MockSocket {
+    type Item = MockSocket;
+
+    fn try_clone(&self) -> io::Result<Self::Item> {
+        Ok(self.clone())
+    }
+}
+
+impl<S> TryClone for MseSocket<S>
+where
+    S: TryClone,
+{
+    type Item = MseSocket<S::Item>;
+
+    fn try_clone(&self) -> io::Result<Self::Item> {
+        self.get_ref().try_clone().map(|sock| self.with_socket(sock))
+    }
+}
@@ -3,7 +3,7 @@ use std::io;
 use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
 use std::str;
 
-use crate::bencode::{BConvert, BDictAccess, BRefAccess, BencodeConvertError};
+use crate::bencode::{BConvert, BDecodeOpt, BDictAccess, BRefAccess, BencodeConvertError};
 use crate::util::convert;
 
 use crate::peer::message::bits_ext::ExtendedType;
@@ -22,6 +22,23 @@ impl BConvert for IoErrorBencodeConvert {
 
 // ----------------------------------------------------------------------------//
 
+/// Maximum nesting of bencode sent by peers, none of our messages nest more than a few levels.
+pub const PEER_MAX_RECURSION: usize = 8;
+/// Maximum number of values in bencode sent by peers.
+pub const PEER_MAX_TOKENS: usize = 1024;
+/// Maximum length of a byte string in bencode sent by peers, fits a full compact peer list.
+pub const PEER_MAX_BYTES_LEN: usize = 64 * 1024;
+
+/// Options for decoding bencode sent by peers, which should not be able to make us
+/// allocate much more than the size of the message.
+pub fn peer_decode_opt(max_recursion: usize, enforce_full_decode: bool) -> BDecodeOpt {
+    BDecodeOpt::new(max_recursion, false, enforce_full_decode)
+        .with_max_tokens(PEER_MAX_TOKENS)
+        .with_max_bytes_len(PEER_MAX_BYTES_LEN)
+}
+
+// ----------------------------------------------------------------------------//
+
 pub const ID_MAP_KEY: &'static [u8] = b"m";
 pub const CLIENT_ID_KEY: &'static [u8] = b"v";
 pub const CLIENT_TCP_PORT_KEY: &'static [u8] = b"p";
@@ -30,6 +47,7 @@ pub const CLIENT_IPV6_ADDR_KEY: &'static [u8] = b"ipv6";
 pub const CLIENT_IPV4_ADDR_KEY: &'static [u8] = b"ipv4";
 pub const CLIENT_MAX_REQUESTS_KEY: &'static [u8] = b"reqq";
 pub const METADATA_SIZE_KEY: &'static [u8] = b"metadata_size";
+pub const UPLOAD_ONLY_KEY: &'static [u8] = b"upload_only";
 
 pub fn parse_id_map<K, V>(root: &dyn BDictAccess<K, V>) -> HashMap<ExtendedType, u8>
 where
@@ -147,6 +165,20 @@ where
     CONVERT.lookup_and_convert_int(root, METADATA_SIZE_KEY).ok()
 }
 
+pub fn parse_upload_only<K, V>(root: &dyn BDictAccess<K, V>) -> Option<bool>
+where
+    V: BRefAccess,
+{
+    CONVERT
+        .lookup_and_convert_int(root, UPLOAD_ONLY_KEY)
+        .ok()
+        .and_then(|upload_only| match upload_only {
+            0 => Some(false),
+            1 => Some(true),
+            _ => None,
+        })
+}
+
 fn parse_ipv4_addr(ipv4_bytes: &[u8]) -> Ipv4Addr {
     convert::bytes_be_to_ipv4([ipv4_bytes[0], ipv4_bytes[1], ipv4_bytes[2], ipv4_bytes[3]])
 }
@@ -201,3 +233,12 @@ where
 {
     CONVERT.lookup_and_convert_int(root, TOTAL_SIZE_KEY).into()
 }
+
+// ----------------------------------------------------------------------------//
+
+pub const ADDED_KEY: &'static [u8] = b"added";
+pub const ADDED_FLAGS_KEY: &'static [u8] = b"added.f";
+pub const ADDED_V6_KEY: &'static [u8] = b"added6";
+pub const ADDED_V6_FLAGS_KEY: &'static [u8] = b"added6.f";
+pub const DROPPED_KEY: &'static [u8] = b"dropped";
+pub const DROPPED_V6_KEY: &'static [u8] = b"dropped6";
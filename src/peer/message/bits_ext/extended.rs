@@ -1,4 +1,5 @@
 use std::collections::HashMap;
+use std::fmt;
 use std::io::{self, Write};
 use std::mem;
 use std::net::IpAddr;
@@ -6,13 +7,12 @@ use std::net::Ipv4Addr;
 use std::net::Ipv6Addr;
 
 use bytes::{Bytes, BytesMut};
-use nom::{IResult, Needed};
 
 use crate::bencode::BencodeMut;
 use crate::bencode::{BConvert, BDecodeOpt, BMutAccess, BencodeRef};
 use crate::util::convert;
 
-use crate::peer::message::{self, bencode, bits_ext};
+use crate::peer::message::{self, bencode, bits_ext, ProtocolViolation};
 
 /// Builder type for an `ExtendedMessage`.
 #[derive(Clone, Debug, PartialEq, Eq)]
@@ -25,6 +25,7 @@ pub struct ExtendedMessageBuilder {
     our_ipv4_addr: Option<Ipv4Addr>,
     our_max_requests: Option<i64>,
     metadata_size: Option<i64>,
+    upload_only: Option<bool>,
     custom_entries: HashMap<String, BencodeMut<'static>>,
 }
 
@@ -40,6 +41,7 @@ impl ExtendedMessageBuilder {
             our_ipv4_addr: None,
             our_max_requests: None,
             metadata_size: None,
+            upload_only: None,
             custom_entries: HashMap::new(),
         }
     }
@@ -50,6 +52,11 @@ impl ExtendedMessageBuilder {
         self
     }
 
+    /// Set our client name and version (the `v` key).
+    pub fn with_client_version(self, version: &str) -> ExtendedMessageBuilder {
+        self.with_our_id(Some(version.to_string()))
+    }
+
     /// Set the given `ExtendedType` to map to the given value.
     pub fn with_extended_type(
         mut self,
@@ -76,6 +83,14 @@ impl ExtendedMessageBuilder {
         self
     }
 
+    /// Set our ipv4 or ipv6 address, depending on the address type.
+    pub fn with_our_ip(self, ip: IpAddr) -> ExtendedMessageBuilder {
+        match ip {
+            IpAddr::V4(ipv4) => self.with_our_ipv4_addr(Some(ipv4)),
+            IpAddr::V6(ipv6) => self.with_our_ipv6_addr(Some(ipv6)),
+        }
+    }
+
     /// Set our ipv6 address.
     pub fn with_our_ipv6_addr(mut self, ipv6: Option<Ipv6Addr>) -> ExtendedMessageBuilder {
         self.our_ipv6_addr = ipv6;
@@ -94,12 +109,23 @@ impl ExtendedMessageBuilder {
         self
     }
 
+    /// Set the maximum number of outstanding requests we support (the `reqq` key).
+    pub fn with_request_queue_size(self, size: u32) -> ExtendedMessageBuilder {
+        self.with_max_requests(Some(size as i64))
+    }
+
     /// Set the info dictionary metadata size.
     pub fn with_metadata_size(mut self, metadata_size: Option<i64>) -> ExtendedMessageBuilder {
         self.metadata_size = metadata_size;
         self
     }
 
+    /// Set whether or not we are only uploading (we are a seed).
+    pub fn with_upload_only(mut self, upload_only: bool) -> ExtendedMessageBuilder {
+        self.upload_only = Some(upload_only);
+        self
+    }
+
     /// Set a custom entry in the message with the given dictionary key.
     pub fn with_custom_entry(
         mut self,
@@ -190,11 +216,27 @@ fn bencode_from_builder(
                 bt_ben_int!(metadata_size),
             )
         });
+        builder.upload_only.map(|upload_only| {
+            root_map_access.insert(
+                bencode::UPLOAD_ONLY_KEY.into(),
+                bt_ben_int!(upload_only as i64),
+            )
+        });
     }
 
     root_map.encode()
 }
 
+/// Error for an extended handshake whose bencode we could not make sense of.
+///
+/// The message itself was framed correctly, so it can be skipped.
+fn extended_violation<E>(err: E) -> io::Error
+where
+    E: fmt::Display,
+{
+    ProtocolViolation::new("extended handshake", err.to_string()).into_skippable()
+}
+
 // ----------------------------------------------------------------------------//
 
 // Terminology is written as if we were receiving the message. Example: Our ip is
@@ -205,12 +247,16 @@ const ROOT_ERROR_KEY: &'static str = "ExtendedMessage";
 
 const UT_METADATA_ID: &'static str = "ut_metadata";
 const UT_PEX_ID: &'static str = "ut_pex";
+const LT_DONTHAVE_ID: &'static str = "lt_donthave";
+const UT_HOLEPUNCH_ID: &'static str = "ut_holepunch";
 
 /// Enumeration of extended types activated via `ExtendedMessage`.
 #[derive(Clone, Debug, Hash, PartialEq, Eq)]
 pub enum ExtendedType {
     UtMetadata,
     UtPex,
+    LtDontHave,
+    UtHolepunch,
     Custom(String),
 }
 
@@ -220,6 +266,8 @@ impl ExtendedType {
         match id {
             UT_METADATA_ID => ExtendedType::UtMetadata,
             UT_PEX_ID => ExtendedType::UtPex,
+            LT_DONTHAVE_ID => ExtendedType::LtDontHave,
+            UT_HOLEPUNCH_ID => ExtendedType::UtHolepunch,
             custom => ExtendedType::Custom(custom.to_string()),
         }
     }
@@ -229,6 +277,8 @@ impl ExtendedType {
         match self {
             &ExtendedType::UtMetadata => UT_METADATA_ID,
             &ExtendedType::UtPex => UT_PEX_ID,
+            &ExtendedType::LtDontHave => LT_DONTHAVE_ID,
+            &ExtendedType::UtHolepunch => UT_HOLEPUNCH_ID,
             &ExtendedType::Custom(ref id) => &**id,
         }
     }
@@ -247,6 +297,7 @@ pub struct ExtendedMessage {
     our_ipv4_addr: Option<Ipv4Addr>,
     our_max_requests: Option<i64>,
     metadata_size: Option<i64>,
+    upload_only: Option<bool>,
     raw_bencode: Bytes,
 }
 
@@ -269,53 +320,32 @@ impl ExtendedMessage {
             our_ipv4_addr: builder.our_ipv4_addr,
             our_max_requests: builder.our_max_requests,
             metadata_size: builder.metadata_size,
+            upload_only: builder.upload_only,
             raw_bencode: raw_bencode.freeze(),
         }
     }
 
-    /// Parse an `ExtendedMessage` from some raw bencode of the given length.
-    pub fn parse_bytes(
-        _input: (),
-        mut bytes: Bytes,
-        len: u32,
-    ) -> IResult<(), io::Result<ExtendedMessage>> {
-        let cast_len = message::u32_to_usize(len);
-
-        if bytes.len() >= cast_len {
-            let raw_bencode = bytes.split_to(cast_len);
-            let clone_raw_bencode = raw_bencode.clone();
-
-            let res_extended_message = BencodeRef::decode(&*raw_bencode, BDecodeOpt::default())
-                .map_err(|err| io::Error::new(io::ErrorKind::Other, err.to_string()))
-                .and_then(|bencode| {
-                    let ben_dict = bencode::CONVERT.convert_dict(&bencode, ROOT_ERROR_KEY)?;
-
-                    let id_map = bencode::parse_id_map(ben_dict);
-                    let our_id = bencode::parse_client_id(ben_dict);
-                    let our_tcp_port = bencode::parse_client_tcp_port(ben_dict);
-                    let their_ip = bencode::parse_our_ip(ben_dict);
-                    let our_ipv6_addr = bencode::parse_client_ipv6_addr(ben_dict);
-                    let our_ipv4_addr = bencode::parse_client_ipv4_addr(ben_dict);
-                    let our_max_requests = bencode::parse_client_max_requests(ben_dict);
-                    let metadata_size = bencode::parse_metadata_size(ben_dict);
-
-                    Ok(ExtendedMessage {
-                        id_map: id_map,
-                        our_id: our_id,
-                        our_tcp_port: our_tcp_port,
-                        their_ip: their_ip,
-                        our_ipv6_addr: our_ipv6_addr,
-                        our_ipv4_addr: our_ipv4_addr,
-                        our_max_requests: our_max_requests,
-                        metadata_size: metadata_size,
-                        raw_bencode: clone_raw_bencode,
-                    })
-                });
-
-            IResult::Done((), res_extended_message)
-        } else {
-            IResult::Incomplete(Needed::Size(cast_len - bytes.len()))
-        }
+    /// Parse an `ExtendedMessage` from its raw bencode.
+    pub fn parse_bytes(bytes: Bytes) -> io::Result<ExtendedMessage> {
+        let decode_opts = bencode::peer_decode_opt(bencode::PEER_MAX_RECURSION, true);
+
+        let bencode = BencodeRef::decode(&*bytes, decode_opts).map_err(extended_violation)?;
+        let ben_dict = bencode::CONVERT
+            .convert_dict(&bencode, ROOT_ERROR_KEY)
+            .map_err(extended_violation)?;
+
+        Ok(ExtendedMessage {
+            id_map: bencode::parse_id_map(ben_dict),
+            our_id: bencode::parse_client_id(ben_dict),
+            our_tcp_port: bencode::parse_client_tcp_port(ben_dict),
+            their_ip: bencode::parse_our_ip(ben_dict),
+            our_ipv6_addr: bencode::parse_client_ipv6_addr(ben_dict),
+            our_ipv4_addr: bencode::parse_client_ipv4_addr(ben_dict),
+            our_max_requests: bencode::parse_client_max_requests(ben_dict),
+            metadata_size: bencode::parse_metadata_size(ben_dict),
+            upload_only: bencode::parse_upload_only(ben_dict),
+            raw_bencode: bytes.clone(),
+        })
     }
 
     /// Write the `ExtendedMessage` out to the given writer.
@@ -345,11 +375,31 @@ impl ExtendedMessage {
         self.id_map.get(ext_type).map(|id| *id)
     }
 
+    /// Retrieve the id of every `ExtendedType` in the message.
+    ///
+    /// An id of 0 means the sender disabled the extension.
+    pub fn id_map(&self) -> &HashMap<ExtendedType, u8> {
+        &self.id_map
+    }
+
+    /// Query for the `ExtendedType` corresponding to the given id.
+    pub fn query_type(&self, id: u8) -> Option<&ExtendedType> {
+        self.id_map
+            .iter()
+            .find(|&(_, &ext_id)| ext_id == id)
+            .map(|(ext_type, _)| ext_type)
+    }
+
     /// Retrieve our id from the message.
     pub fn our_id(&self) -> Option<&str> {
         self.our_id.as_ref().map(|id| &**id)
     }
 
+    /// Retrieve the client name and version from the message.
+    pub fn client_version(&self) -> Option<&str> {
+        self.our_id()
+    }
+
     /// Retrieve our tcp port from the message.
     pub fn our_tcp_port(&self) -> Option<u16> {
         self.our_tcp_port
@@ -375,14 +425,132 @@ impl ExtendedMessage {
         self.our_max_requests
     }
 
+    /// Retrieve the maximum number of outstanding requests from the message.
+    ///
+    /// Values that do not fit in a `u32` are treated as if they were not sent.
+    pub fn request_queue_size(&self) -> Option<u32> {
+        self.our_max_requests.and_then(|max_requests| {
+            if max_requests as u32 as i64 == max_requests {
+                Some(max_requests as u32)
+            } else {
+                None
+            }
+        })
+    }
+
     /// Retrieve the info dictionary metadata size from the message.
     pub fn metadata_size(&self) -> Option<i64> {
         self.metadata_size
     }
 
+    /// Retrieve whether or not the sender is only uploading (is a seed).
+    pub fn upload_only(&self) -> Option<bool> {
+        self.upload_only
+    }
+
     /// Retrieve a raw `BencodeRef` representing the current message.
     pub fn bencode_ref<'a>(&'a self) -> BencodeRef<'a> {
         // We already verified that this is valid bencode
         BencodeRef::decode(&*self.raw_bencode, BDecodeOpt::default()).unwrap()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{ExtendedMessage, ExtendedMessageBuilder, ExtendedType};
+
+    use bytes::Bytes;
+    use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+    use crate::peer::message::ProtocolViolation;
+
+    fn parse(raw_bencode: Vec<u8>) -> ExtendedMessage {
+        ExtendedMessage::parse_bytes(Bytes::from(raw_bencode)).unwrap()
+    }
+
+    #[test]
+    fn positive_round_trip_optional_fields() {
+        let message = ExtendedMessageBuilder::new()
+            .with_client_version("bittorrent-protocol 0.3")
+            .with_request_queue_size(250)
+            .with_their_ip(Some(IpAddr::V4(Ipv4Addr::new(1, 2, 3, 4))))
+            .with_our_ip(IpAddr::V4(Ipv4Addr::new(5, 6, 7, 8)))
+            .with_our_ip(IpAddr::V6(Ipv6Addr::new(1, 0, 0, 0, 0, 0, 0, 1)))
+            .build();
+
+        let parsed = parse(message.bencode_ref().buffer().to_vec());
+
+        assert_eq!(Some("bittorrent-protocol 0.3"), parsed.client_version());
+        assert_eq!(Some(250), parsed.request_queue_size());
+        assert_eq!(Some(IpAddr::V4(Ipv4Addr::new(1, 2, 3, 4))), parsed.their_ip());
+        assert_eq!(Some(Ipv4Addr::new(5, 6, 7, 8)), parsed.our_ipv4_addr());
+        assert_eq!(
+            Some(Ipv6Addr::new(1, 0, 0, 0, 0, 0, 0, 1)),
+            parsed.our_ipv6_addr()
+        );
+    }
+
+    #[test]
+    fn positive_omit_unset_fields() {
+        let message = ExtendedMessageBuilder::new()
+            .with_extended_type(ExtendedType::UtMetadata, Some(1))
+            .build();
+
+        assert_eq!(&b"d1:md11:ut_metadatai1eee"[..], message.bencode_ref().buffer());
+        assert_eq!(None, message.client_version());
+        assert_eq!(None, message.request_queue_size());
+        assert_eq!(None, message.their_ip());
+    }
+
+    #[test]
+    fn positive_parse_tolerates_unknown_keys() {
+        let parsed = parse(b"d1:md6:ut_pexi2ee7:unknowni5e4:reqqi500e1:v5:Testee".to_vec());
+
+        assert_eq!(Some(2), parsed.query_id(&ExtendedType::UtPex));
+        assert_eq!(Some(500), parsed.request_queue_size());
+        assert_eq!(Some("Teste"), parsed.client_version());
+    }
+
+    #[test]
+    fn positive_round_trip_upload_only() {
+        let message = ExtendedMessageBuilder::new().with_upload_only(true).build();
+
+        assert_eq!(&b"d1:mde11:upload_onlyi1ee"[..], message.bencode_ref().buffer());
+        assert_eq!(Some(true), parse(message.bencode_ref().buffer().to_vec()).upload_only());
+    }
+
+    #[test]
+    fn positive_parse_upload_only_values() {
+        assert_eq!(Some(false), parse(b"d11:upload_onlyi0ee".to_vec()).upload_only());
+        assert_eq!(Some(true), parse(b"d11:upload_onlyi1ee".to_vec()).upload_only());
+        assert_eq!(None, parse(b"d11:upload_onlyi2ee".to_vec()).upload_only());
+        assert_eq!(None, parse(b"de".to_vec()).upload_only());
+    }
+
+    #[test]
+    fn positive_request_queue_size_out_of_range() {
+        let parsed = parse(b"d4:reqqi-1ee".to_vec());
+
+        assert_eq!(Some(-1), parsed.our_max_requests());
+        assert_eq!(None, parsed.request_queue_size());
+    }
+
+    #[test]
+    fn negative_parse_deeply_nested_value() {
+        let mut raw_bencode = b"d1:v".to_vec();
+        raw_bencode.extend(vec![b'l'; 100_000]);
+        raw_bencode.extend(vec![b'e'; 100_000]);
+        raw_bencode.push(b'e');
+
+        let error = ExtendedMessage::parse_bytes(Bytes::from(raw_bencode)).unwrap_err();
+
+        assert_eq!(Some("extended handshake"), ProtocolViolation::rule_of(&error));
+    }
+
+    #[test]
+    fn negative_parse_not_a_dictionary() {
+        let error = ExtendedMessage::parse_bytes(Bytes::from(&b"li1ee"[..])).unwrap_err();
+
+        assert_eq!(Some("extended handshake"), ProtocolViolation::rule_of(&error));
+    }
+}
@@ -4,12 +4,11 @@ use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
 
 use byteorder::{BigEndian, WriteBytesExt};
 use bytes::Bytes;
-use nom::{be_u16, be_u32, be_u8, IResult, Needed};
 
 use crate::bencode::{BConvert, BDecodeOpt, BMutAccess, BencodeMut, BencodeRef};
 use crate::util::convert;
 
-use crate::peer::message::{self, bencode};
+use crate::peer::message::{self, bencode, ProtocolViolation};
 
 const PORT_MESSAGE_LEN: u32 = 3;
 const BASE_EXTENDED_MESSAGE_LEN: u32 = 2;
@@ -37,8 +36,11 @@ pub enum BitsExtensionMessage {
 }
 
 impl BitsExtensionMessage {
-    pub fn parse_bytes(_input: (), bytes: Bytes) -> IResult<(), io::Result<BitsExtensionMessage>> {
-        parse_extension(bytes)
+    /// Parse a whole message (including its length prefix), any bytes after it are ignored.
+    pub fn parse_bytes(bytes: Bytes) -> io::Result<BitsExtensionMessage> {
+        let (id, payload) = message::split_message(&bytes)?;
+
+        parse_payload(id, payload)
     }
 
     pub fn write_bytes<W>(&self, writer: W) -> io::Result<()>
@@ -61,25 +63,24 @@ impl BitsExtensionMessage {
     }
 }
 
-fn parse_extension(mut bytes: Bytes) -> IResult<(), io::Result<BitsExtensionMessage>> {
-    let header_bytes = bytes.clone();
+/// Whether the payload of an `EXTENDED_MESSAGE_ID` message is for the extended handshake.
+pub(crate) fn is_extended_handshake(payload: &[u8]) -> bool {
+    payload.first() == Some(&EXTENDED_MESSAGE_HANDSHAKE_ID)
+}
 
-    alt!(
-        (),
-        ignore_input!(
-            switch!(header_bytes.as_ref(), throwaway_input!(tuple!(be_u32, be_u8)),
-                (PORT_MESSAGE_LEN, PORT_MESSAGE_ID) => map!(
-                    call!(PortMessage::parse_bytes, bytes.split_off(message::HEADER_LEN)),
-                    |res_port| res_port.map(|port| BitsExtensionMessage::Port(port))
-                )
-            )
-        ) | ignore_input!(
-            switch!(header_bytes.as_ref(), throwaway_input!(tuple!(be_u32, be_u8, be_u8)),
-                (message_len, EXTENDED_MESSAGE_ID, EXTENDED_MESSAGE_HANDSHAKE_ID) => map!(
-                    call!(ExtendedMessage::parse_bytes, bytes.split_off(message::HEADER_LEN + 1), message_len - 2),
-                    |res_extended| res_extended.map(|extended| BitsExtensionMessage::Extended(extended))
-                )
-            )
+/// Parse the payload (the message without its length prefix and id) of a message.
+pub(crate) fn parse_payload(id: u8, payload: Bytes) -> io::Result<BitsExtensionMessage> {
+    match id {
+        PORT_MESSAGE_ID => PortMessage::parse_bytes(payload).map(BitsExtensionMessage::Port),
+        EXTENDED_MESSAGE_ID if is_extended_handshake(&payload) => {
+            ExtendedMessage::parse_bytes(payload.slice_from(1)).map(BitsExtensionMessage::Extended)
+        }
+        EXTENDED_MESSAGE_ID => Err(ProtocolViolation::new(
+            "extended handshake id",
+            "Extended Message Was Not A Handshake",
         )
-    )
+        .into()),
+        id => Err(ProtocolViolation::new("message id", format!("Unknown Message Id {}", id))
+            .into_skippable()),
+    }
 }
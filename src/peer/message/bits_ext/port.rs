@@ -1,8 +1,6 @@
 use byteorder::WriteBytesExt;
-use byteorder::BigEndian;
+use byteorder::{BigEndian, ByteOrder};
 use bytes::Bytes;
-use nom::be_u16;
-use nom::IResult;
 use std::io;
 use std::io::Write;
 
@@ -19,12 +17,16 @@ impl PortMessage {
         PortMessage { port: port }
     }
 
-    pub fn parse_bytes(_input: (), bytes: Bytes) -> IResult<(), io::Result<PortMessage>> {
-        match parse_port(bytes.as_ref()) {
-            IResult::Done(_, result) => IResult::Done((), Ok(result)),
-            IResult::Error(err) => IResult::Error(err),
-            IResult::Incomplete(need) => IResult::Incomplete(need),
-        }
+    /// Port the DHT of the peer is listening on.
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    /// Parse the payload of a port message.
+    pub fn parse_bytes(bytes: Bytes) -> io::Result<PortMessage> {
+        message::check_payload_length(&bytes, bits_ext::PORT_MESSAGE_LEN, "port length")?;
+
+        Ok(PortMessage::new(BigEndian::read_u16(&bytes)))
     }
 
     pub fn write_bytes<W>(&self, mut writer: W) -> io::Result<()>
@@ -40,7 +42,3 @@ impl PortMessage {
         writer.write_u16::<BigEndian>(self.port)
     }
 }
-
-fn parse_port(bytes: &[u8]) -> IResult<&[u8], PortMessage> {
-    map!(bytes, be_u16, |port| PortMessage::new(port))
-}
@@ -0,0 +1,124 @@
+use std::cmp;
+use std::default::Default;
+use std::io;
+
+use crate::peer::message::{self, bits_ext, ProtocolViolation};
+
+const DEFAULT_MAX_BLOCK_LEN: usize = 16 * 1024;
+/// Enough for a torrent with a little over two million pieces.
+const DEFAULT_MAX_BITFIELD_LEN: usize = 256 * 1024;
+const DEFAULT_MAX_EXTENSION_LEN: usize = 1024 * 1024;
+
+/// Upper bounds on the lengths of messages we accept from a peer.
+///
+/// Lengths are checked as soon as the message header is available,
+/// so an oversized message is rejected before it is buffered.
+#[derive(PartialEq, Eq, Debug, Copy, Clone)]
+pub struct MessageLimits {
+    max_block_len: usize,
+    max_bitfield_len: usize,
+    max_extension_len: usize,
+    piece_count: Option<usize>,
+}
+
+impl MessageLimits {
+    /// Sets the maximum size of a block contained in a `PieceMessage`.
+    pub fn with_max_block_length(mut self, length: usize) -> MessageLimits {
+        self.max_block_len = length;
+        self
+    }
+
+    /// Sets the maximum size of the bitfield contained in a `BitFieldMessage`.
+    pub fn with_max_bitfield_length(mut self, length: usize) -> MessageLimits {
+        self.max_bitfield_len = length;
+        self
+    }
+
+    /// Sets the maximum size of the bitfield to that needed for the given number of pieces.
+    ///
+    /// Bitfields must then also be exactly that size, with none of the spare bits set.
+    pub fn with_piece_count(mut self, pieces: usize) -> MessageLimits {
+        self.piece_count = Some(pieces);
+        self.with_max_bitfield_length((pieces + 7) / 8)
+    }
+
+    /// Sets the maximum size of the payload of an extension message.
+    pub fn with_max_extension_length(mut self, length: usize) -> MessageLimits {
+        self.max_extension_len = length;
+        self
+    }
+
+    /// Gets the maximum block size.
+    pub fn max_block_length(&self) -> usize {
+        self.max_block_len
+    }
+
+    /// Gets the maximum bitfield size.
+    pub fn max_bitfield_length(&self) -> usize {
+        self.max_bitfield_len
+    }
+
+    /// Gets the number of pieces bitfields are validated against, if any.
+    pub fn piece_count(&self) -> Option<usize> {
+        self.piece_count
+    }
+
+    /// Gets the maximum extension payload size.
+    pub fn max_extension_length(&self) -> usize {
+        self.max_extension_len
+    }
+
+    /// Gets the maximum length (not including the length prefix) of any message.
+    pub fn max_message_length(&self) -> usize {
+        let max_piece = message::BASE_PIECE_MESSAGE_LEN as usize + self.max_block_len;
+        let max_bitfield = message::BASE_BITFIELD_MESSAGE_LEN as usize + self.max_bitfield_len;
+        let max_extension = message::BASE_PROT_EXTENSION_MESSAGE_LEN + self.max_extension_len;
+
+        cmp::max(max_piece, cmp::max(max_bitfield, max_extension))
+    }
+
+    /// Check the given message length (not including the length prefix) against our limits.
+    ///
+    /// If the message id is not yet available, we check against the largest message allowed.
+    pub fn check_length(&self, length: usize, opt_id: Option<u8>) -> io::Result<()> {
+        let (max_length, rule) = match opt_id {
+            Some(message::PIECE_MESSAGE_ID) => (
+                message::BASE_PIECE_MESSAGE_LEN as usize + self.max_block_len,
+                "block length",
+            ),
+            Some(message::BITFIELD_MESSAGE_ID) => (
+                message::BASE_BITFIELD_MESSAGE_LEN as usize + self.max_bitfield_len,
+                "bitfield length",
+            ),
+            Some(bits_ext::EXTENDED_MESSAGE_ID) => (
+                message::BASE_PROT_EXTENSION_MESSAGE_LEN + self.max_extension_len,
+                "extension length",
+            ),
+            _ => (self.max_message_length(), "message length"),
+        };
+
+        if length > max_length {
+            Err(ProtocolViolation::new(
+                rule,
+                format!(
+                    "Message Length {} Exceeds Limit Of {} For Message Id {:?}",
+                    length, max_length, opt_id
+                ),
+            )
+            .into())
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl Default for MessageLimits {
+    fn default() -> MessageLimits {
+        MessageLimits {
+            max_block_len: DEFAULT_MAX_BLOCK_LEN,
+            max_bitfield_len: DEFAULT_MAX_BITFIELD_LEN,
+            max_extension_len: DEFAULT_MAX_EXTENSION_LEN,
+            piece_count: None,
+        }
+    }
+}
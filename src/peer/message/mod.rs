@@ -1,24 +1,29 @@
 #![allow(unused)]
 //! Serializable and deserializable protocol messages.
 
-// TODO: Propogate failures to cast values to/from usize
-
 use std::io::{self, Write};
 
-use byteorder::{BigEndian, WriteBytesExt};
+use byteorder::{BigEndian, ByteOrder, WriteBytesExt};
 use bytes::Bytes;
-use nom::{be_u32, be_u8, IResult};
 
 pub use bits_ext::{
     BitsExtensionMessage, ExtendedMessage, ExtendedMessageBuilder, ExtendedType, PortMessage,
 };
 pub use prot_ext::{
-    PeerExtensionProtocolMessage, UtMetadataDataMessage, UtMetadataMessage,
-    UtMetadataRejectMessage, UtMetadataRequestMessage,NullProtocolMessage,
+    metadata_piece_len, num_metadata_pieces, DontHaveMessage, PeerExtensionProtocolMessage,
+    UtMetadataDataMessage, UtMetadataError, UtMetadataMessage, UtMetadataRejectMessage,
+    UtMetadataRequestMessage, UtPexMessage, CustomExtensionMessage, UtHolepunchErrorCode,
+    UtHolepunchMessage, UT_METADATA_PIECE_LEN,
+    UT_PEX_FLAG_ENCRYPTION, UT_PEX_FLAG_HOLEPUNCH, UT_PEX_FLAG_REACHABLE, UT_PEX_FLAG_SEED,
+    UT_PEX_FLAG_UTP,
 };
+pub use limits::MessageLimits;
 pub use standard::{
-    BitFieldIter, BitFieldMessage, CancelMessage, HaveMessage, PieceMessage, RequestMessage,
+    AllowedFastMessage, BitFieldIter, BitFieldMessage, CancelMessage, HaveMessage, PieceMessage,
+    RejectMessage, RequestMessage, SuggestMessage,
 };
+pub use validation::{MessageValidator, ValidationError, MAX_BLOCK_LEN};
+pub use violation::ProtocolViolation;
 
 use super::manager::ManagedMessage;
 
@@ -32,6 +37,11 @@ const BASE_BITFIELD_MESSAGE_LEN: u32 = 1;
 const REQUEST_MESSAGE_LEN: u32 = 13;
 const BASE_PIECE_MESSAGE_LEN: u32 = 9;
 const CANCEL_MESSAGE_LEN: u32 = 13;
+const SUGGEST_MESSAGE_LEN: u32 = 5;
+const HAVE_ALL_MESSAGE_LEN: u32 = 1;
+const HAVE_NONE_MESSAGE_LEN: u32 = 1;
+const REJECT_MESSAGE_LEN: u32 = 13;
+const ALLOWED_FAST_MESSAGE_LEN: u32 = 5;
 
 const CHOKE_MESSAGE_ID: u8 = 0;
 const UNCHOKE_MESSAGE_ID: u8 = 1;
@@ -42,18 +52,25 @@ const BITFIELD_MESSAGE_ID: u8 = 5;
 const REQUEST_MESSAGE_ID: u8 = 6;
 const PIECE_MESSAGE_ID: u8 = 7;
 const CANCEL_MESSAGE_ID: u8 = 8;
+const SUGGEST_MESSAGE_ID: u8 = 13;
+const HAVE_ALL_MESSAGE_ID: u8 = 14;
+const HAVE_NONE_MESSAGE_ID: u8 = 15;
+const REJECT_MESSAGE_ID: u8 = 16;
+const ALLOWED_FAST_MESSAGE_ID: u8 = 17;
 
 const MESSAGE_LENGTH_LEN_BYTES: usize = 4;
 const MESSAGE_ID_LEN_BYTES: usize = 1;
 const HEADER_LEN: usize = MESSAGE_LENGTH_LEN_BYTES + MESSAGE_ID_LEN_BYTES;
 const BASE_PROT_EXTENSION_MESSAGE_LEN: usize = 2;
-// Nom has lots of unused warnings atm, keep this here for now.
 
 mod bencode;
+mod limits;
 
 mod prot_ext;
 mod bits_ext;
 mod standard;
+mod validation;
+mod violation;
 
 /// Enumeration of messages for `PeerWireProtocol`.
 #[derive(Debug,PartialEq)]
@@ -83,12 +100,26 @@ pub enum PeerWireProtocolMessage
     Piece(PieceMessage),
     /// Message to cancel a block request from a peer.
     Cancel(CancelMessage),
+    /// Message to tell a peer we have all pieces.
+    ///
+    /// Fast extension replacement for a full `BitField` message.
+    HaveAll,
+    /// Message to tell a peer we have no pieces.
+    ///
+    /// Fast extension replacement for an empty `BitField` message.
+    HaveNone,
+    /// Message to suggest a piece a peer may want to download from us.
+    Suggest(SuggestMessage),
+    /// Message to tell a peer we will not be responding to one of their requests.
+    Reject(RejectMessage),
+    /// Message to tell a peer they may request a piece from us even while choked.
+    AllowedFast(AllowedFastMessage),
     /// Extension messages which are activated via the `ExtensionBits` as part of the handshake.
     BitsExtension(BitsExtensionMessage),
     /// Extension messages which are activated via the Extension Protocol.
     ///
-    /// In reality, this can be any type that implements `ProtocolMessage` if, for example,
-    /// you are running a private swarm where you know all nodes support a given message(s).
+    /// User defined extensions, for example in a private swarm, can be registered as an
+    /// `ExtendedType::Custom` in our `ExtendedMessage` and are delivered as `Custom` messages.
     ProtExtension(PeerExtensionProtocolMessage),
 }
 
@@ -108,24 +139,82 @@ impl ManagedMessage for PeerWireProtocolMessage {
 
 impl PeerWireProtocolMessage
 {
-    pub fn bytes_needed(bytes: &[u8]) -> io::Result<Option<usize>> {
-        match be_u32(bytes) {
-            // We need 4 bytes for the length, plus whatever the length is...
-            IResult::Done(_, length) => Ok(Some(MESSAGE_LENGTH_LEN_BYTES + u32_to_usize(length))),
-            _ => Ok(None),
+    /// Whether or not this message is only valid if the fast extension was negotiated.
+    pub fn is_fast_extension(&self) -> bool {
+        match self {
+            &PeerWireProtocolMessage::HaveAll
+            | &PeerWireProtocolMessage::HaveNone
+            | &PeerWireProtocolMessage::Suggest(_)
+            | &PeerWireProtocolMessage::Reject(_)
+            | &PeerWireProtocolMessage::AllowedFast(_) => true,
+            _ => false,
+        }
+    }
+
+    /// Short name of the message, for logging.
+    pub fn name(&self) -> &'static str {
+        match *self {
+            PeerWireProtocolMessage::KeepAlive => "keep_alive",
+            PeerWireProtocolMessage::Choke => "choke",
+            PeerWireProtocolMessage::UnChoke => "unchoke",
+            PeerWireProtocolMessage::Interested => "interested",
+            PeerWireProtocolMessage::UnInterested => "not_interested",
+            PeerWireProtocolMessage::Have(_) => "have",
+            PeerWireProtocolMessage::BitField(_) => "bitfield",
+            PeerWireProtocolMessage::Request(_) => "request",
+            PeerWireProtocolMessage::Piece(_) => "piece",
+            PeerWireProtocolMessage::Cancel(_) => "cancel",
+            PeerWireProtocolMessage::HaveAll => "have_all",
+            PeerWireProtocolMessage::HaveNone => "have_none",
+            PeerWireProtocolMessage::Suggest(_) => "suggest",
+            PeerWireProtocolMessage::Reject(_) => "reject",
+            PeerWireProtocolMessage::AllowedFast(_) => "allowed_fast",
+            PeerWireProtocolMessage::BitsExtension(BitsExtensionMessage::Port(_)) => "port",
+            PeerWireProtocolMessage::BitsExtension(BitsExtensionMessage::Extended(_)) => {
+                "extended_handshake"
+            }
+            PeerWireProtocolMessage::ProtExtension(_) => "extension",
         }
     }
 
+    pub fn bytes_needed(bytes: &[u8], limits: &MessageLimits) -> io::Result<Option<usize>> {
+        if bytes.len() < MESSAGE_LENGTH_LEN_BYTES {
+            return Ok(None);
+        }
+
+        // We need 4 bytes for the length, plus whatever the length is...
+        let length = check_message_length(bytes, limits)?;
+        length
+            .checked_add(MESSAGE_LENGTH_LEN_BYTES)
+            .map(Some)
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "Message Length Overflows usize When Including Length Prefix",
+                )
+            })
+    }
+
+    /// Parse the message at the front of the given bytes, any bytes after it are ignored.
+    ///
+    /// An error of kind `UnexpectedEof` means the message has not been fully buffered yet and
+    /// may parse once more bytes arrive. An error of kind `InvalidData` means the message is
+    /// malformed, any other error is for a whole message we do not understand, which can be
+    /// skipped. Either way, the rule it broke can be recovered with `ProtocolViolation::rule_of`.
     pub fn parse_bytes(
         bytes: Bytes,
-        extended: &Option<ExtendedMessage>
+        extended: &Option<ExtendedMessage>,
+        limits: &MessageLimits,
     ) -> io::Result<PeerWireProtocolMessage> {
-        match parse_message(bytes,extended) {
-            IResult::Done(_, result) => result,
-            _ => Err(io::Error::new(
-                io::ErrorKind::Other,
-                "Failed To Parse PeerWireProtocolMessage",
-            )),
+        if bytes.len() >= MESSAGE_LENGTH_LEN_BYTES {
+            check_message_length(bytes.as_ref(), limits)?;
+        }
+
+        match (parse_message(bytes, extended), limits.piece_count()) {
+            (Ok(PeerWireProtocolMessage::BitField(bitfield)), Some(piece_count)) => bitfield
+                .validate(piece_count)
+                .map(|_| PeerWireProtocolMessage::BitField(bitfield)),
+            (result, _) => result,
         }
     }
 
@@ -156,6 +245,15 @@ impl PeerWireProtocolMessage
             &PeerWireProtocolMessage::Request(ref msg) => msg.write_bytes(writer),
             &PeerWireProtocolMessage::Piece(ref msg) => msg.write_bytes(writer),
             &PeerWireProtocolMessage::Cancel(ref msg) => msg.write_bytes(writer),
+            &PeerWireProtocolMessage::HaveAll => {
+                write_length_id_pair(writer, HAVE_ALL_MESSAGE_LEN, Some(HAVE_ALL_MESSAGE_ID))
+            }
+            &PeerWireProtocolMessage::HaveNone => {
+                write_length_id_pair(writer, HAVE_NONE_MESSAGE_LEN, Some(HAVE_NONE_MESSAGE_ID))
+            }
+            &PeerWireProtocolMessage::Suggest(ref msg) => msg.write_bytes(writer),
+            &PeerWireProtocolMessage::Reject(ref msg) => msg.write_bytes(writer),
+            &PeerWireProtocolMessage::AllowedFast(ref msg) => msg.write_bytes(writer),
             &PeerWireProtocolMessage::BitsExtension(ref ext) => ext.write_bytes(writer),
             &PeerWireProtocolMessage::ProtExtension(ref ext) => {
                 ext.write_bytes( writer,extended)
@@ -179,6 +277,11 @@ impl PeerWireProtocolMessage
                 BASE_PIECE_MESSAGE_LEN as usize + msg.block().len()
             }
             &PeerWireProtocolMessage::Cancel(_) => CANCEL_MESSAGE_LEN as usize,
+            &PeerWireProtocolMessage::HaveAll => HAVE_ALL_MESSAGE_LEN as usize,
+            &PeerWireProtocolMessage::HaveNone => HAVE_NONE_MESSAGE_LEN as usize,
+            &PeerWireProtocolMessage::Suggest(_) => SUGGEST_MESSAGE_LEN as usize,
+            &PeerWireProtocolMessage::Reject(_) => REJECT_MESSAGE_LEN as usize,
+            &PeerWireProtocolMessage::AllowedFast(_) => ALLOWED_FAST_MESSAGE_LEN as usize,
             &PeerWireProtocolMessage::BitsExtension(ref ext) => ext.message_size(),
             &PeerWireProtocolMessage::ProtExtension(ref ext) =>{
                 BASE_PROT_EXTENSION_MESSAGE_LEN + ext.message_size()
@@ -205,85 +308,396 @@ where
 
 /// Parse the length portion of a message.
 ///
-/// Panics if parsing failed for any reason.
-fn parse_message_length(bytes: &[u8]) -> usize {
-    if let IResult::Done(_, len) = be_u32(bytes) {
-        u32_to_usize(len)
+/// Returns an error if the length was less than 4 bytes or does not fit in a `usize`.
+fn parse_message_length(bytes: &[u8]) -> io::Result<usize> {
+    if bytes.len() >= MESSAGE_LENGTH_LEN_BYTES {
+        u32_to_usize(BigEndian::read_u32(bytes))
     } else {
-        panic!("bittorrent-protocol_peer: Message Length Was Less Than 4 Bytes")
+        Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "Message Length Was Less Than 4 Bytes",
+        ))
     }
 }
 
-/// Panics if the conversion from a u32 to usize is not valid.
-fn u32_to_usize(value: u32) -> usize {
+/// Parse the length portion of a message and check it against the given limits.
+///
+/// The message id is included in the check if it is available.
+fn check_message_length(bytes: &[u8], limits: &MessageLimits) -> io::Result<usize> {
+    let length = parse_message_length(bytes)?;
+    let opt_id = bytes.get(MESSAGE_LENGTH_LEN_BYTES).map(|id| *id);
+
+    limits.check_length(length, opt_id).map(|_| length)
+}
+
+/// Returns an error if the conversion from a u32 to usize is not valid.
+fn u32_to_usize(value: u32) -> io::Result<usize> {
     if value as usize as u32 != value {
-        panic!("bittorrent-protocol_peer: Cannot Convert u32 To usize, usize Is Less Than 32-Bits")
+        Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "Cannot Convert u32 To usize, usize Is Less Than 32-Bits",
+        ))
+    } else {
+        Ok(value as usize)
+    }
+}
+
+/// Error for a message that is cut short, it may still parse once more bytes arrive.
+fn incomplete(needed: usize) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::UnexpectedEof,
+        format!("Message Needs {} More Bytes", needed),
+    )
+}
+
+/// Split a whole message into its id and its payload, dropping any bytes after it.
+///
+/// Keep alives have no id, so they have to be handled before getting here.
+fn split_message(bytes: &Bytes) -> io::Result<(u8, Bytes)> {
+    let length = parse_message_length(bytes.as_ref())?;
+    if length == KEEP_ALIVE_MESSAGE_LEN as usize {
+        return Err(ProtocolViolation::new("message length", "Message Has No Id").into());
     }
 
-    value as usize
+    let end = length.checked_add(MESSAGE_LENGTH_LEN_BYTES).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            "Message Length Overflows usize When Including Length Prefix",
+        )
+    })?;
+    if bytes.len() < end {
+        return Err(incomplete(end - bytes.len()));
+    }
+
+    Ok((bytes[MESSAGE_LENGTH_LEN_BYTES], bytes.slice(HEADER_LEN, end)))
+}
+
+/// Check the payload (the message without its length prefix and id) against the length of a
+/// fixed size message.
+///
+/// The payload was already cut to the length prefix, so more bytes would not help.
+fn check_payload_length(payload: &[u8], message_len: u32, rule: &'static str) -> io::Result<()> {
+    let expected = message_len as usize - MESSAGE_ID_LEN_BYTES;
+
+    if payload.len() == expected {
+        Ok(())
+    } else {
+        Err(ProtocolViolation::new(
+            rule,
+            format!(
+                "Message Payload Was {} Bytes But Expected {}",
+                payload.len(),
+                expected
+            ),
+        )
+        .into())
+    }
 }
 
-// Since these messages may come over a stream oriented protocol, if a message is incomplete
-// the number of bytes needed will be returned. However, that number of bytes is on a per parser
-// basis. If possible, we should return the number of bytes needed for the rest of the WHOLE message.
-// This allows clients to only re invoke the parser when it knows it has enough of the data.
 fn parse_message(
-    mut bytes: Bytes,
-    extended: &Option<ExtendedMessage>
-) -> IResult<(), io::Result<PeerWireProtocolMessage>>
-{
-    let header_bytes = bytes.clone();
-
-    // Attempt to parse a built in message type, otherwise, see if it is an extension type.
-    alt!(
-        (),
-        ignore_input!(
-            switch!(header_bytes.as_ref(), throwaway_input!(tuple!(be_u32, opt!(be_u8))),
-                (KEEP_ALIVE_MESSAGE_LEN, None) => value!(
-                    Ok(PeerWireProtocolMessage::KeepAlive)
-                ) |
-                (KEEP_ALIVE_MESSAGE_LEN, Some(0)) => value!(
-                    Ok(PeerWireProtocolMessage::KeepAlive)
-                ) |
-                (CHOKE_MESSAGE_LEN, Some(CHOKE_MESSAGE_ID)) => value!(
-                    Ok(PeerWireProtocolMessage::Choke)
-                ) |
-                (UNCHOKE_MESSAGE_LEN, Some(UNCHOKE_MESSAGE_ID)) => value!(
-                    Ok(PeerWireProtocolMessage::UnChoke)
-                ) |
-                (INTERESTED_MESSAGE_LEN, Some(INTERESTED_MESSAGE_ID)) => value!(
-                    Ok(PeerWireProtocolMessage::Interested)
-                ) |
-                (UNINTERESTED_MESSAGE_LEN, Some(UNINTERESTED_MESSAGE_ID)) => value!(
-                    Ok(PeerWireProtocolMessage::UnInterested)
-                ) |
-                (HAVE_MESSAGE_LEN, Some(HAVE_MESSAGE_ID)) => map!(
-                    call!(HaveMessage::parse_bytes, bytes.split_off(HEADER_LEN)),
-                    |res_have| res_have.map(|have| PeerWireProtocolMessage::Have(have))
-                ) |
-                (message_len, Some(BITFIELD_MESSAGE_ID)) => map!(
-                    call!(BitFieldMessage::parse_bytes, bytes.split_off(HEADER_LEN), message_len - 1),
-                    |res_bitfield| res_bitfield.map(|bitfield| PeerWireProtocolMessage::BitField(bitfield))
-                ) |
-                (REQUEST_MESSAGE_LEN, Some(REQUEST_MESSAGE_ID)) => map!(
-                    call!(RequestMessage::parse_bytes, bytes.split_off(HEADER_LEN)),
-                    |res_request| res_request.map(|request| PeerWireProtocolMessage::Request(request))
-                ) |
-                (message_len, Some(PIECE_MESSAGE_ID)) => map!(
-                    call!(PieceMessage::parse_bytes, bytes.split_off(HEADER_LEN), message_len - 1),
-                    |res_piece| res_piece.map(|piece| PeerWireProtocolMessage::Piece(piece))
-                ) |
-                (CANCEL_MESSAGE_LEN, Some(CANCEL_MESSAGE_ID)) => map!(
-                    call!(CancelMessage::parse_bytes, bytes.split_off(HEADER_LEN)),
-                    |res_cancel| res_cancel.map(|cancel| PeerWireProtocolMessage::Cancel(cancel))
-                )
-            )
-        ) | map!(
-            call!(BitsExtensionMessage::parse_bytes, bytes.clone()),
-            |res_bits_ext| res_bits_ext
-                .map(|bits_ext| PeerWireProtocolMessage::BitsExtension(bits_ext))
-        ) | map!(value!(PeerExtensionProtocolMessage::parse_bytes(bytes,extended)), |res_prot_ext| {
-            res_prot_ext.map(|prot_ext| PeerWireProtocolMessage::ProtExtension(prot_ext))
-        })
-    )
+    bytes: Bytes,
+    extended: &Option<ExtendedMessage>,
+) -> io::Result<PeerWireProtocolMessage> {
+    // Any byte after a keep alive belongs to the next message
+    if parse_message_length(bytes.as_ref())? == KEEP_ALIVE_MESSAGE_LEN as usize {
+        return Ok(PeerWireProtocolMessage::KeepAlive);
+    }
+    let (id, payload) = split_message(&bytes)?;
+
+    match id {
+        CHOKE_MESSAGE_ID => check_payload_length(&payload, CHOKE_MESSAGE_LEN, "choke length")
+            .map(|_| PeerWireProtocolMessage::Choke),
+        UNCHOKE_MESSAGE_ID => {
+            check_payload_length(&payload, UNCHOKE_MESSAGE_LEN, "unchoke length")
+                .map(|_| PeerWireProtocolMessage::UnChoke)
+        }
+        INTERESTED_MESSAGE_ID => {
+            check_payload_length(&payload, INTERESTED_MESSAGE_LEN, "interested length")
+                .map(|_| PeerWireProtocolMessage::Interested)
+        }
+        UNINTERESTED_MESSAGE_ID => {
+            check_payload_length(&payload, UNINTERESTED_MESSAGE_LEN, "not interested length")
+                .map(|_| PeerWireProtocolMessage::UnInterested)
+        }
+        HAVE_MESSAGE_ID => HaveMessage::parse_bytes(payload).map(PeerWireProtocolMessage::Have),
+        BITFIELD_MESSAGE_ID => {
+            BitFieldMessage::parse_bytes(payload).map(PeerWireProtocolMessage::BitField)
+        }
+        REQUEST_MESSAGE_ID => {
+            RequestMessage::parse_bytes(payload).map(PeerWireProtocolMessage::Request)
+        }
+        PIECE_MESSAGE_ID => PieceMessage::parse_bytes(payload).map(PeerWireProtocolMessage::Piece),
+        CANCEL_MESSAGE_ID => {
+            CancelMessage::parse_bytes(payload).map(PeerWireProtocolMessage::Cancel)
+        }
+        SUGGEST_MESSAGE_ID => {
+            SuggestMessage::parse_bytes(payload).map(PeerWireProtocolMessage::Suggest)
+        }
+        HAVE_ALL_MESSAGE_ID => {
+            check_payload_length(&payload, HAVE_ALL_MESSAGE_LEN, "have all length")
+                .map(|_| PeerWireProtocolMessage::HaveAll)
+        }
+        HAVE_NONE_MESSAGE_ID => {
+            check_payload_length(&payload, HAVE_NONE_MESSAGE_LEN, "have none length")
+                .map(|_| PeerWireProtocolMessage::HaveNone)
+        }
+        REJECT_MESSAGE_ID => {
+            RejectMessage::parse_bytes(payload).map(PeerWireProtocolMessage::Reject)
+        }
+        ALLOWED_FAST_MESSAGE_ID => {
+            AllowedFastMessage::parse_bytes(payload).map(PeerWireProtocolMessage::AllowedFast)
+        }
+        bits_ext::EXTENDED_MESSAGE_ID if !bits_ext::is_extended_handshake(&payload) => {
+            prot_ext::parse_payload(payload, extended).map(PeerWireProtocolMessage::ProtExtension)
+        }
+        id => bits_ext::parse_payload(id, payload).map(PeerWireProtocolMessage::BitsExtension),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        AllowedFastMessage, MessageLimits, PeerWireProtocolMessage, ProtocolViolation,
+        RejectMessage, SuggestMessage,
+    };
+
+    use bytes::Bytes;
+    use std::io;
+
+    fn round_trip(message: PeerWireProtocolMessage) {
+        let mut bytes = Vec::new();
+        message.write_bytes(&mut bytes, &None).unwrap();
+
+        assert_eq!(message.message_size(), bytes.len());
+        assert_eq!(
+            Some(bytes.len()),
+            PeerWireProtocolMessage::bytes_needed(&bytes, &MessageLimits::default()).unwrap()
+        );
+
+        let parsed = PeerWireProtocolMessage::parse_bytes(Bytes::from(bytes), &None, &MessageLimits::default()).unwrap();
+        assert_eq!(message, parsed);
+    }
+
+    #[test]
+    fn positive_round_trip_have_all() {
+        round_trip(PeerWireProtocolMessage::HaveAll);
+    }
+
+    #[test]
+    fn positive_round_trip_have_none() {
+        round_trip(PeerWireProtocolMessage::HaveNone);
+    }
+
+    #[test]
+    fn positive_round_trip_suggest() {
+        round_trip(PeerWireProtocolMessage::Suggest(SuggestMessage::new(34)));
+    }
+
+    #[test]
+    fn positive_round_trip_reject() {
+        round_trip(PeerWireProtocolMessage::Reject(RejectMessage::new(
+            12, 16384, 16384,
+        )));
+    }
+
+    #[test]
+    fn positive_round_trip_allowed_fast() {
+        round_trip(PeerWireProtocolMessage::AllowedFast(AllowedFastMessage::new(
+            7,
+        )));
+    }
+
+    #[test]
+    fn positive_parse_have_all_bytes() {
+        let parsed =
+            PeerWireProtocolMessage::parse_bytes(Bytes::from(vec![0, 0, 0, 1, 0x0E]), &None, &MessageLimits::default())
+                .unwrap();
+
+        assert_eq!(PeerWireProtocolMessage::HaveAll, parsed);
+    }
+
+    #[test]
+    fn positive_bytes_needed_truncated_length() {
+        let limits = MessageLimits::default();
+
+        assert_eq!(None, PeerWireProtocolMessage::bytes_needed(&[0, 0, 1], &limits).unwrap());
+    }
+
+    #[test]
+    fn positive_bytes_needed_max_length() {
+        let limits = MessageLimits::default().with_max_extension_length(u32::max_value() as usize);
+        let needed = PeerWireProtocolMessage::bytes_needed(&[0xFF, 0xFF, 0xFF, 0xFF], &limits).unwrap();
+
+        assert_eq!(Some(u32::max_value() as usize + 4), needed);
+    }
+
+    #[test]
+    fn negative_bytes_needed_max_length_default_limits() {
+        let limits = MessageLimits::default();
+        let error = PeerWireProtocolMessage::bytes_needed(&[0xFF, 0xFF, 0xFF, 0xFF], &limits).unwrap_err();
+
+        assert_eq!(io::ErrorKind::InvalidData, error.kind());
+    }
+
+    #[test]
+    fn negative_bytes_needed_huge_bitfield() {
+        let limits = MessageLimits::default().with_piece_count(100);
+        // 1 byte id + 14 bytes of bitfield, 100 pieces only needs 13
+        let error = PeerWireProtocolMessage::bytes_needed(&[0, 0, 0, 15, 5], &limits).unwrap_err();
+
+        assert_eq!(io::ErrorKind::InvalidData, error.kind());
+        assert_eq!(Some("bitfield length"), ProtocolViolation::rule_of(&error));
+        assert_eq!(
+            Some(18),
+            PeerWireProtocolMessage::bytes_needed(&[0, 0, 0, 14, 5], &limits).unwrap()
+        );
+    }
+
+    #[test]
+    fn negative_bytes_needed_huge_piece_block() {
+        let limits = MessageLimits::default();
+        // 1 byte id + 8 bytes header + 16 KiB + 1 byte block
+        let error = PeerWireProtocolMessage::bytes_needed(&[0x00, 0x00, 0x40, 0x0A, 7], &limits).unwrap_err();
+
+        assert_eq!(io::ErrorKind::InvalidData, error.kind());
+        assert_eq!(
+            Some(16 * 1024 + 13),
+            PeerWireProtocolMessage::bytes_needed(&[0x00, 0x00, 0x40, 0x09, 7], &limits).unwrap()
+        );
+    }
+
+    #[test]
+    fn negative_parse_huge_piece_block_before_buffered() {
+        let limits = MessageLimits::default().with_max_block_length(4);
+        // Only the header has arrived, the limit is checked before waiting on the block
+        let bytes = Bytes::from(vec![0, 0, 0, 14, 7, 0, 0, 0, 0]);
+        let error = PeerWireProtocolMessage::parse_bytes(bytes, &None, &limits).unwrap_err();
+
+        assert_eq!(io::ErrorKind::InvalidData, error.kind());
+    }
+
+    #[test]
+    fn negative_parse_message_length_truncated() {
+        let error = super::parse_message_length(&[0, 0]).unwrap_err();
+
+        assert_eq!(io::ErrorKind::UnexpectedEof, error.kind());
+    }
+
+    #[test]
+    fn negative_parse_truncated_length() {
+        let error = PeerWireProtocolMessage::parse_bytes(Bytes::from(vec![0, 0]), &None, &MessageLimits::default()).unwrap_err();
+
+        assert_eq!(io::ErrorKind::UnexpectedEof, error.kind());
+    }
+
+    #[test]
+    fn negative_parse_truncated_payload_needs_bytes() {
+        let bytes = Bytes::from(vec![0, 0, 0, 5, 4, 0, 0]);
+        let error = PeerWireProtocolMessage::parse_bytes(bytes, &None, &MessageLimits::default()).unwrap_err();
+
+        assert_eq!(io::ErrorKind::UnexpectedEof, error.kind());
+        assert_eq!(None, ProtocolViolation::rule_of(&error));
+    }
+
+    #[test]
+    fn negative_parse_fixed_length_mismatch() {
+        // A have message with a piece index one byte short, the length prefix says it is done
+        let bytes = Bytes::from(vec![0, 0, 0, 4, 4, 0, 0, 1]);
+        let error = PeerWireProtocolMessage::parse_bytes(bytes, &None, &MessageLimits::default()).unwrap_err();
+
+        assert_eq!(io::ErrorKind::InvalidData, error.kind());
+        assert_eq!(Some("have length"), ProtocolViolation::rule_of(&error));
+
+        let bytes = Bytes::from(vec![0, 0, 0, 2, 0, 0]);
+        let error = PeerWireProtocolMessage::parse_bytes(bytes, &None, &MessageLimits::default()).unwrap_err();
+
+        assert_eq!(Some("choke length"), ProtocolViolation::rule_of(&error));
+    }
+
+    #[test]
+    fn negative_parse_unknown_message_id() {
+        let bytes = Bytes::from(vec![0, 0, 0, 1, 0x7F]);
+        let error = PeerWireProtocolMessage::parse_bytes(bytes, &None, &MessageLimits::default()).unwrap_err();
+
+        // Skippable, peers may send messages from extensions we do not know of
+        assert_eq!(io::ErrorKind::Other, error.kind());
+        assert_eq!(Some("message id"), ProtocolViolation::rule_of(&error));
+    }
+
+    #[test]
+    fn negative_parse_extension_before_extended() {
+        let bytes = Bytes::from(vec![0, 0, 0, 3, 20, 1, 0]);
+        let error = PeerWireProtocolMessage::parse_bytes(bytes, &None, &MessageLimits::default()).unwrap_err();
+
+        assert_eq!(Some("extension before extended"), ProtocolViolation::rule_of(&error));
+    }
+
+    #[test]
+    fn negative_parse_piece_length_shorter_than_header() {
+        let bytes = Bytes::from(vec![0, 0, 0, 5, 7, 0, 0, 0, 1]);
+        let error = PeerWireProtocolMessage::parse_bytes(bytes, &None, &MessageLimits::default()).unwrap_err();
+
+        assert_eq!(io::ErrorKind::InvalidData, error.kind());
+        assert_eq!(Some("piece length"), ProtocolViolation::rule_of(&error));
+    }
+
+    #[test]
+    fn negative_parse_extended_length_shorter_than_header() {
+        let bytes = Bytes::from(vec![0, 0, 0, 1, 20, 0]);
+        let error = PeerWireProtocolMessage::parse_bytes(bytes, &None, &MessageLimits::default()).unwrap_err();
+
+        assert_eq!(Some("extended length"), ProtocolViolation::rule_of(&error));
+    }
+
+    #[test]
+    fn positive_parse_bitfield_with_piece_count() {
+        let limits = MessageLimits::default().with_piece_count(10);
+        let bytes = Bytes::from(vec![0, 0, 0, 3, 5, 0xFF, 0xC0]);
+
+        let parsed = PeerWireProtocolMessage::parse_bytes(bytes, &None, &limits).unwrap();
+
+        match parsed {
+            PeerWireProtocolMessage::BitField(bitfield) => assert_eq!(10, bitfield.iter().count()),
+            other => panic!("Unexpected Message {:?}", other),
+        }
+    }
+
+    #[test]
+    fn negative_parse_bitfield_spare_bits_with_piece_count() {
+        let limits = MessageLimits::default().with_piece_count(10);
+        let bytes = Bytes::from(vec![0, 0, 0, 3, 5, 0xFF, 0xE0]);
+
+        let error = PeerWireProtocolMessage::parse_bytes(bytes.clone(), &None, &limits).unwrap_err();
+
+        assert_eq!(io::ErrorKind::InvalidData, error.kind());
+        assert_eq!(Some("bitfield spare bits"), ProtocolViolation::rule_of(&error));
+        assert!(PeerWireProtocolMessage::parse_bytes(bytes, &None, &MessageLimits::default()).is_ok());
+    }
+
+    #[test]
+    fn negative_parse_bitfield_short_with_piece_count() {
+        let limits = MessageLimits::default().with_piece_count(10);
+        let bytes = Bytes::from(vec![0, 0, 0, 2, 5, 0xFF]);
+
+        let error = PeerWireProtocolMessage::parse_bytes(bytes, &None, &limits).unwrap_err();
+
+        assert_eq!(io::ErrorKind::InvalidData, error.kind());
+        assert_eq!(Some("bitfield length"), ProtocolViolation::rule_of(&error));
+    }
+
+    #[test]
+    fn positive_parse_keep_alive() {
+        let bytes = Bytes::from(vec![0, 0, 0, 0]);
+        let parsed = PeerWireProtocolMessage::parse_bytes(bytes, &None, &MessageLimits::default()).unwrap();
+
+        assert_eq!(PeerWireProtocolMessage::KeepAlive, parsed);
+    }
+
+    #[test]
+    fn positive_parse_keep_alive_followed_by_message() {
+        let bytes = Bytes::from(vec![0, 0, 0, 0, 5, 0, 0, 0]);
+        let parsed = PeerWireProtocolMessage::parse_bytes(bytes, &None, &MessageLimits::default()).unwrap();
+
+        assert_eq!(PeerWireProtocolMessage::KeepAlive, parsed);
+    }
 }
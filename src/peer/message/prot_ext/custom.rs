@@ -0,0 +1,116 @@
+use bytes::Bytes;
+use std::io::{self, Write};
+
+use crate::peer::message::ExtendedType;
+
+/// Message for a user defined extension, registered via `ExtendedMessageBuilder::with_extended_type`.
+///
+/// The payload is passed through untouched, so it may hold bencode or any binary format
+/// agreed upon by the peers of a (private) swarm.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CustomExtensionMessage {
+    ext_type: ExtendedType,
+    payload: Bytes,
+}
+
+impl CustomExtensionMessage {
+    pub fn new(ext_type: ExtendedType, payload: Bytes) -> CustomExtensionMessage {
+        CustomExtensionMessage {
+            ext_type: ext_type,
+            payload: payload,
+        }
+    }
+
+    pub fn write_bytes<W>(&self, mut writer: W) -> io::Result<()>
+    where
+        W: Write,
+    {
+        writer.write_all(self.payload.as_ref())
+    }
+
+    pub fn message_size(&self) -> usize {
+        self.payload.len()
+    }
+
+    pub fn ext_type(&self) -> &ExtendedType {
+        &self.ext_type
+    }
+
+    pub fn payload(&self) -> &Bytes {
+        &self.payload
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CustomExtensionMessage;
+    use crate::peer::message::{
+        ExtendedMessageBuilder, ExtendedType, MessageLimits, PeerExtensionProtocolMessage,
+        PeerWireProtocolMessage,
+    };
+
+    use bytes::Bytes;
+
+    fn chat_type() -> ExtendedType {
+        ExtendedType::Custom("my_swarm_chat".to_string())
+    }
+
+    #[test]
+    fn positive_round_trip_through_extended_handshake() {
+        let extended = ExtendedMessageBuilder::new()
+            .with_extended_type(chat_type(), Some(9))
+            .build();
+        let message = PeerWireProtocolMessage::ProtExtension(PeerExtensionProtocolMessage::Custom(
+            CustomExtensionMessage::new(chat_type(), Bytes::from(&b"d4:text5:hello!e"[..])),
+        ));
+
+        let mut bytes = Vec::new();
+        message.write_bytes(&mut bytes, &Some(extended.clone())).unwrap();
+        assert_eq!(message.message_size(), bytes.len());
+        assert_eq!(9, bytes[5]);
+
+        let parsed = PeerWireProtocolMessage::parse_bytes(
+            Bytes::from(bytes),
+            &Some(extended),
+            &MessageLimits::default(),
+        )
+        .unwrap();
+
+        assert_eq!(message, parsed);
+    }
+
+    #[test]
+    fn positive_parse_unknown_id_as_raw_extension() {
+        let extended = ExtendedMessageBuilder::new()
+            .with_extended_type(chat_type(), Some(9))
+            .build();
+        let bytes = vec![0, 0, 0, 5, 20, 3, 1, 2, 3];
+
+        let parsed = PeerWireProtocolMessage::parse_bytes(
+            Bytes::from(bytes.clone()),
+            &Some(extended.clone()),
+            &MessageLimits::default(),
+        )
+        .unwrap();
+
+        let expected = PeerWireProtocolMessage::ProtExtension(PeerExtensionProtocolMessage::RawExtension {
+            id: 3,
+            payload: Bytes::from(vec![1, 2, 3]),
+        });
+        assert_eq!(expected, parsed);
+
+        let mut written = Vec::new();
+        parsed.write_bytes(&mut written, &Some(extended)).unwrap();
+        assert_eq!(bytes, written);
+    }
+
+    #[test]
+    fn negative_write_without_remote_id() {
+        let extended = ExtendedMessageBuilder::new().build();
+        let message = PeerWireProtocolMessage::ProtExtension(PeerExtensionProtocolMessage::Custom(
+            CustomExtensionMessage::new(chat_type(), Bytes::from(&b"de"[..])),
+        ));
+
+        assert!(message.write_bytes(&mut Vec::new(), &Some(extended)).is_err());
+    }
+}
@@ -0,0 +1,119 @@
+use byteorder::{BigEndian, ByteOrder, WriteBytesExt};
+use bytes::Bytes;
+use std::io::{self, Write};
+
+use crate::peer::message::ProtocolViolation;
+
+const DONT_HAVE_MESSAGE_LEN: usize = 4;
+
+/// Message for notifying a peer that we no longer have a piece.
+///
+/// See `http://bittorrent.org/beps/bep_0054.html`.
+#[derive(Copy, Clone, Debug, Hash, PartialEq, Eq)]
+pub struct DontHaveMessage {
+    piece_index: u32,
+}
+
+impl DontHaveMessage {
+    pub fn new(piece_index: u32) -> DontHaveMessage {
+        DontHaveMessage {
+            piece_index: piece_index,
+        }
+    }
+
+    pub fn parse_bytes(bytes: Bytes) -> io::Result<DontHaveMessage> {
+        if bytes.len() != DONT_HAVE_MESSAGE_LEN {
+            return Err(ProtocolViolation::new(
+                "dont have length",
+                format!(
+                    "Failed To Parse DontHaveMessage, Expected {} Bytes But Found {}",
+                    DONT_HAVE_MESSAGE_LEN,
+                    bytes.len()
+                ),
+            )
+            .into());
+        }
+
+        Ok(DontHaveMessage::new(BigEndian::read_u32(&bytes)))
+    }
+
+    pub fn write_bytes<W>(&self, mut writer: W) -> io::Result<()>
+    where
+        W: Write,
+    {
+        writer.write_u32::<BigEndian>(self.piece_index)
+    }
+
+    pub fn message_size(&self) -> usize {
+        DONT_HAVE_MESSAGE_LEN
+    }
+
+    pub fn piece_index(&self) -> u32 {
+        self.piece_index
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::DontHaveMessage;
+    use crate::peer::message::{
+        ExtendedMessageBuilder, ExtendedType, MessageLimits, PeerExtensionProtocolMessage,
+        PeerWireProtocolMessage,
+    };
+
+    use bytes::Bytes;
+
+    #[test]
+    fn positive_round_trip() {
+        let message = DontHaveMessage::new(1234);
+
+        let mut bytes = Vec::new();
+        message.write_bytes(&mut bytes).unwrap();
+        assert_eq!(message.message_size(), bytes.len());
+
+        assert_eq!(message, DontHaveMessage::parse_bytes(Bytes::from(bytes)).unwrap());
+    }
+
+    #[test]
+    fn positive_round_trip_through_extended_handshake() {
+        let extended = ExtendedMessageBuilder::new()
+            .with_extended_type(ExtendedType::LtDontHave, Some(7))
+            .build();
+        let message = PeerWireProtocolMessage::ProtExtension(
+            PeerExtensionProtocolMessage::LtDontHave(DontHaveMessage::new(42)),
+        );
+
+        let mut bytes = Vec::new();
+        message.write_bytes(&mut bytes, &Some(extended.clone())).unwrap();
+        assert_eq!(message.message_size(), bytes.len());
+
+        let parsed = PeerWireProtocolMessage::parse_bytes(
+            Bytes::from(bytes),
+            &Some(extended),
+            &MessageLimits::default(),
+        )
+        .unwrap();
+
+        assert_eq!(message, parsed);
+    }
+
+    #[test]
+    fn negative_parse_short_payload() {
+        assert!(DontHaveMessage::parse_bytes(Bytes::from(vec![0, 0, 1])).is_err());
+    }
+
+    #[test]
+    fn negative_write_without_remote_id() {
+        let extended = ExtendedMessageBuilder::new()
+            .with_extended_type(ExtendedType::UtMetadata, Some(1))
+            .build();
+        let message = PeerWireProtocolMessage::ProtExtension(
+            PeerExtensionProtocolMessage::LtDontHave(DontHaveMessage::new(42)),
+        );
+
+        let mut bytes = Vec::new();
+
+        assert!(message.write_bytes(&mut bytes, &Some(extended)).is_err());
+        assert!(bytes.is_empty());
+    }
+}
@@ -2,166 +2,282 @@ use std::io::{self, Write};
 
 use byteorder::{BigEndian, WriteBytesExt};
 use bytes::Bytes;
-use nom::{be_u32, be_u8, ErrorKind, IResult};
 
 use crate::bencode::{BConvert, BDecodeOpt, BencodeRef};
-use crate::peer::message::{self, bencode, bits_ext, ExtendedMessage, ExtendedType, PeerWireProtocolMessage, MESSAGE_LENGTH_LEN_BYTES, u32_to_usize};
-
-const EXTENSION_HEADER_LEN: usize = message::HEADER_LEN + 1;
+use crate::peer::manager::capabilities::PeerCapabilities;
+use crate::peer::message::{
+    self, bencode, bits_ext, ExtendedMessage, ExtendedType, MessageLimits, PeerWireProtocolMessage,
+    ProtocolViolation,
+};
 
 mod ut_metadata;
 pub use self::ut_metadata::{
-    UtMetadataDataMessage, UtMetadataMessage, UtMetadataRejectMessage, UtMetadataRequestMessage,
+    metadata_piece_len, num_metadata_pieces, UtMetadataDataMessage, UtMetadataError,
+    UtMetadataMessage, UtMetadataRejectMessage, UtMetadataRequestMessage, UT_METADATA_PIECE_LEN,
+};
+
+mod ut_pex;
+pub use self::ut_pex::{
+    UtPexMessage, UT_PEX_FLAG_ENCRYPTION, UT_PEX_FLAG_HOLEPUNCH, UT_PEX_FLAG_REACHABLE,
+    UT_PEX_FLAG_SEED, UT_PEX_FLAG_UTP,
 };
 
-mod null;
-pub use self::null::NullProtocolMessage;
+mod lt_donthave;
+pub use self::lt_donthave::DontHaveMessage;
+
+mod ut_holepunch;
+pub use self::ut_holepunch::{UtHolepunchErrorCode, UtHolepunchMessage};
+
+mod custom;
+pub use self::custom::CustomExtensionMessage;
 
 /// Enumeration of `BEP 10` extension protocol compatible messages.
 #[derive(Debug,PartialEq)]
 pub enum PeerExtensionProtocolMessage
 {
     UtMetadata(UtMetadataMessage),
-    //UtPex(UtPexMessage),
-    Custom(NullProtocolMessage),
+    UtPex(UtPexMessage),
+    LtDontHave(DontHaveMessage),
+    UtHolepunch(UtHolepunchMessage),
+    /// Message for an extension we registered in our `ExtendedMessage`.
+    Custom(CustomExtensionMessage),
+    /// Message with an extended id we do not recognize.
+    ///
+    /// When sending, `id` is written as is, so it must be the id the remote peer expects.
+    RawExtension { id: u8, payload: Bytes },
 }
 
 impl PeerExtensionProtocolMessage {
 
-    pub fn bytes_needed(bytes: &[u8]) -> io::Result<Option<usize>> {
+    pub fn bytes_needed(bytes: &[u8], limits: &MessageLimits) -> io::Result<Option<usize>> {
         // Follows same length prefix logic as our normal wire protocol...
-        match be_u32(bytes) {
-            // We need 4 bytes for the length, plus whatever the length is...
-            IResult::Done(_, length) => Ok(Some(MESSAGE_LENGTH_LEN_BYTES + u32_to_usize(length))),
-            _ => Ok(None),
-        }
+        PeerWireProtocolMessage::bytes_needed(bytes, limits)
     }
 
+    /// Parse a whole message (including its length prefix), any bytes after it are ignored.
+    ///
+    /// The extended ids in the message are the ones we sent in our `ExtendedMessage`.
     pub fn parse_bytes(
         bytes: Bytes,
         extended: &Option<ExtendedMessage>,
     ) -> io::Result<PeerExtensionProtocolMessage> {
+        match message::split_message(&bytes)? {
+            (bits_ext::EXTENDED_MESSAGE_ID, payload) => parse_payload(payload, extended),
+            (id, _) => Err(ProtocolViolation::new(
+                "message id",
+                format!("Message Id {} Is Not An Extension Message", id),
+            )
+            .into()),
+        }
+    }
 
-        match  extended {
-            Some(ref extended_msg) =>{
-                match parse_extensions(bytes, extended_msg) {
-                    IResult::Done(_, result) => result,
-                    _ => Err(io::Error::new(
-                        io::ErrorKind::Other,
-                        "Failed To Parse PeerExtensionProtocolMessage",
-                    )),
-                }
-           }
+    pub fn write_bytes<W>(
+        &self,
+        writer: W,
+        extended: &Option<ExtendedMessage>,
+    ) -> io::Result<()>
+    where
+        W: Write,
+    {
+        match extended {
+            Some(ref extended_msg) => {
+                self.write_bytes_with_ids(writer, |ext_type| extended_msg.query_id(ext_type))
+            }
+            None if matches!(self, &PeerExtensionProtocolMessage::RawExtension { .. }) => {
+                self.write_bytes_with_ids(writer, |_| None)
+            }
             None => Err(io::Error::new(
                 io::ErrorKind::Other,
-                "Extension Message Received From Peer Before Extended Message...",
-            ))
+                "Extension Message Sent From Us Before Extended Message...",
+            )),
         }
     }
 
-    pub fn write_bytes<W>(
+    /// Write the message using the extension ids negotiated in the given `PeerCapabilities`.
+    pub fn write_bytes_with_capabilities<W>(
         &self,
-        mut writer: W,
-        extended: &Option<ExtendedMessage>,
+        writer: W,
+        capabilities: &PeerCapabilities,
     ) -> io::Result<()>
     where
         W: Write,
     {
-        match (self,extended) {
-            (&PeerExtensionProtocolMessage::UtMetadata(ref msg),Some(ref extended_msg))=> {
-                        let ext_id = if let Some(ext_id) = extended_msg.query_id(&ExtendedType::UtMetadata) {
-                            ext_id
-                        } else {
-                            return Err(io::Error::new(
-                                io::ErrorKind::Other,
-                                "Can't Send UtMetadataMessage As We Have No Id Mapping",
-                            ));
-                        };
-
-                        let total_len = (2 + msg.message_size()) as u32;
-
-                        message::write_length_id_pair(
-                            &mut writer,
-                            total_len,
-                            Some(bits_ext::EXTENDED_MESSAGE_ID),
-                        )?;
-                        writer.write_u8(ext_id)?;
-
-                        msg.write_bytes(writer)
-                    }
-            (&PeerExtensionProtocolMessage::UtMetadata(ref msg),None)  => Err(io::Error::new(
-                        io::ErrorKind::Other,
-                        "Extension Message Sent From Us Before Extended Message...",
-                    )),
-
-            (&PeerExtensionProtocolMessage::Custom(ref msg), _) => msg.write_bytes( writer),
+        self.write_bytes_with_ids(writer, |ext_type| capabilities.extended_id_for(ext_type))
+    }
+
+    fn write_bytes_with_ids<W, F>(&self, mut writer: W, query_id: F) -> io::Result<()>
+    where
+        W: Write,
+        F: Fn(&ExtendedType) -> Option<u8>,
+    {
+        match self {
+            &PeerExtensionProtocolMessage::UtMetadata(ref msg) => {
+                let ext_type = ExtendedType::UtMetadata;
+                write_extension_header(
+                    &mut writer,
+                    query_id(&ext_type),
+                    &ext_type,
+                    msg.message_size(),
+                )?;
+
+                msg.write_bytes(writer)
+            }
+            &PeerExtensionProtocolMessage::UtPex(ref msg) => {
+                let ext_type = ExtendedType::UtPex;
+                write_extension_header(
+                    &mut writer,
+                    query_id(&ext_type),
+                    &ext_type,
+                    msg.message_size(),
+                )?;
+
+                msg.write_bytes(writer)
+            }
+            &PeerExtensionProtocolMessage::LtDontHave(ref msg) => {
+                let ext_type = ExtendedType::LtDontHave;
+                write_extension_header(
+                    &mut writer,
+                    query_id(&ext_type),
+                    &ext_type,
+                    msg.message_size(),
+                )?;
+
+                msg.write_bytes(writer)
+            }
+            &PeerExtensionProtocolMessage::UtHolepunch(ref msg) => {
+                let ext_type = ExtendedType::UtHolepunch;
+                write_extension_header(
+                    &mut writer,
+                    query_id(&ext_type),
+                    &ext_type,
+                    msg.message_size(),
+                )?;
+
+                msg.write_bytes(writer)
+            }
+            &PeerExtensionProtocolMessage::Custom(ref msg) => {
+                let ext_type = msg.ext_type();
+                write_extension_header(
+                    &mut writer,
+                    query_id(ext_type),
+                    ext_type,
+                    msg.message_size(),
+                )?;
+
+                msg.write_bytes(writer)
+            }
+            &PeerExtensionProtocolMessage::RawExtension { id, ref payload } => {
+                message::write_length_id_pair(
+                    &mut writer,
+                    (2 + payload.len()) as u32,
+                    Some(bits_ext::EXTENDED_MESSAGE_ID),
+                )?;
+                writer.write_u8(id)?;
+
+                writer.write_all(payload.as_ref())
+            }
         }
     }
 
     pub fn message_size(&self ) -> usize {
         match self {
             &PeerExtensionProtocolMessage::UtMetadata(ref msg) => msg.message_size(),
+            &PeerExtensionProtocolMessage::UtPex(ref msg) => msg.message_size(),
+            &PeerExtensionProtocolMessage::LtDontHave(ref msg) => msg.message_size(),
+            &PeerExtensionProtocolMessage::UtHolepunch(ref msg) => msg.message_size(),
             &PeerExtensionProtocolMessage::Custom(ref msg) => msg.message_size(),
+            &PeerExtensionProtocolMessage::RawExtension { ref payload, .. } => payload.len(),
         }
     }
 }
 
-fn parse_extensions(
-    mut bytes: Bytes,
-    extended_msg: &ExtendedMessage,
-) -> IResult<(), io::Result<PeerExtensionProtocolMessage>>
-{
-    let header_bytes = bytes.clone();
-
-    // Attempt to parse a built in message type, otherwise, see if it is an extension type.
-    alt!(
-        (),
-        ignore_input!(
-            switch!(header_bytes.as_ref(), throwaway_input!(tuple!(be_u32, be_u8, be_u8)),
-                (message_len, bits_ext::EXTENDED_MESSAGE_ID, message_id) =>
-                    call!(parse_extensions_with_id, bytes, extended_msg, message_len, message_id)
-            )
-        )
-    )
-}
-
-fn parse_extensions_with_id(
-    _input: (),
-    mut bytes: Bytes,
-    extended_msg: &ExtendedMessage,
-    message_len:u32,
-    message_id: u8,
-) -> IResult<(), io::Result<PeerExtensionProtocolMessage>>
+/// Write the length, extended message id, and the id the peer mapped the given `ExtendedType` to.
+fn write_extension_header<W>(
+    mut writer: W,
+    opt_ext_id: Option<u8>,
+    ext_type: &ExtendedType,
+    message_size: usize,
+) -> io::Result<()>
+where
+    W: Write,
 {
-    let msg_len= message_len as usize - 2;
+    // An id of 0 is how the peer tells us it disabled the extension
+    let ext_id = if let Some(ext_id) = opt_ext_id.filter(|&ext_id| ext_id != 0) {
+        ext_id
+    } else {
+        return Err(io::Error::new(
+            io::ErrorKind::Other,
+            format!("Can't Send {:?} Message As We Have No Id Mapping", ext_type),
+        ));
+    };
 
-    let mut temp_bytes = bytes.split_off(EXTENSION_HEADER_LEN);
+    let total_len = (2 + message_size) as u32;
 
-    if temp_bytes.len() < msg_len {
+    message::write_length_id_pair(
+        &mut writer,
+        total_len,
+        Some(bits_ext::EXTENDED_MESSAGE_ID),
+    )?;
 
-        let result =  Err(io::Error::new(
-            io::ErrorKind::Other,
-            format!("PeerExtensionProtocolMessage temp_bytes len < : {:?}", msg_len),
-        ));
+    writer.write_u8(ext_id)
+}
 
-       return  IResult::Done((), result);
+/// Parse the payload (the message without its length prefix and id) of an extension message.
+pub(crate) fn parse_payload(
+    mut payload: Bytes,
+    extended: &Option<ExtendedMessage>,
+) -> io::Result<PeerExtensionProtocolMessage> {
+    if payload.is_empty() {
+        return Err(ProtocolViolation::new(
+            "extended length",
+            "Extension Message Was Missing Its Extended Id",
+        )
+        .into());
     }
+    let extended_msg = extended.as_ref().ok_or_else(|| {
+        ProtocolViolation::new(
+            "extension before extended",
+            "Extension Message Received From Peer Before Extended Message",
+        )
+        .into_skippable()
+    })?;
 
-    let msg_bytes= temp_bytes.split_to(msg_len);
+    let message_id = payload[0];
+    let msg_bytes = payload.split_off(1);
 
     let lt_metadata_id = extended_msg.query_id(&ExtendedType::UtMetadata);
-    //let ut_pex_id = extended.query_id(&ExtendedType::UtPex);
+    let ut_pex_id = extended_msg.query_id(&ExtendedType::UtPex);
+    let lt_donthave_id = extended_msg.query_id(&ExtendedType::LtDontHave);
+    let ut_holepunch_id = extended_msg.query_id(&ExtendedType::UtHolepunch);
 
     let result = if lt_metadata_id == Some(message_id) {
         UtMetadataMessage::parse_bytes(msg_bytes)
             .map(|lt_metadata_msg| PeerExtensionProtocolMessage::UtMetadata(lt_metadata_msg))
+    } else if ut_pex_id == Some(message_id) {
+        UtPexMessage::parse_bytes(msg_bytes)
+            .map(|ut_pex_msg| PeerExtensionProtocolMessage::UtPex(ut_pex_msg))
+    } else if lt_donthave_id == Some(message_id) {
+        DontHaveMessage::parse_bytes(msg_bytes)
+            .map(|dont_have_msg| PeerExtensionProtocolMessage::LtDontHave(dont_have_msg))
+    } else if ut_holepunch_id == Some(message_id) {
+        UtHolepunchMessage::parse_bytes(msg_bytes)
+            .map(|holepunch_msg| PeerExtensionProtocolMessage::UtHolepunch(holepunch_msg))
     } else {
-        Err(io::Error::new(
-            io::ErrorKind::Other,
-            format!("Unknown Id For PeerExtensionProtocolMessage: {:?}", message_id),
-        ))
+        match extended_msg.query_type(message_id) {
+            Some(ext_type @ &ExtendedType::Custom(_)) => Ok(PeerExtensionProtocolMessage::Custom(
+                CustomExtensionMessage::new(ext_type.clone(), msg_bytes),
+            )),
+            _ => Ok(PeerExtensionProtocolMessage::RawExtension {
+                id: message_id,
+                payload: msg_bytes,
+            }),
+        }
     };
 
-    IResult::Done((), result)
+    // The frame was intact, so an extension payload we cannot parse is skipped over
+    result.map_err(|err| {
+        let rule = ProtocolViolation::rule_of(&err).unwrap_or("extension payload");
 
+        ProtocolViolation::new(rule, err.to_string()).into_skippable()
+    })
 }
@@ -0,0 +1,276 @@
+use byteorder::{BigEndian, ByteOrder, WriteBytesExt};
+use bytes::Bytes;
+use std::io::{self, Write};
+use std::net::SocketAddr;
+
+use crate::util::{convert, net};
+
+const RENDEZVOUS_MESSAGE_TYPE_ID: u8 = 0;
+const CONNECT_MESSAGE_TYPE_ID: u8 = 1;
+const ERROR_MESSAGE_TYPE_ID: u8 = 2;
+
+const IPV4_ADDR_TYPE_ID: u8 = 0;
+const IPV6_ADDR_TYPE_ID: u8 = 1;
+
+const NO_SUCH_PEER_ERROR_CODE: u32 = 1;
+const NOT_CONNECTED_ERROR_CODE: u32 = 2;
+const NO_SUPPORT_ERROR_CODE: u32 = 3;
+const NO_SELF_ERROR_CODE: u32 = 4;
+
+// Message type, address type, port and error code around the address
+const BASE_MESSAGE_LEN: usize = 1 + 1 + 2 + 4;
+const IPV4_ADDR_LEN: usize = 4;
+const IPV6_ADDR_LEN: usize = 16;
+
+/// Reason for a peer failing to relay a rendezvous.
+#[derive(Copy, Clone, Debug, Hash, PartialEq, Eq)]
+pub enum UtHolepunchErrorCode {
+    /// Target endpoint is invalid.
+    NoSuchPeer,
+    /// Relaying peer is not connected to the target peer.
+    NotConnected,
+    /// Target peer does not support the holepunch extension.
+    NoSupport,
+    /// Target endpoint belongs to the relaying peer.
+    NoSelf,
+}
+
+impl UtHolepunchErrorCode {
+    fn from_code(code: u32) -> Option<UtHolepunchErrorCode> {
+        match code {
+            NO_SUCH_PEER_ERROR_CODE => Some(UtHolepunchErrorCode::NoSuchPeer),
+            NOT_CONNECTED_ERROR_CODE => Some(UtHolepunchErrorCode::NotConnected),
+            NO_SUPPORT_ERROR_CODE => Some(UtHolepunchErrorCode::NoSupport),
+            NO_SELF_ERROR_CODE => Some(UtHolepunchErrorCode::NoSelf),
+            _ => None,
+        }
+    }
+
+    /// Code the error is sent as.
+    pub fn code(&self) -> u32 {
+        match self {
+            &UtHolepunchErrorCode::NoSuchPeer => NO_SUCH_PEER_ERROR_CODE,
+            &UtHolepunchErrorCode::NotConnected => NOT_CONNECTED_ERROR_CODE,
+            &UtHolepunchErrorCode::NoSupport => NO_SUPPORT_ERROR_CODE,
+            &UtHolepunchErrorCode::NoSelf => NO_SELF_ERROR_CODE,
+        }
+    }
+}
+
+/// Message for connecting two peers, that can not accept each other's connections, through a
+/// peer both of them are connected to.
+///
+/// See `http://www.bittorrent.org/beps/bep_0055.html`.
+#[derive(Copy, Clone, Debug, Hash, PartialEq, Eq)]
+pub enum UtHolepunchMessage {
+    /// Ask the relaying peer to have the peer at the address connect to us.
+    Rendezvous(SocketAddr),
+    /// Tell the peer to connect to the peer at the address, which is connecting to it as well.
+    Connect(SocketAddr),
+    /// Rendezvous with the peer at the address failed.
+    Error(SocketAddr, UtHolepunchErrorCode),
+}
+
+impl UtHolepunchMessage {
+    pub fn parse_bytes(bytes: Bytes) -> io::Result<UtHolepunchMessage> {
+        let bytes = bytes.as_ref();
+        if bytes.len() < 2 {
+            return Err(parse_error("Message Is Too Short"));
+        }
+
+        let addr_len = match bytes[1] {
+            IPV4_ADDR_TYPE_ID => IPV4_ADDR_LEN,
+            IPV6_ADDR_TYPE_ID => IPV6_ADDR_LEN,
+            other => return Err(parse_error(&format!("Unknown Address Type {}", other))),
+        };
+        if bytes.len() != BASE_MESSAGE_LEN + addr_len {
+            return Err(parse_error(&format!(
+                "Expected {} Bytes But Found {}",
+                BASE_MESSAGE_LEN + addr_len,
+                bytes.len()
+            )));
+        }
+
+        let (addr_bytes, rest) = bytes[2..].split_at(addr_len);
+        let ip = if addr_len == IPV4_ADDR_LEN {
+            let mut octets = [0u8; IPV4_ADDR_LEN];
+            octets.copy_from_slice(addr_bytes);
+
+            convert::bytes_be_to_ipv4(octets).into()
+        } else {
+            let mut octets = [0u8; IPV6_ADDR_LEN];
+            octets.copy_from_slice(addr_bytes);
+
+            convert::bytes_be_to_ipv6(octets).into()
+        };
+        let addr = SocketAddr::new(ip, BigEndian::read_u16(&rest[..2]));
+        let err_code = BigEndian::read_u32(&rest[2..]);
+
+        match bytes[0] {
+            RENDEZVOUS_MESSAGE_TYPE_ID => Ok(UtHolepunchMessage::Rendezvous(addr)),
+            CONNECT_MESSAGE_TYPE_ID => Ok(UtHolepunchMessage::Connect(addr)),
+            ERROR_MESSAGE_TYPE_ID => UtHolepunchErrorCode::from_code(err_code)
+                .map(|code| UtHolepunchMessage::Error(addr, code))
+                .ok_or_else(|| parse_error(&format!("Unknown Error Code {}", err_code))),
+            other => Err(parse_error(&format!("Unknown Message Type {}", other))),
+        }
+    }
+
+    pub fn write_bytes<W>(&self, mut writer: W) -> io::Result<()>
+    where
+        W: Write,
+    {
+        let (type_id, err_code) = match self {
+            &UtHolepunchMessage::Rendezvous(_) => (RENDEZVOUS_MESSAGE_TYPE_ID, 0),
+            &UtHolepunchMessage::Connect(_) => (CONNECT_MESSAGE_TYPE_ID, 0),
+            &UtHolepunchMessage::Error(_, code) => (ERROR_MESSAGE_TYPE_ID, code.code()),
+        };
+        writer.write_u8(type_id)?;
+
+        let addr = self.addr();
+        match addr {
+            SocketAddr::V4(v4_addr) => {
+                writer.write_u8(IPV4_ADDR_TYPE_ID)?;
+                writer.write_all(&convert::ipv4_to_bytes_be(*v4_addr.ip()))?;
+            }
+            SocketAddr::V6(v6_addr) => {
+                writer.write_u8(IPV6_ADDR_TYPE_ID)?;
+                writer.write_all(&convert::ipv6_to_bytes_be(*v6_addr.ip()))?;
+            }
+        }
+        writer.write_u16::<BigEndian>(addr.port())?;
+
+        writer.write_u32::<BigEndian>(err_code)
+    }
+
+    pub fn message_size(&self) -> usize {
+        match self.addr() {
+            SocketAddr::V4(_) => BASE_MESSAGE_LEN + IPV4_ADDR_LEN,
+            SocketAddr::V6(_) => BASE_MESSAGE_LEN + IPV6_ADDR_LEN,
+        }
+    }
+
+    /// Address of the peer the message is about.
+    ///
+    /// Ipv4 mapped ipv6 addresses are sent as ipv4 addresses.
+    pub fn addr(&self) -> SocketAddr {
+        match self {
+            &UtHolepunchMessage::Rendezvous(addr)
+            | &UtHolepunchMessage::Connect(addr)
+            | &UtHolepunchMessage::Error(addr, _) => net::unmap_v4(addr),
+        }
+    }
+}
+
+fn parse_error(reason: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Other,
+        format!("Failed To Parse UtHolepunchMessage, {}", reason),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{UtHolepunchErrorCode, UtHolepunchMessage};
+    use crate::peer::message::{
+        ExtendedMessageBuilder, ExtendedType, MessageLimits, PeerExtensionProtocolMessage,
+        PeerWireProtocolMessage,
+    };
+
+    use bytes::Bytes;
+
+    fn round_trip(message: UtHolepunchMessage) -> UtHolepunchMessage {
+        let mut bytes = Vec::new();
+        message.write_bytes(&mut bytes).unwrap();
+        assert_eq!(message.message_size(), bytes.len());
+
+        UtHolepunchMessage::parse_bytes(Bytes::from(bytes)).unwrap()
+    }
+
+    #[test]
+    fn positive_round_trip() {
+        let messages = vec![
+            UtHolepunchMessage::Rendezvous("10.0.0.1:6881".parse().unwrap()),
+            UtHolepunchMessage::Connect("[2001:db8::1]:51413".parse().unwrap()),
+            UtHolepunchMessage::Error(
+                "10.0.0.2:6881".parse().unwrap(),
+                UtHolepunchErrorCode::NoSupport,
+            ),
+        ];
+
+        for message in messages {
+            assert_eq!(message, round_trip(message));
+        }
+    }
+
+    #[test]
+    fn positive_parse_wire_format() {
+        let bytes = vec![2, 0, 10, 0, 0, 1, 0x1A, 0xE1, 0, 0, 0, 4];
+
+        assert_eq!(
+            UtHolepunchMessage::Error(
+                "10.0.0.1:6881".parse().unwrap(),
+                UtHolepunchErrorCode::NoSelf
+            ),
+            UtHolepunchMessage::parse_bytes(Bytes::from(bytes)).unwrap()
+        );
+    }
+
+    #[test]
+    fn positive_write_mapped_v4_as_v4() {
+        let message = UtHolepunchMessage::Connect("[::ffff:10.0.0.1]:6881".parse().unwrap());
+
+        assert_eq!(
+            UtHolepunchMessage::Connect("10.0.0.1:6881".parse().unwrap()),
+            round_trip(message)
+        );
+    }
+
+    #[test]
+    fn positive_round_trip_through_extended_handshake() {
+        let extended = ExtendedMessageBuilder::new()
+            .with_extended_type(ExtendedType::UtHolepunch, Some(4))
+            .build();
+        let message =
+            PeerWireProtocolMessage::ProtExtension(PeerExtensionProtocolMessage::UtHolepunch(
+                UtHolepunchMessage::Rendezvous("10.0.0.1:6881".parse().unwrap()),
+            ));
+
+        let mut bytes = Vec::new();
+        message
+            .write_bytes(&mut bytes, &Some(extended.clone()))
+            .unwrap();
+        assert_eq!(message.message_size(), bytes.len());
+
+        let parsed = PeerWireProtocolMessage::parse_bytes(
+            Bytes::from(bytes),
+            &Some(extended),
+            &MessageLimits::default(),
+        )
+        .unwrap();
+
+        assert_eq!(message, parsed);
+    }
+
+    #[test]
+    fn negative_parse_unknown_message_type() {
+        let bytes = vec![3, 0, 10, 0, 0, 1, 0x1A, 0xE1, 0, 0, 0, 0];
+
+        assert!(UtHolepunchMessage::parse_bytes(Bytes::from(bytes)).is_err());
+    }
+
+    #[test]
+    fn negative_parse_unknown_error_code() {
+        let bytes = vec![2, 0, 10, 0, 0, 1, 0x1A, 0xE1, 0, 0, 0, 9];
+
+        assert!(UtHolepunchMessage::parse_bytes(Bytes::from(bytes)).is_err());
+    }
+
+    #[test]
+    fn negative_parse_address_length_mismatch() {
+        // Claims an ipv6 address, carries an ipv4 one
+        let bytes = vec![1, 1, 10, 0, 0, 1, 0x1A, 0xE1, 0, 0, 0, 0];
+
+        assert!(UtHolepunchMessage::parse_bytes(Bytes::from(bytes)).is_err());
+    }
+}
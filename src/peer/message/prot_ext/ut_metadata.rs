@@ -1,8 +1,10 @@
 use bytes::Bytes;
+use std::error::Error;
+use std::fmt;
 use std::io;
 use std::io::Write;
 
-use crate::bencode::{BConvert, BDecodeOpt, BencodeRef};
+use crate::bencode::{BConvert, BencodeRef};
 use crate::peer::message::bencode;
 
 const REQUEST_MESSAGE_TYPE_ID: u8 = 0;
@@ -11,6 +13,78 @@ const REJECT_MESSAGE_TYPE_ID: u8 = 2;
 
 const ROOT_ERROR_KEY: &'static str = "PeerExtensionProtocolMessage";
 
+/// Size of every metadata piece, except for possibly the last one.
+pub const UT_METADATA_PIECE_LEN: usize = 16 * 1024;
+
+/// Errors occuring when validating metadata against an advertised metadata size.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum UtMetadataError {
+    /// Piece index or piece length does not fit the metadata size.
+    InvalidPiece {
+        piece: i64,
+        length: usize,
+        metadata_size: i64,
+    },
+    /// Total size in a data message does not match the metadata size.
+    InvalidTotalSize { total_size: i64, metadata_size: i64 },
+}
+
+impl fmt::Display for UtMetadataError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            &UtMetadataError::InvalidPiece {
+                piece,
+                length,
+                metadata_size,
+            } => write!(
+                f,
+                "Invalid Metadata Piece {} With Length {} For Metadata Size {}",
+                piece, length, metadata_size
+            ),
+            &UtMetadataError::InvalidTotalSize {
+                total_size,
+                metadata_size,
+            } => write!(
+                f,
+                "Invalid Metadata Total Size {} For Metadata Size {}",
+                total_size, metadata_size
+            ),
+        }
+    }
+}
+
+impl Error for UtMetadataError {}
+
+impl From<UtMetadataError> for io::Error {
+    fn from(error: UtMetadataError) -> io::Error {
+        io::Error::new(io::ErrorKind::InvalidData, error)
+    }
+}
+
+/// Number of pieces needed to hold metadata of the given size.
+///
+/// Negative sizes hold no pieces.
+pub fn num_metadata_pieces(metadata_size: i64) -> usize {
+    if metadata_size <= 0 {
+        0
+    } else {
+        (metadata_size as usize + UT_METADATA_PIECE_LEN - 1) / UT_METADATA_PIECE_LEN
+    }
+}
+
+/// Expected length of the given piece, if the piece exists for the metadata size.
+pub fn metadata_piece_len(piece: i64, metadata_size: i64) -> Option<usize> {
+    let num_pieces = num_metadata_pieces(metadata_size);
+
+    if piece < 0 || piece as u64 >= num_pieces as u64 {
+        None
+    } else if piece as usize + 1 == num_pieces {
+        Some(metadata_size as usize - piece as usize * UT_METADATA_PIECE_LEN)
+    } else {
+        Some(UT_METADATA_PIECE_LEN)
+    }
+}
+
 /// Enumeration of messages for `PeerExtensionProtocolMessage::UtMetadata`.
 #[derive(Clone, Debug, Hash, PartialEq, Eq)]
 pub enum UtMetadataMessage {
@@ -23,7 +97,7 @@ impl UtMetadataMessage {
     pub fn parse_bytes(mut bytes: Bytes) -> io::Result<UtMetadataMessage> {
         // Our bencode is pretty flat, and we dont want to enforce a full decode, as data
         // messages have the raw data appended outside of the bencode structure...
-        let decode_opts = BDecodeOpt::new(2, false, false);
+        let decode_opts = bencode::peer_decode_opt(2, false);
 
         match BencodeRef::decode(bytes.clone().as_ref(), decode_opts) {
             Ok(bencode) => {
@@ -214,6 +288,25 @@ impl UtMetadataDataMessage {
     pub fn data(&self) -> &Bytes {
         &self.data
     }
+
+    /// Validate the piece index, piece length, and total size against the given metadata size.
+    pub fn validate(&self, metadata_size: i64) -> Result<(), UtMetadataError> {
+        if self.total_size != metadata_size {
+            return Err(UtMetadataError::InvalidTotalSize {
+                total_size: self.total_size,
+                metadata_size: metadata_size,
+            });
+        }
+
+        match metadata_piece_len(self.piece, metadata_size) {
+            Some(length) if length == self.data.len() => Ok(()),
+            _ => Err(UtMetadataError::InvalidPiece {
+                piece: self.piece,
+                length: self.data.len(),
+                metadata_size: metadata_size,
+            }),
+        }
+    }
 }
 
 /// Message for rejecting a request for metadata from a peer.
@@ -266,3 +359,97 @@ impl UtMetadataRejectMessage {
         self.piece
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        metadata_piece_len, num_metadata_pieces, UtMetadataDataMessage, UtMetadataError,
+        UT_METADATA_PIECE_LEN,
+    };
+
+    use bytes::Bytes;
+
+    fn data_message(piece: i64, total_size: i64, length: usize) -> UtMetadataDataMessage {
+        UtMetadataDataMessage::new(piece, total_size, Bytes::from(vec![0u8; length]))
+    }
+
+    #[test]
+    fn positive_num_metadata_pieces() {
+        assert_eq!(0, num_metadata_pieces(0));
+        assert_eq!(0, num_metadata_pieces(-1));
+        assert_eq!(1, num_metadata_pieces(1));
+        assert_eq!(1, num_metadata_pieces(UT_METADATA_PIECE_LEN as i64));
+        assert_eq!(2, num_metadata_pieces(UT_METADATA_PIECE_LEN as i64 + 1));
+    }
+
+    #[test]
+    fn positive_validate_pieces() {
+        let metadata_size = UT_METADATA_PIECE_LEN as i64 + 100;
+
+        assert_eq!(Ok(()), data_message(0, metadata_size, UT_METADATA_PIECE_LEN).validate(metadata_size));
+        assert_eq!(Ok(()), data_message(1, metadata_size, 100).validate(metadata_size));
+    }
+
+    #[test]
+    fn positive_validate_final_piece_divisible() {
+        let metadata_size = 2 * UT_METADATA_PIECE_LEN as i64;
+
+        assert_eq!(Some(UT_METADATA_PIECE_LEN), metadata_piece_len(1, metadata_size));
+        assert_eq!(Ok(()), data_message(1, metadata_size, UT_METADATA_PIECE_LEN).validate(metadata_size));
+    }
+
+    #[test]
+    fn negative_validate_final_piece_off_by_one() {
+        let metadata_size = UT_METADATA_PIECE_LEN as i64 + 100;
+
+        for &length in [99, 101].iter() {
+            assert_eq!(
+                Err(UtMetadataError::InvalidPiece {
+                    piece: 1,
+                    length: length,
+                    metadata_size: metadata_size,
+                }),
+                data_message(1, metadata_size, length).validate(metadata_size)
+            );
+        }
+    }
+
+    #[test]
+    fn negative_validate_short_non_final_piece() {
+        let metadata_size = UT_METADATA_PIECE_LEN as i64 + 100;
+
+        assert!(data_message(0, metadata_size, 100).validate(metadata_size).is_err());
+    }
+
+    #[test]
+    fn negative_validate_piece_index_out_of_range() {
+        let metadata_size = UT_METADATA_PIECE_LEN as i64;
+
+        assert!(data_message(1, metadata_size, 0).validate(metadata_size).is_err());
+        assert!(data_message(-1, metadata_size, UT_METADATA_PIECE_LEN).validate(metadata_size).is_err());
+    }
+
+    #[test]
+    fn negative_validate_total_size_mismatch() {
+        assert_eq!(
+            Err(UtMetadataError::InvalidTotalSize {
+                total_size: 200,
+                metadata_size: 100,
+            }),
+            data_message(0, 200, 100).validate(100)
+        );
+    }
+
+    #[test]
+    fn negative_validate_zero_metadata_size() {
+        assert_eq!(None, metadata_piece_len(0, 0));
+        assert_eq!(
+            Err(UtMetadataError::InvalidPiece {
+                piece: 0,
+                length: 0,
+                metadata_size: 0,
+            }),
+            data_message(0, 0, 0).validate(0)
+        );
+    }
+}
@@ -0,0 +1,369 @@
+use bytes::Bytes;
+use std::io;
+use std::io::Write;
+use std::net::SocketAddr;
+
+use crate::bencode::{BConvert, BDictAccess, BRefAccess, BencodeRef};
+use crate::peer::message::bencode;
+use crate::util::{convert, net};
+
+const COMPACT_V4_LEN: usize = 6;
+const COMPACT_V6_LEN: usize = 18;
+
+const ROOT_ERROR_KEY: &'static str = "UtPexMessage";
+
+/// Flag set on an added peer that prefers encryption.
+pub const UT_PEX_FLAG_ENCRYPTION: u8 = 0x01;
+/// Flag set on an added peer that is a seed (or upload only).
+pub const UT_PEX_FLAG_SEED: u8 = 0x02;
+/// Flag set on an added peer that supports uTP.
+pub const UT_PEX_FLAG_UTP: u8 = 0x04;
+/// Flag set on an added peer that supports the holepunch extension.
+pub const UT_PEX_FLAG_HOLEPUNCH: u8 = 0x08;
+/// Flag set on an added peer that is reachable (outgoing connection succeeded).
+pub const UT_PEX_FLAG_REACHABLE: u8 = 0x10;
+
+/// Message for exchanging peers we are connected to with a peer.
+///
+/// See `http://www.bittorrent.org/beps/bep_0011.html`.
+#[derive(Clone, Debug, Hash, PartialEq, Eq)]
+pub struct UtPexMessage {
+    added: Vec<SocketAddr>,
+    added_flags: Vec<u8>,
+    dropped: Vec<SocketAddr>,
+    bencode_size: usize,
+}
+
+impl UtPexMessage {
+    /// Create a new `UtPexMessage` from the given added peers (with flags) and dropped peers.
+    ///
+    /// Ipv4 mapped ipv6 addresses are sent as ipv4 peers.
+    pub fn new(added: Vec<(SocketAddr, u8)>, dropped: Vec<SocketAddr>) -> UtPexMessage {
+        let (added, added_flags): (Vec<SocketAddr>, Vec<u8>) = added
+            .into_iter()
+            .map(|(addr, flags)| (net::unmap_v4(addr), flags))
+            .unzip();
+        let dropped = dropped.into_iter().map(net::unmap_v4).collect();
+
+        let mut message = UtPexMessage {
+            added: added,
+            added_flags: added_flags,
+            dropped: dropped,
+            bencode_size: 0,
+        };
+        message.bencode_size = message.encode().len();
+
+        message
+    }
+
+    pub fn parse_bytes(bytes: Bytes) -> io::Result<UtPexMessage> {
+        let decode_opts = bencode::peer_decode_opt(bencode::PEER_MAX_RECURSION, true);
+
+        match BencodeRef::decode(bytes.as_ref(), decode_opts) {
+            Ok(bencode) => {
+                let bencode_dict = bencode::CONVERT.convert_dict(&bencode, ROOT_ERROR_KEY)?;
+
+                let mut added = parse_compact_v4(bencode_dict, bencode::ADDED_KEY)?;
+                let mut added_flags =
+                    lookup_flags(bencode_dict, bencode::ADDED_FLAGS_KEY, added.len());
+                let added_v6 = parse_compact_v6(bencode_dict, bencode::ADDED_V6_KEY)?;
+                let added_v6_flags =
+                    lookup_flags(bencode_dict, bencode::ADDED_V6_FLAGS_KEY, added_v6.len());
+                added.extend(added_v6);
+                added_flags.extend(added_v6_flags);
+
+                let mut dropped = parse_compact_v4(bencode_dict, bencode::DROPPED_KEY)?;
+                dropped.extend(parse_compact_v6(bencode_dict, bencode::DROPPED_V6_KEY)?);
+
+                Ok(UtPexMessage {
+                    added: added,
+                    added_flags: added_flags,
+                    dropped: dropped,
+                    bencode_size: bytes.len(),
+                })
+            }
+            Err(err) => Err(io::Error::new(
+                io::ErrorKind::Other,
+                format!("Failed To Parse UtPexMessage As Bencode: {}", err),
+            )),
+        }
+    }
+
+    pub fn write_bytes<W>(&self, mut writer: W) -> io::Result<()>
+    where
+        W: Write,
+    {
+        writer.write_all(self.encode().as_ref())
+    }
+
+    pub fn message_size(&self) -> usize {
+        self.bencode_size
+    }
+
+    /// Peers that were added since the last `UtPexMessage`.
+    pub fn added(&self) -> &[SocketAddr] {
+        &self.added
+    }
+
+    /// Flags for each of the added peers, in the same order as `added`.
+    ///
+    /// Peers whose flags were not sent will have all flags cleared.
+    pub fn added_flags(&self) -> &[u8] {
+        &self.added_flags
+    }
+
+    /// Peers that were dropped since the last `UtPexMessage`.
+    pub fn dropped(&self) -> &[SocketAddr] {
+        &self.dropped
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let (mut added, mut added_flags) = (Vec::new(), Vec::new());
+        let (mut added_v6, mut added_v6_flags) = (Vec::new(), Vec::new());
+        for (addr, flags) in self.added.iter().zip(self.added_flags.iter()) {
+            match addr {
+                &SocketAddr::V4(v4_addr) => {
+                    added.extend_from_slice(&convert::sock_v4_to_bytes_be(v4_addr));
+                    added_flags.push(*flags);
+                }
+                &SocketAddr::V6(v6_addr) => {
+                    added_v6.extend_from_slice(&convert::sock_v6_to_bytes_be(v6_addr));
+                    added_v6_flags.push(*flags);
+                }
+            }
+        }
+
+        let (mut dropped, mut dropped_v6) = (Vec::new(), Vec::new());
+        for addr in self.dropped.iter() {
+            match addr {
+                &SocketAddr::V4(v4_addr) => {
+                    dropped.extend_from_slice(&convert::sock_v4_to_bytes_be(v4_addr))
+                }
+                &SocketAddr::V6(v6_addr) => {
+                    dropped_v6.extend_from_slice(&convert::sock_v6_to_bytes_be(v6_addr))
+                }
+            }
+        }
+
+        (bt_ben_map! {
+            bencode::ADDED_KEY          => bt_ben_bytes!(added),
+            bencode::ADDED_FLAGS_KEY    => bt_ben_bytes!(added_flags),
+            bencode::ADDED_V6_KEY       => bt_ben_bytes!(added_v6),
+            bencode::ADDED_V6_FLAGS_KEY => bt_ben_bytes!(added_v6_flags),
+            bencode::DROPPED_KEY        => bt_ben_bytes!(dropped),
+            bencode::DROPPED_V6_KEY     => bt_ben_bytes!(dropped_v6)
+        })
+        .encode()
+    }
+}
+
+/// Lookup the given compact peer list, treating a missing key as an empty list.
+fn lookup_compact<'a, K, V>(
+    root: &'a dyn BDictAccess<K, V>,
+    key: &'static [u8],
+    chunk_len: usize,
+) -> io::Result<&'a [u8]>
+where
+    V: BRefAccess,
+{
+    let compact = bencode::CONVERT.lookup_and_convert_bytes(root, key).unwrap_or(&[]);
+
+    if compact.len() % chunk_len != 0 {
+        Err(io::Error::new(
+            io::ErrorKind::Other,
+            format!(
+                "Failed To Parse UtPexMessage Compact Peers For Key {:?}, Length {} Not A Multiple Of {}",
+                String::from_utf8_lossy(key),
+                compact.len(),
+                chunk_len
+            ),
+        ))
+    } else {
+        Ok(compact)
+    }
+}
+
+fn parse_compact_v4<K, V>(
+    root: &dyn BDictAccess<K, V>,
+    key: &'static [u8],
+) -> io::Result<Vec<SocketAddr>>
+where
+    V: BRefAccess,
+{
+    let compact = lookup_compact(root, key, COMPACT_V4_LEN)?;
+
+    Ok(compact
+        .chunks(COMPACT_V4_LEN)
+        .map(|chunk| {
+            let mut bytes = [0u8; COMPACT_V4_LEN];
+            bytes.copy_from_slice(chunk);
+
+            SocketAddr::V4(convert::bytes_be_to_sock_v4(bytes))
+        })
+        .collect())
+}
+
+fn parse_compact_v6<K, V>(
+    root: &dyn BDictAccess<K, V>,
+    key: &'static [u8],
+) -> io::Result<Vec<SocketAddr>>
+where
+    V: BRefAccess,
+{
+    let compact = lookup_compact(root, key, COMPACT_V6_LEN)?;
+
+    Ok(compact
+        .chunks(COMPACT_V6_LEN)
+        .map(|chunk| {
+            let mut bytes = [0u8; COMPACT_V6_LEN];
+            bytes.copy_from_slice(chunk);
+
+            SocketAddr::V6(convert::bytes_be_to_sock_v6(bytes))
+        })
+        .collect())
+}
+
+/// Lookup the flags for `num_peers` peers, padding (or truncating) so there is one per peer.
+fn lookup_flags<K, V>(
+    root: &dyn BDictAccess<K, V>,
+    key: &'static [u8],
+    num_peers: usize,
+) -> Vec<u8>
+where
+    V: BRefAccess,
+{
+    let mut flags = bencode::CONVERT
+        .lookup_and_convert_bytes(root, key)
+        .map(|flags| flags.to_vec())
+        .unwrap_or(Vec::new());
+    flags.resize(num_peers, 0);
+
+    flags
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{UtPexMessage, UT_PEX_FLAG_SEED, UT_PEX_FLAG_UTP};
+    use crate::peer::message::{
+        ExtendedMessageBuilder, ExtendedType, MessageLimits, PeerExtensionProtocolMessage,
+        PeerWireProtocolMessage,
+    };
+
+    use bytes::Bytes;
+    use std::net::SocketAddr;
+
+    #[test]
+    fn positive_round_trip_v4_and_v6() {
+        let v4_addr: SocketAddr = "1.2.3.4:6881".parse().unwrap();
+        let v6_addr: SocketAddr = "[::1]:6882".parse().unwrap();
+        let dropped_addr: SocketAddr = "5.6.7.8:100".parse().unwrap();
+
+        let message = UtPexMessage::new(
+            vec![(v4_addr, UT_PEX_FLAG_SEED), (v6_addr, UT_PEX_FLAG_UTP)],
+            vec![dropped_addr],
+        );
+        let mut bytes = Vec::new();
+        message.write_bytes(&mut bytes).unwrap();
+        assert_eq!(message.message_size(), bytes.len());
+
+        let parsed = UtPexMessage::parse_bytes(Bytes::from(bytes)).unwrap();
+
+        assert_eq!(&[v4_addr, v6_addr], parsed.added());
+        assert_eq!(&[UT_PEX_FLAG_SEED, UT_PEX_FLAG_UTP], parsed.added_flags());
+        assert_eq!(&[dropped_addr], parsed.dropped());
+    }
+
+    #[test]
+    fn positive_round_trip_mapped_v4_as_v4() {
+        let mapped_addr: SocketAddr = "[::ffff:1.2.3.4]:6881".parse().unwrap();
+        let v6_addr: SocketAddr = "[2001:db8::1]:6882".parse().unwrap();
+
+        let message = UtPexMessage::new(vec![(mapped_addr, 0), (v6_addr, 0)], vec![mapped_addr]);
+        let mut bytes = Vec::new();
+        message.write_bytes(&mut bytes).unwrap();
+
+        let parsed = UtPexMessage::parse_bytes(Bytes::from(bytes)).unwrap();
+        let v4_addr: SocketAddr = "1.2.3.4:6881".parse().unwrap();
+
+        assert_eq!(&[v4_addr, v6_addr], parsed.added());
+        assert_eq!(&[v4_addr], parsed.dropped());
+    }
+
+    #[test]
+    fn positive_parse_empty_lists() {
+        let message = UtPexMessage::new(Vec::new(), Vec::new());
+        let mut bytes = Vec::new();
+        message.write_bytes(&mut bytes).unwrap();
+
+        let parsed = UtPexMessage::parse_bytes(Bytes::from(bytes)).unwrap();
+
+        assert!(parsed.added().is_empty());
+        assert!(parsed.added_flags().is_empty());
+        assert!(parsed.dropped().is_empty());
+    }
+
+    #[test]
+    fn positive_parse_missing_keys() {
+        let parsed = UtPexMessage::parse_bytes(Bytes::from(&b"de"[..])).unwrap();
+
+        assert!(parsed.added().is_empty());
+        assert!(parsed.dropped().is_empty());
+    }
+
+    #[test]
+    fn positive_parse_flags_shorter_than_added() {
+        let bytes = (bt_ben_map! {
+            "added"   => bt_ben_bytes!(vec![1, 2, 3, 4, 0x1A, 0xE1, 5, 6, 7, 8, 0x1A, 0xE2]),
+            "added.f" => bt_ben_bytes!(vec![UT_PEX_FLAG_SEED])
+        })
+        .encode();
+
+        let parsed = UtPexMessage::parse_bytes(Bytes::from(bytes)).unwrap();
+
+        assert_eq!(2, parsed.added().len());
+        assert_eq!(&[UT_PEX_FLAG_SEED, 0], parsed.added_flags());
+    }
+
+    #[test]
+    fn negative_parse_partial_compact_peer() {
+        let bytes = (bt_ben_map! {
+            "added" => bt_ben_bytes!(vec![1, 2, 3, 4, 0x1A])
+        })
+        .encode();
+
+        assert!(UtPexMessage::parse_bytes(Bytes::from(bytes)).is_err());
+    }
+
+    #[test]
+    fn positive_receive_through_extended_handshake() {
+        let our_extended = ExtendedMessageBuilder::new()
+            .with_extended_type(ExtendedType::UtPex, Some(3))
+            .build();
+        let their_extended = ExtendedMessageBuilder::new()
+            .with_extended_type(ExtendedType::UtPex, Some(3))
+            .build();
+
+        let added_addr: SocketAddr = "10.0.0.1:51413".parse().unwrap();
+        let message = PeerWireProtocolMessage::ProtExtension(PeerExtensionProtocolMessage::UtPex(
+            UtPexMessage::new(vec![(added_addr, 0)], Vec::new()),
+        ));
+
+        let mut bytes = Vec::new();
+        message.write_bytes(&mut bytes, &Some(their_extended)).unwrap();
+        assert_eq!(message.message_size(), bytes.len());
+
+        let parsed = PeerWireProtocolMessage::parse_bytes(
+            Bytes::from(bytes),
+            &Some(our_extended),
+            &MessageLimits::default(),
+        )
+        .unwrap();
+
+        match parsed {
+            PeerWireProtocolMessage::ProtExtension(PeerExtensionProtocolMessage::UtPex(pex)) => {
+                assert_eq!(vec![added_addr], pex.added().iter().cloned().collect::<Vec<_>>());
+            }
+            other => panic!("Expected UtPex Message, Found {:?}", other),
+        }
+    }
+}
@@ -1,9 +1,10 @@
-use byteorder::{BigEndian, WriteBytesExt};
-use bytes::Bytes;
-use nom::{be_u32, IResult, Needed};
+use byteorder::{BigEndian, ByteOrder, WriteBytesExt};
+use bytes::{Bytes, BytesMut};
 use std::io::{self, Write};
+use std::mem;
 
-use crate::peer::message;
+use crate::peer::message::{self, ProtocolViolation};
+use crate::peer::message::validation::{self, ValidationError};
 
 /// Message for notifying a peer of a piece that you have.
 #[derive(Copy, Clone, Debug, Hash, PartialEq, Eq)]
@@ -18,8 +19,9 @@ impl HaveMessage {
         }
     }
 
-    pub fn parse_bytes(_input: (), bytes: Bytes) -> IResult<(), io::Result<HaveMessage>> {
-        throwaway_input!(parse_have(bytes.as_ref()))
+    /// Parse the payload of a have message.
+    pub fn parse_bytes(bytes: Bytes) -> io::Result<HaveMessage> {
+        parse_piece_index(&bytes, message::HAVE_MESSAGE_LEN, "have length").map(HaveMessage::new)
     }
 
     pub fn write_bytes<W>(&self, mut writer: W) -> io::Result<()>
@@ -38,10 +40,11 @@ impl HaveMessage {
     pub fn piece_index(&self) -> u32 {
         self.piece_index
     }
-}
 
-fn parse_have(bytes: &[u8]) -> IResult<&[u8], io::Result<HaveMessage>> {
-    map!(bytes, be_u32, |index| Ok(HaveMessage::new(index)))
+    /// Validate the piece index against the number of pieces in the torrent.
+    pub fn validate(&self, piece_count: u32) -> Result<(), ValidationError> {
+        validation::validate_piece_index(self.piece_index, piece_count)
+    }
 }
 
 // ----------------------------------------------------------------------------//
@@ -59,23 +62,36 @@ impl BitFieldMessage {
         BitFieldMessage { bytes: bytes }
     }
 
-    pub fn parse_bytes(
-        _input: (),
-        mut bytes: Bytes,
-        len: u32,
-    ) -> IResult<(), io::Result<BitFieldMessage>> {
-        let cast_len = message::u32_to_usize(len);
+    /// Create a `BitFieldMessage` for the given number of pieces, with no pieces set.
+    pub fn with_capacity(num_pieces: usize) -> BitFieldMessage {
+        BitFieldMessage::new(Bytes::from(vec![0u8; bitfield_len(num_pieces)]))
+    }
 
-        if bytes.len() >= cast_len {
-            IResult::Done(
-                (),
-                Ok(BitFieldMessage {
-                    bytes: bytes.split_to(cast_len),
-                }),
-            )
-        } else {
-            IResult::Incomplete(Needed::Size(cast_len - bytes.len()))
+    /// Create a `BitFieldMessage` for the given number of pieces, with the given pieces set.
+    ///
+    /// Panics if any of the pieces are not less than `num_pieces`.
+    pub fn from_pieces<I>(num_pieces: usize, have: I) -> BitFieldMessage
+    where
+        I: Iterator<Item = usize>,
+    {
+        let mut bytes = vec![0u8; bitfield_len(num_pieces)];
+        for piece in have {
+            assert!(
+                piece < num_pieces,
+                "bittorrent-protocol_peer: BitFieldMessage::from_pieces Piece {} Out Of Range For {} Pieces",
+                piece,
+                num_pieces
+            );
+
+            bytes[piece / 8] |= piece_mask(piece);
         }
+
+        BitFieldMessage::new(Bytes::from(bytes))
+    }
+
+    /// Parse the payload of a bitfield message, which is the bitfield itself.
+    pub fn parse_bytes(bytes: Bytes) -> io::Result<BitFieldMessage> {
+        Ok(BitFieldMessage { bytes: bytes })
     }
 
     pub fn write_bytes<W>(&self, mut writer: W) -> io::Result<()>
@@ -92,13 +108,87 @@ impl BitFieldMessage {
         writer.write_all(&self.bytes)
     }
 
-    pub fn bitfield(&self) -> &[u8] {
-        &self.bytes
+    /// Retrieve the bitfield, which shares memory with the buffer the message was parsed from.
+    pub fn bitfield(&self) -> Bytes {
+        self.bytes.clone()
     }
 
     pub fn iter(&self) -> BitFieldIter {
         BitFieldIter::new(self.bytes.clone())
     }
+
+    /// Whether or not the given piece is set.
+    ///
+    /// Pieces past the end of the bitfield are not set.
+    pub fn has_piece(&self, piece: usize) -> bool {
+        self.bytes
+            .get(piece / 8)
+            .map(|byte| byte & piece_mask(piece) != 0)
+            .unwrap_or(false)
+    }
+
+    /// Set the given piece.
+    ///
+    /// Panics if the piece is past the end of the bitfield.
+    pub fn set_piece(&mut self, piece: usize) {
+        assert!(
+            piece / 8 < self.bytes.len(),
+            "bittorrent-protocol_peer: BitFieldMessage::set_piece Piece {} Out Of Range",
+            piece
+        );
+
+        // Wont copy if we are the only ones referencing the bitfield
+        let mut bytes = BytesMut::from(mem::replace(&mut self.bytes, Bytes::new()));
+        bytes[piece / 8] |= piece_mask(piece);
+
+        self.bytes = bytes.freeze();
+    }
+
+    /// Check that the bitfield is exactly long enough for the given number of pieces,
+    /// and that none of the spare bits at the end are set.
+    pub fn validate(&self, num_pieces: usize) -> io::Result<()> {
+        let expected_len = bitfield_len(num_pieces);
+        if self.bytes.len() != expected_len {
+            return Err(ProtocolViolation::new(
+                "bitfield length",
+                format!(
+                    "BitFieldMessage Length {} Does Not Match Expected Length {} For {} Pieces",
+                    self.bytes.len(),
+                    expected_len,
+                    num_pieces
+                ),
+            )
+            .into());
+        }
+
+        let spare_bits = expected_len * 8 - num_pieces;
+        let spare_mask = ((1u16 << spare_bits) - 1) as u8;
+        let has_spare_bits = self
+            .bytes
+            .last()
+            .map(|byte| byte & spare_mask != 0)
+            .unwrap_or(false);
+
+        if has_spare_bits {
+            Err(ProtocolViolation::new(
+                "bitfield spare bits",
+                format!("BitFieldMessage Has Spare Bits Set Past {} Pieces", num_pieces),
+            )
+            .into())
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Number of bytes needed to hold a bitfield for the given number of pieces.
+fn bitfield_len(num_pieces: usize) -> usize {
+    (num_pieces + 7) / 8
+}
+
+/// Mask for the bit of the given piece within its byte.
+fn piece_mask(piece: usize) -> u8 {
+    0x80 >> (piece % 8)
 }
 
 /// Iterator for a `BitFieldMessage` to `HaveMessage`s.
@@ -116,26 +206,49 @@ impl BitFieldIter {
             cur_bit: 0,
         }
     }
+
+    /// Number of set bits that have not been iterated over yet.
+    pub fn count_ones(&self) -> usize {
+        let byte_in_bytes = self.cur_bit / 8;
+        let bit_in_byte = self.cur_bit % 8;
+
+        match self.bytes.get(byte_in_bytes) {
+            Some(byte) => {
+                let first_ones = *byte << bit_in_byte;
+                let rest_ones: u32 = self.bytes[byte_in_bytes + 1..]
+                    .iter()
+                    .map(|byte| byte.count_ones())
+                    .sum();
+
+                (first_ones.count_ones() + rest_ones) as usize
+            }
+            None => 0,
+        }
+    }
 }
 
 impl Iterator for BitFieldIter {
     type Item = HaveMessage;
 
     fn next(&mut self) -> Option<HaveMessage> {
-        let byte_in_bytes = self.cur_bit / 8;
-        let bit_in_byte = self.cur_bit % 8;
+        while let Some(byte) = self.bytes.get(self.cur_bit / 8).map(|byte| *byte) {
+            let bit_in_byte = self.cur_bit % 8;
+
+            // Skip over empty bytes entirely
+            if bit_in_byte == 0 && byte == 0 {
+                self.cur_bit += 8;
+                continue;
+            }
 
-        let opt_byte = self.bytes.get(byte_in_bytes).map(|byte| *byte);
-        opt_byte.and_then(|byte| {
             let have_message = HaveMessage::new(self.cur_bit as u32);
             self.cur_bit += 1;
 
             if (byte << bit_in_byte) >> 7 == 1 {
-                Some(have_message)
-            } else {
-                self.next()
+                return Some(have_message);
             }
-        })
+        }
+
+        None
     }
 }
 
@@ -158,8 +271,10 @@ impl RequestMessage {
         }
     }
 
-    pub fn parse_bytes(_input: (), bytes: Bytes) -> IResult<(), io::Result<RequestMessage>> {
-        throwaway_input!(parse_request(bytes.as_ref()))
+    /// Parse the payload of a request message.
+    pub fn parse_bytes(bytes: Bytes) -> io::Result<RequestMessage> {
+        parse_block_fields(&bytes, message::REQUEST_MESSAGE_LEN, "request length")
+            .map(|(index, offset, length)| RequestMessage::new(index, offset, length))
     }
 
     pub fn write_bytes<W>(&self, mut writer: W) -> io::Result<()>
@@ -188,16 +303,25 @@ impl RequestMessage {
     pub fn block_length(&self) -> usize {
         self.block_length
     }
-}
 
-fn parse_request(bytes: &[u8]) -> IResult<&[u8], io::Result<RequestMessage>> {
-    map!(bytes, tuple!(be_u32, be_u32, be_u32), |(
-        index,
-        offset,
-        length,
-    )| Ok(
-        RequestMessage::new(index, offset, message::u32_to_usize(length))
-    ))
+    /// Validate the block against the geometry of the torrent.
+    ///
+    /// Every piece but the last is `piece_len` long, the last piece is `last_piece_len` long.
+    pub fn validate(
+        &self,
+        piece_count: u32,
+        piece_len: u32,
+        last_piece_len: u32,
+    ) -> Result<(), ValidationError> {
+        validation::validate_block(
+            self.piece_index,
+            self.block_offset,
+            self.block_length,
+            piece_count,
+            piece_len,
+            last_piece_len,
+        )
+    }
 }
 
 // ----------------------------------------------------------------------------//
@@ -223,15 +347,35 @@ impl PieceMessage {
         }
     }
 
-    pub fn parse_bytes(
-        _input: (),
-        bytes: Bytes,
-        len: u32,
-    ) -> IResult<(), io::Result<PieceMessage>> {
-        throwaway_input!(parse_piece(&bytes, len))
+    /// Parse the payload of a piece message, the block shares the given bytes.
+    pub fn parse_bytes(bytes: Bytes) -> io::Result<PieceMessage> {
+        let header_len = message::BASE_PIECE_MESSAGE_LEN as usize - message::MESSAGE_ID_LEN_BYTES;
+        if bytes.len() < header_len {
+            return Err(ProtocolViolation::new(
+                "piece length",
+                "Piece Message Length Was Less Than Its Header",
+            )
+            .into());
+        }
+
+        Ok(PieceMessage::new(
+            BigEndian::read_u32(&bytes[0..4]),
+            BigEndian::read_u32(&bytes[4..8]),
+            bytes.slice_from(header_len),
+        ))
     }
 
     pub fn write_bytes<W>(&self, mut writer: W) -> io::Result<()>
+    where
+        W: Write,
+    {
+        self.write_header(&mut writer)?;
+
+        writer.write_all(&self.block[..])
+    }
+
+    /// Write everything but the block out to the given writer.
+    pub(crate) fn write_header<W>(&self, mut writer: W) -> io::Result<()>
     where
         W: Write,
     {
@@ -239,9 +383,7 @@ impl PieceMessage {
         message::write_length_id_pair(&mut writer, actual_length, Some(message::PIECE_MESSAGE_ID))?;
 
         writer.write_u32::<BigEndian>(self.piece_index)?;
-        writer.write_u32::<BigEndian>(self.block_offset)?;
-
-        writer.write_all(&self.block[..])
+        writer.write_u32::<BigEndian>(self.block_offset)
     }
 
     pub fn piece_index(&self) -> u32 {
@@ -256,20 +398,34 @@ impl PieceMessage {
         self.block.len()
     }
 
+    /// Retrieve the block, which shares memory with the buffer the message was parsed from.
     pub fn block(&self) -> Bytes {
         self.block.clone()
     }
-}
 
-fn parse_piece(bytes: &Bytes, len: u32) -> IResult<&[u8], io::Result<PieceMessage>> {
-    do_parse!(
-        bytes.as_ref(),
-        piece_index: be_u32
-            >> block_offset: be_u32
-            >> block_len: value!(message::u32_to_usize(len - 8))
-            >> block: map!(take!(block_len), |_| bytes.slice(8, 8 + block_len))
-            >> (Ok(PieceMessage::new(piece_index, block_offset, block)))
-    )
+    /// Take ownership of the block without copying it.
+    pub fn into_block(self) -> Bytes {
+        self.block
+    }
+
+    /// Validate the block against the geometry of the torrent.
+    ///
+    /// Every piece but the last is `piece_len` long, the last piece is `last_piece_len` long.
+    pub fn validate(
+        &self,
+        piece_count: u32,
+        piece_len: u32,
+        last_piece_len: u32,
+    ) -> Result<(), ValidationError> {
+        validation::validate_block(
+            self.piece_index,
+            self.block_offset,
+            self.block_length(),
+            piece_count,
+            piece_len,
+            last_piece_len,
+        )
+    }
 }
 
 // ----------------------------------------------------------------------------//
@@ -291,8 +447,10 @@ impl CancelMessage {
         }
     }
 
-    pub fn parse_bytes(_input: (), bytes: Bytes) -> IResult<(), io::Result<CancelMessage>> {
-        throwaway_input!(parse_cancel(bytes.as_ref()))
+    /// Parse the payload of a cancel message.
+    pub fn parse_bytes(bytes: Bytes) -> io::Result<CancelMessage> {
+        parse_block_fields(&bytes, message::CANCEL_MESSAGE_LEN, "cancel length")
+            .map(|(index, offset, length)| CancelMessage::new(index, offset, length))
     }
 
     pub fn write_bytes<W>(&self, mut writer: W) -> io::Result<()>
@@ -321,21 +479,191 @@ impl CancelMessage {
     pub fn block_length(&self) -> usize {
         self.block_length
     }
+
+    /// Validate the block against the geometry of the torrent.
+    ///
+    /// Every piece but the last is `piece_len` long, the last piece is `last_piece_len` long.
+    pub fn validate(
+        &self,
+        piece_count: u32,
+        piece_len: u32,
+        last_piece_len: u32,
+    ) -> Result<(), ValidationError> {
+        validation::validate_block(
+            self.piece_index,
+            self.block_offset,
+            self.block_length,
+            piece_count,
+            piece_len,
+            last_piece_len,
+        )
+    }
+}
+
+// ----------------------------------------------------------------------------//
+
+/// Message for suggesting a piece that a peer may want to download from us.
+///
+/// Only valid when the fast extension has been negotiated.
+#[derive(Copy, Clone, Debug, Hash, PartialEq, Eq)]
+pub struct SuggestMessage {
+    piece_index: u32,
+}
+
+impl SuggestMessage {
+    pub fn new(piece_index: u32) -> SuggestMessage {
+        SuggestMessage {
+            piece_index: piece_index,
+        }
+    }
+
+    /// Parse the payload of a suggest message.
+    pub fn parse_bytes(bytes: Bytes) -> io::Result<SuggestMessage> {
+        parse_piece_index(&bytes, message::SUGGEST_MESSAGE_LEN, "suggest length").map(SuggestMessage::new)
+    }
+
+    pub fn write_bytes<W>(&self, mut writer: W) -> io::Result<()>
+    where
+        W: Write,
+    {
+        message::write_length_id_pair(
+            &mut writer,
+            message::SUGGEST_MESSAGE_LEN,
+            Some(message::SUGGEST_MESSAGE_ID),
+        )?;
+
+        writer.write_u32::<BigEndian>(self.piece_index)
+    }
+
+    pub fn piece_index(&self) -> u32 {
+        self.piece_index
+    }
 }
 
-fn parse_cancel(bytes: &[u8]) -> IResult<&[u8], io::Result<CancelMessage>> {
-    map!(bytes, tuple!(be_u32, be_u32, be_u32), |(
-        index,
-        offset,
-        length,
-    )| Ok(
-        CancelMessage::new(index, offset, message::u32_to_usize(length))
+// ----------------------------------------------------------------------------//
+
+/// Message for rejecting a `RequestMessage` sent to us by a peer.
+///
+/// Only valid when the fast extension has been negotiated.
+#[derive(Copy, Clone, Debug, Hash, PartialEq, Eq)]
+pub struct RejectMessage {
+    piece_index: u32,
+    block_offset: u32,
+    block_length: usize,
+}
+
+impl RejectMessage {
+    pub fn new(piece_index: u32, block_offset: u32, block_length: usize) -> RejectMessage {
+        RejectMessage {
+            piece_index: piece_index,
+            block_offset: block_offset,
+            block_length: block_length,
+        }
+    }
+
+    /// Parse the payload of a reject message.
+    pub fn parse_bytes(bytes: Bytes) -> io::Result<RejectMessage> {
+        parse_block_fields(&bytes, message::REJECT_MESSAGE_LEN, "reject length")
+            .map(|(index, offset, length)| RejectMessage::new(index, offset, length))
+    }
+
+    pub fn write_bytes<W>(&self, mut writer: W) -> io::Result<()>
+    where
+        W: Write,
+    {
+        message::write_length_id_pair(
+            &mut writer,
+            message::REJECT_MESSAGE_LEN,
+            Some(message::REJECT_MESSAGE_ID),
+        )?;
+
+        writer.write_u32::<BigEndian>(self.piece_index)?;
+        writer.write_u32::<BigEndian>(self.block_offset)?;
+        writer.write_u32::<BigEndian>(self.block_length as u32)
+    }
+
+    pub fn piece_index(&self) -> u32 {
+        self.piece_index
+    }
+
+    pub fn block_offset(&self) -> u32 {
+        self.block_offset
+    }
+
+    pub fn block_length(&self) -> usize {
+        self.block_length
+    }
+}
+
+// ----------------------------------------------------------------------------//
+
+/// Message for telling a peer it may request the given piece even while choked.
+///
+/// Only valid when the fast extension has been negotiated.
+#[derive(Copy, Clone, Debug, Hash, PartialEq, Eq)]
+pub struct AllowedFastMessage {
+    piece_index: u32,
+}
+
+impl AllowedFastMessage {
+    pub fn new(piece_index: u32) -> AllowedFastMessage {
+        AllowedFastMessage {
+            piece_index: piece_index,
+        }
+    }
+
+    /// Parse the payload of a allowed fast message.
+    pub fn parse_bytes(bytes: Bytes) -> io::Result<AllowedFastMessage> {
+        parse_piece_index(&bytes, message::ALLOWED_FAST_MESSAGE_LEN, "allowed fast length").map(AllowedFastMessage::new)
+    }
+
+    pub fn write_bytes<W>(&self, mut writer: W) -> io::Result<()>
+    where
+        W: Write,
+    {
+        message::write_length_id_pair(
+            &mut writer,
+            message::ALLOWED_FAST_MESSAGE_LEN,
+            Some(message::ALLOWED_FAST_MESSAGE_ID),
+        )?;
+
+        writer.write_u32::<BigEndian>(self.piece_index)
+    }
+
+    pub fn piece_index(&self) -> u32 {
+        self.piece_index
+    }
+}
+
+// ----------------------------------------------------------------------------//
+
+/// Parse the payload of a message that is only a piece index.
+fn parse_piece_index(bytes: &[u8], message_len: u32, rule: &'static str) -> io::Result<u32> {
+    message::check_payload_length(bytes, message_len, rule)?;
+
+    Ok(BigEndian::read_u32(bytes))
+}
+
+/// Parse the payload of a message that is a piece index, block offset and block length.
+fn parse_block_fields(
+    bytes: &[u8],
+    message_len: u32,
+    rule: &'static str,
+) -> io::Result<(u32, u32, usize)> {
+    message::check_payload_length(bytes, message_len, rule)?;
+
+    let block_length = message::u32_to_usize(BigEndian::read_u32(&bytes[8..12]))?;
+
+    Ok((
+        BigEndian::read_u32(&bytes[0..4]),
+        BigEndian::read_u32(&bytes[4..8]),
+        block_length,
     ))
 }
 
 #[cfg(test)]
 mod tests {
-    use super::{BitFieldMessage, HaveMessage};
+    use super::{BitFieldMessage, HaveMessage, PieceMessage};
 
     use bytes::Bytes;
 
@@ -413,4 +741,86 @@ mod tests {
             messages
         );
     }
+
+    #[test]
+    fn positive_bitfield_from_pieces_not_divisible_by_eight() {
+        let bitfield = BitFieldMessage::from_pieces(11, vec![0, 7, 8, 10].into_iter());
+
+        assert_eq!(&[0x81, 0xA0][..], bitfield.bitfield().as_ref());
+        assert!(bitfield.has_piece(10));
+        assert!(!bitfield.has_piece(9));
+        assert!(!bitfield.has_piece(11));
+        assert!(!bitfield.has_piece(100));
+        assert!(bitfield.validate(11).is_ok());
+    }
+
+    #[test]
+    fn positive_bitfield_set_piece() {
+        let mut bitfield = BitFieldMessage::with_capacity(13);
+        assert_eq!(2, bitfield.bitfield().len());
+
+        bitfield.set_piece(12);
+        bitfield.set_piece(3);
+
+        assert_eq!(
+            vec![HaveMessage::new(3), HaveMessage::new(12)],
+            bitfield.iter().collect::<Vec<HaveMessage>>()
+        );
+    }
+
+    #[test]
+    #[should_panic]
+    fn negative_bitfield_set_piece_out_of_range() {
+        BitFieldMessage::with_capacity(8).set_piece(8);
+    }
+
+    #[test]
+    fn negative_bitfield_validate_spare_bits() {
+        let bitfield = BitFieldMessage::new(Bytes::from(vec![0x00, 0x10]));
+
+        assert!(bitfield.validate(11).is_err());
+        assert!(bitfield.validate(12).is_ok());
+        assert!(bitfield.validate(16).is_ok());
+    }
+
+    #[test]
+    fn negative_bitfield_validate_length() {
+        let bitfield = BitFieldMessage::with_capacity(17);
+
+        assert!(bitfield.validate(17).is_ok());
+        assert!(bitfield.validate(16).is_err());
+        assert!(bitfield.validate(25).is_err());
+    }
+
+    #[test]
+    fn positive_bitfield_iter_count_ones() {
+        let bitfield = BitFieldMessage::new(Bytes::from(vec![0xFF, 0x01, 0x80]));
+        let mut iter = bitfield.iter();
+
+        assert_eq!(10, iter.count_ones());
+        iter.next();
+        assert_eq!(9, iter.count_ones());
+        assert_eq!(9, iter.count());
+    }
+
+    #[test]
+    fn positive_bitfield_iter_large_empty() {
+        let bitfield = BitFieldMessage::with_capacity(8 * 256 * 1024);
+
+        assert_eq!(0, bitfield.iter().count_ones());
+        assert_eq!(None, bitfield.iter().next());
+    }
+
+    #[test]
+    fn positive_piece_block_shares_parsed_buffer() {
+        let mut buffer = vec![0, 0, 0, 1, 0, 0, 0, 2];
+        buffer.extend_from_slice(&[0xAB; 16 * 1024]);
+        let bytes = Bytes::from(buffer);
+
+        let piece = PieceMessage::parse_bytes(bytes.clone()).unwrap();
+
+        assert_eq!(16 * 1024, piece.block_length());
+        assert_eq!(bytes[8..].as_ptr(), piece.block().as_ptr());
+        assert_eq!(bytes[8..].as_ptr(), piece.into_block().as_ptr());
+    }
 }
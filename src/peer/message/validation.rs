@@ -0,0 +1,390 @@
+use std::convert::TryFrom;
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+use crate::peer::message::{PeerExtensionProtocolMessage, PeerWireProtocolMessage};
+
+/// Largest block length we consider a peer to be using in good faith.
+///
+/// The de facto block length is 16 KiB, anything over this is treated as abuse.
+pub const MAX_BLOCK_LEN: usize = 128 * 1024;
+
+/// Error for messages that do not fit the geometry of a torrent.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ValidationError {
+    /// Piece index is not less than the number of pieces.
+    InvalidPieceIndex { piece_index: u32, piece_count: u32 },
+    /// Block extends past the end of the piece it is in.
+    InvalidBlockOffset {
+        piece_index: u32,
+        block_offset: u32,
+        block_length: usize,
+        piece_length: u32,
+    },
+    /// Block length is larger than `MAX_BLOCK_LEN`.
+    InvalidBlockLength { block_length: usize },
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            &ValidationError::InvalidPieceIndex {
+                piece_index,
+                piece_count,
+            } => write!(
+                f,
+                "Invalid Piece Index {} For Piece Count {}",
+                piece_index, piece_count
+            ),
+            &ValidationError::InvalidBlockOffset {
+                piece_index,
+                block_offset,
+                block_length,
+                piece_length,
+            } => write!(
+                f,
+                "Invalid Block Offset {} With Length {} For Piece {} Of Length {}",
+                block_offset, block_length, piece_index, piece_length
+            ),
+            &ValidationError::InvalidBlockLength { block_length } => write!(
+                f,
+                "Invalid Block Length {} Exceeds Maximum Of {}",
+                block_length, MAX_BLOCK_LEN
+            ),
+        }
+    }
+}
+
+impl ValidationError {
+    /// Short name of the rule that was broken, see `ProtocolViolation::rule`.
+    pub fn rule(&self) -> &'static str {
+        match self {
+            &ValidationError::InvalidPieceIndex { .. } => "piece index",
+            &ValidationError::InvalidBlockOffset { .. } => "block offset",
+            &ValidationError::InvalidBlockLength { .. } => "block length",
+        }
+    }
+}
+
+impl Error for ValidationError {}
+
+impl From<ValidationError> for io::Error {
+    fn from(error: ValidationError) -> io::Error {
+        io::Error::new(io::ErrorKind::InvalidData, error)
+    }
+}
+
+/// Validate that the given piece index exists.
+pub(crate) fn validate_piece_index(
+    piece_index: u32,
+    piece_count: u32,
+) -> Result<(), ValidationError> {
+    if piece_index < piece_count {
+        Ok(())
+    } else {
+        Err(ValidationError::InvalidPieceIndex {
+            piece_index: piece_index,
+            piece_count: piece_count,
+        })
+    }
+}
+
+/// Validate that the given block lies within its piece.
+///
+/// The last piece has a length of `last_piece_len`, every other piece a length of `piece_len`.
+pub(crate) fn validate_block(
+    piece_index: u32,
+    block_offset: u32,
+    block_length: usize,
+    piece_count: u32,
+    piece_len: u32,
+    last_piece_len: u32,
+) -> Result<(), ValidationError> {
+    if block_length > MAX_BLOCK_LEN {
+        return Err(ValidationError::InvalidBlockLength {
+            block_length: block_length,
+        });
+    }
+    validate_piece_index(piece_index, piece_count)?;
+
+    let piece_length = if piece_index + 1 == piece_count {
+        last_piece_len
+    } else {
+        piece_len
+    };
+
+    if block_offset as u64 + block_length as u64 > piece_length as u64 {
+        Err(ValidationError::InvalidBlockOffset {
+            piece_index: piece_index,
+            block_offset: block_offset,
+            block_length: block_length,
+            piece_length: piece_length,
+        })
+    } else {
+        Ok(())
+    }
+}
+
+/// Validates messages received from a peer against the geometry of a torrent.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct MessageValidator {
+    piece_count: u32,
+    piece_len: u32,
+    last_piece_len: u32,
+}
+
+impl MessageValidator {
+    /// Create a new `MessageValidator` for a torrent with the given geometry.
+    pub fn new(piece_count: u32, piece_len: u32, last_piece_len: u32) -> MessageValidator {
+        MessageValidator {
+            piece_count: piece_count,
+            piece_len: piece_len,
+            last_piece_len: last_piece_len,
+        }
+    }
+
+    /// Create a new `MessageValidator` for a torrent of the given total length.
+    ///
+    /// Returns `None` if the piece length is zero, or if there are more than `u32::MAX` pieces.
+    pub fn from_total_length(total_length: u64, piece_len: u32) -> Option<MessageValidator> {
+        if piece_len == 0 {
+            return None;
+        }
+
+        let piece_len_u64 = piece_len as u64;
+        let piece_count = total_length / piece_len_u64 + (total_length % piece_len_u64 != 0) as u64;
+        let last_piece_len = total_length - piece_count.saturating_sub(1) * piece_len_u64;
+
+        Some(MessageValidator::new(
+            u32::try_from(piece_count).ok()?,
+            piece_len,
+            last_piece_len as u32,
+        ))
+    }
+
+    /// Number of pieces in the torrent.
+    pub fn piece_count(&self) -> u32 {
+        self.piece_count
+    }
+
+    /// Length of every piece except the last.
+    pub fn piece_length(&self) -> u32 {
+        self.piece_len
+    }
+
+    /// Length of the last piece.
+    pub fn last_piece_length(&self) -> u32 {
+        self.last_piece_len
+    }
+
+    /// Validate the given message.
+    ///
+    /// Messages that do not reference pieces are always valid. Bitfields are checked
+    /// when parsing, see `MessageLimits::with_piece_count`.
+    pub fn validate(&self, message: &PeerWireProtocolMessage) -> Result<(), ValidationError> {
+        match message {
+            &PeerWireProtocolMessage::Have(ref msg) => msg.validate(self.piece_count),
+            &PeerWireProtocolMessage::Request(ref msg) => {
+                msg.validate(self.piece_count, self.piece_len, self.last_piece_len)
+            }
+            &PeerWireProtocolMessage::Piece(ref msg) => {
+                msg.validate(self.piece_count, self.piece_len, self.last_piece_len)
+            }
+            &PeerWireProtocolMessage::Cancel(ref msg) => {
+                msg.validate(self.piece_count, self.piece_len, self.last_piece_len)
+            }
+            &PeerWireProtocolMessage::Suggest(ref msg) => {
+                validate_piece_index(msg.piece_index(), self.piece_count)
+            }
+            &PeerWireProtocolMessage::Reject(ref msg) => validate_block(
+                msg.piece_index(),
+                msg.block_offset(),
+                msg.block_length(),
+                self.piece_count,
+                self.piece_len,
+                self.last_piece_len,
+            ),
+            &PeerWireProtocolMessage::AllowedFast(ref msg) => {
+                validate_piece_index(msg.piece_index(), self.piece_count)
+            }
+            &PeerWireProtocolMessage::ProtExtension(PeerExtensionProtocolMessage::LtDontHave(
+                ref msg,
+            )) => validate_piece_index(msg.piece_index(), self.piece_count),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{MessageValidator, ValidationError, MAX_BLOCK_LEN};
+    use crate::peer::message::{
+        CancelMessage, DontHaveMessage, HaveMessage, PeerExtensionProtocolMessage,
+        PeerWireProtocolMessage, PieceMessage, RequestMessage,
+    };
+
+    use bytes::Bytes;
+
+    const PIECE_LEN: u32 = 256 * 1024;
+    const LAST_PIECE_LEN: u32 = 1000;
+
+    fn validator() -> MessageValidator {
+        MessageValidator::new(100, PIECE_LEN, LAST_PIECE_LEN)
+    }
+
+    #[test]
+    fn positive_from_total_length_partial_last_piece() {
+        let from_length =
+            MessageValidator::from_total_length(99 * PIECE_LEN as u64 + 1000, PIECE_LEN);
+
+        assert_eq!(Some(validator()), from_length);
+    }
+
+    #[test]
+    fn positive_from_total_length_full_last_piece() {
+        let validator =
+            MessageValidator::from_total_length(4 * PIECE_LEN as u64, PIECE_LEN).unwrap();
+
+        assert_eq!(4, validator.piece_count());
+        assert_eq!(PIECE_LEN, validator.last_piece_length());
+    }
+
+    #[test]
+    fn negative_from_total_length_zero_piece_length() {
+        assert_eq!(None, MessageValidator::from_total_length(1000, 0));
+    }
+
+    #[test]
+    fn negative_from_total_length_too_many_pieces() {
+        let total_length = (u32::max_value() as u64 + 1) * 16384;
+
+        assert_eq!(None, MessageValidator::from_total_length(total_length, 16384));
+        assert_eq!(None, MessageValidator::from_total_length(u64::max_value(), 1));
+    }
+
+    #[test]
+    fn positive_have_last_piece() {
+        assert_eq!(Ok(()), HaveMessage::new(99).validate(100));
+    }
+
+    #[test]
+    fn negative_have_past_last_piece() {
+        assert_eq!(
+            Err(ValidationError::InvalidPieceIndex {
+                piece_index: 100,
+                piece_count: 100
+            }),
+            HaveMessage::new(100).validate(100)
+        );
+    }
+
+    #[test]
+    fn positive_request_ends_at_last_piece_length() {
+        let request = RequestMessage::new(99, 0, LAST_PIECE_LEN as usize);
+
+        assert_eq!(Ok(()), request.validate(100, PIECE_LEN, LAST_PIECE_LEN));
+    }
+
+    #[test]
+    fn positive_request_last_byte_of_last_piece() {
+        let request = RequestMessage::new(99, LAST_PIECE_LEN - 1, 1);
+
+        assert_eq!(Ok(()), request.validate(100, PIECE_LEN, LAST_PIECE_LEN));
+    }
+
+    #[test]
+    fn negative_request_one_past_last_piece_length() {
+        let request = RequestMessage::new(99, 1, LAST_PIECE_LEN as usize);
+
+        assert_eq!(
+            Err(ValidationError::InvalidBlockOffset {
+                piece_index: 99,
+                block_offset: 1,
+                block_length: LAST_PIECE_LEN as usize,
+                piece_length: LAST_PIECE_LEN
+            }),
+            request.validate(100, PIECE_LEN, LAST_PIECE_LEN)
+        );
+    }
+
+    #[test]
+    fn negative_request_full_piece_from_last_piece() {
+        let request = RequestMessage::new(99, PIECE_LEN - 16384, 16384);
+
+        assert!(request.validate(100, PIECE_LEN, LAST_PIECE_LEN).is_err());
+    }
+
+    #[test]
+    fn positive_request_end_of_second_to_last_piece() {
+        let request = RequestMessage::new(98, PIECE_LEN - 16384, 16384);
+
+        assert_eq!(Ok(()), request.validate(100, PIECE_LEN, LAST_PIECE_LEN));
+    }
+
+    #[test]
+    fn negative_request_offset_overflow() {
+        let request = RequestMessage::new(0, u32::max_value(), 16384);
+
+        assert!(request.validate(100, PIECE_LEN, LAST_PIECE_LEN).is_err());
+    }
+
+    #[test]
+    fn positive_request_max_block_length() {
+        let request = RequestMessage::new(0, 0, MAX_BLOCK_LEN);
+
+        assert_eq!(Ok(()), request.validate(100, PIECE_LEN, LAST_PIECE_LEN));
+    }
+
+    #[test]
+    fn negative_request_exceeds_max_block_length() {
+        let request = RequestMessage::new(0, 0, MAX_BLOCK_LEN + 1);
+
+        assert_eq!(
+            Err(ValidationError::InvalidBlockLength {
+                block_length: MAX_BLOCK_LEN + 1
+            }),
+            request.validate(100, PIECE_LEN, LAST_PIECE_LEN)
+        );
+    }
+
+    #[test]
+    fn negative_cancel_past_last_piece() {
+        let cancel = CancelMessage::new(100, 0, 16384);
+
+        assert!(cancel.validate(100, PIECE_LEN, LAST_PIECE_LEN).is_err());
+    }
+
+    #[test]
+    fn positive_single_piece_torrent() {
+        let validator = MessageValidator::from_total_length(10, PIECE_LEN).unwrap();
+        let message = PeerWireProtocolMessage::Request(RequestMessage::new(0, 0, 10));
+
+        assert_eq!(Ok(()), validator.validate(&message));
+    }
+
+    #[test]
+    fn negative_validate_piece_past_last_piece_length() {
+        let message = PeerWireProtocolMessage::Piece(PieceMessage::new(
+            99,
+            0,
+            Bytes::from(vec![0u8; LAST_PIECE_LEN as usize + 1]),
+        ));
+
+        assert!(validator().validate(&message).is_err());
+    }
+
+    #[test]
+    fn negative_validate_dont_have_past_last_piece() {
+        let message = PeerWireProtocolMessage::ProtExtension(
+            PeerExtensionProtocolMessage::LtDontHave(DontHaveMessage::new(100)),
+        );
+
+        assert!(validator().validate(&message).is_err());
+    }
+
+    #[test]
+    fn positive_validate_ignores_other_messages() {
+        assert_eq!(Ok(()), validator().validate(&PeerWireProtocolMessage::HaveAll));
+    }
+}
@@ -0,0 +1,110 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+use crate::peer::message::ValidationError;
+
+/// Error for a message that breaks a rule of the protocol.
+///
+/// Converts into an `io::Error` of kind `InvalidData`, the rule can be recovered from
+/// that error with `ProtocolViolation::rule_of`. Messages we merely do not understand
+/// use `ProtocolViolation::into_skippable` instead, so they can be skipped over.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ProtocolViolation {
+    rule: &'static str,
+    message: String,
+}
+
+impl ProtocolViolation {
+    /// Create a new `ProtocolViolation` for the given rule.
+    pub fn new<M>(rule: &'static str, message: M) -> ProtocolViolation
+    where
+        M: Into<String>,
+    {
+        ProtocolViolation {
+            rule: rule,
+            message: message.into(),
+        }
+    }
+
+    /// Convert into an `io::Error` of kind `Other`, for a complete message that can be
+    /// skipped without losing our place in the stream.
+    pub fn into_skippable(self) -> io::Error {
+        io::Error::new(io::ErrorKind::Other, self)
+    }
+
+    /// Short name of the rule that was broken, for example `"bitfield length"`.
+    pub fn rule(&self) -> &'static str {
+        self.rule
+    }
+
+    /// Retrieve the rule broken by the message that caused the given error, if any.
+    pub fn rule_of(error: &io::Error) -> Option<&'static str> {
+        let inner = error.get_ref()?;
+
+        inner
+            .downcast_ref::<ProtocolViolation>()
+            .map(ProtocolViolation::rule)
+            .or_else(|| {
+                inner
+                    .downcast_ref::<ValidationError>()
+                    .map(ValidationError::rule)
+            })
+    }
+}
+
+impl fmt::Display for ProtocolViolation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl Error for ProtocolViolation {}
+
+impl From<ProtocolViolation> for io::Error {
+    fn from(violation: ProtocolViolation) -> io::Error {
+        io::Error::new(io::ErrorKind::InvalidData, violation)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use super::ProtocolViolation;
+    use crate::peer::message::ValidationError;
+
+    #[test]
+    fn positive_rule_of_violation() {
+        let error: io::Error = ProtocolViolation::new("bitfield length", "Too Long").into();
+
+        assert_eq!(io::ErrorKind::InvalidData, error.kind());
+        assert_eq!(Some("bitfield length"), ProtocolViolation::rule_of(&error));
+        assert_eq!("Too Long", error.to_string());
+    }
+
+    #[test]
+    fn positive_rule_of_skippable_violation() {
+        let error = ProtocolViolation::new("message id", "Unknown Message Id 99").into_skippable();
+
+        assert_eq!(io::ErrorKind::Other, error.kind());
+        assert_eq!(Some("message id"), ProtocolViolation::rule_of(&error));
+    }
+
+    #[test]
+    fn positive_rule_of_validation_error() {
+        let error: io::Error = ValidationError::InvalidBlockLength {
+            block_length: 1 << 20,
+        }
+        .into();
+
+        assert_eq!(Some("block length"), ProtocolViolation::rule_of(&error));
+    }
+
+    #[test]
+    fn negative_rule_of_plain_error() {
+        let error = io::Error::new(io::ErrorKind::InvalidData, "Bad Message");
+
+        assert_eq!(None, ProtocolViolation::rule_of(&error));
+    }
+}
@@ -1,7 +1,12 @@
 use std::io::{self, Write};
 
 use super::{MessageCodec};
-use crate::peer::message::{BitsExtensionMessage, ExtendedMessage, PeerWireProtocolMessage};
+use crate::handshake::{Extension, Extensions};
+use crate::peer::manager::capabilities::PeerCapabilities;
+use crate::peer::message::{
+    BitsExtensionMessage, ExtendedMessage, MessageLimits, PeerWireProtocolMessage,
+    ProtocolViolation,
+};
 
 use bytes::Bytes;
 
@@ -9,6 +14,9 @@ use bytes::Bytes;
 pub struct PeerWireMessageCodec {
     our_extended_msg: Option<ExtendedMessage>,
     their_extended_msg: Option<ExtendedMessage>,
+    their_capabilities: PeerCapabilities,
+    fast_extension: bool,
+    limits: MessageLimits,
 }
 
 impl PeerWireMessageCodec {
@@ -21,8 +29,43 @@ impl PeerWireMessageCodec {
         PeerWireMessageCodec {
             our_extended_msg: None,
             their_extended_msg: None,
+            their_capabilities: PeerCapabilities::new(Extensions::new()),
+            fast_extension: false,
+            limits: MessageLimits::default(),
         }
     }
+
+    /// Set the `MessageLimits` that incoming messages are checked against.
+    pub fn with_limits(mut self, limits: MessageLimits) -> PeerWireMessageCodec {
+        self.limits = limits;
+        self
+    }
+
+    /// Create a new `PeerWireProtocol` for a connection that negotiated the given `Extensions`.
+    ///
+    /// Fast extension messages will only be accepted if the fast extension bit is set.
+    pub fn with_extensions(extensions: &Extensions) -> PeerWireMessageCodec {
+        let mut codec = PeerWireMessageCodec::new();
+        codec.fast_extension = extensions.contains(Extension::FastExtension);
+        codec.their_capabilities = PeerCapabilities::new(*extensions);
+
+        codec
+    }
+
+    /// Retrieve the last `ExtendedMessage` we sent, if any.
+    pub fn our_extended_message(&self) -> Option<&ExtendedMessage> {
+        self.our_extended_msg.as_ref()
+    }
+
+    /// Retrieve the last `ExtendedMessage` we received, if any.
+    pub fn their_extended_message(&self) -> Option<&ExtendedMessage> {
+        self.their_extended_msg.as_ref()
+    }
+
+    /// Retrieve the capabilities negotiated with the peer so far.
+    pub fn their_capabilities(&self) -> &PeerCapabilities {
+        &self.their_capabilities
+    }
 }
 
 impl MessageCodec for PeerWireMessageCodec
@@ -30,18 +73,26 @@ impl MessageCodec for PeerWireMessageCodec
     type Message = PeerWireProtocolMessage;
 
     fn bytes_needed(&mut self, bytes: &[u8]) -> io::Result<Option<usize>> {
-        PeerWireProtocolMessage::bytes_needed(bytes)
+        PeerWireProtocolMessage::bytes_needed(bytes, &self.limits)
     }
 
     fn parse_bytes(&mut self, bytes: Bytes) -> io::Result<Self::Message> {
-        match PeerWireProtocolMessage::parse_bytes(bytes, &self.our_extended_msg) {
+        match PeerWireProtocolMessage::parse_bytes(bytes, &self.our_extended_msg, &self.limits) {
             Ok(PeerWireProtocolMessage::BitsExtension(BitsExtensionMessage::Extended(msg))) => {
+                self.their_capabilities.update(&msg);
                 self.their_extended_msg = Some(msg.clone());
 
                 Ok(PeerWireProtocolMessage::BitsExtension(
                     BitsExtensionMessage::Extended(msg),
                 ))
             }
+            Ok(ref msg) if msg.is_fast_extension() && !self.fast_extension => {
+                Err(ProtocolViolation::new(
+                    "fast extension",
+                    "Received Fast Extension Message Without Negotiating Fast Extension",
+                )
+                .into())
+            }
             other => other,
         }
     }
@@ -50,7 +101,15 @@ impl MessageCodec for PeerWireMessageCodec
     where
         W: Write,
     {
-        match (message.write_bytes(writer, &self.their_extended_msg), message) {
+        // Extension ids come from every extended message they sent, not just the last one
+        let result = match message {
+            &PeerWireProtocolMessage::ProtExtension(ref ext) => {
+                ext.write_bytes_with_capabilities(writer, &self.their_capabilities)
+            }
+            _ => message.write_bytes(writer, &self.their_extended_msg),
+        };
+
+        match (result, message) {
             (
                 Ok(()),
                 &PeerWireProtocolMessage::BitsExtension(BitsExtensionMessage::Extended(ref msg)),
@@ -69,3 +128,140 @@ impl MessageCodec for PeerWireMessageCodec
     }
 
 }
+
+#[cfg(test)]
+mod tests {
+    use super::PeerWireMessageCodec;
+    use crate::handshake::{Extension, Extensions};
+    use crate::peer::message::{
+        ExtendedType, PeerExtensionProtocolMessage, PeerWireProtocolMessage, ProtocolViolation,
+        UtMetadataMessage, UtMetadataRequestMessage, UtPexMessage,
+    };
+    use crate::peer::MessageCodec;
+    use crate::peer::messages::builders::ExtendedMessageBuilder;
+
+    use bytes::Bytes;
+    use std::io;
+
+    #[test]
+    fn positive_parse_fast_extension_negotiated() {
+        let mut extensions = Extensions::new();
+        extensions.add(Extension::FastExtension);
+        let mut codec = PeerWireMessageCodec::with_extensions(&extensions);
+
+        let message = codec.parse_bytes(Bytes::from(vec![0, 0, 0, 1, 14])).unwrap();
+
+        assert_eq!(PeerWireProtocolMessage::HaveAll, message);
+    }
+
+    #[test]
+    fn negative_parse_fast_extension_not_negotiated() {
+        let mut codec = PeerWireMessageCodec::with_extensions(&Extensions::new());
+
+        let error = codec.parse_bytes(Bytes::from(vec![0, 0, 0, 1, 15])).unwrap_err();
+
+        assert_eq!(io::ErrorKind::InvalidData, error.kind());
+        assert_eq!(Some("fast extension"), ProtocolViolation::rule_of(&error));
+    }
+
+    fn extended_bytes(builder: ExtendedMessageBuilder) -> Bytes {
+        let message = builder.build();
+        let bencode = message.bencode_ref().buffer();
+
+        let mut bytes = vec![0, 0, 0, bencode.len() as u8 + 2, 20, 0];
+        bytes.extend_from_slice(bencode);
+
+        Bytes::from(bytes)
+    }
+
+    #[test]
+    fn positive_parse_extended_replaces_previous() {
+        let mut codec = PeerWireMessageCodec::new();
+
+        codec
+            .parse_bytes(extended_bytes(
+                ExtendedMessageBuilder::new().with_upload_only(false),
+            ))
+            .unwrap();
+        codec
+            .parse_bytes(extended_bytes(
+                ExtendedMessageBuilder::new().with_upload_only(true),
+            ))
+            .unwrap();
+
+        let their_upload_only = codec
+            .their_extended_msg
+            .as_ref()
+            .and_then(|msg| msg.upload_only());
+        assert_eq!(Some(true), their_upload_only);
+    }
+
+    fn pex_message() -> PeerWireProtocolMessage {
+        PeerWireProtocolMessage::ProtExtension(PeerExtensionProtocolMessage::UtPex(
+            UtPexMessage::new(Vec::new(), Vec::new()),
+        ))
+    }
+
+    #[test]
+    fn positive_write_uses_merged_extension_ids() {
+        let mut codec = PeerWireMessageCodec::new();
+        codec
+            .parse_bytes(extended_bytes(
+                ExtendedMessageBuilder::new()
+                    .with_extended_type(ExtendedType::UtMetadata, Some(3))
+                    .with_extended_type(ExtendedType::UtPex, Some(4)),
+            ))
+            .unwrap();
+        codec
+            .parse_bytes(extended_bytes(
+                ExtendedMessageBuilder::new().with_extended_type(ExtendedType::UtPex, Some(0)),
+            ))
+            .unwrap();
+
+        let request = PeerWireProtocolMessage::ProtExtension(
+            PeerExtensionProtocolMessage::UtMetadata(UtMetadataMessage::Request(
+                UtMetadataRequestMessage::new(0),
+            )),
+        );
+        let mut bytes = Vec::new();
+        codec.write_bytes(&request, &mut bytes).unwrap();
+
+        assert_eq!(3, bytes[5]);
+        assert_eq!(
+            Some(3),
+            codec
+                .their_capabilities()
+                .extended_id_for(&ExtendedType::UtMetadata)
+        );
+    }
+
+    #[test]
+    fn negative_write_extension_removed_by_rehandshake() {
+        let mut codec = PeerWireMessageCodec::new();
+        codec
+            .parse_bytes(extended_bytes(
+                ExtendedMessageBuilder::new().with_extended_type(ExtendedType::UtPex, Some(4)),
+            ))
+            .unwrap();
+        codec.write_bytes(&pex_message(), &mut Vec::new()).unwrap();
+
+        codec
+            .parse_bytes(extended_bytes(
+                ExtendedMessageBuilder::new().with_extended_type(ExtendedType::UtPex, Some(0)),
+            ))
+            .unwrap();
+
+        assert!(codec.write_bytes(&pex_message(), &mut Vec::new()).is_err());
+    }
+
+    #[test]
+    fn negative_write_extension_disabled_in_extended_message() {
+        let extended = ExtendedMessageBuilder::new()
+            .with_extended_type(ExtendedType::UtPex, Some(0))
+            .build();
+
+        assert!(pex_message()
+            .write_bytes(&mut Vec::new(), &Some(extended))
+            .is_err());
+    }
+}
@@ -0,0 +1,248 @@
+use std::io;
+
+use bytes::BytesMut;
+
+use super::codec::PeerWireMessageCodec;
+use super::MessageCodec;
+use crate::peer::message::PeerWireProtocolMessage;
+
+/// Incremental decoder for peer wire messages arriving in arbitrarily sized chunks.
+///
+/// The length prefix of each message is parsed exactly once, after which we simply wait
+/// for the rest of the message to arrive, instead of re-scanning the header on every read.
+pub struct PeerWireMessageDecoder {
+    codec: PeerWireMessageCodec,
+    frame_len: Option<usize>,
+}
+
+impl PeerWireMessageDecoder {
+    /// Create a new `PeerWireMessageDecoder` parsing messages with the given codec.
+    pub fn new(codec: PeerWireMessageCodec) -> PeerWireMessageDecoder {
+        PeerWireMessageDecoder {
+            codec: codec,
+            frame_len: None,
+        }
+    }
+
+    /// Retrieve the codec used to parse messages.
+    pub fn codec(&self) -> &PeerWireMessageCodec {
+        &self.codec
+    }
+
+    /// Retrieve the codec used to parse messages.
+    ///
+    /// Messages we send should go through this codec, so it knows our extended message.
+    pub fn codec_mut(&mut self) -> &mut PeerWireMessageCodec {
+        &mut self.codec
+    }
+
+    /// Decode the next message from the front of the given bytes.
+    ///
+    /// Returns `None` if a complete message is not yet available. Once a complete message is
+    /// available it is removed from the buffer, even if it fails to parse, so errors that are
+    /// not `io::ErrorKind::InvalidData` can be skipped by calling `decode` again.
+    pub fn decode(&mut self, bytes: &mut BytesMut) -> io::Result<Option<PeerWireProtocolMessage>> {
+        let frame_len = match self.frame_len {
+            Some(frame_len) => frame_len,
+            None => match self.codec.bytes_needed(bytes.as_ref())? {
+                Some(frame_len) => {
+                    self.frame_len = Some(frame_len);
+                    frame_len
+                }
+                None => return Ok(None),
+            },
+        };
+
+        if bytes.len() < frame_len {
+            bytes.reserve(frame_len - bytes.len());
+
+            return Ok(None);
+        }
+        self.frame_len = None;
+
+        // Message payloads (piece blocks, bitfields) are views into this buffer, not copies
+        let frame = bytes.split_to(frame_len).freeze();
+        self.codec.parse_bytes(frame).map(Some)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PeerWireMessageDecoder;
+    use crate::handshake::{Extension, Extensions};
+    use crate::peer::message::{
+        AllowedFastMessage, BitFieldMessage, BitsExtensionMessage, CancelMessage,
+        CustomExtensionMessage, DontHaveMessage, ExtendedMessage, ExtendedMessageBuilder,
+        ExtendedType, HaveMessage, MessageLimits, PeerExtensionProtocolMessage,
+        PeerWireProtocolMessage, PieceMessage, PortMessage, RejectMessage, RequestMessage,
+        SuggestMessage, UtMetadataMessage, UtMetadataRequestMessage, UtPexMessage,
+    };
+    use crate::peer::{MessageCodec, PeerWireMessageCodec};
+
+    use bytes::{Bytes, BytesMut};
+    use rand::{Rng, SeedableRng, XorShiftRng};
+
+    fn extended() -> ExtendedMessage {
+        ExtendedMessageBuilder::new()
+            .with_extended_type(ExtendedType::UtMetadata, Some(1))
+            .with_extended_type(ExtendedType::UtPex, Some(2))
+            .with_extended_type(ExtendedType::LtDontHave, Some(3))
+            .with_extended_type(ExtendedType::Custom("my_swarm_chat".to_string()), Some(4))
+            .build()
+    }
+
+    fn all_messages() -> Vec<PeerWireProtocolMessage> {
+        vec![
+            PeerWireProtocolMessage::KeepAlive,
+            PeerWireProtocolMessage::Choke,
+            PeerWireProtocolMessage::UnChoke,
+            PeerWireProtocolMessage::Interested,
+            PeerWireProtocolMessage::UnInterested,
+            PeerWireProtocolMessage::Have(HaveMessage::new(7)),
+            PeerWireProtocolMessage::BitField(BitFieldMessage::new(Bytes::from(vec![0xAA; 37]))),
+            PeerWireProtocolMessage::Request(RequestMessage::new(1, 16384, 16384)),
+            PeerWireProtocolMessage::Piece(PieceMessage::new(1, 16384, Bytes::from(vec![0x55; 1024]))),
+            PeerWireProtocolMessage::Cancel(CancelMessage::new(1, 16384, 16384)),
+            PeerWireProtocolMessage::HaveAll,
+            PeerWireProtocolMessage::HaveNone,
+            PeerWireProtocolMessage::Suggest(SuggestMessage::new(3)),
+            PeerWireProtocolMessage::Reject(RejectMessage::new(1, 0, 16384)),
+            PeerWireProtocolMessage::AllowedFast(AllowedFastMessage::new(9)),
+            PeerWireProtocolMessage::BitsExtension(BitsExtensionMessage::Port(PortMessage::new(
+                6881,
+            ))),
+            PeerWireProtocolMessage::BitsExtension(BitsExtensionMessage::Extended(extended())),
+            PeerWireProtocolMessage::ProtExtension(PeerExtensionProtocolMessage::UtMetadata(
+                UtMetadataMessage::Request(UtMetadataRequestMessage::new(2)),
+            )),
+            PeerWireProtocolMessage::ProtExtension(PeerExtensionProtocolMessage::UtPex(
+                UtPexMessage::new(
+                    vec![("10.0.0.1:6881".parse().unwrap(), 0x02)],
+                    vec!["10.0.0.2:6881".parse().unwrap()],
+                ),
+            )),
+            PeerWireProtocolMessage::ProtExtension(PeerExtensionProtocolMessage::LtDontHave(
+                DontHaveMessage::new(5),
+            )),
+            PeerWireProtocolMessage::ProtExtension(PeerExtensionProtocolMessage::Custom(
+                CustomExtensionMessage::new(
+                    ExtendedType::Custom("my_swarm_chat".to_string()),
+                    Bytes::from(&b"hello"[..]),
+                ),
+            )),
+            PeerWireProtocolMessage::ProtExtension(PeerExtensionProtocolMessage::RawExtension {
+                id: 200,
+                payload: Bytes::from(vec![1, 2, 3]),
+            }),
+        ]
+    }
+
+    fn write_stream(messages: &[PeerWireProtocolMessage]) -> Vec<u8> {
+        let mut stream = Vec::new();
+        for message in messages {
+            message.write_bytes(&mut stream, &Some(extended())).unwrap();
+        }
+
+        stream
+    }
+
+    fn new_decoder() -> PeerWireMessageDecoder {
+        let mut extensions = Extensions::new();
+        extensions.add(Extension::FastExtension);
+
+        let mut decoder = PeerWireMessageDecoder::new(PeerWireMessageCodec::with_extensions(
+            &extensions,
+        ));
+
+        // Let the codec know which ids we advertised for extension messages
+        let our_extended =
+            PeerWireProtocolMessage::BitsExtension(BitsExtensionMessage::Extended(extended()));
+        decoder
+            .codec_mut()
+            .write_bytes(&our_extended, &mut Vec::new())
+            .unwrap();
+
+        decoder
+    }
+
+    /// Feed the stream to a decoder, splitting it at the given boundaries.
+    fn decode_chunks(stream: &[u8], boundaries: &[usize]) -> Vec<PeerWireProtocolMessage> {
+        let mut decoder = new_decoder();
+        let mut buffer = BytesMut::new();
+        let mut messages = Vec::new();
+
+        let mut start = 0;
+        for &end in boundaries.iter().chain(Some(stream.len()).iter()) {
+            buffer.extend_from_slice(&stream[start..end]);
+            start = end;
+
+            while let Some(message) = decoder.decode(&mut buffer).unwrap() {
+                messages.push(message);
+            }
+        }
+
+        assert!(buffer.is_empty());
+        messages
+    }
+
+    /// Parse the stream one message at a time with `bytes_needed` and `parse_bytes`.
+    fn parse_whole(stream: &[u8]) -> Vec<PeerWireProtocolMessage> {
+        let mut codec = new_decoder().codec;
+        let mut bytes = Bytes::from(stream);
+        let mut messages = Vec::new();
+
+        while !bytes.is_empty() {
+            let needed = codec.bytes_needed(bytes.as_ref()).unwrap().unwrap();
+            messages.push(codec.parse_bytes(bytes.split_to(needed)).unwrap());
+        }
+
+        messages
+    }
+
+    #[test]
+    fn positive_decode_matches_parser_at_every_boundary() {
+        let messages = all_messages();
+        let stream = write_stream(&messages[..]);
+
+        assert_eq!(messages, parse_whole(&stream));
+        for boundary in 0..stream.len() {
+            assert_eq!(messages, decode_chunks(&stream, &[boundary]));
+        }
+    }
+
+    #[test]
+    fn positive_decode_matches_parser_at_random_boundaries() {
+        let messages = all_messages();
+        let stream = write_stream(&messages[..]);
+        let mut rng = XorShiftRng::from_seed([1, 2, 3, 4]);
+
+        for _ in 0..200 {
+            let num_boundaries = rng.gen_range(1, 64);
+            let mut boundaries: Vec<usize> = (0..num_boundaries)
+                .map(|_| rng.gen_range(0, stream.len()))
+                .collect();
+            boundaries.sort();
+
+            assert_eq!(messages, decode_chunks(&stream, &boundaries));
+        }
+    }
+
+    #[test]
+    fn positive_decode_byte_at_a_time() {
+        let messages = all_messages();
+        let stream = write_stream(&messages[..]);
+        let boundaries: Vec<usize> = (1..stream.len()).collect();
+
+        assert_eq!(messages, decode_chunks(&stream, &boundaries));
+    }
+
+    #[test]
+    fn negative_decode_oversized_length_before_payload() {
+        let mut decoder = PeerWireMessageDecoder::new(
+            PeerWireMessageCodec::new().with_limits(MessageLimits::default()),
+        );
+        let mut buffer = BytesMut::from(vec![0xFF, 0xFF, 0xFF, 0xFF]);
+
+        assert!(decoder.decode(&mut buffer).is_err());
+    }
+}
@@ -5,6 +5,9 @@ use std::io::{self, Write};
 use bytes::Bytes;
 
 pub mod codec;
+pub mod decoder;
+#[cfg(feature = "tokio-codec")]
+pub mod tokio_codec;
 
 /// Trait for implementing a bittorrent protocol message.
 pub trait MessageCodec {
@@ -0,0 +1,179 @@
+use std::io;
+
+use bytes::BytesMut;
+use bytes_1::BufMut;
+use tokio_util::codec::{Decoder, Encoder};
+
+use super::codec::PeerWireMessageCodec;
+use super::decoder::PeerWireMessageDecoder;
+use super::MessageCodec;
+use crate::peer::message::{ExtendedMessage, PeerWireProtocolMessage};
+
+/// Length of the length prefix of a peer wire message.
+const LENGTH_PREFIX_LEN: usize = 4;
+
+/// `tokio_util` codec for sending and receiving `PeerWireProtocolMessage`s over a framed transport.
+///
+/// Extended messages that are sent or received are tracked, so extension protocol messages
+/// are written and parsed with the ids negotiated for the connection.
+pub struct PeerWireCodec {
+    decoder: PeerWireMessageDecoder,
+    // Frames have to be moved over from `bytes` 1.x, so payloads can be views into this buffer
+    buffer: BytesMut,
+    max_frame_len: Option<usize>,
+}
+
+impl PeerWireCodec {
+    /// Create a new `PeerWireCodec` parsing and writing messages with the given codec.
+    pub fn new(codec: PeerWireMessageCodec) -> PeerWireCodec {
+        PeerWireCodec {
+            decoder: PeerWireMessageDecoder::new(codec),
+            buffer: BytesMut::new(),
+            max_frame_len: None,
+        }
+    }
+
+    /// Sets the maximum length of a frame, including the length prefix.
+    ///
+    /// This is checked in addition to the `MessageLimits` of the codec.
+    pub fn with_max_frame_length(mut self, length: usize) -> PeerWireCodec {
+        self.max_frame_len = Some(length);
+        self
+    }
+
+    /// Retrieve the last `ExtendedMessage` we sent, if any.
+    pub fn our_extended_message(&self) -> Option<&ExtendedMessage> {
+        self.decoder.codec().our_extended_message()
+    }
+
+    /// Retrieve the last `ExtendedMessage` we received, if any.
+    pub fn their_extended_message(&self) -> Option<&ExtendedMessage> {
+        self.decoder.codec().their_extended_message()
+    }
+
+    fn check_frame_length(&self) -> io::Result<()> {
+        let max_frame_len = match self.max_frame_len {
+            Some(max_frame_len) if self.buffer.len() >= LENGTH_PREFIX_LEN => max_frame_len,
+            _ => return Ok(()),
+        };
+
+        let length_bytes = &self.buffer[..LENGTH_PREFIX_LEN];
+        let frame_len = LENGTH_PREFIX_LEN as u64
+            + ((length_bytes[0] as u64) << 24
+                | (length_bytes[1] as u64) << 16
+                | (length_bytes[2] as u64) << 8
+                | length_bytes[3] as u64);
+
+        if frame_len > max_frame_len as u64 {
+            Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "Frame Length {} Exceeds Maximum Frame Length {}",
+                    frame_len, max_frame_len
+                ),
+            ))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl Decoder for PeerWireCodec {
+    type Item = PeerWireProtocolMessage;
+    type Error = io::Error;
+
+    fn decode(&mut self, src: &mut bytes_1::BytesMut) -> io::Result<Option<PeerWireProtocolMessage>> {
+        self.buffer.extend_from_slice(src.as_ref());
+        src.clear();
+
+        loop {
+            self.check_frame_length()?;
+
+            match self.decoder.decode(&mut self.buffer) {
+                Ok(opt_message) => return Ok(opt_message),
+                Err(err) if err.kind() == io::ErrorKind::InvalidData => return Err(err),
+                // Complete message that we dont recognize, skip over it
+                Err(err) => info!("bittorrent-protocol_peer: PeerWireCodec Skipping Message: {:?}", err),
+            }
+        }
+    }
+
+    fn decode_eof(&mut self, src: &mut bytes_1::BytesMut) -> io::Result<Option<PeerWireProtocolMessage>> {
+        match self.decode(src)? {
+            Some(message) => Ok(Some(message)),
+            None if self.buffer.is_empty() => Ok(None),
+            None => Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!("Stream Ended With {} Bytes Of An Incomplete Message", self.buffer.len()),
+            )),
+        }
+    }
+}
+
+impl Encoder<PeerWireProtocolMessage> for PeerWireCodec {
+    type Error = io::Error;
+
+    fn encode(&mut self, item: PeerWireProtocolMessage, dst: &mut bytes_1::BytesMut) -> io::Result<()> {
+        let codec = self.decoder.codec_mut();
+
+        dst.reserve(codec.message_size(&item));
+        codec.write_bytes(&item, dst.writer())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PeerWireCodec;
+    use crate::peer::message::PeerWireProtocolMessage;
+    use crate::peer::PeerWireMessageCodec;
+
+    use bytes_1::BytesMut;
+    use std::io;
+    use tokio_util::codec::{Decoder, Encoder};
+
+    #[test]
+    fn positive_decode_split_frame() {
+        let mut codec = PeerWireCodec::new(PeerWireMessageCodec::new());
+
+        let mut src = BytesMut::from(&[0u8, 0, 0][..]);
+        assert_eq!(None, codec.decode(&mut src).unwrap());
+
+        src.extend_from_slice(&[1, 2]);
+        assert_eq!(Some(PeerWireProtocolMessage::Interested), codec.decode(&mut src).unwrap());
+        assert_eq!(None, codec.decode(&mut src).unwrap());
+    }
+
+    #[test]
+    fn positive_encode_message() {
+        let mut codec = PeerWireCodec::new(PeerWireMessageCodec::new());
+        let mut dst = BytesMut::new();
+
+        codec.encode(PeerWireProtocolMessage::UnChoke, &mut dst).unwrap();
+
+        assert_eq!(&[0u8, 0, 0, 1, 1][..], dst.as_ref());
+    }
+
+    #[test]
+    fn positive_skip_unrecognized_message() {
+        let mut codec = PeerWireCodec::new(PeerWireMessageCodec::new());
+        let mut src = BytesMut::from(&[0u8, 0, 0, 1, 99, 0, 0, 0, 1, 0][..]);
+
+        assert_eq!(Some(PeerWireProtocolMessage::Choke), codec.decode(&mut src).unwrap());
+    }
+
+    #[test]
+    fn negative_decode_exceeds_max_frame_length() {
+        let mut codec = PeerWireCodec::new(PeerWireMessageCodec::new()).with_max_frame_length(16);
+        let mut src = BytesMut::from(&[0u8, 0, 0, 13, 6][..]);
+
+        assert_eq!(io::ErrorKind::InvalidData, codec.decode(&mut src).unwrap_err().kind());
+    }
+
+    #[test]
+    fn negative_decode_eof_incomplete_message() {
+        let mut codec = PeerWireCodec::new(PeerWireMessageCodec::new());
+        let mut src = BytesMut::from(&[0u8, 0, 0, 5, 4][..]);
+
+        assert_eq!(io::ErrorKind::UnexpectedEof, codec.decode_eof(&mut src).unwrap_err().kind());
+    }
+}
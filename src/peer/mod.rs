@@ -1,16 +1,19 @@
 
-#[macro_use]
-mod macros;
-
 mod message;
 
 /// Serializable and deserializable protocol messages.
 pub mod messages {
     pub use crate::peer::message::{
-        BitFieldIter, BitFieldMessage, BitsExtensionMessage, CancelMessage, ExtendedMessage,
-        ExtendedType, HaveMessage, NullProtocolMessage, PeerExtensionProtocolMessage,
-        PeerWireProtocolMessage, PieceMessage, PortMessage, RequestMessage, UtMetadataDataMessage,
-        UtMetadataMessage, UtMetadataRejectMessage, UtMetadataRequestMessage,
+        metadata_piece_len, num_metadata_pieces, AllowedFastMessage, BitFieldIter,
+        BitFieldMessage, BitsExtensionMessage, CancelMessage, CustomExtensionMessage,
+        DontHaveMessage, ExtendedMessage, ExtendedType, HaveMessage, MessageLimits,
+        MessageValidator, PeerExtensionProtocolMessage, PeerWireProtocolMessage, PieceMessage,
+        PortMessage, ProtocolViolation, RejectMessage, RequestMessage, SuggestMessage,
+        UtMetadataDataMessage, UtMetadataError, UtMetadataMessage, UtMetadataRejectMessage,
+        UtMetadataRequestMessage, UtPexMessage, UtHolepunchErrorCode, UtHolepunchMessage,
+        ValidationError, MAX_BLOCK_LEN,
+        UT_METADATA_PIECE_LEN, UT_PEX_FLAG_ENCRYPTION, UT_PEX_FLAG_HOLEPUNCH,
+        UT_PEX_FLAG_REACHABLE, UT_PEX_FLAG_SEED, UT_PEX_FLAG_UTP,
     };
 
     /// Builder types for protocol messages.
@@ -22,14 +25,22 @@ pub mod messages {
 mod message_codec;
 pub use message_codec::MessageCodec;
 pub use message_codec::codec::PeerWireMessageCodec;
+pub use message_codec::decoder::PeerWireMessageDecoder;
+#[cfg(feature = "tokio-codec")]
+pub use message_codec::tokio_codec::PeerWireCodec;
 
 mod manager;
 pub use manager::{
-    IPeerManagerMessage, ManagedMessage, MessageId, OPeerManagerMessage, PeerManager,
-    PeerManagerSink, PeerManagerStream,
+    IPeerManagerMessage, ManagedMessage, MessageId, OPeerManagerMessage, PeerDisconnectReason,
+    PeerManager, PeerManagerEvent, PeerManagerSink, PeerManagerStream,
 };
-pub use manager::builder::PeerManagerBuilder;
+pub use manager::batch::MessageBatch;
+pub use manager::builder::{PeerManagerBuilder, ValidationPolicy};
+pub use manager::capabilities::PeerCapabilities;
+pub use manager::capacity::PeerCapacity;
 pub use manager::peer_info::PeerInfo;
+pub use manager::rate_limit::RateLimiter;
+pub use manager::stats::{MessageKind, PeerRates, PeerStats};
 
 /// `PeerManager` error types.
 pub mod error {
@@ -0,0 +1,161 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::time::Duration;
+
+use crate::peer::PeerInfo;
+use crate::select::ban::BanList;
+use crate::select::verify::VerifyEvent;
+
+pub(crate) const DEFAULT_BAN_THRESHOLD_BLOCKS: usize = 32;
+pub(crate) const DEFAULT_BAN_DURATION_SECS: u64 = 60 * 60;
+
+/// Scores peers by the blocks they sent us for pieces that failed their hash check.
+///
+/// Every peer that sent part of a failed piece is scored, but since any one of them could
+/// have sent the corrupt block, an address is only banned once it crosses the threshold
+/// while being the sole contributor to a failed piece. Connections from the same address
+/// are treated as one contributor.
+pub struct PeerBanner {
+    ban_list: BanList,
+    threshold: usize,
+    duration: Duration,
+    scores: HashMap<IpAddr, usize>,
+}
+
+impl PeerBanner {
+    /// Create a new `PeerBanner` that bans addresses in the given `BanList`.
+    pub fn new(ban_list: BanList) -> PeerBanner {
+        PeerBanner {
+            ban_list: ban_list,
+            threshold: DEFAULT_BAN_THRESHOLD_BLOCKS,
+            duration: Duration::from_secs(DEFAULT_BAN_DURATION_SECS),
+            scores: HashMap::new(),
+        }
+    }
+
+    /// Number of blocks from failed pieces an address can send before it is banned.
+    pub fn with_threshold(mut self, blocks: usize) -> PeerBanner {
+        self.threshold = blocks;
+
+        self
+    }
+
+    /// Duration of the bans handed out.
+    pub fn with_ban_duration(mut self, duration: Duration) -> PeerBanner {
+        self.duration = duration;
+
+        self
+    }
+
+    /// `BanList` that addresses are banned in.
+    pub fn ban_list(&self) -> &BanList {
+        &self.ban_list
+    }
+
+    /// Number of blocks from failed pieces the address has sent since it was last banned.
+    pub fn score(&self, ip: &IpAddr) -> usize {
+        self.scores.get(ip).cloned().unwrap_or(0)
+    }
+
+    /// Score the contributors to a failed piece, returning the address that was banned, if any.
+    pub fn on_piece_failed(&mut self, contributors: &[(PeerInfo, usize)]) -> Option<IpAddr> {
+        for &(info, blocks) in contributors {
+            *self.scores.entry(info.addr().ip()).or_insert(0) += blocks;
+        }
+
+        let mut addrs = contributors.iter().map(|&(info, _)| info.addr().ip());
+        let sole = match addrs.next() {
+            Some(ip) if addrs.all(|other| other == ip) => ip,
+            _ => return None,
+        };
+
+        if self.score(&sole) >= self.threshold {
+            self.scores.remove(&sole);
+            self.ban_list.ban(sole, self.duration);
+
+            Some(sole)
+        } else {
+            None
+        }
+    }
+
+    /// Score the contributors to the piece if it failed, returning the address that was banned.
+    pub fn on_verify_event(&mut self, event: &VerifyEvent) -> Option<IpAddr> {
+        match *event {
+            VerifyEvent::PieceFailed(_, ref contributors) => self.on_piece_failed(contributors),
+            VerifyEvent::PieceVerified(_) => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::IpAddr;
+
+    use super::PeerBanner;
+    use crate::handshake::Extensions;
+    use crate::peer::PeerInfo;
+    use crate::select::ban::BanList;
+    use crate::select::verify::VerifyEvent;
+
+    fn peer(last: u8, port: u16) -> PeerInfo {
+        PeerInfo::new(
+            ([10, 0, 0, last], port).into(),
+            [last; 20].into(),
+            [0u8; 20].into(),
+            Extensions::new(),
+        )
+    }
+
+    fn ip(last: u8) -> IpAddr {
+        [10, 0, 0, last].into()
+    }
+
+    #[test]
+    fn positive_sole_contributor_banned_at_threshold() {
+        let list = BanList::new();
+        let mut banner = PeerBanner::new(list.clone()).with_threshold(8);
+
+        assert_eq!(None, banner.on_piece_failed(&[(peer(1, 1), 4)]));
+        assert_eq!(4, banner.score(&ip(1)));
+        assert!(!list.is_banned(&ip(1)));
+
+        assert_eq!(Some(ip(1)), banner.on_piece_failed(&[(peer(1, 1), 4)]));
+        assert!(list.is_banned(&ip(1)));
+        assert_eq!(0, banner.score(&ip(1)));
+    }
+
+    #[test]
+    fn positive_shared_piece_only_scores() {
+        let list = BanList::new();
+        let mut banner = PeerBanner::new(list.clone()).with_threshold(1);
+
+        let contributors = [(peer(1, 1), 15), (peer(2, 1), 1)];
+        assert_eq!(None, banner.on_piece_failed(&contributors));
+        assert_eq!(15, banner.score(&ip(1)));
+        assert_eq!(1, banner.score(&ip(2)));
+        assert!(list.banned().is_empty());
+
+        // Score from shared pieces counts once the address fails a piece alone
+        let event = VerifyEvent::PieceFailed(0, vec![(peer(1, 1), 1)]);
+        assert_eq!(Some(ip(1)), banner.on_verify_event(&event));
+    }
+
+    #[test]
+    fn positive_connections_from_one_addr_are_sole_contributor() {
+        let list = BanList::new();
+        let mut banner = PeerBanner::new(list.clone()).with_threshold(8);
+
+        let contributors = [(peer(1, 1), 4), (peer(1, 2), 4)];
+        assert_eq!(Some(ip(1)), banner.on_piece_failed(&contributors));
+        assert!(list.is_banned(&ip(1)));
+    }
+
+    #[test]
+    fn negative_verified_piece_not_scored() {
+        let mut banner = PeerBanner::new(BanList::new()).with_threshold(1);
+
+        assert_eq!(None, banner.on_verify_event(&VerifyEvent::PieceVerified(0)));
+        assert_eq!(None, banner.on_piece_failed(&[]));
+    }
+}
@@ -0,0 +1,143 @@
+use std::any::Any;
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use crate::handshake::{FilterDecision, HandshakeFilter};
+
+/// Addresses that we refuse to handshake with until their ban runs out.
+///
+/// Clones share the same list, so a clone can be added as a filter to a handshaker while
+/// another is used to ban, inspect, and unban addresses.
+#[derive(Clone, Default)]
+pub struct BanList {
+    banned: Arc<Mutex<HashMap<IpAddr, Instant>>>,
+}
+
+impl BanList {
+    /// Create a new, empty `BanList`.
+    pub fn new() -> BanList {
+        BanList::default()
+    }
+
+    /// Ban the address for the given duration, replacing any ban it already had.
+    pub fn ban(&self, ip: IpAddr, duration: Duration) {
+        self.lock_banned().insert(ip, Instant::now() + duration);
+    }
+
+    /// Lift the ban on the address, false if it was not banned.
+    pub fn unban(&self, ip: &IpAddr) -> bool {
+        let now = Instant::now();
+
+        self.lock_banned()
+            .remove(ip)
+            .map_or(false, |until| until > now)
+    }
+
+    /// Whether or not the address is currently banned.
+    pub fn is_banned(&self, ip: &IpAddr) -> bool {
+        let now = Instant::now();
+        let mut banned = self.lock_banned();
+
+        match banned.get(ip) {
+            Some(&until) if until > now => true,
+            Some(_) => {
+                banned.remove(ip);
+                false
+            }
+            None => false,
+        }
+    }
+
+    /// Addresses currently banned, with the time left on each of their bans.
+    pub fn banned(&self) -> Vec<(IpAddr, Duration)> {
+        let now = Instant::now();
+        let mut banned = self.lock_banned();
+
+        banned.retain(|_, &mut until| until > now);
+        banned
+            .iter()
+            .map(|(&ip, &until)| (ip, until - now))
+            .collect()
+    }
+
+    fn lock_banned(&self) -> MutexGuard<'_, HashMap<IpAddr, Instant>> {
+        self.banned
+            .lock()
+            .expect("bittorrent-protocol_select: Poisoned Lock In BanList")
+    }
+}
+
+impl PartialEq for BanList {
+    fn eq(&self, other: &BanList) -> bool {
+        Arc::ptr_eq(&self.banned, &other.banned)
+    }
+}
+
+impl Eq for BanList {}
+
+impl HandshakeFilter for BanList {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn on_addr(&self, opt_addr: Option<&SocketAddr>) -> FilterDecision {
+        match opt_addr {
+            Some(addr) if self.is_banned(&addr.ip()) => FilterDecision::Block,
+            Some(_) => FilterDecision::Pass,
+            None => FilterDecision::NeedData,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{IpAddr, SocketAddr};
+    use std::time::Duration;
+
+    use super::BanList;
+    use crate::handshake::{FilterDecision, HandshakeFilter};
+
+    const BAN_DURATION: Duration = Duration::from_secs(60);
+
+    fn ip(last: u8) -> IpAddr {
+        [10, 0, 0, last].into()
+    }
+
+    #[test]
+    fn positive_banned_addr_is_blocked() {
+        let list = BanList::new();
+        list.clone().ban(ip(1), BAN_DURATION);
+
+        let banned: SocketAddr = (ip(1), 6881).into();
+        let other: SocketAddr = (ip(2), 6881).into();
+        assert_eq!(FilterDecision::Block, list.on_addr(Some(&banned)));
+        assert_eq!(FilterDecision::Pass, list.on_addr(Some(&other)));
+        assert_eq!(FilterDecision::NeedData, list.on_addr(None));
+    }
+
+    #[test]
+    fn positive_unban_lifts_ban() {
+        let list = BanList::new();
+        list.ban(ip(1), BAN_DURATION);
+
+        assert!(list.unban(&ip(1)));
+        assert!(!list.unban(&ip(1)));
+        assert!(!list.is_banned(&ip(1)));
+        assert!(list.banned().is_empty());
+    }
+
+    #[test]
+    fn positive_ban_runs_out() {
+        let list = BanList::new();
+        list.ban(ip(1), Duration::from_secs(0));
+        list.ban(ip(2), BAN_DURATION);
+
+        assert!(!list.is_banned(&ip(1)));
+        let banned = list.banned();
+        assert_eq!(1, banned.len());
+        assert_eq!(ip(2), banned[0].0);
+        assert!(banned[0].1 <= BAN_DURATION);
+    }
+}
@@ -0,0 +1,8 @@
+//! Module for banning peers that keep sending us blocks which fail their hash check.
+
+mod banner;
+pub use self::banner::PeerBanner;
+pub(crate) use self::banner::{DEFAULT_BAN_DURATION_SECS, DEFAULT_BAN_THRESHOLD_BLOCKS};
+
+mod list;
+pub use self::list::BanList;
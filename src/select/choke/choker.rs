@@ -0,0 +1,543 @@
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::time::Duration;
+
+use crate::peer::messages::PeerWireProtocolMessage;
+use crate::peer::{PeerInfo, PeerRates};
+
+const DEFAULT_UPLOAD_SLOTS: usize = 4;
+const DEFAULT_RECHOKE_INTERVAL_MILLIS: u64 = 10 * 1000;
+const DEFAULT_OPTIMISTIC_INTERVAL_MILLIS: u64 = 30 * 1000;
+
+/// Tit-for-tat choking with a rotating optimistic unchoke.
+///
+/// Every rechoke interval, the interested peers with the best rates are given our
+/// upload slots; while downloading we reciprocate the peers we download the fastest
+/// from, once seeding we favor the peers we upload the fastest to. One extra interested
+/// peer is optimistically unchoked, rotating every optimistic interval, so that new
+/// peers get a chance to prove themselves.
+///
+/// Peers that are snubbing us, see `set_snubbed`, are only ever unchoked optimistically.
+///
+/// Messages to send out are retrieved via `poll`. Peers start out choked, as they do
+/// on the wire, so no messages are generated for peers we have not unchoked.
+pub struct ChokeManager {
+    upload_slots: usize,
+    rechoke_interval: Duration,
+    optimistic_interval: Duration,
+    choke_snubbed: bool,
+    seeding: bool,
+    peers: HashMap<PeerInfo, PeerChokeState>,
+    // Order that peers are considered for the optimistic unchoke
+    order: VecDeque<PeerInfo>,
+    optimistic: Option<PeerInfo>,
+    since_rechoke: Duration,
+    since_optimistic: Duration,
+    out_queue: VecDeque<(PeerInfo, PeerWireProtocolMessage)>,
+}
+
+struct PeerChokeState {
+    interested: bool,
+    snubbed: bool,
+    unchoked: bool,
+    rates: PeerRates,
+}
+
+impl ChokeManager {
+    /// Create a new `ChokeManager`.
+    pub fn new() -> ChokeManager {
+        ChokeManager {
+            upload_slots: DEFAULT_UPLOAD_SLOTS,
+            rechoke_interval: Duration::from_millis(DEFAULT_RECHOKE_INTERVAL_MILLIS),
+            optimistic_interval: Duration::from_millis(DEFAULT_OPTIMISTIC_INTERVAL_MILLIS),
+            choke_snubbed: false,
+            seeding: false,
+            peers: HashMap::new(),
+            order: VecDeque::new(),
+            optimistic: None,
+            since_rechoke: Duration::from_millis(0),
+            since_optimistic: Duration::from_millis(0),
+            out_queue: VecDeque::new(),
+        }
+    }
+
+    /// Sets the number of peers unchoked for their rates, not counting the optimistic unchoke.
+    pub fn with_upload_slots(mut self, slots: usize) -> ChokeManager {
+        self.upload_slots = slots;
+        self
+    }
+
+    /// Sets the interval at which we recompute which peers are unchoked.
+    pub fn with_rechoke_interval(mut self, interval: Duration) -> ChokeManager {
+        self.rechoke_interval = interval;
+        self
+    }
+
+    /// Sets the interval at which the optimistic unchoke is rotated.
+    pub fn with_optimistic_interval(mut self, interval: Duration) -> ChokeManager {
+        self.optimistic_interval = interval;
+        self
+    }
+
+    /// Sets whether or not a peer is choked as soon as it snubs us, instead of at the next rechoke.
+    pub fn with_choke_snubbed(mut self, choke: bool) -> ChokeManager {
+        self.choke_snubbed = choke;
+        self
+    }
+
+    /// Retrieve the number of upload slots.
+    pub fn upload_slots(&self) -> usize {
+        self.upload_slots
+    }
+
+    /// Whether or not we are seeding.
+    pub fn is_seeding(&self) -> bool {
+        self.seeding
+    }
+
+    /// Whether or not we have unchoked the given peer.
+    pub fn is_unchoked(&self, info: &PeerInfo) -> bool {
+        self.peers.get(info).map_or(false, |peer| peer.unchoked)
+    }
+
+    /// Whether or not the given peer is snubbing us.
+    pub fn is_snubbed(&self, info: &PeerInfo) -> bool {
+        self.peers.get(info).map_or(false, |peer| peer.snubbed)
+    }
+
+    /// Retrieve the peer that is currently optimistically unchoked, if any.
+    pub fn optimistic_unchoke(&self) -> Option<&PeerInfo> {
+        self.optimistic.as_ref()
+    }
+
+    /// Add a peer, which starts out choked and not interested.
+    pub fn add_peer(&mut self, info: PeerInfo) {
+        if self.peers.contains_key(&info) {
+            return;
+        }
+
+        self.peers.insert(
+            info,
+            PeerChokeState {
+                interested: false,
+                snubbed: false,
+                unchoked: false,
+                rates: PeerRates::new(0.0, 0.0),
+            },
+        );
+        self.order.push_back(info);
+    }
+
+    /// Remove a peer, if it was unchoked its slot is given to another peer right away.
+    pub fn remove_peer(&mut self, info: &PeerInfo) {
+        let was_unchoked = match self.peers.remove(info) {
+            Some(peer) => peer.unchoked,
+            None => return,
+        };
+        self.order.retain(|peer| peer != info);
+        self.out_queue.retain(|&(peer, _)| peer != *info);
+
+        if was_unchoked {
+            self.rechoke(false);
+        }
+    }
+
+    /// Update whether or not the given peer is interested in downloading from us.
+    ///
+    /// If an upload slot is free, a newly interested peer is unchoked right away.
+    pub fn peer_interested(&mut self, info: &PeerInfo, interested: bool) {
+        let num_regular = self.num_regular_unchoked();
+
+        let unchoke = match self.peers.get_mut(info) {
+            Some(peer) => {
+                peer.interested = interested;
+
+                interested && !peer.snubbed && !peer.unchoked && num_regular < self.upload_slots
+            }
+            None => false,
+        };
+
+        if unchoke {
+            self.set_unchoked(*info, true);
+        }
+    }
+
+    /// Update the rates that we are uploading to and downloading from the given peer.
+    pub fn update_rates(&mut self, info: &PeerInfo, rates: PeerRates) {
+        if let Some(peer) = self.peers.get_mut(info) {
+            peer.rates = rates;
+        }
+    }
+
+    /// Update whether or not the given peer is snubbing us, see `RequestEvent::PeerSnubbed`.
+    ///
+    /// Takes effect at the next rechoke, unless snubbed peers are choked right away.
+    pub fn set_snubbed(&mut self, info: &PeerInfo, snubbed: bool) {
+        let regular_unchoked = match self.peers.get_mut(info) {
+            Some(peer) => {
+                peer.snubbed = snubbed;
+
+                peer.unchoked && Some(*info) != self.optimistic
+            }
+            None => return,
+        };
+
+        if snubbed && regular_unchoked && self.choke_snubbed {
+            self.rechoke(false);
+        }
+    }
+
+    /// Update whether or not we are seeding, which takes effect at the next rechoke.
+    pub fn set_seeding(&mut self, seeding: bool) {
+        self.seeding = seeding;
+    }
+
+    /// Apply the given duration, rechoking peers if an interval has passed.
+    pub fn tick(&mut self, duration: Duration) {
+        self.since_rechoke += duration;
+        self.since_optimistic += duration;
+
+        if self.since_optimistic >= self.optimistic_interval {
+            self.since_optimistic = Duration::from_millis(0);
+            self.since_rechoke = Duration::from_millis(0);
+
+            self.rechoke(true);
+        } else if self.since_rechoke >= self.rechoke_interval {
+            self.since_rechoke = Duration::from_millis(0);
+
+            self.rechoke(false);
+        }
+    }
+
+    /// Retrieve the next message to send to a peer.
+    pub fn poll(&mut self) -> Option<(PeerInfo, PeerWireProtocolMessage)> {
+        self.out_queue.pop_front()
+    }
+
+    fn num_regular_unchoked(&self) -> usize {
+        let optimistic = self.optimistic;
+
+        self.peers
+            .iter()
+            .filter(|&(info, peer)| peer.unchoked && Some(*info) != optimistic)
+            .count()
+    }
+
+    fn rate(&self, info: &PeerInfo) -> f64 {
+        let rates = self.peers[info].rates;
+
+        if self.seeding {
+            rates.upload()
+        } else {
+            rates.download()
+        }
+    }
+
+    fn rechoke(&mut self, rotate_optimistic: bool) {
+        let mut candidates: Vec<PeerInfo> = self
+            .order
+            .iter()
+            .filter(|info| self.peers[*info].interested && !self.peers[*info].snubbed)
+            .cloned()
+            .collect();
+        // Stable sort, so ties go to whoever is next in line for the optimistic unchoke
+        candidates.sort_by(|a, b| {
+            self.rate(b)
+                .partial_cmp(&self.rate(a))
+                .unwrap_or(Ordering::Equal)
+        });
+        candidates.truncate(self.upload_slots);
+
+        let regular: HashSet<PeerInfo> = candidates.into_iter().collect();
+
+        let keep_optimistic = self.optimistic.map_or(false, |info| {
+            self.peers.get(&info).map_or(false, |peer| peer.interested) && !regular.contains(&info)
+        });
+        if rotate_optimistic || !keep_optimistic {
+            self.optimistic = self.next_optimistic(&regular);
+        }
+
+        let optimistic = self.optimistic;
+        let order: Vec<PeerInfo> = self.order.iter().cloned().collect();
+        for info in order {
+            self.set_unchoked(info, regular.contains(&info) || Some(info) == optimistic);
+        }
+    }
+
+    fn next_optimistic(&mut self, regular: &HashSet<PeerInfo>) -> Option<PeerInfo> {
+        for _ in 0..self.order.len() {
+            let info = self.order.pop_front().unwrap();
+            self.order.push_back(info);
+
+            if self.peers[&info].interested && !regular.contains(&info) {
+                return Some(info);
+            }
+        }
+
+        None
+    }
+
+    fn set_unchoked(&mut self, info: PeerInfo, unchoked: bool) {
+        let peer = self.peers.get_mut(&info).unwrap();
+        if peer.unchoked == unchoked {
+            return;
+        }
+        peer.unchoked = unchoked;
+
+        let message = if unchoked {
+            PeerWireProtocolMessage::UnChoke
+        } else {
+            PeerWireProtocolMessage::Choke
+        };
+        self.out_queue.push_back((info, message));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ChokeManager;
+    use crate::handshake::Extensions;
+    use crate::peer::messages::PeerWireProtocolMessage;
+    use crate::peer::{PeerInfo, PeerRates};
+
+    use std::time::Duration;
+
+    fn peer(port: u16) -> PeerInfo {
+        PeerInfo::new(
+            format!("127.0.0.1:{}", port).parse().unwrap(),
+            [port as u8; 20].into(),
+            [0u8; 20].into(),
+            Extensions::new(),
+        )
+    }
+
+    fn drain(manager: &mut ChokeManager) -> Vec<(PeerInfo, PeerWireProtocolMessage)> {
+        let mut messages = Vec::new();
+        while let Some(message) = manager.poll() {
+            messages.push(message);
+        }
+
+        messages
+    }
+
+    /// Add interested peers, downloading from each at the given rate.
+    fn manager_with_peers(slots: usize, download_rates: &[f64]) -> (ChokeManager, Vec<PeerInfo>) {
+        let mut manager = ChokeManager::new().with_upload_slots(slots);
+        let mut peers = Vec::new();
+
+        for (index, &rate) in download_rates.iter().enumerate() {
+            let info = peer(6881 + index as u16);
+
+            manager.add_peer(info);
+            manager.update_rates(&info, PeerRates::new(0.0, rate));
+            peers.push(info);
+        }
+
+        (manager, peers)
+    }
+
+    #[test]
+    fn positive_unchoke_free_slot_on_interest() {
+        let (mut manager, peers) = manager_with_peers(1, &[0.0, 0.0]);
+
+        manager.peer_interested(&peers[0], true);
+        manager.peer_interested(&peers[1], true);
+
+        assert_eq!(vec![(peers[0], PeerWireProtocolMessage::UnChoke)], drain(&mut manager));
+    }
+
+    #[test]
+    fn positive_no_messages_for_uninterested_peers() {
+        let (mut manager, _) = manager_with_peers(2, &[10.0, 20.0]);
+
+        manager.tick(Duration::from_secs(30));
+
+        assert!(drain(&mut manager).is_empty());
+    }
+
+    #[test]
+    fn positive_rechoke_unchokes_fastest_downloaders() {
+        let (mut manager, peers) = manager_with_peers(2, &[10.0, 30.0, 20.0, 5.0]);
+        for info in peers.iter() {
+            manager.peer_interested(info, true);
+        }
+        drain(&mut manager);
+
+        manager.tick(Duration::from_secs(9));
+        assert!(drain(&mut manager).is_empty());
+        manager.tick(Duration::from_secs(1));
+
+        assert!(manager.is_unchoked(&peers[1]));
+        assert!(manager.is_unchoked(&peers[2]));
+        // First peer lost its regular slot, but the optimistic unchoke is given to a choked peer
+        assert_eq!(Some(&peers[0]), manager.optimistic_unchoke());
+        assert!(!manager.is_unchoked(&peers[3]));
+        assert_eq!(
+            vec![(peers[2], PeerWireProtocolMessage::UnChoke)],
+            drain(&mut manager)
+        );
+    }
+
+    #[test]
+    fn positive_optimistic_unchoke_rotates() {
+        let (mut manager, peers) = manager_with_peers(1, &[100.0, 1.0, 1.0, 1.0]);
+        for info in peers.iter() {
+            manager.peer_interested(info, true);
+        }
+
+        manager.tick(Duration::from_secs(10));
+        let first = *manager.optimistic_unchoke().unwrap();
+        drain(&mut manager);
+
+        // Rechokes in between keep the same optimistic unchoke
+        manager.tick(Duration::from_secs(10));
+        assert!(drain(&mut manager).is_empty());
+
+        manager.tick(Duration::from_secs(10));
+        let second = *manager.optimistic_unchoke().unwrap();
+
+        assert!(first != second && first != peers[0] && second != peers[0]);
+        assert_eq!(
+            vec![
+                (first, PeerWireProtocolMessage::Choke),
+                (second, PeerWireProtocolMessage::UnChoke)
+            ],
+            drain(&mut manager)
+                .into_iter()
+                .filter(|&(info, _)| info == first || info == second)
+                .collect::<Vec<_>>()
+        );
+        assert!(manager.is_unchoked(&peers[0]));
+    }
+
+    #[test]
+    fn positive_optimistic_unchoke_visits_every_peer() {
+        let (mut manager, peers) = manager_with_peers(1, &[100.0, 1.0, 1.0, 1.0]);
+        for info in peers.iter() {
+            manager.peer_interested(info, true);
+        }
+
+        let mut visited = Vec::new();
+        for _ in 0..3 {
+            manager.tick(Duration::from_secs(30));
+            visited.push(*manager.optimistic_unchoke().unwrap());
+        }
+        visited.sort_by_key(|info| info.addr().port());
+
+        assert_eq!(&peers[1..], &visited[..]);
+    }
+
+    #[test]
+    fn positive_seeding_orders_by_upload_rate() {
+        let (mut manager, peers) = manager_with_peers(1, &[100.0, 0.0]);
+        manager.update_rates(&peers[1], PeerRates::new(50.0, 0.0));
+        for info in peers.iter() {
+            manager.peer_interested(info, true);
+        }
+        manager.tick(Duration::from_secs(10));
+        assert_eq!(Some(&peers[1]), manager.optimistic_unchoke());
+
+        manager.set_seeding(true);
+        manager.tick(Duration::from_secs(10));
+
+        assert!(manager.is_seeding());
+        assert_eq!(Some(&peers[0]), manager.optimistic_unchoke());
+        assert!(manager.is_unchoked(&peers[0]) && manager.is_unchoked(&peers[1]));
+    }
+
+    #[test]
+    fn positive_uninterested_peer_choked_at_rechoke() {
+        let (mut manager, peers) = manager_with_peers(1, &[10.0]);
+        manager.peer_interested(&peers[0], true);
+        drain(&mut manager);
+
+        manager.peer_interested(&peers[0], false);
+        assert!(manager.is_unchoked(&peers[0]));
+
+        manager.tick(Duration::from_secs(10));
+        assert_eq!(vec![(peers[0], PeerWireProtocolMessage::Choke)], drain(&mut manager));
+    }
+
+    #[test]
+    fn positive_remove_peer_frees_slot() {
+        let (mut manager, peers) = manager_with_peers(1, &[10.0, 5.0, 1.0]);
+        for info in peers.iter() {
+            manager.peer_interested(info, true);
+        }
+        manager.tick(Duration::from_secs(10));
+        drain(&mut manager);
+
+        manager.remove_peer(&peers[0]);
+
+        assert!(manager.is_unchoked(&peers[1]) && manager.is_unchoked(&peers[2]));
+        assert_eq!(
+            vec![(peers[2], PeerWireProtocolMessage::UnChoke)],
+            drain(&mut manager)
+        );
+    }
+
+    #[test]
+    fn positive_snubbed_peer_loses_regular_slot() {
+        let (mut manager, peers) = manager_with_peers(1, &[100.0, 1.0, 1.0]);
+        for info in peers.iter() {
+            manager.peer_interested(info, true);
+        }
+        manager.tick(Duration::from_secs(10));
+        drain(&mut manager);
+
+        manager.set_snubbed(&peers[0], true);
+        assert!(manager.is_unchoked(&peers[0]));
+        manager.tick(Duration::from_secs(10));
+
+        assert!(manager.is_snubbed(&peers[0]));
+        assert!(!manager.is_unchoked(&peers[0]));
+        assert!(manager.is_unchoked(&peers[1]) && manager.is_unchoked(&peers[2]));
+    }
+
+    #[test]
+    fn positive_snubbed_peer_unchoked_optimistically() {
+        let (mut manager, peers) = manager_with_peers(1, &[100.0, 1.0]);
+        for info in peers.iter() {
+            manager.peer_interested(info, true);
+        }
+        manager.set_snubbed(&peers[0], true);
+
+        manager.tick(Duration::from_secs(10));
+
+        // Only the optimistic unchoke is left for it, the regular slot goes to the other peer
+        assert!(manager.is_unchoked(&peers[1]));
+        assert_eq!(Some(&peers[0]), manager.optimistic_unchoke());
+
+        manager.set_snubbed(&peers[0], false);
+        manager.tick(Duration::from_secs(10));
+        assert!(manager.is_unchoked(&peers[0]));
+        assert_eq!(Some(&peers[1]), manager.optimistic_unchoke());
+    }
+
+    #[test]
+    fn positive_choke_snubbed_right_away() {
+        let (manager, peers) = manager_with_peers(1, &[100.0, 10.0, 1.0]);
+        let mut manager = manager.with_choke_snubbed(true);
+        for info in peers.iter() {
+            manager.peer_interested(info, true);
+        }
+        manager.tick(Duration::from_secs(10));
+        drain(&mut manager);
+
+        manager.set_snubbed(&peers[0], true);
+
+        assert!(drain(&mut manager).contains(&(peers[0], PeerWireProtocolMessage::Choke)));
+        assert!(!manager.is_unchoked(&peers[0]));
+        assert!(manager.is_unchoked(&peers[1]) && manager.is_unchoked(&peers[2]));
+    }
+
+    #[test]
+    fn negative_snubbed_peer_not_unchoked_on_interest() {
+        let (mut manager, peers) = manager_with_peers(1, &[0.0]);
+
+        manager.set_snubbed(&peers[0], true);
+        manager.peer_interested(&peers[0], true);
+
+        assert!(drain(&mut manager).is_empty());
+    }
+}
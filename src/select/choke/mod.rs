@@ -0,0 +1,4 @@
+//! Module for deciding which peers we upload to.
+
+mod choker;
+pub use self::choker::ChokeManager;
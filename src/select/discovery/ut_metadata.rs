@@ -1,7 +1,6 @@
 use std::collections::HashMap;
 use std::collections::HashSet;
 // use std::collections::VecDeque;
-use bytes::BytesMut;
 use rand::{self, Rng};
 use std::collections::hash_map::Entry;
 use std::collections::vec_deque::VecDeque;
@@ -16,16 +15,19 @@ use crate::peer::messages::UtMetadataDataMessage;
 use crate::peer::messages::UtMetadataMessage;
 use crate::peer::messages::UtMetadataRejectMessage;
 use crate::peer::messages::UtMetadataRequestMessage;
+use crate::peer::messages::{num_metadata_pieces, UT_METADATA_PIECE_LEN};
 use crate::peer::messages::{ExtendedMessage, ExtendedType};
 use crate::peer::PeerInfo;
 
 use crate::select::discovery::error::{DiscoveryError, DiscoveryErrorKind};
 use crate::select::discovery::{IDiscoveryMessage, ODiscoveryMessage, Run};
 use crate::select::extended::{ExtendedListener, ExtendedPeerInfo};
+use crate::select::metadata::server::UT_METADATA_ID;
+use crate::select::metadata::UtMetadataServer;
 use crate::select::ControlMessage;
 
 const REQUEST_TIMEOUT_MILLIS: u64 = 5000;
-const MAX_REQUEST_SIZE: usize = 16 * 1024;
+const MAX_REQUEST_SIZE: usize = UT_METADATA_PIECE_LEN;
 
 const MAX_ACTIVE_REQUESTS: usize = 100;
 const MAX_PEER_REQUESTS: usize = 100;
@@ -41,7 +43,7 @@ const MAX_PEER_REQUESTS: usize = 100;
 /// `IDiscoveryMessage::Control(ControlMessage::AddTorrent)` is received.
 pub struct UtMetadataModule {
     //已完成下载的种子列表
-    completed_map: HashMap<InfoHash, Vec<u8>>,
+    completed_map: HashMap<InfoHash, UtMetadataServer>,
 
     //未完成下载的种子列表
     pending_map: HashMap<InfoHash, Option<PendingInfo>>,
@@ -102,7 +104,7 @@ impl UtMetadataModule {
             )),
             Entry::Vacant(vac) => {
                 let info_bytes = metainfo.info().to_bytes();
-                vac.insert(info_bytes);
+                vac.insert(UtMetadataServer::new(info_bytes.into()));
 
                 Ok(None)
             }
@@ -154,7 +156,7 @@ impl UtMetadataModule {
         );
         // If peer supports it, but they dont have the metadata size, then they probably dont have the file yet...
         match (our_support, they_support, opt_metadata_size) {
-            (true, true, Some(metadata_size)) => {
+            (true, true, Some(metadata_size)) if metadata_size > 0 => {
                 self.active_peers
                     .entry(*info.hash())
                     .or_insert_with(|| ActivePeers {
@@ -270,7 +272,30 @@ impl UtMetadataModule {
 
         // If so, go ahead and process it, if not, ignore it (could ban peer...)
         if let Some(index) = opt_index {
-            self.active_requests.swap_remove(index);
+            let request = self.active_requests.swap_remove(index);
+
+            let opt_metadata_size = self
+                .active_peers
+                .get(info.hash())
+                .map(|active_peers| active_peers.metadata_size);
+            let validation = opt_metadata_size
+                .map(|metadata_size| data.validate(metadata_size))
+                .unwrap_or(Ok(()));
+
+            if let Err(err) = validation {
+                // Stop asking the peer for data, and give the piece to someone else
+                self.remove_peer(info)?;
+                self.pending_map.get_mut(info.hash()).map(|opt_pending| {
+                    opt_pending
+                        .as_mut()
+                        .map(|pending| pending.messages.push(request.message))
+                });
+
+                return Err(DiscoveryError::from_kind(DiscoveryErrorKind::InvalidMessage {
+                    info: info,
+                    message: err.to_string(),
+                }));
+            }
 
             if let Some(&mut Some(ref mut pending)) = self.pending_map.get_mut(&info.hash()) {
                 let data_offset = (data.piece() as usize) * MAX_REQUEST_SIZE;
@@ -366,30 +391,12 @@ impl UtMetadataModule {
     fn retrieve_piece_response(&mut self) -> Option<Result<ODiscoveryMessage, DiscoveryError>> {
         while let Some(request) = self.peer_requests.pop_front() {
             let hash = request.send_to.hash();
-            let piece = request.request.piece();
-
-            let start = piece as usize * MAX_REQUEST_SIZE;
-            let end = start + MAX_REQUEST_SIZE;
-
-            if let Some(data) = self.completed_map.get(hash) {
-                if start <= data.len() && end <= data.len() {
-                    let info_slice: &[u8] = &data[start..end];
-                    let mut info_payload = BytesMut::with_capacity(info_slice.len());
-
-                    info_payload.extend_from_slice(info_slice);
-                    let message = UtMetadataDataMessage::new(
-                        piece,
-                        info_slice.len() as i64,
-                        info_payload.freeze(),
-                    );
-
-                    return Some(Ok(ODiscoveryMessage::SendUtMetadataMessage(
-                        request.send_to,
-                        UtMetadataMessage::Data(message),
-                    )));
-                } else {
-                    // Peer asked for a piece outside of the range...dont respond to that
-                }
+
+            if let Some(server) = self.completed_map.get(hash) {
+                return Some(Ok(ODiscoveryMessage::SendUtMetadataMessage(
+                    request.send_to,
+                    server.respond(&request.request),
+                )));
             }
         }
 
@@ -502,11 +509,7 @@ fn pending_info_from_metadata_size(metadata_size: i64) -> PendingInfo {
     let bytes = vec![0u8; cast_metadata_size];
     let mut messages = Vec::new();
 
-    let num_pieces = if cast_metadata_size % MAX_REQUEST_SIZE != 0 {
-        cast_metadata_size / MAX_REQUEST_SIZE + 1
-    } else {
-        cast_metadata_size / MAX_REQUEST_SIZE
-    };
+    let num_pieces = num_metadata_pieces(metadata_size);
 
     for index in 0..num_pieces {
         messages.push(UtMetadataRequestMessage::new((index) as i64));
@@ -522,8 +525,12 @@ fn pending_info_from_metadata_size(metadata_size: i64) -> PendingInfo {
 //-------------------------------------------------------------------------------//
 
 impl ExtendedListener for UtMetadataModule {
-    fn extend(&self, _info: &PeerInfo, builder: ExtendedMessageBuilder) -> ExtendedMessageBuilder {
-        builder.with_extended_type(ExtendedType::UtMetadata, Some(5))
+    fn extend(&self, info: &PeerInfo, builder: ExtendedMessageBuilder) -> ExtendedMessageBuilder {
+        // Advertise our metadata size if we have the torrent
+        match self.completed_map.get(info.hash()) {
+            Some(server) => server.extend(builder),
+            None => builder.with_extended_type(ExtendedType::UtMetadata, Some(UT_METADATA_ID)),
+        }
     }
 
     fn on_update(&mut self, info: &PeerInfo, extended: &ExtendedPeerInfo) {
@@ -13,6 +13,10 @@ use crate::select::uber::DiscoveryTrait;
 pub enum IExtendedMessage {
     Control(ControlMessage),
     RecievedExtendedMessage(PeerInfo, ExtendedMessage),
+    /// Update our upload only status (for example, once we become a seed).
+    ///
+    /// An updated extended message is sent to all connected peers.
+    UpdateUploadOnly(bool),
 }
 
 /// Enumeration of extended messages that can be received from the extended module.
@@ -50,6 +54,27 @@ impl ExtendedPeerInfo {
     pub fn their_message(&self) -> Option<&ExtendedMessage> {
         self.theirs.as_ref()
     }
+
+    /// Whether or not the remote peer has told us it is only uploading.
+    pub fn their_upload_only(&self) -> bool {
+        self.theirs
+            .as_ref()
+            .and_then(|message| message.upload_only())
+            .unwrap_or(false)
+    }
+
+    /// Whether or not both sides of the connection are only uploading.
+    ///
+    /// Neither side has anything to gain from the other, so these peers can be deprioritized.
+    pub fn is_mutual_seed(&self) -> bool {
+        let ours = self
+            .ours
+            .as_ref()
+            .and_then(|message| message.upload_only())
+            .unwrap_or(false);
+
+        ours && self.their_upload_only()
+    }
 }
 
 //------------------------------------------------------------------------------//
@@ -87,15 +112,7 @@ impl ExtendedModule {
     {
         match message {
             IExtendedMessage::Control(ControlMessage::PeerConnected(info)) => {
-                let mut builder = self.builder.clone();
-
-                //调用回调接口 生成拓展builder对象
-                for d_module in d_modules.iter() {
-                    let temp_builder = builder;
-                    builder = d_module.extend(&info, temp_builder);
-                }
-
-                let ext_message = builder.build();
+                let ext_message = self.build_message(&info, d_modules);
                 let ext_peer_info = ExtendedPeerInfo::new(Some(ext_message.clone()), None);
 
                 //调用回调接口 更新实现类中的拓展信息
@@ -107,6 +124,25 @@ impl ExtendedModule {
                 self.out_queue
                     .push_back(OExtendedMessage::SendExtendedMessage(info, ext_message));
             }
+            IExtendedMessage::UpdateUploadOnly(upload_only) => {
+                self.builder = self.builder.clone().with_upload_only(upload_only);
+
+                let infos: Vec<PeerInfo> = self.peers.keys().cloned().collect();
+                for info in infos {
+                    let ext_message = self.build_message(&info, d_modules);
+
+                    if let Some(ext_peer_info) = self.peers.get_mut(&info) {
+                        ext_peer_info.update_ours(ext_message.clone());
+
+                        for d_module in d_modules.iter_mut() {
+                            d_module.on_update(&info, ext_peer_info);
+                        }
+                    }
+
+                    self.out_queue
+                        .push_back(OExtendedMessage::SendExtendedMessage(info, ext_message));
+                }
+            }
             IExtendedMessage::Control(ControlMessage::PeerDisconnected(info)) => {
                 self.peers.remove(&info);
             }
@@ -129,6 +165,19 @@ impl ExtendedModule {
 }
 
 impl ExtendedModule {
+    /// Build our extended message for the given peer, letting each module extend it.
+    fn build_message(&self, info: &PeerInfo, d_modules: &[Box<dyn DiscoveryTrait>]) -> ExtendedMessage {
+        let mut builder = self.builder.clone();
+
+        //调用回调接口 生成拓展builder对象
+        for d_module in d_modules.iter() {
+            let temp_builder = builder;
+            builder = d_module.extend(info, temp_builder);
+        }
+
+        builder.build()
+    }
+
     pub(crate) fn poll(&mut self) -> Result<Option<OExtendedMessage>, UberError> {
         let opt_message = self.out_queue.pop_front();
         Ok(opt_message)
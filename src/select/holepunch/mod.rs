@@ -0,0 +1,4 @@
+//! Module for connecting to peers that can not accept connections, via `ut_holepunch` (BEP 55).
+
+mod puncher;
+pub use self::puncher::HolepunchManager;
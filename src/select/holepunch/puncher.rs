@@ -0,0 +1,356 @@
+use std::collections::{HashMap, VecDeque};
+use std::net::SocketAddr;
+
+use crate::handshake::{InitiateMessage, Protocol};
+use crate::peer::messages::builders::ExtendedMessageBuilder;
+use crate::peer::messages::{
+    ExtendedMessage, ExtendedType, PeerExtensionProtocolMessage, UtHolepunchErrorCode,
+    UtHolepunchMessage,
+};
+use crate::peer::PeerInfo;
+use crate::util::net;
+
+/// Id we map `ExtendedType::UtHolepunch` to in our `ExtendedMessage`.
+pub(crate) const UT_HOLEPUNCH_ID: u8 = 4;
+
+/// Holepunching (BEP 55) between peers that can not accept each other's connections.
+///
+/// As a relay, rendezvous requests from one of our peers are answered by telling both it and
+/// the peer it wants to reach to connect to each other. As an initiator, we ask a peer we share
+/// with the target to relay our rendezvous, and connect to the target once told to.
+///
+/// Messages to send out are retrieved via `poll`, connections to open via `poll_connect`.
+pub struct HolepunchManager {
+    opt_local_addr: Option<SocketAddr>,
+    peers: HashMap<SocketAddr, HolepunchPeer>,
+    // Mediator relaying our rendezvous with each target
+    pending: HashMap<SocketAddr, PeerInfo>,
+    out_queue: VecDeque<(PeerInfo, PeerExtensionProtocolMessage)>,
+    connect_queue: VecDeque<InitiateMessage>,
+}
+
+struct HolepunchPeer {
+    info: PeerInfo,
+    supported: bool,
+}
+
+impl HolepunchManager {
+    /// Create a new `HolepunchManager`.
+    pub fn new() -> HolepunchManager {
+        HolepunchManager {
+            opt_local_addr: None,
+            peers: HashMap::new(),
+            pending: HashMap::new(),
+            out_queue: VecDeque::new(),
+            connect_queue: VecDeque::new(),
+        }
+    }
+
+    /// Address peers know us by, rendezvous with it are rejected instead of relayed.
+    pub fn with_local_addr(mut self, addr: SocketAddr) -> HolepunchManager {
+        self.opt_local_addr = Some(net::unmap_v4(addr));
+        self
+    }
+
+    /// Advertise `ut_holepunch` support.
+    pub fn extend(&self, builder: ExtendedMessageBuilder) -> ExtendedMessageBuilder {
+        builder.with_extended_type(ExtendedType::UtHolepunch, Some(UT_HOLEPUNCH_ID))
+    }
+
+    /// Whether or not the given peer told us it supports `ut_holepunch`.
+    pub fn supports_holepunch(&self, info: &PeerInfo) -> bool {
+        self.peers
+            .get(&net::unmap_v4(*info.addr()))
+            .map_or(false, |peer| peer.supported)
+    }
+
+    /// Whether or not we are waiting on a rendezvous with the given address.
+    pub fn is_pending(&self, target: &SocketAddr) -> bool {
+        self.pending.contains_key(&net::unmap_v4(*target))
+    }
+
+    /// Add a peer we are connected to.
+    ///
+    /// Any rendezvous with the peer is complete once it is connected.
+    pub fn add_peer(&mut self, info: PeerInfo) {
+        let addr = net::unmap_v4(*info.addr());
+
+        self.pending.remove(&addr);
+        self.peers.entry(addr).or_insert(HolepunchPeer {
+            info: info,
+            supported: false,
+        });
+    }
+
+    /// Remove a peer, dropping any rendezvous it was relaying for us.
+    pub fn remove_peer(&mut self, info: &PeerInfo) {
+        self.peers.remove(&net::unmap_v4(*info.addr()));
+
+        self.pending.retain(|_, mediator| mediator != info);
+        self.out_queue.retain(|&(peer, _)| peer != *info);
+    }
+
+    /// Peer has sent us its `ExtendedMessage`.
+    pub fn on_extended(&mut self, info: &PeerInfo, extended: &ExtendedMessage) {
+        // An id of 0 is how the peer tells us it disabled the extension
+        let supported = extended
+            .query_id(&ExtendedType::UtHolepunch)
+            .map_or(false, |id| id != 0);
+
+        if let Some(peer) = self.peers.get_mut(&net::unmap_v4(*info.addr())) {
+            peer.supported = supported;
+        }
+    }
+
+    /// Ask the mediator to relay a rendezvous with the peer at the target address.
+    ///
+    /// Returns false if the mediator is not a peer of ours supporting `ut_holepunch`, or we are
+    /// already connected to the target.
+    pub fn rendezvous(&mut self, mediator: &PeerInfo, target: SocketAddr) -> bool {
+        let target = net::unmap_v4(target);
+        if !self.supports_holepunch(mediator) || self.peers.contains_key(&target) {
+            return false;
+        }
+
+        self.pending.insert(target, *mediator);
+        self.out_queue.push_back((
+            *mediator,
+            PeerExtensionProtocolMessage::UtHolepunch(UtHolepunchMessage::Rendezvous(target)),
+        ));
+
+        true
+    }
+
+    /// Peer has sent us a `ut_holepunch` message.
+    pub fn on_message(&mut self, info: &PeerInfo, message: &UtHolepunchMessage) {
+        if !self.peers.contains_key(&net::unmap_v4(*info.addr())) {
+            return;
+        }
+
+        match message {
+            &UtHolepunchMessage::Rendezvous(target) => self.relay(info, net::unmap_v4(target)),
+            &UtHolepunchMessage::Connect(target) => self.connect(info, net::unmap_v4(target)),
+            &UtHolepunchMessage::Error(target, code) => {
+                let target = net::unmap_v4(target);
+                warn!(
+                    "bittorrent-protocol_select: Rendezvous With {:?} Via {:?} Failed: {:?}",
+                    target,
+                    info.addr(),
+                    code
+                );
+
+                if self.pending.get(&target) == Some(info) {
+                    self.pending.remove(&target);
+                }
+            }
+        }
+    }
+
+    /// Retrieve the next message to send to a peer.
+    pub fn poll(&mut self) -> Option<(PeerInfo, PeerExtensionProtocolMessage)> {
+        self.out_queue.pop_front()
+    }
+
+    /// Retrieve the next connection to open, which should be sent to the handshaker right away.
+    pub fn poll_connect(&mut self) -> Option<InitiateMessage> {
+        self.connect_queue.pop_front()
+    }
+
+    /// Tell the sender and the target to connect to each other, or the sender why they can't.
+    fn relay(&mut self, sender: &PeerInfo, target: SocketAddr) {
+        let result = if Some(target) == self.opt_local_addr {
+            Err(UtHolepunchErrorCode::NoSelf)
+        } else if target.ip().is_unspecified() || target.port() == 0 {
+            Err(UtHolepunchErrorCode::NoSuchPeer)
+        } else {
+            match self.peers.get(&target) {
+                Some(peer) if peer.supported => Ok(peer.info),
+                Some(_) => Err(UtHolepunchErrorCode::NoSupport),
+                None => Err(UtHolepunchErrorCode::NotConnected),
+            }
+        };
+
+        match result {
+            Ok(target_info) => {
+                self.out_queue.push_back((
+                    *sender,
+                    PeerExtensionProtocolMessage::UtHolepunch(UtHolepunchMessage::Connect(target)),
+                ));
+                self.out_queue.push_back((
+                    target_info,
+                    PeerExtensionProtocolMessage::UtHolepunch(UtHolepunchMessage::Connect(
+                        net::unmap_v4(*sender.addr()),
+                    )),
+                ));
+            }
+            Err(code) => self.out_queue.push_back((
+                *sender,
+                PeerExtensionProtocolMessage::UtHolepunch(UtHolepunchMessage::Error(target, code)),
+            )),
+        }
+    }
+
+    /// Connect to the target, which is connecting to us at the same time.
+    fn connect(&mut self, relay: &PeerInfo, target: SocketAddr) {
+        if Some(target) == self.opt_local_addr || self.peers.contains_key(&target) {
+            return;
+        }
+        self.pending.remove(&target);
+
+        self.connect_queue.push_back(
+            InitiateMessage::new(Protocol::BitTorrent, *relay.hash(), target).with_holepunch(),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::HolepunchManager;
+    use crate::handshake::Extensions;
+    use crate::peer::messages::builders::ExtendedMessageBuilder;
+    use crate::peer::messages::{
+        ExtendedType, PeerExtensionProtocolMessage, UtHolepunchErrorCode, UtHolepunchMessage,
+    };
+    use crate::peer::PeerInfo;
+
+    use std::net::SocketAddr;
+
+    fn addr(port: u16) -> SocketAddr {
+        format!("10.0.0.{}:6881", port).parse().unwrap()
+    }
+
+    fn peer(port: u16) -> PeerInfo {
+        PeerInfo::new(
+            addr(port),
+            [port as u8; 20].into(),
+            [0u8; 20].into(),
+            Extensions::new(),
+        )
+    }
+
+    fn supporting_peer(manager: &mut HolepunchManager, port: u16) -> PeerInfo {
+        let info = peer(port);
+        let extended = manager.extend(ExtendedMessageBuilder::new()).build();
+
+        manager.add_peer(info);
+        manager.on_extended(&info, &extended);
+
+        info
+    }
+
+    fn holepunch(message: UtHolepunchMessage) -> PeerExtensionProtocolMessage {
+        PeerExtensionProtocolMessage::UtHolepunch(message)
+    }
+
+    #[test]
+    fn positive_extend_advertises_holepunch() {
+        let message = HolepunchManager::new()
+            .extend(ExtendedMessageBuilder::new())
+            .build();
+
+        assert!(message.query_id(&ExtendedType::UtHolepunch).is_some());
+    }
+
+    #[test]
+    fn positive_relay_rendezvous_to_both_peers() {
+        let mut manager = HolepunchManager::new();
+        let (one, two) = (
+            supporting_peer(&mut manager, 1),
+            supporting_peer(&mut manager, 2),
+        );
+
+        manager.on_message(&one, &UtHolepunchMessage::Rendezvous(addr(2)));
+
+        assert_eq!(
+            Some((one, holepunch(UtHolepunchMessage::Connect(addr(2))))),
+            manager.poll()
+        );
+        assert_eq!(
+            Some((two, holepunch(UtHolepunchMessage::Connect(addr(1))))),
+            manager.poll()
+        );
+        assert_eq!(None, manager.poll());
+    }
+
+    #[test]
+    fn positive_relay_errors() {
+        let mut manager = HolepunchManager::new().with_local_addr(addr(9));
+        let one = supporting_peer(&mut manager, 1);
+        manager.add_peer(peer(2));
+
+        let expected = vec![
+            (addr(9), UtHolepunchErrorCode::NoSelf),
+            (
+                "10.0.0.3:0".parse().unwrap(),
+                UtHolepunchErrorCode::NoSuchPeer,
+            ),
+            (addr(3), UtHolepunchErrorCode::NotConnected),
+            (addr(2), UtHolepunchErrorCode::NoSupport),
+        ];
+        for &(target, code) in expected.iter() {
+            manager.on_message(&one, &UtHolepunchMessage::Rendezvous(target));
+
+            assert_eq!(
+                Some((one, holepunch(UtHolepunchMessage::Error(target, code)))),
+                manager.poll()
+            );
+        }
+        assert_eq!(None, manager.poll());
+    }
+
+    #[test]
+    fn positive_rendezvous_then_connect() {
+        let mut manager = HolepunchManager::new();
+        let mediator = supporting_peer(&mut manager, 1);
+
+        assert!(manager.rendezvous(&mediator, addr(2)));
+        assert!(manager.is_pending(&addr(2)));
+        assert_eq!(
+            Some((mediator, holepunch(UtHolepunchMessage::Rendezvous(addr(2))))),
+            manager.poll()
+        );
+
+        manager.on_message(&mediator, &UtHolepunchMessage::Connect(addr(2)));
+
+        let initiate = manager.poll_connect().unwrap();
+        assert_eq!(addr(2), *initiate.address());
+        assert_eq!(mediator.hash(), initiate.hash());
+        assert!(initiate.is_holepunch());
+        assert!(!manager.is_pending(&addr(2)));
+    }
+
+    #[test]
+    fn positive_error_clears_pending() {
+        let mut manager = HolepunchManager::new();
+        let mediator = supporting_peer(&mut manager, 1);
+
+        manager.rendezvous(&mediator, addr(2));
+        manager.on_message(
+            &mediator,
+            &UtHolepunchMessage::Error(addr(2), UtHolepunchErrorCode::NotConnected),
+        );
+
+        assert!(!manager.is_pending(&addr(2)));
+    }
+
+    #[test]
+    fn negative_rendezvous_via_unsupporting_peer() {
+        let mut manager = HolepunchManager::new();
+        let mediator = peer(1);
+        manager.add_peer(mediator);
+
+        assert!(!manager.rendezvous(&mediator, addr(2)));
+        assert_eq!(None, manager.poll());
+    }
+
+    #[test]
+    fn negative_connect_to_connected_peer() {
+        let mut manager = HolepunchManager::new();
+        let mediator = supporting_peer(&mut manager, 1);
+        manager.add_peer(peer(2));
+
+        manager.on_message(&mediator, &UtHolepunchMessage::Connect(addr(2)));
+
+        assert!(manager.poll_connect().is_none());
+    }
+}
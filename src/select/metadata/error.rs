@@ -0,0 +1,27 @@
+//! Module for metadata error types.
+
+use crate::handshake::InfoHash;
+use crate::peer::PeerInfo;
+
+error_chain! {
+    types {
+        MetadataError, MetadataErrorKind, MetadataResultExt, MetadataResult;
+    }
+
+    errors {
+        InvalidMessage {
+            info:    PeerInfo,
+            message: String
+        } {
+            description("Peer Sent An Invalid Metadata Message")
+            display("Peer {:?} Sent An Invalid Metadata Message: {:?}", info, message)
+        }
+        InvalidInfoHash {
+            expected: InfoHash,
+            actual:   InfoHash
+        } {
+            description("Downloaded Metadata Failed The Hash Check")
+            display("Downloaded Metadata Hashed To {:?} But Expected {:?}", actual, expected)
+        }
+    }
+}
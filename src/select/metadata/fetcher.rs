@@ -0,0 +1,503 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::mem;
+use std::time::Duration;
+
+use bytes::Bytes;
+
+use crate::handshake::InfoHash;
+use crate::peer::messages::{
+    num_metadata_pieces, PeerExtensionProtocolMessage, UtMetadataDataMessage, UtMetadataMessage,
+    UtMetadataRequestMessage, UT_METADATA_PIECE_LEN,
+};
+use crate::peer::PeerInfo;
+use crate::select::metadata::error::{MetadataError, MetadataErrorKind, MetadataResult};
+
+const DEFAULT_PIPELINE_DEPTH: usize = 4;
+const DEFAULT_REQUEST_TIMEOUT_MILLIS: u64 = 5000;
+const DEFAULT_MAX_METADATA_SIZE: i64 = 16 * 1024 * 1024;
+
+/// Drives `ut_metadata` requests to peers until the metadata for an `InfoHash` is downloaded.
+///
+/// Peers are added once they advertise a `metadata_size` in their `ExtendedMessage`. Requests
+/// to send out are retrieved via `poll`, and messages received from peers are given to
+/// `recv_message`, which resolves to the info dictionary once it passes the hash check.
+pub struct MetadataFetcher {
+    hash: InfoHash,
+    pipeline_depth: usize,
+    request_timeout: Duration,
+    max_metadata_size: i64,
+    metadata_size: Option<i64>,
+    bytes: Vec<u8>,
+    left: usize,
+    pending: VecDeque<i64>,
+    active: Vec<ActiveRequest>,
+    // Pieces that each peer has rejected
+    peers: HashMap<PeerInfo, HashSet<i64>>,
+}
+
+struct ActiveRequest {
+    piece: i64,
+    sent_to: PeerInfo,
+    left: Duration,
+}
+
+impl MetadataFetcher {
+    /// Create a new `MetadataFetcher` for the given `InfoHash`.
+    pub fn new(hash: InfoHash) -> MetadataFetcher {
+        MetadataFetcher {
+            hash: hash,
+            pipeline_depth: DEFAULT_PIPELINE_DEPTH,
+            request_timeout: Duration::from_millis(DEFAULT_REQUEST_TIMEOUT_MILLIS),
+            max_metadata_size: DEFAULT_MAX_METADATA_SIZE,
+            metadata_size: None,
+            bytes: Vec::new(),
+            left: 0,
+            pending: VecDeque::new(),
+            active: Vec::new(),
+            peers: HashMap::new(),
+        }
+    }
+
+    /// Sets the maximum number of outstanding requests to any single peer.
+    pub fn with_pipeline_depth(mut self, depth: usize) -> MetadataFetcher {
+        self.pipeline_depth = depth;
+        self
+    }
+
+    /// Sets the duration after which an unanswered request is sent to another peer.
+    pub fn with_request_timeout(mut self, timeout: Duration) -> MetadataFetcher {
+        self.request_timeout = timeout;
+        self
+    }
+
+    /// Sets the largest metadata size we will accept from a peer.
+    pub fn with_max_metadata_size(mut self, size: i64) -> MetadataFetcher {
+        self.max_metadata_size = size;
+        self
+    }
+
+    /// Retrieve the `InfoHash` we are downloading metadata for.
+    pub fn hash(&self) -> &InfoHash {
+        &self.hash
+    }
+
+    /// Retrieve the metadata size we are downloading, if any peers have been added.
+    pub fn metadata_size(&self) -> Option<i64> {
+        self.metadata_size
+    }
+
+    /// Add a peer that advertised the given metadata size.
+    ///
+    /// The first peer added decides the metadata size; peers that disagree with it, or that
+    /// are for a different `InfoHash`, are ignored and false is returned.
+    pub fn add_peer(&mut self, info: PeerInfo, metadata_size: i64) -> bool {
+        if *info.hash() != self.hash || metadata_size <= 0 || metadata_size > self.max_metadata_size {
+            return false;
+        }
+
+        match self.metadata_size {
+            None => self.initialize(metadata_size),
+            Some(size) if size != metadata_size => return false,
+            Some(_) => (),
+        }
+
+        self.peers.entry(info).or_insert_with(HashSet::new);
+
+        true
+    }
+
+    /// Remove a peer, any requests outstanding to it will be sent to other peers.
+    pub fn remove_peer(&mut self, info: &PeerInfo) {
+        self.peers.remove(info);
+
+        self.requeue_requests(|request| request.sent_to == *info);
+    }
+
+    /// Apply the given duration to outstanding requests, expired requests are sent to other peers.
+    pub fn tick(&mut self, duration: Duration) {
+        self.requeue_requests(|request| request.left <= duration);
+
+        for request in self.active.iter_mut() {
+            request.left -= duration;
+        }
+    }
+
+    /// Process a message received from the given peer.
+    ///
+    /// Returns the info dictionary once the last piece has been received and the metadata
+    /// hashes to our `InfoHash`. If the hash check fails, the download is restarted and
+    /// `MetadataErrorKind::InvalidInfoHash` is returned.
+    pub fn recv_message(
+        &mut self,
+        info: PeerInfo,
+        message: PeerExtensionProtocolMessage,
+    ) -> MetadataResult<Option<Bytes>> {
+        match message {
+            PeerExtensionProtocolMessage::UtMetadata(UtMetadataMessage::Data(data)) => {
+                self.recv_data(info, data)
+            }
+            PeerExtensionProtocolMessage::UtMetadata(UtMetadataMessage::Reject(reject)) => {
+                self.recv_reject(info, reject.piece());
+
+                Ok(None)
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// Retrieve the next request that should be sent out, if any.
+    ///
+    /// Requests go to the least loaded peer that has not rejected the piece.
+    pub fn poll(&mut self) -> Option<(PeerInfo, PeerExtensionProtocolMessage)> {
+        for index in 0..self.pending.len() {
+            let piece = self.pending[index];
+
+            let opt_peer = self
+                .peers
+                .iter()
+                .filter(|&(_, rejected)| !rejected.contains(&piece))
+                .map(|(peer, _)| (*peer, self.active_requests(peer)))
+                .filter(|&(_, active)| active < self.pipeline_depth)
+                .min_by_key(|&(_, active)| active)
+                .map(|(peer, _)| peer);
+
+            if let Some(peer) = opt_peer {
+                self.pending.remove(index);
+                self.active.push(ActiveRequest {
+                    piece: piece,
+                    sent_to: peer,
+                    left: self.request_timeout,
+                });
+
+                let request = UtMetadataRequestMessage::new(piece);
+                return Some((
+                    peer,
+                    PeerExtensionProtocolMessage::UtMetadata(UtMetadataMessage::Request(request)),
+                ));
+            }
+        }
+
+        None
+    }
+
+    //-------------------------------------------------------------------------------//
+
+    fn initialize(&mut self, metadata_size: i64) {
+        let num_pieces = num_metadata_pieces(metadata_size);
+
+        self.metadata_size = Some(metadata_size);
+        self.bytes = vec![0u8; metadata_size as usize];
+        self.left = num_pieces;
+        self.pending = (0..num_pieces as i64).collect();
+        self.active.clear();
+    }
+
+    fn active_requests(&self, peer: &PeerInfo) -> usize {
+        self.active
+            .iter()
+            .filter(|request| request.sent_to == *peer)
+            .count()
+    }
+
+    fn requeue_requests<F>(&mut self, mut should_requeue: F)
+    where
+        F: FnMut(&ActiveRequest) -> bool,
+    {
+        let pending = &mut self.pending;
+
+        self.active.retain(|request| {
+            let requeue = should_requeue(request);
+            if requeue {
+                pending.push_front(request.piece);
+            }
+
+            !requeue
+        });
+    }
+
+    fn recv_data(
+        &mut self,
+        info: PeerInfo,
+        data: UtMetadataDataMessage,
+    ) -> MetadataResult<Option<Bytes>> {
+        // Ignore any data we did not request from the peer
+        let index = match self
+            .active
+            .iter()
+            .position(|request| request.sent_to == info && request.piece == data.piece())
+        {
+            Some(index) => index,
+            None => return Ok(None),
+        };
+        self.active.swap_remove(index);
+
+        let metadata_size = self
+            .metadata_size
+            .expect("bittorrent-protocol_select: MetadataFetcher Had A Request Without A Metadata Size");
+
+        if let Err(err) = data.validate(metadata_size) {
+            self.pending.push_front(data.piece());
+            self.remove_peer(&info);
+
+            return Err(MetadataError::from_kind(MetadataErrorKind::InvalidMessage {
+                info: info,
+                message: err.to_string(),
+            }));
+        }
+
+        let offset = data.piece() as usize * UT_METADATA_PIECE_LEN;
+        self.bytes[offset..offset + data.data().len()].copy_from_slice(data.data().as_ref());
+        self.left -= 1;
+
+        if self.left != 0 {
+            return Ok(None);
+        }
+
+        let actual_hash = InfoHash::from_bytes(&self.bytes[..]);
+        if actual_hash == self.hash {
+            let bytes = mem::replace(&mut self.bytes, Vec::new());
+
+            Ok(Some(Bytes::from(bytes)))
+        } else {
+            self.initialize(metadata_size);
+
+            Err(MetadataError::from_kind(MetadataErrorKind::InvalidInfoHash {
+                expected: self.hash,
+                actual: actual_hash,
+            }))
+        }
+    }
+
+    fn recv_reject(&mut self, info: PeerInfo, piece: i64) {
+        let opt_index = self
+            .active
+            .iter()
+            .position(|request| request.sent_to == info && request.piece == piece);
+
+        if let Some(index) = opt_index {
+            self.active.swap_remove(index);
+            self.pending.push_front(piece);
+
+            self.peers.get_mut(&info).map(|rejected| rejected.insert(piece));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::MetadataFetcher;
+    use crate::handshake::{Extensions, InfoHash};
+    use crate::peer::messages::{
+        PeerExtensionProtocolMessage, UtMetadataDataMessage, UtMetadataMessage,
+        UtMetadataRejectMessage, UT_METADATA_PIECE_LEN,
+    };
+    use crate::peer::PeerInfo;
+    use crate::select::metadata::error::MetadataErrorKind;
+
+    use bytes::Bytes;
+    use std::net::SocketAddr;
+    use std::time::Duration;
+
+    fn info_bytes() -> Vec<u8> {
+        (0..(2 * UT_METADATA_PIECE_LEN + 100))
+            .map(|index| index as u8)
+            .collect()
+    }
+
+    fn peer(port: u16, hash: InfoHash) -> PeerInfo {
+        let addr: SocketAddr = format!("127.0.0.1:{}", port).parse().unwrap();
+
+        PeerInfo::new(addr, [port as u8; 20].into(), hash, Extensions::new())
+    }
+
+    /// Answer a request the same way a seeding peer would.
+    fn seed_response(
+        info_bytes: &[u8],
+        message: &PeerExtensionProtocolMessage,
+    ) -> PeerExtensionProtocolMessage {
+        let piece = match message {
+            &PeerExtensionProtocolMessage::UtMetadata(UtMetadataMessage::Request(ref request)) => {
+                request.piece()
+            }
+            other => panic!("Fetcher Sent Unexpected Message {:?}", other),
+        };
+
+        let start = piece as usize * UT_METADATA_PIECE_LEN;
+        let end = ::std::cmp::min(start + UT_METADATA_PIECE_LEN, info_bytes.len());
+        let data = UtMetadataDataMessage::new(
+            piece,
+            info_bytes.len() as i64,
+            Bytes::from(&info_bytes[start..end]),
+        );
+
+        PeerExtensionProtocolMessage::UtMetadata(UtMetadataMessage::Data(data))
+    }
+
+    fn requested_piece(message: &PeerExtensionProtocolMessage) -> i64 {
+        match message {
+            &PeerExtensionProtocolMessage::UtMetadata(UtMetadataMessage::Request(ref request)) => {
+                request.piece()
+            }
+            other => panic!("Fetcher Sent Unexpected Message {:?}", other),
+        }
+    }
+
+    #[test]
+    fn positive_fetch_from_seeding_peer() {
+        let info_bytes = info_bytes();
+        let hash = InfoHash::from_bytes(&info_bytes);
+        let seed = peer(1, hash);
+
+        let mut fetcher = MetadataFetcher::new(hash);
+        assert!(fetcher.add_peer(seed, info_bytes.len() as i64));
+
+        let mut opt_metadata = None;
+        while let Some((to, request)) = fetcher.poll() {
+            assert_eq!(seed, to);
+
+            opt_metadata = fetcher
+                .recv_message(seed, seed_response(&info_bytes, &request))
+                .unwrap();
+        }
+
+        assert_eq!(Some(Bytes::from(info_bytes)), opt_metadata);
+    }
+
+    #[test]
+    fn positive_respects_pipeline_depth() {
+        let info_bytes = info_bytes();
+        let hash = InfoHash::from_bytes(&info_bytes);
+        let seed = peer(1, hash);
+
+        let mut fetcher = MetadataFetcher::new(hash).with_pipeline_depth(2);
+        fetcher.add_peer(seed, info_bytes.len() as i64);
+
+        let (_, first) = fetcher.poll().unwrap();
+        fetcher.poll().unwrap();
+        assert!(fetcher.poll().is_none());
+
+        fetcher
+            .recv_message(seed, seed_response(&info_bytes, &first))
+            .unwrap();
+        assert!(fetcher.poll().is_some());
+    }
+
+    #[test]
+    fn positive_rejected_piece_retried_on_other_peer() {
+        let info_bytes = info_bytes();
+        let hash = InfoHash::from_bytes(&info_bytes);
+        let (peer_one, peer_two) = (peer(1, hash), peer(2, hash));
+
+        let mut fetcher = MetadataFetcher::new(hash).with_pipeline_depth(1);
+        fetcher.add_peer(peer_one, info_bytes.len() as i64);
+
+        let (_, request) = fetcher.poll().unwrap();
+        let piece = requested_piece(&request);
+        let reject = PeerExtensionProtocolMessage::UtMetadata(UtMetadataMessage::Reject(
+            UtMetadataRejectMessage::new(piece),
+        ));
+        assert_eq!(None, fetcher.recv_message(peer_one, reject).unwrap());
+
+        fetcher.add_peer(peer_two, info_bytes.len() as i64);
+
+        let mut retried_by = Vec::new();
+        while let Some((to, request)) = fetcher.poll() {
+            if requested_piece(&request) == piece {
+                retried_by.push(to);
+            }
+            fetcher
+                .recv_message(to, seed_response(&info_bytes, &request))
+                .unwrap();
+        }
+
+        assert_eq!(vec![peer_two], retried_by);
+    }
+
+    #[test]
+    fn positive_timed_out_request_is_retried() {
+        let info_bytes = info_bytes();
+        let hash = InfoHash::from_bytes(&info_bytes);
+        let seed = peer(1, hash);
+
+        let mut fetcher = MetadataFetcher::new(hash)
+            .with_pipeline_depth(1)
+            .with_request_timeout(Duration::from_millis(100));
+        fetcher.add_peer(seed, info_bytes.len() as i64);
+
+        let (_, first) = fetcher.poll().unwrap();
+        assert!(fetcher.poll().is_none());
+
+        fetcher.tick(Duration::from_millis(100));
+
+        let (_, retried) = fetcher.poll().unwrap();
+        assert_eq!(requested_piece(&first), requested_piece(&retried));
+    }
+
+    #[test]
+    fn negative_ignore_mismatched_peers() {
+        let info_bytes = info_bytes();
+        let hash = InfoHash::from_bytes(&info_bytes);
+
+        let mut fetcher = MetadataFetcher::new(hash);
+
+        assert!(!fetcher.add_peer(peer(1, hash), 0));
+        assert!(!fetcher.add_peer(peer(2, [0u8; 20].into()), info_bytes.len() as i64));
+        assert!(fetcher.add_peer(peer(3, hash), info_bytes.len() as i64));
+        assert!(!fetcher.add_peer(peer(4, hash), info_bytes.len() as i64 + 1));
+        assert_eq!(Some(info_bytes.len() as i64), fetcher.metadata_size());
+    }
+
+    #[test]
+    fn negative_invalid_piece_drops_peer() {
+        let info_bytes = info_bytes();
+        let hash = InfoHash::from_bytes(&info_bytes);
+        let seed = peer(1, hash);
+
+        let mut fetcher = MetadataFetcher::new(hash);
+        fetcher.add_peer(seed, info_bytes.len() as i64);
+
+        let (_, request) = fetcher.poll().unwrap();
+        let data = UtMetadataDataMessage::new(
+            requested_piece(&request),
+            info_bytes.len() as i64,
+            Bytes::from(vec![0u8; UT_METADATA_PIECE_LEN + 1]),
+        );
+
+        let error = fetcher
+            .recv_message(
+                seed,
+                PeerExtensionProtocolMessage::UtMetadata(UtMetadataMessage::Data(data)),
+            )
+            .unwrap_err();
+
+        match error.kind() {
+            &MetadataErrorKind::InvalidMessage { info, .. } => assert_eq!(seed, info),
+            other => panic!("Unexpected Error {:?}", other),
+        }
+        assert!(fetcher.poll().is_none());
+    }
+
+    #[test]
+    fn negative_hash_mismatch_restarts_download() {
+        let info_bytes = info_bytes();
+        let hash = InfoHash::from_bytes(&[0u8; 20]);
+        let seed = peer(1, hash);
+
+        let mut fetcher = MetadataFetcher::new(hash);
+        fetcher.add_peer(seed, info_bytes.len() as i64);
+
+        let mut result = Ok(None);
+        while let Some((_, request)) = fetcher.poll() {
+            result = fetcher.recv_message(seed, seed_response(&info_bytes, &request));
+            if result.is_err() {
+                break;
+            }
+        }
+
+        match result.unwrap_err().kind() {
+            &MetadataErrorKind::InvalidInfoHash { expected, .. } => assert_eq!(hash, expected),
+            other => panic!("Unexpected Error {:?}", other),
+        }
+        assert!(fetcher.poll().is_some());
+    }
+}
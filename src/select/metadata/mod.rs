@@ -0,0 +1,9 @@
+//! Module for downloading and serving torrent metadata via `ut_metadata`.
+
+pub mod error;
+
+mod fetcher;
+pub use self::fetcher::MetadataFetcher;
+
+pub(crate) mod server;
+pub use self::server::UtMetadataServer;
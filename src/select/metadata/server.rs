@@ -0,0 +1,161 @@
+use std::cmp;
+
+use bytes::Bytes;
+
+use crate::peer::messages::builders::ExtendedMessageBuilder;
+use crate::peer::messages::{
+    metadata_piece_len, ExtendedType, UtMetadataDataMessage, UtMetadataMessage,
+    UtMetadataRejectMessage, UtMetadataRequestMessage, UT_METADATA_PIECE_LEN,
+};
+
+/// Id we map `ExtendedType::UtMetadata` to in our `ExtendedMessage`.
+pub(crate) const UT_METADATA_ID: u8 = 5;
+
+/// Answers `ut_metadata` requests from our own info dictionary.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UtMetadataServer {
+    info_bytes: Bytes,
+}
+
+impl UtMetadataServer {
+    /// Create a new `UtMetadataServer` serving the given (bencoded) info dictionary.
+    pub fn new(info_bytes: Bytes) -> UtMetadataServer {
+        UtMetadataServer {
+            info_bytes: info_bytes,
+        }
+    }
+
+    /// Retrieve the metadata size that we advertise.
+    pub fn metadata_size(&self) -> i64 {
+        self.info_bytes.len() as i64
+    }
+
+    /// Retrieve the info dictionary we are serving.
+    pub fn info_bytes(&self) -> &Bytes {
+        &self.info_bytes
+    }
+
+    /// Advertise `ut_metadata` support along with our metadata size.
+    pub fn extend(&self, builder: ExtendedMessageBuilder) -> ExtendedMessageBuilder {
+        builder
+            .with_extended_type(ExtendedType::UtMetadata, Some(UT_METADATA_ID))
+            .with_metadata_size(Some(self.metadata_size()))
+    }
+
+    /// Generate the response to the given request.
+    ///
+    /// Requests for pieces outside of our metadata are rejected.
+    pub fn respond(&self, request: &UtMetadataRequestMessage) -> UtMetadataMessage {
+        let piece = request.piece();
+
+        match metadata_piece_len(piece, self.metadata_size()) {
+            Some(length) => {
+                let start = piece as usize * UT_METADATA_PIECE_LEN;
+                let end = cmp::min(start + length, self.info_bytes.len());
+
+                UtMetadataMessage::Data(UtMetadataDataMessage::new(
+                    piece,
+                    self.metadata_size(),
+                    self.info_bytes.slice(start, end),
+                ))
+            }
+            None => UtMetadataMessage::Reject(UtMetadataRejectMessage::new(piece)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::UtMetadataServer;
+    use crate::handshake::{Extensions, InfoHash};
+    use crate::peer::messages::builders::ExtendedMessageBuilder;
+    use crate::peer::messages::{
+        ExtendedType, PeerExtensionProtocolMessage, UtMetadataMessage, UtMetadataRequestMessage,
+        UT_METADATA_PIECE_LEN,
+    };
+    use crate::peer::PeerInfo;
+    use crate::select::metadata::MetadataFetcher;
+
+    use bytes::Bytes;
+
+    fn info_bytes(len: usize) -> Bytes {
+        (0..len)
+            .map(|index| (index % 251) as u8)
+            .collect::<Vec<u8>>()
+            .into()
+    }
+
+    #[test]
+    fn positive_respond_with_pieces() {
+        let server = UtMetadataServer::new(info_bytes(UT_METADATA_PIECE_LEN + 10));
+
+        match server.respond(&UtMetadataRequestMessage::new(0)) {
+            UtMetadataMessage::Data(data) => {
+                assert_eq!(UT_METADATA_PIECE_LEN, data.data().len());
+                assert_eq!(UT_METADATA_PIECE_LEN as i64 + 10, data.total_size());
+            }
+            other => panic!("Unexpected Response {:?}", other),
+        }
+
+        match server.respond(&UtMetadataRequestMessage::new(1)) {
+            UtMetadataMessage::Data(data) => assert_eq!(10, data.data().len()),
+            other => panic!("Unexpected Response {:?}", other),
+        }
+    }
+
+    #[test]
+    fn positive_reject_out_of_range() {
+        let server = UtMetadataServer::new(info_bytes(UT_METADATA_PIECE_LEN));
+
+        for &piece in [-1, 1].iter() {
+            match server.respond(&UtMetadataRequestMessage::new(piece)) {
+                UtMetadataMessage::Reject(reject) => assert_eq!(piece, reject.piece()),
+                other => panic!("Unexpected Response {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn positive_extend_advertises_metadata_size() {
+        let server = UtMetadataServer::new(info_bytes(100));
+
+        let message = server.extend(ExtendedMessageBuilder::new()).build();
+
+        assert_eq!(Some(100), message.metadata_size());
+        assert!(message.query_id(&ExtendedType::UtMetadata).is_some());
+    }
+
+    #[test]
+    fn positive_fetcher_reconstructs_served_metadata() {
+        let served = info_bytes(3 * UT_METADATA_PIECE_LEN + 1234);
+        let hash = InfoHash::from_bytes(&served);
+        let server = UtMetadataServer::new(served.clone());
+
+        let seed = PeerInfo::new(
+            "127.0.0.1:6881".parse().unwrap(),
+            [1u8; 20].into(),
+            hash,
+            Extensions::new(),
+        );
+        let advertised = server.extend(ExtendedMessageBuilder::new()).build();
+
+        let mut fetcher = MetadataFetcher::new(hash);
+        fetcher.add_peer(seed, advertised.metadata_size().unwrap());
+
+        let mut opt_metadata = None;
+        while let Some((_, request)) = fetcher.poll() {
+            let response = match request {
+                PeerExtensionProtocolMessage::UtMetadata(UtMetadataMessage::Request(request)) => {
+                    server.respond(&request)
+                }
+                other => panic!("Fetcher Sent Unexpected Message {:?}", other),
+            };
+
+            opt_metadata = fetcher
+                .recv_message(seed, PeerExtensionProtocolMessage::UtMetadata(response))
+                .unwrap();
+        }
+
+        assert_eq!(Some(served), opt_metadata);
+    }
+}
@@ -8,6 +8,26 @@ pub mod revelation;
 
 pub mod discovery;
 
+pub mod metadata;
+
+pub mod choke;
+
+pub mod request;
+
+pub mod piece;
+
+pub mod superseed;
+
+pub mod holepunch;
+
+pub mod verify;
+
+pub mod ban;
+
+pub mod upload;
+
+pub mod webseed;
+
 mod extended;
 pub use self::extended::{ExtendedListener, ExtendedPeerInfo, IExtendedMessage, OExtendedMessage};
 
@@ -0,0 +1,51 @@
+use std::sync::Arc;
+
+/// Snapshot of how many connected peers have each piece of a torrent.
+///
+/// Taking a snapshot does not copy the counts, they are shared with the picker until it
+/// next changes them, so a snapshot can be taken every second even for torrents with
+/// hundreds of thousands of pieces.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Availability {
+    counts: Arc<Vec<u32>>,
+    distributed_copies: f64,
+}
+
+impl Availability {
+    pub(crate) fn new(counts: Arc<Vec<u32>>, min: u32, num_at_min: usize) -> Availability {
+        let num_pieces = counts.len();
+        let distributed_copies = if num_pieces == 0 {
+            0.0
+        } else {
+            min as f64 + (num_pieces - num_at_min) as f64 / num_pieces as f64
+        };
+
+        Availability {
+            counts: counts,
+            distributed_copies: distributed_copies,
+        }
+    }
+
+    /// Number of complete copies of the torrent that peers have, as a fraction.
+    ///
+    /// The whole part is the lowest availability of any piece, the fractional part is the
+    /// share of pieces that more peers than that have. Below 1.0, some pieces can not be
+    /// downloaded from the peers we are connected to.
+    pub fn distributed_copies(&self) -> f64 {
+        self.distributed_copies
+    }
+
+    /// Number of peers that have each piece, indexed by piece.
+    pub fn histogram(&self) -> &[u32] {
+        &self.counts[..]
+    }
+
+    /// Pieces that none of the peers have.
+    pub fn unavailable_pieces(&self) -> impl Iterator<Item = u32> + '_ {
+        self.counts
+            .iter()
+            .enumerate()
+            .filter(|&(_, &count)| count == 0)
+            .map(|(piece, _)| piece as u32)
+    }
+}
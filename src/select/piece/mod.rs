@@ -0,0 +1,38 @@
+//! Module for deciding which pieces we request from peers.
+
+use std::collections::HashSet;
+
+use crate::peer::messages::BitFieldMessage;
+use crate::peer::PeerInfo;
+
+mod availability;
+pub use self::availability::Availability;
+
+mod rarest;
+pub use self::rarest::RarestFirstPicker;
+
+mod sequential;
+pub use self::sequential::SequentialPicker;
+
+/// Trait for strategies that pick the next piece to request.
+///
+/// Pickers are fed the same availability events, so they can be swapped.
+pub trait PiecePicker {
+    /// Record that the given peer has the given piece.
+    fn on_have(&mut self, peer: PeerInfo, piece: u32);
+
+    /// Record that the given peer has every piece set in the given bitfield.
+    fn on_bitfield(&mut self, peer: PeerInfo, bits: &BitFieldMessage);
+
+    /// Record that the given peer has every piece, as told by a `HaveAll` message.
+    fn on_have_all(&mut self, peer: PeerInfo);
+
+    /// Record that the given peer has no pieces, as told by a `HaveNone` message.
+    fn on_have_none(&mut self, peer: PeerInfo);
+
+    /// Remove the given peer, no longer counting any of its pieces.
+    fn on_peer_gone(&mut self, peer: PeerInfo);
+
+    /// Pick a piece in `pending` that is set in `peer_bitfield`.
+    fn pick(&mut self, peer_bitfield: &BitFieldMessage, pending: &HashSet<u32>) -> Option<u32>;
+}
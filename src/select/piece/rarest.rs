@@ -0,0 +1,558 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+use rand::{self, Rng, SeedableRng, XorShiftRng};
+
+use crate::peer::messages::BitFieldMessage;
+use crate::peer::PeerInfo;
+use crate::select::piece::{Availability, PiecePicker};
+
+/// Rarest first piece selection.
+///
+/// Keeps a histogram of how many peers have each piece, built from `Have` and `BitField`
+/// messages, and picks the least available piece that a peer can give us. Pieces with
+/// the same availability are picked at random, so that peers requesting from the same
+/// swarm spread out across pieces.
+///
+/// Pieces are bucketed by availability, so an update only moves a single piece between
+/// two buckets, regardless of how many pieces the torrent has.
+pub struct RarestFirstPicker {
+    num_pieces: usize,
+    // Shared with snapshots, copied on the first change after a snapshot was taken
+    availability: Arc<Vec<u32>>,
+    // Bucket at index n holds every piece that n peers have
+    buckets: Vec<Vec<u32>>,
+    // Index of each piece within its bucket
+    positions: Vec<usize>,
+    peers: HashMap<PeerInfo, Vec<u8>>,
+    rng: XorShiftRng,
+}
+
+impl RarestFirstPicker {
+    /// Create a new `RarestFirstPicker` for a torrent with the given number of pieces.
+    pub fn new(num_pieces: usize) -> RarestFirstPicker {
+        RarestFirstPicker {
+            num_pieces: num_pieces,
+            availability: Arc::new(vec![0; num_pieces]),
+            buckets: vec![(0..num_pieces as u32).collect()],
+            positions: (0..num_pieces).collect(),
+            peers: HashMap::new(),
+            rng: rand::weak_rng(),
+        }
+    }
+
+    /// Seeds the random number generator used for breaking ties.
+    pub fn with_seed(mut self, seed: [u32; 4]) -> RarestFirstPicker {
+        self.rng = XorShiftRng::from_seed(seed);
+        self
+    }
+
+    /// Number of pieces in the torrent.
+    pub fn num_pieces(&self) -> usize {
+        self.num_pieces
+    }
+
+    /// Number of peers that we know have the given piece.
+    pub fn availability(&self, piece: u32) -> u32 {
+        self.availability.get(piece as usize).cloned().unwrap_or(0)
+    }
+
+    /// Lowest availability of any piece, which is how many complete copies peers have.
+    pub fn min_availability(&self) -> u32 {
+        self.buckets
+            .iter()
+            .position(|bucket| !bucket.is_empty())
+            .unwrap_or(0) as u32
+    }
+
+    /// Take a snapshot of the availability of every piece, see `Availability`.
+    pub fn availability_snapshot(&self) -> Availability {
+        let min = self.min_availability();
+        let num_at_min = self.buckets.get(min as usize).map_or(0, Vec::len);
+
+        Availability::new(self.availability.clone(), min, num_at_min)
+    }
+
+    /// Whether or not the given peer told us it has the given piece.
+    pub fn peer_has_piece(&self, peer: &PeerInfo, piece: u32) -> bool {
+        (piece as usize) < self.num_pieces
+            && self
+                .peers
+                .get(peer)
+                .map_or(false, |bits| has_bit(bits, piece as usize))
+    }
+
+    /// Number of peers that we have received a `Have` or `BitField` from.
+    pub fn num_peers(&self) -> usize {
+        self.peers.len()
+    }
+
+    /// Record that the given peer has the given piece.
+    ///
+    /// Pieces past the end of the torrent, and pieces the peer already told us about, are ignored.
+    pub fn on_have(&mut self, peer: PeerInfo, piece: u32) {
+        if piece as usize >= self.num_pieces {
+            return;
+        }
+
+        let bitfield_len = bitfield_len(self.num_pieces);
+        let bits = self
+            .peers
+            .entry(peer)
+            .or_insert_with(|| vec![0u8; bitfield_len]);
+
+        if set_bit(bits, piece as usize) {
+            self.increment(piece);
+        }
+    }
+
+    /// Record that the given peer has every piece set in the given bitfield.
+    ///
+    /// Bitfields are merged with what we already know about the peer.
+    pub fn on_bitfield(&mut self, peer: PeerInfo, bits: &BitFieldMessage) {
+        let mut peer_bits = self
+            .peers
+            .remove(&peer)
+            .unwrap_or_else(|| vec![0u8; bitfield_len(self.num_pieces)]);
+
+        for have in bits.iter() {
+            let piece = have.piece_index();
+
+            if (piece as usize) < self.num_pieces && set_bit(&mut peer_bits, piece as usize) {
+                self.increment(piece);
+            }
+        }
+
+        self.peers.insert(peer, peer_bits);
+    }
+
+    /// Record that the given peer has every piece.
+    pub fn on_have_all(&mut self, peer: PeerInfo) {
+        let mut peer_bits = self
+            .peers
+            .remove(&peer)
+            .unwrap_or_else(|| vec![0u8; bitfield_len(self.num_pieces)]);
+
+        for piece in 0..self.num_pieces {
+            if set_bit(&mut peer_bits, piece) {
+                self.increment(piece as u32);
+            }
+        }
+
+        self.peers.insert(peer, peer_bits);
+    }
+
+    /// Record that the given peer has no pieces, forgetting any it told us about before.
+    pub fn on_have_none(&mut self, peer: PeerInfo) {
+        self.on_peer_gone(peer);
+
+        self.peers.insert(peer, vec![0u8; bitfield_len(self.num_pieces)]);
+    }
+
+    /// Remove the given peer, no longer counting any of its pieces.
+    pub fn on_peer_gone(&mut self, peer: PeerInfo) {
+        if let Some(bits) = self.peers.remove(&peer) {
+            for piece in 0..self.num_pieces {
+                if has_bit(&bits, piece) {
+                    self.decrement(piece as u32);
+                }
+            }
+        }
+    }
+
+    /// Pick the rarest piece in `pending` that is set in `peer_bitfield`.
+    ///
+    /// Returns `None` if the peer has none of the pending pieces.
+    pub fn pick(&mut self, peer_bitfield: &BitFieldMessage, pending: &HashSet<u32>) -> Option<u32> {
+        for bucket in self.buckets.iter() {
+            let mut num_candidates = 0;
+            let mut picked = None;
+
+            // Reservoir sample the candidates, so every tied piece is equally likely
+            for &piece in bucket.iter() {
+                if pending.contains(&piece) && peer_bitfield.has_piece(piece as usize) {
+                    num_candidates += 1;
+
+                    if self.rng.gen_range(0, num_candidates) == 0 {
+                        picked = Some(piece);
+                    }
+                }
+            }
+
+            if picked.is_some() {
+                return picked;
+            }
+        }
+
+        None
+    }
+
+    fn increment(&mut self, piece: u32) {
+        let from = self.availability[piece as usize];
+
+        Arc::make_mut(&mut self.availability)[piece as usize] = from + 1;
+        self.move_piece(piece, from as usize, from as usize + 1);
+    }
+
+    fn decrement(&mut self, piece: u32) {
+        let from = self.availability[piece as usize];
+
+        Arc::make_mut(&mut self.availability)[piece as usize] = from - 1;
+        self.move_piece(piece, from as usize, from as usize - 1);
+    }
+
+    fn move_piece(&mut self, piece: u32, from: usize, to: usize) {
+        let position = self.positions[piece as usize];
+
+        self.buckets[from].swap_remove(position);
+        if let Some(&swapped) = self.buckets[from].get(position) {
+            self.positions[swapped as usize] = position;
+        }
+
+        if to == self.buckets.len() {
+            self.buckets.push(Vec::new());
+        }
+        self.positions[piece as usize] = self.buckets[to].len();
+        self.buckets[to].push(piece);
+    }
+}
+
+impl PiecePicker for RarestFirstPicker {
+    fn on_have(&mut self, peer: PeerInfo, piece: u32) {
+        RarestFirstPicker::on_have(self, peer, piece)
+    }
+
+    fn on_bitfield(&mut self, peer: PeerInfo, bits: &BitFieldMessage) {
+        RarestFirstPicker::on_bitfield(self, peer, bits)
+    }
+
+    fn on_have_all(&mut self, peer: PeerInfo) {
+        RarestFirstPicker::on_have_all(self, peer)
+    }
+
+    fn on_have_none(&mut self, peer: PeerInfo) {
+        RarestFirstPicker::on_have_none(self, peer)
+    }
+
+    fn on_peer_gone(&mut self, peer: PeerInfo) {
+        RarestFirstPicker::on_peer_gone(self, peer)
+    }
+
+    fn pick(&mut self, peer_bitfield: &BitFieldMessage, pending: &HashSet<u32>) -> Option<u32> {
+        RarestFirstPicker::pick(self, peer_bitfield, pending)
+    }
+}
+
+fn bitfield_len(num_pieces: usize) -> usize {
+    (num_pieces + 7) / 8
+}
+
+fn has_bit(bits: &[u8], piece: usize) -> bool {
+    bits[piece / 8] & (0x80 >> (piece % 8)) != 0
+}
+
+/// Set the given bit, returning true if it was not already set.
+fn set_bit(bits: &mut [u8], piece: usize) -> bool {
+    let was_set = has_bit(bits, piece);
+    bits[piece / 8] |= 0x80 >> (piece % 8);
+
+    !was_set
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RarestFirstPicker;
+    use crate::handshake::Extensions;
+    use crate::peer::messages::BitFieldMessage;
+    use crate::peer::PeerInfo;
+
+    use std::collections::{HashMap, HashSet};
+
+    use crate::quickcheck::{QuickCheck, TestResult};
+
+    const NUM_PIECES: usize = 37;
+
+    fn peer(port: u16) -> PeerInfo {
+        PeerInfo::new(
+            format!("127.0.0.1:{}", port).parse().unwrap(),
+            [port as u8; 20].into(),
+            [0u8; 20].into(),
+            Extensions::new(),
+        )
+    }
+
+    fn picker() -> RarestFirstPicker {
+        RarestFirstPicker::new(NUM_PIECES).with_seed([1, 2, 3, 4])
+    }
+
+    fn bitfield(pieces: &[usize]) -> BitFieldMessage {
+        BitFieldMessage::from_pieces(NUM_PIECES, pieces.iter().cloned())
+    }
+
+    fn all_pending() -> HashSet<u32> {
+        (0..NUM_PIECES as u32).collect()
+    }
+
+    /// Check that the buckets agree with the availability of every piece.
+    fn is_consistent(picker: &RarestFirstPicker) -> bool {
+        let mut seen = 0;
+
+        for (availability, bucket) in picker.buckets.iter().enumerate() {
+            for (position, &piece) in bucket.iter().enumerate() {
+                if picker.availability[piece as usize] as usize != availability
+                    || picker.positions[piece as usize] != position
+                {
+                    return false;
+                }
+                seen += 1;
+            }
+        }
+
+        seen == picker.num_pieces
+    }
+
+    #[test]
+    fn positive_pick_rarest_piece() {
+        let mut picker = picker();
+
+        picker.on_bitfield(peer(1), &bitfield(&[0, 1, 2]));
+        picker.on_bitfield(peer(2), &bitfield(&[0, 1]));
+        picker.on_have(peer(3), 0);
+
+        assert_eq!(Some(2), picker.pick(&bitfield(&[0, 1, 2]), &all_pending()));
+        assert_eq!(Some(1), picker.pick(&bitfield(&[0, 1]), &all_pending()));
+    }
+
+    #[test]
+    fn positive_pick_only_pending_pieces() {
+        let mut picker = picker();
+
+        picker.on_bitfield(peer(1), &bitfield(&[0, 1, 2]));
+        picker.on_bitfield(peer(2), &bitfield(&[0, 1]));
+
+        let pending = [0, 1].iter().cloned().collect();
+        assert!(picker.pick(&bitfield(&[0, 1, 2]), &pending).is_some());
+        assert_ne!(Some(2), picker.pick(&bitfield(&[0, 1, 2]), &pending));
+    }
+
+    #[test]
+    fn negative_pick_peer_has_no_pending_pieces() {
+        let mut picker = picker();
+
+        picker.on_bitfield(peer(1), &bitfield(&[0, 1]));
+
+        let pending = [2, 3].iter().cloned().collect();
+        assert_eq!(None, picker.pick(&bitfield(&[0, 1]), &pending));
+        assert_eq!(None, picker.pick(&bitfield(&[]), &all_pending()));
+    }
+
+    #[test]
+    fn positive_ties_broken_randomly() {
+        let mut picker = picker();
+
+        picker.on_bitfield(peer(1), &bitfield(&[0, 1, 2, 3]));
+        picker.on_have(peer(2), 3);
+
+        let peer_bitfield = bitfield(&[0, 1, 2, 3]);
+        let picked: HashSet<u32> = (0..100)
+            .map(|_| picker.pick(&peer_bitfield, &all_pending()).unwrap())
+            .collect();
+
+        assert_eq!([0, 1, 2].iter().cloned().collect::<HashSet<u32>>(), picked);
+    }
+
+    #[test]
+    fn positive_duplicate_have_counted_once() {
+        let mut picker = picker();
+
+        picker.on_have(peer(1), 5);
+        picker.on_have(peer(1), 5);
+        picker.on_bitfield(peer(1), &bitfield(&[5]));
+
+        assert_eq!(1, picker.availability(5));
+        assert!(is_consistent(&picker));
+    }
+
+    #[test]
+    fn positive_peer_gone_decrements_availability() {
+        let mut picker = picker();
+
+        picker.on_bitfield(peer(1), &bitfield(&[0, 1]));
+        picker.on_have(peer(2), 1);
+        picker.on_peer_gone(peer(1));
+
+        assert_eq!(0, picker.availability(0));
+        assert_eq!(1, picker.availability(1));
+        assert_eq!(1, picker.num_peers());
+        assert!(is_consistent(&picker));
+    }
+
+    #[test]
+    fn positive_min_availability_counts_copies() {
+        let mut picker = picker();
+        assert_eq!(0, picker.min_availability());
+
+        picker.on_bitfield(peer(1), &bitfield(&(0..NUM_PIECES).collect::<Vec<usize>>()));
+        picker.on_have(peer(2), 0);
+        assert_eq!(1, picker.min_availability());
+
+        picker.on_peer_gone(peer(1));
+        assert_eq!(0, picker.min_availability());
+        assert!(picker.peer_has_piece(&peer(2), 0));
+        assert!(!picker.peer_has_piece(&peer(2), 1));
+    }
+
+    #[test]
+    fn positive_have_all_and_have_none() {
+        let mut picker = picker();
+
+        picker.on_have(peer(1), 3);
+        picker.on_have_all(peer(1));
+        picker.on_have_all(peer(2));
+        assert_eq!(2, picker.min_availability());
+
+        picker.on_have_none(peer(1));
+        assert_eq!(1, picker.availability(3));
+        assert_eq!(2, picker.num_peers());
+        assert!(!picker.peer_has_piece(&peer(1), 3));
+        assert!(is_consistent(&picker));
+    }
+
+    #[test]
+    fn positive_snapshot_distributed_copies() {
+        let mut picker = picker();
+        assert_eq!(0.0, picker.availability_snapshot().distributed_copies());
+
+        picker.on_have_all(peer(1));
+        picker.on_bitfield(peer(2), &bitfield(&(0..NUM_PIECES / 2).collect::<Vec<usize>>()));
+        let snapshot = picker.availability_snapshot();
+
+        let more = (NUM_PIECES / 2) as f64 / NUM_PIECES as f64;
+        assert_eq!(1.0 + more, snapshot.distributed_copies());
+        assert_eq!(&picker.availability[..], snapshot.histogram());
+        assert_eq!(0, snapshot.unavailable_pieces().count());
+    }
+
+    #[test]
+    fn positive_snapshot_unchanged_by_later_events() {
+        let mut picker = picker();
+
+        picker.on_bitfield(peer(1), &bitfield(&[0, 2]));
+        let snapshot = picker.availability_snapshot();
+        picker.on_have(peer(2), 1);
+        picker.on_peer_gone(peer(1));
+
+        assert_eq!(0.0 + 2.0 / NUM_PIECES as f64, snapshot.distributed_copies());
+        assert_eq!(&[1, 0, 1], &snapshot.histogram()[..3]);
+        assert_eq!(
+            (1..NUM_PIECES as u32).filter(|&piece| piece != 2).collect::<Vec<u32>>(),
+            snapshot.unavailable_pieces().collect::<Vec<u32>>()
+        );
+        assert_eq!(
+            vec![0, 1, 0],
+            picker.availability_snapshot().histogram()[..3].to_vec()
+        );
+    }
+
+    #[test]
+    fn negative_have_past_last_piece_ignored() {
+        let mut picker = picker();
+
+        picker.on_have(peer(1), NUM_PIECES as u32);
+
+        assert_eq!(0, picker.availability(NUM_PIECES as u32));
+        assert!(is_consistent(&picker));
+    }
+
+    // Events are (peer, kind, piece), where a bitfield event uses the piece to pick
+    // which pieces are set, checked against a naive model of who has what
+    #[test]
+    fn positive_histogram_consistent_after_events() {
+        fn run(events: Vec<(u8, u8, u16)>) -> TestResult {
+            let mut picker = RarestFirstPicker::new(NUM_PIECES);
+            let mut model: HashMap<u16, HashSet<u32>> = HashMap::new();
+
+            for (peer_id, kind, piece) in events {
+                let peer_id = peer_id as u16 % 8;
+                let piece = piece as u32 % NUM_PIECES as u32;
+
+                match kind % 3 {
+                    0 => {
+                        picker.on_have(peer(peer_id), piece);
+                        model
+                            .entry(peer_id)
+                            .or_insert_with(HashSet::new)
+                            .insert(piece);
+                    }
+                    1 => {
+                        let pieces: Vec<usize> = (0..NUM_PIECES)
+                            .filter(|&index| (index as u32 + piece) % 3 == 0)
+                            .collect();
+
+                        picker.on_bitfield(peer(peer_id), &bitfield(&pieces));
+                        model
+                            .entry(peer_id)
+                            .or_insert_with(HashSet::new)
+                            .extend(pieces.iter().map(|&index| index as u32));
+                    }
+                    _ => {
+                        picker.on_peer_gone(peer(peer_id));
+                        model.remove(&peer_id);
+                    }
+                }
+            }
+
+            let matches_model = (0..NUM_PIECES as u32).all(|piece| {
+                let expected = model.values().filter(|have| have.contains(&piece)).count();
+
+                picker.availability(piece) as usize == expected
+            });
+
+            TestResult::from_bool(
+                matches_model && is_consistent(&picker) && picker.num_peers() == model.len(),
+            )
+        }
+        QuickCheck::new()
+            .tests(1000)
+            .quickcheck(run as fn(Vec<(u8, u8, u16)>) -> TestResult)
+    }
+
+    #[test]
+    fn positive_pick_is_least_available_candidate() {
+        fn run(haves: Vec<(u8, u16)>, peer_pieces: Vec<u16>, pending: Vec<u16>) -> TestResult {
+            let mut picker = RarestFirstPicker::new(NUM_PIECES);
+            for (peer_id, piece) in haves {
+                picker.on_have(peer(peer_id as u16 % 8), piece as u32 % NUM_PIECES as u32);
+            }
+
+            let peer_pieces: Vec<usize> = peer_pieces
+                .iter()
+                .map(|&piece| piece as usize % NUM_PIECES)
+                .collect();
+            let pending: HashSet<u32> = pending
+                .iter()
+                .map(|&piece| piece as u32 % NUM_PIECES as u32)
+                .collect();
+
+            let peer_bitfield = bitfield(&peer_pieces);
+            let rarest = pending
+                .iter()
+                .filter(|&&piece| peer_bitfield.has_piece(piece as usize))
+                .map(|&piece| picker.availability(piece))
+                .min();
+
+            match (picker.pick(&peer_bitfield, &pending), rarest) {
+                (Some(piece), Some(rarest)) => TestResult::from_bool(
+                    pending.contains(&piece)
+                        && peer_bitfield.has_piece(piece as usize)
+                        && picker.availability(piece) == rarest,
+                ),
+                (None, None) => TestResult::passed(),
+                _ => TestResult::failed(),
+            }
+        }
+        QuickCheck::new()
+            .tests(1000)
+            .quickcheck(run as fn(Vec<(u8, u16)>, Vec<u16>, Vec<u16>) -> TestResult)
+    }
+}
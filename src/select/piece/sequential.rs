@@ -0,0 +1,381 @@
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
+
+use crate::peer::messages::BitFieldMessage;
+use crate::peer::PeerInfo;
+use crate::select::piece::{Availability, PiecePicker, RarestFirstPicker};
+
+const DEFAULT_WINDOW_SIZE: usize = 8;
+const DEFAULT_PIECE_DEADLINE_MILLIS: u64 = 5 * 1000;
+
+/// In order piece selection for streaming.
+///
+/// Pieces inside a window starting at the cursor (the playback position) are picked
+/// in order, everything else falls back to rarest first. A piece picked from inside the
+/// window that has not been completed within the piece deadline is late, and is picked
+/// again, ahead of any other piece, so that it ends up requested from multiple peers.
+///
+/// Pieces are late once the accumulated `tick` durations since they were last picked
+/// reach the deadline; `on_piece_complete` should be called once a piece is downloaded.
+pub struct SequentialPicker {
+    rarest: RarestFirstPicker,
+    cursor: u32,
+    window_size: usize,
+    piece_deadline: Duration,
+    // Pieces picked from inside the window, and how long ago they were picked
+    picked: HashMap<u32, Duration>,
+}
+
+impl SequentialPicker {
+    /// Create a new `SequentialPicker` for a torrent with the given number of pieces.
+    pub fn new(num_pieces: usize) -> SequentialPicker {
+        SequentialPicker::with_fallback(RarestFirstPicker::new(num_pieces))
+    }
+
+    /// Create a new `SequentialPicker` using the given picker outside of the window.
+    pub fn with_fallback(rarest: RarestFirstPicker) -> SequentialPicker {
+        SequentialPicker {
+            rarest: rarest,
+            cursor: 0,
+            window_size: DEFAULT_WINDOW_SIZE,
+            piece_deadline: Duration::from_millis(DEFAULT_PIECE_DEADLINE_MILLIS),
+            picked: HashMap::new(),
+        }
+    }
+
+    /// Sets the number of pieces, starting at the cursor, that are picked in order.
+    pub fn with_window_size(mut self, window_size: usize) -> SequentialPicker {
+        self.window_size = window_size;
+        self
+    }
+
+    /// Sets how long a piece inside the window may take before it is picked again.
+    pub fn with_piece_deadline(mut self, deadline: Duration) -> SequentialPicker {
+        self.piece_deadline = deadline;
+        self
+    }
+
+    /// Number of pieces in the torrent.
+    pub fn num_pieces(&self) -> usize {
+        self.rarest.num_pieces()
+    }
+
+    /// Current playback position.
+    pub fn cursor(&self) -> u32 {
+        self.cursor
+    }
+
+    /// Number of pieces, starting at the cursor, that are picked in order.
+    pub fn window_size(&self) -> usize {
+        self.window_size
+    }
+
+    /// How long a piece inside the window may take before it is picked again.
+    pub fn piece_deadline(&self) -> Duration {
+        self.piece_deadline
+    }
+
+    /// Number of peers that we know have the given piece.
+    pub fn availability(&self, piece: u32) -> u32 {
+        self.rarest.availability(piece)
+    }
+
+    /// Take a snapshot of the availability of every piece, see `Availability`.
+    pub fn availability_snapshot(&self) -> Availability {
+        self.rarest.availability_snapshot()
+    }
+
+    /// Move the playback position to the given piece.
+    ///
+    /// Pieces that fall out of the window are no longer escalated.
+    pub fn set_cursor(&mut self, piece: u32) {
+        self.cursor = piece;
+
+        let window_end = self.window_end();
+        self.picked
+            .retain(|&picked, _| picked >= piece && picked < window_end);
+    }
+
+    /// Record that the given piece has been downloaded, it will no longer be escalated.
+    pub fn on_piece_complete(&mut self, piece: u32) {
+        self.picked.remove(&piece);
+    }
+
+    /// Advance the time since pieces inside the window were picked.
+    pub fn tick(&mut self, elapsed: Duration) {
+        for since_picked in self.picked.values_mut() {
+            *since_picked += elapsed;
+        }
+    }
+
+    /// Record that the given peer has the given piece.
+    pub fn on_have(&mut self, peer: PeerInfo, piece: u32) {
+        self.rarest.on_have(peer, piece)
+    }
+
+    /// Record that the given peer has every piece set in the given bitfield.
+    pub fn on_bitfield(&mut self, peer: PeerInfo, bits: &BitFieldMessage) {
+        self.rarest.on_bitfield(peer, bits)
+    }
+
+    /// Record that the given peer has every piece.
+    pub fn on_have_all(&mut self, peer: PeerInfo) {
+        self.rarest.on_have_all(peer)
+    }
+
+    /// Record that the given peer has no pieces.
+    pub fn on_have_none(&mut self, peer: PeerInfo) {
+        self.rarest.on_have_none(peer)
+    }
+
+    /// Remove the given peer, no longer counting any of its pieces.
+    pub fn on_peer_gone(&mut self, peer: PeerInfo) {
+        self.rarest.on_peer_gone(peer)
+    }
+
+    /// Pick the next piece to request from a peer with the given bitfield.
+    ///
+    /// In order of preference, this is the earliest late piece inside the window, the
+    /// earliest piece in `pending` inside the window, or the rarest piece in `pending`.
+    /// Late pieces do not have to be in `pending`, since they will have been picked before.
+    pub fn pick(&mut self, peer_bitfield: &BitFieldMessage, pending: &HashSet<u32>) -> Option<u32> {
+        let piece_deadline = self.piece_deadline;
+        let late = self
+            .picked
+            .iter()
+            .filter(|&(&piece, &since_picked)| {
+                since_picked >= piece_deadline && peer_bitfield.has_piece(piece as usize)
+            })
+            .map(|(&piece, _)| piece)
+            .min();
+
+        let in_window = || {
+            (self.cursor..self.window_end())
+                .find(|&piece| pending.contains(&piece) && peer_bitfield.has_piece(piece as usize))
+        };
+
+        match late.or_else(in_window) {
+            Some(piece) => {
+                self.picked.insert(piece, Duration::from_millis(0));
+
+                Some(piece)
+            }
+            None => self.rarest.pick(peer_bitfield, pending),
+        }
+    }
+
+    fn window_end(&self) -> u32 {
+        let window_end = self.cursor as u64 + self.window_size as u64;
+
+        window_end.min(self.num_pieces() as u64) as u32
+    }
+}
+
+impl PiecePicker for SequentialPicker {
+    fn on_have(&mut self, peer: PeerInfo, piece: u32) {
+        SequentialPicker::on_have(self, peer, piece)
+    }
+
+    fn on_bitfield(&mut self, peer: PeerInfo, bits: &BitFieldMessage) {
+        SequentialPicker::on_bitfield(self, peer, bits)
+    }
+
+    fn on_have_all(&mut self, peer: PeerInfo) {
+        SequentialPicker::on_have_all(self, peer)
+    }
+
+    fn on_have_none(&mut self, peer: PeerInfo) {
+        SequentialPicker::on_have_none(self, peer)
+    }
+
+    fn on_peer_gone(&mut self, peer: PeerInfo) {
+        SequentialPicker::on_peer_gone(self, peer)
+    }
+
+    fn pick(&mut self, peer_bitfield: &BitFieldMessage, pending: &HashSet<u32>) -> Option<u32> {
+        SequentialPicker::pick(self, peer_bitfield, pending)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SequentialPicker;
+    use crate::handshake::Extensions;
+    use crate::peer::messages::BitFieldMessage;
+    use crate::peer::PeerInfo;
+    use crate::select::piece::{PiecePicker, RarestFirstPicker};
+
+    use std::collections::HashSet;
+    use std::time::Duration;
+
+    const NUM_PIECES: usize = 20;
+    const WINDOW_SIZE: usize = 4;
+    const DEADLINE: Duration = Duration::from_secs(5);
+
+    fn peer(port: u16) -> PeerInfo {
+        PeerInfo::new(
+            format!("127.0.0.1:{}", port).parse().unwrap(),
+            [port as u8; 20].into(),
+            [0u8; 20].into(),
+            Extensions::new(),
+        )
+    }
+
+    fn picker() -> SequentialPicker {
+        let rarest = RarestFirstPicker::new(NUM_PIECES).with_seed([1, 2, 3, 4]);
+
+        SequentialPicker::with_fallback(rarest)
+            .with_window_size(WINDOW_SIZE)
+            .with_piece_deadline(DEADLINE)
+    }
+
+    fn bitfield(pieces: &[usize]) -> BitFieldMessage {
+        BitFieldMessage::from_pieces(NUM_PIECES, pieces.iter().cloned())
+    }
+
+    fn full_bitfield() -> BitFieldMessage {
+        BitFieldMessage::from_pieces(NUM_PIECES, 0..NUM_PIECES)
+    }
+
+    /// Pick a piece and take it out of `pending`, as a caller requesting it would.
+    fn pick(
+        picker: &mut SequentialPicker,
+        peer_bitfield: &BitFieldMessage,
+        pending: &mut HashSet<u32>,
+    ) -> Option<u32> {
+        let piece = picker.pick(peer_bitfield, pending);
+        if let Some(piece) = piece {
+            pending.remove(&piece);
+        }
+
+        piece
+    }
+
+    #[test]
+    fn positive_pick_in_order_as_cursor_advances() {
+        let mut picker = picker();
+        let mut pending: HashSet<u32> = (0..NUM_PIECES as u32).collect();
+
+        picker.on_bitfield(peer(1), &full_bitfield());
+        picker.on_bitfield(peer(2), &full_bitfield());
+
+        let mut requested = Vec::new();
+        for cursor in 0..NUM_PIECES as u32 {
+            picker.set_cursor(cursor);
+
+            // The window is kept full, so advancing by one piece requests one new piece
+            while let Some(piece) = pick(&mut picker, &full_bitfield(), &mut pending) {
+                if piece >= cursor + WINDOW_SIZE as u32 {
+                    pending.insert(piece);
+                    break;
+                }
+                requested.push(piece);
+            }
+            picker.on_piece_complete(cursor);
+        }
+
+        assert_eq!((0..NUM_PIECES as u32).collect::<Vec<u32>>(), requested);
+    }
+
+    #[test]
+    fn positive_pick_rarest_outside_window() {
+        let mut picker = picker();
+        let mut pending: HashSet<u32> = (0..NUM_PIECES as u32).collect();
+
+        picker.on_bitfield(peer(1), &full_bitfield());
+        picker.on_have(peer(2), 0);
+        picker.on_have(peer(2), 1);
+        picker.on_bitfield(peer(3), &bitfield(&[0, 1, 2, 3, 10]));
+
+        for piece in 0..WINDOW_SIZE as u32 {
+            assert_eq!(
+                Some(piece),
+                pick(&mut picker, &full_bitfield(), &mut pending)
+            );
+        }
+
+        // Every piece past the window except 10 is only available from a single peer
+        let piece = pick(&mut picker, &full_bitfield(), &mut pending).unwrap();
+        assert!(piece >= WINDOW_SIZE as u32);
+        assert_eq!(1, picker.availability(piece));
+    }
+
+    #[test]
+    fn positive_pick_window_pieces_peer_has() {
+        let mut picker = picker();
+        let mut pending: HashSet<u32> = (0..NUM_PIECES as u32).collect();
+
+        picker.on_bitfield(peer(1), &bitfield(&[2, 15]));
+
+        assert_eq!(
+            Some(2),
+            pick(&mut picker, &bitfield(&[2, 15]), &mut pending)
+        );
+        assert_eq!(
+            Some(15),
+            pick(&mut picker, &bitfield(&[2, 15]), &mut pending)
+        );
+        assert_eq!(None, pick(&mut picker, &bitfield(&[2, 15]), &mut pending));
+    }
+
+    #[test]
+    fn positive_late_piece_picked_again() {
+        let mut picker = picker();
+        let mut pending: HashSet<u32> = (0..NUM_PIECES as u32).collect();
+
+        assert_eq!(Some(0), pick(&mut picker, &full_bitfield(), &mut pending));
+        assert_eq!(Some(1), pick(&mut picker, &full_bitfield(), &mut pending));
+        picker.on_piece_complete(0);
+
+        picker.tick(DEADLINE - Duration::from_millis(1));
+        assert_eq!(Some(2), pick(&mut picker, &full_bitfield(), &mut pending));
+
+        picker.tick(Duration::from_millis(1));
+        assert_eq!(Some(1), pick(&mut picker, &full_bitfield(), &mut pending));
+
+        // Escalating restarts the deadline
+        assert_eq!(Some(3), pick(&mut picker, &full_bitfield(), &mut pending));
+    }
+
+    #[test]
+    fn negative_late_piece_outside_window_not_picked_again() {
+        let mut picker = picker();
+        let mut pending: HashSet<u32> = (0..NUM_PIECES as u32).collect();
+
+        assert_eq!(Some(0), pick(&mut picker, &full_bitfield(), &mut pending));
+        picker.tick(DEADLINE);
+        picker.set_cursor(1);
+
+        assert_eq!(Some(1), pick(&mut picker, &full_bitfield(), &mut pending));
+    }
+
+    #[test]
+    fn negative_late_piece_peer_does_not_have() {
+        let mut picker = picker();
+        let mut pending: HashSet<u32> = (0..NUM_PIECES as u32).collect();
+
+        assert_eq!(Some(0), pick(&mut picker, &full_bitfield(), &mut pending));
+        picker.tick(DEADLINE);
+
+        assert_eq!(Some(1), pick(&mut picker, &bitfield(&[1]), &mut pending));
+        assert_eq!(Some(0), pick(&mut picker, &full_bitfield(), &mut pending));
+    }
+
+    #[test]
+    fn positive_interchangeable_with_rarest_first() {
+        let mut pickers: Vec<Box<dyn PiecePicker>> = vec![
+            Box::new(RarestFirstPicker::new(NUM_PIECES)),
+            Box::new(SequentialPicker::new(NUM_PIECES)),
+        ];
+        let pending: HashSet<u32> = (0..NUM_PIECES as u32).collect();
+
+        for picker in pickers.iter_mut() {
+            picker.on_bitfield(peer(1), &bitfield(&[5]));
+            picker.on_have(peer(1), 6);
+            picker.on_peer_gone(peer(1));
+
+            let piece = picker.pick(&bitfield(&[5]), &pending);
+            assert_eq!(Some(5), piece);
+        }
+    }
+}
@@ -0,0 +1,4 @@
+//! Module for pipelining block requests to peers.
+
+mod queue;
+pub use self::queue::{ReceivedBlock, RequestEvent, RequestQueue};
@@ -0,0 +1,1158 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::time::Duration;
+
+use crate::disk::TorrentMode;
+use crate::handshake::Extension;
+use crate::peer::messages::{
+    CancelMessage, ExtendedMessage, PeerWireProtocolMessage, PieceMessage, RejectMessage,
+    RequestMessage,
+};
+use crate::peer::PeerInfo;
+
+const DEFAULT_QUEUE_SIZE: usize = 250;
+const DEFAULT_REQUEST_TIMEOUT_MILLIS: u64 = 30 * 1000;
+const DEFAULT_SNUB_TIMEOUT_MILLIS: u64 = 60 * 1000;
+/// Number of requests a snubbed peer is allowed, so it can still clear its name.
+const SNUBBED_QUEUE_SIZE: usize = 1;
+
+/// Outcome of receiving a block from a peer.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ReceivedBlock {
+    /// Block was needed, and should be written out.
+    New,
+    /// Block was already received, or was never queued, and should be discarded.
+    Duplicate,
+}
+
+/// Events emitted by a `RequestQueue`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum RequestEvent {
+    /// Number of missing blocks dropped below the endgame threshold.
+    EndgameStarted { missing_blocks: usize },
+    /// Number of missing blocks reached the endgame threshold, or every block was received.
+    EndgameStopped { missing_blocks: usize },
+    /// Peer has not sent us any block within the snub timeout, while it had requests outstanding.
+    PeerSnubbed { peer: PeerInfo },
+    /// Snubbed peer has sent us a block.
+    PeerUnsnubbed { peer: PeerInfo },
+}
+
+/// Keeps a pipeline of outstanding block requests to each peer.
+///
+/// Blocks to download are added with `add_blocks`, and handed out to unchoked peers by
+/// `fill_requests`, up to the number of outstanding requests each peer supports (their
+/// `reqq`). Blocks that are rejected, time out, or that were requested from a peer that
+/// chokes us, are requeued so they can be requested from another peer.
+///
+/// Normally a block is only ever outstanding to a single peer. Once the number of blocks
+/// that have not been received drops below the endgame threshold (by default, the combined
+/// pipeline depth of unchoked peers), we enter endgame mode: once no blocks are left to
+/// hand out, blocks outstanding to other peers are duplicated; whichever peer sends the
+/// block first wins, and the other peers are sent a cancel. Blocks that arrive anyway are
+/// counted as wasted bytes.
+///
+/// Unchoked peers that have requests outstanding, but do not send us a single block within
+/// the snub timeout, are marked as snubbed. Snubbed peers are only given a single request at a
+/// time, until a block arrives from them.
+///
+/// A queue for a `TorrentMode::SeedOnly` torrent never requests blocks, and is never
+/// interested in any peer, so the torrent is only seeded.
+///
+/// Messages to send out are retrieved via `poll`, endgame and snub transitions via `poll_event`.
+pub struct RequestQueue {
+    mode: TorrentMode,
+    default_queue_size: usize,
+    request_timeout: Duration,
+    snub_timeout: Duration,
+    endgame_threshold: Option<usize>,
+    endgame: bool,
+    wasted_bytes: u64,
+    pending: VecDeque<RequestMessage>,
+    pending_set: HashSet<RequestMessage>,
+    // Peers each outstanding block is requested from
+    in_flight: HashMap<RequestMessage, HashSet<PeerInfo>>,
+    peers: HashMap<PeerInfo, PeerRequests>,
+    out_queue: VecDeque<(PeerInfo, PeerWireProtocolMessage)>,
+    events: VecDeque<RequestEvent>,
+}
+
+struct PeerRequests {
+    choked: bool,
+    snubbed: bool,
+    // Time spent unchoked with requests outstanding, since the last block
+    since_block: Duration,
+    queue_size: usize,
+    requests: Vec<ActiveRequest>,
+}
+
+impl PeerRequests {
+    fn pipeline_len(&self) -> usize {
+        if self.snubbed {
+            self.queue_size.min(SNUBBED_QUEUE_SIZE)
+        } else {
+            self.queue_size
+        }
+    }
+}
+
+struct ActiveRequest {
+    block: RequestMessage,
+    left: Duration,
+}
+
+impl RequestQueue {
+    /// Create a new `RequestQueue`.
+    pub fn new() -> RequestQueue {
+        RequestQueue {
+            mode: TorrentMode::Download,
+            default_queue_size: DEFAULT_QUEUE_SIZE,
+            request_timeout: Duration::from_millis(DEFAULT_REQUEST_TIMEOUT_MILLIS),
+            snub_timeout: Duration::from_millis(DEFAULT_SNUB_TIMEOUT_MILLIS),
+            endgame_threshold: None,
+            endgame: false,
+            wasted_bytes: 0,
+            pending: VecDeque::new(),
+            pending_set: HashSet::new(),
+            in_flight: HashMap::new(),
+            peers: HashMap::new(),
+            out_queue: VecDeque::new(),
+            events: VecDeque::new(),
+        }
+    }
+
+    /// Sets whether the torrent is downloaded or only seeded.
+    pub fn with_torrent_mode(mut self, mode: TorrentMode) -> RequestQueue {
+        self.set_torrent_mode(mode);
+        self
+    }
+
+    /// Sets the number of outstanding requests for peers that do not advertise a `reqq`.
+    pub fn with_default_queue_size(mut self, size: usize) -> RequestQueue {
+        self.default_queue_size = size;
+        self
+    }
+
+    /// Sets the duration after which an outstanding request is cancelled and requeued.
+    pub fn with_request_timeout(mut self, timeout: Duration) -> RequestQueue {
+        self.request_timeout = timeout;
+        self
+    }
+
+    /// Sets the duration without receiving a block after which a peer is marked as snubbed.
+    pub fn with_snub_timeout(mut self, timeout: Duration) -> RequestQueue {
+        self.snub_timeout = timeout;
+        self
+    }
+
+    /// Sets a fixed number of missing blocks below which we enter endgame mode.
+    ///
+    /// A threshold of zero disables endgame mode.
+    pub fn with_endgame_threshold(mut self, threshold: usize) -> RequestQueue {
+        self.endgame_threshold = Some(threshold);
+        self
+    }
+
+    /// Number of missing blocks below which we enter endgame mode.
+    pub fn endgame_threshold(&self) -> usize {
+        self.endgame_threshold.unwrap_or_else(|| {
+            self.peers
+                .values()
+                .filter(|peer| !peer.choked)
+                .map(|peer| peer.pipeline_len())
+                .sum()
+        })
+    }
+
+    /// Whether the torrent is downloaded or only seeded.
+    pub fn torrent_mode(&self) -> TorrentMode {
+        self.mode
+    }
+
+    /// Change whether the torrent is downloaded or only seeded, following the disk manager.
+    ///
+    /// Switching to `TorrentMode::SeedOnly` drops every block waiting to be requested, and
+    /// cancels every outstanding request. Blocks have to be added again after switching back.
+    pub fn set_torrent_mode(&mut self, mode: TorrentMode) {
+        self.mode = mode;
+        if !mode.is_seed_only() {
+            return;
+        }
+
+        for (info, peer) in self.peers.iter_mut() {
+            for request in peer.requests.drain(..) {
+                self.out_queue
+                    .push_back((*info, cancel_message(&request.block)));
+            }
+        }
+        self.pending.clear();
+        self.pending_set.clear();
+        self.in_flight.clear();
+        self.update_endgame();
+    }
+
+    /// Whether or not we are interested in the given peer, as it has a block we still need.
+    ///
+    /// Only blocks in pieces for which `has_piece` returns true count. We are never
+    /// interested in peers of a `TorrentMode::SeedOnly` torrent.
+    pub fn is_interested<F>(&self, has_piece: F) -> bool
+    where
+        F: Fn(u32) -> bool,
+    {
+        !self.mode.is_seed_only()
+            && self
+                .pending
+                .iter()
+                .chain(self.in_flight.keys())
+                .any(|block| has_piece(block.piece_index()))
+    }
+
+    /// Duration without receiving a block after which a peer is marked as snubbed.
+    pub fn snub_timeout(&self) -> Duration {
+        self.snub_timeout
+    }
+
+    /// Whether or not the given peer is snubbing us.
+    pub fn is_snubbed(&self, info: &PeerInfo) -> bool {
+        self.peers.get(info).map_or(false, |peer| peer.snubbed)
+    }
+
+    /// Whether or not outstanding blocks may be requested from multiple peers.
+    pub fn is_endgame(&self) -> bool {
+        self.endgame
+    }
+
+    /// Number of blocks waiting to be requested.
+    pub fn num_pending(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Number of blocks that have not been received yet, whether requested or not.
+    pub fn num_missing(&self) -> usize {
+        self.pending.len() + self.in_flight.len()
+    }
+
+    /// Number of bytes received in blocks that were not needed.
+    pub fn wasted_bytes(&self) -> u64 {
+        self.wasted_bytes
+    }
+
+    /// Number of requests outstanding to the given peer.
+    pub fn num_in_flight(&self, info: &PeerInfo) -> usize {
+        self.peers.get(info).map_or(0, |peer| peer.requests.len())
+    }
+
+    /// Add blocks that we want to download.
+    ///
+    /// Blocks that are already pending or outstanding are ignored. Blocks that were already
+    /// received are downloaded again, for when a piece fails its hash check. Blocks of a
+    /// `TorrentMode::SeedOnly` torrent are ignored as well.
+    pub fn add_blocks<I>(&mut self, blocks: I)
+    where
+        I: IntoIterator<Item = RequestMessage>,
+    {
+        if self.mode.is_seed_only() {
+            return;
+        }
+
+        for block in blocks {
+            let queued = self.in_flight.contains_key(&block) || self.pending_set.contains(&block);
+
+            if !queued {
+                self.pending_set.insert(block);
+                self.pending.push_back(block);
+            }
+        }
+        self.update_endgame();
+    }
+
+    /// Add a peer, which starts out choking us.
+    pub fn add_peer(&mut self, info: PeerInfo) {
+        let default_queue_size = self.default_queue_size;
+
+        self.peers.entry(info).or_insert_with(|| PeerRequests {
+            choked: true,
+            snubbed: false,
+            since_block: Duration::from_millis(0),
+            queue_size: default_queue_size,
+            requests: Vec::new(),
+        });
+    }
+
+    /// Remove a peer, requeueing any requests outstanding to it.
+    pub fn remove_peer(&mut self, info: &PeerInfo) {
+        if let Some(peer) = self.peers.remove(info) {
+            for request in peer.requests {
+                self.release(info, request.block);
+            }
+        }
+        self.out_queue.retain(|&(peer, _)| peer != *info);
+        self.update_endgame();
+    }
+
+    /// Update the number of outstanding requests the peer supports from their `ExtendedMessage`.
+    pub fn on_extended(&mut self, info: &PeerInfo, extended: &ExtendedMessage) {
+        let queue_size = extended
+            .request_queue_size()
+            .map(|size| size as usize)
+            .unwrap_or(self.default_queue_size);
+
+        if let Some(peer) = self.peers.get_mut(info) {
+            peer.queue_size = queue_size;
+        }
+        self.update_endgame();
+    }
+
+    /// Peer has choked us.
+    ///
+    /// Without the fast extension, the peer discards our requests, so they are requeued.
+    /// With the fast extension, requests stay outstanding until the peer rejects them.
+    pub fn on_choke(&mut self, info: &PeerInfo) {
+        let discarded = match self.peers.get_mut(info) {
+            Some(peer) => {
+                peer.choked = true;
+
+                if info.extensions().contains(Extension::FastExtension) {
+                    Vec::new()
+                } else {
+                    peer.requests
+                        .drain(..)
+                        .map(|request| request.block)
+                        .collect()
+                }
+            }
+            None => return,
+        };
+
+        for block in discarded {
+            self.release(info, block);
+        }
+        self.update_endgame();
+    }
+
+    /// Peer has unchoked us, call `fill_requests` to start requesting blocks.
+    pub fn on_unchoke(&mut self, info: &PeerInfo) {
+        if let Some(peer) = self.peers.get_mut(info) {
+            peer.choked = false;
+        }
+        self.update_endgame();
+    }
+
+    /// Peer has sent us a block.
+    ///
+    /// Other peers that the block is outstanding to are sent a cancel. Blocks that
+    /// are no longer needed, such as those arriving after a cancel, count as wasted bytes.
+    /// Any block clears the peer of snubbing us.
+    pub fn on_piece(&mut self, info: &PeerInfo, piece: &PieceMessage) -> ReceivedBlock {
+        let block = RequestMessage::new(
+            piece.piece_index(),
+            piece.block_offset(),
+            piece.block_length(),
+        );
+
+        if let Some(peer) = self.peers.get_mut(info) {
+            peer.requests.retain(|request| request.block != block);
+            peer.since_block = Duration::from_millis(0);
+
+            if peer.snubbed {
+                peer.snubbed = false;
+                self.events
+                    .push_back(RequestEvent::PeerUnsnubbed { peer: *info });
+            }
+        }
+
+        let was_pending = self.pending_set.remove(&block);
+        if was_pending {
+            self.pending.retain(|pending| *pending != block);
+        }
+        let requested_from = self.in_flight.remove(&block);
+
+        if !was_pending && requested_from.is_none() {
+            self.wasted_bytes += piece.block_length() as u64;
+
+            return ReceivedBlock::Duplicate;
+        }
+
+        for other in requested_from
+            .into_iter()
+            .flatten()
+            .filter(|other| other != info)
+        {
+            if let Some(peer) = self.peers.get_mut(&other) {
+                peer.requests.retain(|request| request.block != block);
+            }
+
+            self.out_queue.push_back((other, cancel_message(&block)));
+        }
+        self.update_endgame();
+
+        ReceivedBlock::New
+    }
+
+    /// Peer has rejected one of our requests, the block is requeued.
+    pub fn on_reject(&mut self, info: &PeerInfo, reject: &RejectMessage) {
+        let block = RequestMessage::new(
+            reject.piece_index(),
+            reject.block_offset(),
+            reject.block_length(),
+        );
+
+        let had_request = self.peers.get_mut(info).map_or(false, |peer| {
+            let num_requests = peer.requests.len();
+            peer.requests.retain(|request| request.block != block);
+
+            peer.requests.len() != num_requests
+        });
+
+        if had_request {
+            self.release(info, block);
+        }
+    }
+
+    /// Apply the given duration to outstanding requests.
+    ///
+    /// Expired requests are cancelled and requeued, and peers that have gone
+    /// the snub timeout without sending a block are marked as snubbed.
+    pub fn tick(&mut self, duration: Duration) {
+        let mut expired = Vec::new();
+
+        for (info, peer) in self.peers.iter_mut() {
+            if !peer.choked && !peer.requests.is_empty() {
+                peer.since_block += duration;
+            }
+            if !peer.snubbed && peer.since_block >= self.snub_timeout {
+                info!("bittorrent-protocol_select: Peer {:?} Is Snubbing Us", info);
+                peer.snubbed = true;
+                self.events
+                    .push_back(RequestEvent::PeerSnubbed { peer: *info });
+            }
+
+            peer.requests.retain(|request| {
+                if request.left <= duration {
+                    expired.push((*info, request.block));
+                    false
+                } else {
+                    true
+                }
+            });
+
+            for request in peer.requests.iter_mut() {
+                request.left -= duration;
+            }
+        }
+
+        for (info, block) in expired {
+            self.out_queue.push_back((info, cancel_message(&block)));
+            self.release(&info, block);
+        }
+        // Snubbed peers shrink the pipeline, and with it the endgame threshold
+        self.update_endgame();
+    }
+
+    /// Request blocks from the given peer until its pipeline is full.
+    ///
+    /// Only blocks in pieces for which `has_piece` returns true are requested.
+    /// Returns the number of requests that were queued up.
+    pub fn fill_requests<F>(&mut self, info: &PeerInfo, has_piece: F) -> usize
+    where
+        F: Fn(u32) -> bool,
+    {
+        let capacity = match self.peers.get(info) {
+            _ if self.mode.is_seed_only() => return 0,
+            Some(peer) if !peer.choked => peer.pipeline_len().saturating_sub(peer.requests.len()),
+            _ => return 0,
+        };
+
+        let mut blocks = Vec::new();
+        let mut index = 0;
+        while blocks.len() < capacity && index < self.pending.len() {
+            if has_piece(self.pending[index].piece_index()) {
+                let block = self.pending.remove(index).unwrap();
+
+                self.pending_set.remove(&block);
+                blocks.push(block);
+            } else {
+                index += 1;
+            }
+        }
+
+        if self.endgame && blocks.len() < capacity {
+            let peer_requests = &self.peers[info].requests;
+            let mut duplicates: Vec<RequestMessage> = self
+                .in_flight
+                .iter()
+                .filter(|&(block, peers)| {
+                    !peers.contains(info)
+                        && has_piece(block.piece_index())
+                        && !peer_requests.iter().any(|request| request.block == *block)
+                })
+                .map(|(block, _)| *block)
+                .collect();
+            // Request duplicates in a predictable order, regardless of hashing
+            duplicates.sort_by_key(|block| (block.piece_index(), block.block_offset()));
+            duplicates.truncate(capacity - blocks.len());
+
+            blocks.extend(duplicates);
+        }
+
+        let request_timeout = self.request_timeout;
+        let peer = self.peers.get_mut(info).unwrap();
+        for &block in blocks.iter() {
+            peer.requests.push(ActiveRequest {
+                block: block,
+                left: request_timeout,
+            });
+            self.in_flight
+                .entry(block)
+                .or_insert_with(HashSet::new)
+                .insert(*info);
+            self.out_queue
+                .push_back((*info, PeerWireProtocolMessage::Request(block)));
+        }
+
+        blocks.len()
+    }
+
+    /// Retrieve the next message to send to a peer.
+    pub fn poll(&mut self) -> Option<(PeerInfo, PeerWireProtocolMessage)> {
+        self.out_queue.pop_front()
+    }
+
+    /// Retrieve the next endgame or snub transition.
+    pub fn poll_event(&mut self) -> Option<RequestEvent> {
+        self.events.pop_front()
+    }
+
+    /// Enter or leave endgame mode, based on the number of missing blocks.
+    fn update_endgame(&mut self) {
+        let missing_blocks = self.num_missing();
+        let endgame = missing_blocks != 0 && missing_blocks < self.endgame_threshold();
+
+        if endgame == self.endgame {
+            return;
+        }
+        self.endgame = endgame;
+
+        if endgame {
+            info!(
+                "bittorrent-protocol_select: Entering Endgame With {} Missing Blocks",
+                missing_blocks
+            );
+            self.events.push_back(RequestEvent::EndgameStarted {
+                missing_blocks: missing_blocks,
+            });
+        } else {
+            info!(
+                "bittorrent-protocol_select: Leaving Endgame With {} Missing Blocks",
+                missing_blocks
+            );
+            self.events.push_back(RequestEvent::EndgameStopped {
+                missing_blocks: missing_blocks,
+            });
+        }
+    }
+
+    /// Mark the block as no longer outstanding to the given peer.
+    ///
+    /// If no other peer has the block outstanding, it goes to the front of the queue.
+    fn release(&mut self, info: &PeerInfo, block: RequestMessage) {
+        let now_idle = match self.in_flight.get_mut(&block) {
+            Some(peers) => {
+                peers.remove(info);
+                peers.is_empty()
+            }
+            None => false,
+        };
+
+        if now_idle {
+            self.in_flight.remove(&block);
+            self.pending_set.insert(block);
+            self.pending.push_front(block);
+        }
+    }
+}
+
+fn cancel_message(block: &RequestMessage) -> PeerWireProtocolMessage {
+    PeerWireProtocolMessage::Cancel(CancelMessage::new(
+        block.piece_index(),
+        block.block_offset(),
+        block.block_length(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ReceivedBlock, RequestEvent, RequestQueue};
+    use crate::disk::TorrentMode;
+    use crate::handshake::{Extension, Extensions};
+    use crate::peer::messages::builders::ExtendedMessageBuilder;
+    use crate::peer::messages::{
+        CancelMessage, PeerWireProtocolMessage, PieceMessage, RejectMessage, RequestMessage,
+    };
+    use crate::peer::PeerInfo;
+
+    use bytes::Bytes;
+    use std::collections::HashMap;
+    use std::time::Duration;
+
+    const BLOCK_LEN: usize = 16 * 1024;
+
+    fn peer_with(port: u16, extensions: Extensions) -> PeerInfo {
+        PeerInfo::new(
+            format!("127.0.0.1:{}", port).parse().unwrap(),
+            [port as u8; 20].into(),
+            [0u8; 20].into(),
+            extensions,
+        )
+    }
+
+    fn peer(port: u16) -> PeerInfo {
+        peer_with(port, Extensions::new())
+    }
+
+    fn block(piece_index: u32, block: u32) -> RequestMessage {
+        RequestMessage::new(piece_index, block * BLOCK_LEN as u32, BLOCK_LEN)
+    }
+
+    fn piece_for(block: &RequestMessage) -> PieceMessage {
+        PieceMessage::new(
+            block.piece_index(),
+            block.block_offset(),
+            Bytes::from(vec![0u8; block.block_length()]),
+        )
+    }
+
+    fn cancel_for(block: &RequestMessage) -> PeerWireProtocolMessage {
+        PeerWireProtocolMessage::Cancel(CancelMessage::new(
+            block.piece_index(),
+            block.block_offset(),
+            block.block_length(),
+        ))
+    }
+
+    fn drain(queue: &mut RequestQueue) -> Vec<(PeerInfo, PeerWireProtocolMessage)> {
+        let mut messages = Vec::new();
+        while let Some(message) = queue.poll() {
+            messages.push(message);
+        }
+
+        messages
+    }
+
+    fn unchoked_queue(peers: &[PeerInfo], queue_size: usize) -> RequestQueue {
+        let mut queue = RequestQueue::new().with_default_queue_size(queue_size);
+
+        for info in peers {
+            queue.add_peer(*info);
+            queue.on_unchoke(info);
+        }
+
+        queue
+    }
+
+    #[test]
+    fn positive_fill_caps_at_queue_size() {
+        let info = peer(1);
+        let mut queue = unchoked_queue(&[info], 2);
+        queue.add_blocks((0..4).map(|index| block(0, index)));
+
+        assert_eq!(2, queue.fill_requests(&info, |_| true));
+        assert_eq!(0, queue.fill_requests(&info, |_| true));
+
+        assert_eq!(
+            vec![
+                (info, PeerWireProtocolMessage::Request(block(0, 0))),
+                (info, PeerWireProtocolMessage::Request(block(0, 1)))
+            ],
+            drain(&mut queue)
+        );
+        assert_eq!(2, queue.num_pending());
+    }
+
+    #[test]
+    fn positive_fill_uses_advertised_reqq() {
+        let info = peer(1);
+        let mut queue = unchoked_queue(&[info], 2);
+        queue.add_blocks((0..10).map(|index| block(0, index)));
+
+        let extended = ExtendedMessageBuilder::new()
+            .with_request_queue_size(5)
+            .build();
+        queue.on_extended(&info, &extended);
+
+        assert_eq!(5, queue.fill_requests(&info, |_| true));
+    }
+
+    #[test]
+    fn positive_default_queue_size() {
+        let info = peer(1);
+        let mut queue = RequestQueue::new();
+        queue.add_peer(info);
+        queue.on_unchoke(&info);
+        queue.add_blocks((0..300).map(|index| block(0, index)));
+
+        assert_eq!(250, queue.fill_requests(&info, |_| true));
+    }
+
+    #[test]
+    fn positive_fill_only_pieces_peer_has() {
+        let info = peer(1);
+        let mut queue = unchoked_queue(&[info], 10);
+        queue.add_blocks(vec![block(0, 0), block(1, 0), block(0, 1)]);
+
+        assert_eq!(1, queue.fill_requests(&info, |piece| piece == 1));
+        assert_eq!(2, queue.num_pending());
+    }
+
+    #[test]
+    fn negative_fill_while_choked() {
+        let info = peer(1);
+        let mut queue = RequestQueue::new();
+        queue.add_peer(info);
+        queue.add_blocks(vec![block(0, 0)]);
+
+        assert_eq!(0, queue.fill_requests(&info, |_| true));
+    }
+
+    #[test]
+    fn positive_choke_requeues_without_fast_extension() {
+        let info = peer(1);
+        let mut queue = unchoked_queue(&[info], 10);
+        queue.add_blocks(vec![block(0, 0), block(0, 1)]);
+        queue.fill_requests(&info, |_| true);
+
+        queue.on_choke(&info);
+
+        assert_eq!(0, queue.num_in_flight(&info));
+        assert_eq!(2, queue.num_pending());
+    }
+
+    #[test]
+    fn positive_choke_keeps_requests_with_fast_extension() {
+        let mut extensions = Extensions::new();
+        extensions.add(Extension::FastExtension);
+        let info = peer_with(1, extensions);
+
+        let mut queue = unchoked_queue(&[info], 10);
+        queue.add_blocks(vec![block(0, 0), block(0, 1)]);
+        queue.fill_requests(&info, |_| true);
+
+        queue.on_choke(&info);
+        assert_eq!(2, queue.num_in_flight(&info));
+
+        let rejected = block(0, 1);
+        queue.on_reject(
+            &info,
+            &RejectMessage::new(0, rejected.block_offset(), BLOCK_LEN),
+        );
+        assert_eq!(1, queue.num_in_flight(&info));
+        assert_eq!(1, queue.num_pending());
+    }
+
+    #[test]
+    fn positive_rejected_block_requested_from_other_peer() {
+        let (first, second) = (peer(1), peer(2));
+        let mut queue = unchoked_queue(&[first, second], 10);
+        queue.add_blocks(vec![block(0, 0)]);
+        queue.fill_requests(&first, |_| true);
+
+        queue.on_reject(&first, &RejectMessage::new(0, 0, BLOCK_LEN));
+        drain(&mut queue);
+
+        assert_eq!(1, queue.fill_requests(&second, |_| true));
+        assert_eq!(
+            vec![(second, PeerWireProtocolMessage::Request(block(0, 0)))],
+            drain(&mut queue)
+        );
+    }
+
+    #[test]
+    fn positive_timeout_cancels_and_requeues() {
+        let info = peer(1);
+        let mut queue = unchoked_queue(&[info], 10).with_request_timeout(Duration::from_secs(5));
+        queue.add_blocks(vec![block(0, 0)]);
+        queue.fill_requests(&info, |_| true);
+        drain(&mut queue);
+
+        queue.tick(Duration::from_secs(4));
+        assert!(drain(&mut queue).is_empty());
+        queue.tick(Duration::from_secs(1));
+
+        assert_eq!(vec![(info, cancel_for(&block(0, 0)))], drain(&mut queue));
+        assert_eq!(0, queue.num_in_flight(&info));
+        assert_eq!(1, queue.num_pending());
+    }
+
+    #[test]
+    fn positive_late_block_after_timeout_is_accepted() {
+        let info = peer(1);
+        let mut queue = unchoked_queue(&[info], 10).with_request_timeout(Duration::from_secs(5));
+        queue.add_blocks(vec![block(0, 0)]);
+        queue.fill_requests(&info, |_| true);
+        queue.tick(Duration::from_secs(5));
+
+        assert_eq!(
+            ReceivedBlock::New,
+            queue.on_piece(&info, &piece_for(&block(0, 0)))
+        );
+        assert_eq!(0, queue.num_pending());
+    }
+
+    #[test]
+    fn positive_duplicate_block_suppressed() {
+        let info = peer(1);
+        let mut queue = unchoked_queue(&[info], 10);
+        queue.add_blocks(vec![block(0, 0)]);
+        queue.fill_requests(&info, |_| true);
+
+        let piece = piece_for(&block(0, 0));
+        assert_eq!(ReceivedBlock::New, queue.on_piece(&info, &piece));
+        assert_eq!(ReceivedBlock::Duplicate, queue.on_piece(&info, &piece));
+        assert_eq!(0, queue.num_in_flight(&info));
+    }
+
+    #[test]
+    fn positive_add_blocks_ignores_queued_blocks() {
+        let info = peer(1);
+        let mut queue = unchoked_queue(&[info], 1);
+        queue.add_blocks(vec![block(0, 0), block(0, 1)]);
+        queue.fill_requests(&info, |_| true);
+
+        queue.add_blocks(vec![block(0, 0), block(0, 1)]);
+
+        assert_eq!(1, queue.num_pending());
+    }
+
+    #[test]
+    fn positive_add_blocks_redownloads_completed_block() {
+        let info = peer(1);
+        let mut queue = unchoked_queue(&[info], 10);
+        queue.add_blocks(vec![block(0, 0)]);
+        queue.fill_requests(&info, |_| true);
+        queue.on_piece(&info, &piece_for(&block(0, 0)));
+
+        // Piece failed its hash check
+        queue.add_blocks(vec![block(0, 0)]);
+
+        assert_eq!(1, queue.num_pending());
+    }
+
+    #[test]
+    fn negative_no_duplicate_requests_outside_endgame() {
+        let (first, second) = (peer(1), peer(2));
+        let mut queue = unchoked_queue(&[first, second], 10).with_endgame_threshold(0);
+        queue.add_blocks(vec![block(0, 0)]);
+        queue.fill_requests(&first, |_| true);
+
+        assert!(!queue.is_endgame());
+        assert_eq!(0, queue.fill_requests(&second, |_| true));
+    }
+
+    #[test]
+    fn positive_endgame_duplicates_and_cancels() {
+        let (first, second, third) = (peer(1), peer(2), peer(3));
+        let mut queue = unchoked_queue(&[first, second, third], 10);
+        queue.add_blocks(vec![block(0, 0), block(0, 1)]);
+        queue.fill_requests(&first, |_| true);
+        drain(&mut queue);
+
+        assert!(queue.is_endgame());
+        assert_eq!(2, queue.fill_requests(&second, |_| true));
+        assert_eq!(2, queue.fill_requests(&third, |_| true));
+        // Blocks are only ever requested once from the same peer
+        assert_eq!(0, queue.fill_requests(&third, |_| true));
+        drain(&mut queue);
+
+        assert_eq!(
+            ReceivedBlock::New,
+            queue.on_piece(&second, &piece_for(&block(0, 1)))
+        );
+        let mut cancels = drain(&mut queue);
+        cancels.sort_by_key(|&(info, _)| info.addr().port());
+        assert_eq!(
+            vec![
+                (first, cancel_for(&block(0, 1))),
+                (third, cancel_for(&block(0, 1)))
+            ],
+            cancels
+        );
+
+        assert_eq!(
+            ReceivedBlock::Duplicate,
+            queue.on_piece(&third, &piece_for(&block(0, 1)))
+        );
+        assert_eq!(BLOCK_LEN as u64, queue.wasted_bytes());
+        assert_eq!(1, queue.num_in_flight(&first));
+        assert_eq!(1, queue.num_in_flight(&third));
+    }
+
+    #[test]
+    fn positive_endgame_threshold_defaults_to_unchoked_pipelines() {
+        let (first, second) = (peer(1), peer(2));
+        let mut queue = unchoked_queue(&[first], 2);
+        queue.add_peer(second);
+
+        assert_eq!(2, queue.endgame_threshold());
+        queue.on_unchoke(&second);
+        assert_eq!(4, queue.endgame_threshold());
+
+        let extended = ExtendedMessageBuilder::new()
+            .with_request_queue_size(5)
+            .build();
+        queue.on_extended(&second, &extended);
+        assert_eq!(7, queue.endgame_threshold());
+    }
+
+    #[test]
+    fn positive_endgame_transitions_emit_events() {
+        let info = peer(1);
+        let mut queue = RequestQueue::new().with_endgame_threshold(3);
+        queue.add_peer(info);
+        queue.on_unchoke(&info);
+
+        queue.add_blocks((0..3).map(|index| block(0, index)));
+        assert_eq!(None, queue.poll_event());
+        queue.fill_requests(&info, |_| true);
+
+        queue.on_piece(&info, &piece_for(&block(0, 0)));
+        assert_eq!(
+            Some(RequestEvent::EndgameStarted { missing_blocks: 2 }),
+            queue.poll_event()
+        );
+        assert!(queue.is_endgame());
+
+        // Piece failed its hash check
+        queue.add_blocks((0..3).map(|index| block(1, index)));
+        assert_eq!(
+            Some(RequestEvent::EndgameStopped { missing_blocks: 5 }),
+            queue.poll_event()
+        );
+        assert!(!queue.is_endgame());
+        assert_eq!(None, queue.poll_event());
+    }
+
+    #[test]
+    fn positive_endgame_stops_once_every_block_received() {
+        let info = peer(1);
+        let mut queue = unchoked_queue(&[info], 10);
+        queue.add_blocks(vec![block(0, 0)]);
+        queue.fill_requests(&info, |_| true);
+
+        queue.on_piece(&info, &piece_for(&block(0, 0)));
+
+        assert_eq!(
+            vec![
+                RequestEvent::EndgameStarted { missing_blocks: 1 },
+                RequestEvent::EndgameStopped { missing_blocks: 0 }
+            ],
+            vec![queue.poll_event().unwrap(), queue.poll_event().unwrap()]
+        );
+    }
+
+    #[test]
+    fn positive_unrequested_block_is_wasted() {
+        let info = peer(1);
+        let mut queue = unchoked_queue(&[info], 10);
+
+        let piece = piece_for(&block(0, 0));
+        assert_eq!(ReceivedBlock::Duplicate, queue.on_piece(&info, &piece));
+        assert_eq!(BLOCK_LEN as u64, queue.wasted_bytes());
+    }
+
+    // Each peer answers requests after a fixed latency, the slow peer ignores cancels
+    #[test]
+    fn positive_endgame_does_not_wait_on_slow_peer() {
+        const NUM_BLOCKS: u32 = 10;
+        const FAST_LATENCY: u32 = 1;
+        const SLOW_LATENCY: u32 = 10 * FAST_LATENCY;
+
+        let (fast, slow) = (peer(1), peer(2));
+        let mut queue = unchoked_queue(&[fast, slow], 2);
+        queue.add_blocks((0..NUM_BLOCKS).map(|index| block(0, index)));
+
+        let mut in_transit: HashMap<PeerInfo, Vec<(u32, RequestMessage)>> = HashMap::new();
+        let mut received = 0;
+        let mut now = 0;
+        while received < NUM_BLOCKS {
+            queue.fill_requests(&slow, |_| true);
+            queue.fill_requests(&fast, |_| true);
+
+            while let Some((info, message)) = queue.poll() {
+                let latency = if info == fast {
+                    FAST_LATENCY
+                } else {
+                    SLOW_LATENCY
+                };
+
+                match message {
+                    PeerWireProtocolMessage::Request(request) => in_transit
+                        .entry(info)
+                        .or_insert_with(Vec::new)
+                        .push((now + latency, request)),
+                    PeerWireProtocolMessage::Cancel(_) => assert_eq!(slow, info),
+                    other => panic!("Unexpected Message {:?}", other),
+                }
+            }
+
+            now += 1;
+            for (info, requests) in in_transit.iter_mut() {
+                for &(_, request) in requests.iter().filter(|&&(at, _)| at <= now) {
+                    if queue.on_piece(info, &piece_for(&request)) == ReceivedBlock::New {
+                        received += 1;
+                    }
+                }
+                requests.retain(|&(at, _)| at > now);
+            }
+        }
+
+        assert!(now < SLOW_LATENCY);
+        assert_eq!(0, queue.num_missing());
+        assert_eq!(0, queue.wasted_bytes());
+
+        // Blocks the slow peer sends after being cancelled are tolerated
+        for &(_, request) in in_transit[&slow].iter() {
+            assert_eq!(
+                ReceivedBlock::Duplicate,
+                queue.on_piece(&slow, &piece_for(&request))
+            );
+        }
+        assert_eq!(2 * BLOCK_LEN as u64, queue.wasted_bytes());
+    }
+
+    #[test]
+    fn positive_remove_peer_requeues_requests() {
+        let (first, second) = (peer(1), peer(2));
+        let mut queue = unchoked_queue(&[first, second], 10);
+        queue.add_blocks(vec![block(0, 0)]);
+        queue.fill_requests(&first, |_| true);
+
+        queue.remove_peer(&first);
+
+        assert!(drain(&mut queue).is_empty());
+        assert_eq!(1, queue.fill_requests(&second, |_| true));
+    }
+
+    fn snub_queue(info: PeerInfo) -> RequestQueue {
+        let mut queue = unchoked_queue(&[info], 4)
+            .with_request_timeout(Duration::from_secs(90))
+            .with_snub_timeout(Duration::from_secs(60))
+            .with_endgame_threshold(0);
+        queue.add_blocks((0..8).map(|index| block(0, index)));
+        queue.fill_requests(&info, |_| true);
+
+        queue
+    }
+
+    #[test]
+    fn positive_snubbed_after_timeout_without_blocks() {
+        let info = peer(1);
+        let mut queue = snub_queue(info);
+
+        queue.tick(Duration::from_secs(59));
+        assert!(!queue.is_snubbed(&info));
+        assert_eq!(None, queue.poll_event());
+
+        queue.tick(Duration::from_secs(1));
+        assert!(queue.is_snubbed(&info));
+        assert_eq!(
+            Some(RequestEvent::PeerSnubbed { peer: info }),
+            queue.poll_event()
+        );
+        assert_eq!(None, queue.poll_event());
+    }
+
+    #[test]
+    fn positive_snubbed_peer_gets_single_request() {
+        let info = peer(1);
+        let mut queue = snub_queue(info);
+        queue.tick(Duration::from_secs(60));
+
+        // Requests time out, only one of them is requested again
+        queue.tick(Duration::from_secs(30));
+        assert_eq!(0, queue.num_in_flight(&info));
+        assert_eq!(1, queue.fill_requests(&info, |_| true));
+        assert_eq!(0, queue.fill_requests(&info, |_| true));
+    }
+
+    #[test]
+    fn positive_block_clears_snub() {
+        let info = peer(1);
+        let mut queue = snub_queue(info);
+        queue.tick(Duration::from_secs(60));
+        queue.poll_event();
+
+        assert_eq!(
+            ReceivedBlock::New,
+            queue.on_piece(&info, &piece_for(&block(0, 0)))
+        );
+
+        assert!(!queue.is_snubbed(&info));
+        assert_eq!(
+            Some(RequestEvent::PeerUnsnubbed { peer: info }),
+            queue.poll_event()
+        );
+        assert_eq!(1, queue.fill_requests(&info, |_| true));
+
+        // Timer starts over from the block
+        queue.tick(Duration::from_secs(59));
+        assert!(!queue.is_snubbed(&info));
+    }
+
+    #[test]
+    fn negative_no_snub_without_outstanding_requests() {
+        let info = peer(1);
+        let mut queue = unchoked_queue(&[info], 4).with_snub_timeout(Duration::from_secs(60));
+
+        queue.tick(Duration::from_secs(120));
+        queue.on_choke(&info);
+        queue.add_blocks(vec![block(0, 0)]);
+        queue.tick(Duration::from_secs(120));
+
+        assert!(!queue.is_snubbed(&info));
+        assert_eq!(None, queue.poll_event());
+    }
+
+    #[test]
+    fn positive_seed_only_cancels_outstanding_requests() {
+        let info = peer(1);
+        let mut queue = unchoked_queue(&[info], 2);
+        queue.add_blocks((0..4).map(|index| block(0, index)));
+        queue.fill_requests(&info, |_| true);
+        drain(&mut queue);
+
+        queue.set_torrent_mode(TorrentMode::SeedOnly);
+        assert_eq!(
+            vec![
+                (info, cancel_for(&block(0, 0))),
+                (info, cancel_for(&block(0, 1)))
+            ],
+            drain(&mut queue)
+        );
+        assert_eq!(0, queue.num_missing());
+        assert_eq!(0, queue.num_in_flight(&info));
+
+        // Blocks of a late piece are wasted, not requested again
+        assert_eq!(
+            ReceivedBlock::Duplicate,
+            queue.on_piece(&info, &piece_for(&block(0, 0)))
+        );
+    }
+
+    #[test]
+    fn negative_seed_only_never_requests_or_interested() {
+        let info = peer(1);
+        let mut queue = unchoked_queue(&[info], 2).with_torrent_mode(TorrentMode::SeedOnly);
+
+        queue.add_blocks((0..4).map(|index| block(0, index)));
+        assert!(!queue.is_interested(|_| true));
+        assert_eq!(0, queue.fill_requests(&info, |_| true));
+        assert!(drain(&mut queue).is_empty());
+
+        // Switching back to download mode needs the blocks again
+        queue.set_torrent_mode(TorrentMode::Download);
+        assert!(!queue.is_interested(|_| true));
+        queue.add_blocks((0..4).map(|index| block(0, index)));
+        assert!(queue.is_interested(|_| true));
+        assert!(!queue.is_interested(|piece| piece != 0));
+        assert_eq!(2, queue.fill_requests(&info, |_| true));
+    }
+}
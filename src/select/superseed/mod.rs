@@ -0,0 +1,4 @@
+//! Module for super seeding (BEP 16), revealing pieces to peers one at a time.
+
+mod seeder;
+pub use self::seeder::SuperSeedManager;
@@ -0,0 +1,473 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use crate::handshake::Extension;
+use crate::peer::messages::{BitFieldMessage, HaveMessage, PeerWireProtocolMessage};
+use crate::peer::PeerInfo;
+use crate::select::piece::RarestFirstPicker;
+
+/// Super seeding (BEP 16) for the initial seeder of a torrent.
+///
+/// Peers are told we have no pieces, and are then offered a single rare piece each via
+/// a `Have` message. A peer is only offered its next piece once another peer announces
+/// the piece it was offered, meaning the peer passed it on instead of hoarding it. This
+/// gets more distinct pieces into the swarm for every byte we upload.
+///
+/// Once every piece is available from at least one peer, we fall back to normal seeding
+/// and reveal every remaining piece to every peer.
+///
+/// Messages to send out are retrieved via `poll`.
+pub struct SuperSeedManager {
+    num_pieces: usize,
+    super_seeding: bool,
+    picker: RarestFirstPicker,
+    all_pieces: BitFieldMessage,
+    peers: HashMap<PeerInfo, SuperSeedPeer>,
+    // Number of peers each piece is currently offered to
+    offered: HashMap<u32, usize>,
+    out_queue: VecDeque<(PeerInfo, PeerWireProtocolMessage)>,
+}
+
+struct SuperSeedPeer {
+    offered: Option<u32>,
+    revealed: HashSet<u32>,
+}
+
+impl SuperSeedManager {
+    /// Create a new `SuperSeedManager` for a torrent with the given number of pieces.
+    pub fn new(num_pieces: usize) -> SuperSeedManager {
+        SuperSeedManager {
+            num_pieces: num_pieces,
+            super_seeding: true,
+            picker: RarestFirstPicker::new(num_pieces),
+            all_pieces: BitFieldMessage::from_pieces(num_pieces, 0..num_pieces),
+            peers: HashMap::new(),
+            offered: HashMap::new(),
+            out_queue: VecDeque::new(),
+        }
+    }
+
+    /// Number of pieces in the torrent.
+    pub fn num_pieces(&self) -> usize {
+        self.num_pieces
+    }
+
+    /// Whether or not we are still super seeding, or have fallen back to normal seeding.
+    pub fn is_super_seeding(&self) -> bool {
+        self.super_seeding
+    }
+
+    /// Piece currently offered to the given peer.
+    pub fn offered_piece(&self, info: &PeerInfo) -> Option<u32> {
+        self.peers.get(info).and_then(|peer| peer.offered)
+    }
+
+    /// Number of peers that we know have the given piece.
+    pub fn availability(&self, piece: u32) -> u32 {
+        self.picker.availability(piece)
+    }
+
+    /// Add a peer, which should happen before we send it any other messages.
+    ///
+    /// While super seeding, the peer is sent an empty bitfield (or `HaveNone`, with the
+    /// fast extension) followed by its first offered piece. Otherwise it is sent a full
+    /// bitfield (or `HaveAll`).
+    pub fn add_peer(&mut self, info: PeerInfo) {
+        if self.peers.contains_key(&info) {
+            return;
+        }
+        let fast = info.extensions().contains(Extension::FastExtension);
+
+        let message = match (self.super_seeding, fast) {
+            (true, true) => PeerWireProtocolMessage::HaveNone,
+            (true, false) => {
+                PeerWireProtocolMessage::BitField(BitFieldMessage::with_capacity(self.num_pieces))
+            }
+            (false, true) => PeerWireProtocolMessage::HaveAll,
+            (false, false) => PeerWireProtocolMessage::BitField(self.all_pieces.clone()),
+        };
+        self.out_queue.push_back((info, message));
+
+        self.peers.insert(
+            info,
+            SuperSeedPeer {
+                offered: None,
+                revealed: HashSet::new(),
+            },
+        );
+        if self.super_seeding {
+            self.offer_next(&info);
+        }
+    }
+
+    /// Remove a peer, no longer counting any of its pieces.
+    pub fn remove_peer(&mut self, info: &PeerInfo) {
+        self.withdraw_offer(info);
+
+        self.picker.on_peer_gone(*info);
+        self.peers.remove(info);
+        self.out_queue.retain(|&(peer, _)| peer != *info);
+    }
+
+    /// Peer has announced that it has the given piece.
+    ///
+    /// Any other peer that was offered the piece is offered its next piece.
+    pub fn on_have(&mut self, info: &PeerInfo, piece: u32) {
+        self.picker.on_have(*info, piece);
+
+        self.confirm(info, |offered| offered == piece);
+        self.check_fallback();
+    }
+
+    /// Peer has sent us its bitfield.
+    ///
+    /// Any other peer that was offered a piece set in the bitfield is offered its next piece.
+    pub fn on_bitfield(&mut self, info: &PeerInfo, bits: &BitFieldMessage) {
+        self.picker.on_bitfield(*info, bits);
+
+        self.confirm(info, |offered| bits.has_piece(offered as usize));
+        self.check_fallback();
+    }
+
+    /// Retrieve the next message to send to a peer.
+    pub fn poll(&mut self) -> Option<(PeerInfo, PeerWireProtocolMessage)> {
+        self.out_queue.pop_front()
+    }
+
+    /// Offer the next piece to every peer, other than `info`, whose offered piece matches.
+    fn confirm<F>(&mut self, info: &PeerInfo, announced: F)
+    where
+        F: Fn(u32) -> bool,
+    {
+        if !self.super_seeding {
+            return;
+        }
+
+        let confirmed: Vec<PeerInfo> = self
+            .peers
+            .iter()
+            .filter(|&(other, peer)| other != info && peer.offered.map_or(false, &announced))
+            .map(|(other, _)| *other)
+            .collect();
+
+        for other in confirmed {
+            self.withdraw_offer(&other);
+            self.offer_next(&other);
+        }
+    }
+
+    /// Offer the rarest piece the peer does not have, preferring pieces not offered to others.
+    fn offer_next(&mut self, info: &PeerInfo) {
+        let lacking: HashSet<u32> = (0..self.num_pieces as u32)
+            .filter(|&piece| !self.picker.peer_has_piece(info, piece))
+            .collect();
+        let not_offered: HashSet<u32> = lacking
+            .iter()
+            .cloned()
+            .filter(|piece| !self.offered.contains_key(piece))
+            .collect();
+
+        let candidates = if not_offered.is_empty() {
+            &lacking
+        } else {
+            &not_offered
+        };
+        let piece = match self.picker.pick(&self.all_pieces, candidates) {
+            Some(piece) => piece,
+            None => return,
+        };
+
+        let peer = self.peers.get_mut(info).unwrap();
+        peer.offered = Some(piece);
+        peer.revealed.insert(piece);
+
+        *self.offered.entry(piece).or_insert(0) += 1;
+        self.out_queue.push_back((
+            *info,
+            PeerWireProtocolMessage::Have(HaveMessage::new(piece)),
+        ));
+    }
+
+    fn withdraw_offer(&mut self, info: &PeerInfo) {
+        let piece = match self
+            .peers
+            .get_mut(info)
+            .and_then(|peer| peer.offered.take())
+        {
+            Some(piece) => piece,
+            None => return,
+        };
+
+        let now_unoffered = self.offered.get_mut(&piece).map_or(false, |count| {
+            *count -= 1;
+            *count == 0
+        });
+        if now_unoffered {
+            self.offered.remove(&piece);
+        }
+    }
+
+    /// Fall back to normal seeding once there is at least one copy of every piece.
+    fn check_fallback(&mut self) {
+        if !self.super_seeding || self.num_pieces == 0 || self.picker.min_availability() == 0 {
+            return;
+        }
+        info!("bittorrent-protocol_select: Every Piece Distributed, Leaving Super Seeding");
+
+        self.super_seeding = false;
+        self.offered.clear();
+
+        for (info, peer) in self.peers.iter_mut() {
+            peer.offered = None;
+
+            for piece in 0..self.num_pieces as u32 {
+                if !peer.revealed.contains(&piece) && !self.picker.peer_has_piece(info, piece) {
+                    peer.revealed.insert(piece);
+                    self.out_queue.push_back((
+                        *info,
+                        PeerWireProtocolMessage::Have(HaveMessage::new(piece)),
+                    ));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SuperSeedManager;
+    use crate::handshake::{Extension, Extensions};
+    use crate::peer::messages::{BitFieldMessage, HaveMessage, PeerWireProtocolMessage};
+    use crate::peer::PeerInfo;
+
+    use std::collections::{HashMap, HashSet};
+
+    fn peer_with(port: u16, extensions: Extensions) -> PeerInfo {
+        PeerInfo::new(
+            format!("127.0.0.1:{}", port).parse().unwrap(),
+            [port as u8; 20].into(),
+            [0u8; 20].into(),
+            extensions,
+        )
+    }
+
+    fn peer(port: u16) -> PeerInfo {
+        peer_with(port, Extensions::new())
+    }
+
+    fn fast_peer(port: u16) -> PeerInfo {
+        let mut extensions = Extensions::new();
+        extensions.add(Extension::FastExtension);
+
+        peer_with(port, extensions)
+    }
+
+    fn drain(manager: &mut SuperSeedManager) -> Vec<(PeerInfo, PeerWireProtocolMessage)> {
+        let mut messages = Vec::new();
+        while let Some(message) = manager.poll() {
+            messages.push(message);
+        }
+
+        messages
+    }
+
+    fn have(piece: u32) -> PeerWireProtocolMessage {
+        PeerWireProtocolMessage::Have(HaveMessage::new(piece))
+    }
+
+    #[test]
+    fn positive_masks_bitfield_and_offers_one_piece() {
+        let info = peer(1);
+        let mut manager = SuperSeedManager::new(10);
+        manager.add_peer(info);
+
+        let piece = manager.offered_piece(&info).unwrap();
+        assert_eq!(
+            vec![
+                (
+                    info,
+                    PeerWireProtocolMessage::BitField(BitFieldMessage::with_capacity(10))
+                ),
+                (info, have(piece))
+            ],
+            drain(&mut manager)
+        );
+    }
+
+    #[test]
+    fn positive_masks_with_have_none_for_fast_peers() {
+        let info = fast_peer(1);
+        let mut manager = SuperSeedManager::new(10);
+        manager.add_peer(info);
+
+        assert_eq!(
+            (info, PeerWireProtocolMessage::HaveNone),
+            drain(&mut manager)[0]
+        );
+    }
+
+    #[test]
+    fn positive_offers_distinct_pieces() {
+        let mut manager = SuperSeedManager::new(3);
+        for port in 1..4 {
+            manager.add_peer(peer(port));
+        }
+
+        let offered: HashSet<u32> = (1..4)
+            .map(|port| manager.offered_piece(&peer(port)).unwrap())
+            .collect();
+        assert_eq!(3, offered.len());
+    }
+
+    #[test]
+    fn positive_reoffers_when_more_peers_than_pieces() {
+        let mut manager = SuperSeedManager::new(2);
+        for port in 1..4 {
+            manager.add_peer(peer(port));
+        }
+
+        assert!(manager.offered_piece(&peer(3)).is_some());
+    }
+
+    #[test]
+    fn negative_own_have_does_not_confirm() {
+        let (first, second) = (peer(1), peer(2));
+        let mut manager = SuperSeedManager::new(10);
+        manager.add_peer(first);
+        manager.add_peer(second);
+        drain(&mut manager);
+
+        let piece = manager.offered_piece(&first).unwrap();
+        manager.on_have(&first, piece);
+
+        assert_eq!(Some(piece), manager.offered_piece(&first));
+        assert!(drain(&mut manager).is_empty());
+    }
+
+    #[test]
+    fn positive_third_party_have_confirms() {
+        let (first, second) = (peer(1), peer(2));
+        let mut manager = SuperSeedManager::new(10);
+        manager.add_peer(first);
+        manager.add_peer(second);
+        drain(&mut manager);
+
+        let piece = manager.offered_piece(&first).unwrap();
+        manager.on_have(&first, piece);
+        manager.on_have(&second, piece);
+
+        let next = manager.offered_piece(&first).unwrap();
+        assert!(next != piece);
+        assert_eq!(vec![(first, have(next))], drain(&mut manager));
+    }
+
+    #[test]
+    fn positive_bitfield_confirms() {
+        let (first, second) = (peer(1), peer(2));
+        let mut manager = SuperSeedManager::new(10);
+        manager.add_peer(first);
+        drain(&mut manager);
+
+        let piece = manager.offered_piece(&first).unwrap();
+        manager.on_bitfield(
+            &second,
+            &BitFieldMessage::from_pieces(10, Some(piece as usize).into_iter()),
+        );
+
+        assert!(manager.offered_piece(&first) != Some(piece));
+    }
+
+    #[test]
+    fn positive_new_peer_after_fallback_sees_everything() {
+        let mut manager = SuperSeedManager::new(4);
+        manager.on_bitfield(&peer(1), &BitFieldMessage::from_pieces(4, 0..4));
+        assert!(!manager.is_super_seeding());
+
+        manager.add_peer(fast_peer(2));
+        manager.add_peer(peer(3));
+
+        assert_eq!(
+            vec![
+                (fast_peer(2), PeerWireProtocolMessage::HaveAll),
+                (
+                    peer(3),
+                    PeerWireProtocolMessage::BitField(BitFieldMessage::from_pieces(4, 0..4))
+                )
+            ],
+            drain(&mut manager)
+        );
+    }
+
+    // Peers download the piece they were offered, then pass one piece they have to
+    // every other peer, until every piece is in the swarm and super seeding stops
+    #[test]
+    fn positive_three_peer_pieces_distributed_before_reoffered() {
+        const NUM_PIECES: usize = 6;
+
+        let peers = [peer(1), peer(2), peer(3)];
+        let mut manager = SuperSeedManager::new(NUM_PIECES);
+        for info in peers.iter() {
+            manager.add_peer(*info);
+        }
+
+        let mut offers = Vec::new();
+        let mut revealed: HashMap<PeerInfo, HashSet<u32>> = HashMap::new();
+        let mut has: HashMap<PeerInfo, HashSet<u32>> = HashMap::new();
+        let mut rounds = 0;
+        while manager.is_super_seeding() {
+            rounds += 1;
+            assert!(rounds <= NUM_PIECES, "Super Seeding Never Finished");
+
+            // Download from us
+            for (info, message) in drain(&mut manager) {
+                if let PeerWireProtocolMessage::Have(have) = message {
+                    offers.push(have.piece_index());
+                    revealed
+                        .entry(info)
+                        .or_insert_with(HashSet::new)
+                        .insert(have.piece_index());
+
+                    has.entry(info)
+                        .or_insert_with(HashSet::new)
+                        .insert(have.piece_index());
+                    manager.on_have(&info, have.piece_index());
+                }
+            }
+
+            // Download from each other
+            for from in peers.iter() {
+                for to in peers.iter().filter(|&to| to != from) {
+                    let shared = has.get(from).and_then(|from_has| {
+                        from_has
+                            .iter()
+                            .find(|piece| !has[to].contains(piece))
+                            .cloned()
+                    });
+
+                    if let Some(piece) = shared {
+                        has.get_mut(to).unwrap().insert(piece);
+                        manager.on_have(to, piece);
+                    }
+                }
+            }
+        }
+
+        // Every piece was offered exactly once while super seeding
+        let distinct: HashSet<u32> = offers.iter().cloned().collect();
+        assert_eq!(NUM_PIECES, distinct.len());
+        assert_eq!(NUM_PIECES, offers.len());
+
+        // Remaining pieces are revealed to every peer
+        for (info, message) in drain(&mut manager) {
+            if let PeerWireProtocolMessage::Have(have) = message {
+                revealed
+                    .entry(info)
+                    .or_insert_with(HashSet::new)
+                    .insert(have.piece_index());
+            }
+        }
+        for info in peers.iter() {
+            let known: HashSet<u32> = revealed[info].union(&has[info]).cloned().collect();
+            assert_eq!(NUM_PIECES, known.len());
+        }
+    }
+}
@@ -0,0 +1,4 @@
+//! Module for serving blocks to peers.
+
+mod uploader;
+pub use self::uploader::Uploader;
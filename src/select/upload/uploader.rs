@@ -0,0 +1,527 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use bytes::BytesMut;
+
+use crate::disk::{BlockMetadata, BlockMut, IDiskMessage};
+use crate::handshake::Extension;
+use crate::peer::messages::{
+    CancelMessage, PeerWireProtocolMessage, PieceMessage, RejectMessage, RequestMessage,
+};
+use crate::peer::PeerInfo;
+use crate::util::bt::InfoHash;
+
+const DEFAULT_MAX_QUEUED: usize = 250;
+
+/// Answers block requests from peers with blocks read from disk.
+///
+/// Requests are only served while the peer is unchoked, for pieces that we have, and up to
+/// a maximum number of queued uploads per peer. Refused requests are dropped, or rejected for
+/// peers supporting the fast extension. Pieces are sent through the `PeerManager`, which counts
+/// their payload into the `PeerStats` used for choking.
+///
+/// Blocks to load are retrieved via `poll_disk`, the blocks that the disk manager loaded are
+/// handed back through `on_block_loaded`. A block requested by multiple peers is only loaded
+/// once, and a cancel that arrives before the block is loaded suppresses the piece.
+///
+/// Messages to send out are retrieved via `poll`.
+pub struct Uploader {
+    info_hash: InfoHash,
+    max_queued: usize,
+    have: HashSet<u32>,
+    peers: HashMap<PeerInfo, PeerUploads>,
+    // Peers waiting on each block being loaded
+    loading: HashMap<RequestMessage, Vec<PeerInfo>>,
+    disk_queue: VecDeque<IDiskMessage>,
+    out_queue: VecDeque<(PeerInfo, PeerWireProtocolMessage)>,
+}
+
+struct PeerUploads {
+    choked: bool,
+    // Requests being loaded, or loaded but not yet polled
+    queued: HashSet<RequestMessage>,
+}
+
+impl Uploader {
+    /// Create a new `Uploader` for the torrent with the given info hash.
+    pub fn new(info_hash: InfoHash) -> Uploader {
+        Uploader {
+            info_hash: info_hash,
+            max_queued: DEFAULT_MAX_QUEUED,
+            have: HashSet::new(),
+            peers: HashMap::new(),
+            loading: HashMap::new(),
+            disk_queue: VecDeque::new(),
+            out_queue: VecDeque::new(),
+        }
+    }
+
+    /// Sets the maximum number of requests queued up for a single peer.
+    pub fn with_max_queued(mut self, max_queued: usize) -> Uploader {
+        self.max_queued = max_queued;
+        self
+    }
+
+    /// Maximum number of requests queued up for a single peer.
+    pub fn max_queued(&self) -> usize {
+        self.max_queued
+    }
+
+    /// Number of requests queued up for the given peer.
+    pub fn num_queued(&self, info: &PeerInfo) -> usize {
+        self.peers.get(info).map_or(0, |peer| peer.queued.len())
+    }
+
+    /// Whether or not we have the given piece to upload.
+    pub fn has_piece(&self, piece_index: u32) -> bool {
+        self.have.contains(&piece_index)
+    }
+
+    /// Add a piece that we have, and can be requested from us.
+    pub fn add_piece(&mut self, piece_index: u32) {
+        self.have.insert(piece_index);
+    }
+
+    /// Add a peer, which starts out choked.
+    pub fn add_peer(&mut self, info: PeerInfo) {
+        self.peers.entry(info).or_insert_with(|| PeerUploads {
+            choked: true,
+            queued: HashSet::new(),
+        });
+    }
+
+    /// Remove a peer, dropping any requests queued up for it.
+    pub fn remove_peer(&mut self, info: &PeerInfo) {
+        if let Some(peer) = self.peers.remove(info) {
+            for block in peer.queued {
+                self.stop_loading(info, &block);
+            }
+        }
+        self.out_queue.retain(|&(peer, _)| peer != *info);
+    }
+
+    /// Update whether or not we are choking the given peer.
+    ///
+    /// Choking a peer drops its queued requests, peers supporting the
+    /// fast extension are sent a reject for each of them.
+    pub fn set_choked(&mut self, info: &PeerInfo, choked: bool) {
+        let discarded: Vec<RequestMessage> = match self.peers.get_mut(info) {
+            Some(peer) => {
+                peer.choked = choked;
+
+                if choked {
+                    peer.queued.drain().collect()
+                } else {
+                    Vec::new()
+                }
+            }
+            None => return,
+        };
+
+        for block in discarded {
+            self.discard(info, &block);
+            self.refuse(info, block);
+        }
+    }
+
+    /// Peer has requested a block from us.
+    pub fn on_request(&mut self, info: &PeerInfo, request: &RequestMessage) {
+        let block = *request;
+        let accept = match self.peers.get(info) {
+            Some(peer) if peer.queued.contains(&block) => return,
+            Some(peer) => {
+                !peer.choked
+                    && peer.queued.len() < self.max_queued
+                    && self.have.contains(&block.piece_index())
+            }
+            None => return,
+        };
+
+        if !accept {
+            self.refuse(info, block);
+            return;
+        }
+        self.peers.get_mut(info).unwrap().queued.insert(block);
+
+        let waiting = self.loading.entry(block).or_insert_with(Vec::new);
+        if waiting.is_empty() {
+            self.disk_queue
+                .push_back(IDiskMessage::LoadBlock(load_block(self.info_hash, &block)));
+        }
+        waiting.push(*info);
+    }
+
+    /// Peer has cancelled one of its requests.
+    ///
+    /// Peers supporting the fast extension are sent a reject in place of the piece.
+    pub fn on_cancel(&mut self, info: &PeerInfo, cancel: &CancelMessage) {
+        let block = RequestMessage::new(
+            cancel.piece_index(),
+            cancel.block_offset(),
+            cancel.block_length(),
+        );
+
+        let was_queued = self
+            .peers
+            .get_mut(info)
+            .map_or(false, |peer| peer.queued.remove(&block));
+        if was_queued {
+            self.discard(info, &block);
+            self.refuse(info, block);
+        }
+    }
+
+    /// Disk manager has loaded a block, queueing the piece for every peer waiting on it.
+    ///
+    /// Returns false if the block was not loaded for us.
+    pub fn on_block_loaded(&mut self, block: BlockMut) -> bool {
+        let (metadata, bytes) = block.into_parts();
+        let waiting = match self.take_waiting(&metadata) {
+            Some(waiting) => waiting,
+            None => return false,
+        };
+
+        let bytes = bytes.freeze();
+        for info in waiting {
+            let piece = PieceMessage::new(
+                metadata.piece_index() as u32,
+                metadata.block_offset() as u32,
+                bytes.clone(),
+            );
+
+            self.out_queue
+                .push_back((info, PeerWireProtocolMessage::Piece(piece)));
+        }
+
+        true
+    }
+
+    /// Disk manager failed to load a block, refusing the requests waiting on it.
+    ///
+    /// Returns false if the block was not loaded for us.
+    pub fn on_load_error(&mut self, block: BlockMut) -> bool {
+        let metadata = block.metadata();
+        let waiting = match self.take_waiting(&metadata) {
+            Some(waiting) => waiting,
+            None => return false,
+        };
+        let block = request_for(&metadata);
+
+        for info in waiting {
+            if let Some(peer) = self.peers.get_mut(&info) {
+                peer.queued.remove(&block);
+            }
+            self.refuse(&info, block);
+        }
+
+        true
+    }
+
+    /// Retrieve the next message to send to a peer.
+    pub fn poll(&mut self) -> Option<(PeerInfo, PeerWireProtocolMessage)> {
+        let message = self.out_queue.pop_front();
+
+        if let Some((info, PeerWireProtocolMessage::Piece(ref piece))) = message {
+            let block = RequestMessage::new(
+                piece.piece_index(),
+                piece.block_offset(),
+                piece.block_length(),
+            );
+
+            if let Some(peer) = self.peers.get_mut(&info) {
+                peer.queued.remove(&block);
+            }
+        }
+
+        message
+    }
+
+    /// Retrieve the next message to send to the disk manager.
+    pub fn poll_disk(&mut self) -> Option<IDiskMessage> {
+        self.disk_queue.pop_front()
+    }
+
+    /// Take the peers waiting on the block, if it was loaded for us.
+    ///
+    /// Blocks that every peer cancelled are still ours, they just have nobody waiting on them.
+    fn take_waiting(&mut self, metadata: &BlockMetadata) -> Option<Vec<PeerInfo>> {
+        if metadata.info_hash() != self.info_hash {
+            return None;
+        }
+
+        Some(
+            self.loading
+                .remove(&request_for(metadata))
+                .unwrap_or_else(Vec::new),
+        )
+    }
+
+    /// Drop the request from the loading block, or the piece waiting to be polled.
+    fn discard(&mut self, info: &PeerInfo, block: &RequestMessage) {
+        self.stop_loading(info, block);
+
+        self.out_queue.retain(|&(peer, ref message)| match message {
+            &PeerWireProtocolMessage::Piece(ref piece) if peer == *info => {
+                piece.piece_index() != block.piece_index()
+                    || piece.block_offset() != block.block_offset()
+            }
+            _ => true,
+        });
+    }
+
+    fn stop_loading(&mut self, info: &PeerInfo, block: &RequestMessage) {
+        if let Some(waiting) = self.loading.get_mut(block) {
+            waiting.retain(|peer| peer != info);
+        }
+    }
+
+    /// Let the peer know we will not be sending the block, if it supports the fast extension.
+    fn refuse(&mut self, info: &PeerInfo, block: RequestMessage) {
+        if info.extensions().contains(Extension::FastExtension) {
+            let reject = RejectMessage::new(
+                block.piece_index(),
+                block.block_offset(),
+                block.block_length(),
+            );
+
+            self.out_queue
+                .push_back((*info, PeerWireProtocolMessage::Reject(reject)));
+        }
+    }
+}
+
+fn request_for(metadata: &BlockMetadata) -> RequestMessage {
+    RequestMessage::new(
+        metadata.piece_index() as u32,
+        metadata.block_offset() as u32,
+        metadata.block_length(),
+    )
+}
+
+fn load_block(info_hash: InfoHash, block: &RequestMessage) -> BlockMut {
+    let mut bytes = BytesMut::with_capacity(block.block_length());
+    bytes.resize(block.block_length(), 0);
+
+    BlockMut::new(
+        BlockMetadata::new(
+            info_hash,
+            block.piece_index() as u64,
+            block.block_offset() as u64,
+            block.block_length(),
+        ),
+        bytes,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Uploader;
+    use crate::disk::{BlockMut, IDiskMessage};
+    use crate::handshake::{Extension, Extensions};
+    use crate::peer::messages::{
+        CancelMessage, PeerWireProtocolMessage, PieceMessage, RejectMessage, RequestMessage,
+    };
+    use crate::peer::PeerInfo;
+
+    use bytes::Bytes;
+
+    const BLOCK_LEN: usize = 16 * 1024;
+
+    fn peer_with(port: u16, extensions: Extensions) -> PeerInfo {
+        PeerInfo::new(
+            format!("127.0.0.1:{}", port).parse().unwrap(),
+            [port as u8; 20].into(),
+            [0u8; 20].into(),
+            extensions,
+        )
+    }
+
+    fn peer(port: u16) -> PeerInfo {
+        peer_with(port, Extensions::new())
+    }
+
+    fn fast_peer(port: u16) -> PeerInfo {
+        let mut extensions = Extensions::new();
+        extensions.add(Extension::FastExtension);
+
+        peer_with(port, extensions)
+    }
+
+    fn block(piece_index: u32, block: u32) -> RequestMessage {
+        RequestMessage::new(piece_index, block * BLOCK_LEN as u32, BLOCK_LEN)
+    }
+
+    fn cancel_for(block: &RequestMessage) -> CancelMessage {
+        CancelMessage::new(
+            block.piece_index(),
+            block.block_offset(),
+            block.block_length(),
+        )
+    }
+
+    fn reject_for(block: &RequestMessage) -> PeerWireProtocolMessage {
+        PeerWireProtocolMessage::Reject(RejectMessage::new(
+            block.piece_index(),
+            block.block_offset(),
+            block.block_length(),
+        ))
+    }
+
+    fn drain(uploader: &mut Uploader) -> Vec<(PeerInfo, PeerWireProtocolMessage)> {
+        let mut messages = Vec::new();
+        while let Some(message) = uploader.poll() {
+            messages.push(message);
+        }
+
+        messages
+    }
+
+    /// Pretend to be the disk manager, filling every block with its piece index.
+    fn load_all(uploader: &mut Uploader) -> usize {
+        let mut loaded = 0;
+        while let Some(message) = uploader.poll_disk() {
+            let block = match message {
+                IDiskMessage::LoadBlock(block) => block,
+                other => panic!("Unexpected Message {:?}", other),
+            };
+            let (metadata, mut bytes) = block.into_parts();
+            for byte in bytes.iter_mut() {
+                *byte = metadata.piece_index() as u8;
+            }
+
+            assert!(uploader.on_block_loaded(BlockMut::new(metadata, bytes)));
+            loaded += 1;
+        }
+
+        loaded
+    }
+
+    fn unchoked_uploader(peers: &[PeerInfo]) -> Uploader {
+        let mut uploader = Uploader::new([0u8; 20].into());
+        uploader.add_piece(0);
+
+        for info in peers {
+            uploader.add_peer(*info);
+            uploader.set_choked(info, false);
+        }
+
+        uploader
+    }
+
+    #[test]
+    fn positive_request_loaded_and_sent() {
+        let info = peer(1);
+        let mut uploader = unchoked_uploader(&[info]);
+
+        uploader.on_request(&info, &block(0, 1));
+        assert!(drain(&mut uploader).is_empty());
+        assert_eq!(1, load_all(&mut uploader));
+
+        assert_eq!(
+            vec![(
+                info,
+                PeerWireProtocolMessage::Piece(PieceMessage::new(
+                    0,
+                    BLOCK_LEN as u32,
+                    Bytes::from(vec![0u8; BLOCK_LEN])
+                ))
+            )],
+            drain(&mut uploader)
+        );
+        assert_eq!(0, uploader.num_queued(&info));
+    }
+
+    #[test]
+    fn positive_cancel_before_load_suppresses_piece() {
+        let info = peer(1);
+        let mut uploader = unchoked_uploader(&[info]);
+
+        uploader.on_request(&info, &block(0, 0));
+        uploader.on_cancel(&info, &cancel_for(&block(0, 0)));
+        load_all(&mut uploader);
+
+        assert!(drain(&mut uploader).is_empty());
+        assert_eq!(0, uploader.num_queued(&info));
+    }
+
+    #[test]
+    fn positive_cancel_rejected_with_fast_extension() {
+        let info = fast_peer(1);
+        let mut uploader = unchoked_uploader(&[info]);
+
+        uploader.on_request(&info, &block(0, 0));
+        load_all(&mut uploader);
+        uploader.on_cancel(&info, &cancel_for(&block(0, 0)));
+
+        assert_eq!(vec![(info, reject_for(&block(0, 0)))], drain(&mut uploader));
+    }
+
+    #[test]
+    fn positive_block_loaded_once_for_many_peers() {
+        let (first, second) = (peer(1), peer(2));
+        let mut uploader = unchoked_uploader(&[first, second]);
+
+        uploader.on_request(&first, &block(0, 0));
+        uploader.on_request(&second, &block(0, 0));
+
+        assert_eq!(1, load_all(&mut uploader));
+        assert_eq!(2, drain(&mut uploader).len());
+    }
+
+    #[test]
+    fn positive_choke_rejects_queued_with_fast_extension() {
+        let info = fast_peer(1);
+        let mut uploader = unchoked_uploader(&[info]);
+        uploader.on_request(&info, &block(0, 0));
+        uploader.on_request(&info, &block(0, 1));
+        load_all(&mut uploader);
+
+        uploader.set_choked(&info, true);
+
+        let messages = drain(&mut uploader);
+        assert_eq!(2, messages.len());
+        assert!(messages.contains(&(info, reject_for(&block(0, 0)))));
+        assert!(messages.contains(&(info, reject_for(&block(0, 1)))));
+    }
+
+    #[test]
+    fn negative_request_for_missing_piece() {
+        let (info, fast) = (peer(1), fast_peer(2));
+        let mut uploader = unchoked_uploader(&[info, fast]);
+
+        uploader.on_request(&info, &block(1, 0));
+        uploader.on_request(&fast, &block(1, 0));
+
+        assert_eq!(0, load_all(&mut uploader));
+        assert_eq!(vec![(fast, reject_for(&block(1, 0)))], drain(&mut uploader));
+    }
+
+    #[test]
+    fn negative_request_while_choked() {
+        let info = fast_peer(1);
+        let mut uploader = unchoked_uploader(&[]);
+        uploader.add_peer(info);
+
+        uploader.on_request(&info, &block(0, 0));
+
+        assert_eq!(0, load_all(&mut uploader));
+        assert_eq!(vec![(info, reject_for(&block(0, 0)))], drain(&mut uploader));
+    }
+
+    #[test]
+    fn negative_request_over_queue_cap() {
+        let info = peer(1);
+        let mut uploader = unchoked_uploader(&[info]).with_max_queued(2);
+
+        for index in 0..4 {
+            uploader.on_request(&info, &block(0, index));
+        }
+
+        assert_eq!(2, uploader.num_queued(&info));
+        assert_eq!(2, load_all(&mut uploader));
+        drain(&mut uploader);
+
+        // Room frees up once the pieces are sent
+        uploader.on_request(&info, &block(0, 2));
+        assert_eq!(1, uploader.num_queued(&info));
+    }
+}
@@ -0,0 +1,4 @@
+//! Module for verifying pieces received from peers against their hashes.
+
+mod verifier;
+pub use self::verifier::{PieceVerifier, VerifyEvent};
@@ -0,0 +1,454 @@
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::mem;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use bytes::Bytes;
+use threadpool::ThreadPool;
+
+use crate::metainfo::Info;
+use crate::peer::messages::{PieceMessage, ValidationError};
+use crate::peer::PeerInfo;
+use crate::util::sha::{ShaHash, ShaHashBuilder};
+
+const DEFAULT_NUM_THREADS: usize = 2;
+
+/// Outcome of verifying a piece.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum VerifyEvent {
+    /// Piece matched its hash, a `HaveMessage` for it should be sent to every peer.
+    PieceVerified(u32),
+    /// Piece did not match its hash, it should be downloaded again.
+    ///
+    /// Includes every peer that sent us part of the piece, with the number of blocks it sent.
+    PieceFailed(u32, Vec<(PeerInfo, usize)>),
+}
+
+/// Verifies pieces received from peers against the hashes in the info dictionary.
+///
+/// Blocks can arrive in any order; the hash for a piece is computed incrementally as
+/// a contiguous prefix of the piece becomes available, so only blocks that arrived
+/// ahead of a gap are buffered. Hashing happens on a thread pool, so adding blocks never
+/// blocks the caller.
+///
+/// Events are retrieved via `poll` or `poll_timeout`.
+pub struct PieceVerifier {
+    hashes: Vec<ShaHash>,
+    piece_len: u32,
+    last_piece_len: u32,
+    pool: ThreadPool,
+    pieces: HashMap<u32, PartialPiece>,
+    // Pieces that were fully received, and are being verified or were verified
+    completed: HashSet<u32>,
+    send: Sender<VerifyEvent>,
+    recv: Receiver<VerifyEvent>,
+}
+
+struct PartialPiece {
+    hashed_len: u32,
+    // Blocks after the first gap, keyed by their offset
+    out_of_order: BTreeMap<u32, (PeerInfo, Bytes)>,
+    contributors: HashMap<PeerInfo, usize>,
+    hasher: Arc<Mutex<PieceHasher>>,
+}
+
+/// Hash state shared with the pool.
+///
+/// Segments are queued in order, and hashed by whichever job for the piece runs next.
+struct PieceHasher {
+    builder: ShaHashBuilder,
+    queued: VecDeque<Bytes>,
+}
+
+/// Everything needed to check the piece, once its last segment is hashed.
+struct PieceFinish {
+    piece_index: u32,
+    expected: ShaHash,
+    contributors: Vec<(PeerInfo, usize)>,
+    send: Sender<VerifyEvent>,
+}
+
+impl PieceVerifier {
+    /// Create a new `PieceVerifier` for the torrent described by the given info dictionary.
+    pub fn new(info: &Info) -> PieceVerifier {
+        let hashes = info
+            .pieces()
+            .map(|hash| ShaHash::from_hash(hash).unwrap())
+            .collect();
+        let total_len = info.files().map(|file| file.length()).sum();
+
+        PieceVerifier::from_hashes(hashes, info.piece_length() as u32, total_len)
+    }
+
+    /// Create a new `PieceVerifier` for the given piece hashes.
+    pub fn from_hashes(hashes: Vec<ShaHash>, piece_len: u32, total_len: u64) -> PieceVerifier {
+        let last_piece_len = total_len - hashes.len().saturating_sub(1) as u64 * piece_len as u64;
+        let (send, recv) = mpsc::channel();
+
+        PieceVerifier {
+            hashes: hashes,
+            piece_len: piece_len,
+            last_piece_len: last_piece_len as u32,
+            pool: ThreadPool::new(DEFAULT_NUM_THREADS),
+            pieces: HashMap::new(),
+            completed: HashSet::new(),
+            send: send,
+            recv: recv,
+        }
+    }
+
+    /// Sets the number of threads used for hashing.
+    pub fn with_num_threads(mut self, num_threads: usize) -> PieceVerifier {
+        self.pool = ThreadPool::new(num_threads);
+        self
+    }
+
+    /// Hash on the given thread pool, which may be shared with other components.
+    pub fn with_thread_pool(mut self, pool: ThreadPool) -> PieceVerifier {
+        self.pool = pool;
+        self
+    }
+
+    /// Number of pieces in the torrent.
+    pub fn num_pieces(&self) -> usize {
+        self.hashes.len()
+    }
+
+    /// Length of the given piece, which is shorter for the last piece.
+    pub fn piece_length(&self, piece_index: u32) -> u32 {
+        if piece_index as usize + 1 == self.hashes.len() {
+            self.last_piece_len
+        } else {
+            self.piece_len
+        }
+    }
+
+    /// Add a block received from the given peer.
+    ///
+    /// Blocks for pieces that were already fully received are ignored, as are parts
+    /// of blocks we already have.
+    pub fn add_block(
+        &mut self,
+        info: PeerInfo,
+        piece: &PieceMessage,
+    ) -> Result<(), ValidationError> {
+        piece.validate(
+            self.hashes.len() as u32,
+            self.piece_len,
+            self.last_piece_len,
+        )?;
+
+        let piece_index = piece.piece_index();
+        if self.completed.contains(&piece_index) {
+            return Ok(());
+        }
+        let piece_len = self.piece_length(piece_index);
+
+        let partial = self
+            .pieces
+            .entry(piece_index)
+            .or_insert_with(PartialPiece::new);
+        partial
+            .out_of_order
+            .entry(piece.block_offset())
+            .or_insert((info, piece.block()));
+
+        let mut hasher = partial.hasher.lock().unwrap();
+        let queued_len = hasher.queued.len();
+        while let Some(offset) = partial.next_contiguous() {
+            let (peer, block) = partial.out_of_order.remove(&offset).unwrap();
+            let block_end = offset + block.len() as u32;
+
+            if block_end > partial.hashed_len {
+                let skip = (partial.hashed_len - offset) as usize;
+
+                hasher.queued.push_back(block.slice_from(skip));
+                *partial.contributors.entry(peer).or_insert(0) += 1;
+                partial.hashed_len = block_end;
+            }
+        }
+        let advanced = hasher.queued.len() != queued_len;
+        drop(hasher);
+
+        let finish = if partial.hashed_len == piece_len {
+            Some(PieceFinish {
+                piece_index: piece_index,
+                expected: self.hashes[piece_index as usize],
+                contributors: partial
+                    .contributors
+                    .iter()
+                    .map(|(&peer, &blocks)| (peer, blocks))
+                    .collect(),
+                send: self.send.clone(),
+            })
+        } else {
+            None
+        };
+
+        if advanced || finish.is_some() {
+            let hasher = partial.hasher.clone();
+            self.pool.execute(move || hash_queued(&hasher, finish));
+        }
+
+        if partial.hashed_len == piece_len {
+            self.pieces.remove(&piece_index);
+            self.completed.insert(piece_index);
+        }
+
+        Ok(())
+    }
+
+    /// Retrieve the next verification outcome, if one is ready.
+    pub fn poll(&mut self) -> Option<VerifyEvent> {
+        let event = self.recv.try_recv().ok();
+
+        self.on_event(event)
+    }
+
+    /// Retrieve the next verification outcome, waiting up to the given timeout.
+    pub fn poll_timeout(&mut self, timeout: Duration) -> Option<VerifyEvent> {
+        let event = self.recv.recv_timeout(timeout).ok();
+
+        self.on_event(event)
+    }
+
+    fn on_event(&mut self, event: Option<VerifyEvent>) -> Option<VerifyEvent> {
+        // Failed pieces have to be downloaded again
+        if let Some(VerifyEvent::PieceFailed(piece_index, _)) = event {
+            self.completed.remove(&piece_index);
+        }
+
+        event
+    }
+}
+
+impl PartialPiece {
+    fn new() -> PartialPiece {
+        PartialPiece {
+            hashed_len: 0,
+            out_of_order: BTreeMap::new(),
+            contributors: HashMap::new(),
+            hasher: Arc::new(Mutex::new(PieceHasher {
+                builder: ShaHashBuilder::new(),
+                queued: VecDeque::new(),
+            })),
+        }
+    }
+
+    /// Offset of the first buffered block that starts within the hashed prefix.
+    fn next_contiguous(&self) -> Option<u32> {
+        self.out_of_order
+            .keys()
+            .next()
+            .cloned()
+            .filter(|&offset| offset <= self.hashed_len)
+    }
+}
+
+fn hash_queued(hasher: &Mutex<PieceHasher>, finish: Option<PieceFinish>) {
+    let mut hasher = hasher.lock().unwrap();
+
+    while let Some(segment) = hasher.queued.pop_front() {
+        hasher.builder =
+            mem::replace(&mut hasher.builder, ShaHashBuilder::new()).add_bytes(&segment);
+    }
+
+    if let Some(finish) = finish {
+        let event = if hasher.builder.build() == finish.expected {
+            VerifyEvent::PieceVerified(finish.piece_index)
+        } else {
+            VerifyEvent::PieceFailed(finish.piece_index, finish.contributors)
+        };
+
+        // Verifier may have been dropped while we were hashing
+        let _ = finish.send.send(event);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{PieceVerifier, VerifyEvent};
+    use crate::handshake::Extensions;
+    use crate::peer::messages::{PieceMessage, ValidationError};
+    use crate::peer::PeerInfo;
+    use crate::util::sha::ShaHash;
+
+    use bytes::Bytes;
+    use std::time::Duration;
+
+    const PIECE_LEN: u32 = 64;
+    const BLOCK_LEN: u32 = 16;
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    fn peer(port: u16) -> PeerInfo {
+        PeerInfo::new(
+            format!("127.0.0.1:{}", port).parse().unwrap(),
+            [port as u8; 20].into(),
+            [0u8; 20].into(),
+            Extensions::new(),
+        )
+    }
+
+    /// Data for a torrent with three full pieces and a short last piece.
+    fn torrent_data() -> Vec<u8> {
+        (0..(3 * PIECE_LEN + 20)).map(|byte| byte as u8).collect()
+    }
+
+    fn verifier(data: &[u8]) -> PieceVerifier {
+        let hashes = data
+            .chunks(PIECE_LEN as usize)
+            .map(ShaHash::from_bytes)
+            .collect();
+
+        PieceVerifier::from_hashes(hashes, PIECE_LEN, data.len() as u64)
+    }
+
+    fn block(data: &[u8], piece_index: u32, offset: u32, len: u32) -> PieceMessage {
+        let start = (piece_index * PIECE_LEN + offset) as usize;
+        let end = (start + len as usize).min(data.len());
+
+        PieceMessage::new(piece_index, offset, Bytes::from(&data[start..end]))
+    }
+
+    #[test]
+    fn positive_verify_in_order_blocks() {
+        let data = torrent_data();
+        let mut verifier = verifier(&data);
+
+        for offset in (0..PIECE_LEN).step_by(BLOCK_LEN as usize) {
+            verifier
+                .add_block(peer(1), &block(&data, 0, offset, BLOCK_LEN))
+                .unwrap();
+        }
+
+        assert_eq!(
+            Some(VerifyEvent::PieceVerified(0)),
+            verifier.poll_timeout(TIMEOUT)
+        );
+    }
+
+    #[test]
+    fn positive_verify_out_of_order_blocks() {
+        let data = torrent_data();
+        let mut verifier = verifier(&data).with_num_threads(4);
+
+        for &offset in [48, 16, 32, 0].iter() {
+            verifier
+                .add_block(peer(1), &block(&data, 1, offset, BLOCK_LEN))
+                .unwrap();
+        }
+
+        assert_eq!(
+            Some(VerifyEvent::PieceVerified(1)),
+            verifier.poll_timeout(TIMEOUT)
+        );
+    }
+
+    #[test]
+    fn positive_verify_short_final_piece() {
+        let data = torrent_data();
+        let mut verifier = verifier(&data);
+        assert_eq!(20, verifier.piece_length(3));
+
+        verifier
+            .add_block(peer(1), &block(&data, 3, 16, 4))
+            .unwrap();
+        assert_eq!(None, verifier.poll_timeout(Duration::from_millis(50)));
+        verifier
+            .add_block(peer(1), &block(&data, 3, 0, 16))
+            .unwrap();
+
+        assert_eq!(
+            Some(VerifyEvent::PieceVerified(3)),
+            verifier.poll_timeout(TIMEOUT)
+        );
+    }
+
+    #[test]
+    fn negative_block_past_short_final_piece() {
+        let data = torrent_data();
+        let mut verifier = verifier(&data);
+
+        let piece = PieceMessage::new(3, 16, Bytes::from(vec![0u8; BLOCK_LEN as usize]));
+        assert_eq!(
+            Err(ValidationError::InvalidBlockOffset {
+                piece_index: 3,
+                block_offset: 16,
+                block_length: BLOCK_LEN as usize,
+                piece_length: 20
+            }),
+            verifier.add_block(peer(1), &piece)
+        );
+    }
+
+    #[test]
+    fn negative_corrupted_block_fails_piece() {
+        let data = torrent_data();
+        let mut verifier = verifier(&data);
+
+        verifier
+            .add_block(peer(1), &block(&data, 2, 0, 32))
+            .unwrap();
+        let corrupted = PieceMessage::new(2, 32, Bytes::from(vec![0xFFu8; 32]));
+        verifier.add_block(peer(2), &corrupted).unwrap();
+
+        match verifier.poll_timeout(TIMEOUT) {
+            Some(VerifyEvent::PieceFailed(2, mut peers)) => {
+                peers.sort_by_key(|&(info, _)| info.addr().port());
+                assert_eq!(vec![(peer(1), 1), (peer(2), 1)], peers);
+            }
+            other => panic!("Expected Piece 2 To Fail, Got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn positive_failed_piece_can_be_downloaded_again() {
+        let data = torrent_data();
+        let mut verifier = verifier(&data);
+
+        let corrupted = PieceMessage::new(0, 0, Bytes::from(vec![0u8; PIECE_LEN as usize]));
+        verifier.add_block(peer(1), &corrupted).unwrap();
+        assert!(match verifier.poll_timeout(TIMEOUT) {
+            Some(VerifyEvent::PieceFailed(0, _)) => true,
+            _ => false,
+        });
+
+        verifier
+            .add_block(peer(2), &block(&data, 0, 0, PIECE_LEN))
+            .unwrap();
+        assert_eq!(
+            Some(VerifyEvent::PieceVerified(0)),
+            verifier.poll_timeout(TIMEOUT)
+        );
+    }
+
+    #[test]
+    fn positive_duplicate_and_overlapping_blocks_ignored() {
+        let data = torrent_data();
+        let mut verifier = verifier(&data);
+
+        verifier
+            .add_block(peer(1), &block(&data, 0, 0, 32))
+            .unwrap();
+        verifier
+            .add_block(peer(1), &block(&data, 0, 0, 16))
+            .unwrap();
+        verifier
+            .add_block(peer(1), &block(&data, 0, 16, 32))
+            .unwrap();
+        verifier
+            .add_block(peer(1), &block(&data, 0, 48, 16))
+            .unwrap();
+        assert_eq!(
+            Some(VerifyEvent::PieceVerified(0)),
+            verifier.poll_timeout(TIMEOUT)
+        );
+
+        // Piece is already complete
+        verifier
+            .add_block(peer(2), &block(&data, 0, 0, 16))
+            .unwrap();
+        assert_eq!(None, verifier.poll_timeout(Duration::from_millis(50)));
+    }
+}
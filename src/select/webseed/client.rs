@@ -0,0 +1,979 @@
+use std::collections::{HashMap, VecDeque};
+use std::io;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use bytes::Bytes;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::runtime::Handle;
+use url::Url;
+
+use crate::handshake::{Extension, Extensions};
+use crate::htracker::http::{self, HttpResponse};
+use crate::htracker::{HttpConnector, TcpConnector};
+use crate::metainfo::Info;
+use crate::peer::messages::builders::ExtendedMessageBuilder;
+use crate::peer::messages::{
+    BitFieldMessage, BitsExtensionMessage, PeerWireProtocolMessage, PieceMessage, RejectMessage,
+    RequestMessage,
+};
+use crate::peer::PeerInfo;
+use crate::select::webseed::error::{WebSeedErrorKind, WebSeedResult};
+use crate::select::webseed::layout::{FileRange, WebSeedLayout};
+use crate::util::sha::ShaHash;
+
+const DEFAULT_MAX_REQUESTS: usize = 4;
+const DEFAULT_TIMEOUT_SECS: u64 = 30;
+const DEFAULT_MAX_REDIRECTS: usize = 5;
+const DEFAULT_MIN_BACKOFF_SECS: u64 = 15;
+const DEFAULT_MAX_BACKOFF_SECS: u64 = 10 * 60;
+/// Room for the head of a response, on top of the range we asked for.
+const MAX_RESPONSE_HEAD_LEN: usize = 16 * 1024;
+
+/// Downloads blocks from a web seed (BEP 19), presenting it as a peer.
+///
+/// The web seed shows up as a peer that supports the fast extension, and that has every
+/// piece: the first messages polled are an `ExtendedMessage` advertising the number of
+/// concurrent requests as its `reqq`, a full `BitField`, and an `UnChoke`. Messages for the
+/// peer of `peer_info` are handed to `send_message`, and each block request becomes a range
+/// request for the files the block covers. Blocks come back as `PieceMessage`s, so they go
+/// through the same request queue, verifier and disk writes as blocks from any other peer.
+///
+/// When the web seed fails a request, answers with a status other than 206, or ignores the
+/// range we asked for, it chokes us and rejects its outstanding requests, so they can be
+/// requested elsewhere. It unchokes us once the backoff, which doubles with every failure in
+/// a row, has passed in calls to `tick`.
+///
+/// Requests are sent on the given tokio runtime.
+pub struct WebSeedClient {
+    info: PeerInfo,
+    url: Url,
+    layout: WebSeedLayout,
+    handle: Handle,
+    connector: Arc<dyn HttpConnector>,
+    timeout: Duration,
+    max_redirects: usize,
+    max_requests: usize,
+    min_backoff: Duration,
+    max_backoff: Duration,
+    introduced: bool,
+    failures: u32,
+    // Time left until we are unchoked again, while backing off
+    backoff_left: Option<Duration>,
+    queued: VecDeque<RequestMessage>,
+    // Id of the fetch for each outstanding block, results of other fetches are dropped
+    in_flight: HashMap<RequestMessage, u64>,
+    next_fetch_id: u64,
+    send: Sender<FetchResult>,
+    recv: Receiver<FetchResult>,
+    messages: VecDeque<PeerWireProtocolMessage>,
+}
+
+struct FetchResult {
+    id: u64,
+    block: RequestMessage,
+    result: WebSeedResult<Bytes>,
+}
+
+impl WebSeedClient {
+    /// Create a new `WebSeedClient` for the web seed at the given url, sending requests on the given runtime.
+    ///
+    /// The peer of the web seed has an unspecified address with the port of the url, web
+    /// seeds are told apart by their peer id, which is the hash of their url.
+    pub fn new(url: Url, info: &Info, handle: Handle) -> WebSeedResult<WebSeedClient> {
+        let layout = WebSeedLayout::new(&url, info)?;
+        let port = url
+            .port_or_default()
+            .ok_or_else(|| WebSeedErrorKind::InvalidUrl {
+                url: url.serialize(),
+            })?;
+
+        let mut extensions = Extensions::new();
+        extensions.add(Extension::ExtensionProtocol);
+        extensions.add(Extension::FastExtension);
+        let peer_info = PeerInfo::new(
+            SocketAddr::from((Ipv4Addr::UNSPECIFIED, port)),
+            ShaHash::from_bytes(url.serialize().as_bytes()),
+            info.info_hash(),
+            extensions,
+        );
+        let (send, recv) = mpsc::channel();
+
+        Ok(WebSeedClient {
+            info: peer_info,
+            url: url,
+            layout: layout,
+            handle: handle,
+            connector: Arc::new(TcpConnector),
+            timeout: Duration::from_secs(DEFAULT_TIMEOUT_SECS),
+            max_redirects: DEFAULT_MAX_REDIRECTS,
+            max_requests: DEFAULT_MAX_REQUESTS,
+            min_backoff: Duration::from_secs(DEFAULT_MIN_BACKOFF_SECS),
+            max_backoff: Duration::from_secs(DEFAULT_MAX_BACKOFF_SECS),
+            introduced: false,
+            failures: 0,
+            backoff_left: None,
+            queued: VecDeque::new(),
+            in_flight: HashMap::new(),
+            next_fetch_id: 0,
+            send: send,
+            recv: recv,
+            messages: VecDeque::new(),
+        })
+    }
+
+    /// Open connections to the web seed with the given connector.
+    pub fn with_connector<C>(mut self, connector: C) -> WebSeedClient
+    where
+        C: HttpConnector + 'static,
+    {
+        self.connector = Arc::new(connector);
+        self
+    }
+
+    /// Sets the time allowed for each range request, from connecting to reading the whole response.
+    pub fn with_timeout(mut self, timeout: Duration) -> WebSeedClient {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Sets the maximum number of redirects followed for a single range request.
+    pub fn with_max_redirects(mut self, max_redirects: usize) -> WebSeedClient {
+        self.max_redirects = max_redirects;
+        self
+    }
+
+    /// Sets the number of blocks requested from the web seed at the same time.
+    ///
+    /// Should be set before the first call to `poll`, which advertises it as our `reqq`.
+    pub fn with_max_requests(mut self, max_requests: usize) -> WebSeedClient {
+        self.max_requests = max_requests.max(1);
+        self
+    }
+
+    /// Sets the backoff after the first failure, and the longest backoff after failures in a row.
+    pub fn with_backoff(mut self, min_backoff: Duration, max_backoff: Duration) -> WebSeedClient {
+        self.min_backoff = min_backoff;
+        self.max_backoff = max_backoff.max(min_backoff);
+        self
+    }
+
+    /// Peer the web seed presents itself as.
+    pub fn peer_info(&self) -> PeerInfo {
+        self.info
+    }
+
+    /// Url of the web seed.
+    pub fn url(&self) -> &Url {
+        &self.url
+    }
+
+    /// Number of failed requests in a row.
+    pub fn failures(&self) -> u32 {
+        self.failures
+    }
+
+    /// Whether or not the web seed is choking us, after a failure.
+    pub fn is_backing_off(&self) -> bool {
+        self.backoff_left.is_some()
+    }
+
+    /// Number of blocks being fetched from the web seed.
+    pub fn num_in_flight(&self) -> usize {
+        self.in_flight.len()
+    }
+
+    /// Number of blocks waiting for a free request slot.
+    pub fn num_queued(&self) -> usize {
+        self.queued.len()
+    }
+
+    /// Send a message to the web seed, as we would to a peer.
+    ///
+    /// Only requests and cancels are acted on, requests sent while we are choked are rejected.
+    pub fn send_message(&mut self, message: PeerWireProtocolMessage) {
+        match message {
+            PeerWireProtocolMessage::Request(block) => {
+                if self.is_backing_off() {
+                    self.messages.push_back(reject_message(&block));
+                } else if !self.in_flight.contains_key(&block) && !self.queued.contains(&block) {
+                    self.queued.push_back(block);
+                    self.start_queued();
+                }
+            }
+            PeerWireProtocolMessage::Cancel(cancel) => {
+                let block = RequestMessage::new(
+                    cancel.piece_index(),
+                    cancel.block_offset(),
+                    cancel.block_length(),
+                );
+
+                self.queued.retain(|queued| *queued != block);
+                self.in_flight.remove(&block);
+                self.start_queued();
+            }
+            _ => (),
+        }
+    }
+
+    /// Apply the given duration to the backoff, unchoking us once it has passed.
+    pub fn tick(&mut self, duration: Duration) {
+        if let Some(left) = self.backoff_left.take() {
+            if left <= duration {
+                info!(
+                    "bittorrent-protocol_select: Web Seed {} Done Backing Off",
+                    self.url.serialize()
+                );
+                self.messages.push_back(PeerWireProtocolMessage::UnChoke);
+            } else {
+                self.backoff_left = Some(left - duration);
+            }
+        }
+    }
+
+    /// Retrieve the next message from the web seed, if one is ready.
+    pub fn poll(&mut self) -> Option<PeerWireProtocolMessage> {
+        self.introduce();
+
+        while let Ok(fetched) = self.recv.try_recv() {
+            self.on_fetched(fetched);
+        }
+
+        self.messages.pop_front()
+    }
+
+    /// Retrieve the next message from the web seed, waiting up to the given timeout.
+    pub fn poll_timeout(&mut self, timeout: Duration) -> Option<PeerWireProtocolMessage> {
+        self.introduce();
+
+        let deadline = Instant::now() + timeout;
+        while self.messages.is_empty() && !self.in_flight.is_empty() {
+            let left = deadline.saturating_duration_since(Instant::now());
+
+            match self.recv.recv_timeout(left) {
+                Ok(fetched) => self.on_fetched(fetched),
+                Err(_) => break,
+            }
+        }
+
+        self.poll()
+    }
+
+    /// Queue the messages every peer connection starts out with.
+    fn introduce(&mut self) {
+        if self.introduced {
+            return;
+        }
+        self.introduced = true;
+
+        let extended = ExtendedMessageBuilder::new()
+            .with_request_queue_size(self.max_requests as u32)
+            .build();
+        let num_pieces = self.layout.num_pieces() as usize;
+
+        self.messages
+            .push_back(PeerWireProtocolMessage::BitsExtension(
+                BitsExtensionMessage::Extended(extended),
+            ));
+        self.messages.push_back(PeerWireProtocolMessage::BitField(
+            BitFieldMessage::from_pieces(num_pieces, 0..num_pieces),
+        ));
+        self.messages.push_back(PeerWireProtocolMessage::UnChoke);
+    }
+
+    /// Start fetching queued blocks until every request slot is taken.
+    fn start_queued(&mut self) {
+        while self.in_flight.len() < self.max_requests {
+            let block = match self.queued.pop_front() {
+                Some(block) => block,
+                None => return,
+            };
+
+            let ranges = match self.layout.ranges(
+                block.piece_index(),
+                block.block_offset(),
+                block.block_length(),
+            ) {
+                Ok(ranges) => ranges,
+                Err(error) => {
+                    warn!(
+                        "bittorrent-protocol_select: Web Seed {} Rejected Request: {}",
+                        self.url.serialize(),
+                        error
+                    );
+                    self.messages.push_back(reject_message(&block));
+                    continue;
+                }
+            };
+
+            let id = self.next_fetch_id;
+            self.next_fetch_id += 1;
+            self.in_flight.insert(block, id);
+
+            let fetcher = Fetcher {
+                connector: self.connector.clone(),
+                timeout: self.timeout,
+                max_redirects: self.max_redirects,
+            };
+            let send = self.send.clone();
+            self.handle.spawn(async move {
+                let result = fetcher.fetch_block(&ranges).await;
+
+                send.send(FetchResult {
+                    id: id,
+                    block: block,
+                    result: result,
+                })
+                .ok();
+            });
+        }
+    }
+
+    fn on_fetched(&mut self, fetched: FetchResult) {
+        if self.in_flight.get(&fetched.block) != Some(&fetched.id) {
+            return;
+        }
+        self.in_flight.remove(&fetched.block);
+
+        match fetched.result {
+            Ok(bytes) => {
+                self.failures = 0;
+                self.messages
+                    .push_back(PeerWireProtocolMessage::Piece(PieceMessage::new(
+                        fetched.block.piece_index(),
+                        fetched.block.block_offset(),
+                        bytes,
+                    )));
+                self.start_queued();
+            }
+            Err(error) => {
+                self.failures += 1;
+                let backoff = self
+                    .min_backoff
+                    .checked_mul(1 << (self.failures - 1).min(16))
+                    .unwrap_or(self.max_backoff)
+                    .min(self.max_backoff);
+
+                warn!(
+                    "bittorrent-protocol_select: Web Seed {} Failed A Request, Backing Off For {:?}: {}",
+                    self.url.serialize(),
+                    backoff,
+                    error
+                );
+                self.backoff_left = Some(backoff);
+
+                self.messages.push_back(PeerWireProtocolMessage::Choke);
+                self.messages.push_back(reject_message(&fetched.block));
+                for (block, _) in self.in_flight.drain() {
+                    self.messages.push_back(reject_message(&block));
+                }
+                for block in self.queued.drain(..) {
+                    self.messages.push_back(reject_message(&block));
+                }
+            }
+        }
+    }
+}
+
+fn reject_message(block: &RequestMessage) -> PeerWireProtocolMessage {
+    PeerWireProtocolMessage::Reject(RejectMessage::new(
+        block.piece_index(),
+        block.block_offset(),
+        block.block_length(),
+    ))
+}
+
+// ----------------------------------------------------------------------------//
+
+/// Everything needed to fetch a block, moved into the task fetching it.
+struct Fetcher {
+    connector: Arc<dyn HttpConnector>,
+    timeout: Duration,
+    max_redirects: usize,
+}
+
+impl Fetcher {
+    /// Fetch every range of the block in order, padding is filled in without a request.
+    async fn fetch_block(&self, ranges: &[FileRange]) -> WebSeedResult<Bytes> {
+        let mut block = Vec::new();
+
+        for range in ranges {
+            if range.is_padding() {
+                block.resize(block.len() + range.length() as usize, 0);
+            } else {
+                block.extend_from_slice(&self.fetch_range(range).await?);
+            }
+        }
+
+        Ok(Bytes::from(block))
+    }
+
+    /// Get the bytes of the range, following redirects.
+    async fn fetch_range(&self, range: &FileRange) -> WebSeedResult<Vec<u8>> {
+        let mut url = range.url().clone();
+        let mut redirects = 0;
+
+        loop {
+            let response = self.get(&url, range).await?;
+
+            match response.status() {
+                206 => return check_range(&response, range),
+                200 => {
+                    return Err(WebSeedErrorKind::RangeIgnored {
+                        status: response.status(),
+                    }
+                    .into())
+                }
+                301 | 302 | 303 | 307 | 308 => {
+                    if redirects == self.max_redirects {
+                        return Err(WebSeedErrorKind::TooManyRedirects {
+                            limit: self.max_redirects,
+                        }
+                        .into());
+                    }
+                    redirects += 1;
+
+                    let location = response.header("location").ok_or_else(|| {
+                        WebSeedErrorKind::InvalidResponse {
+                            details: "Redirect Has No Location".to_owned(),
+                        }
+                    })?;
+                    url = url
+                        .join(location)
+                        .map_err(|_| WebSeedErrorKind::InvalidUrl {
+                            url: location.to_owned(),
+                        })?;
+                }
+                status => {
+                    return Err(WebSeedErrorKind::HttpStatus {
+                        status: status,
+                        reason: response.reason().to_owned(),
+                    }
+                    .into())
+                }
+            }
+        }
+    }
+
+    /// Send a single range request for the given url, returning the response.
+    async fn get(&self, url: &Url, range: &FileRange) -> WebSeedResult<HttpResponse> {
+        if !self.connector.supports_scheme(&url.scheme) {
+            return Err(WebSeedErrorKind::UnsupportedScheme {
+                scheme: url.scheme.clone(),
+            }
+            .into());
+        }
+
+        let invalid_url = || WebSeedErrorKind::InvalidUrl {
+            url: url.serialize(),
+        };
+        let host = url.serialize_host().ok_or_else(invalid_url)?;
+        let port = url.port_or_default().ok_or_else(invalid_url)?;
+        let host_header = match url.port() {
+            Some(port) => format!("{}:{}", host, port),
+            None => host.clone(),
+        };
+        let target = match url.query {
+            Some(ref query) => format!("{}?{}", url.serialize_path().unwrap(), query),
+            None => url.serialize_path().unwrap(),
+        };
+        let max_response_len = range.length() as usize + MAX_RESPONSE_HEAD_LEN;
+
+        let exchange = async {
+            let mut stream = self.connector.connect(&url.scheme, &host, port).await?;
+            stream
+                .write_all(&http::get_range_request(
+                    &target,
+                    &host_header,
+                    range.first(),
+                    range.last(),
+                ))
+                .await?;
+            stream.flush().await?;
+
+            let mut bytes = Vec::new();
+            let bytes_read = (&mut stream)
+                .take(max_response_len as u64 + 1)
+                .read_to_end(&mut bytes)
+                .await?;
+            if bytes_read > max_response_len {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "Web Seed Response Exceeds The Requested Range",
+                ));
+            }
+
+            Ok(bytes)
+        };
+
+        let bytes = match tokio::time::timeout(self.timeout, exchange).await {
+            Ok(result) => result?,
+            Err(_) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    "Web Seed Did Not Respond In Time",
+                )
+                .into())
+            }
+        };
+
+        HttpResponse::from_bytes(&bytes).map_err(|error| {
+            WebSeedErrorKind::InvalidResponse {
+                details: error.to_string(),
+            }
+            .into()
+        })
+    }
+}
+
+/// Body of a partial content response, if it holds exactly the range we asked for.
+fn check_range(response: &HttpResponse, range: &FileRange) -> WebSeedResult<Vec<u8>> {
+    let invalid = |details: &str| WebSeedErrorKind::InvalidResponse {
+        details: details.to_owned(),
+    };
+
+    // Content-Range: bytes <first>-<last>/<length or *>
+    if let Some(content_range) = response.header("content-range") {
+        let bounds = content_range
+            .trim()
+            .strip_prefix("bytes ")
+            .and_then(|rest| rest.split('/').next())
+            .and_then(|bounds| {
+                let mut parts = bounds.splitn(2, '-');
+                let first = parts.next()?.trim().parse::<u64>().ok()?;
+                let last = parts.next()?.trim().parse::<u64>().ok()?;
+
+                Some((first, last))
+            })
+            .ok_or_else(|| invalid("Invalid Content Range"))?;
+
+        if bounds != (range.first(), range.last()) {
+            return Err(invalid("Content Range Does Not Match The Request").into());
+        }
+    }
+
+    if response.body().len() as u64 != range.length() {
+        return Err(invalid("Body Length Does Not Match The Request").into());
+    }
+
+    Ok(response.body().to_vec())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::io::{Read, Write};
+    use std::net::{SocketAddr, TcpListener};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use tokio::runtime::Runtime;
+    use url::Url;
+
+    use super::WebSeedClient;
+    use crate::metainfo::Metainfo;
+    use crate::peer::messages::{
+        BitsExtensionMessage, CancelMessage, PeerWireProtocolMessage, RequestMessage,
+    };
+    use crate::select::request::{ReceivedBlock, RequestQueue};
+    use crate::select::verify::{PieceVerifier, VerifyEvent};
+    use crate::util::sha::ShaHash;
+
+    const PIECE_LEN: usize = 64;
+    const BLOCK_LEN: usize = 16;
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    /// How the test server answers each request.
+    #[derive(Copy, Clone, PartialEq, Eq)]
+    enum Mode {
+        Range,
+        IgnoreRange,
+        Status(u16),
+    }
+
+    struct Server {
+        addr: SocketAddr,
+        requests: Arc<AtomicUsize>,
+        max_concurrent: Arc<AtomicUsize>,
+    }
+
+    /// Serve the given files, by path, on a local port until the test ends.
+    fn serve(files: HashMap<String, Vec<u8>>, mode: Mode, delay: Duration) -> Server {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let files = Arc::new(files);
+        let requests = Arc::new(AtomicUsize::new(0));
+        let max_concurrent = Arc::new(AtomicUsize::new(0));
+        let concurrent = Arc::new(AtomicUsize::new(0));
+
+        let (thread_requests, thread_max) = (requests.clone(), max_concurrent.clone());
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let (files, requests) = (files.clone(), thread_requests.clone());
+                let (concurrent, max_concurrent) = (concurrent.clone(), thread_max.clone());
+
+                thread::spawn(move || {
+                    let now = concurrent.fetch_add(1, Ordering::SeqCst) + 1;
+                    max_concurrent.fetch_max(now, Ordering::SeqCst);
+                    requests.fetch_add(1, Ordering::SeqCst);
+
+                    let mut head = Vec::new();
+                    let mut byte = [0u8; 1];
+                    while !head.ends_with(b"\r\n\r\n") && stream.read(&mut byte).unwrap() == 1 {
+                        head.push(byte[0]);
+                    }
+                    let head = String::from_utf8(head).unwrap();
+                    let path = head.split(' ').nth(1).unwrap().to_owned();
+                    let range = head
+                        .lines()
+                        .find(|line| line.starts_with("Range: bytes="))
+                        .map(|line| {
+                            let mut bounds = line["Range: bytes=".len()..].split('-');
+                            let first: usize = bounds.next().unwrap().parse().unwrap();
+                            let last: usize = bounds.next().unwrap().parse().unwrap();
+
+                            (first, last)
+                        });
+
+                    thread::sleep(delay);
+                    let response = match (files.get(&path), range, mode) {
+                        (_, _, Mode::Status(status)) => {
+                            format!("HTTP/1.1 {} Oops\r\nContent-Length: 0\r\n\r\n", status)
+                                .into_bytes()
+                        }
+                        (None, _, _) => {
+                            b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\r\n".to_vec()
+                        }
+                        (Some(file), Some((first, last)), Mode::Range) => {
+                            let mut response = format!(
+                                "HTTP/1.1 206 Partial Content\r\nContent-Range: bytes {}-{}/{}\r\n\
+                                 Content-Length: {}\r\n\r\n",
+                                first,
+                                last,
+                                file.len(),
+                                last + 1 - first
+                            )
+                            .into_bytes();
+                            response.extend_from_slice(&file[first..last + 1]);
+                            response
+                        }
+                        (Some(file), _, _) => {
+                            let mut response = format!(
+                                "HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n",
+                                file.len()
+                            )
+                            .into_bytes();
+                            response.extend_from_slice(file);
+                            response
+                        }
+                    };
+                    stream.write_all(&response).unwrap();
+
+                    concurrent.fetch_sub(1, Ordering::SeqCst);
+                });
+            }
+        });
+
+        Server {
+            addr: addr,
+            requests: requests,
+            max_concurrent: max_concurrent,
+        }
+    }
+
+    /// Multi file torrent named "dir", with files "a" and "b" whose data follows each other.
+    fn multi_file(a_len: usize, b_len: usize) -> (Metainfo, Vec<u8>) {
+        let data: Vec<u8> = (0..a_len + b_len).map(|byte| (byte * 7) as u8).collect();
+
+        let mut bytes = format!(
+            "d4:infod5:filesld6:lengthi{}e4:pathl1:aeed6:lengthi{}e4:pathl1:beee\
+             4:name3:dir12:piece lengthi{}e6:pieces{}:",
+            a_len,
+            b_len,
+            PIECE_LEN,
+            data.chunks(PIECE_LEN).count() * 20
+        )
+        .into_bytes();
+        for piece in data.chunks(PIECE_LEN) {
+            bytes.extend_from_slice(ShaHash::from_bytes(piece).as_ref());
+        }
+        bytes.extend_from_slice(b"ee");
+
+        (Metainfo::from_bytes(bytes).unwrap(), data)
+    }
+
+    fn files(data: &[u8], a_len: usize) -> HashMap<String, Vec<u8>> {
+        let mut files = HashMap::new();
+        files.insert("/seed/dir/a".to_owned(), data[..a_len].to_vec());
+        files.insert("/seed/dir/b".to_owned(), data[a_len..].to_vec());
+
+        files
+    }
+
+    fn client(server: &Server, metainfo: &Metainfo, runtime: &Runtime) -> WebSeedClient {
+        let url = Url::parse(&format!("http://{}/seed", server.addr)).unwrap();
+
+        WebSeedClient::new(url, metainfo.info(), runtime.handle().clone())
+            .unwrap()
+            .with_backoff(Duration::from_secs(10), Duration::from_secs(30))
+    }
+
+    fn drain(client: &mut WebSeedClient) -> Vec<PeerWireProtocolMessage> {
+        let mut messages = Vec::new();
+        while let Some(message) = client.poll() {
+            messages.push(message);
+        }
+
+        messages
+    }
+
+    /// Poll until the client has the given number of messages for us, or the timeout passes.
+    fn wait_for(client: &mut WebSeedClient, num_messages: usize) -> Vec<PeerWireProtocolMessage> {
+        let deadline = Instant::now() + TIMEOUT;
+        let mut messages = Vec::new();
+
+        while messages.len() < num_messages && Instant::now() < deadline {
+            if let Some(message) = client.poll_timeout(Duration::from_millis(100)) {
+                messages.push(message);
+            }
+        }
+
+        messages
+    }
+
+    fn request(piece_index: u32, offset: usize) -> RequestMessage {
+        RequestMessage::new(piece_index, offset as u32, BLOCK_LEN)
+    }
+
+    #[test]
+    fn positive_introduced_as_full_peer() {
+        let runtime = Runtime::new().unwrap();
+        let (metainfo, data) = multi_file(100, 60);
+        let server = serve(files(&data, 100), Mode::Range, Duration::from_millis(0));
+        let mut client = client(&server, &metainfo, &runtime).with_max_requests(3);
+
+        let messages = drain(&mut client);
+        assert_eq!(3, messages.len());
+        match messages[0] {
+            PeerWireProtocolMessage::BitsExtension(BitsExtensionMessage::Extended(
+                ref extended,
+            )) => {
+                assert_eq!(Some(3), extended.request_queue_size())
+            }
+            ref message => panic!("Unexpected Message {:?}", message),
+        }
+        match messages[1] {
+            PeerWireProtocolMessage::BitField(ref bitfield) => {
+                assert_eq!(3, bitfield.iter().count())
+            }
+            ref message => panic!("Unexpected Message {:?}", message),
+        }
+        assert_eq!(PeerWireProtocolMessage::UnChoke, messages[2]);
+    }
+
+    #[test]
+    fn positive_download_through_request_queue() {
+        let runtime = Runtime::new().unwrap();
+        let (metainfo, data) = multi_file(100, 60);
+        let server = serve(files(&data, 100), Mode::Range, Duration::from_millis(0));
+        let mut client = client(&server, &metainfo, &runtime).with_max_requests(2);
+        let info = client.peer_info();
+
+        let mut queue = RequestQueue::new();
+        let mut verifier = PieceVerifier::new(metainfo.info());
+        queue.add_peer(info);
+        for piece_index in 0..3u32 {
+            let piece_len = verifier.piece_length(piece_index) as usize;
+
+            queue.add_blocks(
+                (0..piece_len)
+                    .step_by(BLOCK_LEN)
+                    .map(|offset| request(piece_index, offset)),
+            );
+        }
+
+        let mut verified = Vec::new();
+        let deadline = Instant::now() + TIMEOUT;
+        while verified.len() < 3 && Instant::now() < deadline {
+            match client.poll_timeout(Duration::from_millis(10)) {
+                Some(PeerWireProtocolMessage::BitsExtension(BitsExtensionMessage::Extended(
+                    extended,
+                ))) => queue.on_extended(&info, &extended),
+                Some(PeerWireProtocolMessage::UnChoke) => queue.on_unchoke(&info),
+                Some(PeerWireProtocolMessage::Piece(piece)) => {
+                    assert_eq!(ReceivedBlock::New, queue.on_piece(&info, &piece));
+                    verifier.add_block(info, &piece).unwrap();
+                }
+                Some(PeerWireProtocolMessage::BitField(_)) | None => (),
+                Some(message) => panic!("Unexpected Message {:?}", message),
+            }
+
+            queue.fill_requests(&info, |_| true);
+            assert!(queue.num_in_flight(&info) <= 2);
+            while let Some((peer, message)) = queue.poll() {
+                assert_eq!(info, peer);
+                client.send_message(message);
+            }
+
+            while let Some(event) = verifier.poll() {
+                verified.push(event);
+            }
+        }
+
+        verified.sort_by_key(|event| match event {
+            &VerifyEvent::PieceVerified(piece_index) => piece_index,
+            event => panic!("Unexpected Event {:?}", event),
+        });
+        assert_eq!(
+            vec![
+                VerifyEvent::PieceVerified(0),
+                VerifyEvent::PieceVerified(1),
+                VerifyEvent::PieceVerified(2)
+            ],
+            verified
+        );
+        // Block at offset 32 of piece 1 spans both files, and takes a range request for each
+        assert_eq!(11, server.requests.load(Ordering::SeqCst));
+        assert_eq!(0, queue.num_missing());
+    }
+
+    #[test]
+    fn positive_concurrency_limited() {
+        let runtime = Runtime::new().unwrap();
+        let (metainfo, data) = multi_file(100, 60);
+        let server = serve(files(&data, 100), Mode::Range, Duration::from_millis(100));
+        let mut client = client(&server, &metainfo, &runtime).with_max_requests(2);
+        drain(&mut client);
+
+        for offset in (0..PIECE_LEN).step_by(BLOCK_LEN) {
+            client.send_message(PeerWireProtocolMessage::Request(request(0, offset)));
+        }
+        assert_eq!(2, client.num_in_flight());
+        assert_eq!(2, client.num_queued());
+
+        let mut pieces: Vec<_> = wait_for(&mut client, 4)
+            .into_iter()
+            .map(|message| match message {
+                PeerWireProtocolMessage::Piece(piece) => piece,
+                message => panic!("Unexpected Message {:?}", message),
+            })
+            .collect();
+        // Blocks fetched at the same time may finish in any order
+        pieces.sort_by_key(|piece| piece.block_offset());
+
+        assert_eq!(4, pieces.len());
+        for (piece, offset) in pieces.iter().zip((0..PIECE_LEN).step_by(BLOCK_LEN)) {
+            assert_eq!(offset as u32, piece.block_offset());
+            assert_eq!(&data[offset..offset + BLOCK_LEN], &piece.block()[..]);
+        }
+        assert_eq!(2, server.max_concurrent.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn positive_cancel_drops_block() {
+        let runtime = Runtime::new().unwrap();
+        let (metainfo, data) = multi_file(100, 60);
+        let server = serve(files(&data, 100), Mode::Range, Duration::from_millis(50));
+        let mut client = client(&server, &metainfo, &runtime).with_max_requests(1);
+        drain(&mut client);
+
+        client.send_message(PeerWireProtocolMessage::Request(request(0, 0)));
+        client.send_message(PeerWireProtocolMessage::Request(request(0, 16)));
+        client.send_message(PeerWireProtocolMessage::Cancel(CancelMessage::new(
+            0, 0, BLOCK_LEN,
+        )));
+
+        let messages = wait_for(&mut client, 1);
+        match messages[0] {
+            PeerWireProtocolMessage::Piece(ref piece) => assert_eq!(16, piece.block_offset()),
+            ref message => panic!("Unexpected Message {:?}", message),
+        }
+
+        // Cancelled block was still fetched, but is dropped once it arrives
+        thread::sleep(Duration::from_millis(100));
+        assert_eq!(None, client.poll());
+        assert_eq!(2, server.requests.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn negative_error_status_backs_off() {
+        let runtime = Runtime::new().unwrap();
+        let (metainfo, data) = multi_file(100, 60);
+        let server = serve(
+            files(&data, 100),
+            Mode::Status(503),
+            Duration::from_millis(0),
+        );
+        let mut client = client(&server, &metainfo, &runtime).with_max_requests(1);
+        drain(&mut client);
+
+        client.send_message(PeerWireProtocolMessage::Request(request(0, 0)));
+        client.send_message(PeerWireProtocolMessage::Request(request(0, 16)));
+
+        let messages = wait_for(&mut client, 3);
+        assert_eq!(PeerWireProtocolMessage::Choke, messages[0]);
+        assert!(messages[1..]
+            .iter()
+            .all(|message| matches!(message, &PeerWireProtocolMessage::Reject(_))));
+        assert_eq!(3, messages.len());
+        assert!(client.is_backing_off());
+        assert_eq!(1, client.failures());
+
+        // Requests are rejected right away while we are choked
+        client.send_message(PeerWireProtocolMessage::Request(request(1, 0)));
+        assert!(matches!(
+            client.poll(),
+            Some(PeerWireProtocolMessage::Reject(_))
+        ));
+
+        client.tick(Duration::from_secs(5));
+        assert_eq!(None, client.poll());
+        client.tick(Duration::from_secs(5));
+        assert_eq!(Some(PeerWireProtocolMessage::UnChoke), client.poll());
+        assert!(!client.is_backing_off());
+    }
+
+    #[test]
+    fn negative_missing_file_backs_off() {
+        let runtime = Runtime::new().unwrap();
+        let (metainfo, data) = multi_file(100, 60);
+        let mut files = files(&data, 100);
+        files.remove("/seed/dir/b");
+        let server = serve(files, Mode::Range, Duration::from_millis(0));
+        let mut client = client(&server, &metainfo, &runtime);
+        drain(&mut client);
+
+        // First part of the block lies in file a, the rest in the missing file b
+        client.send_message(PeerWireProtocolMessage::Request(request(1, 32)));
+
+        let messages = wait_for(&mut client, 2);
+        assert_eq!(PeerWireProtocolMessage::Choke, messages[0]);
+        assert!(matches!(messages[1], PeerWireProtocolMessage::Reject(_)));
+        assert_eq!(2, server.requests.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn negative_ignored_range_backs_off() {
+        let runtime = Runtime::new().unwrap();
+        let (metainfo, data) = multi_file(100, 60);
+        let server = serve(
+            files(&data, 100),
+            Mode::IgnoreRange,
+            Duration::from_millis(0),
+        );
+        let mut client = client(&server, &metainfo, &runtime);
+        drain(&mut client);
+
+        client.send_message(PeerWireProtocolMessage::Request(request(0, 0)));
+
+        let messages = wait_for(&mut client, 2);
+        assert_eq!(PeerWireProtocolMessage::Choke, messages[0]);
+        assert!(matches!(messages[1], PeerWireProtocolMessage::Reject(_)));
+        assert!(client.is_backing_off());
+    }
+}
@@ -0,0 +1,62 @@
+//! Module for web seed error types.
+
+use std::io;
+
+error_chain! {
+    types {
+        WebSeedError, WebSeedErrorKind, WebSeedResultExt, WebSeedResult;
+    }
+
+    foreign_links {
+        Io(io::Error);
+    }
+
+    errors {
+        InvalidUrl {
+            url: String
+        } {
+            description("Web Seed Url Is Invalid")
+            display("Web Seed Url {:?} Is Invalid", url)
+        }
+        UnsupportedScheme {
+            scheme: String
+        } {
+            description("Web Seed Url Scheme Is Not Supported By The Connector")
+            display("Web Seed Url Scheme {:?} Is Not Supported By The Connector", scheme)
+        }
+        BlockOutOfRange {
+            piece_index: u32,
+            block_offset: u32,
+            block_length: usize
+        } {
+            description("Block Lies Outside Of The Torrent")
+            display("Block At Offset {} With Length {} In Piece {} Lies Outside Of The Torrent",
+                block_offset, block_length, piece_index)
+        }
+        HttpStatus {
+            status: u16,
+            reason: String
+        } {
+            description("Web Seed Responded With A Non Success Status")
+            display("Web Seed Responded With Status {} {}", status, reason)
+        }
+        RangeIgnored {
+            status: u16
+        } {
+            description("Web Seed Ignored The Range Of The Request")
+            display("Web Seed Ignored The Range Of The Request, Responding With Status {}", status)
+        }
+        TooManyRedirects {
+            limit: usize
+        } {
+            description("Web Seed Redirected Too Many Times")
+            display("Web Seed Redirected More Than {} Times", limit)
+        }
+        InvalidResponse {
+            details: String
+        } {
+            description("Web Seed Sent An Invalid Response")
+            display("Web Seed Sent An Invalid Response: {}", details)
+        }
+    }
+}
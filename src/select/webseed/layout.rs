@@ -0,0 +1,357 @@
+use std::fmt::Write;
+use std::path::{Component, Path};
+
+use url::Url;
+
+use crate::metainfo::Info;
+use crate::select::webseed::error::{WebSeedErrorKind, WebSeedResult};
+
+/// Byte range of a single file on a web seed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FileRange {
+    url: Url,
+    first: u64,
+    length: u64,
+    padding: bool,
+}
+
+impl FileRange {
+    /// Url of the file on the web seed.
+    pub fn url(&self) -> &Url {
+        &self.url
+    }
+
+    /// Offset of the first byte of the range within the file.
+    pub fn first(&self) -> u64 {
+        self.first
+    }
+
+    /// Offset of the last byte of the range within the file.
+    pub fn last(&self) -> u64 {
+        self.first + self.length - 1
+    }
+
+    /// Number of bytes in the range.
+    pub fn length(&self) -> u64 {
+        self.length
+    }
+
+    /// Whether or not the file is a padding file, which is never requested and only holds zeroes.
+    pub fn is_padding(&self) -> bool {
+        self.padding
+    }
+}
+
+struct LayoutFile {
+    url: Url,
+    offset: u64,
+    length: u64,
+    padding: bool,
+}
+
+/// Maps blocks of a torrent onto the files of a web seed, following the url rules of BEP 19.
+///
+/// For a single file torrent, a url ending in a slash has the name of the torrent appended,
+/// any other url is the file itself. For a multi file torrent, the url is the parent of the
+/// torrent directory, so each file is found at `<url>/<name>/<path>`.
+pub struct WebSeedLayout {
+    piece_len: u64,
+    total_len: u64,
+    num_pieces: u32,
+    files: Vec<LayoutFile>,
+}
+
+impl WebSeedLayout {
+    /// Create a new `WebSeedLayout` for the web seed at the given url.
+    pub fn new(url: &Url, info: &Info) -> WebSeedResult<WebSeedLayout> {
+        let mut base = url.clone();
+        base.fragment = None;
+
+        let mut files = Vec::new();
+        let mut offset = 0;
+        for file in info.files() {
+            files.push(LayoutFile {
+                url: file_url(&base, info.directory(), file.path())?,
+                offset: offset,
+                length: file.length(),
+                padding: file.is_padding(),
+            });
+
+            offset += file.length();
+        }
+
+        Ok(WebSeedLayout {
+            piece_len: info.piece_length(),
+            total_len: offset,
+            num_pieces: info.pieces().count() as u32,
+            files: files,
+        })
+    }
+
+    /// Number of pieces in the torrent.
+    pub fn num_pieces(&self) -> u32 {
+        self.num_pieces
+    }
+
+    /// Length of the torrent, in bytes.
+    pub fn total_length(&self) -> u64 {
+        self.total_len
+    }
+
+    /// Length of the given piece, which is shorter for the last piece.
+    pub fn piece_length(&self, piece_index: u32) -> u64 {
+        let piece_start = piece_index as u64 * self.piece_len;
+
+        self.piece_len
+            .min(self.total_len.saturating_sub(piece_start))
+    }
+
+    /// Ranges of files making up the given block, in order.
+    ///
+    /// A block spanning the boundary between files is made up of a range of each of them.
+    pub fn ranges(
+        &self,
+        piece_index: u32,
+        block_offset: u32,
+        block_length: usize,
+    ) -> WebSeedResult<Vec<FileRange>> {
+        let block_end = block_offset as u64 + block_length as u64;
+        if piece_index >= self.num_pieces
+            || block_length == 0
+            || block_end > self.piece_length(piece_index)
+        {
+            return Err(WebSeedErrorKind::BlockOutOfRange {
+                piece_index: piece_index,
+                block_offset: block_offset,
+                block_length: block_length,
+            }
+            .into());
+        }
+
+        let start = piece_index as u64 * self.piece_len + block_offset as u64;
+        let end = start + block_length as u64;
+
+        Ok(self
+            .files
+            .iter()
+            .filter(|file| {
+                file.length != 0 && file.offset < end && file.offset + file.length > start
+            })
+            .map(|file| {
+                let first = start.max(file.offset);
+                let last = end.min(file.offset + file.length);
+
+                FileRange {
+                    url: file.url.clone(),
+                    first: first - file.offset,
+                    length: last - first,
+                    padding: file.padding,
+                }
+            })
+            .collect())
+    }
+}
+
+/// Url of the file at the given path, relative to the torrent directory if any.
+fn file_url(base: &Url, opt_directory: Option<&Path>, path: &Path) -> WebSeedResult<Url> {
+    let mut url = base.clone();
+    {
+        let segments = url.path_mut().ok_or_else(|| WebSeedErrorKind::InvalidUrl {
+            url: base.serialize(),
+        })?;
+        let has_trailing_slash = segments.last().map_or(false, |segment| segment.is_empty());
+
+        match opt_directory {
+            None if has_trailing_slash => {
+                segments.pop();
+                push_segments(segments, path);
+            }
+            None => (),
+            Some(directory) => {
+                if has_trailing_slash {
+                    segments.pop();
+                }
+                push_segments(segments, directory);
+                push_segments(segments, path);
+            }
+        }
+    }
+
+    Ok(url)
+}
+
+fn push_segments(segments: &mut Vec<String>, path: &Path) {
+    for component in path.components() {
+        if let Component::Normal(name) = component {
+            let mut segment = String::new();
+            percent_encode(name.to_string_lossy().as_bytes(), &mut segment);
+
+            segments.push(segment);
+        }
+    }
+}
+
+fn percent_encode(bytes: &[u8], output: &mut String) {
+    for &byte in bytes {
+        match byte {
+            b'0'..=b'9' | b'a'..=b'z' | b'A'..=b'Z' | b'-' | b'.' | b'_' | b'~' => {
+                output.push(byte as char)
+            }
+            _ => write!(output, "%{:02X}", byte).unwrap(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use url::Url;
+
+    use super::WebSeedLayout;
+    use crate::metainfo::Metainfo;
+    use crate::select::webseed::error::WebSeedErrorKind;
+
+    fn single_file(length: u64, piece_length: u64) -> Metainfo {
+        let num_pieces = (length + piece_length - 1) / piece_length;
+
+        let mut bytes = format!(
+            "d4:infod6:lengthi{}e4:name8:file.iso12:piece lengthi{}e6:pieces{}:",
+            length,
+            piece_length,
+            num_pieces * 20
+        )
+        .into_bytes();
+        bytes.extend(vec![0u8; num_pieces as usize * 20]);
+        bytes.extend_from_slice(b"ee");
+
+        Metainfo::from_bytes(bytes).unwrap()
+    }
+
+    fn multi_file(files: &[(&str, u64, bool)], piece_length: u64) -> Metainfo {
+        let total_length: u64 = files.iter().map(|&(_, length, _)| length).sum();
+        let num_pieces = (total_length + piece_length - 1) / piece_length;
+
+        let mut bytes = b"d4:infod5:filesl".to_vec();
+        for &(name, length, padding) in files {
+            let attr = if padding { "4:attr1:p" } else { "" };
+
+            bytes.extend_from_slice(
+                format!(
+                    "d{}6:lengthi{}e4:pathl3:sub{}:{}ee",
+                    attr,
+                    length,
+                    name.len(),
+                    name
+                )
+                .as_bytes(),
+            );
+        }
+        bytes.extend_from_slice(
+            format!(
+                "e4:name6:my dir12:piece lengthi{}e6:pieces{}:",
+                piece_length,
+                num_pieces * 20
+            )
+            .as_bytes(),
+        );
+        bytes.extend(vec![0u8; num_pieces as usize * 20]);
+        bytes.extend_from_slice(b"ee");
+
+        Metainfo::from_bytes(bytes).unwrap()
+    }
+
+    fn layout(url: &str, metainfo: &Metainfo) -> WebSeedLayout {
+        WebSeedLayout::new(&Url::parse(url).unwrap(), metainfo.info()).unwrap()
+    }
+
+    #[test]
+    fn positive_single_file_url_is_file() {
+        let metainfo = single_file(100, 64);
+        let layout = layout("http://seed.example/files/renamed.iso#top", &metainfo);
+
+        let ranges = layout.ranges(0, 0, 16).unwrap();
+        assert_eq!(1, ranges.len());
+        assert_eq!(
+            "http://seed.example/files/renamed.iso",
+            ranges[0].url().serialize()
+        );
+    }
+
+    #[test]
+    fn positive_single_file_trailing_slash_appends_name() {
+        let metainfo = single_file(100, 64);
+        let layout = layout("http://seed.example/files/", &metainfo);
+
+        let ranges = layout.ranges(1, 16, 20).unwrap();
+        assert_eq!(
+            "http://seed.example/files/file.iso",
+            ranges[0].url().serialize()
+        );
+        assert_eq!(80, ranges[0].first());
+        assert_eq!(99, ranges[0].last());
+    }
+
+    #[test]
+    fn positive_multi_file_appends_directory_and_path() {
+        let metainfo = multi_file(&[("a b.txt", 10, false)], 16);
+
+        for url in &["http://seed.example/files", "http://seed.example/files/"] {
+            let ranges = layout(url, &metainfo).ranges(0, 0, 10).unwrap();
+
+            assert_eq!(
+                "http://seed.example/files/my%20dir/sub/a%20b.txt",
+                ranges[0].url().serialize()
+            );
+        }
+    }
+
+    #[test]
+    fn positive_block_spanning_files_splits_ranges() {
+        let metainfo = multi_file(&[("a", 10, false), ("b", 0, false), ("c", 30, false)], 16);
+        let layout = layout("http://seed.example/", &metainfo);
+
+        let ranges = layout.ranges(0, 8, 8).unwrap();
+        assert_eq!(2, ranges.len());
+        assert!(ranges[0].url().serialize().ends_with("/sub/a"));
+        assert_eq!((8, 2), (ranges[0].first(), ranges[0].length()));
+        assert!(ranges[1].url().serialize().ends_with("/sub/c"));
+        assert_eq!((0, 6), (ranges[1].first(), ranges[1].length()));
+    }
+
+    #[test]
+    fn positive_padding_ranges_marked() {
+        let metainfo = multi_file(&[("a", 10, false), ("pad", 6, true), ("c", 16, false)], 16);
+        let layout = layout("http://seed.example/", &metainfo);
+
+        let ranges = layout.ranges(0, 0, 16).unwrap();
+        assert!(!ranges[0].is_padding());
+        assert!(ranges[1].is_padding());
+        assert_eq!(6, ranges[1].length());
+    }
+
+    #[test]
+    fn positive_short_last_piece() {
+        let metainfo = single_file(100, 64);
+        let layout = layout("http://seed.example/file.iso", &metainfo);
+
+        assert_eq!(2, layout.num_pieces());
+        assert_eq!(100, layout.total_length());
+        assert_eq!(36, layout.piece_length(1));
+    }
+
+    #[test]
+    fn negative_block_out_of_range() {
+        let metainfo = single_file(100, 64);
+        let layout = layout("http://seed.example/file.iso", &metainfo);
+
+        for &(piece_index, offset, length) in &[(1, 30, 16), (2, 0, 16), (0, 0, 0)] {
+            match layout
+                .ranges(piece_index, offset, length)
+                .unwrap_err()
+                .kind()
+            {
+                &WebSeedErrorKind::BlockOutOfRange { .. } => (),
+                kind => panic!("Unexpected Error {}", kind),
+            }
+        }
+    }
+}
@@ -0,0 +1,9 @@
+//! Module for downloading from web seeds (BEP 19) as if they were peers.
+
+mod client;
+pub use self::client::WebSeedClient;
+
+mod layout;
+pub use self::layout::{FileRange, WebSeedLayout};
+
+pub mod error;
@@ -0,0 +1,372 @@
+use std::collections::HashMap;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::path::PathBuf;
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use futures::executor;
+use futures::StreamExt;
+
+use crate::dht::{DhtBuilder, Router};
+use crate::disk::{DiskManagerBuilder, FileHandleCache, NativeFileSystem};
+use crate::handshake::transports::TcpTransport;
+use crate::handshake::{
+    DiscoveryInfo, Extension, Extensions, HandshakeFilters, HandshakerManagerBuilder,
+};
+use crate::peer::{PeerManager, PeerManagerBuilder};
+use crate::select::ban::{
+    BanList, PeerBanner, DEFAULT_BAN_DURATION_SECS, DEFAULT_BAN_THRESHOLD_BLOCKS,
+};
+use crate::session::error::SessionResult;
+use crate::session::metrics::Metrics;
+use crate::session::policy::SeedPolicy;
+use crate::session::state::StateDir;
+use crate::session::worker::{SessionDiscovery, SessionMessage, SessionSocket, SessionWorker};
+use crate::session::Session;
+use crate::util::bt::PeerId;
+
+const DEFAULT_MAX_GLOBAL_PEERS: usize = 200;
+const DEFAULT_MAX_PEERS_PER_TORRENT: usize = 50;
+const DEFAULT_MAX_HALF_OPEN_CONNECTIONS: usize = 8;
+const DEFAULT_DISK_CAPACITY: usize = 1000;
+const DEFAULT_FILE_HANDLES: usize = 100;
+pub(crate) const DEFAULT_SHUTDOWN_TIMEOUT_MILLIS: u64 = 5 * 1000;
+
+/// Builder for configuring a `Session`.
+#[derive(Clone, Debug)]
+pub struct SessionBuilder {
+    listen_addr: SocketAddr,
+    opt_peer_id: Option<PeerId>,
+    download_root: PathBuf,
+    dht: bool,
+    upload_limit: Option<u64>,
+    download_limit: Option<u64>,
+    max_global_peers: usize,
+    max_peers_per_torrent: usize,
+    max_half_open: usize,
+    ban_threshold: usize,
+    ban_duration: Duration,
+    seed_policy: SeedPolicy,
+    opt_state_dir: Option<PathBuf>,
+    shutdown_timeout: Duration,
+}
+
+impl SessionBuilder {
+    /// Create a new `SessionBuilder`.
+    pub fn new() -> SessionBuilder {
+        SessionBuilder {
+            listen_addr: SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), 0),
+            opt_peer_id: None,
+            download_root: PathBuf::from("."),
+            dht: true,
+            upload_limit: None,
+            download_limit: None,
+            max_global_peers: DEFAULT_MAX_GLOBAL_PEERS,
+            max_peers_per_torrent: DEFAULT_MAX_PEERS_PER_TORRENT,
+            max_half_open: DEFAULT_MAX_HALF_OPEN_CONNECTIONS,
+            ban_threshold: DEFAULT_BAN_THRESHOLD_BLOCKS,
+            ban_duration: Duration::from_secs(DEFAULT_BAN_DURATION_SECS),
+            seed_policy: SeedPolicy::default(),
+            opt_state_dir: None,
+            shutdown_timeout: Duration::from_millis(DEFAULT_SHUTDOWN_TIMEOUT_MILLIS),
+        }
+    }
+
+    /// Address that peers connect to, and that the DHT binds to.
+    ///
+    /// Defaults to IN_ADDR_ANY using port 0 (any free port).
+    pub fn with_listen_addr(mut self, addr: SocketAddr) -> SessionBuilder {
+        self.listen_addr = addr;
+        self
+    }
+
+    /// Port of the listen address, see `with_listen_addr`.
+    pub fn with_listen_port(mut self, port: u16) -> SessionBuilder {
+        self.listen_addr.set_port(port);
+        self
+    }
+
+    /// Peer id advertised to peers, trackers and the DHT.
+    ///
+    /// Defaults to a random peer id.
+    pub fn with_peer_id(mut self, peer_id: PeerId) -> SessionBuilder {
+        self.opt_peer_id = Some(peer_id);
+        self
+    }
+
+    /// Directory that the files of every torrent are relative to.
+    ///
+    /// Defaults to the current directory.
+    pub fn with_download_root<P>(mut self, root: P) -> SessionBuilder
+    where
+        P: Into<PathBuf>,
+    {
+        self.download_root = root.into();
+        self
+    }
+
+    /// Sets whether or not peers are searched for on the mainline DHT.
+    ///
+    /// Defaults to true, torrents marked as private are never searched for.
+    pub fn with_dht(mut self, dht: bool) -> SessionBuilder {
+        self.dht = dht;
+        self
+    }
+
+    /// Limit on piece payload uploaded across every torrent, in bytes per second.
+    pub fn with_upload_limit(mut self, opt_limit: Option<u64>) -> SessionBuilder {
+        self.upload_limit = opt_limit;
+        self
+    }
+
+    /// Limit on piece payload downloaded across every torrent, in bytes per second.
+    pub fn with_download_limit(mut self, opt_limit: Option<u64>) -> SessionBuilder {
+        self.download_limit = opt_limit;
+        self
+    }
+
+    /// Max number of peers connected across every torrent.
+    #[deprecated(note = "use `with_max_global_peers`")]
+    pub fn with_peer_capacity(self, capacity: usize) -> SessionBuilder {
+        self.with_max_global_peers(capacity)
+    }
+
+    /// Max number of peers connected or handshaking across every torrent.
+    ///
+    /// Over the limit, peers connecting to us are closed right away and no peers are dialed.
+    /// Defaults to 200.
+    pub fn with_max_global_peers(mut self, max: usize) -> SessionBuilder {
+        self.max_global_peers = max;
+        self
+    }
+
+    /// Max number of peers connected to any one torrent.
+    ///
+    /// Peers of a torrent at the limit are no longer dialed, and those connecting to us are
+    /// disconnected with `PeerDisconnectReason::TooManyPeers`. While seeding, a peer that is
+    /// seeding as well is disconnected instead, to make room. Defaults to 50.
+    pub fn with_max_peers_per_torrent(mut self, max: usize) -> SessionBuilder {
+        self.max_peers_per_torrent = max;
+        self
+    }
+
+    /// Max number of peers being dialed that have yet to accept the connection.
+    ///
+    /// Defaults to 8, see `ConnectionLimits::set_max_half_open_connections`.
+    pub fn with_max_half_open_connections(mut self, max: usize) -> SessionBuilder {
+        self.max_half_open = max;
+        self
+    }
+
+    /// Number of blocks of pieces that failed their hash check that an address can send us
+    /// before it is banned.
+    ///
+    /// Every peer that sent part of a failed piece is scored, but an address is only banned
+    /// once it was the sole contributor to a failed piece. Defaults to 32.
+    pub fn with_ban_threshold(mut self, blocks: usize) -> SessionBuilder {
+        self.ban_threshold = blocks;
+        self
+    }
+
+    /// Time that a banned address is refused for, see `Session::ban_list`.
+    ///
+    /// Defaults to one hour.
+    pub fn with_ban_duration(mut self, duration: Duration) -> SessionBuilder {
+        self.ban_duration = duration;
+        self
+    }
+
+    /// Conditions for torrents to stop seeding, unless `TorrentOptions` gives them their own.
+    ///
+    /// Defaults to seeding until the torrent is paused or removed.
+    pub fn with_seed_policy(mut self, policy: SeedPolicy) -> SessionBuilder {
+        self.seed_policy = policy;
+        self
+    }
+
+    /// Directory that the DHT state and the resume data of every torrent are saved to on
+    /// shutdown, and loaded from when starting the DHT and adding a torrent.
+    ///
+    /// Resume data is saved as `<info hash in hex>.resume`, the DHT state as `dht.state`.
+    /// Defaults to saving nothing.
+    pub fn with_state_dir<P>(mut self, dir: P) -> SessionBuilder
+    where
+        P: Into<PathBuf>,
+    {
+        self.opt_state_dir = Some(dir.into());
+        self
+    }
+
+    /// Time that trackers are given to answer the `stopped` announce on shutdown, and
+    /// that peers are given to be sent what was queued for them.
+    ///
+    /// Defaults to five seconds.
+    pub fn with_shutdown_timeout(mut self, timeout: Duration) -> SessionBuilder {
+        self.shutdown_timeout = timeout;
+        self
+    }
+
+    /// Start a `Session` with the current configuration.
+    pub fn build(self) -> SessionResult<Session> {
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .enable_all()
+            .thread_name("bittorrent-protocol_session")
+            .build()?;
+        let guard = runtime.enter();
+        let (send, recv) = mpsc::channel();
+        let opt_state = match self.opt_state_dir {
+            Some(ref dir) => Some(StateDir::open(dir)?),
+            None => None,
+        };
+
+        let mut extensions = Extensions::new();
+        extensions.add(Extension::ExtensionProtocol);
+        if self.dht {
+            extensions.add(Extension::Dht);
+        }
+
+        let mut handshaker_builder = HandshakerManagerBuilder::new();
+        handshaker_builder
+            .with_bind_addr(self.listen_addr)
+            .with_extensions(extensions)
+            .with_max_global_peers(Some(self.max_global_peers))
+            .with_max_half_open_connections(self.max_half_open);
+        if let Some(peer_id) = self.opt_peer_id {
+            handshaker_builder.with_peer_id(peer_id);
+        }
+        let (handshaker_send, mut handshaker_recv) =
+            handshaker_builder.build(TcpTransport)?.into_parts();
+        let (port, peer_id) = (handshaker_send.port(), handshaker_send.peer_id());
+        let limits = handshaker_send.connection_limits();
+        let ban_list = BanList::new();
+        handshaker_send.add_filter(ban_list.clone());
+
+        let mut peer_builder = PeerManagerBuilder::new()
+            .with_peer_capacity(self.max_global_peers)
+            .with_torrent_peer_capacity(self.max_peers_per_torrent)
+            .with_shutdown_timeout(self.shutdown_timeout);
+        if self.dht {
+            peer_builder = peer_builder.with_dht_port(port);
+        }
+        let peer_manager: PeerManager<SessionSocket> = peer_builder.build();
+        let limiter = peer_manager.rate_limiter();
+        let capacity = peer_manager.capacity();
+        limiter.set_upload_limit(self.upload_limit);
+        limiter.set_download_limit(self.download_limit);
+        let (peer_send, mut peer_recv) = peer_manager.into_parts();
+
+        let (disk_send, mut disk_recv) = DiskManagerBuilder::new()
+            .with_sink_buffer_capacity(DEFAULT_DISK_CAPACITY)
+            .build(FileHandleCache::new(
+                NativeFileSystem::with_directory(&self.download_root),
+                DEFAULT_FILE_HANDLES,
+            ))
+            .into_parts();
+
+        let discovery = SessionDiscovery::new(send.clone(), peer_id, port);
+        let opt_dht = if self.dht {
+            let mut dht_builder = DhtBuilder::with_router(Router::BitTorrent)
+                .set_source_addr(SocketAddr::new(self.listen_addr.ip(), port))
+                .set_read_only(false);
+            if let Some(dht_state) = opt_state.as_ref().and_then(|state| state.load_dht()) {
+                dht_builder = dht_builder.set_state(dht_state);
+            }
+
+            Some(dht_builder.start_mainline(discovery.clone())?)
+        } else {
+            None
+        };
+
+        // Listener threads of the handshaker never stop, so neither does this one until
+        // a handshake completes after the worker is gone
+        let incoming_send = send.clone();
+        thread::spawn(move || {
+            while let Ok(complete) = handshaker_recv.poll() {
+                if incoming_send
+                    .send(SessionMessage::Incoming(complete))
+                    .is_err()
+                {
+                    break;
+                }
+            }
+        });
+
+        let peer_event_send = send.clone();
+        thread::spawn(move || loop {
+            match peer_recv.poll() {
+                Some(message) => {
+                    if peer_event_send.send(SessionMessage::Peer(message)).is_err() {
+                        break;
+                    }
+                }
+                None if peer_recv.is_closed() => break,
+                None => (),
+            }
+        });
+
+        let disk_event_send = send.clone();
+        thread::spawn(move || {
+            while let Some(message) = executor::block_on(disk_recv.next()) {
+                if disk_event_send.send(SessionMessage::Disk(message)).is_err() {
+                    break;
+                }
+            }
+        });
+
+        let registry = Arc::new(Mutex::new(HashMap::new()));
+        let listeners = Arc::new(Mutex::new(Vec::new()));
+        let metrics = Arc::new(Metrics::default());
+        let worker = SessionWorker::new(
+            discovery,
+            handshaker_send,
+            peer_send,
+            disk_send,
+            opt_dht,
+            registry.clone(),
+            listeners.clone(),
+        )
+        .with_state_dir(opt_state)
+        .with_shutdown_timeout(self.shutdown_timeout)
+        .with_metrics(metrics.clone())
+        .with_seed_policy(self.seed_policy)
+        .with_banner(
+            PeerBanner::new(ban_list.clone())
+                .with_threshold(self.ban_threshold)
+                .with_ban_duration(self.ban_duration),
+        );
+
+        let handle = runtime.handle().clone();
+        let worker = thread::Builder::new()
+            .name("bittorrent-protocol_session".to_string())
+            .spawn(move || {
+                let _guard = handle.enter();
+
+                worker.run(recv);
+            })?;
+
+        drop(guard);
+
+        Ok(Session {
+            send: send,
+            port: port,
+            peer_id: peer_id,
+            limiter: limiter,
+            limits: limits,
+            capacity: capacity,
+            ban_list: ban_list,
+            registry: registry,
+            listeners: listeners,
+            metrics: metrics,
+            opt_worker: Some(worker),
+            opt_runtime: Some(runtime),
+        })
+    }
+}
+
+impl Default for SessionBuilder {
+    fn default() -> SessionBuilder {
+        SessionBuilder::new()
+    }
+}
@@ -0,0 +1,38 @@
+//! Module for session error types.
+
+use std::io;
+
+use crate::util::bt::InfoHash;
+
+error_chain! {
+    types {
+        SessionError, SessionErrorKind, SessionResultExt, SessionResult;
+    }
+
+    foreign_links {
+        Io(io::Error);
+    }
+
+    errors {
+        TorrentExists {
+            hash: InfoHash
+        } {
+            description("Torrent Has Already Been Added")
+            display("Torrent With Hash {:?} Has Already Been Added", hash)
+        }
+        TorrentNotFound {
+            hash: InfoHash
+        } {
+            description("Torrent Was Not Found")
+            display("Torrent With Hash {:?} Was Not Found", hash)
+        }
+        InvalidMagnet {
+            description("Magnet Link Has No BitTorrent V1 Info Hash")
+            display("Magnet Link Has No BitTorrent V1 Info Hash")
+        }
+        SessionShutdown {
+            description("Session Has Shut Down")
+            display("Session Has Shut Down")
+        }
+    }
+}
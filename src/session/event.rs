@@ -0,0 +1,231 @@
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use crate::session::policy::SeedLimit;
+use crate::util::bt::InfoHash;
+
+/// Events emitted by a `Session` for the torrents it runs.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TorrentEvent {
+    /// Torrent was added to the session.
+    Added(InfoHash),
+    /// Metainfo of a torrent added from a magnet link was downloaded from a peer.
+    MetadataReceived(InfoHash),
+    /// Files of the torrent were checked, pieces already on disk count as verified.
+    Checked(InfoHash),
+    /// Connected to a peer for the torrent.
+    PeerConnected(InfoHash, SocketAddr),
+    /// Disconnected from a peer of the torrent.
+    PeerDisconnected(InfoHash, SocketAddr),
+    /// Downloaded piece passed its hash check, and was written to disk.
+    PieceVerified(InfoHash, u64),
+    /// Downloaded piece failed its hash check, it is downloaded again.
+    PieceFailed(InfoHash, u64),
+    /// Every piece of the torrent that is not skipped was downloaded, the torrent is seeded.
+    Completed(InfoHash),
+    /// Seeding torrent reached a limit of its `SeedPolicy`, followed by `Paused` if the
+    /// policy pauses the torrent.
+    SeedLimitReached { hash: InfoHash, which: SeedLimit },
+    /// Torrent was paused, disconnecting from its peers.
+    Paused(InfoHash),
+    /// Torrent was resumed.
+    Resumed(InfoHash),
+    /// Torrent was removed from the session.
+    Removed(InfoHash),
+    /// Disk or network error for the torrent, the torrent keeps running where it can.
+    Error(InfoHash, String),
+    /// Problem with the torrent that does not keep it from running, such as a magnet link
+    /// selecting files that the torrent does not have.
+    Warning(InfoHash, String),
+}
+
+impl TorrentEvent {
+    /// Info hash of the torrent the event is for.
+    pub fn info_hash(&self) -> InfoHash {
+        match *self {
+            TorrentEvent::Added(hash)
+            | TorrentEvent::MetadataReceived(hash)
+            | TorrentEvent::Checked(hash)
+            | TorrentEvent::PeerConnected(hash, _)
+            | TorrentEvent::PeerDisconnected(hash, _)
+            | TorrentEvent::PieceVerified(hash, _)
+            | TorrentEvent::PieceFailed(hash, _)
+            | TorrentEvent::Completed(hash)
+            | TorrentEvent::SeedLimitReached { hash, .. }
+            | TorrentEvent::Paused(hash)
+            | TorrentEvent::Resumed(hash)
+            | TorrentEvent::Removed(hash)
+            | TorrentEvent::Error(hash, _)
+            | TorrentEvent::Warning(hash, _) => hash,
+        }
+    }
+}
+
+/// State of a torrent in a `Session`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TorrentState {
+    /// Waiting on a peer to send us the metainfo of a torrent added from a magnet link.
+    FetchingMetadata,
+    /// Checking which pieces are already on disk.
+    Checking,
+    /// Downloading pieces from peers.
+    Downloading,
+    /// Every piece that is not skipped is on disk, pieces are only uploaded.
+    Seeding,
+    /// Paused, without any peers.
+    Paused,
+}
+
+/// Snapshot of the progress of a torrent in a `Session`.
+///
+/// Rates are in bytes per second, amounts count piece payload only.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct TorrentStats {
+    state: TorrentState,
+    pieces_total: usize,
+    pieces_have: usize,
+    pieces_wanted: usize,
+    downloaded: u64,
+    uploaded: u64,
+    total_downloaded: u64,
+    total_uploaded: u64,
+    seed_time: Duration,
+    wasted: u64,
+    hash_failures: u64,
+    download_rate: f64,
+    upload_rate: f64,
+    peers: usize,
+}
+
+impl TorrentStats {
+    pub(crate) fn new(state: TorrentState) -> TorrentStats {
+        TorrentStats {
+            state: state,
+            pieces_total: 0,
+            pieces_have: 0,
+            pieces_wanted: 0,
+            downloaded: 0,
+            uploaded: 0,
+            total_downloaded: 0,
+            total_uploaded: 0,
+            seed_time: Duration::from_secs(0),
+            wasted: 0,
+            hash_failures: 0,
+            download_rate: 0.0,
+            upload_rate: 0.0,
+            peers: 0,
+        }
+    }
+
+    pub(crate) fn set_state(&mut self, state: TorrentState) {
+        self.state = state;
+    }
+
+    pub(crate) fn set_pieces(&mut self, total: usize, have: usize, wanted: usize) {
+        self.pieces_total = total;
+        self.pieces_have = have;
+        self.pieces_wanted = wanted;
+    }
+
+    pub(crate) fn set_transfer(&mut self, downloaded: u64, uploaded: u64, rates: (f64, f64)) {
+        self.downloaded = downloaded;
+        self.uploaded = uploaded;
+        self.download_rate = rates.0;
+        self.upload_rate = rates.1;
+    }
+
+    pub(crate) fn set_totals(&mut self, downloaded: u64, uploaded: u64, seed_time: Duration) {
+        self.total_downloaded = downloaded;
+        self.total_uploaded = uploaded;
+        self.seed_time = seed_time;
+    }
+
+    pub(crate) fn set_waste(&mut self, wasted: u64, hash_failures: u64) {
+        self.wasted = wasted;
+        self.hash_failures = hash_failures;
+    }
+
+    pub(crate) fn set_peers(&mut self, peers: usize) {
+        self.peers = peers;
+    }
+
+    /// State of the torrent.
+    pub fn state(&self) -> TorrentState {
+        self.state
+    }
+
+    /// Number of pieces in the torrent, zero until the metainfo is known.
+    pub fn pieces_total(&self) -> usize {
+        self.pieces_total
+    }
+
+    /// Number of pieces that are not skipped, that we have verified.
+    pub fn pieces_have(&self) -> usize {
+        self.pieces_have
+    }
+
+    /// Number of pieces that are not skipped.
+    pub fn pieces_wanted(&self) -> usize {
+        self.pieces_wanted
+    }
+
+    /// Piece payload downloaded from peers, including blocks that were discarded.
+    pub fn downloaded(&self) -> u64 {
+        self.downloaded
+    }
+
+    /// Piece payload uploaded to peers.
+    pub fn uploaded(&self) -> u64 {
+        self.uploaded
+    }
+
+    /// Piece payload downloaded from peers, across restarts of the session.
+    pub fn total_downloaded(&self) -> u64 {
+        self.total_downloaded
+    }
+
+    /// Piece payload uploaded to peers, across restarts of the session.
+    pub fn total_uploaded(&self) -> u64 {
+        self.total_uploaded
+    }
+
+    /// Time the torrent was seeded for, across restarts of the session.
+    pub fn seed_time(&self) -> Duration {
+        self.seed_time
+    }
+
+    /// Downloaded piece payload that was thrown away, because we already had the block
+    /// or the piece failed its hash check.
+    pub fn wasted(&self) -> u64 {
+        self.wasted
+    }
+
+    /// Number of downloaded pieces that failed their hash check.
+    pub fn hash_failures(&self) -> u64 {
+        self.hash_failures
+    }
+
+    /// Rate that we are downloading at.
+    pub fn download_rate(&self) -> f64 {
+        self.download_rate
+    }
+
+    /// Rate that we are uploading at.
+    pub fn upload_rate(&self) -> f64 {
+        self.upload_rate
+    }
+
+    /// Number of peers we are connected to for the torrent.
+    pub fn peers(&self) -> usize {
+        self.peers
+    }
+
+    /// Fraction of the wanted pieces that we have, from zero to one.
+    pub fn progress(&self) -> f64 {
+        if self.pieces_wanted == 0 {
+            0.0
+        } else {
+            self.pieces_have as f64 / self.pieces_wanted as f64
+        }
+    }
+}
@@ -0,0 +1,241 @@
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+
+use crate::disk::FilePriority;
+use crate::magnet::MagnetLink;
+use crate::metainfo::Metainfo;
+use crate::select::piece::Availability;
+use crate::session::error::{SessionErrorKind, SessionResult};
+use crate::session::event::{TorrentState, TorrentStats};
+use crate::session::policy::SeedPolicy;
+use crate::session::worker::{SessionCommand, SessionMessage};
+use crate::util::bt::InfoHash;
+
+/// Where the metainfo of a torrent added to a `Session` comes from.
+#[derive(Debug)]
+pub enum TorrentSource {
+    /// Metainfo that we already have.
+    Metainfo(Metainfo),
+    /// Magnet link, the metainfo is downloaded from peers.
+    Magnet(MagnetLink),
+}
+
+impl From<Metainfo> for TorrentSource {
+    fn from(metainfo: Metainfo) -> TorrentSource {
+        TorrentSource::Metainfo(metainfo)
+    }
+}
+
+impl From<MagnetLink> for TorrentSource {
+    fn from(magnet: MagnetLink) -> TorrentSource {
+        TorrentSource::Magnet(magnet)
+    }
+}
+
+/// Options for a torrent added to a `Session`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TorrentOptions {
+    paused: bool,
+    peers: Vec<SocketAddr>,
+    priorities: Vec<FilePriority>,
+    opt_seed_policy: Option<SeedPolicy>,
+}
+
+impl TorrentOptions {
+    /// Create a new `TorrentOptions`, for a torrent that starts right away.
+    pub fn new() -> TorrentOptions {
+        TorrentOptions::default()
+    }
+
+    /// Sets whether or not the torrent is added paused.
+    pub fn with_paused(mut self, paused: bool) -> TorrentOptions {
+        self.paused = paused;
+        self
+    }
+
+    /// Add a peer to connect to, on top of those found through trackers and the DHT.
+    ///
+    /// Peers are connected to whenever the torrent starts or is resumed.
+    pub fn with_peer(mut self, addr: SocketAddr) -> TorrentOptions {
+        self.peers.push(addr);
+        self
+    }
+
+    /// Sets the priority of each file of the torrent, every file is `FilePriority::Normal` by default.
+    pub fn with_file_priorities(mut self, priorities: Vec<FilePriority>) -> TorrentOptions {
+        self.priorities = priorities;
+        self
+    }
+
+    /// Conditions for the torrent to stop seeding, instead of those of the session.
+    pub fn with_seed_policy(mut self, policy: SeedPolicy) -> TorrentOptions {
+        self.opt_seed_policy = Some(policy);
+        self
+    }
+
+    /// Whether or not the torrent is added paused.
+    pub fn paused(&self) -> bool {
+        self.paused
+    }
+
+    /// Peers connected to on top of those found through trackers and the DHT.
+    pub fn peers(&self) -> &[SocketAddr] {
+        &self.peers
+    }
+
+    /// Priority of each file of the torrent, empty if every file is `FilePriority::Normal`.
+    pub fn file_priorities(&self) -> &[FilePriority] {
+        &self.priorities
+    }
+
+    /// Conditions for the torrent to stop seeding, None if those of the session are used.
+    pub fn seed_policy(&self) -> Option<&SeedPolicy> {
+        self.opt_seed_policy.as_ref()
+    }
+
+    pub(crate) fn set_file_priorities(&mut self, priorities: Vec<FilePriority>) {
+        self.priorities = priorities;
+    }
+
+    pub(crate) fn set_seed_policy(&mut self, opt_policy: Option<SeedPolicy>) {
+        self.opt_seed_policy = opt_policy;
+    }
+}
+
+/// State of a torrent shared between its handles and the session.
+pub(crate) struct TorrentShared {
+    stats: Mutex<TorrentStats>,
+    availability: Mutex<Option<Availability>>,
+    removed: AtomicBool,
+}
+
+impl TorrentShared {
+    pub fn new(state: TorrentState) -> TorrentShared {
+        TorrentShared {
+            stats: Mutex::new(TorrentStats::new(state)),
+            availability: Mutex::new(None),
+            removed: AtomicBool::new(false),
+        }
+    }
+
+    pub fn update_stats<F>(&self, update: F)
+    where
+        F: FnOnce(&mut TorrentStats),
+    {
+        update(
+            &mut self
+                .stats
+                .lock()
+                .expect("bittorrent-protocol_session: TorrentShared Failed To Lock Stats"),
+        );
+    }
+
+    pub fn set_availability(&self, availability: Availability) {
+        *self
+            .availability
+            .lock()
+            .expect("bittorrent-protocol_session: TorrentShared Failed To Lock Availability") =
+            Some(availability);
+    }
+
+    pub fn set_removed(&self) {
+        self.removed.store(true, Ordering::SeqCst);
+    }
+}
+
+/// Handle to a torrent running in a `Session`.
+///
+/// Commands are carried out by the session in the background, their outcome is
+/// reported through `Session::events`.
+#[derive(Clone)]
+pub struct TorrentHandle {
+    hash: InfoHash,
+    send: Sender<SessionMessage>,
+    shared: Arc<TorrentShared>,
+}
+
+impl TorrentHandle {
+    pub(crate) fn new(
+        hash: InfoHash,
+        send: Sender<SessionMessage>,
+        shared: Arc<TorrentShared>,
+    ) -> TorrentHandle {
+        TorrentHandle {
+            hash: hash,
+            send: send,
+            shared: shared,
+        }
+    }
+
+    /// Info hash of the torrent.
+    pub fn info_hash(&self) -> InfoHash {
+        self.hash
+    }
+
+    /// Retrieve a snapshot of the progress of the torrent.
+    pub fn stats(&self) -> TorrentStats {
+        *self
+            .shared
+            .stats
+            .lock()
+            .expect("bittorrent-protocol_session: TorrentHandle Failed To Lock Stats")
+    }
+
+    /// Retrieve a snapshot of how many connected peers have each piece.
+    ///
+    /// Snapshots are taken every second, and are `None` until the metainfo is known.
+    pub fn availability(&self) -> Option<Availability> {
+        self.shared
+            .availability
+            .lock()
+            .expect("bittorrent-protocol_session: TorrentHandle Failed To Lock Availability")
+            .clone()
+    }
+
+    /// Whether or not the torrent was removed from the session.
+    pub fn is_removed(&self) -> bool {
+        self.shared.removed.load(Ordering::SeqCst)
+    }
+
+    /// Pause the torrent, disconnecting from its peers and stopping announces.
+    pub fn pause(&self) -> SessionResult<()> {
+        self.send_command(SessionCommand::Pause(self.hash))
+    }
+
+    /// Resume the torrent after it was paused.
+    pub fn resume(&self) -> SessionResult<()> {
+        self.send_command(SessionCommand::Resume(self.hash))
+    }
+
+    /// Remove the torrent from the session, along with its files if `remove_data` is set.
+    pub fn remove(&self, remove_data: bool) -> SessionResult<()> {
+        self.send_command(SessionCommand::Remove(self.hash, remove_data))
+    }
+
+    /// Change the priority of each file of the torrent.
+    ///
+    /// Pieces lying wholly inside skipped files are not downloaded. For a torrent added from
+    /// a magnet link, the priorities are applied once the metainfo is downloaded.
+    pub fn set_file_priorities(&self, priorities: Vec<FilePriority>) -> SessionResult<()> {
+        self.send_command(SessionCommand::SetFilePriorities(self.hash, priorities))
+    }
+
+    /// Change the conditions for the torrent to stop seeding, None for those of the session.
+    ///
+    /// Limits are checked anew, even if the torrent already reached one.
+    pub fn set_seed_policy(&self, opt_policy: Option<SeedPolicy>) -> SessionResult<()> {
+        self.send_command(SessionCommand::SetSeedPolicy(self.hash, opt_policy))
+    }
+
+    fn send_command(&self, command: SessionCommand) -> SessionResult<()> {
+        if self.is_removed() {
+            return Err(SessionErrorKind::TorrentNotFound { hash: self.hash }.into());
+        }
+
+        self.send
+            .send(SessionMessage::Command(command))
+            .map_err(|_| SessionErrorKind::SessionShutdown.into())
+    }
+}
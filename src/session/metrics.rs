@@ -0,0 +1,381 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::dht::DhtStatus;
+use crate::disk::BlockCacheStats;
+use crate::htracker::TrackerStatus;
+use crate::session::event::TorrentStats;
+use crate::util::bt::InfoHash;
+
+/// Registry updated by the worker and the torrents of a `Session`, read by `Session::metrics`.
+///
+/// Counters are only ever added to. Gauges are published by the worker on every tick.
+#[derive(Default)]
+pub(crate) struct Metrics {
+    bytes_downloaded: AtomicU64,
+    bytes_uploaded: AtomicU64,
+    bytes_wasted: AtomicU64,
+    pieces_verified: AtomicU64,
+    hash_failures: AtomicU64,
+    peers_connected: AtomicU64,
+    peers_disconnected: AtomicU64,
+    disk_queue_depth: AtomicUsize,
+    gauges: Mutex<Gauges>,
+}
+
+#[derive(Default)]
+struct Gauges {
+    block_cache: BlockCacheStats,
+    opt_dht: Option<DhtStatus>,
+    trackers: HashMap<InfoHash, Vec<TrackerStatus>>,
+}
+
+impl Metrics {
+    pub fn add_downloaded(&self, bytes: u64) {
+        self.bytes_downloaded.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    pub fn add_uploaded(&self, bytes: u64) {
+        self.bytes_uploaded.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    pub fn add_wasted(&self, bytes: u64) {
+        self.bytes_wasted.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    pub fn add_piece_verified(&self) {
+        self.pieces_verified.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn add_hash_failure(&self) {
+        self.hash_failures.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn add_peer_connected(&self) {
+        self.peers_connected.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn add_peer_disconnected(&self) {
+        self.peers_disconnected.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn set_disk_queue_depth(&self, depth: usize) {
+        self.disk_queue_depth.store(depth, Ordering::Relaxed);
+    }
+
+    /// Publish the gauges that are read off the disk manager, the DHT and the trackers.
+    pub fn set_gauges(
+        &self,
+        block_cache: BlockCacheStats,
+        opt_dht: Option<DhtStatus>,
+        trackers: HashMap<InfoHash, Vec<TrackerStatus>>,
+    ) {
+        let mut gauges = self
+            .gauges
+            .lock()
+            .expect("bittorrent-protocol_session: Metrics Failed To Lock Gauges");
+
+        gauges.block_cache = block_cache;
+        gauges.opt_dht = opt_dht;
+        gauges.trackers = trackers;
+    }
+
+    /// Copy the registry out, along with the stats of the given torrents.
+    pub fn snapshot(&self, torrents: Vec<(InfoHash, TorrentStats)>) -> MetricsSnapshot {
+        let gauges = self
+            .gauges
+            .lock()
+            .expect("bittorrent-protocol_session: Metrics Failed To Lock Gauges");
+
+        let torrents = torrents
+            .into_iter()
+            .map(|(hash, stats)| TorrentMetrics {
+                info_hash: hash,
+                stats: stats,
+                trackers: gauges.trackers.get(&hash).cloned().unwrap_or_default(),
+            })
+            .collect();
+
+        MetricsSnapshot {
+            bytes_downloaded: self.bytes_downloaded.load(Ordering::Relaxed),
+            bytes_uploaded: self.bytes_uploaded.load(Ordering::Relaxed),
+            bytes_wasted: self.bytes_wasted.load(Ordering::Relaxed),
+            pieces_verified: self.pieces_verified.load(Ordering::Relaxed),
+            hash_failures: self.hash_failures.load(Ordering::Relaxed),
+            peers_connected: self.peers_connected.load(Ordering::Relaxed),
+            peers_disconnected: self.peers_disconnected.load(Ordering::Relaxed),
+            disk_queue_depth: self.disk_queue_depth.load(Ordering::Relaxed),
+            block_cache: gauges.block_cache,
+            opt_dht: gauges.opt_dht,
+            torrents: torrents,
+        }
+    }
+}
+
+//----------------------------------------------------------------------------//
+
+/// Metrics of a single torrent in a `MetricsSnapshot`.
+#[derive(Clone, Debug)]
+pub struct TorrentMetrics {
+    info_hash: InfoHash,
+    stats: TorrentStats,
+    trackers: Vec<TrackerStatus>,
+}
+
+impl TorrentMetrics {
+    /// Info hash of the torrent.
+    pub fn info_hash(&self) -> InfoHash {
+        self.info_hash
+    }
+
+    /// Progress, transfer and peers of the torrent, as returned by `TorrentHandle::stats`.
+    pub fn stats(&self) -> &TorrentStats {
+        &self.stats
+    }
+
+    /// Status of every tracker of the torrent, empty while it is paused or if it has none.
+    pub fn trackers(&self) -> &[TrackerStatus] {
+        &self.trackers
+    }
+}
+
+/// Copy of the metrics of a `Session`, taken by `Session::metrics`.
+///
+/// Counters never decrease over the lifetime of the session, gauges are as of the last
+/// tick of the session, which runs every 100 milliseconds. Amounts count piece payload only.
+///
+/// The names returned by `counters` and `gauges` are stable, such that an exporter can map
+/// them onto metric names like `bittorrent_bytes_downloaded_total`.
+#[derive(Clone, Debug)]
+pub struct MetricsSnapshot {
+    bytes_downloaded: u64,
+    bytes_uploaded: u64,
+    bytes_wasted: u64,
+    pieces_verified: u64,
+    hash_failures: u64,
+    peers_connected: u64,
+    peers_disconnected: u64,
+    disk_queue_depth: usize,
+    block_cache: BlockCacheStats,
+    opt_dht: Option<DhtStatus>,
+    torrents: Vec<TorrentMetrics>,
+}
+
+impl MetricsSnapshot {
+    /// Piece payload downloaded across every torrent, including wasted bytes. Never decreases.
+    pub fn bytes_downloaded(&self) -> u64 {
+        self.bytes_downloaded
+    }
+
+    /// Piece payload uploaded across every torrent. Never decreases.
+    pub fn bytes_uploaded(&self) -> u64 {
+        self.bytes_uploaded
+    }
+
+    /// Downloaded bytes that were thrown away, because we already had the block or the
+    /// piece failed its hash check. Never decreases.
+    pub fn bytes_wasted(&self) -> u64 {
+        self.bytes_wasted
+    }
+
+    /// Number of downloaded pieces that passed their hash check. Never decreases.
+    pub fn pieces_verified(&self) -> u64 {
+        self.pieces_verified
+    }
+
+    /// Number of downloaded pieces that failed their hash check. Never decreases.
+    pub fn hash_failures(&self) -> u64 {
+        self.hash_failures
+    }
+
+    /// Number of peer connections made for any torrent. Never decreases.
+    pub fn peers_connected(&self) -> u64 {
+        self.peers_connected
+    }
+
+    /// Number of peer connections that were closed. Never decreases.
+    pub fn peers_disconnected(&self) -> u64 {
+        self.peers_disconnected
+    }
+
+    /// Number of peers we are currently connected to, across every torrent.
+    pub fn peers(&self) -> usize {
+        self.torrents
+            .iter()
+            .map(|torrent| torrent.stats.peers())
+            .sum()
+    }
+
+    /// Number of messages queued for, or being executed by, the disk manager.
+    pub fn disk_queue_depth(&self) -> usize {
+        self.disk_queue_depth
+    }
+
+    /// Hit and miss counters of the block cache of the disk manager.
+    pub fn block_cache(&self) -> BlockCacheStats {
+        self.block_cache
+    }
+
+    /// Fraction of blocks loaded from the block cache, from zero to one.
+    pub fn cache_hit_rate(&self) -> f64 {
+        let total = self.block_cache.hits() + self.block_cache.misses();
+
+        if total == 0 {
+            0.0
+        } else {
+            self.block_cache.hits() as f64 / total as f64
+        }
+    }
+
+    /// Status of our DHT node, if the DHT is enabled.
+    pub fn dht(&self) -> Option<&DhtStatus> {
+        self.opt_dht.as_ref()
+    }
+
+    /// Metrics of every torrent.
+    pub fn torrents(&self) -> &[TorrentMetrics] {
+        &self.torrents
+    }
+
+    /// Name and value of every counter, names end in `_total`.
+    pub fn counters(&self) -> Vec<(&'static str, u64)> {
+        let mut counters = vec![
+            ("bytes_downloaded_total", self.bytes_downloaded),
+            ("bytes_uploaded_total", self.bytes_uploaded),
+            ("bytes_wasted_total", self.bytes_wasted),
+            ("pieces_verified_total", self.pieces_verified),
+            ("hash_failures_total", self.hash_failures),
+            ("peers_connected_total", self.peers_connected),
+            ("peers_disconnected_total", self.peers_disconnected),
+            ("block_cache_hits_total", self.block_cache.hits()),
+            ("block_cache_misses_total", self.block_cache.misses()),
+        ];
+        if let Some(ref dht) = self.opt_dht {
+            counters.push(("dht_lookups_started_total", dht.lookups_started()));
+            counters.push(("dht_lookups_completed_total", dht.lookups_completed()));
+        }
+
+        counters
+    }
+
+    /// Name and value of every gauge.
+    pub fn gauges(&self) -> Vec<(&'static str, u64)> {
+        let mut gauges = vec![
+            ("torrents", self.torrents.len() as u64),
+            ("peers", self.peers() as u64),
+            ("disk_queue_depth", self.disk_queue_depth as u64),
+            ("block_cache_bytes", self.block_cache.cached_bytes() as u64),
+        ];
+        if let Some(ref dht) = self.opt_dht {
+            gauges.push(("dht_nodes", dht.nodes() as u64));
+        }
+
+        gauges
+    }
+
+    /// Rates of the counters between an earlier snapshot and this one, taken the given
+    /// duration apart.
+    pub fn rates(&self, earlier: &MetricsSnapshot, elapsed: Duration) -> MetricsRates {
+        let secs = elapsed.as_secs_f64();
+        let rate = |now: u64, then: u64| {
+            if secs > 0.0 {
+                now.saturating_sub(then) as f64 / secs
+            } else {
+                0.0
+            }
+        };
+
+        MetricsRates {
+            download: rate(self.bytes_downloaded, earlier.bytes_downloaded),
+            upload: rate(self.bytes_uploaded, earlier.bytes_uploaded),
+            wasted: rate(self.bytes_wasted, earlier.bytes_wasted),
+            pieces_verified: rate(self.pieces_verified, earlier.pieces_verified),
+            hash_failures: rate(self.hash_failures, earlier.hash_failures),
+        }
+    }
+}
+
+/// Rates derived from two `MetricsSnapshot`s, per second.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct MetricsRates {
+    download: f64,
+    upload: f64,
+    wasted: f64,
+    pieces_verified: f64,
+    hash_failures: f64,
+}
+
+impl MetricsRates {
+    /// Bytes downloaded per second.
+    pub fn download(&self) -> f64 {
+        self.download
+    }
+
+    /// Bytes uploaded per second.
+    pub fn upload(&self) -> f64 {
+        self.upload
+    }
+
+    /// Bytes wasted per second.
+    pub fn wasted(&self) -> f64 {
+        self.wasted
+    }
+
+    /// Pieces verified per second.
+    pub fn pieces_verified(&self) -> f64 {
+        self.pieces_verified
+    }
+
+    /// Hash failures per second.
+    pub fn hash_failures(&self) -> f64 {
+        self.hash_failures
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::Metrics;
+
+    #[test]
+    fn positive_rates_between_snapshots() {
+        let metrics = Metrics::default();
+        let earlier = metrics.snapshot(Vec::new());
+
+        metrics.add_downloaded(4000);
+        metrics.add_uploaded(1000);
+        metrics.add_wasted(500);
+        metrics.add_piece_verified();
+        metrics.add_piece_verified();
+        let later = metrics.snapshot(Vec::new());
+
+        let rates = later.rates(&earlier, Duration::from_secs(2));
+        assert_eq!(2000.0, rates.download());
+        assert_eq!(500.0, rates.upload());
+        assert_eq!(250.0, rates.wasted());
+        assert_eq!(1.0, rates.pieces_verified());
+        assert_eq!(0.0, rates.hash_failures());
+
+        let rates = later.rates(&earlier, Duration::from_secs(0));
+        assert_eq!(0.0, rates.download());
+    }
+
+    #[test]
+    fn positive_counters_named_total() {
+        let metrics = Metrics::default();
+        metrics.add_hash_failure();
+        metrics.set_disk_queue_depth(3);
+        let snapshot = metrics.snapshot(Vec::new());
+
+        assert!(snapshot
+            .counters()
+            .iter()
+            .all(|(name, _)| name.ends_with("_total")));
+        assert!(snapshot.counters().contains(&("hash_failures_total", 1)));
+        assert!(snapshot.gauges().contains(&("disk_queue_depth", 3)));
+        assert_eq!(0.0, snapshot.cache_hit_rate());
+    }
+}
@@ -0,0 +1,284 @@
+//! Session tying the handshaker, peer manager, trackers, DHT, disk manager and
+//! selection modules together, downloading and seeding any number of torrents.
+//!
+//! Peers are found through http trackers, the mainline DHT, and the peers given in
+//! `TorrentOptions`. Pieces are picked rarest first, and peers are choked tit-for-tat.
+//! Torrents added from a magnet link download their metainfo from peers first.
+//!
+//! # Examples
+//!
+//! ```no_run
+//!     use bittorrent_protocol::metainfo::Metainfo;
+//!     use bittorrent_protocol::session::{Session, TorrentEvent, TorrentOptions};
+//!
+//!     let metainfo = Metainfo::from_bytes(std::fs::read("music.torrent").unwrap()).unwrap();
+//!
+//!     let session = Session::builder().with_download_root("downloads").build().unwrap();
+//!     let events = session.events();
+//!     let torrent = session.add_torrent(metainfo, TorrentOptions::new()).unwrap();
+//!
+//!     for event in events.iter() {
+//!         if event == TorrentEvent::Completed(torrent.info_hash()) {
+//!             break;
+//!         }
+//!     }
+//! ```
+//!
+//! # Instrumentation
+//!
+//! Every module logs through `tracing`, falling back to `log` when no subscriber is set,
+//! so `RUST_LOG=bittorrent_protocol=debug` tells the story of a download. Each peer
+//! connection runs in a `peer` span with `addr`, `peer_id` and `info_hash` fields.
+//! Choking, interest, pieces verified or failed, disk errors and DHT lookup hops are
+//! logged at `DEBUG` or above, while messages and blocks are only logged at `TRACE`.
+//!
+//! Events worth counting carry a `monotonic_counter.<name> = 1` field, such as
+//! `pieces_verified`, `pieces_failed`, `disk_errors`, `peers_connected` and
+//! `dht_lookup_hops`, for subscribers that turn those into metrics.
+//!
+//! # Metrics
+//!
+//! `Session::metrics` copies out the counters and gauges of the session, such as the bytes
+//! transferred and wasted, hash failures, disk queue depth, block cache hits, DHT nodes and
+//! lookups, and the progress, peers and tracker status of every torrent.
+
+use std::collections::hash_map::Entry;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+
+use futures::channel::oneshot;
+use tokio::runtime::Runtime;
+
+use crate::handshake::ConnectionLimits;
+use crate::peer::{PeerCapacity, RateLimiter};
+use crate::select::ban::BanList;
+use crate::session::error::{SessionErrorKind, SessionResult};
+use crate::session::handle::TorrentShared;
+use crate::session::metrics::Metrics;
+use crate::session::worker::{Listeners, Registry, SessionCommand, SessionMessage};
+use crate::util::bt::{InfoHash, PeerId};
+
+mod builder;
+pub use self::builder::SessionBuilder;
+
+pub mod error;
+
+mod event;
+pub use self::event::{TorrentEvent, TorrentState, TorrentStats};
+
+mod handle;
+pub use self::handle::{TorrentHandle, TorrentOptions, TorrentSource};
+
+mod metrics;
+pub use self::metrics::{MetricsRates, MetricsSnapshot, TorrentMetrics};
+
+mod policy;
+pub use self::policy::{SeedLimit, SeedPolicy};
+
+mod state;
+
+mod torrent;
+
+mod worker;
+
+/// Runs torrents in the background, see the module documentation.
+///
+/// Dropping the `Session` shuts it down like `shutdown`, blocking until it is done.
+pub struct Session {
+    send: Sender<SessionMessage>,
+    port: u16,
+    peer_id: PeerId,
+    limiter: RateLimiter,
+    limits: ConnectionLimits,
+    capacity: PeerCapacity,
+    ban_list: BanList,
+    registry: Registry,
+    listeners: Listeners,
+    metrics: Arc<Metrics>,
+    opt_worker: Option<JoinHandle<()>>,
+    opt_runtime: Option<Runtime>,
+}
+
+impl Session {
+    /// Create a new `SessionBuilder`.
+    pub fn builder() -> SessionBuilder {
+        SessionBuilder::new()
+    }
+
+    /// Add a torrent from its metainfo, or from a magnet link.
+    ///
+    /// Files that a magnet link selects with `so` are the only ones downloaded, unless file
+    /// priorities are set before its metainfo is downloaded. Selected files that the torrent
+    /// does not have are reported with `TorrentEvent::Warning`.
+    ///
+    /// Fails if the torrent was already added, or if the magnet link has no v1 info hash.
+    /// Metadata of magnet links that only name a v2 info hash can not be verified, so those
+    /// are rejected.
+    pub fn add_torrent<T>(&self, source: T, options: TorrentOptions) -> SessionResult<TorrentHandle>
+    where
+        T: Into<TorrentSource>,
+    {
+        let source = source.into();
+        let (hash, state) = match source {
+            TorrentSource::Metainfo(ref metainfo) => {
+                (metainfo.info().info_hash(), TorrentState::Checking)
+            }
+            TorrentSource::Magnet(ref magnet) => (
+                magnet
+                    .get_info_hash()
+                    .ok_or(SessionErrorKind::InvalidMagnet)?,
+                TorrentState::FetchingMetadata,
+            ),
+        };
+        let state = if options.paused() {
+            TorrentState::Paused
+        } else {
+            state
+        };
+
+        let mut registry = self
+            .registry
+            .lock()
+            .expect("bittorrent-protocol_session: Session Failed To Lock Registry");
+        let entry = match registry.entry(hash) {
+            Entry::Occupied(_) => return Err(SessionErrorKind::TorrentExists { hash: hash }.into()),
+            Entry::Vacant(vac) => vac,
+        };
+
+        let shared = Arc::new(TorrentShared::new(state));
+        let handle = TorrentHandle::new(hash, self.send.clone(), shared.clone());
+        self.send
+            .send(SessionMessage::Command(SessionCommand::AddTorrent(
+                hash, source, options, shared,
+            )))
+            .map_err(|_| SessionErrorKind::SessionShutdown)?;
+
+        Ok(entry.insert(handle).clone())
+    }
+
+    /// Retrieve the handle of the torrent with the given info hash.
+    pub fn torrent(&self, hash: InfoHash) -> Option<TorrentHandle> {
+        self.registry
+            .lock()
+            .expect("bittorrent-protocol_session: Session Failed To Lock Registry")
+            .get(&hash)
+            .cloned()
+    }
+
+    /// Retrieve the handles of every torrent.
+    pub fn torrents(&self) -> Vec<TorrentHandle> {
+        self.registry
+            .lock()
+            .expect("bittorrent-protocol_session: Session Failed To Lock Registry")
+            .values()
+            .cloned()
+            .collect()
+    }
+
+    /// Receive the events of every torrent from now on.
+    ///
+    /// Each call returns a receiver of its own, dropping it unsubscribes.
+    pub fn events(&self) -> Receiver<TorrentEvent> {
+        let (send, recv) = mpsc::channel();
+
+        self.listeners
+            .lock()
+            .expect("bittorrent-protocol_session: Session Failed To Lock Listeners")
+            .push(send);
+
+        recv
+    }
+
+    /// Take a snapshot of the metrics of the session and of every torrent.
+    ///
+    /// Snapshots are cheap, a status screen can take one every second and pass the previous
+    /// one to `MetricsSnapshot::rates`.
+    pub fn metrics(&self) -> MetricsSnapshot {
+        let torrents = self
+            .registry
+            .lock()
+            .expect("bittorrent-protocol_session: Session Failed To Lock Registry")
+            .values()
+            .map(|handle| (handle.info_hash(), handle.stats()))
+            .collect();
+
+        self.metrics.snapshot(torrents)
+    }
+
+    /// Port that peers connect to.
+    pub fn listen_port(&self) -> u16 {
+        self.port
+    }
+
+    /// Peer id advertised to peers, trackers and the DHT.
+    pub fn peer_id(&self) -> PeerId {
+        self.peer_id
+    }
+
+    /// Limit piece payload uploaded across every torrent, in bytes per second.
+    pub fn set_upload_limit(&self, opt_limit: Option<u64>) {
+        self.limiter.set_upload_limit(opt_limit);
+    }
+
+    /// Limit piece payload downloaded across every torrent, in bytes per second.
+    pub fn set_download_limit(&self, opt_limit: Option<u64>) {
+        self.limiter.set_download_limit(opt_limit);
+    }
+
+    /// Max number of peers connected or handshaking across every torrent.
+    ///
+    /// Lowering the limit leaves the peers that were already connected alone.
+    pub fn set_max_global_peers(&self, max: usize) {
+        self.limits.set_max_global_peers(Some(max));
+        self.capacity.set_peer_capacity(max);
+    }
+
+    /// Max number of peers connected to any one torrent.
+    ///
+    /// Lowering the limit leaves the peers that were already connected alone.
+    pub fn set_max_peers_per_torrent(&self, max: usize) {
+        self.capacity.set_torrent_peer_capacity(max);
+    }
+
+    /// Max number of peers being dialed that have yet to accept the connection.
+    pub fn set_max_half_open_connections(&self, max: usize) {
+        self.limits.set_max_half_open_connections(max);
+    }
+
+    /// Addresses banned for sending blocks of pieces that failed their hash check.
+    ///
+    /// The list is shared with the session, addresses unbanned through it can connect again.
+    pub fn ban_list(&self) -> BanList {
+        self.ban_list.clone()
+    }
+
+    /// Stop every torrent, resolving once everything that can outlive the session was saved.
+    ///
+    /// In order: trackers are announced `stopped`, peers are closed once they were sent what
+    /// was queued for them, blocks being written are flushed and synced, then the resume data
+    /// of every torrent and the DHT state are saved to the state directory, if any. Trackers
+    /// and peers are given at most the shutdown timeout of the `SessionBuilder`.
+    ///
+    /// Torrents can not be added afterwards. Calls after the first return at once.
+    pub async fn shutdown(&self) {
+        let (send, recv) = oneshot::channel();
+
+        if self.send.send(SessionMessage::Shutdown(Some(send))).is_ok() {
+            let _ = recv.await;
+        }
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        let _ = self.send.send(SessionMessage::Shutdown(None));
+
+        if let Some(worker) = self.opt_worker.take() {
+            let _ = worker.join();
+        }
+        if let Some(runtime) = self.opt_runtime.take() {
+            runtime.shutdown_background();
+        }
+    }
+}
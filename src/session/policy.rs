@@ -0,0 +1,253 @@
+use std::f64;
+use std::time::Duration;
+
+/// Condition of a `SeedPolicy` that a torrent reached.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SeedLimit {
+    /// Uploaded the ratio limit times what was downloaded.
+    Ratio,
+    /// Seeded for the seed time limit.
+    SeedTime,
+    /// Uploaded nothing while seeding for the idle limit.
+    Idle,
+    /// Trackers report more seeds per leecher than the limit.
+    SeedsPerLeecher,
+}
+
+/// Conditions for a torrent in a `Session` to stop seeding.
+///
+/// Checked while the torrent is seeding, the first condition reached emits
+/// `TorrentEvent::SeedLimitReached`, and pauses the torrent if auto pause is set. Either
+/// happens once; a torrent resumed after reaching a limit seeds until its policy changes.
+/// Every limit is off by default.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct SeedPolicy {
+    opt_ratio_limit: Option<f64>,
+    opt_seed_time_limit: Option<Duration>,
+    opt_idle_limit: Option<Duration>,
+    opt_seeds_per_leecher_limit: Option<f64>,
+    size_fallback: bool,
+    auto_pause: bool,
+}
+
+/// Amounts a `SeedPolicy` is checked against.
+#[derive(Copy, Clone, Debug, Default)]
+pub(crate) struct SeedStatus {
+    pub uploaded: u64,
+    pub downloaded: u64,
+    pub total_length: u64,
+    pub seed_time: Duration,
+    pub idle_time: Duration,
+    // Seeds and leechers in the swarm, as last reported by trackers
+    pub opt_swarm: Option<(u64, u64)>,
+}
+
+impl SeedPolicy {
+    /// Create a new `SeedPolicy` without any limits.
+    pub fn new() -> SeedPolicy {
+        SeedPolicy::default()
+    }
+
+    /// Stop seeding once we uploaded this many times what we downloaded.
+    ///
+    /// Nothing downloaded, as for a torrent that we seeded from the start, makes for an
+    /// infinite ratio unless the size fallback is set.
+    pub fn with_ratio_limit(mut self, opt_ratio: Option<f64>) -> SeedPolicy {
+        self.opt_ratio_limit = opt_ratio;
+        self
+    }
+
+    /// Stop seeding once the torrent was seeded for this long, across restarts.
+    pub fn with_seed_time_limit(mut self, opt_limit: Option<Duration>) -> SeedPolicy {
+        self.opt_seed_time_limit = opt_limit;
+        self
+    }
+
+    /// Stop seeding once nothing was uploaded for this long.
+    pub fn with_idle_limit(mut self, opt_limit: Option<Duration>) -> SeedPolicy {
+        self.opt_idle_limit = opt_limit;
+        self
+    }
+
+    /// Stop seeding once there are more seeds per leecher in the swarm than this.
+    ///
+    /// Counts are those that the trackers of the torrent reported on their last announce,
+    /// the limit is not checked until a tracker reported any.
+    pub fn with_seeds_per_leecher_limit(mut self, opt_limit: Option<f64>) -> SeedPolicy {
+        self.opt_seeds_per_leecher_limit = opt_limit;
+        self
+    }
+
+    /// Sets whether or not the ratio of a torrent that nothing was downloaded for is taken
+    /// against the size of the torrent.
+    pub fn with_size_fallback(mut self, size_fallback: bool) -> SeedPolicy {
+        self.size_fallback = size_fallback;
+        self
+    }
+
+    /// Sets whether or not the torrent is paused once a limit is reached.
+    pub fn with_auto_pause(mut self, auto_pause: bool) -> SeedPolicy {
+        self.auto_pause = auto_pause;
+        self
+    }
+
+    /// Ratio that seeding stops at, if any.
+    pub fn ratio_limit(&self) -> Option<f64> {
+        self.opt_ratio_limit
+    }
+
+    /// Seed time that seeding stops at, if any.
+    pub fn seed_time_limit(&self) -> Option<Duration> {
+        self.opt_seed_time_limit
+    }
+
+    /// Time without uploading that seeding stops at, if any.
+    pub fn idle_limit(&self) -> Option<Duration> {
+        self.opt_idle_limit
+    }
+
+    /// Seeds per leecher in the swarm that seeding stops at, if any.
+    pub fn seeds_per_leecher_limit(&self) -> Option<f64> {
+        self.opt_seeds_per_leecher_limit
+    }
+
+    /// Whether or not the ratio falls back to the size of the torrent.
+    pub fn size_fallback(&self) -> bool {
+        self.size_fallback
+    }
+
+    /// Whether or not the torrent is paused once a limit is reached.
+    pub fn auto_pause(&self) -> bool {
+        self.auto_pause
+    }
+
+    /// Ratio of uploaded to downloaded bytes, see `with_ratio_limit`.
+    pub fn ratio(&self, uploaded: u64, downloaded: u64, total_length: u64) -> f64 {
+        let base = if downloaded == 0 && self.size_fallback {
+            total_length
+        } else {
+            downloaded
+        };
+
+        if base == 0 {
+            f64::INFINITY
+        } else {
+            uploaded as f64 / base as f64
+        }
+    }
+
+    /// First limit that the torrent reached, if any.
+    pub(crate) fn limit_reached(&self, status: &SeedStatus) -> Option<SeedLimit> {
+        let ratio = self.ratio(status.uploaded, status.downloaded, status.total_length);
+
+        if self.opt_ratio_limit.map_or(false, |limit| ratio >= limit) {
+            Some(SeedLimit::Ratio)
+        } else if self
+            .opt_seed_time_limit
+            .map_or(false, |limit| status.seed_time >= limit)
+        {
+            Some(SeedLimit::SeedTime)
+        } else if self
+            .opt_idle_limit
+            .map_or(false, |limit| status.idle_time >= limit)
+        {
+            Some(SeedLimit::Idle)
+        } else {
+            match (self.opt_seeds_per_leecher_limit, status.opt_swarm) {
+                // Without leechers nobody is left to seed to
+                (Some(_), Some((seeds, 0))) if seeds > 0 => Some(SeedLimit::SeedsPerLeecher),
+                (Some(limit), Some((seeds, leechers))) if leechers > 0 => {
+                    if seeds as f64 / leechers as f64 > limit {
+                        Some(SeedLimit::SeedsPerLeecher)
+                    } else {
+                        None
+                    }
+                }
+                _ => None,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f64;
+    use std::time::Duration;
+
+    use super::{SeedLimit, SeedPolicy, SeedStatus};
+
+    const TOTAL_LENGTH: u64 = 1000;
+
+    fn status(uploaded: u64, downloaded: u64) -> SeedStatus {
+        SeedStatus {
+            uploaded: uploaded,
+            downloaded: downloaded,
+            total_length: TOTAL_LENGTH,
+            ..SeedStatus::default()
+        }
+    }
+
+    #[test]
+    fn positive_ratio_limit_reached() {
+        let policy = SeedPolicy::new().with_ratio_limit(Some(2.0));
+
+        assert_eq!(None, policy.limit_reached(&status(1999, 1000)));
+        assert_eq!(
+            Some(SeedLimit::Ratio),
+            policy.limit_reached(&status(2000, 1000))
+        );
+    }
+
+    #[test]
+    fn positive_nothing_downloaded_is_infinite_ratio() {
+        let policy = SeedPolicy::new().with_ratio_limit(Some(2.0));
+
+        assert_eq!(f64::INFINITY, policy.ratio(0, 0, TOTAL_LENGTH));
+        assert_eq!(Some(SeedLimit::Ratio), policy.limit_reached(&status(0, 0)));
+    }
+
+    #[test]
+    fn positive_size_fallback_when_nothing_downloaded() {
+        let policy = SeedPolicy::new()
+            .with_ratio_limit(Some(2.0))
+            .with_size_fallback(true);
+
+        assert_eq!(1.5, policy.ratio(1500, 0, TOTAL_LENGTH));
+        assert_eq!(None, policy.limit_reached(&status(1500, 0)));
+        assert_eq!(Some(SeedLimit::Ratio), policy.limit_reached(&status(2000, 0)));
+    }
+
+    #[test]
+    fn positive_time_limits_reached() {
+        let policy = SeedPolicy::new()
+            .with_seed_time_limit(Some(Duration::from_secs(48 * 60 * 60)))
+            .with_idle_limit(Some(Duration::from_secs(60)));
+        let mut status = status(0, 0);
+
+        assert_eq!(None, policy.limit_reached(&status));
+        status.idle_time = Duration::from_secs(60);
+        assert_eq!(Some(SeedLimit::Idle), policy.limit_reached(&status));
+        status.seed_time = Duration::from_secs(48 * 60 * 60);
+        assert_eq!(Some(SeedLimit::SeedTime), policy.limit_reached(&status));
+    }
+
+    #[test]
+    fn positive_seeds_per_leecher_limit_reached() {
+        let policy = SeedPolicy::new().with_seeds_per_leecher_limit(Some(4.0));
+        let mut status = status(0, 0);
+
+        assert_eq!(None, policy.limit_reached(&status));
+        status.opt_swarm = Some((8, 2));
+        assert_eq!(None, policy.limit_reached(&status));
+        status.opt_swarm = Some((9, 2));
+        assert_eq!(
+            Some(SeedLimit::SeedsPerLeecher),
+            policy.limit_reached(&status)
+        );
+        status.opt_swarm = Some((1, 0));
+        assert_eq!(
+            Some(SeedLimit::SeedsPerLeecher),
+            policy.limit_reached(&status)
+        );
+    }
+}
@@ -0,0 +1,100 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::dht::DhtState;
+use crate::disk::ResumeData;
+use crate::util::bt::InfoHash;
+
+const DHT_STATE_FILE: &'static str = "dht.state";
+const RESUME_EXTENSION: &'static str = "resume";
+
+/// Directory holding the DHT state and the resume data of every torrent, across restarts.
+///
+/// Files that are missing or unreadable are treated as if nothing was saved.
+#[derive(Clone, Debug)]
+pub(crate) struct StateDir {
+    dir: PathBuf,
+}
+
+impl StateDir {
+    /// Create the directory, if it does not exist yet.
+    pub fn open(dir: &Path) -> io::Result<StateDir> {
+        fs::create_dir_all(dir)?;
+
+        Ok(StateDir {
+            dir: dir.to_path_buf(),
+        })
+    }
+
+    pub fn load_dht(&self) -> Option<DhtState> {
+        let bytes = self.read(&self.dir.join(DHT_STATE_FILE))?;
+
+        match DhtState::from_bytes(&bytes) {
+            Ok(state) => Some(state),
+            Err(error) => {
+                warn!(
+                    "bittorrent-protocol_session: Ignoring Invalid DHT State: {:?}",
+                    error
+                );
+                None
+            }
+        }
+    }
+
+    pub fn save_dht(&self, state: &DhtState) {
+        self.write(&self.dir.join(DHT_STATE_FILE), &state.to_bytes());
+    }
+
+    pub fn load_resume(&self, hash: InfoHash) -> Option<ResumeData> {
+        self.read(&self.resume_path(hash))
+            .map(ResumeData::from_bytes)
+    }
+
+    pub fn save_resume(&self, hash: InfoHash, resume: &ResumeData) {
+        self.write(&self.resume_path(hash), resume.as_bytes());
+    }
+
+    pub fn remove_resume(&self, hash: InfoHash) {
+        match fs::remove_file(self.resume_path(hash)) {
+            Err(ref error) if error.kind() != io::ErrorKind::NotFound => warn!(
+                "bittorrent-protocol_session: Failed To Remove Resume Data For {:?}: {}",
+                hash, error
+            ),
+            _ => (),
+        }
+    }
+
+    fn resume_path(&self, hash: InfoHash) -> PathBuf {
+        self.dir
+            .join(encode_hex(hash.as_ref()))
+            .with_extension(RESUME_EXTENSION)
+    }
+
+    fn read(&self, path: &Path) -> Option<Vec<u8>> {
+        match fs::read(path) {
+            Ok(bytes) => Some(bytes),
+            Err(ref error) if error.kind() == io::ErrorKind::NotFound => None,
+            Err(error) => {
+                warn!(
+                    "bittorrent-protocol_session: Failed To Read {:?}: {}",
+                    path, error
+                );
+                None
+            }
+        }
+    }
+
+    fn write(&self, path: &Path, bytes: &[u8]) {
+        if let Err(error) = fs::write(path, bytes) {
+            warn!(
+                "bittorrent-protocol_session: Failed To Write {:?}: {}",
+                path, error
+            );
+        }
+    }
+}
+
+fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
@@ -0,0 +1,1493 @@
+use std::cmp;
+use std::collections::{HashMap, HashSet};
+use std::mem;
+use std::net::SocketAddr;
+use std::ops::RangeInclusive;
+use std::sync::Arc;
+use std::time::Duration;
+
+use tracing::{debug, info, trace, warn};
+
+use crate::disk::{
+    Block, BlockMetadata, BlockMut, FilePriority, IDiskMessage, PiecePriorities, ResumeData,
+    ResumeState, ResumeTotals,
+};
+use crate::metainfo::Metainfo;
+use crate::peer::messages::{
+    BitFieldMessage, ExtendedMessage, HaveMessage, PeerWireProtocolMessage, RequestMessage,
+};
+use crate::peer::{PeerInfo, PeerStats};
+use crate::select::choke::ChokeManager;
+use crate::select::piece::RarestFirstPicker;
+use crate::select::request::{ReceivedBlock, RequestEvent, RequestQueue};
+use crate::select::upload::Uploader;
+use crate::session::event::{TorrentEvent, TorrentState};
+use crate::session::handle::{TorrentOptions, TorrentShared};
+use crate::session::metrics::Metrics;
+use crate::session::policy::{SeedLimit, SeedPolicy, SeedStatus};
+use crate::util::bt::{InfoHash, PeerId};
+
+const BLOCK_LEN: u64 = 16 * 1024;
+/// Requests outstanding to a peer, below which we queue the blocks of another piece for it.
+const MIN_IN_FLIGHT: usize = 32;
+/// Pieces being downloaded at once, regardless of how many peers we have.
+const MAX_QUEUED_PIECES: usize = 64;
+const INTEREST_INTERVAL_MILLIS: u64 = 5 * 1000;
+/// Time between the availability snapshots handed out by `TorrentHandle::availability`.
+const AVAILABILITY_INTERVAL_MILLIS: u64 = 1000;
+const RATE_WINDOW_MILLIS: u64 = 20 * 1000;
+
+/// Messages produced by a `Torrent`, to be sent out by the session.
+#[derive(Default)]
+pub(crate) struct Outbox {
+    pub peer: Vec<(PeerInfo, PeerWireProtocolMessage)>,
+    pub disk: Vec<IDiskMessage>,
+    pub events: Vec<TorrentEvent>,
+    // Peers that sent part of a piece that failed its hash check, with the number of blocks
+    pub failed: Vec<Vec<(PeerInfo, usize)>>,
+}
+
+struct PeerState {
+    // Peer manager has told us about the peer
+    connected: bool,
+    // Peer manager was asked to shed the peer, to make room for another
+    shed: bool,
+    choking_us: bool,
+    interested: bool,
+    // Pieces the peer has, once we know how many pieces there are
+    opt_bits: Option<BitFieldMessage>,
+}
+
+impl PeerState {
+    fn set_interested(&mut self, info: PeerInfo, interested: bool, out: &mut Outbox) {
+        if self.interested != interested {
+            self.interested = interested;
+            debug!(
+                info_hash = %info.hash(),
+                peer = %info.addr(),
+                interested = interested,
+                "interest in peer changed"
+            );
+
+            let message = if interested {
+                PeerWireProtocolMessage::Interested
+            } else {
+                PeerWireProtocolMessage::UnInterested
+            };
+            out.peer.push((info, message));
+        }
+    }
+}
+
+/// Selection state of a torrent in a `Session`, wired into the outside world by the session.
+///
+/// Availability, choking and interest of peers that arrive before the metainfo are
+/// stashed, and replayed once the metainfo is downloaded.
+pub(crate) struct Torrent {
+    hash: InfoHash,
+    shared: Arc<TorrentShared>,
+    options: TorrentOptions,
+    paused: bool,
+    peers: HashMap<PeerInfo, PeerState>,
+    stash: Vec<(PeerInfo, PeerWireProtocolMessage)>,
+    opt_download: Option<Download>,
+    // Priorities set once the torrent was added along with its resume data
+    opt_pending_priorities: Option<Vec<FilePriority>>,
+    // Files selected by the magnet link that the torrent was added from, empty for every file
+    select_only: Vec<RangeInclusive<usize>>,
+    downloaded: u64,
+    uploaded: u64,
+    // Totals saved in the resume data, before this run of the session
+    resumed: ResumeTotals,
+    seed_time: Duration,
+    // Time seeding since a piece was last uploaded
+    idle_time: Duration,
+    seed_limit_reached: bool,
+    wasted: u64,
+    hash_failures: u64,
+    rates: (f64, f64),
+    metrics: Arc<Metrics>,
+}
+
+impl Torrent {
+    pub fn new(
+        hash: InfoHash,
+        shared: Arc<TorrentShared>,
+        options: TorrentOptions,
+        metrics: Arc<Metrics>,
+    ) -> Torrent {
+        Torrent {
+            hash: hash,
+            shared: shared,
+            paused: options.paused(),
+            options: options,
+            peers: HashMap::new(),
+            stash: Vec::new(),
+            opt_download: None,
+            opt_pending_priorities: None,
+            select_only: Vec::new(),
+            downloaded: 0,
+            uploaded: 0,
+            resumed: ResumeTotals::default(),
+            seed_time: Duration::from_secs(0),
+            idle_time: Duration::from_secs(0),
+            seed_limit_reached: false,
+            wasted: 0,
+            hash_failures: 0,
+            rates: (0.0, 0.0),
+            metrics: metrics,
+        }
+    }
+
+    pub fn options(&self) -> &TorrentOptions {
+        &self.options
+    }
+
+    pub fn metainfo(&self) -> Option<&Metainfo> {
+        self.opt_download
+            .as_ref()
+            .map(|download| &download.metainfo)
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    pub fn set_paused(&mut self, paused: bool) {
+        self.paused = paused;
+    }
+
+    pub fn set_file_priorities(&mut self, priorities: Vec<FilePriority>) {
+        self.options.set_file_priorities(priorities);
+    }
+
+    /// Download only the selected files once the metainfo is known, unless file priorities
+    /// were set by then.
+    pub fn set_select_only(&mut self, select_only: Vec<RangeInclusive<usize>>) {
+        self.select_only = select_only;
+    }
+
+    /// Change the seed policy of the torrent, checking its limits anew.
+    pub fn set_seed_policy(&mut self, opt_policy: Option<SeedPolicy>) {
+        self.options.set_seed_policy(opt_policy);
+        self.seed_limit_reached = false;
+    }
+
+    /// Mark the torrent as removed for its handles.
+    pub fn set_removed(&self) {
+        self.shared.set_removed();
+    }
+
+    /// Every peer, including those handed to the peer manager that it has not yet told us about.
+    pub fn peers(&self) -> Vec<PeerInfo> {
+        self.peers.keys().cloned().collect()
+    }
+
+    /// Number of peers, leaving out those being shed.
+    pub fn num_peers(&self) -> usize {
+        self.peers.values().filter(|peer| !peer.shed).count()
+    }
+
+    /// Pick a connected peer that has every piece to shed, only while we are seeding too.
+    ///
+    /// Seeds have nothing to trade with each other, so they are the first to make room.
+    pub fn shed_seed(&mut self) -> Option<PeerInfo> {
+        let num_pieces = match self.opt_download {
+            Some(ref download) if self.state() == TorrentState::Seeding => download.num_pieces,
+            _ => return None,
+        };
+
+        let (info, peer) = self.peers.iter_mut().find(|(_, peer)| {
+            peer.connected
+                && !peer.shed
+                && peer
+                    .opt_bits
+                    .as_ref()
+                    .map_or(false, |bits| (0..num_pieces).all(|piece| bits.has_piece(piece)))
+        })?;
+        peer.shed = true;
+
+        Some(*info)
+    }
+
+    pub fn has_addr(&self, addr: &SocketAddr) -> bool {
+        self.peers.keys().any(|info| info.addr() == addr)
+    }
+
+    pub fn has_peer_id(&self, pid: &PeerId) -> bool {
+        self.peers.keys().any(|info| info.peer_id() == pid)
+    }
+
+    /// Peer was handed to the peer manager.
+    pub fn add_peer(&mut self, info: PeerInfo) {
+        self.peers.insert(
+            info,
+            PeerState {
+                connected: false,
+                shed: false,
+                choking_us: true,
+                interested: false,
+                opt_bits: None,
+            },
+        );
+    }
+
+    /// Peer manager has added the peer.
+    pub fn on_peer_added(&mut self, info: PeerInfo, out: &mut Outbox) {
+        let peer = match self.peers.get_mut(&info) {
+            Some(peer) => peer,
+            None => return,
+        };
+        peer.connected = true;
+        self.metrics.add_peer_connected();
+        out.events
+            .push(TorrentEvent::PeerConnected(self.hash, *info.addr()));
+
+        if let Some(ref mut download) = self.opt_download {
+            download.add_peer(info, peer);
+
+            // Pieces verified after this are announced with have messages
+            if download.checked && download.num_have > 0 {
+                out.peer
+                    .push((info, PeerWireProtocolMessage::BitField(download.bitfield())));
+            }
+            download.flush(out);
+        }
+    }
+
+    /// Peer manager has removed the peer, or the peer disconnected.
+    pub fn on_peer_removed(&mut self, info: PeerInfo, out: &mut Outbox) {
+        let peer = match self.peers.remove(&info) {
+            Some(peer) => peer,
+            None => return,
+        };
+        self.stash.retain(|(other, _)| *other != info);
+
+        if peer.connected {
+            self.metrics.add_peer_disconnected();
+            out.events
+                .push(TorrentEvent::PeerDisconnected(self.hash, *info.addr()));
+
+            if let Some(ref mut download) = self.opt_download {
+                download.remove_peer(&info);
+                download.flush(out);
+            }
+        }
+    }
+
+    pub fn on_message(
+        &mut self,
+        info: PeerInfo,
+        message: PeerWireProtocolMessage,
+        out: &mut Outbox,
+    ) {
+        let peer = match self.peers.get_mut(&info) {
+            Some(peer) if peer.connected => peer,
+            _ => return,
+        };
+        let download = match self.opt_download {
+            Some(ref mut download) => download,
+            None => {
+                if is_stashed(&message) {
+                    self.stash.push((info, message));
+                }
+                return;
+            }
+        };
+
+        match message {
+            PeerWireProtocolMessage::Choke => {
+                debug!(info_hash = %self.hash, peer = %info.addr(), "peer choked us");
+                peer.choking_us = true;
+                download.queue.on_choke(&info);
+            }
+            PeerWireProtocolMessage::UnChoke => {
+                debug!(info_hash = %self.hash, peer = %info.addr(), "peer unchoked us");
+                peer.choking_us = false;
+                download.queue.on_unchoke(&info);
+                download.fill(&info, peer);
+            }
+            PeerWireProtocolMessage::Interested => {
+                debug!(info_hash = %self.hash, peer = %info.addr(), "peer is interested");
+                download.choker.peer_interested(&info, true)
+            }
+            PeerWireProtocolMessage::UnInterested => {
+                debug!(info_hash = %self.hash, peer = %info.addr(), "peer is not interested");
+                download.choker.peer_interested(&info, false)
+            }
+            PeerWireProtocolMessage::Have(have) => {
+                let piece = have.piece_index();
+
+                if (piece as usize) < download.num_pieces {
+                    if let Some(ref mut bits) = peer.opt_bits {
+                        bits.set_piece(piece as usize);
+                    }
+                    download.picker.on_have(info, piece);
+
+                    if !download.complete && download.wants(piece) {
+                        peer.set_interested(info, true, out);
+                        download.fill(&info, peer);
+                    }
+                }
+            }
+            PeerWireProtocolMessage::BitField(bits) => {
+                if bits.validate(download.num_pieces).is_ok() {
+                    download.picker.on_bitfield(info, &bits);
+                    peer.opt_bits = Some(bits);
+
+                    download.update_interest(info, peer, out);
+                    download.fill(&info, peer);
+                }
+            }
+            PeerWireProtocolMessage::HaveAll => {
+                download.picker.on_have_all(info);
+                peer.opt_bits = Some(BitFieldMessage::from_pieces(
+                    download.num_pieces,
+                    0..download.num_pieces,
+                ));
+
+                download.update_interest(info, peer, out);
+                download.fill(&info, peer);
+            }
+            PeerWireProtocolMessage::HaveNone => {
+                download.picker.on_have_none(info);
+                peer.opt_bits = Some(BitFieldMessage::with_capacity(download.num_pieces));
+
+                download.update_interest(info, peer, out);
+            }
+            PeerWireProtocolMessage::Request(request) => {
+                download.uploader.on_request(&info, &request)
+            }
+            PeerWireProtocolMessage::Cancel(cancel) => download.uploader.on_cancel(&info, &cancel),
+            PeerWireProtocolMessage::Piece(piece) => {
+                self.downloaded += piece.block_length() as u64;
+                self.metrics.add_downloaded(piece.block_length() as u64);
+                trace!(
+                    info_hash = %self.hash,
+                    peer = %info.addr(),
+                    piece = piece.piece_index(),
+                    offset = piece.block_offset(),
+                    length = piece.block_length(),
+                    "block received"
+                );
+
+                if download.queue.on_piece(&info, &piece) == ReceivedBlock::New {
+                    *download
+                        .contributors
+                        .entry(piece.piece_index())
+                        .or_insert_with(HashMap::new)
+                        .entry(info)
+                        .or_insert(0) += 1;
+
+                    let metadata = BlockMetadata::new(
+                        self.hash,
+                        piece.piece_index() as u64,
+                        piece.block_offset() as u64,
+                        piece.block_length(),
+                    );
+
+                    out.disk.push(IDiskMessage::ProcessBlock(Block::new(
+                        metadata,
+                        piece.into_block(),
+                    )));
+                } else {
+                    self.wasted += piece.block_length() as u64;
+                    self.metrics.add_wasted(piece.block_length() as u64);
+                }
+                download.fill(&info, peer);
+            }
+            PeerWireProtocolMessage::Reject(reject) => {
+                download.queue.on_reject(&info, &reject);
+                download.fill(&info, peer);
+            }
+            _ => (),
+        }
+
+        download.flush(out);
+    }
+
+    pub fn on_extended(&mut self, info: &PeerInfo, extended: &ExtendedMessage) {
+        if let Some(ref mut download) = self.opt_download {
+            download.queue.on_extended(info, extended);
+        }
+    }
+
+    /// Metainfo is known, the torrent is added to the disk manager, along with its resume data.
+    pub fn set_metainfo(
+        &mut self,
+        metainfo: Metainfo,
+        opt_resume: Option<ResumeData>,
+        out: &mut Outbox,
+    ) {
+        if let Some(state) = opt_resume
+            .as_ref()
+            .and_then(|resume| ResumeState::decode(resume, metainfo.info()))
+        {
+            self.resumed = state.totals;
+            self.seed_time = state.totals.seed_time;
+        }
+
+        if !self.select_only.is_empty() && self.options.file_priorities().is_empty() {
+            let num_files = metainfo.info().files().count();
+            let (priorities, missing) = select_only_priorities(&self.select_only, num_files);
+
+            if !missing.is_empty() {
+                let missing: Vec<String> = missing
+                    .iter()
+                    .map(|range| match (range.start(), range.end()) {
+                        (start, end) if start == end => start.to_string(),
+                        (start, end) => format!("{}-{}", start, end),
+                    })
+                    .collect();
+                out.events.push(TorrentEvent::Warning(
+                    self.hash,
+                    format!(
+                        "Magnet Link Selects Files {} Of A Torrent With {} Files",
+                        missing.join(","),
+                        num_files
+                    ),
+                ));
+            }
+            self.options.set_file_priorities(priorities);
+        }
+
+        let priorities = self.options.file_priorities();
+        out.disk.push(match opt_resume {
+            Some(resume) => {
+                if !priorities.is_empty() {
+                    self.opt_pending_priorities = Some(priorities.to_vec());
+                }
+
+                IDiskMessage::AddTorrentWithResume(metainfo.clone(), resume)
+            }
+            None if priorities.is_empty() => IDiskMessage::AddTorrent(metainfo.clone()),
+            None => IDiskMessage::AddTorrentWithPriorities(metainfo.clone(), priorities.to_vec()),
+        });
+
+        let mut download = Download::new(self.hash, metainfo);
+        debug!(
+            info_hash = %self.hash,
+            pieces = download.num_pieces,
+            "metainfo known, checking pieces on disk"
+        );
+        for (info, peer) in self.peers.iter_mut().filter(|(_, peer)| peer.connected) {
+            download.add_peer(*info, peer);
+        }
+        self.opt_download = Some(download);
+
+        for (info, message) in mem::take(&mut self.stash) {
+            self.on_message(info, message, out);
+        }
+    }
+
+    /// Disk manager has added the torrent, every piece already on disk was found good.
+    pub fn on_added(&mut self, priorities: Option<PiecePriorities>, out: &mut Outbox) {
+        let download = match self.opt_download {
+            Some(ref mut download) => download,
+            None => return,
+        };
+        download.checked = true;
+        if let Some(priorities) = priorities {
+            download.set_skipped(&priorities);
+        }
+        if let Some(file_priorities) = self.opt_pending_priorities.take() {
+            out.disk
+                .push(IDiskMessage::SetFilePriorities(self.hash, file_priorities));
+        }
+        download.wanted = (0..download.num_pieces as u32)
+            .filter(|&piece| !download.have[piece as usize] && !download.skipped.contains(&piece))
+            .collect();
+        out.events.push(TorrentEvent::Checked(self.hash));
+        info!(
+            info_hash = %self.hash,
+            have = download.num_have,
+            wanted = download.wanted.len(),
+            "torrent checked"
+        );
+
+        let have: Vec<u32> = (0..download.num_pieces as u32)
+            .filter(|&piece| download.have[piece as usize])
+            .collect();
+        for (info, _) in self.peers.iter().filter(|(_, peer)| peer.connected) {
+            for &piece in have.iter() {
+                out.peer.push((
+                    *info,
+                    PeerWireProtocolMessage::Have(HaveMessage::new(piece)),
+                ));
+            }
+        }
+
+        if download.wanted.is_empty() {
+            // Nothing was downloaded, so the torrent did not complete as such
+            download.set_complete(&mut self.peers, out);
+        }
+        // Bitfields received while checking were matched against no wanted pieces
+        for (info, peer) in self.peers.iter_mut() {
+            download.update_interest(*info, peer, out);
+        }
+        download.refresh(&mut self.peers, out);
+    }
+
+    pub fn on_good_piece(&mut self, piece: u64, out: &mut Outbox) {
+        let download = match self.opt_download {
+            Some(ref mut download) if piece < download.num_pieces as u64 => download,
+            _ => return,
+        };
+        let piece = piece as u32;
+
+        if !download.have[piece as usize] {
+            download.have[piece as usize] = true;
+            download.num_have += 1;
+        }
+        download.wanted.remove(&piece);
+        download.queued.remove(&piece);
+        download.contributors.remove(&piece);
+        download.uploader.add_piece(piece);
+
+        // Pieces found while checking are announced once the check is done
+        if !download.checked {
+            return;
+        }
+
+        for (info, _) in self.peers.iter().filter(|(_, peer)| peer.connected) {
+            out.peer.push((
+                *info,
+                PeerWireProtocolMessage::Have(HaveMessage::new(piece)),
+            ));
+        }
+        self.metrics.add_piece_verified();
+        out.events
+            .push(TorrentEvent::PieceVerified(self.hash, piece as u64));
+        debug!(
+            info_hash = %self.hash,
+            piece = piece,
+            have = download.num_have,
+            total = download.num_pieces,
+            monotonic_counter.pieces_verified = 1u64,
+            "piece verified"
+        );
+
+        if download.check_complete(&mut self.peers, out) {
+            out.events.push(TorrentEvent::Completed(self.hash));
+        }
+        download.flush(out);
+    }
+
+    pub fn on_bad_piece(&mut self, piece: u64, out: &mut Outbox) {
+        let download = match self.opt_download {
+            Some(ref mut download) => download,
+            None => return,
+        };
+        let piece = piece as u32;
+
+        if download.checked && download.queued.contains(&piece) {
+            let blocks = download.piece_blocks(piece);
+            download.queue.add_blocks(blocks);
+
+            let length = download.piece_len(piece);
+            self.wasted += length;
+            self.hash_failures += 1;
+            self.metrics.add_wasted(length);
+            self.metrics.add_hash_failure();
+            out.events
+                .push(TorrentEvent::PieceFailed(self.hash, piece as u64));
+            if let Some(contributors) = download.contributors.remove(&piece) {
+                out.failed.push(contributors.into_iter().collect());
+            }
+            warn!(
+                info_hash = %self.hash,
+                piece = piece,
+                monotonic_counter.pieces_failed = 1u64,
+                "piece failed hash check, downloading it again"
+            );
+            download.refresh(&mut self.peers, out);
+        }
+    }
+
+    pub fn on_priorities_set(&mut self, priorities: PiecePriorities, out: &mut Outbox) {
+        let download = match self.opt_download {
+            Some(ref mut download) if download.checked => download,
+            _ => return,
+        };
+        download.set_skipped(&priorities);
+
+        for piece in 0..download.num_pieces as u32 {
+            if download.have[piece as usize] || download.queued.contains(&piece) {
+                continue;
+            }
+
+            if download.skipped.contains(&piece) {
+                download.wanted.remove(&piece);
+            } else {
+                download.wanted.insert(piece);
+            }
+        }
+
+        if download.complete && !download.wanted.is_empty() {
+            download.complete = false;
+            download.choker.set_seeding(false);
+        } else if download.check_complete(&mut self.peers, out) {
+            out.events.push(TorrentEvent::Completed(self.hash));
+        }
+        for (info, peer) in self.peers.iter_mut() {
+            download.update_interest(*info, peer, out);
+        }
+        download.refresh(&mut self.peers, out);
+    }
+
+    pub fn on_block_loaded(&mut self, block: BlockMut, out: &mut Outbox) {
+        if let Some(ref mut download) = self.opt_download {
+            download.uploader.on_block_loaded(block);
+            download.flush(out);
+        }
+    }
+
+    pub fn on_load_error(&mut self, block: BlockMut, out: &mut Outbox) {
+        if let Some(ref mut download) = self.opt_download {
+            download.uploader.on_load_error(block);
+            download.flush(out);
+        }
+    }
+
+    /// Disk manager did not process a block, it is downloaded again if we still want it.
+    pub fn on_process_error(&mut self, metadata: BlockMetadata, out: &mut Outbox) {
+        let download = match self.opt_download {
+            Some(ref mut download) => download,
+            None => return,
+        };
+        let piece = metadata.piece_index() as u32;
+
+        if download.skipped.contains(&piece) {
+            download.queued.remove(&piece);
+
+            if download.check_complete(&mut self.peers, out) {
+                out.events.push(TorrentEvent::Completed(self.hash));
+            }
+        } else if download.queued.contains(&piece) {
+            download.queue.add_blocks(Some(RequestMessage::new(
+                piece,
+                metadata.block_offset() as u32,
+                metadata.block_length(),
+            )));
+            download.refresh(&mut self.peers, out);
+        }
+    }
+
+    pub fn on_piece_sent(&mut self, length: usize) {
+        self.uploaded += length as u64;
+        self.idle_time = Duration::from_secs(0);
+        self.metrics.add_uploaded(length as u64);
+    }
+
+    pub fn tick(
+        &mut self,
+        elapsed: Duration,
+        stats: &HashMap<PeerInfo, PeerStats>,
+        out: &mut Outbox,
+    ) {
+        let (mut download_rate, mut upload_rate) = (0.0, 0.0);
+
+        for info in self.peers.keys() {
+            if let Some(peer_stats) = stats.get(info) {
+                let rates = peer_stats.rates(Duration::from_millis(RATE_WINDOW_MILLIS));
+                download_rate += rates.download();
+                upload_rate += rates.upload();
+
+                if let Some(ref mut download) = self.opt_download {
+                    download.choker.update_rates(info, rates);
+                }
+            }
+        }
+        self.rates = (download_rate, upload_rate);
+
+        if self.state() == TorrentState::Seeding {
+            self.seed_time += elapsed;
+            self.idle_time += elapsed;
+        } else {
+            self.idle_time = Duration::from_secs(0);
+        }
+
+        if let Some(ref mut download) = self.opt_download {
+            download.queue.tick(elapsed);
+            download.choker.tick(elapsed);
+
+            // Counts are only copied when they change after a snapshot, so at most once
+            // within every interval
+            download.since_availability += elapsed;
+            if download.since_availability >= Duration::from_millis(AVAILABILITY_INTERVAL_MILLIS) {
+                download.since_availability = Duration::from_millis(0);
+
+                self.shared
+                    .set_availability(download.picker.availability_snapshot());
+            }
+
+            download.since_interest += elapsed;
+            if download.since_interest >= Duration::from_millis(INTEREST_INTERVAL_MILLIS) {
+                download.since_interest = Duration::from_millis(0);
+
+                for (info, peer) in self.peers.iter_mut() {
+                    download.update_interest(*info, peer, out);
+                }
+            }
+            download.refresh(&mut self.peers, out);
+        }
+    }
+
+    /// Bytes left to download, for announcing to trackers.
+    pub fn bytes_left(&self) -> u64 {
+        match self.opt_download {
+            Some(ref download) => download
+                .wanted
+                .iter()
+                .chain(download.queued.iter())
+                .map(|&piece| download.piece_len(piece))
+                .sum(),
+            // Anything but zero, so that trackers do not take us for a seed
+            None => BLOCK_LEN,
+        }
+    }
+
+    pub fn downloaded(&self) -> u64 {
+        self.downloaded
+    }
+
+    pub fn uploaded(&self) -> u64 {
+        self.uploaded
+    }
+
+    /// Amounts transferred across restarts of the session.
+    pub fn totals(&self) -> ResumeTotals {
+        ResumeTotals {
+            uploaded: self.resumed.uploaded + self.uploaded,
+            downloaded: self.resumed.downloaded + self.downloaded,
+            seed_time: self.seed_time,
+        }
+    }
+
+    /// Resume data saved by the disk manager, along with the totals of the torrent.
+    pub fn resume_with_totals(&self, resume: ResumeData) -> ResumeData {
+        let opt_state = self
+            .opt_download
+            .as_ref()
+            .and_then(|download| ResumeState::decode(&resume, download.metainfo.info()));
+
+        match opt_state {
+            Some(mut state) => {
+                state.totals = self.totals();
+                state.encode()
+            }
+            None => resume,
+        }
+    }
+
+    /// Limit of the policy that the seeding torrent newly reached, if any.
+    ///
+    /// Swarm holds the seeds and leechers last reported by trackers.
+    pub fn check_seed_limit(
+        &mut self,
+        policy: &SeedPolicy,
+        opt_swarm: Option<(u64, u64)>,
+    ) -> Option<SeedLimit> {
+        if self.seed_limit_reached || self.state() != TorrentState::Seeding {
+            return None;
+        }
+        let total_length = self
+            .opt_download
+            .as_ref()
+            .map_or(0, |download| download.total_length);
+        let totals = self.totals();
+        let status = SeedStatus {
+            uploaded: totals.uploaded,
+            downloaded: totals.downloaded,
+            total_length: total_length,
+            seed_time: totals.seed_time,
+            idle_time: self.idle_time,
+            opt_swarm: opt_swarm,
+        };
+
+        let which = policy.limit_reached(&status)?;
+        self.seed_limit_reached = true;
+        info!(info_hash = %self.hash, which = ?which, "seed limit reached");
+
+        Some(which)
+    }
+
+    pub fn state(&self) -> TorrentState {
+        match self.opt_download {
+            _ if self.paused => TorrentState::Paused,
+            None => TorrentState::FetchingMetadata,
+            Some(ref download) if !download.checked => TorrentState::Checking,
+            Some(ref download) if download.complete => TorrentState::Seeding,
+            Some(_) => TorrentState::Downloading,
+        }
+    }
+
+    /// Update the snapshot handed out by `TorrentHandle::stats`.
+    pub fn update_shared(&self) {
+        let state = self.state();
+        let pieces = match self.opt_download {
+            Some(ref download) => {
+                let skipped_have = download
+                    .skipped
+                    .iter()
+                    .filter(|&&piece| download.have[piece as usize])
+                    .count();
+
+                (
+                    download.num_pieces,
+                    download.num_have - skipped_have,
+                    download.num_pieces - download.skipped.len(),
+                )
+            }
+            None => (0, 0, 0),
+        };
+        let num_peers = self.peers.values().filter(|peer| peer.connected).count();
+        let (downloaded, uploaded, rates) = (self.downloaded, self.uploaded, self.rates);
+        let (wasted, hash_failures) = (self.wasted, self.hash_failures);
+        let totals = self.totals();
+
+        self.shared.update_stats(|stats| {
+            stats.set_state(state);
+            stats.set_pieces(pieces.0, pieces.1, pieces.2);
+            stats.set_transfer(downloaded, uploaded, rates);
+            stats.set_totals(totals.downloaded, totals.uploaded, totals.seed_time);
+            stats.set_waste(wasted, hash_failures);
+            stats.set_peers(num_peers);
+        });
+    }
+}
+
+/// Priority of each file, skipping those that none of the ranges select, along with the
+/// parts of the ranges past the last file.
+fn select_only_priorities(
+    select_only: &[RangeInclusive<usize>],
+    num_files: usize,
+) -> (Vec<FilePriority>, Vec<RangeInclusive<usize>>) {
+    let priorities = (0..num_files)
+        .map(|index| {
+            if select_only.iter().any(|range| range.contains(&index)) {
+                FilePriority::Normal
+            } else {
+                FilePriority::Skip
+            }
+        })
+        .collect();
+    let missing = select_only
+        .iter()
+        .filter(|range| *range.end() >= num_files)
+        .map(|range| cmp::max(*range.start(), num_files)..=*range.end())
+        .collect();
+
+    (priorities, missing)
+}
+
+/// Whether the message is kept around until the metainfo is known.
+fn is_stashed(message: &PeerWireProtocolMessage) -> bool {
+    matches!(
+        *message,
+        PeerWireProtocolMessage::Choke
+            | PeerWireProtocolMessage::UnChoke
+            | PeerWireProtocolMessage::Interested
+            | PeerWireProtocolMessage::UnInterested
+            | PeerWireProtocolMessage::Have(_)
+            | PeerWireProtocolMessage::BitField(_)
+            | PeerWireProtocolMessage::HaveAll
+    )
+}
+
+//----------------------------------------------------------------------------//
+
+struct Download {
+    metainfo: Metainfo,
+    num_pieces: usize,
+    piece_length: u64,
+    total_length: u64,
+    checked: bool,
+    complete: bool,
+    have: Vec<bool>,
+    num_have: usize,
+    skipped: HashSet<u32>,
+    // Pieces that no blocks were queued for yet
+    wanted: HashSet<u32>,
+    // Pieces that blocks were queued for, until they are verified
+    queued: HashSet<u32>,
+    // Blocks received from each peer for pieces that are not verified yet
+    contributors: HashMap<u32, HashMap<PeerInfo, usize>>,
+    since_interest: Duration,
+    since_availability: Duration,
+    picker: RarestFirstPicker,
+    queue: RequestQueue,
+    uploader: Uploader,
+    choker: ChokeManager,
+}
+
+impl Download {
+    fn new(hash: InfoHash, metainfo: Metainfo) -> Download {
+        let num_pieces = metainfo.info().pieces().count();
+        let piece_length = metainfo.info().piece_length();
+        let total_length = metainfo.info().files().map(|file| file.length()).sum();
+
+        Download {
+            metainfo: metainfo,
+            num_pieces: num_pieces,
+            piece_length: piece_length,
+            total_length: total_length,
+            checked: false,
+            complete: false,
+            have: vec![false; num_pieces],
+            num_have: 0,
+            skipped: HashSet::new(),
+            wanted: HashSet::new(),
+            queued: HashSet::new(),
+            contributors: HashMap::new(),
+            since_interest: Duration::from_millis(0),
+            // First snapshot is taken on the first tick
+            since_availability: Duration::from_millis(AVAILABILITY_INTERVAL_MILLIS),
+            picker: RarestFirstPicker::new(num_pieces),
+            queue: RequestQueue::new(),
+            uploader: Uploader::new(hash),
+            choker: ChokeManager::new(),
+        }
+    }
+
+    fn add_peer(&mut self, info: PeerInfo, peer: &mut PeerState) {
+        peer.opt_bits = Some(BitFieldMessage::with_capacity(self.num_pieces));
+
+        self.queue.add_peer(info);
+        self.uploader.add_peer(info);
+        self.choker.add_peer(info);
+    }
+
+    fn remove_peer(&mut self, info: &PeerInfo) {
+        self.queue.remove_peer(info);
+        self.uploader.remove_peer(info);
+        self.choker.remove_peer(info);
+        self.picker.on_peer_gone(*info);
+    }
+
+    fn set_skipped(&mut self, priorities: &PiecePriorities) {
+        self.skipped = priorities
+            .skipped_pieces()
+            .map(|piece| piece as u32)
+            .collect();
+    }
+
+    fn wants(&self, piece: u32) -> bool {
+        self.wanted.contains(&piece) || self.queued.contains(&piece)
+    }
+
+    fn bitfield(&self) -> BitFieldMessage {
+        BitFieldMessage::from_pieces(
+            self.num_pieces,
+            (0..self.num_pieces).filter(|&piece| self.have[piece]),
+        )
+    }
+
+    fn piece_len(&self, piece: u32) -> u64 {
+        let start = piece as u64 * self.piece_length;
+
+        cmp::min(self.piece_length, self.total_length - start)
+    }
+
+    fn piece_blocks(&self, piece: u32) -> Vec<RequestMessage> {
+        let piece_len = self.piece_len(piece);
+
+        (0..piece_len)
+            .step_by(BLOCK_LEN as usize)
+            .map(|offset| {
+                let block_len = cmp::min(BLOCK_LEN, piece_len - offset);
+
+                RequestMessage::new(piece, offset as u32, block_len as usize)
+            })
+            .collect()
+    }
+
+    fn update_interest(&self, info: PeerInfo, peer: &mut PeerState, out: &mut Outbox) {
+        let interested = match peer.opt_bits {
+            Some(ref bits) if peer.connected && !self.complete => self
+                .wanted
+                .iter()
+                .chain(self.queued.iter())
+                .any(|&piece| bits.has_piece(piece as usize)),
+            _ => false,
+        };
+
+        peer.set_interested(info, interested, out);
+    }
+
+    /// Queue up requests to the peer, picking new pieces while its pipeline runs low.
+    fn fill(&mut self, info: &PeerInfo, peer: &PeerState) {
+        let bits = match peer.opt_bits {
+            Some(ref bits) if !peer.choking_us && self.checked && !self.complete => bits,
+            _ => return,
+        };
+
+        loop {
+            self.queue
+                .fill_requests(info, |piece| bits.has_piece(piece as usize));
+            if self.queue.num_in_flight(info) >= MIN_IN_FLIGHT
+                || self.queued.len() >= MAX_QUEUED_PIECES
+            {
+                break;
+            }
+
+            match self.picker.pick(bits, &self.wanted) {
+                Some(piece) => {
+                    self.wanted.remove(&piece);
+                    self.queued.insert(piece);
+
+                    let blocks = self.piece_blocks(piece);
+                    self.queue.add_blocks(blocks);
+                }
+                None => break,
+            }
+        }
+    }
+
+    /// Fill the requests of every peer, and flush out the resulting messages.
+    fn refresh(&mut self, peers: &mut HashMap<PeerInfo, PeerState>, out: &mut Outbox) {
+        for (info, peer) in peers.iter().filter(|(_, peer)| peer.connected) {
+            self.fill(info, peer);
+        }
+
+        self.flush(out);
+    }
+
+    /// Complete the download if every wanted piece was verified, returns true if it completed.
+    fn check_complete(
+        &mut self,
+        peers: &mut HashMap<PeerInfo, PeerState>,
+        out: &mut Outbox,
+    ) -> bool {
+        if !self.checked || self.complete || !self.wanted.is_empty() || !self.queued.is_empty() {
+            return false;
+        }
+
+        self.set_complete(peers, out);
+        out.disk
+            .push(IDiskMessage::SyncTorrent(self.metainfo.info().info_hash()));
+        info!(
+            info_hash = %self.metainfo.info().info_hash(),
+            monotonic_counter.torrents_completed = 1u64,
+            "download complete"
+        );
+
+        true
+    }
+
+    fn set_complete(&mut self, peers: &mut HashMap<PeerInfo, PeerState>, out: &mut Outbox) {
+        self.complete = true;
+        self.choker.set_seeding(true);
+
+        for (info, peer) in peers.iter_mut() {
+            peer.set_interested(*info, false, out);
+        }
+    }
+
+    fn flush(&mut self, out: &mut Outbox) {
+        while let Some(event) = self.queue.poll_event() {
+            match event {
+                RequestEvent::PeerSnubbed { peer } => self.choker.set_snubbed(&peer, true),
+                RequestEvent::PeerUnsnubbed { peer } => self.choker.set_snubbed(&peer, false),
+                _ => (),
+            }
+        }
+
+        while let Some((info, message)) = self.choker.poll() {
+            let choked = message == PeerWireProtocolMessage::Choke;
+            debug!(
+                info_hash = %info.hash(),
+                peer = %info.addr(),
+                choked = choked,
+                "choke state of peer changed"
+            );
+            self.uploader.set_choked(&info, choked);
+            out.peer.push((info, message));
+        }
+
+        while let Some(item) = self.uploader.poll() {
+            out.peer.push(item);
+        }
+        while let Some(message) = self.uploader.poll_disk() {
+            out.disk.push(message);
+        }
+        while let Some(item) = self.queue.poll() {
+            out.peer.push(item);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::net::SocketAddr;
+    use std::sync::mpsc;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use bytes::Bytes;
+
+    use crate::disk::{
+        FilePriority, IDiskMessage, ResumeData, ResumeFile, ResumeState, ResumeTotals,
+    };
+    use crate::handshake::Extensions;
+    use crate::metainfo::{DirectAccessor, Metainfo, MetainfoBuilder, PieceLength};
+    use crate::peer::messages::{
+        BitFieldMessage, PeerWireProtocolMessage, PieceMessage, RequestMessage,
+    };
+    use crate::peer::PeerInfo;
+    use crate::session::event::{TorrentEvent, TorrentState};
+    use crate::session::handle::{TorrentHandle, TorrentOptions, TorrentShared};
+    use crate::session::metrics::Metrics;
+    use crate::session::policy::{SeedLimit, SeedPolicy};
+    use crate::session::torrent::{
+        select_only_priorities, Download, Outbox, Torrent, BLOCK_LEN,
+    };
+
+    fn metainfo(length: usize, piece_length: usize) -> Metainfo {
+        let data = vec![0u8; length];
+        let bytes = MetainfoBuilder::new()
+            .set_piece_length(PieceLength::Custom(piece_length))
+            .build(1, DirectAccessor::new("file", &data[..]), |_| ())
+            .unwrap();
+
+        Metainfo::from_bytes(bytes).unwrap()
+    }
+
+    fn torrent(metainfo: &Metainfo) -> Torrent {
+        let hash = metainfo.info().info_hash();
+
+        Torrent::new(
+            hash,
+            Arc::new(TorrentShared::new(TorrentState::FetchingMetadata)),
+            TorrentOptions::new(),
+            Arc::new(Metrics::default()),
+        )
+    }
+
+    #[test]
+    fn positive_piece_blocks_last_piece_shorter() {
+        let metainfo = metainfo(3 * BLOCK_LEN as usize + 100, 2 * BLOCK_LEN as usize);
+        let download = Download::new(metainfo.info().info_hash(), metainfo);
+
+        let first = download.piece_blocks(0);
+        assert_eq!(2, first.len());
+        assert!(first
+            .iter()
+            .all(|block| block.block_length() == BLOCK_LEN as usize));
+
+        let last = download.piece_blocks(1);
+        assert_eq!(2, last.len());
+        assert_eq!(BLOCK_LEN as u32, last[1].block_offset());
+        assert_eq!(100, last[1].block_length());
+    }
+
+    #[test]
+    fn positive_complete_once_every_piece_verified() {
+        let metainfo = metainfo(4 * BLOCK_LEN as usize, 2 * BLOCK_LEN as usize);
+        let hash = metainfo.info().info_hash();
+        let mut torrent = torrent(&metainfo);
+        let mut out = Outbox::default();
+
+        torrent.set_metainfo(metainfo, None, &mut out);
+        assert_eq!(TorrentState::Checking, torrent.state());
+        torrent.on_good_piece(0, &mut out);
+        torrent.on_added(None, &mut out);
+        assert_eq!(TorrentState::Downloading, torrent.state());
+        assert_eq!(2 * BLOCK_LEN, torrent.bytes_left());
+
+        torrent.on_good_piece(1, &mut out);
+        assert_eq!(TorrentState::Seeding, torrent.state());
+        assert_eq!(0, torrent.bytes_left());
+        assert!(out.events.contains(&TorrentEvent::Completed(hash)));
+    }
+
+    #[test]
+    fn positive_seeding_when_checked_complete() {
+        let metainfo = metainfo(2 * BLOCK_LEN as usize, 2 * BLOCK_LEN as usize);
+        let hash = metainfo.info().info_hash();
+        let mut torrent = torrent(&metainfo);
+        let mut out = Outbox::default();
+
+        torrent.set_metainfo(metainfo, None, &mut out);
+        torrent.on_good_piece(0, &mut out);
+        torrent.on_added(None, &mut out);
+
+        assert_eq!(TorrentState::Seeding, torrent.state());
+        assert!(!out.events.contains(&TorrentEvent::Completed(hash)));
+    }
+
+    #[test]
+    fn positive_interested_in_bitfield_received_while_checking() {
+        let metainfo = metainfo(4 * BLOCK_LEN as usize, 2 * BLOCK_LEN as usize);
+        let hash = metainfo.info().info_hash();
+        let mut torrent = torrent(&metainfo);
+        let mut out = Outbox::default();
+
+        let addr: SocketAddr = "127.0.0.1:6881".parse().unwrap();
+        let info = PeerInfo::new(addr, [0u8; 20].into(), hash, Extensions::new());
+        torrent.set_metainfo(metainfo, None, &mut out);
+        torrent.add_peer(info);
+        torrent.on_peer_added(info, &mut out);
+        torrent.on_message(
+            info,
+            PeerWireProtocolMessage::BitField(BitFieldMessage::from_pieces(2, 0..2)),
+            &mut out,
+        );
+        assert!(!out
+            .peer
+            .contains(&(info, PeerWireProtocolMessage::Interested)));
+
+        torrent.on_added(None, &mut out);
+        assert!(out
+            .peer
+            .contains(&(info, PeerWireProtocolMessage::Interested)));
+    }
+
+    #[test]
+    fn positive_priorities_set_once_added_with_resume() {
+        let metainfo = metainfo(2 * BLOCK_LEN as usize, 2 * BLOCK_LEN as usize);
+        let hash = metainfo.info().info_hash();
+        let mut torrent = Torrent::new(
+            hash,
+            Arc::new(TorrentShared::new(TorrentState::Checking)),
+            TorrentOptions::new().with_file_priorities(vec![FilePriority::High]),
+            Arc::new(Metrics::default()),
+        );
+        let mut out = Outbox::default();
+
+        torrent.set_metainfo(metainfo, Some(ResumeData::from_bytes(Vec::new())), &mut out);
+        assert!(matches!(
+            out.disk[..],
+            [IDiskMessage::AddTorrentWithResume(_, _)]
+        ));
+
+        torrent.on_added(None, &mut out);
+        assert!(matches!(
+            out.disk[1..],
+            [IDiskMessage::SetFilePriorities(set_hash, ref priorities)]
+                if set_hash == hash && priorities == &[FilePriority::High]
+        ));
+    }
+
+    #[test]
+    fn positive_select_only_priorities() {
+        let (priorities, missing) = select_only_priorities(&[0..=0, 2..=3, 3..=6], 5);
+
+        assert_eq!(
+            vec![
+                FilePriority::Normal,
+                FilePriority::Skip,
+                FilePriority::Normal,
+                FilePriority::Normal,
+                FilePriority::Normal
+            ],
+            priorities
+        );
+        assert_eq!(vec![5..=6], missing);
+    }
+
+    #[test]
+    fn positive_select_only_applied_once_metainfo_known() {
+        let metainfo = metainfo(2 * BLOCK_LEN as usize, 2 * BLOCK_LEN as usize);
+        let hash = metainfo.info().info_hash();
+        let mut torrent = torrent(&metainfo);
+        let mut out = Outbox::default();
+
+        torrent.set_select_only(vec![0..=0, 2..=2]);
+        torrent.set_metainfo(metainfo, None, &mut out);
+
+        assert!(matches!(
+            out.disk[..],
+            [IDiskMessage::AddTorrentWithPriorities(_, ref priorities)]
+                if priorities == &[FilePriority::Normal]
+        ));
+        assert_eq!([FilePriority::Normal], torrent.options().file_priorities());
+        assert!(out.events.contains(&TorrentEvent::Warning(
+            hash,
+            "Magnet Link Selects Files 2 Of A Torrent With 1 Files".to_string()
+        )));
+    }
+
+    #[test]
+    fn positive_shed_seed_only_while_seeding() {
+        let metainfo = metainfo(4 * BLOCK_LEN as usize, 2 * BLOCK_LEN as usize);
+        let hash = metainfo.info().info_hash();
+        let mut torrent = torrent(&metainfo);
+        let mut out = Outbox::default();
+
+        torrent.set_metainfo(metainfo, None, &mut out);
+        torrent.on_added(None, &mut out);
+        let seed = PeerInfo::new(
+            "127.0.0.1:6881".parse().unwrap(),
+            [1u8; 20].into(),
+            hash,
+            Extensions::new(),
+        );
+        let leech = PeerInfo::new(
+            "127.0.0.1:6882".parse().unwrap(),
+            [2u8; 20].into(),
+            hash,
+            Extensions::new(),
+        );
+        for &(info, ref bits) in [
+            (seed, BitFieldMessage::from_pieces(2, 0..2)),
+            (leech, BitFieldMessage::from_pieces(2, 0..1)),
+        ]
+        .iter()
+        {
+            torrent.add_peer(info);
+            torrent.on_peer_added(info, &mut out);
+            torrent.on_message(info, PeerWireProtocolMessage::BitField(bits.clone()), &mut out);
+        }
+        assert_eq!(None, torrent.shed_seed());
+
+        torrent.on_good_piece(0, &mut out);
+        torrent.on_good_piece(1, &mut out);
+        assert_eq!(Some(seed), torrent.shed_seed());
+        assert_eq!(1, torrent.num_peers());
+        assert_eq!(None, torrent.shed_seed());
+    }
+
+    #[test]
+    fn positive_availability_snapshot_on_tick() {
+        let metainfo = metainfo(4 * BLOCK_LEN as usize, BLOCK_LEN as usize);
+        let hash = metainfo.info().info_hash();
+        let shared = Arc::new(TorrentShared::new(TorrentState::Checking));
+        let handle = TorrentHandle::new(hash, mpsc::channel().0, shared.clone());
+        let mut torrent = Torrent::new(
+            hash,
+            shared,
+            TorrentOptions::new(),
+            Arc::new(Metrics::default()),
+        );
+        let mut out = Outbox::default();
+
+        torrent.set_metainfo(metainfo, None, &mut out);
+        torrent.on_added(None, &mut out);
+        assert!(handle.availability().is_none());
+
+        let info = PeerInfo::new(
+            "127.0.0.1:6881".parse().unwrap(),
+            [1u8; 20].into(),
+            hash,
+            Extensions::new(),
+        );
+        torrent.add_peer(info);
+        torrent.on_peer_added(info, &mut out);
+        torrent.on_message(info, PeerWireProtocolMessage::HaveAll, &mut out);
+        torrent.tick(Duration::from_millis(100), &HashMap::new(), &mut out);
+
+        let availability = handle.availability().unwrap();
+        assert_eq!(1.0, availability.distributed_copies());
+        assert_eq!(&[1, 1, 1, 1], availability.histogram());
+
+        torrent.on_message(info, PeerWireProtocolMessage::HaveNone, &mut out);
+        torrent.tick(Duration::from_millis(1000), &HashMap::new(), &mut out);
+
+        let availability = handle.availability().unwrap();
+        assert_eq!(vec![0, 1, 2, 3], availability.unavailable_pieces().collect::<Vec<u32>>());
+    }
+
+    #[test]
+    fn positive_seed_limit_reached_once_per_policy() {
+        let metainfo = metainfo(2 * BLOCK_LEN as usize, 2 * BLOCK_LEN as usize);
+        let mut torrent = torrent(&metainfo);
+        let mut out = Outbox::default();
+        let policy = SeedPolicy::new().with_ratio_limit(Some(2.0));
+
+        torrent.set_metainfo(metainfo, None, &mut out);
+        assert_eq!(None, torrent.check_seed_limit(&policy, None));
+        torrent.on_good_piece(0, &mut out);
+        torrent.on_added(None, &mut out);
+
+        // Seeded from the start, so nothing was downloaded
+        assert_eq!(Some(SeedLimit::Ratio), torrent.check_seed_limit(&policy, None));
+        assert_eq!(None, torrent.check_seed_limit(&policy, None));
+
+        torrent.set_seed_policy(Some(policy.with_size_fallback(true)));
+        assert_eq!(None, torrent.check_seed_limit(&policy.with_size_fallback(true), None));
+        assert_eq!(Some(SeedLimit::Ratio), torrent.check_seed_limit(&policy, None));
+    }
+
+    #[test]
+    fn positive_totals_carried_in_resume_data() {
+        let metainfo = metainfo(2 * BLOCK_LEN as usize, 2 * BLOCK_LEN as usize);
+        let mut torrent = torrent(&metainfo);
+        let mut out = Outbox::default();
+        let mut state = ResumeState {
+            info_hash: metainfo.info().info_hash(),
+            good_pieces: vec![true],
+            files: vec![ResumeFile {
+                size: 2 * BLOCK_LEN,
+                opt_modified: None,
+            }],
+            blocks: Vec::new(),
+            totals: ResumeTotals {
+                uploaded: 100,
+                downloaded: 50,
+                seed_time: Duration::from_secs(60),
+            },
+        };
+
+        torrent.set_metainfo(metainfo.clone(), Some(state.encode()), &mut out);
+        assert_eq!(state.totals, torrent.totals());
+
+        torrent.on_good_piece(0, &mut out);
+        torrent.on_added(None, &mut out);
+        torrent.on_piece_sent(10);
+        torrent.tick(Duration::from_secs(5), &HashMap::new(), &mut out);
+
+        // Disk manager saves the totals as zero
+        state.totals = ResumeTotals::default();
+        let saved = torrent.resume_with_totals(state.encode());
+        let totals = ResumeState::decode(&saved, metainfo.info()).unwrap().totals;
+        assert_eq!(110, totals.uploaded);
+        assert_eq!(50, totals.downloaded);
+        assert_eq!(Duration::from_secs(65), totals.seed_time);
+    }
+
+    #[test]
+    fn positive_bad_piece_reports_contributors() {
+        let metainfo = metainfo(4 * BLOCK_LEN as usize, 2 * BLOCK_LEN as usize);
+        let hash = metainfo.info().info_hash();
+        let mut torrent = torrent(&metainfo);
+        let mut out = Outbox::default();
+
+        torrent.set_metainfo(metainfo, None, &mut out);
+        torrent.on_added(None, &mut out);
+        let info = PeerInfo::new(
+            "127.0.0.1:6881".parse().unwrap(),
+            [1u8; 20].into(),
+            hash,
+            Extensions::new(),
+        );
+        torrent.add_peer(info);
+        torrent.on_peer_added(info, &mut out);
+        torrent.on_message(info, PeerWireProtocolMessage::HaveAll, &mut out);
+        torrent.on_message(info, PeerWireProtocolMessage::UnChoke, &mut out);
+
+        let requests: Vec<RequestMessage> = out
+            .peer
+            .drain(..)
+            .filter_map(|(_, message)| match message {
+                PeerWireProtocolMessage::Request(request) => Some(request),
+                _ => None,
+            })
+            .filter(|request| request.piece_index() == 0)
+            .collect();
+        assert_eq!(2, requests.len());
+        for request in requests {
+            let block = Bytes::from(vec![0u8; request.block_length()]);
+            let piece = PieceMessage::new(0, request.block_offset(), block);
+            torrent.on_message(info, PeerWireProtocolMessage::Piece(piece), &mut out);
+        }
+
+        torrent.on_bad_piece(0, &mut out);
+        assert_eq!(vec![vec![(info, 2)]], out.failed);
+
+        // Contributors are reported once per failure
+        torrent.on_bad_piece(0, &mut out);
+        assert_eq!(1, out.failed.len());
+    }
+}
@@ -0,0 +1,1134 @@
+use std::collections::{HashMap, VecDeque};
+use std::net::{IpAddr, SocketAddr, TcpStream};
+use std::sync::mpsc::{Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use futures::channel::oneshot;
+use futures::future;
+use tokio::runtime::Handle;
+use tracing::{debug, info, warn};
+
+use crate::dht::{self, MainlineDht};
+use crate::disk::{
+    DiskManagerSink, FileHandleCache, FilePriority, IDiskMessage, NativeFileSystem, ODiskMessage,
+};
+use crate::handshake::{
+    CompleteMessage, HandshakerManagerSink, InitiateMessage, MseSocket, Protocol,
+};
+use crate::htracker::{
+    AnnounceEvent, ClientState, HttpTrackerClient, TrackerManager, TrackerManagerBuilder,
+    TrackerProtocol, TrackerStatus, TrackerUrl,
+};
+use crate::metainfo::Metainfo;
+use crate::peer::error::PeerManagerErrorKind;
+use crate::peer::messages::builders::ExtendedMessageBuilder;
+use crate::peer::messages::{
+    BitsExtensionMessage, PeerExtensionProtocolMessage, PeerWireProtocolMessage, PieceMessage,
+};
+use crate::peer::{
+    IPeerManagerMessage, OPeerManagerMessage, PeerDisconnectReason, PeerInfo, PeerManagerSink,
+};
+use crate::select::ban::{BanList, PeerBanner};
+use crate::select::discovery::{IDiscoveryMessage, ODiscoveryMessage, UtMetadataModule};
+use crate::select::{
+    ControlMessage, IExtendedMessage, IUberMessage, OExtendedMessage, OUberMessage, UberModule,
+    UberModuleBuilder,
+};
+use crate::session::builder::DEFAULT_SHUTDOWN_TIMEOUT_MILLIS;
+use crate::session::event::TorrentEvent;
+use crate::session::handle::{TorrentHandle, TorrentOptions, TorrentShared, TorrentSource};
+use crate::session::metrics::Metrics;
+use crate::session::policy::SeedPolicy;
+use crate::session::state::StateDir;
+use crate::session::torrent::{Outbox, Torrent};
+use crate::util::bt::{InfoHash, PeerId};
+use crate::utracker;
+
+const TICK_MILLIS: u64 = 100;
+const TRACKER_UPDATE_MILLIS: u64 = 5 * 1000;
+const DHT_SEARCH_MILLIS: u64 = 15 * 60 * 1000;
+/// Time before an address that we dialed is dialed again.
+const DIAL_COOLDOWN_MILLIS: u64 = 60 * 1000;
+
+pub(crate) type SessionSocket = MseSocket<TcpStream>;
+pub(crate) type SessionFileSystem = FileHandleCache<NativeFileSystem>;
+pub(crate) type Registry = Arc<Mutex<HashMap<InfoHash, TorrentHandle>>>;
+pub(crate) type Listeners = Arc<Mutex<Vec<Sender<TorrentEvent>>>>;
+
+/// Commands sent by a `Session` and its `TorrentHandle`s.
+pub(crate) enum SessionCommand {
+    AddTorrent(InfoHash, TorrentSource, TorrentOptions, Arc<TorrentShared>),
+    Pause(InfoHash),
+    Resume(InfoHash),
+    Remove(InfoHash, bool),
+    SetFilePriorities(InfoHash, Vec<FilePriority>),
+    SetSeedPolicy(InfoHash, Option<SeedPolicy>),
+}
+
+/// Everything the worker of a `Session` reacts to.
+pub(crate) enum SessionMessage {
+    Command(SessionCommand),
+    Incoming(CompleteMessage<SessionSocket>),
+    Peer(OPeerManagerMessage),
+    Disk(ODiskMessage),
+    Discovered(InfoHash, SocketAddr),
+    // Completed once the session has shut down
+    Shutdown(Option<oneshot::Sender<()>>),
+}
+
+/// Handshaker given to trackers and the DHT, passing the peers they find to the worker.
+#[derive(Clone)]
+pub(crate) struct SessionDiscovery {
+    send: Sender<SessionMessage>,
+    pid: PeerId,
+    port: u16,
+}
+
+impl SessionDiscovery {
+    pub fn new(send: Sender<SessionMessage>, pid: PeerId, port: u16) -> SessionDiscovery {
+        SessionDiscovery {
+            send: send,
+            pid: pid,
+            port: port,
+        }
+    }
+}
+
+impl utracker::Handshaker for SessionDiscovery {
+    type Metadata = ();
+
+    fn id(&self) -> PeerId {
+        self.pid
+    }
+
+    fn port(&self) -> u16 {
+        self.port
+    }
+
+    fn connect(&mut self, _expected: Option<PeerId>, hash: InfoHash, addr: SocketAddr) {
+        let _ = self.send.send(SessionMessage::Discovered(hash, addr));
+    }
+
+    fn metadata(&mut self, _data: ()) {}
+}
+
+impl dht::Handshaker for SessionDiscovery {
+    type Metadata = ();
+
+    fn id(&self) -> PeerId {
+        self.pid
+    }
+
+    fn port(&self) -> u16 {
+        self.port
+    }
+
+    fn connect(&mut self, _expected: Option<PeerId>, hash: InfoHash, addr: SocketAddr) {
+        let _ = self.send.send(SessionMessage::Discovered(hash, addr));
+    }
+
+    fn metadata(&mut self, _data: ()) {}
+}
+
+//----------------------------------------------------------------------------//
+
+struct TorrentEntry {
+    torrent: Torrent,
+    tiers: Vec<Vec<TrackerUrl>>,
+    // Peers from the magnet link, dialed along with those from the options
+    peers: Vec<SocketAddr>,
+    private: bool,
+    removing: bool,
+    opt_trackers: Option<TrackerManager>,
+    since_search: Duration,
+}
+
+/// Runs every torrent of a `Session`, on a thread of its own.
+pub(crate) struct SessionWorker {
+    pid: PeerId,
+    discovery: SessionDiscovery,
+    handshaker: HandshakerManagerSink,
+    peers: PeerManagerSink<SessionSocket>,
+    disk: DiskManagerSink<SessionFileSystem>,
+    uber: UberModule,
+    opt_dht: Option<MainlineDht>,
+    registry: Registry,
+    listeners: Listeners,
+    torrents: HashMap<InfoHash, TorrentEntry>,
+    dialed: HashMap<SocketAddr, Instant>,
+    since_tracker_update: Duration,
+    peer_backlog: VecDeque<(PeerInfo, PieceMessage)>,
+    disk_backlog: VecDeque<IDiskMessage>,
+    opt_state: Option<StateDir>,
+    shutdown_timeout: Duration,
+    metrics: Arc<Metrics>,
+    banner: PeerBanner,
+    seed_policy: SeedPolicy,
+}
+
+impl SessionWorker {
+    pub fn new(
+        discovery: SessionDiscovery,
+        handshaker: HandshakerManagerSink,
+        peers: PeerManagerSink<SessionSocket>,
+        disk: DiskManagerSink<SessionFileSystem>,
+        opt_dht: Option<MainlineDht>,
+        registry: Registry,
+        listeners: Listeners,
+    ) -> SessionWorker {
+        SessionWorker {
+            pid: discovery.pid,
+            discovery: discovery,
+            handshaker: handshaker,
+            peers: peers,
+            disk: disk,
+            uber: UberModuleBuilder::new()
+                .with_extended_builder(Some(ExtendedMessageBuilder::new()))
+                .with_discovery_module(UtMetadataModule::new())
+                .build(),
+            opt_dht: opt_dht,
+            registry: registry,
+            listeners: listeners,
+            torrents: HashMap::new(),
+            dialed: HashMap::new(),
+            since_tracker_update: Duration::from_millis(0),
+            peer_backlog: VecDeque::new(),
+            disk_backlog: VecDeque::new(),
+            opt_state: None,
+            shutdown_timeout: Duration::from_millis(DEFAULT_SHUTDOWN_TIMEOUT_MILLIS),
+            metrics: Arc::new(Metrics::default()),
+            banner: PeerBanner::new(BanList::new()),
+            seed_policy: SeedPolicy::default(),
+        }
+    }
+
+    /// Directory that the DHT state and resume data are saved to.
+    pub fn with_state_dir(mut self, opt_state: Option<StateDir>) -> SessionWorker {
+        self.opt_state = opt_state;
+        self
+    }
+
+    /// Time given to trackers and peers on shutdown.
+    pub fn with_shutdown_timeout(mut self, timeout: Duration) -> SessionWorker {
+        self.shutdown_timeout = timeout;
+        self
+    }
+
+    /// Registry that the torrents and the worker update.
+    pub fn with_metrics(mut self, metrics: Arc<Metrics>) -> SessionWorker {
+        self.metrics = metrics;
+        self
+    }
+
+    /// Banner that scores the peers sending blocks of pieces which fail their hash check.
+    pub fn with_banner(mut self, banner: PeerBanner) -> SessionWorker {
+        self.banner = banner;
+        self
+    }
+
+    /// Policy for torrents that were not given one of their own.
+    pub fn with_seed_policy(mut self, policy: SeedPolicy) -> SessionWorker {
+        self.seed_policy = policy;
+        self
+    }
+
+    /// Handle messages until the session shuts down.
+    pub fn run(mut self, recv: Receiver<SessionMessage>) {
+        let tick = Duration::from_millis(TICK_MILLIS);
+        let mut last_tick = Instant::now();
+
+        let opt_done = loop {
+            match recv.recv_timeout(tick) {
+                Ok(SessionMessage::Shutdown(opt_done)) => break opt_done,
+                Err(RecvTimeoutError::Disconnected) => break None,
+                Ok(message) => self.handle_message(message),
+                Err(RecvTimeoutError::Timeout) => (),
+            }
+
+            let elapsed = last_tick.elapsed();
+            if elapsed >= tick {
+                last_tick = Instant::now();
+                self.tick(elapsed);
+            }
+        };
+
+        Handle::current().block_on(self.shutdown());
+        if let Some(done) = opt_done {
+            let _ = done.send(());
+        }
+    }
+
+    /// Shut every component down, in the order documented on `Session::shutdown`.
+    async fn shutdown(&mut self) {
+        info!(torrents = self.torrents.len(), "session shutting down");
+        let trackers: Vec<TrackerManager> = self
+            .torrents
+            .values_mut()
+            .filter_map(|entry| entry.opt_trackers.take())
+            .collect();
+        future::join_all(trackers.iter().map(|trackers| trackers.shutdown())).await;
+
+        self.peers.shutdown().await;
+
+        // Blocks held back for a full disk manager would be lost otherwise
+        let deadline = Instant::now() + self.shutdown_timeout;
+        self.retry_disk();
+        while !self.disk_backlog.is_empty() && Instant::now() < deadline {
+            tokio::time::sleep(Duration::from_millis(TICK_MILLIS)).await;
+            self.retry_disk();
+        }
+        if let Err(error) = self.disk.shutdown().await {
+            warn!(
+                "bittorrent-protocol_session: Failed To Sync Torrents On Shutdown: {}",
+                error
+            );
+        }
+
+        if let Some(ref state) = self.opt_state {
+            for (hash, entry) in self.torrents.iter() {
+                if entry.removing || entry.torrent.metainfo().is_none() {
+                    continue;
+                }
+
+                match self.disk.save_resume_data(*hash) {
+                    Ok(resume) => {
+                        state.save_resume(*hash, &entry.torrent.resume_with_totals(resume))
+                    }
+                    Err(error) => warn!(
+                        "bittorrent-protocol_session: Failed To Save Resume Data For {:?}: {}",
+                        hash, error
+                    ),
+                }
+            }
+        }
+
+        if let Some(ref dht) = self.opt_dht {
+            if let (Some(dht_state), Some(state)) = (dht.shutdown().await, self.opt_state.as_ref())
+            {
+                state.save_dht(&dht_state);
+            }
+        }
+        info!("session shut down");
+    }
+
+    fn handle_message(&mut self, message: SessionMessage) {
+        match message {
+            SessionMessage::Command(command) => self.handle_command(command),
+            SessionMessage::Incoming(complete) => self.handle_incoming(complete),
+            SessionMessage::Peer(message) => self.handle_peer(message),
+            SessionMessage::Disk(message) => {
+                self.handle_disk(message);
+                self.retry_disk();
+            }
+            SessionMessage::Discovered(hash, addr) => self.dial(hash, addr, false),
+            SessionMessage::Shutdown(_) => (),
+        }
+    }
+
+    fn handle_command(&mut self, command: SessionCommand) {
+        match command {
+            SessionCommand::AddTorrent(hash, source, options, shared) => {
+                self.add_torrent(hash, source, options, shared)
+            }
+            SessionCommand::Pause(hash) => {
+                let entry = match self.torrents.get_mut(&hash) {
+                    Some(entry) if !entry.removing && !entry.torrent.is_paused() => entry,
+                    _ => return,
+                };
+                entry.torrent.set_paused(true);
+                entry.opt_trackers = None;
+
+                for info in entry.torrent.peers() {
+                    self.peers.send(IPeerManagerMessage::RemovePeer(info));
+                }
+                entry.torrent.update_shared();
+                self.emit(TorrentEvent::Paused(hash));
+            }
+            SessionCommand::Resume(hash) => {
+                match self.torrents.get_mut(&hash) {
+                    Some(entry) if !entry.removing && entry.torrent.is_paused() => {
+                        entry.torrent.set_paused(false);
+                        entry.torrent.update_shared();
+                    }
+                    _ => return,
+                }
+
+                self.emit(TorrentEvent::Resumed(hash));
+                self.start(hash);
+            }
+            SessionCommand::Remove(hash, remove_data) => self.remove_torrent(hash, remove_data),
+            SessionCommand::SetFilePriorities(hash, priorities) => {
+                let entry = match self.torrents.get_mut(&hash) {
+                    Some(entry) if !entry.removing => entry,
+                    _ => return,
+                };
+                entry.torrent.set_file_priorities(priorities.clone());
+
+                // Otherwise the priorities go along with adding the torrent to the disk manager
+                if entry.torrent.metainfo().is_some() {
+                    self.send_disk(IDiskMessage::SetFilePriorities(hash, priorities));
+                }
+            }
+            SessionCommand::SetSeedPolicy(hash, opt_policy) => {
+                if let Some(entry) = self.torrents.get_mut(&hash) {
+                    entry.torrent.set_seed_policy(opt_policy);
+                }
+            }
+        }
+    }
+
+    fn add_torrent(
+        &mut self,
+        hash: InfoHash,
+        source: TorrentSource,
+        options: TorrentOptions,
+        shared: Arc<TorrentShared>,
+    ) {
+        // Removed from the registry already, but the disk manager has yet to let go of it
+        if self.torrents.contains_key(&hash) {
+            shared.set_removed();
+            self.registry
+                .lock()
+                .expect("bittorrent-protocol_session: SessionWorker Failed To Lock Registry")
+                .remove(&hash);
+            self.emit(TorrentEvent::Error(
+                hash,
+                "Torrent Is Still Being Removed".to_string(),
+            ));
+
+            return;
+        }
+
+        let (opt_metainfo, tiers, peers, select_only) = match source {
+            TorrentSource::Metainfo(metainfo) => {
+                let tiers = metainfo.tracker_tiers().to_vec();
+
+                (Some(metainfo), tiers, Vec::new(), Vec::new())
+            }
+            TorrentSource::Magnet(magnet) => {
+                let tier = magnet
+                    .get_trackers()
+                    .iter()
+                    .filter_map(|url| TrackerUrl::parse(url))
+                    .collect();
+                let peers = magnet
+                    .get_peers()
+                    .iter()
+                    .filter_map(|&(ref host, port)| {
+                        host.trim_matches(|c| c == '[' || c == ']')
+                            .parse::<IpAddr>()
+                            .ok()
+                            .map(|ip| SocketAddr::new(ip, port))
+                    })
+                    .collect();
+
+                (None, vec![tier], peers, magnet.get_select_only().to_vec())
+            }
+        };
+
+        let mut torrent = Torrent::new(hash, shared, options, self.metrics.clone());
+        torrent.set_select_only(select_only);
+        self.handshaker.add_info_hash(hash);
+        self.torrents.insert(
+            hash,
+            TorrentEntry {
+                torrent: torrent,
+                tiers: tiers,
+                peers: peers,
+                private: opt_metainfo
+                    .as_ref()
+                    .map_or(false, |metainfo| metainfo.is_private()),
+                removing: false,
+                opt_trackers: None,
+                since_search: Duration::from_millis(0),
+            },
+        );
+        self.emit(TorrentEvent::Added(hash));
+        info!(
+            info_hash = %hash,
+            metainfo = opt_metainfo.is_some(),
+            "torrent added"
+        );
+
+        match opt_metainfo {
+            Some(metainfo) => self.set_metainfo(hash, metainfo),
+            None => self.send_uber(IUberMessage::Discovery(
+                IDiscoveryMessage::DownloadMetainfo(hash),
+            )),
+        }
+
+        let paused = self.torrents.get(&hash).map_or(true, |entry| {
+            entry.torrent.update_shared();
+            entry.torrent.is_paused()
+        });
+        if !paused {
+            self.start(hash);
+        }
+    }
+
+    fn set_metainfo(&mut self, hash: InfoHash, metainfo: Metainfo) {
+        let mut out = Outbox::default();
+        let opt_resume = self
+            .opt_state
+            .as_ref()
+            .and_then(|state| state.load_resume(hash));
+
+        if let Some(entry) = self.torrents.get_mut(&hash) {
+            entry.private = metainfo.is_private();
+            entry
+                .torrent
+                .set_metainfo(metainfo.clone(), opt_resume, &mut out);
+        }
+        self.send_uber(IUberMessage::Control(ControlMessage::AddTorrent(metainfo)));
+
+        self.flush(hash, out);
+    }
+
+    fn remove_torrent(&mut self, hash: InfoHash, remove_data: bool) {
+        let entry = match self.torrents.get_mut(&hash) {
+            Some(entry) if !entry.removing => entry,
+            _ => return,
+        };
+        entry.removing = true;
+        entry.opt_trackers = None;
+        info!(info_hash = %hash, remove_data = remove_data, "removing torrent");
+        entry.torrent.set_removed();
+
+        self.registry
+            .lock()
+            .expect("bittorrent-protocol_session: SessionWorker Failed To Lock Registry")
+            .remove(&hash);
+        self.handshaker.remove_info_hash(&hash);
+        if let Some(ref state) = self.opt_state {
+            state.remove_resume(hash);
+        }
+        for info in entry.torrent.peers() {
+            self.peers.send(IPeerManagerMessage::RemovePeer(info));
+        }
+
+        match entry.torrent.metainfo().cloned() {
+            Some(metainfo) => {
+                self.send_uber(IUberMessage::Control(ControlMessage::RemoveTorrent(
+                    metainfo,
+                )));
+                self.send_disk(if remove_data {
+                    IDiskMessage::RemoveTorrentWithData(hash)
+                } else {
+                    IDiskMessage::RemoveTorrent(hash)
+                });
+            }
+            // Nothing was handed to the disk manager
+            None => self.finish_remove(hash),
+        }
+    }
+
+    fn finish_remove(&mut self, hash: InfoHash) {
+        if self.torrents.remove(&hash).is_some() {
+            self.emit(TorrentEvent::Removed(hash));
+        }
+    }
+
+    /// Announce the torrent, and dial the peers we were given.
+    fn start(&mut self, hash: InfoHash) {
+        let entry = match self.torrents.get_mut(&hash) {
+            Some(entry) => entry,
+            None => return,
+        };
+
+        // Udp and websocket trackers need a client of their own
+        let tiers: Vec<Vec<TrackerUrl>> = entry
+            .tiers
+            .iter()
+            .map(|tier| {
+                tier.iter()
+                    .filter(|url| {
+                        matches!(
+                            url.protocol(),
+                            TrackerProtocol::Http | TrackerProtocol::Https
+                        )
+                    })
+                    .cloned()
+                    .collect::<Vec<TrackerUrl>>()
+            })
+            .filter(|tier| !tier.is_empty())
+            .collect();
+        if !tiers.is_empty() {
+            entry.opt_trackers = Some(
+                TrackerManagerBuilder::new()
+                    .with_stop_timeout(self.shutdown_timeout)
+                    .build(
+                        hash,
+                        tiers,
+                        client_state(&entry.torrent),
+                        HttpTrackerClient::new(),
+                        self.discovery.clone(),
+                    ),
+            );
+        }
+
+        if let Some(ref dht) = self.opt_dht {
+            if !entry.private {
+                dht.search(hash, true);
+            }
+        }
+        entry.since_search = Duration::from_millis(0);
+
+        let addrs: Vec<SocketAddr> = entry
+            .torrent
+            .options()
+            .peers()
+            .iter()
+            .chain(entry.peers.iter())
+            .cloned()
+            .collect();
+        for addr in addrs {
+            self.dial(hash, addr, true);
+        }
+    }
+
+    fn dial(&mut self, hash: InfoHash, addr: SocketAddr, ignore_cooldown: bool) {
+        // Dialing stops once the torrent, or the session, has as many peers as it may keep
+        let capacity = self.peers.capacity();
+        let session_full = self.num_peers() >= capacity.peer_capacity();
+        match self.torrents.get(&hash) {
+            Some(entry)
+                if !entry.removing
+                    && !entry.torrent.is_paused()
+                    && !entry.torrent.has_addr(&addr)
+                    && entry.torrent.num_peers() < capacity.torrent_peer_capacity()
+                    && !session_full => {}
+            _ => return,
+        }
+
+        let now = Instant::now();
+        let cooling_down = self.dialed.get(&addr).map_or(false, |dialed_at| {
+            now.duration_since(*dialed_at) < Duration::from_millis(DIAL_COOLDOWN_MILLIS)
+        });
+        if cooling_down && !ignore_cooldown {
+            return;
+        }
+        self.dialed.insert(addr, now);
+        debug!(info_hash = %hash, peer = %addr, "dialing peer");
+
+        if self
+            .handshaker
+            .send(InitiateMessage::new(Protocol::BitTorrent, hash, addr))
+            .is_err()
+        {
+            warn!(
+                "bittorrent-protocol_session: Handshaker Has Shut Down, Not Dialing {:?}",
+                addr
+            );
+        }
+    }
+
+    fn handle_incoming(&mut self, complete: CompleteMessage<SessionSocket>) {
+        let (_, extensions, hash, pid, addr, sock) = complete.into_parts();
+
+        match self.torrents.get(&hash) {
+            Some(entry)
+                if !entry.removing
+                    && !entry.torrent.is_paused()
+                    && pid != self.pid
+                    && !entry.torrent.has_peer_id(&pid) => {}
+            _ => {
+                // Dropping the socket closes the connection
+                self.update_connected();
+                return;
+            }
+        }
+        self.make_room(hash);
+
+        let info = PeerInfo::new(addr, pid, hash, extensions);
+        if let Some(entry) = self.torrents.get_mut(&hash) {
+            entry.torrent.add_peer(info);
+        }
+        self.peers.send(IPeerManagerMessage::AddPeer(info, sock));
+        self.update_connected();
+    }
+
+    /// Shed a seed while we are seeding too, if a peer of the torrent would not fit otherwise.
+    ///
+    /// Peers that still do not fit are turned away by the peer manager.
+    fn make_room(&mut self, hash: InfoHash) {
+        let capacity = self.peers.capacity();
+        let torrent_full = self.torrents.get(&hash).map_or(false, |entry| {
+            entry.torrent.num_peers() >= capacity.torrent_peer_capacity()
+        });
+
+        let opt_seed = if torrent_full {
+            self.torrents
+                .get_mut(&hash)
+                .and_then(|entry| entry.torrent.shed_seed())
+        } else if self.num_peers() >= capacity.peer_capacity() {
+            self.torrents
+                .values_mut()
+                .find_map(|entry| entry.torrent.shed_seed())
+        } else {
+            None
+        };
+
+        if let Some(seed) = opt_seed {
+            debug!(info_hash = %seed.hash(), peer = %seed.addr(), "shedding seed");
+
+            self.peers.send(IPeerManagerMessage::ShedPeer(seed));
+        }
+    }
+
+    /// Number of peers across every torrent, leaving out those being shed.
+    fn num_peers(&self) -> usize {
+        self.torrents
+            .values()
+            .map(|entry| entry.torrent.num_peers())
+            .sum()
+    }
+
+    /// Tell the handshaker how many peers we kept, so it knows how many more it may accept.
+    fn update_connected(&self) {
+        self.handshaker
+            .connection_limits()
+            .set_connected_peers(self.num_peers());
+    }
+
+    fn handle_peer(&mut self, message: OPeerManagerMessage) {
+        if let (Some(node), Some(dht)) = (message.dht_node(), self.opt_dht.as_ref()) {
+            dht.add_node(node);
+        }
+
+        match message {
+            OPeerManagerMessage::PeerAdded(info, _) => {
+                let mut out = Outbox::default();
+                if let Some(entry) = self.torrents.get_mut(info.hash()) {
+                    entry.torrent.on_peer_added(info, &mut out);
+                }
+                self.flush(*info.hash(), out);
+
+                self.send_uber(IUberMessage::Control(ControlMessage::PeerConnected(info)));
+            }
+            OPeerManagerMessage::PeerRemoved(info)
+            | OPeerManagerMessage::PeerDisconnected { info, .. } => {
+                let mut out = Outbox::default();
+                if let Some(entry) = self.torrents.get_mut(info.hash()) {
+                    entry.torrent.on_peer_removed(info, &mut out);
+                }
+                self.flush(*info.hash(), out);
+                self.update_connected();
+
+                self.send_uber(IUberMessage::Control(ControlMessage::PeerDisconnected(
+                    info,
+                )));
+            }
+            OPeerManagerMessage::ReceivedMessage(info, message) => match message {
+                PeerWireProtocolMessage::BitsExtension(BitsExtensionMessage::Extended(
+                    extended,
+                )) => {
+                    if let Some(entry) = self.torrents.get_mut(info.hash()) {
+                        entry.torrent.on_extended(&info, &extended);
+                    }
+
+                    self.send_uber(IUberMessage::Extended(
+                        IExtendedMessage::RecievedExtendedMessage(info, extended),
+                    ));
+                }
+                PeerWireProtocolMessage::ProtExtension(
+                    PeerExtensionProtocolMessage::UtMetadata(message),
+                ) => {
+                    self.send_uber(IUberMessage::Discovery(
+                        IDiscoveryMessage::ReceivedUtMetadataMessage(info, message),
+                    ));
+                }
+                message => {
+                    let mut out = Outbox::default();
+                    if let Some(entry) = self.torrents.get_mut(info.hash()) {
+                        entry.torrent.on_message(info, message, &mut out);
+                    }
+                    self.flush(*info.hash(), out);
+                }
+            },
+            OPeerManagerMessage::SentMessage(_, _) => (),
+        }
+    }
+
+    fn handle_disk(&mut self, message: ODiskMessage) {
+        let hash = match message {
+            ODiskMessage::BlockLoaded(ref block) | ODiskMessage::LoadBlockError(ref block, _) => {
+                block.metadata().info_hash()
+            }
+            ODiskMessage::BlockProcessed(ref block)
+            | ODiskMessage::ProcessBlockError(ref block, _) => block.metadata().info_hash(),
+            ODiskMessage::TorrentAdded(hash)
+            | ODiskMessage::TorrentRemoved(hash)
+            | ODiskMessage::FoundGoodPiece(hash, _)
+            | ODiskMessage::FoundBadPiece(hash, _)
+            | ODiskMessage::FilePrioritiesSet(hash, _)
+            | ODiskMessage::TorrentPaused(hash, _)
+            | ODiskMessage::TorrentError(hash, _) => hash,
+            _ => return,
+        };
+        let entry = match self.torrents.get_mut(&hash) {
+            Some(entry) => entry,
+            None => return,
+        };
+
+        if entry.removing {
+            match message {
+                ODiskMessage::TorrentRemoved(_) => self.finish_remove(hash),
+                ODiskMessage::TorrentError(_, error) => {
+                    self.emit(TorrentEvent::Error(hash, error.to_string()));
+                    // Removing the torrent only fails for its files, the torrent itself is gone
+                    self.finish_remove(hash);
+                }
+                _ => (),
+            }
+            return;
+        }
+
+        let mut out = Outbox::default();
+        match message {
+            ODiskMessage::TorrentAdded(_) => {
+                let opt_priorities = self.disk.piece_priorities(hash).ok();
+                entry.torrent.on_added(opt_priorities, &mut out);
+            }
+            ODiskMessage::FoundGoodPiece(_, piece) => entry.torrent.on_good_piece(piece, &mut out),
+            ODiskMessage::FoundBadPiece(_, piece) => entry.torrent.on_bad_piece(piece, &mut out),
+            ODiskMessage::FilePrioritiesSet(_, priorities) => {
+                entry.torrent.on_priorities_set(priorities, &mut out)
+            }
+            ODiskMessage::BlockLoaded(block) => entry.torrent.on_block_loaded(block, &mut out),
+            ODiskMessage::LoadBlockError(block, error) => {
+                warn!(
+                    "bittorrent-protocol_session: Failed To Load Block: {}",
+                    error
+                );
+                entry.torrent.on_load_error(block, &mut out);
+            }
+            ODiskMessage::ProcessBlockError(block, error) => {
+                info!(
+                    "bittorrent-protocol_session: Failed To Process Block: {}",
+                    error
+                );
+                entry.torrent.on_process_error(block.metadata(), &mut out);
+            }
+            ODiskMessage::TorrentPaused(_, fault) => out.events.push(TorrentEvent::Error(
+                hash,
+                format!("Disk Fault: {:?}", fault),
+            )),
+            ODiskMessage::TorrentError(_, error) => out
+                .events
+                .push(TorrentEvent::Error(hash, error.to_string())),
+            _ => (),
+        }
+        entry.torrent.update_shared();
+
+        self.flush(hash, out);
+    }
+
+    fn handle_uber(&mut self, message: OUberMessage) {
+        match message {
+            OUberMessage::Extended(OExtendedMessage::SendExtendedMessage(info, extended)) => self
+                .send_peer(
+                    info,
+                    PeerWireProtocolMessage::BitsExtension(BitsExtensionMessage::Extended(
+                        extended,
+                    )),
+                ),
+            OUberMessage::Discovery(ODiscoveryMessage::SendUtMetadataMessage(info, message)) => {
+                self.send_peer(
+                    info,
+                    PeerWireProtocolMessage::ProtExtension(
+                        PeerExtensionProtocolMessage::UtMetadata(message),
+                    ),
+                )
+            }
+            OUberMessage::Discovery(ODiscoveryMessage::DownloadedMetainfo(metainfo)) => {
+                let hash = metainfo.info().info_hash();
+
+                match self.torrents.get(&hash) {
+                    Some(entry) if !entry.removing && entry.torrent.metainfo().is_none() => {
+                        self.emit(TorrentEvent::MetadataReceived(hash));
+                        self.set_metainfo(hash, metainfo);
+
+                        if let Some(entry) = self.torrents.get(&hash) {
+                            entry.torrent.update_shared();
+                        }
+                    }
+                    Some(_) => (),
+                    None => {
+                        // Metadata is not checked against the info hash it was requested for
+                        warn!(
+                            "bittorrent-protocol_session: Downloaded Metainfo Matches No Torrent"
+                        );
+                        let missing: Vec<InfoHash> = self
+                            .torrents
+                            .iter()
+                            .filter(|&(_, entry)| {
+                                !entry.removing && entry.torrent.metainfo().is_none()
+                            })
+                            .map(|(hash, _)| *hash)
+                            .collect();
+
+                        for hash in missing {
+                            self.send_uber(IUberMessage::Discovery(
+                                IDiscoveryMessage::DownloadMetainfo(hash),
+                            ));
+                        }
+                    }
+                }
+            }
+            _ => (),
+        }
+    }
+
+    fn tick(&mut self, elapsed: Duration) {
+        self.send_uber(IUberMessage::Control(ControlMessage::Tick(elapsed)));
+        let stats = self.peers.all_peer_stats();
+
+        self.since_tracker_update += elapsed;
+        let update_trackers =
+            self.since_tracker_update >= Duration::from_millis(TRACKER_UPDATE_MILLIS);
+        if update_trackers {
+            self.since_tracker_update = Duration::from_millis(0);
+        }
+
+        let hashes: Vec<InfoHash> = self.torrents.keys().cloned().collect();
+        for hash in hashes {
+            let mut out = Outbox::default();
+            let mut auto_pause = false;
+
+            if let Some(entry) = self.torrents.get_mut(&hash) {
+                if entry.removing {
+                    continue;
+                }
+                entry.torrent.tick(elapsed, &stats, &mut out);
+
+                let policy = *entry
+                    .torrent
+                    .options()
+                    .seed_policy()
+                    .unwrap_or(&self.seed_policy);
+                let opt_swarm = entry
+                    .opt_trackers
+                    .as_ref()
+                    .and_then(|trackers| swarm(&trackers.status()));
+                if let Some(which) = entry.torrent.check_seed_limit(&policy, opt_swarm) {
+                    out.events.push(TorrentEvent::SeedLimitReached {
+                        hash: hash,
+                        which: which,
+                    });
+                    auto_pause = policy.auto_pause();
+                }
+                entry.torrent.update_shared();
+
+                if let (true, Some(trackers)) = (update_trackers, entry.opt_trackers.as_ref()) {
+                    trackers.update_state(client_state(&entry.torrent));
+                }
+
+                entry.since_search += elapsed;
+                if entry.since_search >= Duration::from_millis(DHT_SEARCH_MILLIS)
+                    && !entry.torrent.is_paused()
+                {
+                    entry.since_search = Duration::from_millis(0);
+
+                    if let (false, Some(dht)) = (entry.private, self.opt_dht.as_ref()) {
+                        dht.search(hash, true);
+                    }
+                }
+            }
+
+            self.flush(hash, out);
+            if auto_pause {
+                self.handle_command(SessionCommand::Pause(hash));
+            }
+        }
+
+        let now = Instant::now();
+        self.dialed.retain(|_, dialed_at| {
+            now.duration_since(*dialed_at) < Duration::from_millis(DIAL_COOLDOWN_MILLIS)
+        });
+
+        self.retry_peers();
+        self.retry_disk();
+        self.publish_metrics();
+    }
+
+    /// Publish the gauges of the session metrics.
+    fn publish_metrics(&self) {
+        let trackers = self
+            .torrents
+            .iter()
+            .filter_map(|(hash, entry)| {
+                entry
+                    .opt_trackers
+                    .as_ref()
+                    .map(|trackers| (*hash, trackers.status()))
+            })
+            .collect();
+
+        self.metrics
+            .set_disk_queue_depth(self.disk.queue_depth() + self.disk_backlog.len());
+        self.metrics.set_gauges(
+            self.disk.block_cache_stats(),
+            self.opt_dht.as_ref().map(|dht| dht.status()),
+            trackers,
+        );
+    }
+
+    /// Send out everything that the torrent produced.
+    fn flush(&mut self, hash: InfoHash, out: Outbox) {
+        for (info, message) in out.peer {
+            self.send_peer(info, message);
+        }
+        for message in out.disk {
+            self.send_disk(message);
+        }
+        for contributors in out.failed {
+            if let Some(ip) = self.banner.on_piece_failed(&contributors) {
+                self.disconnect_banned(ip);
+            }
+        }
+
+        for event in out.events {
+            if let TorrentEvent::Completed(_) = event {
+                if let Some(trackers) = self
+                    .torrents
+                    .get(&hash)
+                    .and_then(|entry| entry.opt_trackers.as_ref())
+                {
+                    trackers.completed();
+                }
+            }
+
+            self.emit(event);
+        }
+    }
+
+    /// Disconnect every peer from the banned address, across every torrent.
+    fn disconnect_banned(&mut self, ip: IpAddr) {
+        info!(addr = %ip, "banning address for sending corrupt blocks");
+
+        let banned: Vec<PeerInfo> = self
+            .torrents
+            .values()
+            .flat_map(|entry| entry.torrent.peers())
+            .filter(|info| info.addr().ip() == ip)
+            .collect();
+        for info in banned {
+            self.peers.send(IPeerManagerMessage::DisconnectPeer(
+                info,
+                PeerDisconnectReason::ProtocolViolation("hash failures"),
+            ));
+        }
+    }
+
+    fn send_uber(&mut self, message: IUberMessage) {
+        if let Err(error) = self.uber.send(message) {
+            warn!(
+                "bittorrent-protocol_session: Uber Module Failed To Send: {}",
+                error
+            );
+        }
+
+        loop {
+            match self.uber.poll() {
+                Ok(Some(message)) => self.handle_uber(message),
+                Ok(None) => break,
+                Err(error) => {
+                    warn!(
+                        "bittorrent-protocol_session: Uber Module Failed To Poll: {}",
+                        error
+                    );
+                    break;
+                }
+            }
+        }
+    }
+
+    fn send_peer(&mut self, info: PeerInfo, message: PeerWireProtocolMessage) {
+        match message {
+            PeerWireProtocolMessage::Piece(piece) => self.send_piece(info, piece),
+            message => {
+                // Control messages are never held back, the peer may be gone though
+                let _ = self
+                    .peers
+                    .try_send(IPeerManagerMessage::SendMessage(info, 0, message));
+            }
+        }
+    }
+
+    /// Send the piece, keeping it around to send again while the queue of the peer is full.
+    fn send_piece(&mut self, info: PeerInfo, piece: PieceMessage) {
+        let length = piece.block_length();
+        let retry = piece.clone();
+
+        match self.peers.try_send(IPeerManagerMessage::SendMessage(
+            info,
+            0,
+            PeerWireProtocolMessage::Piece(piece),
+        )) {
+            Ok(()) => {
+                if let Some(entry) = self.torrents.get_mut(info.hash()) {
+                    entry.torrent.on_piece_sent(length);
+                }
+            }
+            Err(error) => {
+                if let PeerManagerErrorKind::QueueFull { .. } = *error.kind() {
+                    self.peer_backlog.push_back((info, retry));
+                }
+            }
+        }
+    }
+
+    fn send_disk(&mut self, message: IDiskMessage) {
+        if !self.disk_backlog.is_empty() {
+            self.disk_backlog.push_back(message);
+        } else if let Err(message) = self.disk.try_send(message) {
+            self.disk_backlog.push_back(message);
+        }
+    }
+
+    fn retry_peers(&mut self) {
+        let backlog: Vec<(PeerInfo, PieceMessage)> = self.peer_backlog.drain(..).collect();
+
+        for (info, piece) in backlog {
+            self.send_piece(info, piece);
+        }
+    }
+
+    fn retry_disk(&mut self) {
+        while let Some(message) = self.disk_backlog.pop_front() {
+            if let Err(message) = self.disk.try_send(message) {
+                self.disk_backlog.push_front(message);
+                break;
+            }
+        }
+    }
+
+    fn emit(&self, event: TorrentEvent) {
+        self.listeners
+            .lock()
+            .expect("bittorrent-protocol_session: SessionWorker Failed To Lock Listeners")
+            .retain(|send| send.send(event.clone()).is_ok());
+    }
+}
+
+/// Seeds and leechers of the largest swarm that any of the trackers reported.
+fn swarm(status: &[TrackerStatus]) -> Option<(u64, u64)> {
+    status
+        .iter()
+        .filter_map(|tracker| match (tracker.seeders(), tracker.leechers()) {
+            (Some(seeders), Some(leechers)) => Some((seeders, leechers)),
+            _ => None,
+        })
+        .max_by_key(|&(seeders, leechers)| seeders + leechers)
+}
+
+/// State of the torrent reported to its trackers.
+fn client_state(torrent: &Torrent) -> ClientState {
+    ClientState::new(
+        torrent.downloaded() as i64,
+        torrent.bytes_left() as i64,
+        torrent.uploaded() as i64,
+        AnnounceEvent::None,
+    )
+}
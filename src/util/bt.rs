@@ -1,4 +1,8 @@
-use super::sha;
+use std::fmt;
+use std::str::FromStr;
+
+use super::error::{LengthError, LengthErrorKind, LengthResult, ParseInfoHashError};
+use super::sha::{self, ShaHash};
 
 /// Bittorrent `NodeId`.
 pub type NodeId = sha::ShaHash;
@@ -6,14 +10,264 @@ pub type NodeId = sha::ShaHash;
 /// Bittorrent `PeerId`.
 pub type PeerId = sha::ShaHash;
 
-/// Bittorrent `InfoHash`.
-pub type InfoHash = sha::ShaHash;
-
 /// Length of a `NodeId`.
 pub const NODE_ID_LEN: usize = sha::SHA_HASH_LEN;
 
 /// Length of a `PeerId`.
 pub const PEER_ID_LEN: usize = sha::SHA_HASH_LEN;
 
-/// Length of an `InfoHash`.
+/// Length of a v1 `InfoHash`, and of any `InfoHash` as it is sent to peers.
 pub const INFO_HASH_LEN: usize = sha::SHA_HASH_LEN;
+
+/// Length of a v2 `InfoHash`.
+pub const INFO_HASH_V2_LEN: usize = 32;
+
+/// Bittorrent `InfoHash`, of either a v1 or a v2 torrent.
+///
+/// Peers, trackers and the DHT are only ever sent the first 20 bytes of a v2 hash, so
+/// hashes handed to those are truncated, and hashes coming from the wire are always v1.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, PartialOrd, Ord)]
+pub enum InfoHash {
+    /// SHA-1 hash of the info dictionary of a v1 torrent.
+    V1([u8; INFO_HASH_LEN]),
+    /// SHA-256 hash of the info dictionary of a v2 torrent.
+    V2([u8; INFO_HASH_V2_LEN]),
+}
+
+impl InfoHash {
+    /// Create a v1 InfoHash by hashing the given bytes.
+    pub fn from_bytes(bytes: &[u8]) -> InfoHash {
+        InfoHash::from(ShaHash::from_bytes(bytes))
+    }
+
+    /// Create an InfoHash directly from the given hash, v1 for 20 bytes and v2 for 32 bytes.
+    pub fn from_hash(hash: &[u8]) -> LengthResult<InfoHash> {
+        match hash.len() {
+            INFO_HASH_LEN => {
+                let mut v1 = [0u8; INFO_HASH_LEN];
+                v1.copy_from_slice(hash);
+
+                Ok(InfoHash::V1(v1))
+            }
+            INFO_HASH_V2_LEN => {
+                let mut v2 = [0u8; INFO_HASH_V2_LEN];
+                v2.copy_from_slice(hash);
+
+                Ok(InfoHash::V2(v2))
+            }
+            _ => Err(LengthError::new(
+                LengthErrorKind::LengthExpected,
+                INFO_HASH_LEN,
+            )),
+        }
+    }
+
+    /// Hash as it is sent to peers, trackers and the DHT.
+    ///
+    /// That is the first 20 bytes of a v2 hash, and the whole of a v1 hash.
+    pub fn truncated(&self) -> [u8; INFO_HASH_LEN] {
+        let mut truncated = [0u8; INFO_HASH_LEN];
+        truncated.copy_from_slice(&self.as_ref()[..INFO_HASH_LEN]);
+
+        truncated
+    }
+
+    /// Whether or not the hash is that of a v2 torrent.
+    pub fn is_v2(&self) -> bool {
+        match *self {
+            InfoHash::V1(_) => false,
+            InfoHash::V2(_) => true,
+        }
+    }
+
+    /// Length of a v1 hash, which is that of any hash as it is sent to peers.
+    pub fn len() -> usize {
+        INFO_HASH_LEN
+    }
+}
+
+/// Lowercase hex, as hashes are shown in magnet links and by most clients.
+impl fmt::Display for InfoHash {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.as_ref()
+            .iter()
+            .try_for_each(|byte| write!(f, "{:02x}", byte))
+    }
+}
+
+/// Parses 40 hex digits or 32 base-32 digits as a v1 hash, and 64 hex digits as a v2 hash.
+///
+/// Digits are case insensitive, and surrounding whitespace is ignored.
+impl FromStr for InfoHash {
+    type Err = ParseInfoHashError;
+
+    fn from_str(s: &str) -> Result<InfoHash, ParseInfoHashError> {
+        let s = s.trim();
+
+        let opt_hash = match s.len() {
+            len if len == 2 * INFO_HASH_LEN || len == 2 * INFO_HASH_V2_LEN => decode_hex(s),
+            32 => base32::decode(
+                base32::Alphabet::RFC4648 { padding: false },
+                &s.to_ascii_uppercase(),
+            ),
+            len => return Err(ParseInfoHashError::InvalidLength(len)),
+        };
+
+        opt_hash
+            .and_then(|hash| InfoHash::from_hash(&hash).ok())
+            .ok_or(ParseInfoHashError::InvalidDigit)
+    }
+}
+
+fn decode_hex(s: &str) -> Option<Vec<u8>> {
+    if !s.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+        return None;
+    }
+
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&s[i..i + 2], 16).ok())
+        .collect()
+}
+
+impl AsRef<[u8]> for InfoHash {
+    fn as_ref(&self) -> &[u8] {
+        match *self {
+            InfoHash::V1(ref hash) => hash,
+            InfoHash::V2(ref hash) => hash,
+        }
+    }
+}
+
+impl From<[u8; INFO_HASH_LEN]> for InfoHash {
+    fn from(hash: [u8; INFO_HASH_LEN]) -> InfoHash {
+        InfoHash::V1(hash)
+    }
+}
+
+impl From<[u8; INFO_HASH_V2_LEN]> for InfoHash {
+    fn from(hash: [u8; INFO_HASH_V2_LEN]) -> InfoHash {
+        InfoHash::V2(hash)
+    }
+}
+
+impl From<ShaHash> for InfoHash {
+    fn from(hash: ShaHash) -> InfoHash {
+        InfoHash::V1(hash.into())
+    }
+}
+
+/// Truncates a v2 hash, see `InfoHash::truncated`.
+impl From<InfoHash> for [u8; INFO_HASH_LEN] {
+    fn from(hash: InfoHash) -> [u8; INFO_HASH_LEN] {
+        hash.truncated()
+    }
+}
+
+/// Truncates a v2 hash, see `InfoHash::truncated`.
+impl From<InfoHash> for ShaHash {
+    fn from(hash: InfoHash) -> ShaHash {
+        ShaHash::from(hash.truncated())
+    }
+}
+
+impl PartialEq<[u8]> for InfoHash {
+    fn eq(&self, other: &[u8]) -> bool {
+        self.as_ref() == other
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::{InfoHash, INFO_HASH_LEN, INFO_HASH_V2_LEN};
+    use crate::util::error::ParseInfoHashError;
+
+    const V1_HEX: &'static str = "c12fe1c06bba254a9dc9f519b335aa7c1367a88a";
+    const V1_BASE32: &'static str = "YEX6DQDLXISUVHOJ6UM3GNNKPQJWPKEK";
+    const V2_HEX: &'static str =
+        "caf1e1c30e81cb361b9ee167c4aa64228a7fa4fa9f6105232b28ad099f3a302e";
+
+    fn v1() -> InfoHash {
+        InfoHash::V1([
+            0xc1, 0x2f, 0xe1, 0xc0, 0x6b, 0xba, 0x25, 0x4a, 0x9d, 0xc9, 0xf5, 0x19, 0xb3, 0x35,
+            0xaa, 0x7c, 0x13, 0x67, 0xa8, 0x8a,
+        ])
+    }
+
+    fn parse(s: &str) -> InfoHash {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn positive_parse_hex_and_base32() {
+        assert_eq!(v1(), parse(V1_HEX));
+        assert_eq!(v1(), parse(V1_BASE32));
+
+        let v2 = parse(V2_HEX);
+        assert!(v2.is_v2());
+        assert_eq!(INFO_HASH_V2_LEN, v2.as_ref().len());
+        assert_eq!(V2_HEX, v2.to_string());
+    }
+
+    #[test]
+    fn positive_parse_mixed_case_and_whitespace() {
+        assert_eq!(v1(), parse(&V1_HEX.to_uppercase()));
+        assert_eq!(v1(), parse(&V1_BASE32.to_lowercase()));
+        assert_eq!(v1(), parse(&format!("  {}\n", V1_HEX)));
+        assert_eq!(
+            parse(V2_HEX),
+            parse(&format!("\t{}  ", V2_HEX.to_uppercase()))
+        );
+    }
+
+    #[test]
+    fn negative_parse_invalid() {
+        assert_eq!(
+            Err(ParseInfoHashError::InvalidLength(39)),
+            V1_HEX[1..].parse::<InfoHash>()
+        );
+        assert_eq!(
+            Err(ParseInfoHashError::InvalidDigit),
+            V1_HEX.replace('c', "g").parse::<InfoHash>()
+        );
+        assert_eq!(
+            Err(ParseInfoHashError::InvalidDigit),
+            V1_BASE32.replace('Y', "1").parse::<InfoHash>()
+        );
+        assert_eq!(
+            Err(ParseInfoHashError::InvalidDigit),
+            V1_HEX.replace("c1", "+f").parse::<InfoHash>()
+        );
+    }
+
+    #[test]
+    fn positive_display_lowercase_hex() {
+        assert_eq!(V1_HEX, v1().to_string());
+    }
+
+    #[test]
+    fn positive_truncated() {
+        let v2 = parse(V2_HEX);
+
+        assert_eq!(v1().as_ref(), &v1().truncated()[..]);
+        assert_eq!(&v2.as_ref()[..INFO_HASH_LEN], &v2.truncated()[..]);
+        assert_eq!(InfoHash::V1(v2.truncated()), InfoHash::from(v2.truncated()));
+        assert_ne!(v2, InfoHash::from(v2.truncated()));
+    }
+
+    #[test]
+    fn positive_map_keys() {
+        let v2 = parse(V2_HEX);
+        let mut map = BTreeMap::new();
+
+        map.insert(v2, 2);
+        map.insert(v1(), 1);
+        map.insert(InfoHash::from(v2.truncated()), 3);
+
+        assert_eq!(3, map.len());
+        assert_eq!(Some(&1), map.get(&v1()));
+        assert_eq!(Some(&2), map.get(&v2));
+    }
+}
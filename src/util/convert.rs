@@ -1,5 +1,8 @@
 use std::net::{Ipv4Addr, Ipv6Addr, SocketAddrV4, SocketAddrV6};
 
+const BASE64_ALPHABET: &'static [u8] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
 /// Convert a 4 byte value to an array of 4 bytes.
 pub fn four_bytes_to_array(bytes: u32) -> [u8; 4] {
     let eight_bytes = eight_bytes_to_array(bytes as u64);
@@ -156,6 +159,58 @@ pub fn bytes_be_to_sock_v6(bytes: [u8; 18]) -> SocketAddrV6 {
     SocketAddrV6::new(ip, port, 0, 0)
 }
 
+/// Convert bytes to their padded, standard alphabet base64 encoding.
+pub fn bytes_to_base64(bytes: &[u8]) -> String {
+    let mut encoded = String::with_capacity((bytes.len() + 2) / 3 * 4);
+
+    for chunk in bytes.chunks(3) {
+        let group = (chunk[0] as u32) << 16
+            | (*chunk.get(1).unwrap_or(&0) as u32) << 8
+            | *chunk.get(2).unwrap_or(&0) as u32;
+
+        for index in 0..4 {
+            if index <= chunk.len() {
+                let sextet = (group >> (18 - index * 6)) & 0x3F;
+                encoded.push(BASE64_ALPHABET[sextet as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+
+    encoded
+}
+
+/// Convert a padded, standard alphabet base64 encoding back to bytes.
+pub fn base64_to_bytes(encoded: &str) -> Option<Vec<u8>> {
+    let encoded = encoded.as_bytes();
+    if encoded.len() % 4 != 0 {
+        return None;
+    }
+
+    let mut bytes = Vec::with_capacity(encoded.len() / 4 * 3);
+    for (chunk_index, chunk) in encoded.chunks(4).enumerate() {
+        let is_last = (chunk_index + 1) * 4 == encoded.len();
+        let padding = chunk.iter().rev().take_while(|&&n| n == b'=').count();
+        if padding > 2 || (padding > 0 && !is_last) {
+            return None;
+        }
+
+        let mut group = 0u32;
+        for &symbol in &chunk[..4 - padding] {
+            let sextet = BASE64_ALPHABET.iter().position(|&n| n == symbol)?;
+
+            group = group << 6 | sextet as u32;
+        }
+        group <<= 6 * padding as u32;
+
+        let group_bytes = [(group >> 16) as u8, (group >> 8) as u8, group as u8];
+        bytes.extend_from_slice(&group_bytes[..3 - padding]);
+    }
+
+    Some(bytes)
+}
+
 #[cfg(test)]
 mod tests {
     use std::net::{Ipv4Addr, Ipv6Addr, SocketAddrV4, SocketAddrV6};
@@ -315,4 +370,30 @@ mod tests {
 
         assert_eq!(expected_sock, result_sock);
     }
+
+    #[test]
+    fn positive_bytes_to_base64() {
+        assert_eq!(super::bytes_to_base64(b""), "");
+        assert_eq!(super::bytes_to_base64(b"f"), "Zg==");
+        assert_eq!(super::bytes_to_base64(b"fo"), "Zm8=");
+        assert_eq!(super::bytes_to_base64(b"foo"), "Zm9v");
+        assert_eq!(super::bytes_to_base64(b"user:pass"), "dXNlcjpwYXNz");
+    }
+
+    #[test]
+    fn positive_base64_to_bytes() {
+        for bytes in [&b""[..], b"f", b"fo", b"foo", b"user:pass", b"\x00\xff\xfe"].iter() {
+            let encoded = super::bytes_to_base64(bytes);
+
+            assert_eq!(super::base64_to_bytes(&encoded).as_deref(), Some(*bytes));
+        }
+    }
+
+    #[test]
+    fn negative_base64_to_bytes() {
+        assert_eq!(super::base64_to_bytes("Zg="), None);
+        assert_eq!(super::base64_to_bytes("Zg==Zg=="), None);
+        assert_eq!(super::base64_to_bytes("Z==="), None);
+        assert_eq!(super::base64_to_bytes("Zm9-"), None);
+    }
 }
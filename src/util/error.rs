@@ -1,3 +1,6 @@
+use std::error::Error;
+use std::fmt;
+
 /// Result type for a `LengthError`.
 pub type LengthResult<T> = Result<T, LengthError>;
 
@@ -49,3 +52,25 @@ impl LengthError {
         self.index
     }
 }
+
+/// Error parsing an `InfoHash` from its hex or base-32 digits.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub enum ParseInfoHashError {
+    /// Number of digits is not that of any info hash.
+    InvalidLength(usize),
+    /// Digits are not valid hex or base-32.
+    InvalidDigit,
+}
+
+impl fmt::Display for ParseInfoHashError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ParseInfoHashError::InvalidLength(len) => {
+                write!(f, "Info Hash Has An Invalid Length Of {}", len)
+            }
+            ParseInfoHashError::InvalidDigit => write!(f, "Info Hash Has An Invalid Digit"),
+        }
+    }
+}
+
+impl Error for ParseInfoHashError {}
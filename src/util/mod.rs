@@ -12,6 +12,9 @@ pub mod convert;
 /// Networking primitives and helpers.
 pub mod net;
 
+/// Connecting through SOCKS5 and HTTP proxies.
+pub mod proxy;
+
 /// Generic sender utilities.
 pub mod send;
 
@@ -1,4 +1,4 @@
-use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4};
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6};
 
 /// Abstraction of some ip address.
 #[derive(Copy, Clone, Eq, PartialEq, Debug, Hash)]
@@ -24,3 +24,53 @@ pub fn default_route_v4() -> SocketAddr {
 
     SocketAddr::V4(v4_sock)
 }
+
+/// Get the default route ipv6 socket.
+///
+/// On most platforms this also accepts ipv4 peers, as ipv4 mapped ipv6 addresses.
+pub fn default_route_v6() -> SocketAddr {
+    let v6_addr = Ipv6Addr::new(0, 0, 0, 0, 0, 0, 0, 0);
+    let v6_sock = SocketAddrV6::new(v6_addr, 0, 0, 0);
+
+    SocketAddr::V6(v6_sock)
+}
+
+/// Convert an ipv4 mapped ipv6 socket (`[::ffff:a.b.c.d]:port`) back to an ipv4 socket.
+///
+/// Dual stack sockets report ipv4 peers this way, any other address is returned as is.
+pub fn unmap_v4(sock_addr: SocketAddr) -> SocketAddr {
+    match sock_addr {
+        SocketAddr::V6(v6_sock_addr) => match v6_sock_addr.ip().segments() {
+            [0, 0, 0, 0, 0, 0xFFFF, high, low] => {
+                let v4_addr =
+                    Ipv4Addr::new((high >> 8) as u8, high as u8, (low >> 8) as u8, low as u8);
+
+                SocketAddr::V4(SocketAddrV4::new(v4_addr, v6_sock_addr.port()))
+            }
+            _ => sock_addr,
+        },
+        SocketAddr::V4(_) => sock_addr,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    #[test]
+    fn positive_unmap_v4_mapped() {
+        let mapped: SocketAddr = "[::ffff:10.0.0.1]:6881".parse().unwrap();
+        let expected: SocketAddr = "10.0.0.1:6881".parse().unwrap();
+
+        assert_eq!(expected, super::unmap_v4(mapped));
+    }
+
+    #[test]
+    fn positive_unmap_v4_keeps_v6() {
+        let v6_addr: SocketAddr = "[2001:db8::ffff:a00:1]:6881".parse().unwrap();
+        let v4_addr: SocketAddr = "10.0.0.1:6881".parse().unwrap();
+
+        assert_eq!(v6_addr, super::unmap_v4(v6_addr));
+        assert_eq!(v4_addr, super::unmap_v4(v4_addr));
+    }
+}
@@ -0,0 +1,666 @@
+//! Reaching trackers through SOCKS5 (RFC 1928) and HTTP proxies.
+//!
+//! Tcp connections can be tunneled through either kind of proxy, udp packets can only be
+//! relayed by a SOCKS5 proxy, after associating the sending socket with its relay.
+
+use std::error::Error;
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::net::{self, IpAddr, SocketAddr};
+use std::str;
+use std::time::Duration;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{self as tokio_net, TcpStream};
+
+use crate::util::convert;
+
+const SOCKS_VERSION: u8 = 0x05;
+const PASSWORD_VERSION: u8 = 0x01;
+
+const NO_AUTH_METHOD: u8 = 0x00;
+const PASSWORD_METHOD: u8 = 0x02;
+
+const CONNECT_COMMAND: u8 = 0x01;
+const UDP_ASSOCIATE_COMMAND: u8 = 0x03;
+
+const IPV4_ADDR_TYPE: u8 = 0x01;
+const DOMAIN_ADDR_TYPE: u8 = 0x03;
+const IPV6_ADDR_TYPE: u8 = 0x04;
+
+const SUCCEEDED_REPLY: u8 = 0x00;
+
+/// Maximum length of the header prefixed to relayed udp packets.
+pub const MAX_UDP_HEADER_LEN: usize = 3 + 1 + 16 + 2;
+
+const MAX_CONNECT_REPLY_LEN: usize = 8 * 1024;
+const ASSOCIATE_TIMEOUT_SECS: u64 = 30;
+
+/// Protocol spoken by a proxy.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum ProxyKind {
+    /// SOCKS5 proxy, tunneling tcp connections and relaying udp packets.
+    Socks5,
+    /// HTTP proxy, tunneling tcp connections with CONNECT requests.
+    Http,
+}
+
+/// Username and password to authenticate with a proxy.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct ProxyAuth {
+    username: String,
+    password: String,
+}
+
+impl ProxyAuth {
+    /// Create a new ProxyAuth.
+    ///
+    /// Panics if the username or password is longer than 255 bytes, as SOCKS5 can not send them.
+    pub fn new(username: &str, password: &str) -> ProxyAuth {
+        if username.len() > u8::max_value() as usize || password.len() > u8::max_value() as usize {
+            panic!(
+                "bittorrent-protocol_util: Proxy Username And Password Must Be At Most 255 Bytes"
+            );
+        }
+
+        ProxyAuth {
+            username: username.to_owned(),
+            password: password.to_owned(),
+        }
+    }
+
+    /// Username sent to the proxy.
+    pub fn username(&self) -> &str {
+        &self.username
+    }
+
+    /// Password sent to the proxy.
+    pub fn password(&self) -> &str {
+        &self.password
+    }
+}
+
+/// Proxy that connections to trackers are made through.
+///
+/// Hostnames are resolved locally by default, before asking the proxy for a connection to
+/// the resolved address. With remote resolution, hostnames are handed to the proxy as is and
+/// never looked up locally, so that no dns request leaks past the proxy.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct ProxyConfig {
+    kind: ProxyKind,
+    addr: SocketAddr,
+    opt_auth: Option<ProxyAuth>,
+    resolve_hostnames_remotely: bool,
+}
+
+impl ProxyConfig {
+    /// Create a new ProxyConfig for the proxy of the given kind at the given address.
+    pub fn new(kind: ProxyKind, addr: SocketAddr) -> ProxyConfig {
+        ProxyConfig {
+            kind: kind,
+            addr: addr,
+            opt_auth: None,
+            resolve_hostnames_remotely: false,
+        }
+    }
+
+    /// Authenticate with the proxy using the given username and password.
+    pub fn with_auth(mut self, auth: ProxyAuth) -> ProxyConfig {
+        self.opt_auth = Some(auth);
+
+        self
+    }
+
+    /// Whether or not hostnames are resolved by the proxy instead of locally.
+    pub fn with_resolve_hostnames_remotely(mut self, remotely: bool) -> ProxyConfig {
+        self.resolve_hostnames_remotely = remotely;
+
+        self
+    }
+
+    /// Protocol spoken by the proxy.
+    pub fn kind(&self) -> ProxyKind {
+        self.kind
+    }
+
+    /// Address of the proxy.
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Credentials sent to the proxy, if any.
+    pub fn auth(&self) -> Option<&ProxyAuth> {
+        self.opt_auth.as_ref()
+    }
+
+    /// Returns true if hostnames are resolved by the proxy.
+    pub fn resolve_hostnames_remotely(&self) -> bool {
+        self.resolve_hostnames_remotely
+    }
+}
+
+//----------------------------------------------------------------------------//
+
+/// Failure of a proxy, as opposed to a failure of whatever is behind it.
+///
+/// Carried inside the `io::Error`s returned when going through a proxy fails, see
+/// `ProxyError::from_io` for getting it back out.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum ProxyError {
+    /// Connection to the proxy failed or was closed by the proxy.
+    Connection(io::ErrorKind),
+    /// Proxy accepts none of the offered authentication methods.
+    NoAcceptableAuth,
+    /// Proxy rejected our credentials.
+    AuthRejected,
+    /// SOCKS5 proxy refused the request with the given reply code.
+    SocksReply(u8),
+    /// HTTP proxy refused the tunnel with the given status code.
+    HttpStatus(u16),
+    /// Proxy sent a reply that does not follow its protocol.
+    InvalidReply,
+}
+
+impl ProxyError {
+    /// Proxy error carried by the given io error, if any.
+    pub fn from_io(error: &io::Error) -> Option<ProxyError> {
+        error
+            .get_ref()
+            .and_then(|inner| inner.downcast_ref::<ProxyError>())
+            .cloned()
+    }
+
+    fn io_kind(&self) -> io::ErrorKind {
+        match self {
+            &ProxyError::Connection(kind) => kind,
+            &ProxyError::NoAcceptableAuth | &ProxyError::AuthRejected => {
+                io::ErrorKind::PermissionDenied
+            }
+            &ProxyError::SocksReply(_) | &ProxyError::HttpStatus(_) => {
+                io::ErrorKind::ConnectionRefused
+            }
+            &ProxyError::InvalidReply => io::ErrorKind::InvalidData,
+        }
+    }
+}
+
+impl fmt::Display for ProxyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            &ProxyError::Connection(kind) => {
+                write!(f, "Connection To The Proxy Failed: {:?}", kind)
+            }
+            &ProxyError::NoAcceptableAuth => {
+                f.write_str("Proxy Accepts None Of The Authentication Methods")
+            }
+            &ProxyError::AuthRejected => f.write_str("Proxy Rejected The Credentials"),
+            &ProxyError::SocksReply(reply) => write!(f, "Proxy Refused With Reply {}", reply),
+            &ProxyError::HttpStatus(status) => write!(f, "Proxy Refused With Status {}", status),
+            &ProxyError::InvalidReply => f.write_str("Proxy Sent An Invalid Reply"),
+        }
+    }
+}
+
+impl Error for ProxyError {}
+
+impl From<ProxyError> for io::Error {
+    fn from(error: ProxyError) -> io::Error {
+        io::Error::new(error.io_kind(), error)
+    }
+}
+
+/// Io error of the connection to the proxy, unless it already is a proxy error.
+fn connection_error(error: io::Error) -> io::Error {
+    match ProxyError::from_io(&error) {
+        Some(_) => error,
+        None => ProxyError::Connection(error.kind()).into(),
+    }
+}
+
+//----------------------------------------------------------------------------//
+
+/// Destination the proxy is asked to reach.
+enum Target<'a> {
+    Addr(SocketAddr),
+    Host(&'a str, u16),
+}
+
+impl<'a> Target<'a> {
+    fn authority(&self) -> String {
+        match self {
+            &Target::Addr(addr) => addr.to_string(),
+            &Target::Host(host, port) => format!("{}:{}", host, port),
+        }
+    }
+}
+
+/// Target for the given host, looking it up locally unless the proxy resolves hostnames.
+async fn resolve<'a>(config: &ProxyConfig, host: &'a str, port: u16) -> io::Result<Target<'a>> {
+    // IPv6 addresses are bracketed in urls, but not when parsed
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+
+    if let Ok(ip) = host.parse::<IpAddr>() {
+        return Ok(Target::Addr(SocketAddr::new(ip, port)));
+    }
+    if config.resolve_hostnames_remotely() {
+        return Ok(Target::Host(host, port));
+    }
+
+    tokio_net::lookup_host((host, port))
+        .await?
+        .next()
+        .map(Target::Addr)
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                "Hostname Did Not Resolve To Any Address",
+            )
+        })
+}
+
+/// Open a tcp connection to the given host and port through the proxy.
+///
+/// Failures of the proxy are returned as io errors carrying a `ProxyError`.
+pub async fn connect(config: &ProxyConfig, host: &str, port: u16) -> io::Result<TcpStream> {
+    let target = resolve(config, host, port).await?;
+
+    let tunnel = async {
+        let mut stream = TcpStream::connect(config.addr()).await?;
+        stream.set_nodelay(true)?;
+
+        match config.kind() {
+            ProxyKind::Socks5 => {
+                socks_handshake(&mut stream, config.auth(), CONNECT_COMMAND, &target).await?;
+            }
+            ProxyKind::Http => {
+                stream
+                    .write_all(&connect_request(&target, config.auth()))
+                    .await?;
+                connect_reply(&mut stream).await?;
+            }
+        }
+
+        Ok(stream)
+    };
+
+    tunnel.await.map_err(connection_error)
+}
+
+async fn socks_handshake(
+    stream: &mut TcpStream,
+    opt_auth: Option<&ProxyAuth>,
+    command: u8,
+    target: &Target<'_>,
+) -> io::Result<Option<SocketAddr>> {
+    stream.write_all(&method_request(opt_auth)).await?;
+    let mut method = [0u8; 2];
+    stream.read_exact(&mut method).await?;
+
+    if let Some(auth) = check_method(method, opt_auth)? {
+        stream.write_all(&password_request(auth)).await?;
+        let mut status = [0u8; 2];
+        stream.read_exact(&mut status).await?;
+        check_password(status)?;
+    }
+
+    stream.write_all(&command_request(command, target)).await?;
+    let mut head = [0u8; 5];
+    stream.read_exact(&mut head).await?;
+    let mut addr = vec![head[4]; 1 + reply_rest_len(head)?];
+    stream.read_exact(&mut addr[1..]).await?;
+
+    Ok(parse_addr(head[3], &addr))
+}
+
+/// Read the reply to a CONNECT request, up to the end of its head.
+async fn connect_reply(stream: &mut TcpStream) -> io::Result<()> {
+    // Read byte by byte so that nothing sent through the tunnel is consumed
+    let mut head = Vec::new();
+    while !head.ends_with(b"\r\n\r\n") {
+        if head.len() == MAX_CONNECT_REPLY_LEN {
+            return Err(ProxyError::InvalidReply.into());
+        }
+        head.push(stream.read_u8().await?);
+    }
+
+    let status = str::from_utf8(&head)
+        .ok()
+        .filter(|head| head.starts_with("HTTP/1."))
+        .and_then(|head| head.split(' ').nth(1))
+        .and_then(|status| status.parse::<u16>().ok())
+        .ok_or(ProxyError::InvalidReply)?;
+
+    if (200..300).contains(&status) {
+        Ok(())
+    } else {
+        Err(ProxyError::HttpStatus(status).into())
+    }
+}
+
+fn connect_request(target: &Target, opt_auth: Option<&ProxyAuth>) -> Vec<u8> {
+    let authority = target.authority();
+    let mut request = format!(
+        "CONNECT {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: bittorrent-protocol\r\n",
+        authority, authority
+    );
+
+    if let Some(auth) = opt_auth {
+        let credentials = format!("{}:{}", auth.username(), auth.password());
+        request.push_str(&format!(
+            "Proxy-Authorization: Basic {}\r\n",
+            convert::bytes_to_base64(credentials.as_bytes())
+        ));
+    }
+    request.push_str("\r\n");
+
+    request.into_bytes()
+}
+
+//----------------------------------------------------------------------------//
+
+/// Association of a udp socket with the relay of a SOCKS5 proxy.
+///
+/// The proxy relays packets for as long as the control connection, owned by the
+/// association, stays open.
+#[derive(Debug)]
+pub struct UdpAssociation {
+    _control: net::TcpStream,
+    relay: SocketAddr,
+}
+
+impl UdpAssociation {
+    /// Ask the proxy to relay packets sent from the given address, blocking until it answers.
+    ///
+    /// The address may be left unspecified if it is not known yet.
+    pub fn new(config: &ProxyConfig, source: SocketAddr) -> io::Result<UdpAssociation> {
+        if config.kind() != ProxyKind::Socks5 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Udp Packets Can Only Be Relayed By A SOCKS5 Proxy",
+            ));
+        }
+
+        let associate = || -> io::Result<UdpAssociation> {
+            let timeout = Duration::from_secs(ASSOCIATE_TIMEOUT_SECS);
+            let mut control = net::TcpStream::connect_timeout(&config.addr(), timeout)?;
+            control.set_read_timeout(Some(timeout))?;
+            control.set_write_timeout(Some(timeout))?;
+
+            control.write_all(&method_request(config.auth()))?;
+            let mut method = [0u8; 2];
+            control.read_exact(&mut method)?;
+
+            if let Some(auth) = check_method(method, config.auth())? {
+                control.write_all(&password_request(auth))?;
+                let mut status = [0u8; 2];
+                control.read_exact(&mut status)?;
+                check_password(status)?;
+            }
+
+            control.write_all(&command_request(
+                UDP_ASSOCIATE_COMMAND,
+                &Target::Addr(source),
+            ))?;
+            let mut head = [0u8; 5];
+            control.read_exact(&mut head)?;
+            let mut addr = vec![head[4]; 1 + reply_rest_len(head)?];
+            control.read_exact(&mut addr[1..])?;
+
+            let mut relay = parse_addr(head[3], &addr).ok_or(ProxyError::InvalidReply)?;
+            // An unspecified relay address is the address of the proxy itself
+            if relay.ip().is_unspecified() {
+                relay.set_ip(config.addr().ip());
+            }
+
+            Ok(UdpAssociation {
+                _control: control,
+                relay: relay,
+            })
+        };
+
+        associate().map_err(connection_error)
+    }
+
+    /// Address that packets have to be sent to, to be relayed by the proxy.
+    pub fn relay(&self) -> SocketAddr {
+        self.relay
+    }
+}
+
+/// Write the header for a packet to be relayed to the given address.
+pub fn write_udp_header<W>(target: SocketAddr, mut writer: W) -> io::Result<()>
+where
+    W: Write,
+{
+    // Reserved bytes and fragment number, fragments are never sent
+    writer.write_all(&[0, 0, 0])?;
+    writer.write_all(&target_bytes(&Target::Addr(target)))
+}
+
+/// Parse the header of a relayed packet, returning the address it came from and its payload.
+///
+/// Fragmented packets and packets from hostnames are not supported and return None.
+pub fn parse_udp_packet(bytes: &[u8]) -> Option<(SocketAddr, &[u8])> {
+    if bytes.len() < 5 || bytes[2] != 0 {
+        return None;
+    }
+
+    let payload_start = 5 + addr_rest_len(bytes[3], bytes[4])?;
+    if bytes.len() < payload_start {
+        return None;
+    }
+
+    parse_addr(bytes[3], &bytes[4..payload_start]).map(|addr| (addr, &bytes[payload_start..]))
+}
+
+//----------------------------------------------------------------------------//
+
+fn method_request(opt_auth: Option<&ProxyAuth>) -> Vec<u8> {
+    match opt_auth {
+        Some(_) => vec![SOCKS_VERSION, 2, NO_AUTH_METHOD, PASSWORD_METHOD],
+        None => vec![SOCKS_VERSION, 1, NO_AUTH_METHOD],
+    }
+}
+
+/// Check the method chosen by the proxy, returning the credentials to send if any.
+fn check_method(
+    reply: [u8; 2],
+    opt_auth: Option<&ProxyAuth>,
+) -> Result<Option<&ProxyAuth>, ProxyError> {
+    match (reply, opt_auth) {
+        ([SOCKS_VERSION, NO_AUTH_METHOD], _) => Ok(None),
+        ([SOCKS_VERSION, PASSWORD_METHOD], Some(auth)) => Ok(Some(auth)),
+        ([SOCKS_VERSION, _], _) => Err(ProxyError::NoAcceptableAuth),
+        _ => Err(ProxyError::InvalidReply),
+    }
+}
+
+fn password_request(auth: &ProxyAuth) -> Vec<u8> {
+    let mut request = vec![PASSWORD_VERSION, auth.username().len() as u8];
+    request.extend_from_slice(auth.username().as_bytes());
+    request.push(auth.password().len() as u8);
+    request.extend_from_slice(auth.password().as_bytes());
+
+    request
+}
+
+fn check_password(reply: [u8; 2]) -> Result<(), ProxyError> {
+    match reply {
+        [PASSWORD_VERSION, SUCCEEDED_REPLY] => Ok(()),
+        [PASSWORD_VERSION, _] => Err(ProxyError::AuthRejected),
+        _ => Err(ProxyError::InvalidReply),
+    }
+}
+
+fn command_request(command: u8, target: &Target) -> Vec<u8> {
+    let mut request = vec![SOCKS_VERSION, command, 0];
+    request.extend_from_slice(&target_bytes(target));
+
+    request
+}
+
+fn target_bytes(target: &Target) -> Vec<u8> {
+    let mut bytes = Vec::new();
+
+    let port = match target {
+        &Target::Addr(SocketAddr::V4(addr)) => {
+            bytes.push(IPV4_ADDR_TYPE);
+            bytes.extend_from_slice(&addr.ip().octets());
+            addr.port()
+        }
+        &Target::Addr(SocketAddr::V6(addr)) => {
+            bytes.push(IPV6_ADDR_TYPE);
+            bytes.extend_from_slice(&addr.ip().octets());
+            addr.port()
+        }
+        &Target::Host(host, port) => {
+            // Hostnames in urls are far shorter than the 255 bytes allowed by SOCKS5
+            bytes.push(DOMAIN_ADDR_TYPE);
+            bytes.push(host.len() as u8);
+            bytes.extend_from_slice(host.as_bytes());
+            port
+        }
+    };
+    bytes.extend_from_slice(&convert::port_to_bytes_be(port));
+
+    bytes
+}
+
+/// Check the first five bytes of a reply, returning the number of bytes left in it.
+fn reply_rest_len(head: [u8; 5]) -> Result<usize, ProxyError> {
+    if head[0] != SOCKS_VERSION {
+        return Err(ProxyError::InvalidReply);
+    }
+    if head[1] != SUCCEEDED_REPLY {
+        return Err(ProxyError::SocksReply(head[1]));
+    }
+
+    addr_rest_len(head[3], head[4]).ok_or(ProxyError::InvalidReply)
+}
+
+/// Number of bytes after the first of an address of the given type, including the port.
+fn addr_rest_len(addr_type: u8, first: u8) -> Option<usize> {
+    match addr_type {
+        IPV4_ADDR_TYPE => Some(4 - 1 + 2),
+        IPV6_ADDR_TYPE => Some(16 - 1 + 2),
+        DOMAIN_ADDR_TYPE => Some(first as usize + 2),
+        _ => None,
+    }
+}
+
+/// Address of the given type from its bytes followed by the port, None for hostnames.
+fn parse_addr(addr_type: u8, bytes: &[u8]) -> Option<SocketAddr> {
+    let (ip_bytes, port_bytes) = bytes.split_at(bytes.len() - 2);
+    let port = convert::bytes_be_to_port([port_bytes[0], port_bytes[1]]);
+
+    let ip = match addr_type {
+        IPV4_ADDR_TYPE => IpAddr::from(<[u8; 4]>::try_from(ip_bytes).ok()?),
+        IPV6_ADDR_TYPE => IpAddr::from(<[u8; 16]>::try_from(ip_bytes).ok()?),
+        _ => return None,
+    };
+
+    Some(SocketAddr::new(ip, port))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use std::net::SocketAddr;
+
+    use super::{ProxyAuth, ProxyError, Target};
+
+    #[test]
+    fn positive_command_request_hostname() {
+        let request = super::command_request(0x01, &Target::Host("tracker.example", 6969));
+
+        let mut expected = vec![0x05, 0x01, 0x00, 0x03, 15];
+        expected.extend_from_slice(b"tracker.example");
+        expected.extend_from_slice(&[0x1B, 0x39]);
+        assert_eq!(request, expected);
+    }
+
+    #[test]
+    fn positive_command_request_ipv6() {
+        let addr: SocketAddr = "[2001:db8::1]:80".parse().unwrap();
+        let request = super::command_request(0x03, &Target::Addr(addr));
+
+        assert_eq!(&request[..4], &[0x05, 0x03, 0x00, 0x04]);
+        assert_eq!(request.len(), 4 + 16 + 2);
+        assert_eq!(&request[4..6], &[0x20, 0x01]);
+        assert_eq!(&request[20..], &[0x00, 0x50]);
+    }
+
+    #[test]
+    fn positive_udp_header_round_trip() {
+        for addr in &["10.0.0.1:6969", "[2001:db8::1]:6969"] {
+            let addr: SocketAddr = addr.parse().unwrap();
+
+            let mut packet = Vec::new();
+            super::write_udp_header(addr, &mut packet).unwrap();
+            packet.extend_from_slice(b"payload");
+
+            assert_eq!(
+                super::parse_udp_packet(&packet),
+                Some((addr, &b"payload"[..]))
+            );
+        }
+    }
+
+    #[test]
+    fn negative_parse_udp_packet() {
+        // Fragment, hostname and truncated address
+        assert_eq!(
+            super::parse_udp_packet(&[0, 0, 1, 1, 10, 0, 0, 1, 0, 80]),
+            None
+        );
+        assert_eq!(super::parse_udp_packet(&[0, 0, 0, 3, 1, b'a', 0, 80]), None);
+        assert_eq!(super::parse_udp_packet(&[0, 0, 0, 1, 10, 0]), None);
+    }
+
+    #[test]
+    fn positive_password_request() {
+        let request = super::password_request(&ProxyAuth::new("user", "pass"));
+
+        assert_eq!(request, b"\x01\x04user\x04pass".to_vec());
+    }
+
+    #[test]
+    fn negative_check_method() {
+        let auth = ProxyAuth::new("user", "pass");
+
+        assert_eq!(
+            super::check_method([0x05, 0x02], None),
+            Err(ProxyError::NoAcceptableAuth)
+        );
+        assert_eq!(
+            super::check_method([0x05, 0xFF], Some(&auth)),
+            Err(ProxyError::NoAcceptableAuth)
+        );
+        assert_eq!(
+            super::check_method([0x04, 0x00], None),
+            Err(ProxyError::InvalidReply)
+        );
+        assert_eq!(
+            super::check_method([0x05, 0x02], Some(&auth)),
+            Ok(Some(&auth))
+        );
+    }
+
+    #[test]
+    fn positive_proxy_error_from_io() {
+        let error: io::Error = ProxyError::SocksReply(5).into();
+
+        assert_eq!(error.kind(), io::ErrorKind::ConnectionRefused);
+        assert_eq!(ProxyError::from_io(&error), Some(ProxyError::SocksReply(5)));
+        assert_eq!(
+            ProxyError::from_io(&io::Error::new(io::ErrorKind::Other, "Other")),
+            None
+        );
+    }
+
+    #[test]
+    #[should_panic]
+    fn negative_auth_too_long() {
+        ProxyAuth::new(&"u".repeat(256), "pass");
+    }
+}
@@ -3,6 +3,8 @@ mod builder;
 pub use builder::ShaHashBuilder;
 
 use crate::util::error::{LengthError, LengthErrorKind, LengthResult};
+use rand::{self, Rng};
+use std::fmt;
 use std::ops::BitXor;
 
 /// Length of a SHA-1 hash.
@@ -40,6 +42,26 @@ impl ShaHash {
         }
     }
 
+    /// Create a random peer id starting with the given client prefix.
+    ///
+    /// The prefix is typically Azureus style, such as `-XX1234-`, and the rest
+    /// is filled with random alphanumeric characters.
+    ///
+    /// See http://www.bittorrent.org/beps/bep_0020.html.
+    pub fn random_with_prefix(prefix: &[u8; 8]) -> ShaHash {
+        let mut hash = [0u8; SHA_HASH_LEN];
+        hash[..prefix.len()].copy_from_slice(prefix);
+
+        for (dst, src) in hash[prefix.len()..]
+            .iter_mut()
+            .zip(rand::thread_rng().gen_ascii_chars())
+        {
+            *dst = src as u8;
+        }
+
+        ShaHash { hash: hash }
+    }
+
     pub fn bits<'a>(&'a self) -> Bits<'a> {
         Bits::new(&self.hash)
     }
@@ -49,6 +71,15 @@ impl ShaHash {
     }
 }
 
+/// Lowercase hex, as hashes are shown in magnet links and by most clients.
+impl fmt::Display for ShaHash {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.hash
+            .iter()
+            .try_for_each(|byte| write!(f, "{:02x}", byte))
+    }
+}
+
 impl AsRef<[u8]> for ShaHash {
     fn as_ref(&self) -> &[u8] {
         &self.hash
@@ -229,6 +260,28 @@ mod tests {
         assert!(leading_zeroes == 0);
     }
 
+    #[test]
+    fn positive_random_with_prefix() {
+        let first = ShaHash::random_with_prefix(b"-XX1234-");
+        let second = ShaHash::random_with_prefix(b"-XX1234-");
+
+        assert_eq!(b"-XX1234-", &first.as_ref()[..8]);
+        assert!(first.as_ref()[8..].iter().all(|byte| byte.is_ascii_alphanumeric()));
+        assert_ne!(first, second);
+    }
+
+    #[test]
+    fn positive_display_lowercase_hex() {
+        let mut bytes = [0u8; super::SHA_HASH_LEN];
+        bytes[0] = 0xAB;
+        bytes[super::SHA_HASH_LEN - 1] = 0x01;
+
+        assert_eq!(
+            "ab00000000000000000000000000000000000001",
+            ShaHash::from(bytes).to_string()
+        );
+    }
+
     #[test]
     #[should_panic]
     fn negative_from_hash_too_long() {
@@ -19,11 +19,6 @@ impl<'a> BitIterator<'a> {
             end_index: obj.len() * U8BITS,
         }
     }
-
-    /// Returns the number of ones in the binary representation of the underlying object.
-    pub fn count_ones(&self) -> u32 {
-        self.object.iter().fold(0, |acc, bv| acc + bv.count_ones())
-    }
 }
 
 impl<'a> Iterator for BitIterator<'a> {
@@ -0,0 +1,525 @@
+use std::cmp::{max, min};
+use std::collections::VecDeque;
+use std::time::Duration;
+
+use super::time::{Delay, Timestamp};
+use super::util::ewma;
+
+/// Default maximum segment size, windows grow and shrink in multiples of the segment size.
+pub const MSS: u32 = 1400;
+/// Smallest congestion window, in segments.
+pub const MIN_CWND: u32 = 2;
+const INIT_CWND: u32 = 2;
+const GAIN: f64 = 1.0;
+const ALLOWED_INCREASE: u32 = 1;
+/// Default queuing delay we aim for, in microseconds (100 milliseconds).
+pub const DEFAULT_TARGET_DELAY: u32 = 100_000;
+const BASE_HISTORY: usize = 10; // base delays history size
+
+// Maximum age of base delay sample (60 seconds)
+const MAX_BASE_DELAY_AGE: Delay = Delay(60_000_000);
+
+#[derive(Copy, Clone, Debug)]
+struct DelaySample {
+    received_at: Timestamp,
+    // Wrapping one-way delay, including the offset between our clocks
+    delay: u32,
+}
+
+/// LEDBAT congestion control, as described in BEP 29 and RFC 6817.
+///
+/// Delays are one-way delays reported back to us by the remote peer in the `timestamp_difference`
+/// of its packets. They include the difference between our clocks, which cancels out when
+/// comparing them to the base delay: the lowest delay seen in the last `BASE_HISTORY` minutes.
+/// Whatever delay is left on top of the base delay is queuing delay, which we keep at the target
+/// by growing the window while we are below it, and shrinking it when we are above it.
+#[derive(Clone, Debug)]
+pub struct Ledbat {
+    target: u32,
+    cwnd: u32,
+    /// Size of the packets we send, in bytes
+    mss: u32,
+    /// Lowest delay of each of the last minutes
+    base_delays: VecDeque<u32>,
+    /// Start of the current minute for sampling purposes
+    last_rollover: Timestamp,
+    /// Delays measured within the last round trip
+    current_delays: VecDeque<DelaySample>,
+}
+
+impl Ledbat {
+    /// Create a new `Ledbat` with the default target delay.
+    pub fn new() -> Ledbat {
+        Ledbat {
+            target: DEFAULT_TARGET_DELAY,
+            cwnd: INIT_CWND * MSS,
+            mss: MSS,
+            base_delays: VecDeque::with_capacity(BASE_HISTORY),
+            last_rollover: Timestamp::default(),
+            current_delays: VecDeque::new(),
+        }
+    }
+
+    /// Queuing delay we aim for.
+    pub fn target(&self) -> Duration {
+        Duration::from_micros(self.target as u64)
+    }
+
+    /// Sets the queuing delay we aim for, of at least a millisecond.
+    pub fn set_target(&mut self, target: Duration) {
+        let micros = min(target.as_micros(), u32::MAX as u128) as u32;
+
+        self.target = max(micros, 1000);
+    }
+
+    /// Sets the size of the segments we send.
+    ///
+    /// The window grows by at most a segment per round trip, and never shrinks below `MIN_CWND`
+    /// segments.
+    pub fn set_mss(&mut self, mss: u32) {
+        self.mss = max(mss, 1);
+        self.cwnd = max(self.cwnd, MIN_CWND * self.mss);
+    }
+
+    /// Congestion window in bytes.
+    pub fn cwnd(&self) -> u32 {
+        self.cwnd
+    }
+
+    /// Number of bytes we may have in flight, given the window advertised by the remote peer.
+    pub fn max_window(&self, remote_wnd_size: u32) -> u32 {
+        max(min(self.cwnd, remote_wnd_size), MIN_CWND * self.mss)
+    }
+
+    /// Lowest one-way delay in the base delay history, in microseconds.
+    pub fn base_delay(&self) -> u32 {
+        self.base_delays
+            .iter()
+            .cloned()
+            .fold(None, |lowest: Option<u32>, delay| match lowest {
+                Some(lowest) if !wrapping_less(delay, lowest) => Some(lowest),
+                _ => Some(delay),
+            })
+            .unwrap_or(0)
+    }
+
+    /// Filtered one-way delay of the last round trip, in microseconds.
+    pub fn current_delay(&self) -> u32 {
+        self.base_delay().wrapping_add(self.queuing_delay())
+    }
+
+    /// Delay on top of the base delay of the last round trip, in microseconds.
+    ///
+    /// The exponential weighted moving average, with smoothing factor 0.333, of the delays
+    /// in the last round trip above the base delay.
+    pub fn queuing_delay(&self) -> u32 {
+        let base_delay = self.base_delay();
+        let above_base: Vec<u32> = self
+            .current_delays
+            .iter()
+            .map(|sample| above(sample.delay, base_delay))
+            .collect();
+
+        ewma(above_base.iter(), 0.333) as u32
+    }
+
+    /// Adjust the window for an acknowledgement of `bytes_acked` bytes.
+    ///
+    /// The one-way delay is the timestamp difference of the acknowledgement, a delay of zero
+    /// means the remote peer did not measure it and leaves the window as it is.
+    pub fn on_ack(
+        &mut self,
+        one_way_delay: u32,
+        bytes_acked: u32,
+        flight_size: u32,
+        remote_wnd_size: u32,
+        rtt: Delay,
+        now: Timestamp,
+    ) {
+        if one_way_delay == 0 {
+            return;
+        }
+        self.update_base_delay(one_way_delay, now);
+        self.update_current_delay(one_way_delay, now, rtt);
+
+        // Normalized distance from the target, between -1.0 and 1.0
+        let target = self.target as f64;
+        let off_target = ((target - self.queuing_delay() as f64) / target)
+            .max(-1.0)
+            .min(1.0);
+        debug!("off_target: {}", off_target);
+
+        let cwnd_increase =
+            GAIN * off_target * bytes_acked as f64 * self.mss as f64 / self.cwnd as f64;
+        debug!("cwnd_increase: {}", cwnd_increase);
+
+        let cwnd = (self.cwnd as f64 + cwnd_increase).round().max(0.0) as u32;
+        // Only grow the window while we use it, and never past what the remote peer accepts
+        let max_allowed_cwnd = min(
+            flight_size + ALLOWED_INCREASE * self.mss,
+            max(remote_wnd_size, MIN_CWND * self.mss),
+        );
+        self.cwnd = max(min(cwnd, max_allowed_cwnd), MIN_CWND * self.mss);
+
+        debug!("cwnd: {}", self.cwnd);
+        debug!("max_allowed_cwnd: {}", max_allowed_cwnd);
+    }
+
+    /// Decay the window after a packet was lost.
+    pub fn on_loss(&mut self) {
+        self.cwnd = max(self.cwnd / 2, MIN_CWND * self.mss);
+        debug!("packet loss, cwnd: {}", self.cwnd);
+    }
+
+    /// Decay the window after the remote peer did not acknowledge anything within the timeout.
+    pub fn on_timeout(&mut self) {
+        self.cwnd = max(self.cwnd / 2, MIN_CWND * self.mss);
+        debug!("timeout, cwnd: {}", self.cwnd);
+    }
+
+    // Insert a new sample in the base delay list.
+    //
+    // The base delay list contains at most `BASE_HISTORY` samples, each sample is the minimum
+    // measured over a period of a minute (MAX_BASE_DELAY_AGE).
+    fn update_base_delay(&mut self, base_delay: u32, now: Timestamp) {
+        if self.base_delays.is_empty() || now - self.last_rollover > MAX_BASE_DELAY_AGE {
+            // Update last rollover
+            self.last_rollover = now;
+
+            // Drop the oldest sample, if need be
+            if self.base_delays.len() == BASE_HISTORY {
+                self.base_delays.pop_front();
+            }
+
+            // Insert new sample
+            self.base_delays.push_back(base_delay);
+        } else {
+            // Replace sample for the current minute if the delay is lower
+            let last_idx = self.base_delays.len() - 1;
+            if wrapping_less(base_delay, self.base_delays[last_idx]) {
+                self.base_delays[last_idx] = base_delay;
+            }
+        }
+    }
+
+    /// Inserts a new sample in the current delay list after removing samples older than one RTT, as
+    /// specified in RFC6817.
+    fn update_current_delay(&mut self, delay: u32, now: Timestamp, rtt: Delay) {
+        while self
+            .current_delays
+            .front()
+            .map_or(false, |sample| now - sample.received_at > rtt)
+        {
+            self.current_delays.pop_front();
+        }
+
+        self.current_delays.push_back(DelaySample {
+            received_at: now,
+            delay: delay,
+        });
+    }
+}
+
+/// Whether the wrapping delay `a` is lower than `b`, delays are never more than half the range apart.
+fn wrapping_less(a: u32, b: u32) -> bool {
+    b.wrapping_sub(a) != 0 && b.wrapping_sub(a) < u32::MAX / 2
+}
+
+/// How far the wrapping delay lies above the base delay, or zero if it lies below it.
+fn above(delay: u32, base_delay: u32) -> u32 {
+    if wrapping_less(delay, base_delay) {
+        0
+    } else {
+        delay.wrapping_sub(base_delay)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::cmp::min;
+    use std::collections::VecDeque;
+    use std::time::Duration;
+
+    use super::{Ledbat, MIN_CWND, MSS};
+    use crate::utp::time::{Delay, Timestamp};
+
+    const MILLIS: u32 = 1000;
+
+    #[test]
+    fn test_base_delay_calculation() {
+        let minute_in_microseconds = 60 * 10u32.pow(6);
+        let samples = vec![
+            (0, 10),
+            (1, 8),
+            (2, 12),
+            (3, 7),
+            (minute_in_microseconds + 1, 11),
+            (minute_in_microseconds + 2, 19),
+            (minute_in_microseconds + 3, 9),
+        ];
+        let mut ledbat = Ledbat::new();
+
+        for (timestamp, delay) in samples {
+            ledbat.update_base_delay(delay, (timestamp + delay).into());
+        }
+
+        let expected = vec![7u32, 9u32];
+        let actual = ledbat.base_delays.iter().cloned().collect::<Vec<_>>();
+        assert_eq!(expected, actual);
+        assert_eq!(ledbat.base_delay(), 7);
+    }
+
+    #[test]
+    fn test_base_delay_across_clock_wrap() {
+        let mut ledbat = Ledbat::new();
+        let now = Timestamp(1);
+
+        // Remote clock is behind ours, delays wrap around
+        ledbat.update_base_delay(u32::MAX - 5 * MILLIS, now);
+        ledbat.update_base_delay(10 * MILLIS, now);
+        ledbat.update_base_delay(u32::MAX - 10 * MILLIS, now);
+
+        assert_eq!(ledbat.base_delay(), u32::MAX - 10 * MILLIS);
+    }
+
+    #[test]
+    fn test_window_grows_below_target() {
+        let mut ledbat = Ledbat::new();
+        let mut now = 0;
+
+        for _ in 0..100 {
+            now += 10 * MILLIS;
+            let flight_size = ledbat.cwnd();
+            ledbat.on_ack(
+                20 * MILLIS,
+                MSS,
+                flight_size,
+                u32::MAX,
+                Delay(40_000),
+                now.into(),
+            );
+        }
+
+        assert!(ledbat.cwnd() > 10 * MSS);
+        assert_eq!(ledbat.queuing_delay(), 0);
+    }
+
+    #[test]
+    fn test_window_shrinks_above_target() {
+        let mut ledbat = Ledbat::new();
+        ledbat.cwnd = 50 * MSS;
+        ledbat.on_ack(
+            20 * MILLIS,
+            MSS,
+            50 * MSS,
+            u32::MAX,
+            Delay(40_000),
+            Timestamp(1),
+        );
+
+        let mut now = 1;
+        for _ in 0..20 {
+            now += 10 * MILLIS;
+            ledbat.on_ack(
+                320 * MILLIS,
+                MSS,
+                50 * MSS,
+                u32::MAX,
+                Delay(40_000),
+                now.into(),
+            );
+        }
+
+        assert_eq!(ledbat.queuing_delay(), 300 * MILLIS);
+        // Off target is clamped, each ack takes at most one segment per window off
+        assert!(ledbat.cwnd() < 50 * MSS && ledbat.cwnd() >= 29 * MSS);
+    }
+
+    #[test]
+    fn test_window_clamped_to_remote_window() {
+        let mut ledbat = Ledbat::new();
+        let mut now = 0;
+
+        for _ in 0..100 {
+            now += 10 * MILLIS;
+            let flight_size = ledbat.cwnd();
+            ledbat.on_ack(
+                20 * MILLIS,
+                MSS,
+                flight_size,
+                5 * MSS,
+                Delay(40_000),
+                now.into(),
+            );
+        }
+
+        assert_eq!(ledbat.cwnd(), 5 * MSS);
+        assert_eq!(ledbat.max_window(3 * MSS), 3 * MSS);
+        assert_eq!(ledbat.max_window(0), MIN_CWND * MSS);
+    }
+
+    #[test]
+    fn test_window_decays_on_timeout() {
+        let mut ledbat = Ledbat::new();
+        ledbat.cwnd = 16 * MSS;
+
+        ledbat.on_timeout();
+        assert_eq!(ledbat.cwnd(), 8 * MSS);
+
+        for _ in 0..10 {
+            ledbat.on_timeout();
+        }
+        assert_eq!(ledbat.cwnd(), MIN_CWND * MSS);
+    }
+
+    #[test]
+    fn test_zero_delay_ignored() {
+        let mut ledbat = Ledbat::new();
+        ledbat.on_ack(0, MSS, MSS, u32::MAX, Delay(40_000), Timestamp(1));
+
+        assert!(ledbat.base_delays.is_empty());
+        assert_eq!(ledbat.cwnd(), 2 * MSS);
+    }
+
+    #[test]
+    fn test_configurable_target() {
+        let mut ledbat = Ledbat::new();
+        assert_eq!(ledbat.target(), Duration::from_millis(100));
+
+        ledbat.set_target(Duration::from_millis(25));
+        assert_eq!(ledbat.target(), Duration::from_millis(25));
+
+        ledbat.set_target(Duration::from_micros(1));
+        assert_eq!(ledbat.target(), Duration::from_millis(1));
+    }
+
+    #[test]
+    fn test_window_follows_segment_size() {
+        let mut ledbat = Ledbat::new();
+        ledbat.set_mss(548);
+        assert_eq!(ledbat.mss, 548);
+        assert_eq!(ledbat.cwnd(), 2 * MSS);
+
+        // Grows by at most a segment past the bytes in flight
+        ledbat.on_ack(20 * MILLIS, 548, 548, u32::MAX, Delay(40_000), Timestamp(1));
+        assert_eq!(ledbat.cwnd(), 2 * 548);
+
+        for _ in 0..10 {
+            ledbat.on_timeout();
+        }
+        assert_eq!(ledbat.cwnd(), MIN_CWND * 548);
+
+        // Larger segments never leave the window below the minimum
+        ledbat.set_mss(1400);
+        assert_eq!(ledbat.max_window(0), MIN_CWND * 1400);
+        assert_eq!(ledbat.cwnd(), MIN_CWND * 1400);
+    }
+
+    /// Packet on its way from the sender to the receiver.
+    struct InFlight {
+        sent_at: u32,
+        // Bytes left to put on the wire
+        left: u32,
+    }
+
+    /// In-memory link with a fixed rate, propagation delay and an injected queuing delay.
+    ///
+    /// Packets queue up behind each other when they are sent faster than the rate, acknowledgements
+    /// carry the one-way delay of the packet they acknowledge, as seen by a remote clock whose offset
+    /// wraps the delay around.
+    struct Pipe {
+        bytes_per_milli: u32,
+        propagation: u32,
+        injected: u32,
+        clock_offset: u32,
+        queue: VecDeque<InFlight>,
+        // Acknowledgements on their way back, with the time they arrive and their delay sample
+        acks: VecDeque<(u32, u32)>,
+        flight_size: u32,
+    }
+
+    impl Pipe {
+        fn new() -> Pipe {
+            Pipe {
+                bytes_per_milli: 100,
+                propagation: 10 * MILLIS,
+                injected: 0,
+                clock_offset: u32::MAX - 5 * MILLIS,
+                queue: VecDeque::new(),
+                acks: VecDeque::new(),
+                flight_size: 0,
+            }
+        }
+
+        /// Run the link for the given number of milliseconds, with the sender keeping its window full.
+        fn run(&mut self, ledbat: &mut Ledbat, start: u32, millis: u32) -> u32 {
+            let mut now = start;
+
+            for _ in 0..millis {
+                now += MILLIS;
+
+                while self.flight_size + MSS <= ledbat.max_window(u32::MAX) {
+                    self.flight_size += MSS;
+                    self.queue.push_back(InFlight {
+                        sent_at: now,
+                        left: MSS,
+                    });
+                }
+
+                let mut budget = self.bytes_per_milli;
+                while budget > 0 && !self.queue.is_empty() {
+                    let sent = min(budget, self.queue[0].left);
+                    self.queue[0].left -= sent;
+                    budget -= sent;
+
+                    if self.queue[0].left == 0 {
+                        let packet = self.queue.pop_front().unwrap();
+                        let arrival = now + self.propagation + self.injected;
+                        let delay = (arrival - packet.sent_at).wrapping_add(self.clock_offset);
+
+                        self.acks.push_back((arrival + self.propagation, delay));
+                    }
+                }
+
+                while self
+                    .acks
+                    .front()
+                    .map_or(false, |&(arrival, _)| arrival <= now)
+                {
+                    let (_, delay) = self.acks.pop_front().unwrap();
+                    let rtt = Delay(2 * self.propagation as i64 + self.injected as i64);
+
+                    ledbat.on_ack(delay, MSS, self.flight_size, u32::MAX, rtt, now.into());
+                    self.flight_size -= MSS;
+                }
+            }
+
+            now
+        }
+    }
+
+    #[test]
+    fn test_simulated_window_shrinks_as_delay_grows() {
+        let mut ledbat = Ledbat::new();
+        let mut pipe = Pipe::new();
+
+        // Settle on an empty link, the window fills the link up to the target delay
+        let mut now = pipe.run(&mut ledbat, 0, 20_000);
+        let settled = ledbat.cwnd();
+        assert!(settled > 10 * MSS);
+        let queuing_delay = ledbat.queuing_delay();
+        assert!(queuing_delay > 50 * MILLIS && queuing_delay < 150 * MILLIS);
+
+        // Other traffic queues up on the link, there is less room for us below the target
+        pipe.injected = 50 * MILLIS;
+        now = pipe.run(&mut ledbat, now, 20_000);
+        let crowded = ledbat.cwnd();
+        assert!(crowded < settled);
+
+        // Queuing delay past the target, we get out of the way
+        pipe.injected = 200 * MILLIS;
+        pipe.run(&mut ledbat, now, 20_000);
+        assert!(ledbat.cwnd() < crowded);
+        assert_eq!(ledbat.cwnd(), MIN_CWND * MSS);
+    }
+}
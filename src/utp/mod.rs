@@ -57,10 +57,14 @@
 // Public API
 pub use socket::UtpSocket;
 pub use socket::UtpListener;
+pub use socket::UtpStats;
 pub use stream::UtpStream;
 
 mod bit_iterator;
 mod error;
+mod ledbat;
+mod mtu;
+mod mux;
 mod packet;
 mod socket;
 mod stream;
@@ -0,0 +1,282 @@
+use std::cmp::{max, min};
+use std::time::{Duration, Instant};
+
+/// Smallest packet size, which every IPv4 path carries: the 576 bytes of the minimum reassembly
+/// buffer, minus the IPv4 and UDP headers.
+pub const MIN_PACKET_SIZE: u32 = 548;
+/// Largest packet size probed for by default, filling an Ethernet frame.
+pub const DEFAULT_MAX_PACKET_SIZE: u32 = 1472;
+/// Largest packet size we ever send, the remote peer may not receive larger packets.
+pub const MAX_PACKET_SIZE: u32 = 1500;
+/// Sizes closer than this to the largest size known to make it through are not probed for.
+const PROBE_GRANULARITY: u32 = 16;
+/// Number of times a probe of a given size is lost before we stop probing for that size.
+const PROBE_LOSS_LIMIT: u32 = 2;
+/// Time after which a finished search starts over, the path may have changed.
+const RESEARCH_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+#[derive(Copy, Clone, Debug)]
+struct Probe {
+    seq_nr: u16,
+    size: u32,
+}
+
+/// Path MTU discovery, picking the size of the packets we send.
+///
+/// Packets start out at `MIN_PACKET_SIZE`, while every now and then a single packet is padded to a
+/// larger size to probe whether the path carries it. The sizes to probe are found by binary search
+/// between the largest size known to make it through (the floor) and the smallest size known not
+/// to (the ceiling). A probe that is acknowledged raises the floor, a size whose probes are lost
+/// `PROBE_LOSS_LIMIT` times over lowers the ceiling, other packets being lost have no say in it.
+///
+/// Sizes are the sizes of uTP packets, including the uTP header but not the UDP and IP headers.
+#[derive(Clone, Debug)]
+pub struct MtuDiscovery {
+    floor: u32,
+    ceiling: u32,
+    max: u32,
+    probe: Option<Probe>,
+    probe_losses: u32,
+    pinned: bool,
+    /// When the last search finished
+    searched_at: Option<Instant>,
+}
+
+impl MtuDiscovery {
+    /// Create a new `MtuDiscovery` probing for packets of up to `DEFAULT_MAX_PACKET_SIZE` bytes.
+    pub fn new() -> MtuDiscovery {
+        MtuDiscovery {
+            floor: MIN_PACKET_SIZE,
+            ceiling: DEFAULT_MAX_PACKET_SIZE,
+            max: DEFAULT_MAX_PACKET_SIZE,
+            probe: None,
+            probe_losses: 0,
+            pinned: false,
+            searched_at: None,
+        }
+    }
+
+    /// Size of the packets to send, the largest size known to make it through.
+    pub fn packet_size(&self) -> u32 {
+        self.floor
+    }
+
+    /// Sets the largest size to probe for, between `MIN_PACKET_SIZE` and `MAX_PACKET_SIZE`.
+    ///
+    /// The search starts over up to the new size.
+    pub fn set_max_packet_size(&mut self, size: u32) {
+        self.max = clamp_size(size);
+        self.floor = min(self.floor, self.max);
+        self.restart();
+    }
+
+    /// Sends packets of the given size from now on, between `MIN_PACKET_SIZE` and
+    /// `MAX_PACKET_SIZE`, without probing for larger sizes.
+    pub fn pin(&mut self, size: u32) {
+        self.floor = clamp_size(size);
+        self.pinned = true;
+        self.probe = None;
+    }
+
+    /// Sequence number of the packet probing the path, if one is in flight.
+    pub fn probe_seq_nr(&self) -> Option<u16> {
+        self.probe.map(|probe| probe.seq_nr)
+    }
+
+    /// Size to pad the packet with the given sequence number to, if it should probe the path.
+    ///
+    /// A single probe is in flight at a time.
+    pub fn next_probe(&mut self, seq_nr: u16, now: Instant) -> Option<u32> {
+        if self.pinned || self.probe.is_some() {
+            return None;
+        }
+
+        if self.searched() {
+            let searched_at = *self.searched_at.get_or_insert(now);
+            if now.duration_since(searched_at) < RESEARCH_INTERVAL {
+                return None;
+            }
+
+            self.restart();
+            if self.searched() {
+                self.searched_at = Some(now);
+                return None;
+            }
+        }
+
+        let size = (self.floor + self.ceiling + 1) / 2;
+        self.probe = Some(Probe {
+            seq_nr: seq_nr,
+            size: size,
+        });
+
+        Some(size)
+    }
+
+    /// The probe in flight was acknowledged, the path carries packets of its size.
+    pub fn on_probe_acked(&mut self) {
+        if let Some(probe) = self.probe.take() {
+            self.floor = max(self.floor, probe.size);
+            self.probe_losses = 0;
+        }
+    }
+
+    /// The probe in flight was lost, the path may not carry packets of its size.
+    pub fn on_probe_lost(&mut self) {
+        if let Some(probe) = self.probe.take() {
+            self.probe_losses += 1;
+
+            if self.probe_losses >= PROBE_LOSS_LIMIT {
+                self.ceiling = max(probe.size - 1, self.floor);
+                self.probe_losses = 0;
+            }
+        }
+    }
+
+    /// Whether the search is finished, no size left to probe for is worth it.
+    fn searched(&self) -> bool {
+        self.ceiling < self.floor + PROBE_GRANULARITY
+    }
+
+    fn restart(&mut self) {
+        self.ceiling = self.max;
+        self.probe = None;
+        self.probe_losses = 0;
+        self.searched_at = None;
+    }
+}
+
+fn clamp_size(size: u32) -> u32 {
+    max(min(size, MAX_PACKET_SIZE), MIN_PACKET_SIZE)
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, Instant};
+
+    use super::{
+        MtuDiscovery, DEFAULT_MAX_PACKET_SIZE, MAX_PACKET_SIZE, MIN_PACKET_SIZE, PROBE_GRANULARITY,
+        RESEARCH_INTERVAL,
+    };
+
+    /// Probes the path until the search finishes, returning the number of probes sent.
+    fn search(mtu: &mut MtuDiscovery, path_mtu: u32, now: Instant) -> u32 {
+        let mut probes = 0;
+
+        while let Some(size) = mtu.next_probe(probes as u16, now) {
+            probes += 1;
+            assert!(probes < 100);
+
+            if size <= path_mtu {
+                mtu.on_probe_acked();
+            } else {
+                mtu.on_probe_lost();
+            }
+        }
+
+        probes
+    }
+
+    #[test]
+    fn test_starts_conservative() {
+        let mtu = MtuDiscovery::new();
+
+        assert_eq!(mtu.packet_size(), MIN_PACKET_SIZE);
+        assert_eq!(mtu.max, DEFAULT_MAX_PACKET_SIZE);
+        assert_eq!(mtu.probe_seq_nr(), None);
+    }
+
+    #[test]
+    fn test_converges_on_path_mtu() {
+        let now = Instant::now();
+
+        for &path_mtu in &[600, 1000, 1232, 1400] {
+            let mut mtu = MtuDiscovery::new();
+            search(&mut mtu, path_mtu, now);
+
+            assert!(mtu.packet_size() <= path_mtu);
+            assert!(mtu.packet_size() + PROBE_GRANULARITY > path_mtu);
+        }
+
+        // Paths carrying larger packets than we probe for
+        let mut mtu = MtuDiscovery::new();
+        search(&mut mtu, 9000, now);
+        assert!(mtu.packet_size() + PROBE_GRANULARITY > DEFAULT_MAX_PACKET_SIZE);
+    }
+
+    #[test]
+    fn test_single_probe_loss_does_not_lower_ceiling() {
+        let mut mtu = MtuDiscovery::new();
+        let now = Instant::now();
+
+        let size = mtu.next_probe(1, now).unwrap();
+        mtu.on_probe_lost();
+
+        // Lost once, probed for again
+        assert_eq!(mtu.next_probe(2, now), Some(size));
+        assert_eq!(mtu.probe_seq_nr(), Some(2));
+        mtu.on_probe_acked();
+        assert_eq!(mtu.packet_size(), size);
+
+        // Lost twice over, probing lower
+        let size = mtu.next_probe(3, now).unwrap();
+        mtu.on_probe_lost();
+        assert_eq!(mtu.next_probe(4, now), Some(size));
+        mtu.on_probe_lost();
+        assert!(mtu.next_probe(5, now).unwrap() < size);
+    }
+
+    #[test]
+    fn test_single_probe_in_flight() {
+        let mut mtu = MtuDiscovery::new();
+        let now = Instant::now();
+
+        assert!(mtu.next_probe(1, now).is_some());
+        assert_eq!(mtu.next_probe(2, now), None);
+        assert_eq!(mtu.probe_seq_nr(), Some(1));
+    }
+
+    #[test]
+    fn test_clamped_to_max_packet_size() {
+        let mut mtu = MtuDiscovery::new();
+        let now = Instant::now();
+
+        mtu.set_max_packet_size(1000);
+        search(&mut mtu, 9000, now);
+        assert!(mtu.packet_size() <= 1000 && mtu.packet_size() + PROBE_GRANULARITY > 1000);
+
+        mtu.set_max_packet_size(100);
+        assert_eq!(mtu.max, MIN_PACKET_SIZE);
+        assert_eq!(mtu.packet_size(), MIN_PACKET_SIZE);
+
+        mtu.set_max_packet_size(9000);
+        assert_eq!(mtu.max, MAX_PACKET_SIZE);
+    }
+
+    #[test]
+    fn test_pinned_size_is_not_probed() {
+        let mut mtu = MtuDiscovery::new();
+
+        mtu.pin(1200);
+        assert_eq!(mtu.packet_size(), 1200);
+        assert_eq!(mtu.next_probe(1, Instant::now()), None);
+
+        mtu.pin(9000);
+        assert_eq!(mtu.packet_size(), MAX_PACKET_SIZE);
+    }
+
+    #[test]
+    fn test_search_restarts_after_interval() {
+        let mut mtu = MtuDiscovery::new();
+        let now = Instant::now();
+
+        search(&mut mtu, 1000, now);
+        let found = mtu.packet_size();
+        assert_eq!(mtu.next_probe(1, now + Duration::from_secs(60)), None);
+
+        // The path carries larger packets by now
+        let later = now + RESEARCH_INTERVAL;
+        assert!(search(&mut mtu, 1400, later) > 0);
+        assert!(mtu.packet_size() > found);
+    }
+}
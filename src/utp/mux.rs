@@ -0,0 +1,476 @@
+use std::cell::Cell;
+use std::cmp::min;
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::io::{self, ErrorKind};
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, SendError, Sender};
+use std::sync::{Arc, Mutex, Weak};
+use std::thread;
+use std::time::Duration;
+
+use super::mtu::MAX_PACKET_SIZE;
+use super::packet::{Packet, PacketType, TryFromType, HEADER_SIZE};
+use super::time::now_microseconds;
+use super::util::generate_sequential_identifiers;
+
+/// How often the receiving thread checks whether everyone let go of the shared socket.
+const SHUTDOWN_CHECK_MS: u64 = 500;
+
+/// Datagram received for a connection, along with the address it came from.
+type Datagram = (Vec<u8>, SocketAddr);
+
+/// Socket the packets of a uTP connection are sent and received on.
+///
+/// Either a UDP socket of its own, or a UDP socket shared with other connections through a
+/// `Multiplexer`.
+#[derive(Debug)]
+pub enum RawSocket {
+    /// UDP socket used by this connection alone
+    Udp(UdpSocket),
+    /// UDP socket shared with other connections
+    Mux(MuxSocket),
+}
+
+impl RawSocket {
+    /// Sends a datagram to the given address.
+    pub fn send_to<A: ToSocketAddrs>(&self, buf: &[u8], addr: A) -> io::Result<usize> {
+        match *self {
+            RawSocket::Udp(ref socket) => socket.send_to(buf, addr),
+            RawSocket::Mux(ref socket) => socket.mux.socket.send_to(buf, addr),
+        }
+    }
+
+    /// Receives a datagram, returning the number of bytes read and the address it came from.
+    pub fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        match *self {
+            RawSocket::Udp(ref socket) => socket.recv_from(buf),
+            RawSocket::Mux(ref socket) => socket.recv_from(buf),
+        }
+    }
+
+    /// Sets how long `recv_from` blocks, `None` blocks indefinitely.
+    pub fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        match *self {
+            RawSocket::Udp(ref socket) => socket.set_read_timeout(timeout),
+            RawSocket::Mux(ref socket) => {
+                socket.read_timeout.set(timeout);
+                Ok(())
+            }
+        }
+    }
+
+    /// Returns the local address of the socket.
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        match *self {
+            RawSocket::Udp(ref socket) => socket.local_addr(),
+            RawSocket::Mux(ref socket) => socket.mux.local_addr(),
+        }
+    }
+
+    /// Creates a new handle to the socket.
+    ///
+    /// Datagrams received on a shared socket are handed to a single connection, so only sockets of
+    /// their own can be cloned.
+    pub fn try_clone(&self) -> io::Result<RawSocket> {
+        match *self {
+            RawSocket::Udp(ref socket) => socket.try_clone().map(RawSocket::Udp),
+            RawSocket::Mux(_) => Err(io::Error::new(
+                ErrorKind::Other,
+                "Can not clone a uTP connection sharing its socket",
+            )),
+        }
+    }
+}
+
+/// Connection on a shared socket, packets from its remote peer carrying its connection id are
+/// handed to it.
+#[derive(Debug)]
+struct Route {
+    sender_connection_id: u16,
+    /// Sequence number of the SYN opening the connection, if the remote peer opened it
+    syn_seq_nr: Option<u16>,
+    send: Sender<Datagram>,
+}
+
+/// Connection opened by a remote peer, waiting for the listener to accept it.
+pub struct Accepted {
+    /// SYN opening the connection
+    pub syn: Packet,
+    /// Address of the remote peer
+    pub src: SocketAddr,
+    /// Socket of the connection
+    pub socket: MuxSocket,
+}
+
+/// UDP socket shared by many uTP connections.
+///
+/// A thread receives every datagram on the socket and hands it to the connection it belongs to,
+/// going by the address of the remote peer and the connection id, as set out in BEP 29. A SYN
+/// opens a new connection, which is handed to the listener, unless the remote peer already has a
+/// connection with that id. Colliding SYNs and packets for connections we do not know of are
+/// answered with a reset. The thread stops once the listener and every connection let go of it.
+#[derive(Debug)]
+pub struct Multiplexer {
+    socket: UdpSocket,
+    routes: Mutex<HashMap<(SocketAddr, u16), Route>>,
+    accept: Mutex<Sender<Accepted>>,
+}
+
+impl Multiplexer {
+    /// Binds a shared socket to the given address, connections opened by remote peers come out
+    /// of the returned receiver.
+    pub fn bind(addr: SocketAddr) -> io::Result<(Arc<Multiplexer>, Receiver<Accepted>)> {
+        let socket = UdpSocket::bind(addr)?;
+        let recv_socket = socket.try_clone()?;
+        recv_socket.set_read_timeout(Some(Duration::from_millis(SHUTDOWN_CHECK_MS)))?;
+
+        let (send, recv) = mpsc::channel();
+        let mux = Arc::new(Multiplexer {
+            socket: socket,
+            routes: Mutex::new(HashMap::new()),
+            accept: Mutex::new(send),
+        });
+
+        let weak_mux = Arc::downgrade(&mux);
+        thread::spawn(move || run(recv_socket, weak_mux));
+
+        Ok((mux, recv))
+    }
+
+    /// Returns the local address of the shared socket.
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+
+    /// Adds a connection we open to the remote peer, returning its socket along with the receiver
+    /// connection id picked for it.
+    pub fn register(mux: &Arc<Multiplexer>, remote: SocketAddr) -> (MuxSocket, u16) {
+        let mut routes = mux.routes();
+
+        loop {
+            let (receiver_id, sender_id) = generate_sequential_identifiers();
+            let key = (remote, receiver_id);
+
+            if let Entry::Vacant(entry) = routes.entry(key) {
+                let (send, recv) = mpsc::channel();
+                entry.insert(Route {
+                    sender_connection_id: sender_id,
+                    syn_seq_nr: None,
+                    send: send,
+                });
+
+                return (MuxSocket::new(mux.clone(), key, recv), receiver_id);
+            }
+        }
+    }
+
+    /// Hands a datagram to the connection it belongs to.
+    fn dispatch(mux: &Arc<Multiplexer>, bytes: &[u8], src: SocketAddr) {
+        let packet = match Packet::try_from_type(bytes) {
+            Ok(packet) => packet,
+            Err(e) => {
+                debug!("Ignoring invalid packet from {}: {}", src, e);
+                return;
+            }
+        };
+
+        if packet.get_type() == PacketType::Syn {
+            return Multiplexer::dispatch_syn(mux, packet, src);
+        }
+
+        let routes = mux.routes();
+        let connection_id = packet.connection_id();
+        // Resets may carry the id the remote peer receives on, rather than the one we receive on
+        let route = routes.get(&(src, connection_id)).or_else(|| {
+            routes
+                .iter()
+                .find(|&(&(addr, _), route)| {
+                    packet.get_type() == PacketType::Reset
+                        && addr == src
+                        && route.sender_connection_id == connection_id
+                })
+                .map(|(_, route)| route)
+        });
+
+        match route {
+            Some(route) => {
+                let _ = route.send.send((bytes.to_vec(), src));
+            }
+            None => {
+                drop(routes);
+
+                if packet.get_type() != PacketType::Reset {
+                    debug!("No connection {} from {}, resetting", connection_id, src);
+                    mux.reset(&packet, src);
+                }
+            }
+        }
+    }
+
+    /// Opens a connection for a SYN, or hands it to the connection it already opened.
+    fn dispatch_syn(mux: &Arc<Multiplexer>, syn: Packet, src: SocketAddr) {
+        // The remote peer sends on the id following the one in its SYN
+        let key = (src, syn.connection_id().wrapping_add(1));
+
+        let mut routes = mux.routes();
+        match routes.get(&key) {
+            // The remote peer did not get our reply, the connection answers it again
+            Some(route) if route.syn_seq_nr == Some(syn.seq_nr()) => {
+                let _ = route.send.send((syn.as_ref().to_vec(), src));
+                return;
+            }
+            Some(_) => {
+                drop(routes);
+
+                debug!(
+                    "Connection id {} from {} already in use, resetting",
+                    key.1, src
+                );
+                return mux.reset(&syn, src);
+            }
+            None => (),
+        }
+
+        let (send, recv) = mpsc::channel();
+        routes.insert(
+            key,
+            Route {
+                sender_connection_id: syn.connection_id(),
+                syn_seq_nr: Some(syn.seq_nr()),
+                send: send,
+            },
+        );
+        drop(routes);
+
+        let accepted = Accepted {
+            syn: syn,
+            src: src,
+            socket: MuxSocket::new(mux.clone(), key, recv),
+        };
+        let result = mux
+            .accept
+            .lock()
+            .expect("bittorrent-protocol_utp: Poisoned Lock In Multiplexer")
+            .send(accepted);
+
+        // No one listens for connections anymore, the route goes along with the socket
+        if let Err(SendError(accepted)) = result {
+            mux.reset(&accepted.syn, src);
+        }
+    }
+
+    /// Answers a packet with a reset.
+    fn reset(&self, packet: &Packet, dst: SocketAddr) {
+        let mut reset = Packet::new();
+        reset.set_type(PacketType::Reset);
+        reset.set_connection_id(packet.connection_id());
+        reset.set_seq_nr(rand::random());
+        reset.set_ack_nr(packet.seq_nr());
+        reset.set_timestamp(now_microseconds());
+
+        let _ = self.socket.send_to(reset.as_ref(), dst);
+    }
+
+    fn routes(&self) -> ::std::sync::MutexGuard<'_, HashMap<(SocketAddr, u16), Route>> {
+        self.routes
+            .lock()
+            .expect("bittorrent-protocol_utp: Poisoned Lock In Multiplexer")
+    }
+}
+
+/// Receives datagrams on the shared socket until everyone let go of the multiplexer.
+fn run(socket: UdpSocket, weak_mux: Weak<Multiplexer>) {
+    let mut buf = [0; MAX_PACKET_SIZE as usize + HEADER_SIZE];
+
+    loop {
+        let result = socket.recv_from(&mut buf);
+        let mux = match weak_mux.upgrade() {
+            Some(mux) => mux,
+            None => return,
+        };
+
+        match result {
+            Ok((read, src)) => Multiplexer::dispatch(&mux, &buf[..read], src),
+            Err(ref e) if e.kind() == ErrorKind::WouldBlock || e.kind() == ErrorKind::TimedOut => {}
+            Err(e) => debug!("Failed to receive on the shared socket: {}", e),
+        }
+    }
+}
+
+/// Socket of a single connection on a shared socket.
+#[derive(Debug)]
+pub struct MuxSocket {
+    mux: Arc<Multiplexer>,
+    key: (SocketAddr, u16),
+    recv: Receiver<Datagram>,
+    read_timeout: Cell<Option<Duration>>,
+}
+
+impl MuxSocket {
+    fn new(mux: Arc<Multiplexer>, key: (SocketAddr, u16), recv: Receiver<Datagram>) -> MuxSocket {
+        MuxSocket {
+            mux: mux,
+            key: key,
+            recv: recv,
+            read_timeout: Cell::new(None),
+        }
+    }
+
+    fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        let (datagram, src) = match self.read_timeout.get() {
+            Some(timeout) => self.recv.recv_timeout(timeout).map_err(|e| match e {
+                RecvTimeoutError::Timeout => io::Error::new(ErrorKind::TimedOut, "Timed Out"),
+                RecvTimeoutError::Disconnected => disconnected(),
+            })?,
+            None => self.recv.recv().map_err(|_| disconnected())?,
+        };
+
+        let len = min(buf.len(), datagram.len());
+        buf[..len].copy_from_slice(&datagram[..len]);
+
+        Ok((len, src))
+    }
+}
+
+impl Drop for MuxSocket {
+    fn drop(&mut self) {
+        self.mux.routes().remove(&self.key);
+    }
+}
+
+fn disconnected() -> io::Error {
+    io::Error::new(ErrorKind::NotConnected, "Shared Socket Closed")
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::{SocketAddr, UdpSocket};
+    use std::time::Duration;
+
+    use super::Multiplexer;
+    use crate::utp::packet::{Packet, PacketType, TryFromType};
+
+    fn mux() -> (
+        std::sync::Arc<Multiplexer>,
+        std::sync::mpsc::Receiver<super::Accepted>,
+    ) {
+        Multiplexer::bind("127.0.0.1:0".parse().unwrap()).unwrap()
+    }
+
+    fn peer() -> UdpSocket {
+        let peer = UdpSocket::bind("127.0.0.1:0").unwrap();
+        peer.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        peer
+    }
+
+    fn syn(connection_id: u16, seq_nr: u16) -> Packet {
+        let mut packet = Packet::new();
+        packet.set_type(PacketType::Syn);
+        packet.set_connection_id(connection_id);
+        packet.set_seq_nr(seq_nr);
+        packet
+    }
+
+    fn recv_packet(socket: &UdpSocket) -> (Packet, SocketAddr) {
+        let mut buf = [0; 1500];
+        let (read, src) = socket.recv_from(&mut buf).unwrap();
+
+        (Packet::try_from_type(&buf[..read]).unwrap(), src)
+    }
+
+    #[test]
+    fn test_syn_accepted_once() {
+        let (mux, accepted) = mux();
+        let mux_addr = mux.local_addr().unwrap();
+        let peer = peer();
+
+        peer.send_to(syn(10, 1).as_ref(), mux_addr).unwrap();
+        let connection = accepted.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(connection.syn.connection_id(), 10);
+        assert_eq!(connection.src, peer.local_addr().unwrap());
+
+        // A retransmitted SYN goes to the connection it opened
+        peer.send_to(syn(10, 1).as_ref(), mux_addr).unwrap();
+        let mut buf = [0; 1500];
+        connection
+            .socket
+            .read_timeout
+            .set(Some(Duration::from_secs(5)));
+        let (read, _) = connection.socket.recv_from(&mut buf).unwrap();
+        assert_eq!(
+            Packet::try_from_type(&buf[..read]).unwrap().get_type(),
+            PacketType::Syn
+        );
+        assert!(accepted.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_colliding_syn_reset() {
+        let (mux, accepted) = mux();
+        let mux_addr = mux.local_addr().unwrap();
+        let peer = peer();
+
+        peer.send_to(syn(10, 1).as_ref(), mux_addr).unwrap();
+        let _connection = accepted.recv_timeout(Duration::from_secs(5)).unwrap();
+
+        // Same connection id, another connection
+        peer.send_to(syn(10, 500).as_ref(), mux_addr).unwrap();
+        let (reset, src) = recv_packet(&peer);
+        assert_eq!(reset.get_type(), PacketType::Reset);
+        assert_eq!(reset.connection_id(), 10);
+        assert_eq!(reset.ack_nr(), 500);
+        assert_eq!(src, mux_addr);
+        assert!(accepted.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_unknown_connection_reset() {
+        let (mux, _accepted) = mux();
+        let mux_addr = mux.local_addr().unwrap();
+        let peer = peer();
+
+        let mut packet = Packet::with_payload(&[1, 2, 3]);
+        packet.set_connection_id(42);
+        packet.set_seq_nr(7);
+        peer.send_to(packet.as_ref(), mux_addr).unwrap();
+
+        let (reset, _) = recv_packet(&peer);
+        assert_eq!(reset.get_type(), PacketType::Reset);
+        assert_eq!(reset.connection_id(), 42);
+        assert_eq!(reset.ack_nr(), 7);
+
+        // Never answer a reset with a reset
+        peer.send_to(reset.as_ref(), mux_addr).unwrap();
+        peer.set_read_timeout(Some(Duration::from_millis(100)))
+            .unwrap();
+        assert!(peer.recv_from(&mut [0; 1500]).is_err());
+    }
+
+    #[test]
+    fn test_connection_forgotten_when_dropped() {
+        let (mux, accepted) = mux();
+        let mux_addr = mux.local_addr().unwrap();
+        let peer = peer();
+
+        peer.send_to(syn(10, 1).as_ref(), mux_addr).unwrap();
+        drop(accepted.recv_timeout(Duration::from_secs(5)).unwrap());
+
+        // The connection went away, the id is free for another one
+        peer.send_to(syn(10, 500).as_ref(), mux_addr).unwrap();
+        let connection = accepted.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(connection.syn.seq_nr(), 500);
+    }
+
+    #[test]
+    fn test_syn_reset_without_listener() {
+        let (mux, accepted) = mux();
+        let mux_addr = mux.local_addr().unwrap();
+        let peer = peer();
+        drop(accepted);
+
+        peer.send_to(syn(10, 1).as_ref(), mux_addr).unwrap();
+        let (reset, _) = recv_packet(&peer);
+        assert_eq!(reset.get_type(), PacketType::Reset);
+    }
+}
@@ -2,11 +2,15 @@
 
 use super::bit_iterator::BitIterator;
 use super::error::ParseError;
+use std::cmp::min;
 use std::fmt;
 use super::time::{Timestamp, Delay};
 
 pub const HEADER_SIZE: usize = 20;
 
+/// Extension padding packets that probe the path MTU, unknown to receivers which skip over it.
+pub const PADDING_EXTENSION: ExtensionType = ExtensionType::Unknown(0x7f);
+
 macro_rules! u8_to_unsigned_be {
     ($src:ident, $start:expr, $end:expr, $t:ty) => ({
         (0 .. $end - $start + 1).rev().fold(0, |acc, i| acc | $src[$start+i] as $t << (i * 8))
@@ -324,13 +328,36 @@ impl Packet {
         assert!(bv.len() >= 4);
         assert_eq!(bv.len() % 4, 0);
 
+        self.add_extension(ExtensionType::SelectiveAck, &bv);
+    }
+
+    /// Pads the packet with extensions receivers skip over, bringing it to `size` bytes.
+    ///
+    /// Padding lets a packet probe whether packets of the given size make it to the remote peer,
+    /// the packet can still be resent without it. A packet within a byte of `size` is left as is.
+    pub fn pad_to(&mut self, size: usize) {
+        while self.len() + 2 <= size {
+            let remaining = size - self.len() - 2;
+            let mut len = min(remaining, u8::MAX as usize);
+
+            // Never leave a single byte to pad, an extension takes up two bytes at least
+            if remaining - len == 1 {
+                len -= 1;
+            }
+
+            self.add_extension(PADDING_EXTENSION, &vec![0; len]);
+        }
+    }
+
+    /// Adds an extension after the last one, before the payload.
+    fn add_extension(&mut self, ty: ExtensionType, data: &[u8]) {
         let mut index = HEADER_SIZE;
         let mut extension_type = ExtensionType::from(self.0[1]);
 
         // Set extension type in header if none is used, otherwise find and update the
         // "next extension type" marker in the last extension before payload
         if extension_type == ExtensionType::None {
-            self.0[1] = ExtensionType::SelectiveAck.into();
+            self.0[1] = ty.into();
         } else {
             // Skip over all extensions until last, then modify its "next extension type" field and
             // add the new extension after it.
@@ -346,25 +373,16 @@ impl Packet {
                 // Arrived at last extension
                 if extension_type == ExtensionType::None {
                     // Mark existence of an additional extension
-                    self.0[index] = ExtensionType::SelectiveAck.into();
+                    self.0[index] = ty.into();
                 }
                 index += len + 2;
             }
         }
 
-        // Insert the new extension into the packet's data.
-        // The way this is currently done is potentially slower than the alternative of resizing the
-        // underlying Vec, moving the payload forward and then writing the extension in the "new"
-        // place before the payload.
-
-        // Set the type of the following (non-existent) extension
-        self.0.insert(index, ExtensionType::None.into());
-        // Set this extension's length
-        self.0.insert(index + 1, bv.len() as u8);
-        // Write this extension's data
-        for (i, &value) in bv.iter().enumerate() {
-            self.0.insert(index + 2 + i, value);
-        }
+        // Insert the new extension into the packet's data: the type of the following
+        // (non-existent) extension, this extension's length and its data
+        let header = [ExtensionType::None.into(), data.len() as u8];
+        self.0.splice(index..index, header.iter().chain(data.iter()).cloned());
     }
 
     pub fn len(&self) -> usize {
@@ -475,10 +493,11 @@ fn check_extensions(data: &[u8]) -> Result<(), ParseError> {
         let extension_end = extension_start + len;
 
         // Check validity of extension length:
-        // - non-zero,
-        // - multiple of 4,
-        // - does not exceed packet length
-        if len == 0 || len % 4 != 0 || extension_end > data.len() {
+        // - does not exceed packet length,
+        // - non-zero and a multiple of 4 for selective acknowledgements, unknown extensions are
+        //   skipped whatever their length
+        let invalid_sack = extension_type == ExtensionType::SelectiveAck && (len == 0 || len % 4 != 0);
+        if invalid_sack || extension_end > data.len() {
             return Err(ParseError::InvalidExtensionLength);
         }
 
@@ -495,7 +514,7 @@ fn check_extensions(data: &[u8]) -> Result<(), ParseError> {
 
 #[cfg(test)]
 mod tests {
-    use crate::utp::packet::{ExtensionType, Extension, Packet, PacketType, HEADER_SIZE, TryFromType, check_extensions, PacketHeader};
+    use crate::utp::packet::{ExtensionType, Extension, Packet, PacketType, HEADER_SIZE, PADDING_EXTENSION, TryFromType, check_extensions, PacketHeader};
     use crate::utp::packet::PacketType::{State, Data};
     use crate::utp::time::{Timestamp, Delay};
     use crate::quickcheck::TestResult;
@@ -596,6 +615,29 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_decode_packet_with_unknown_extension_of_any_length() {
+        let buf = [0x01, 0x02, 0x41, 0xa7, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+                   0x00, 0x00, 0x00, 0x00, 0x05, 0xdc, 0xab, 0x53, 0x3a, 0xf5,
+                   0x01, 0x03, 0xaa, 0xbb, 0xcc, // Extension bits, odd length
+                   0x00, 0x04, 0x05, 0x00, 0x00, 0x00, // Selective acknowledgement
+                   0x01, 0x02, 0x03];
+        let packet = Packet::try_from_type(&buf).unwrap();
+        let extensions: Vec<Extension> = packet.extensions().collect();
+        assert_eq!(extensions.len(), 2);
+        assert_eq!(extensions[0].ty, ExtensionType::Unknown(2));
+        assert_eq!(extensions[0].data, &[0xaa, 0xbb, 0xcc]);
+        assert_eq!(extensions[1].ty, ExtensionType::SelectiveAck);
+        assert_eq!(extensions[1].iter().take(3).collect::<Vec<_>>(), vec![true, false, true]);
+        assert_eq!(packet.payload(), &[1, 2, 3]);
+
+        // Selective acknowledgements still need a multiple of 4 bytes
+        let mut buf = buf.to_vec();
+        buf.remove(30);
+        buf[26] = 0x03;
+        assert!(Packet::try_from_type(&buf).is_err());
+    }
+
     #[test]
     fn test_packet_set_type() {
         let mut packet = Packet::new();
@@ -640,6 +682,27 @@ mod tests {
         assert_eq!(extensions[1].len(), 8);
     }
 
+    #[test]
+    fn test_packet_pad_to() {
+        for &size in &[600, 1000, 1280, 1500] {
+            let mut packet = Packet::with_payload(&[1, 2, 3]);
+            packet.set_sack(vec![1, 0, 0, 0]);
+            packet.pad_to(size);
+
+            assert_eq!(packet.len(), size);
+            let packet = Packet::try_from_type(packet.as_ref()).unwrap();
+            assert_eq!(packet.payload(), &[1, 2, 3]);
+            let extensions: Vec<Extension> = packet.extensions().collect();
+            assert_eq!(extensions[0].ty, ExtensionType::SelectiveAck);
+            assert!(extensions[1..].iter().all(|extension| extension.ty == PADDING_EXTENSION));
+        }
+
+        // Already large enough
+        let mut packet = Packet::with_payload(&[0; 100]);
+        packet.pad_to(HEADER_SIZE + 101);
+        assert_eq!(packet.len(), HEADER_SIZE + 100);
+    }
+
     #[test]
     fn test_packet_encode() {
         let payload = b"Hello\n".to_vec();
@@ -1,5 +1,5 @@
 use std::cmp::{min, max};
-use std::collections::VecDeque;
+use std::collections::{HashSet, VecDeque};
 use std::net::{ToSocketAddrs, SocketAddr, UdpSocket};
 use std::io::{Result, ErrorKind, Read, Write};
 use super::util::*;
@@ -8,31 +8,29 @@ use super::error::SocketError;
 use rand;
 use std::time::{Duration, Instant};
 use super::time::*;
+use super::ledbat::Ledbat;
+use super::mtu::MtuDiscovery;
+use super::mux::{Accepted, Multiplexer, RawSocket};
 use std::io;
+use std::iter;
+use std::sync::Arc;
+use std::sync::mpsc::Receiver;
 
 // For simplicity's sake, let us assume no packet will ever exceed the
 // Ethernet maximum transfer unit of 1500 bytes.
 const BUF_SIZE: usize = 1500;
-const GAIN: f64 = 1.0;
-const ALLOWED_INCREASE: u32 = 1;
-const TARGET: f64 = 100_000.0; // 100 milliseconds
-const MSS: u32 = 1400;
-const MIN_CWND: u32 = 2;
-const INIT_CWND: u32 = 2;
 const INITIAL_CONGESTION_TIMEOUT: u64 = 2000; // one second
 const MIN_CONGESTION_TIMEOUT: u64 = 500; // 500 ms
 const MAX_CONGESTION_TIMEOUT: u64 = 60_000; // one minute
-const BASE_HISTORY: usize = 10; // base delays history size
 const MAX_SYN_RETRIES: u32 = 5; // maximum connection retries
 const MAX_RETRANSMISSION_RETRIES: u32 = 5; // maximum retransmission retries
+const DUPLICATE_ACKS_BEFORE_RESEND: u32 = 3; // repeated or selective acks before a fast resend
+const MAX_SACK_LEN: usize = 252; // largest selective ack bitmask an extension can hold
 const WINDOW_SIZE: u32 = 1024 * 1024; // local receive window size
 
 // Maximum time (in microseconds) to wait for incoming packets when the send window is full
 const PRE_SEND_TIMEOUT: u32 = 500_000;
 
-// Maximum age of base delay sample (60 seconds)
-const MAX_BASE_DELAY_AGE: Delay = Delay(60_000_000);
-
 #[derive(PartialEq, Eq, Debug, Copy, Clone)]
 enum SocketState {
     New,
@@ -42,10 +40,85 @@ enum SocketState {
     ResetReceived,
     Closed,
 }
-#[derive(Copy, Clone,Debug)]
-struct DelayDifferenceSample {
-    received_at: Timestamp,
-    difference: Delay,
+/// Statistics on the congestion control of a uTP connection.
+///
+/// Delays are one-way delays measured by the remote peer, which include the difference between the
+/// clocks of both peers. Only the queuing delay, the current delay above the base delay, is
+/// meaningful on its own.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UtpStats {
+    congestion_window: u32,
+    remote_window: u32,
+    bytes_in_flight: u32,
+    base_delay: u32,
+    current_delay: u32,
+    queuing_delay: u32,
+    target_delay: Duration,
+    rtt: u64,
+    timeouts: u32,
+    mss: u32,
+}
+
+impl UtpStats {
+    /// Congestion window, in bytes.
+    pub fn congestion_window(&self) -> u32 {
+        self.congestion_window
+    }
+
+    /// Receive window advertised by the remote peer, in bytes.
+    pub fn remote_window(&self) -> u32 {
+        self.remote_window
+    }
+
+    /// Bytes sent but not yet acknowledged.
+    pub fn bytes_in_flight(&self) -> u32 {
+        self.bytes_in_flight
+    }
+
+    /// Lowest one-way delay measured in the last minutes.
+    pub fn base_delay(&self) -> Duration {
+        Duration::from_micros(self.base_delay as u64)
+    }
+
+    /// Filtered one-way delay measured in the last round trip.
+    pub fn current_delay(&self) -> Duration {
+        Duration::from_micros(self.current_delay as u64)
+    }
+
+    /// Delay of the last round trip on top of the base delay.
+    pub fn queuing_delay(&self) -> Duration {
+        Duration::from_micros(self.queuing_delay as u64)
+    }
+
+    /// Queuing delay the congestion control aims for.
+    pub fn target_delay(&self) -> Duration {
+        self.target_delay
+    }
+
+    /// Smoothed round-trip time to the remote peer.
+    pub fn rtt(&self) -> Duration {
+        Duration::from_millis(self.rtt)
+    }
+
+    /// Number of times the remote peer did not reply within the congestion timeout.
+    pub fn timeouts(&self) -> u32 {
+        self.timeouts
+    }
+
+    /// Size of the packets we send, including the uTP header, as found by MTU discovery.
+    pub fn mss(&self) -> u32 {
+        self.mss
+    }
+}
+
+/// Returns whether sequence number `a` comes after `b`, taking wrapping around into account.
+fn seq_after(a: u16, b: u16) -> bool {
+    a != b && a.wrapping_sub(b) < 0x8000
+}
+
+/// Returns whether timestamp `a` comes before `b`, taking wrapping around into account.
+fn timestamp_before(a: Timestamp, b: Timestamp) -> bool {
+    a != b && u32::from(wrapping_delay(b, a)) < 0x8000_0000
 }
 
 /// Returns the first valid address in a `ToSocketAddrs` iterator.
@@ -85,8 +158,8 @@ fn take_address<A: ToSocketAddrs>(addr: A) -> Result<SocketAddr> {
 /// ```
 #[derive(Debug)]
 pub struct UtpSocket {
-    /// The wrapped UDP socket
-    socket: UdpSocket,
+    /// The wrapped UDP socket, possibly shared with other connections
+    socket: RawSocket,
 
     /// Remote peer
     connected_to: SocketAddr,
@@ -115,9 +188,20 @@ pub struct UtpSocket {
     /// Packets not yet sent
     unsent_queue: VecDeque<Packet>,
 
-    /// How many ACKs did the socket receive for packet with sequence number equal to `ack_nr`
+    /// Sequence numbers of packets in the send window the remote peer selectively acknowledged,
+    /// which no longer count as bytes in flight
+    selectively_acked: HashSet<u16>,
+
+    /// How many ACKs did the socket receive for packet with sequence number equal to `last_acked`
     duplicate_ack_count: u32,
 
+    /// Sequence number of the last packet sent when the congestion window was last halved for a
+    /// packet loss
+    last_loss_seq_nr: Option<u16>,
+
+    /// Number of times the remote peer did not reply within the congestion timeout
+    timeouts: u32,
+
     /// Sequence number of the latest packet the remote peer acknowledged
     last_acked: u16,
 
@@ -142,23 +226,17 @@ pub struct UtpSocket {
     /// Window size of the remote peer
     remote_wnd_size: u32,
 
-    /// Rolling window of packet delay to remote peer
-    base_delays: VecDeque<Delay>,
-
-    /// Rolling window of the difference between sending a packet and receiving its acknowledgement
-    current_delays: Vec<DelayDifferenceSample>,
-
     /// Difference between timestamp of the latest packet received and time of reception
     their_delay: Delay,
 
-    /// Start of the current minute for sampling purposes
-    last_rollover: Timestamp,
-
     /// Current congestion timeout in milliseconds
     congestion_timeout: u64,
 
-    /// Congestion window in bytes
-    cwnd: u32,
+    /// LEDBAT congestion control, holding the congestion window
+    congestion: Ledbat,
+
+    /// Path MTU discovery, picking the size of the packets we send
+    mtu: MtuDiscovery,
 
     /// Maximum retransmission retries
     pub max_retransmission_retries: u32,
@@ -168,8 +246,11 @@ impl UtpSocket {
     /// Creates a new UTP socket from the given UDP socket and the remote peer's address.
     ///
     /// The connection identifier of the resulting socket is randomly generated.
-    fn from_raw_parts(s: UdpSocket, src: SocketAddr) -> UtpSocket {
+    fn from_raw_parts(s: RawSocket, src: SocketAddr) -> UtpSocket {
         let (receiver_id, sender_id) = generate_sequential_identifiers();
+        let mtu = MtuDiscovery::new();
+        let mut congestion = Ledbat::new();
+        congestion.set_mss(mtu.packet_size());
 
         UtpSocket {
             socket: s,
@@ -182,7 +263,10 @@ impl UtpSocket {
             incoming_buffer: Vec::new(),
             send_window: Vec::new(),
             unsent_queue: VecDeque::new(),
+            selectively_acked: HashSet::new(),
             duplicate_ack_count: 0,
+            last_loss_seq_nr: None,
+            timeouts: 0,
             last_acked: 0,
             last_acked_timestamp: Timestamp::default(),
             last_dropped: 0,
@@ -191,12 +275,10 @@ impl UtpSocket {
             pending_data: Vec::new(),
             curr_window: 0,
             remote_wnd_size: 0,
-            current_delays: Vec::new(),
-            base_delays: VecDeque::with_capacity(BASE_HISTORY),
             their_delay: Delay::default(),
-            last_rollover: Timestamp::default(),
             congestion_timeout: INITIAL_CONGESTION_TIMEOUT,
-            cwnd: INIT_CWND * MSS,
+            congestion: congestion,
+            mtu: mtu,
             max_retransmission_retries: MAX_RETRANSMISSION_RETRIES,
         }
     }
@@ -208,7 +290,9 @@ impl UtpSocket {
     ///
     /// If more than one valid address is specified, only the first will be used.
     pub fn bind<A: ToSocketAddrs>(addr: A) -> Result<UtpSocket> {
-        take_address(addr).and_then(|a| UdpSocket::bind(a).map(|s| UtpSocket::from_raw_parts(s, a)))
+        take_address(addr).and_then(|a| {
+            UdpSocket::bind(a).map(|s| UtpSocket::from_raw_parts(RawSocket::Udp(s), a))
+        })
     }
 
     /// Returns the socket address that this socket was created from.
@@ -225,6 +309,49 @@ impl UtpSocket {
         }
     }
 
+    /// Changes the queuing delay the congestion control aims for.
+    ///
+    /// The congestion window grows while the queuing delay stays below the target, and shrinks when
+    /// it rises above it, making room for other traffic on the link. Default value is 100 ms.
+    pub fn set_target_delay(&mut self, target: Duration) {
+        self.congestion.set_target(target);
+    }
+
+    /// Returns statistics on the congestion control of this connection.
+    pub fn stats(&self) -> UtpStats {
+        UtpStats {
+            congestion_window: self.congestion.cwnd(),
+            remote_window: self.remote_wnd_size,
+            bytes_in_flight: self.curr_window,
+            base_delay: self.congestion.base_delay(),
+            current_delay: self.congestion.current_delay(),
+            queuing_delay: self.congestion.queuing_delay(),
+            target_delay: self.congestion.target(),
+            rtt: max(self.rtt, 0) as u64,
+            timeouts: self.timeouts,
+            mss: self.mtu.packet_size(),
+        }
+    }
+
+    /// Changes the largest packet size MTU discovery probes for, in bytes including the uTP header.
+    ///
+    /// Packets start out at 548 bytes, which every path carries, and grow as larger packets are
+    /// found to make it through. The size is kept between 548 and 1500 bytes, default value is
+    /// 1472 bytes.
+    pub fn set_max_packet_size(&mut self, size: u32) {
+        self.mtu.set_max_packet_size(size);
+        self.congestion.set_mss(self.mtu.packet_size());
+    }
+
+    /// Sends packets of the given size from now on, in bytes including the uTP header, disabling
+    /// MTU discovery.
+    ///
+    /// The size is kept between 548 and 1500 bytes.
+    pub fn set_packet_size(&mut self, size: u32) {
+        self.mtu.pin(size);
+        self.congestion.set_mss(self.mtu.packet_size());
+    }
+
     /// Opens a connection to a remote host by hostname or IP address.
     ///
     /// The address type can be any implementer of the `ToSocketAddr` trait. See its documentation
@@ -240,6 +367,12 @@ impl UtpSocket {
         let mut socket = UtpSocket::bind(my_addr)?;
         socket.connected_to = addr;
 
+        socket.establish()
+    }
+
+    /// Sends a SYN to the remote peer and waits for its reply, retrying a few times.
+    fn establish(self) -> Result<UtpSocket> {
+        let mut socket = self;
         let mut packet = Packet::new();
         packet.set_type(PacketType::Syn);
         packet.set_connection_id(socket.receiver_connection_id);
@@ -403,6 +536,14 @@ impl UtpSocket {
         };
         debug!("received {:?}", packet);
 
+        // Insert data packet into the incoming buffer if it isn't a duplicate of a previously
+        // discarded packet, the reply acknowledges it along with the packets buffered before it
+        let connected = self.state == SocketState::Connected || self.state == SocketState::FinSent;
+        if connected && packet.get_type() == PacketType::Data &&
+           seq_after(packet.seq_nr(), self.last_dropped) {
+            self.insert_into_buffer(packet.clone());
+        }
+
         // Process packet, including sending a reply if necessary
         if let Some(mut pkt) = self.handle_packet(&packet, src)? {
             pkt.set_wnd_size(WINDOW_SIZE);
@@ -410,13 +551,6 @@ impl UtpSocket {
             debug!("sent {:?}", pkt);
         }
 
-        // Insert data packet into the incoming buffer if it isn't a duplicate of a previously
-        // discarded packet
-        if packet.get_type() == PacketType::Data &&
-           packet.seq_nr().wrapping_sub(self.last_dropped) > 0 {
-            self.insert_into_buffer(packet);
-        }
-
         // Flush incoming buffer if possible
         let read = self.flush_incoming_buffer(buf);
 
@@ -425,7 +559,8 @@ impl UtpSocket {
 
     fn handle_receive_timeout(&mut self) -> Result<()> {
         self.congestion_timeout *= 2;
-        self.cwnd = MSS;
+        self.congestion.on_timeout();
+        self.timeouts += 1;
 
         // There are three possible cases here:
         //
@@ -462,6 +597,9 @@ impl UtpSocket {
         } else {
             // The socket is sending data packets but there is no reply from the remote
             // peer: resend the first unacknowledged packet with the current timestamp.
+            if self.mtu.probe_seq_nr() == Some(self.send_window[0].seq_nr()) {
+                self.mtu.on_probe_lost();
+            }
             let mut packet = &mut self.send_window[0];
             packet.set_timestamp(now_microseconds());
             self.socket.send_to(packet.as_ref(), self.connected_to)?;
@@ -476,7 +614,7 @@ impl UtpSocket {
         resp.set_type(t);
         let self_t_micro = now_microseconds();
         let other_t_micro = original.timestamp();
-        let time_difference: Delay = abs_diff(self_t_micro, other_t_micro);
+        let time_difference = wrapping_delay(self_t_micro, other_t_micro);
         resp.set_timestamp(self_t_micro);
         resp.set_timestamp_difference(time_difference);
         resp.set_connection_id(self.sender_connection_id);
@@ -491,8 +629,10 @@ impl UtpSocket {
         if !self.incoming_buffer.is_empty() {
             let packet = self.incoming_buffer.remove(0);
             debug!("Removed packet from incoming buffer: {:?}", packet);
-            self.ack_nr = packet.seq_nr();
-            self.last_dropped = self.ack_nr;
+            if seq_after(packet.seq_nr(), self.ack_nr) {
+                self.ack_nr = packet.seq_nr();
+            }
+            self.last_dropped = packet.seq_nr();
             Some(packet)
         } else {
             None
@@ -531,8 +671,7 @@ impl UtpSocket {
         }
 
         if !self.incoming_buffer.is_empty() &&
-            (self.ack_nr == self.incoming_buffer[0].seq_nr() ||
-             self.ack_nr + 1 == self.incoming_buffer[0].seq_nr())
+            !seq_after(self.incoming_buffer[0].seq_nr(), self.ack_nr.wrapping_add(1))
         {
             let flushed = unsafe_copy(&self.incoming_buffer[0].payload()[..], buf);
 
@@ -567,7 +706,8 @@ impl UtpSocket {
 
         let total_length = buf.len();
 
-        for chunk in buf.chunks(MSS as usize - HEADER_SIZE) {
+        let payload_size = self.mtu.packet_size() as usize - HEADER_SIZE;
+        for chunk in buf.chunks(payload_size) {
             let mut packet = Packet::with_payload(chunk);
             packet.set_wnd_size(WINDOW_SIZE - self.send_window.len() as u32);
             packet.set_seq_nr(self.seq_nr);
@@ -613,8 +753,7 @@ impl UtpSocket {
     #[inline]
     fn send_packet(&mut self, packet: &mut Packet) -> Result<()> {
         debug!("current window: {}", self.send_window.len());
-        let max_inflight = min(self.cwnd, self.remote_wnd_size);
-        let max_inflight = max(MIN_CWND * MSS, max_inflight);
+        let max_inflight = self.congestion.max_window(self.remote_wnd_size);
         let now = now_microseconds();
 
         // Wait until enough in-flight packets are acknowledged for rate control purposes, but don't
@@ -624,7 +763,8 @@ impl UtpSocket {
             debug!("max_inflight: {}", max_inflight);
             debug!("self.duplicate_ack_count: {}", self.duplicate_ack_count);
             debug!("now_microseconds() - now = {}", now_microseconds() - now);
-            let mut buf = [0; BUF_SIZE];
+            // Data received meanwhile stays in the incoming buffer for `recv_from` to return
+            let mut buf = [0u8; 0];
             self.recv(&mut buf)?;
         }
         debug!("out: now_microseconds() - now = {}", now_microseconds() - now);
@@ -645,51 +785,24 @@ impl UtpSocket {
 
         packet.set_timestamp(now_microseconds());
         packet.set_timestamp_difference(self.their_delay);
-        self.socket.send_to(packet.as_ref(), self.connected_to)?;
-        debug!("sent {:?}", packet);
-
-        Ok(())
-    }
 
-    // Insert a new sample in the base delay list.
-    //
-    // The base delay list contains at most `BASE_HISTORY` samples, each sample is the minimum
-    // measured over a period of a minute (MAX_BASE_DELAY_AGE).
-    fn update_base_delay(&mut self, base_delay: Delay, now: Timestamp) {
-        if self.base_delays.is_empty() || now - self.last_rollover > MAX_BASE_DELAY_AGE {
-            // Update last rollover
-            self.last_rollover = now;
-
-            // Drop the oldest sample, if need be
-            if self.base_delays.len() == BASE_HISTORY {
-                self.base_delays.pop_front();
+        // Every now and then, pad a packet to probe whether the path carries larger packets. The
+        // packet in the send window is left as is, if the probe is lost it is resent at the size
+        // known to make it through.
+        match self.mtu.next_probe(packet.seq_nr(), Instant::now()) {
+            Some(size) => {
+                let mut probe = packet.clone();
+                probe.pad_to(size as usize);
+                self.socket.send_to(probe.as_ref(), self.connected_to)?;
+                debug!("sent probe of {} bytes {:?}", size, probe);
             }
-
-            // Insert new sample
-            self.base_delays.push_back(base_delay);
-        } else {
-            // Replace sample for the current minute if the delay is lower
-            let last_idx = self.base_delays.len() - 1;
-            if base_delay < self.base_delays[last_idx] {
-                self.base_delays[last_idx] = base_delay;
+            None => {
+                self.socket.send_to(packet.as_ref(), self.connected_to)?;
+                debug!("sent {:?}", packet);
             }
         }
-    }
-
-    /// Inserts a new sample in the current delay list after removing samples older than one RTT, as
-    /// specified in RFC6817.
-    fn update_current_delay(&mut self, v: Delay, now: Timestamp) {
-        // Remove samples more than one RTT old
-        let rtt = (self.rtt as i64 * 100).into();
-        while !self.current_delays.is_empty() && now - self.current_delays[0].received_at > rtt {
-            self.current_delays.remove(0);
-        }
 
-        // Insert new measurement
-        self.current_delays.push(DelayDifferenceSample {
-            received_at: now,
-            difference: v,
-        });
+        Ok(())
     }
 
     fn update_congestion_timeout(&mut self, current_delay: i32) {
@@ -707,26 +820,15 @@ impl UtpSocket {
         debug!("self.congestion_timeout: {}", self.congestion_timeout);
     }
 
-    /// Calculates the filtered current delay in the current window.
-    ///
-    /// The current delay is calculated through application of the exponential
-    /// weighted moving average filter with smoothing factor 0.333 over the
-    /// current delays in the current window.
-    fn filtered_current_delay(&self) -> Delay {
-        let input = self.current_delays.iter().map(|delay| &delay.difference);
-        (ewma(input, 0.333) as i64).into()
-    }
-
-    /// Calculates the lowest base delay in the current window.
-    fn min_base_delay(&self) -> Delay {
-        self.base_delays.iter().min().cloned().unwrap_or_default()
-    }
-
     /// Builds the selective acknowledgement extension data for usage in packets.
+    ///
+    /// Bit `i` of the bitmask marks the packet with sequence number `ack_nr + 2 + i` as received,
+    /// packets too far past `ack_nr` to fit in an extension are left out.
     fn build_selective_ack(&self) -> Vec<u8> {
         let stashed = self.incoming_buffer.iter()
-            .filter(|pkt| pkt.seq_nr() > self.ack_nr + 1)
-            .map(|pkt| (pkt.seq_nr() - self.ack_nr - 2) as usize)
+            .filter(|pkt| seq_after(pkt.seq_nr(), self.ack_nr.wrapping_add(1)))
+            .map(|pkt| pkt.seq_nr().wrapping_sub(self.ack_nr).wrapping_sub(2) as usize)
+            .filter(|&diff| diff < MAX_SACK_LEN * 8)
             .map(|diff| (diff / 8, diff % 8));
 
         let mut sack = Vec::new();
@@ -763,14 +865,20 @@ impl UtpSocket {
 
     fn resend_lost_packet(&mut self, lost_packet_nr: u16) {
         debug!("---> resend_lost_packet({}) <---", lost_packet_nr);
-        match self.send_window.iter().position(|pkt| pkt.seq_nr() == lost_packet_nr) {
+        match self.send_window_position(lost_packet_nr) {
             None => debug!("Packet {} not found", lost_packet_nr),
             Some(position) => {
                 debug!("self.send_window.len(): {}", self.send_window.len());
                 debug!("position: {}", position);
-                let mut packet = self.send_window[position].clone();
+                // Resend right away rather than through `send_packet`, which may wait for
+                // acknowledgements while we are still handling one. The packet in the send window
+                // keeps the new timestamp, telling when it was last sent.
+                let packet = &mut self.send_window[position];
+                packet.set_timestamp(now_microseconds());
+                packet.set_timestamp_difference(self.their_delay);
                 // FIXME: Unchecked result
-                let _ = self.send_packet(&mut packet);
+                let _ = self.socket.send_to(packet.as_ref(), self.connected_to);
+                debug!("resent {:?}", packet);
 
                 // We intentionally don't increase `curr_window` because otherwise a packet's length
                 // would be counted more than once
@@ -779,6 +887,75 @@ impl UtpSocket {
         debug!("---> END resend_lost_packet <---");
     }
 
+    /// Stops counting the packets a selective acknowledgement acknowledges as in flight.
+    ///
+    /// Returns the number of bytes acknowledged for the first time.
+    fn mark_selectively_acked(&mut self, ack_nr: u16, extension: &Extension) -> u32 {
+        let mut bytes_newly_acked = 0;
+
+        for (idx, received) in extension.iter().enumerate() {
+            let seq_nr = ack_nr.wrapping_add(2 + idx as u16);
+
+            if let (true, Some(position)) = (received, self.send_window_position(seq_nr)) {
+                if self.mtu.probe_seq_nr() == Some(seq_nr) {
+                    self.mtu.on_probe_acked();
+                }
+                if self.selectively_acked.insert(seq_nr) {
+                    let len = self.send_window[position].len() as u32;
+                    self.curr_window -= len;
+                    bytes_newly_acked += len;
+                }
+            }
+        }
+
+        bytes_newly_acked
+    }
+
+    /// Returns the position of the packet with the given sequence number in the send window.
+    fn send_window_position(&self, seq_nr: u16) -> Option<usize> {
+        let first_seq_nr = self.send_window.first()?.seq_nr();
+        let position = seq_nr.wrapping_sub(first_seq_nr) as usize;
+
+        self.send_window.get(position)
+            .filter(|packet| packet.seq_nr() == seq_nr)
+            .map(|_| position)
+    }
+
+    /// Finds the packets a selective acknowledgement reports as lost, in order.
+    ///
+    /// The packet following `ack_nr` is implicitly missing, bit `i` of the bitmask acknowledges the
+    /// packet `ack_nr + 2 + i`. A missing packet is lost once `DUPLICATE_ACKS_BEFORE_RESEND` packets
+    /// past it were received, and one of those was sent after it was last sent: a resent packet is
+    /// only resent again when a packet sent after it made it through.
+    fn selective_ack_losses(&self, ack_nr: u16, extension: &Extension) -> Vec<u16> {
+        let received = iter::once(false).chain(extension.iter()).collect::<Vec<bool>>();
+        let mut received_after = 0;
+        let mut latest_sent: Option<Timestamp> = None;
+        let mut lost = Vec::new();
+
+        for (idx, &was_received) in received.iter().enumerate().rev() {
+            let seq_nr = ack_nr.wrapping_add(1 + idx as u16);
+            let sent_at = match self.send_window_position(seq_nr) {
+                Some(position) => self.send_window[position].timestamp(),
+                None => continue,
+            };
+
+            if was_received {
+                received_after += 1;
+                latest_sent = match latest_sent {
+                    Some(latest) if !timestamp_before(latest, sent_at) => Some(latest),
+                    _ => Some(sent_at),
+                };
+            } else if received_after >= DUPLICATE_ACKS_BEFORE_RESEND &&
+                      latest_sent.map_or(false, |latest| !timestamp_before(latest, sent_at)) {
+                lost.push(seq_nr);
+            }
+        }
+
+        lost.reverse();
+        lost
+    }
+
     /// Forgets sent packets that were acknowledged by the remote peer.
     fn advance_send_window(&mut self) {
         // The reason I'm not removing the first element in a loop while its sequence number is
@@ -794,7 +971,9 @@ impl UtpSocket {
                                     .position(|packet| packet.seq_nr() == self.last_acked) {
             for _ in 0..position + 1 {
                 let packet = self.send_window.remove(0);
-                self.curr_window -= packet.len() as u32;
+                if !self.selectively_acked.remove(&packet.seq_nr()) {
+                    self.curr_window -= packet.len() as u32;
+                }
             }
         }
         debug!("self.curr_window: {}", self.curr_window);
@@ -806,8 +985,9 @@ impl UtpSocket {
     fn handle_packet(&mut self, packet: &Packet, src: SocketAddr) -> Result<Option<Packet>> {
         debug!("({:?}, {:?})", self.state, packet.get_type());
 
-        // Acknowledge only if the packet strictly follows the previous one
-        if packet.seq_nr().wrapping_sub(self.ack_nr) == 1 {
+        // Acknowledge only if the packet strictly follows the previous one, state packets don't
+        // take up a sequence number
+        if packet.get_type() != PacketType::State && packet.seq_nr().wrapping_sub(self.ack_nr) == 1 {
             self.ack_nr = packet.seq_nr();
         }
 
@@ -824,7 +1004,7 @@ impl UtpSocket {
 
         // Update remote peer's delay between them sending the packet and us receiving it
         let now = now_microseconds();
-        self.their_delay = abs_diff(now, packet.timestamp());
+        self.their_delay = wrapping_delay(now, packet.timestamp());
         debug!("self.their_delay: {}", self.their_delay);
 
         match (self.state, packet.get_type()) {
@@ -839,12 +1019,18 @@ impl UtpSocket {
 
                 Ok(Some(self.prepare_reply(packet, PacketType::State)))
             }
+            // The remote peer did not get our reply to the SYN opening the connection
+            (SocketState::Connected, PacketType::Syn)
+                if packet.connection_id().wrapping_add(1) == self.receiver_connection_id => {
+                Ok(Some(self.prepare_reply(packet, PacketType::State)))
+            }
             (_, PacketType::Syn) => Ok(Some(self.prepare_reply(packet, PacketType::Reset))),
             (SocketState::SynSent, PacketType::State) => {
                 self.connected_to = src;
                 self.ack_nr = packet.seq_nr()-1;
                 self.seq_nr += 1;
                 self.state = SocketState::Connected;
+                self.last_dropped = self.ack_nr;
                 self.last_acked = packet.ack_nr();
                 self.last_acked_timestamp = now_microseconds();
                 Ok(None)
@@ -902,6 +1088,15 @@ impl UtpSocket {
     }
 
     fn handle_data_packet(&mut self, packet: &Packet) -> Option<Packet> {
+        // Acknowledge packets received out of order, now that the packets before them arrived
+        for buffered in &self.incoming_buffer {
+            if buffered.seq_nr() == self.ack_nr.wrapping_add(1) {
+                self.ack_nr = buffered.seq_nr();
+            } else if seq_after(buffered.seq_nr(), self.ack_nr) {
+                break;
+            }
+        }
+
         // If a FIN was previously sent, reply with a FIN packet acknowledging the received packet.
         let packet_type = if self.state == SocketState::FinSent {
             PacketType::Fin
@@ -913,65 +1108,18 @@ impl UtpSocket {
         if packet.seq_nr().wrapping_sub(self.ack_nr) > 1 {
             debug!("current ack_nr ({}) is behind received packet seq_nr ({})",
                    self.ack_nr, packet.seq_nr());
+        }
 
-            // Set SACK extension payload if the packet is not in order
-            let sack = self.build_selective_ack();
+        // Set SACK extension payload if packets past a missing one were received
+        let sack = self.build_selective_ack();
 
-            if !sack.is_empty() {
-                reply.set_sack(sack);
-            }
+        if !sack.is_empty() {
+            reply.set_sack(sack);
         }
 
         Some(reply)
     }
 
-    fn queuing_delay(&self) -> Delay {
-        let filtered_current_delay = self.filtered_current_delay();
-        let min_base_delay = self.min_base_delay();
-        let queuing_delay = filtered_current_delay - min_base_delay;
-
-        debug!("filtered_current_delay: {}", filtered_current_delay);
-        debug!("min_base_delay: {}", min_base_delay);
-        debug!("queuing_delay: {}", queuing_delay);
-
-        return queuing_delay;
-    }
-
-    /// Calculates the new congestion window size, increasing it or decreasing it.
-    ///
-    /// This is the core of uTP, the [LEDBAT][ledbat_rfc] congestion algorithm. It depends on
-    /// estimating the queuing delay between the two peers, and adjusting the congestion window
-    /// accordingly.
-    ///
-    /// `off_target` is a normalized value representing the difference between the current queuing
-    /// delay and a fixed target delay (`TARGET`). `off_target` ranges between -1.0 and 1.0. A
-    /// positive value makes the congestion window increase, while a negative value makes the
-    /// congestion window decrease.
-    ///
-    /// `bytes_newly_acked` is the number of bytes acknowledged by an inbound `State` packet. It may
-    /// be the size of the packet explicitly acknowledged by the inbound packet (i.e., with sequence
-    /// number equal to the inbound packet's acknowledgement number), or every packet implicitly
-    /// acknowledged (every packet with sequence number between the previous inbound `State`
-    /// packet's acknowledgement number and the current inbound `State` packet's acknowledgement
-    /// number).
-    ///
-    ///[ledbat_rfc]: https://tools.ietf.org/html/rfc6817
-    fn update_congestion_window(&mut self, off_target: f64, bytes_newly_acked: u32) {
-        let flightsize = self.curr_window;
-
-        let cwnd_increase = GAIN * off_target * bytes_newly_acked as f64 * MSS as f64;
-        let cwnd_increase = cwnd_increase / self.cwnd as f64;
-        debug!("cwnd_increase: {}", cwnd_increase);
-
-        self.cwnd = (self.cwnd as f64 + cwnd_increase) as u32;
-        let max_allowed_cwnd = flightsize + ALLOWED_INCREASE * MSS;
-        self.cwnd = min(self.cwnd, max_allowed_cwnd);
-        self.cwnd = max(self.cwnd, MIN_CWND * MSS);
-
-        debug!("cwnd: {}", self.cwnd);
-        debug!("max_allowed_cwnd: {}", max_allowed_cwnd);
-    }
-
     fn handle_state_packet(&mut self, packet: &Packet) {
 
         //如果 state 的 ack_nr 不增加，则表示发生丢包
@@ -983,6 +1131,21 @@ impl UtpSocket {
             self.duplicate_ack_count = 1;
         }
 
+        let mut lost_packets = Vec::new();
+        let mut has_selective_ack = false;
+        let mut bytes_newly_sacked = 0;
+
+        // Process extensions, if any
+        for extension in packet.extensions() {
+            if extension.get_type() == ExtensionType::SelectiveAck {
+                has_selective_ack = true;
+                bytes_newly_sacked += self.mark_selectively_acked(packet.ack_nr(), &extension);
+                lost_packets.extend(self.selective_ack_losses(packet.ack_nr(), &extension));
+            } else {
+                debug!("Unknown extension {:?}, ignoring", extension.get_type());
+            }
+        }
+
         // Update congestion window size
         if let Some(index) = self.send_window.iter().position(|p| {
             debug!("handle_state_packet : packet.ack_nr() = {:?} , p.seq_nr() = {:?}",packet.ack_nr(),p.seq_nr());
@@ -990,72 +1153,67 @@ impl UtpSocket {
         }) {
             // Calculate the sum of the size of every packet implicitly and explicitly acknowledged
             // by the inbound packet (i.e., every packet whose sequence number precedes the inbound
-            // packet's acknowledgement number, plus the packet whose sequence number matches)
+            // packet's acknowledgement number, plus the packet whose sequence number matches), on
+            // top of the packets it selectively acknowledged for the first time
             let bytes_newly_acked = self.send_window.iter()
                 .take(index + 1)
-                .fold(0, |acc, p| acc + p.len());
-
-            // Update base and current delay
-            let now = now_microseconds();
-            let our_delay = now - self.send_window[index].timestamp();
-            debug!("our_delay: {}", our_delay);
-            self.update_base_delay(our_delay, now);
-            self.update_current_delay(our_delay, now);
-
-            let off_target: f64 = (TARGET - u32::from(self.queuing_delay()) as f64) / TARGET;
-            debug!("off_target: {}", off_target);
-
-            self.update_congestion_window(off_target, bytes_newly_acked as u32);
+                .filter(|p| !self.selectively_acked.contains(&p.seq_nr()))
+                .fold(bytes_newly_sacked as usize, |acc, p| acc + p.len());
 
             // Update congestion timeout
-            let rtt = u32::from(our_delay - self.queuing_delay()) / 1000; // in milliseconds
-            self.update_congestion_timeout(rtt as i32);
+            let now = now_microseconds();
+            let rtt = wrapping_delay(now, self.send_window[index].timestamp());
+            debug!("rtt: {}", rtt);
+            self.update_congestion_timeout((u32::from(rtt) / 1000) as i32); // in milliseconds
+
+            // The remote peer measured the one-way delay of the packet it last received from us,
+            // samples older than one round trip are dropped from the current delay
+            let one_way_delay = u32::from(packet.timestamp_difference());
+            let smoothed_rtt = Delay::from(max(self.rtt, 0) as i64 * 1000);
+            self.congestion.on_ack(one_way_delay, bytes_newly_acked as u32, self.curr_window,
+                                   self.remote_wnd_size, smoothed_rtt, now);
         }
 
-        let mut packet_loss_detected: bool = !self.send_window.is_empty() &&
-                                             self.duplicate_ack_count == 3;
+        // Three duplicate ACKs mean a fast resend request. Resend the first unacknowledged packet
+        // if the incoming packet doesn't have a SACK extension. If it does, the lost packets were
+        // already found from it.
+        if !self.send_window.is_empty() && !has_selective_ack &&
+           self.duplicate_ack_count == DUPLICATE_ACKS_BEFORE_RESEND {
+            lost_packets.push(packet.ack_nr().wrapping_add(1));
+        }
 
-        // Process extensions, if any
-        for extension in packet.extensions() {
-            if extension.get_type() == ExtensionType::SelectiveAck {
-                // If three or more packets are acknowledged past the implicit missing one,
-                // assume it was lost.
-                if extension.iter().count_ones() >= 3 {
-                    self.resend_lost_packet(packet.ack_nr() + 1);
-                    packet_loss_detected = true;
-                }
+        // A cumulative acknowledgement past the probe tells it made it through
+        let probe_acked = self.mtu.probe_seq_nr().map_or(false, |seq_nr| {
+            !seq_after(seq_nr, packet.ack_nr()) && self.send_window_position(seq_nr).is_some()
+        });
+        if probe_acked {
+            self.mtu.on_probe_acked();
+        }
 
-                if let Some(last_seq_nr) = self.send_window.last().map(Packet::seq_nr) {
-                    let lost_packets = extension.iter()
-                        .enumerate()
-                        .filter(|&(_, received)| !received)
-                        .map(|(idx, _)| packet.ack_nr() + 2 + idx as u16)
-                        .take_while(|&seq_nr| seq_nr < last_seq_nr);
-
-                    for seq_nr in lost_packets {
-                        debug!("SACK: packet {} lost", seq_nr);
-                        self.resend_lost_packet(seq_nr);
-                        packet_loss_detected = true;
-                    }
-                }
-            } else {
-                debug!("Unknown extension {:?}, ignoring", extension.get_type());
-            }
+        // Losing the probe tells about the size of the packets the path carries, not about
+        // congestion: the probe is resent without padding, but the window is left as is
+        let probe_seq_nr = self.mtu.probe_seq_nr();
+        if lost_packets.iter().any(|&seq_nr| Some(seq_nr) == probe_seq_nr) {
+            self.mtu.on_probe_lost();
         }
+        self.congestion.set_mss(self.mtu.packet_size());
 
-        // Three duplicate ACKs mean a fast resend request. Resend the first unacknowledged packet
-        // if the incoming packet doesn't have a SACK extension. If it does, the lost packets were
-        // already resent.
-        if !self.send_window.is_empty() && self.duplicate_ack_count == 3 &&
-           !packet.extensions().any(|ext| ext.get_type() == ExtensionType::SelectiveAck) {
-            self.resend_lost_packet(packet.ack_nr() + 1);
+        for &seq_nr in &lost_packets {
+            debug!("packet {} lost", seq_nr);
+            self.resend_lost_packet(seq_nr);
         }
 
-        // Packet lost, halve the congestion window
-        if packet_loss_detected {
+        // Packet lost, halve the congestion window once for the packets in flight at the time
+        let last_sent_seq_nr = self.seq_nr.wrapping_sub(1);
+        let new_loss = lost_packets.iter()
+            .filter(|&&seq_nr| Some(seq_nr) != probe_seq_nr)
+            .any(|&seq_nr| {
+                self.last_loss_seq_nr.map_or(true, |loss_seq_nr| seq_after(seq_nr, loss_seq_nr))
+            });
+        if new_loss {
             debug!("packet loss detected, halving congestion window");
-            self.cwnd = max(self.cwnd / 2, MIN_CWND * MSS);
-            debug!("cwnd: {}", self.cwnd);
+            self.congestion.on_loss();
+            self.last_loss_seq_nr = Some(last_sent_seq_nr);
         }
 
         // Success, advance send window
@@ -1071,13 +1229,18 @@ impl UtpSocket {
     /// Trying to insert a duplicate of a packet will silently fail.
     /// it's more recent (larger timestamp).
     fn insert_into_buffer(&mut self, packet: Packet) {
+        // Sequence numbers wrap around, order packets by how far they come after the last
+        // packet removed from the buffer
+        let last_dropped = self.last_dropped;
+        let distance = |p: &Packet| p.seq_nr().wrapping_sub(last_dropped);
+
         // Immediately push to the end if the packet's sequence number comes after the last
         // packet's.
-        if self.incoming_buffer.last().map_or(false, |p| packet.seq_nr() > p.seq_nr()) {
+        if self.incoming_buffer.last().map_or(false, |p| distance(&packet) > distance(p)) {
             self.incoming_buffer.push(packet);
         } else {
             // Find index following the most recent packet before the one we wish to insert
-            let i = self.incoming_buffer.iter().filter(|p| p.seq_nr() < packet.seq_nr()).count();
+            let i = self.incoming_buffer.iter().filter(|p| distance(p) < distance(&packet)).count();
 
             if self.incoming_buffer.get(i).map_or(true, |p| p.seq_nr() != packet.seq_nr()) {
                 self.incoming_buffer.insert(i, packet);
@@ -1107,7 +1270,7 @@ impl UtpSocket {
     #[warn(unstable_features)]
    pub fn try_clone(&self) -> io::Result<UtpSocket> {
 
-    let mut socket = self.socket.try_clone().expect("UtpSocket clone fail");
+    let socket = self.socket.try_clone()?;
     let mut connected_to = self.connected_to.clone();
     let mut sender_connection_id = self.sender_connection_id;
     let mut receiver_connection_id = self.receiver_connection_id;
@@ -1117,7 +1280,10 @@ impl UtpSocket {
     let mut incoming_buffer = self.incoming_buffer.clone();
     let mut send_window = self.send_window.clone();
     let mut unsent_queue = self.unsent_queue.clone();
+    let selectively_acked = self.selectively_acked.clone();
     let mut duplicate_ack_count = self.duplicate_ack_count.clone();
+    let last_loss_seq_nr = self.last_loss_seq_nr;
+    let timeouts = self.timeouts;
     let mut last_acked = self.last_acked.clone();
     let mut last_acked_timestamp = self.last_acked_timestamp.clone();
     let mut last_dropped = self.last_dropped.clone();
@@ -1126,12 +1292,10 @@ impl UtpSocket {
     let mut rtt_variance = self.rtt_variance.clone();
     let mut curr_window = self.curr_window.clone();
     let mut remote_wnd_size= self.remote_wnd_size.clone();
-    let mut base_delays= self.base_delays.clone();
-    let mut current_delays= self.current_delays.clone();
     let mut their_delay = self.their_delay.clone();
-    let mut last_rollover = self.last_rollover.clone();
     let mut congestion_timeout = self.congestion_timeout.clone();
-    let mut cwnd = self.cwnd.clone();
+    let congestion = self.congestion.clone();
+    let mtu = self.mtu.clone();
     let mut max_retransmission_retries = self.max_retransmission_retries.clone();
 
     let mut utp_socket = UtpSocket {
@@ -1145,7 +1309,10 @@ impl UtpSocket {
             incoming_buffer,
             send_window,
             unsent_queue,
+            selectively_acked,
             duplicate_ack_count,
+            last_loss_seq_nr,
+            timeouts,
             last_acked,
             last_acked_timestamp,
             last_dropped,
@@ -1154,12 +1321,10 @@ impl UtpSocket {
             pending_data,
             curr_window,
             remote_wnd_size,
-            base_delays,
-            current_delays,
             their_delay,
-            last_rollover,
             congestion_timeout,
-            cwnd,
+            congestion,
+            mtu,
             max_retransmission_retries,
         };
 
@@ -1194,6 +1359,9 @@ impl Write for UtpSocket {
 
 /// A structure representing a socket server.
 ///
+/// Every connection the listener accepts or opens shares its UDP socket, packets are handed to the
+/// connection they belong to going by the address of the remote peer and the connection id.
+///
 /// # Examples
 ///
 /// ```no_run
@@ -1218,8 +1386,11 @@ impl Write for UtpSocket {
 /// }
 /// ```
 pub struct UtpListener {
-    /// The public facing UDP socket
-    socket: UdpSocket,
+    /// The public facing UDP socket, shared by every connection
+    mux: Arc<Multiplexer>,
+
+    /// Connections opened by remote peers, not yet accepted
+    accepted: Receiver<Accepted>,
 }
 
 impl UtpListener {
@@ -1232,7 +1403,9 @@ impl UtpListener {
     ///
     /// If more than one valid address is specified, only the first will be used.
     pub fn bind<A: ToSocketAddrs>(addr: A) -> Result<UtpListener> {
-        UdpSocket::bind(addr).and_then(|s| Ok(UtpListener { socket: s }))
+        let (mux, accepted) = take_address(addr).and_then(Multiplexer::bind)?;
+
+        Ok(UtpListener { mux: mux, accepted: accepted })
     }
 
     /// Accepts a new incoming connection from this listener.
@@ -1240,35 +1413,35 @@ impl UtpListener {
     /// This function will block the caller until a new uTP connection is established. When
     /// established, the corresponding `UtpSocket` and the peer's remote address will be returned.
     ///
-    /// Notice that the resulting `UtpSocket` is bound to a different local port than the public
-    /// listening port (which `UtpListener` holds). This may confuse the remote peer!
+    /// The resulting `UtpSocket` sends and receives on the listening port, along with every other
+    /// connection of the listener.
     pub fn accept(&self) -> Result<(UtpSocket, SocketAddr)> {
-        let mut buf = [0; BUF_SIZE];
-
-        self.socket.recv_from(&mut buf).and_then(|(nread, src)| {
-            let packet = Packet::try_from_type(&buf[..nread])?;
-
-            // Ignore non-SYN packets
-            if packet.get_type() != PacketType::Syn {
-                let message = format!("Expected SYN packet, got {:?} instead", packet.get_type());
-                return Err(SocketError::Other(message).into());
-            }
+        let Accepted { syn, src, socket } = self.accepted.recv().map_err(|_| {
+            SocketError::Other("Listener socket closed".to_owned())
+        })?;
+        let mut socket = UtpSocket::from_raw_parts(RawSocket::Mux(socket), src);
+
+        // Establish connection with remote peer
+        if let Some(reply) = socket.handle_packet(&syn, src)? {
+            socket.socket.send_to(reply.as_ref(), src).and(Ok((socket, src)))
+        } else {
+            Err(SocketError::Other("Reached unreachable statement".to_owned()).into())
+        }
+    }
 
-            // The address of the new socket will depend on the type of the listener.
-            let inner_socket = self.socket.local_addr().and_then(|addr| match addr {
-                SocketAddr::V4(_) => UdpSocket::bind("0.0.0.0:0"),
-                SocketAddr::V6(_) => UdpSocket::bind("[::]:0"),
-            });
+    /// Opens a connection to a remote host from the listening port.
+    ///
+    /// The connection shares the socket of the listener with the connections it accepted, which
+    /// lets remote peers connect back to the port we connect from.
+    pub fn connect<A: ToSocketAddrs>(&self, other: A) -> Result<UtpSocket> {
+        let addr = take_address(other)?;
+        let (socket, receiver_id) = Multiplexer::register(&self.mux, addr);
 
-            let mut socket = inner_socket.map(|s| UtpSocket::from_raw_parts(s, src))?;
+        let mut socket = UtpSocket::from_raw_parts(RawSocket::Mux(socket), addr);
+        socket.receiver_connection_id = receiver_id;
+        socket.sender_connection_id = receiver_id.wrapping_add(1);
 
-            // Establish connection with remote peer
-            if let Ok(Some(reply)) = socket.handle_packet(&packet, src) {
-                socket.socket.send_to(reply.as_ref(), src).and(Ok((socket, src)))
-            } else {
-                Err(SocketError::Other("Reached unreachable statement".to_owned()).into())
-            }
-        })
+        socket.establish()
     }
 
     /// Returns an iterator over the connections being received by this listener.
@@ -1280,7 +1453,7 @@ impl UtpListener {
 
     /// Returns the local socket address of this listener.
     pub fn local_addr(&self) -> Result<SocketAddr> {
-        self.socket.local_addr()
+        self.mux.local_addr()
     }
 }
 
@@ -1302,9 +1475,13 @@ mod test {
     use std::net::ToSocketAddrs;
     use std::io::ErrorKind;
     use rand;
-    use crate::utp::socket::{UtpSocket, UtpListener, SocketState, BUF_SIZE, take_address};
+    use std::time::Duration;
+    use std::net::{SocketAddr, UdpSocket};
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use crate::utp::socket::{UtpSocket, UtpListener, SocketState, BUF_SIZE, WINDOW_SIZE, take_address};
     use crate::utp::packet::*;
-    use crate::utp::time::now_microseconds;
+    use crate::utp::time::{now_microseconds, Timestamp};
 
     macro_rules! iotry {
         ($e:expr) => (match $e { Ok(e) => e, Err(e) => panic!("{:?}", e) })
@@ -1740,6 +1917,8 @@ mod test {
 
         let child = thread::spawn(move || {
             let mut client = iotry!(UtpSocket::connect(server_addr));
+            // Send the data in a single packet, which is never padded to probe the path
+            client.set_packet_size(BUF_SIZE as u32);
             iotry!(client.send_to(&d[..]));
             iotry!(client.close());
         });
@@ -1949,6 +2128,207 @@ mod test {
         assert!(child.join().is_ok());
     }
 
+    #[test]
+    fn test_selective_ack_bitmask() {
+        let mut socket = iotry!(UtpSocket::bind(next_test_ip4()));
+        socket.ack_nr = 65533;
+        socket.last_dropped = 65533;
+
+        // Nothing received past a missing packet
+        assert!(socket.build_selective_ack().is_empty());
+
+        // Packets 65535 and 0 to 2 arrived, 65534 is missing
+        for &seq_nr in &[1u16, 65535, 2, 0] {
+            let mut packet = Packet::with_payload(&[seq_nr as u8]);
+            packet.set_seq_nr(seq_nr);
+            socket.insert_into_buffer(packet);
+        }
+        assert_eq!(socket.incoming_buffer.iter().map(Packet::seq_nr).collect::<Vec<_>>(),
+                   vec![65535, 0, 1, 2]);
+        assert_eq!(socket.build_selective_ack(), vec![0b1111, 0, 0, 0]);
+
+        // Packets past what the bitmask holds are left out
+        let mut packet = Packet::with_payload(&[0]);
+        packet.set_seq_nr(65533u16.wrapping_add(2 + 252 * 8));
+        socket.insert_into_buffer(packet);
+        let sack = socket.build_selective_ack();
+        assert_eq!(sack.len(), 4);
+    }
+
+    #[test]
+    fn test_selective_ack_losses() {
+        let mut socket = iotry!(UtpSocket::bind(next_test_ip4()));
+
+        // Packets 10 to 17 in flight, sent in order
+        for seq_nr in 10..18u16 {
+            let mut packet = Packet::with_payload(&[0; 10]);
+            packet.set_seq_nr(seq_nr);
+            packet.set_timestamp(Timestamp(1000 + seq_nr as u32));
+            socket.send_window.push(packet);
+        }
+
+        // Packet 9 acknowledged, 10 is implicitly missing, 11 and 14 are missing as well
+        let mut ack = Packet::new();
+        ack.set_type(PacketType::State);
+        ack.set_ack_nr(9);
+        ack.set_sack(vec![0b0011_0110, 0, 0, 0]);
+        let extension = ack.extensions().next().unwrap();
+        // Packet 14 only has two packets received past it
+        assert_eq!(socket.selective_ack_losses(9, &extension), vec![10, 11]);
+
+        // Packets resent right away are not lost again until a packet sent after them is received
+        for seq_nr in 10..12usize {
+            socket.send_window[seq_nr - 10].set_timestamp(Timestamp(2000));
+        }
+        assert!(socket.selective_ack_losses(9, &extension).is_empty());
+        socket.send_window[7].set_timestamp(Timestamp(3000));
+        let mut ack = Packet::new();
+        ack.set_ack_nr(9);
+        ack.set_sack(vec![0b0111_0110, 0, 0, 0]);
+        let extension = ack.extensions().next().unwrap();
+        assert_eq!(socket.selective_ack_losses(9, &extension), vec![10, 11, 14]);
+
+        socket.state = SocketState::Closed;
+    }
+
+    /// Forwards packets between a client and a server, dropping every `drop_every`th packet on its
+    /// way to the server, until the returned flag is set.
+    fn lossy_pipe<F>(server_addr: SocketAddr, mut drop_packet: F) -> (SocketAddr, Arc<AtomicBool>)
+        where F: FnMut(usize, &[u8]) -> bool + Send + 'static {
+        let pipe = iotry!(UdpSocket::bind(next_test_ip4()));
+        let pipe_addr = iotry!(pipe.local_addr());
+        let stop = Arc::new(AtomicBool::new(false));
+        iotry!(pipe.set_read_timeout(Some(Duration::from_millis(50))));
+
+        let stop_pipe = stop.clone();
+        thread::spawn(move || {
+            let mut buf = [0; BUF_SIZE + HEADER_SIZE];
+            let mut client_addr = None;
+            let mut sent_to_server = 0;
+
+            while !stop_pipe.load(Ordering::Relaxed) {
+                let (read, src) = match pipe.recv_from(&mut buf) {
+                    Ok(received) => received,
+                    Err(_) => continue,
+                };
+
+                if src == server_addr {
+                    if let Some(client_addr) = client_addr {
+                        let _ = pipe.send_to(&buf[..read], client_addr);
+                    }
+                } else {
+                    client_addr = Some(src);
+                    sent_to_server += 1;
+
+                    if !drop_packet(sent_to_server, &buf[..read]) {
+                        let _ = pipe.send_to(&buf[..read], server_addr);
+                    }
+                }
+            }
+        });
+
+        (pipe_addr, stop)
+    }
+
+    #[test]
+    fn test_transfer_through_lossy_pipe() {
+        let server_addr = next_test_ip4().to_socket_addrs().unwrap().next().unwrap();
+        let mut server = iotry!(UtpSocket::bind(server_addr));
+        let (pipe_addr, stop) = lossy_pipe(server_addr, |sent, _| sent % 10 == 0);
+
+        // One MiB, followed by some more so that losses at the end of it are found from the
+        // packets received after them
+        const LEN: usize = 1024 * 1024;
+        const TAIL_LEN: usize = 64 * 1024;
+        let data = (0..LEN + TAIL_LEN).map(|idx| (idx % 251) as u8).collect::<Vec<u8>>();
+        let to_send = data.clone();
+
+        let child = thread::spawn(move || {
+            let mut client = iotry!(UtpSocket::connect(pipe_addr));
+            iotry!(client.send_to(&to_send[..LEN]));
+            iotry!(client.send_to(&to_send[LEN..]));
+            let stats = client.stats();
+
+            // Losses in the tail may take a timeout to recover from
+            let _ = client.close();
+            stats
+        });
+
+        let mut buf = [0; BUF_SIZE];
+        let mut received: Vec<u8> = vec![];
+        loop {
+            match server.recv_from(&mut buf) {
+                Ok((0, _src)) => break,
+                Ok((len, _src)) => received.extend(buf[..len].to_vec()),
+                Err(e) => panic!("{:?}", e),
+            }
+        }
+        assert_eq!(received.len(), data.len());
+        assert!(received == data);
+
+        // Holes were filled through selective acks, without waiting for a timeout
+        let stats = iotry!(child.join().map_err(|_| "client panicked"));
+        assert_eq!(stats.timeouts(), 0);
+
+        stop.store(true, Ordering::Relaxed);
+    }
+
+    #[test]
+    fn test_packet_size_follows_path_mtu() {
+        let server_addr = next_test_ip4().to_socket_addrs().unwrap().next().unwrap();
+        let mut server = iotry!(UtpSocket::bind(server_addr));
+        // The path does not carry packets larger than 1100 bytes
+        const PATH_MTU: usize = 1100;
+        let (pipe_addr, stop) = lossy_pipe(server_addr, |_, packet| packet.len() > PATH_MTU);
+
+        const LEN: usize = 256 * 1024;
+        let data = (0..LEN).map(|idx| (idx % 251) as u8).collect::<Vec<u8>>();
+        let to_send = data.clone();
+
+        let child = thread::spawn(move || {
+            let mut client = iotry!(UtpSocket::connect(pipe_addr));
+            assert_eq!(client.stats().mss(), 548);
+
+            iotry!(client.send_to(&to_send[..]));
+            let stats = client.stats();
+            iotry!(client.close());
+            stats
+        });
+
+        let mut buf = [0; BUF_SIZE];
+        let mut received: Vec<u8> = vec![];
+        loop {
+            match server.recv_from(&mut buf) {
+                Ok((0, _src)) => break,
+                Ok((len, _src)) => received.extend(buf[..len].to_vec()),
+                Err(e) => panic!("{:?}", e),
+            }
+        }
+        assert!(received == data);
+
+        // Packets grew as large as the path carries
+        let stats = iotry!(child.join().map_err(|_| "client panicked"));
+        assert!(stats.mss() as usize <= PATH_MTU && stats.mss() as usize > PATH_MTU - 16);
+        assert!(stats.congestion_window() >= 2 * stats.mss());
+
+        stop.store(true, Ordering::Relaxed);
+    }
+
+    #[test]
+    fn test_pinned_packet_size() {
+        let mut socket = iotry!(UtpSocket::bind(next_test_ip4()));
+
+        socket.set_packet_size(1200);
+        assert_eq!(socket.stats().mss(), 1200);
+
+        socket.set_packet_size(100);
+        assert_eq!(socket.stats().mss(), 548);
+
+        socket.set_max_packet_size(100);
+        socket.set_packet_size(9000);
+        assert_eq!(socket.stats().mss(), 1500);
+    }
+
     #[test]
     fn test_correct_packet_loss() {
         let server_addr = next_test_ip4();
@@ -2247,23 +2627,64 @@ mod test {
     }
 
     #[test]
-    fn test_base_delay_calculation() {
-        let minute_in_microseconds = 60 * 10i64.pow(6);
-        let samples = vec![(0, 10), (1, 8), (2, 12), (3, 7),
-                           (minute_in_microseconds + 1, 11),
-                           (minute_in_microseconds + 2, 19),
-                           (minute_in_microseconds + 3, 9)];
-        let addr = next_test_ip4();
-        let mut socket = UtpSocket::bind(addr).unwrap();
+    fn test_stats_window_shrinks_with_delay() {
+        let initial_connection_id: u16 = rand::random();
+        let client_addr = next_test_ip4().to_socket_addrs().unwrap().next().unwrap();
+        let mut socket = iotry!(UtpSocket::bind(next_test_ip4()));
+        socket.set_target_delay(Duration::from_millis(50));
 
-        for (timestamp, delay) in samples {
-            socket.update_base_delay(delay.into(), ((timestamp + delay) as u32).into());
+        // Establish connection
+        let mut packet = Packet::new();
+        packet.set_wnd_size(WINDOW_SIZE);
+        packet.set_type(PacketType::Syn);
+        packet.set_connection_id(initial_connection_id);
+        assert!(iotry!(socket.handle_packet(&packet, client_addr)).is_some());
+
+        // Data packets waiting for an acknowledgement
+        let first_seq_nr = socket.seq_nr;
+        for _ in 0..40 {
+            let mut data = Packet::with_payload(&[0; 1000]);
+            data.set_seq_nr(socket.seq_nr);
+            data.set_timestamp(now_microseconds());
+            socket.curr_window += data.len() as u32;
+            socket.send_window.push(data);
+            socket.seq_nr = socket.seq_nr.wrapping_add(1);
         }
 
-        let expected = vec![7i64, 9i64].into_iter().map(Into::into).collect::<Vec<_>>();
-        let actual = socket.base_delays.iter().cloned().collect::<Vec<_>>();
-        assert_eq!(expected, actual);
-        assert_eq!(socket.min_base_delay(), expected.iter().min().cloned().unwrap_or_default());
+        // Acknowledge each packet, the one-way delay grows from 20 ms to 120 ms half way through
+        for idx in 0..40u16 {
+            let mut ack = Packet::new();
+            ack.set_wnd_size(WINDOW_SIZE);
+            ack.set_type(PacketType::State);
+            ack.set_connection_id(initial_connection_id);
+            ack.set_seq_nr(packet.seq_nr());
+            ack.set_ack_nr(first_seq_nr.wrapping_add(idx));
+            let delay: u32 = if idx < 20 { 20_000 } else { 120_000 };
+            ack.set_timestamp_difference(delay.into());
+
+            let before = socket.stats();
+            assert!(iotry!(socket.handle_packet(&ack, client_addr)).is_none());
+            let after = socket.stats();
+
+            if idx == 0 {
+                continue;
+            } else if idx < 20 {
+                assert!(after.congestion_window() > before.congestion_window());
+                assert_eq!(after.queuing_delay(), Duration::from_millis(0));
+            } else if after.congestion_window() > 2 * 1400 {
+                assert!(after.congestion_window() < before.congestion_window());
+            }
+        }
+
+        let stats = socket.stats();
+        assert_eq!(stats.base_delay(), Duration::from_millis(20));
+        assert_eq!(stats.queuing_delay(), Duration::from_millis(100));
+        assert_eq!(stats.target_delay(), Duration::from_millis(50));
+        assert_eq!(stats.remote_window(), WINDOW_SIZE);
+        assert_eq!(stats.bytes_in_flight(), 0);
+
+        // Nobody to close the connection with
+        socket.state = SocketState::Closed;
     }
 
     #[test]
@@ -2286,6 +2707,76 @@ mod test {
         assert_eq!(listener.local_addr().unwrap(), addr);
     }
 
+    #[test]
+    fn test_listener_multiplexes_connections() {
+        let server_addr = next_test_ip4().to_socket_addrs().unwrap().next().unwrap();
+        let other_addr = next_test_ip4().to_socket_addrs().unwrap().next().unwrap();
+        let listener = iotry!(UtpListener::bind(server_addr));
+        let other = iotry!(UtpListener::bind(other_addr));
+
+        // Connections from several clients at once
+        const CLIENTS: u8 = 3;
+        const LEN: usize = 10_000;
+        let clients = (0..CLIENTS).map(|id| thread::spawn(move || {
+            let mut client = iotry!(UtpSocket::connect(server_addr));
+            let data = vec![id; LEN];
+            iotry!(client.send_to(&data));
+
+            let mut buf = [0; BUF_SIZE];
+            let mut received = vec![];
+            while received.len() < LEN {
+                let (read, _src) = iotry!(client.recv_from(&mut buf));
+                received.extend_from_slice(&buf[..read]);
+            }
+            assert_eq!(received, data);
+            iotry!(client.close());
+        })).collect::<Vec<_>>();
+
+        // A connection to another listener, from the listening port
+        let peer = thread::spawn(move || {
+            let (mut socket, src) = iotry!(other.accept());
+            assert_eq!(src, server_addr);
+
+            let mut buf = [0; BUF_SIZE];
+            let mut received = vec![];
+            loop {
+                match socket.recv_from(&mut buf) {
+                    Ok((0, _src)) => break,
+                    Ok((read, _src)) => received.extend_from_slice(&buf[..read]),
+                    Err(e) => panic!("{:?}", e),
+                }
+            }
+            received
+        });
+        let mut outgoing = iotry!(listener.connect(other_addr));
+        iotry!(outgoing.send_to(b"hello"));
+        iotry!(outgoing.close());
+        assert_eq!(iotry!(peer.join().map_err(|_| "peer panicked")), b"hello");
+
+        // Every accepted connection echoes what it receives, over the listening port
+        let servers = (0..CLIENTS).map(|_| {
+            let (mut socket, _src) = iotry!(listener.accept());
+            assert_eq!(iotry!(socket.local_addr()), server_addr);
+
+            thread::spawn(move || {
+                let mut buf = [0; BUF_SIZE];
+                let mut received = vec![];
+                while received.len() < LEN {
+                    let (read, _src) = iotry!(socket.recv_from(&mut buf));
+                    received.extend_from_slice(&buf[..read]);
+                }
+                iotry!(socket.send_to(&received));
+
+                // Wait for the client to close the connection
+                while iotry!(socket.recv_from(&mut buf)).0 > 0 {}
+            })
+        }).collect::<Vec<_>>();
+
+        for handle in clients.into_iter().chain(servers) {
+            assert!(handle.join().is_ok());
+        }
+    }
+
     #[test]
     fn test_peer_addr() {
         use std::sync::mpsc::channel;
@@ -1,6 +1,7 @@
 use std::io::{Read, Write, Result};
 use std::net::{ToSocketAddrs, SocketAddr};
-use super::socket::UtpSocket;
+use std::time::Duration;
+use super::socket::{UtpSocket, UtpStats};
 
 /// A structure that represents a uTP (Micro Transport Protocol) stream between a local socket and a
 /// remote socket.
@@ -65,6 +66,26 @@ impl UtpStream {
     pub fn set_max_retransmission_retries(&mut self, n: u32) {
         self.socket.max_retransmission_retries = n;
     }
+
+    /// Changes the queuing delay the congestion control of the underlying socket aims for.
+    pub fn set_target_delay(&mut self, target: Duration) {
+        self.socket.set_target_delay(target);
+    }
+
+    /// Changes the largest packet size MTU discovery probes for on the underlying socket.
+    pub fn set_max_packet_size(&mut self, size: u32) {
+        self.socket.set_max_packet_size(size);
+    }
+
+    /// Pins the size of the packets the underlying socket sends, disabling MTU discovery.
+    pub fn set_packet_size(&mut self, size: u32) {
+        self.socket.set_packet_size(size);
+    }
+
+    /// Returns statistics on the congestion control of the underlying socket.
+    pub fn stats(&self) -> UtpStats {
+        self.socket.stats()
+    }
 }
 
 impl Read for UtpStream {
@@ -14,6 +14,11 @@ pub fn now_microseconds() -> Timestamp {
 #[derive(Debug, Clone, Copy, PartialOrd, PartialEq)]
 pub struct Timestamp(pub u32);
 
+/// Return the delay between two timestamps, wrapping around like the clocks of both peers.
+pub fn wrapping_delay(later: Timestamp, earlier: Timestamp) -> Delay {
+    later.0.wrapping_sub(earlier.0).into()
+}
+
 impl Sub for Timestamp {
     type Output = Delay;
 
@@ -1,4 +1,3 @@
-use std::ops::Sub;
 use num_traits::ToPrimitive;
 use rand::{self, Rng};
 
@@ -13,15 +12,6 @@ pub fn ewma<'a, T, I>(mut samples: I, alpha: f64) -> f64
            .fold(first, |avg, sample| alpha * sample + (1.0 - alpha) * avg)
 }
 
-/// Returns the absolute difference between two values.
-pub fn abs_diff<T: PartialOrd + Sub<Output = U>, U>(a: T, b: T) -> U {
-    if a > b {
-        a - b
-    } else {
-        b - a
-    }
-}
-
 /// Safely generates two sequential connection identifiers.
 ///
 /// This avoids an overflow when the generated receiver identifier is the largest
@@ -71,12 +61,4 @@ mod test {
                         158488.0 / 19683.0];
         assert_eq!(ewma(input.iter(), alpha), expected[expected.len() - 1]);
     }
-
-    #[test]
-    fn test_abs_diff() {
-        let a = 10;
-        let b = 5;
-        assert_eq!(abs_diff(a, b), 5);
-        assert_eq!(abs_diff(b, a), 5);
-    }
 }
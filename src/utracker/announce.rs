@@ -74,7 +74,7 @@ impl<'a> AnnounceRequest<'a> {
     where
         W: Write,
     {
-        writer.write_all(self.info_hash.as_ref())?;
+        writer.write_all(&self.info_hash.truncated())?;
         writer.write_all(self.peer_id.as_ref())?;
 
         self.state.write_bytes(&mut writer)?;
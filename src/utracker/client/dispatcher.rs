@@ -10,6 +10,7 @@ use rand::{self};
 use umio::external::{self, Timeout};
 use umio::{Dispatcher, ELoopBuilder, Provider};
 
+use crate::util::proxy::{self, ProxyConfig, UdpAssociation};
 use crate::utracker::announce::{AnnounceRequest, DesiredPeers, SourceIP};
 use crate::utracker::client::RequestLimiter;
 use crate::utracker::option::AnnounceOptions;
@@ -25,6 +26,7 @@ const EXPECTED_PACKET_LENGTH: usize = 1500;
 
 const CONNECTION_ID_VALID_DURATION_MILLIS: i64 = 60000;
 const MAXIMUM_REQUEST_RETRANSMIT_ATTEMPTS: u64 = 8;
+const REQUEST_TIMEOUT_BASE_MILLIS: u64 = 15000;
 
 /// Internal dispatch timeout.
 enum DispatchTimeout {
@@ -41,28 +43,65 @@ pub enum DispatchMessage {
 
 /// Create a new background dispatcher to execute request and send responses back.
 ///
+/// If a proxy is given, packets are relayed by it instead of sent to trackers directly.
+///
 /// Assumes msg_capacity is less than usize::max_value().
 pub fn create_dispatcher<H>(
     bind: SocketAddr,
     handshaker: H,
     msg_capacity: usize,
     limiter: RequestLimiter,
+    opt_proxy: Option<&ProxyConfig>,
 ) -> io::Result<external::Sender<DispatchMessage>>
 where
     H: Handshaker + 'static,
     H::Metadata: From<ClientMetadata>,
 {
+    let opt_relay = match opt_proxy {
+        Some(proxy) => Some(UdpAssociation::new(proxy, bind)?),
+        None => None,
+    };
+
+    spawn_dispatcher(
+        bind,
+        handshaker,
+        msg_capacity,
+        limiter,
+        opt_relay,
+        REQUEST_TIMEOUT_BASE_MILLIS,
+    )
+}
+
+/// Spawn the dispatcher, retransmitting requests after timeout_base * 2 ^ n milliseconds.
+fn spawn_dispatcher<H>(
+    bind: SocketAddr,
+    handshaker: H,
+    msg_capacity: usize,
+    limiter: RequestLimiter,
+    opt_relay: Option<UdpAssociation>,
+    timeout_base: u64,
+) -> io::Result<external::Sender<DispatchMessage>>
+where
+    H: Handshaker + 'static,
+    H::Metadata: From<ClientMetadata>,
+{
+    // Relayed packets are prefixed with the address of the tracker they are for
+    let buffer_length = match opt_relay {
+        Some(_) => EXPECTED_PACKET_LENGTH + proxy::MAX_UDP_HEADER_LEN,
+        None => EXPECTED_PACKET_LENGTH,
+    };
+
     // Timer capacity is plus one for the cache cleanup timer
     let builder = ELoopBuilder::new()
         .channel_capacity(msg_capacity)
         .timer_capacity(msg_capacity + 1)
         .bind_address(bind)
-        .buffer_length(EXPECTED_PACKET_LENGTH);
+        .buffer_length(buffer_length);
 
     let mut eloop = builder.build()?;
     let channel = eloop.channel();
 
-    let dispatch = ClientDispatcher::new(handshaker, bind, limiter);
+    let dispatch = ClientDispatcher::new(handshaker, bind, limiter, opt_relay, timeout_base);
 
     thread::spawn(move || {
         eloop
@@ -89,6 +128,8 @@ where
     active_requests: HashMap<ClientToken, ConnectTimer>,
     id_cache: ConnectIdCache,
     limiter: RequestLimiter,
+    opt_relay: Option<UdpAssociation>,
+    timeout_base: u64,
 }
 
 impl<H> ClientDispatcher<H>
@@ -97,13 +138,21 @@ where
     H::Metadata: From<ClientMetadata>,
 {
     /// Create a new ClientDispatcher.
-    pub fn new(handshaker: H, bind: SocketAddr, limiter: RequestLimiter) -> ClientDispatcher<H> {
+    pub fn new(
+        handshaker: H,
+        bind: SocketAddr,
+        limiter: RequestLimiter,
+        opt_relay: Option<UdpAssociation>,
+        timeout_base: u64,
+    ) -> ClientDispatcher<H> {
         ClientDispatcher {
             handshaker: handshaker,
             bound_addr: bind,
             active_requests: HashMap::new(),
             id_cache: ConnectIdCache::new(),
             limiter: limiter,
+            opt_relay: opt_relay,
+            timeout_base: timeout_base,
         }
     }
 
@@ -142,8 +191,10 @@ where
         token: ClientToken,
         request: ClientRequest,
     ) {
-        // Check for IP version mismatch between source addr and dest addr
-        match (self.bound_addr, addr) {
+        // Check for IP version mismatch between source addr and dest addr, relayed packets
+        // can be for trackers of either version
+        let dest_addr = self.opt_relay.as_ref().map_or(addr, UdpAssociation::relay);
+        match (self.bound_addr, dest_addr) {
             (SocketAddr::V4(_), SocketAddr::V6(_)) | (SocketAddr::V6(_), SocketAddr::V4(_)) => {
                 self.notify_client(token, Err(ClientError::IPVersionMismatch));
 
@@ -152,7 +203,7 @@ where
             _ => (),
         };
         self.active_requests
-            .insert(token, ConnectTimer::new(addr, request));
+            .insert(token, ConnectTimer::new(addr, request, self.timeout_base));
 
         self.process_request(provider, token, false);
     }
@@ -168,16 +219,25 @@ where
             token: response.transaction_id(),
         };
 
-        let conn_timer = if let Some(conn_timer) = self.active_requests.remove(&token) {
-            if conn_timer.message_params().0 == addr {
-                conn_timer
-            } else {
-                return;
-            } // TODO: Add Logging (Server Receive Addr Different Than Send Addr)
-        } else {
-            return;
-        }; // TODO: Add Logging (Server Gave Us Invalid Transaction Id)
+        // Responses to unknown transactions or from other addresses are not ours to answer,
+        // the request keeps waiting on its own timeout
+        let sent_id = match self.active_requests.get(&token) {
+            Some(conn_timer) if conn_timer.message_params().0 == addr => conn_timer.sent_id(),
+            _ => return, // TODO: Add Logging
+        };
+
+        // A connect is only answered by a connection id and a request only by its response,
+        // although either can be answered with an error
+        let is_connect = matches!(response.response_type(), &ResponseType::Connect(_));
+        let is_error = matches!(response.response_type(), &ResponseType::Error(_));
+        if !is_error && is_connect != sent_id.is_none() {
+            return; // TODO: Add Logging
+        }
 
+        let mut conn_timer = self
+            .active_requests
+            .remove(&token)
+            .expect("bittorrent-protocol_utracker: Failed To Find Active Request");
         provider.clear_timeout(
             conn_timer
                 .timeout_id()
@@ -187,9 +247,23 @@ where
         // Check if the response requires us to update the connection timer
         if let &ResponseType::Connect(id) = response.response_type() {
             self.id_cache.put(addr, id);
+            conn_timer.set_connected();
 
             self.active_requests.insert(token, conn_timer);
             self.process_request(provider, token, false);
+        } else if let (Some(id), &ResponseType::Error(ref res)) =
+            (sent_id, response.response_type())
+        {
+            // Trackers answer stale connection ids with an error, so never reuse the id, and
+            // retry once with a fresh id unless this request already connected by itself
+            self.id_cache.remove(addr, id);
+
+            if conn_timer.connected() {
+                self.notify_client(token, Err(ClientError::ServerMessage(res.to_owned())));
+            } else {
+                self.active_requests.insert(token, conn_timer);
+                self.process_request(provider, token, false);
+            }
         } else {
             // Match the request type against the response type and update our client
             match (conn_timer.message_params().1, response.response_type()) {
@@ -204,6 +278,12 @@ where
                 (&ClientRequest::Scrape(..), &ResponseType::Scrape(ref res)) => {
                     self.notify_client(token, Ok(ClientResponse::Scrape(res.to_owned())));
                 }
+                (&ClientRequest::ScrapeBatch(ref hashes), &ResponseType::Scrape(ref res)) => {
+                    // Stats are in the order of our hashes, trailing hashes may be left out
+                    let stats = hashes.iter().cloned().zip(res.iter()).collect();
+
+                    self.notify_client(token, Ok(ClientResponse::ScrapeBatch(stats)));
+                }
                 (_, &ResponseType::Error(ref res)) => {
                     self.notify_client(token, Err(ClientError::ServerMessage(res.to_owned())));
                 }
@@ -245,10 +325,8 @@ where
         // Resolve the type of request we need to make
         let (conn_id, request_type) = match (opt_conn_id, conn_timer.message_params().1) {
             (Some(id), &ClientRequest::Announce(hash, state)) => {
-                let source_ip = match addr {
-                    SocketAddr::V4(_) => SourceIP::ImpliedV4,
-                    SocketAddr::V6(_) => SourceIP::ImpliedV6,
-                };
+                // As in BEP 15, trackers reached over IPv6 get the same announce as IPv4 ones
+                let source_ip = SourceIP::ImpliedV4;
                 let key = rand::random::<u32>();
 
                 (
@@ -271,18 +349,32 @@ where
 
                 (id, RequestType::Scrape(scrape_request))
             }
+            (Some(id), &ClientRequest::ScrapeBatch(ref hashes)) => {
+                let mut scrape_request = ScrapeRequest::new();
+                for &hash in hashes.iter() {
+                    scrape_request.insert(hash);
+                }
+
+                (id, RequestType::Scrape(scrape_request))
+            }
             (None, _) => (request::CONNECT_ID_PROTOCOL_ID, RequestType::Connect),
         };
+        conn_timer.set_sent_id(opt_conn_id);
         let tracker_request = TrackerRequest::new(conn_id, token.token, request_type);
 
-        // Try to write the request out to the server
+        // Try to write the request out to the server, or to the relay of our proxy
         let mut write_success = false;
+        let opt_relay_addr = self.opt_relay.as_ref().map(UdpAssociation::relay);
         provider.outgoing(|bytes| {
             let mut writer = Cursor::new(bytes);
-            write_success = tracker_request.write_bytes(&mut writer).is_ok();
+            let header_written = match opt_relay_addr {
+                Some(_) => proxy::write_udp_header(addr, &mut writer).is_ok(),
+                None => true,
+            };
+            write_success = header_written && tracker_request.write_bytes(&mut writer).is_ok();
 
             if write_success {
-                Some((writer.position() as usize, addr))
+                Some((writer.position() as usize, opt_relay_addr.unwrap_or(addr)))
             } else {
                 None
             }
@@ -312,7 +404,21 @@ where
     type Message = DispatchMessage;
 
     fn incoming<'a>(&mut self, mut provider: Provider<'a, Self>, message: &[u8], addr: SocketAddr) {
-        let response = match TrackerResponse::from_bytes(message) {
+        // Behind a proxy, only packets relayed from trackers are expected
+        let (message, addr) = match self.opt_relay {
+            Some(ref relay) if relay.relay() == addr => match proxy::parse_udp_packet(message) {
+                Some((tracker_addr, payload)) => (payload, tracker_addr),
+                None => return, // TODO: Add Logging
+            },
+            Some(_) => return, // TODO: Add Logging
+            None => (message, addr),
+        };
+
+        let parse_result = match addr {
+            SocketAddr::V4(_) => TrackerResponse::from_bytes(message),
+            SocketAddr::V6(_) => TrackerResponse::from_bytes_v6(message),
+        };
+        let response = match parse_result {
             IResult::Done(_, rsp) => rsp,
             _ => return, // TODO: Add Logging
         };
@@ -341,7 +447,9 @@ where
                         DispatchTimeout::CleanUp,
                         CONNECTION_ID_VALID_DURATION_MILLIS as u64,
                     )
-                    .expect("bittorrent-protocol_utracker: Failed To Restart Connect Id Cleanup Timer");
+                    .expect(
+                        "bittorrent-protocol_utracker: Failed To Restart Connect Id Cleanup Timer",
+                    );
             }
         };
     }
@@ -354,18 +462,24 @@ where
 struct ConnectTimer {
     addr: SocketAddr,
     attempt: u64,
+    base: u64,
     request: ClientRequest,
     timeout_id: Option<Timeout>,
+    sent_id: Option<u64>,
+    connected: bool,
 }
 
 impl ConnectTimer {
     /// Create a new ConnectTimer.
-    pub fn new(addr: SocketAddr, request: ClientRequest) -> ConnectTimer {
+    pub fn new(addr: SocketAddr, request: ClientRequest, base: u64) -> ConnectTimer {
         ConnectTimer {
             addr: addr,
             attempt: 0,
+            base: base,
             request: request,
             timeout_id: None,
+            sent_id: None,
+            connected: false,
         }
     }
 
@@ -378,7 +492,7 @@ impl ConnectTimer {
                 self.attempt += 1;
             }
 
-            Some(calculate_message_timeout_millis(self.attempt))
+            Some(calculate_message_timeout_millis(self.base, self.attempt))
         }
     }
 
@@ -392,15 +506,35 @@ impl ConnectTimer {
         self.timeout_id = Some(id);
     }
 
+    /// Yields the connection id the last request was sent with, None if a connect was sent.
+    pub fn sent_id(&self) -> Option<u64> {
+        self.sent_id
+    }
+
+    /// Sets the connection id the last request was sent with.
+    pub fn set_sent_id(&mut self, opt_id: Option<u64>) {
+        self.sent_id = opt_id;
+    }
+
+    /// Returns true if a connection id was received for this request.
+    pub fn connected(&self) -> bool {
+        self.connected
+    }
+
+    /// Marks that a connection id was received for this request.
+    pub fn set_connected(&mut self) {
+        self.connected = true;
+    }
+
     /// Yields the message parameters for the current connection.
     pub fn message_params(&self) -> (SocketAddr, &ClientRequest) {
         (self.addr, &self.request)
     }
 }
 
-/// Calculates the timeout for the request given the attempt count.
-fn calculate_message_timeout_millis(attempt: u64) -> u64 {
-    (15 * 2u64.pow(attempt as u32)) * 1000
+/// Calculates the timeout for the request given the base timeout and attempt count.
+fn calculate_message_timeout_millis(base: u64, attempt: u64) -> u64 {
+    base * 2u64.pow(attempt as u32)
 }
 
 //----------------------------------------------------------------------------//
@@ -444,30 +578,21 @@ impl ConnectIdCache {
         self.cache.insert(addr, (connect_id, curr_time));
     }
 
+    /// Remove the connection id for the given addr, if it is still the one cached.
+    fn remove(&mut self, addr: SocketAddr, connect_id: u64) {
+        if let Entry::Occupied(occ) = self.cache.entry(addr) {
+            if occ.get().0 == connect_id {
+                occ.remove();
+            }
+        }
+    }
+
     /// Removes all entries that have expired.
     fn clean_expired(&mut self) {
         let curr_time = Utc::now();
-        let mut curr_index = 0;
-
-        let mut opt_curr_entry = self
-            .cache
-            .iter()
-            .skip(curr_index)
-            .map(|(&k, &v)| (k, v))
-            .next();
-        while let Some((addr, (_, prev_time))) = opt_curr_entry.take() {
-            if is_expired(curr_time, prev_time) {
-                self.cache.remove(&addr);
-            }
 
-            curr_index += 1;
-            opt_curr_entry = self
-                .cache
-                .iter()
-                .skip(curr_index)
-                .map(|(&k, &v)| (k, v))
-                .next();
-        }
+        self.cache
+            .retain(|_, &mut (_, prev_time)| !is_expired(curr_time, prev_time));
     }
 }
 
@@ -477,3 +602,459 @@ fn is_expired(curr_time: DateTime<Utc>, prev_time: DateTime<Utc>) -> bool {
 
     curr_time - prev_time >= valid_duration
 }
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+    use std::net::{SocketAddr, UdpSocket};
+    use std::sync::mpsc::{self, Receiver, Sender};
+    use std::time::{Duration, Instant};
+
+    use nom::IResult;
+    use umio::external;
+
+    use super::{ConnectTimer, DispatchMessage, REQUEST_TIMEOUT_BASE_MILLIS};
+    use crate::util::bt::{InfoHash, PeerId};
+    use crate::utracker::announce::{AnnounceEvent, AnnounceResponse, ClientState, SourceIP};
+    use crate::utracker::client::RequestLimiter;
+    use crate::utracker::contact::{CompactPeers, CompactPeersV4, CompactPeersV6};
+    use crate::utracker::error::ErrorResponse;
+    use crate::utracker::request::{RequestType, TrackerRequest};
+    use crate::utracker::response::{ResponseType, TrackerResponse};
+    use crate::utracker::scrape::{ScrapeResponse, ScrapeStats};
+    use crate::utracker::{ClientError, ClientMetadata, ClientRequest, ClientToken, Handshaker};
+
+    /// Retransmit base for tests that expect no retransmissions.
+    const NO_RETRANSMIT_BASE_MILLIS: u64 = 60000;
+    const RETRANSMIT_BASE_MILLIS: u64 = 100;
+
+    const RECV_TIMEOUT: Duration = Duration::from_secs(5);
+
+    struct MockHandshaker {
+        metadata: Sender<ClientMetadata>,
+        connects: Sender<SocketAddr>,
+    }
+
+    impl Handshaker for MockHandshaker {
+        type Metadata = ClientMetadata;
+
+        fn id(&self) -> PeerId {
+            [0u8; 20].into()
+        }
+
+        fn port(&self) -> u16 {
+            6969
+        }
+
+        fn connect(&mut self, _: Option<PeerId>, _: InfoHash, addr: SocketAddr) {
+            self.connects.send(addr).unwrap();
+        }
+
+        fn metadata(&mut self, data: ClientMetadata) {
+            self.metadata.send(data).unwrap();
+        }
+    }
+
+    struct MockClient {
+        channel: external::Sender<DispatchMessage>,
+        limiter: RequestLimiter,
+        metadata: Receiver<ClientMetadata>,
+        connects: Receiver<SocketAddr>,
+    }
+
+    impl MockClient {
+        fn start(bind: &str, timeout_base: u64) -> MockClient {
+            let (meta_send, meta_recv) = mpsc::channel();
+            let (conn_send, conn_recv) = mpsc::channel();
+            let handshaker = MockHandshaker {
+                metadata: meta_send,
+                connects: conn_send,
+            };
+            let limiter = RequestLimiter::new(16);
+
+            let channel = super::spawn_dispatcher(
+                bind.parse().unwrap(),
+                handshaker,
+                16,
+                limiter.clone(),
+                None,
+                timeout_base,
+            )
+            .unwrap();
+
+            MockClient {
+                channel: channel,
+                limiter: limiter,
+                metadata: meta_recv,
+                connects: conn_recv,
+            }
+        }
+
+        fn request(&self, addr: SocketAddr, token: u32, request: ClientRequest) {
+            assert!(self.limiter.can_initiate());
+
+            self.channel
+                .send(DispatchMessage::Request(
+                    addr,
+                    ClientToken { token: token },
+                    request,
+                ))
+                .unwrap();
+        }
+
+        fn metadata(&self) -> ClientMetadata {
+            self.metadata.recv_timeout(RECV_TIMEOUT).unwrap()
+        }
+    }
+
+    impl Drop for MockClient {
+        fn drop(&mut self) {
+            let _ = self.channel.send(DispatchMessage::Shutdown);
+        }
+    }
+
+    /// Tracker answering client packets exactly as each test scripts it.
+    struct FakeTracker {
+        socket: UdpSocket,
+    }
+
+    impl FakeTracker {
+        fn bind(bind: &str) -> FakeTracker {
+            let socket = UdpSocket::bind(bind).unwrap();
+            socket.set_read_timeout(Some(RECV_TIMEOUT)).unwrap();
+
+            FakeTracker { socket: socket }
+        }
+
+        fn addr(&self) -> SocketAddr {
+            self.socket.local_addr().unwrap()
+        }
+
+        fn recv(&self) -> (TrackerRequest<'static>, SocketAddr) {
+            let mut buffer = [0u8; 1500];
+            let (length, addr) = self.socket.recv_from(&mut buffer).unwrap();
+
+            match TrackerRequest::from_bytes(&buffer[..length]) {
+                IResult::Done(_, request) => (request.to_owned(), addr),
+                _ => panic!("FakeTracker Received An Invalid Request"),
+            }
+        }
+
+        fn send(&self, response: &TrackerResponse, addr: SocketAddr) {
+            self.send_bytes(&response_bytes(response), addr);
+        }
+
+        fn send_bytes(&self, bytes: &[u8], addr: SocketAddr) {
+            self.socket.send_to(bytes, addr).unwrap();
+        }
+    }
+
+    fn response_bytes(response: &TrackerResponse) -> Vec<u8> {
+        let mut bytes = Cursor::new(Vec::new());
+        response.write_bytes(&mut bytes).unwrap();
+
+        bytes.into_inner()
+    }
+
+    fn announce_request() -> ClientRequest {
+        ClientRequest::Announce(
+            [1u8; 20].into(),
+            ClientState::new(0, 0, 0, AnnounceEvent::Started),
+        )
+    }
+
+    fn v4_peers(peers: &[&str]) -> CompactPeers<'static> {
+        let mut v4_peers = CompactPeersV4::new();
+        for peer in peers {
+            v4_peers.insert(peer.parse().unwrap());
+        }
+
+        CompactPeers::V4(v4_peers)
+    }
+
+    fn scrape_response(seeders: i32) -> ResponseType<'static> {
+        let mut response = ScrapeResponse::new();
+        response.insert(ScrapeStats::new(seeders, 0, 0));
+
+        ResponseType::Scrape(response)
+    }
+
+    fn is_connect(request: &TrackerRequest) -> bool {
+        match request.request_type() {
+            &RequestType::Connect => true,
+            _ => false,
+        }
+    }
+
+    fn num_seeders(metadata: &ClientMetadata) -> i32 {
+        metadata
+            .result()
+            .as_ref()
+            .unwrap()
+            .scrape_response()
+            .unwrap()
+            .iter()
+            .next()
+            .unwrap()
+            .num_seeders()
+    }
+
+    #[test]
+    fn positive_retransmit_schedule() {
+        let mut timer = ConnectTimer::new(
+            "127.0.0.1:6969".parse().unwrap(),
+            announce_request(),
+            REQUEST_TIMEOUT_BASE_MILLIS,
+        );
+
+        assert_eq!(timer.current_timeout(false), Some(15 * 1000));
+        for attempt in 1..9 {
+            assert_eq!(
+                timer.current_timeout(true),
+                Some(15 * 2u64.pow(attempt) * 1000)
+            );
+        }
+        assert_eq!(timer.current_timeout(true), None);
+    }
+
+    #[test]
+    fn positive_retransmit_lost_connect() {
+        let tracker = FakeTracker::bind("127.0.0.1:0");
+        let client = MockClient::start("127.0.0.1:0", RETRANSMIT_BASE_MILLIS);
+
+        client.request(tracker.addr(), 1, announce_request());
+
+        // Drop the first connect on the floor
+        let (first, _) = tracker.recv();
+        let lost_at = Instant::now();
+        assert!(is_connect(&first));
+
+        let (second, client_addr) = tracker.recv();
+        assert!(is_connect(&second));
+        assert_eq!(second.transaction_id(), first.transaction_id());
+        assert!(lost_at.elapsed() >= Duration::from_millis(RETRANSMIT_BASE_MILLIS / 2));
+
+        tracker.send(
+            &TrackerResponse::new(second.transaction_id(), ResponseType::Connect(77)),
+            client_addr,
+        );
+
+        let (announce, _) = tracker.recv();
+        assert_eq!(announce.connection_id(), 77);
+        assert_eq!(announce.transaction_id(), first.transaction_id());
+
+        tracker.send(
+            &TrackerResponse::new(
+                announce.transaction_id(),
+                ResponseType::Announce(AnnounceResponse::new(
+                    1800,
+                    0,
+                    1,
+                    v4_peers(&["10.0.0.1:6881"]),
+                )),
+            ),
+            client_addr,
+        );
+
+        let metadata = client.metadata();
+        assert_eq!(metadata.token(), ClientToken { token: 1 });
+        assert!(metadata
+            .result()
+            .as_ref()
+            .unwrap()
+            .announce_response()
+            .is_some());
+        assert_eq!(
+            client.connects.recv_timeout(RECV_TIMEOUT).unwrap(),
+            "10.0.0.1:6881".parse().unwrap()
+        );
+    }
+
+    #[test]
+    fn positive_reconnect_on_stale_connection_id() {
+        let tracker = FakeTracker::bind("127.0.0.1:0");
+        let client = MockClient::start("127.0.0.1:0", NO_RETRANSMIT_BASE_MILLIS);
+
+        client.request(tracker.addr(), 1, ClientRequest::Scrape([1u8; 20].into()));
+        let (connect, client_addr) = tracker.recv();
+        tracker.send(
+            &TrackerResponse::new(connect.transaction_id(), ResponseType::Connect(1)),
+            client_addr,
+        );
+        let (scrape, _) = tracker.recv();
+        tracker.send(
+            &TrackerResponse::new(scrape.transaction_id(), scrape_response(1)),
+            client_addr,
+        );
+        assert_eq!(num_seeders(&client.metadata()), 1);
+
+        // The cached id is reused, but the tracker has since forgotten it
+        client.request(tracker.addr(), 2, ClientRequest::Scrape([1u8; 20].into()));
+        let (stale, _) = tracker.recv();
+        assert_eq!(stale.connection_id(), 1);
+        tracker.send(
+            &TrackerResponse::new(
+                stale.transaction_id(),
+                ResponseType::Error(ErrorResponse::new("Connection ID Mismatch")),
+            ),
+            client_addr,
+        );
+
+        let (reconnect, _) = tracker.recv();
+        assert!(is_connect(&reconnect));
+        tracker.send(
+            &TrackerResponse::new(reconnect.transaction_id(), ResponseType::Connect(2)),
+            client_addr,
+        );
+        let (retry, _) = tracker.recv();
+        assert_eq!(retry.connection_id(), 2);
+        tracker.send(
+            &TrackerResponse::new(retry.transaction_id(), scrape_response(2)),
+            client_addr,
+        );
+
+        let metadata = client.metadata();
+        assert_eq!(metadata.token(), ClientToken { token: 2 });
+        assert_eq!(num_seeders(&metadata), 2);
+
+        // And the regenerated id is what is cached from now on
+        client.request(tracker.addr(), 3, ClientRequest::Scrape([1u8; 20].into()));
+        assert_eq!(tracker.recv().0.connection_id(), 2);
+    }
+
+    #[test]
+    fn negative_error_with_fresh_connection_id() {
+        let tracker = FakeTracker::bind("127.0.0.1:0");
+        let client = MockClient::start("127.0.0.1:0", NO_RETRANSMIT_BASE_MILLIS);
+
+        client.request(tracker.addr(), 1, announce_request());
+        let (connect, client_addr) = tracker.recv();
+        tracker.send(
+            &TrackerResponse::new(connect.transaction_id(), ResponseType::Connect(1)),
+            client_addr,
+        );
+        let (announce, _) = tracker.recv();
+        tracker.send(
+            &TrackerResponse::new(
+                announce.transaction_id(),
+                ResponseType::Error(ErrorResponse::new("Torrent Not Registered")),
+            ),
+            client_addr,
+        );
+
+        match client.metadata().result() {
+            &Err(ClientError::ServerMessage(ref error)) => {
+                assert_eq!(error.message(), "Torrent Not Registered")
+            }
+            _ => panic!("Expected A Server Message"),
+        }
+    }
+
+    #[test]
+    fn positive_ignore_mismatched_responses() {
+        let tracker = FakeTracker::bind("127.0.0.1:0");
+        let imposter = FakeTracker::bind("127.0.0.1:0");
+        let client = MockClient::start("127.0.0.1:0", NO_RETRANSMIT_BASE_MILLIS);
+
+        client.request(tracker.addr(), 1, announce_request());
+        let (connect, client_addr) = tracker.recv();
+        let tid = connect.transaction_id();
+
+        // Wrong sender, wrong transaction and wrong kind of response are all dropped
+        imposter.send(
+            &TrackerResponse::new(tid, ResponseType::Connect(5)),
+            client_addr,
+        );
+        tracker.send(
+            &TrackerResponse::new(tid.wrapping_add(1), ResponseType::Connect(6)),
+            client_addr,
+        );
+        tracker.send(&TrackerResponse::new(tid, scrape_response(1)), client_addr);
+        tracker.send(
+            &TrackerResponse::new(tid, ResponseType::Connect(7)),
+            client_addr,
+        );
+
+        let (announce, _) = tracker.recv();
+        assert_eq!(announce.connection_id(), 7);
+
+        tracker.send(
+            &TrackerResponse::new(tid, ResponseType::Connect(8)),
+            client_addr,
+        );
+        tracker.send(
+            &TrackerResponse::new(
+                tid,
+                ResponseType::Announce(AnnounceResponse::new(1800, 0, 0, v4_peers(&[]))),
+            ),
+            client_addr,
+        );
+
+        let metadata = client.metadata();
+        assert!(metadata
+            .result()
+            .as_ref()
+            .unwrap()
+            .announce_response()
+            .is_some());
+    }
+
+    #[test]
+    fn positive_announce_ipv6_tracker() {
+        let tracker = FakeTracker::bind("[::1]:0");
+        let client = MockClient::start("[::1]:0", NO_RETRANSMIT_BASE_MILLIS);
+
+        client.request(tracker.addr(), 1, announce_request());
+        let (connect, client_addr) = tracker.recv();
+        tracker.send(
+            &TrackerResponse::new(connect.transaction_id(), ResponseType::Connect(1)),
+            client_addr,
+        );
+
+        // Same announce as over IPv4, leaving the tracker to use the address it sees
+        let (announce, _) = tracker.recv();
+        match announce.request_type() {
+            &RequestType::Announce(ref request) => match request.source_ip() {
+                SourceIP::ImpliedV4 => (),
+                _ => panic!("Expected An Implied Source Ip"),
+            },
+            _ => panic!("Expected An Announce"),
+        }
+
+        // Answered with the ipv4 announce action, but 18 byte peers
+        let mut v6_peers = CompactPeersV6::new();
+        v6_peers.insert("[2001:db8::1]:6881".parse().unwrap());
+        v6_peers.insert("[2001:db8::2]:51413".parse().unwrap());
+        let mut bytes = response_bytes(&TrackerResponse::new(
+            announce.transaction_id(),
+            ResponseType::Announce(AnnounceResponse::new(
+                1800,
+                0,
+                2,
+                CompactPeers::V6(v6_peers),
+            )),
+        ));
+        bytes[..4].copy_from_slice(&[0, 0, 0, 1]);
+        tracker.send_bytes(&bytes, client_addr);
+
+        let metadata = client.metadata();
+        let peers: Vec<SocketAddr> = metadata
+            .result()
+            .as_ref()
+            .unwrap()
+            .announce_response()
+            .unwrap()
+            .peers()
+            .iter()
+            .collect();
+        let expected: Vec<SocketAddr> = vec![
+            "[2001:db8::1]:6881".parse().unwrap(),
+            "[2001:db8::2]:51413".parse().unwrap(),
+        ];
+        assert_eq!(peers, expected);
+        assert_eq!(
+            client.connects.recv_timeout(RECV_TIMEOUT).unwrap(),
+            expected[0]
+        );
+    }
+}
@@ -1,13 +1,15 @@
+use std::collections::HashMap;
 use std::io::{self};
 use std::net::SocketAddr;
 use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
 
 use crate::util::bt::InfoHash;
+use crate::util::proxy::ProxyConfig;
 use crate::util::trans::old::TIDGenerator;
 use crate::utracker::announce::{AnnounceResponse, ClientState};
 use crate::utracker::client::dispatcher::DispatchMessage;
-use crate::utracker::scrape::ScrapeResponse;
+use crate::utracker::scrape::{self, ScrapeResponse, ScrapeStats};
 use crate::utracker::ClientResult;
 use umio::external::Sender;
 
@@ -25,6 +27,8 @@ const DEFAULT_CAPACITY: usize = 4096;
 pub enum ClientRequest {
     Announce(InfoHash, ClientState),
     Scrape(InfoHash),
+    /// Scrape of up to `scrape::MAX_SCRAPE_HASHES` hashes in a single request.
+    ScrapeBatch(Vec<InfoHash>),
 }
 
 /// Response metadata from a request.
@@ -61,6 +65,8 @@ pub enum ClientResponse {
     Announce(AnnounceResponse<'static>),
     /// Scrape response.
     Scrape(ScrapeResponse<'static>),
+    /// Batch scrape response, missing the hashes the server left out.
+    ScrapeBatch(HashMap<InfoHash, ScrapeStats>),
 }
 
 impl ClientResponse {
@@ -72,7 +78,7 @@ impl ClientResponse {
     pub fn announce_response(&self) -> Option<&AnnounceResponse<'static>> {
         match self {
             &ClientResponse::Announce(ref res) => Some(res),
-            &ClientResponse::Scrape(_) => None,
+            _ => None,
         }
     }
 
@@ -83,8 +89,20 @@ impl ClientResponse {
     /// succeed.
     pub fn scrape_response(&self) -> Option<&ScrapeResponse<'static>> {
         match self {
-            &ClientResponse::Announce(_) => None,
             &ClientResponse::Scrape(ref res) => Some(res),
+            _ => None,
+        }
+    }
+
+    /// Optionally return a reference to the underyling batch scrape statistics.
+    ///
+    /// If you know that the token associated with the response was retrived
+    /// from a batch scrape, then unwrapping this value is guaranteed to
+    /// succeed.
+    pub fn scrape_batch_response(&self) -> Option<&HashMap<InfoHash, ScrapeStats>> {
+        match self {
+            &ClientResponse::ScrapeBatch(ref stats) => Some(stats),
+            _ => None,
         }
     }
 }
@@ -119,6 +137,36 @@ impl TrackerClient {
         handshaker: H,
         capacity: usize,
     ) -> io::Result<TrackerClient>
+    where
+        H: Handshaker + 'static,
+        H::Metadata: From<ClientMetadata>,
+    {
+        TrackerClient::build(bind, handshaker, capacity, None)
+    }
+
+    /// Create a new TrackerClient sending all of its packets through the given SOCKS5 proxy.
+    ///
+    /// Blocks until the proxy agreed to relay packets for the bound address, failing with
+    /// an io error carrying a `ProxyError` if it did not. Trackers are still given by their
+    /// address, hostnames of udp trackers have to be resolved by the caller.
+    pub fn with_proxy<H>(
+        bind: SocketAddr,
+        handshaker: H,
+        proxy: &ProxyConfig,
+    ) -> io::Result<TrackerClient>
+    where
+        H: Handshaker + 'static,
+        H::Metadata: From<ClientMetadata>,
+    {
+        TrackerClient::build(bind, handshaker, DEFAULT_CAPACITY, Some(proxy))
+    }
+
+    fn build<H>(
+        bind: SocketAddr,
+        handshaker: H,
+        capacity: usize,
+        opt_proxy: Option<&ProxyConfig>,
+    ) -> io::Result<TrackerClient>
     where
         H: Handshaker + 'static,
         H::Metadata: From<ClientMetadata>,
@@ -127,18 +175,19 @@ impl TrackerClient {
         // is dropped so shutdown message can get through in the worst case
         let (chan_capacity, would_overflow) = capacity.overflowing_add(1);
         if would_overflow {
-            panic!("bittorrent-protocol_utracker: Tracker Client Capacity Must Be Less Than Max Size");
+            panic!(
+                "bittorrent-protocol_utracker: Tracker Client Capacity Must Be Less Than Max Size"
+            );
         }
         // Limit the capacity of messages (channel capacity - 1)
         let limiter = RequestLimiter::new(capacity);
 
-        dispatcher::create_dispatcher(bind, handshaker, chan_capacity, limiter.clone()).map(
-            |chan| TrackerClient {
+        dispatcher::create_dispatcher(bind, handshaker, chan_capacity, limiter.clone(), opt_proxy)
+            .map(|chan| TrackerClient {
                 send: chan,
                 limiter: limiter,
                 generator: TokenGenerator::new(),
-            },
-        )
+            })
     }
 
     /// Execute an asynchronous request to the given tracker.
@@ -156,6 +205,46 @@ impl TrackerClient {
             None
         }
     }
+
+    /// Execute asynchronous scrapes of the given hashes to the given tracker.
+    ///
+    /// Hashes are split into batches of at most `scrape::MAX_SCRAPE_HASHES`, each sent as
+    /// its own `ClientRequest::ScrapeBatch`, with the tokens returned in order of the batches.
+    ///
+    /// If the maximum number of requests would be exceeded, no batch is sent and None is returned.
+    pub fn scrape(&mut self, addr: SocketAddr, hashes: &[InfoHash]) -> Option<Vec<ClientToken>> {
+        let batches: Vec<&[InfoHash]> = hashes.chunks(scrape::MAX_SCRAPE_HASHES).collect();
+
+        for initiated in 0..batches.len() {
+            if !self.limiter.can_initiate() {
+                for _ in 0..initiated {
+                    self.limiter.acknowledge();
+                }
+
+                return None;
+            }
+        }
+
+        Some(
+            batches
+                .into_iter()
+                .map(|batch| {
+                    let token = self.generator.generate();
+                    self.send
+                        .send(DispatchMessage::Request(
+                            addr,
+                            token,
+                            ClientRequest::ScrapeBatch(batch.to_vec()),
+                        ))
+                        .expect(
+                            "bittorrent-protocol_utracker: Failed To Send Client Request Message...",
+                        );
+
+                    token
+                })
+                .collect(),
+        )
+    }
 }
 
 impl Drop for TrackerClient {
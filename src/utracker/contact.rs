@@ -4,7 +4,7 @@ use std::borrow::Cow;
 use std::io::{self, Write};
 use std::net::{SocketAddr, SocketAddrV4, SocketAddrV6};
 
-use crate::util::convert;
+use crate::util::{convert, net};
 use nom::{IResult, Needed};
 
 const SOCKET_ADDR_V4_BYTES: usize = 6;
@@ -38,6 +38,44 @@ impl<'a> CompactPeers<'a> {
         }
     }
 
+    /// Split the given peers into ipv4 and ipv6 peers, such as for `peers` and `peers6`.
+    ///
+    /// Ipv4 mapped ipv6 addresses are stored as ipv4 peers.
+    pub fn split<I>(peers: I) -> (CompactPeersV4<'static>, CompactPeersV6<'static>)
+    where
+        I: IntoIterator<Item = SocketAddr>,
+    {
+        let (mut peers_v4, mut peers_v6) = (CompactPeersV4::new(), CompactPeersV6::new());
+
+        for peer in peers {
+            match net::unmap_v4(peer) {
+                SocketAddr::V4(v4_peer) => peers_v4.insert(v4_peer),
+                SocketAddr::V6(v6_peer) => peers_v6.insert(v6_peer),
+            }
+        }
+
+        (peers_v4, peers_v6)
+    }
+
+    /// Add the given peer to the list of peers, if it is of the same address family.
+    ///
+    /// Ipv4 mapped ipv6 addresses are added to ipv4 peers. Returns false if the peer was not added.
+    pub fn insert(&mut self, peer: SocketAddr) -> bool {
+        match (self, net::unmap_v4(peer)) {
+            (&mut CompactPeers::V4(ref mut peers), SocketAddr::V4(v4_peer)) => {
+                peers.insert(v4_peer);
+
+                true
+            }
+            (&mut CompactPeers::V6(ref mut peers), SocketAddr::V6(v6_peer)) => {
+                peers.insert(v6_peer);
+
+                true
+            }
+            _ => false,
+        }
+    }
+
     /// Write the underlying CompactPeers to the given writer.
     pub fn write_bytes<W>(&self, writer: W) -> io::Result<()>
     where
@@ -331,7 +369,9 @@ impl<'a> Iterator for CompactPeersV6Iter<'a> {
 mod tests {
     use nom::IResult;
 
-    use super::{CompactPeersV4, CompactPeersV6};
+    use std::net::SocketAddr;
+
+    use super::{CompactPeers, CompactPeersV4, CompactPeersV6};
 
     #[test]
     fn positive_iterate_v4() {
@@ -392,7 +432,7 @@ mod tests {
         let peers = CompactPeersV4::new();
         peers.write_bytes(&mut received).unwrap();
 
-        let expected = [];
+        let expected: [u8; 0] = [];
 
         assert_eq!(&received[..], &expected[..]);
     }
@@ -494,7 +534,7 @@ mod tests {
         let peers = CompactPeersV6::new();
         peers.write_bytes(&mut received).unwrap();
 
-        let expected = [];
+        let expected: [u8; 0] = [];
 
         assert_eq!(&received[..], &expected[..]);
     }
@@ -532,4 +572,50 @@ mod tests {
 
         assert_eq!(&received[..], &expected[..]);
     }
+
+    #[test]
+    fn positive_round_trip_mixed_peers() {
+        let peer_v4: SocketAddr = "10.0.0.5:3245".parse().unwrap();
+        let peer_v6: SocketAddr = "[ADBB:234A:55BD:FF34:3D3A::234A:55BD]:256".parse().unwrap();
+        let peer_mapped: SocketAddr = "[::ffff:127.0.0.1]:2354".parse().unwrap();
+
+        let (peers_v4, peers_v6) = CompactPeers::split(vec![peer_v4, peer_v6, peer_mapped]);
+        let (mut bytes_v4, mut bytes_v6) = (Vec::new(), Vec::new());
+        peers_v4.write_bytes(&mut bytes_v4).unwrap();
+        peers_v6.write_bytes(&mut bytes_v6).unwrap();
+        assert_eq!(2 * 6, bytes_v4.len());
+        assert_eq!(18, bytes_v6.len());
+
+        let parsed_v4 = CompactPeers::from_bytes_v4(&bytes_v4).unwrap().1;
+        let parsed_v6 = CompactPeers::from_bytes_v6(&bytes_v6).unwrap().1;
+        let received: Vec<SocketAddr> = parsed_v4.iter().chain(parsed_v6.iter()).collect();
+
+        let expected = vec![peer_v4, "127.0.0.1:2354".parse().unwrap(), peer_v6];
+        assert_eq!(expected, received);
+    }
+
+    #[test]
+    fn positive_insert_matching_family() {
+        let mut peers_v4 = CompactPeers::V4(CompactPeersV4::new());
+        let mut peers_v6 = CompactPeers::V6(CompactPeersV6::new());
+
+        assert!(peers_v4.insert("127.0.0.1:15".parse().unwrap()));
+        assert!(peers_v4.insert("[::ffff:127.0.0.1]:16".parse().unwrap()));
+        assert!(peers_v6.insert("[::1]:17".parse().unwrap()));
+
+        assert_eq!(2, peers_v4.iter().count());
+        assert_eq!(1, peers_v6.iter().count());
+    }
+
+    #[test]
+    fn negative_insert_other_family() {
+        let mut peers_v4 = CompactPeers::V4(CompactPeersV4::new());
+        let mut peers_v6 = CompactPeers::V6(CompactPeersV6::new());
+
+        assert!(!peers_v4.insert("[::1]:15".parse().unwrap()));
+        assert!(!peers_v6.insert("127.0.0.1:15".parse().unwrap()));
+
+        assert_eq!(0, peers_v4.iter().count());
+        assert_eq!(0, peers_v6.iter().count());
+    }
 }
@@ -28,3 +28,4 @@ pub use server::handler::{ServerHandler, ServerResult};
 pub use server::TrackerServer;
 
 pub use crate::util::bt::{InfoHash, PeerId};
+pub use crate::util::proxy::{ProxyAuth, ProxyConfig, ProxyError, ProxyKind};
@@ -49,7 +49,14 @@ impl<'a> TrackerResponse<'a> {
 
     /// Create a new TrackerResponse from the given bytes.
     pub fn from_bytes(bytes: &'a [u8]) -> IResult<&'a [u8], TrackerResponse<'a>> {
-        parse_response(bytes)
+        parse_response(bytes, AnnounceResponse::from_bytes_v4)
+    }
+
+    /// Create a new TrackerResponse from the given bytes, received from a tracker over IPv6.
+    ///
+    /// As described in BEP 15, such trackers may answer an ordinary announce with 18 byte peers.
+    pub fn from_bytes_v6(bytes: &'a [u8]) -> IResult<&'a [u8], TrackerResponse<'a>> {
+        parse_response(bytes, AnnounceResponse::from_bytes_v6)
     }
 
     /// Write the TrackerResponse to the given writer.
@@ -111,10 +118,13 @@ impl<'a> TrackerResponse<'a> {
     }
 }
 
-fn parse_response<'a>(bytes: &'a [u8]) -> IResult<&'a [u8], TrackerResponse<'a>> {
+fn parse_response<'a>(
+    bytes: &'a [u8],
+    announce_type: fn(bytes: &'a [u8]) -> IResult<&'a [u8], AnnounceResponse<'a>>,
+) -> IResult<&'a [u8], TrackerResponse<'a>> {
     switch!(bytes, tuple!(be_u32, be_u32),
         (crate::utracker::CONNECT_ACTION_ID, tid)  => map!(be_u64, |cid| TrackerResponse::new(tid, ResponseType::Connect(cid)) ) |
-        (crate::utracker::ANNOUNCE_IPV4_ACTION_ID, tid) => map!(call!(AnnounceResponse::from_bytes_v4), |ann_res| {
+        (crate::utracker::ANNOUNCE_IPV4_ACTION_ID, tid) => map!(call!(announce_type), |ann_res| {
             TrackerResponse::new(tid, ResponseType::Announce(ann_res))
         }) |
         (crate::utracker::SCRAPE_ACTION_ID, tid)   => map!(call!(ScrapeResponse::from_bytes), |scr_res| {
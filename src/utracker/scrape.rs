@@ -9,6 +9,10 @@ use nom::{be_i32, IResult, Needed};
 
 const SCRAPE_STATS_BYTES: usize = 12;
 
+/// Maximum number of hashes scraped in a single request, keeping the request
+/// within a 1500 byte packet.
+pub const MAX_SCRAPE_HASHES: usize = 74;
+
 /// Status for a given InfoHash.
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub struct ScrapeStats {
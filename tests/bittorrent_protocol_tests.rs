@@ -1,6 +1,9 @@
 #[macro_use]
 extern crate bittorrent_protocol;
 
+use std::fs;
+use std::path::PathBuf;
+
 mod test1_bencode;
 
 mod test2_metainfo;
@@ -13,3 +16,25 @@ mod test5_handshake;
 
 mod test6_utp;
 
+mod test7_peer;
+
+mod test8_htracker;
+
+mod test9_dht;
+
+mod test10_lsd;
+
+mod test11_session;
+
+/// Create an empty directory for the named test, removing any left behind by an earlier run.
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!(
+        "bittorrent-protocol_tests_{}_{}",
+        name,
+        std::process::id()
+    ));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+
+    dir
+}
@@ -0,0 +1,68 @@
+use std::net::SocketAddr;
+use std::sync::mpsc::{self, Sender};
+use std::time::Duration;
+
+use bittorrent_protocol::lsd::LsdBuilder;
+use bittorrent_protocol::util::bt::{InfoHash, PeerId};
+use bittorrent_protocol::utracker::Handshaker;
+
+struct MockHandshaker {
+    port: u16,
+    send: Sender<(InfoHash, SocketAddr)>,
+}
+
+impl Handshaker for MockHandshaker {
+    type Metadata = ();
+
+    fn id(&self) -> PeerId {
+        [0u8; 20].into()
+    }
+
+    fn port(&self) -> u16 {
+        self.port
+    }
+
+    fn connect(&mut self, _: Option<PeerId>, hash: InfoHash, addr: SocketAddr) {
+        self.send.send((hash, addr)).unwrap();
+    }
+
+    fn metadata(&mut self, _: ()) {}
+}
+
+// Needs multicast looped back on the default interface, which CI machines often do not have
+#[test]
+#[ignore]
+fn positive_lsd_finds_local_peer() {
+    let hash = InfoHash::from_bytes(b"positive_lsd_finds_local_peer");
+
+    let (send_one, recv_one) = mpsc::channel();
+    let lsd_one = LsdBuilder::new()
+        .start(MockHandshaker {
+            port: 7001,
+            send: send_one,
+        })
+        .unwrap();
+
+    let (send_two, recv_two) = mpsc::channel();
+    let lsd_two = LsdBuilder::new()
+        .start(MockHandshaker {
+            port: 7002,
+            send: send_two,
+        })
+        .unwrap();
+
+    lsd_one.add_info_hash(hash);
+    lsd_two.add_info_hash(hash);
+
+    let (found_hash, found_addr) = recv_one.recv_timeout(Duration::from_secs(10)).unwrap();
+    assert_eq!(found_hash, hash);
+    assert_eq!(found_addr.port(), 7002);
+
+    let (found_hash, found_addr) = recv_two.recv_timeout(Duration::from_secs(10)).unwrap();
+    assert_eq!(found_hash, hash);
+    assert_eq!(found_addr.port(), 7001);
+
+    // Each service ignores its own announcements
+    assert!(recv_one.try_recv().is_err());
+    assert!(recv_two.try_recv().is_err());
+}
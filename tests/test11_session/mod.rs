@@ -0,0 +1,331 @@
+use std::collections::HashSet;
+use std::fs;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::path::PathBuf;
+use std::sync::mpsc::Receiver;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use bittorrent_protocol::disk::{ResumeData, ResumeState};
+use bittorrent_protocol::magnet::MagnetLink;
+use bittorrent_protocol::metainfo::{
+    DirectAccessor, FileAccessor, Metainfo, MetainfoBuilder, PieceLength,
+};
+use bittorrent_protocol::session::error::SessionErrorKind;
+use bittorrent_protocol::session::{
+    SeedLimit, SeedPolicy, Session, SessionBuilder, TorrentEvent, TorrentOptions, TorrentSource,
+    TorrentState,
+};
+
+const FILE_NAME: &'static str = "session.bin";
+const PIECE_LENGTH: usize = 32 * 1024;
+const TIMEOUT_SECS: u64 = 60;
+
+/// Five full pieces and a short last one.
+fn file_data() -> Vec<u8> {
+    (0..(5 * PIECE_LENGTH + 1000) as u32)
+        .map(|i| ((i * 7 + 3) % 251) as u8)
+        .collect()
+}
+
+fn local_builder(root: &PathBuf) -> SessionBuilder {
+    Session::builder()
+        .with_listen_addr(SocketAddr::new(Ipv4Addr::LOCALHOST.into(), 0))
+        .with_download_root(root.clone())
+        .with_dht(false)
+}
+
+fn local_session(root: &PathBuf) -> Session {
+    local_builder(root).build().unwrap()
+}
+
+/// Write the file data for a seed, returning its root along with the metainfo.
+fn seed_files(name: &str) -> (PathBuf, Metainfo) {
+    let data = file_data();
+    let root = crate::temp_dir(&format!("{}_seed", name));
+    fs::write(root.join(FILE_NAME), &data).unwrap();
+
+    let bytes = MetainfoBuilder::new()
+        .set_piece_length(PieceLength::Custom(PIECE_LENGTH))
+        .build(1, DirectAccessor::new(FILE_NAME, &data[..]), |_| ())
+        .unwrap();
+
+    (root, Metainfo::from_bytes(bytes).unwrap())
+}
+
+/// Start a seed for the file data, returning it with its metainfo.
+fn seed(name: &str) -> (Session, Metainfo) {
+    let (root, metainfo) = seed_files(name);
+
+    let session = local_session(&root);
+    let events = session.events();
+    let handle = session
+        .add_torrent(metainfo.clone(), TorrentOptions::new())
+        .unwrap();
+    wait_for(&events, TorrentEvent::Checked(handle.info_hash()));
+    assert_eq!(TorrentState::Seeding, handle.stats().state());
+
+    (session, metainfo)
+}
+
+fn wait_for(events: &Receiver<TorrentEvent>, expected: TorrentEvent) {
+    let deadline = Instant::now() + Duration::from_secs(TIMEOUT_SECS);
+
+    loop {
+        let remaining = deadline
+            .checked_duration_since(Instant::now())
+            .expect("Timed Out Waiting For Torrent Event");
+
+        match events.recv_timeout(remaining) {
+            Ok(event) if event == expected => return,
+            Ok(TorrentEvent::Error(_, error)) => panic!("Torrent Error: {}", error),
+            Ok(_) => (),
+            Err(_) => panic!("Timed Out Waiting For {:?}", expected),
+        }
+    }
+}
+
+/// Download the torrent from the seed, and remove it along with its data.
+fn download_from_seed<T>(name: &str, source: T, seed: &Session)
+where
+    T: Into<TorrentSource>,
+{
+    let root = crate::temp_dir(&format!("{}_leech", name));
+    let session = local_session(&root);
+    let events = session.events();
+
+    let seed_addr = SocketAddr::new(Ipv4Addr::LOCALHOST.into(), seed.listen_port());
+    let handle = session
+        .add_torrent(source, TorrentOptions::new().with_peer(seed_addr))
+        .unwrap();
+    wait_for(&events, TorrentEvent::Completed(handle.info_hash()));
+
+    let stats = handle.stats();
+    assert_eq!(TorrentState::Seeding, stats.state());
+    assert_eq!(stats.pieces_total(), stats.pieces_have());
+    assert_eq!(file_data(), fs::read(root.join(FILE_NAME)).unwrap());
+
+    let metrics = session.metrics();
+    assert!(metrics.bytes_downloaded() >= file_data().len() as u64);
+    assert_eq!(stats.pieces_total() as u64, metrics.pieces_verified());
+    assert_eq!(0, metrics.hash_failures());
+    assert_eq!(1, metrics.peers_connected());
+    assert_eq!(1, metrics.torrents().len());
+    assert!(metrics.dht().is_none());
+    assert!(seed.metrics().bytes_uploaded() >= file_data().len() as u64);
+
+    handle.remove(true).unwrap();
+    wait_for(&events, TorrentEvent::Removed(handle.info_hash()));
+    assert!(handle.is_removed());
+    assert!(session.torrent(handle.info_hash()).is_none());
+    assert!(!root.join(FILE_NAME).exists());
+}
+
+#[test]
+fn positive_download_metainfo_from_local_seed() {
+    let (seed, metainfo) = seed("metainfo");
+
+    download_from_seed("metainfo", metainfo, &seed);
+}
+
+#[test]
+fn positive_download_magnet_from_local_seed() {
+    let (seed, metainfo) = seed("magnet");
+
+    download_from_seed("magnet", MagnetLink::from_metainfo(&metainfo), &seed);
+}
+
+#[test]
+fn negative_add_torrent_twice() {
+    let (seed, metainfo) = seed("twice");
+
+    assert!(seed.add_torrent(metainfo, TorrentOptions::new()).is_err());
+    assert_eq!(1, seed.torrents().len());
+}
+
+#[test]
+fn negative_add_v2_only_magnet() {
+    let root = crate::temp_dir("v2_only");
+    let session = local_session(&root);
+
+    // Metadata downloaded for it could not be checked against the v2 hash
+    let magnet = MagnetLink::parse(
+        "magnet:?xt=urn:btmh:1220caf1e1c30e81cb361b9ee167c4aa64228a7fa4fa9f6105232b28ad099f3a302e",
+    )
+    .unwrap();
+    match session.add_torrent(magnet, TorrentOptions::new()) {
+        Err(error) => match error.kind() {
+            SessionErrorKind::InvalidMagnet => (),
+            unexpected @ _ => panic!("Unexpected Error: {:?}", unexpected),
+        },
+        Ok(_) => panic!("Added A Magnet Link Without A V1 Info Hash"),
+    };
+    assert!(session.torrents().is_empty());
+}
+
+#[test]
+fn positive_shutdown_mid_download_saves_verified_pieces() {
+    let (seed, metainfo) = seed("shutdown");
+    let hash = metainfo.info().info_hash();
+    let root = crate::temp_dir("shutdown_leech");
+    let state_dir = root.join("state");
+
+    // Slow enough that the shutdown comes before the download completes
+    let session = local_builder(&root)
+        .with_state_dir(state_dir.clone())
+        .with_download_limit(Some(PIECE_LENGTH as u64))
+        .build()
+        .unwrap();
+    let events = session.events();
+    let seed_addr = SocketAddr::new(Ipv4Addr::LOCALHOST.into(), seed.listen_port());
+    session
+        .add_torrent(metainfo.clone(), TorrentOptions::new().with_peer(seed_addr))
+        .unwrap();
+
+    let mut verified = HashSet::new();
+    let deadline = Instant::now() + Duration::from_secs(TIMEOUT_SECS);
+    while verified.is_empty() {
+        let remaining = deadline
+            .checked_duration_since(Instant::now())
+            .expect("Timed Out Waiting For A Verified Piece");
+
+        if let TorrentEvent::PieceVerified(_, piece) = events.recv_timeout(remaining).unwrap() {
+            verified.insert(piece);
+        }
+    }
+    futures::executor::block_on(session.shutdown());
+    futures::executor::block_on(session.shutdown());
+    verified.extend(events.try_iter().filter_map(|event| match event {
+        TorrentEvent::PieceVerified(_, piece) => Some(piece),
+        _ => None,
+    }));
+    assert!(session
+        .add_torrent(metainfo.clone(), TorrentOptions::new())
+        .is_err());
+
+    let resume_name = format!("{}.resume", hex::encode(hash.as_ref()));
+    let resume = ResumeData::from_bytes(fs::read(state_dir.join(resume_name)).unwrap());
+    let state = ResumeState::decode(&resume, metainfo.info()).unwrap();
+    for &piece in verified.iter() {
+        assert!(
+            state.good_pieces[piece as usize],
+            "Piece {} Not Saved",
+            piece
+        );
+    }
+    assert!(!state_dir.join("dht.state").exists());
+    assert!(state.totals.downloaded > 0);
+    drop(session);
+
+    // Restarting picks up the pieces from the resume data
+    let session = local_builder(&root)
+        .with_state_dir(state_dir)
+        .build()
+        .unwrap();
+    let events = session.events();
+    let handle = session
+        .add_torrent(metainfo, TorrentOptions::new())
+        .unwrap();
+    wait_for(&events, TorrentEvent::Checked(hash));
+    assert!(handle.stats().pieces_have() >= verified.len());
+    assert_eq!(state.totals.downloaded, handle.stats().total_downloaded());
+}
+
+#[test]
+fn positive_seed_limit_pauses_torrent() {
+    let (root, metainfo) = seed_files("seed_limit");
+    let hash = metainfo.info().info_hash();
+    let session = local_builder(&root)
+        .with_seed_policy(
+            SeedPolicy::new()
+                .with_ratio_limit(Some(2.0))
+                .with_auto_pause(true),
+        )
+        .build()
+        .unwrap();
+    let events = session.events();
+
+    // Nothing was downloaded for a torrent seeded from the start
+    let handle = session
+        .add_torrent(metainfo, TorrentOptions::new())
+        .unwrap();
+    wait_for(
+        &events,
+        TorrentEvent::SeedLimitReached {
+            hash: hash,
+            which: SeedLimit::Ratio,
+        },
+    );
+    wait_for(&events, TorrentEvent::Paused(hash));
+    assert_eq!(TorrentState::Paused, handle.stats().state());
+
+    // Limits reached are not paused for again
+    handle.resume().unwrap();
+    wait_for(&events, TorrentEvent::Resumed(hash));
+    thread::sleep(Duration::from_secs(2));
+    assert_eq!(TorrentState::Seeding, handle.stats().state());
+}
+
+#[test]
+fn positive_magnet_select_only_downloads_selected_file() {
+    let seed_root = crate::temp_dir("select_only_seed");
+    let data = file_data();
+    let (first, second) = data.split_at(2 * PIECE_LENGTH + 500);
+    fs::create_dir_all(seed_root.join("select")).unwrap();
+    fs::write(seed_root.join("select/first.bin"), first).unwrap();
+    fs::write(seed_root.join("select/second.bin"), second).unwrap();
+
+    let bytes = MetainfoBuilder::new()
+        .set_piece_length(PieceLength::Custom(PIECE_LENGTH))
+        .build(1, FileAccessor::new(seed_root.join("select")).unwrap(), |_| ())
+        .unwrap();
+    let metainfo = Metainfo::from_bytes(bytes).unwrap();
+    let hash = metainfo.info().info_hash();
+
+    let seed = local_session(&seed_root);
+    let seed_events = seed.events();
+    seed.add_torrent(metainfo.clone(), TorrentOptions::new()).unwrap();
+    wait_for(&seed_events, TorrentEvent::Checked(hash));
+
+    let root = crate::temp_dir("select_only_leech");
+    let session = local_session(&root);
+    let events = session.events();
+    let magnet =
+        MagnetLink::parse(&format!("{}&so=1", MagnetLink::from_metainfo(&metainfo))).unwrap();
+    let seed_addr = SocketAddr::new(Ipv4Addr::LOCALHOST.into(), seed.listen_port());
+    let handle = session
+        .add_torrent(magnet, TorrentOptions::new().with_peer(seed_addr))
+        .unwrap();
+    wait_for(&events, TorrentEvent::Completed(hash));
+
+    // Piece shared by both files is downloaded, the bytes of the first are kept aside
+    let stats = handle.stats();
+    assert_eq!(4, stats.pieces_have());
+    assert_eq!(second, &fs::read(root.join("select/second.bin")).unwrap()[..]);
+    assert!(!root.join("select/first.bin").exists());
+}
+
+#[test]
+fn positive_magnet_select_only_warns_of_missing_files() {
+    let (seed, metainfo) = seed("select_missing");
+    let hash = metainfo.info().info_hash();
+    let root = crate::temp_dir("select_missing_leech");
+    let session = local_session(&root);
+    let events = session.events();
+
+    let magnet =
+        MagnetLink::parse(&format!("{}&so=0,3-4", MagnetLink::from_metainfo(&metainfo))).unwrap();
+    let seed_addr = SocketAddr::new(Ipv4Addr::LOCALHOST.into(), seed.listen_port());
+    session
+        .add_torrent(magnet, TorrentOptions::new().with_peer(seed_addr))
+        .unwrap();
+    wait_for(
+        &events,
+        TorrentEvent::Warning(
+            hash,
+            "Magnet Link Selects Files 3-4 Of A Torrent With 1 Files".to_string(),
+        ),
+    );
+    wait_for(&events, TorrentEvent::Completed(hash));
+    assert_eq!(file_data(), fs::read(root.join(FILE_NAME)).unwrap());
+}
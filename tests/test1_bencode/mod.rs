@@ -1,4 +1,5 @@
-use bittorrent_protocol::bencode::{BDecodeOpt, BencodeRef};
+use bittorrent_protocol::bencode::{BDecodeOpt, BRefAccess, BencodeRef, BencodeView};
+use bytes::{Bytes, BytesMut};
 
 #[test]
 pub fn my_print() {
@@ -38,3 +39,32 @@ fn bench_multi_kb_bencode() {
     let result = BencodeRef::decode(&bencode[..], BDecodeOpt::default()).unwrap();
     println!("{:?}", result);
 }
+
+#[test]
+fn positive_ben_map_macro_encode_into() {
+    let message = ben_map! {
+        "b" => ben_list!(ben_int!(-1), ben_bytes!("")),
+        "a" => ben_int!(0)
+    };
+
+    let mut buffer = BytesMut::new();
+    message.encode_into(&mut buffer);
+
+    assert_eq!(message.encoded_len(), buffer.len());
+    assert_eq!(&message.encode()[..], &buffer[..]);
+    assert_eq!("d1:ai0e1:bli-1e0:ee".as_bytes(), &buffer[..]);
+}
+
+#[test]
+fn positive_view_multi_kb_bencode() {
+    let bencode = include_bytes!("multi_kb.bencode");
+
+    let reference = BencodeRef::decode(&bencode[..], BDecodeOpt::default()).unwrap();
+    let view = BencodeView::decode(Bytes::from(&bencode[..]), BDecodeOpt::default()).unwrap();
+
+    assert_eq!(&bencode[..], &view.buffer()[..]);
+    assert_eq!(
+        reference.dict().unwrap().to_list().len(),
+        view.dict().unwrap().count()
+    );
+}
@@ -1,9 +1,58 @@
-use bittorrent_protocol::metainfo::MetainfoBuilder;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use bittorrent_protocol::metainfo::error::ParseErrorKind;
+use bittorrent_protocol::metainfo::{
+    DirectAccessor, FileAccessor, InfoHash, Metainfo, MetainfoBuilder, MetainfoV2, PieceLength,
+};
+use bittorrent_protocol::util::sha::ShaHash;
 
 const TRACKER: &'static str = "udp://foo.bar.baz:6969";
 const DATE: i64 = 1517651523851;
 const COMMENT: &'static str = "Foo bar baz";
 const CREATED_BY: &'static str = "Fridge";
+const WEB_SEED: &'static str = "http://foo.bar.baz/files/";
+
+/// Hybrid torrent with a piece length of 32 KiB, for the files `a.bin` (70000 bytes),
+/// `dir/b.bin` (20000 bytes) and `dir/empty`, see `hybrid_file_data`.
+///
+/// Every file but the last is padded up to a piece boundary, as libtorrent does.
+const HYBRID_TORRENT: &'static [u8] = include_bytes!("hybrid.torrent");
+const HYBRID_TRACKER: &'static str = "udp://tracker.example.com:6969";
+const HYBRID_V1_INFO_HASH: &'static str = "b52af96a6233a9fc17fafa07c235ee7c881afb05";
+const HYBRID_V2_INFO_HASH: &'static str =
+    "d0684f3573f6709f3604f2c6d90c08aee14b0f22f85dacb92919903177ccce82";
+const HYBRID_A_PIECES_ROOT: &'static str =
+    "5e3da1462e20b58e221b63941e0964d3e98610e8a852bb438e4acad5e17a6021";
+
+/// Single file torrent as written by mktorrent for a private tracker, with the `source` and
+/// `x_cross_seed` keys in its info dictionary and a `publisher-url` key outside of it.
+///
+/// Neither the outer nor the info dictionary have their keys in sorted order.
+const CROSS_SEED_TORRENT: &'static [u8] = include_bytes!("cross_seed.torrent");
+const CROSS_SEED_INFO_HASH: &'static str = "a3574d71856e121911e7f55b88dfeef234fa9b0a";
+
+/// Contents of the files `a.bin` and `dir/b.bin` of the hybrid torrent.
+fn hybrid_file_data() -> (Vec<u8>, Vec<u8>) {
+    (
+        (0..70000u32).map(|i| ((i * 7 + 3) % 251) as u8).collect(),
+        (0..20000u32).map(|i| ((i * 13 + 5) % 241) as u8).collect(),
+    )
+}
+
+fn hex_info_hash(hex_hash: &str) -> InfoHash {
+    InfoHash::from_hash(&hex::decode(hex_hash).unwrap()).unwrap()
+}
+
+fn write_file<P>(root: &Path, path: P, contents: &[u8])
+where
+    P: AsRef<Path>,
+{
+    let path = root.join(path);
+
+    fs::create_dir_all(path.parent().unwrap()).unwrap();
+    fs::write(path, contents).unwrap();
+}
 
 #[test]
 pub fn my_print() {
@@ -46,3 +95,511 @@ fn positive_set_created_by() {
 
     assert_eq!(builder.get_created_by(), Some(CREATED_BY.to_string()));
 }
+
+#[test]
+fn positive_set_web_seeds() {
+    let web_seeds = vec![WEB_SEED.to_string()];
+
+    let builder = MetainfoBuilder::new().set_web_seeds(Some(&web_seeds));
+
+    assert_eq!(builder.get_web_seeds(), Some(web_seeds.clone()));
+}
+
+#[test]
+fn positive_build_directory_round_trip() {
+    let root = crate::temp_dir("round_trip").join("torrent");
+    write_file(&root, "b.txt", &[1u8; 3000]);
+    write_file(&root, "a/2.txt", &[2u8; 1500]);
+    write_file(&root, "a/1.txt", &[3u8; 700]);
+    write_file(&root, ".hidden", &[4u8; 10]);
+    write_file(&root, "a/.git/config", &[5u8; 10]);
+    write_file(&root, "a/scratch.tmp", &[6u8; 10]);
+
+    let trackers = vec![
+        vec![TRACKER.to_string()],
+        vec!["udp://foo.bar.qux:6969".to_string()],
+    ];
+    let web_seeds = vec![WEB_SEED.to_string()];
+    let build = |threads| {
+        let accessor = FileAccessor::new(&root)
+            .unwrap()
+            .with_ignore_hidden(true)
+            .with_ignore_pattern("*.tmp");
+
+        MetainfoBuilder::new()
+            .set_main_tracker(Some(TRACKER))
+            .set_trackers(Some(&trackers))
+            .set_web_seeds(Some(&web_seeds))
+            .set_comment(Some(COMMENT))
+            .set_created_by(Some(CREATED_BY))
+            .set_creation_date(Some(DATE))
+            .set_private_flag(Some(true))
+            .set_piece_length(PieceLength::Custom(1024))
+            .build(threads, accessor, |_| ())
+            .unwrap()
+    };
+
+    let metainfo_bytes = build(1);
+    let metainfo = Metainfo::from_bytes(&metainfo_bytes).unwrap();
+
+    assert_eq!(metainfo.main_tracker(), Some(TRACKER));
+    assert_eq!(metainfo.trackers(), Some(&trackers));
+    assert_eq!(metainfo.web_seeds(), Some(&web_seeds));
+    assert_eq!(metainfo.comment(), Some(COMMENT));
+    assert_eq!(metainfo.created_by(), Some(CREATED_BY));
+    assert_eq!(metainfo.creation_date(), Some(DATE));
+
+    let info = metainfo.info();
+    assert_eq!(info.directory(), Some(Path::new("torrent")));
+    assert_eq!(info.is_private(), Some(true));
+    assert_eq!(info.piece_length(), 1024);
+    assert_eq!(info.pieces().count(), 6);
+
+    let files: Vec<(PathBuf, u64)> = info
+        .files()
+        .map(|file| (file.path().to_path_buf(), file.length()))
+        .collect();
+    assert_eq!(
+        files,
+        vec![
+            (PathBuf::from("a/1.txt"), 700),
+            (PathBuf::from("a/2.txt"), 1500),
+            (PathBuf::from("b.txt"), 3000),
+        ]
+    );
+
+    // Building again, with more threads, yields the same torrent file
+    assert_eq!(build(4), metainfo_bytes);
+    assert_eq!(Metainfo::from_bytes(metainfo.to_bytes()).unwrap(), metainfo);
+
+    fs::remove_dir_all(root.parent().unwrap()).unwrap();
+}
+
+#[test]
+fn positive_build_single_file() {
+    let root = crate::temp_dir("single_file");
+    write_file(&root, "file.bin", &[7u8; 2500]);
+
+    let metainfo_bytes = MetainfoBuilder::new()
+        .set_piece_length(PieceLength::Custom(1024))
+        .build(2, root.join("file.bin"), |_| ())
+        .unwrap();
+    let metainfo = Metainfo::from_bytes(&metainfo_bytes).unwrap();
+    let info = metainfo.info();
+
+    assert_eq!(info.directory(), None);
+    assert_eq!(info.pieces().count(), 3);
+
+    let files: Vec<(PathBuf, u64)> = info
+        .files()
+        .map(|file| (file.path().to_path_buf(), file.length()))
+        .collect();
+    assert_eq!(files, vec![(PathBuf::from("file.bin"), 2500)]);
+
+    fs::remove_dir_all(root).unwrap();
+}
+
+#[test]
+fn positive_parse_hybrid_torrent() {
+    let metainfo = MetainfoV2::from_bytes(HYBRID_TORRENT).unwrap();
+
+    assert!(metainfo.is_hybrid());
+    assert_eq!(metainfo.info_hash(), hex_info_hash(HYBRID_V2_INFO_HASH));
+    assert_eq!(
+        metainfo.info_hash().truncated(),
+        hex_info_hash(&HYBRID_V2_INFO_HASH[..40]).truncated()
+    );
+    assert_eq!(
+        metainfo.v1_info_hash(),
+        Some(hex_info_hash(HYBRID_V1_INFO_HASH))
+    );
+    assert_eq!(metainfo.name(), "hybrid");
+    assert_eq!(metainfo.piece_length(), 32 * 1024);
+
+    let files: Vec<(PathBuf, u64)> = metainfo
+        .files()
+        .iter()
+        .map(|file| (file.path().to_path_buf(), file.length()))
+        .collect();
+    assert_eq!(
+        files,
+        vec![
+            (PathBuf::from("a.bin"), 70000),
+            (PathBuf::from("dir/b.bin"), 20000),
+            (PathBuf::from("dir/empty"), 0),
+        ]
+    );
+
+    // Only the file larger than a piece has a piece layer
+    let pieces_root_a = hex::decode(HYBRID_A_PIECES_ROOT).unwrap();
+    let pieces_root_b = metainfo.files()[1].pieces_root().unwrap();
+    assert_eq!(metainfo.files()[0].pieces_root(), Some(&pieces_root_a[..]));
+    assert_eq!(metainfo.piece_layer(&pieces_root_a).unwrap().len(), 3);
+    assert_eq!(metainfo.piece_layer(pieces_root_b), None);
+    assert_eq!(metainfo.files()[2].pieces_root(), None);
+
+    // The v1 view of the same torrent has both hashes as well
+    let info = Metainfo::from_bytes(HYBRID_TORRENT).unwrap().info().clone();
+    assert_eq!(info.info_hash(), hex_info_hash(HYBRID_V1_INFO_HASH));
+    assert_eq!(
+        info.v2_info_hash(),
+        Some(hex_info_hash(HYBRID_V2_INFO_HASH))
+    );
+}
+
+#[test]
+fn negative_parse_hybrid_torrent_corrupted_piece_layer() {
+    // The piece layer is the last value in the torrent, closed by two dictionaries
+    let mut torrent = HYBRID_TORRENT.to_vec();
+    let last_layer_byte = torrent.len() - 3;
+    torrent[last_layer_byte] ^= 0xFF;
+
+    match MetainfoV2::from_bytes(&torrent).unwrap_err().kind() {
+        &ParseErrorKind::InvalidData { .. } => (),
+        unexpected @ _ => panic!("Unexpected Error: {:?}", unexpected),
+    }
+}
+
+#[test]
+fn negative_parse_v1_torrent_as_v2() {
+    let metainfo_bytes = MetainfoBuilder::new().build(1, "src", |_| ()).unwrap();
+
+    assert_eq!(
+        Metainfo::from_bytes(&metainfo_bytes)
+            .unwrap()
+            .info()
+            .v2_info_hash(),
+        None
+    );
+    match MetainfoV2::from_bytes(&metainfo_bytes).unwrap_err().kind() {
+        &ParseErrorKind::MissingData { .. } => (),
+        unexpected @ _ => panic!("Unexpected Error: {:?}", unexpected),
+    }
+}
+
+#[test]
+fn positive_build_hybrid_torrent() {
+    let root = crate::temp_dir("hybrid").join("hybrid");
+    let (data_a, data_b) = hybrid_file_data();
+    write_file(&root, "a.bin", &data_a);
+    write_file(&root, "dir/b.bin", &data_b);
+    write_file(&root, "dir/empty", &[]);
+
+    let build = |threads| {
+        MetainfoBuilder::new()
+            .set_main_tracker(Some(HYBRID_TRACKER))
+            .set_piece_length(PieceLength::Custom(32 * 1024))
+            .set_hybrid(true)
+            .build(threads, &root, |_| ())
+            .unwrap()
+    };
+
+    assert_eq!(build(1), HYBRID_TORRENT);
+    assert_eq!(build(4), HYBRID_TORRENT);
+
+    fs::remove_dir_all(root.parent().unwrap()).unwrap();
+}
+
+#[test]
+fn positive_build_padded_torrent() {
+    let root = crate::temp_dir("padded").join("padded");
+    let (data_a, data_b) = hybrid_file_data();
+    write_file(&root, "a.bin", &data_a);
+    write_file(&root, "dir/b.bin", &data_b);
+
+    let metainfo_bytes = MetainfoBuilder::new()
+        .set_piece_length(PieceLength::Custom(32 * 1024))
+        .set_padding(true)
+        .build(2, &root, |_| ())
+        .unwrap();
+    let metainfo = Metainfo::from_bytes(&metainfo_bytes).unwrap();
+    let info = metainfo.info();
+
+    let files: Vec<(PathBuf, u64, bool)> = info
+        .files()
+        .map(|file| (file.path().to_path_buf(), file.length(), file.is_padding()))
+        .collect();
+    assert_eq!(
+        files,
+        vec![
+            (PathBuf::from("a.bin"), 70000, false),
+            (PathBuf::from(".pad/28304"), 28304, true),
+            (PathBuf::from("dir/b.bin"), 20000, false),
+        ]
+    );
+    assert_eq!(info.pieces().count(), 4);
+    assert_eq!(info.v2_info_hash(), None);
+
+    // The piece after the padding starts with the second file
+    let piece_after_padding = info.pieces().nth(3).unwrap();
+    assert_eq!(piece_after_padding, ShaHash::from_bytes(&data_b).as_ref());
+
+    fs::remove_dir_all(root.parent().unwrap()).unwrap();
+}
+
+#[test]
+fn negative_build_hybrid_torrent_piece_length() {
+    let data = [0u8; 1000];
+    let accessor = DirectAccessor::new("file.bin", &data);
+
+    let result = MetainfoBuilder::new()
+        .set_piece_length(PieceLength::Custom(1000))
+        .set_hybrid(true)
+        .build(1, accessor, |_| ());
+
+    match result.unwrap_err().kind() {
+        &ParseErrorKind::InvalidData { .. } => (),
+        unexpected @ _ => panic!("Unexpected Error: {:?}", unexpected),
+    }
+}
+
+#[test]
+fn positive_edit_trackers_keeps_info_hash() {
+    let trackers = vec![vec![TRACKER.to_string()]];
+    let metainfo = Metainfo::from_bytes(CROSS_SEED_TORRENT).unwrap();
+    let info_bytes = metainfo.info_bytes().to_vec();
+    assert_eq!(
+        metainfo.info().info_hash(),
+        hex_info_hash(CROSS_SEED_INFO_HASH)
+    );
+
+    let metainfo_bytes = metainfo
+        .set_main_tracker(Some(TRACKER))
+        .set_trackers(Some(&trackers))
+        .to_bytes();
+    let metainfo = Metainfo::from_bytes(&metainfo_bytes).unwrap();
+
+    assert_eq!(metainfo.main_tracker(), Some(TRACKER));
+    assert_eq!(metainfo.trackers(), Some(&trackers));
+    assert_eq!(metainfo.created_by(), Some("mktorrent 1.1"));
+    assert_eq!(metainfo.creation_date(), Some(1700000000));
+    assert_eq!(
+        metainfo.info().info_hash(),
+        hex_info_hash(CROSS_SEED_INFO_HASH)
+    );
+    assert_eq!(metainfo.info_bytes(), &info_bytes[..]);
+    assert_eq!(metainfo.info().to_bytes(), info_bytes);
+
+    // Outer keys are sorted, with the unknown key kept after the info dictionary
+    let expected_bytes = [
+        &b"d8:announce22:udp://foo.bar.baz:696913:announce-listll22:udp://foo.bar.baz:6969ee"[..],
+        &b"10:created by13:mktorrent 1.113:creation datei1700000000e4:info"[..],
+        &info_bytes[..],
+        &b"13:publisher-url20:https://example.com/e"[..],
+    ]
+    .concat();
+    assert_eq!(metainfo_bytes, expected_bytes);
+    assert_eq!(metainfo.to_bytes(), metainfo_bytes);
+}
+
+#[test]
+fn positive_hybrid_torrent_round_trip() {
+    // Piece layers are not known to `Metainfo`, but are kept as they were
+    let metainfo = Metainfo::from_bytes(HYBRID_TORRENT).unwrap();
+
+    assert_eq!(metainfo.to_bytes(), HYBRID_TORRENT);
+}
+
+/// How parsing a broken torrent is expected to fail.
+enum Broken {
+    /// Bencode error of the given kind, at the given byte offset.
+    Bencode(&'static str, usize),
+    /// Conversion error of the given kind, for the value at the given path.
+    Convert(&'static str, &'static str),
+}
+
+fn single_file_torrent(info: &[u8]) -> Vec<u8> {
+    [
+        &b"d8:announce22:udp://foo.bar.baz:69694:info"[..],
+        info,
+        b"e",
+    ]
+    .concat()
+}
+
+const SINGLE_FILE_INFO: &'static [u8] =
+    b"d6:lengthi10e4:name5:a.txt12:piece lengthi16384e6:pieces20:aaaaaaaaaaaaaaaaaaaae";
+
+fn multi_file_info(files: &[u8]) -> Vec<u8> {
+    [
+        &b"d5:filesl"[..],
+        files,
+        b"e4:name3:dir12:piece lengthi16384e6:pieces20:aaaaaaaaaaaaaaaaaaaae",
+    ]
+    .concat()
+}
+
+fn v2_torrent(file_tree: &[u8]) -> Vec<u8> {
+    [
+        &b"d4:infod9:file tree"[..],
+        file_tree,
+        b"12:meta versioni2e4:name1:x12:piece lengthi16384eee",
+    ]
+    .concat()
+}
+
+/// Offset of the first occurrence of the pattern in the bytes.
+fn offset_of(bytes: &[u8], pattern: &[u8]) -> usize {
+    bytes
+        .windows(pattern.len())
+        .position(|window| window == pattern)
+        .unwrap()
+}
+
+/// Torrents broken in every way we report, along with how parsing them should fail.
+fn broken_torrents() -> Vec<(&'static str, Vec<u8>, Broken)> {
+    let valid = single_file_torrent(SINGLE_FILE_INFO);
+    let info_start = offset_of(&valid, b"4:info") + 6;
+    let mut corpus = Vec::new();
+
+    let truncated = valid[..valid.len() - 2].to_vec();
+    corpus.push((
+        "truncated",
+        truncated,
+        Broken::Bencode("InvalidContainerNoEnd", valid.len() - 2),
+    ));
+
+    let mut unexpected_byte = valid.clone();
+    let length_start = offset_of(&valid, b"i10e");
+    unexpected_byte[length_start] = b'x';
+    corpus.push((
+        "unexpected byte",
+        unexpected_byte,
+        Broken::Bencode("InvalidByte", length_start),
+    ));
+
+    let overflow = single_file_torrent(
+        b"d6:lengthi99999999999999999999e4:name5:a.txt12:piece lengthi16384e6:pieces0:e",
+    );
+    corpus.push((
+        "integer overflow",
+        overflow,
+        Broken::Bencode("InvalidIntOverflow", info_start + 10),
+    ));
+
+    let duplicate = single_file_torrent(
+        b"d6:lengthi10e4:name5:a.txt4:name5:b.txt12:piece lengthi16384e6:pieces0:e",
+    );
+    corpus.push((
+        "duplicate key",
+        duplicate,
+        Broken::Bencode("InvalidKeyDuplicates", info_start + 32),
+    ));
+
+    let mut trailing = valid.clone();
+    trailing.extend_from_slice(b"garbage");
+    corpus.push((
+        "trailing bytes",
+        trailing,
+        Broken::Bencode("InvalidByte", valid.len()),
+    ));
+
+    let no_piece_length =
+        single_file_torrent(b"d6:lengthi10e4:name5:a.txt6:pieces20:aaaaaaaaaaaaaaaaaaaae");
+    corpus.push((
+        "missing piece length",
+        no_piece_length,
+        Broken::Convert("MissingKey", "info.piece length"),
+    ));
+
+    let no_file_length = single_file_torrent(&multi_file_info(
+        b"d6:lengthi1e4:pathl1:aeed4:pathl1:b1:ceed6:lengthi2e4:pathl1:dee",
+    ));
+    corpus.push((
+        "missing file length",
+        no_file_length,
+        Broken::Convert("MissingKey", "info.files[1].length"),
+    ));
+
+    let file_not_dict = single_file_torrent(&multi_file_info(b"i1e"));
+    corpus.push((
+        "file not a dictionary",
+        file_not_dict,
+        Broken::Convert("WrongType", "info.files[0]"),
+    ));
+
+    let path_not_str = single_file_torrent(&multi_file_info(
+        b"d6:lengthi1e4:pathl1:aeed6:lengthi2e4:pathl1:bi3eee",
+    ));
+    corpus.push((
+        "path element not a string",
+        path_not_str,
+        Broken::Convert("WrongType", "info.files[1].path[1]"),
+    ));
+
+    corpus
+}
+
+fn assert_broken(name: &str, error: &ParseErrorKind, expected: &Broken) {
+    match (error, expected) {
+        (&ParseErrorKind::BencodeParse(ref error), &Broken::Bencode(kind, pos)) => {
+            assert!(
+                format!("{:?}", error.kind()).starts_with(kind),
+                "{}: {:?}",
+                name,
+                error
+            );
+            assert_eq!(error.pos(), Some(pos), "{}: {:?}", name, error);
+        }
+        (&ParseErrorKind::BencodeConvert(ref error), &Broken::Convert(kind, path)) => {
+            assert!(
+                format!("{:?}", error.kind()).starts_with(kind),
+                "{}: {:?}",
+                name,
+                error
+            );
+            assert_eq!(error.path(), Some(path), "{}: {:?}", name, error);
+            assert!(error.to_string().contains(path), "{}: {}", name, error);
+        }
+        (unexpected @ _, _) => panic!("{}: Unexpected Error: {:?}", name, unexpected),
+    }
+}
+
+#[test]
+fn negative_parse_broken_torrents() {
+    for (name, torrent, expected) in broken_torrents() {
+        let error = Metainfo::from_bytes(&torrent).unwrap_err();
+        assert_broken(name, error.kind(), &expected);
+
+        let error = Metainfo::from_bytes_strict(&torrent).unwrap_err();
+        assert_broken(name, error.kind(), &expected);
+    }
+}
+
+#[test]
+fn negative_parse_strict_unsorted_keys() {
+    let unsorted = single_file_torrent(
+        b"d4:name5:a.txt6:lengthi10e12:piece lengthi16384e6:pieces20:aaaaaaaaaaaaaaaaaaaae",
+    );
+    let info_start = offset_of(&unsorted, b"4:info") + 6;
+
+    Metainfo::from_bytes(&unsorted).unwrap();
+    let error = Metainfo::from_bytes_strict(&unsorted).unwrap_err();
+    assert_broken(
+        "unsorted keys",
+        error.kind(),
+        &Broken::Bencode("InvalidKeyOrdering", info_start + 14),
+    );
+
+    // Keys written out of order by other clients are only accepted when not strict
+    Metainfo::from_bytes(CROSS_SEED_TORRENT).unwrap();
+    assert!(Metainfo::from_bytes_strict(CROSS_SEED_TORRENT).is_err());
+    Metainfo::from_bytes_strict(single_file_torrent(SINGLE_FILE_INFO)).unwrap();
+}
+
+#[test]
+fn negative_parse_v2_broken_file_tree() {
+    let non_utf8_key = v2_torrent(b"d3:dird2:\xffad0:d6:lengthi1eeeee");
+    assert_broken(
+        "non utf-8 key",
+        MetainfoV2::from_bytes(&non_utf8_key).unwrap_err().kind(),
+        &Broken::Convert("NonUtf8Key", "info.file tree.dir.\u{FFFD}a"),
+    );
+
+    let no_length = v2_torrent(b"d3:dird1:ad0:deeee");
+    assert_broken(
+        "missing file length",
+        MetainfoV2::from_bytes(&no_length).unwrap_err().kind(),
+        &Broken::Convert("MissingKey", "info.file tree.dir.a.length"),
+    );
+}
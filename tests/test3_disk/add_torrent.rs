@@ -1,11 +1,12 @@
 use futures::{SinkExt, StreamExt};
-use tokio::test;
 
-use super::{InMemoryFileSystem, MultiFileDirectAccessor};
-use bittorrent_protocol::disk::{DiskManagerBuilder, FileSystem, IDiskMessage, ODiskMessage};
+use super::MultiFileDirectAccessor;
+use bittorrent_protocol::disk::{
+    DiskManagerBuilder, FileSystem, IDiskMessage, MemoryFileSystem, ODiskMessage,
+};
 use bittorrent_protocol::metainfo::{Metainfo, MetainfoBuilder, PieceLength};
 
-#[test]
+#[tokio::test(flavor = "multi_thread")]
 async fn positive_add_torrent() {
     // Create some "files" as random bytes
     let data_a = (super::random_buffer(50), "/path/to/file/a".into());
@@ -24,16 +25,15 @@ async fn positive_add_torrent() {
     let metainfo_file = Metainfo::from_bytes(metainfo_bytes).unwrap();
 
     // Spin up a disk manager and add our created torrent to it
-    let filesystem = InMemoryFileSystem::new();
+    let filesystem = MemoryFileSystem::new();
     let disk_manager = DiskManagerBuilder::new().build(filesystem.clone());
 
     let (mut send, mut recv) = disk_manager.into_parts();
-    tokio::spawn(async move {
-         send.send(IDiskMessage::AddTorrent(metainfo_file)).await
-        }
-    );
+    send.send(IDiskMessage::AddTorrent(metainfo_file))
+        .await
+        .unwrap();
 
-    let good_pieces = 0;
+    let mut good_pieces = 0;
 
     // Run a core loop until we get the TorrentAdded message
     loop {
@@ -41,7 +41,7 @@ async fn positive_add_torrent() {
 
         match msg {
             ODiskMessage::TorrentAdded(_) => break,
-            ODiskMessage::FoundGoodPiece(_, _) => good_pieces + 1,
+            ODiskMessage::FoundGoodPiece(_, _) => good_pieces += 1,
             unexpected @ _ => panic!("Unexpected Message: {:?}", unexpected),
         };
     }
@@ -0,0 +1,182 @@
+use std::path::PathBuf;
+
+use bittorrent_protocol::disk::error::TorrentErrorKind;
+use bittorrent_protocol::disk::{
+    AllocationMode, DiskManagerBuilder, DiskManagerStream, IDiskMessage, MemoryFileSystem,
+    ODiskMessage,
+};
+use futures::{SinkExt, StreamExt};
+
+/// Length of the file following our tiny file, spanning multiple zero fill chunks.
+const FILE_B_LEN: usize = 3 * 1024 * 1024 / 2;
+const PIECE_LEN: usize = 16 * 1024;
+
+fn file_sizes(filesystem: &MemoryFileSystem) -> Vec<(PathBuf, Vec<u8>)> {
+    let mut files: Vec<(PathBuf, Vec<u8>)> =
+        filesystem.run_with_lock(|files| files.clone().into_iter().collect());
+    files.sort();
+
+    files
+}
+
+async fn next_message(recv: &mut DiskManagerStream) -> ODiskMessage {
+    recv.next().await.unwrap()
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn positive_full_allocation_zero_fills() {
+    let data = super::random_buffer(1023 + FILE_B_LEN);
+    let (data_a, data_b) = data.split_at(1023);
+    let metainfo_file = super::build_torrent(&[(data_a, "a"), (data_b, "b")], PIECE_LEN);
+
+    // Our in memory file system can not reserve storage, so files are filled with zeroes
+    let filesystem = MemoryFileSystem::new();
+    let disk_manager = DiskManagerBuilder::new()
+        .with_allocation_mode(AllocationMode::Full)
+        .build(filesystem.clone());
+
+    let (mut send, mut recv) = disk_manager.into_parts();
+    send.send(IDiskMessage::AddTorrent(metainfo_file))
+        .await
+        .unwrap();
+
+    let mut progress = Vec::new();
+    loop {
+        match next_message(&mut recv).await {
+            ODiskMessage::AllocationProgress(_, allocated, total) => {
+                progress.push((allocated, total))
+            }
+            ODiskMessage::TorrentAdded(_) => break,
+            unexpected @ _ => panic!("Unexpected Message: {:?}", unexpected),
+        };
+    }
+
+    let total = (1023 + FILE_B_LEN) as u64;
+    assert_eq!(
+        vec![
+            (1023, total),
+            (1023 + 1024 * 1024, total),
+            (total, total)
+        ],
+        progress
+    );
+
+    let files = file_sizes(&filesystem);
+    assert_eq!(1023, files[0].1.len());
+    assert_eq!(FILE_B_LEN, files[1].1.len());
+    assert!(files
+        .iter()
+        .all(|(_, bytes)| bytes.iter().all(|&byte| byte == 0)));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn negative_full_allocation_out_of_space() {
+    let data = super::random_buffer(1023 + FILE_B_LEN);
+    let (data_a, data_b) = data.split_at(1023);
+    let metainfo_file = super::build_torrent(&[(data_a, "a"), (data_b, "b")], PIECE_LEN);
+    let info_hash = metainfo_file.info().info_hash();
+
+    let filesystem = MemoryFileSystem::new();
+    filesystem.fail_writes_after(1024);
+    let disk_manager = DiskManagerBuilder::new()
+        .with_allocation_mode(AllocationMode::Full)
+        .build(filesystem);
+
+    let (mut send, mut recv) = disk_manager.into_parts();
+    send.send(IDiskMessage::AddTorrent(metainfo_file))
+        .await
+        .unwrap();
+
+    loop {
+        match next_message(&mut recv).await {
+            ODiskMessage::AllocationProgress(_, _, _) => (),
+            ODiskMessage::TorrentError(hash, err) => {
+                assert_eq!(info_hash, hash);
+
+                match err.kind() {
+                    &TorrentErrorKind::AllocationFailed {
+                        ref file_path,
+                        required_bytes,
+                    } => {
+                        assert!(file_path.ends_with("b"));
+                        assert_eq!(FILE_B_LEN as u64, required_bytes);
+                    }
+                    other => panic!("Unexpected Error {:?}", other),
+                }
+                break;
+            }
+            unexpected @ _ => panic!("Unexpected Message: {:?}", unexpected),
+        };
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn positive_dont_allocate_resumes_partial_files() {
+    let data = super::random_buffer(1023 + FILE_B_LEN);
+    let (data_a, data_b) = data.split_at(1023);
+    let metainfo_file = super::build_torrent(&[(data_a, "a"), (data_b, "b")], PIECE_LEN);
+    let info_hash = metainfo_file.info().info_hash();
+
+    let filesystem = MemoryFileSystem::new();
+    let disk_manager = DiskManagerBuilder::new()
+        .with_allocation_mode(AllocationMode::DontAllocate)
+        .build(filesystem.clone());
+
+    let (mut send, mut recv) = disk_manager.into_parts();
+    send.send(IDiskMessage::AddTorrent(metainfo_file.clone()))
+        .await
+        .unwrap();
+
+    match next_message(&mut recv).await {
+        ODiskMessage::TorrentAdded(_) => (),
+        unexpected @ _ => panic!("Unexpected Message: {:?}", unexpected),
+    };
+    assert!(file_sizes(&filesystem)
+        .iter()
+        .all(|(_, bytes)| bytes.is_empty()));
+
+    // Piece 0 spans the first file and the start of the second file
+    super::send_block(
+        send.clone(),
+        &data[0..PIECE_LEN],
+        info_hash,
+        0,
+        0,
+        PIECE_LEN,
+        |_| (),
+    );
+
+    let (mut good_piece, mut processed) = (false, false);
+    while !good_piece || !processed {
+        match next_message(&mut recv).await {
+            ODiskMessage::FoundGoodPiece(_, 0) => good_piece = true,
+            ODiskMessage::BlockProcessed(_) => processed = true,
+            unexpected @ _ => panic!("Unexpected Message: {:?}", unexpected),
+        };
+    }
+    assert_eq!(PIECE_LEN - 1023, file_sizes(&filesystem)[1].1.len());
+
+    // Re-adding the torrent checks the pieces already on disk, leaving the rest alone
+    send.send(IDiskMessage::RemoveTorrent(info_hash))
+        .await
+        .unwrap();
+    match next_message(&mut recv).await {
+        ODiskMessage::TorrentRemoved(_) => (),
+        unexpected @ _ => panic!("Unexpected Message: {:?}", unexpected),
+    };
+
+    send.send(IDiskMessage::AddTorrent(metainfo_file))
+        .await
+        .unwrap();
+
+    let mut good_pieces = Vec::new();
+    loop {
+        match next_message(&mut recv).await {
+            ODiskMessage::FoundGoodPiece(_, index) => good_pieces.push(index),
+            ODiskMessage::TorrentAdded(_) => break,
+            unexpected @ _ => panic!("Unexpected Message: {:?}", unexpected),
+        };
+    }
+    assert_eq!(vec![0], good_pieces);
+    assert_eq!(PIECE_LEN - 1023, file_sizes(&filesystem)[1].1.len());
+}
@@ -0,0 +1,201 @@
+use std::io;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use bittorrent_protocol::disk::{
+    BlockMetadata, BlockMut, DiskManagerBuilder, DiskManagerSink, DiskManagerStream, FileSystem,
+    IDiskMessage, MemoryFile, MemoryFileSystem, ODiskMessage,
+};
+use bittorrent_protocol::util::bt::InfoHash;
+use bytes::BytesMut;
+use futures::{SinkExt, StreamExt};
+
+/// Load the block once for each of the given number of peers, returning the bytes loaded.
+async fn load_block(
+    send: &mut DiskManagerSink<CountingFileSystem>,
+    recv: &mut DiskManagerStream,
+    metadata: BlockMetadata,
+    peers: usize,
+) -> Vec<Vec<u8>> {
+    for _ in 0..peers {
+        let mut bytes = BytesMut::new();
+        bytes.extend_from_slice(&vec![0u8; metadata.block_length()]);
+
+        send.send(IDiskMessage::LoadBlock(BlockMut::new(metadata, bytes)))
+            .await
+            .unwrap();
+    }
+
+    let mut blocks = Vec::new();
+    while blocks.len() != peers {
+        match recv.next().await.unwrap() {
+            ODiskMessage::BlockLoaded(block) => blocks.push(block[..].to_vec()),
+            unexpected @ _ => panic!("Unexpected Message: {:?}", unexpected),
+        };
+    }
+
+    blocks
+}
+
+/// Process the blocks, returning whether the piece was found good once they are processed.
+async fn process_blocks(
+    send: &DiskManagerSink<CountingFileSystem>,
+    recv: &mut DiskManagerStream,
+    hash: InfoHash,
+    blocks: &[(u64, u64, &[u8])],
+) -> bool {
+    for &(piece_index, block_offset, bytes) in blocks {
+        super::send_block(
+            send.clone(),
+            bytes,
+            hash,
+            piece_index,
+            block_offset,
+            bytes.len(),
+            |_| (),
+        );
+    }
+
+    let (mut opt_good, mut processed) = (None, 0);
+    while opt_good.is_none() || processed != blocks.len() {
+        match recv.next().await.unwrap() {
+            ODiskMessage::FoundGoodPiece(_, _) => opt_good = Some(true),
+            ODiskMessage::FoundBadPiece(_, _) => opt_good = Some(false),
+            ODiskMessage::BlockProcessed(_) => processed += 1,
+            unexpected @ _ => panic!("Unexpected Message: {:?}", unexpected),
+        };
+    }
+
+    opt_good.unwrap()
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn positive_cache_block() {
+    let data = super::random_buffer(2048);
+    let metainfo_file = super::build_torrent(&[(&data, "a")], 1024);
+    let info_hash = metainfo_file.info().info_hash();
+
+    let filesystem = CountingFileSystem {
+        inner: MemoryFileSystem::new(),
+        reads: Arc::new(AtomicUsize::new(0)),
+    };
+    let disk_manager = DiskManagerBuilder::new()
+        .with_block_cache_size(2048)
+        .build(filesystem.clone());
+    let (mut send, mut recv) = disk_manager.into_parts();
+
+    send.send(IDiskMessage::AddTorrent(metainfo_file))
+        .await
+        .unwrap();
+    match recv.next().await.unwrap() {
+        ODiskMessage::TorrentAdded(_) => (),
+        unexpected @ _ => panic!("Unexpected Message: {:?}", unexpected),
+    };
+
+    assert!(
+        process_blocks(
+            &send,
+            &mut recv,
+            info_hash,
+            &[(0, 0, &data[0..512]), (0, 512, &data[512..1024])]
+        )
+        .await
+    );
+
+    // Blocks written are served from memory, no matter how many peers want them
+    filesystem.reads.store(0, Ordering::SeqCst);
+    let first_block = BlockMetadata::new(info_hash, 0, 0, 512);
+    for block in load_block(&mut send, &mut recv, first_block, 10).await {
+        assert_eq!(&data[0..512], &block[..]);
+    }
+    assert_eq!(0, filesystem.reads.load(Ordering::SeqCst));
+
+    // Blocks not cached are read once, then served from memory
+    let whole_piece = BlockMetadata::new(info_hash, 0, 0, 1024);
+    for block in load_block(&mut send, &mut recv, whole_piece, 2).await {
+        assert_eq!(&data[0..1024], &block[..]);
+    }
+    assert_eq!(1, filesystem.reads.load(Ordering::SeqCst));
+
+    let stats = send.block_cache_stats();
+    assert_eq!(11, stats.hits());
+    assert_eq!(1, stats.misses());
+    assert_eq!(1024, stats.cached_bytes());
+
+    // Blocks of a piece failing verification are dropped, so the rewritten blocks are served
+    let corrupted = vec![0u8; 512];
+    assert!(
+        !process_blocks(
+            &send,
+            &mut recv,
+            info_hash,
+            &[(1, 0, &data[1024..1536]), (1, 512, &corrupted)]
+        )
+        .await
+    );
+    assert_eq!(1024, send.block_cache_stats().cached_bytes());
+
+    assert!(
+        process_blocks(
+            &send,
+            &mut recv,
+            info_hash,
+            &[(1, 0, &data[1024..1536]), (1, 512, &data[1536..2048])]
+        )
+        .await
+    );
+
+    filesystem.reads.store(0, Ordering::SeqCst);
+    let last_block = BlockMetadata::new(info_hash, 1, 512, 512);
+    for block in load_block(&mut send, &mut recv, last_block, 1).await {
+        assert_eq!(&data[1536..2048], &block[..]);
+    }
+    assert_eq!(0, filesystem.reads.load(Ordering::SeqCst));
+}
+
+//----------------------------------------------------------------------------//
+
+/// File system that counts the calls to read from a file.
+#[derive(Clone)]
+struct CountingFileSystem {
+    inner: MemoryFileSystem,
+    reads: Arc<AtomicUsize>,
+}
+
+impl FileSystem for CountingFileSystem {
+    type File = MemoryFile;
+
+    fn open_file<P>(&self, path: P) -> io::Result<Self::File>
+    where
+        P: AsRef<Path> + Send + 'static,
+    {
+        self.inner.open_file(path)
+    }
+
+    fn sync_file<P>(&self, path: P) -> io::Result<()>
+    where
+        P: AsRef<Path> + Send + 'static,
+    {
+        self.inner.sync_file(path)
+    }
+
+    fn file_size(&self, file: &Self::File) -> io::Result<u64> {
+        self.inner.file_size(file)
+    }
+
+    fn read_file(
+        &self,
+        file: &mut Self::File,
+        offset: u64,
+        buffer: &mut [u8],
+    ) -> io::Result<usize> {
+        self.reads.fetch_add(1, Ordering::SeqCst);
+
+        self.inner.read_file(file, offset, buffer)
+    }
+
+    fn write_file(&self, file: &mut Self::File, offset: u64, buffer: &[u8]) -> io::Result<usize> {
+        self.inner.write_file(file, offset, buffer)
+    }
+}
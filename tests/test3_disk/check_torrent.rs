@@ -0,0 +1,200 @@
+use bittorrent_protocol::disk::error::TorrentErrorKind;
+use bittorrent_protocol::disk::{
+    DiskManagerBuilder, DiskManagerSink, DiskManagerStream, IDiskMessage, MemoryFileSystem,
+    ODiskMessage,
+};
+use bittorrent_protocol::util::bt::InfoHash;
+use futures::{SinkExt, StreamExt};
+
+/// Check the torrent, returning the progress reported and the pieces found good.
+async fn check_torrent(
+    send: &mut DiskManagerSink<MemoryFileSystem>,
+    recv: &mut DiskManagerStream,
+    hash: InfoHash,
+) -> (Vec<(u64, u64, u64)>, Vec<bool>) {
+    send.send(IDiskMessage::CheckTorrent(hash)).await.unwrap();
+
+    let mut progress = Vec::new();
+    loop {
+        match recv.next().await.unwrap() {
+            ODiskMessage::TorrentCheckProgress {
+                hash: progress_hash,
+                pieces_checked,
+                pieces_total,
+                pieces_good,
+            } => {
+                assert_eq!(hash, progress_hash);
+                progress.push((pieces_checked, pieces_total, pieces_good));
+            }
+            ODiskMessage::TorrentChecked(checked_hash, good_pieces) => {
+                assert_eq!(hash, checked_hash);
+
+                return (progress, good_pieces);
+            }
+            unexpected @ _ => panic!("Unexpected Message: {:?}", unexpected),
+        };
+    }
+}
+
+/// Overwrite the file ending with the given name, without going through the disk manager.
+fn replace_file(filesystem: &MemoryFileSystem, name: &str, bytes: Option<&[u8]>) {
+    filesystem.run_with_lock(|files| {
+        let path = files
+            .keys()
+            .find(|path| path.ends_with(name))
+            .unwrap()
+            .clone();
+
+        match bytes {
+            Some(bytes) => {
+                files.insert(path, bytes.to_vec());
+            }
+            None => {
+                files.remove(&path);
+            }
+        }
+    });
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn positive_check_torrent() {
+    // Piece 0 spans both files
+    let data_a = super::random_buffer(1023);
+    let data_b = super::random_buffer(2000);
+    let metainfo_file = super::build_torrent(&[(&data_a, "a"), (&data_b, "b")], 1024);
+    let info_hash = metainfo_file.info().info_hash();
+
+    let filesystem = MemoryFileSystem::new();
+    let disk_manager = DiskManagerBuilder::new().build(filesystem.clone());
+    let (mut send, mut recv) = disk_manager.into_parts();
+
+    // Pieces of zeroes are good right after the files were allocated
+    super::add_torrent(
+        &mut send,
+        &mut recv,
+        IDiskMessage::AddTorrent(metainfo_file),
+    )
+    .await;
+
+    // Data shows up behind our back, with a corrupted last piece
+    let mut corrupted_b = data_b.clone();
+    corrupted_b[1999] ^= 0xFF;
+    replace_file(&filesystem, "a", Some(&data_a));
+    replace_file(&filesystem, "b", Some(&corrupted_b));
+
+    let (progress, good_pieces) = check_torrent(&mut send, &mut recv, info_hash).await;
+    assert_eq!(vec![true, true, false], good_pieces);
+    // Pieces are hashed in parallel, so they may be found good in any order
+    assert_eq!(
+        vec![1, 2, 3],
+        progress
+            .iter()
+            .map(|&(checked, _, _)| checked)
+            .collect::<Vec<u64>>()
+    );
+    assert_eq!(Some(&(3, 3, 2)), progress.last());
+
+    // Writing the last piece completes the torrent, the checked pieces are not found again
+    super::send_block(send.clone(), &data_b[1025..], info_hash, 2, 0, 975, |_| ());
+
+    let (mut good_piece, mut processed) = (false, false);
+    while !good_piece || !processed {
+        match recv.next().await.unwrap() {
+            ODiskMessage::FoundGoodPiece(_, 2) => good_piece = true,
+            ODiskMessage::BlockProcessed(_) => processed = true,
+            unexpected @ _ => panic!("Unexpected Message: {:?}", unexpected),
+        };
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn positive_check_torrent_missing_file() {
+    let data_a = super::random_buffer(1023);
+    let data_b = super::random_buffer(2000);
+    // File c is all zeroes, so it is good when missing
+    let data_c = vec![0u8; 1072];
+    let metainfo_file =
+        super::build_torrent(&[(&data_a, "a"), (&data_b, "b"), (&data_c, "c")], 1024);
+    let info_hash = metainfo_file.info().info_hash();
+
+    let filesystem = MemoryFileSystem::new();
+    let disk_manager = DiskManagerBuilder::new().build(filesystem.clone());
+    let (mut send, mut recv) = disk_manager.into_parts();
+
+    super::add_torrent(
+        &mut send,
+        &mut recv,
+        IDiskMessage::AddTorrent(metainfo_file),
+    )
+    .await;
+
+    // File a is missing, file b was truncated half way through piece 2
+    replace_file(&filesystem, "a", None);
+    replace_file(&filesystem, "b", Some(&data_b[..1500]));
+    replace_file(&filesystem, "c", None);
+
+    let (_, good_pieces) = check_torrent(&mut send, &mut recv, info_hash).await;
+    assert_eq!(vec![false, true, false, true], good_pieces);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn negative_check_torrent_cancelled() {
+    let data = super::random_buffer(512 * 1024);
+    let metainfo_file = super::build_torrent(&[(&data, "a")], 1024);
+    let info_hash = metainfo_file.info().info_hash();
+
+    let filesystem = MemoryFileSystem::new();
+    let disk_manager = DiskManagerBuilder::new().build(filesystem.clone());
+    let (mut send, mut recv) = disk_manager.into_parts();
+
+    super::add_torrent(
+        &mut send,
+        &mut recv,
+        IDiskMessage::AddTorrent(metainfo_file),
+    )
+    .await;
+    assert!(!send.cancel_check(info_hash));
+
+    send.send(IDiskMessage::CheckTorrent(info_hash))
+        .await
+        .unwrap();
+    match recv.next().await.unwrap() {
+        ODiskMessage::TorrentCheckProgress { .. } => (),
+        unexpected @ _ => panic!("Unexpected Message: {:?}", unexpected),
+    };
+
+    // Only one check runs for a torrent at a time
+    send.send(IDiskMessage::CheckTorrent(info_hash))
+        .await
+        .unwrap();
+    loop {
+        match recv.next().await.unwrap() {
+            ODiskMessage::TorrentCheckProgress { .. } => (),
+            ODiskMessage::TorrentError(_, err) => match err.kind() {
+                &TorrentErrorKind::ExistingCheck { hash } => {
+                    assert_eq!(info_hash, hash);
+                    break;
+                }
+                other => panic!("Unexpected Error {:?}", other),
+            },
+            unexpected @ _ => panic!("Unexpected Message: {:?}", unexpected),
+        };
+    }
+
+    assert!(send.cancel_check(info_hash));
+    loop {
+        match recv.next().await.unwrap() {
+            ODiskMessage::TorrentCheckProgress {
+                pieces_checked,
+                pieces_total,
+                ..
+            } => assert!(pieces_checked < pieces_total),
+            ODiskMessage::TorrentCheckCancelled(hash) => {
+                assert_eq!(info_hash, hash);
+                break;
+            }
+            unexpected @ _ => panic!("Unexpected Message: {:?}", unexpected),
+        };
+    }
+    assert!(!send.cancel_check(info_hash));
+}
@@ -1,10 +1,10 @@
 use futures::{SinkExt, StreamExt};
-use tokio::test;
-use super::{InMemoryFileSystem, MultiFileDirectAccessor};
-use bittorrent_protocol::disk::{DiskManagerBuilder, IDiskMessage, ODiskMessage};
+
+use super::MultiFileDirectAccessor;
+use bittorrent_protocol::disk::{DiskManagerBuilder, IDiskMessage, MemoryFileSystem, ODiskMessage};
 use bittorrent_protocol::metainfo::{Metainfo, MetainfoBuilder, PieceLength};
 
-#[test]
+#[tokio::test(flavor = "multi_thread")]
 async fn positive_complete_torrent() {
     // Create some "files" as random bytes
     let data_a = (super::random_buffer(1023), "/path/to/file/a".into());
@@ -22,22 +22,23 @@ async fn positive_complete_torrent() {
     let metainfo_file = Metainfo::from_bytes(metainfo_bytes).unwrap();
 
     // Spin up a disk manager and add our created torrent to it
-    let filesystem = InMemoryFileSystem::new();
+    let filesystem = MemoryFileSystem::new();
     let disk_manager = DiskManagerBuilder::new().build(filesystem.clone());
 
     let (mut blocking_send, mut recv) = disk_manager.into_parts();
 
     blocking_send
         .send(IDiskMessage::AddTorrent(metainfo_file.clone()))
+        .await
         .unwrap();
 
-    let good_pieces = 0;
+    let mut good_pieces = 0;
 
     // Run a core loop until we get the TorrentAdded message
     loop {
-        match recv.next().unwrap() {
+        match recv.next().await.unwrap() {
             ODiskMessage::TorrentAdded(_) => break,
-            ODiskMessage::FoundGoodPiece(_, _) => good_pieces + 1,
+            ODiskMessage::FoundGoodPiece(_, _) => good_pieces += 1,
             unexpected @ _ => panic!("Unexpected Message: {:?}", unexpected),
         };
     }
@@ -137,7 +138,8 @@ async fn positive_complete_torrent() {
     let mut messages_recvd = 0;
 
     loop {
-        let msg = recv.next().unwrap();
+        let msg = recv.next().await.unwrap();
+        messages_recvd += 1;
 
         // Map BlockProcessed to a None piece index so we don't update our state
         let (opt_piece_index, new_value) = match msg {
@@ -147,11 +149,11 @@ async fn positive_complete_torrent() {
             unexpected @ _ => panic!("Unexpected Message: {:?}", unexpected),
         };
 
-        let (piece_zero_good, piece_one_good, piece_two_good) = match opt_piece_index {
-            None => (piece_zero_good, piece_one_good, piece_two_good),
-            Some(0) => (new_value, piece_one_good, piece_two_good),
-            Some(1) => (piece_zero_good, new_value, piece_two_good),
-            Some(2) => (piece_zero_good, piece_one_good, new_value),
+        match opt_piece_index {
+            None => (),
+            Some(0) => piece_zero_good = new_value,
+            Some(1) => piece_one_good = new_value,
+            Some(2) => piece_two_good = new_value,
             Some(x) => panic!("Unexpected Index {:?}", x),
         };
 
@@ -200,9 +202,8 @@ async fn positive_complete_torrent() {
     let mut messages_recvd = 0;
 
     loop {
-        let msg = recv.next().unwrap();
-
-        let messages_recvd = messages_recvd + 1;
+        let msg = recv.next().await.unwrap();
+        messages_recvd += 1;
 
         // Map BlockProcessed to a None piece index so we don't update our state
         let (opt_piece_index, new_value) = match msg {
@@ -212,9 +213,9 @@ async fn positive_complete_torrent() {
             unexpected @ _ => panic!("Unexpected Message: {:?}", unexpected),
         };
 
-        piece_zero_good = match opt_piece_index {
-            None => false,
-            Some(0) => new_value,
+        match opt_piece_index {
+            None => (),
+            Some(0) => piece_zero_good = new_value,
             Some(x) => panic!("Unexpected Index {:?}", x),
         };
 
@@ -0,0 +1,163 @@
+use std::time::Duration;
+
+use bittorrent_protocol::disk::error::{BlockErrorKind, TorrentErrorKind};
+use bittorrent_protocol::disk::{
+    AllocationMode, DiskFault, DiskManagerBuilder, DiskManagerStream, FaultPolicy, IDiskMessage,
+    MemoryFileSystem, ODiskMessage,
+};
+use futures::{SinkExt, StreamExt};
+
+/// Wait for the piece to be found good, and its block to be processed.
+async fn expect_good_piece(recv: &mut DiskManagerStream, piece_index: u64) {
+    let (mut good, mut processed) = (false, false);
+    while !good || !processed {
+        match recv.next().await.unwrap() {
+            ODiskMessage::FoundGoodPiece(_, index) if index == piece_index => good = true,
+            ODiskMessage::BlockProcessed(_) => processed = true,
+            unexpected @ _ => panic!("Unexpected Message: {:?}", unexpected),
+        };
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn positive_pause_on_no_space_and_resume() {
+    // Piece 0 is file a and piece 1 is file b
+    let data = super::random_buffer(2048);
+    let (data_a, data_b) = data.split_at(1024);
+    let metainfo_file = super::build_torrent(&[(data_a, "a"), (data_b, "b")], 1024);
+    let info_hash = metainfo_file.info().info_hash();
+
+    let filesystem = MemoryFileSystem::new();
+    // Files grow as blocks are written
+    let (mut send, mut recv) = DiskManagerBuilder::new()
+        .with_allocation_mode(AllocationMode::DontAllocate)
+        .build(filesystem.clone())
+        .into_parts();
+    super::add_torrent(
+        &mut send,
+        &mut recv,
+        IDiskMessage::AddTorrent(metainfo_file),
+    )
+    .await;
+    filesystem.fail_writes_after(1024);
+
+    super::send_piece(&send, &data, info_hash, 0);
+    expect_good_piece(&mut recv, 0).await;
+
+    // The disk fills up, which is not worth retrying
+    super::send_piece(&send, &data, info_hash, 1);
+    match recv.next().await.unwrap() {
+        ODiskMessage::TorrentPaused(hash, DiskFault::OutOfSpace) => assert_eq!(info_hash, hash),
+        unexpected @ _ => panic!("Unexpected Message: {:?}", unexpected),
+    };
+    assert_eq!(Some(Vec::new()), filesystem.file_contents("downloads/b"));
+
+    // Once space is freed, the failed block is written without downloading piece 0 again
+    filesystem.clear_faults();
+    send.send(IDiskMessage::ResumeTorrent(info_hash))
+        .await
+        .unwrap();
+    match recv.next().await.unwrap() {
+        ODiskMessage::TorrentResumed(hash) => assert_eq!(info_hash, hash),
+        unexpected @ _ => panic!("Unexpected Message: {:?}", unexpected),
+    };
+    expect_good_piece(&mut recv, 1).await;
+
+    assert_eq!(
+        Some(data[..1024].to_vec()),
+        filesystem.file_contents("downloads/a")
+    );
+    assert_eq!(
+        Some(data[1024..].to_vec()),
+        filesystem.file_contents("downloads/b")
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn positive_retry_transient_error() {
+    let data = super::random_buffer(2048);
+    let (data_a, data_b) = data.split_at(1024);
+    let metainfo_file = super::build_torrent(&[(data_a, "a"), (data_b, "b")], 1024);
+    let info_hash = metainfo_file.info().info_hash();
+
+    let filesystem = MemoryFileSystem::new();
+    let policy = FaultPolicy::new().with_backoff(Duration::from_millis(1));
+    let (mut send, mut recv) = DiskManagerBuilder::new()
+        .with_allocation_mode(AllocationMode::DontAllocate)
+        .with_fault_policy(policy)
+        .build(filesystem.clone())
+        .into_parts();
+    super::add_torrent(
+        &mut send,
+        &mut recv,
+        IDiskMessage::AddTorrent(metainfo_file),
+    )
+    .await;
+
+    filesystem.fail_next_write("downloads/a");
+    super::send_piece(&send, &data, info_hash, 0);
+    expect_good_piece(&mut recv, 0).await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn negative_no_pause_without_policy() {
+    let data = super::random_buffer(2048);
+    let (data_a, data_b) = data.split_at(1024);
+    let metainfo_file = super::build_torrent(&[(data_a, "a"), (data_b, "b")], 1024);
+    let info_hash = metainfo_file.info().info_hash();
+
+    let filesystem = MemoryFileSystem::new();
+    let policy = FaultPolicy::new().with_pause_on_fault(false);
+    let (mut send, mut recv) = DiskManagerBuilder::new()
+        .with_allocation_mode(AllocationMode::DontAllocate)
+        .with_fault_policy(policy)
+        .build(filesystem.clone())
+        .into_parts();
+    super::add_torrent(
+        &mut send,
+        &mut recv,
+        IDiskMessage::AddTorrent(metainfo_file),
+    )
+    .await;
+
+    filesystem.fail_writes_after(0);
+    super::send_piece(&send, &data, info_hash, 0);
+    match recv.next().await.unwrap() {
+        ODiskMessage::ProcessBlockError(_, err) => match err.kind() {
+            &BlockErrorKind::Io(_) => (),
+            unexpected @ _ => panic!("Unexpected Error: {:?}", unexpected),
+        },
+        unexpected @ _ => panic!("Unexpected Message: {:?}", unexpected),
+    };
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn negative_resume_torrent_not_paused() {
+    let data = super::random_buffer(2048);
+    let (data_a, data_b) = data.split_at(1024);
+    let metainfo_file = super::build_torrent(&[(data_a, "a"), (data_b, "b")], 1024);
+    let info_hash = metainfo_file.info().info_hash();
+
+    let filesystem = MemoryFileSystem::new();
+    let (mut send, mut recv) = DiskManagerBuilder::new()
+        .with_allocation_mode(AllocationMode::DontAllocate)
+        .build(filesystem.clone())
+        .into_parts();
+    super::add_torrent(
+        &mut send,
+        &mut recv,
+        IDiskMessage::AddTorrent(metainfo_file),
+    )
+    .await;
+
+    send.send(IDiskMessage::ResumeTorrent(info_hash))
+        .await
+        .unwrap();
+    match recv.next().await.unwrap() {
+        ODiskMessage::TorrentError(_, err) => match err.kind() {
+            &TorrentErrorKind::TorrentNotPaused { hash } => assert_eq!(info_hash, hash),
+            unexpected @ _ => panic!("Unexpected Error: {:?}", unexpected),
+        },
+        unexpected @ _ => panic!("Unexpected Message: {:?}", unexpected),
+    };
+}
@@ -1,10 +1,10 @@
 use futures::{SinkExt, StreamExt};
-use tokio::test;
-use super::{InMemoryFileSystem, MultiFileDirectAccessor};
-use bittorrent_protocol::disk::{DiskManagerBuilder, IDiskMessage};
+
+use super::MultiFileDirectAccessor;
+use bittorrent_protocol::disk::{DiskManagerBuilder, IDiskMessage, MemoryFileSystem, ODiskMessage};
 use bittorrent_protocol::metainfo::{Metainfo, MetainfoBuilder, PieceLength};
 
-#[test]
+#[tokio::test(flavor = "multi_thread")]
 async fn positive_disk_manager_send_backpressure() {
     // Create some "files" as random bytes
     let data_a = (super::random_buffer(50), "/path/to/file/a".into());
@@ -24,17 +24,37 @@ async fn positive_disk_manager_send_backpressure() {
     let info_hash = metainfo_file.info().info_hash();
 
     // Spin up a disk manager and add our created torrent to it
-    let filesystem = InMemoryFileSystem::new();
+    let filesystem = MemoryFileSystem::new();
     let (mut m_send, mut m_recv) = DiskManagerBuilder::new()
-        .with_buffer_capacity(1)
+        .with_sink_buffer_capacity(1)
         .build(filesystem.clone())
         .into_parts();
 
     // Add a torrent, so our receiver has a single torrent added message buffered
     m_send
         .send(IDiskMessage::AddTorrent(metainfo_file))
+        .await
         .unwrap();
 
+    // Try to send a remove message (but it should fail)
+    assert!(m_send
+        .try_send(IDiskMessage::RemoveTorrent(info_hash))
+        .is_err());
+
+    // Receive from our stream to unblock the backpressure
+    match m_recv.next().await.unwrap() {
+        ODiskMessage::TorrentAdded(_) => (),
+        unexpected => panic!("Unexpected Message: {:?}", unexpected),
+    }
+
     // Try to send a remove message again which should go through
-    m_send.send(IDiskMessage::RemoveTorrent(info_hash)).unwrap();
+    m_send
+        .send(IDiskMessage::RemoveTorrent(info_hash))
+        .await
+        .unwrap();
+
+    match m_recv.next().await.unwrap() {
+        ODiskMessage::TorrentRemoved(_) => (),
+        unexpected => panic!("Unexpected Message: {:?}", unexpected),
+    }
 }
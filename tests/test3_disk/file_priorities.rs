@@ -0,0 +1,261 @@
+use std::path::PathBuf;
+
+use bittorrent_protocol::disk::error::{BlockErrorKind, TorrentErrorKind};
+use bittorrent_protocol::disk::{
+    BlockMetadata, BlockMut, DiskManagerBuilder, DiskManagerSink, DiskManagerStream, FilePriority,
+    IDiskMessage, MemoryFileSystem, ODiskMessage,
+};
+use bittorrent_protocol::util::bt::InfoHash;
+use bytes::BytesMut;
+use futures::{SinkExt, StreamExt};
+
+fn part_file_contents(filesystem: &MemoryFileSystem) -> Option<Vec<u8>> {
+    filesystem
+        .snapshot()
+        .into_iter()
+        .find(|(path, _)| path.extension().map_or(false, |ext| ext == "parts"))
+        .map(|(_, contents)| contents)
+}
+
+/// Process the whole piece, returning whether it was found good.
+async fn process_piece(
+    send: &mut DiskManagerSink<MemoryFileSystem>,
+    recv: &mut DiskManagerStream,
+    data: &[u8],
+    hash: InfoHash,
+    piece_index: u64,
+) -> bool {
+    let start = piece_index as usize * 1024;
+    super::send_block(
+        send.clone(),
+        &data[start..(start + 1024)],
+        hash,
+        piece_index,
+        0,
+        1024,
+        |_| (),
+    );
+
+    let (mut opt_good, mut processed) = (None, false);
+    while opt_good.is_none() || !processed {
+        match recv.next().await.unwrap() {
+            ODiskMessage::FoundGoodPiece(_, index) if index == piece_index => opt_good = Some(true),
+            ODiskMessage::FoundBadPiece(_, index) if index == piece_index => opt_good = Some(false),
+            ODiskMessage::BlockProcessed(_) => processed = true,
+            unexpected @ _ => panic!("Unexpected Message: {:?}", unexpected),
+        };
+    }
+
+    opt_good.unwrap()
+}
+
+async fn load_piece(
+    send: &mut DiskManagerSink<MemoryFileSystem>,
+    recv: &mut DiskManagerStream,
+    hash: InfoHash,
+    piece_index: u64,
+) -> Vec<u8> {
+    let mut bytes = BytesMut::new();
+    bytes.extend_from_slice(&[0u8; 1024]);
+
+    send.send(IDiskMessage::LoadBlock(BlockMut::new(
+        BlockMetadata::new(hash, piece_index, 0, 1024),
+        bytes,
+    )))
+    .await
+    .unwrap();
+
+    match recv.next().await.unwrap() {
+        ODiskMessage::BlockLoaded(block) => block[..].to_vec(),
+        unexpected @ _ => panic!("Unexpected Message: {:?}", unexpected),
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn positive_skipped_file_boundary_piece() {
+    // Piece 0 holds all of file a and the start of file b
+    let (data_a, data_b) = (super::random_buffer(1000), super::random_buffer(1048));
+    let metainfo_file = super::build_torrent(&[(&data_a, "a"), (&data_b, "b")], 1024);
+    let info_hash = metainfo_file.info().info_hash();
+    let data = [&data_a[..], &data_b[..]].concat();
+
+    let filesystem = MemoryFileSystem::new();
+    let (mut send, mut recv) = DiskManagerBuilder::new()
+        .build(filesystem.clone())
+        .into_parts();
+
+    super::add_torrent(
+        &mut send,
+        &mut recv,
+        IDiskMessage::AddTorrentWithPriorities(
+            metainfo_file,
+            vec![FilePriority::Skip, FilePriority::Normal],
+        ),
+    )
+    .await;
+
+    let priorities = send.piece_priorities(info_hash).unwrap();
+    assert_eq!(0, priorities.skipped_pieces().count());
+    assert_eq!(vec![0], priorities.partial_pieces().collect::<Vec<u64>>());
+
+    // The boundary piece is downloaded in full, without creating the skipped file
+    assert!(process_piece(&mut send, &mut recv, &data, info_hash, 0).await);
+    assert!(process_piece(&mut send, &mut recv, &data, info_hash, 1).await);
+    assert_eq!(
+        &data[..1024],
+        &load_piece(&mut send, &mut recv, info_hash, 0).await[..]
+    );
+
+    assert_eq!(None, filesystem.file_contents("downloads/a"));
+    assert_eq!(Some(data_b), filesystem.file_contents("downloads/b"));
+    assert_eq!(
+        &data_a[..],
+        &part_file_contents(&filesystem).unwrap()[..1000]
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn positive_unskip_file_mid_download() {
+    let (data_a, data_b) = (super::random_buffer(1000), super::random_buffer(1048));
+    let metainfo_file = super::build_torrent(&[(&data_a, "a"), (&data_b, "b")], 1024);
+    let info_hash = metainfo_file.info().info_hash();
+    let data = [&data_a[..], &data_b[..]].concat();
+
+    let filesystem = MemoryFileSystem::new();
+    let (mut send, mut recv) = DiskManagerBuilder::new()
+        .build(filesystem.clone())
+        .into_parts();
+
+    super::add_torrent(
+        &mut send,
+        &mut recv,
+        IDiskMessage::AddTorrentWithPriorities(
+            metainfo_file,
+            vec![FilePriority::Normal, FilePriority::Skip],
+        ),
+    )
+    .await;
+    assert!(process_piece(&mut send, &mut recv, &data, info_hash, 0).await);
+
+    // Pieces wholly inside skipped files are not processed
+    super::send_block(send.clone(), &data[1024..], info_hash, 1, 0, 1024, |_| ());
+    match recv.next().await.unwrap() {
+        ODiskMessage::ProcessBlockError(_, err) => match err.kind() {
+            &BlockErrorKind::PieceSkipped { index: 1, .. } => (),
+            unexpected @ _ => panic!("Unexpected Error: {:?}", unexpected),
+        },
+        unexpected @ _ => panic!("Unexpected Message: {:?}", unexpected),
+    };
+
+    send.send(IDiskMessage::SetFilePriorities(
+        info_hash,
+        vec![FilePriority::Normal, FilePriority::High],
+    ))
+    .await
+    .unwrap();
+    match recv.next().await.unwrap() {
+        ODiskMessage::FilePrioritiesSet(_, priorities) => {
+            assert_eq!(FilePriority::High, priorities.priority(1));
+            assert_eq!(0, priorities.skipped_pieces().count());
+            assert_eq!(0, priorities.partial_pieces().count());
+        }
+        unexpected @ _ => panic!("Unexpected Message: {:?}", unexpected),
+    };
+
+    // Bytes of the boundary piece kept for the skipped file were moved in to it
+    assert_eq!(
+        &data_b[..24],
+        &filesystem.file_contents("downloads/b").unwrap()[..24]
+    );
+    assert_eq!(None, part_file_contents(&filesystem));
+
+    assert!(process_piece(&mut send, &mut recv, &data, info_hash, 1).await);
+    assert_eq!(Some(data_a), filesystem.file_contents("downloads/a"));
+    assert_eq!(Some(data_b), filesystem.file_contents("downloads/b"));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn positive_skip_downloaded_file() {
+    let (data_a, data_b) = (super::random_buffer(1000), super::random_buffer(1048));
+    let metainfo_file = super::build_torrent(&[(&data_a, "a"), (&data_b, "b")], 1024);
+    let info_hash = metainfo_file.info().info_hash();
+    let data = [&data_a[..], &data_b[..]].concat();
+
+    let filesystem = MemoryFileSystem::new();
+    let (mut send, mut recv) = DiskManagerBuilder::new()
+        .build(filesystem.clone())
+        .into_parts();
+
+    super::add_torrent(
+        &mut send,
+        &mut recv,
+        IDiskMessage::AddTorrentWithPriorities(
+            metainfo_file,
+            vec![FilePriority::Normal, FilePriority::Normal],
+        ),
+    )
+    .await;
+    assert!(process_piece(&mut send, &mut recv, &data, info_hash, 0).await);
+    assert!(process_piece(&mut send, &mut recv, &data, info_hash, 1).await);
+
+    send.send(IDiskMessage::SetFilePriorities(
+        info_hash,
+        vec![FilePriority::Normal, FilePriority::Skip],
+    ))
+    .await
+    .unwrap();
+    match recv.next().await.unwrap() {
+        ODiskMessage::FilePrioritiesSet(_, priorities) => {
+            assert_eq!(vec![1], priorities.skipped_pieces().collect::<Vec<u64>>());
+            assert_eq!(vec![0], priorities.partial_pieces().collect::<Vec<u64>>());
+        }
+        unexpected @ _ => panic!("Unexpected Message: {:?}", unexpected),
+    };
+
+    // The skipped file is left alone, but the boundary piece is now read from the part file
+    assert_eq!(
+        Some(data_b.clone()),
+        filesystem.file_contents("downloads/b")
+    );
+    filesystem.seed_file(PathBuf::from("downloads/b"), vec![0u8; 1048]);
+    assert_eq!(
+        &data[..1024],
+        &load_piece(&mut send, &mut recv, info_hash, 0).await[..]
+    );
+    assert_eq!(
+        &data_b[..24],
+        &part_file_contents(&filesystem).unwrap()[1000..]
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn negative_wrong_number_of_priorities() {
+    let (data_a, data_b) = (super::random_buffer(1000), super::random_buffer(1048));
+    let metainfo_file = super::build_torrent(&[(&data_a, "a"), (&data_b, "b")], 1024);
+    let info_hash = metainfo_file.info().info_hash();
+
+    let filesystem = MemoryFileSystem::new();
+    let (mut send, mut recv) = DiskManagerBuilder::new().build(filesystem).into_parts();
+
+    send.send(IDiskMessage::AddTorrentWithPriorities(
+        metainfo_file,
+        vec![FilePriority::Skip],
+    ))
+    .await
+    .unwrap();
+    match recv.next().await.unwrap() {
+        ODiskMessage::TorrentError(hash, err) => {
+            assert_eq!(info_hash, hash);
+
+            match err.kind() {
+                &TorrentErrorKind::InvalidFilePriorities {
+                    expected: 2,
+                    actual: 1,
+                    ..
+                } => (),
+                unexpected @ _ => panic!("Unexpected Error: {:?}", unexpected),
+            }
+        }
+        unexpected @ _ => panic!("Unexpected Message: {:?}", unexpected),
+    };
+}
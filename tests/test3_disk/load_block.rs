@@ -1,13 +1,14 @@
 use futures::{SinkExt, StreamExt};
-use tokio::test;
-use super::{InMemoryFileSystem, MultiFileDirectAccessor};
+
+use super::MultiFileDirectAccessor;
 use bittorrent_protocol::disk::{
-    Block, BlockMetadata, BlockMut, DiskManagerBuilder, IDiskMessage, ODiskMessage,
+    Block, BlockMetadata, BlockMut, DiskManagerBuilder, IDiskMessage, MemoryFileSystem,
+    ODiskMessage,
 };
 use bittorrent_protocol::metainfo::{Metainfo, MetainfoBuilder, PieceLength};
 use bytes::BytesMut;
 
-#[test]
+#[tokio::test(flavor = "multi_thread")]
 async fn positive_load_block() {
     // Create some "files" as random bytes
     let data_a = (super::random_buffer(1023), "/path/to/file/a".into());
@@ -25,7 +26,7 @@ async fn positive_load_block() {
     let metainfo_file = Metainfo::from_bytes(metainfo_bytes).unwrap();
 
     // Spin up a disk manager and add our created torrent to its
-    let filesystem = InMemoryFileSystem::new();
+    let filesystem = MemoryFileSystem::new();
     let disk_manager = DiskManagerBuilder::new().build(filesystem.clone());
 
     let mut process_block_bytemut = BytesMut::new();
@@ -47,14 +48,16 @@ async fn positive_load_block() {
     let (mut blocking_send, mut recv) = disk_manager.into_parts();
     blocking_send
         .send(IDiskMessage::AddTorrent(metainfo_file))
+        .await
         .unwrap();
 
     loop {
-        let msg = recv.next().unwrap();
+        let msg = recv.next().await.unwrap();
         match msg {
             ODiskMessage::TorrentAdded(_) => {
                 blocking_send
                     .send(IDiskMessage::ProcessBlock(process_block.clone()))
+                    .await
                     .unwrap();
             }
             ODiskMessage::BlockProcessed(block) => {
@@ -62,6 +65,7 @@ async fn positive_load_block() {
 
                 blocking_send
                     .send(IDiskMessage::LoadBlock(load_block.clone()))
+                    .await
                     .unwrap();
             }
             ODiskMessage::BlockLoaded(block) => {
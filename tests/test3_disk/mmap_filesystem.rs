@@ -0,0 +1,85 @@
+use std::fs;
+
+use bittorrent_protocol::disk::{
+    BlockMetadata, BlockMut, DiskManagerBuilder, IDiskMessage, MmapFileSystem, ODiskMessage,
+};
+use bytes::BytesMut;
+use futures::{SinkExt, StreamExt};
+
+#[tokio::test(flavor = "multi_thread")]
+async fn positive_mmap_filesystem() {
+    let (data_a, data_b) = (super::random_buffer(1000), super::random_buffer(1048));
+    let data = [&data_a[..], &data_b[..]].concat();
+    let metainfo_file = super::build_torrent(&[(&data_a, "a"), (&data_b, "b")], 1024);
+    let info_hash = metainfo_file.info().info_hash();
+
+    // Small enough that the files are unmapped while the pieces are written
+    let dir = crate::temp_dir("mmap_filesystem");
+    let (mut send, mut recv) = DiskManagerBuilder::new()
+        .build(MmapFileSystem::with_directory(&dir, 1024))
+        .into_parts();
+
+    send.send(IDiskMessage::AddTorrent(metainfo_file))
+        .await
+        .unwrap();
+    match recv.next().await.unwrap() {
+        ODiskMessage::TorrentAdded(_) => (),
+        unexpected @ _ => panic!("Unexpected Message: {:?}", unexpected),
+    };
+
+    for piece_index in 0..2 {
+        let start = piece_index * 1024;
+        super::send_block(
+            send.clone(),
+            &data[start..(start + 1024)],
+            info_hash,
+            piece_index as u64,
+            0,
+            1024,
+            |_| (),
+        );
+    }
+
+    let (mut good_pieces, mut processed) = (0, 0);
+    while good_pieces != 2 || processed != 2 {
+        match recv.next().await.unwrap() {
+            ODiskMessage::FoundGoodPiece(_, _) => good_pieces += 1,
+            ODiskMessage::BlockProcessed(_) => processed += 1,
+            unexpected @ _ => panic!("Unexpected Message: {:?}", unexpected),
+        };
+    }
+
+    let mut bytes = BytesMut::new();
+    bytes.extend_from_slice(&[0u8; 1024]);
+    send.send(IDiskMessage::LoadBlock(BlockMut::new(
+        BlockMetadata::new(info_hash, 1, 0, 1024),
+        bytes,
+    )))
+    .await
+    .unwrap();
+    match recv.next().await.unwrap() {
+        ODiskMessage::BlockLoaded(block) => assert_eq!(&data[1024..2048], &block[..]),
+        unexpected @ _ => panic!("Unexpected Message: {:?}", unexpected),
+    };
+
+    send.send(IDiskMessage::CheckTorrent(info_hash))
+        .await
+        .unwrap();
+    let good_pieces = loop {
+        match recv.next().await.unwrap() {
+            ODiskMessage::TorrentCheckProgress { .. } => (),
+            ODiskMessage::TorrentChecked(_, good_pieces) => break good_pieces,
+            unexpected @ _ => panic!("Unexpected Message: {:?}", unexpected),
+        };
+    };
+
+    let (on_disk_a, on_disk_b) = (
+        fs::read(dir.join("downloads").join("a")).unwrap(),
+        fs::read(dir.join("downloads").join("b")).unwrap(),
+    );
+    fs::remove_dir_all(&dir).unwrap();
+
+    assert_eq!(vec![true, true], good_pieces);
+    assert_eq!(data_a, on_disk_a);
+    assert_eq!(data_b, on_disk_b);
+}
@@ -1,28 +1,65 @@
-use std::cmp;
-use std::collections::HashMap;
 use std::io::{self};
 use std::path::{Path, PathBuf};
-use std::sync::{Arc, Mutex};
 
 use bytes::BytesMut;
 use rand::Rng;
-use futures::SinkExt;
+use futures::{SinkExt, StreamExt};
 
 use bittorrent_protocol::disk::{
     BlockMetadata, BlockMut, DiskManagerSink, DiskManagerStream, FileSystem, IDiskMessage,
     ODiskMessage,
 };
-use bittorrent_protocol::metainfo::{Accessor, IntoAccessor, PieceAccess};
+use bittorrent_protocol::metainfo::{
+    Accessor, IntoAccessor, Metainfo, MetainfoBuilder, PieceAccess, PieceLength,
+};
 use bittorrent_protocol::util::bt::InfoHash;
 
 mod add_torrent;
+mod allocate_torrent;
+mod cache_block;
+mod check_torrent;
 mod complete_torrent;
+mod disk_fault;
 mod disk_manager_send_backpressure;
+mod file_priorities;
 mod load_block;
+mod mmap_filesystem;
+mod move_torrent;
+mod padding_files;
 mod process_block;
 mod remove_torrent;
+mod resume_data;
 mod resume_torrent;
+mod seed_only;
+mod shutdown;
 mod start;
+mod upload_block;
+mod write_coalescing;
+
+/// Add a torrent with the given message, returning the good and invalidated pieces reported.
+async fn add_torrent<F>(
+    send: &mut DiskManagerSink<F>,
+    recv: &mut DiskManagerStream,
+    message: IDiskMessage,
+) -> (Vec<u64>, Vec<u64>)
+where
+    F: FileSystem + Send + Sync + 'static,
+{
+    send.send(message).await.unwrap();
+
+    let (mut good_pieces, mut invalidated_pieces) = (Vec::new(), Vec::new());
+    loop {
+        match recv.next().await.unwrap() {
+            ODiskMessage::FoundGoodPiece(_, index) => good_pieces.push(index),
+            ODiskMessage::InvalidatedPiece(_, index) => invalidated_pieces.push(index),
+            ODiskMessage::TorrentAdded(_) => break,
+            unexpected @ _ => panic!("Unexpected Message: {:?}", unexpected),
+        };
+    }
+    good_pieces.sort();
+
+    (good_pieces, invalidated_pieces)
+}
 
 /// Send block with the given metadata and entire data given.
 fn send_block<F, M>(
@@ -55,6 +92,24 @@ fn send_block<F, M>(
     );
 }
 
+/// Send the piece of 1024 bytes at the given index of the data as a single block.
+fn send_piece<F>(send: &DiskManagerSink<F>, data: &[u8], hash: InfoHash, index: u64)
+where
+    F: FileSystem + Send + Sync + 'static,
+{
+    let start = index as usize * 1024;
+
+    send_block(
+        send.clone(),
+        &data[start..(start + 1024)],
+        hash,
+        index,
+        0,
+        1024,
+        |_| (),
+    );
+}
+
 //----------------------------------------------------------------------------//
 
 /// Allow us to mock out multi file torrents.
@@ -111,117 +166,29 @@ impl Accessor for MultiFileDirectAccessor {
     }
 }
 
-//----------------------------------------------------------------------------//
-
-/// Allow us to mock out the file system.
-#[derive(Clone)]
-struct InMemoryFileSystem {
-    files: Arc<Mutex<HashMap<PathBuf, Vec<u8>>>>,
+/// Create a torrent of the given files within a `downloads` directory, in pieces of the
+/// given length.
+fn build_torrent(files: &[(&[u8], &str)], piece_len: usize) -> Metainfo {
+    build_torrent_with(
+        MetainfoBuilder::new().set_piece_length(PieceLength::Custom(piece_len)),
+        files,
+    )
 }
 
-impl InMemoryFileSystem {
-    pub fn new() -> InMemoryFileSystem {
-        InMemoryFileSystem {
-            files: Arc::new(Mutex::new(HashMap::new())),
-        }
-    }
-
-    pub fn run_with_lock<C, R>(&self, call: C) -> R
-    where
-        C: FnOnce(&mut HashMap<PathBuf, Vec<u8>>) -> R,
-    {
-        let mut lock_files = self.files.lock().unwrap();
+/// Create a torrent of the given files like `build_torrent`, using the given builder.
+fn build_torrent_with(builder: MetainfoBuilder, files: &[(&[u8], &str)]) -> Metainfo {
+    let files = files
+        .iter()
+        .map(|&(bytes, name)| (bytes.to_vec(), name.into()))
+        .collect();
 
-        call(&mut *lock_files)
-    }
-}
+    let files_accessor = MultiFileDirectAccessor::new("downloads".into(), files);
+    let metainfo_bytes = builder.build(1, files_accessor, |_| ()).unwrap();
 
-struct InMemoryFile {
-    path: PathBuf,
+    Metainfo::from_bytes(metainfo_bytes).unwrap()
 }
 
-impl FileSystem for InMemoryFileSystem {
-    type File = InMemoryFile;
-
-    fn open_file<P>(&self, path: P) -> io::Result<Self::File>
-    where
-        P: AsRef<Path> + Send + 'static,
-    {
-        let file_path = path.as_ref().to_path_buf();
-
-        self.run_with_lock(|files| {
-            if !files.contains_key(&file_path) {
-                files.insert(file_path.clone(), Vec::new());
-            }
-        });
-
-        Ok(InMemoryFile { path: file_path })
-    }
-
-    fn sync_file<P>(&self, _path: P) -> io::Result<()>
-    where
-        P: AsRef<Path> + Send + 'static,
-    {
-        Ok(())
-    }
-
-    fn file_size(&self, file: &Self::File) -> io::Result<u64> {
-        self.run_with_lock(|files| {
-            files
-                .get(&file.path)
-                .map(|file| file.len() as u64)
-                .ok_or(io::Error::new(io::ErrorKind::NotFound, "File Not Found"))
-        })
-    }
-
-    fn read_file(
-        &self,
-        file: &mut Self::File,
-        offset: u64,
-        buffer: &mut [u8],
-    ) -> io::Result<usize> {
-        self.run_with_lock(|files| {
-            files
-                .get(&file.path)
-                .map(|file_buffer| {
-                    let cast_offset = offset as usize;
-                    let bytes_to_copy = cmp::min(file_buffer.len() - cast_offset, buffer.len());
-                    let bytes = &file_buffer[cast_offset..(bytes_to_copy + cast_offset)];
-
-                    buffer.clone_from_slice(bytes);
-
-                    bytes_to_copy
-                })
-                .ok_or(io::Error::new(io::ErrorKind::NotFound, "File Not Found"))
-        })
-    }
-
-    fn write_file(&self, file: &mut Self::File, offset: u64, buffer: &[u8]) -> io::Result<usize> {
-        self.run_with_lock(|files| {
-            files
-                .get_mut(&file.path)
-                .map(|file_buffer| {
-                    let cast_offset = offset as usize;
-
-                    let last_byte_pos = cast_offset + buffer.len();
-                    if last_byte_pos > file_buffer.len() {
-                        file_buffer.resize(last_byte_pos, 0);
-                    }
-
-                    let bytes_to_copy = cmp::min(file_buffer.len() - cast_offset, buffer.len());
-
-                    if bytes_to_copy != 0 {
-                        file_buffer[cast_offset..(cast_offset + bytes_to_copy)]
-                            .clone_from_slice(buffer);
-                    }
-
-                    // TODO: If the file is full, this will return zero, we should also simulate io::ErrorKind::WriteZero
-                    bytes_to_copy
-                })
-                .ok_or(io::Error::new(io::ErrorKind::NotFound, "File Not Found"))
-        })
-    }
-}
+//----------------------------------------------------------------------------//
 
 /// Generate buffer of size random bytes.
 fn random_buffer(size: usize) -> Vec<u8> {
@@ -0,0 +1,274 @@
+use std::io;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use bittorrent_protocol::disk::error::TorrentErrorKind;
+use bittorrent_protocol::disk::{
+    BlockMetadata, BlockMut, DiskManagerBuilder, DiskManagerSink, DiskManagerStream, FileSystem,
+    IDiskMessage, MemoryFile, MemoryFileSystem, ODiskMessage,
+};
+use bittorrent_protocol::metainfo::Metainfo;
+use bittorrent_protocol::util::bt::InfoHash;
+use bytes::BytesMut;
+use futures::{SinkExt, StreamExt};
+
+const OLD_PATHS: [&str; 2] = ["downloads/path/to/file/a", "downloads/path/to/file/b"];
+const NEW_PATHS: [&str; 2] = [
+    "/archive/downloads/path/to/file/a",
+    "/archive/downloads/path/to/file/b",
+];
+
+/// Build a torrent with two files spanning two pieces, returning the metainfo and the data.
+fn metainfo() -> (Metainfo, Vec<u8>, Vec<u8>) {
+    let (data_a, data_b) = (super::random_buffer(1000), super::random_buffer(1048));
+    let metainfo_file = super::build_torrent(
+        &[(&data_a, "path/to/file/a"), (&data_b, "path/to/file/b")],
+        1024,
+    );
+
+    (metainfo_file, data_a, data_b)
+}
+
+/// Wait for the given number of good pieces and processed blocks, and for the result of
+/// a move if one was sent, returning the result of the move.
+async fn wait_for(
+    recv: &mut DiskManagerStream,
+    moving: bool,
+    good_pieces: usize,
+    processed: usize,
+) -> Option<ODiskMessage> {
+    let (mut opt_moved, mut found_good, mut found_processed) = (None, 0, 0);
+    while (moving && opt_moved.is_none())
+        || found_good != good_pieces
+        || found_processed != processed
+    {
+        match recv.next().await.unwrap() {
+            msg @ ODiskMessage::TorrentMoved(_) | msg @ ODiskMessage::TorrentMoveFailed { .. } => {
+                opt_moved = Some(msg)
+            }
+            ODiskMessage::FoundGoodPiece(_, _) => found_good += 1,
+            ODiskMessage::BlockProcessed(_) => found_processed += 1,
+            unexpected @ _ => panic!("Unexpected Message: {:?}", unexpected),
+        };
+    }
+
+    opt_moved
+}
+
+/// Process the pieces of the torrent, one block per piece.
+fn process_pieces<F>(send: &DiskManagerSink<F>, hash: InfoHash, data: &[u8], pieces: &[u64])
+where
+    F: FileSystem + Send + Sync + 'static,
+{
+    for &piece_index in pieces {
+        let start = piece_index as usize * 1024;
+        let end = std::cmp::min(start + 1024, data.len());
+
+        super::send_block(
+            send.clone(),
+            &data[start..end],
+            hash,
+            piece_index,
+            0,
+            end - start,
+            |_| (),
+        );
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn positive_move_torrent() {
+    let (metainfo_file, data_a, data_b) = metainfo();
+    let info_hash = metainfo_file.info().info_hash();
+    let data = [&data_a[..], &data_b[..]].concat();
+
+    let filesystem = MemoryFileSystem::new();
+    let (mut send, mut recv) = DiskManagerBuilder::new()
+        .build(filesystem.clone())
+        .into_parts();
+    super::add_torrent(
+        &mut send,
+        &mut recv,
+        IDiskMessage::AddTorrent(metainfo_file),
+    )
+    .await;
+
+    process_pieces(&send, info_hash, &data, &[0]);
+    wait_for(&mut recv, false, 1, 1).await;
+
+    assert!(send.move_torrent(info_hash, "/archive".into()));
+    match wait_for(&mut recv, true, 0, 0).await {
+        Some(ODiskMessage::TorrentMoved(hash)) => assert_eq!(info_hash, hash),
+        unexpected @ _ => panic!("Unexpected Message: {:?}", unexpected),
+    };
+
+    // Blocks are written to, and loaded from, the new location
+    process_pieces(&send, info_hash, &data, &[1]);
+    wait_for(&mut recv, false, 1, 1).await;
+
+    let mut bytes = BytesMut::new();
+    bytes.extend_from_slice(&[0u8; 1024]);
+    send.send(IDiskMessage::LoadBlock(BlockMut::new(
+        BlockMetadata::new(info_hash, 0, 0, 1024),
+        bytes,
+    )))
+    .await
+    .unwrap();
+    match recv.next().await.unwrap() {
+        ODiskMessage::BlockLoaded(block) => assert_eq!(&data[0..1024], &block[..]),
+        unexpected @ _ => panic!("Unexpected Message: {:?}", unexpected),
+    };
+
+    assert_eq!(None, filesystem.file_contents(OLD_PATHS[0]));
+    assert_eq!(None, filesystem.file_contents(OLD_PATHS[1]));
+    assert_eq!(Some(data_a), filesystem.file_contents(NEW_PATHS[0]));
+    assert_eq!(Some(data_b), filesystem.file_contents(NEW_PATHS[1]));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn positive_move_torrent_copies_while_processing() {
+    let (metainfo_file, data_a, data_b) = metainfo();
+    let info_hash = metainfo_file.info().info_hash();
+    let data = [&data_a[..], &data_b[..]].concat();
+
+    let filesystem = CopyingFileSystem {
+        inner: MemoryFileSystem::new(),
+    };
+    let (mut send, mut recv) = DiskManagerBuilder::new()
+        .build(filesystem.clone())
+        .into_parts();
+    super::add_torrent(
+        &mut send,
+        &mut recv,
+        IDiskMessage::AddTorrent(metainfo_file),
+    )
+    .await;
+
+    // Blocks sent while the files are copied are not lost
+    assert!(send.move_torrent(info_hash, "/archive".into()));
+    process_pieces(&send, info_hash, &data, &[0, 1]);
+    match wait_for(&mut recv, true, 2, 2).await {
+        Some(ODiskMessage::TorrentMoved(hash)) => assert_eq!(info_hash, hash),
+        unexpected @ _ => panic!("Unexpected Message: {:?}", unexpected),
+    };
+
+    let inner = &filesystem.inner;
+    assert_eq!(None, inner.file_contents(OLD_PATHS[0]));
+    assert_eq!(None, inner.file_contents(OLD_PATHS[1]));
+    assert_eq!(Some(data_a), inner.file_contents(NEW_PATHS[0]));
+    assert_eq!(Some(data_b), inner.file_contents(NEW_PATHS[1]));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn negative_move_torrent_rolled_back() {
+    let (metainfo_file, data_a, data_b) = metainfo();
+    let info_hash = metainfo_file.info().info_hash();
+    let data = [&data_a[..], &data_b[..]].concat();
+
+    let filesystem = MemoryFileSystem::new();
+    filesystem.seed_file(NEW_PATHS[1], b"other".to_vec());
+    let (mut send, mut recv) = DiskManagerBuilder::new()
+        .build(filesystem.clone())
+        .into_parts();
+    super::add_torrent(
+        &mut send,
+        &mut recv,
+        IDiskMessage::AddTorrent(metainfo_file),
+    )
+    .await;
+
+    process_pieces(&send, info_hash, &data, &[0]);
+    wait_for(&mut recv, false, 1, 1).await;
+
+    // The second file would replace an existing file, so the first file is moved back
+    assert!(send.move_torrent(info_hash, "/archive".into()));
+    match wait_for(&mut recv, true, 0, 0).await {
+        Some(ODiskMessage::TorrentMoveFailed {
+            hash,
+            error,
+            rolled_back,
+        }) => {
+            assert_eq!(info_hash, hash);
+            assert!(rolled_back);
+            match error.kind() {
+                TorrentErrorKind::MoveFailed { file_path, .. } => {
+                    assert_eq!(&PathBuf::from(OLD_PATHS[1]), file_path)
+                }
+                unexpected @ _ => panic!("Unexpected Error: {:?}", unexpected),
+            };
+        }
+        unexpected @ _ => panic!("Unexpected Message: {:?}", unexpected),
+    };
+
+    // The torrent is still read from, and written to, the old location
+    process_pieces(&send, info_hash, &data, &[1]);
+    wait_for(&mut recv, false, 1, 1).await;
+
+    assert_eq!(Some(data_a), filesystem.file_contents(OLD_PATHS[0]));
+    assert_eq!(Some(data_b), filesystem.file_contents(OLD_PATHS[1]));
+    assert_eq!(None, filesystem.file_contents(NEW_PATHS[0]));
+    assert_eq!(
+        Some(b"other".to_vec()),
+        filesystem.file_contents(NEW_PATHS[1])
+    );
+}
+
+//----------------------------------------------------------------------------//
+
+/// File system that can not rename files, and writes slowly to the new location.
+#[derive(Clone)]
+struct CopyingFileSystem {
+    inner: MemoryFileSystem,
+}
+
+impl FileSystem for CopyingFileSystem {
+    type File = MemoryFile;
+
+    fn open_file<P>(&self, path: P) -> io::Result<Self::File>
+    where
+        P: AsRef<Path> + Send + 'static,
+    {
+        self.inner.open_file(path)
+    }
+
+    fn sync_file<P>(&self, path: P) -> io::Result<()>
+    where
+        P: AsRef<Path> + Send + 'static,
+    {
+        self.inner.sync_file(path)
+    }
+
+    fn file_size(&self, file: &Self::File) -> io::Result<u64> {
+        self.inner.file_size(file)
+    }
+
+    fn file_modified(&self, file: &Self::File) -> io::Result<Option<SystemTime>> {
+        self.inner.file_modified(file)
+    }
+
+    fn read_file(
+        &self,
+        file: &mut Self::File,
+        offset: u64,
+        buffer: &mut [u8],
+    ) -> io::Result<usize> {
+        self.inner.read_file(file, offset, buffer)
+    }
+
+    fn write_file(&self, file: &mut Self::File, offset: u64, buffer: &[u8]) -> io::Result<usize> {
+        // Give blocks a chance to arrive while the files are copied
+        if file.path().starts_with("/archive") {
+            thread::sleep(Duration::from_millis(50));
+        }
+
+        self.inner.write_file(file, offset, buffer)
+    }
+
+    fn remove_file<P>(&self, path: P) -> io::Result<()>
+    where
+        P: AsRef<Path> + Send + 'static,
+    {
+        self.inner.remove_file(path)
+    }
+}
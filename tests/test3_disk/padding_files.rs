@@ -0,0 +1,92 @@
+use bittorrent_protocol::disk::{
+    BlockMetadata, BlockMut, DiskManagerBuilder, IDiskMessage, MemoryFileSystem, ODiskMessage,
+};
+use bittorrent_protocol::metainfo::{MetainfoBuilder, PieceLength};
+use bytes::BytesMut;
+use futures::{SinkExt, StreamExt};
+
+#[tokio::test(flavor = "multi_thread")]
+async fn positive_padding_files_not_stored() {
+    // Piece 0 is file a and its padding, and piece 1 is file b
+    let (data_a, data_b) = (super::random_buffer(1000), super::random_buffer(1000));
+    let builder = MetainfoBuilder::new()
+        .set_piece_length(PieceLength::Custom(1024))
+        .set_padding(true);
+    let metainfo_file = super::build_torrent_with(builder, &[(&data_a, "a"), (&data_b, "b")]);
+    let info_hash = metainfo_file.info().info_hash();
+    assert_eq!(
+        vec![false, true, false],
+        metainfo_file
+            .info()
+            .files()
+            .map(|file| file.is_padding())
+            .collect::<Vec<bool>>()
+    );
+
+    let filesystem = MemoryFileSystem::new();
+    let (mut send, mut recv) = DiskManagerBuilder::new()
+        .build(filesystem.clone())
+        .into_parts();
+    let (good_pieces, _) = super::add_torrent(
+        &mut send,
+        &mut recv,
+        IDiskMessage::AddTorrent(metainfo_file),
+    )
+    .await;
+    assert!(good_pieces.is_empty());
+
+    let piece = [&data_a[..], &[0u8; 24][..]].concat();
+    super::send_block(send.clone(), &piece, info_hash, 0, 0, 1024, |_| ());
+    let (mut good, mut processed) = (false, false);
+    while !good || !processed {
+        match recv.next().await.unwrap() {
+            ODiskMessage::FoundGoodPiece(_, 0) => good = true,
+            ODiskMessage::BlockProcessed(_) => processed = true,
+            unexpected @ _ => panic!("Unexpected Message: {:?}", unexpected),
+        };
+    }
+
+    assert_eq!(Some(data_a), filesystem.file_contents("downloads/a"));
+    assert_eq!(
+        Some(vec![0u8; 1000]),
+        filesystem.file_contents("downloads/b")
+    );
+    assert_eq!(None, filesystem.file_contents("downloads/.pad/24"));
+
+    // Loading the piece again reads the padding back as zeroes
+    let block = BlockMut::new(
+        BlockMetadata::new(info_hash, 0, 0, 1024),
+        BytesMut::from(&vec![0xFFu8; 1024][..]),
+    );
+    send.send(IDiskMessage::LoadBlock(block)).await.unwrap();
+    match recv.next().await.unwrap() {
+        ODiskMessage::BlockLoaded(block) => assert_eq!(&piece[..], &block[..]),
+        unexpected @ _ => panic!("Unexpected Message: {:?}", unexpected),
+    };
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn positive_padding_files_checked_without_existing() {
+    let (data_a, data_b) = (super::random_buffer(1000), super::random_buffer(1000));
+    let builder = MetainfoBuilder::new()
+        .set_piece_length(PieceLength::Custom(1024))
+        .set_padding(true);
+    let metainfo_file = super::build_torrent_with(builder, &[(&data_a, "a"), (&data_b, "b")]);
+
+    let filesystem = MemoryFileSystem::new();
+    filesystem.seed_file("downloads/a", data_a);
+    filesystem.seed_file("downloads/b", data_b);
+    let snapshot = filesystem.snapshot();
+    let (mut send, mut recv) = DiskManagerBuilder::new()
+        .build(filesystem.clone())
+        .into_parts();
+
+    let (good_pieces, _) = super::add_torrent(
+        &mut send,
+        &mut recv,
+        IDiskMessage::AddTorrent(metainfo_file),
+    )
+    .await;
+    assert_eq!(vec![0, 1], good_pieces);
+    assert_eq!(snapshot, filesystem.snapshot());
+}
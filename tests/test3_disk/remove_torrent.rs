@@ -1,13 +1,13 @@
 use futures::{SinkExt, StreamExt};
-use tokio::test;
-use super::{InMemoryFileSystem, MultiFileDirectAccessor};
+
+use super::MultiFileDirectAccessor;
 use bittorrent_protocol::disk::{
-    Block, BlockMetadata, DiskManagerBuilder, IDiskMessage, ODiskMessage,
+    Block, BlockMetadata, DiskManagerBuilder, IDiskMessage, MemoryFileSystem, ODiskMessage,
 };
 use bittorrent_protocol::metainfo::{Metainfo, MetainfoBuilder, PieceLength};
 use bytes::BytesMut;
 
-#[test]
+#[tokio::test(flavor = "multi_thread")]
 async fn positive_remove_torrent() {
     // Create some "files" as random bytes
     let data_a = (super::random_buffer(50), "/path/to/file/a".into());
@@ -27,23 +27,25 @@ async fn positive_remove_torrent() {
     let info_hash = metainfo_file.info().info_hash();
 
     // Spin up a disk manager and add our created torrent to it
-    let filesystem = InMemoryFileSystem::new();
+    let filesystem = MemoryFileSystem::new();
     let disk_manager = DiskManagerBuilder::new().build(filesystem.clone());
 
     let (mut blocking_send, mut recv) = disk_manager.into_parts();
     blocking_send
         .send(IDiskMessage::AddTorrent(metainfo_file))
+        .await
         .unwrap();
 
     // Verify that zero pieces are marked as good
     let mut good_pieces = 0;
     loop {
-        let msg = recv.next().unwrap();
+        let msg = recv.next().await.unwrap();
 
         match msg {
             ODiskMessage::TorrentAdded(_) => {
                 blocking_send
                     .send(IDiskMessage::RemoveTorrent(info_hash))
+                    .await
                     .unwrap();
             }
             ODiskMessage::TorrentRemoved(_) => break,
@@ -66,10 +68,11 @@ async fn positive_remove_torrent() {
 
     blocking_send
         .send(IDiskMessage::ProcessBlock(process_block))
+        .await
         .unwrap();
 
     loop {
-        let msg = recv.next().unwrap();
+        let msg = recv.next().await.unwrap();
         match msg {
             ODiskMessage::ProcessBlockError(_, _) => break,
             unexpected => panic!("Unexpected Message: {:?}", unexpected),
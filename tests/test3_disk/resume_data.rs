@@ -0,0 +1,211 @@
+use bittorrent_protocol::disk::{
+    DiskManagerBuilder, DiskManagerSink, DiskManagerStream, FileSystem, IDiskMessage,
+    MemoryFileSystem, ODiskMessage, ResumeData,
+};
+use bittorrent_protocol::util::bt::InfoHash;
+use futures::{SinkExt, StreamExt};
+
+async fn remove_torrent(
+    send: &mut DiskManagerSink<MemoryFileSystem>,
+    recv: &mut DiskManagerStream,
+    hash: InfoHash,
+) {
+    send.send(IDiskMessage::RemoveTorrent(hash)).await.unwrap();
+
+    match recv.next().await.unwrap() {
+        ODiskMessage::TorrentRemoved(_) => (),
+        unexpected @ _ => panic!("Unexpected Message: {:?}", unexpected),
+    };
+}
+
+/// Write the blocks, waiting until they are processed and the expected pieces are found good.
+async fn write_blocks(
+    send: &DiskManagerSink<MemoryFileSystem>,
+    recv: &mut DiskManagerStream,
+    files_bytes: &[u8],
+    hash: InfoHash,
+    blocks: &[(u64, u64, usize)],
+    expected_good: &[u64],
+) {
+    for &(piece_index, block_offset, block_len) in blocks {
+        let start = (piece_index * 1024 + block_offset) as usize;
+
+        super::send_block(
+            send.clone(),
+            &files_bytes[start..(start + block_len)],
+            hash,
+            piece_index,
+            block_offset,
+            block_len,
+            |_| (),
+        );
+    }
+
+    let (mut good_pieces, mut blocks_processed) = (Vec::new(), 0);
+    while good_pieces.len() != expected_good.len() || blocks_processed != blocks.len() {
+        match recv.next().await.unwrap() {
+            ODiskMessage::FoundGoodPiece(_, index) => good_pieces.push(index),
+            ODiskMessage::BlockProcessed(_) => blocks_processed += 1,
+            unexpected @ _ => panic!("Unexpected Message: {:?}", unexpected),
+        };
+    }
+    good_pieces.sort();
+
+    assert_eq!(expected_good, &good_pieces[..]);
+}
+
+fn file_path(filesystem: &MemoryFileSystem, name: &str) -> std::path::PathBuf {
+    filesystem.run_with_lock(|files| {
+        files
+            .keys()
+            .find(|path| path.ends_with(name))
+            .unwrap()
+            .clone()
+    })
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn positive_resume_skips_check_and_restores_blocks() {
+    // Pieces 0 and 1 are backed by file a, pieces 2 and 3 by file b
+    let files_bytes = super::random_buffer(4048);
+    let (data_a, data_b) = files_bytes.split_at(2048);
+    let metainfo_file = super::build_torrent(&[(data_a, "a"), (data_b, "b")], 1024);
+    let info_hash = metainfo_file.info().info_hash();
+
+    let filesystem = MemoryFileSystem::new();
+    let disk_manager = DiskManagerBuilder::new().build(filesystem.clone());
+    let (mut send, mut recv) = disk_manager.into_parts();
+
+    super::add_torrent(
+        &mut send,
+        &mut recv,
+        IDiskMessage::AddTorrent(metainfo_file.clone()),
+    )
+    .await;
+    write_blocks(
+        &send,
+        &mut recv,
+        &files_bytes,
+        info_hash,
+        &[(0, 0, 1024), (2, 0, 512)],
+        &[0],
+    )
+    .await;
+
+    let resume = send.save_resume_data(info_hash).unwrap();
+    remove_torrent(&mut send, &mut recv, info_hash).await;
+
+    // Corrupt piece 0 behind our back, since file a was not modified it is not checked
+    let path_a = file_path(&filesystem, "a");
+    filesystem.run_with_lock(|files| files.get_mut(&path_a).unwrap()[0] ^= 0xFF);
+
+    let (good_pieces, invalidated_pieces) = super::add_torrent(
+        &mut send,
+        &mut recv,
+        IDiskMessage::AddTorrentWithResume(
+            metainfo_file,
+            ResumeData::from_bytes(resume.into_bytes()),
+        ),
+    )
+    .await;
+    assert_eq!(vec![0], good_pieces);
+    assert!(invalidated_pieces.is_empty());
+
+    // First half of piece 2 was restored from the resume data
+    write_blocks(
+        &send,
+        &mut recv,
+        &files_bytes,
+        info_hash,
+        &[(2, 512, 512)],
+        &[2],
+    )
+    .await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn positive_resume_rechecks_changed_files() {
+    let files_bytes = super::random_buffer(4048);
+    let (data_a, data_b) = files_bytes.split_at(2048);
+    let metainfo_file = super::build_torrent(&[(data_a, "a"), (data_b, "b")], 1024);
+    let info_hash = metainfo_file.info().info_hash();
+
+    let filesystem = MemoryFileSystem::new();
+    let disk_manager = DiskManagerBuilder::new().build(filesystem.clone());
+    let (mut send, mut recv) = disk_manager.into_parts();
+
+    super::add_torrent(
+        &mut send,
+        &mut recv,
+        IDiskMessage::AddTorrent(metainfo_file.clone()),
+    )
+    .await;
+    write_blocks(
+        &send,
+        &mut recv,
+        &files_bytes,
+        info_hash,
+        &[(0, 0, 1024), (2, 0, 1024)],
+        &[0, 2],
+    )
+    .await;
+
+    let resume = send.save_resume_data(info_hash).unwrap();
+    remove_torrent(&mut send, &mut recv, info_hash).await;
+
+    // Modify file b, so the pieces it backs are checked again
+    let mut file_b = filesystem.open_file(file_path(&filesystem, "b")).unwrap();
+    filesystem
+        .write_file(&mut file_b, 0, &[!files_bytes[2048]])
+        .unwrap();
+
+    let (good_pieces, invalidated_pieces) = super::add_torrent(
+        &mut send,
+        &mut recv,
+        IDiskMessage::AddTorrentWithResume(metainfo_file, resume),
+    )
+    .await;
+    assert_eq!(vec![0], good_pieces);
+    assert_eq!(vec![2], invalidated_pieces);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn negative_corrupted_resume_checks_all_pieces() {
+    let files_bytes = super::random_buffer(4048);
+    let (data_a, data_b) = files_bytes.split_at(2048);
+    let metainfo_file = super::build_torrent(&[(data_a, "a"), (data_b, "b")], 1024);
+    let info_hash = metainfo_file.info().info_hash();
+
+    let filesystem = MemoryFileSystem::new();
+    let disk_manager = DiskManagerBuilder::new().build(filesystem.clone());
+    let (mut send, mut recv) = disk_manager.into_parts();
+
+    super::add_torrent(
+        &mut send,
+        &mut recv,
+        IDiskMessage::AddTorrent(metainfo_file.clone()),
+    )
+    .await;
+    write_blocks(
+        &send,
+        &mut recv,
+        &files_bytes,
+        info_hash,
+        &[(0, 0, 1024), (3, 0, 976)],
+        &[0, 3],
+    )
+    .await;
+
+    let mut resume_bytes = send.save_resume_data(info_hash).unwrap().into_bytes();
+    resume_bytes.truncate(resume_bytes.len() - 10);
+    remove_torrent(&mut send, &mut recv, info_hash).await;
+
+    let (good_pieces, invalidated_pieces) = super::add_torrent(
+        &mut send,
+        &mut recv,
+        IDiskMessage::AddTorrentWithResume(metainfo_file, ResumeData::from_bytes(resume_bytes)),
+    )
+    .await;
+    assert_eq!(vec![0, 3], good_pieces);
+    assert!(invalidated_pieces.is_empty());
+}
@@ -1,10 +1,10 @@
 use futures::{SinkExt, StreamExt};
-use tokio::test;
-use super::{InMemoryFileSystem, MultiFileDirectAccessor};
-use bittorrent_protocol::disk::{DiskManagerBuilder, IDiskMessage, ODiskMessage};
+
+use super::MultiFileDirectAccessor;
+use bittorrent_protocol::disk::{DiskManagerBuilder, IDiskMessage, MemoryFileSystem, ODiskMessage};
 use bittorrent_protocol::metainfo::{Metainfo, MetainfoBuilder, PieceLength};
 
-#[test]
+#[tokio::test(flavor = "multi_thread")]
 async fn positive_complete_torrent() {
     // Create some "files" as random bytes
     let data_a = (super::random_buffer(1023), "/path/to/file/a".into());
@@ -23,19 +23,20 @@ async fn positive_complete_torrent() {
     let info_hash = metainfo_file.info().info_hash();
 
     // Spin up a disk manager and add our created torrent to it
-    let filesystem = InMemoryFileSystem::new();
+    let filesystem = MemoryFileSystem::new();
     let disk_manager = DiskManagerBuilder::new().build(filesystem.clone());
 
     let (mut blocking_send, mut recv) = disk_manager.into_parts();
 
     blocking_send
         .send(IDiskMessage::AddTorrent(metainfo_file.clone()))
+        .await
         .unwrap();
 
     // Run a core loop until we get the TorrentAdded message
     let mut good_pieces = 0;
     loop {
-        let msg = recv.next().unwrap();
+        let msg = recv.next().await.unwrap();
         match msg {
             ODiskMessage::TorrentAdded(_) => break,
             ODiskMessage::FoundGoodPiece(_, _) => good_pieces += 1,
@@ -84,8 +85,8 @@ async fn positive_complete_torrent() {
     let mut messages_recvd = 0;
 
     loop {
-        let msg = recv.next().unwrap();
-        let messages_recvd = messages_recvd + 1;
+        let msg = recv.next().await.unwrap();
+        messages_recvd += 1;
 
         // Map BlockProcessed to a None piece index so we don't update our state
         let (opt_piece_index, new_value) = match msg {
@@ -96,7 +97,7 @@ async fn positive_complete_torrent() {
         };
 
         match opt_piece_index {
-            None => piece_zero_good = false,
+            None => (),
             Some(0) => piece_zero_good = new_value,
             Some(x) => panic!("Unexpected Index {:?}", x),
         };
@@ -111,11 +112,12 @@ async fn positive_complete_torrent() {
     // Remove the torrent from our manager
     blocking_send
         .send(IDiskMessage::RemoveTorrent(info_hash))
+        .await
         .unwrap();
 
     // Verify that our torrent was removed
     loop {
-        let msg = recv.next().unwrap();
+        let msg = recv.next().await.unwrap();
         match msg {
             ODiskMessage::TorrentRemoved(_) => break,
             unexpected @ _ => panic!("Unexpected Message: {:?}", unexpected),
@@ -125,11 +127,12 @@ async fn positive_complete_torrent() {
     // Re-add our torrent and verify that we see our good first block
     blocking_send
         .send(IDiskMessage::AddTorrent(metainfo_file.clone()))
+        .await
         .unwrap();
 
     let mut piece_zero_good = false;
     loop {
-        let msg = recv.next().unwrap();
+        let msg = recv.next().await.unwrap();
         match msg {
             ODiskMessage::TorrentAdded(_) => break,
             ODiskMessage::FoundGoodPiece(_, piece) if piece == 0 => {
@@ -193,10 +196,11 @@ async fn positive_complete_torrent() {
     // Verify last two blocks are good
     let mut piece_one_good = false;
     let mut piece_two_good = false;
+    let mut messages_recvd = 0;
 
     loop {
-        let msg = recv.next().unwrap();
-        let messages_recvd = messages_recvd + 1;
+        let msg = recv.next().await.unwrap();
+        messages_recvd += 1;
         // Map BlockProcessed to a None piece index so we don't update our state
         let (opt_piece_index, new_value) = match msg {
             ODiskMessage::FoundGoodPiece(_, index) => (Some(index), true),
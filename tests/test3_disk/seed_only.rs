@@ -0,0 +1,199 @@
+use std::path::PathBuf;
+
+use bittorrent_protocol::disk::error::{BlockErrorKind, TorrentErrorKind};
+use bittorrent_protocol::disk::{
+    BlockMetadata, BlockMut, DiskManagerBuilder, FilePriority, IDiskMessage, MemoryFileSystem,
+    ODiskMessage, TorrentMode,
+};
+use bytes::BytesMut;
+use futures::{SinkExt, StreamExt};
+
+/// File system holding the data of the torrent, with the last byte of file b corrupted.
+fn seeded_filesystem(data: &[u8]) -> MemoryFileSystem {
+    let mut corrupted_b = data[1024..].to_vec();
+    corrupted_b[1023] ^= 0xFF;
+
+    let filesystem = MemoryFileSystem::new();
+    filesystem.seed_file("downloads/a", data[..1024].to_vec());
+    filesystem.seed_file("downloads/b", corrupted_b);
+
+    filesystem
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn positive_seed_only_serves_checked_pieces() {
+    // Piece 0 is file a and piece 1 is file b
+    let data = super::random_buffer(2048);
+    let (data_a, data_b) = data.split_at(1024);
+    let metainfo_file = super::build_torrent(&[(data_a, "a"), (data_b, "b")], 1024);
+    let info_hash = metainfo_file.info().info_hash();
+
+    let filesystem = seeded_filesystem(&data);
+    let snapshot = filesystem.snapshot();
+    let (mut send, mut recv) = DiskManagerBuilder::new()
+        .build(filesystem.clone())
+        .into_parts();
+
+    // Every piece is checked, with nothing allocated over the corrupted piece
+    let (good_pieces, _) = super::add_torrent(
+        &mut send,
+        &mut recv,
+        IDiskMessage::AddTorrentWithMode(metainfo_file, TorrentMode::SeedOnly),
+    )
+    .await;
+    assert_eq!(vec![0], good_pieces);
+    assert_eq!(snapshot, filesystem.snapshot());
+
+    let block = BlockMut::new(
+        BlockMetadata::new(info_hash, 0, 0, 1024),
+        BytesMut::from(&vec![0u8; 1024][..]),
+    );
+    send.send(IDiskMessage::LoadBlock(block)).await.unwrap();
+    match recv.next().await.unwrap() {
+        ODiskMessage::BlockLoaded(block) => assert_eq!(&data[..1024], &block[..]),
+        unexpected @ _ => panic!("Unexpected Message: {:?}", unexpected),
+    };
+
+    // Fixing the corrupted piece requires switching to download mode
+    super::send_piece(&send, &data, info_hash, 1);
+    match recv.next().await.unwrap() {
+        ODiskMessage::ProcessBlockError(_, err) => match err.kind() {
+            &BlockErrorKind::ReadOnlyTorrent { hash } => assert_eq!(info_hash, hash),
+            unexpected @ _ => panic!("Unexpected Error: {:?}", unexpected),
+        },
+        unexpected @ _ => panic!("Unexpected Message: {:?}", unexpected),
+    };
+    assert_eq!(snapshot, filesystem.snapshot());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn positive_switch_to_download_rechecks() {
+    let data = super::random_buffer(2048);
+    let (data_a, data_b) = data.split_at(1024);
+    let metainfo_file = super::build_torrent(&[(data_a, "a"), (data_b, "b")], 1024);
+    let info_hash = metainfo_file.info().info_hash();
+
+    let filesystem = seeded_filesystem(&data);
+    let (mut send, mut recv) = DiskManagerBuilder::new()
+        .build(filesystem.clone())
+        .into_parts();
+    super::add_torrent(
+        &mut send,
+        &mut recv,
+        IDiskMessage::AddTorrentWithMode(metainfo_file, TorrentMode::SeedOnly),
+    )
+    .await;
+
+    send.send(IDiskMessage::SetTorrentMode(
+        info_hash,
+        TorrentMode::Download,
+    ))
+    .await
+    .unwrap();
+    let mut num_progress = 0;
+    loop {
+        match recv.next().await.unwrap() {
+            ODiskMessage::TorrentCheckProgress { .. } => num_progress += 1,
+            ODiskMessage::TorrentModeSet(hash, TorrentMode::Download, good_pieces) => {
+                assert_eq!(info_hash, hash);
+                assert_eq!(vec![true, false], good_pieces);
+                break;
+            }
+            unexpected @ _ => panic!("Unexpected Message: {:?}", unexpected),
+        };
+    }
+    assert_eq!(2, num_progress);
+
+    // Downloading the corrupted piece again completes the torrent
+    super::send_piece(&send, &data, info_hash, 1);
+    let (mut good, mut processed) = (false, false);
+    while !good || !processed {
+        match recv.next().await.unwrap() {
+            ODiskMessage::FoundGoodPiece(_, 1) => good = true,
+            ODiskMessage::BlockProcessed(_) => processed = true,
+            unexpected @ _ => panic!("Unexpected Message: {:?}", unexpected),
+        };
+    }
+    assert_eq!(
+        Some(data[1024..].to_vec()),
+        filesystem.file_contents("downloads/b")
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn negative_seed_only_missing_file() {
+    let data = super::random_buffer(2048);
+    let (data_a, data_b) = data.split_at(1024);
+    let metainfo_file = super::build_torrent(&[(data_a, "a"), (data_b, "b")], 1024);
+    let info_hash = metainfo_file.info().info_hash();
+
+    let filesystem = MemoryFileSystem::new();
+    filesystem.seed_file("downloads/a", data[..1024].to_vec());
+    let (mut send, mut recv) = DiskManagerBuilder::new()
+        .build(filesystem.clone())
+        .into_parts();
+
+    send.send(IDiskMessage::AddTorrentWithMode(
+        metainfo_file,
+        TorrentMode::SeedOnly,
+    ))
+    .await
+    .unwrap();
+    match recv.next().await.unwrap() {
+        ODiskMessage::TorrentError(hash, err) => match err.kind() {
+            &TorrentErrorKind::Io(_) => assert_eq!(info_hash, hash),
+            unexpected @ _ => panic!("Unexpected Error: {:?}", unexpected),
+        },
+        unexpected @ _ => panic!("Unexpected Message: {:?}", unexpected),
+    };
+    assert_eq!(None, filesystem.file_contents("downloads/b"));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn negative_seed_only_not_moved_or_prioritized() {
+    let data = super::random_buffer(2048);
+    let (data_a, data_b) = data.split_at(1024);
+    let metainfo_file = super::build_torrent(&[(data_a, "a"), (data_b, "b")], 1024);
+    let info_hash = metainfo_file.info().info_hash();
+
+    let filesystem = seeded_filesystem(&data);
+    let snapshot = filesystem.snapshot();
+    let (mut send, mut recv) = DiskManagerBuilder::new()
+        .build(filesystem.clone())
+        .into_parts();
+    super::add_torrent(
+        &mut send,
+        &mut recv,
+        IDiskMessage::AddTorrentWithMode(metainfo_file, TorrentMode::SeedOnly),
+    )
+    .await;
+
+    assert!(send.move_torrent(info_hash, PathBuf::from("archive")));
+    match recv.next().await.unwrap() {
+        ODiskMessage::TorrentMoveFailed {
+            error, rolled_back, ..
+        } => {
+            assert!(rolled_back);
+            match error.kind() {
+                &TorrentErrorKind::ReadOnlyTorrent { hash } => assert_eq!(info_hash, hash),
+                unexpected @ _ => panic!("Unexpected Error: {:?}", unexpected),
+            }
+        }
+        unexpected @ _ => panic!("Unexpected Message: {:?}", unexpected),
+    };
+
+    send.send(IDiskMessage::SetFilePriorities(
+        info_hash,
+        vec![FilePriority::Skip, FilePriority::Normal],
+    ))
+    .await
+    .unwrap();
+    match recv.next().await.unwrap() {
+        ODiskMessage::TorrentError(_, err) => match err.kind() {
+            &TorrentErrorKind::ReadOnlyTorrent { hash } => assert_eq!(info_hash, hash),
+            unexpected @ _ => panic!("Unexpected Error: {:?}", unexpected),
+        },
+        unexpected @ _ => panic!("Unexpected Message: {:?}", unexpected),
+    };
+    assert_eq!(snapshot, filesystem.snapshot());
+}
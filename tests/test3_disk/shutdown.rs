@@ -0,0 +1,74 @@
+use std::time::Duration;
+
+use super::MultiFileDirectAccessor;
+use bittorrent_protocol::disk::{
+    AllocationMode, DiskManagerBuilder, IDiskMessage, MemoryFileSystem, ODiskMessage, ResumeState,
+};
+use bittorrent_protocol::metainfo::{Metainfo, MetainfoBuilder, PieceLength};
+use futures::{SinkExt, StreamExt};
+
+const PIECE_LEN: usize = 2048;
+const BLOCK_LEN: usize = 512;
+
+#[tokio::test(flavor = "multi_thread")]
+async fn positive_shutdown_writes_buffered_blocks() {
+    let data = super::random_buffer(2 * PIECE_LEN);
+    let files_accessor =
+        MultiFileDirectAccessor::new("downloads".into(), vec![(data.clone(), "a".into())]);
+    let metainfo_bytes = MetainfoBuilder::new()
+        .set_piece_length(PieceLength::Custom(PIECE_LEN))
+        .build(1, files_accessor, |_| ())
+        .unwrap();
+    let metainfo_file = Metainfo::from_bytes(metainfo_bytes).unwrap();
+    let info_hash = metainfo_file.info().info_hash();
+
+    let filesystem = MemoryFileSystem::new();
+    let (mut send, mut recv) = DiskManagerBuilder::new()
+        .with_sink_buffer_capacity(100)
+        .with_allocation_mode(AllocationMode::DontAllocate)
+        .with_write_buffer_size(1024 * 1024)
+        .with_write_flush_interval(Duration::from_secs(60))
+        .build(filesystem.clone())
+        .into_parts();
+
+    send.send(IDiskMessage::AddTorrent(metainfo_file.clone()))
+        .await
+        .unwrap();
+    match recv.next().await.unwrap() {
+        ODiskMessage::TorrentAdded(_) => (),
+        unexpected @ _ => panic!("Unexpected Message: {:?}", unexpected),
+    };
+
+    super::send_block(
+        send.clone(),
+        &data[..BLOCK_LEN],
+        info_hash,
+        0,
+        0,
+        BLOCK_LEN,
+        |_| (),
+    );
+    match recv.next().await.unwrap() {
+        ODiskMessage::BlockProcessed(_) => (),
+        unexpected @ _ => panic!("Unexpected Message: {:?}", unexpected),
+    };
+    assert_eq!(Some(Vec::new()), filesystem.file_contents("downloads/a"));
+
+    send.shutdown().await.unwrap();
+    assert_eq!(
+        Some(data[..BLOCK_LEN].to_vec()),
+        filesystem.file_contents("downloads/a")
+    );
+
+    // The written block survives a restart, without the piece being verified yet
+    let resume = send.save_resume_data(info_hash).unwrap();
+    let state = ResumeState::decode(&resume, metainfo_file.info()).unwrap();
+    assert_eq!(vec![false, false], state.good_pieces);
+    assert_eq!(vec![(0, 0, BLOCK_LEN as u64)], state.blocks);
+
+    // Nothing is taken from now on, and shutting down again does nothing
+    assert!(send
+        .try_send(IDiskMessage::RemoveTorrent(info_hash))
+        .is_err());
+    send.shutdown().await.unwrap();
+}
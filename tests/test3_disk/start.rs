@@ -1,5 +1,4 @@
 use futures::{SinkExt, StreamExt};
-use tokio::test;
 use bytes::BytesMut;
 use rand::{self, Rng};
 use std::fs;
@@ -43,7 +42,7 @@ fn generate_single_file_torrent(piece_len: usize, file_len: usize) -> (Metainfo,
 }
 
 /// Adds the given metainfo file to the given sender, and waits for the added notification.
-fn add_metainfo_file<F>(
+async fn add_metainfo_file<F>(
     metainfo: Metainfo,
     block_send: &mut DiskManagerSink<F>,
     block_recv: &mut DiskManagerStream,
@@ -52,10 +51,11 @@ fn add_metainfo_file<F>(
 {
     (*block_send)
         .send(IDiskMessage::AddTorrent(metainfo))
+        .await
         .unwrap();
 
-    for res_message in (*block_recv).next() {
-        match res_message {
+    loop {
+        match (*block_recv).next().await.unwrap() {
             ODiskMessage::TorrentAdded(_) => {
                 break;
             }
@@ -67,7 +67,7 @@ fn add_metainfo_file<F>(
 
 /// Pushes the given bytes as piece blocks to the given sender, and blocks until all notifications
 /// of the blocks being processed have been received (does not check piece messages).
-fn process_blocks<F>(
+async fn process_blocks<F>(
     piece_length: usize,
     block_length: usize,
     hash: InfoHash,
@@ -90,12 +90,15 @@ fn process_blocks<F>(
                 bytes.freeze(),
             );
 
-            block_send.send(IDiskMessage::ProcessBlock(block)).unwrap();
+            block_send
+                .send(IDiskMessage::ProcessBlock(block))
+                .await
+                .unwrap();
             blocks_sent += 1;
         }
     }
     loop {
-        match block_recv.next().unwrap() {
+        match block_recv.next().await.unwrap() {
             ODiskMessage::BlockProcessed(_) => blocks_sent -= 1,
             ODiskMessage::FoundGoodPiece(_, _) => (),
             ODiskMessage::FoundBadPiece(_, _) => (),
@@ -108,8 +111,9 @@ fn process_blocks<F>(
     }
 }
 
-/// Benchmarking method to setup a torrent file with the given attributes, and benchmark the block processing code.
-fn bench_process_file_with_fs<F>(
+/// Benchmarking method to setup a torrent file with the given attributes, and benchmark the
+/// block processing code.
+async fn bench_process_file_with_fs<F>(
     piece_length: usize,
     block_length: usize,
     file_length: usize,
@@ -121,12 +125,12 @@ fn bench_process_file_with_fs<F>(
     let info_hash = metainfo.info().info_hash();
 
     let disk_manager = DiskManagerBuilder::new()
-        .with_buffer_capacity(1000000)
+        .with_sink_buffer_capacity(1000000)
         .build(fs);
 
     let (mut d_send, mut d_recv) = disk_manager.into_parts();
 
-    add_metainfo_file(metainfo, &mut d_send, &mut d_recv);
+    add_metainfo_file(metainfo, &mut d_send, &mut d_recv).await;
 
     process_blocks(
         piece_length,
@@ -135,10 +139,11 @@ fn bench_process_file_with_fs<F>(
         &bytes[..],
         d_send.clone(),
         d_recv,
-    );
+    )
+    .await;
 }
 
-#[test]
+#[tokio::test(flavor = "multi_thread")]
 async fn bench_native_fs_1_mb_pieces_128_kb_blocks() {
     let piece_length = 1 * 1024 * 1024;
     let block_length = 128 * 1024;
@@ -150,10 +155,10 @@ async fn bench_native_fs_1_mb_pieces_128_kb_blocks() {
     }
     let filesystem = NativeFileSystem::with_directory(data_directory);
 
-    bench_process_file_with_fs(piece_length, block_length, file_length, filesystem);
+    bench_process_file_with_fs(piece_length, block_length, file_length, filesystem).await;
 }
 
-#[test]
+#[tokio::test(flavor = "multi_thread")]
 async fn bench_native_fs_1_mb_pieces_16_kb_blocks() {
     let piece_length = 1 * 1024 * 1024;
     let block_length = 16 * 1024;
@@ -165,10 +170,10 @@ async fn bench_native_fs_1_mb_pieces_16_kb_blocks() {
     }
     let filesystem = NativeFileSystem::with_directory(data_directory);
 
-    bench_process_file_with_fs(piece_length, block_length, file_length, filesystem);
+    bench_process_file_with_fs(piece_length, block_length, file_length, filesystem).await;
 }
 
-#[test]
+#[tokio::test(flavor = "multi_thread")]
 async fn bench_native_fs_1_mb_pieces_2_kb_blocks() {
     let piece_length = 1 * 1024 * 1024;
     let block_length = 2 * 1024;
@@ -180,10 +185,10 @@ async fn bench_native_fs_1_mb_pieces_2_kb_blocks() {
     }
     let filesystem = NativeFileSystem::with_directory(data_directory);
 
-    bench_process_file_with_fs(piece_length, block_length, file_length, filesystem);
+    bench_process_file_with_fs(piece_length, block_length, file_length, filesystem).await;
 }
 
-#[test]
+#[tokio::test(flavor = "multi_thread")]
 async fn bench_file_handle_cache_fs_1_mb_pieces_128_kb_blocks() {
     let piece_length = 1 * 1024 * 1024;
     let block_length = 128 * 1024;
@@ -195,10 +200,10 @@ async fn bench_file_handle_cache_fs_1_mb_pieces_128_kb_blocks() {
     }
     let filesystem = FileHandleCache::new(NativeFileSystem::with_directory(data_directory), 1);
 
-    bench_process_file_with_fs(piece_length, block_length, file_length, filesystem);
+    bench_process_file_with_fs(piece_length, block_length, file_length, filesystem).await;
 }
 
-#[test]
+#[tokio::test(flavor = "multi_thread")]
 async fn bench_file_handle_cache_fs_1_mb_pieces_16_kb_blocks() {
     let piece_length = 1 * 1024 * 1024;
     let block_length = 16 * 1024;
@@ -210,10 +215,10 @@ async fn bench_file_handle_cache_fs_1_mb_pieces_16_kb_blocks() {
     }
     let filesystem = FileHandleCache::new(NativeFileSystem::with_directory(data_directory), 1);
 
-    bench_process_file_with_fs(piece_length, block_length, file_length, filesystem);
+    bench_process_file_with_fs(piece_length, block_length, file_length, filesystem).await;
 }
 
-#[test]
+#[tokio::test(flavor = "multi_thread")]
 async fn bench_file_handle_cache_fs_1_mb_pieces_2_kb_blocks() {
     let piece_length = 1 * 1024 * 1024;
     let block_length = 2 * 1024;
@@ -225,5 +230,5 @@ async fn bench_file_handle_cache_fs_1_mb_pieces_2_kb_blocks() {
     }
     let filesystem = FileHandleCache::new(NativeFileSystem::with_directory(data_directory), 1);
 
-    bench_process_file_with_fs(piece_length, block_length, file_length, filesystem);
+    bench_process_file_with_fs(piece_length, block_length, file_length, filesystem).await;
 }
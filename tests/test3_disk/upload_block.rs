@@ -0,0 +1,126 @@
+use super::MultiFileDirectAccessor;
+use bittorrent_protocol::disk::{DiskManagerBuilder, IDiskMessage, MemoryFileSystem, ODiskMessage};
+use bittorrent_protocol::handshake::Extensions;
+use bittorrent_protocol::metainfo::{Metainfo, MetainfoBuilder, PieceLength};
+use bittorrent_protocol::peer::messages::{
+    CancelMessage, PeerWireProtocolMessage, PieceMessage, RequestMessage,
+};
+use bittorrent_protocol::peer::PeerInfo;
+use bittorrent_protocol::select::upload::Uploader;
+use bytes::Bytes;
+use futures::{SinkExt, StreamExt};
+
+#[tokio::test(flavor = "multi_thread")]
+async fn positive_upload_block() {
+    // Create some "files" as random bytes
+    let data_a = (super::random_buffer(1023), "/path/to/file/a".into());
+    let data_b = (super::random_buffer(2000), "/path/to/file/b".into());
+
+    // Create our accessor for our in memory files and create a torrent file for them
+    let files_accessor = MultiFileDirectAccessor::new(
+        "/my/downloads/".into(),
+        vec![data_a.clone(), data_b.clone()],
+    );
+    let metainfo_bytes = MetainfoBuilder::new()
+        .set_piece_length(PieceLength::Custom(1024))
+        .build(1, files_accessor, |_| ())
+        .unwrap();
+    let metainfo_file = Metainfo::from_bytes(metainfo_bytes).unwrap();
+    let info_hash = metainfo_file.info().info_hash();
+
+    // Spin up a disk manager and add our created torrent to it
+    let filesystem = MemoryFileSystem::new();
+    let disk_manager = DiskManagerBuilder::new().build(filesystem.clone());
+
+    let (mut send, mut recv) = disk_manager.into_parts();
+    send.send(IDiskMessage::AddTorrent(metainfo_file))
+        .await
+        .unwrap();
+
+    loop {
+        match recv.next().await.unwrap() {
+            ODiskMessage::TorrentAdded(_) => break,
+            unexpected @ _ => panic!("Unexpected Message: {:?}", unexpected),
+        };
+    }
+
+    // Write piece 0 so that we have something to upload
+    super::send_block(
+        send.clone(),
+        &data_a.0[0..512],
+        info_hash,
+        0,
+        0,
+        512,
+        |_| (),
+    );
+    let mut piece_zero = Vec::new();
+    piece_zero.extend_from_slice(&data_a.0);
+    piece_zero.push(data_b.0[0]);
+    super::send_block(
+        send.clone(),
+        &piece_zero[512..],
+        info_hash,
+        0,
+        512,
+        512,
+        |_| (),
+    );
+
+    let mut pieces_good = 0;
+    let mut blocks_processed = 0;
+    while pieces_good == 0 || blocks_processed != 2 {
+        match recv.next().await.unwrap() {
+            ODiskMessage::FoundGoodPiece(_, 0) => pieces_good += 1,
+            ODiskMessage::BlockProcessed(_) => blocks_processed += 1,
+            unexpected @ _ => panic!("Unexpected Message: {:?}", unexpected),
+        };
+    }
+
+    let info = PeerInfo::new(
+        "127.0.0.1:6881".parse().unwrap(),
+        [1u8; 20].into(),
+        info_hash,
+        Extensions::new(),
+    );
+    let mut uploader = Uploader::new(info_hash);
+    uploader.add_piece(0);
+    uploader.add_peer(info);
+    uploader.set_choked(&info, false);
+
+    // Peer asks for both blocks, then cancels the second one while it is still being read
+    let first = RequestMessage::new(0, 0, 512);
+    let second = RequestMessage::new(0, 512, 512);
+    uploader.on_request(&info, &first);
+    uploader.on_request(&info, &second);
+
+    let mut blocks_loading = 0;
+    while let Some(message) = uploader.poll_disk() {
+        send.send(message).await.unwrap();
+        blocks_loading += 1;
+    }
+    assert_eq!(2, blocks_loading);
+    uploader.on_cancel(&info, &CancelMessage::new(0, 512, 512));
+
+    for _ in 0..blocks_loading {
+        match recv.next().await.unwrap() {
+            ODiskMessage::BlockLoaded(block) => assert!(uploader.on_block_loaded(block)),
+            unexpected @ _ => panic!("Unexpected Message: {:?}", unexpected),
+        };
+    }
+
+    // Only the first block goes out, as read from disk
+    assert_eq!(
+        Some((
+            info,
+            PeerWireProtocolMessage::Piece(PieceMessage::new(
+                0,
+                0,
+                Bytes::from(data_a.0[0..512].to_vec())
+            ))
+        )),
+        uploader.poll()
+    );
+    assert!(uploader.poll().is_none());
+    assert_eq!(0, uploader.num_queued(&info));
+}
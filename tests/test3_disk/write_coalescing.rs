@@ -0,0 +1,233 @@
+use std::time::Duration;
+
+use bittorrent_protocol::disk::{
+    AllocationMode, DiskManagerBuilder, DiskManagerSink, DiskManagerStream, IDiskMessage,
+    MemoryFileSystem, ODiskMessage,
+};
+use bittorrent_protocol::util::bt::InfoHash;
+use futures::StreamExt;
+use rand::Rng;
+
+const PIECE_LEN: usize = 2048;
+const BLOCK_LEN: usize = 512;
+
+/// Every block of the torrent, as the piece index and block offset.
+fn torrent_blocks(total_len: usize) -> Vec<(u64, u64)> {
+    (0..total_len)
+        .step_by(BLOCK_LEN)
+        .map(|start| ((start / PIECE_LEN) as u64, (start % PIECE_LEN) as u64))
+        .collect()
+}
+
+fn send_block(
+    send: &DiskManagerSink<MemoryFileSystem>,
+    data: &[u8],
+    hash: InfoHash,
+    (piece_index, block_offset): (u64, u64),
+) {
+    let start = piece_index as usize * PIECE_LEN + block_offset as usize;
+    let end = std::cmp::min(start + BLOCK_LEN, data.len());
+
+    super::send_block(
+        send.clone(),
+        &data[start..end],
+        hash,
+        piece_index,
+        block_offset,
+        end - start,
+        |_| (),
+    );
+}
+
+/// Receive messages until the given number of pieces were found and blocks were processed,
+/// returning whether each piece found was good.
+async fn receive(
+    recv: &mut DiskManagerStream,
+    mut pieces: usize,
+    mut blocks: usize,
+) -> Vec<(u64, bool)> {
+    let mut found = Vec::new();
+
+    while pieces != 0 || blocks != 0 {
+        match recv.next().await.unwrap() {
+            ODiskMessage::FoundGoodPiece(_, index) => found.push((index, true)),
+            ODiskMessage::FoundBadPiece(_, index) => found.push((index, false)),
+            ODiskMessage::BlockProcessed(_) => {
+                blocks -= 1;
+                continue;
+            }
+            unexpected @ _ => panic!("Unexpected Message: {:?}", unexpected),
+        };
+        pieces -= 1;
+    }
+    found.sort();
+
+    found
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn positive_out_of_order_blocks_coalesced() {
+    // Four pieces, the second one spanning both files
+    let data = super::random_buffer(8000);
+    let (data_a, data_b) = data.split_at(3000);
+    let metainfo_file = super::build_torrent(&[(data_a, "a"), (data_b, "b")], PIECE_LEN);
+    let info_hash = metainfo_file.info().info_hash();
+
+    let filesystem = MemoryFileSystem::new();
+    let (mut send, mut recv) = DiskManagerBuilder::new()
+        .with_write_buffer_size(1024 * 1024)
+        .with_sink_buffer_capacity(100)
+        .with_allocation_mode(AllocationMode::DontAllocate)
+        .build(filesystem.clone())
+        .into_parts();
+    super::add_torrent(
+        &mut send,
+        &mut recv,
+        IDiskMessage::AddTorrent(metainfo_file),
+    )
+    .await;
+
+    let mut blocks = torrent_blocks(data.len());
+    rand::weak_rng().shuffle(&mut blocks);
+    for &block in blocks.iter() {
+        send_block(&send, &data, info_hash, block);
+    }
+
+    assert_eq!(
+        vec![(0, true), (1, true), (2, true), (3, true)],
+        receive(&mut recv, 4, blocks.len()).await
+    );
+    assert_eq!(
+        Some(data[..3000].to_vec()),
+        filesystem.file_contents("downloads/a")
+    );
+    assert_eq!(
+        Some(data[3000..].to_vec()),
+        filesystem.file_contents("downloads/b")
+    );
+
+    // Each piece was written at once
+    let stats = send.write_buffer_stats();
+    assert_eq!(4, stats.coalesced_writes());
+    assert_eq!(blocks.len() as u64, stats.coalesced_blocks());
+    assert_eq!(0, stats.direct_writes());
+    assert_eq!(0, stats.buffered_bytes());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn positive_flush_incomplete_piece_after_interval() {
+    let data = super::random_buffer(8000);
+    let (data_a, data_b) = data.split_at(3000);
+    let metainfo_file = super::build_torrent(&[(data_a, "a"), (data_b, "b")], PIECE_LEN);
+    let info_hash = metainfo_file.info().info_hash();
+
+    let filesystem = MemoryFileSystem::new();
+    let (mut send, mut recv) = DiskManagerBuilder::new()
+        .with_write_buffer_size(1024 * 1024)
+        .with_write_flush_interval(Duration::from_millis(10))
+        .with_sink_buffer_capacity(100)
+        .with_allocation_mode(AllocationMode::DontAllocate)
+        .build(filesystem.clone())
+        .into_parts();
+    super::add_torrent(
+        &mut send,
+        &mut recv,
+        IDiskMessage::AddTorrent(metainfo_file),
+    )
+    .await;
+
+    send_block(&send, &data, info_hash, (0, 0));
+    assert!(receive(&mut recv, 0, 1).await.is_empty());
+    assert_eq!(Some(Vec::new()), filesystem.file_contents("downloads/a"));
+
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    assert_eq!(
+        Some(data[..BLOCK_LEN].to_vec()),
+        filesystem.file_contents("downloads/a")
+    );
+
+    // The rest of the piece is read back from the file system to verify it
+    for block_offset in (BLOCK_LEN..PIECE_LEN).step_by(BLOCK_LEN) {
+        send_block(&send, &data, info_hash, (0, block_offset as u64));
+    }
+    assert_eq!(vec![(0, true)], receive(&mut recv, 1, 3).await);
+
+    let stats = send.write_buffer_stats();
+    assert_eq!((1, 3), (stats.coalesced_writes(), stats.direct_writes()));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn positive_flush_under_memory_pressure() {
+    let data = super::random_buffer(8000);
+    let (data_a, data_b) = data.split_at(3000);
+    let metainfo_file = super::build_torrent(&[(data_a, "a"), (data_b, "b")], PIECE_LEN);
+    let info_hash = metainfo_file.info().info_hash();
+
+    let filesystem = MemoryFileSystem::new();
+    let (mut send, mut recv) = DiskManagerBuilder::new()
+        .with_write_buffer_size(2 * BLOCK_LEN)
+        .with_sink_buffer_capacity(100)
+        .with_allocation_mode(AllocationMode::DontAllocate)
+        .build(filesystem.clone())
+        .into_parts();
+    super::add_torrent(
+        &mut send,
+        &mut recv,
+        IDiskMessage::AddTorrent(metainfo_file),
+    )
+    .await;
+
+    // The third block does not fit, so the first two are written out to make room
+    for block_offset in (0..(PIECE_LEN - BLOCK_LEN)).step_by(BLOCK_LEN) {
+        send_block(&send, &data, info_hash, (0, block_offset as u64));
+        assert!(receive(&mut recv, 0, 1).await.is_empty());
+    }
+    send_block(&send, &data, info_hash, (0, (PIECE_LEN - BLOCK_LEN) as u64));
+    assert_eq!(vec![(0, true)], receive(&mut recv, 1, 1).await);
+    assert_eq!(
+        Some(data[..PIECE_LEN].to_vec()),
+        filesystem.file_contents("downloads/a")
+    );
+
+    let stats = send.write_buffer_stats();
+    assert_eq!(
+        (1, 2, 2),
+        (
+            stats.coalesced_writes(),
+            stats.coalesced_blocks(),
+            stats.direct_writes()
+        )
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn negative_corrupt_piece_never_written() {
+    let data = super::random_buffer(8000);
+    let (data_a, data_b) = data.split_at(3000);
+    let metainfo_file = super::build_torrent(&[(data_a, "a"), (data_b, "b")], PIECE_LEN);
+    let info_hash = metainfo_file.info().info_hash();
+
+    let filesystem = MemoryFileSystem::new();
+    let (mut send, mut recv) = DiskManagerBuilder::new()
+        .with_write_buffer_size(1024 * 1024)
+        .with_sink_buffer_capacity(100)
+        .with_allocation_mode(AllocationMode::DontAllocate)
+        .build(filesystem.clone())
+        .into_parts();
+    super::add_torrent(
+        &mut send,
+        &mut recv,
+        IDiskMessage::AddTorrent(metainfo_file),
+    )
+    .await;
+
+    let mut corrupt_data = data.clone();
+    corrupt_data[BLOCK_LEN] = !corrupt_data[BLOCK_LEN];
+    for block_offset in (0..PIECE_LEN).step_by(BLOCK_LEN) {
+        send_block(&send, &corrupt_data, info_hash, (0, block_offset as u64));
+    }
+
+    assert_eq!(vec![(0, false)], receive(&mut recv, 1, 4).await);
+    assert_eq!(Some(Vec::new()), filesystem.file_contents("downloads/a"));
+    assert_eq!(0, send.write_buffer_stats().coalesced_writes());
+}
@@ -15,7 +15,9 @@ mod test_client_drop;
 mod test_client_full;
 mod test_connect;
 mod test_connect_cache;
+mod test_proxy;
 mod test_scrape;
+mod test_scrape_batch;
 mod test_server_drop;
 
 #[test]
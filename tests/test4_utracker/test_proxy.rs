@@ -0,0 +1,153 @@
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpListener, UdpSocket};
+use std::sync::mpsc::{self};
+use std::sync::{Arc, Mutex};
+use std::thread::{self};
+use std::time::Duration;
+
+use super::{MockHandshaker, MockTrackerHandler};
+use bittorrent_protocol::util::bt;
+use bittorrent_protocol::utracker::announce::{AnnounceEvent, ClientState};
+use bittorrent_protocol::utracker::{
+    ClientRequest, ProxyConfig, ProxyError, ProxyKind, TrackerClient, TrackerServer,
+};
+
+/// SOCKS5 proxy relaying the packets of a single UDP ASSOCIATE, returning the tracker
+/// addresses that relayed packets were sent to.
+fn socks_relay() -> (SocketAddr, Arc<Mutex<Vec<SocketAddr>>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let relayed = Arc::new(Mutex::new(Vec::new()));
+
+    let thread_relayed = relayed.clone();
+    thread::spawn(move || {
+        let (mut control, _) = listener.accept().unwrap();
+
+        let mut greeting = [0u8; 3];
+        control.read_exact(&mut greeting).unwrap();
+        assert_eq!(greeting, [0x05, 0x01, 0x00]);
+        control.write_all(&[0x05, 0x00]).unwrap();
+
+        let mut request = [0u8; 10];
+        control.read_exact(&mut request).unwrap();
+        assert_eq!(&request[..4], &[0x05, 0x03, 0x00, 0x01]);
+
+        // Answer with an unspecified address, meaning the address of the proxy
+        let relay = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let port = relay.local_addr().unwrap().port().to_be_bytes();
+        control
+            .write_all(&[0x05, 0x00, 0x00, 0x01, 0, 0, 0, 0, port[0], port[1]])
+            .unwrap();
+
+        let mut opt_client = None;
+        let mut buffer = [0u8; 1600];
+        loop {
+            let (length, from) = relay.recv_from(&mut buffer).unwrap();
+
+            match opt_client {
+                Some(client) if client != from => {
+                    let mut packet = vec![0, 0, 0, 0x01];
+                    match from {
+                        SocketAddr::V4(from) => packet.extend_from_slice(&from.ip().octets()),
+                        SocketAddr::V6(_) => panic!("Relay Only Expects IPv4 Trackers"),
+                    }
+                    packet.extend_from_slice(&from.port().to_be_bytes());
+                    packet.extend_from_slice(&buffer[..length]);
+
+                    relay.send_to(&packet, client).unwrap();
+                }
+                _ => {
+                    opt_client = Some(from);
+                    assert_eq!(&buffer[..4], &[0, 0, 0, 0x01]);
+
+                    let tracker = SocketAddr::from((
+                        [buffer[4], buffer[5], buffer[6], buffer[7]],
+                        u16::from_be_bytes([buffer[8], buffer[9]]),
+                    ));
+                    thread_relayed.lock().unwrap().push(tracker);
+
+                    relay.send_to(&buffer[10..length], tracker).unwrap();
+                }
+            }
+        }
+    });
+
+    (addr, relayed)
+}
+
+#[test]
+#[allow(unused)]
+fn positive_announce_through_proxy() {
+    let (send, recv) = mpsc::channel();
+
+    let server_addr = "127.0.0.1:3510".parse().unwrap();
+    let mock_handler = MockTrackerHandler::new();
+    let server = TrackerServer::run(server_addr, mock_handler).unwrap();
+
+    thread::sleep(Duration::from_millis(100));
+
+    let (proxy_addr, relayed) = socks_relay();
+    let mock_handshaker = MockHandshaker::new(send);
+    let mut client = TrackerClient::with_proxy(
+        "127.0.0.1:4510".parse().unwrap(),
+        mock_handshaker.clone(),
+        &ProxyConfig::new(ProxyKind::Socks5, proxy_addr),
+    )
+    .unwrap();
+
+    let send_token = client
+        .request(
+            server_addr,
+            ClientRequest::Announce(
+                [0u8; bt::INFO_HASH_LEN].into(),
+                ClientState::new(0, 0, 0, AnnounceEvent::Started),
+            ),
+        )
+        .unwrap();
+
+    let metadata = recv.recv_timeout(Duration::from_secs(5)).unwrap();
+    assert_eq!(send_token, metadata.token());
+
+    let response = metadata
+        .result()
+        .as_ref()
+        .unwrap()
+        .announce_response()
+        .unwrap();
+    assert_eq!(response.peers().iter().count(), 1);
+
+    // Both the connect and the announce went through the relay
+    assert_eq!(*relayed.lock().unwrap(), vec![server_addr, server_addr]);
+}
+
+#[test]
+fn negative_proxy_rejected() {
+    let (send, _recv) = mpsc::channel();
+    let closed_addr = TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap();
+
+    let error = TrackerClient::with_proxy(
+        "127.0.0.1:0".parse().unwrap(),
+        MockHandshaker::new(send.clone()),
+        &ProxyConfig::new(ProxyKind::Socks5, closed_addr),
+    )
+    .err()
+    .unwrap();
+    assert_eq!(
+        ProxyError::from_io(&error),
+        Some(ProxyError::Connection(io::ErrorKind::ConnectionRefused))
+    );
+
+    // Http proxies can not relay udp packets at all
+    let error = TrackerClient::with_proxy(
+        "127.0.0.1:0".parse().unwrap(),
+        MockHandshaker::new(send),
+        &ProxyConfig::new(ProxyKind::Http, closed_addr),
+    )
+    .err()
+    .unwrap();
+    assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+    assert_eq!(ProxyError::from_io(&error), None);
+}
@@ -0,0 +1,51 @@
+use std::sync::mpsc::{self};
+use std::thread::{self};
+use std::time::Duration;
+
+use super::{MockHandshaker, MockTrackerHandler};
+use bittorrent_protocol::util::bt::InfoHash;
+use bittorrent_protocol::utracker::scrape::MAX_SCRAPE_HASHES;
+use bittorrent_protocol::utracker::{TrackerClient, TrackerServer};
+
+#[test]
+#[allow(unused)]
+fn positive_scrape_batch() {
+    let (send, recv) = mpsc::channel();
+
+    let server_addr = "127.0.0.1:3509".parse().unwrap();
+    let mock_handler = MockTrackerHandler::new();
+    let server = TrackerServer::run(server_addr, mock_handler).unwrap();
+
+    thread::sleep(Duration::from_millis(100));
+
+    let mock_handshaker = MockHandshaker::new(send);
+    let mut client =
+        TrackerClient::new("127.0.0.1:4509".parse().unwrap(), mock_handshaker.clone()).unwrap();
+
+    let hashes: Vec<InfoHash> = (0..MAX_SCRAPE_HASHES + 26)
+        .map(|index| [index as u8; 20].into())
+        .collect();
+    let send_tokens = client.scrape(server_addr, &hashes).unwrap();
+    assert_eq!(send_tokens.len(), 2);
+
+    for _ in 0..send_tokens.len() {
+        let metadata = recv.recv().unwrap();
+        let stats = metadata
+            .result()
+            .as_ref()
+            .unwrap()
+            .scrape_batch_response()
+            .unwrap();
+
+        let batch = if metadata.token() == send_tokens[0] {
+            &hashes[..MAX_SCRAPE_HASHES]
+        } else {
+            assert_eq!(metadata.token(), send_tokens[1]);
+            &hashes[MAX_SCRAPE_HASHES..]
+        };
+        assert_eq!(stats.len(), batch.len());
+        for hash in batch {
+            assert_eq!(stats.get(hash).unwrap().num_seeders(), 0);
+        }
+    }
+}
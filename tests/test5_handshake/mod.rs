@@ -1,10 +1,18 @@
+mod test_async_connect;
 mod test_byte_after_handshake;
 mod test_bytes_after_handshake;
 mod test_connect;
+mod test_connect_ipv6;
+mod test_connection_limits;
 mod test_filter_allow_all;
 mod test_filter_block_all;
+mod test_filter_hash_allowlist;
 mod test_filter_whitelist_diff_data;
 mod test_filter_whitelist_same_data;
+mod test_handshake_timeout;
+mod test_holepunch;
+mod test_mock_transport;
+mod test_utp_transport;
 
 //----------------------------------------------------------------------------------//
 
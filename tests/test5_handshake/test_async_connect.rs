@@ -0,0 +1,102 @@
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use bittorrent_protocol::handshake::transports::{MockTransport, TcpTransport};
+use bittorrent_protocol::handshake::{
+    DiscoveryInfo, HandshakerManagerBuilder, InitiateMessage, Protocol,
+};
+use bittorrent_protocol::util::bt;
+use futures::sink::SinkExt;
+use futures::stream::StreamExt;
+
+const TIMEOUT_SECS: u64 = 10;
+
+#[tokio::test]
+async fn positive_async_connect() {
+    let mut handshaker_one_addr: SocketAddr = "127.0.0.1:0".parse().unwrap();
+    let handshaker_one_pid = [4u8; bt::PEER_ID_LEN].into();
+    let handshaker_one = HandshakerManagerBuilder::new()
+        .with_bind_addr(handshaker_one_addr)
+        .with_peer_id(handshaker_one_pid)
+        .build(TcpTransport)
+        .unwrap();
+    handshaker_one_addr.set_port(handshaker_one.port());
+    let (_, mut stream_one) = handshaker_one.into_parts();
+
+    let handshaker_two_pid = [5u8; bt::PEER_ID_LEN].into();
+    let handshaker_two = HandshakerManagerBuilder::new()
+        .with_bind_addr("127.0.0.1:0".parse().unwrap())
+        .with_peer_id(handshaker_two_pid)
+        .build(TcpTransport)
+        .unwrap();
+    let (mut sink_two, mut stream_two) = handshaker_two.into_parts();
+
+    SinkExt::send(
+        &mut sink_two,
+        InitiateMessage::new(
+            Protocol::BitTorrent,
+            [55u8; bt::INFO_HASH_LEN].into(),
+            handshaker_one_addr,
+        ),
+    )
+    .await
+    .unwrap();
+
+    let timeout = Duration::from_secs(TIMEOUT_SECS);
+    let (_, _, hash_one, pid_one, _, _) = tokio::time::timeout(timeout, stream_one.next())
+        .await
+        .unwrap()
+        .unwrap()
+        .into_parts();
+    let (_, _, hash_two, pid_two, addr_two, _) = tokio::time::timeout(timeout, stream_two.next())
+        .await
+        .unwrap()
+        .unwrap()
+        .into_parts();
+
+    assert_eq!(handshaker_two_pid, pid_one);
+    assert_eq!(handshaker_one_pid, pid_two);
+    assert_eq!(hash_one, hash_two);
+    assert_eq!(handshaker_one_addr, addr_two);
+}
+
+#[tokio::test]
+async fn positive_async_and_blocking_halves_agree() {
+    let transport = MockTransport::new();
+
+    let mut handshaker_one_addr: SocketAddr = "10.0.0.1:0".parse().unwrap();
+    let handshaker_one_pid = [4u8; bt::PEER_ID_LEN].into();
+    let mut handshaker_one = HandshakerManagerBuilder::new()
+        .with_bind_addr(handshaker_one_addr)
+        .with_peer_id(handshaker_one_pid)
+        .build(transport.clone())
+        .unwrap();
+    handshaker_one_addr.set_port(handshaker_one.port());
+
+    let handshaker_two_pid = [5u8; bt::PEER_ID_LEN].into();
+    let mut handshaker_two = HandshakerManagerBuilder::new()
+        .with_bind_addr("10.0.0.2:0".parse().unwrap())
+        .with_peer_id(handshaker_two_pid)
+        .build(transport)
+        .unwrap();
+
+    // Initiated through the blocking send, completed through the stream and blocking poll
+    handshaker_two
+        .send(InitiateMessage::new(
+            Protocol::BitTorrent,
+            [55u8; bt::INFO_HASH_LEN].into(),
+            handshaker_one_addr,
+        ))
+        .unwrap();
+    let timeout = Duration::from_secs(TIMEOUT_SECS);
+    let complete_one = tokio::time::timeout(timeout, handshaker_one.next())
+        .await
+        .unwrap()
+        .unwrap();
+    let complete_two = handshaker_two.poll().unwrap();
+
+    assert_eq!(&handshaker_two_pid, complete_one.peer_id());
+    assert_eq!(&handshaker_one_pid, complete_two.peer_id());
+    assert_eq!(complete_one.hash(), complete_two.hash());
+    assert_eq!(&handshaker_one_addr, complete_two.address());
+}
@@ -0,0 +1,71 @@
+use std::net::{IpAddr, SocketAddr};
+
+use bittorrent_protocol::handshake::transports::TcpTransport;
+use bittorrent_protocol::handshake::{
+    DiscoveryInfo, HandshakerManagerBuilder, InitiateMessage, Protocol,
+};
+use bittorrent_protocol::util::bt;
+
+#[test]
+fn positive_connect_over_ipv6() {
+    let mut handshaker_one_addr: SocketAddr = "[::1]:0".parse().unwrap();
+    let handshaker_one_pid = [4u8; bt::PEER_ID_LEN].into();
+    let mut handshaker_one = HandshakerManagerBuilder::new()
+        .with_bind_addr(handshaker_one_addr)
+        .with_peer_id(handshaker_one_pid)
+        .build(TcpTransport)
+        .unwrap();
+    handshaker_one_addr.set_port(handshaker_one.port());
+
+    let mut handshaker_two = HandshakerManagerBuilder::new()
+        .with_bind_addr("[::1]:0".parse().unwrap())
+        .with_peer_id([5u8; bt::PEER_ID_LEN].into())
+        .build(TcpTransport)
+        .unwrap();
+
+    handshaker_two
+        .send(InitiateMessage::new(
+            Protocol::BitTorrent,
+            [55u8; bt::INFO_HASH_LEN].into(),
+            handshaker_one_addr,
+        ))
+        .unwrap();
+
+    let complete = handshaker_one.poll().unwrap();
+    assert_eq!(
+        handshaker_one_pid,
+        *handshaker_two.poll().unwrap().peer_id()
+    );
+    assert!(complete.address().is_ipv6());
+}
+
+#[test]
+fn positive_dual_stack_reports_ipv4_peer() {
+    let mut handshaker_one_addr: SocketAddr = "127.0.0.1:0".parse().unwrap();
+    let mut handshaker_one = HandshakerManagerBuilder::new()
+        .with_bind_addr("[::]:0".parse().unwrap())
+        .with_peer_id([4u8; bt::PEER_ID_LEN].into())
+        .build(TcpTransport)
+        .unwrap();
+    handshaker_one_addr.set_port(handshaker_one.port());
+
+    let mut handshaker_two = HandshakerManagerBuilder::new()
+        .with_bind_addr("127.0.0.1:0".parse().unwrap())
+        .with_peer_id([5u8; bt::PEER_ID_LEN].into())
+        .build(TcpTransport)
+        .unwrap();
+
+    handshaker_two
+        .send(InitiateMessage::new(
+            Protocol::BitTorrent,
+            [55u8; bt::INFO_HASH_LEN].into(),
+            handshaker_one_addr,
+        ))
+        .unwrap();
+
+    let complete = handshaker_one.poll().unwrap();
+    assert_eq!(
+        "127.0.0.1".parse::<IpAddr>().unwrap(),
+        complete.address().ip()
+    );
+}
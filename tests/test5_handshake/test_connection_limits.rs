@@ -0,0 +1,109 @@
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use bittorrent_protocol::handshake::transports::MockTransport;
+use bittorrent_protocol::handshake::{
+    DiscoveryInfo, HandshakeError, HandshakerConfig, HandshakerManagerBuilder, InitiateMessage,
+    Protocol,
+};
+use bittorrent_protocol::util::bt;
+use futures::stream::StreamExt;
+
+const TIMEOUT_SECS: u64 = 10;
+const NUM_CONNECTIONS: usize = 50;
+const MAX_PEERS: usize = 10;
+
+#[tokio::test]
+async fn positive_inbound_over_max_global_peers_are_closed() {
+    let transport = MockTransport::new();
+
+    let mut handshaker_one_addr: SocketAddr = "10.0.0.1:0".parse().unwrap();
+    let handshaker_one = HandshakerManagerBuilder::new()
+        .with_bind_addr(handshaker_one_addr)
+        .with_peer_id([4u8; bt::PEER_ID_LEN].into())
+        .with_max_global_peers(Some(MAX_PEERS))
+        .build(transport.clone())
+        .unwrap();
+    handshaker_one_addr.set_port(handshaker_one.port());
+    let limits = handshaker_one.connection_limits();
+    let (_, mut stream_one) = handshaker_one.into_parts();
+
+    let mut handshaker_two = HandshakerManagerBuilder::new()
+        .with_bind_addr("10.0.0.2:0".parse().unwrap())
+        .with_peer_id([5u8; bt::PEER_ID_LEN].into())
+        .with_config(HandshakerConfig::default().with_done_buffer_size(NUM_CONNECTIONS))
+        .build(transport)
+        .unwrap();
+    for _ in 0..NUM_CONNECTIONS {
+        handshaker_two
+            .send(InitiateMessage::new(
+                Protocol::BitTorrent,
+                [55u8; bt::INFO_HASH_LEN].into(),
+                handshaker_one_addr,
+            ))
+            .unwrap();
+    }
+
+    // Completed handshakes are not taken off the stream until the rest were turned away
+    let timeout = Duration::from_secs(TIMEOUT_SECS);
+    for _ in 0..NUM_CONNECTIONS - MAX_PEERS {
+        match stream_one.poll_error_timeout(timeout) {
+            Some(HandshakeError::TooManyPeers(_)) => (),
+            other => panic!("Unexpected Error {:?}", other),
+        }
+    }
+    for _ in 0..MAX_PEERS {
+        tokio::time::timeout(timeout, stream_one.next())
+            .await
+            .unwrap()
+            .unwrap();
+    }
+    assert_eq!(MAX_PEERS, limits.connected_peers());
+    assert_eq!(0, limits.handshaking_peers());
+
+    // Room is made once fewer peers are reported as kept
+    limits.set_connected_peers(MAX_PEERS - 1);
+    handshaker_two
+        .send(InitiateMessage::new(
+            Protocol::BitTorrent,
+            [55u8; bt::INFO_HASH_LEN].into(),
+            handshaker_one_addr,
+        ))
+        .unwrap();
+    tokio::time::timeout(timeout, stream_one.next())
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(stream_one.poll_error().is_none());
+}
+
+#[test]
+fn positive_outbound_over_max_global_peers_not_attempted() {
+    let transport = MockTransport::new();
+
+    let mut handshaker_one_addr: SocketAddr = "10.0.0.1:0".parse().unwrap();
+    let handshaker_one = HandshakerManagerBuilder::new()
+        .with_bind_addr(handshaker_one_addr)
+        .build(transport.clone())
+        .unwrap();
+    handshaker_one_addr.set_port(handshaker_one.port());
+
+    let mut handshaker_two = HandshakerManagerBuilder::new()
+        .with_bind_addr("10.0.0.2:0".parse().unwrap())
+        .with_max_global_peers(Some(0))
+        .build(transport)
+        .unwrap();
+    handshaker_two
+        .send(InitiateMessage::new(
+            Protocol::BitTorrent,
+            [55u8; bt::INFO_HASH_LEN].into(),
+            handshaker_one_addr,
+        ))
+        .unwrap();
+
+    assert_eq!(
+        Some(HandshakeError::TooManyPeers(handshaker_one_addr)),
+        handshaker_two.poll_error_timeout(Duration::from_secs(TIMEOUT_SECS))
+    );
+    assert_eq!(0, handshaker_two.connection_limits().half_open_connections());
+}
@@ -4,8 +4,8 @@ use std::time::Duration;
 
 use bittorrent_protocol::handshake::transports::TcpTransport;
 use bittorrent_protocol::handshake::{
-    DiscoveryInfo, Extensions, FilterDecision, HandshakeFilter, HandshakeFilters,
-    HandshakerManagerBuilder, InitiateMessage, Protocol,
+    DiscoveryInfo, Extensions, FilterDecision, FilteredMessage, HandshakeFilter,
+    HandshakeFilters, HandshakerManagerBuilder, InitiateMessage, Protocol,
 };
 use bittorrent_protocol::util::bt;
 use bittorrent_protocol::util::bt::{InfoHash, PeerId};
@@ -64,11 +64,13 @@ fn test_filter_all() {
 
 
     let (_, mut stream_one) = handshaker_one.into_parts();
-    let (mut sink_two, mut stream_two) = handshaker_two.into_parts();
+    let (mut sink_two, _) = handshaker_two.into_parts();
 
     sink_two.send(InitiateMessage::new(Protocol::BitTorrent, [55u8; bt::INFO_HASH_LEN].into(), handshaker_one_addr));
 
-    let result_one = stream_one.poll().unwrap();
-
-    let result_two = stream_two.poll().unwrap();
+    // The connection is dropped as soon as it is accepted, before any handshake is read
+    match stream_one.poll_filtered_timeout(Duration::from_secs(5)) {
+        Some(FilteredMessage::Complete(_, None, None)) => (),
+        other => panic!("Unexpected Filtered Message {:?}", other),
+    }
 }
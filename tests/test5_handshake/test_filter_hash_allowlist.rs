@@ -0,0 +1,121 @@
+use std::any::Any;
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::time::Duration;
+
+use bittorrent_protocol::handshake::transports::TcpTransport;
+use bittorrent_protocol::handshake::{
+    DiscoveryInfo, FilterDecision, FilteredMessage, HandshakeFilter, HandshakerManagerBuilder,
+    InitiateMessage, Protocol,
+};
+use bittorrent_protocol::peer::{
+    IPeerManagerMessage, OPeerManagerMessage, PeerInfo, PeerManagerBuilder,
+};
+use bittorrent_protocol::util::bt;
+use bittorrent_protocol::util::bt::InfoHash;
+
+pub struct FilterAllowHashes {
+    hashes: HashSet<InfoHash>,
+}
+
+impl HandshakeFilter for FilterAllowHashes {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn on_hash(&self, opt_hash: Option<&InfoHash>) -> FilterDecision {
+        match opt_hash {
+            Some(hash) if self.hashes.contains(hash) => FilterDecision::Pass,
+            Some(_) => FilterDecision::Block,
+            None => FilterDecision::NeedData,
+        }
+    }
+}
+
+#[test]
+fn positive_filtered_hash_never_reaches_peer_manager() {
+    let allowed_hash: InfoHash = [55u8; bt::INFO_HASH_LEN].into();
+    let blocked_hash: InfoHash = [66u8; bt::INFO_HASH_LEN].into();
+
+    let mut handshaker_one_addr = "127.0.0.1:0".parse().unwrap();
+    let mut handshaker_one = HandshakerManagerBuilder::new()
+        .with_bind_addr(handshaker_one_addr)
+        .with_peer_id([4u8; bt::PEER_ID_LEN].into())
+        .with_filter(Arc::new(FilterAllowHashes {
+            hashes: vec![allowed_hash].into_iter().collect(),
+        }))
+        .build(TcpTransport)
+        .unwrap();
+    handshaker_one_addr.set_port(handshaker_one.port());
+
+    let mut handshaker_two = HandshakerManagerBuilder::new()
+        .with_bind_addr("127.0.0.1:0".parse().unwrap())
+        .with_peer_id([5u8; bt::PEER_ID_LEN].into())
+        .build(TcpTransport)
+        .unwrap();
+
+    // Handshakes are processed in order, so the blocked one is done by the time the allowed one is
+    handshaker_two
+        .send(InitiateMessage::new(Protocol::BitTorrent, blocked_hash, handshaker_one_addr))
+        .unwrap();
+    handshaker_two
+        .send(InitiateMessage::new(Protocol::BitTorrent, allowed_hash, handshaker_one_addr))
+        .unwrap();
+
+    let mut peer_manager = PeerManagerBuilder::new().build();
+
+    let (_, ext, hash, pid, addr, sock) = handshaker_one.poll().unwrap().into_parts();
+    let info = PeerInfo::new(addr, pid, hash, ext);
+    peer_manager.send(IPeerManagerMessage::AddPeer(info, sock));
+
+    match peer_manager.poll() {
+        Some(OPeerManagerMessage::PeerAdded(added, _)) => assert_eq!(allowed_hash, *added.hash()),
+        other => panic!("Unexpected Message {:?}", other),
+    }
+
+    match handshaker_one.poll_filtered_timeout(Duration::from_secs(5)) {
+        Some(FilteredMessage::Complete(_, Some(hash), _)) => assert_eq!(blocked_hash, hash),
+        other => panic!("Unexpected Filtered Message {:?}", other),
+    }
+    assert_eq!(None, handshaker_one.poll_filtered());
+}
+
+#[test]
+fn positive_filter_swapped_at_runtime() {
+    let hash: InfoHash = [55u8; bt::INFO_HASH_LEN].into();
+
+    let mut handshaker_one_addr = "127.0.0.1:0".parse().unwrap();
+    let mut handshaker_one = HandshakerManagerBuilder::new()
+        .with_bind_addr(handshaker_one_addr)
+        .with_peer_id([4u8; bt::PEER_ID_LEN].into())
+        .with_filter(Arc::new(FilterAllowHashes {
+            hashes: HashSet::new(),
+        }))
+        .build(TcpTransport)
+        .unwrap();
+    handshaker_one_addr.set_port(handshaker_one.port());
+
+    let mut handshaker_two = HandshakerManagerBuilder::new()
+        .with_bind_addr("127.0.0.1:0".parse().unwrap())
+        .with_peer_id([5u8; bt::PEER_ID_LEN].into())
+        .build(TcpTransport)
+        .unwrap();
+
+    handshaker_two
+        .send(InitiateMessage::new(Protocol::BitTorrent, hash, handshaker_one_addr))
+        .unwrap();
+    match handshaker_one.poll_filtered_timeout(Duration::from_secs(5)) {
+        Some(filtered) => assert_eq!(Some(&hash), filtered.hash()),
+        None => panic!("Expected Handshake To Be Filtered"),
+    }
+
+    // Reload the allowlist, the next handshake for the same hash should go through
+    handshaker_one.set_filter(Some(Arc::new(FilterAllowHashes {
+        hashes: vec![hash].into_iter().collect(),
+    })));
+    handshaker_two
+        .send(InitiateMessage::new(Protocol::BitTorrent, hash, handshaker_one_addr))
+        .unwrap();
+
+    assert_eq!(hash, *handshaker_one.poll().unwrap().hash());
+}
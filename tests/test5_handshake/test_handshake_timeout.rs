@@ -0,0 +1,79 @@
+use std::net::TcpListener;
+use std::time::Duration;
+
+use bittorrent_protocol::handshake::transports::TcpTransport;
+use bittorrent_protocol::handshake::{
+    HandshakeError, HandshakerManagerBuilder, InitiateMessage, Protocol, RetryPolicy,
+};
+use bittorrent_protocol::util::bt;
+
+#[test]
+fn positive_silent_peer_times_out() {
+    // Accepts connections, but never sends a handshake back
+    let silent_listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let silent_addr = silent_listener.local_addr().unwrap();
+
+    let mut handshaker = HandshakerManagerBuilder::new()
+        .with_bind_addr("127.0.0.1:0".parse().unwrap())
+        .with_peer_id([4u8; bt::PEER_ID_LEN].into())
+        .with_handshake_timeout(Duration::from_millis(200))
+        .build(TcpTransport)
+        .unwrap();
+
+    handshaker
+        .send(InitiateMessage::new(
+            Protocol::BitTorrent,
+            [55u8; bt::INFO_HASH_LEN].into(),
+            silent_addr,
+        ))
+        .unwrap();
+
+    assert_eq!(
+        Some(HandshakeError::HandshakeTimedOut(silent_addr)),
+        handshaker.poll_error_timeout(Duration::from_secs(5))
+    );
+}
+
+#[test]
+fn positive_failed_address_backs_off_and_retries() {
+    // Grab a free port, then close it so connections are refused
+    let closed_addr = TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap();
+
+    let mut handshaker = HandshakerManagerBuilder::new()
+        .with_bind_addr("127.0.0.1:0".parse().unwrap())
+        .with_peer_id([4u8; bt::PEER_ID_LEN].into())
+        .with_retry_policy(
+            RetryPolicy::default()
+                .with_max_retries(1)
+                .with_initial_backoff(Duration::from_millis(500)),
+        )
+        .build(TcpTransport)
+        .unwrap();
+    let initiate = InitiateMessage::new(
+        Protocol::BitTorrent,
+        [55u8; bt::INFO_HASH_LEN].into(),
+        closed_addr,
+    );
+
+    handshaker.send(initiate.clone()).unwrap();
+    assert_eq!(
+        Some(HandshakeError::ConnectFailed(closed_addr)),
+        handshaker.poll_error_timeout(Duration::from_secs(5))
+    );
+
+    handshaker.send(initiate).unwrap();
+    assert_eq!(
+        Some(HandshakeError::CoolingDown(closed_addr)),
+        handshaker.poll_error_timeout(Duration::from_secs(5))
+    );
+
+    // The retry is attempted once the backoff is over
+    assert_eq!(
+        Some(HandshakeError::ConnectFailed(closed_addr)),
+        handshaker.poll_error_timeout(Duration::from_secs(5))
+    );
+    assert_eq!(None, handshaker.poll_error_timeout(Duration::from_secs(2)));
+}
@@ -0,0 +1,327 @@
+use std::collections::HashMap;
+use std::io::{self, Error, ErrorKind, Read, Write};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use bittorrent_protocol::handshake::transports::MockSocket;
+use bittorrent_protocol::handshake::{
+    Extensions, HandshakerManagerBuilder, InitiateMessage, LocalAddr, Protocol, Stream, Transport,
+};
+use bittorrent_protocol::peer::messages::builders::ExtendedMessageBuilder;
+use bittorrent_protocol::peer::messages::{
+    BitsExtensionMessage, HaveMessage, PeerExtensionProtocolMessage, PeerWireProtocolMessage,
+};
+use bittorrent_protocol::peer::{
+    MessageCodec, PeerInfo, PeerWireMessageCodec, PeerWireMessageDecoder,
+};
+use bittorrent_protocol::select::holepunch::HolepunchManager;
+use bittorrent_protocol::util::bt::{self, InfoHash, PeerId};
+use bytes::BytesMut;
+use crossbeam::channel::{unbounded, Receiver, Sender};
+
+/// Network where only public nodes accept connections, nodes behind a NAT can only be
+/// reached by dialing them while they dial us (a simultaneous open).
+#[derive(Default)]
+struct NatNetwork {
+    listeners: Mutex<HashMap<SocketAddr, Sender<(MockSocket, SocketAddr)>>>,
+    // Dials waiting on the node they dial to dial them back
+    dialing: Mutex<HashMap<(SocketAddr, SocketAddr), Sender<MockSocket>>>,
+}
+
+struct NatTransport {
+    network: Arc<NatNetwork>,
+    addr: SocketAddr,
+    public: bool,
+}
+
+struct NatListener {
+    addr: SocketAddr,
+    recv: Receiver<(MockSocket, SocketAddr)>,
+}
+
+impl Transport for NatTransport {
+    type Socket = MockSocket;
+    type Listener = NatListener;
+
+    fn connect(&self, addr: &SocketAddr) -> io::Result<MockSocket> {
+        self.connect_timeout(addr, Duration::from_millis(1000))
+    }
+
+    fn connect_timeout(&self, addr: &SocketAddr, timeout: Duration) -> io::Result<MockSocket> {
+        if let Some(send) = self.network.listeners.lock().unwrap().get(addr) {
+            let (ours, theirs) = MockSocket::pair();
+            send.send((theirs, self.addr)).unwrap();
+
+            return Ok(ours);
+        }
+
+        let recv = {
+            let mut dialing = self.network.dialing.lock().unwrap();
+
+            if let Some(send) = dialing.remove(&(*addr, self.addr)) {
+                let (ours, theirs) = MockSocket::pair();
+                send.send(theirs).unwrap();
+
+                return Ok(ours);
+            }
+            let (send, recv) = unbounded();
+            dialing.insert((self.addr, *addr), send);
+
+            recv
+        };
+
+        recv.recv_timeout(timeout).map_err(|_| {
+            self.network
+                .dialing
+                .lock()
+                .unwrap()
+                .remove(&(self.addr, *addr));
+
+            Error::new(ErrorKind::TimedOut, "Peer Did Not Dial Us Back")
+        })
+    }
+
+    fn listen(&self, _addr: &SocketAddr) -> io::Result<NatListener> {
+        let (send, recv) = unbounded();
+        if self.public {
+            self.network
+                .listeners
+                .lock()
+                .unwrap()
+                .insert(self.addr, send);
+        }
+
+        Ok(NatListener {
+            addr: self.addr,
+            recv: recv,
+        })
+    }
+}
+
+impl LocalAddr for NatListener {
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        Ok(self.addr)
+    }
+}
+
+impl Stream for NatListener {
+    type Item = (MockSocket, SocketAddr);
+
+    fn poll(&mut self) -> io::Result<(MockSocket, SocketAddr)> {
+        self.recv
+            .recv()
+            .map_err(|_| Error::new(ErrorKind::NotFound, "listener fail"))
+    }
+}
+
+/// Peer wire connection with the node on the other end.
+struct Conn<S> {
+    info: PeerInfo,
+    sock: S,
+    decoder: PeerWireMessageDecoder,
+    bytes: BytesMut,
+}
+
+impl<S: Read + Write> Conn<S> {
+    fn new(hash: InfoHash, pid: PeerId, addr: SocketAddr, sock: S) -> Conn<S> {
+        Conn {
+            info: PeerInfo::new(addr, pid, hash, Extensions::new()),
+            sock: sock,
+            decoder: PeerWireMessageDecoder::new(PeerWireMessageCodec::new()),
+            bytes: BytesMut::new(),
+        }
+    }
+
+    fn send(&mut self, message: PeerWireProtocolMessage) {
+        self.decoder
+            .codec_mut()
+            .write_bytes(&message, &mut self.sock)
+            .unwrap();
+    }
+
+    fn recv(&mut self) -> PeerWireProtocolMessage {
+        let mut buf = [0u8; 1024];
+
+        loop {
+            if let Some(message) = self.decoder.decode(&mut self.bytes).unwrap() {
+                return message;
+            }
+
+            let read = self.sock.read(&mut buf).unwrap();
+            assert!(read > 0, "connection closed before the message arrived");
+            self.bytes.extend_from_slice(&buf[..read]);
+        }
+    }
+
+    /// Send our extended message, advertising holepunch support.
+    fn extend(&mut self, manager: &mut HolepunchManager) {
+        let extended = manager.extend(ExtendedMessageBuilder::new()).build();
+        self.send(PeerWireProtocolMessage::BitsExtension(
+            BitsExtensionMessage::Extended(extended),
+        ));
+
+        manager.add_peer(self.info);
+    }
+
+    /// Let the manager know what the node supports.
+    fn recv_extended(&mut self, manager: &mut HolepunchManager) {
+        match self.recv() {
+            PeerWireProtocolMessage::BitsExtension(BitsExtensionMessage::Extended(extended)) => {
+                manager.on_extended(&self.info, &extended)
+            }
+            other => panic!("Expected Extended Message, Got {:?}", other),
+        }
+    }
+
+    /// Hand every holepunch message the manager has for this node to it.
+    fn flush(&mut self, manager: &mut HolepunchManager) {
+        let mut rest = Vec::new();
+
+        while let Some((info, message)) = manager.poll() {
+            if info == self.info {
+                self.send(PeerWireProtocolMessage::ProtExtension(message));
+            } else {
+                rest.push((info, message));
+            }
+        }
+        assert!(rest.is_empty(), "messages for other nodes: {:?}", rest);
+    }
+
+    fn recv_holepunch(&mut self, manager: &mut HolepunchManager) {
+        match self.recv() {
+            PeerWireProtocolMessage::ProtExtension(PeerExtensionProtocolMessage::UtHolepunch(
+                message,
+            )) => manager.on_message(&self.info, &message),
+            other => panic!("Expected Holepunch Message, Got {:?}", other),
+        }
+    }
+}
+
+#[test]
+fn positive_holepunch_through_mediator() {
+    let network = Arc::new(NatNetwork::default());
+    let hash: InfoHash = [55u8; bt::INFO_HASH_LEN].into();
+
+    let nodes = [
+        ("10.0.0.1:6881", true),
+        ("10.0.0.2:6881", false),
+        ("10.0.0.3:6881", false),
+    ];
+    let mut handshakers = nodes.iter().enumerate().map(|(index, &(addr, public))| {
+        let addr: SocketAddr = addr.parse().unwrap();
+
+        HandshakerManagerBuilder::new()
+            .with_bind_addr(addr)
+            .with_peer_id([index as u8; bt::PEER_ID_LEN].into())
+            .build(NatTransport {
+                network: network.clone(),
+                addr: addr,
+                public: public,
+            })
+            .unwrap()
+    });
+    let (mut handshaker_m, mut handshaker_a, mut handshaker_b) = (
+        handshakers.next().unwrap(),
+        handshakers.next().unwrap(),
+        handshakers.next().unwrap(),
+    );
+    let (addr_m, addr_a, addr_b) = (
+        nodes[0].0.parse().unwrap(),
+        nodes[1].0.parse().unwrap(),
+        nodes[2].0.parse().unwrap(),
+    );
+
+    // Nodes behind the NAT can not connect to each other on their own
+    let transport_a = NatTransport {
+        network: network.clone(),
+        addr: addr_a,
+        public: false,
+    };
+    assert!(transport_a
+        .connect_timeout(&addr_b, Duration::from_millis(50))
+        .is_err());
+
+    // Both connect to the mediator
+    handshaker_a
+        .send(InitiateMessage::new(Protocol::BitTorrent, hash, addr_m))
+        .unwrap();
+    handshaker_b
+        .send(InitiateMessage::new(Protocol::BitTorrent, hash, addr_m))
+        .unwrap();
+
+    let mut m_conns: Vec<_> = (0..2)
+        .map(|_| {
+            let (_, _, hash, pid, addr, sock) = handshaker_m.poll().unwrap().into_parts();
+            Conn::new(hash, pid, addr, sock)
+        })
+        .collect();
+    m_conns.sort_by_key(|conn| *conn.info.addr());
+    let (mut m_to_b, mut m_to_a) = (m_conns.pop().unwrap(), m_conns.pop().unwrap());
+    assert_eq!(addr_a, *m_to_a.info.addr());
+    assert_eq!(addr_b, *m_to_b.info.addr());
+
+    let (_, _, hash_a, pid_a, addr, sock) = handshaker_a.poll().unwrap().into_parts();
+    let mut a_to_m = Conn::new(hash_a, pid_a, addr, sock);
+    let (_, _, hash_b, pid_b, addr, sock) = handshaker_b.poll().unwrap().into_parts();
+    let mut b_to_m = Conn::new(hash_b, pid_b, addr, sock);
+
+    let mut manager_m = HolepunchManager::new().with_local_addr(addr_m);
+    let mut manager_a = HolepunchManager::new().with_local_addr(addr_a);
+    let mut manager_b = HolepunchManager::new().with_local_addr(addr_b);
+
+    a_to_m.extend(&mut manager_a);
+    m_to_a.extend(&mut manager_m);
+    b_to_m.extend(&mut manager_b);
+    m_to_b.extend(&mut manager_m);
+
+    a_to_m.recv_extended(&mut manager_a);
+    m_to_a.recv_extended(&mut manager_m);
+    b_to_m.recv_extended(&mut manager_b);
+    m_to_b.recv_extended(&mut manager_m);
+
+    // A asks the mediator to rendezvous with B, which the mediator relays to both of them
+    assert!(manager_a.rendezvous(&a_to_m.info, addr_b));
+    a_to_m.flush(&mut manager_a);
+    m_to_a.recv_holepunch(&mut manager_m);
+
+    let (first, second) = (manager_m.poll().unwrap(), manager_m.poll().unwrap());
+    for (info, message) in vec![first, second] {
+        let conn = if info == m_to_a.info {
+            &mut m_to_a
+        } else {
+            &mut m_to_b
+        };
+        conn.send(PeerWireProtocolMessage::ProtExtension(message));
+    }
+    assert!(manager_m.poll().is_none());
+
+    a_to_m.recv_holepunch(&mut manager_a);
+    b_to_m.recv_holepunch(&mut manager_b);
+
+    // Both dial each other at once, which gets through their NATs
+    let initiate_a = manager_a.poll_connect().unwrap();
+    let initiate_b = manager_b.poll_connect().unwrap();
+    assert_eq!(addr_b, *initiate_a.address());
+    assert_eq!(addr_a, *initiate_b.address());
+
+    handshaker_a.send(initiate_a).unwrap();
+    handshaker_b.send(initiate_b).unwrap();
+
+    let (_, _, _, pid_from_a, addr_from_a, sock_a) = handshaker_a.poll().unwrap().into_parts();
+    let (_, _, _, pid_from_b, addr_from_b, sock_b) = handshaker_b.poll().unwrap().into_parts();
+    assert_eq!(PeerId::from([2u8; bt::PEER_ID_LEN]), pid_from_a);
+    assert_eq!(PeerId::from([1u8; bt::PEER_ID_LEN]), pid_from_b);
+    assert_eq!(addr_b, addr_from_a);
+    assert_eq!(addr_a, addr_from_b);
+
+    // A and B are now directly connected
+    let mut a_to_b = Conn::new(hash, pid_from_a, addr_from_a, sock_a);
+    let mut b_to_a = Conn::new(hash, pid_from_b, addr_from_b, sock_b);
+
+    a_to_b.send(PeerWireProtocolMessage::Have(HaveMessage::new(3)));
+    assert_eq!(
+        PeerWireProtocolMessage::Have(HaveMessage::new(3)),
+        b_to_a.recv()
+    );
+}
@@ -0,0 +1,76 @@
+use std::net::SocketAddr;
+
+use bittorrent_protocol::handshake::transports::MockTransport;
+use bittorrent_protocol::handshake::{
+    DiscoveryInfo, HandshakerManagerBuilder, InitiateMessage, Protocol,
+};
+use bittorrent_protocol::peer::messages::PeerWireProtocolMessage;
+use bittorrent_protocol::peer::{
+    IPeerManagerMessage, OPeerManagerMessage, PeerInfo, PeerManagerBuilder,
+};
+use bittorrent_protocol::util::bt;
+
+#[test]
+fn positive_handshake_and_message_over_mock_transport() {
+    let transport = MockTransport::new();
+
+    let mut handshaker_one_addr: SocketAddr = "10.0.0.1:0".parse().unwrap();
+    let handshaker_one_pid = [4u8; bt::PEER_ID_LEN].into();
+    let mut handshaker_one = HandshakerManagerBuilder::new()
+        .with_bind_addr(handshaker_one_addr)
+        .with_peer_id(handshaker_one_pid)
+        .build(transport.clone())
+        .unwrap();
+    handshaker_one_addr.set_port(handshaker_one.port());
+
+    let handshaker_two_pid = [5u8; bt::PEER_ID_LEN].into();
+    let mut handshaker_two = HandshakerManagerBuilder::new()
+        .with_bind_addr("10.0.0.2:0".parse().unwrap())
+        .with_peer_id(handshaker_two_pid)
+        .build(transport)
+        .unwrap();
+
+    handshaker_two
+        .send(InitiateMessage::new(
+            Protocol::BitTorrent,
+            [55u8; bt::INFO_HASH_LEN].into(),
+            handshaker_one_addr,
+        ))
+        .unwrap();
+
+    let (_, ext_one, hash_one, pid_one, addr_one, sock_one) =
+        handshaker_one.poll().unwrap().into_parts();
+    let (_, ext_two, hash_two, pid_two, addr_two, sock_two) =
+        handshaker_two.poll().unwrap().into_parts();
+    assert_eq!(handshaker_two_pid, pid_one);
+    assert_eq!(handshaker_one_pid, pid_two);
+    assert_eq!(handshaker_one_addr, addr_two);
+
+    let mut manager_one = PeerManagerBuilder::new().build();
+    let mut manager_two = PeerManagerBuilder::new().build();
+    let info_one = PeerInfo::new(addr_one, pid_one, hash_one, ext_one);
+    let info_two = PeerInfo::new(addr_two, pid_two, hash_two, ext_two);
+
+    manager_one.send(IPeerManagerMessage::AddPeer(info_one, sock_one));
+    manager_two.send(IPeerManagerMessage::AddPeer(info_two, sock_two));
+    match (manager_one.poll(), manager_two.poll()) {
+        (Some(OPeerManagerMessage::PeerAdded(..)), Some(OPeerManagerMessage::PeerAdded(..))) => (),
+        other => panic!("Unexpected Messages {:?}", other),
+    }
+
+    manager_two.send(IPeerManagerMessage::SendMessage(
+        info_two,
+        0,
+        PeerWireProtocolMessage::Interested,
+    ));
+    match manager_two.poll() {
+        Some(OPeerManagerMessage::SentMessage(_, 0)) => (),
+        other => panic!("Unexpected Message {:?}", other),
+    }
+    match manager_one.poll() {
+        Some(OPeerManagerMessage::ReceivedMessage(info, PeerWireProtocolMessage::Interested)) => {
+            assert_eq!(info_one, info)
+        }
+        other => panic!("Unexpected Message {:?}", other),
+    }
+}
@@ -0,0 +1,102 @@
+use std::io::{Read, Write};
+use std::thread;
+
+use bittorrent_protocol::handshake::transports::UtpTransport;
+use bittorrent_protocol::handshake::{
+    DiscoveryInfo, HandshakerManagerBuilder, InitiateMessage, Protocol,
+};
+use bittorrent_protocol::peer::messages::{HaveMessage, PeerWireProtocolMessage};
+use bittorrent_protocol::peer::{MessageCodec, PeerWireMessageCodec, PeerWireMessageDecoder};
+use bittorrent_protocol::util::bt;
+use bytes::BytesMut;
+
+fn messages() -> Vec<PeerWireProtocolMessage> {
+    vec![
+        PeerWireProtocolMessage::Interested,
+        PeerWireProtocolMessage::Have(HaveMessage::new(7)),
+        PeerWireProtocolMessage::UnChoke,
+    ]
+}
+
+fn send_messages<S: Write>(sock: &mut S, messages: &[PeerWireProtocolMessage]) {
+    let mut codec = PeerWireMessageCodec::new();
+
+    for message in messages {
+        codec.write_bytes(message, &mut *sock).unwrap();
+    }
+}
+
+fn recv_messages<S: Read>(sock: &mut S, count: usize) -> Vec<PeerWireProtocolMessage> {
+    let mut decoder = PeerWireMessageDecoder::new(PeerWireMessageCodec::new());
+    let mut bytes = BytesMut::new();
+    let mut messages = Vec::new();
+
+    let mut buf = [0u8; 1024];
+    while messages.len() < count {
+        if let Some(message) = decoder.decode(&mut bytes).unwrap() {
+            messages.push(message);
+            continue;
+        }
+
+        let read = sock.read(&mut buf).unwrap();
+        assert!(read > 0, "connection closed before every message arrived");
+        bytes.extend_from_slice(&buf[..read]);
+    }
+
+    messages
+}
+
+#[test]
+fn positive_utp_handshake_and_messages() {
+    let mut handshaker_one_addr = "127.0.0.1:0".parse().unwrap();
+    let handshaker_one_pid = [4u8; bt::PEER_ID_LEN].into();
+    let mut handshaker_one = HandshakerManagerBuilder::new()
+        .with_bind_addr(handshaker_one_addr)
+        .with_peer_id(handshaker_one_pid)
+        .build(UtpTransport)
+        .unwrap();
+    handshaker_one_addr.set_port(handshaker_one.port());
+
+    let mut handshaker_two_addr = "127.0.0.1:0".parse().unwrap();
+    let handshaker_two_pid = [5u8; bt::PEER_ID_LEN].into();
+    let mut handshaker_two = HandshakerManagerBuilder::new()
+        .with_bind_addr(handshaker_two_addr)
+        .with_peer_id(handshaker_two_pid)
+        .build(UtpTransport)
+        .unwrap();
+    handshaker_two_addr.set_port(handshaker_two.port());
+
+    handshaker_two
+        .send(InitiateMessage::new(
+            Protocol::BitTorrent,
+            [55u8; bt::INFO_HASH_LEN].into(),
+            handshaker_one_addr,
+        ))
+        .unwrap();
+
+    // Handshaker one accepted the connection, handshaker two opened it
+    let (_, _, info_hash_one, pid_one, _, mut sock_one) =
+        handshaker_one.poll().unwrap().into_parts();
+    let (_, _, info_hash_two, pid_two, addr_two, mut sock_two) =
+        handshaker_two.poll().unwrap().into_parts();
+
+    assert_eq!(handshaker_two_pid, pid_one);
+    assert_eq!(handshaker_one_pid, pid_two);
+    assert_eq!(info_hash_one, info_hash_two);
+    assert_eq!(handshaker_one_addr, addr_two);
+
+    // Peer wire messages both ways over the connection
+    let echo = thread::spawn(move || {
+        let received = recv_messages(&mut sock_one, messages().len());
+        send_messages(&mut sock_one, &received);
+    });
+
+    send_messages(&mut sock_two, &messages());
+    let echoed = recv_messages(&mut sock_two, messages().len());
+
+    assert_eq!(messages(), echoed);
+
+    // Both ends close at once, each acknowledging the other's FIN
+    drop(sock_two);
+    echo.join().unwrap();
+}
@@ -0,0 +1,48 @@
+mod test_message_corpus;
+mod test_peer_backpressure;
+mod test_peer_capabilities;
+mod test_peer_capacity;
+mod test_peer_dht_port;
+mod test_peer_disconnect_reason;
+mod test_peer_rate_limit;
+mod test_peer_timeout;
+mod test_peer_tracing;
+#[cfg(feature = "tokio-codec")]
+mod test_tokio_codec;
+
+use bittorrent_protocol::handshake::transports::MockSocket;
+use bittorrent_protocol::handshake::Extensions;
+use bittorrent_protocol::peer::{IPeerManagerMessage, PeerInfo, PeerManager, PeerManagerEvent};
+
+fn peer_info(port: u16, hash: u8) -> PeerInfo {
+    PeerInfo::new(
+        ([127, 0, 0, 1], port).into(),
+        [port as u8; 20].into(),
+        [hash; 20].into(),
+        Extensions::new(),
+    )
+}
+
+fn add_peer(manager: &mut PeerManager<MockSocket>, info: PeerInfo) -> MockSocket {
+    let (ours, theirs) = MockSocket::pair();
+
+    manager.send(IPeerManagerMessage::AddPeer(info, ours));
+
+    theirs
+}
+
+/// Next event reported, skipping messages the stream handled itself.
+fn next_event(manager: &mut PeerManager<MockSocket>) -> PeerManagerEvent {
+    loop {
+        if let Some(event) = manager.poll_event() {
+            return event;
+        }
+    }
+}
+
+fn assert_connected(manager: &mut PeerManager<MockSocket>, info: PeerInfo) {
+    match next_event(manager) {
+        PeerManagerEvent::PeerConnected { peer, .. } => assert_eq!(info, peer),
+        other => panic!("Unexpected Event {:?}", other),
+    }
+}
@@ -0,0 +1,300 @@
+use std::fs;
+use std::io;
+
+use bittorrent_protocol::peer::messages::builders::ExtendedMessageBuilder;
+use bittorrent_protocol::peer::messages::{
+    AllowedFastMessage, BitFieldMessage, BitsExtensionMessage, CancelMessage, DontHaveMessage,
+    ExtendedMessage, ExtendedType, HaveMessage, MessageLimits, PeerExtensionProtocolMessage,
+    PeerWireProtocolMessage, PieceMessage, PortMessage, ProtocolViolation, RejectMessage,
+    RequestMessage, SuggestMessage, UtMetadataMessage, UtMetadataRequestMessage,
+};
+use bytes::Bytes;
+
+/// Outcomes of parsing every corpus input, recorded from the parser this corpus guards.
+///
+/// Regenerate with `REGENERATE_MESSAGE_CORPUS=1` only when a change in behavior is intended.
+const CORPUS: &'static str = include_str!("message_corpus.txt");
+const CORPUS_PATH: &'static str = concat!(
+    env!("CARGO_MANIFEST_DIR"),
+    "/tests/test7_peer/message_corpus.txt"
+);
+const REGENERATE_VAR: &'static str = "REGENERATE_MESSAGE_CORPUS";
+
+/// Extended message we would have sent the peer, which decides how extension ids parse.
+fn our_extended() -> ExtendedMessage {
+    ExtendedMessageBuilder::new()
+        .with_extended_type(ExtendedType::UtMetadata, Some(1))
+        .with_extended_type(ExtendedType::UtPex, Some(2))
+        .with_extended_type(ExtendedType::LtDontHave, Some(3))
+        .with_extended_type(ExtendedType::UtHolepunch, Some(4))
+        .with_extended_type(ExtendedType::Custom("xx_custom".to_string()), Some(5))
+        .build()
+}
+
+fn extended_state(with_extended: bool) -> Option<ExtendedMessage> {
+    if with_extended {
+        Some(our_extended())
+    } else {
+        None
+    }
+}
+
+fn write_message(message: &PeerWireProtocolMessage, extended: &Option<ExtendedMessage>) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    message.write_bytes(&mut bytes, extended).unwrap();
+
+    bytes
+}
+
+fn frame(id: u8, payload: &[u8]) -> Vec<u8> {
+    let mut bytes = ((payload.len() + 1) as u32).to_be_bytes().to_vec();
+    bytes.push(id);
+    bytes.extend_from_slice(payload);
+
+    bytes
+}
+
+fn extension_frame(ext_id: u8, payload: &[u8]) -> Vec<u8> {
+    let mut inner = vec![ext_id];
+    inner.extend_from_slice(payload);
+
+    frame(20, &inner)
+}
+
+/// Every message we can send, written out as the peer would see it.
+fn valid_messages() -> Vec<(Vec<u8>, bool)> {
+    let extended = Some(our_extended());
+    let standard = vec![
+        PeerWireProtocolMessage::KeepAlive,
+        PeerWireProtocolMessage::Choke,
+        PeerWireProtocolMessage::UnChoke,
+        PeerWireProtocolMessage::Interested,
+        PeerWireProtocolMessage::UnInterested,
+        PeerWireProtocolMessage::Have(HaveMessage::new(0x0102_0304)),
+        PeerWireProtocolMessage::BitField(BitFieldMessage::new(Bytes::new())),
+        PeerWireProtocolMessage::BitField(BitFieldMessage::new(Bytes::from(vec![0xFF, 0xC0]))),
+        PeerWireProtocolMessage::Request(RequestMessage::new(1, 16384, 16384)),
+        PeerWireProtocolMessage::Piece(PieceMessage::new(2, 0, Bytes::new())),
+        PeerWireProtocolMessage::Piece(PieceMessage::new(2, 8, Bytes::from(vec![9u8; 17]))),
+        PeerWireProtocolMessage::Cancel(CancelMessage::new(3, 32768, 16384)),
+        PeerWireProtocolMessage::HaveAll,
+        PeerWireProtocolMessage::HaveNone,
+        PeerWireProtocolMessage::Suggest(SuggestMessage::new(4)),
+        PeerWireProtocolMessage::Reject(RejectMessage::new(5, 0, 16384)),
+        PeerWireProtocolMessage::AllowedFast(AllowedFastMessage::new(6)),
+        PeerWireProtocolMessage::BitsExtension(BitsExtensionMessage::Port(PortMessage::new(6881))),
+        PeerWireProtocolMessage::BitsExtension(BitsExtensionMessage::Extended(our_extended())),
+    ];
+    let extensions = vec![
+        PeerExtensionProtocolMessage::UtMetadata(UtMetadataMessage::Request(
+            UtMetadataRequestMessage::new(3),
+        )),
+        PeerExtensionProtocolMessage::LtDontHave(DontHaveMessage::new(7)),
+        PeerExtensionProtocolMessage::RawExtension {
+            id: 9,
+            payload: Bytes::from(vec![1, 2, 3]),
+        },
+    ];
+
+    let mut messages = Vec::new();
+    for message in standard.iter() {
+        let bytes = write_message(message, &None);
+        messages.push((bytes.clone(), false));
+        messages.push((bytes, true));
+    }
+    for extension in extensions {
+        let message = PeerWireProtocolMessage::ProtExtension(extension);
+        messages.push((write_message(&message, &extended), true));
+    }
+
+    messages
+}
+
+/// Small deterministic generator for junk bytes.
+struct Lcg(u32);
+
+impl Lcg {
+    fn next(&mut self) -> u8 {
+        self.0 = self.0.wrapping_mul(1_103_515_245).wrapping_add(12345);
+
+        (self.0 >> 16) as u8
+    }
+}
+
+fn corpus_inputs() -> Vec<(Vec<u8>, bool)> {
+    let mut inputs = Vec::new();
+
+    // Valid messages, every truncation of them and with trailing junk
+    for (bytes, with_extended) in valid_messages() {
+        for end in 0..bytes.len() {
+            inputs.push((bytes[..end].to_vec(), with_extended));
+        }
+        let mut trailing = bytes.clone();
+        trailing.push(0xAB);
+        inputs.push((trailing, with_extended));
+        inputs.push((bytes, with_extended));
+    }
+
+    // Every message id we know of (and a few we do not) at boundary lengths
+    let ids = (0u8..=24).chain(vec![0x7F, 0xFF]);
+    for id in ids {
+        for &length in [1usize, 2, 3, 4, 5, 6, 7, 9, 10, 13, 14, 17, 18].iter() {
+            let zeros = vec![0u8; length - 1];
+            let pattern: Vec<u8> = (0..length - 1)
+                .map(|index| (index as u8).wrapping_mul(31).wrapping_add(id))
+                .collect();
+
+            for payload in vec![zeros, pattern] {
+                let bytes = frame(id, &payload);
+                for &with_extended in [false, true].iter() {
+                    inputs.push((bytes[..bytes.len() - 1].to_vec(), with_extended));
+                    inputs.push((bytes.clone(), with_extended));
+                }
+            }
+        }
+    }
+
+    // Keep alives followed by some of the next message
+    for extra in 0..4 {
+        let mut bytes = vec![0, 0, 0, 0];
+        bytes.extend((0..extra).map(|index| index as u8 + 5));
+        inputs.push((bytes, false));
+    }
+
+    // Extension messages against our extended message
+    let payloads: Vec<&[u8]> = vec![
+        b"",
+        b"d8:msg_typei0e5:piecei0ee",
+        b"d8:msg_typei0e5:piecei0e",
+        b"d5:added6:\x01\x02\x03\x04\x1a\xe1e",
+        b"\x00\x00\x00\x07",
+        b"\x00\x00\x07",
+        b"\x00\x00\x01\x02\x03\x04\x1a\xe1",
+        b"\x00\x00\x01\x02\x03\x04\x1a\xe1\x00\x00\x00\x00",
+        b"junk",
+        b"de",
+        b"d1:md11:ut_metadatai1eee",
+    ];
+    for ext_id in 0u8..=6 {
+        for payload in payloads.iter() {
+            let bytes = extension_frame(ext_id, payload);
+            for &with_extended in [false, true].iter() {
+                inputs.push((bytes[..bytes.len() - 1].to_vec(), with_extended));
+                inputs.push((bytes.clone(), with_extended));
+            }
+        }
+    }
+
+    // Junk, half with a small length so it reaches the message parsers
+    let mut rng = Lcg(0x5EED);
+    for sample in 0..256 {
+        let len = (rng.next() % 32) as usize;
+        let mut bytes: Vec<u8> = (0..len).map(|_| rng.next()).collect();
+        if sample % 2 == 0 && bytes.len() >= 4 {
+            bytes[0] = 0;
+            bytes[1] = 0;
+            bytes[2] = 0;
+            bytes[3] %= 32;
+        }
+        inputs.push((bytes, sample % 4 < 2));
+    }
+
+    inputs
+}
+
+/// Outcome of parsing the given input, as recorded in the corpus.
+fn outcome(bytes: &[u8], with_extended: bool) -> String {
+    let extended = extended_state(with_extended);
+    let limits = MessageLimits::default();
+
+    match PeerWireProtocolMessage::parse_bytes(Bytes::from(bytes.to_vec()), &extended, &limits) {
+        Ok(message) => {
+            let debug = format!("{:?}", message);
+            let kind = debug.split(' ').next().unwrap();
+
+            format!(
+                "ok {} {}",
+                kind,
+                hex::encode(write_message(&message, &extended))
+            )
+        }
+        Err(_) => "err".to_string(),
+    }
+}
+
+fn corpus_line(bytes: &[u8], with_extended: bool) -> String {
+    let state = if with_extended { "e" } else { "-" };
+
+    format!(
+        "{} {} {}",
+        hex_or_empty(bytes),
+        state,
+        outcome(bytes, with_extended)
+    )
+}
+
+fn hex_or_empty(bytes: &[u8]) -> String {
+    if bytes.is_empty() {
+        "_".to_string()
+    } else {
+        hex::encode(bytes)
+    }
+}
+
+#[test]
+fn positive_message_corpus_unchanged() {
+    let lines: Vec<String> = corpus_inputs()
+        .iter()
+        .map(|&(ref bytes, with_extended)| corpus_line(bytes, with_extended))
+        .collect();
+
+    if std::env::var_os(REGENERATE_VAR).is_some() {
+        fs::write(CORPUS_PATH, lines.join("\n") + "\n").unwrap();
+        return;
+    }
+
+    let recorded: Vec<&str> = CORPUS.lines().collect();
+    assert_eq!(recorded.len(), lines.len(), "Corpus Inputs Changed");
+    for (recorded, line) in recorded.iter().zip(lines.iter()) {
+        assert_eq!(recorded, line);
+    }
+}
+
+#[test]
+fn positive_message_corpus_errors_need_bytes_or_name_rule() {
+    let extended = Some(our_extended());
+    let limits = MessageLimits::default();
+
+    for (bytes, with_extended) in corpus_inputs() {
+        let state = if with_extended { &extended } else { &None };
+        let error = match PeerWireProtocolMessage::parse_bytes(
+            Bytes::from(bytes.clone()),
+            state,
+            &limits,
+        ) {
+            Ok(_) => continue,
+            Err(error) => error,
+        };
+
+        if error.kind() == io::ErrorKind::UnexpectedEof {
+            let declared = if bytes.len() >= 4 {
+                u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize + 4
+            } else {
+                4
+            };
+
+            assert!(
+                bytes.len() < declared,
+                "{} Needs No More Bytes",
+                hex::encode(&bytes)
+            );
+        } else {
+            assert!(
+                ProtocolViolation::rule_of(&error).is_some(),
+                "{} Failed Without A Rule: {}",
+                hex::encode(&bytes),
+                error
+            );
+        }
+    }
+}
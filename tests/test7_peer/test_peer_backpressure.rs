@@ -0,0 +1,103 @@
+use std::io::Read;
+use std::time::Duration;
+
+use bittorrent_protocol::handshake::transports::MockSocket;
+use bittorrent_protocol::handshake::{Extensions, TimeoutSocket};
+use bittorrent_protocol::peer::error::PeerManagerErrorKind;
+use bittorrent_protocol::peer::messages::{PeerWireProtocolMessage, PieceMessage};
+use bittorrent_protocol::peer::{
+    IPeerManagerMessage, OPeerManagerMessage, PeerInfo, PeerManagerBuilder,
+};
+
+use bytes::Bytes;
+
+const BLOCK_LEN: usize = 16 * 1024;
+const QUEUE_BYTE_BUDGET: usize = 4 * BLOCK_LEN;
+const MAX_PIECES: u64 = 100;
+
+const CHOKE_ID: u8 = 0;
+const PIECE_ID: u8 = 7;
+
+/// Read a message off the wire, returning its id, or `None` for a keep-alive.
+fn read_message_id(sock: &mut MockSocket) -> Option<u8> {
+    let mut length = [0u8; 4];
+    sock.read_exact(&mut length).unwrap();
+
+    let mut message = vec![0u8; u32::from_be_bytes(length) as usize];
+    sock.read_exact(&mut message).unwrap();
+
+    message.first().cloned()
+}
+
+#[test]
+fn positive_stalled_reader_bounds_queue() {
+    let (ours, mut theirs) = MockSocket::pair_with_capacity(BLOCK_LEN);
+    theirs.set_timeout(Some(Duration::from_secs(5))).unwrap();
+
+    let mut manager = PeerManagerBuilder::new()
+        .with_queue_byte_budget(QUEUE_BYTE_BUDGET)
+        .with_keep_alive_interval(Duration::from_millis(100))
+        .build();
+    let info = PeerInfo::new(
+        "10.0.0.1:6881".parse().unwrap(),
+        [0u8; 20].into(),
+        [0u8; 20].into(),
+        Extensions::new(),
+    );
+
+    manager.send(IPeerManagerMessage::AddPeer(info, ours));
+    match manager.poll() {
+        Some(OPeerManagerMessage::PeerAdded(..)) => (),
+        other => panic!("Unexpected Message {:?}", other),
+    }
+
+    // Nobody is reading, so eventually the queue has to push back
+    let mut num_pieces = 0;
+    let error = loop {
+        assert!(num_pieces < MAX_PIECES, "Queue Never Filled Up");
+
+        let piece = PieceMessage::new(0, 0, Bytes::from(vec![0u8; BLOCK_LEN]));
+        match manager.try_send(IPeerManagerMessage::SendMessage(
+            info,
+            num_pieces,
+            PeerWireProtocolMessage::Piece(piece),
+        )) {
+            Ok(()) => num_pieces += 1,
+            Err(error) => break error,
+        }
+    };
+    match error.kind() {
+        &PeerManagerErrorKind::QueueFull { info: full } => assert_eq!(info, full),
+        other => panic!("Unexpected Error {:?}", other),
+    }
+
+    let stats = manager.peer_stats(&info).unwrap();
+    assert!(stats.queued_bytes() <= QUEUE_BYTE_BUDGET);
+    assert!(stats.queued_bytes() + BLOCK_LEN > QUEUE_BYTE_BUDGET);
+
+    // Control messages skip ahead of the pieces still in the queue
+    manager
+        .try_send(IPeerManagerMessage::SendMessage(
+            info,
+            num_pieces,
+            PeerWireProtocolMessage::Choke,
+        ))
+        .unwrap();
+
+    let mut pieces_before_choke = 0;
+    let mut opt_id = read_message_id(&mut theirs);
+    while opt_id == Some(PIECE_ID) {
+        pieces_before_choke += 1;
+        opt_id = read_message_id(&mut theirs);
+    }
+    assert_eq!(Some(CHOKE_ID), opt_id);
+    assert!(pieces_before_choke < num_pieces);
+
+    for _ in pieces_before_choke..num_pieces {
+        assert_eq!(Some(PIECE_ID), read_message_id(&mut theirs));
+    }
+    assert_eq!(0, manager.peer_stats(&info).unwrap().queued_bytes());
+
+    // Once drained, we go back to heartbeating the peer
+    assert_eq!(None, read_message_id(&mut theirs));
+}
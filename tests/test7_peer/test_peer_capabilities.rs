@@ -0,0 +1,119 @@
+use bittorrent_protocol::handshake::transports::MockSocket;
+use bittorrent_protocol::handshake::{Extension, Extensions};
+use bittorrent_protocol::peer::messages::builders::ExtendedMessageBuilder;
+use bittorrent_protocol::peer::messages::{
+    BitsExtensionMessage, ExtendedType, PeerExtensionProtocolMessage, PeerWireProtocolMessage,
+    UtPexMessage,
+};
+use bittorrent_protocol::peer::{
+    IPeerManagerMessage, OPeerManagerMessage, PeerDisconnectReason, PeerInfo, PeerManager,
+    PeerManagerBuilder,
+};
+
+fn extended(builder: ExtendedMessageBuilder) -> PeerWireProtocolMessage {
+    PeerWireProtocolMessage::BitsExtension(BitsExtensionMessage::Extended(builder.build()))
+}
+
+fn send_extended(
+    send_manager: &mut PeerManager<MockSocket>,
+    send_info: PeerInfo,
+    recv_manager: &mut PeerManager<MockSocket>,
+    message: PeerWireProtocolMessage,
+) {
+    send_manager.send(IPeerManagerMessage::SendMessage(send_info, 0, message));
+    match send_manager.poll() {
+        Some(OPeerManagerMessage::SentMessage(..)) => (),
+        other => panic!("Unexpected Message {:?}", other),
+    }
+
+    match recv_manager.poll() {
+        Some(OPeerManagerMessage::ReceivedMessage(
+            _,
+            PeerWireProtocolMessage::BitsExtension(_),
+        )) => (),
+        other => panic!("Unexpected Message {:?}", other),
+    }
+}
+
+#[test]
+fn positive_rehandshake_removes_extension() {
+    let (sock_one, sock_two) = MockSocket::pair();
+    let mut extensions = Extensions::new();
+    extensions.add(Extension::ExtensionProtocol);
+
+    let mut manager_one = PeerManagerBuilder::new().build();
+    let mut manager_two = PeerManagerBuilder::new().build();
+    let info_one = PeerInfo::new(
+        "10.0.0.2:6881".parse().unwrap(),
+        [2u8; 20].into(),
+        [0u8; 20].into(),
+        extensions,
+    );
+    let info_two = PeerInfo::new(
+        "10.0.0.1:6881".parse().unwrap(),
+        [1u8; 20].into(),
+        [0u8; 20].into(),
+        extensions,
+    );
+
+    manager_one.send(IPeerManagerMessage::AddPeer(info_one, sock_one));
+    manager_two.send(IPeerManagerMessage::AddPeer(info_two, sock_two));
+    manager_one.poll().unwrap();
+    match manager_two.poll() {
+        Some(OPeerManagerMessage::PeerAdded(_, capabilities)) => {
+            assert!(capabilities.supports_extension_protocol());
+            assert_eq!(None, capabilities.extended_id_for(&ExtendedType::UtPex));
+        }
+        other => panic!("Unexpected Message {:?}", other),
+    }
+
+    send_extended(
+        &mut manager_one,
+        info_one,
+        &mut manager_two,
+        extended(
+            ExtendedMessageBuilder::new()
+                .with_extended_type(ExtendedType::UtMetadata, Some(2))
+                .with_extended_type(ExtendedType::UtPex, Some(1))
+                .with_client_version("Test 1.0"),
+        ),
+    );
+    let capabilities = manager_two.capabilities(&info_two).unwrap();
+    assert_eq!(Some(1), capabilities.extended_id_for(&ExtendedType::UtPex));
+    assert_eq!(
+        Some(2),
+        capabilities.extended_id_for(&ExtendedType::UtMetadata)
+    );
+
+    // Second extended handshake only mentions what changed
+    send_extended(
+        &mut manager_one,
+        info_one,
+        &mut manager_two,
+        extended(ExtendedMessageBuilder::new().with_extended_type(ExtendedType::UtPex, Some(0))),
+    );
+    let capabilities = manager_two.capabilities(&info_two).unwrap();
+    assert_eq!(None, capabilities.extended_id_for(&ExtendedType::UtPex));
+    assert_eq!(
+        Some(2),
+        capabilities.extended_id_for(&ExtendedType::UtMetadata)
+    );
+    assert_eq!(Some("Test 1.0"), capabilities.client());
+
+    // Peer took its id back, so we have no way of sending them pex messages anymore
+    manager_two.send(IPeerManagerMessage::SendMessage(
+        info_two,
+        1,
+        PeerWireProtocolMessage::ProtExtension(PeerExtensionProtocolMessage::UtPex(
+            UtPexMessage::new(Vec::new(), Vec::new()),
+        )),
+    ));
+    match manager_two.poll() {
+        Some(OPeerManagerMessage::PeerDisconnected { info, reason }) => {
+            assert_eq!(info_two, info);
+            assert_eq!(PeerDisconnectReason::WriteError, reason);
+        }
+        other => panic!("Unexpected Message {:?}", other),
+    }
+    assert!(manager_two.capabilities(&info_two).is_none());
+}
@@ -0,0 +1,103 @@
+use bittorrent_protocol::handshake::transports::MockSocket;
+use bittorrent_protocol::peer::{
+    IPeerManagerMessage, PeerDisconnectReason, PeerInfo, PeerManager, PeerManagerBuilder,
+    PeerManagerEvent,
+};
+
+const NUM_PEERS: u16 = 50;
+const TORRENT_CAPACITY: usize = 10;
+
+fn assert_too_many_peers(manager: &mut PeerManager<MockSocket>, info: PeerInfo) {
+    match super::next_event(manager) {
+        PeerManagerEvent::PeerDisconnected { peer, reason } => {
+            assert_eq!(info, peer);
+            assert_eq!(PeerDisconnectReason::TooManyPeers, reason);
+        }
+        other => panic!("Unexpected Event {:?}", other),
+    }
+}
+
+#[test]
+fn positive_over_torrent_capacity_is_too_many_peers() {
+    let mut manager = PeerManagerBuilder::new()
+        .with_torrent_peer_capacity(TORRENT_CAPACITY)
+        .build();
+
+    let mut sockets = Vec::new();
+    for port in 0..NUM_PEERS {
+        let info = super::peer_info(port, 0);
+        sockets.push(super::add_peer(&mut manager, info));
+
+        if (port as usize) < TORRENT_CAPACITY {
+            super::assert_connected(&mut manager, info);
+        } else {
+            assert_too_many_peers(&mut manager, info);
+        }
+    }
+
+    // Other torrents have room of their own
+    let info = super::peer_info(NUM_PEERS, 1);
+    let _theirs = super::add_peer(&mut manager, info);
+    super::assert_connected(&mut manager, info);
+}
+
+#[test]
+fn positive_shed_peer_makes_room_right_away() {
+    let mut manager = PeerManagerBuilder::new().with_peer_capacity(1).build();
+    let _theirs_one = super::add_peer(&mut manager, super::peer_info(1, 0));
+    super::assert_connected(&mut manager, super::peer_info(1, 0));
+
+    manager.send(IPeerManagerMessage::ShedPeer(super::peer_info(1, 0)));
+    let _theirs_two = super::add_peer(&mut manager, super::peer_info(2, 0));
+
+    // Second peer may be added before the first is gone
+    let mut events = vec![
+        super::next_event(&mut manager),
+        super::next_event(&mut manager),
+    ];
+    events.sort_by_key(|event| match *event {
+        PeerManagerEvent::PeerDisconnected { .. } => 0,
+        _ => 1,
+    });
+    match events[0] {
+        PeerManagerEvent::PeerDisconnected { peer, reason } => {
+            assert_eq!(super::peer_info(1, 0), peer);
+            assert_eq!(PeerDisconnectReason::TooManyPeers, reason);
+        }
+        ref other => panic!("Unexpected Event {:?}", other),
+    }
+    match events[1] {
+        PeerManagerEvent::PeerConnected { peer, .. } => assert_eq!(super::peer_info(2, 0), peer),
+        ref other => panic!("Unexpected Event {:?}", other),
+    }
+    assert!(manager.peer_stats(&super::peer_info(1, 0)).is_none());
+}
+
+#[test]
+fn positive_shed_peer_closed_remotely_is_too_many_peers() {
+    let mut manager = PeerManagerBuilder::new().build();
+    let theirs = super::add_peer(&mut manager, super::peer_info(1, 0));
+    super::assert_connected(&mut manager, super::peer_info(1, 0));
+
+    manager.send(IPeerManagerMessage::ShedPeer(super::peer_info(1, 0)));
+    drop(theirs);
+    assert_too_many_peers(&mut manager, super::peer_info(1, 0));
+
+    // Peer is reported once, however many ways it went
+    let _theirs = super::add_peer(&mut manager, super::peer_info(2, 0));
+    super::assert_connected(&mut manager, super::peer_info(2, 0));
+}
+
+#[test]
+fn positive_raised_capacity_takes_effect() {
+    let mut manager = PeerManagerBuilder::new().with_peer_capacity(1).build();
+    let _theirs_one = super::add_peer(&mut manager, super::peer_info(1, 0));
+    super::assert_connected(&mut manager, super::peer_info(1, 0));
+    let _theirs_two = super::add_peer(&mut manager, super::peer_info(2, 0));
+    assert_too_many_peers(&mut manager, super::peer_info(2, 0));
+
+    manager.capacity().set_peer_capacity(2);
+
+    let _theirs_three = super::add_peer(&mut manager, super::peer_info(3, 0));
+    super::assert_connected(&mut manager, super::peer_info(3, 0));
+}
@@ -0,0 +1,112 @@
+use bittorrent_protocol::handshake::transports::MockSocket;
+use bittorrent_protocol::handshake::{Extension, Extensions};
+use bittorrent_protocol::peer::messages::{
+    BitFieldMessage, BitsExtensionMessage, PeerWireProtocolMessage, PortMessage,
+};
+use bittorrent_protocol::peer::{
+    IPeerManagerMessage, OPeerManagerMessage, PeerInfo, PeerManager, PeerManagerBuilder,
+    PeerManagerEvent,
+};
+
+const DHT_PORT: u16 = 6882;
+
+/// Connects a manager sending our DHT port to a plain manager, sending a bitfield between them.
+fn connect(
+    extensions: Extensions,
+) -> (
+    PeerManager<MockSocket>,
+    PeerInfo,
+    PeerManager<MockSocket>,
+    PeerInfo,
+) {
+    let (sock_one, sock_two) = MockSocket::pair();
+
+    let mut manager_one = PeerManagerBuilder::new().with_dht_port(DHT_PORT).build();
+    let mut manager_two = PeerManagerBuilder::new().build();
+    let info_one = PeerInfo::new(
+        "10.0.0.2:6881".parse().unwrap(),
+        [2u8; 20].into(),
+        [0u8; 20].into(),
+        extensions,
+    );
+    let info_two = PeerInfo::new(
+        "10.0.0.1:6881".parse().unwrap(),
+        [1u8; 20].into(),
+        [0u8; 20].into(),
+        extensions,
+    );
+
+    manager_one.send(IPeerManagerMessage::AddPeer(info_one, sock_one));
+    manager_two.send(IPeerManagerMessage::AddPeer(info_two, sock_two));
+    for manager in [&mut manager_one, &mut manager_two].iter_mut() {
+        match manager.poll() {
+            Some(OPeerManagerMessage::PeerAdded(..)) => (),
+            other => panic!("Unexpected Message {:?}", other),
+        }
+    }
+
+    let bitfield = PeerWireProtocolMessage::BitField(BitFieldMessage::new(vec![0xFF].into()));
+    manager_one.send(IPeerManagerMessage::SendMessage(info_one, 0, bitfield));
+    match manager_one.poll() {
+        Some(OPeerManagerMessage::SentMessage(_, 0)) => (),
+        other => panic!("Unexpected Message {:?}", other),
+    }
+    match manager_two.poll() {
+        Some(OPeerManagerMessage::ReceivedMessage(_, PeerWireProtocolMessage::BitField(_))) => (),
+        other => panic!("Unexpected Message {:?}", other),
+    }
+
+    (manager_one, info_one, manager_two, info_two)
+}
+
+#[test]
+fn positive_port_sent_after_bitfield() {
+    let mut extensions = Extensions::new();
+    extensions.add(Extension::Dht);
+
+    let (_manager_one, _, mut manager_two, info_two) = connect(extensions);
+
+    match manager_two.poll_event() {
+        Some(PeerManagerEvent::DhtNodeDiscovered { peer, addr }) => {
+            assert_eq!(info_two, peer);
+            assert_eq!(
+                "10.0.0.1:6882".parse::<std::net::SocketAddr>().unwrap(),
+                addr
+            );
+        }
+        other => panic!("Unexpected Event {:?}", other),
+    }
+}
+
+#[test]
+fn negative_port_not_sent_without_dht_bit() {
+    let (mut manager_one, info_one, mut manager_two, _) = connect(Extensions::new());
+
+    manager_one.send(IPeerManagerMessage::SendMessage(
+        info_one,
+        1,
+        PeerWireProtocolMessage::Interested,
+    ));
+    match manager_two.poll() {
+        Some(OPeerManagerMessage::ReceivedMessage(_, PeerWireProtocolMessage::Interested)) => (),
+        other => panic!("Unexpected Message {:?}", other),
+    }
+}
+
+#[test]
+fn negative_port_ignored_without_dht_bit() {
+    let info = PeerInfo::new(
+        "10.0.0.1:6881".parse().unwrap(),
+        [1u8; 20].into(),
+        [0u8; 20].into(),
+        Extensions::new(),
+    );
+    let port = PeerWireProtocolMessage::BitsExtension(BitsExtensionMessage::Port(
+        PortMessage::new(DHT_PORT),
+    ));
+
+    assert_eq!(
+        None,
+        OPeerManagerMessage::ReceivedMessage(info, port).dht_node()
+    );
+}
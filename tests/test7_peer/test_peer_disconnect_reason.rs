@@ -0,0 +1,102 @@
+use std::io::Write;
+
+use bittorrent_protocol::handshake::transports::MockSocket;
+use bittorrent_protocol::peer::messages::MessageLimits;
+use bittorrent_protocol::peer::{
+    IPeerManagerMessage, PeerDisconnectReason, PeerInfo, PeerManager, PeerManagerBuilder,
+    PeerManagerEvent,
+};
+
+fn assert_disconnected(
+    manager: &mut PeerManager<MockSocket>,
+    info: PeerInfo,
+    expected: PeerDisconnectReason,
+) {
+    match manager.poll_event() {
+        Some(PeerManagerEvent::PeerDisconnected { peer, reason }) => {
+            assert_eq!(info, peer);
+            assert_eq!(expected, reason);
+        }
+        other => panic!("Unexpected Event {:?}", other),
+    }
+    assert!(manager.peer_stats(&info).is_none());
+}
+
+#[test]
+fn positive_malformed_bitfield_is_protocol_violation() {
+    let mut manager = PeerManagerBuilder::new()
+        .with_message_limits(MessageLimits::default().with_piece_count(10))
+        .build();
+    let info = super::peer_info(1, 0);
+    let mut theirs = super::add_peer(&mut manager, info);
+    super::assert_connected(&mut manager, info);
+
+    // Ten pieces need two bytes of bitfield
+    theirs.write_all(&[0, 0, 0, 2, 5, 0xFF]).unwrap();
+
+    assert_disconnected(
+        &mut manager,
+        info,
+        PeerDisconnectReason::ProtocolViolation("bitfield length"),
+    );
+}
+
+#[test]
+fn positive_remote_closed() {
+    let mut manager = PeerManagerBuilder::new().build();
+    let info = super::peer_info(1, 0);
+    let theirs = super::add_peer(&mut manager, info);
+    super::assert_connected(&mut manager, info);
+
+    drop(theirs);
+
+    assert_disconnected(&mut manager, info, PeerDisconnectReason::RemoteClosed);
+}
+
+#[test]
+fn positive_remove_peer_is_requested() {
+    let mut manager = PeerManagerBuilder::new().build();
+    let info = super::peer_info(1, 0);
+    let _theirs = super::add_peer(&mut manager, info);
+    super::assert_connected(&mut manager, info);
+
+    manager.send(IPeerManagerMessage::RemovePeer(info));
+
+    assert_disconnected(&mut manager, info, PeerDisconnectReason::Requested);
+}
+
+#[test]
+fn positive_disconnect_peer_keeps_reason() {
+    let mut manager = PeerManagerBuilder::new().build();
+    let info = super::peer_info(1, 0);
+    let _theirs = super::add_peer(&mut manager, info);
+    super::assert_connected(&mut manager, info);
+
+    manager.send(IPeerManagerMessage::DisconnectPeer(
+        info,
+        PeerDisconnectReason::ProtocolViolation("hash failures"),
+    ));
+
+    assert_disconnected(
+        &mut manager,
+        info,
+        PeerDisconnectReason::ProtocolViolation("hash failures"),
+    );
+}
+
+#[test]
+fn positive_over_capacity_is_too_many_peers() {
+    let mut manager = PeerManagerBuilder::new().with_peer_capacity(1).build();
+    let _theirs = super::add_peer(&mut manager, super::peer_info(1, 0));
+    super::assert_connected(&mut manager, super::peer_info(1, 0));
+    let (ours, _theirs_two) = MockSocket::pair();
+
+    manager.send(IPeerManagerMessage::AddPeer(super::peer_info(2, 0), ours));
+
+    assert_disconnected(
+        &mut manager,
+        super::peer_info(2, 0),
+        PeerDisconnectReason::TooManyPeers,
+    );
+    assert!(manager.peer_stats(&super::peer_info(1, 0)).is_some());
+}
@@ -0,0 +1,57 @@
+use std::io::Read;
+use std::time::{Duration, Instant};
+
+use bittorrent_protocol::handshake::transports::MockSocket;
+use bittorrent_protocol::handshake::{Extensions, TimeoutSocket};
+use bittorrent_protocol::peer::messages::{PeerWireProtocolMessage, PieceMessage};
+use bittorrent_protocol::peer::{
+    IPeerManagerMessage, OPeerManagerMessage, PeerInfo, PeerManagerBuilder,
+};
+
+use bytes::Bytes;
+
+const BLOCK_LEN: usize = 16 * 1024;
+const NUM_PIECES: usize = 8;
+const UPLOAD_LIMIT: u64 = 4 * BLOCK_LEN as u64;
+
+const PIECE_HEADER_LEN: usize = 13;
+
+#[test]
+fn positive_upload_throttled_to_limit() {
+    let (ours, mut theirs) = MockSocket::pair();
+    theirs.set_timeout(Some(Duration::from_secs(10))).unwrap();
+
+    let mut manager = PeerManagerBuilder::new().build();
+    manager.rate_limiter().set_upload_limit(Some(UPLOAD_LIMIT));
+    let info = PeerInfo::new(
+        "10.0.0.1:6881".parse().unwrap(),
+        [0u8; 20].into(),
+        [0u8; 20].into(),
+        Extensions::new(),
+    );
+
+    manager.send(IPeerManagerMessage::AddPeer(info, ours));
+    match manager.poll() {
+        Some(OPeerManagerMessage::PeerAdded(..)) => (),
+        other => panic!("Unexpected Message {:?}", other),
+    }
+
+    let start = Instant::now();
+    for index in 0..NUM_PIECES {
+        let piece = PieceMessage::new(index as u32, 0, Bytes::from(vec![0u8; BLOCK_LEN]));
+
+        manager.send(IPeerManagerMessage::SendMessage(
+            info,
+            index as u64,
+            PeerWireProtocolMessage::Piece(piece),
+        ));
+    }
+
+    let mut received = vec![0u8; NUM_PIECES * (PIECE_HEADER_LEN + BLOCK_LEN)];
+    theirs.read_exact(&mut received).unwrap();
+    let elapsed = start.elapsed();
+
+    // One second worth of payload goes out right away, the rest takes a second
+    assert!(elapsed >= Duration::from_millis(900), "{:?}", elapsed);
+    assert!(elapsed < Duration::from_secs(5), "{:?}", elapsed);
+}
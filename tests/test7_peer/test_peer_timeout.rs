@@ -0,0 +1,82 @@
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use bittorrent_protocol::handshake::Extensions;
+use bittorrent_protocol::peer::messages::PeerWireProtocolMessage;
+use bittorrent_protocol::peer::{
+    IPeerManagerMessage, MessageKind, OPeerManagerMessage, PeerDisconnectReason, PeerInfo,
+    PeerManagerBuilder,
+};
+
+const KEEP_ALIVE_BYTES: [u8; 4] = [0, 0, 0, 0];
+const NUM_KEEP_ALIVES: usize = 5;
+
+#[test]
+fn positive_peer_timeout_after_keep_alives() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let ours = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+    let (mut theirs, _) = listener.accept().unwrap();
+
+    let mut manager = PeerManagerBuilder::new()
+        .with_keep_alive_interval(Duration::from_millis(50))
+        .with_peer_timeout(Duration::from_millis(300))
+        .build();
+    let info = PeerInfo::new(
+        listener.local_addr().unwrap(),
+        [0u8; 20].into(),
+        [0u8; 20].into(),
+        Extensions::new(),
+    );
+
+    let start = Instant::now();
+    manager.send(IPeerManagerMessage::AddPeer(info, ours));
+    match manager.poll() {
+        Some(OPeerManagerMessage::PeerAdded(added, _)) => assert_eq!(info, added),
+        other => panic!("Unexpected Message {:?}", other),
+    }
+
+    // We should heartbeat them, even though we have nothing else to send
+    let mut received = [0u8; 4];
+    theirs.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    theirs.read_exact(&mut received).unwrap();
+    assert_eq!(KEEP_ALIVE_BYTES, received);
+
+    // Keep the peer alive for longer than the timeout, then go silent
+    let remote = thread::spawn(move || {
+        for _ in 0..NUM_KEEP_ALIVES {
+            thread::sleep(Duration::from_millis(100));
+            theirs.write_all(&KEEP_ALIVE_BYTES).unwrap();
+        }
+
+        theirs
+    });
+
+    for _ in 0..NUM_KEEP_ALIVES {
+        match manager.poll() {
+            Some(OPeerManagerMessage::ReceivedMessage(_, PeerWireProtocolMessage::KeepAlive)) => (),
+            other => panic!("Unexpected Message {:?}", other),
+        }
+    }
+
+    let stats = manager.peer_stats(&info).unwrap();
+    assert_eq!(NUM_KEEP_ALIVES as u64, stats.messages_received(MessageKind::KeepAlive));
+    assert!(stats.messages_sent(MessageKind::KeepAlive) >= 1);
+    assert_eq!(0, stats.downloaded_payload());
+
+    match manager.poll() {
+        Some(OPeerManagerMessage::PeerDisconnected {
+            info: disconnected,
+            reason,
+        }) => {
+            assert_eq!(info, disconnected);
+            assert_eq!(PeerDisconnectReason::Timeout, reason);
+        }
+        other => panic!("Unexpected Message {:?}", other),
+    }
+    assert!(manager.peer_stats(&info).is_none());
+    assert!(start.elapsed() >= Duration::from_millis(NUM_KEEP_ALIVES as u64 * 100 + 300));
+
+    remote.join().unwrap();
+}
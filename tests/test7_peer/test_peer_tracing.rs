@@ -0,0 +1,183 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{Read, Write};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use bittorrent_protocol::handshake::transports::MockSocket;
+use bittorrent_protocol::handshake::Extensions;
+use bittorrent_protocol::peer::messages::PeerWireProtocolMessage;
+use bittorrent_protocol::peer::{
+    IPeerManagerMessage, PeerDisconnectReason, PeerInfo, PeerManagerBuilder, PeerManagerEvent,
+};
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing::{Event, Metadata, Subscriber};
+
+type Fields = HashMap<String, String>;
+
+thread_local! {
+    static ENTERED: RefCell<Vec<u64>> = RefCell::new(Vec::new());
+}
+
+struct FieldVisitor<'a>(&'a mut Fields);
+
+impl<'a> Visit for FieldVisitor<'a> {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.insert(field.name().to_string(), value.to_string());
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.0
+            .insert(field.name().to_string(), format!("{:?}", value));
+    }
+}
+
+/// Spans and events seen by the subscriber, events along with the span they were in.
+#[derive(Default)]
+struct Recorded {
+    next_id: AtomicU64,
+    spans: Mutex<HashMap<u64, (&'static str, Fields)>>,
+    events: Mutex<Vec<(Option<u64>, Fields)>>,
+}
+
+struct RecordingSubscriber(Arc<Recorded>);
+
+impl Subscriber for RecordingSubscriber {
+    fn enabled(&self, _metadata: &Metadata) -> bool {
+        true
+    }
+
+    fn new_span(&self, span: &Attributes) -> Id {
+        let id = self.0.next_id.fetch_add(1, Ordering::SeqCst) + 1;
+        let mut fields = Fields::new();
+        span.record(&mut FieldVisitor(&mut fields));
+
+        self.0
+            .spans
+            .lock()
+            .unwrap()
+            .insert(id, (span.metadata().name(), fields));
+        Id::from_u64(id)
+    }
+
+    fn record(&self, span: &Id, values: &Record) {
+        if let Some((_, fields)) = self.0.spans.lock().unwrap().get_mut(&span.into_u64()) {
+            values.record(&mut FieldVisitor(fields));
+        }
+    }
+
+    fn record_follows_from(&self, _span: &Id, _follows: &Id) {}
+
+    fn event(&self, event: &Event) {
+        let mut fields = Fields::new();
+        event.record(&mut FieldVisitor(&mut fields));
+        let parent = event
+            .parent()
+            .map(Id::into_u64)
+            .or_else(|| ENTERED.with(|entered| entered.borrow().last().cloned()));
+
+        self.0.events.lock().unwrap().push((parent, fields));
+    }
+
+    fn enter(&self, span: &Id) {
+        ENTERED.with(|entered| entered.borrow_mut().push(span.into_u64()));
+    }
+
+    fn exit(&self, span: &Id) {
+        ENTERED.with(|entered| {
+            let mut entered = entered.borrow_mut();
+            if let Some(position) = entered.iter().rposition(|&id| id == span.into_u64()) {
+                entered.remove(position);
+            }
+        });
+    }
+}
+
+#[test]
+fn positive_peer_events_recorded_in_peer_span() {
+    let recorded = Arc::new(Recorded::default());
+    tracing::subscriber::set_global_default(RecordingSubscriber(recorded.clone())).unwrap();
+
+    let mut manager = PeerManagerBuilder::new().build();
+    let info = PeerInfo::new(
+        "10.0.0.7:6881".parse().unwrap(),
+        [7u8; 20].into(),
+        [9u8; 20].into(),
+        Extensions::new(),
+    );
+    let (ours, mut theirs) = MockSocket::pair();
+
+    manager.send(IPeerManagerMessage::AddPeer(info, ours));
+    match manager.poll_event() {
+        Some(PeerManagerEvent::PeerConnected { .. }) => (),
+        other => panic!("Unexpected Event {:?}", other),
+    }
+    manager.send(IPeerManagerMessage::SendMessage(
+        info,
+        0,
+        PeerWireProtocolMessage::Interested,
+    ));
+    match manager.poll_event() {
+        Some(PeerManagerEvent::MessageSent { .. }) => (),
+        other => panic!("Unexpected Event {:?}", other),
+    }
+    let mut interested = [0u8; 5];
+    theirs.read_exact(&mut interested).unwrap();
+
+    theirs.write_all(&[0, 0, 0, 1, 1]).unwrap();
+    match manager.poll_event() {
+        Some(PeerManagerEvent::MessageReceived {
+            message: PeerWireProtocolMessage::UnChoke,
+            ..
+        }) => (),
+        other => panic!("Unexpected Event {:?}", other),
+    }
+    drop(theirs);
+    match manager.poll_event() {
+        Some(PeerManagerEvent::PeerDisconnected { reason, .. }) => {
+            assert_eq!(PeerDisconnectReason::RemoteClosed, reason)
+        }
+        other => panic!("Unexpected Event {:?}", other),
+    }
+
+    let span_id = {
+        let spans = recorded.spans.lock().unwrap();
+        let (&id, (_, fields)) = spans
+            .iter()
+            .find(|(_, (name, fields))| {
+                *name == "peer" && fields.get("addr").map(String::as_str) == Some("10.0.0.7:6881")
+            })
+            .expect("No Span For The Peer");
+
+        assert_eq!(&"07".repeat(20), &fields["peer_id"]);
+        assert_eq!(&"09".repeat(20), &fields["info_hash"]);
+        id
+    };
+
+    let events = recorded.events.lock().unwrap();
+    let in_span = |message: &str| -> Vec<&Fields> {
+        events
+            .iter()
+            .filter(|(parent, fields)| {
+                *parent == Some(span_id)
+                    && fields.get("message").map(String::as_str) == Some(message)
+            })
+            .map(|(_, fields)| fields)
+            .collect()
+    };
+
+    assert_eq!(1, in_span("peer connected").len());
+    assert!(in_span("sent message")
+        .iter()
+        .any(|fields| fields["kind"] == "interested"));
+    assert!(in_span("received message")
+        .iter()
+        .any(|fields| fields["kind"] == "unchoke"));
+
+    let disconnected = in_span("peer disconnected");
+    assert_eq!(1, disconnected.len());
+    assert_eq!("RemoteClosed", disconnected[0]["reason"]);
+    assert_eq!("1", disconnected[0]["monotonic_counter.peers_disconnected"]);
+}
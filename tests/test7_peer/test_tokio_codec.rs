@@ -0,0 +1,124 @@
+use bittorrent_protocol::handshake::{Extension, Extensions};
+use bittorrent_protocol::peer::messages::builders::ExtendedMessageBuilder;
+use bittorrent_protocol::peer::messages::{
+    AllowedFastMessage, BitFieldMessage, BitsExtensionMessage, CancelMessage,
+    CustomExtensionMessage, DontHaveMessage, ExtendedMessage, ExtendedType, HaveMessage,
+    PeerExtensionProtocolMessage, PeerWireProtocolMessage, PieceMessage, PortMessage,
+    RejectMessage, RequestMessage, SuggestMessage, UtMetadataDataMessage, UtMetadataMessage,
+    UtMetadataRequestMessage, UtPexMessage,
+};
+use bittorrent_protocol::peer::{PeerWireCodec, PeerWireMessageCodec};
+
+use bytes::Bytes;
+use futures::{SinkExt, StreamExt};
+use tokio_util::codec::Framed;
+
+fn extended(chat_id: u8) -> ExtendedMessage {
+    ExtendedMessageBuilder::new()
+        .with_extended_type(ExtendedType::UtMetadata, Some(chat_id + 1))
+        .with_extended_type(ExtendedType::UtPex, Some(chat_id + 2))
+        .with_extended_type(ExtendedType::LtDontHave, Some(chat_id + 3))
+        .with_extended_type(ExtendedType::Custom("my_swarm_chat".to_string()), Some(chat_id))
+        .build()
+}
+
+fn all_messages() -> Vec<PeerWireProtocolMessage> {
+    vec![
+        PeerWireProtocolMessage::KeepAlive,
+        PeerWireProtocolMessage::Choke,
+        PeerWireProtocolMessage::UnChoke,
+        PeerWireProtocolMessage::Interested,
+        PeerWireProtocolMessage::UnInterested,
+        PeerWireProtocolMessage::Have(HaveMessage::new(7)),
+        PeerWireProtocolMessage::BitField(BitFieldMessage::new(Bytes::from(vec![0xF0; 20]))),
+        PeerWireProtocolMessage::Request(RequestMessage::new(1, 0, 16384)),
+        PeerWireProtocolMessage::Piece(PieceMessage::new(1, 0, Bytes::from(vec![0x55; 16384]))),
+        PeerWireProtocolMessage::Cancel(CancelMessage::new(1, 0, 16384)),
+        PeerWireProtocolMessage::HaveAll,
+        PeerWireProtocolMessage::HaveNone,
+        PeerWireProtocolMessage::Suggest(SuggestMessage::new(3)),
+        PeerWireProtocolMessage::Reject(RejectMessage::new(1, 0, 16384)),
+        PeerWireProtocolMessage::AllowedFast(AllowedFastMessage::new(9)),
+        PeerWireProtocolMessage::BitsExtension(BitsExtensionMessage::Port(PortMessage::new(6881))),
+        PeerWireProtocolMessage::ProtExtension(PeerExtensionProtocolMessage::UtMetadata(
+            UtMetadataMessage::Request(UtMetadataRequestMessage::new(0)),
+        )),
+        PeerWireProtocolMessage::ProtExtension(PeerExtensionProtocolMessage::UtMetadata(
+            UtMetadataMessage::Data(UtMetadataDataMessage::new(
+                0,
+                5,
+                Bytes::from(&b"d1:ae"[..]),
+            )),
+        )),
+        PeerWireProtocolMessage::ProtExtension(PeerExtensionProtocolMessage::UtPex(
+            UtPexMessage::new(
+                vec![("10.0.0.1:6881".parse().unwrap(), 0x02)],
+                vec!["10.0.0.2:6881".parse().unwrap()],
+            ),
+        )),
+        PeerWireProtocolMessage::ProtExtension(PeerExtensionProtocolMessage::LtDontHave(
+            DontHaveMessage::new(5),
+        )),
+        PeerWireProtocolMessage::ProtExtension(PeerExtensionProtocolMessage::Custom(
+            CustomExtensionMessage::new(
+                ExtendedType::Custom("my_swarm_chat".to_string()),
+                Bytes::from(&b"hello"[..]),
+            ),
+        )),
+    ]
+}
+
+fn new_codec() -> PeerWireCodec {
+    let mut extensions = Extensions::new();
+    extensions.add(Extension::FastExtension);
+    extensions.add(Extension::ExtensionProtocol);
+
+    PeerWireCodec::new(PeerWireMessageCodec::with_extensions(&extensions))
+        .with_max_frame_length(32 * 1024)
+}
+
+#[tokio::test]
+async fn positive_exchange_all_messages() {
+    let (stream_one, stream_two) = tokio::io::duplex(64 * 1024);
+    let mut peer_one = Framed::new(stream_one, new_codec());
+    let mut peer_two = Framed::new(stream_two, new_codec());
+
+    // Each peer maps the extensions to different ids
+    let handshake = |id| {
+        PeerWireProtocolMessage::BitsExtension(BitsExtensionMessage::Extended(extended(id)))
+    };
+    peer_one.send(handshake(10)).await.unwrap();
+    assert_eq!(handshake(10), peer_two.next().await.unwrap().unwrap());
+    peer_two.send(handshake(20)).await.unwrap();
+    assert_eq!(handshake(20), peer_one.next().await.unwrap().unwrap());
+
+    assert_eq!(Some(&extended(20)), peer_one.codec().their_extended_message());
+    assert_eq!(Some(&extended(10)), peer_one.codec().our_extended_message());
+
+    for (message, expected) in all_messages().into_iter().zip(all_messages()) {
+        peer_one.send(message).await.unwrap();
+        assert_eq!(expected, peer_two.next().await.unwrap().unwrap());
+    }
+
+    for (message, expected) in all_messages().into_iter().zip(all_messages()) {
+        peer_two.send(message).await.unwrap();
+        assert_eq!(expected, peer_one.next().await.unwrap().unwrap());
+    }
+
+    // Closing the stream should end the other side cleanly
+    drop(peer_one);
+    assert!(peer_two.next().await.is_none());
+}
+
+#[tokio::test]
+async fn negative_oversized_frame_errors() {
+    let (stream_one, stream_two) = tokio::io::duplex(64 * 1024);
+    let mut peer_one = Framed::new(stream_one, PeerWireCodec::new(PeerWireMessageCodec::new()));
+    let mut peer_two = Framed::new(stream_two, new_codec().with_max_frame_length(1024));
+
+    let piece = PieceMessage::new(1, 0, Bytes::from(vec![0x55; 2048]));
+    peer_one.send(PeerWireProtocolMessage::Piece(piece)).await.unwrap();
+
+    let error = peer_two.next().await.unwrap().unwrap_err();
+    assert_eq!(std::io::ErrorKind::InvalidData, error.kind());
+}
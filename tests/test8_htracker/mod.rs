@@ -0,0 +1,378 @@
+use bittorrent_protocol::htracker::error::{HttpTrackerError, HttpTrackerErrorKind};
+use bittorrent_protocol::htracker::{
+    AnnounceEvent, AnnounceRequest, AnnounceResponse, ClientState, DesiredPeers, HttpTrackerClient,
+    InfoHash, PeerId, ScrapeResponse, TrackerPeer, Url,
+};
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+use tokio::task::JoinHandle;
+
+mod test_manager;
+mod test_proxy;
+#[cfg(feature = "websocket")]
+mod test_websocket;
+
+const COMPACT: &'static [u8] = include_bytes!("responses/compact.http");
+const COMPACT_CHUNKED: &'static [u8] = include_bytes!("responses/compact_chunked.http");
+const DICTIONARY: &'static [u8] = include_bytes!("responses/dictionary.http");
+const WARNING: &'static [u8] = include_bytes!("responses/warning.http");
+const FAILURE: &'static [u8] = include_bytes!("responses/failure.http");
+const FAILURE_STATUS: &'static [u8] = include_bytes!("responses/failure_status.http");
+const NOT_FOUND: &'static [u8] = include_bytes!("responses/not_found.http");
+const REDIRECT: &'static [u8] = include_bytes!("responses/redirect.http");
+const REDIRECT_LOOP: &'static [u8] = include_bytes!("responses/redirect_loop.http");
+const SCRAPE_OPENTRACKER: &'static [u8] = include_bytes!("responses/scrape_opentracker.http");
+const SCRAPE_FLAGS: &'static [u8] = include_bytes!("responses/scrape_flags.http");
+const SCRAPE_PARTIAL: &'static [u8] = include_bytes!("responses/scrape_partial.http");
+const SCRAPE_FAILURE: &'static [u8] = include_bytes!("responses/scrape_failure.http");
+
+const FIRST_HASH: &'static str = "c9e15763f722f23e98a29decdfae341b98d53056";
+const SECOND_HASH: &'static str = "dd8255ecdc7ca55fb0bbf81323d87062db1f6d1c";
+const PARTIAL_HASH: &'static str = "2e5b4f0a5c4b1a2f7e2a0d6c2f0b8f3a1d4c5e6f";
+
+#[test]
+pub fn my_print() {
+    println!("test htracker");
+}
+
+/// Serve each canned response to one connection, in order, returning the request
+/// target of every connection.
+async fn serve(responses: Vec<&'static [u8]>) -> (Url, JoinHandle<Vec<String>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = Url::parse(&format!(
+        "http://{}/announce",
+        listener.local_addr().unwrap()
+    ))
+    .unwrap();
+
+    let handle = tokio::spawn(async move {
+        let mut targets = Vec::new();
+
+        for response in responses {
+            let (mut stream, _) = listener.accept().await.unwrap();
+
+            let mut request = Vec::new();
+            while !request.ends_with(b"\r\n\r\n") {
+                let mut byte = [0u8; 1];
+                stream.read_exact(&mut byte).await.unwrap();
+                request.push(byte[0]);
+            }
+            let request = String::from_utf8(request).unwrap();
+            targets.push(request.split(' ').nth(1).unwrap().to_owned());
+
+            stream.write_all(response).await.unwrap();
+            stream.shutdown().await.unwrap();
+        }
+
+        targets
+    });
+
+    (url, handle)
+}
+
+fn announce_request() -> AnnounceRequest {
+    AnnounceRequest::new(
+        [0xABu8; 20].into(),
+        PeerId::from(*b"-BP0300-123456789012"),
+        ClientState::new(100, 200, 300, AnnounceEvent::Started),
+        0x01020304,
+        DesiredPeers::Specified(30),
+        6881,
+    )
+}
+
+async fn announce(responses: Vec<&'static [u8]>) -> (Vec<String>, AnnounceResponse) {
+    let (url, handle) = serve(responses).await;
+    let response = HttpTrackerClient::new()
+        .announce(&url, &announce_request())
+        .await
+        .unwrap();
+
+    (handle.await.unwrap(), response)
+}
+
+async fn announce_error(responses: Vec<&'static [u8]>) -> HttpTrackerError {
+    let (url, _handle) = serve(responses).await;
+
+    HttpTrackerClient::new()
+        .with_max_redirects(2)
+        .announce(&url, &announce_request())
+        .await
+        .unwrap_err()
+}
+
+fn peer(addr: &str, opt_peer_id: Option<&[u8; 20]>) -> TrackerPeer {
+    TrackerPeer::new(
+        addr.parse::<SocketAddr>().unwrap(),
+        opt_peer_id.map(|peer_id| PeerId::from(*peer_id)),
+    )
+}
+
+#[tokio::test]
+async fn positive_announce_compact() {
+    let (targets, response) = announce(vec![COMPACT]).await;
+
+    assert_eq!(
+        targets,
+        vec![
+            "/announce?info_hash=%AB%AB%AB%AB%AB%AB%AB%AB%AB%AB%AB%AB%AB%AB%AB%AB%AB%AB%AB%AB\
+             &peer_id=-BP0300-123456789012&port=6881&uploaded=300&downloaded=100&left=200\
+             &compact=1&key=01020304&numwant=30&event=started"
+                .to_owned()
+        ]
+    );
+
+    assert_eq!(response.interval(), 1800);
+    assert_eq!(response.min_interval(), Some(300));
+    assert_eq!(response.complete(), Some(12));
+    assert_eq!(response.incomplete(), Some(4));
+    assert_eq!(response.tracker_id(), Some(&b"tracker-7"[..]));
+    assert_eq!(
+        response.peers(),
+        &[
+            peer("127.0.0.1:6881", None),
+            peer("192.168.1.20:51413", None),
+            peer("[2001:db8::1]:6881", None),
+        ][..]
+    );
+}
+
+#[tokio::test]
+async fn positive_announce_chunked() {
+    let (_, chunked) = announce(vec![COMPACT_CHUNKED]).await;
+    let (_, compact) = announce(vec![COMPACT]).await;
+
+    assert_eq!(chunked, compact);
+}
+
+#[tokio::test]
+async fn positive_announce_dictionary() {
+    let (_, response) = announce(vec![DICTIONARY]).await;
+
+    assert_eq!(response.interval(), 900);
+    assert_eq!(response.min_interval(), None);
+    assert_eq!(response.complete(), None);
+    assert_eq!(
+        response.peers(),
+        &[
+            peer("10.20.30.40:51413", Some(b"-qB4250-000000000001")),
+            peer("[2001:db8::dead:beef]:6881", None),
+        ][..]
+    );
+}
+
+#[tokio::test]
+async fn positive_announce_warning() {
+    let (_, response) = announce(vec![WARNING]).await;
+
+    assert_eq!(
+        response.warning_message(),
+        Some("tracker is in read only mode")
+    );
+    assert!(response.peers().is_empty());
+}
+
+#[tokio::test]
+async fn positive_announce_tracker_id_and_existing_query() {
+    let (url, handle) = serve(vec![COMPACT]).await;
+    let url = Url::parse(&format!("{}?passkey=secret", url)).unwrap();
+    HttpTrackerClient::new()
+        .announce(&url, &announce_request().with_tracker_id(b"tracker-7"))
+        .await
+        .unwrap();
+
+    let targets = handle.await.unwrap();
+    assert!(targets[0].starts_with("/announce?passkey=secret&info_hash=%AB"));
+    assert!(targets[0].ends_with("&event=started&trackerid=tracker-7"));
+}
+
+#[tokio::test]
+async fn positive_announce_follows_redirect() {
+    let (targets, response) = announce(vec![REDIRECT, COMPACT]).await;
+
+    assert_eq!(targets.len(), 2);
+    assert_eq!(targets[1], "/moved/announce?passkey=abc");
+    assert_eq!(response.interval(), 1800);
+}
+
+#[tokio::test]
+async fn negative_announce_too_many_redirects() {
+    match announce_error(vec![REDIRECT_LOOP, REDIRECT_LOOP, REDIRECT_LOOP])
+        .await
+        .kind()
+    {
+        HttpTrackerErrorKind::TooManyRedirects { limit } => assert_eq!(*limit, 2),
+        kind => panic!("Unexpected Error: {:?}", kind),
+    }
+}
+
+#[tokio::test]
+async fn negative_announce_failure_reason() {
+    match announce_error(vec![FAILURE]).await.kind() {
+        HttpTrackerErrorKind::TrackerFailure { reason } => {
+            assert_eq!(reason, "unregistered torrent hash")
+        }
+        kind => panic!("Unexpected Error: {:?}", kind),
+    }
+}
+
+#[tokio::test]
+async fn negative_announce_failure_reason_with_status() {
+    match announce_error(vec![FAILURE_STATUS]).await.kind() {
+        HttpTrackerErrorKind::TrackerFailure { reason } => {
+            assert_eq!(reason, "invalid info_hash sent")
+        }
+        kind => panic!("Unexpected Error: {:?}", kind),
+    }
+}
+
+#[tokio::test]
+async fn negative_announce_http_status() {
+    match announce_error(vec![NOT_FOUND]).await.kind() {
+        HttpTrackerErrorKind::HttpStatus { status, reason } => {
+            assert_eq!(*status, 404);
+            assert_eq!(reason, "Not Found");
+        }
+        kind => panic!("Unexpected Error: {:?}", kind),
+    }
+}
+
+#[tokio::test]
+async fn negative_announce_https_without_connector() {
+    let url = Url::parse("https://tracker.example.com/announce").unwrap();
+    let error = HttpTrackerClient::new()
+        .announce(&url, &announce_request())
+        .await
+        .unwrap_err();
+
+    match error.kind() {
+        HttpTrackerErrorKind::UnsupportedScheme { scheme } => assert_eq!(scheme, "https"),
+        kind => panic!("Unexpected Error: {:?}", kind),
+    }
+}
+
+#[tokio::test]
+async fn negative_announce_timeout() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = Url::parse(&format!(
+        "http://{}/announce",
+        listener.local_addr().unwrap()
+    ))
+    .unwrap();
+
+    let error = HttpTrackerClient::new()
+        .with_timeout(Duration::from_millis(100))
+        .announce(&url, &announce_request())
+        .await
+        .unwrap_err();
+
+    assert!(error.to_string().contains("Did Not Respond In Time"));
+}
+
+fn hash(hex: &str) -> InfoHash {
+    let bytes: Vec<u8> = (0..hex.len())
+        .step_by(2)
+        .map(|index| u8::from_str_radix(&hex[index..index + 2], 16).unwrap())
+        .collect();
+
+    InfoHash::from_hash(&bytes).unwrap()
+}
+
+async fn scrape(
+    responses: Vec<&'static [u8]>,
+    hashes: &[InfoHash],
+) -> (Vec<String>, ScrapeResponse) {
+    let (url, handle) = serve(responses).await;
+    let response = HttpTrackerClient::new().scrape(&url, hashes).await.unwrap();
+
+    (handle.await.unwrap(), response)
+}
+
+#[tokio::test]
+async fn positive_scrape_multiple_hashes() {
+    let hashes = [hash(FIRST_HASH), hash(SECOND_HASH)];
+    let (targets, response) = scrape(vec![SCRAPE_OPENTRACKER], &hashes).await;
+
+    assert_eq!(
+        targets,
+        vec![
+            "/scrape?info_hash=%C9%E1Wc%F7%22%F2%3E%98%A2%9D%EC%DF%AE4%1B%98%D50V\
+             &info_hash=%DD%82U%EC%DC%7C%A5_%B0%BB%F8%13%23%D8pb%DB%1Fm%1C"
+                .to_owned()
+        ]
+    );
+
+    assert_eq!(response.files().len(), 2);
+    let first = response.get(&hashes[0]).unwrap();
+    assert_eq!(
+        (
+            first.num_seeders(),
+            first.num_downloads(),
+            first.num_leechers()
+        ),
+        (1523, 48211, 37)
+    );
+    let second = response.get(&hashes[1]).unwrap();
+    assert_eq!(
+        (
+            second.num_seeders(),
+            second.num_downloads(),
+            second.num_leechers()
+        ),
+        (418, 9032, 12)
+    );
+}
+
+#[tokio::test]
+async fn positive_scrape_missing_hash_and_flags() {
+    let hashes = [hash(FIRST_HASH), hash(SECOND_HASH)];
+    let (_, response) = scrape(vec![SCRAPE_FLAGS], &hashes).await;
+
+    assert_eq!(response.min_request_interval(), Some(1800));
+    assert_eq!(response.get(&hashes[0]).unwrap().num_downloads(), 120);
+    assert_eq!(response.get(&hashes[1]), None);
+}
+
+#[tokio::test]
+async fn positive_scrape_partial_stats() {
+    let (_, response) = scrape(vec![SCRAPE_PARTIAL], &[hash(PARTIAL_HASH)]).await;
+
+    let stats = response.get(&hash(PARTIAL_HASH)).unwrap();
+    assert_eq!(stats.num_seeders(), i32::max_value());
+    assert_eq!(stats.num_downloads(), 0);
+    assert_eq!(stats.num_leechers(), 2);
+}
+
+#[tokio::test]
+async fn negative_scrape_failure_reason() {
+    let (url, _handle) = serve(vec![SCRAPE_FAILURE]).await;
+    let error = HttpTrackerClient::new()
+        .scrape(&url, &[hash(FIRST_HASH)])
+        .await
+        .unwrap_err();
+
+    match error.kind() {
+        HttpTrackerErrorKind::TrackerFailure { reason } => {
+            assert_eq!(reason, "scrape is disabled on this tracker")
+        }
+        kind => panic!("Unexpected Error: {:?}", kind),
+    }
+}
+
+#[tokio::test]
+async fn negative_scrape_without_announce_convention() {
+    let url = Url::parse("http://127.0.0.1:1/tracker.php?action=announce").unwrap();
+    let error = HttpTrackerClient::new()
+        .scrape(&url, &[hash(FIRST_HASH)])
+        .await
+        .unwrap_err();
+
+    match error.kind() {
+        HttpTrackerErrorKind::ScrapeNotSupported { url } => {
+            assert_eq!(url, "http://127.0.0.1:1/tracker.php?action=announce")
+        }
+        kind => panic!("Unexpected Error: {:?}", kind),
+    }
+}
@@ -0,0 +1,432 @@
+use bittorrent_protocol::htracker::error::HttpTrackerResult;
+use bittorrent_protocol::htracker::{
+    AnnounceEvent, AnnounceRequest, AnnounceResponse, ClientState, InfoHash, PeerId, TrackerEvent,
+    TrackerManager, TrackerManagerBuilder, TrackerPeer, TrackerTransport, TrackerUrl,
+};
+use bittorrent_protocol::utracker::Handshaker;
+use futures::future::BoxFuture;
+use std::collections::HashMap;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio::time::{self, Instant};
+
+const TRACKER_A: &'static str = "http://a.example.com/announce";
+const TRACKER_B: &'static str = "http://b.example.com/announce";
+const TRACKER_C: &'static str = "udp://c.example.com:6969";
+
+/// Announce received by the mock transport.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Announce {
+    tracker: String,
+    event: AnnounceEvent,
+    at: Duration,
+}
+
+/// Transport answering every tracker with its configured response, failing trackers
+/// without one.
+#[derive(Clone)]
+struct MockTransport {
+    start: Instant,
+    responses: Arc<Mutex<HashMap<String, AnnounceResponse>>>,
+    announces: Arc<Mutex<Vec<Announce>>>,
+    requests: Arc<Mutex<Vec<AnnounceRequest>>>,
+}
+
+impl MockTransport {
+    fn new() -> MockTransport {
+        MockTransport {
+            start: Instant::now(),
+            responses: Arc::new(Mutex::new(HashMap::new())),
+            announces: Arc::new(Mutex::new(Vec::new())),
+            requests: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    fn set_response(&self, tracker: &str, opt_response: Option<AnnounceResponse>) {
+        let tracker = TrackerUrl::parse(tracker).unwrap().to_string();
+        let mut responses = self.responses.lock().unwrap();
+
+        match opt_response {
+            Some(response) => responses.insert(tracker, response),
+            None => responses.remove(&tracker),
+        };
+    }
+
+    fn announces(&self) -> Vec<Announce> {
+        self.announces.lock().unwrap().clone()
+    }
+}
+
+impl TrackerTransport for MockTransport {
+    fn send_announce(
+        &self,
+        tracker: &TrackerUrl,
+        request: &AnnounceRequest,
+    ) -> BoxFuture<'static, HttpTrackerResult<AnnounceResponse>> {
+        let tracker = tracker.to_string();
+        self.announces.lock().unwrap().push(Announce {
+            tracker: tracker.clone(),
+            event: request.state().event(),
+            at: Instant::now() - self.start,
+        });
+        self.requests.lock().unwrap().push(request.clone());
+
+        let opt_response = self.responses.lock().unwrap().get(&tracker).cloned();
+        Box::pin(async move {
+            opt_response.ok_or_else(|| {
+                io::Error::new(io::ErrorKind::ConnectionRefused, "Tracker Unreachable").into()
+            })
+        })
+    }
+}
+
+#[derive(Clone)]
+struct MockHandshaker {
+    connects: Arc<Mutex<Vec<(Option<PeerId>, InfoHash, SocketAddr)>>>,
+}
+
+impl Handshaker for MockHandshaker {
+    type Metadata = ();
+
+    fn id(&self) -> PeerId {
+        [1u8; 20].into()
+    }
+
+    fn port(&self) -> u16 {
+        6881
+    }
+
+    fn connect(&mut self, expected: Option<PeerId>, hash: InfoHash, addr: SocketAddr) {
+        self.connects.lock().unwrap().push((expected, hash, addr));
+    }
+
+    fn metadata(&mut self, _: ()) {}
+}
+
+fn tiers(tiers: &[&[&str]]) -> Vec<Vec<TrackerUrl>> {
+    tiers
+        .iter()
+        .map(|tier| {
+            tier.iter()
+                .map(|url| TrackerUrl::parse(url).unwrap())
+                .collect()
+        })
+        .collect()
+}
+
+fn response(interval: u64, min_interval: u64) -> AnnounceResponse {
+    AnnounceResponse::new(
+        interval,
+        vec![TrackerPeer::new("10.0.0.1:6881".parse().unwrap(), None)],
+    )
+    .with_min_interval(min_interval)
+}
+
+fn announce(tracker: &str, event: AnnounceEvent, at_secs: u64) -> Announce {
+    Announce {
+        tracker: TrackerUrl::parse(tracker).unwrap().to_string(),
+        event: event,
+        at: Duration::from_secs(at_secs),
+    }
+}
+
+fn build(transport: &MockTransport, tracker_tiers: &[&[&str]]) -> (TrackerManager, MockHandshaker) {
+    build_with(TrackerManagerBuilder::new(), transport, tracker_tiers)
+}
+
+fn build_with(
+    builder: TrackerManagerBuilder,
+    transport: &MockTransport,
+    tracker_tiers: &[&[&str]],
+) -> (TrackerManager, MockHandshaker) {
+    let handshaker = MockHandshaker {
+        connects: Arc::new(Mutex::new(Vec::new())),
+    };
+    let manager = builder.build(
+        [0xAAu8; 20].into(),
+        tiers(tracker_tiers),
+        ClientState::new(0, 1000, 0, AnnounceEvent::None),
+        transport.clone(),
+        handshaker.clone(),
+    );
+
+    (manager, handshaker)
+}
+
+#[tokio::test(start_paused = true)]
+async fn positive_manager_started_then_interval() {
+    let transport = MockTransport::new();
+    transport.set_response(TRACKER_A, Some(response(1800, 0)));
+    let (manager, handshaker) = build(&transport, &[&[TRACKER_A]]);
+
+    time::sleep(Duration::from_secs(1801)).await;
+    assert_eq!(
+        transport.announces(),
+        vec![
+            announce(TRACKER_A, AnnounceEvent::Started, 0),
+            announce(TRACKER_A, AnnounceEvent::None, 1800),
+        ]
+    );
+
+    let connects = handshaker.connects.lock().unwrap().clone();
+    assert_eq!(connects.len(), 2);
+    assert_eq!(connects[0].1, InfoHash::from([0xAAu8; 20]));
+    assert_eq!(connects[0].2, "10.0.0.1:6881".parse().unwrap());
+
+    let status = manager.status();
+    assert_eq!(status.len(), 1);
+    assert_eq!(status[0].peers_returned(), 1);
+    assert_eq!(status[0].last_error(), None);
+    assert!(status[0].last_announce().is_some());
+}
+
+#[tokio::test(start_paused = true)]
+async fn positive_manager_keeps_swarm_counts() {
+    let transport = MockTransport::new();
+    transport.set_response(
+        TRACKER_A,
+        Some(response(1800, 0).with_complete(12).with_incomplete(3)),
+    );
+    let (manager, _) = build(&transport, &[&[TRACKER_A]]);
+
+    time::sleep(Duration::from_secs(1)).await;
+    let status = manager.status();
+    assert_eq!(status[0].seeders(), Some(12));
+    assert_eq!(status[0].leechers(), Some(3));
+
+    // Responses without counts leave the last ones
+    transport.set_response(TRACKER_A, Some(response(1800, 0)));
+    time::sleep(Duration::from_secs(1800)).await;
+    assert_eq!(manager.status()[0].seeders(), Some(12));
+}
+
+#[tokio::test(start_paused = true)]
+async fn positive_manager_respects_min_interval() {
+    let transport = MockTransport::new();
+    transport.set_response(TRACKER_A, Some(response(60, 300)));
+    let (_manager, _) = build(&transport, &[&[TRACKER_A]]);
+
+    time::sleep(Duration::from_secs(301)).await;
+    assert_eq!(
+        transport.announces(),
+        vec![
+            announce(TRACKER_A, AnnounceEvent::Started, 0),
+            announce(TRACKER_A, AnnounceEvent::None, 300),
+        ]
+    );
+}
+
+#[tokio::test(start_paused = true)]
+async fn positive_manager_promotes_working_tracker() {
+    let transport = MockTransport::new();
+    transport.set_response(TRACKER_B, Some(response(1800, 0)));
+    let (manager, _) = build(&transport, &[&[TRACKER_C, TRACKER_A, TRACKER_B]]);
+
+    time::sleep(Duration::from_secs(1)).await;
+    let order: Vec<String> = manager
+        .status()
+        .iter()
+        .map(|status| status.url().to_string())
+        .collect();
+    assert_eq!(
+        order,
+        vec![
+            TrackerUrl::parse(TRACKER_B).unwrap().to_string(),
+            TrackerUrl::parse(TRACKER_C).unwrap().to_string(),
+            TrackerUrl::parse(TRACKER_A).unwrap().to_string(),
+        ]
+    );
+    assert_eq!(manager.status()[2].failures(), 1);
+    assert!(manager.status()[2].last_error().is_some());
+
+    // Failing trackers are left alone while the promoted one keeps answering
+    time::sleep(Duration::from_secs(1800)).await;
+    assert_eq!(
+        transport.announces(),
+        vec![
+            announce(TRACKER_C, AnnounceEvent::Started, 0),
+            announce(TRACKER_A, AnnounceEvent::Started, 0),
+            announce(TRACKER_B, AnnounceEvent::Started, 0),
+            announce(TRACKER_B, AnnounceEvent::None, 1800),
+        ]
+    );
+}
+
+#[tokio::test(start_paused = true)]
+async fn positive_manager_falls_back_across_tiers() {
+    let transport = MockTransport::new();
+    transport.set_response(TRACKER_B, Some(response(1800, 0)));
+    let (manager, _) = build(&transport, &[&[TRACKER_A], &[TRACKER_B]]);
+
+    time::sleep(Duration::from_secs(1)).await;
+    assert_eq!(
+        transport.announces(),
+        vec![
+            announce(TRACKER_A, AnnounceEvent::Started, 0),
+            announce(TRACKER_B, AnnounceEvent::Started, 0),
+        ]
+    );
+
+    let status = manager.status();
+    assert_eq!((status[0].tier(), status[1].tier()), (0, 1));
+    assert!(status[0].last_announce().is_none());
+    assert!(status[1].last_announce().is_some());
+
+    // Once the first tier is back, it is preferred again
+    transport.set_response(TRACKER_A, Some(response(1800, 0)));
+    time::sleep(Duration::from_secs(1800)).await;
+    assert_eq!(
+        transport.announces()[2],
+        announce(TRACKER_A, AnnounceEvent::None, 1800)
+    );
+}
+
+#[tokio::test(start_paused = true)]
+async fn positive_manager_backs_off_exponentially() {
+    let transport = MockTransport::new();
+    let (manager, _) = build(&transport, &[&[TRACKER_A]]);
+
+    time::sleep(Duration::from_secs(106)).await;
+    assert_eq!(
+        transport.announces(),
+        vec![
+            announce(TRACKER_A, AnnounceEvent::Started, 0),
+            announce(TRACKER_A, AnnounceEvent::Started, 15),
+            announce(TRACKER_A, AnnounceEvent::Started, 45),
+            announce(TRACKER_A, AnnounceEvent::Started, 105),
+        ]
+    );
+    assert_eq!(manager.status()[0].failures(), 4);
+
+    // Nothing was started, so nothing is stopped
+    manager.stop().await;
+    assert_eq!(transport.announces().len(), 4);
+}
+
+#[tokio::test(start_paused = true)]
+async fn positive_manager_completed_exactly_once() {
+    let transport = MockTransport::new();
+    transport.set_response(TRACKER_A, Some(response(1800, 60)));
+    let (manager, _) = build(&transport, &[&[TRACKER_A]]);
+
+    time::sleep(Duration::from_secs(10)).await;
+    manager.update_state(ClientState::new(1000, 0, 0, AnnounceEvent::None));
+    manager.completed();
+    manager.completed();
+
+    time::sleep(Duration::from_secs(1900)).await;
+    manager.completed();
+    time::sleep(Duration::from_secs(1)).await;
+
+    assert_eq!(
+        transport.announces(),
+        vec![
+            announce(TRACKER_A, AnnounceEvent::Started, 0),
+            announce(TRACKER_A, AnnounceEvent::Completed, 60),
+            announce(TRACKER_A, AnnounceEvent::None, 1860),
+        ]
+    );
+}
+
+#[tokio::test(start_paused = true)]
+async fn positive_manager_stopped_on_stop() {
+    let transport = MockTransport::new();
+    transport.set_response(TRACKER_B, Some(response(1800, 0)));
+    let (manager, _) = build(&transport, &[&[TRACKER_A, TRACKER_B]]);
+
+    time::sleep(Duration::from_secs(100)).await;
+    manager.stop().await;
+
+    assert_eq!(
+        transport.announces().last().unwrap(),
+        &announce(TRACKER_B, AnnounceEvent::Stopped, 100)
+    );
+}
+
+#[tokio::test(start_paused = true)]
+async fn positive_manager_stopped_once_on_shutdown() {
+    let transport = MockTransport::new();
+    transport.set_response(TRACKER_A, Some(response(1800, 0)));
+    let (manager, _) = build(&transport, &[&[TRACKER_A]]);
+
+    time::sleep(Duration::from_secs(10)).await;
+    manager.shutdown().await;
+    manager.shutdown().await;
+    drop(manager);
+    time::sleep(Duration::from_secs(1)).await;
+
+    assert_eq!(
+        transport.announces(),
+        vec![
+            announce(TRACKER_A, AnnounceEvent::Started, 0),
+            announce(TRACKER_A, AnnounceEvent::Stopped, 10),
+        ]
+    );
+}
+
+#[tokio::test(start_paused = true)]
+async fn positive_manager_stopped_on_drop() {
+    let transport = MockTransport::new();
+    transport.set_response(TRACKER_A, Some(response(1800, 0)));
+    let (manager, _) = build(&transport, &[&[TRACKER_A]]);
+
+    time::sleep(Duration::from_secs(100)).await;
+    drop(manager);
+    time::sleep(Duration::from_secs(1)).await;
+
+    assert_eq!(
+        transport.announces(),
+        vec![
+            announce(TRACKER_A, AnnounceEvent::Started, 0),
+            announce(TRACKER_A, AnnounceEvent::Stopped, 100),
+        ]
+    );
+}
+
+#[tokio::test(start_paused = true)]
+async fn positive_manager_reports_external_ips() {
+    let transport = MockTransport::new();
+    let first_ip: IpAddr = "203.0.113.7".parse().unwrap();
+    let second_ip: IpAddr = "198.51.100.9".parse().unwrap();
+    let reported_ip: IpAddr = "192.0.2.1".parse().unwrap();
+    transport.set_response(
+        TRACKER_A,
+        Some(response(1800, 0).with_external_ip(first_ip)),
+    );
+    transport.set_response(
+        TRACKER_B,
+        Some(response(1800, 0).with_external_ip(second_ip)),
+    );
+
+    let (events_send, mut events) = mpsc::unbounded_channel();
+    let builder = TrackerManagerBuilder::new()
+        .with_reported_ip(reported_ip)
+        .with_events(events_send);
+    let (manager, _) = build_with(builder, &transport, &[&[TRACKER_A], &[TRACKER_B]]);
+
+    // Once the first tier stops answering, the second tier disagrees about our address
+    time::sleep(Duration::from_secs(1)).await;
+    transport.set_response(TRACKER_A, None);
+    time::sleep(Duration::from_secs(1800)).await;
+
+    assert_eq!(
+        events.recv().await.unwrap(),
+        TrackerEvent::ExternalIpObserved(TrackerUrl::parse(TRACKER_A).unwrap(), first_ip)
+    );
+    assert_eq!(
+        events.recv().await.unwrap(),
+        TrackerEvent::ExternalIpObserved(TrackerUrl::parse(TRACKER_B).unwrap(), second_ip)
+    );
+
+    let status = manager.status();
+    assert_eq!(status[0].external_ip(), Some(first_ip));
+    assert_eq!(status[1].external_ip(), Some(second_ip));
+
+    for request in transport.requests.lock().unwrap().iter() {
+        assert_eq!(request.reported_ipv4(), Some("192.0.2.1".parse().unwrap()));
+        assert_eq!(request.reported_ipv6(), None);
+    }
+}
@@ -0,0 +1,248 @@
+use bittorrent_protocol::htracker::error::{HttpTrackerError, HttpTrackerErrorKind};
+use bittorrent_protocol::htracker::{
+    HttpTrackerClient, ProxyAuth, ProxyConfig, ProxyError, ProxyKind, Url,
+};
+use std::io;
+use std::net::SocketAddr;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::JoinHandle;
+
+const USERNAME: &'static str = "user";
+const PASSWORD: &'static str = "pass";
+
+/// SOCKS5 proxy handling a single connection, connecting it to the tracker whatever the
+/// requested destination, and returning the requested destination.
+async fn socks_proxy(
+    tracker: SocketAddr,
+    opt_auth: Option<(&'static str, &'static str)>,
+    reply: u8,
+) -> (SocketAddr, JoinHandle<Vec<u8>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let handle = tokio::spawn(async move {
+        let (mut stream, _) = listener.accept().await.unwrap();
+
+        let mut greeting = [0u8; 2];
+        stream.read_exact(&mut greeting).await.unwrap();
+        let mut methods = vec![0u8; greeting[1] as usize];
+        stream.read_exact(&mut methods).await.unwrap();
+
+        let method = match opt_auth {
+            Some(_) if methods.contains(&0x02) => 0x02,
+            Some(_) => 0xFF,
+            None => 0x00,
+        };
+        stream.write_all(&[0x05, method]).await.unwrap();
+
+        if let Some((username, password)) = opt_auth {
+            let mut version = [0u8; 2];
+            stream.read_exact(&mut version).await.unwrap();
+            let mut sent_username = vec![0u8; version[1] as usize];
+            stream.read_exact(&mut sent_username).await.unwrap();
+            let mut sent_password = vec![0u8; stream.read_u8().await.unwrap() as usize];
+            stream.read_exact(&mut sent_password).await.unwrap();
+
+            let accepted =
+                sent_username == username.as_bytes() && sent_password == password.as_bytes();
+            stream
+                .write_all(&[0x01, if accepted { 0x00 } else { 0x01 }])
+                .await
+                .unwrap();
+            if !accepted {
+                return Vec::new();
+            }
+        }
+
+        let mut head = [0u8; 4];
+        stream.read_exact(&mut head).await.unwrap();
+        assert_eq!(&head[..3], &[0x05, 0x01, 0x00]);
+
+        let mut destination = vec![head[3]];
+        let addr_len = match head[3] {
+            0x01 => 4,
+            0x04 => 16,
+            _ => {
+                let length = stream.read_u8().await.unwrap();
+                destination.push(length);
+                length as usize
+            }
+        };
+        let mut addr_bytes = vec![0u8; addr_len + 2];
+        stream.read_exact(&mut addr_bytes).await.unwrap();
+        destination.extend_from_slice(&addr_bytes);
+
+        stream
+            .write_all(&[0x05, reply, 0x00, 0x01, 127, 0, 0, 1, 0, 0])
+            .await
+            .unwrap();
+        if reply == 0x00 {
+            pipe(stream, tracker).await;
+        }
+
+        destination
+    });
+
+    (addr, handle)
+}
+
+/// HTTP proxy handling a single CONNECT, returning its request head.
+async fn http_proxy(tracker: SocketAddr) -> (SocketAddr, JoinHandle<String>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let handle = tokio::spawn(async move {
+        let (mut stream, _) = listener.accept().await.unwrap();
+
+        let mut request = Vec::new();
+        while !request.ends_with(b"\r\n\r\n") {
+            request.push(stream.read_u8().await.unwrap());
+        }
+        stream
+            .write_all(b"HTTP/1.1 200 Connection Established\r\n\r\n")
+            .await
+            .unwrap();
+        pipe(stream, tracker).await;
+
+        String::from_utf8(request).unwrap()
+    });
+
+    (addr, handle)
+}
+
+async fn pipe(mut stream: TcpStream, tracker: SocketAddr) {
+    let mut tracker = TcpStream::connect(tracker).await.unwrap();
+
+    let _ = tokio::io::copy_bidirectional(&mut stream, &mut tracker).await;
+}
+
+fn tracker_addr(url: &Url) -> SocketAddr {
+    format!("{}:{}", url.serialize_host().unwrap(), url.port().unwrap())
+        .parse()
+        .unwrap()
+}
+
+async fn proxy_error(proxy: ProxyConfig, url: &Url) -> ProxyError {
+    let error: HttpTrackerError = HttpTrackerClient::new()
+        .with_proxy(proxy)
+        .announce(url, &super::announce_request())
+        .await
+        .unwrap_err();
+
+    match error.kind() {
+        &HttpTrackerErrorKind::ProxyFailure { error } => error,
+        kind => panic!("Unexpected Error {:?}", kind),
+    }
+}
+
+#[tokio::test]
+async fn positive_proxy_socks5_remote_hostname() {
+    let (url, tracker) = super::serve(vec![super::COMPACT]).await;
+    let (proxy_addr, proxy) = socks_proxy(tracker_addr(&url), None, 0x00).await;
+
+    // Only the proxy knows this hostname, looking it up locally would fail
+    let hostname_url = Url::parse(&format!(
+        "http://tracker.invalid:{}/announce",
+        url.port().unwrap()
+    ))
+    .unwrap();
+    let response = HttpTrackerClient::new()
+        .with_proxy(
+            ProxyConfig::new(ProxyKind::Socks5, proxy_addr).with_resolve_hostnames_remotely(true),
+        )
+        .announce(&hostname_url, &super::announce_request())
+        .await
+        .unwrap();
+    assert_eq!(response.interval(), 1800);
+    assert_eq!(tracker.await.unwrap().len(), 1);
+
+    let mut expected = vec![0x03, 15];
+    expected.extend_from_slice(b"tracker.invalid");
+    expected.extend_from_slice(&url.port().unwrap().to_be_bytes());
+    assert_eq!(proxy.await.unwrap(), expected);
+}
+
+#[tokio::test]
+async fn positive_proxy_socks5_auth() {
+    let (url, tracker) = super::serve(vec![super::COMPACT]).await;
+    let (proxy_addr, proxy) =
+        socks_proxy(tracker_addr(&url), Some((USERNAME, PASSWORD)), 0x00).await;
+
+    let response = HttpTrackerClient::new()
+        .with_proxy(
+            ProxyConfig::new(ProxyKind::Socks5, proxy_addr)
+                .with_auth(ProxyAuth::new(USERNAME, PASSWORD)),
+        )
+        .announce(&url, &super::announce_request())
+        .await
+        .unwrap();
+    assert_eq!(response.interval(), 1800);
+    assert_eq!(tracker.await.unwrap().len(), 1);
+
+    // Addresses are sent as is, even with remote resolution off
+    let mut expected = vec![0x01, 127, 0, 0, 1];
+    expected.extend_from_slice(&url.port().unwrap().to_be_bytes());
+    assert_eq!(proxy.await.unwrap(), expected);
+}
+
+#[tokio::test]
+async fn positive_proxy_http_connect() {
+    let (url, tracker) = super::serve(vec![super::COMPACT]).await;
+    let (proxy_addr, proxy) = http_proxy(tracker_addr(&url)).await;
+
+    let response = HttpTrackerClient::new()
+        .with_proxy(
+            ProxyConfig::new(ProxyKind::Http, proxy_addr)
+                .with_auth(ProxyAuth::new(USERNAME, PASSWORD)),
+        )
+        .announce(&url, &super::announce_request())
+        .await
+        .unwrap();
+    assert_eq!(response.interval(), 1800);
+    assert_eq!(tracker.await.unwrap().len(), 1);
+
+    let request = proxy.await.unwrap();
+    assert!(request.starts_with(&format!("CONNECT {} HTTP/1.1\r\n", tracker_addr(&url))));
+    assert!(request.contains("\r\nProxy-Authorization: Basic dXNlcjpwYXNz\r\n"));
+}
+
+#[tokio::test]
+async fn negative_proxy_socks5_auth_rejected() {
+    let (url, _tracker) = super::serve(vec![super::COMPACT]).await;
+    let (proxy_addr, _proxy) =
+        socks_proxy(tracker_addr(&url), Some((USERNAME, PASSWORD)), 0x00).await;
+
+    let proxy = ProxyConfig::new(ProxyKind::Socks5, proxy_addr)
+        .with_auth(ProxyAuth::new(USERNAME, "wrong"));
+    assert_eq!(proxy_error(proxy, &url).await, ProxyError::AuthRejected);
+}
+
+#[tokio::test]
+async fn negative_proxy_socks5_refused() {
+    let (url, _tracker) = super::serve(vec![super::COMPACT]).await;
+    // Connection refused by the destination host
+    let (proxy_addr, _proxy) = socks_proxy(tracker_addr(&url), None, 0x05).await;
+
+    let proxy = ProxyConfig::new(ProxyKind::Socks5, proxy_addr);
+    assert_eq!(proxy_error(proxy, &url).await, ProxyError::SocksReply(0x05));
+}
+
+#[tokio::test]
+async fn negative_proxy_unreachable() {
+    let (url, _tracker) = super::serve(vec![super::COMPACT]).await;
+    let closed_addr = TcpListener::bind("127.0.0.1:0")
+        .await
+        .unwrap()
+        .local_addr()
+        .unwrap();
+
+    for &kind in &[ProxyKind::Socks5, ProxyKind::Http] {
+        let proxy = ProxyConfig::new(kind, closed_addr);
+
+        assert_eq!(
+            proxy_error(proxy, &url).await,
+            ProxyError::Connection(io::ErrorKind::ConnectionRefused)
+        );
+    }
+}
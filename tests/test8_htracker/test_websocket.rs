@@ -0,0 +1,296 @@
+use bittorrent_protocol::htracker::error::HttpTrackerErrorKind;
+use bittorrent_protocol::htracker::{
+    AnnounceEvent, AnnounceRequest, ClientState, DesiredPeers, InfoHash, PeerId, TrackerTransport,
+    TrackerUrl, WsTrackerClient, WsTrackerEvent,
+};
+use bittorrent_protocol::util::sha::ShaHashBuilder;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+use tokio::time;
+
+const BASE64_ALPHABET: &'static [u8] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Info hash of 0x41 bytes, as a binary string.
+const INFO_HASH: &'static str = "AAAAAAAAAAAAAAAAAAAA";
+const OFFER_PEER_ID: &'static str = "-WW0100-abcdefghijkl";
+
+fn announce_request(event: AnnounceEvent) -> AnnounceRequest {
+    AnnounceRequest::new(
+        [0x41u8; 20].into(),
+        PeerId::from(*b"-BP0300-123456789012"),
+        ClientState::new(100, 200, 300, event),
+        0,
+        DesiredPeers::Specified(5),
+        6881,
+    )
+}
+
+fn client(events: mpsc::UnboundedSender<WsTrackerEvent>) -> WsTrackerClient {
+    WsTrackerClient::new()
+        .with_timeout(Duration::from_secs(5))
+        .with_retry_delay(Duration::from_millis(50))
+        .with_events(events)
+}
+
+async fn bind() -> (TcpListener, TrackerUrl) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = TrackerUrl::parse(&format!("ws://{}/", listener.local_addr().unwrap())).unwrap();
+
+    (listener, url)
+}
+
+/// Accept a connection and answer its websocket handshake.
+async fn accept(listener: &TcpListener) -> TcpStream {
+    let (mut stream, _) = listener.accept().await.unwrap();
+
+    let mut request = Vec::new();
+    while !request.ends_with(b"\r\n\r\n") {
+        let mut byte = [0u8; 1];
+        stream.read_exact(&mut byte).await.unwrap();
+        request.push(byte[0]);
+    }
+    let request = String::from_utf8(request).unwrap();
+    let key = request
+        .lines()
+        .find(|line| line.starts_with("Sec-WebSocket-Key: "))
+        .unwrap()
+        .trim_start_matches("Sec-WebSocket-Key: ");
+    let accept = ShaHashBuilder::new()
+        .add_bytes(key.as_bytes())
+        .add_bytes(b"258EAFA5-E914-47DA-95CA-C5AB0DC85B11")
+        .build();
+
+    stream
+        .write_all(
+            format!(
+                "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\n\
+                 Connection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n\r\n",
+                base64(accept.as_ref())
+            )
+            .as_bytes(),
+        )
+        .await
+        .unwrap();
+
+    stream
+}
+
+/// Read a single masked text frame sent by the client.
+async fn recv_text(stream: &mut TcpStream) -> String {
+    let mut head = [0u8; 2];
+    stream.read_exact(&mut head).await.unwrap();
+    assert_eq!(head[0], 0x81);
+    assert_eq!(head[1] & 0x80, 0x80);
+
+    let length = match head[1] & 0x7F {
+        126 => stream.read_u16().await.unwrap() as usize,
+        127 => stream.read_u64().await.unwrap() as usize,
+        length => length as usize,
+    };
+    let mut mask = [0u8; 4];
+    stream.read_exact(&mut mask).await.unwrap();
+
+    let mut payload = vec![0u8; length];
+    stream.read_exact(&mut payload).await.unwrap();
+    for (index, byte) in payload.iter_mut().enumerate() {
+        *byte ^= mask[index % 4];
+    }
+
+    String::from_utf8(payload).unwrap()
+}
+
+/// Send a single unmasked text frame to the client.
+async fn send_text(stream: &mut TcpStream, text: &str) {
+    let mut frame = vec![0x81];
+    if text.len() < 126 {
+        frame.push(text.len() as u8);
+    } else {
+        frame.push(126);
+        frame.extend_from_slice(&(text.len() as u16).to_be_bytes());
+    }
+    frame.extend_from_slice(text.as_bytes());
+
+    stream.write_all(&frame).await.unwrap();
+}
+
+fn base64(bytes: &[u8]) -> String {
+    let mut encoded = String::new();
+
+    for chunk in bytes.chunks(3) {
+        let group = (chunk[0] as u32) << 16
+            | (*chunk.get(1).unwrap_or(&0) as u32) << 8
+            | *chunk.get(2).unwrap_or(&0) as u32;
+
+        for index in 0..4 {
+            if index <= chunk.len() {
+                encoded
+                    .push(BASE64_ALPHABET[((group >> (18 - index * 6)) & 0x3F) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+
+    encoded
+}
+
+fn response(interval: u64) -> String {
+    format!(
+        r#"{{"action":"announce","interval":{},"info_hash":"{}","complete":2,"incomplete":3}}"#,
+        interval, INFO_HASH
+    )
+}
+
+#[tokio::test]
+async fn positive_ws_announce_and_offer() {
+    let (listener, url) = bind().await;
+    let (events_send, mut events) = mpsc::unbounded_channel();
+    let client = client(events_send);
+
+    let tracker = tokio::spawn(async move {
+        let mut stream = accept(&listener).await;
+        let announce = recv_text(&mut stream).await;
+
+        send_text(&mut stream, &response(120)).await;
+        send_text(
+            &mut stream,
+            &format!(
+                r#"{{"action":"announce","offer":{{"type":"offer","sdp":"v=0"}},"offer_id":"offer","peer_id":"{}","info_hash":"{}"}}"#,
+                OFFER_PEER_ID, INFO_HASH
+            ),
+        )
+        .await;
+
+        (announce, stream)
+    });
+
+    let response = client
+        .send_announce(&url, &announce_request(AnnounceEvent::Started))
+        .await
+        .unwrap();
+    assert_eq!(response.interval(), 120);
+    assert_eq!(response.complete(), Some(2));
+    assert_eq!(response.incomplete(), Some(3));
+    assert!(response.peers().is_empty());
+
+    let (announce, _stream) = tracker.await.unwrap();
+    assert!(announce.contains(r#""action":"announce""#));
+    assert!(announce.contains(&format!(r#""info_hash":"{}""#, INFO_HASH)));
+    assert!(announce.contains(r#""peer_id":"-BP0300-123456789012""#));
+    assert!(announce.contains(r#""event":"started""#));
+    assert!(announce.contains(r#""numwant":5"#));
+    assert!(announce.contains(r#""left":200"#));
+
+    match events.recv().await.unwrap() {
+        WsTrackerEvent::Announce(hash, announced) => {
+            assert_eq!(hash, InfoHash::from([0x41u8; 20]));
+            assert_eq!(announced, response);
+        }
+        event => panic!("Unexpected Event {:?}", event),
+    }
+    match events.recv().await.unwrap() {
+        WsTrackerEvent::Offer(offer) => {
+            assert_eq!(offer.info_hash(), InfoHash::from([0x41u8; 20]));
+            assert_eq!(offer.peer_id(), PeerId::from(*b"-WW0100-abcdefghijkl"));
+            assert_eq!(offer.offer_id(), b"offer");
+            assert_eq!(offer.sdp(), "v=0");
+        }
+        event => panic!("Unexpected Event {:?}", event),
+    }
+}
+
+#[tokio::test]
+async fn positive_ws_reconnects_after_drop() {
+    let (listener, url) = bind().await;
+    let (events_send, mut events) = mpsc::unbounded_channel();
+    let client = client(events_send);
+
+    let tracker = tokio::spawn(async move {
+        // First connection goes away right after answering
+        let mut stream = accept(&listener).await;
+        recv_text(&mut stream).await;
+        send_text(&mut stream, &response(120)).await;
+        stream.shutdown().await.unwrap();
+        drop(stream);
+
+        let mut stream = accept(&listener).await;
+        let reannounce = recv_text(&mut stream).await;
+        send_text(&mut stream, &response(120)).await;
+
+        (reannounce, stream)
+    });
+
+    client
+        .send_announce(&url, &announce_request(AnnounceEvent::Started))
+        .await
+        .unwrap();
+
+    let (reannounce, _stream) = time::timeout(Duration::from_secs(5), tracker)
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(reannounce.contains(&format!(r#""info_hash":"{}""#, INFO_HASH)));
+    assert!(!reannounce.contains(r#""event""#));
+
+    for _ in 0..2 {
+        match events.recv().await.unwrap() {
+            WsTrackerEvent::Announce(..) => (),
+            event => panic!("Unexpected Event {:?}", event),
+        }
+    }
+}
+
+#[tokio::test]
+async fn negative_ws_failure_reason() {
+    let (listener, url) = bind().await;
+    let (events_send, _events) = mpsc::unbounded_channel();
+    let client = client(events_send);
+
+    tokio::spawn(async move {
+        let mut stream = accept(&listener).await;
+        recv_text(&mut stream).await;
+        send_text(
+            &mut stream,
+            &format!(
+                r#"{{"action":"announce","failure reason":"torrent not allowed","info_hash":"{}"}}"#,
+                INFO_HASH
+            ),
+        )
+        .await;
+
+        stream
+    });
+
+    let error = client
+        .send_announce(&url, &announce_request(AnnounceEvent::Started))
+        .await
+        .unwrap_err();
+    match error.kind() {
+        &HttpTrackerErrorKind::TrackerFailure { ref reason } => {
+            assert_eq!(reason, "torrent not allowed")
+        }
+        kind => panic!("Unexpected Error {:?}", kind),
+    }
+}
+
+#[tokio::test]
+async fn negative_ws_unsupported_trackers() {
+    let (events_send, _events) = mpsc::unbounded_channel();
+    let client = client(events_send);
+    let request = announce_request(AnnounceEvent::Started);
+
+    for tracker in &["http://127.0.0.1:1/announce", "wss://127.0.0.1:1/"] {
+        let error = client
+            .send_announce(&TrackerUrl::parse(tracker).unwrap(), &request)
+            .await
+            .unwrap_err();
+
+        match error.kind() {
+            &HttpTrackerErrorKind::UnsupportedScheme { .. } => (),
+            kind => panic!("Unexpected Error {:?}", kind),
+        }
+    }
+}
@@ -0,0 +1,171 @@
+use bittorrent_protocol::dht::{DhtBuilder, DhtEvent, Handshaker, MainlineDht, SearchEvent};
+use bittorrent_protocol::util::bt::{InfoHash, PeerId};
+use futures::stream::StreamExt;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::mpsc::{self, Sender};
+use std::time::Duration;
+
+mod test_bootstrap;
+mod test_ipv6;
+mod test_item;
+mod test_krpc;
+mod test_node_id;
+mod test_port;
+mod test_rate_limit;
+mod test_read_only;
+mod test_sample;
+mod test_search;
+mod test_state;
+
+#[test]
+pub fn my_print() {
+    println!("test dht");
+}
+
+/// Number of nodes in a test network, enough for the bootstrap to not retry.
+const NUM_NODES: u16 = 12;
+
+#[derive(Clone)]
+struct MockHandshaker {
+    port: u16,
+    send: Sender<(InfoHash, SocketAddr)>,
+}
+
+impl Handshaker for MockHandshaker {
+    type Metadata = ();
+
+    fn id(&self) -> PeerId {
+        [0u8; 20].into()
+    }
+
+    fn port(&self) -> u16 {
+        self.port
+    }
+
+    fn connect(&mut self, _: Option<PeerId>, hash: InfoHash, addr: SocketAddr) {
+        let _ = self.send.send((hash, addr));
+    }
+
+    fn metadata(&mut self, _: ()) {}
+}
+
+/// Node in a loopback network of DHTs.
+struct TestNode {
+    addr: SocketAddr,
+    handshake_port: u16,
+    connects: mpsc::Receiver<(InfoHash, SocketAddr)>,
+    dht: MainlineDht,
+}
+
+/// Starts a DHT on the given port, leaving the handshaker connects unused.
+fn start_node(builder: DhtBuilder, port: u16) -> MainlineDht {
+    let (send, _recv) = mpsc::channel();
+
+    builder
+        .set_source_addr(([127, 0, 0, 1], port).into())
+        .set_read_only(false)
+        .start_mainline(MockHandshaker {
+            port: port + 1000,
+            send: send,
+        })
+        .unwrap()
+}
+
+/// Starts a network of nodes on consecutive ports, each knowing about every other node,
+/// and waits for all of them to finish bootstrapping.
+///
+/// The builder of each node is passed through configure along with its index.
+fn start_network<F>(base_port: u16, configure: F) -> Vec<TestNode>
+where
+    F: Fn(u16, DhtBuilder) -> DhtBuilder,
+{
+    start_network_at(Ipv4Addr::LOCALHOST.into(), base_port, configure)
+}
+
+/// Starts a network of nodes like `start_network`, with the nodes listening on the given ip.
+fn start_network_at<F>(ip: IpAddr, base_port: u16, configure: F) -> Vec<TestNode>
+where
+    F: Fn(u16, DhtBuilder) -> DhtBuilder,
+{
+    start_network_on(base_port, |index| (ip, base_port + index).into(), configure)
+}
+
+/// Starts a network of nodes like `start_network`, each node listening on the address that
+/// addr gives for its index.
+fn start_network_on<A, F>(base_port: u16, addr: A, configure: F) -> Vec<TestNode>
+where
+    A: Fn(u16) -> SocketAddr,
+    F: Fn(u16, DhtBuilder) -> DhtBuilder,
+{
+    let (nodes, events): (Vec<TestNode>, Vec<_>) = (0..NUM_NODES)
+        .map(|index| {
+            let (send, recv) = mpsc::channel();
+            let handshake_port = base_port + 1000 + index;
+
+            let builder = (0..NUM_NODES).filter(|&other| other != index).fold(
+                DhtBuilder::with_node(addr((index + 1) % NUM_NODES)),
+                |builder, other| builder.add_node(addr(other)),
+            );
+            let builder = builder.set_source_addr(addr(index)).set_read_only(false);
+            let dht = configure(index, builder)
+                .start_mainline(MockHandshaker {
+                    port: handshake_port,
+                    send: send,
+                })
+                .unwrap();
+            let events = dht.events();
+
+            let node = TestNode {
+                addr: addr(index),
+                handshake_port: handshake_port,
+                connects: recv,
+                dht: dht,
+            };
+
+            (node, events)
+        })
+        .unzip();
+
+    for events in events {
+        loop {
+            match events.recv_timeout(Duration::from_secs(30)).unwrap() {
+                DhtEvent::BootstrapCompleted { .. } => break,
+                DhtEvent::ShuttingDown(cause) => panic!("Node Shut Down: {:?}", cause),
+                _ => (),
+            }
+        }
+    }
+
+    nodes
+}
+
+/// Runs a search to completion, returning the peers found and the final summary.
+async fn run_search(
+    node: &TestNode,
+    hash: InfoHash,
+    announce: bool,
+) -> (Vec<SocketAddr>, usize, usize) {
+    let events: Vec<SearchEvent> = tokio::time::timeout(
+        Duration::from_secs(30),
+        node.dht.search_peers(hash, announce).collect(),
+    )
+    .await
+    .unwrap();
+
+    let (last, peers) = events.split_last().expect("Search Ended Without A Summary");
+    let peers = peers
+        .iter()
+        .map(|event| match event {
+            &SearchEvent::Peer(addr) => addr,
+            event => panic!("Unexpected Event {:?}", event),
+        })
+        .collect();
+
+    match last {
+        &SearchEvent::SearchCompleted {
+            nodes_contacted,
+            peers_found,
+        } => (peers, nodes_contacted, peers_found),
+        event => panic!("Unexpected Final Event {:?}", event),
+    }
+}
@@ -0,0 +1,104 @@
+use std::net::SocketAddr;
+use std::sync::mpsc::Receiver;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use bittorrent_protocol::dht::{DhtBuilder, DhtEvent, MainlineDht};
+
+use super::start_network;
+
+const ADD_NODE_TIMEOUT_MS: u64 = 10_000;
+
+/// Waits for the next bootstrap to complete, returning the number of good nodes it ended with.
+fn wait_bootstrap_completed(events: &Receiver<DhtEvent>) -> usize {
+    loop {
+        match events.recv_timeout(Duration::from_secs(30)).unwrap() {
+            DhtEvent::BootstrapCompleted { nodes } => return nodes,
+            DhtEvent::ShuttingDown(cause) => panic!("Node Shut Down: {:?}", cause),
+            _ => (),
+        }
+    }
+}
+
+fn has_node(dht: &MainlineDht, addr: SocketAddr) -> bool {
+    dht.save_state()
+        .node_addrs()
+        .any(|node_addr| node_addr == addr)
+}
+
+/// Waits for the given node to make it into our routing table, returning whether it did.
+fn wait_has_node(dht: &MainlineDht, addr: SocketAddr) -> bool {
+    let deadline = Instant::now() + Duration::from_millis(ADD_NODE_TIMEOUT_MS);
+
+    while !has_node(dht, addr) {
+        if Instant::now() >= deadline {
+            return false;
+        }
+        thread::sleep(Duration::from_millis(50));
+    }
+
+    true
+}
+
+#[test]
+fn positive_bootstrap_from_host_strings() {
+    let nodes = start_network(5940, |_, builder| builder);
+
+    // Invalid hosts are given up on without holding back the valid ones
+    let dht = super::start_node(
+        DhtBuilder::with_bootstrap_nodes(vec![
+            format!("localhost:{}", nodes[0].addr.port()),
+            "missing.port.invalid".to_owned(),
+        ]),
+        5952,
+    );
+    let events = dht.events();
+
+    assert!(wait_bootstrap_completed(&events) != 0);
+}
+
+#[test]
+fn positive_watchdog_rebootstraps_starved_table() {
+    let nodes = start_network(5960, |_, builder| builder);
+
+    // Our table can never hold that many good nodes, so it is starved right after bootstrapping
+    let dht = super::start_node(
+        DhtBuilder::with_node(nodes[0].addr)
+            .set_rebootstrap_threshold(1000, Duration::from_millis(500)),
+        5972,
+    );
+    let events = dht.events();
+
+    assert!(wait_bootstrap_completed(&events) != 0);
+    loop {
+        match events.recv_timeout(Duration::from_secs(10)).unwrap() {
+            DhtEvent::BootstrapStarted => break,
+            DhtEvent::ShuttingDown(cause) => panic!("Node Shut Down: {:?}", cause),
+            _ => (),
+        }
+    }
+    assert!(wait_bootstrap_completed(&events) != 0);
+}
+
+#[test]
+fn positive_add_node() {
+    let nodes = start_network(5980, |_, builder| builder);
+
+    // Nothing listens on the bootstrap node, the added node is all we have to go on
+    let dht = super::start_node(DhtBuilder::with_node(([127, 0, 0, 1], 5999).into()), 5992);
+    let events = dht.events();
+    dht.add_node(nodes[0].addr);
+
+    assert!(wait_bootstrap_completed(&events) != 0);
+    assert!(wait_has_node(&dht, nodes[0].addr));
+
+    // Nodes never send their own address along, so we only hear of this one when added
+    let other_addr = ([127, 0, 0, 1], 5993).into();
+    let other = super::start_node(DhtBuilder::with_node(nodes[1].addr), 5993);
+    wait_bootstrap_completed(&other.events());
+    assert!(!has_node(&dht, other_addr));
+
+    dht.add_node(other_addr);
+
+    assert!(wait_has_node(&dht, other_addr));
+}
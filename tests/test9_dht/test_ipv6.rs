@@ -0,0 +1,59 @@
+use std::collections::HashSet;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::thread;
+use std::time::Duration;
+
+use bittorrent_protocol::dht::IpStack;
+use bittorrent_protocol::util::bt::InfoHash;
+
+use super::{run_search, start_network, start_network_at, NUM_NODES};
+
+const ANNOUNCE_PROPAGATION_MS: u64 = 500;
+
+#[tokio::test]
+async fn positive_search_ipv6_network() {
+    let nodes = start_network_at(Ipv6Addr::LOCALHOST.into(), 5840, |_, builder| {
+        builder.set_ip_stack(IpStack::V6Only)
+    });
+    let hash = InfoHash::from_bytes(b"positive_search_ipv6_network");
+
+    run_search(&nodes[3], hash, true).await;
+    thread::sleep(Duration::from_millis(ANNOUNCE_PROPAGATION_MS));
+
+    let expected: SocketAddr = (Ipv6Addr::LOCALHOST, nodes[3].handshake_port).into();
+    let (peers, nodes_contacted, peers_found) = run_search(&nodes[8], hash, false).await;
+    assert_eq!(peers, vec![expected]);
+    assert!(nodes_contacted > 0);
+    assert_eq!(peers_found, 1);
+}
+
+#[tokio::test]
+async fn positive_search_dual_stack() {
+    let v6_addr = |index: u16| -> SocketAddr { (Ipv6Addr::LOCALHOST, 5860 + index).into() };
+    let nodes = start_network(5860, |index, builder| {
+        let builder = builder
+            .set_ip_stack(IpStack::DualStack)
+            .set_source_addr(v6_addr(index));
+
+        (0..NUM_NODES)
+            .filter(|&other| other != index)
+            .fold(builder, |builder, other| builder.add_node(v6_addr(other)))
+    });
+    let hash = InfoHash::from_bytes(b"positive_search_dual_stack");
+
+    run_search(&nodes[3], hash, true).await;
+    thread::sleep(Duration::from_millis(ANNOUNCE_PROPAGATION_MS));
+
+    // Announced over both address families, found on both DHTs
+    let port = nodes[3].handshake_port;
+    let expected: HashSet<SocketAddr> = vec![
+        (IpAddr::from(Ipv4Addr::LOCALHOST), port).into(),
+        (IpAddr::from(Ipv6Addr::LOCALHOST), port).into(),
+    ]
+    .into_iter()
+    .collect();
+    let (peers, nodes_contacted, peers_found) = run_search(&nodes[8], hash, false).await;
+    assert_eq!(peers.into_iter().collect::<HashSet<_>>(), expected);
+    assert!(nodes_contacted > 0);
+    assert_eq!(peers_found, 2);
+}
@@ -0,0 +1,69 @@
+use std::sync::mpsc::Receiver;
+use std::time::Duration;
+
+use bittorrent_protocol::dht::{DhtEvent, ImmutableItem, MutableItem};
+use bittorrent_protocol::util::bt::InfoHash;
+
+use super::start_network;
+
+const SEED: [u8; 32] = [7u8; 32];
+
+/// Waits for the put of the given target to finish, returning the number of nodes storing it.
+fn wait_put(events: &Receiver<DhtEvent>, target: InfoHash) -> usize {
+    loop {
+        match events.recv_timeout(Duration::from_secs(30)).unwrap() {
+            DhtEvent::PutCompleted(hash, nodes_stored) if hash == target => return nodes_stored,
+            DhtEvent::ShuttingDown(cause) => panic!("Node Shut Down: {:?}", cause),
+            _ => (),
+        }
+    }
+}
+
+#[tokio::test]
+async fn positive_item_put_then_get_immutable() {
+    let nodes = start_network(5700, |_, builder| builder);
+    let events = nodes[1].dht.events();
+
+    let item = ImmutableItem::new(&ben_bytes!("Hello World!")).unwrap();
+    let target = nodes[1].dht.put_immutable(item.clone());
+    assert!(wait_put(&events, target) > 0);
+
+    let found = tokio::time::timeout(Duration::from_secs(30), nodes[10].dht.get_immutable(target))
+        .await
+        .unwrap();
+    assert_eq!(found, Some(item));
+}
+
+#[tokio::test]
+async fn positive_item_put_then_get_mutable() {
+    let nodes = start_network(5720, |_, builder| builder);
+    let events = nodes[2].dht.events();
+
+    let first = MutableItem::sign(&SEED, b"salt", 1, &ben_bytes!("first")).unwrap();
+    let target = nodes[2].dht.put_mutable(first.clone(), None);
+    assert!(wait_put(&events, target) > 0);
+
+    // Replacing the item requires knowing its current sequence number
+    let second = MutableItem::sign(&SEED, b"salt", 2, &ben_bytes!("second")).unwrap();
+    assert_eq!(nodes[2].dht.put_mutable(second.clone(), Some(1)), target);
+    assert!(wait_put(&events, target) > 0);
+
+    let found = tokio::time::timeout(
+        Duration::from_secs(30),
+        nodes[9].dht.get_mutable(*first.public_key(), b"salt"),
+    )
+    .await
+    .unwrap();
+    assert_eq!(found, Some(second));
+}
+
+#[tokio::test]
+async fn negative_item_get_missing() {
+    let nodes = start_network(5740, |_, builder| builder);
+
+    let target = InfoHash::from_bytes(b"negative_item_get_missing");
+    let found = tokio::time::timeout(Duration::from_secs(30), nodes[5].dht.get_immutable(target))
+        .await
+        .unwrap();
+    assert_eq!(found, None);
+}
@@ -0,0 +1,151 @@
+use std::net::{SocketAddr, UdpSocket};
+use std::thread;
+use std::time::Duration;
+
+use bittorrent_protocol::dht::DhtBuilder;
+
+const REPLY_TIMEOUT_MS: u64 = 500;
+
+const NUM_FUZZ_ROUNDS: u8 = 3;
+const NUM_FUZZ_IPS: u8 = 50;
+// Stays below the number of malformed messages an ip can send before being banned
+const NUM_FUZZ_PACKETS_PER_IP: usize = 10;
+const FUZZ_IP_DELAY_MS: u64 = 10;
+
+/// Encoded ping query with the transaction id "aa".
+const PING_QUERY: &'static [u8] = b"d1:ad2:id20:abcdefghij0123456789e1:q4:ping1:t2:aa1:y1:qe";
+
+// Packets as sent by real clients, BEP 5 examples
+const CORPUS: &'static [&'static [u8]] = &[
+    PING_QUERY,
+    b"d1:rd2:id20:mnopqrstuvwxyz123456e1:t2:aa1:y1:re",
+    b"d1:ad2:id20:abcdefghij01234567896:target20:mnopqrstuvwxyz123456e1:q9:find_node1:t2:aa1:y1:qe",
+    b"d1:ad2:id20:abcdefghij01234567899:info_hash20:mnopqrstuvwxyz123456e1:q9:get_peers1:t2:aa1:y1:qe",
+    b"d1:rd2:id20:abcdefghij01234567895:token8:aoeusnth6:valuesl6:axje.u6:idhtnmee1:t2:aa1:y1:re",
+    b"d1:ad2:id20:abcdefghij012345678912:implied_porti1e9:info_hash20:mnopqrstuvwxyz1234564:porti6881e5:token8:aoeusnthe1:q13:announce_peer1:t2:aa1:y1:qe",
+    b"d1:eli201e23:A Generic Error Ocurrede1:t2:aa1:y1:ee",
+];
+
+/// Builder for a node on the given port that answers every query it is sent.
+fn unlimited_builder(port: u16) -> DhtBuilder {
+    // Nothing listens on the bootstrap node, we only care about packets sent to us
+    DhtBuilder::with_node(([127, 0, 0, 1], port + 1).into()).set_query_rate_limit(0, 0)
+}
+
+/// Sends the packet to the node at the given address from the given ip, returning its reply if any.
+fn query(ip: [u8; 4], addr: SocketAddr, packet: &[u8]) -> Option<Vec<u8>> {
+    let socket = UdpSocket::bind(SocketAddr::from((ip, 0))).unwrap();
+    socket
+        .set_read_timeout(Some(Duration::from_millis(REPLY_TIMEOUT_MS)))
+        .unwrap();
+    socket.send_to(packet, addr).unwrap();
+
+    let mut buffer = vec![0u8; 1500];
+    socket.recv_from(&mut buffer).ok().map(|(size, _)| {
+        buffer.truncate(size);
+        buffer
+    })
+}
+
+fn contains(bytes: &[u8], part: &[u8]) -> bool {
+    bytes.windows(part.len()).any(|window| window == part)
+}
+
+/// Deterministic xorshift generator, keeps failures reproducible.
+struct Mutator(u64);
+
+impl Mutator {
+    fn next(&mut self) -> usize {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+
+        self.0 as usize
+    }
+
+    fn mutate(&mut self, packet: &[u8]) -> Vec<u8> {
+        let mut bytes = packet.to_vec();
+
+        for _ in 0..(1 + self.next() % 4) {
+            let pos = self.next() % (bytes.len() + 1);
+
+            match self.next() % 4 {
+                0 => bytes.truncate(pos),
+                1 if pos < bytes.len() => bytes[pos] ^= 1 << (self.next() % 8),
+                2 if pos < bytes.len() => {
+                    bytes.remove(pos);
+                }
+                _ => bytes.insert(pos, b"0123456789:deil"[self.next() % 15]),
+            }
+        }
+
+        bytes
+    }
+}
+
+#[test]
+fn positive_krpc_errors_answered_or_dropped() {
+    let addr = ([127, 0, 0, 1], 5932).into();
+    let dht = super::start_node(unlimited_builder(5932), 5932);
+    let ip = [127, 0, 0, 1];
+
+    // Queries we can make out get an error back with the matching code
+    let method_unknown = b"d1:ad2:id20:abcdefghij0123456789e1:q4:pong1:t2:aa1:y1:qe";
+    let reply = query(ip, addr, method_unknown).unwrap();
+    assert!(contains(&reply, b"1:eli204e") && contains(&reply, b"1:t2:aa"));
+
+    let missing_args = b"d1:ad6:target20:mnopqrstuvwxyz123456e1:q9:find_node1:t2:aa1:y1:qe";
+    let reply = query(ip, addr, missing_args).unwrap();
+    assert!(contains(&reply, b"1:eli203e"));
+
+    let wrong_type_method = b"d1:ad2:id20:abcdefghij0123456789e1:qi5e1:t2:aa1:y1:qe";
+    let reply = query(ip, addr, wrong_type_method).unwrap();
+    assert!(contains(&reply, b"1:eli203e"));
+
+    // Anything else is dropped
+    let truncated = b"d1:ad2:id20:abcdefghij0123456789e1:q4:ping1:t2:a";
+    assert_eq!(query(ip, addr, truncated), None);
+
+    let huge_trans_id = format!(
+        "d1:ad2:id20:abcdefghij0123456789e1:q4:ping1:t64:{}1:y1:qe",
+        "a".repeat(64)
+    );
+    assert_eq!(query(ip, addr, huge_trans_id.as_bytes()), None);
+
+    let unsolicited = b"d1:rd2:id20:mnopqrstuvwxyz123456e1:t2:zz1:y1:re";
+    assert_eq!(query(ip, addr, unsolicited), None);
+
+    assert_eq!(dht.status().malformed_dropped(), 3);
+    assert!(query(ip, addr, PING_QUERY).is_some());
+}
+
+#[test]
+fn positive_mutated_corpus_survives() {
+    let addr = ([127, 0, 0, 1], 5934).into();
+    let dht = super::start_node(unlimited_builder(5934), 5934);
+    let mut mutator = Mutator(0x9e37_79b9_7f4a_7c15);
+
+    for round in 1..=NUM_FUZZ_ROUNDS {
+        for last in 1..=NUM_FUZZ_IPS {
+            let socket = UdpSocket::bind(SocketAddr::from(([127, 1, round, last], 0))).unwrap();
+
+            for index in 0..NUM_FUZZ_PACKETS_PER_IP {
+                let packet = mutator.mutate(CORPUS[index % CORPUS.len()]);
+                socket.send_to(&packet, addr).unwrap();
+            }
+
+            // Give the node time to keep up, packets dropped by the os never reach it
+            thread::sleep(Duration::from_millis(FUZZ_IP_DELAY_MS));
+        }
+
+        // Nothing we were sent sticks around after answering it
+        assert!(query([127, 0, 0, 1], addr, PING_QUERY).is_some());
+
+        let status = dht.status();
+        assert_eq!(status.infohashes_tracked(), 0);
+        assert_eq!(status.peers_stored(), 0);
+        assert_eq!(dht.save_state().node_addrs().count(), 0);
+    }
+
+    assert!(dht.status().malformed_dropped() > 0);
+}
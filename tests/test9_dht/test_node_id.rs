@@ -0,0 +1,63 @@
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+use bittorrent_protocol::dht::{DhtBuilder, NodeIdPolicy};
+
+use super::{start_network_on, MockHandshaker};
+
+#[test]
+fn positive_status_external_addr() {
+    let (send, _recv) = mpsc::channel();
+    let external_ip: IpAddr = "124.31.75.21".parse().unwrap();
+
+    let dht = DhtBuilder::with_node("127.0.0.1:5699".parse().unwrap())
+        .set_source_addr("127.0.0.1:5698".parse().unwrap())
+        .set_external_addr(SocketAddr::new(external_ip, 6881))
+        .start_mainline(MockHandshaker {
+            port: 6698,
+            send: send,
+        })
+        .unwrap();
+
+    let status = dht.status();
+    assert_eq!(status.external_ip(), Some(external_ip));
+    assert!(status.is_node_id_compliant());
+    assert!(status.node_id().is_compliant_for_ip(external_ip));
+}
+
+#[test]
+fn positive_node_id_regenerated_for_voted_ip() {
+    let external_ip: IpAddr = "124.31.75.21".parse().unwrap();
+
+    // Votes are counted per voter ip, so each node gets a loopback address of its own
+    let addr = |index: u16| SocketAddr::new(Ipv4Addr::new(127, 0, 0, index as u8 + 1).into(), 5680);
+
+    // Every node gets told it is at its loopback address, even the node we told otherwise
+    let nodes = start_network_on(5680, addr, |index, builder| {
+        let builder = builder.set_node_id_policy(NodeIdPolicy::RequireCompliant);
+
+        if index == 0 {
+            builder.set_external_addr(SocketAddr::new(external_ip, 6881))
+        } else {
+            builder
+        }
+    });
+
+    for node in nodes.iter() {
+        let local_ip = node.addr.ip();
+        let mut status = node.dht.status();
+        for _ in 0..50 {
+            if status.external_ip() == Some(local_ip) {
+                break;
+            }
+            thread::sleep(Duration::from_millis(100));
+            status = node.dht.status();
+        }
+
+        assert_eq!(status.external_ip(), Some(local_ip));
+        assert!(status.is_node_id_compliant());
+        assert!(!status.node_id().is_compliant_for_ip(external_ip));
+    }
+}
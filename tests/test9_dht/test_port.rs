@@ -0,0 +1,91 @@
+use std::net::SocketAddr;
+use std::sync::mpsc;
+use std::time::Duration;
+
+use bittorrent_protocol::dht::{DhtBuilder, DhtEvent};
+use bittorrent_protocol::handshake::transports::MockSocket;
+use bittorrent_protocol::handshake::{Extension, Extensions};
+use bittorrent_protocol::peer::messages::{BitFieldMessage, PeerWireProtocolMessage};
+use bittorrent_protocol::peer::{IPeerManagerMessage, PeerInfo, PeerManagerBuilder};
+
+use super::MockHandshaker;
+
+#[test]
+fn positive_port_message_adds_dht_node() {
+    let (send, _recv) = mpsc::channel();
+    let addr: SocketAddr = ([127, 0, 0, 1], 5994).into();
+    // Nothing listens on the bootstrap node, the peer is all we have to go on
+    let dht = DhtBuilder::with_node(([127, 0, 0, 1], 5999).into())
+        .set_source_addr(addr)
+        .set_read_only(false)
+        .start_mainline(MockHandshaker {
+            port: 6994,
+            send: send.clone(),
+        })
+        .unwrap();
+    let events = dht.events();
+
+    // Remote nodes do not add us when we query them, so the peer only knows about us
+    let peer_addr: SocketAddr = ([127, 0, 0, 1], 5995).into();
+    let _peer_dht = DhtBuilder::with_node(addr)
+        .set_source_addr(peer_addr)
+        .set_read_only(false)
+        .start_mainline(MockHandshaker {
+            port: 6995,
+            send: send,
+        })
+        .unwrap();
+
+    let (sock_ours, sock_peer) = MockSocket::pair();
+    let mut extensions = Extensions::new();
+    extensions.add(Extension::Dht);
+    let mut ours = PeerManagerBuilder::new().with_dht_port(addr.port()).build();
+    let mut peer = PeerManagerBuilder::new()
+        .with_dht_port(peer_addr.port())
+        .build();
+    let peer_info = PeerInfo::new(
+        ([127, 0, 0, 1], 6881).into(),
+        [1u8; 20].into(),
+        [0u8; 20].into(),
+        extensions,
+    );
+    let our_info = PeerInfo::new(
+        ([127, 0, 0, 1], 6882).into(),
+        [2u8; 20].into(),
+        [0u8; 20].into(),
+        extensions,
+    );
+    ours.send(IPeerManagerMessage::AddPeer(peer_info, sock_ours));
+    peer.send(IPeerManagerMessage::AddPeer(our_info, sock_peer));
+
+    let bitfield = PeerWireProtocolMessage::BitField(BitFieldMessage::new(vec![0xFF].into()));
+    peer.send(IPeerManagerMessage::SendMessage(our_info, 0, bitfield));
+
+    let node_addr = loop {
+        match ours.poll() {
+            Some(message) => {
+                if let Some(node_addr) = message.dht_node() {
+                    break node_addr;
+                }
+            }
+            None => panic!("Peer Manager Shut Down"),
+        }
+    };
+    assert_eq!(peer_addr, node_addr);
+    dht.add_node(node_addr);
+
+    loop {
+        match events.recv_timeout(Duration::from_secs(30)).unwrap() {
+            DhtEvent::BootstrapCompleted { nodes } => {
+                assert!(nodes != 0);
+                break;
+            }
+            DhtEvent::ShuttingDown(cause) => panic!("Node Shut Down: {:?}", cause),
+            _ => (),
+        }
+    }
+    assert!(dht
+        .save_state()
+        .node_addrs()
+        .any(|node_addr| node_addr == peer_addr));
+}
@@ -0,0 +1,75 @@
+use std::net::{SocketAddr, UdpSocket};
+use std::sync::mpsc;
+use std::time::Duration;
+
+use bittorrent_protocol::dht::DhtBuilder;
+
+use super::MockHandshaker;
+
+const QPS: u32 = 10;
+const BURST: u32 = 20;
+
+const NUM_FLOOD_QUERIES: usize = 300;
+
+const REPLY_TIMEOUT_MS: u64 = 500;
+
+/// Encoded ping query with the transaction id "aa".
+const PING_QUERY: &'static [u8] = b"d1:ad2:id20:abcdefghij0123456789e1:q4:ping1:t2:aa1:y1:qe";
+
+fn bind(ip: [u8; 4]) -> UdpSocket {
+    let socket = UdpSocket::bind(SocketAddr::from((ip, 0))).unwrap();
+    socket
+        .set_read_timeout(Some(Duration::from_millis(REPLY_TIMEOUT_MS)))
+        .unwrap();
+
+    socket
+}
+
+/// Receive replies on the socket until none arrive in time, returning how many arrived.
+fn count_replies(socket: &UdpSocket) -> usize {
+    let mut buffer = vec![0u8; 1500];
+    let mut num_replies = 0;
+
+    while socket.recv_from(&mut buffer).is_ok() {
+        num_replies += 1;
+    }
+
+    num_replies
+}
+
+#[test]
+fn positive_flood_dropped_and_banned() {
+    let (send, _recv) = mpsc::channel();
+    let addr: SocketAddr = ([127, 0, 0, 1], 5930).into();
+    // Nothing listens on the bootstrap node, we only care about queries sent to us
+    let dht = DhtBuilder::with_node(([127, 0, 0, 1], 5931).into())
+        .set_source_addr(addr)
+        .set_read_only(false)
+        .set_query_rate_limit(QPS, BURST)
+        .start_mainline(MockHandshaker {
+            port: 6930,
+            send: send,
+        })
+        .unwrap();
+
+    let flood = bind([127, 0, 0, 1]);
+    for _ in 0..NUM_FLOOD_QUERIES {
+        flood.send_to(PING_QUERY, addr).unwrap();
+    }
+
+    // Only the burst, plus whatever refilled while flooding, gets answered
+    let num_replies = count_replies(&flood);
+    assert!(num_replies >= BURST as usize && num_replies < NUM_FLOOD_QUERIES / 2);
+
+    // Flooding ip is banned now, other ips are unaffected
+    flood.send_to(PING_QUERY, addr).unwrap();
+    assert_eq!(count_replies(&flood), 0);
+
+    let other = bind([127, 0, 0, 2]);
+    other.send_to(PING_QUERY, addr).unwrap();
+    assert_eq!(count_replies(&other), 1);
+
+    let status = dht.status();
+    assert!(status.queries_dropped() > 0);
+    assert_eq!(status.ips_banned(), 1);
+}
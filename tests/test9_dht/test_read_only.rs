@@ -0,0 +1,126 @@
+use std::net::{SocketAddr, UdpSocket};
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+use bittorrent_protocol::dht::{DhtBuilder, DhtEvent, ReadOnlyQueries};
+use bittorrent_protocol::util::bt::InfoHash;
+
+use super::{run_search, start_network, MockHandshaker, TestNode};
+
+const ANNOUNCE_PROPAGATION_MS: u64 = 500;
+
+const PING_TIMEOUT_MS: u64 = 1000;
+
+/// Encoded ping query with the transaction id "aa".
+const PING_QUERY: &'static [u8] = b"d1:ad2:id20:abcdefghij0123456789e1:q4:ping1:t2:aa1:y1:qe";
+
+/// Sends a ping query to the node at the given address, returning its reply if any.
+fn ping(addr: SocketAddr) -> Option<Vec<u8>> {
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    socket
+        .set_read_timeout(Some(Duration::from_millis(PING_TIMEOUT_MS)))
+        .unwrap();
+    socket.send_to(PING_QUERY, addr).unwrap();
+
+    let mut buffer = vec![0u8; 1500];
+    socket.recv_from(&mut buffer).ok().map(|(size, _)| {
+        buffer.truncate(size);
+        buffer
+    })
+}
+
+fn contains(bytes: &[u8], part: &[u8]) -> bool {
+    bytes.windows(part.len()).any(|window| window == part)
+}
+
+#[tokio::test]
+async fn positive_read_only_not_in_tables() {
+    let nodes = start_network(5900, |_, builder| builder);
+    let hash = InfoHash::from_bytes(b"positive_read_only_not_in_tables");
+
+    let (send, recv) = mpsc::channel();
+    let addr = ([127, 0, 0, 1], 5920).into();
+    let builder = nodes
+        .iter()
+        .fold(DhtBuilder::with_node(nodes[0].addr), |builder, node| {
+            builder.add_node(node.addr)
+        });
+    let dht = builder
+        .set_source_addr(addr)
+        .set_read_only(true)
+        .set_read_only_queries(ReadOnlyQueries::Reject)
+        .start_mainline(MockHandshaker {
+            port: 6920,
+            send: send,
+        })
+        .unwrap();
+    let events = dht.events();
+    loop {
+        match events.recv_timeout(Duration::from_secs(30)).unwrap() {
+            DhtEvent::BootstrapCompleted { .. } => break,
+            DhtEvent::ShuttingDown(cause) => panic!("Node Shut Down: {:?}", cause),
+            _ => (),
+        }
+    }
+    let read_only = TestNode {
+        addr: addr,
+        handshake_port: 6920,
+        connects: recv,
+        dht: dht,
+    };
+
+    // Lookups still work while we are read only
+    run_search(&nodes[3], hash, true).await;
+    thread::sleep(Duration::from_millis(ANNOUNCE_PROPAGATION_MS));
+
+    let (peers, _, _) = run_search(&read_only, hash, false).await;
+    assert_eq!(
+        peers,
+        vec![([127, 0, 0, 1], nodes[3].handshake_port).into()]
+    );
+
+    for node in nodes.iter() {
+        assert!(node
+            .dht
+            .save_state()
+            .node_addrs()
+            .all(|node_addr| node_addr != read_only.addr));
+    }
+
+    // Read only nodes rejecting queries answer with an error instead of a response
+    let reply = ping(read_only.addr).unwrap();
+    assert!(contains(&reply, b"1:y1:e"));
+}
+
+#[tokio::test]
+async fn positive_read_only_toggle() {
+    let remote = UdpSocket::bind("127.0.0.1:0").unwrap();
+    remote
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+
+    let (send, _recv) = mpsc::channel();
+    let addr = ([127, 0, 0, 1], 5921).into();
+    let dht = DhtBuilder::with_node(remote.local_addr().unwrap())
+        .set_source_addr(addr)
+        .set_read_only(true)
+        .start_mainline(MockHandshaker {
+            port: 6921,
+            send: send,
+        })
+        .unwrap();
+
+    // Our bootstrap queries are marked as read only
+    let mut buffer = vec![0u8; 1500];
+    let (size, _) = remote.recv_from(&mut buffer).unwrap();
+    assert!(contains(&buffer[..size], b"2:roi1e"));
+
+    // Queries are dropped by default
+    assert_eq!(ping(addr), None);
+
+    dht.set_read_only(false);
+
+    let reply = ping(addr).unwrap();
+    assert!(contains(&reply, b"1:y1:r"));
+}
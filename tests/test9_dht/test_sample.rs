@@ -0,0 +1,89 @@
+use std::net::{SocketAddr, UdpSocket};
+use std::thread;
+use std::time::Duration;
+
+use bittorrent_protocol::dht::{SampleError, SampleResult};
+use bittorrent_protocol::util::bt::InfoHash;
+
+use super::{run_search, start_network, TestNode};
+
+const ANNOUNCE_PROPAGATION_MS: u64 = 500;
+
+async fn sample(node: &TestNode, addr: SocketAddr) -> Result<SampleResult, SampleError> {
+    let target = node.dht.status().node_id();
+
+    tokio::time::timeout(
+        Duration::from_secs(30),
+        node.dht.sample_infohashes(addr, target),
+    )
+    .await
+    .unwrap()
+}
+
+#[tokio::test]
+async fn positive_sample_announced_info_hash() {
+    let nodes = start_network(5780, |_, builder| builder);
+    let hash = InfoHash::from_bytes(b"positive_sample_announced_info_hash");
+
+    run_search(&nodes[4], hash, true).await;
+    thread::sleep(Duration::from_millis(ANNOUNCE_PROPAGATION_MS));
+
+    // Only the nodes closest to the info hash were announced to
+    let mut nodes_storing = 0;
+    for other in nodes[1..].iter() {
+        let result = sample(&nodes[0], other.addr).await.unwrap();
+
+        assert!(result.interval() > Duration::from_secs(0) || result.samples().is_empty());
+        assert!(!result.nodes().is_empty());
+        if result.samples() == &[hash][..] {
+            assert_eq!(result.num(), 1);
+            nodes_storing += 1;
+        } else {
+            assert!(result.samples().is_empty());
+            assert_eq!(result.num(), 0);
+        }
+    }
+    assert!(nodes_storing > 0);
+}
+
+#[tokio::test]
+async fn negative_sample_before_interval() {
+    let nodes = start_network(5800, |_, builder| builder);
+    let hash = InfoHash::from_bytes(b"negative_sample_before_interval");
+
+    run_search(&nodes[4], hash, true).await;
+    thread::sleep(Duration::from_millis(ANNOUNCE_PROPAGATION_MS));
+
+    // Find a node that told us to wait before sampling it again
+    let mut opt_waiting = None;
+    for other in nodes[1..].iter() {
+        if sample(&nodes[0], other.addr).await.unwrap().interval() > Duration::from_secs(0) {
+            opt_waiting = Some(other.addr);
+            break;
+        }
+    }
+    let waiting = opt_waiting.expect("No Node Stored The Announce");
+
+    match sample(&nodes[0], waiting).await {
+        Err(SampleError::TooEarly(remaining)) => assert!(remaining > Duration::from_secs(0)),
+        other => panic!("Unexpected Sample {:?}", other),
+    }
+
+    // Intervals only apply to the node that was told to wait
+    let requester = nodes[1..].iter().find(|node| node.addr != waiting).unwrap();
+    assert!(sample(requester, waiting).await.is_ok());
+}
+
+#[tokio::test]
+async fn negative_sample_unresponsive_node() {
+    let nodes = start_network(5820, |_, builder| builder);
+
+    // Bound so that no other node can take the port, but never read from
+    let silent = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let silent_addr = silent.local_addr().unwrap();
+
+    assert_eq!(
+        sample(&nodes[0], silent_addr).await,
+        Err(SampleError::Timeout)
+    );
+}
@@ -0,0 +1,122 @@
+use std::net::SocketAddr;
+use std::thread;
+use std::time::Duration;
+
+use bittorrent_protocol::dht::SearchEvent;
+use bittorrent_protocol::util::bt::InfoHash;
+use futures::stream::StreamExt;
+
+use super::{run_search, start_network};
+
+const ANNOUNCE_PROPAGATION_MS: u64 = 500;
+
+#[tokio::test]
+async fn positive_search_announce_then_find() {
+    let nodes = start_network(5600, |_, builder| builder);
+    let hash = InfoHash::from_bytes(b"positive_search_announce_then_find");
+
+    let (peers, nodes_contacted, peers_found) = run_search(&nodes[3], hash, true).await;
+    assert!(peers.is_empty());
+    assert!(nodes_contacted > 0);
+    assert_eq!(peers_found, 0);
+
+    thread::sleep(Duration::from_millis(ANNOUNCE_PROPAGATION_MS));
+
+    // Every node storing the announce tracks it in its status
+    let storing: Vec<_> = nodes
+        .iter()
+        .map(|node| node.dht.status())
+        .filter(|status| status.peers_stored() > 0)
+        .collect();
+    assert!(!storing.is_empty());
+    assert!(storing
+        .iter()
+        .all(|status| status.infohashes_tracked() == 1 && status.peers_stored() == 1));
+
+    let status = nodes[3].dht.status();
+    assert!(status.nodes() > 0);
+    assert_eq!(1, status.lookups_started());
+    assert_eq!(1, status.lookups_completed());
+
+    // Announced on multiple nodes, but only reported once
+    let expected: SocketAddr = ([127, 0, 0, 1], nodes[3].handshake_port).into();
+    let (peers, nodes_contacted, peers_found) = run_search(&nodes[8], hash, false).await;
+    assert_eq!(peers, vec![expected]);
+    assert!(nodes_contacted > 0);
+    assert_eq!(peers_found, 1);
+
+    // Peers are still handed to the handshaker
+    assert_eq!(nodes[8].connects.try_recv().unwrap(), (hash, expected));
+}
+
+#[tokio::test]
+async fn positive_search_implied_port() {
+    let nodes = start_network(5620, |_, builder| builder.set_implied_port(true));
+    let hash = InfoHash::from_bytes(b"positive_search_implied_port");
+
+    run_search(&nodes[2], hash, true).await;
+    thread::sleep(Duration::from_millis(ANNOUNCE_PROPAGATION_MS));
+
+    // Nodes stored the port we announced from, not our handshaker port
+    let (peers, _, _) = run_search(&nodes[9], hash, false).await;
+    assert_eq!(peers, vec![nodes[2].addr]);
+}
+
+#[tokio::test]
+async fn positive_search_unresponsive_nodes() {
+    let mut nodes = start_network(5640, |_, builder| builder);
+    let hash = InfoHash::from_bytes(b"positive_search_unresponsive_nodes");
+
+    // Half of the network goes away, queries to it have to time out
+    nodes.truncate(6);
+
+    let (peers, nodes_contacted, peers_found) = run_search(&nodes[0], hash, true).await;
+    assert!(peers.is_empty());
+    assert!(nodes_contacted > 0);
+    assert_eq!(peers_found, 0);
+}
+
+#[tokio::test]
+async fn positive_search_cancelled() {
+    let nodes = start_network(5660, |_, builder| builder);
+    let hash = InfoHash::from_bytes(b"positive_search_cancelled");
+
+    // Dropping the search should cancel the lookup before it announces
+    drop(nodes[4].dht.search_peers(hash, true));
+    thread::sleep(Duration::from_millis(4000));
+
+    let (peers, _, peers_found) = run_search(&nodes[7], hash, false).await;
+    assert!(peers.is_empty());
+    assert_eq!(peers_found, 0);
+}
+
+#[tokio::test]
+async fn positive_search_scrape() {
+    let nodes = start_network(5760, |_, builder| builder);
+    let hash = InfoHash::from_bytes(b"positive_search_scrape");
+
+    run_search(&nodes[5], hash, true).await;
+    thread::sleep(Duration::from_millis(ANNOUNCE_PROPAGATION_MS));
+
+    let events: Vec<SearchEvent> = tokio::time::timeout(
+        Duration::from_secs(30),
+        nodes[0].dht.scrape_peers(hash, false).collect(),
+    )
+    .await
+    .unwrap();
+
+    // Peers are still found, the estimate comes right before the summary
+    let expected: SocketAddr = ([127, 0, 0, 1], nodes[5].handshake_port).into();
+    assert_eq!(events[0], SearchEvent::Peer(expected));
+    assert_eq!(
+        events[events.len() - 2],
+        SearchEvent::Scrape {
+            seeds: 0,
+            downloaders: 1
+        }
+    );
+    match events[events.len() - 1] {
+        SearchEvent::SearchCompleted { peers_found, .. } => assert_eq!(peers_found, 1),
+        ref event => panic!("Unexpected Final Event {:?}", event),
+    }
+}
@@ -0,0 +1,71 @@
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+use bittorrent_protocol::dht::{DhtBuilder, DhtEvent, DhtState};
+use bittorrent_protocol::util::bt::InfoHash;
+
+use super::{run_search, start_network, MockHandshaker, TestNode};
+
+const ANNOUNCE_PROPAGATION_MS: u64 = 500;
+
+#[tokio::test]
+async fn positive_restore_saved_state() {
+    let mut nodes = start_network(5880, |_, builder| builder);
+    let hash = InfoHash::from_bytes(b"positive_restore_saved_state");
+
+    let state = nodes[0].dht.save_state();
+    assert_eq!(state.node_id(), nodes[0].dht.status().node_id());
+    assert!(state.num_nodes() > 0);
+
+    // Restart the node on another port with nothing but its saved state
+    let loaded = DhtState::from_bytes(&state.to_bytes()).unwrap();
+    drop(nodes.remove(0));
+
+    let (send, recv) = mpsc::channel();
+    let addr = ([127, 0, 0, 1], 5899).into();
+    let dht = DhtBuilder::with_state(loaded)
+        .set_source_addr(addr)
+        .set_read_only(false)
+        .start_mainline(MockHandshaker {
+            port: 6899,
+            send: send,
+        })
+        .unwrap();
+    let events = dht.events();
+    loop {
+        match events.recv_timeout(Duration::from_secs(30)).unwrap() {
+            DhtEvent::BootstrapCompleted { .. } => break,
+            DhtEvent::ShuttingDown(cause) => panic!("Node Shut Down: {:?}", cause),
+            _ => (),
+        }
+    }
+    assert_eq!(dht.status().node_id(), state.node_id());
+
+    let restored = TestNode {
+        addr: addr,
+        handshake_port: 6899,
+        connects: recv,
+        dht: dht,
+    };
+    run_search(&nodes[4], hash, true).await;
+    thread::sleep(Duration::from_millis(ANNOUNCE_PROPAGATION_MS));
+
+    let (peers, _, _) = run_search(&restored, hash, false).await;
+    assert_eq!(
+        peers,
+        vec![([127, 0, 0, 1], nodes[4].handshake_port).into()]
+    );
+}
+
+#[tokio::test]
+async fn positive_shutdown_returns_state_once() {
+    let nodes = start_network(6000, |_, builder| builder);
+    let node_id = nodes[0].dht.status().node_id();
+
+    let state = nodes[0].dht.shutdown().await.unwrap();
+    assert_eq!(state.node_id(), node_id);
+    assert!(state.num_nodes() > 0);
+
+    assert!(nodes[0].dht.shutdown().await.is_none());
+}